@@ -1,13 +1,17 @@
 //! Types and traits for converting objects to addresses which
 //! Tor can connect to.
 
+use crate::config::IpLiteralPolicy;
 use crate::err::ErrorDetail;
+use crate::hostname::{canonicalize_hostname, is_valid_hostname};
 use crate::StreamPrefs;
+use safelog::sensitive;
 use std::fmt::Display;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::str::FromStr;
 use thiserror::Error;
 use tor_basic_utils::StrExt;
+use tracing::warn;
 
 #[cfg(feature = "onion-service-client")]
 use tor_hscrypto::pk::{HsId, HSID_ONION_SUFFIX};
@@ -238,6 +242,19 @@ impl TorAddr {
     ) -> Result<StreamInstructions, ErrorDetail> {
         self.enforce_config(cfg, prefs)?;
 
+        // Resolving an IP address never involves the network, so we only
+        // apply this policy to connections, not in `into_resolve_instructions`.
+        if let Host::Ip(ip) = &self.host {
+            match cfg.ip_literals {
+                IpLiteralPolicy::Allow => {}
+                IpLiteralPolicy::Warn => warn!(
+                    "Connecting to IP address {}; the application may have leaked the hostname with a local DNS lookup",
+                    sensitive(ip)
+                ),
+                IpLiteralPolicy::Reject => return Err(ErrorDetail::IpLiteralRejected),
+            }
+        }
+
         let port = self.port;
         Ok(match self.host {
             Host::Hostname(hostname) => StreamInstructions::Exit { hostname, port },
@@ -397,19 +414,20 @@ enum Host {
 impl FromStr for Host {
     type Err = TorAddrError;
     fn from_str(s: &str) -> Result<Host, TorAddrError> {
-        if s.ends_with_ignore_ascii_case(".onion") && is_valid_hostname(s) {
-            Ok(Host::Onion(s.to_owned()))
+        // We classify the canonical form of the name, so that (for example)
+        // `EXAMPLE.ONION.` is recognized as an onion address.
+        let s = canonicalize_hostname(s)?;
+        if s.ends_with(".onion") {
+            Ok(Host::Onion(s))
         } else if let Ok(ip_addr) = s.parse() {
             Ok(Host::Ip(ip_addr))
-        } else if is_valid_hostname(s) {
+        } else {
             // TODO(nickm): we might someday want to reject some kinds of bad
             // hostnames here, rather than when we're about to connect to them.
             // But that would be an API break, and maybe not what people want.
             // Maybe instead we should have a method to check whether a hostname
             // is "bad"? Not sure; we'll need to decide the right behavior here.
-            Ok(Host::Hostname(s.to_owned()))
-        } else {
-            Err(TorAddrError::InvalidHostname)
+            Ok(Host::Hostname(s))
         }
     }
 }
@@ -544,13 +562,6 @@ impl DangerouslyIntoTorAddr for SocketAddrV6 {
     }
 }
 
-/// Check whether `hostname` is a valid hostname or not.
-///
-/// (Note that IPv6 addresses don't follow these rules.)
-fn is_valid_hostname(hostname: &str) -> bool {
-    hostname_validator::is_valid(hostname)
-}
-
 #[cfg(test)]
 mod test {
     // @@ begin test lint list maintained by maint/add_warning @@
@@ -582,21 +593,6 @@ mod test {
         prefs
     }
 
-    #[test]
-    fn validate_hostname() {
-        // Valid hostname tests
-        assert!(is_valid_hostname("torproject.org"));
-        assert!(is_valid_hostname("Tor-Project.org"));
-        assert!(is_valid_hostname("example.onion"));
-        assert!(is_valid_hostname("some.example.onion"));
-
-        // Invalid hostname tests
-        assert!(!is_valid_hostname("-torproject.org"));
-        assert!(!is_valid_hostname("_torproject.org"));
-        assert!(!is_valid_hostname("tor_project1.org"));
-        assert!(!is_valid_hostname("iwanna$money.org"));
-    }
-
     #[test]
     fn validate_addr() {
         use crate::err::ErrorDetail;
@@ -651,6 +647,43 @@ mod test {
         ));
     }
 
+    #[test]
+    fn canonical_hosts() {
+        fn host(s: &str) -> Host {
+            s.parse().unwrap()
+        }
+
+        assert_eq!(
+            host("WWW.Example.COM."),
+            Host::Hostname("www.example.com".into())
+        );
+        assert_eq!(
+            host("b\u{fc}cher.example"),
+            Host::Hostname("xn--bcher-kva.example".into())
+        );
+        // Odd spellings of an onion address are still onion addresses.
+        assert_eq!(host("Example.ONION."), Host::Onion("example.onion".into()));
+        assert_eq!(
+            host("example\u{ff0e}onion"),
+            Host::Onion("example.onion".into())
+        );
+        assert_eq!(host("localhost."), Host::Hostname("localhost".into()));
+        assert!(host("localhost.").is_local());
+
+        assert_eq!(
+            "www.example.com\0".parse::<Host>(),
+            Err(TorAddrError::InvalidHostname)
+        );
+        assert_eq!(
+            "www.example.com\n".parse::<Host>(),
+            Err(TorAddrError::InvalidHostname)
+        );
+        assert_eq!(
+            TorAddr::from(("Example.COM.", 80)).unwrap().to_string(),
+            "example.com:80"
+        );
+    }
+
     #[test]
     fn local_addrs() {
         fn is_local_hostname(s: &str) -> bool {
@@ -761,6 +794,46 @@ mod test {
         );
     }
 
+    #[test]
+    fn ip_literals() {
+        use crate::config::ClientAddrConfigBuilder;
+
+        fn sap(s: &str, policy: IpLiteralPolicy) -> Result<StreamInstructions, ErrorDetail> {
+            let cfg = ClientAddrConfigBuilder::default()
+                .ip_literals(policy)
+                .build()
+                .unwrap();
+            TorAddr::from(s)
+                .unwrap()
+                .into_stream_instructions(&cfg, &mk_stream_prefs())
+        }
+
+        for policy in [IpLiteralPolicy::Allow, IpLiteralPolicy::Warn] {
+            assert!(sap("198.151.100.42:443", policy).is_ok());
+            assert!(sap("[2001:db8::42]:443", policy).is_ok());
+        }
+        assert!(matches!(
+            sap("198.151.100.42:443", IpLiteralPolicy::Reject),
+            Err(ErrorDetail::IpLiteralRejected)
+        ));
+        assert!(matches!(
+            sap("[2001:db8::42]:443", IpLiteralPolicy::Reject),
+            Err(ErrorDetail::IpLiteralRejected)
+        ));
+        // Hostnames are always fine: the exit resolves them.
+        assert!(sap("www.torproject.org:443", IpLiteralPolicy::Reject).is_ok());
+
+        // Resolving an IP address doesn't touch the network, so it's permitted.
+        let cfg = ClientAddrConfigBuilder::default()
+            .ip_literals(IpLiteralPolicy::Reject)
+            .build()
+            .unwrap();
+        assert!(TorAddr::from("198.151.100.42:443")
+            .unwrap()
+            .into_resolve_instructions(&cfg, &mk_stream_prefs())
+            .is_ok());
+    }
+
     #[test]
     fn prefs_onion_services() {
         use crate::err::ErrorDetailDiscriminants;
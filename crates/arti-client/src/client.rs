@@ -10,15 +10,15 @@ use {derive_deftly::Deftly, tor_rpcbase::templates::*};
 
 use crate::address::{IntoTorAddr, ResolveInstructions, StreamInstructions};
 
-use crate::config::{ClientAddrConfig, StreamTimeoutConfig, TorClientConfig};
+use crate::config::{ClientAddrConfig, StreamBufferConfig, StreamTimeoutConfig, TorClientConfig};
 use safelog::{sensitive, Sensitive};
 use tor_async_utils::{DropNotifyWatchSender, PostageWatchSenderExt};
 use tor_circmgr::isolation::{Isolation, StreamIsolation};
-use tor_circmgr::{isolation::StreamIsolationBuilder, IsolationToken, TargetPort};
-use tor_config::MutCfg;
+use tor_circmgr::{isolation::StreamIsolationBuilder, IsolationToken, StreamSlot, TargetPort};
+use tor_config::{ConfigBuildError, MutCfg};
 #[cfg(feature = "bridge-client")]
 use tor_dirmgr::bridgedesc::BridgeDescMgr;
-use tor_dirmgr::{DirMgrStore, Timeliness};
+use tor_dirmgr::{DirMgrStore, DirUsage};
 use tor_error::{error_report, internal, Bug};
 use tor_guardmgr::{GuardMgr, RetireCircuits};
 use tor_netdir::{params::NetParameters, NetDirProvider};
@@ -52,14 +52,32 @@ use futures::StreamExt as _;
 use std::net::IpAddr;
 use std::path::PathBuf;
 use std::result::Result as StdResult;
+#[cfg(any(feature = "onion-service-service", feature = "experimental-api"))]
+use std::sync::Weak;
 use std::sync::{Arc, Mutex};
 
 use crate::err::ErrorDetail;
 use crate::{status, util, TorClientBuilder};
 #[cfg(feature = "geoip")]
 use tor_geoip::CountryCode;
-use tor_rtcompat::scheduler::TaskHandle;
-use tracing::{debug, info};
+use tor_rtcompat::scheduler::{ScheduleGroup, TaskHandle};
+use tracing::{debug, info, warn};
+#[cfg(feature = "pt-client")]
+use {
+    crate::moat::MoatState,
+    std::time::Duration,
+    tor_error::warn_report,
+    tor_guardmgr::bridge::moat::{MoatBridges, SettingsRequest},
+    tor_guardmgr::bridge::BridgeConfig,
+    tor_linkspec::{ChannelMethod, HasChanMethod as _, OwnedChanTarget},
+};
+
+/// How long we wait for a moat bridge distributor to answer us.
+///
+/// This is generous, since we usually reach the distributor through a slow,
+/// domain-fronted, `meek` tunnel.
+#[cfg(feature = "pt-client")]
+const MOAT_TIMEOUT: Duration = Duration::from_secs(120);
 
 /// An active client session on the Tor network.
 ///
@@ -118,6 +136,12 @@ pub struct TorClient<R: Runtime> {
     /// Pluggable transport manager.
     #[cfg(feature = "pt-client")]
     pt_mgr: Arc<tor_ptmgr::PtMgr<R>>,
+    /// The bridges that we have learned from a moat bridge distributor, and
+    /// whether we still need to.
+    ///
+    /// Lock hierarchy: don't acquire `reconfigure_lock` while holding this.
+    #[cfg(feature = "pt-client")]
+    moat: Arc<Mutex<MoatState>>,
     /// HS client connector
     #[cfg(feature = "onion-service-client")]
     hsclient: HsClientConnector<R>,
@@ -157,8 +181,14 @@ pub struct TorClient<R: Runtime> {
     statemgr: FsStateMgr,
     /// Client address configuration
     addrcfg: Arc<MutCfg<ClientAddrConfig>>,
+    /// The IPv4/IPv6 preference for streams whose `StreamPrefs` don't set one.
+    ///
+    /// Derived from our channel configuration.
+    ip_ver_pref: Arc<MutCfg<IpVersionPreference>>,
     /// Client DNS configuration
     timeoutcfg: Arc<MutCfg<StreamTimeoutConfig>>,
+    /// Client stream buffering configuration
+    buffercfg: Arc<MutCfg<StreamBufferConfig>>,
     /// Mutex used to serialize concurrent attempts to reconfigure a TorClient.
     ///
     /// See [`TorClient::reconfigure`] for more information on its use.
@@ -184,8 +214,73 @@ pub struct TorClient<R: Runtime> {
     // The sent value is `Option`, so that `None` is sent when the sender, here,
     // is dropped,.  That shuts down the monitoring task.
     dormant: Arc<Mutex<DropNotifyWatchSender<Option<DormantMode>>>>,
+
+    /// The onion services that we have launched, so that we can report on their status.
+    #[cfg(feature = "onion-service-service")]
+    onion_services: Arc<Mutex<OnionServiceRegistry>>,
+
+    /// The data streams that we have opened, so that we can wait for them on shutdown.
+    #[cfg(feature = "experimental-api")]
+    streams: Arc<Mutex<StreamRegistry>>,
+}
+
+/// The data streams that a [`TorClient`] has opened.
+///
+/// Used by [`TorClient::shutdown`].
+#[cfg(feature = "experimental-api")]
+#[derive(Default)]
+struct StreamRegistry {
+    /// True once we have started shutting down.
+    ///
+    /// After that, we don't open any more streams.
+    shutting_down: bool,
+    /// A handle on each stream.
+    ///
+    /// Streams that have been dropped or closed are removed lazily.
+    streams: Vec<Weak<tor_proto::stream::DataStreamCtrl>>,
 }
 
+#[cfg(feature = "experimental-api")]
+impl StreamRegistry {
+    /// Forget every stream that has been dropped or closed,
+    /// and return the ones that are still open.
+    fn open_streams(&mut self) -> Vec<Arc<tor_proto::stream::DataStreamCtrl>> {
+        let open = self
+            .streams
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|s| s.is_open())
+            .collect::<Vec<_>>();
+        self.streams = open.iter().map(Arc::downgrade).collect();
+        open
+    }
+}
+
+/// The onion services that a [`TorClient`] has launched.
+#[cfg(feature = "onion-service-service")]
+#[derive(Default)]
+struct OnionServiceRegistry {
+    /// Each service, with its nickname.
+    ///
+    /// Services that have been dropped are removed lazily.
+    services: Vec<(
+        tor_hsservice::HsNickname,
+        Weak<tor_hsservice::RunningOnionService>,
+    )>,
+    /// Where to send the status events of each newly launched service.
+    ///
+    /// Watchers that have gone away are removed when we next launch a service.
+    #[cfg(feature = "rpc")]
+    watchers: Vec<futures::channel::mpsc::UnboundedSender<OnionServiceStatusEvents>>,
+}
+
+/// The nickname of an onion service, and a stream of its status events.
+#[cfg(all(feature = "onion-service-service", feature = "rpc"))]
+pub(crate) type OnionServiceStatusEvents = (
+    tor_hsservice::HsNickname,
+    tor_hsservice::status::OnionServiceStatusStream,
+);
+
 /// Preferences for whether a [`TorClient`] should bootstrap on its own or not.
 #[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
 #[non_exhaustive]
@@ -212,6 +307,15 @@ pub enum DormantMode {
     Normal,
     /// Background tasks are suspended, conserving CPU usage. Attempts to use the client will
     /// wake it back up again.
+    ///
+    /// While in this mode, the client:
+    ///  * does not build predictive circuits,
+    ///  * stops sending channel padding,
+    ///  * postpones directory downloads until it wakes up,
+    ///  * keeps its existing channels open, relying on their minimal keepalive traffic.
+    ///
+    /// The next application request (such as [`TorClient::connect`]) returns the client to
+    /// [`Normal`](DormantMode::Normal) mode.
     Soft,
 }
 
@@ -219,7 +323,9 @@ pub enum DormantMode {
 #[derive(Debug, Default, Clone)]
 pub struct StreamPrefs {
     /// What kind of IPv6/IPv4 we'd prefer, and how strongly.
-    ip_ver_pref: IpVersionPreference,
+    ///
+    /// `None` means to follow the client's channel configuration.
+    ip_ver_pref: Option<IpVersionPreference>,
     /// How should we isolate connection(s)?
     isolation: StreamIsolationPreference,
     /// Whether to return the stream optimistically.
@@ -269,6 +375,21 @@ impl From<DormantMode> for tor_dirmgr::bridgedesc::Dormancy {
     }
 }
 
+/// Return the IPv4/IPv6 preference for streams on a client whose channels
+/// are configured by `channel`.
+///
+/// We prefer the address family that we use ourselves: a client that can
+/// only reach relays over IPv6 is most likely on an IPv6-only network, and
+/// would rather have IPv6 connections too.  This is only a preference, though:
+/// exits may still give us IPv4 connections if that is all they have.
+fn default_ip_ver_pref(channel: &tor_chanmgr::ChannelConfig) -> IpVersionPreference {
+    if !channel.use_ipv4() || (channel.use_ipv6() && channel.prefer_ipv6()) {
+        IpVersionPreference::Ipv6Preferred
+    } else {
+        IpVersionPreference::Ipv4Preferred
+    }
+}
+
 impl StreamPrefs {
     /// Construct a new StreamPrefs.
     pub fn new() -> Self {
@@ -278,7 +399,7 @@ impl StreamPrefs {
     /// Indicate that a stream may be made over IPv4 or IPv6, but that
     /// we'd prefer IPv6.
     pub fn ipv6_preferred(&mut self) -> &mut Self {
-        self.ip_ver_pref = IpVersionPreference::Ipv6Preferred;
+        self.ip_ver_pref = Some(IpVersionPreference::Ipv6Preferred);
         self
     }
 
@@ -288,16 +409,17 @@ impl StreamPrefs {
     /// support IPv6, and we will tell them to only give us IPv6
     /// connections.
     pub fn ipv6_only(&mut self) -> &mut Self {
-        self.ip_ver_pref = IpVersionPreference::Ipv6Only;
+        self.ip_ver_pref = Some(IpVersionPreference::Ipv6Only);
         self
     }
 
     /// Indicate that a stream may be made over IPv4 or IPv6, but that
     /// we'd prefer IPv4.
     ///
-    /// This is the default.
+    /// This is the default, unless the client's channels are configured to
+    /// prefer IPv6 or to avoid IPv4.
     pub fn ipv4_preferred(&mut self) -> &mut Self {
-        self.ip_ver_pref = IpVersionPreference::Ipv4Preferred;
+        self.ip_ver_pref = Some(IpVersionPreference::Ipv4Preferred);
         self
     }
 
@@ -307,7 +429,7 @@ impl StreamPrefs {
     /// support IPv4, and we will tell them to only give us IPv4
     /// connections.
     pub fn ipv4_only(&mut self) -> &mut Self {
-        self.ip_ver_pref = IpVersionPreference::Ipv4Only;
+        self.ip_ver_pref = Some(IpVersionPreference::Ipv4Only);
         self
     }
 
@@ -385,20 +507,26 @@ impl StreamPrefs {
         self.connect_to_onion_services = connect_to_onion_services;
         self
     }
+    /// Return the IPv4/IPv6 preference to use for a stream, given the
+    /// client's default preference `dflt`.
+    fn ip_ver_pref(&self, dflt: IpVersionPreference) -> IpVersionPreference {
+        self.ip_ver_pref.unwrap_or(dflt)
+    }
+
     /// Return a TargetPort to describe what kind of exit policy our
     /// target circuit needs to support.
-    fn wrap_target_port(&self, port: u16) -> TargetPort {
-        match self.ip_ver_pref {
+    fn wrap_target_port(&self, port: u16, dflt_ip_ver: IpVersionPreference) -> TargetPort {
+        match self.ip_ver_pref(dflt_ip_ver) {
             IpVersionPreference::Ipv6Only => TargetPort::ipv6(port),
             _ => TargetPort::ipv4(port),
         }
     }
 
     /// Return a new StreamParameters based on this configuration.
-    fn stream_parameters(&self) -> StreamParameters {
+    fn stream_parameters(&self, dflt_ip_ver: IpVersionPreference) -> StreamParameters {
         let mut params = StreamParameters::default();
         params
-            .ip_version(self.ip_ver_pref)
+            .ip_version(self.ip_ver_pref(dflt_ip_ver))
             .optimistic(self.optimistic_stream);
         params
     }
@@ -590,6 +718,10 @@ impl<R: Runtime> TorClient<R> {
                 pt_state_dir,
                 runtime.clone(),
             )?);
+            mgr.set_outbound_bind(
+                config.channel.outbound_bind_ipv4(),
+                config.channel.outbound_bind_ipv6(),
+            );
 
             chanmgr.set_pt_mgr(mgr.clone());
 
@@ -606,6 +738,7 @@ impl<R: Runtime> TorClient<R> {
         .map_err(ErrorDetail::CircMgrSetup)?;
 
         let timeout_cfg = config.stream_timeouts.clone();
+        let buffer_cfg = config.stream_buffers.clone();
 
         let dirmgr_store =
             DirMgrStore::new(&dir_cfg, runtime.clone(), false).map_err(ErrorDetail::DirMgrSetup)?;
@@ -637,9 +770,11 @@ impl<R: Runtime> TorClient<R> {
         #[cfg(any(feature = "onion-service-client", feature = "onion-service-service"))]
         let hs_circ_pool = {
             let circpool = tor_circmgr::hspool::HsCircPool::new(&circmgr);
-            circpool
-                .launch_background_tasks(&runtime, &dirmgr.clone().upcast_arc())
-                .map_err(ErrorDetail::CircMgrSetup)?;
+            periodic_task_handles.extend(
+                circpool
+                    .launch_background_tasks(&runtime, &dirmgr.clone().upcast_arc())
+                    .map_err(ErrorDetail::CircMgrSetup)?,
+            );
             circpool
         };
 
@@ -674,12 +809,14 @@ impl<R: Runtime> TorClient<R> {
         let conn_status = chanmgr.bootstrap_events();
         let dir_status = dirmgr.bootstrap_events();
         let skew_status = circmgr.skew_events();
+        let reachability_status = circmgr.reachability_events();
         runtime
             .spawn(status::report_status(
                 status_sender,
                 conn_status,
                 dir_status,
                 skew_status,
+                reachability_status,
             ))
             .map_err(|e| ErrorDetail::from_spawn("top-level status reporter", e))?;
 
@@ -697,6 +834,8 @@ impl<R: Runtime> TorClient<R> {
             bridge_desc_mgr,
             #[cfg(feature = "pt-client")]
             pt_mgr,
+            #[cfg(feature = "pt-client")]
+            moat: Arc::new(Mutex::new(MoatState::new(config))),
             #[cfg(feature = "onion-service-client")]
             hsclient,
             #[cfg(any(feature = "onion-service-client", feature = "onion-service-service"))]
@@ -705,7 +844,9 @@ impl<R: Runtime> TorClient<R> {
             guardmgr,
             statemgr,
             addrcfg: Arc::new(addr_cfg.into()),
+            ip_ver_pref: Arc::new(default_ip_ver_pref(&config.channel).into()),
             timeoutcfg: Arc::new(timeout_cfg.into()),
+            buffercfg: Arc::new(buffer_cfg.into()),
             reconfigure_lock: Arc::new(Mutex::new(())),
             status_receiver,
             bootstrap_in_progress: Arc::new(AsyncMutex::new(())),
@@ -715,6 +856,10 @@ impl<R: Runtime> TorClient<R> {
             state_dir,
             #[cfg(feature = "onion-service-service")]
             storage_mistrust: mistrust.clone(),
+            #[cfg(feature = "onion-service-service")]
+            onion_services: Default::default(),
+            #[cfg(feature = "experimental-api")]
+            streams: Default::default(),
         })
     }
 
@@ -790,10 +935,16 @@ impl<R: Runtime> TorClient<R> {
         // unlock the state files.
         let unlock_guard = util::StateMgrUnlockGuard::new(&self.statemgr);
 
-        self.dirmgr
-            .bootstrap()
-            .await
-            .map_err(ErrorDetail::DirMgrBootstrap)?;
+        #[cfg(feature = "pt-client")]
+        self.learn_moat_bridges().await?;
+
+        self.dirmgr.bootstrap().await.map_err(|e| {
+            let report = self.bootstrap_status().failure_report();
+            if !report.is_empty() {
+                warn!("Unable to bootstrap: {}", report);
+            }
+            ErrorDetail::DirMgrBootstrap(e)
+        })?;
 
         // Since we succeeded, disarm the unlock guard.
         unlock_guard.disarm();
@@ -801,6 +952,66 @@ impl<R: Runtime> TorClient<R> {
         Ok(())
     }
 
+    /// If our configuration asks us to learn bridges from a moat bridge
+    /// distributor, and we haven't yet, try to do so, and start using them.
+    ///
+    /// If we can't reach the distributor, or it gives us no usable bridges,
+    /// we log a warning and carry on without them; we try again the next time
+    /// we bootstrap.
+    #[cfg(feature = "pt-client")]
+    async fn learn_moat_bridges(&self) -> StdResult<(), ErrorDetail> {
+        let Some(config) = self.moat.lock().expect("lock poisoned").wanted().cloned() else {
+            return Ok(());
+        };
+        let Some(moat_bridge) = config.bridges.moat_bridge_wanted() else {
+            return Ok(());
+        };
+
+        let mut request = SettingsRequest::default();
+        request.transports = config
+            .bridges
+            .transport_names()
+            .map(ToString::to_string)
+            .collect();
+
+        info!("Asking bridge distributor for bridges.");
+        let learned = match self.fetch_moat_bridges_inner(moat_bridge, &request).await {
+            Ok(learned) => learned,
+            Err(e) => {
+                warn_report!(e, "Unable to learn bridges from bridge distributor");
+                return Ok(());
+            }
+        };
+
+        // Only keep bridges whose transport we have configured: the
+        // distributor may not have honoured our list.
+        let transports: Vec<_> = config.bridges.transport_names().collect();
+        let bridges: Vec<_> = learned
+            .bridges
+            .iter()
+            .filter_map(|bridge| match bridge.build() {
+                Ok(bridge) => Some(bridge),
+                Err(e) => {
+                    warn_report!(e, "Ignoring unusable bridge from bridge distributor");
+                    None
+                }
+            })
+            .filter(|bridge| match bridge.chan_method() {
+                ChannelMethod::Pluggable(target) => transports.contains(&target.transport()),
+                _ => true,
+            })
+            .collect();
+        if bridges.is_empty() {
+            warn!("Bridge distributor gave us no usable bridges.");
+            return Ok(());
+        }
+        info!("Learned {} bridges from bridge distributor.", bridges.len());
+
+        self.moat.lock().expect("lock poisoned").learned(bridges);
+        self.reconfigure(&config, tor_config::Reconfigure::AllOrNothing)
+            .map_err(crate::Error::into_detail)
+    }
+
     /// ## For `BootstrapBehavior::OnDemand` clients
     ///
     /// Initiate a bootstrap by calling `bootstrap` (which is idempotent, so attempts to
@@ -866,6 +1077,17 @@ impl<R: Runtime> TorClient<R> {
         // deciding how to change it, then applying the changes.
         let guard = self.reconfigure_lock.lock().expect("Poisoned lock");
 
+        // Put back any bridges that we learned from a moat bridge distributor,
+        // if the new configuration still asks for them.
+        #[cfg(feature = "pt-client")]
+        let moat_config = self
+            .moat
+            .lock()
+            .expect("lock poisoned")
+            .effective_config(new_config);
+        #[cfg(feature = "pt-client")]
+        let (new_config, original_config) = (&*moat_config, new_config);
+
         match how {
             tor_config::Reconfigure::AllOrNothing => {
                 // We have to check before we make any changes.
@@ -883,6 +1105,14 @@ impl<R: Runtime> TorClient<R> {
         // Actually reconfigure
         self.reconfigure_inner(new_config, how, &guard)?;
 
+        #[cfg(feature = "pt-client")]
+        if how != tor_config::Reconfigure::CheckAllOrNothing {
+            self.moat
+                .lock()
+                .expect("lock poisoned")
+                .note_config(original_config);
+        }
+
         Ok(())
     }
 
@@ -899,6 +1129,7 @@ impl<R: Runtime> TorClient<R> {
         let state_cfg = new_config.storage.expand_state_dir().map_err(wrap_err)?;
         let addr_cfg = &new_config.address_filter;
         let timeout_cfg = &new_config.stream_timeouts;
+        let buffer_cfg = &new_config.stream_buffers;
 
         if state_cfg != self.statemgr.path() {
             how.cannot_change("storage.state_dir").map_err(wrap_err)?;
@@ -927,16 +1158,77 @@ impl<R: Runtime> TorClient<R> {
             .reconfigure(how, new_config.bridges.transports.clone())
             .map_err(wrap_err)?;
 
+        #[cfg(feature = "onion-service-client")]
+        self.hsclient
+            .reconfigure(new_config, how)
+            .map_err(wrap_err)?;
+
         if how == tor_config::Reconfigure::CheckAllOrNothing {
             return Ok(());
         }
 
         self.addrcfg.replace(addr_cfg.clone());
+        self.ip_ver_pref
+            .replace(default_ip_ver_pref(&new_config.channel));
+        #[cfg(feature = "pt-client")]
+        self.pt_mgr.set_outbound_bind(
+            new_config.channel.outbound_bind_ipv4(),
+            new_config.channel.outbound_bind_ipv6(),
+        );
         self.timeoutcfg.replace(timeout_cfg.clone());
+        self.buffercfg.replace(buffer_cfg.clone());
 
         Ok(())
     }
 
+    /// Ask a moat bridge distributor which bridges we should use.
+    ///
+    /// We reach the distributor through `moat_bridge`: usually a `meek`
+    /// bridge line, whose `url` and `front` parameters say how to reach the
+    /// distributor.  Its transport must be configured in `bridges.transports`.
+    ///
+    /// Bridge lines from the distributor that we can't parse are skipped, and
+    /// listed in the result.
+    ///
+    /// You only need this to learn bridges yourself: if `bridges.moat_bridge`
+    /// is configured, and no bridges are, we do this when we bootstrap, and
+    /// use the bridges that we learn.
+    #[cfg(feature = "pt-client")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "pt-client")))]
+    pub async fn fetch_moat_bridges(
+        &self,
+        moat_bridge: &BridgeConfig,
+        request: &SettingsRequest,
+    ) -> crate::Result<MoatBridges> {
+        self.fetch_moat_bridges_inner(moat_bridge, request)
+            .await
+            .map_err(ErrorDetail::into)
+    }
+
+    /// Implementation of `fetch_moat_bridges`, split out in order to avoid
+    /// manually specifying double error conversions.
+    #[cfg(feature = "pt-client")]
+    async fn fetch_moat_bridges_inner(
+        &self,
+        moat_bridge: &BridgeConfig,
+        request: &SettingsRequest,
+    ) -> StdResult<MoatBridges, ErrorDetail> {
+        let target = OwnedChanTarget::from_chan_target(moat_bridge);
+        let started = self.runtime.now();
+        let stream = self
+            .runtime
+            .timeout(MOAT_TIMEOUT, self.pt_mgr.connect_stream(&target))
+            .await
+            .map_err(|_| ErrorDetail::MoatTimeout)?
+            .map_err(ErrorDetail::MoatConnect)?;
+
+        // The connection and the exchange share a single timeout.
+        let remaining = MOAT_TIMEOUT.saturating_sub(self.runtime.now() - started);
+        tor_guardmgr::bridge::moat::fetch_settings(&self.runtime, stream, request, remaining)
+            .await
+            .map_err(ErrorDetail::Moat)
+    }
+
     /// Return a new isolated `TorClient` handle.
     ///
     /// The two `TorClient`s will share internal state and configuration, but
@@ -1037,21 +1329,32 @@ impl<R: Runtime> TorClient<R> {
         target: A,
         prefs: &StreamPrefs,
     ) -> crate::Result<DataStream> {
-        let addr = target.into_tor_addr().map_err(wrap_err)?;
-        let mut stream_parameters = prefs.stream_parameters();
+        #[cfg(feature = "experimental-api")]
+        if self.streams.lock().expect("lock poisoned").shutting_down {
+            return Err(ErrorDetail::ShuttingDown.into());
+        }
 
-        let (circ, addr, port) = match addr.into_stream_instructions(&self.addrcfg.get(), prefs)? {
+        let addr = target.into_tor_addr().map_err(wrap_err)?;
+        let dflt_ip_ver = *self.ip_ver_pref.get();
+        let mut stream_parameters = prefs.stream_parameters(dflt_ip_ver);
+        stream_parameters.buffer_watermarks(self.buffercfg.get().watermarks());
+
+        // The stream slot (if any) that we hold on `circ` until our stream is
+        // open.
+        let (circ, _slot, addr, port) = match addr
+            .into_stream_instructions(&self.addrcfg.get(), prefs)?
+        {
             StreamInstructions::Exit {
                 hostname: addr,
                 port,
             } => {
-                let exit_ports = [prefs.wrap_target_port(port)];
-                let circ = self
+                let exit_ports = [prefs.wrap_target_port(port, dflt_ip_ver)];
+                let (circ, slot) = self
                     .get_or_launch_exit_circ(&exit_ports, prefs)
                     .await
                     .map_err(wrap_err)?;
                 debug!("Got a circuit for {}:{}", sensitive(&addr), port);
-                (circ, addr, port)
+                (circ, Some(slot), addr, port)
             }
 
             #[cfg(not(feature = "onion-service-client"))]
@@ -1069,7 +1372,7 @@ impl<R: Runtime> TorClient<R> {
                 port,
             } => {
                 self.wait_for_bootstrap().await?;
-                let netdir = self.netdir(Timeliness::Timely, "connect to a hidden service")?;
+                let netdir = self.netdir(DirUsage::OnionServices, "connect to a hidden service")?;
 
                 let mut hs_client_secret_keys_builder = HsClientSecretKeysBuilder::default();
 
@@ -1113,7 +1416,7 @@ impl<R: Runtime> TorClient<R> {
                     .suppress_hostname()
                     .suppress_begin_flags()
                     .optimistic(false);
-                (circ, hostname, port)
+                (circ, None, hostname, port)
             }
         };
 
@@ -1129,6 +1432,13 @@ impl<R: Runtime> TorClient<R> {
                 kind: "data",
             })?;
 
+        #[cfg(feature = "experimental-api")]
+        self.streams
+            .lock()
+            .expect("lock poisoned")
+            .streams
+            .push(Arc::downgrade(stream.ctrl()));
+
         Ok(stream)
     }
 
@@ -1173,7 +1483,7 @@ impl<R: Runtime> TorClient<R> {
 
         match addr.into_resolve_instructions(&self.addrcfg.get(), prefs)? {
             ResolveInstructions::Exit(hostname) => {
-                let circ = self.get_or_launch_exit_circ(&[], prefs).await?;
+                let (circ, _slot) = self.get_or_launch_exit_circ(&[], prefs).await?;
 
                 let resolve_future = circ.resolve(&hostname);
                 let addrs = self
@@ -1207,7 +1517,7 @@ impl<R: Runtime> TorClient<R> {
         addr: IpAddr,
         prefs: &StreamPrefs,
     ) -> crate::Result<Vec<String>> {
-        let circ = self.get_or_launch_exit_circ(&[], prefs).await?;
+        let (circ, _slot) = self.get_or_launch_exit_circ(&[], prefs).await?;
 
         let resolve_ptr_future = circ.resolve_ptr(addr);
         let hostnames = self
@@ -1226,6 +1536,18 @@ impl<R: Runtime> TorClient<R> {
         Ok(hostnames)
     }
 
+    /// Return this client's directory provider, for reporting directory events over RPC.
+    #[cfg(feature = "rpc")]
+    pub(crate) fn dir_provider(&self) -> Arc<dyn tor_dirmgr::DirProvider> {
+        Arc::clone(&self.dirmgr)
+    }
+
+    /// Return a stream of events about this client's primary guards.
+    #[cfg(feature = "rpc")]
+    pub(crate) fn primary_guard_events(&self) -> tor_guardmgr::PrimaryGuardEvents {
+        self.guardmgr.primary_guard_events()
+    }
+
     /// Return a reference to this client's directory manager.
     ///
     /// This function is unstable. It is only enabled if the crate was
@@ -1278,17 +1600,17 @@ impl<R: Runtime> TorClient<R> {
         &self.runtime
     }
 
-    /// Return a netdir that is timely according to the rules of `timeliness`.
+    /// Return a netdir that is live enough to be used for `usage`.
     ///
     /// The `action` string is a description of what we wanted to do with the
     /// directory, to be put into the error message if we couldn't find a directory.
     fn netdir(
         &self,
-        timeliness: Timeliness,
+        usage: DirUsage,
         action: &'static str,
     ) -> StdResult<Arc<tor_netdir::NetDir>, ErrorDetail> {
         use tor_netdir::Error as E;
-        match self.dirmgr.netdir(timeliness) {
+        match self.dirmgr.netdir_for(usage) {
             Ok(netdir) => Ok(netdir),
             Err(E::NoInfo) | Err(E::NotEnoughInfo) => {
                 Err(ErrorDetail::BootstrapRequired { action })
@@ -1299,19 +1621,22 @@ impl<R: Runtime> TorClient<R> {
 
     /// Get or launch an exit-suitable circuit with a given set of
     /// exit ports.
+    ///
+    /// Also return the stream slot reserved for us on that circuit: hold it
+    /// until the stream is open.
     async fn get_or_launch_exit_circ(
         &self,
         exit_ports: &[TargetPort],
         prefs: &StreamPrefs,
-    ) -> StdResult<Arc<ClientCirc>, ErrorDetail> {
+    ) -> StdResult<(Arc<ClientCirc>, StreamSlot), ErrorDetail> {
         // TODO HS probably this netdir ought to be made in connect_with_prefs
         // like for StreamInstructions::Hs.
         self.wait_for_bootstrap().await?;
-        let dir = self.netdir(Timeliness::Timely, "build a circuit")?;
+        let dir = self.netdir(DirUsage::NewCircuits, "build a circuit")?;
 
         let circ = self
             .circmgr
-            .get_or_launch_exit(
+            .get_or_launch_exit_reserved(
                 dir.as_ref().into(),
                 exit_ports,
                 self.isolation(prefs),
@@ -1379,10 +1704,10 @@ impl<R: Runtime> TorClient<R> {
         let state_dir = self::StateDirectory::new(&self.state_dir, &self.storage_mistrust)
             .map_err(ErrorDetail::StateAccess)?;
 
+        let nickname = config.nickname().clone();
         let service = tor_hsservice::OnionService::builder()
-            .config(config) // TODO #1186: Allow override of KeyMgr for "ephemeral" operation?
+            .config(config)
             .keymgr(keymgr)
-            // TODO #1186: Allow override of StateMgr for "ephemeral" operation?
             .state_dir(state_dir)
             .build()
             .map_err(ErrorDetail::LaunchOnionService)?;
@@ -1393,10 +1718,129 @@ impl<R: Runtime> TorClient<R> {
                 self.hs_circ_pool.clone(),
             )
             .map_err(ErrorDetail::LaunchOnionService)?;
+        self.note_onion_service(nickname, &service);
+
+        Ok((service, stream))
+    }
+
+    /// Try to launch an ephemeral onion service with a given configuration.
+    ///
+    /// Unlike [`launch_onion_service`](TorClient::launch_onion_service),
+    /// this does not use the client's keystore or state directory:
+    /// the service's keys and state are kept in memory,
+    /// and are forgotten once the service is dropped.
+    ///
+    /// If `hsid_keypair` is provided, it is used as the service's identity key;
+    /// otherwise, a new identity key is generated.
+    /// Either way, you can find the resulting onion address with
+    /// [`RunningOnionService::onion_name`](tor_hsservice::RunningOnionService::onion_name).
+    ///
+    /// The returned stream behaves as for `launch_onion_service`.
+    #[cfg(feature = "onion-service-service")]
+    pub fn launch_onion_service_ephemeral(
+        &self,
+        config: tor_hsservice::OnionServiceConfig,
+        hsid_keypair: Option<tor_hscrypto::pk::HsIdKeypair>,
+    ) -> crate::Result<(
+        Arc<tor_hsservice::RunningOnionService>,
+        impl futures::Stream<Item = tor_hsservice::RendRequest>,
+    )> {
+        use tor_keymgr::{ArtiEphemeralKeystore, KeystoreSelector};
+
+        let keystore = ArtiEphemeralKeystore::new("ephemeral".to_string());
+        let keymgr = KeyMgrBuilder::default()
+            .default_store(Box::new(keystore))
+            .build()
+            .map_err(|e| internal!("failed to build ephemeral key manager: {e}"))?;
+        if let Some(hsid_keypair) = hsid_keypair {
+            let spec = tor_hsservice::HsIdKeypairSpecifier::new(config.nickname().clone());
+            keymgr.insert(hsid_keypair, &spec, KeystoreSelector::Default)?;
+        }
+
+        let nickname = config.nickname().clone();
+        let service = tor_hsservice::OnionService::builder()
+            .config(config)
+            .keymgr(Arc::new(keymgr))
+            .ephemeral(true)
+            .build()
+            .map_err(ErrorDetail::LaunchOnionService)?;
+        let (service, stream) = service
+            .launch(
+                self.runtime.clone(),
+                self.dirmgr.clone().upcast_arc(),
+                self.hs_circ_pool.clone(),
+            )
+            .map_err(ErrorDetail::LaunchOnionService)?;
+        self.note_onion_service(nickname, &service);
 
         Ok((service, stream))
     }
 
+    /// Remember that we have launched `service` as `nickname`, so that we can
+    /// report on its status.
+    #[cfg(feature = "onion-service-service")]
+    fn note_onion_service(
+        &self,
+        nickname: tor_hsservice::HsNickname,
+        service: &Arc<tor_hsservice::RunningOnionService>,
+    ) {
+        let mut registry = self.onion_services.lock().expect("lock poisoned");
+        registry.services.retain(|(_, svc)| svc.strong_count() > 0);
+        #[cfg(feature = "rpc")]
+        registry.watchers.retain(|watcher| {
+            watcher
+                .unbounded_send((nickname.clone(), service.status_events()))
+                .is_ok()
+        });
+        registry.services.push((nickname, Arc::downgrade(service)));
+    }
+
+    /// Return a stream that yields a stream of status events for each onion
+    /// service that we have launched and that is still running,
+    /// and then for each service that we launch later.
+    #[cfg(all(feature = "onion-service-service", feature = "rpc"))]
+    pub(crate) fn onion_service_status_events(
+        &self,
+    ) -> impl futures::Stream<Item = OnionServiceStatusEvents> + Send + Unpin + 'static {
+        let mut registry = self.onion_services.lock().expect("lock poisoned");
+        registry.services.retain(|(_, svc)| svc.strong_count() > 0);
+        let current: Vec<_> = registry
+            .services
+            .iter()
+            .filter_map(|(nickname, svc)| Some((nickname.clone(), svc.upgrade()?.status_events())))
+            .collect();
+        // We register the watcher while we still hold the lock, so that we
+        // can't miss a service that is launched in the meantime.
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        registry.watchers.push(tx);
+        futures::stream::iter(current).chain(rx)
+    }
+
+    /// Delete the stored state of onion services that are not in `configured`.
+    ///
+    /// Only services whose state has not been used for at least `retain_for`
+    /// are affected; services that are currently running are never affected.
+    /// The services' keys are not deleted.
+    ///
+    /// See [`tor_hsservice::expire_unused_service_state`] for details.
+    #[cfg(feature = "onion-service-service")]
+    pub fn expire_unused_onion_service_state<'n>(
+        &self,
+        configured: impl IntoIterator<Item = &'n tor_hsservice::HsNickname>,
+        retain_for: std::time::Duration,
+    ) -> crate::Result<()> {
+        let state_dir = self::StateDirectory::new(&self.state_dir, &self.storage_mistrust)
+            .map_err(ErrorDetail::StateAccess)?;
+        tor_hsservice::expire_unused_service_state(
+            &state_dir,
+            configured,
+            retain_for,
+            self.runtime.wallclock(),
+        )
+        .map_err(ErrorDetail::StateAccess)?;
+        Ok(())
+    }
+
     /// Generate a service discovery keypair for connecting to a hidden service running in
     /// "restricted discovery" mode.
     ///
@@ -1438,17 +1882,23 @@ impl<R: Runtime> TorClient<R> {
         selector: KeystoreSelector,
         hsid: HsId,
     ) -> crate::Result<HsClientDescEncKey> {
-        let mut rng = rand::thread_rng();
         let spec = HsClientDescEncKeypairSpecifier::new(hsid);
-        let key = self
-            .keymgr
-            .as_ref()
-            .ok_or(ErrorDetail::KeystoreRequired {
-                action: "generate client service discovery key",
-            })?
-            .generate::<HsClientDescEncKeypair>(
-                &spec, selector, &mut rng, false, /* overwrite */
-            )?;
+        let keymgr = self.keymgr.as_ref().ok_or(ErrorDetail::KeystoreRequired {
+            action: "generate client service discovery key",
+        })?;
+
+        // If we have a master seed, derive the key from it, so that it can be recovered.
+        #[cfg(feature = "key-derivation")]
+        if keymgr.has_master_seed() {
+            let key = keymgr
+                .derive::<HsClientDescEncKeypair>(&spec, selector, false /* overwrite */)?;
+            return Ok(key.public().clone());
+        }
+
+        let mut rng = rand::thread_rng();
+        let key = keymgr.generate::<HsClientDescEncKeypair>(
+            &spec, selector, &mut rng, false, /* overwrite */
+        )?;
 
         Ok(key.public().clone())
     }
@@ -1480,6 +1930,93 @@ impl<R: Runtime> TorClient<R> {
         Ok(key)
     }
 
+    /// Return information about the onion service descriptors this client has cached.
+    ///
+    /// See [`HsClientConnector::cached_descriptors`] for details.
+    #[cfg(all(feature = "onion-service-client", feature = "experimental-api"))]
+    #[cfg_attr(
+        docsrs,
+        doc(cfg(all(feature = "onion-service-client", feature = "experimental-api")))
+    )]
+    pub fn cached_onion_service_descriptors(
+        &self,
+    ) -> crate::Result<Vec<tor_hsclient::CachedDescriptorInfo>> {
+        Ok(self
+            .hsclient
+            .cached_descriptors()
+            .map_err(ErrorDetail::from)?)
+    }
+
+    /// Return a histogram of how long it took to fetch each onion service
+    /// descriptor that this client has downloaded.
+    ///
+    /// See [`HsClientConnector::descriptor_fetch_times`] for details.
+    #[cfg(all(feature = "onion-service-client", feature = "experimental-api"))]
+    #[cfg_attr(
+        docsrs,
+        doc(cfg(all(feature = "onion-service-client", feature = "experimental-api")))
+    )]
+    pub fn onion_service_descriptor_fetch_times(
+        &self,
+    ) -> crate::Result<tor_circmgr::LatencyHistogram> {
+        Ok(self
+            .hsclient
+            .descriptor_fetch_times()
+            .map_err(ErrorDetail::from)?)
+    }
+
+    /// Return the isolation of the rendezvous circuit that `stream`, a stream to
+    /// the onion service `hsid`, was opened on.
+    ///
+    /// This is the isolation shared by every request that may be given the
+    /// same circuit; with `circuit_timing.hs_strict_isolation`, it is just the
+    /// isolation of the request that built the circuit.
+    ///
+    /// Returns `None` if the circuit has closed, or if we no longer keep it
+    /// for reuse.
+    ///
+    /// See [`HsClientConnector::circuit_isolation`] for details.
+    #[cfg(all(feature = "onion-service-client", feature = "experimental-api"))]
+    #[cfg_attr(
+        docsrs,
+        doc(cfg(all(feature = "onion-service-client", feature = "experimental-api")))
+    )]
+    pub fn onion_service_circuit_isolation(
+        &self,
+        hsid: HsId,
+        stream: &DataStream,
+    ) -> crate::Result<Option<Box<dyn Isolation>>> {
+        use tor_proto::stream::ClientStreamCtrl as _;
+
+        let Some(circ) = stream.ctrl().circuit() else {
+            return Ok(None);
+        };
+        Ok(self
+            .hsclient
+            .circuit_isolation(&hsid, &circ)
+            .map_err(ErrorDetail::from)?)
+    }
+
+    /// Forget the cached descriptor for the onion service `hsid`,
+    /// and stop reusing existing circuits to it.
+    ///
+    /// The next connection to the service will download a fresh descriptor
+    /// and build a new circuit.
+    /// Existing streams to the service are not closed.
+    ///
+    /// See [`HsClientConnector::flush_service`] for details.
+    #[cfg(all(feature = "onion-service-client", feature = "experimental-api"))]
+    #[cfg_attr(
+        docsrs,
+        doc(cfg(all(feature = "onion-service-client", feature = "experimental-api")))
+    )]
+    pub fn flush_onion_service(&self, hsid: HsId) -> crate::Result<()> {
+        self.hsclient
+            .flush_service(&hsid)
+            .map_err(ErrorDetail::from)?;
+        Ok(())
+    }
+
     /// Create (but do not launch) a new
     /// [`OnionService`](tor_hsservice::OnionService)
     /// using the given configuration.
@@ -1508,6 +2045,81 @@ impl<R: Runtime> TorClient<R> {
             .map_err(ErrorDetail::LaunchOnionService)?)
     }
 
+    /// Check the health of the persistent state and keystores
+    /// used with the given configuration.
+    ///
+    /// This looks for state files that can't be parsed,
+    /// stale temporary files and lockfiles,
+    /// unexpected entries in the onion service state directory,
+    /// and keys that can't be loaded.
+    ///
+    /// If `repair` is true, trivial problems (such as stale temporary files)
+    /// are also fixed, but only in state that isn't in use by another process.
+    ///
+    /// This doesn't need a `TorClient`,
+    /// so that it can be used to investigate a client that won't start.
+    #[cfg(feature = "experimental-api")]
+    pub fn check_storage(
+        config: &TorClientConfig,
+        repair: bool,
+    ) -> crate::Result<tor_persist::CheckReport> {
+        use tor_error::ErrorReport as _;
+        use tor_persist::{CheckReport, CheckSeverity};
+
+        let (state_dir, mistrust) = Self::state_dir(config)?;
+        let mut report = CheckReport::default();
+
+        let statemgr = FsStateMgr::from_path_and_mistrust(&state_dir, mistrust)
+            .map_err(ErrorDetail::StateMgrSetup)?;
+        // We only take the lock if we might want to change something.
+        if repair
+            && !statemgr
+                .try_lock()
+                .map_err(ErrorDetail::StateMgrSetup)?
+                .held()
+        {
+            report.push(
+                CheckSeverity::Info,
+                state_dir.join("state"),
+                "state is in use, so not repairing it",
+                false,
+            );
+        }
+        report.merge(statemgr.check(repair).map_err(ErrorDetail::StateMgrSetup)?);
+
+        #[cfg(feature = "onion-service-service")]
+        {
+            use tor_persist::state_dir::InstanceIdentity;
+
+            let state_dir = self::StateDirectory::new(&state_dir, mistrust)
+                .map_err(ErrorDetail::StateAccess)?;
+            let kind = <tor_hsservice::HsNickname as InstanceIdentity>::kind();
+            report.merge(
+                state_dir
+                    .check_instances(kind, repair)
+                    .map_err(ErrorDetail::StateAccess)?,
+            );
+        }
+
+        if let Some(keymgr) = Self::create_keymgr(config)? {
+            let all_keys = tor_keymgr::KeyPathPattern::Arti("**".into());
+            for (entry, error) in keymgr.check_matching(&all_keys)? {
+                report.push(
+                    CheckSeverity::Error,
+                    entry.key_path().to_string().into(),
+                    format!(
+                        "cannot load key from keystore {}: {}",
+                        entry.keystore_id(),
+                        error.report()
+                    ),
+                    false,
+                );
+            }
+        }
+
+        Ok(report)
+    }
+
     /// Return a current [`status::BootstrapStatus`] describing how close this client
     /// is to being ready for user traffic.
     pub fn bootstrap_status(&self) -> status::BootstrapStatus {
@@ -1540,6 +2152,86 @@ impl<R: Runtime> TorClient<R> {
             .borrow_mut() = Some(mode);
     }
 
+    /// Return the client's current dormant mode.
+    ///
+    /// This reflects any automatic wakeup caused by using the client.
+    pub fn dormant_mode(&self) -> DormantMode {
+        let mut dormant = self.dormant.lock().expect("dormant lock poisoned");
+        let dormant = *dormant.borrow();
+        dormant.unwrap_or_default()
+    }
+
+    /// Save any unsaved persistent state (such as our guards and circuit
+    /// timeout estimates) to disk now.
+    ///
+    /// This also happens when the last handle to this client is dropped.
+    /// Programs that are about to exit while other tasks might still hold a
+    /// handle should call this first.
+    ///
+    /// Everything is written as a complete snapshot, so that versions of
+    /// Arti that don't read our state journals can still find it.
+    ///
+    /// Does nothing if we don't hold the lock on the state directory.
+    pub fn flush_state(&self) -> crate::Result<()> {
+        self.circmgr
+            .compact_persistent_state()
+            .map_err(ErrorDetail::StateFlush)?;
+        Ok(())
+    }
+
+    /// Shut this client down gracefully.
+    ///
+    /// Once this is called, this client (and every clone of it)
+    /// refuses to open any new data streams.
+    /// We then wait up to `timeout` for the data streams that it has already opened
+    /// to be closed,
+    /// close the circuits that any streams still open are using
+    /// (sending `DESTROY` cells),
+    /// and save our persistent state (see [`flush_state`](TorClient::flush_state)).
+    ///
+    /// Returns the number of streams that were still open when we stopped waiting.
+    ///
+    /// The lock on the state directory is released
+    /// when the last handle on this client is dropped.
+    #[cfg(feature = "experimental-api")]
+    pub async fn shutdown(&self, timeout: std::time::Duration) -> crate::Result<usize> {
+        use tor_proto::stream::ClientStreamCtrl as _;
+
+        /// How often we check whether our streams are done.
+        const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+        let open_streams = || {
+            let mut streams = self.streams.lock().expect("lock poisoned");
+            streams.shutting_down = true;
+            streams.open_streams()
+        };
+
+        let deadline = self.runtime.now() + timeout;
+        let mut n_open = open_streams().len();
+        while n_open > 0 {
+            let now = self.runtime.now();
+            if now >= deadline {
+                break;
+            }
+            self.runtime
+                .sleep(std::cmp::min(POLL_INTERVAL, deadline - now))
+                .await;
+            n_open = open_streams().len();
+        }
+
+        if n_open > 0 {
+            info!("Closing the circuits of {n_open} stream(s) that are still open.");
+            for stream in open_streams() {
+                if let Some(circ) = stream.circuit() {
+                    circ.terminate();
+                }
+            }
+        }
+
+        self.flush_state()?;
+        Ok(n_open)
+    }
+
     /// Create a [`KeyMgr`] using the specified configuration.
     ///
     /// Returns `Ok(None)` if keystore use is disabled.
@@ -1557,10 +2249,47 @@ impl<R: Runtime> TorClient<R> {
             // TODO #1106: make the default store configurable
             let default_store = arti_store;
 
-            let keymgr = KeyMgrBuilder::default()
-                .default_store(Box::new(default_store))
+            let mut builder = KeyMgrBuilder::default().default_store(Box::new(default_store));
+
+            for secondary in keystore.secondary() {
+                let dir = secondary
+                    .path()
+                    .path()
+                    .map_err(|e| ConfigBuildError::Invalid {
+                        field: "storage.keystore.secondary.path".to_owned(),
+                        problem: e.to_string(),
+                    })
+                    .map_err(ErrorDetail::Configuration)?;
+
+                let store = if secondary.read_only() {
+                    ArtiNativeKeystore::open_read_only(&dir, permissions)?
+                } else {
+                    ArtiNativeKeystore::from_path_and_mistrust(&dir, permissions)?
+                };
+                info!(
+                    "Using secondary keystore {} from {dir:?} (read-only: {})",
+                    secondary.id(),
+                    secondary.read_only(),
+                );
+
+                builder
+                    .secondary_stores()
+                    .push(Box::new(store.with_id(secondary.id().clone())));
+            }
+
+            #[cfg(feature = "key-derivation")]
+            if let Some(seed_file) = keystore.master_seed_file() {
+                builder = builder.master_seed(Self::load_master_seed(seed_file, permissions)?);
+                info!("Deriving new keys from the master seed in {seed_file}");
+            }
+
+            let keymgr = builder
                 .build()
-                .map_err(|_| internal!("failed to build keymgr"))?;
+                .map_err(|e| ConfigBuildError::Invalid {
+                    field: "storage.keystore".to_owned(),
+                    problem: e.to_string(),
+                })
+                .map_err(ErrorDetail::Configuration)?;
 
             // TODO #858: add support for the C Tor key store
             Ok(Some(Arc::new(keymgr)))
@@ -1570,6 +2299,33 @@ impl<R: Runtime> TorClient<R> {
         }
     }
 
+    /// Read the master seed from which the [`KeyMgr`] derives keys from `seed_file`.
+    #[cfg(feature = "key-derivation")]
+    fn load_master_seed(
+        seed_file: &tor_config::CfgPath,
+        permissions: &fs_mistrust::Mistrust,
+    ) -> StdResult<tor_keymgr::MasterSeed, ErrorDetail> {
+        let invalid = |problem: String| {
+            ErrorDetail::Configuration(ConfigBuildError::Invalid {
+                field: "storage.keystore.master_seed_file".to_owned(),
+                problem,
+            })
+        };
+
+        let path = seed_file.path().map_err(|e| invalid(e.to_string()))?;
+        // The seed is as secret as the keys derived from it.
+        permissions
+            .verifier()
+            .require_file()
+            .check(&path)
+            .map_err(|e| invalid(e.to_string()))?;
+        let seed = std::fs::read_to_string(&path)
+            .map_err(|e| invalid(format!("could not read {path:?}: {e}")))?;
+
+        seed.parse()
+            .map_err(|e: tor_keymgr::InvalidMasterSeed| invalid(e.to_string()))
+    }
+
     /// Get the state directory and its corresponding
     /// [`Mistrust`](fs_mistrust::Mistrust) configuration.
     fn state_dir(
@@ -1600,7 +2356,10 @@ impl<R: Runtime> TorClient<R> {
 /// Monitor `dormant_mode` and enable/disable periodic tasks as applicable
 ///
 /// This function is spawned as a task during client construction.
-// TODO should this perhaps be done by each TaskHandle?
+///
+/// The periodic tasks are put into a single [`ScheduleGroup`], which is suspended while we are
+/// dormant.  Unlike cancelling each task, this also keeps a task that happens to be running
+/// when we go dormant from rescheduling itself.
 async fn tasks_monitor_dormant<R: Runtime>(
     mut dormant_rx: postage::watch::Receiver<Option<DormantMode>>,
     netdir: Arc<dyn NetDirProvider>,
@@ -1608,6 +2367,11 @@ async fn tasks_monitor_dormant<R: Runtime>(
     #[cfg(feature = "bridge-client")] bridge_desc_mgr: Arc<Mutex<Option<Arc<BridgeDescMgr<R>>>>>,
     periodic_task_handles: Vec<TaskHandle>,
 ) {
+    let group = ScheduleGroup::new();
+    for task in periodic_task_handles.iter() {
+        task.join_group(&group);
+    }
+
     while let Some(Some(mode)) = dormant_rx.next().await {
         let netparams = netdir.params();
 
@@ -1626,10 +2390,11 @@ async fn tasks_monitor_dormant<R: Runtime>(
 
         let is_dormant = matches!(mode, DormantMode::Soft);
 
-        for task in periodic_task_handles.iter() {
-            if is_dormant {
-                task.cancel();
-            } else {
+        if is_dormant {
+            group.suspend();
+        } else {
+            group.resume();
+            for task in periodic_task_handles.iter() {
                 task.fire();
             }
         }
@@ -1714,7 +2479,11 @@ mod test {
     #[test]
     fn streamprefs_new_has_expected_defaults() {
         let observed = StreamPrefs::new();
-        assert_eq!(observed.ip_ver_pref, IpVersionPreference::Ipv4Preferred);
+        assert_eq!(observed.ip_ver_pref, None);
+        assert_eq!(
+            observed.ip_ver_pref(IpVersionPreference::Ipv6Preferred),
+            IpVersionPreference::Ipv6Preferred
+        );
         assert!(!observed.optimistic_stream);
         // StreamIsolationPreference does not implement Eq, check manually.
         match observed.isolation {
@@ -1737,28 +2506,60 @@ mod test {
     fn streamprefs_ipv6_only() {
         let mut observed = StreamPrefs::new();
         observed.ipv6_only();
-        assert_eq!(observed.ip_ver_pref, IpVersionPreference::Ipv6Only);
+        assert_eq!(observed.ip_ver_pref, Some(IpVersionPreference::Ipv6Only));
     }
 
     #[test]
     fn streamprefs_ipv6_preferred() {
         let mut observed = StreamPrefs::new();
         observed.ipv6_preferred();
-        assert_eq!(observed.ip_ver_pref, IpVersionPreference::Ipv6Preferred);
+        assert_eq!(
+            observed.ip_ver_pref,
+            Some(IpVersionPreference::Ipv6Preferred)
+        );
     }
 
     #[test]
     fn streamprefs_ipv4_only() {
         let mut observed = StreamPrefs::new();
         observed.ipv4_only();
-        assert_eq!(observed.ip_ver_pref, IpVersionPreference::Ipv4Only);
+        assert_eq!(observed.ip_ver_pref, Some(IpVersionPreference::Ipv4Only));
     }
 
     #[test]
     fn streamprefs_ipv4_preferred() {
         let mut observed = StreamPrefs::new();
         observed.ipv4_preferred();
-        assert_eq!(observed.ip_ver_pref, IpVersionPreference::Ipv4Preferred);
+        assert_eq!(
+            observed.ip_ver_pref,
+            Some(IpVersionPreference::Ipv4Preferred)
+        );
+    }
+
+    #[test]
+    fn ip_ver_pref_from_channel_config() {
+        use tor_chanmgr::ChannelConfig;
+        use IpVersionPreference as IVP;
+
+        assert_eq!(
+            default_ip_ver_pref(&ChannelConfig::default()),
+            IVP::Ipv4Preferred
+        );
+
+        let prefer6 = ChannelConfig::builder().prefer_ipv6(true).build().unwrap();
+        assert_eq!(default_ip_ver_pref(&prefer6), IVP::Ipv6Preferred);
+
+        // Not reaching relays over IPv4 is a preference for IPv6 exit
+        // connections, not a refusal of IPv4 ones.
+        let only6 = ChannelConfig::builder().use_ipv4(false).build().unwrap();
+        assert_eq!(default_ip_ver_pref(&only6), IVP::Ipv6Preferred);
+
+        let only4 = ChannelConfig::builder()
+            .use_ipv6(false)
+            .prefer_ipv6(true)
+            .build()
+            .unwrap();
+        assert_eq!(default_ip_ver_pref(&only4), IVP::Ipv4Preferred);
     }
 
     #[test]
@@ -27,6 +27,7 @@ pub use tor_guardmgr::bridge::BridgeParseError;
 
 use tor_guardmgr::bridge::BridgeConfig;
 use tor_keymgr::config::arti::{ArtiNativeKeystoreConfig, ArtiNativeKeystoreConfigBuilder};
+use tor_proto::stream::StreamBufferWatermarks;
 
 /// Types for configuring how Tor circuits are built.
 pub mod circ {
@@ -87,9 +88,38 @@ pub struct ClientAddrConfig {
     #[cfg(feature = "onion-service-client")]
     #[builder(default = "false")]
     pub(crate) allow_onion_addrs: bool,
+
+    /// What should we do when asked to connect to an IP address, rather than
+    /// a hostname?
+    ///
+    /// Arti never looks up hostnames locally: they are always resolved by the
+    /// exit relay.  But an application that hands us an IP address has
+    /// probably done its own DNS lookup first, which may have leaked the
+    /// hostname to the local resolver.
+    ///
+    /// This option is `allow` by default.
+    #[builder(default)]
+    pub(crate) ip_literals: IpLiteralPolicy,
 }
 impl_standard_builder! { ClientAddrConfig }
 
+/// What to do when asked to connect to an IP address rather than a hostname.
+///
+/// See [`ClientAddrConfigBuilder::ip_literals`].
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)] //
+#[derive(Serialize, Deserialize)] //
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum IpLiteralPolicy {
+    /// Connect to the address.
+    #[default]
+    Allow,
+    /// Connect to the address, but log a warning.
+    Warn,
+    /// Refuse to connect to the address.
+    Reject,
+}
+
 /// Configuration for client behavior relating to stream connection timeouts
 ///
 /// This type is immutable once constructed. To create an object of this type,
@@ -137,6 +167,62 @@ fn default_dns_resolve_ptr_timeout() -> Duration {
     Duration::new(10, 0)
 }
 
+/// Configuration for how much data we buffer for each stream
+///
+/// On circuits that use XON/XOFF flow control, these limits decide when we
+/// ask the other side to stop and resume sending.  On other circuits, we
+/// instead stop granting the other side more SENDME credit above the high
+/// watermark (so it only takes effect there if it is below the SENDME window
+/// of 500 messages), and resume once we are back to the low watermark.
+/// Both limits are counted in DATA messages (of up to 498 bytes each).
+///
+/// This type is immutable once constructed. To create an object of this type,
+/// use [`StreamBufferConfigBuilder`].
+///
+/// You can replace this configuration on a running Arti client.  Doing so will
+/// affect new streams, but will have no effect on existing streams.
+#[derive(Debug, Clone, Builder, Eq, PartialEq)]
+#[builder(build_fn(error = "ConfigBuildError", validate = "Self::validate"))]
+#[builder(derive(Debug, Serialize, Deserialize))]
+#[non_exhaustive]
+pub struct StreamBufferConfig {
+    /// How many messages may be waiting for a stream's reader before we ask
+    /// the other side to stop sending?
+    #[builder(default = "StreamBufferWatermarks::default().high()")]
+    #[builder_field_attr(serde(default))]
+    pub(crate) high_watermark: usize,
+
+    /// After asking the other side to stop sending, how far must the reader
+    /// drain the buffer before we ask it to resume?
+    #[builder(default = "StreamBufferWatermarks::default().low()")]
+    #[builder_field_attr(serde(default))]
+    pub(crate) low_watermark: usize,
+}
+impl_standard_builder! { StreamBufferConfig }
+
+impl StreamBufferConfigBuilder {
+    /// Check that this builder will give a reasonable configuration.
+    fn validate(&self) -> Result<(), ConfigBuildError> {
+        let defaults = StreamBufferWatermarks::default();
+        let high = self.high_watermark.unwrap_or(defaults.high());
+        let low = self.low_watermark.unwrap_or(defaults.low());
+        StreamBufferWatermarks::new(high, low).map_err(|_| ConfigBuildError::Inconsistent {
+            fields: vec!["high_watermark".into(), "low_watermark".into()],
+            problem: "low_watermark must be less than high_watermark, which must not be zero"
+                .into(),
+        })?;
+        Ok(())
+    }
+}
+
+impl StreamBufferConfig {
+    /// Return the watermarks to use for new streams.
+    pub(crate) fn watermarks(&self) -> StreamBufferWatermarks {
+        StreamBufferWatermarks::new(self.high_watermark, self.low_watermark)
+            .expect("stream buffer watermarks weren't validated")
+    }
+}
+
 /// Extension trait for `MistrustBuilder` to convert the error type on
 /// build.
 trait BuilderExt {
@@ -356,13 +442,80 @@ pub struct BridgesConfig {
     /// Configured list of bridges (possibly via pluggable transports)
     #[builder(sub_builder, setter(custom))]
     #[builder_field_attr(serde(default))]
-    bridges: BridgeList,
+    pub(crate) bridges: BridgeList,
 
     /// Configured list of pluggable transports.
     #[builder(sub_builder, setter(custom))]
     #[builder_field_attr(serde(default))]
     #[cfg(feature = "pt-client")]
     pub(crate) transports: TransportConfigList,
+
+    /// Names of pluggable transports that run inside this process.
+    ///
+    /// A program that registers an in-process transport with
+    /// [`ChanMgr::register_in_process_transport`](tor_chanmgr::ChanMgr::register_in_process_transport)
+    /// should list its name here, so that bridges can use it without a
+    /// corresponding entry in `transports`.
+    #[builder(default)]
+    #[cfg(feature = "pt-client")]
+    pub(crate) in_process_transports: Vec<PtTransportName>,
+
+    /// A bridge through which to ask a moat bridge distributor for bridges,
+    /// if no bridges are configured.
+    ///
+    /// This is usually a `meek` bridge line, whose `url` and `front`
+    /// parameters say how to reach the distributor.  Its transport must be
+    /// one of the configured `transports`.
+    ///
+    /// Unless `enabled` is false, we ask the distributor for bridges when we
+    /// bootstrap, and use them as if they were configured.
+    #[builder(
+        field(
+            type = "Option<BridgeConfigBuilder>",
+            build = "self.build_moat_bridge()?"
+        ),
+        setter(custom)
+    )]
+    #[builder_field_attr(serde(default))]
+    #[cfg(feature = "pt-client")]
+    pub(crate) moat_bridge: Option<BridgeConfig>,
+}
+
+#[cfg(feature = "pt-client")]
+impl BridgesConfigBuilder {
+    /// Set the bridge through which to ask a moat bridge distributor for
+    /// bridges, if no bridges are configured.
+    pub fn moat_bridge(&mut self, bridge: BridgeConfigBuilder) -> &mut Self {
+        self.moat_bridge = Some(bridge);
+        self
+    }
+
+    /// Build the `moat_bridge` field.
+    fn build_moat_bridge(&self) -> Result<Option<BridgeConfig>, ConfigBuildError> {
+        self.moat_bridge.as_ref().map(|b| b.build()).transpose()
+    }
+}
+
+#[cfg(feature = "pt-client")]
+impl BridgesConfig {
+    /// If we should ask a moat bridge distributor for bridges, return the
+    /// bridge through which to do so.
+    pub(crate) fn moat_bridge_wanted(&self) -> Option<&BridgeConfig> {
+        if self.bridges.is_empty() && self.enabled != BoolOrAuto::Explicit(false) {
+            self.moat_bridge.as_ref()
+        } else {
+            None
+        }
+    }
+
+    /// Return the names of the pluggable transports that we have configured,
+    /// including the in-process ones.
+    pub(crate) fn transport_names(&self) -> impl Iterator<Item = &PtTransportName> + '_ {
+        self.transports
+            .iter()
+            .flat_map(|t| t.protocols())
+            .chain(self.in_process_transports.iter())
+    }
 }
 
 /// A list of configured transport binaries (type alias for macrology).
@@ -395,7 +548,8 @@ fn validate_pt_config(bridges: &BridgesConfigBuilder) -> Result<(), ConfigBuildE
     use std::collections::HashSet;
     use std::str::FromStr;
 
-    // These are all the protocols that the user has defined
+    // These are all the protocols that the user has defined,
+    // including the ones that run in-process
     let mut protocols_defined: HashSet<PtTransportName> = HashSet::new();
     if let Some(transportlist) = bridges.opt_transports() {
         for protocols in transportlist.iter() {
@@ -404,6 +558,7 @@ fn validate_pt_config(bridges: &BridgesConfigBuilder) -> Result<(), ConfigBuildE
             }
         }
     }
+    protocols_defined.extend(bridges.in_process_transports.iter().flatten().cloned());
 
     // Iterate over all the transports that bridges are going to use
     // If any one is valid, we validate the entire config
@@ -440,11 +595,54 @@ fn validate_pt_config(bridges: &BridgesConfigBuilder) -> Result<(), ConfigBuildE
     }
 
     Err(ConfigBuildError::Inconsistent {
-        fields: ["bridges.bridges", "bridges.transports"].map(Into::into).into_iter().collect(),
-        problem: "Bridges configured, but all bridges unusable due to lack of corresponding pluggable transport in `[bridges.transports]`".into(),
+        fields: ["bridges.bridges", "bridges.transports", "bridges.in_process_transports"].map(Into::into).into_iter().collect(),
+        problem: "Bridges configured, but all bridges unusable due to lack of corresponding pluggable transport in `[bridges.transports]` or `bridges.in_process_transports`".into(),
     })
 }
 
+/// Check that the bridge through which we ask a moat bridge distributor for
+/// bridges, if any, uses a configured pluggable transport.
+#[cfg(feature = "pt-client")]
+fn validate_moat_config(bridges: &BridgesConfigBuilder) -> Result<(), ConfigBuildError> {
+    use std::str::FromStr;
+
+    let Some(moat_bridge) = &bridges.moat_bridge else {
+        return Ok(());
+    };
+    let protocol = moat_bridge
+        .get_transport()
+        .and_then(|t| TransportId::from_str(t).ok())
+        .and_then(TransportId::into_pluggable);
+    let configured = protocol.is_some_and(|protocol| {
+        bridges
+            .opt_transports()
+            .iter()
+            .flat_map(|transports| transports.iter())
+            .any(|transport| transport.get_protocols().contains(&protocol))
+            || bridges
+                .in_process_transports
+                .iter()
+                .flatten()
+                .any(|name| name == &protocol)
+    });
+
+    if configured {
+        Ok(())
+    } else {
+        Err(ConfigBuildError::Inconsistent {
+            fields: [
+                "bridges.moat_bridge",
+                "bridges.transports",
+                "bridges.in_process_transports",
+            ]
+                .map(Into::into)
+                .into_iter()
+                .collect(),
+            problem: "bridges.moat_bridge must use a pluggable transport from `[bridges.transports]` or `bridges.in_process_transports`".into(),
+        })
+    }
+}
+
 /// Check that the bridge configuration is right
 #[allow(clippy::unnecessary_wraps)]
 fn validate_bridges_config(bridges: &BridgesConfigBuilder) -> Result<(), ConfigBuildError> {
@@ -478,6 +676,7 @@ fn validate_bridges_config(bridges: &BridgesConfigBuilder) -> Result<(), ConfigB
         ) {
             validate_pt_config(bridges)?;
         }
+        validate_moat_config(bridges)?;
     }
 
     Ok(())
@@ -598,6 +797,7 @@ pub struct TorClientConfig {
     pub(crate) bridges: BridgesConfig,
 
     /// Information about how to build paths through the network.
+    #[as_ref]
     #[builder(sub_builder)]
     #[builder_field_attr(serde(default))]
     pub(crate) channel: ChannelConfig,
@@ -630,6 +830,11 @@ pub struct TorClientConfig {
     #[builder_field_attr(serde(default))]
     pub(crate) stream_timeouts: StreamTimeoutConfig,
 
+    /// Information about how much data to buffer for each stream.
+    #[builder(sub_builder)]
+    #[builder_field_attr(serde(default))]
+    pub(crate) stream_buffers: StreamBufferConfig,
+
     /// Information about vanguards.
     #[builder(sub_builder)]
     #[builder_field_attr(serde(default))]
@@ -742,6 +947,34 @@ impl TorClientConfig {
     pub fn keystore(&self) -> ArtiNativeKeystoreConfig {
         self.storage.keystore()
     }
+
+    /// Return every directory in which a client with this configuration
+    /// may read or write files.
+    ///
+    /// This is the state directory, the cache directory, and the directory
+    /// of every secondary keystore.
+    /// (Everything else that the client stores, including its primary keystore,
+    /// lives in one of the first two.)
+    ///
+    /// This is meant for callers that need to declare, in advance, which parts
+    /// of the filesystem the client will use: for example, in order to sandbox it.
+    pub fn storage_dirs(&self) -> Result<Vec<PathBuf>, ConfigBuildError> {
+        let mut dirs = vec![
+            self.storage.expand_state_dir()?,
+            self.storage.expand_cache_dir()?,
+        ];
+        for secondary in self.storage.keystore().secondary() {
+            let dir = secondary
+                .path()
+                .path()
+                .map_err(|e| ConfigBuildError::Invalid {
+                    field: "storage.keystore.secondary.path".to_owned(),
+                    problem: e.to_string(),
+                })?;
+            dirs.push(dir);
+        }
+        Ok(dirs)
+    }
 }
 
 impl TorClientConfigBuilder {
@@ -855,12 +1088,44 @@ mod test {
             .request_max_retries(22)
             .request_loyalty(3600 * sec);
         bld.address_filter().allow_local_addrs(true);
+        bld.stream_buffers().high_watermark(100).low_watermark(20);
 
         let val = bld.build().unwrap();
 
         assert_ne!(val, TorClientConfig::default());
     }
 
+    #[test]
+    fn stream_buffers() {
+        let cfg = StreamBufferConfig::default();
+        assert_eq!(cfg.watermarks(), StreamBufferWatermarks::default());
+
+        let cfg = StreamBufferConfig::builder()
+            .high_watermark(100)
+            .low_watermark(20)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.watermarks().high(), 100);
+        assert_eq!(cfg.watermarks().low(), 20);
+
+        // The low watermark must be below the high one.
+        assert!(StreamBufferConfig::builder()
+            .high_watermark(100)
+            .low_watermark(100)
+            .build()
+            .is_err());
+        assert!(StreamBufferConfig::builder()
+            .high_watermark(0)
+            .low_watermark(0)
+            .build()
+            .is_err());
+        // ... including when one of them is left at its default.
+        assert!(StreamBufferConfig::builder()
+            .high_watermark(10)
+            .build()
+            .is_err());
+    }
+
     #[test]
     fn bridges_supported() {
         /// checks that when s is processed as TOML for a client config,
@@ -923,6 +1188,20 @@ mod test {
         assert_eq!(dflt.len(), 2);
     }
 
+    #[test]
+    fn storage_dirs() {
+        let cfg = TorClientConfigBuilder::from_directories("/var/lib/arti", "/var/cache/arti")
+            .build()
+            .unwrap();
+        assert_eq!(
+            cfg.storage_dirs().unwrap(),
+            vec![
+                PathBuf::from("/var/lib/arti"),
+                PathBuf::from("/var/cache/arti")
+            ]
+        );
+    }
+
     #[test]
     #[cfg(feature = "pt-client")]
     fn check_bridge_pt() {
@@ -1000,6 +1279,18 @@ mod test {
                 "#,
                 Ok(()),
             ),
+            (
+                r#"
+                    # One obfs4 bridge with an in-process transport.
+                    [bridges]
+                    enabled = true
+                    bridges = [
+                        "obfs4 bridge.example.net:80 $0bac39417268b69b9f514e7f63fa6fba1a788958 ed25519:dGhpcyBpcyBbpmNyZWRpYmx5IHNpbGx5ISEhISEhISA iat-mode=1",
+                    ]
+                    in_process_transports = ["obfs4"]
+                "#,
+                Ok(()),
+            ),
             (
                 r#"
                     # Transport is both managed and unmanaged.
@@ -16,6 +16,8 @@ use tor_error::{ErrorKind, HasKind};
 
 use crate::TorAddrError;
 #[cfg(feature = "onion-service-client")]
+use tor_hsclient::HsConnFailure;
+#[cfg(feature = "onion-service-client")]
 use tor_hscrypto::pk::HsId;
 
 pub use hint::HintableError;
@@ -158,6 +160,10 @@ enum ErrorDetail {
     #[error("Error setting up the persistent state manager")]
     StateMgrSetup(#[source] tor_persist::Error),
 
+    /// Error saving our persistent state.
+    #[error("Unable to save persistent state")]
+    StateFlush(#[source] tor_circmgr::Error),
+
     /// Error setting up the hidden service client connector.
     #[error("Error setting up the hidden service client connector")]
     #[cfg(feature = "onion-service-client")]
@@ -209,6 +215,12 @@ enum ErrorDetail {
     #[error("Timed out while waiting for answer from exit")]
     ExitTimeout,
 
+    /// We were asked to open a stream after [`TorClient::shutdown`](crate::TorClient::shutdown)
+    /// was called.
+    #[error("Client is shutting down")]
+    #[cfg(feature = "experimental-api")]
+    ShuttingDown,
+
     /// Onion services are not compiled in, but we were asked to connect to one.
     #[error("Rejecting .onion address; feature onion-service-client not compiled in")]
     OnionAddressNotSupported,
@@ -246,6 +258,11 @@ enum ErrorDetail {
     #[error("Cannot connect to a local-only address without enabling allow_local_addrs")]
     LocalAddress,
 
+    /// Address was an IP address, and we have been configured not to permit
+    /// connecting to those.
+    #[error("Cannot connect to an IP address while address_filter.ip_literals is \"reject\"")]
+    IpLiteralRejected,
+
     /// Building configuration for the client failed.
     #[error("Problem with configuration")]
     Configuration(#[from] tor_config::ConfigBuildError),
@@ -259,6 +276,21 @@ enum ErrorDetail {
     #[error("Problem with a pluggable transport")]
     PluggableTransport(#[from] tor_ptmgr::err::PtError),
 
+    /// We couldn't connect to a moat bridge distributor.
+    #[cfg(feature = "pt-client")]
+    #[error("Unable to connect to bridge distributor")]
+    MoatConnect(#[source] tor_chanmgr::Error),
+
+    /// A moat bridge distributor didn't give us any bridges.
+    #[cfg(feature = "pt-client")]
+    #[error("Unable to learn bridges from bridge distributor")]
+    Moat(#[source] tor_guardmgr::bridge::moat::MoatError),
+
+    /// We took too long to connect to a moat bridge distributor.
+    #[cfg(feature = "pt-client")]
+    #[error("Timed out connecting to bridge distributor")]
+    MoatTimeout,
+
     /// We encountered a problem while inspecting or creating a directory.
     #[error("Problem accessing filesystem")]
     FsMistrust(#[from] fs_mistrust::Error),
@@ -355,6 +387,22 @@ impl Error {
     }
 }
 
+#[cfg(feature = "onion-service-client")]
+impl Error {
+    /// If this error came from a failed attempt to connect to an onion service,
+    /// return which step of the connection failed.
+    ///
+    /// This is more specific than [`kind`](`tor_error::HasKind::kind`),
+    /// and is suitable for use with the extended SOCKS5 reply codes
+    /// that Tor Browser understands.
+    pub fn onion_service_failure(&self) -> Option<HsConnFailure> {
+        match &*self.detail {
+            ErrorDetail::ObtainHsCircuit { cause, .. } => cause.failure(),
+            _ => None,
+        }
+    }
+}
+
 impl ErrorDetail {
     /// Construct a new `Error` from a `SpawnError`.
     pub(crate) fn from_spawn(spawning: &'static str, err: SpawnError) -> ErrorDetail {
@@ -386,6 +434,8 @@ impl tor_error::HasKind for ErrorDetail {
             #[cfg(feature = "onion-service-client")]
             E::ObtainHsCircuit { cause, .. } => cause.kind(),
             E::ExitTimeout => EK::RemoteNetworkTimeout,
+            #[cfg(feature = "experimental-api")]
+            E::ShuttingDown => EK::ArtiShuttingDown,
             E::BootstrapRequired { .. } => EK::BootstrapRequired,
             E::GuardMgrSetup(e) => e.kind(),
             #[cfg(all(
@@ -398,11 +448,18 @@ impl tor_error::HasKind for ErrorDetail {
             E::CircMgrSetup(e) => e.kind(),
             E::DirMgrSetup(e) => e.kind(),
             E::StateMgrSetup(e) => e.kind(),
+            E::StateFlush(e) => e.kind(),
             #[cfg(feature = "onion-service-client")]
             E::HsClientConnectorSetup(e) => e.kind(),
             E::DirMgrBootstrap(e) => e.kind(),
             #[cfg(feature = "pt-client")]
             E::PluggableTransport(e) => e.kind(),
+            #[cfg(feature = "pt-client")]
+            E::MoatConnect(e) => e.kind(),
+            #[cfg(feature = "pt-client")]
+            E::Moat(e) => e.kind(),
+            #[cfg(feature = "pt-client")]
+            E::MoatTimeout => EK::TorAccessFailed,
             E::StreamFailed { cause, .. } => cause.kind(),
             E::StateAccess(e) => e.kind(),
             E::Configuration(e) => e.kind(),
@@ -419,6 +476,7 @@ impl tor_error::HasKind for ErrorDetail {
             // TODO Should delegate to TorAddrError EK
             E::Address(_) | E::InvalidHostname => EK::InvalidStreamTarget,
             E::LocalAddress => EK::ForbiddenStreamTarget,
+            E::IpLiteralRejected => EK::ForbiddenStreamTarget,
             E::ChanMgrSetup(e) => e.kind(),
             E::NoDir { error, .. } => error.kind(),
             E::Keystore(e) => e.kind(),
@@ -0,0 +1,135 @@
+//! Canonicalization of the hostnames in stream targets.
+//!
+//! Every hostname that we're asked to connect to or to resolve passes through
+//! [`canonicalize_hostname`] before we look at it: this happens when we make a
+//! [`TorAddr`](crate::TorAddr) (which is what [`TorClient::connect`](crate::TorClient::connect)
+//! and the RPC connect methods take), and the SOCKS proxy applies it to the
+//! hostnames it receives before doing anything else with them.
+//!
+//! That way, an exit only ever sees one spelling of each name,
+//! and our own checks (for `.onion` addresses, for `localhost`, and so on)
+//! can't be bypassed by writing a name in some unusual way.
+
+use std::net::IpAddr;
+
+use crate::TorAddrError;
+
+/// Return the canonical form of `hostname`.
+///
+/// If `hostname` is an IP address, its canonical form is the usual textual form
+/// of that address.
+///
+/// Otherwise, we:
+///  * reject it if it contains a NUL, whitespace, or any other control character;
+///  * convert it to its ASCII form using [UTS #46] processing: this lowercases it,
+///    maps any lookalike dots to `.`, and converts internationalized labels to
+///    punycode (`xn--...`) labels;
+///  * remove a single trailing `.`, so that `example.com.` and `example.com`
+///    are the same name;
+///  * reject it if the result isn't a valid hostname.
+///
+/// [UTS #46]: https://www.unicode.org/reports/tr46/
+pub fn canonicalize_hostname(hostname: &str) -> Result<String, TorAddrError> {
+    if let Ok(ip) = hostname.parse::<IpAddr>() {
+        return Ok(ip.to_string());
+    }
+
+    if hostname
+        .chars()
+        .any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(TorAddrError::InvalidHostname);
+    }
+
+    let mut ascii = idna::domain_to_ascii(hostname).map_err(|_| TorAddrError::InvalidHostname)?;
+    if ascii.ends_with('.') {
+        ascii.pop();
+    }
+
+    if is_valid_hostname(&ascii) {
+        Ok(ascii)
+    } else {
+        Err(TorAddrError::InvalidHostname)
+    }
+}
+
+/// Check whether `hostname` is a valid hostname or not.
+///
+/// (Note that IPv6 addresses don't follow these rules.)
+pub(crate) fn is_valid_hostname(hostname: &str) -> bool {
+    hostname_validator::is_valid(hostname)
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    #[test]
+    fn validate_hostname() {
+        // Valid hostname tests
+        assert!(is_valid_hostname("torproject.org"));
+        assert!(is_valid_hostname("Tor-Project.org"));
+        assert!(is_valid_hostname("example.onion"));
+        assert!(is_valid_hostname("some.example.onion"));
+
+        // Invalid hostname tests
+        assert!(!is_valid_hostname("-torproject.org"));
+        assert!(!is_valid_hostname("_torproject.org"));
+        assert!(!is_valid_hostname("tor_project1.org"));
+        assert!(!is_valid_hostname("iwanna$money.org"));
+    }
+
+    #[test]
+    fn canonical() {
+        let canon = |s: &str| canonicalize_hostname(s).unwrap();
+
+        assert_eq!(canon("www.torproject.org"), "www.torproject.org");
+        assert_eq!(canon("WWW.TorProject.ORG"), "www.torproject.org");
+        assert_eq!(canon("www.torproject.org."), "www.torproject.org");
+        assert_eq!(canon("bücher.example"), "xn--bcher-kva.example");
+        assert_eq!(canon("BÜCHER.example"), "xn--bcher-kva.example");
+        assert_eq!(canon("xn--bcher-kva.example"), "xn--bcher-kva.example");
+        // An ideographic full stop is just another way to write a dot.
+        assert_eq!(canon("example\u{3002}onion"), "example.onion");
+        assert_eq!(canon("Example.ONION."), "example.onion");
+
+        assert_eq!(canon("198.51.100.7"), "198.51.100.7");
+        assert_eq!(canon("2001:DB8::0042"), "2001:db8::42");
+    }
+
+    #[test]
+    fn rejected() {
+        for bad in [
+            "",
+            ".",
+            "example.com..",
+            "exa mple.com",
+            "example.com ",
+            "\texample.com",
+            "example\0.com",
+            "example.com\0",
+            "example\n.com",
+            "iwanna$money.org",
+            "-torproject.org",
+        ] {
+            assert_eq!(
+                canonicalize_hostname(bad),
+                Err(TorAddrError::InvalidHostname),
+                "{bad:?}"
+            );
+        }
+    }
+}
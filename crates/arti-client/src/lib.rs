@@ -44,6 +44,9 @@
 mod address;
 mod builder;
 mod client;
+mod hostname;
+#[cfg(feature = "pt-client")]
+mod moat;
 #[cfg(feature = "rpc")]
 pub mod rpc;
 mod util;
@@ -55,9 +58,11 @@ pub use address::{DangerouslyIntoTorAddr, IntoTorAddr, TorAddr, TorAddrError};
 pub use builder::{TorClientBuilder, MAX_LOCAL_RESOURCE_TIMEOUT};
 pub use client::{BootstrapBehavior, DormantMode, StreamPrefs, TorClient};
 pub use config::TorClientConfig;
+pub use hostname::canonicalize_hostname;
 
 pub use tor_circmgr::isolation;
 pub use tor_circmgr::IsolationToken;
+pub use tor_circmgr::NetworkReachability;
 pub use tor_error::{ErrorKind, HasKind};
 pub use tor_proto::stream::{DataReader, DataStream, DataWriter};
 
@@ -73,6 +78,10 @@ pub type Result<T> = std::result::Result<T, Error>;
 #[cfg(feature = "experimental-api")]
 pub use builder::DirProviderBuilder;
 
+#[cfg(feature = "experimental-api")]
+#[cfg_attr(docsrs, doc(cfg(feature = "experimental-api")))]
+pub use tor_persist::{CheckProblem, CheckReport, CheckSeverity};
+
 #[cfg(all(feature = "onion-service-client", feature = "experimental-api"))]
 #[cfg_attr(
     docsrs,
@@ -83,6 +92,14 @@ pub use {
     tor_keymgr::KeystoreSelector,
 };
 
+#[cfg(feature = "onion-service-client")]
+#[cfg_attr(docsrs, doc(cfg(feature = "onion-service-client")))]
+pub use tor_hsclient::HsConnFailure;
+
 #[cfg(feature = "geoip")]
 #[cfg_attr(docsrs, doc(cfg(feature = "geoip")))]
 pub use tor_geoip::CountryCode;
+
+#[cfg(feature = "pt-client")]
+#[cfg_attr(docsrs, doc(cfg(feature = "pt-client")))]
+pub use tor_guardmgr::bridge::moat::{MoatBridges, MoatError, SettingsRequest};
@@ -0,0 +1,71 @@
+//! Keeping track of bridges that we learned from a moat bridge distributor.
+
+use std::borrow::Cow;
+
+use tor_guardmgr::bridge::BridgeConfig;
+
+use crate::config::TorClientConfig;
+
+/// What a [`TorClient`](crate::TorClient) knows about learning bridges from a
+/// moat bridge distributor.
+///
+/// The bridges that we learn are not part of anybody's configuration file, so
+/// we have to put them back into every configuration that we are given, for
+/// as long as that configuration still asks for them.
+#[derive(Debug, Default)]
+pub(crate) struct MoatState {
+    /// Our latest configuration, if it asks us to learn bridges from a moat
+    /// bridge distributor.
+    config: Option<TorClientConfig>,
+    /// The bridges that we have learned, if any.
+    bridges: Vec<BridgeConfig>,
+}
+
+impl MoatState {
+    /// Return a new `MoatState` for a client whose configuration is `config`.
+    pub(crate) fn new(config: &TorClientConfig) -> Self {
+        let mut state = MoatState::default();
+        state.note_config(config);
+        state
+    }
+
+    /// Note that our configuration is now `config`.
+    ///
+    /// If `config` no longer asks for bridges from a moat bridge distributor,
+    /// we forget the bridges that we learned.
+    pub(crate) fn note_config(&mut self, config: &TorClientConfig) {
+        if config.bridges.moat_bridge_wanted().is_some() {
+            self.config = Some(config.clone());
+        } else {
+            self.config = None;
+            self.bridges.clear();
+        }
+    }
+
+    /// Return the configuration that we should use in place of `config`.
+    ///
+    /// That is `config` with the bridges that we learned, if it asks for
+    /// them, and we have learned some.  Otherwise, it is just `config`.
+    pub(crate) fn effective_config<'c>(
+        &self,
+        config: &'c TorClientConfig,
+    ) -> Cow<'c, TorClientConfig> {
+        if self.bridges.is_empty() || config.bridges.moat_bridge_wanted().is_none() {
+            return Cow::Borrowed(config);
+        }
+        let mut config = config.clone();
+        config.bridges.bridges = self.bridges.clone();
+        Cow::Owned(config)
+    }
+
+    /// If we should ask a moat bridge distributor for bridges, return our
+    /// configuration, which says how to reach it.
+    pub(crate) fn wanted(&self) -> Option<&TorClientConfig> {
+        self.config.as_ref().filter(|_| self.bridges.is_empty())
+    }
+
+    /// Remember that we learned `bridges` from a moat bridge distributor.
+    pub(crate) fn learned(&mut self, bridges: Vec<BridgeConfig>) {
+        self.bridges = bridges;
+    }
+}
@@ -12,6 +12,8 @@ use tor_rtcompat::Runtime;
 
 use crate::{StreamPrefs, TorAddr, TorClient};
 
+mod events;
+
 impl<R: Runtime> TorClient<R> {
     /// Ensure that every RPC method is registered for this instantiation of TorClient.
     ///
@@ -21,6 +23,7 @@ impl<R: Runtime> TorClient<R> {
         rpc::invoker_ent_list![
             get_client_status::<R>,
             watch_client_status::<R>,
+            events::subscribe::<R>,
             isolated_client::<R>,
             @special client_connect_with_prefs::<R>,
             @special client_resolve_with_prefs::<R>,
@@ -63,6 +66,20 @@ struct ClientStatusInfo {
     /// If present, a description of possible problem(s) that may be stopping
     /// the client from using the Tor network.
     blocked: Option<String>,
+    /// Every problem that we could identify that may be stopping the client
+    /// from bootstrapping, most likely root cause first.
+    problems: Vec<ProblemInfo>,
+}
+
+/// RPC result: A single problem reported in a ClientStatusInfo.
+#[derive(Serialize, Deserialize)]
+struct ProblemInfo {
+    /// A stable, machine-readable name for the kind of problem.
+    kind: String,
+    /// A human-readable description of what we observed.
+    message: String,
+    /// A human-readable suggestion for how to fix the problem.
+    hint: String,
 }
 
 impl From<crate::status::BootstrapStatus> for ClientStatusInfo {
@@ -70,10 +87,21 @@ impl From<crate::status::BootstrapStatus> for ClientStatusInfo {
         let ready = s.ready_for_traffic();
         let fraction = s.as_frac();
         let blocked = s.blocked().map(|b| b.to_string());
+        let problems = s
+            .failure_report()
+            .problems()
+            .iter()
+            .map(|p| ProblemInfo {
+                kind: p.kind().as_str().to_string(),
+                message: p.message().to_string(),
+                hint: p.hint().to_string(),
+            })
+            .collect();
         Self {
             ready,
             fraction,
             blocked,
+            problems,
         }
     }
 }
@@ -0,0 +1,485 @@
+//! RPC support for subscribing to global events on a `TorClient`.
+//!
+//! A client invokes `arti:subscribe` on a `TorClient` to get a new
+//! subscription object, and then `arti:watch_events` on that object to
+//! receive events as updates.  Events are filtered on our side, so that the
+//! client only receives the kinds of events it asked for.
+
+use derive_deftly::Deftly;
+use futures::future::ready;
+use futures::stream::{self, BoxStream, SelectAll};
+use futures::{SinkExt as _, Stream, StreamExt as _};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tor_linkspec::HasRelayIds as _;
+use tor_netdir::{DirEvent, NetDirProvider, Timeliness};
+use tor_rpcbase::{self as rpc, templates::*};
+use tor_rtcompat::Runtime;
+
+use super::ClientStatusInfo;
+use crate::TorClient;
+
+/// A kind of global event that an RPC client can subscribe to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum EventKind {
+    /// A change in the client's bootstrap status.
+    Bootstrap,
+    /// The arrival of a new consensus.
+    Consensus,
+    /// A change in the client's list of primary guards.
+    Guards,
+    /// A change in the status of an onion service that the client is running.
+    OnionService,
+}
+
+impl EventKind {
+    /// Every kind of event.
+    const ALL: [EventKind; 4] = [
+        EventKind::Bootstrap,
+        EventKind::Consensus,
+        EventKind::Guards,
+        EventKind::OnionService,
+    ];
+}
+
+/// RPC method: Create a subscription to global events on a client.
+///
+/// Returns the ID of a new subscription object;
+/// invoke `arti:watch_events` on that object to receive the events.
+#[derive(Deftly, Debug, Serialize, Deserialize)]
+#[derive_deftly(rpc::DynMethod)]
+#[deftly(rpc(method_name = "arti:subscribe"))]
+pub(super) struct Subscribe {
+    /// The kinds of event to deliver.
+    ///
+    /// If absent, every kind of event is delivered.
+    #[serde(default)]
+    events: Option<Vec<EventKind>>,
+}
+
+impl rpc::RpcMethod for Subscribe {
+    type Output = rpc::SingletonId;
+    type Update = rpc::NoUpdates;
+}
+
+/// RPC method: Run forever, delivering each event from a subscription as an update.
+///
+/// Each time this method is invoked, it begins by reporting the current state
+/// of everything that the subscription covers,
+/// and then reports changes as they happen.
+#[derive(Deftly, Debug, Serialize, Deserialize)]
+#[derive_deftly(rpc::DynMethod)]
+#[deftly(rpc(method_name = "arti:watch_events"))]
+struct WatchEvents {}
+
+impl rpc::RpcMethod for WatchEvents {
+    type Output = rpc::Nil;
+    type Update = EventInfo;
+}
+
+/// RPC update: A single global event.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum EventInfo {
+    /// The client's bootstrap status is as given.
+    Bootstrap {
+        /// The new status.
+        status: ClientStatusInfo,
+    },
+    /// We have a new consensus.
+    Consensus {
+        /// The time at which the consensus became valid, in RFC 3339 format.
+        valid_after: String,
+        /// The time until which the consensus is the newest one we expect.
+        fresh_until: String,
+        /// The time after which the consensus is no longer valid.
+        valid_until: String,
+    },
+    /// The client's primary guards have changed.
+    Guards {
+        /// The identities of the primary guards, in preference order.
+        primary: Vec<String>,
+    },
+    /// The status of an onion service has changed.
+    OnionService {
+        /// The nickname of the service.
+        nickname: String,
+        /// The service's new high-level state.
+        state: OnionServiceState,
+        /// The service's current problem, if it has one.
+        problem: Option<OnionServiceProblem>,
+    },
+}
+
+/// The high-level state of an onion service, as reported in an [`EventInfo`].
+///
+/// See `tor_hsservice::status::State` for the meaning of each state.
+#[cfg_attr(not(feature = "onion-service-service"), allow(dead_code))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum OnionServiceState {
+    /// The service is not launched.
+    Shutdown,
+    /// The service is bootstrapping.
+    Bootstrapping,
+    /// The service is running, but we're not satisfied with its introduction points.
+    Degraded,
+    /// The service is running.
+    Running,
+    /// The service is trying to recover from a minor interruption.
+    Recovering,
+    /// The service is not working.
+    Broken,
+    /// A state that this version of Arti doesn't know how to report.
+    Unknown,
+}
+
+/// A problem with an onion service, as reported in an [`EventInfo`].
+#[cfg_attr(not(feature = "onion-service-service"), allow(dead_code))]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+struct OnionServiceProblem {
+    /// What kind of problem this is.
+    kind: OnionServiceProblemKind,
+    /// A human-readable description of the problem.
+    message: String,
+}
+
+/// A kind of [`OnionServiceProblem`].
+#[cfg_attr(not(feature = "onion-service-service"), allow(dead_code))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum OnionServiceProblemKind {
+    /// A fatal error occurred.
+    Runtime,
+    /// We failed to upload a descriptor.
+    DescriptorUpload,
+    /// We failed to establish one or more introduction points.
+    IntroductionPoints,
+    /// A kind of problem that this version of Arti doesn't know how to report.
+    Unknown,
+}
+
+#[cfg(feature = "onion-service-service")]
+impl From<tor_hsservice::status::State> for OnionServiceState {
+    fn from(state: tor_hsservice::status::State) -> Self {
+        use tor_hsservice::status::State as S;
+        match state {
+            S::Shutdown => Self::Shutdown,
+            S::Bootstrapping => Self::Bootstrapping,
+            S::Degraded => Self::Degraded,
+            S::Running => Self::Running,
+            S::Recovering => Self::Recovering,
+            S::Broken => Self::Broken,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[cfg(feature = "onion-service-service")]
+impl From<&tor_hsservice::status::Problem> for OnionServiceProblem {
+    fn from(problem: &tor_hsservice::status::Problem) -> Self {
+        use tor_hsservice::status::Problem as P;
+        let (kind, message) = match problem {
+            P::Runtime(e) => (OnionServiceProblemKind::Runtime, e.to_string()),
+            P::DescriptorUpload(e) => (OnionServiceProblemKind::DescriptorUpload, e.to_string()),
+            P::Ipt(errs) => (
+                OnionServiceProblemKind::IntroductionPoints,
+                errs.iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            ),
+            _ => (
+                OnionServiceProblemKind::Unknown,
+                "Unrecognized problem".to_string(),
+            ),
+        };
+        OnionServiceProblem { kind, message }
+    }
+}
+
+/// An object that delivers the global events of a client, of selected kinds.
+///
+/// Created by `arti:subscribe`.
+#[derive(Deftly)]
+#[derive_deftly(Object)]
+struct EventSubscription {
+    /// The client whose events we report.
+    source: Arc<dyn EventSource>,
+    /// The kinds of event that we report.
+    kinds: HashSet<EventKind>,
+}
+
+/// Type-erased `TorClient`, as used within an [`EventSubscription`].
+trait EventSource: Send + Sync {
+    /// Return a stream of the events of the kinds in `kinds`,
+    /// starting with a report of the current state of each.
+    fn events(&self, kinds: &HashSet<EventKind>) -> BoxStream<'static, EventInfo>;
+}
+
+impl<R: Runtime> EventSource for TorClient<R> {
+    fn events(&self, kinds: &HashSet<EventKind>) -> BoxStream<'static, EventInfo> {
+        let mut streams: Vec<BoxStream<'static, EventInfo>> = Vec::new();
+
+        if kinds.contains(&EventKind::Bootstrap) {
+            let current = self.bootstrap_status();
+            streams.push(
+                stream::once(ready(current))
+                    .chain(self.bootstrap_events())
+                    .map(|status| EventInfo::Bootstrap {
+                        status: status.into(),
+                    })
+                    .boxed(),
+            );
+        }
+
+        if kinds.contains(&EventKind::Consensus) {
+            let dirmgr = self.dir_provider();
+            let current = consensus_info(dirmgr.as_ref());
+            let updates = dirmgr
+                .events()
+                .filter(|event| ready(*event == DirEvent::NewConsensus))
+                .map(move |_| consensus_info(dirmgr.as_ref()));
+            // We also get NewConsensus events for changes in the network parameters:
+            // only report each consensus once.
+            let consensus = stream::once(ready(current))
+                .chain(updates)
+                .filter_map(ready);
+            streams.push(dedup_by_key(consensus).boxed());
+        }
+
+        if kinds.contains(&EventKind::Guards) {
+            let events = self.primary_guard_events();
+            let current = events.get();
+            let primary = stream::once(ready(current)).chain(events).map(|primary| {
+                let info = EventInfo::Guards {
+                    primary: primary
+                        .iter()
+                        .map(|ids| ids.display_relay_ids().to_string())
+                        .collect(),
+                };
+                (primary, info)
+            });
+            streams.push(dedup_by_key(primary).boxed());
+        }
+
+        #[cfg(feature = "onion-service-service")]
+        if kinds.contains(&EventKind::OnionService) {
+            // This includes the services that are launched after we subscribe.
+            let services = self
+                .onion_service_status_events()
+                .map(|(nickname, events)| {
+                    let nickname = nickname.to_string();
+                    events
+                        .map(move |status| EventInfo::OnionService {
+                            nickname: nickname.clone(),
+                            state: status.state().into(),
+                            problem: status.current_problem().map(Into::into),
+                        })
+                        .boxed()
+                });
+            streams.push(merge_dynamic(services).boxed());
+        }
+
+        stream::select_all(streams).boxed()
+    }
+}
+
+/// Return the `valid_after` time of the current consensus of `dirmgr`,
+/// and an [`EventInfo`] describing it.
+fn consensus_info<P>(dirmgr: &P) -> Option<(std::time::SystemTime, EventInfo)>
+where
+    P: NetDirProvider + ?Sized,
+{
+    let netdir = dirmgr.netdir(Timeliness::Unchecked).ok()?;
+    let lifetime = netdir.lifetime();
+    let fmt = |t| humantime::format_rfc3339(t).to_string();
+    Some((
+        lifetime.valid_after(),
+        EventInfo::Consensus {
+            valid_after: fmt(lifetime.valid_after()),
+            fresh_until: fmt(lifetime.fresh_until()),
+            valid_until: fmt(lifetime.valid_until()),
+        },
+    ))
+}
+
+/// Given a stream of streams, yield the items of every stream that it yields,
+/// in whatever order they arrive.
+///
+/// The result ends once `streams` and every stream that it yielded have ended.
+#[cfg_attr(not(feature = "onion-service-service"), allow(dead_code))]
+fn merge_dynamic<S, T>(streams: S) -> impl Stream<Item = T>
+where
+    S: Stream<Item = BoxStream<'static, T>> + Unpin,
+    T: 'static,
+{
+    stream::unfold(
+        (streams.fuse(), SelectAll::new()),
+        |(mut streams, mut running)| async move {
+            loop {
+                futures::select! {
+                    new = streams.select_next_some() => running.push(new),
+                    item = running.select_next_some() => return Some((item, (streams, running))),
+                    complete => return None,
+                }
+            }
+        },
+    )
+}
+
+/// Given a stream of `(key, item)` pairs, yield each item whose key differs
+/// from the key of the item before it.
+fn dedup_by_key<K, T, S>(stream: S) -> impl Stream<Item = T>
+where
+    K: PartialEq + Send + 'static,
+    S: Stream<Item = (K, T)>,
+{
+    stream
+        .scan(None, |last: &mut Option<K>, (key, item)| {
+            let repeated = last.as_ref() == Some(&key);
+            *last = Some(key);
+            ready(Some((!repeated).then_some(item)))
+        })
+        .filter_map(ready)
+}
+
+/// Invocable function to run [`Subscribe`] on a [`TorClient`].
+pub(super) async fn subscribe<R: Runtime>(
+    client: Arc<TorClient<R>>,
+    method: Box<Subscribe>,
+    ctx: Arc<dyn rpc::Context>,
+) -> Result<rpc::SingletonId, rpc::RpcError> {
+    let kinds = match method.events {
+        Some(kinds) => kinds.into_iter().collect(),
+        None => EventKind::ALL.into_iter().collect(),
+    };
+    let subscription = Arc::new(EventSubscription {
+        source: client,
+        kinds,
+    });
+    Ok(rpc::SingletonId::from(ctx.register_owned(subscription)))
+}
+
+/// Invocable function to run [`WatchEvents`] on an [`EventSubscription`].
+async fn watch_events(
+    subscription: Arc<EventSubscription>,
+    _method: Box<WatchEvents>,
+    _ctx: Arc<dyn rpc::Context>,
+    mut updates: rpc::UpdateSink<EventInfo>,
+) -> Result<rpc::Nil, rpc::RpcError> {
+    let mut events = subscription.source.events(&subscription.kinds);
+    while let Some(event) = events.next().await {
+        updates.send(event).await?;
+    }
+
+    // This can only happen if the client exits.
+    Ok(rpc::NIL)
+}
+
+rpc::static_rpc_invoke_fn! {
+    watch_events;
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    #[test]
+    fn dedup() {
+        let input = [(1, "a"), (1, "b"), (2, "c"), (1, "d"), (1, "e"), (3, "f")];
+        let output: Vec<_> =
+            futures::executor::block_on(dedup_by_key(stream::iter(input)).collect());
+        assert_eq!(output, vec!["a", "c", "d", "f"]);
+    }
+
+    #[test]
+    fn subscribe_params() {
+        let s: Subscribe = serde_json::from_str(r#"{}"#).unwrap();
+        assert!(s.events.is_none());
+        let s: Subscribe =
+            serde_json::from_str(r#"{"events": ["bootstrap", "onion_service"]}"#).unwrap();
+        assert_eq!(
+            s.events.unwrap(),
+            vec![EventKind::Bootstrap, EventKind::OnionService]
+        );
+        assert!(serde_json::from_str::<Subscribe>(r#"{"events": ["weather"]}"#).is_err());
+    }
+
+    #[test]
+    fn merge() {
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let merged = merge_dynamic(rx);
+        tx.unbounded_send(stream::iter([1, 2]).boxed()).unwrap();
+        tx.unbounded_send(stream::iter([3]).boxed()).unwrap();
+        drop(tx);
+        let mut output: Vec<_> = futures::executor::block_on(merged.collect());
+        output.sort();
+        assert_eq!(output, vec![1, 2, 3]);
+
+        // Streams that arrive after the others have ended are still merged.
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let mut merged = Box::pin(merge_dynamic(rx));
+        tx.unbounded_send(stream::iter([1]).boxed()).unwrap();
+        futures::executor::block_on(async {
+            assert_eq!(merged.next().await, Some(1));
+            tx.unbounded_send(stream::iter([2]).boxed()).unwrap();
+            assert_eq!(merged.next().await, Some(2));
+            drop(tx);
+            assert_eq!(merged.next().await, None);
+        });
+    }
+
+    #[test]
+    fn onion_service_format() {
+        let event = EventInfo::OnionService {
+            nickname: "allium-cepa".into(),
+            state: OnionServiceState::Recovering,
+            problem: Some(OnionServiceProblem {
+                kind: OnionServiceProblemKind::DescriptorUpload,
+                message: "oops".into(),
+            }),
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "kind": "onion_service",
+                "nickname": "allium-cepa",
+                "state": "recovering",
+                "problem": {
+                    "kind": "descriptor_upload",
+                    "message": "oops",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn event_format() {
+        let event = EventInfo::Guards {
+            primary: vec!["ed25519:abc".into()],
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "kind": "guards",
+                "primary": ["ed25519:abc"],
+            })
+        );
+    }
+}
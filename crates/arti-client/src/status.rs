@@ -8,8 +8,9 @@ use educe::Educe;
 use futures::{Stream, StreamExt};
 use tor_basic_utils::skip_fmt;
 use tor_chanmgr::{ConnBlockage, ConnStatus, ConnStatusEvents};
-use tor_circmgr::{ClockSkewEvents, SkewEstimate};
+use tor_circmgr::{ClockSkewEvents, NetworkReachability, ReachabilityEvents, SkewEstimate};
 use tor_dirmgr::{DirBlockage, DirBootstrapStatus};
+use tor_error::ErrorKind;
 use tracing::debug;
 
 /// Information about how ready a [`crate::TorClient`] is to handle requests.
@@ -32,6 +33,8 @@ pub struct BootstrapStatus {
     dir_status: DirBootstrapStatus,
     /// Current estimate of our clock skew.
     skew: Option<SkewEstimate>,
+    /// Our opinion of whether we can reach the network, from our self-tests.
+    reachability: NetworkReachability,
 }
 
 impl BootstrapStatus {
@@ -54,6 +57,20 @@ impl BootstrapStatus {
         self.conn_status.usable() && self.dir_status.usable_at(now)
     }
 
+    /// Return our best guess about whether we can reach the Tor network.
+    ///
+    /// This comes from a periodic self-test, in which we build a throwaway
+    /// circuit and make a small request over it.
+    /// It is [`NetworkReachability::Unknown`] unless the self-test is enabled
+    /// with `circuit_timing.reachability_self_test`.
+    ///
+    /// Unlike [`blocked`](BootstrapStatus::blocked), this can help to distinguish
+    /// a problem with the Tor network from a problem with our own internet connection:
+    /// if we're [`Down`](NetworkReachability::Down), we couldn't build any circuit at all.
+    pub fn reachability(&self) -> NetworkReachability {
+        self.reachability
+    }
+
     /// If the client is unable to make forward progress for some reason, return
     /// that reason.
     ///
@@ -94,6 +111,72 @@ impl BootstrapStatus {
         }
     }
 
+    /// Return a structured report of every problem that seems to be keeping
+    /// the client from bootstrapping.
+    ///
+    /// Where [`blocked`](BootstrapStatus::blocked) reports a single
+    /// problem, this collects all the problems that we can identify from our
+    /// connection status, directory status, clock skew estimate, and
+    /// reachability self-test, along with the kinds of error we have seen
+    /// while downloading directory information.
+    ///
+    /// The report is empty if the client is ready for traffic.
+    ///
+    /// The same caveats apply as for [`blocked`](BootstrapStatus::blocked):
+    /// this is a best-effort diagnostic.
+    pub fn failure_report(&self) -> BootstrapReport {
+        self.failure_report_at(SystemTime::now())
+    }
+
+    /// As [`failure_report`](BootstrapStatus::failure_report), but use `now`
+    /// as the current time.
+    fn failure_report_at(&self, now: SystemTime) -> BootstrapReport {
+        use BootstrapProblemKind as K;
+
+        let mut report = BootstrapReport::default();
+        if self.ready_for_traffic() {
+            return report;
+        }
+
+        if let Some(b) = self.conn_status.blockage() {
+            let kind = match b {
+                ConnBlockage::NoTcp => K::Offline,
+                ConnBlockage::NoHandshake => K::Filtered,
+                ConnBlockage::CertsExpired => K::ClockSkew,
+                _ => K::GuardsUnreachable,
+            };
+            report.add(kind, b.to_string());
+        }
+        if self.skew_is_noteworthy() {
+            let skew = self.skew.as_ref().expect("logic error");
+            report.add(K::ClockSkew, format!("Clock is {}", skew));
+        }
+        if let Some(b) = self.dir_status.blockage(now) {
+            let kind = match b {
+                DirBlockage::ClockSkew { .. } => K::ClockSkew,
+                _ => K::DirectoryStalled,
+            };
+            report.add(kind, b.to_string());
+        }
+        if self.dir_status.consensus_expired_at(now) {
+            report.add(K::ConsensusTooOld, "Our most recent consensus has expired");
+        }
+        if self.reachability == NetworkReachability::Down {
+            report.add(K::NoRoute, "Our self-test was unable to build any circuit");
+        }
+        for (error_kind, n) in self.dir_status.recent_error_kinds() {
+            if let Some(kind) = K::from_error_kind(error_kind) {
+                report.add(
+                    kind,
+                    format!("{} recent directory errors: {}", n, error_kind),
+                );
+            }
+        }
+
+        report.problems.sort_by_key(|p| p.kind);
+        report
+    }
+
     /// Adjust this status based on new connection-status information.
     fn apply_conn_status(&mut self, status: ConnStatus) {
         self.conn_status = status;
@@ -109,6 +192,11 @@ impl BootstrapStatus {
         self.skew = status;
     }
 
+    /// Adjust this status based on new reachability information.
+    fn apply_reachability(&mut self, status: NetworkReachability) {
+        self.reachability = status;
+    }
+
     /// Return true if our current clock skew estimate is considered noteworthy.
     fn skew_is_noteworthy(&self) -> bool {
         matches!(&self.skew, Some(s) if s.noteworthy())
@@ -175,8 +263,186 @@ impl From<ConnBlockage> for BlockageKind {
 }
 
 impl From<DirBlockage> for BlockageKind {
-    fn from(_: DirBlockage) -> Self {
-        BlockageKind::CantBootstrap
+    fn from(b: DirBlockage) -> Self {
+        match b {
+            DirBlockage::ClockSkew { .. } => BlockageKind::ClockSkewed,
+            _ => BlockageKind::CantBootstrap,
+        }
+    }
+}
+
+/// A structured account of why a client is failing to bootstrap.
+///
+/// Returned by [`BootstrapStatus::failure_report`].
+///
+/// The report lists every problem that we could identify, most likely root
+/// cause first.  Each problem has a machine-readable
+/// [`BootstrapProblemKind`] and a human-readable remediation hint.
+#[derive(Clone, Debug, Default)]
+pub struct BootstrapReport {
+    /// The problems we found, ordered by kind.
+    ///
+    /// There is at most one problem of each kind.
+    problems: Vec<BootstrapProblem>,
+}
+
+impl BootstrapReport {
+    /// Return true if we didn't find any problems.
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Return every problem that we found, most likely root cause first.
+    pub fn problems(&self) -> &[BootstrapProblem] {
+        &self.problems
+    }
+
+    /// Return the problem that is most likely to be the root cause, if we found any.
+    pub fn primary(&self) -> Option<&BootstrapProblem> {
+        self.problems.first()
+    }
+
+    /// Record a problem of a given kind.
+    ///
+    /// If we already have a problem of this kind, add `message` to it.
+    fn add(&mut self, kind: BootstrapProblemKind, message: impl Into<Cow<'static, str>>) {
+        let message = message.into();
+        match self.problems.iter_mut().find(|p| p.kind == kind) {
+            Some(p) => p.message = format!("{}; {}", p.message, message).into(),
+            None => self.problems.push(BootstrapProblem { kind, message }),
+        }
+    }
+}
+
+impl fmt::Display for BootstrapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.problems.is_empty() {
+            return write!(f, "No bootstrap problems found");
+        }
+        for (idx, problem) in self.problems.iter().enumerate() {
+            if idx > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// A single problem in a [`BootstrapReport`].
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "{} ({})", "kind", "message")]
+pub struct BootstrapProblem {
+    /// What kind of problem is this?
+    kind: BootstrapProblemKind,
+    /// A human-readable description of what we observed.
+    message: Cow<'static, str>,
+}
+
+impl BootstrapProblem {
+    /// Get a programmatic indication of the kind of problem this is.
+    pub fn kind(&self) -> BootstrapProblemKind {
+        self.kind
+    }
+
+    /// Get a human-readable description of what we observed.
+    pub fn message(&self) -> impl Display + '_ {
+        &self.message
+    }
+
+    /// Get a human-readable suggestion for how to fix this problem.
+    pub fn hint(&self) -> &'static str {
+        self.kind.hint()
+    }
+}
+
+/// A category of problem that can keep a client from bootstrapping.
+///
+/// Variants are declared (and ordered) from the most fundamental problem to
+/// the least: a client that is offline will usually also fail to reach its
+/// guards, for example.
+#[derive(Clone, Copy, Debug, Display, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[non_exhaustive]
+pub enum BootstrapProblemKind {
+    /// We can't make connections to the internet at all.
+    #[display(fmt = "We seem to be offline")]
+    Offline,
+    /// We can make connections, but they seem to be filtered or tampered with.
+    #[display(fmt = "Our internet connection seems filtered")]
+    Filtered,
+    /// Our clock seems to be set incorrectly.
+    #[display(fmt = "Clock is skewed")]
+    ClockSkew,
+    /// None of our guards (or fallback directories) are reachable.
+    #[display(fmt = "Can't reach any guard")]
+    GuardsUnreachable,
+    /// The most recent consensus that we have has expired, and we haven't been
+    /// able to replace it.
+    #[display(fmt = "Consensus is too old")]
+    ConsensusTooOld,
+    /// We couldn't find a usable path through the network.
+    #[display(fmt = "No route through the Tor network")]
+    NoRoute,
+    /// We're downloading directory information, but not making progress.
+    #[display(fmt = "Directory download is stuck")]
+    DirectoryStalled,
+}
+
+impl BootstrapProblemKind {
+    /// Return a short, stable, machine-readable name for this kind of problem.
+    pub fn as_str(&self) -> &'static str {
+        use BootstrapProblemKind as K;
+        match self {
+            K::Offline => "offline",
+            K::Filtered => "filtered",
+            K::ClockSkew => "clock_skew",
+            K::GuardsUnreachable => "guards_unreachable",
+            K::ConsensusTooOld => "consensus_too_old",
+            K::NoRoute => "no_route",
+            K::DirectoryStalled => "directory_stalled",
+        }
+    }
+
+    /// Return a human-readable suggestion for how to fix this kind of problem.
+    pub fn hint(&self) -> &'static str {
+        use BootstrapProblemKind as K;
+        match self {
+            K::Offline => "Check that this computer is connected to the internet.",
+            K::Filtered => {
+                "Your network may be blocking Tor: consider configuring bridges or a pluggable transport."
+            }
+            K::ClockSkew => "Check that your system clock, date, and time zone are set correctly.",
+            K::GuardsUnreachable => {
+                "Check your network connection and any firewall; if Tor is blocked, consider configuring bridges."
+            }
+            K::ConsensusTooOld => {
+                "Wait a while for a new directory to download; if this persists, check your clock and network connection."
+            }
+            K::NoRoute => {
+                "Check for overly restrictive path or exit settings in your configuration."
+            }
+            K::DirectoryStalled => {
+                "Wait a while and try again; if this persists, check the logs for directory errors."
+            }
+        }
+    }
+
+    /// Return the kind of problem that an error of kind `kind`, seen while
+    /// bootstrapping, indicates.
+    ///
+    /// Returns None for kinds of error that don't tell us much about why
+    /// we can't bootstrap.
+    fn from_error_kind(kind: ErrorKind) -> Option<Self> {
+        use BootstrapProblemKind as K;
+        use ErrorKind as EK;
+        match kind {
+            EK::LocalNetworkError => Some(K::Offline),
+            EK::ClockSkew => Some(K::ClockSkew),
+            EK::TorAccessFailed | EK::TorNetworkTimeout => Some(K::GuardsUnreachable),
+            EK::DirectoryExpired => Some(K::ConsensusTooOld),
+            EK::NoPath | EK::NoExit | EK::TorDirectoryUnusable => Some(K::NoRoute),
+            _ => None,
+        }
     }
 }
 
@@ -202,6 +468,9 @@ impl fmt::Display for BootstrapStatus {
                 write!(f, ". Clock is {}", skew)?;
             }
         }
+        if self.reachability != NetworkReachability::Unknown {
+            write!(f, ". Network is {}", self.reachability)?;
+        }
         Ok(())
     }
 }
@@ -220,6 +489,7 @@ pub(crate) async fn report_status(
     conn_status: ConnStatusEvents,
     dir_status: impl Stream<Item = DirBootstrapStatus> + Send + Unpin,
     skew_status: ClockSkewEvents,
+    reachability_status: ReachabilityEvents,
 ) {
     /// Internal enumeration to combine incoming status changes.
     #[allow(clippy::large_enum_variant)]
@@ -230,11 +500,14 @@ pub(crate) async fn report_status(
         Dir(DirBootstrapStatus),
         /// A clock skew change
         Skew(Option<SkewEstimate>),
+        /// A reachability change
+        Reachability(NetworkReachability),
     }
     let mut stream = futures::stream::select_all(vec![
         conn_status.map(Event::Conn).boxed(),
         dir_status.map(Event::Dir).boxed(),
         skew_status.map(Event::Skew).boxed(),
+        reachability_status.map(Event::Reachability).boxed(),
     ]);
 
     while let Some(event) = stream.next().await {
@@ -243,6 +516,7 @@ pub(crate) async fn report_status(
             Event::Conn(e) => b.apply_conn_status(e),
             Event::Dir(e) => b.apply_dir_status(e),
             Event::Skew(e) => b.apply_skew_estimate(e),
+            Event::Reachability(e) => b.apply_reachability(e),
         }
         debug!("{}", *b);
     }
@@ -276,3 +550,48 @@ impl Stream for BootstrapEvents {
         self.inner.poll_next_unpin(cx)
     }
 }
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+
+    #[test]
+    fn report_merges_and_orders() {
+        use BootstrapProblemKind as K;
+
+        let mut status = BootstrapStatus::default();
+        status.apply_reachability(NetworkReachability::Down);
+        let report = status.failure_report();
+        assert_eq!(report.primary().unwrap().kind(), K::NoRoute);
+
+        let mut report = BootstrapReport::default();
+        assert!(report.is_empty());
+        assert_eq!(report.to_string(), "No bootstrap problems found");
+        report.add(K::NoRoute, "a");
+        report.add(K::Offline, "b");
+        report.add(K::NoRoute, "c");
+        report.problems.sort_by_key(|p| p.kind);
+        let kinds: Vec<_> = report.problems().iter().map(|p| p.kind()).collect();
+        assert_eq!(kinds, vec![K::Offline, K::NoRoute]);
+        assert_eq!(report.problems()[1].message().to_string(), "a; c");
+        assert_eq!(
+            report.to_string(),
+            "We seem to be offline (b); No route through the Tor network (a; c)"
+        );
+        assert_eq!(K::ClockSkew.as_str(), "clock_skew");
+    }
+}
@@ -0,0 +1,8 @@
+//! Generate Kotlin and Swift bindings for `arti-mobile`.
+//!
+//! Run this as `uniffi-bindgen generate --library <path to libarti_mobile> --language kotlin --out-dir <dir>`
+//! (or `--language swift`).
+
+fn main() {
+    uniffi::uniffi_bindgen_main();
+}
@@ -0,0 +1,593 @@
+#![cfg_attr(docsrs, feature(doc_auto_cfg, doc_cfg))]
+#![doc = include_str!("../README.md")]
+// @@ begin lint list maintained by maint/add_warning @@
+#![allow(renamed_and_removed_lints)] // @@REMOVE_WHEN(ci_arti_stable)
+#![allow(unknown_lints)] // @@REMOVE_WHEN(ci_arti_nightly)
+#![warn(missing_docs)]
+#![warn(noop_method_call)]
+#![warn(unreachable_pub)]
+#![warn(clippy::all)]
+#![deny(clippy::await_holding_lock)]
+#![deny(clippy::cargo_common_metadata)]
+#![deny(clippy::cast_lossless)]
+#![deny(clippy::checked_conversions)]
+#![warn(clippy::cognitive_complexity)]
+#![deny(clippy::debug_assert_with_mut_call)]
+#![deny(clippy::exhaustive_enums)]
+#![deny(clippy::exhaustive_structs)]
+#![deny(clippy::expl_impl_clone_on_copy)]
+#![deny(clippy::fallible_impl_from)]
+#![deny(clippy::implicit_clone)]
+#![deny(clippy::large_stack_arrays)]
+#![warn(clippy::manual_ok_or)]
+#![deny(clippy::missing_docs_in_private_items)]
+#![warn(clippy::needless_borrow)]
+#![warn(clippy::needless_pass_by_value)]
+#![warn(clippy::option_option)]
+#![deny(clippy::print_stderr)]
+#![deny(clippy::print_stdout)]
+#![warn(clippy::rc_buffer)]
+#![deny(clippy::ref_option_ref)]
+#![warn(clippy::semicolon_if_nothing_returned)]
+#![warn(clippy::trait_duplication_in_bounds)]
+#![deny(clippy::unchecked_duration_subtraction)]
+#![deny(clippy::unnecessary_wraps)]
+#![warn(clippy::unseparated_literal_suffix)]
+#![deny(clippy::unwrap_used)]
+#![allow(clippy::let_unit_value)] // This can reasonably be done for explicitness
+#![allow(clippy::uninlined_format_args)]
+#![allow(clippy::significant_drop_in_scrutinee)] // arti/-/merge_requests/588/#note_2812945
+#![allow(clippy::result_large_err)] // temporary workaround for arti#587
+#![allow(clippy::needless_raw_string_hashes)] // complained-about code is fine, often best
+//! <!-- @@ end lint list maintained by maint/add_warning @@ -->
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use futures::channel::oneshot;
+use futures::future::Shared;
+use futures::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+use futures::task::SpawnExt as _;
+use futures::{Future, FutureExt as _, StreamExt as _};
+use tracing::debug;
+
+use arti_client::config::TorClientConfigBuilder;
+use arti_client::{DataReader, DataWriter, TorClient};
+use tor_async_utils::copy_interactive;
+use tor_cell::relaycell::msg::{Connected, End, EndReason};
+use tor_hsservice::config::OnionServiceConfigBuilder;
+use tor_hsservice::{HsNickname, RunningOnionService, StreamRequest};
+use tor_proto::stream::IncomingStreamRequest;
+use tor_rtcompat::{BlockOn, PreferredRuntime, TcpProvider as _};
+
+uniffi::setup_scaffolding!();
+
+/// An error from one of the operations in this crate.
+///
+/// Foreign code sees which kind of error this is, and its message.
+#[derive(Debug, thiserror::Error, uniffi::Error)]
+#[uniffi(flat_error)]
+#[non_exhaustive]
+pub enum ArtiMobileError {
+    /// We were given an argument that we couldn't use.
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+    /// Arti reported an error.
+    #[error("{0}")]
+    Tor(#[from] arti_client::Error),
+    /// Reading or writing a stream failed.
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// We couldn't start a background task.
+    #[error("Unable to spawn task: {0}")]
+    Spawn(#[from] futures::task::SpawnError),
+    /// The object has already been closed or stopped.
+    #[error("Already closed")]
+    Closed,
+}
+
+/// A summary of Arti's progress in bootstrapping.
+#[derive(Clone, Debug, uniffi::Record)]
+#[non_exhaustive]
+pub struct BootstrapStatus {
+    /// True if we are ready to carry traffic.
+    pub ready: bool,
+    /// Our estimated progress, from 0.0 (just started) to 1.0 (ready).
+    pub fraction: f32,
+    /// If our progress seems to be stuck, a description of why.
+    pub blocked: Option<String>,
+}
+
+impl From<&arti_client::status::BootstrapStatus> for BootstrapStatus {
+    fn from(status: &arti_client::status::BootstrapStatus) -> Self {
+        BootstrapStatus {
+            ready: status.ready_for_traffic(),
+            fraction: status.as_frac(),
+            blocked: status.blocked().map(|b| b.to_string()),
+        }
+    }
+}
+
+/// An object that foreign code provides, to be told about changes in
+/// Arti's bootstrap status.
+#[uniffi::export(callback_interface)]
+pub trait StatusListener: Send + Sync {
+    /// Called whenever our bootstrap status changes.
+    ///
+    /// This is called on one of Arti's own threads.
+    /// It should return promptly, and must not call any of the blocking
+    /// methods in this crate.
+    fn on_status(&self, status: BootstrapStatus);
+}
+
+/// An Arti client, for use from foreign code.
+///
+/// Every `ArtiClient` has its own asynchronous runtime.
+/// Its methods (and those of the objects it returns) block the calling
+/// thread until they are done:
+/// apps should call them from a background thread, never from the UI thread.
+#[derive(uniffi::Object)]
+pub struct ArtiClient {
+    /// The runtime on which `client` runs, and on which we block.
+    runtime: PreferredRuntime,
+    /// The underlying client.
+    client: TorClient<PreferredRuntime>,
+}
+
+#[uniffi::export]
+impl ArtiClient {
+    /// Create a new client, which stores its persistent state in `state_dir`,
+    /// and its cached directory information in `cache_dir`.
+    ///
+    /// Apps should use directories within their private storage.
+    ///
+    /// The new client does not connect to the network until
+    /// [`bootstrap`](ArtiClient::bootstrap) is called,
+    /// or it is first asked to make a connection.
+    #[uniffi::constructor]
+    pub fn new(state_dir: String, cache_dir: String) -> Result<Arc<Self>, ArtiMobileError> {
+        let runtime = PreferredRuntime::create()?;
+        let config = TorClientConfigBuilder::from_directories(state_dir, cache_dir)
+            .build()
+            .map_err(|e| ArtiMobileError::Config(e.to_string()))?;
+        let client = runtime.block_on(
+            TorClient::with_runtime(runtime.clone())
+                .config(config)
+                .create_unbootstrapped_async(),
+        )?;
+        Ok(Arc::new(ArtiClient { runtime, client }))
+    }
+
+    /// Bootstrap a connection to the Tor network, and return once we are
+    /// ready to carry traffic.
+    pub fn bootstrap(&self) -> Result<(), ArtiMobileError> {
+        Ok(self.runtime.block_on(self.client.bootstrap())?)
+    }
+
+    /// Return our current bootstrap status.
+    pub fn bootstrap_status(&self) -> BootstrapStatus {
+        (&self.client.bootstrap_status()).into()
+    }
+
+    /// Tell `listener` about every change in our bootstrap status,
+    /// for as long as this client exists.
+    pub fn watch_status(&self, listener: Box<dyn StatusListener>) -> Result<(), ArtiMobileError> {
+        let mut events = self.client.bootstrap_events();
+        self.runtime.spawn(async move {
+            while let Some(status) = events.next().await {
+                listener.on_status((&status).into());
+            }
+        })?;
+        Ok(())
+    }
+
+    /// Open an anonymized stream to `port` on `host`.
+    ///
+    /// `host` may be a hostname, an IP address, or (if onion services
+    /// are enabled in the client) an onion address.
+    pub fn connect(&self, host: String, port: u16) -> Result<Arc<ArtiStream>, ArtiMobileError> {
+        let stream = self
+            .runtime
+            .block_on(self.client.connect((host.as_str(), port)))?;
+        let (reader, writer) = stream.split();
+        Ok(Arc::new(ArtiStream {
+            runtime: self.runtime.clone(),
+            stream: CloseableStream::new(reader, writer),
+        }))
+    }
+
+    /// Launch an onion service called `nickname`,
+    /// forwarding every connection to its port `virtual_port`
+    /// to `local_port` on localhost.
+    ///
+    /// The service's keys are kept in this client's state directory,
+    /// so a service launched with the same nickname keeps the same address.
+    ///
+    /// Connections to any other port are refused,
+    /// by closing the circuit that they arrived on.
+    pub fn launch_onion_service(
+        &self,
+        nickname: String,
+        virtual_port: u16,
+        local_port: u16,
+    ) -> Result<Arc<OnionService>, ArtiMobileError> {
+        let nickname: HsNickname = nickname
+            .try_into()
+            .map_err(|e: tor_hsservice::InvalidNickname| ArtiMobileError::Config(e.to_string()))?;
+        let config = OnionServiceConfigBuilder::default()
+            .nickname(nickname)
+            .build()
+            .map_err(|e| ArtiMobileError::Config(e.to_string()))?;
+        let (service, rend_requests) = self.client.launch_onion_service(config)?;
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let local_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, local_port));
+        let runtime = self.runtime.clone();
+        let mut requests =
+            tor_hsservice::handle_rend_requests(rend_requests).take_until(shutdown_rx);
+        self.runtime.spawn(async move {
+            while let Some(request) = requests.next().await {
+                let runtime2 = runtime.clone();
+                let forward = forward_request(runtime2, request, virtual_port, local_addr);
+                if let Err(e) = runtime.spawn(forward) {
+                    debug!("Unable to spawn task for onion service stream: {}", e);
+                }
+            }
+        })?;
+
+        Ok(Arc::new(OnionService {
+            service: Mutex::new(Some((service, shutdown_tx))),
+        }))
+    }
+}
+
+/// Handle a single stream `request` to an onion service,
+/// by forwarding it to `local_addr` if it is for `virtual_port`.
+async fn forward_request(
+    runtime: PreferredRuntime,
+    request: StreamRequest,
+    virtual_port: u16,
+    local_addr: SocketAddr,
+) {
+    let for_us = matches!(
+        request.request(),
+        IncomingStreamRequest::Begin(begin) if begin.port() == virtual_port
+    );
+    if !for_us {
+        if let Err(e) = request.shutdown_circuit() {
+            debug!("Unable to close onion service circuit: {}", e);
+        }
+        return;
+    }
+
+    let local = match runtime.connect(&local_addr).await {
+        Ok(local) => local,
+        Err(e) => {
+            debug!("Unable to connect to local port for onion service: {}", e);
+            let end = End::new_with_reason(EndReason::DONE);
+            if let Err(e) = request.reject(end).await {
+                debug!("Unable to reject onion service stream: {}", e);
+            }
+            return;
+        }
+    };
+    let remote = match request.accept(Connected::new_empty()).await {
+        Ok(remote) => remote,
+        Err(e) => {
+            debug!("Unable to accept onion service stream: {}", e);
+            return;
+        }
+    };
+
+    let (remote_r, remote_w) = remote.split();
+    let (local_r, local_w) = local.split();
+    futures::future::join(
+        copy_interactive(local_r, remote_w).map(|_| ()),
+        copy_interactive(remote_r, local_w).map(|_| ()),
+    )
+    .await;
+}
+
+/// An anonymized stream, opened with [`ArtiClient::connect`].
+///
+/// Reading and writing may happen at the same time, on different threads.
+#[derive(uniffi::Object)]
+pub struct ArtiStream {
+    /// The runtime on which we block.
+    runtime: PreferredRuntime,
+    /// The underlying stream.
+    stream: CloseableStream<DataReader, DataWriter>,
+}
+
+#[uniffi::export]
+impl ArtiStream {
+    /// Read at most `max_len` bytes from this stream,
+    /// waiting until at least one is available.
+    ///
+    /// No single read returns more than 64 KiB, however large `max_len` is.
+    ///
+    /// Returns an empty buffer once the other side has closed the stream.
+    pub fn read(&self, max_len: u32) -> Result<Vec<u8>, ArtiMobileError> {
+        self.stream.read(&self.runtime, max_len)
+    }
+
+    /// Write all of `data` to this stream.
+    ///
+    /// The data may be buffered: call [`flush`](ArtiStream::flush) to make
+    /// sure that it is sent.
+    pub fn write(&self, data: Vec<u8>) -> Result<(), ArtiMobileError> {
+        self.stream.write(&self.runtime, &data)
+    }
+
+    /// Send any data that has been written to this stream, but not yet sent.
+    pub fn flush(&self) -> Result<(), ArtiMobileError> {
+        self.stream.flush(&self.runtime)
+    }
+
+    /// Flush and close this stream.
+    ///
+    /// Any reads or writes that are in progress on other threads,
+    /// and any later ones, will fail with [`ArtiMobileError::Closed`].
+    pub fn close(&self) -> Result<(), ArtiMobileError> {
+        self.stream.close(&self.runtime)
+    }
+}
+
+/// The largest number of bytes that we return from a single read.
+///
+/// Callers can ask for up to `u32::MAX` bytes, and we don't want to allocate
+/// a buffer that large just because they asked for it.
+const MAX_READ_LEN: u32 = 64 * 1024;
+
+/// The two halves of a stream, which one thread can close while others are
+/// blocked reading from it or writing to it.
+///
+/// Each half is behind its own lock, which is held while we block on it.
+/// So that [`close`](CloseableStream::close) never waits for a read that
+/// might never finish, it doesn't take the reader's lock: instead, it tells
+/// any blocked operations to give up by dropping `close_tx`.
+struct CloseableStream<R, W> {
+    /// The reading half of the stream.
+    ///
+    /// We never drop this before the stream itself is dropped, since a
+    /// read may be using it; once the stream is closed, we just stop
+    /// reading from it.
+    reader: Mutex<R>,
+    /// The writing half of the stream, or `None` if it has been closed.
+    writer: Mutex<Option<W>>,
+    /// A sender that we drop to tell blocked operations that the stream has
+    /// been closed; or `None` if we have already done so.
+    close_tx: Mutex<Option<oneshot::Sender<()>>>,
+    /// A future that becomes ready once `close_tx` is dropped.
+    closed: Shared<oneshot::Receiver<()>>,
+}
+
+impl<R, W> CloseableStream<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    /// Wrap `reader` and `writer` in a new `CloseableStream`.
+    fn new(reader: R, writer: W) -> Self {
+        let (close_tx, close_rx) = oneshot::channel();
+        CloseableStream {
+            reader: Mutex::new(reader),
+            writer: Mutex::new(Some(writer)),
+            close_tx: Mutex::new(Some(close_tx)),
+            closed: close_rx.shared(),
+        }
+    }
+
+    /// Block on `runtime` until `fut` is ready,
+    /// or until this stream is closed.
+    ///
+    /// If the stream is already closed, fail without polling `fut`.
+    fn block_unless_closed<RT, F, T>(&self, runtime: &RT, fut: F) -> Result<T, ArtiMobileError>
+    where
+        RT: BlockOn,
+        F: Future<Output = std::io::Result<T>>,
+    {
+        let closed = self.closed.clone();
+        runtime.block_on(async move {
+            futures::select_biased! {
+                _ = closed.fuse() => Err(ArtiMobileError::Closed),
+                result = fut.fuse() => Ok(result?),
+            }
+        })
+    }
+
+    /// Read at most `max_len` (or [`MAX_READ_LEN`]) bytes from this stream.
+    fn read<RT: BlockOn>(&self, runtime: &RT, max_len: u32) -> Result<Vec<u8>, ArtiMobileError> {
+        let mut reader = self.reader.lock().expect("lock poisoned");
+        let mut buf = vec![0; max_len.min(MAX_READ_LEN) as usize];
+        let n = self.block_unless_closed(runtime, reader.read(&mut buf))?;
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    /// Write all of `data` to this stream.
+    fn write<RT: BlockOn>(&self, runtime: &RT, data: &[u8]) -> Result<(), ArtiMobileError> {
+        let mut writer = self.writer.lock().expect("lock poisoned");
+        let writer = writer.as_mut().ok_or(ArtiMobileError::Closed)?;
+        self.block_unless_closed(runtime, writer.write_all(data))
+    }
+
+    /// Flush this stream.
+    fn flush<RT: BlockOn>(&self, runtime: &RT) -> Result<(), ArtiMobileError> {
+        let mut writer = self.writer.lock().expect("lock poisoned");
+        let writer = writer.as_mut().ok_or(ArtiMobileError::Closed)?;
+        self.block_unless_closed(runtime, writer.flush())
+    }
+
+    /// Flush and close this stream,
+    /// making any blocked reads and writes fail.
+    fn close<RT: BlockOn>(&self, runtime: &RT) -> Result<(), ArtiMobileError> {
+        // Wake up any blocked operations first, so that they release their
+        // locks.  (In particular, a blocked write would otherwise keep us from
+        // taking the writer.)
+        let close_tx = self.close_tx.lock().expect("lock poisoned").take();
+        drop(close_tx);
+        let writer = self.writer.lock().expect("lock poisoned").take();
+        match writer {
+            Some(mut writer) => Ok(runtime.block_on(writer.close())?),
+            None => Err(ArtiMobileError::Closed),
+        }
+    }
+}
+
+/// An onion service, launched with [`ArtiClient::launch_onion_service`].
+#[derive(uniffi::Object)]
+pub struct OnionService {
+    /// The running service, and a sender that we drop to stop forwarding
+    /// its streams; or `None` if it has been stopped.
+    service: Mutex<Option<(Arc<RunningOnionService>, oneshot::Sender<()>)>>,
+}
+
+#[uniffi::export]
+impl OnionService {
+    /// Return this service's onion address (ending in `.onion`),
+    /// or `None` if it is not yet known, or the service has been stopped.
+    pub fn onion_address(&self) -> Option<String> {
+        let service = self.service.lock().expect("lock poisoned");
+        let (service, _) = service.as_ref()?;
+        service.onion_name().map(|hsid| hsid.to_string())
+    }
+
+    /// Return a short description of this service's state.
+    ///
+    /// The format of this description is not stable:
+    /// it is meant for display and logging.
+    pub fn state(&self) -> String {
+        match self.service.lock().expect("lock poisoned").as_ref() {
+            Some((service, _)) => format!("{:?}", service.status().state()),
+            None => "Stopped".to_owned(),
+        }
+    }
+
+    /// Stop this service.
+    ///
+    /// Streams that are already open are not closed.
+    pub fn stop(&self) {
+        drop(self.service.lock().expect("lock poisoned").take());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use futures::channel::mpsc;
+    use futures::io::IntoAsyncRead;
+    use futures::TryStreamExt as _;
+    use std::time::Duration;
+
+    /// A sender for the data that a [`TestStream`] reads.
+    type DataTx = mpsc::UnboundedSender<std::io::Result<Vec<u8>>>;
+
+    /// A stream whose reader blocks until we send it data,
+    /// and whose writer collects everything written to it.
+    type TestStream =
+        CloseableStream<IntoAsyncRead<mpsc::UnboundedReceiver<std::io::Result<Vec<u8>>>>, Vec<u8>>;
+
+    /// Return a new [`TestStream`], and a sender for the data it reads.
+    fn test_stream() -> (TestStream, DataTx) {
+        let (tx, rx) = mpsc::unbounded();
+        (CloseableStream::new(rx.into_async_read(), Vec::new()), tx)
+    }
+
+    #[test]
+    fn read_and_write() {
+        let runtime = PreferredRuntime::create().unwrap();
+        let (stream, tx) = test_stream();
+
+        tx.unbounded_send(Ok(b"hello".to_vec())).unwrap();
+        assert_eq!(stream.read(&runtime, 3).unwrap(), b"hel");
+        assert_eq!(stream.read(&runtime, 100).unwrap(), b"lo");
+
+        stream.write(&runtime, b"world").unwrap();
+        stream.flush(&runtime).unwrap();
+        assert_eq!(
+            stream.writer.lock().unwrap().as_deref(),
+            Some(&b"world"[..])
+        );
+
+        // Once the other side is done, we read an empty buffer.
+        drop(tx);
+        assert_eq!(stream.read(&runtime, 100).unwrap(), b"");
+    }
+
+    #[test]
+    fn huge_read() {
+        let runtime = PreferredRuntime::create().unwrap();
+        let (stream, tx) = test_stream();
+
+        // Asking for more than we allow gets us no more than we allow.
+        let data = vec![7; MAX_READ_LEN as usize * 2];
+        tx.unbounded_send(Ok(data)).unwrap();
+        let got = stream.read(&runtime, u32::MAX).unwrap();
+        assert_eq!(got.len(), MAX_READ_LEN as usize);
+        let got = stream.read(&runtime, u32::MAX).unwrap();
+        assert_eq!(got.len(), MAX_READ_LEN as usize);
+    }
+
+    #[test]
+    fn close_interrupts_read() {
+        let runtime = PreferredRuntime::create().unwrap();
+        let (stream, _tx) = test_stream();
+        let stream = Arc::new(stream);
+
+        let reading = {
+            let stream = Arc::clone(&stream);
+            let runtime = runtime.clone();
+            std::thread::spawn(move || stream.read(&runtime, 100))
+        };
+        // Give the reader a chance to block.  (If it hasn't yet, it will see
+        // that the stream is closed as soon as it tries to read.)
+        std::thread::sleep(Duration::from_millis(100));
+
+        // This must not wait for the read, which would otherwise never finish.
+        stream.close(&runtime).unwrap();
+        assert!(matches!(
+            reading.join().unwrap(),
+            Err(ArtiMobileError::Closed)
+        ));
+    }
+
+    #[test]
+    fn use_after_close() {
+        let runtime = PreferredRuntime::create().unwrap();
+        let (stream, tx) = test_stream();
+
+        stream.close(&runtime).unwrap();
+        // Even if there is data waiting, we don't read it.
+        tx.unbounded_send(Ok(b"hello".to_vec())).unwrap();
+        assert!(matches!(
+            stream.read(&runtime, 100),
+            Err(ArtiMobileError::Closed)
+        ));
+        assert!(matches!(
+            stream.write(&runtime, b"world"),
+            Err(ArtiMobileError::Closed)
+        ));
+        assert!(matches!(
+            stream.flush(&runtime),
+            Err(ArtiMobileError::Closed)
+        ));
+        assert!(matches!(
+            stream.close(&runtime),
+            Err(ArtiMobileError::Closed)
+        ));
+    }
+}
@@ -0,0 +1,270 @@
+/**
+ * # Arti RPC client library
+ *
+ * This library lets C programs talk to Arti over its RPC protocol.
+ *
+ * ## Conventions
+ *
+ * - All strings passed to or returned from these functions are
+ *   NUL-terminated UTF-8.
+ * - Every fallible function returns an ArtiRpcStatus.  On failure,
+ *   if `error_out` is not NULL, `*error_out` is set to a newly allocated
+ *   ArtiRpcError, which must be released with `arti_rpc_err_free()`.
+ * - Every object returned to the caller must be released with its
+ *   corresponding `_free` function.
+ * - An ArtiRpcConn may be used from multiple threads at once.
+ *
+ * This file is generated by cbindgen; do not edit it by hand.
+ */
+
+#ifndef ARTI_RPC_CLIENT_CORE_H_
+#define ARTI_RPC_CLIENT_CORE_H_
+
+#include <stdint.h>
+
+/**
+ * The function has returned successfully.
+ */
+#define ARTI_RPC_STATUS_SUCCESS 0
+
+/**
+ * One or more of the inputs to the function was invalid.
+ */
+#define ARTI_RPC_STATUS_INVALID_INPUT 1
+
+/**
+ * Tried to use some functionality (for example, a connection scheme)
+ * that isn't supported in this build.
+ */
+#define ARTI_RPC_STATUS_NOT_SUPPORTED 2
+
+/**
+ * An IO error occurred while trying to connect to Arti.
+ */
+#define ARTI_RPC_STATUS_CONNECT_IO 3
+
+/**
+ * We could not authenticate, or Arti rejected our negotiation attempts.
+ */
+#define ARTI_RPC_STATUS_BAD_AUTH 4
+
+/**
+ * Arti sent a message that didn't conform to the RPC protocol.
+ */
+#define ARTI_RPC_STATUS_PEER_PROTOCOL_VIOLATION 5
+
+/**
+ * The RPC connection was closed, or failed.
+ */
+#define ARTI_RPC_STATUS_SHUTDOWN 6
+
+/**
+ * An internal error occurred in the Arti RPC client.
+ */
+#define ARTI_RPC_STATUS_INTERNAL 7
+
+/**
+ * The request was sent successfully, but Arti reported an error in response.
+ *
+ * The error's response (see `arti_rpc_err_response`) holds Arti's reply.
+ */
+#define ARTI_RPC_STATUS_REQUEST_FAILED 8
+
+/**
+ * The request was cancelled before it could complete.
+ */
+#define ARTI_RPC_STATUS_REQUEST_CANCELLED 9
+
+/**
+ * We gave up waiting for a response, since our timeout expired.
+ */
+#define ARTI_RPC_STATUS_TIMEOUT 10
+
+/**
+ * An open connection to Arti over the RPC protocol.
+ *
+ * It is safe to use one of these from multiple threads at once.
+ */
+typedef struct ArtiRpcConn ArtiRpcConn;
+
+/**
+ * An error returned by the Arti RPC code.
+ */
+typedef struct ArtiRpcError ArtiRpcError;
+
+/**
+ * A status code returned by an Arti RPC function.
+ *
+ * On success, a function will return `ARTI_RPC_STATUS_SUCCESS`.
+ * On failure, a function will return some other status code.
+ */
+typedef uint32_t ArtiRpcStatus;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Try to open a new connection to Arti.
+ *
+ * The connection string is as described for
+ * [`RpcConnBuilder::from_connect_string`].
+ *
+ * On success, set `*rpc_conn_out` to a new `ArtiRpcConn`,
+ * which must later be released with [`arti_rpc_conn_free`].
+ *
+ * # Safety
+ *
+ * `connection_string` must be a valid NUL-terminated string.
+ * `rpc_conn_out` must be a valid pointer.
+ * `error_out` must be NULL or a valid pointer.
+ */
+ArtiRpcStatus arti_rpc_connect(const char *connection_string,
+                               ArtiRpcConn **rpc_conn_out,
+                               ArtiRpcError **error_out);
+
+/**
+ * Return the session ID for an `ArtiRpcConn`, as a NUL-terminated string.
+ *
+ * The returned string is owned by `rpc_conn`, and remains valid until
+ * `rpc_conn` is freed.  Do not free it yourself.
+ *
+ * Return NULL if `rpc_conn` is NULL, or if the connection has no session.
+ *
+ * # Safety
+ *
+ * `rpc_conn` must be NULL or a valid pointer to an `ArtiRpcConn`.
+ */
+const char *arti_rpc_conn_get_session_id(const ArtiRpcConn *rpc_conn);
+
+/**
+ * Run an RPC request over `rpc_conn`, and wait for a final response.
+ *
+ * The request must be a JSON object, as described in the RPC specification.
+ * It may omit the `id` field; if it does, one will be generated.
+ *
+ * On success, set `*response_out` to a newly allocated string holding
+ * Arti's entire reply, which must later be released with [`arti_rpc_str_free`].
+ *
+ * If Arti replies with an error, return `ARTI_RPC_STATUS_REQUEST_FAILED`,
+ * and include the reply in the error (see [`arti_rpc_err_response`]).
+ *
+ * Any updates that Arti sends for this request are ignored.
+ *
+ * # Safety
+ *
+ * `rpc_conn` must be a valid pointer to an `ArtiRpcConn`.
+ * `msg` must be a valid NUL-terminated string.
+ * `response_out` must be a valid pointer.
+ * `error_out` must be NULL or a valid pointer.
+ */
+ArtiRpcStatus arti_rpc_conn_execute(const ArtiRpcConn *rpc_conn,
+                                    const char *msg,
+                                    char **response_out,
+                                    ArtiRpcError **error_out);
+
+/**
+ * As [`arti_rpc_conn_execute`], but give up with `ARTI_RPC_STATUS_TIMEOUT`
+ * if no final response arrives within `timeout_msec` milliseconds.
+ *
+ * Note that Arti may still be running the request after a timeout.
+ *
+ * # Safety
+ *
+ * As for [`arti_rpc_conn_execute`].
+ */
+ArtiRpcStatus arti_rpc_conn_execute_with_timeout(const ArtiRpcConn *rpc_conn,
+                                                 const char *msg,
+                                                 uint64_t timeout_msec,
+                                                 char **response_out,
+                                                 ArtiRpcError **error_out);
+
+/**
+ * Close and free an open `ArtiRpcConn`.
+ *
+ * Does nothing if `rpc_conn` is NULL.
+ *
+ * # Safety
+ *
+ * `rpc_conn` must be NULL, or a pointer returned by [`arti_rpc_connect`]
+ * that has not already been freed.
+ * No other thread may be using it.
+ */
+void arti_rpc_conn_free(ArtiRpcConn *rpc_conn);
+
+/**
+ * Free a string returned by the Arti RPC API.
+ *
+ * Does nothing if `string` is NULL.
+ *
+ * # Safety
+ *
+ * `string` must be NULL, or a string that was returned to the caller
+ * by an Arti RPC function as something to free, and that has not already been freed.
+ */
+void arti_rpc_str_free(char *string);
+
+/**
+ * Return the status code associated with a given `ArtiRpcError`.
+ *
+ * Return `ARTI_RPC_STATUS_INVALID_INPUT` if `err` is NULL.
+ *
+ * # Safety
+ *
+ * `err` must be NULL or a valid pointer to an `ArtiRpcError`.
+ */
+ArtiRpcStatus arti_rpc_err_status(const ArtiRpcError *err);
+
+/**
+ * Return a human-readable error message associated with a given `ArtiRpcError`.
+ *
+ * The returned string is owned by `err`, and remains valid until
+ * `err` is freed.  Do not free it yourself.
+ *
+ * Return NULL if `err` is NULL.
+ *
+ * # Safety
+ *
+ * `err` must be NULL or a valid pointer to an `ArtiRpcError`.
+ */
+const char *arti_rpc_err_message(const ArtiRpcError *err);
+
+/**
+ * If the `ArtiRpcError` was caused by an error reply from Arti,
+ * return that reply, as a JSON string.
+ *
+ * The returned string is owned by `err`, and remains valid until
+ * `err` is freed.  Do not free it yourself.
+ *
+ * Return NULL if `err` is NULL, or if it was not caused by an error reply.
+ *
+ * # Safety
+ *
+ * `err` must be NULL or a valid pointer to an `ArtiRpcError`.
+ */
+const char *arti_rpc_err_response(const ArtiRpcError *err);
+
+/**
+ * Free an `ArtiRpcError`.
+ *
+ * Does nothing if `err` is NULL.
+ *
+ * # Safety
+ *
+ * `err` must be NULL, or a pointer returned by an Arti RPC function
+ * that has not already been freed.
+ */
+void arti_rpc_err_free(ArtiRpcError *err);
+
+/**
+ * Return a short, static, human-readable name for an `ArtiRpcStatus`.
+ *
+ * The returned string is statically allocated.  Do not free it.
+ */
+const char *arti_rpc_status_to_str(ArtiRpcStatus status);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* ARTI_RPC_CLIENT_CORE_H_ */
@@ -7,6 +7,7 @@ use std::{
     io::{self, BufReader},
     path::PathBuf,
     sync::Arc,
+    time::{Duration, Instant},
 };
 
 use crate::{
@@ -20,8 +21,19 @@ use crate::{
 
 mod auth;
 mod connimpl;
+mod datastream;
+mod events;
+mod objects;
+mod stream;
 
+pub use auth::RpcAuth;
 pub use connimpl::RpcConn;
+pub use datastream::StreamError;
+pub use events::{
+    BootstrapProblem, BootstrapStatus, Event, EventKind, EventStream, OnionServiceProblem,
+    OnionServiceProblemKind, OnionServiceState,
+};
+pub use stream::UpdateStream;
 
 /// A handle to an open request.
 ///
@@ -126,6 +138,10 @@ pub struct RpcConnBuilder {
     /// A path to a unix domain socket at which Arti is listening.
     // TODO RPC: Right now this is the only kind of supported way to connect.
     unix_socket: PathBuf,
+    /// How to authenticate once we've connected.
+    auth: RpcAuth,
+    /// Which wire encoding to negotiate once we've connected.
+    framing: llconn::Framing,
     // todo RPC: include selector for how to connect.
     //
     // TODO RPC: Possibly kill off the builder entirely.
@@ -137,22 +153,66 @@ pub struct RpcConnBuilder {
 impl RpcConnBuilder {
     /// Create a Builder from a connect string.
     ///
-    /// (Right now the only supported string type is "unix:" followed by a path.)
+    /// Right now the only supported string type is "unix:" followed by a path,
+    /// optionally followed by `;`-separated `key=value` options
+    /// describing how to authenticate:
+    ///
+    ///  * `auth=` one of `auto` (the default), `unix_path`, `peer_uid`, `cookie`, or `token`.
+    ///  * `cookie_path=` the location of the cookie file (implies `auth=cookie`,
+    ///    which requires it).
+    ///  * `token=` a pre-shared token (implies `auth=token`).
+    ///  * `framing=` one of `jsonlines` (the default), `length_prefixed`, or `cbor`.
+    ///
+    /// For example, `unix:/run/arti/SOCKET;cookie_path=/run/arti/cookie`.
     //
     // TODO RPC: Should this take an OsString?
     //
     // TODO RPC: Specify the actual metaformat that we want to use here.
-    // Possibly turn this into a K=V sequence ... or possibly, just
-    // turn it into a JSON object.
+    // Possibly turn this into a JSON object.
     pub fn from_connect_string(s: &str) -> Result<Self, BuilderError> {
         let (kind, location) = s
             .split_once(':')
             .ok_or(BuilderError::InvalidConnectString)?;
-        if kind == "unix" {
-            Ok(Self::new_unix_socket(location))
-        } else {
-            Err(BuilderError::InvalidConnectString)
+        if kind != "unix" {
+            return Err(BuilderError::InvalidConnectString);
         }
+
+        let mut options = location.split(';');
+        let path = options.next().ok_or(BuilderError::InvalidConnectString)?;
+        let (mut auth, mut cookie_path, mut token) = (None, None, None);
+        let mut framing = llconn::Framing::default();
+        for option in options {
+            match option
+                .split_once('=')
+                .ok_or(BuilderError::InvalidConnectString)?
+            {
+                ("auth", v) => auth = Some(v),
+                ("cookie_path", v) => cookie_path = Some(PathBuf::from(v)),
+                ("token", v) => token = Some(v.to_owned()),
+                ("framing", v) => {
+                    framing = match v {
+                        "jsonlines" => llconn::Framing::JsonLines,
+                        "length_prefixed" => llconn::Framing::LengthPrefixedJson,
+                        "cbor" => llconn::Framing::Cbor,
+                        _ => return Err(BuilderError::InvalidConnectString),
+                    }
+                }
+                (_, _) => return Err(BuilderError::InvalidConnectString),
+            }
+        }
+
+        let auth = match (auth, cookie_path, token) {
+            (None | Some("auto"), None, None) => RpcAuth::Auto,
+            (Some("unix_path"), None, None) => RpcAuth::UnixPath,
+            (Some("peer_uid"), None, None) => RpcAuth::PeerUid,
+            (None | Some("cookie"), Some(cookie_path), None) => RpcAuth::Cookie(cookie_path),
+            (None | Some("token"), None, Some(token)) => RpcAuth::Token(token),
+            (_, _, _) => return Err(BuilderError::InvalidConnectString),
+        };
+
+        Ok(Self::new_unix_socket(path)
+            .with_auth(auth)
+            .with_framing(framing))
     }
 
     /// Create a Builder to connect to a unix socket at a given path.
@@ -163,9 +223,30 @@ impl RpcConnBuilder {
     pub fn new_unix_socket(addr: impl Into<PathBuf>) -> Self {
         Self {
             unix_socket: addr.into(),
+            auth: RpcAuth::default(),
+            framing: llconn::Framing::default(),
         }
     }
 
+    /// Set the way in which we authenticate once we've connected.
+    ///
+    /// By default, we use [`RpcAuth::Auto`].
+    pub fn with_auth(mut self, auth: RpcAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Set the wire encoding to negotiate once we've connected.
+    ///
+    /// By default, we use [`Framing::JsonLines`](llconn::Framing::JsonLines),
+    /// which requires no negotiation.  Other framings lift the restriction
+    /// that messages may not contain newlines, and (for CBOR) reduce overhead
+    /// on busy update streams.
+    pub fn with_framing(mut self, framing: llconn::Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
     /// Try to connect to an Arti process as specified by this Builder.
     pub fn connect(&self) -> Result<RpcConn, ConnectError> {
         #[cfg(not(unix))]
@@ -179,12 +260,22 @@ impl RpcConnBuilder {
             let sock_dup = sock
                 .try_clone()
                 .map_err(|e| ConnectError::CannotConnect(Arc::new(e)))?;
-            let mut conn = RpcConn::new(
-                llconn::Reader::new(Box::new(BufReader::new(sock))),
-                llconn::Writer::new(Box::new(sock_dup)),
-            );
+            let sock_timeout = sock
+                .try_clone()
+                .map_err(|e| ConnectError::CannotConnect(Arc::new(e)))?;
+            let mut reader = llconn::Reader::new(Box::new(BufReader::new(sock)))
+                .with_read_timeout_fn(move |t| sock_timeout.set_read_timeout(t));
+            let mut writer = llconn::Writer::new(Box::new(sock_dup));
+            llconn::negotiate(&mut reader, &mut writer, self.framing).map_err(|e| {
+                if e.kind() == std::io::ErrorKind::Unsupported {
+                    ConnectError::FramingNotSupported(Arc::new(e))
+                } else {
+                    ConnectError::CannotConnect(Arc::new(e))
+                }
+            })?;
+            let mut conn = RpcConn::new(reader, writer);
 
-            let session_id = conn.authenticate_inherent("inherent:unix_path")?;
+            let session_id = conn.authenticate(&self.auth)?;
             conn.session = Some(session_id);
 
             Ok(conn)
@@ -229,14 +320,28 @@ impl RpcConn {
         let hnd = self.execute_with_handle(cmd)?;
         hnd.wait()
     }
-    /// Cancel a request by ID.
-    pub fn cancel(&self, _id: &AnyRequestId) -> Result<(), ProtoError> {
-        todo!()
+    /// As `execute`, but give up if no final response arrives within `timeout`.
+    ///
+    /// On timeout, this function returns [`ProtoError::Timeout`], and discards
+    /// any response that arrives later.
+    /// Note that Arti may still be running the command in that case.
+    pub fn execute_with_timeout(
+        &self,
+        cmd: &str,
+        timeout: Duration,
+    ) -> Result<FinalResponse, ProtoError> {
+        let mut hnd = self.execute_with_handle(cmd)?;
+        let result = hnd.wait_timeout(timeout);
+        if matches!(result, Err(ProtoError::Timeout)) {
+            // TODO RPC: Cancel the request once we can.
+            hnd.conn.forget(&hnd.id);
+        }
+        result
     }
     /// Like `execute`, but don't wait.  This lets the caller see the
     /// request ID and  maybe cancel it.
     pub fn execute_with_handle(&self, cmd: &str) -> Result<RequestHandle, ProtoError> {
-        self.send_request(cmd)
+        self.send_request(cmd, false)
     }
     /// As execute(), but run update_cb for every update we receive.
     pub fn execute_with_updates<F>(
@@ -291,7 +396,41 @@ impl RequestHandle {
     /// All future calls to this function will fail with `CmdError::RequestCancelled`.
     /// (TODO RPC: Maybe rename that error.)
     pub fn wait_with_updates(&mut self) -> Result<AnyResponse, ProtoError> {
-        let validated = self.conn.wait_on_message_for(&self.id)?;
+        let validated = self.conn.wait_on_message_for(&self.id, None)?;
+
+        Ok(AnyResponse::from_validated(validated))
+    }
+    /// As `wait`, but give up with [`ProtoError::Timeout`] if no final response
+    /// arrives within `timeout`.
+    ///
+    /// (Ignores any update messages that are received.)
+    ///
+    /// After a timeout, the request is still pending, and it is okay
+    /// to wait for it again.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Result<FinalResponse, ProtoError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.wait_with_updates_until(deadline)? {
+                AnyResponse::Success(s) => return Ok(Ok(s)),
+                AnyResponse::Error(e) => return Ok(Err(e)),
+                AnyResponse::Update(_) => {}
+            }
+        }
+    }
+    /// As `wait_with_updates`, but give up with [`ProtoError::Timeout`] if
+    /// no message arrives within `timeout`.
+    ///
+    /// After a timeout, the request is still pending, and it is okay
+    /// to wait for it again.
+    pub fn wait_with_updates_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<AnyResponse, ProtoError> {
+        self.wait_with_updates_until(Instant::now() + timeout)
+    }
+    /// Helper: Wait for the next message from this handle, until `deadline`.
+    fn wait_with_updates_until(&mut self, deadline: Instant) -> Result<AnyResponse, ProtoError> {
+        let validated = self.conn.wait_on_message_for(&self.id, Some(deadline))?;
 
         Ok(AnyResponse::from_validated(validated))
     }
@@ -371,6 +510,10 @@ pub enum ProtoError {
     /// (This should be impossible.)
     #[error("Internal error while encoding request: {0}")]
     CouldNotEncode(Arc<serde_json::Error>),
+
+    /// We gave up waiting for a response, since our timeout expired.
+    #[error("Timed out while waiting for a response")]
+    Timeout,
 }
 
 /// An error while trying to connect to the Arti process.
@@ -384,12 +527,27 @@ pub enum ConnectError {
     /// IO error while connecting to Arti.
     #[error("Unable to make a connection: {0}")]
     CannotConnect(Arc<std::io::Error>),
+    /// Arti would not use the framing that we asked for.
+    #[error("Arti did not accept the requested framing: {0}")]
+    FramingNotSupported(Arc<std::io::Error>),
     /// One of our protocol negotiation messages was rejected.
     #[error("Arti rejected our negotiation attempts: {0:?}")]
     NegotiationRejected(ErrorResponse),
     /// One of our authentication messages was rejected.
     #[error("Arti rejected our authentication: {0:?}")]
     AuthenticationRejected(ErrorResponse),
+    /// Arti didn't offer any authentication scheme that we can use.
+    #[error("No supported authentication scheme")]
+    NoSupportedAuth,
+    /// We couldn't read the cookie file that we needed to authenticate.
+    #[error("Unable to read RPC cookie file: {0}")]
+    CannotReadCookie(Arc<std::io::Error>),
+    /// The other side of our connection couldn't prove that it knew our cookie.
+    ///
+    /// Either the cookie file is out of date,
+    /// or we're not talking to the Arti that wrote it.
+    #[error("Arti did not prove that it knows the RPC cookie")]
+    CookieMismatch,
     /// We couldn't decode one of the responses we got.
     #[error("Message not in expected format: {0:?}")]
     BadMessage(Arc<serde_json::Error>),
@@ -399,6 +557,26 @@ pub enum ConnectError {
 }
 define_from_for_arc!(serde_json::Error => ConnectError [BadMessage]);
 
+/// An error from one of the helper methods that sends a request on an [`RpcConn`],
+/// such as [`RpcConn::release`].
+#[derive(Clone, Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum RequestError {
+    /// We couldn't send the request, or get a response.
+    #[error("Unable to complete request: {0}")]
+    ProtoError(#[from] ProtoError),
+    /// Arti reported an error in response to our request.
+    #[error("Arti reported an error: {0:?}")]
+    ErrorReply(ErrorResponse),
+    /// We couldn't decode Arti's response.
+    #[error("Message not in expected format: {0:?}")]
+    BadMessage(Arc<serde_json::Error>),
+    /// We haven't authenticated, so we have no session to send the request to.
+    #[error("Not authenticated")]
+    NoSession,
+}
+define_from_for_arc!(serde_json::Error => RequestError [BadMessage]);
+
 /// An error occurred while trying to construct or manipulate a
 #[derive(Clone, Debug, thiserror::Error)]
 #[non_exhaustive]
@@ -424,9 +602,9 @@ mod test {
     #![allow(clippy::needless_pass_by_value)]
     //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
 
-    use std::{sync::atomic::AtomicUsize, thread, time::Duration};
+    use std::{path::Path, sync::atomic::AtomicUsize, thread, time::Duration};
 
-    use io::{BufRead as _, Write as _};
+    use io::{BufRead, Write as _};
     use rand::{seq::SliceRandom as _, Rng as _, SeedableRng as _};
     use tor_basic_utils::{test_rng::testing_rng, RngExt as _};
 
@@ -738,4 +916,465 @@ mod test {
             )
         });
     }
+
+    #[test]
+    fn connect_string() {
+        let auth_of = |s| RpcConnBuilder::from_connect_string(s).map(|b| b.auth);
+
+        let b = RpcConnBuilder::from_connect_string("unix:/a/b").unwrap();
+        assert_eq!(b.unix_socket, PathBuf::from("/a/b"));
+        assert!(matches!(b.auth, RpcAuth::Auto));
+        assert!(matches!(
+            auth_of("unix:/a/b;auth=peer_uid"),
+            Ok(RpcAuth::PeerUid)
+        ));
+        assert!(matches!(
+            auth_of("unix:/a/b;cookie_path=/c"),
+            Ok(RpcAuth::Cookie(p)) if p == Path::new("/c")
+        ));
+        assert!(matches!(
+            auth_of("unix:/a/b;auth=cookie;cookie_path=/c"),
+            Ok(RpcAuth::Cookie(p)) if p == Path::new("/c")
+        ));
+        assert!(matches!(
+            auth_of("unix:/a/b;token=xyzzy"),
+            Ok(RpcAuth::Token(t)) if t == "xyzzy"
+        ));
+        let b =
+            RpcConnBuilder::from_connect_string("unix:/a/b;framing=cbor;auth=peer_uid").unwrap();
+        assert_eq!(b.framing, llconn::Framing::Cbor);
+        assert!(matches!(b.auth, RpcAuth::PeerUid));
+
+        for bad in [
+            "tcp:127.0.0.1",
+            "unix:/a/b;auth=frob",
+            "unix:/a/b;auth=token",
+            // We never take the cookie's location from Arti.
+            "unix:/a/b;auth=cookie",
+            "unix:/a/b;auth=peer_uid;token=xyzzy",
+            "unix:/a/b;cookie_path",
+            "unix:/a/b;flavor=cherry",
+            "unix:/a/b;framing=xml",
+        ] {
+            assert!(RpcConnBuilder::from_connect_string(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn auto_auth() {
+        let (conn, sock) = dummy_connected();
+
+        let user_thread = thread::spawn(move || conn.authenticate(&RpcAuth::Auto));
+
+        let fake_arti_thread = thread::spawn(move || {
+            fn read_request(sock: &mut impl BufRead) -> Request<JsonMap> {
+                let mut s = String::new();
+                let _len = sock.read_line(&mut s).unwrap();
+                serde_json::from_str(&s).unwrap()
+            }
+            let mut sock = BufReader::new(sock);
+
+            let query = read_request(&mut sock);
+            assert_eq!(query.method, "auth:query");
+            let response = serde_json::json!({
+                "id": query.id.clone(),
+                "result": {
+                    // We have been told of no cookie, and we have no token,
+                    // so we should fall back to peer_uid.
+                    "schemes": ["fs:cookie", "preshared:token", "x:frob", "inherent:peer_uid"],
+                }
+            });
+            write_val(sock.get_mut(), &response);
+
+            let auth = read_request(&mut sock);
+            assert_eq!(auth.method, "auth:authenticate");
+            assert_eq!(auth.params.get("scheme").unwrap(), "inherent:peer_uid");
+            assert!(auth.params.get("secret").is_none());
+            let response = serde_json::json!({
+                "id": auth.id.clone(),
+                "result": { "session": "abc" }
+            });
+            write_val(sock.get_mut(), &response);
+            sock // prevent close
+        });
+
+        let _sock = fake_arti_thread.join().unwrap();
+        let session = user_thread.join().unwrap().unwrap();
+        assert_eq!(session.as_ref(), "abc");
+    }
+
+    #[test]
+    fn cookie_auth() {
+        use auth::COOKIE_SERVER_MAC_CUSTOM as SERVER;
+        use auth::{cookie_mac, encode_cookie_field, COOKIE_CLIENT_MAC_CUSTOM};
+
+        let dir = tempfile::tempdir().unwrap();
+        let cookie_path = dir.path().join("cookie");
+        std::fs::write(&cookie_path, "the-real-cookie\n").unwrap();
+
+        // If `arti_cookie` is wrong, the fake Arti can't prove that it knows the cookie.
+        for arti_cookie in ["the-real-cookie", "a-guess"] {
+            let (conn, sock) = dummy_connected();
+            let auth = RpcAuth::Cookie(cookie_path.clone());
+            let user_thread = thread::spawn(move || conn.authenticate(&auth));
+
+            let fake_arti_thread = thread::spawn(move || {
+                fn read_request(sock: &mut impl BufRead) -> Request<JsonMap> {
+                    let mut s = String::new();
+                    let _len = sock.read_line(&mut s).unwrap();
+                    serde_json::from_str(&s).unwrap()
+                }
+                /// Decode a nonce or MAC.
+                fn decode(v: &serde_json::Value) -> [u8; 32] {
+                    use base64ct::{Base64Unpadded as B64, Encoding};
+                    let mut out = [0; 32];
+                    B64::decode(v.as_str().unwrap(), &mut out).unwrap();
+                    out
+                }
+                let mut sock = BufReader::new(sock);
+
+                let begin = read_request(&mut sock);
+                assert_eq!(begin.method, "auth:cookie_begin");
+                let client_nonce = decode(begin.params.get("client_nonce").unwrap());
+                let server_nonce = [7; 32];
+                let server_mac = cookie_mac(arti_cookie, SERVER, &client_nonce, &server_nonce);
+                let response = serde_json::json!({
+                    "id": begin.id.clone(),
+                    "result": {
+                        "server_nonce": encode_cookie_field(&server_nonce),
+                        "server_mac": encode_cookie_field(&server_mac),
+                    }
+                });
+                write_val(sock.get_mut(), &response);
+
+                if arti_cookie != "the-real-cookie" {
+                    // The client must give up without answering.
+                    let mut s = String::new();
+                    assert_eq!(sock.read_line(&mut s).unwrap(), 0);
+                    return;
+                }
+
+                let auth = read_request(&mut sock);
+                assert_eq!(auth.method, "auth:authenticate");
+                assert_eq!(auth.params.get("scheme").unwrap(), "fs:cookie");
+                let expected_mac = cookie_mac(
+                    "the-real-cookie",
+                    COOKIE_CLIENT_MAC_CUSTOM,
+                    &client_nonce,
+                    &server_nonce,
+                );
+                assert_eq!(decode(auth.params.get("secret").unwrap()), expected_mac);
+                let response = serde_json::json!({
+                    "id": auth.id.clone(),
+                    "result": { "session": "abc" }
+                });
+                write_val(sock.get_mut(), &response);
+                let mut s = String::new();
+                let _ = sock.read_line(&mut s); // wait for the client to finish
+            });
+
+            let result = user_thread.join().unwrap();
+            fake_arti_thread.join().unwrap();
+            match arti_cookie {
+                "the-real-cookie" => assert_eq!(result.unwrap().as_ref(), "abc"),
+                _ => assert!(matches!(result, Err(ConnectError::CookieMismatch))),
+            }
+        }
+    }
+
+    #[test]
+    fn cookie_mac_known_answer() {
+        // arti-rpcserver has the same test, so we know that we agree.
+        use auth::{cookie_mac, COOKIE_CLIENT_MAC_CUSTOM, COOKIE_SERVER_MAC_CUSTOM};
+        let cookie = "cookie-for-testing";
+        let (client_nonce, server_nonce) = ([1; 32], [2; 32]);
+        assert_eq!(
+            hex::encode(cookie_mac(
+                cookie,
+                COOKIE_SERVER_MAC_CUSTOM,
+                &client_nonce,
+                &server_nonce
+            )),
+            "b8fe49dce33f50bd76174f1761381759277c9397709e315467cd41660e1e2c6b"
+        );
+        assert_eq!(
+            hex::encode(cookie_mac(
+                cookie,
+                COOKIE_CLIENT_MAC_CUSTOM,
+                &client_nonce,
+                &server_nonce
+            )),
+            "aed2f80427211f2da148a371219bc6b614d85d1598d0c334371d854e7dde9514"
+        );
+    }
+
+    #[test]
+    fn object_lifetimes() {
+        let (mut conn, sock) = dummy_connected();
+        let obj = ObjectId::from("strong".to_string());
+        assert!(matches!(conn.release(&obj), Err(RequestError::NoSession)));
+        conn.session = Some(ObjectId::from("sess".to_string()));
+
+        let user_thread = thread::spawn(move || {
+            conn.release(&obj).unwrap();
+            let weak = conn.downgrade(&obj).unwrap();
+            assert_eq!(weak.as_object_id().as_ref(), "weak");
+
+            let mut hnd = conn.watch_expiry([weak.as_object_id()]).unwrap();
+            let AnyResponse::Update(u) = hnd.wait_with_updates().unwrap() else {
+                panic!("Expected an update");
+            };
+            assert_eq!(u.expired_object(), Some(weak.into()));
+            assert!(matches!(
+                hnd.wait_with_updates().unwrap(),
+                AnyResponse::Success(_)
+            ));
+            conn
+        });
+
+        let fake_arti_thread = thread::spawn(move || {
+            fn expect_request(
+                sock: &mut impl BufRead,
+                method: &str,
+                params: serde_json::Value,
+            ) -> AnyRequestId {
+                let mut s = String::new();
+                let _len = sock.read_line(&mut s).unwrap();
+                let request: Request<JsonMap> = serde_json::from_str(&s).unwrap();
+                assert_eq!(request.obj.as_ref(), "sess");
+                assert_eq!(request.method, method);
+                assert_eq!(serde_json::Value::Object(request.params), params);
+                request.id
+            }
+            let mut sock = BufReader::new(sock);
+
+            let id = expect_request(
+                &mut sock,
+                "rpc:release",
+                serde_json::json!({"obj": "strong"}),
+            );
+            let response = serde_json::json!({"id": id, "result": {}});
+            write_val(sock.get_mut(), &response);
+
+            let id = expect_request(
+                &mut sock,
+                "rpc:downgrade",
+                serde_json::json!({"obj": "strong"}),
+            );
+            let response = serde_json::json!({"id": id, "result": {"obj": "weak"}});
+            write_val(sock.get_mut(), &response);
+
+            let id = expect_request(
+                &mut sock,
+                "rpc:watch_expiry",
+                serde_json::json!({"objs": ["weak"]}),
+            );
+            let update = serde_json::json!({"id": id.clone(), "update": {"expired": "weak"}});
+            write_val(sock.get_mut(), &update);
+            let response = serde_json::json!({"id": id, "result": {}});
+            write_val(sock.get_mut(), &response);
+            sock // prevent close
+        });
+
+        let _sock = fake_arti_thread.join().unwrap();
+        let _conn = user_thread.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timeouts() {
+        use std::{os::unix::net::UnixStream, sync::mpsc};
+
+        let (s1, sock) = UnixStream::pair().unwrap();
+        let s1_w = s1.try_clone().unwrap();
+        let s1_t = s1.try_clone().unwrap();
+        let conn = RpcConn::new(
+            llconn::Reader::new(io::BufReader::new(s1))
+                .with_read_timeout_fn(move |t| s1_t.set_read_timeout(t)),
+            llconn::Writer::new(s1_w),
+        );
+        let (go_tx, go_rx) = mpsc::channel::<()>();
+
+        let user_thread = thread::spawn(move || {
+            let short = Duration::from_millis(50);
+            let long = Duration::from_secs(60);
+
+            // Nothing arrives in time, but we can keep waiting.
+            let mut hnd = conn
+                .execute_with_handle(r#"{"id":1,"obj":"fred","method":"arti:x-frob","params":{}}"#)
+                .unwrap();
+            assert!(matches!(hnd.wait_timeout(short), Err(ProtoError::Timeout)));
+            go_tx.send(()).unwrap();
+            let AnyResponse::Update(_) = hnd.wait_with_updates_timeout(long).unwrap() else {
+                panic!("Expected an update");
+            };
+            let response = hnd.wait_timeout(long).unwrap().unwrap();
+            let map = response.deserialize_as::<JsonMap>().unwrap();
+            assert_eq!(map.get("x"), Some(&serde_json::json!(1)));
+
+            // A request that times out is forgotten, and its reply is discarded.
+            let r = conn.execute_with_timeout(
+                r#"{"id":2,"obj":"fred","method":"arti:x-frob","params":{}}"#,
+                short,
+            );
+            assert!(matches!(r, Err(ProtoError::Timeout)));
+            go_tx.send(()).unwrap();
+            let response = conn
+                .execute_with_timeout(
+                    r#"{"id":3,"obj":"fred","method":"arti:x-frob","params":{}}"#,
+                    long,
+                )
+                .unwrap()
+                .unwrap();
+            let map = response.deserialize_as::<JsonMap>().unwrap();
+            assert_eq!(map.get("x"), Some(&serde_json::json!(3)));
+            conn
+        });
+
+        let fake_arti_thread = thread::spawn(move || {
+            fn read_request(sock: &mut impl BufRead) -> AnyRequestId {
+                let mut s = String::new();
+                let _len = sock.read_line(&mut s).unwrap();
+                let request: Request<JsonMap> = serde_json::from_str(&s).unwrap();
+                request.id
+            }
+            let mut sock = BufReader::new(sock);
+
+            let id = read_request(&mut sock);
+            go_rx.recv().unwrap();
+            write_val(
+                sock.get_mut(),
+                &serde_json::json!({"id": id.clone(), "update": {}}),
+            );
+            write_val(
+                sock.get_mut(),
+                &serde_json::json!({"id": id, "result": {"x": 1}}),
+            );
+
+            let id = read_request(&mut sock);
+            go_rx.recv().unwrap();
+            write_val(
+                sock.get_mut(),
+                &serde_json::json!({"id": id, "result": {"x": 2}}),
+            );
+            let id = read_request(&mut sock);
+            write_val(
+                sock.get_mut(),
+                &serde_json::json!({"id": id, "result": {"x": 3}}),
+            );
+            sock // prevent close
+        });
+
+        let _sock = fake_arti_thread.join().unwrap();
+        let _conn = user_thread.join().unwrap();
+    }
+
+    #[test]
+    fn update_stream() {
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Progress {
+            n: u32,
+        }
+
+        let (mut conn, sock) = dummy_connected();
+        conn.session = Some(ObjectId::from("sess".to_string()));
+
+        let user_thread = thread::spawn(move || {
+            // Read a whole stream.
+            let mut stream = conn
+                .execute_with_update_stream::<Progress>(
+                    r#"{"obj":"fred","method":"arti:x-frob","params":{}}"#,
+                )
+                .unwrap();
+            assert_eq!(stream.next().unwrap().unwrap(), Progress { n: 1 });
+            assert!(matches!(
+                stream.next().unwrap(),
+                Err(RequestError::BadMessage(_))
+            ));
+            assert_eq!(stream.next().unwrap().unwrap(), Progress { n: 2 });
+            assert!(stream.next().is_none());
+            assert!(stream.next().is_none());
+            assert!(matches!(stream.final_response(), Some(Ok(_))));
+            assert!(stream.finish().unwrap().is_ok());
+
+            // Drop a stream early: it gets cancelled.
+            let mut stream = conn
+                .execute_with_update_stream::<Progress>(
+                    r#"{"id":"dropme","obj":"fred","method":"arti:x-frob","params":{}}"#,
+                )
+                .unwrap();
+            assert_eq!(stream.next().unwrap().unwrap(), Progress { n: 1 });
+            drop(stream);
+
+            // Later responses to the cancelled request are discarded.
+            let response = conn
+                .execute(r#"{"obj":"fred","method":"arti:x-frob","params":{}}"#)
+                .unwrap()
+                .unwrap();
+            let map = response.deserialize_as::<JsonMap>().unwrap();
+            assert_eq!(map.get("done"), Some(&serde_json::json!(true)));
+            conn
+        });
+
+        let fake_arti_thread = thread::spawn(move || {
+            fn read_request(sock: &mut impl BufRead) -> Request<JsonMap> {
+                let mut s = String::new();
+                let _len = sock.read_line(&mut s).unwrap();
+                serde_json::from_str(&s).unwrap()
+            }
+            let mut sock = BufReader::new(sock);
+
+            let req = read_request(&mut sock);
+            assert_eq!(req.meta.unwrap().updates, true);
+            for update in [
+                serde_json::json!({"n": 1}),
+                serde_json::json!({"n": "bogus"}),
+                serde_json::json!({"n": 2}),
+            ] {
+                write_val(
+                    sock.get_mut(),
+                    &serde_json::json!({"id": req.id.clone(), "update": update}),
+                );
+            }
+            write_val(
+                sock.get_mut(),
+                &serde_json::json!({"id": req.id, "result": {}}),
+            );
+
+            let req = read_request(&mut sock);
+            write_val(
+                sock.get_mut(),
+                &serde_json::json!({"id": req.id.clone(), "update": {"n": 1}}),
+            );
+            let cancel = read_request(&mut sock);
+            assert_eq!(cancel.method, "rpc:cancel");
+            assert_eq!(cancel.obj.as_ref(), "sess");
+            assert_eq!(
+                serde_json::Value::Object(cancel.params),
+                serde_json::json!({"request_id": "dropme"})
+            );
+            write_val(
+                sock.get_mut(),
+                &serde_json::json!({"id": req.id, "update": {"n": 2}}),
+            );
+            write_val(
+                sock.get_mut(),
+                &serde_json::json!({"id": "dropme", "error": {"message": "cancelled", "code": 2, "kinds": ["Other"]}}),
+            );
+            write_val(
+                sock.get_mut(),
+                &serde_json::json!({"id": cancel.id, "result": {}}),
+            );
+
+            let req = read_request(&mut sock);
+            write_val(
+                sock.get_mut(),
+                &serde_json::json!({"id": req.id, "result": {"done": true}}),
+            );
+            sock // prevent close
+        });
+
+        let _sock = fake_arti_thread.join().unwrap();
+        let _conn = user_thread.join().unwrap();
+    }
 }
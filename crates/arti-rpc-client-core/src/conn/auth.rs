@@ -1,5 +1,10 @@
 //! Authentication for RpcConn.
 
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use crate::msgs::{request::Request, ObjectId};
@@ -10,7 +15,7 @@ impl super::SuccessResponse {
     /// Try to decode the "result" field of a SuccessResponse as an instance of `D`.
     //
     // TODO RPC: This might want to be moved and made public.  If we do, it needs a different error type.
-    pub(crate) fn deserialize_as<D: DeserializeOwned>(&self) -> Result<D, ConnectError> {
+    pub(crate) fn deserialize_as<D: DeserializeOwned>(&self) -> Result<D, serde_json::Error> {
         /// Helper object for decoding the "result" field.
         #[derive(Deserialize)]
         struct Response<R> {
@@ -23,11 +28,82 @@ impl super::SuccessResponse {
     }
 }
 
+/// How to authenticate to Arti.
+///
+/// Used with [`RpcConnBuilder::with_auth`](super::RpcConnBuilder::with_auth).
+#[derive(Clone, Default, educe::Educe)]
+#[educe(Debug)]
+#[non_exhaustive]
+pub enum RpcAuth {
+    /// Ask Arti which authentication schemes it supports,
+    /// and use the first one that we're able to.
+    ///
+    /// We never try `fs:cookie` or `preshared:token` in this mode,
+    /// since we don't know where a cookie is, or what the token is.
+    #[default]
+    Auto,
+    /// Claim authority from having been able to open the socket at all.
+    UnixPath,
+    /// Claim authority from the user ID of this process.
+    PeerUid,
+    /// Prove that we can read the cookie file at this location.
+    ///
+    /// We never send the cookie itself:
+    /// instead, Arti proves that it knows the cookie,
+    /// and then we do the same, in a challenge-response exchange
+    /// like C Tor's `SAFECOOKIE`.
+    /// So a process that is only pretending to be Arti can't learn the cookie.
+    Cookie(PathBuf),
+    /// Present a pre-shared token.
+    Token(#[educe(Debug(ignore))] String),
+}
+
+/// Scheme name for `RpcAuth::UnixPath`.
+const SCHEME_UNIX_PATH: &str = "inherent:unix_path";
+/// Scheme name for `RpcAuth::PeerUid`.
+const SCHEME_PEER_UID: &str = "inherent:peer_uid";
+/// Scheme name for `RpcAuth::Cookie`.
+const SCHEME_COOKIE: &str = "fs:cookie";
+/// Scheme name for `RpcAuth::Token`.
+const SCHEME_TOKEN: &str = "preshared:token";
+
+/// Arguments to an `auth:query` request.
+#[derive(Serialize, Debug)]
+struct QueryParams {}
+
+/// Response to an `auth:query` request.
+#[derive(Deserialize, Debug)]
+struct SupportedAuth {
+    /// The schemes that Arti supports, most preferred first.
+    ///
+    /// We use strings here so that we can ignore schemes we don't recognize.
+    schemes: Vec<String>,
+}
+
+/// Arguments to an `auth:cookie_begin` request.
+#[derive(Serialize, Debug)]
+struct CookieBeginParams {
+    /// Our random nonce.
+    client_nonce: String,
+}
+
+/// Response to an `auth:cookie_begin` request.
+#[derive(Deserialize, Debug)]
+struct CookieBeginReply {
+    /// Arti's random nonce.
+    server_nonce: String,
+    /// Arti's MAC over both nonces, keyed with the cookie.
+    server_mac: String,
+}
+
 /// Arguments to an `auth:authenticate` request.
 #[derive(Serialize, Debug)]
 struct AuthParams<'a> {
     /// The authentication scheme we are using.
     scheme: &'a str,
+    /// The secret that we're presenting, if the scheme requires one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<&'a str>,
 }
 /// Response to an `auth:authenticate` request.
 #[derive(Deserialize, Debug)]
@@ -36,15 +112,152 @@ struct Authenticated {
     session: ObjectId,
 }
 
+/// Read the secret from the cookie file at `path`.
+fn read_cookie(path: &Path) -> Result<String, ConnectError> {
+    let cookie =
+        std::fs::read_to_string(path).map_err(|e| ConnectError::CannotReadCookie(Arc::new(e)))?;
+    Ok(cookie.trim().to_owned())
+}
+
+/// Length of the nonces in `fs:cookie` authentication, in bytes.
+const COOKIE_NONCE_LEN: usize = 32;
+
+/// Customization string for the MAC by which Arti proves that it knows the cookie.
+pub(super) const COOKIE_SERVER_MAC_CUSTOM: &[u8] = b"arti-rpc-cookie-v1-server";
+
+/// Customization string for the MAC by which we prove that we know the cookie.
+pub(super) const COOKIE_CLIENT_MAC_CUSTOM: &[u8] = b"arti-rpc-cookie-v1-client";
+
+/// Return the KMAC256 of our two nonces, keyed with `cookie`,
+/// with `custom` as the customization string.
+///
+/// This must agree with `CookieChallenge` in `arti-rpcserver`.
+pub(super) fn cookie_mac(
+    cookie: &str,
+    custom: &[u8],
+    client_nonce: &[u8; COOKIE_NONCE_LEN],
+    server_nonce: &[u8; COOKIE_NONCE_LEN],
+) -> [u8; 32] {
+    use tiny_keccak::{Hasher as _, Kmac};
+    let mut mac = Kmac::v256(cookie.as_bytes(), custom);
+    mac.update(&client_nonce[..]);
+    mac.update(&server_nonce[..]);
+    let mut out = [0_u8; 32];
+    mac.finalize(&mut out);
+    out
+}
+
+/// Return true if `a` and `b` are equal, in time that doesn't depend on where they differ.
+fn ct_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Encode a nonce or MAC for `fs:cookie` authentication, as it goes on the wire.
+pub(super) fn encode_cookie_field(bytes: &[u8]) -> String {
+    use base64ct::{Base64Unpadded as B64, Encoding};
+    B64::encode_string(bytes)
+}
+
+/// Decode a nonce or MAC for `fs:cookie` authentication, as it goes on the wire.
+fn decode_cookie_field(s: &str) -> Result<[u8; 32], ConnectError> {
+    use base64ct::{Base64Unpadded as B64, Encoding};
+    let mut out = [0_u8; 32];
+    match B64::decode(s, &mut out[..]) {
+        Ok(decoded) if decoded.len() == out.len() => Ok(out),
+        _ => Err(ConnectError::CookieMismatch),
+    }
+}
+
 impl RpcConn {
-    /// Try to negotiate "inherent" authentication, using the provided scheme name.
+    /// Authenticate to Arti as described by `auth`, and return the resulting session.
+    pub(crate) fn authenticate(&self, auth: &RpcAuth) -> Result<ObjectId, ConnectError> {
+        match auth {
+            RpcAuth::Auto => self.authenticate_auto(),
+            RpcAuth::UnixPath => self.authenticate_with(SCHEME_UNIX_PATH, None),
+            RpcAuth::PeerUid => self.authenticate_with(SCHEME_PEER_UID, None),
+            RpcAuth::Cookie(path) => self.authenticate_cookie(&read_cookie(path)?),
+            RpcAuth::Token(token) => self.authenticate_with(SCHEME_TOKEN, Some(token)),
+        }
+    }
+
+    /// Ask Arti how we may authenticate, and use the first scheme that we can.
+    fn authenticate_auto(&self) -> Result<ObjectId, ConnectError> {
+        let supported = self.query_auth()?;
+        for scheme in &supported.schemes {
+            match scheme.as_str() {
+                SCHEME_PEER_UID | SCHEME_UNIX_PATH => {
+                    return self.authenticate_with(scheme, None);
+                }
+                // We have no cookie or token; and we can't use schemes we don't know.
+                _ => {}
+            }
+        }
+        Err(ConnectError::NoSupportedAuth)
+    }
+
+    /// Authenticate with `fs:cookie`, proving that we know `cookie`.
     ///
-    /// (Inherent authentication is available whenever the client proves that they
-    /// are authorized through being able to connect to Arti at all.  Examples
-    /// include connecting to a unix domain socket, and an in-process Arti implementation.)
-    pub(crate) fn authenticate_inherent(
+    /// We check that Arti knows the cookie before we answer its challenge.
+    fn authenticate_cookie(&self, cookie: &str) -> Result<ObjectId, ConnectError> {
+        use rand::Rng as _;
+        let client_nonce: [u8; COOKIE_NONCE_LEN] = rand::thread_rng().gen();
+        let r: Request<CookieBeginParams> = Request {
+            id: 0.into(),
+            obj: "connection".to_string().into(),
+            meta: Default::default(),
+            method: "auth:cookie_begin".into(),
+            params: CookieBeginParams {
+                client_nonce: encode_cookie_field(&client_nonce[..]),
+            },
+        };
+        let reply = self
+            .execute(&r.encode()?)?
+            .map_err(ConnectError::AuthenticationRejected)?
+            .deserialize_as::<CookieBeginReply>()?;
+        let server_nonce = decode_cookie_field(&reply.server_nonce)?;
+        let server_mac = decode_cookie_field(&reply.server_mac)?;
+
+        let expected = cookie_mac(
+            cookie,
+            COOKIE_SERVER_MAC_CUSTOM,
+            &client_nonce,
+            &server_nonce,
+        );
+        if !ct_eq(&expected, &server_mac) {
+            return Err(ConnectError::CookieMismatch);
+        }
+
+        let client_mac = cookie_mac(
+            cookie,
+            COOKIE_CLIENT_MAC_CUSTOM,
+            &client_nonce,
+            &server_nonce,
+        );
+        self.authenticate_with(SCHEME_COOKIE, Some(&encode_cookie_field(&client_mac[..])))
+    }
+
+    /// Send an `auth:query` request, and return the answer.
+    fn query_auth(&self) -> Result<SupportedAuth, ConnectError> {
+        let r: Request<QueryParams> = Request {
+            id: 0.into(),
+            obj: "connection".to_string().into(),
+            meta: Default::default(),
+            method: "auth:query".into(),
+            params: QueryParams {},
+        };
+        let supported = self
+            .execute(&r.encode()?)?
+            .map_err(ConnectError::NegotiationRejected)?
+            .deserialize_as::<SupportedAuth>()?;
+        Ok(supported)
+    }
+
+    /// Try to authenticate using the scheme named `scheme_name`,
+    /// presenting `secret` if it's provided.
+    fn authenticate_with(
         &self,
         scheme_name: &str,
+        secret: Option<&str>,
     ) -> Result<ObjectId, ConnectError> {
         let r: Request<AuthParams> = Request {
             id: 0.into(),
@@ -53,6 +266,7 @@ impl RpcConn {
             method: "auth:authenticate".into(),
             params: AuthParams {
                 scheme: scheme_name,
+                secret,
             },
         };
         let authenticated = self
@@ -10,6 +10,7 @@
 use std::{
     collections::{HashMap, VecDeque},
     sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::Instant,
 };
 
 use crate::{
@@ -209,13 +210,23 @@ impl RpcConn {
     /// make sense. If `msg` has no `id` field, we allocate a new one
     /// according to the rules in [`IdGenerator`].
     ///
+    /// If `want_updates` is true, we ask Arti for incremental updates,
+    /// regardless of what `msg` says.
+    ///
     /// Limitation: We don't preserved unrecognized fields in the framing and meta
     /// parts of `msg`.  See notes in `request.rs`.
-    pub(super) fn send_request(&self, msg: &str) -> Result<super::RequestHandle, ProtoError> {
+    pub(super) fn send_request(
+        &self,
+        msg: &str,
+        want_updates: bool,
+    ) -> Result<super::RequestHandle, ProtoError> {
         use std::collections::hash_map::Entry::*;
 
-        let loose: LooseParsedRequest =
+        let mut loose: LooseParsedRequest =
             serde_json::from_str(msg).map_err(|e| ProtoError::InvalidRequest(Arc::new(e)))?;
+        if want_updates {
+            loose.request_updates();
+        }
         let mut state = self.receiver.state.lock().expect("poisoned");
         if let Some(f) = &state.fatal {
             // If there's been a fatal error we don't even try to send the request.
@@ -267,14 +278,23 @@ impl Receiver {
     /// Wait until there is either a fatal error on this connection,
     /// _or_ there is a new message for the request with the provided `id`.
     /// Return that message, or a copy of the fatal error.
+    ///
+    /// If `deadline` is provided, and it passes before either of those things happens,
+    /// return [`ProtoError::Timeout`].  The request remains pending in that case,
+    /// and it is okay to wait for it again.
+    ///
+    /// (Limitation: If the `llconn::Reader` for this connection does not
+    /// support read timeouts, a thread that is currently reading may not notice
+    /// that its deadline has passed until it receives another message.)
     pub(super) fn wait_on_message_for(
         &self,
         id: &AnyRequestId,
+        deadline: Option<Instant>,
     ) -> Result<ValidatedResponse, ProtoError> {
         // Here in wait_on_message_for_impl, we do the the actual work
         // of waiting for the message.
         let state = self.state.lock().expect("posioned");
-        let (result, mut state, should_alert) = self.wait_on_message_for_impl(state, id);
+        let (result, mut state, should_alert) = self.wait_on_message_for_impl(state, id, deadline);
 
         // Great; we have a message or a fatal error.  All we need to do now
         // is to restore our invariants before we drop state_lock.
@@ -286,6 +306,8 @@ impl Receiver {
             // "final" in this case means that we are not expecting any more
             // replies for this request.
             let is_final = match &result {
+                // A timeout just means that we stopped waiting.
+                Err(ProtoError::Timeout) => false,
                 Err(_) => true,
                 Ok(r) => r.is_final(),
             };
@@ -332,6 +354,7 @@ impl Receiver {
         &'a self,
         mut state_lock: MutexGuard<'a, ReceiverState>,
         id: &AnyRequestId,
+        deadline: Option<Instant>,
     ) -> (
         Result<ValidatedResponse, ProtoError>,
         MutexGuard<'a, ReceiverState>,
@@ -370,6 +393,12 @@ impl Receiver {
                 return (ready.map_err(ProtoError::from), state_lock, should_alert);
             }
 
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                // We've run out of time.  If we were supposed to take the reader,
+                // `should_alert` will make sure that somebody else does.
+                return (Err(ProtoError::Timeout), state_lock, should_alert);
+            }
+
             // If we reach this point, we are about to either take the reader or
             // register a cv.  This means that when we return, we need to make
             // sure that at least one other cv gets notified.
@@ -384,7 +413,15 @@ impl Receiver {
             let cv = Arc::new(Condvar::new());
             this_ent.waiter = Some(Arc::clone(&cv));
 
-            state_lock = cv.wait(state_lock).expect("poisoned lock");
+            state_lock = match deadline {
+                None => cv.wait(state_lock).expect("poisoned lock"),
+                Some(d) => {
+                    let remaining = d.saturating_duration_since(Instant::now());
+                    cv.wait_timeout(state_lock, remaining)
+                        .expect("poisoned lock")
+                        .0
+                }
+            };
             state = &mut state_lock;
             // Restore `this_ent`...
             let Some(e) = state.pending.get_mut(id) else {
@@ -394,21 +431,35 @@ impl Receiver {
             // ... And un-register our condvar.
             this_ent.waiter = None;
 
-            // We have been notified: either there is a reply or us,
-            // or we are supposed to take the reader.  We'll find out on our
-            // next time through the loop.
+            // We have been notified (or timed out): either there is a reply or us,
+            // or we are supposed to take the reader, or our deadline has passed.
+            // We'll find out on our next time through the loop.
         };
 
         let (result, mut state_lock, should_alert) =
-            self.read_until_message_for(state_lock, &mut reader, id);
+            self.read_until_message_for(state_lock, &mut reader, id, deadline);
         // Put the reader back.
         state_lock.reader = Some(reader);
 
-        (result.map_err(ProtoError::from), state_lock, should_alert)
+        (result, state_lock, should_alert)
+    }
+
+    /// Forget about the request with the provided `id`.
+    ///
+    /// Any responses that arrive for it later will be discarded.
+    pub(super) fn forget(&self, id: &AnyRequestId) {
+        let mut state = self.state.lock().expect("poisoned");
+        if let Some(ent) = state.pending.remove(id) {
+            // Nobody should be waiting on this request, but if they are,
+            // let them know that it's gone.
+            if let Some(cv) = ent.waiter {
+                cv.notify_one();
+            }
+        }
     }
 
     /// Read messages, delivering them as appropriate, until we find one for `id`,
-    /// or a fatal error occurs.
+    /// or a fatal error occurs, or `deadline` passes.
     ///
     /// Return that message or error, along with a `MutexGuard`.
     ///
@@ -422,8 +473,9 @@ impl Receiver {
         mut state_lock: MutexGuard<'a, ReceiverState>,
         reader: &mut llconn::Reader,
         id: &AnyRequestId,
+        deadline: Option<Instant>,
     ) -> (
-        Result<ValidatedResponse, ShutdownError>,
+        Result<ValidatedResponse, ProtoError>,
         MutexGuard<'a, ReceiverState>,
         AlertWhom,
     ) {
@@ -432,17 +484,17 @@ impl Receiver {
             // This is okay, since all our invariants should hold at this point.
             drop(state_lock);
 
-            let result: Result<ValidatedResponse, _> = match reader.read_msg() {
-                Err(e) => Err(ShutdownError::Read(Arc::new(e))),
-                Ok(None) => Err(ShutdownError::ConnectionClosed),
-                Ok(Some(m)) => m.try_validate().map_err(ShutdownError::from),
-            };
+            let result = read_msg_before(reader, deadline);
 
             state_lock = self.state.lock().expect("poisoned lock");
             let state = &mut state_lock;
 
             match result {
-                Ok(m) if m.id() == id => {
+                Ok(None) => {
+                    // We timed out; somebody else will have to take the reader.
+                    return (Err(ProtoError::Timeout), state_lock, AlertWhom::Anybody);
+                }
+                Ok(Some(m)) if m.id() == id => {
                     // This only is for us, so there's no need to alert anybody
                     // or queue it.
                     return (Ok(m), state_lock, AlertWhom::Anybody);
@@ -455,9 +507,9 @@ impl Receiver {
                     if state.fatal.is_none() {
                         state.fatal = Some(e.clone());
                     }
-                    return (Err(e), state_lock, AlertWhom::Everybody);
+                    return (Err(e.into()), state_lock, AlertWhom::Everybody);
                 }
-                Ok(m) => {
+                Ok(Some(m)) => {
                     // This is a message for exactly one ID, that isn't us.
                     // Queue it and notify them.
                     if let Some(ent) = state.pending.get_mut(m.id()) {
@@ -476,3 +528,41 @@ impl Receiver {
         }
     }
 }
+
+/// Read a single message from `reader`, giving up if `deadline` passes.
+///
+/// Return `Ok(None)` if we timed out.
+fn read_msg_before(
+    reader: &mut llconn::Reader,
+    deadline: Option<Instant>,
+) -> Result<Option<ValidatedResponse>, ShutdownError> {
+    let timeout = match deadline {
+        None => None,
+        Some(d) => match d.checked_duration_since(Instant::now()) {
+            Some(t) if !t.is_zero() => Some(t),
+            _ => return Ok(None),
+        },
+    };
+    // Note that we have to set (or clear) the timeout every time,
+    // since the last thread to use this reader may have had a different deadline.
+    //
+    // If the reader doesn't support timeouts, we just block.
+    reader
+        .set_read_timeout(timeout)
+        .map_err(|e| ShutdownError::Read(Arc::new(e)))?;
+
+    match reader.read_msg() {
+        Err(e)
+            if deadline.is_some()
+                && matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(ShutdownError::Read(Arc::new(e))),
+        Ok(None) => Err(ShutdownError::ConnectionClosed),
+        Ok(Some(m)) => m.try_validate().map(Some).map_err(ShutdownError::from),
+    }
+}
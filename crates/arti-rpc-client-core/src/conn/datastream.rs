@@ -0,0 +1,364 @@
+//! Support for opening data streams through Arti.
+//!
+//! To open a stream, we ask Arti for a stream handle over RPC,
+//! and then open a SOCKS connection to one of Arti's proxy listeners,
+//! presenting that handle as our SOCKS credentials.
+//! The SOCKS connection then carries the stream's data.
+
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::Arc,
+};
+
+use serde::Deserialize;
+
+use crate::{msgs::ObjectId, util::define_from_for_arc};
+
+use super::{RequestError, RpcConn};
+
+/// The SOCKS username that tells Arti to look up an RPC object.
+const RPC_SESSION_USERNAME: &[u8] = b"<arti-rpc-session>";
+
+/// Response to an `arti:get_rpc_proxy_info` request.
+#[derive(Deserialize, Debug)]
+struct ProxyInfo {
+    /// The proxies that Arti is running.
+    proxies: Vec<Proxy>,
+}
+
+/// A single proxy in a [`ProxyInfo`].
+#[derive(Deserialize, Debug)]
+struct Proxy {
+    /// Where the proxy is listening.
+    listener: ProxyListener,
+}
+
+/// A proxy listener in a [`ProxyInfo`].
+///
+/// Listeners of kinds that we don't recognize are decoded with every field empty.
+#[derive(Deserialize, Debug)]
+struct ProxyListener {
+    /// Present if this is a SOCKS5 proxy.
+    socks5: Option<Socks5Listener>,
+}
+
+/// A SOCKS5 proxy listener in a [`ProxyInfo`].
+#[derive(Deserialize, Debug)]
+struct Socks5Listener {
+    /// The TCP address of the proxy, if it has one.
+    tcp_address: Option<SocketAddr>,
+}
+
+/// Response to an `arti:new_stream_handle` request.
+#[derive(Deserialize, Debug)]
+struct StreamHandle {
+    /// The new stream object.
+    id: ObjectId,
+}
+
+/// An error that occurred while opening a data stream.
+#[derive(Clone, Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum StreamError {
+    /// One of our RPC requests failed.
+    #[error("RPC request failed: {0}")]
+    RpcMethods(#[from] RequestError),
+    /// Arti didn't tell us about any proxy that we can use.
+    #[error("Arti has no SOCKS proxy that we can use")]
+    NoProxy,
+    /// We couldn't talk to the SOCKS proxy.
+    #[error("Unable to talk to the SOCKS proxy: {0}")]
+    Io(Arc<io::Error>),
+    /// The SOCKS proxy said something we didn't understand.
+    #[error("SOCKS protocol violation: {0}")]
+    SocksProtocol(&'static str),
+    /// The SOCKS proxy reported that it couldn't open the stream.
+    #[error("SOCKS request failed with reply code {0}")]
+    SocksRequest(u8),
+    /// A hostname or isolation string was too long to send over SOCKS.
+    #[error("Hostname or isolation string too long")]
+    ParamTooLong,
+}
+define_from_for_arc!( io::Error => StreamError [Io] );
+
+impl RpcConn {
+    /// Open a data stream to `target_host`:`target_port` over the Tor network.
+    ///
+    /// The stream is launched by `on_object`, if provided;
+    /// otherwise, by our session.
+    /// Streams with different `isolation` strings will not share circuits.
+    ///
+    /// The returned stream carries the stream's data directly;
+    /// there is nothing more to do with RPC.
+    pub fn open_stream(
+        &self,
+        on_object: Option<&ObjectId>,
+        (target_host, target_port): (&str, u16),
+        isolation: &str,
+    ) -> Result<TcpStream, StreamError> {
+        let (handle, stream) =
+            self.open_stream_as_object(on_object, (target_host, target_port), isolation)?;
+        self.release(&handle)?;
+        Ok(stream)
+    }
+
+    /// As [`open_stream`](Self::open_stream), but also return
+    /// the RPC object that represents the stream.
+    ///
+    /// The caller is responsible for releasing that object once it
+    /// no longer needs it.
+    pub fn open_stream_as_object(
+        &self,
+        on_object: Option<&ObjectId>,
+        (target_host, target_port): (&str, u16),
+        isolation: &str,
+    ) -> Result<(ObjectId, TcpStream), StreamError> {
+        let proxy = self.find_socks_proxy()?;
+
+        let cmd = match on_object {
+            Some(obj) => serde_json::to_string(&serde_json::json!({
+                "obj": obj,
+                "method": "arti:new_stream_handle",
+                "params": {},
+            }))
+            .map_err(RequestError::from)?,
+            None => self.session_request("arti:new_stream_handle", serde_json::json!({}), false)?,
+        };
+        let handle: StreamHandle = self
+            .execute(&cmd)
+            .map_err(RequestError::from)?
+            .map_err(RequestError::ErrorReply)?
+            .deserialize_as()
+            .map_err(RequestError::from)?;
+
+        let mut password = handle.id.as_ref().to_owned();
+        if !isolation.is_empty() {
+            password.push(':');
+            password.push_str(isolation);
+        }
+
+        let connected = TcpStream::connect(proxy)
+            .map_err(StreamError::from)
+            .and_then(|mut s| {
+                socks_handshake(
+                    &mut s,
+                    password.as_bytes(),
+                    target_host.as_bytes(),
+                    target_port,
+                )?;
+                Ok(s)
+            });
+        match connected {
+            Ok(stream) => Ok((handle.id, stream)),
+            Err(e) => {
+                // We won't be using this handle after all.
+                let _ignore = self.release(&handle.id);
+                Err(e)
+            }
+        }
+    }
+
+    /// Ask Arti for the address of a SOCKS proxy that we can use to open streams.
+    fn find_socks_proxy(&self) -> Result<SocketAddr, StreamError> {
+        let cmd = serde_json::to_string(&serde_json::json!({
+            "obj": "connection",
+            "method": "arti:get_rpc_proxy_info",
+            "params": {},
+        }))
+        .map_err(RequestError::from)?;
+        let info: ProxyInfo = self
+            .execute(&cmd)
+            .map_err(RequestError::from)?
+            .map_err(RequestError::ErrorReply)?
+            .deserialize_as()
+            .map_err(RequestError::from)?;
+        info.proxies
+            .into_iter()
+            .find_map(|p| p.listener.socks5?.tcp_address)
+            .ok_or(StreamError::NoProxy)
+    }
+}
+
+/// Perform a SOCKS5 handshake on `s`, authenticating as an RPC stream with
+/// `password`, and asking to connect to `host`:`port`.
+fn socks_handshake<S: Read + Write>(
+    s: &mut S,
+    password: &[u8],
+    host: &[u8],
+    port: u16,
+) -> Result<(), StreamError> {
+    let host_len = u8::try_from(host.len()).map_err(|_| StreamError::ParamTooLong)?;
+    let password_len = u8::try_from(password.len()).map_err(|_| StreamError::ParamTooLong)?;
+
+    // Offer username/password authentication only.
+    s.write_all(&[5, 1, 2])?;
+    let mut reply = [0_u8; 2];
+    s.read_exact(&mut reply)?;
+    if reply != [5, 2] {
+        return Err(StreamError::SocksProtocol(
+            "Proxy did not accept username/password authentication",
+        ));
+    }
+
+    let mut msg = vec![1, RPC_SESSION_USERNAME.len() as u8];
+    msg.extend_from_slice(RPC_SESSION_USERNAME);
+    msg.push(password_len);
+    msg.extend_from_slice(password);
+    s.write_all(&msg)?;
+    s.read_exact(&mut reply)?;
+    if reply[0] != 1 {
+        return Err(StreamError::SocksProtocol(
+            "Bad username/password negotiation version",
+        ));
+    }
+    // (Arti always accepts our "authentication";
+    // it only checks the handle when we send the request.)
+
+    let mut msg = vec![5, 1, 0, 3, host_len];
+    msg.extend_from_slice(host);
+    msg.extend_from_slice(&port.to_be_bytes());
+    s.write_all(&msg)?;
+    s.flush()?;
+
+    let mut header = [0_u8; 4];
+    s.read_exact(&mut header)?;
+    if header[0] != 5 {
+        return Err(StreamError::SocksProtocol("Bad reply version"));
+    }
+    // Consume the bound address, which we don't need.
+    let addr_len = match header[3] {
+        1 => 4,
+        4 => 16,
+        3 => {
+            let mut len = [0_u8; 1];
+            s.read_exact(&mut len)?;
+            usize::from(len[0])
+        }
+        _ => {
+            return Err(StreamError::SocksProtocol(
+                "Unrecognized address type in reply",
+            ))
+        }
+    };
+    let mut addr = vec![0_u8; addr_len + 2];
+    s.read_exact(&mut addr)?;
+
+    match header[1] {
+        0 => Ok(()),
+        code => Err(StreamError::SocksRequest(code)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+    use std::io::Cursor;
+
+    /// A fake SOCKS proxy, with canned replies.
+    struct FakeProxy {
+        /// What the proxy will say.
+        replies: Cursor<Vec<u8>>,
+        /// What we have said to the proxy.
+        sent: Vec<u8>,
+    }
+    impl Read for FakeProxy {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+    impl Write for FakeProxy {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    impl FakeProxy {
+        fn new(replies: &[u8]) -> Self {
+            Self {
+                replies: Cursor::new(replies.to_vec()),
+                sent: Vec::new(),
+            }
+        }
+    }
+
+    #[test]
+    fn handshake() {
+        let mut p = FakeProxy::new(&[5, 2, 1, 0, 5, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        socks_handshake(&mut p, b"abc:iso", b"example.com", 443).unwrap();
+
+        let mut expect = vec![5, 1, 2, 1, 18];
+        expect.extend_from_slice(b"<arti-rpc-session>");
+        expect.push(7);
+        expect.extend_from_slice(b"abc:iso");
+        expect.extend_from_slice(&[5, 1, 0, 3, 11]);
+        expect.extend_from_slice(b"example.com");
+        expect.extend_from_slice(&[1, 187]);
+        assert_eq!(p.sent, expect);
+
+        // Hostname-style bound address, and a failure code.
+        let mut p = FakeProxy::new(&[5, 2, 1, 0, 5, 4, 0, 3, 2, b'h', b'i', 0, 0]);
+        assert!(matches!(
+            socks_handshake(&mut p, b"abc", b"example.com", 80),
+            Err(StreamError::SocksRequest(4))
+        ));
+
+        // No username/password support.
+        let mut p = FakeProxy::new(&[5, 0xff]);
+        assert!(matches!(
+            socks_handshake(&mut p, b"abc", b"example.com", 80),
+            Err(StreamError::SocksProtocol(_))
+        ));
+
+        // Proxy hangs up.
+        let mut p = FakeProxy::new(&[5, 2, 1]);
+        assert!(matches!(
+            socks_handshake(&mut p, b"abc", b"example.com", 80),
+            Err(StreamError::Io(_))
+        ));
+
+        // Over-long hostname.
+        let mut p = FakeProxy::new(&[]);
+        assert!(matches!(
+            socks_handshake(&mut p, b"abc", &[b'x'; 256], 80),
+            Err(StreamError::ParamTooLong)
+        ));
+    }
+
+    #[test]
+    fn proxy_info() {
+        let info: ProxyInfo = serde_json::from_str(
+            r#"{"proxies":[
+                {"listener":{"x-frob":{}}},
+                {"listener":{"socks5":{"tcp_address":"127.0.0.1:9150"}}}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(info.proxies[0].listener.socks5.is_none());
+        assert_eq!(
+            info.proxies[1]
+                .listener
+                .socks5
+                .as_ref()
+                .unwrap()
+                .tcp_address,
+            Some("127.0.0.1:9150".parse().unwrap())
+        );
+    }
+}
@@ -0,0 +1,355 @@
+//! Support for subscribing to global events from Arti.
+//!
+//! To subscribe, we invoke `arti:subscribe` on a client, naming the kinds of
+//! event we want; Arti gives us back a subscription object.
+//! We then invoke `arti:watch_events` on that object,
+//! and receive each event as an update to that request.
+
+use serde::{Deserialize, Serialize};
+
+use crate::msgs::ObjectId;
+
+use super::{RequestError, RpcConn, UpdateStream};
+
+/// A kind of global event that we can subscribe to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum EventKind {
+    /// A change in the client's bootstrap status.
+    Bootstrap,
+    /// The arrival of a new consensus.
+    Consensus,
+    /// A change in the client's list of primary guards.
+    Guards,
+    /// A change in the status of an onion service that Arti is running.
+    OnionService,
+}
+
+/// A single global event, as received from an [`EventStream`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Event {
+    /// The client's bootstrap status is as given.
+    Bootstrap {
+        /// The new status.
+        status: BootstrapStatus,
+    },
+    /// The client has a new consensus.
+    Consensus {
+        /// The time at which the consensus became valid, in RFC 3339 format.
+        valid_after: String,
+        /// The time until which the consensus is the newest one we expect,
+        /// in RFC 3339 format.
+        fresh_until: String,
+        /// The time after which the consensus is no longer valid, in RFC 3339 format.
+        valid_until: String,
+    },
+    /// The client's primary guards have changed.
+    Guards {
+        /// The identities of the primary guards, in preference order.
+        primary: Vec<String>,
+    },
+    /// The status of an onion service has changed.
+    OnionService {
+        /// The nickname of the service.
+        nickname: String,
+        /// The service's new high-level state.
+        state: OnionServiceState,
+        /// The service's current problem, if it has one.
+        problem: Option<OnionServiceProblem>,
+    },
+    /// An event of a kind that this version of the library doesn't recognize.
+    #[serde(other)]
+    Unrecognized,
+}
+
+/// A client's bootstrap status, as reported in an [`Event::Bootstrap`].
+#[derive(Clone, Debug, Deserialize)]
+#[non_exhaustive]
+pub struct BootstrapStatus {
+    /// True if the client is ready for traffic.
+    pub ready: bool,
+    /// Approximate estimate of how close the client is to being ready for traffic,
+    /// from 0.0 to 1.0.
+    pub fraction: f32,
+    /// If present, a description of possible problem(s) that may be stopping
+    /// the client from using the Tor network.
+    pub blocked: Option<String>,
+    /// Every problem that Arti could identify that may be stopping the client
+    /// from bootstrapping, most likely root cause first.
+    #[serde(default)]
+    pub problems: Vec<BootstrapProblem>,
+}
+
+/// A single problem reported in a [`BootstrapStatus`].
+#[derive(Clone, Debug, Deserialize)]
+#[non_exhaustive]
+pub struct BootstrapProblem {
+    /// A stable, machine-readable name for the kind of problem.
+    pub kind: String,
+    /// A human-readable description of what Arti observed.
+    pub message: String,
+    /// A human-readable suggestion for how to fix the problem.
+    pub hint: String,
+}
+
+/// The high-level state of an onion service, as reported in an [`Event::OnionService`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum OnionServiceState {
+    /// The service is not launched.
+    Shutdown,
+    /// The service is bootstrapping.
+    Bootstrapping,
+    /// The service is running, but Arti isn't satisfied with its introduction points.
+    Degraded,
+    /// The service is running.
+    Running,
+    /// The service is trying to recover from a minor interruption.
+    Recovering,
+    /// The service is not working.
+    Broken,
+    /// A state that Arti, or this version of the library, doesn't recognize.
+    #[serde(other)]
+    Unknown,
+}
+
+/// A problem with an onion service, as reported in an [`Event::OnionService`].
+#[derive(Clone, Debug, Deserialize)]
+#[non_exhaustive]
+pub struct OnionServiceProblem {
+    /// What kind of problem this is.
+    pub kind: OnionServiceProblemKind,
+    /// A human-readable description of the problem.
+    pub message: String,
+}
+
+/// A kind of [`OnionServiceProblem`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum OnionServiceProblemKind {
+    /// A fatal error occurred.
+    Runtime,
+    /// Arti failed to upload a descriptor.
+    DescriptorUpload,
+    /// Arti failed to establish one or more introduction points.
+    IntroductionPoints,
+    /// A kind of problem that Arti, or this version of the library, doesn't recognize.
+    #[serde(other)]
+    Unknown,
+}
+
+/// Arguments to an `arti:subscribe` request.
+#[derive(Serialize, Debug)]
+struct SubscribeParams<'a> {
+    /// The kinds of event to receive; if absent, we receive all of them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    events: Option<&'a [EventKind]>,
+}
+
+/// Response to an `arti:get_client` or `arti:subscribe` request.
+#[derive(Deserialize, Debug)]
+struct NewObject {
+    /// The object that Arti returned.
+    id: ObjectId,
+}
+
+/// A stream of global events from Arti.
+///
+/// Created with [`RpcConn::subscribe_events`].
+///
+/// You can receive events with [`next_event`](EventStream::next_event),
+/// or by using this type as an [`Iterator`].
+/// The stream only ends if Arti stops delivering events,
+/// for example because the client has shut down.
+///
+/// Dropping this object stops the delivery of events,
+/// but does not release the subscription object:
+/// see [`subscription`](EventStream::subscription).
+#[derive(Debug)]
+pub struct EventStream<'a> {
+    /// The subscription object that we are receiving events from.
+    subscription: ObjectId,
+    /// The `arti:watch_events` request that delivers the events.
+    updates: UpdateStream<'a, Event>,
+}
+
+impl RpcConn {
+    /// Subscribe to the global events of a client, and return a stream of them.
+    ///
+    /// If `on_object` is provided, it must be a client; otherwise we use
+    /// the session's default client.
+    ///
+    /// We only receive events of the kinds in `kinds`; if `kinds` is empty,
+    /// we receive every kind of event.
+    /// The stream begins by reporting the current state of each kind of event
+    /// that we asked for.
+    pub fn subscribe_events(
+        &self,
+        on_object: Option<&ObjectId>,
+        kinds: &[EventKind],
+    ) -> Result<EventStream<'_>, RequestError> {
+        let client = match on_object {
+            Some(obj) => obj.clone(),
+            None => {
+                // Arti gives us the same ID for the default client every time we ask,
+                // so there's no need to release it afterwards.
+                let cmd = self.session_request("arti:get_client", serde_json::json!({}), false)?;
+                let client: NewObject = self
+                    .execute(&cmd)?
+                    .map_err(RequestError::ErrorReply)?
+                    .deserialize_as()?;
+                client.id
+            }
+        };
+
+        let params = SubscribeParams {
+            events: (!kinds.is_empty()).then_some(kinds),
+        };
+        let cmd = serde_json::to_string(&serde_json::json!({
+            "obj": client,
+            "method": "arti:subscribe",
+            "params": params,
+        }))?;
+        let subscription: NewObject = self
+            .execute(&cmd)?
+            .map_err(RequestError::ErrorReply)?
+            .deserialize_as()?;
+        let subscription = subscription.id;
+
+        let cmd = serde_json::to_string(&serde_json::json!({
+            "obj": subscription,
+            "method": "arti:watch_events",
+            "params": {},
+        }))?;
+        let updates = self.execute_with_update_stream(&cmd)?;
+
+        Ok(EventStream {
+            subscription,
+            updates,
+        })
+    }
+}
+
+impl EventStream<'_> {
+    /// Return the subscription object that this stream receives events from.
+    ///
+    /// The caller is responsible for releasing that object
+    /// (with [`RpcConn::release`]) once it no longer needs it.
+    pub fn subscription(&self) -> &ObjectId {
+        &self.subscription
+    }
+
+    /// Wait for the next event.
+    ///
+    /// Return `Ok(None)` if Arti has stopped delivering events.
+    pub fn next_event(&mut self) -> Result<Option<Event>, RequestError> {
+        self.updates.next_update()
+    }
+}
+
+impl Iterator for EventStream<'_> {
+    type Item = Result<Event, RequestError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event().transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+
+    #[test]
+    fn decode_events() {
+        let ev: Event = serde_json::from_str(
+            r#"{"kind":"bootstrap","status":{"ready":false,"fraction":0.5,"blocked":null,"problems":[
+                {"kind":"clock_skew","message":"Clock is wrong","hint":"Fix it"}
+            ]}}"#,
+        )
+        .unwrap();
+        match ev {
+            Event::Bootstrap { status } => {
+                assert!(!status.ready);
+                assert_eq!(status.fraction, 0.5);
+                assert!(status.blocked.is_none());
+                assert_eq!(status.problems.len(), 1);
+                assert_eq!(status.problems[0].kind, "clock_skew");
+                assert_eq!(status.problems[0].hint, "Fix it");
+            }
+            other => panic!("{other:?}"),
+        }
+
+        let ev: Event = serde_json::from_str(
+            r#"{"kind":"onion_service","nickname":"allium-cepa","state":"recovering",
+                "problem":{"kind":"descriptor_upload","message":"oops"}}"#,
+        )
+        .unwrap();
+        match ev {
+            Event::OnionService {
+                nickname,
+                state,
+                problem,
+            } => {
+                assert_eq!(nickname, "allium-cepa");
+                assert_eq!(state, OnionServiceState::Recovering);
+                let problem = problem.unwrap();
+                assert_eq!(problem.kind, OnionServiceProblemKind::DescriptorUpload);
+                assert_eq!(problem.message, "oops");
+            }
+            other => panic!("{other:?}"),
+        }
+        let ev: Event = serde_json::from_str(
+            r#"{"kind":"onion_service","nickname":"x","state":"hibernating","problem":null}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            ev,
+            Event::OnionService {
+                state: OnionServiceState::Unknown,
+                ..
+            }
+        ));
+
+        let ev: Event =
+            serde_json::from_str(r#"{"kind":"guards","primary":["ed25519:abc"]}"#).unwrap();
+        match ev {
+            Event::Guards { primary } => assert_eq!(primary, vec!["ed25519:abc".to_string()]),
+            other => panic!("{other:?}"),
+        }
+
+        let ev: Event = serde_json::from_str(r#"{"kind":"solar_flare","x":3}"#).unwrap();
+        assert!(matches!(ev, Event::Unrecognized));
+    }
+
+    #[test]
+    fn encode_params() {
+        let p = SubscribeParams {
+            events: Some(&[EventKind::Consensus, EventKind::OnionService]),
+        };
+        assert_eq!(
+            serde_json::to_string(&p).unwrap(),
+            r#"{"events":["consensus","onion_service"]}"#
+        );
+        let p = SubscribeParams { events: None };
+        assert_eq!(serde_json::to_string(&p).unwrap(), "{}");
+    }
+}
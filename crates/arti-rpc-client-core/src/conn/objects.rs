@@ -0,0 +1,130 @@
+//! Helpers for managing the lifetime of objects on an RpcConn.
+//!
+//! Every strong reference that Arti gives us keeps an object alive
+//! until we release it, or until the connection closes.
+//! Long-lived applications should release references they no longer need,
+//! or downgrade them to weak references.
+
+use serde::{Deserialize, Serialize};
+
+use crate::msgs::{ObjectId, WeakObjectId};
+
+use super::{RequestError, RequestHandle, RpcConn, SuccessResponse, UpdateResponse};
+
+/// Arguments to `rpc:release` and `rpc:downgrade` requests.
+#[derive(Serialize, Debug)]
+struct ObjParams<'a> {
+    /// The object to act on.
+    obj: &'a ObjectId,
+}
+
+/// Arguments to an `rpc:watch_expiry` request.
+#[derive(Serialize, Debug)]
+struct WatchExpiryParams<'a> {
+    /// The objects to watch.
+    objs: Vec<&'a ObjectId>,
+}
+
+/// Response to an `rpc:downgrade` request.
+#[derive(Deserialize, Debug)]
+struct Downgraded {
+    /// The new weak reference.
+    obj: WeakObjectId,
+}
+
+/// Update from an `rpc:watch_expiry` request.
+#[derive(Deserialize, Debug)]
+struct Expired {
+    /// The object that can no longer be used.
+    expired: ObjectId,
+}
+
+impl RpcConn {
+    /// Helper: Send `method` with `params` to our session object,
+    /// and wait for a successful response.
+    pub(super) fn call_on_session<P: Serialize>(
+        &self,
+        method: &str,
+        params: P,
+    ) -> Result<SuccessResponse, RequestError> {
+        let cmd = self.session_request(method, params, false)?;
+        self.execute(&cmd)?.map_err(RequestError::ErrorReply)
+    }
+
+    /// Helper: Encode a request to send `method` with `params` to our session object.
+    ///
+    /// We leave out the `id`, so that one will be generated.
+    pub(super) fn session_request<P: Serialize>(
+        &self,
+        method: &str,
+        params: P,
+        updates: bool,
+    ) -> Result<String, RequestError> {
+        let session = self.session().ok_or(RequestError::NoSession)?;
+        let mut request = serde_json::json!({
+            "obj": session,
+            "method": method,
+            "params": params,
+        });
+        if updates {
+            request["meta"] = serde_json::json!({ "updates": true });
+        }
+        Ok(serde_json::to_string(&request)?)
+    }
+
+    /// Release our strong reference to `obj`.
+    ///
+    /// Once every reference to an object has been released, Arti may drop it.
+    /// Afterwards, `obj` may not be used.
+    pub fn release(&self, obj: &ObjectId) -> Result<(), RequestError> {
+        let _: SuccessResponse = self.call_on_session("rpc:release", ObjParams { obj })?;
+        Ok(())
+    }
+
+    /// Replace our strong reference to `obj` with a weak reference.
+    ///
+    /// Afterwards, `obj` may not be used; use the returned [`WeakObjectId`] instead,
+    /// for as long as Arti keeps the object alive for some other reason.
+    pub fn downgrade(&self, obj: &ObjectId) -> Result<WeakObjectId, RequestError> {
+        let downgraded: Downgraded = self
+            .call_on_session("rpc:downgrade", ObjParams { obj })?
+            .deserialize_as()?;
+        Ok(downgraded.obj)
+    }
+
+    /// Ask Arti to tell us when any of `objs` can no longer be used.
+    ///
+    /// The returned request receives an update for each object once it has
+    /// expired, which can be decoded with [`UpdateResponse::expired_object`].
+    /// It finishes once every object has been reported.
+    ///
+    /// Arti doesn't necessarily notice an expired object immediately,
+    /// so applications should also be prepared for requests about an object to fail.
+    pub fn watch_expiry<'a>(
+        &self,
+        objs: impl IntoIterator<Item = &'a ObjectId>,
+    ) -> Result<RequestHandle, RequestError> {
+        let params = WatchExpiryParams {
+            objs: objs.into_iter().collect(),
+        };
+        let cmd = self.session_request("rpc:watch_expiry", params, true)?;
+        Ok(self.execute_with_handle(&cmd)?)
+    }
+}
+
+impl UpdateResponse {
+    /// If this is an update from [`RpcConn::watch_expiry`],
+    /// return the object that has expired.
+    ///
+    /// The object is identified exactly as it was in the call to `watch_expiry`.
+    pub fn expired_object(&self) -> Option<ObjectId> {
+        /// Helper object for decoding the "update" field.
+        #[derive(Deserialize)]
+        struct Update {
+            /// The decoded value.
+            update: Expired,
+        }
+        let u: Update = serde_json::from_str(self.as_ref()).ok()?;
+        Some(u.update.expired)
+    }
+}
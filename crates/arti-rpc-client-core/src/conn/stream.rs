@@ -0,0 +1,202 @@
+//! Support for receiving a stream of typed updates from a request,
+//! and for cancelling requests.
+
+use std::marker::PhantomData;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::msgs::AnyRequestId;
+
+use super::{
+    AnyResponse, FinalResponse, ProtoError, RequestError, RequestHandle, RpcConn, SuccessResponse,
+    UpdateResponse,
+};
+
+/// Arguments to an `rpc:cancel` request.
+#[derive(Serialize, Debug)]
+struct CancelParams<'a> {
+    /// The request to cancel.
+    request_id: &'a AnyRequestId,
+}
+
+impl UpdateResponse {
+    /// Try to decode the "update" field of an UpdateResponse as an instance of `D`.
+    pub(crate) fn deserialize_as<D: DeserializeOwned>(&self) -> Result<D, serde_json::Error> {
+        /// Helper object for decoding the "update" field.
+        #[derive(Deserialize)]
+        struct Update<U> {
+            /// The decoded value.
+            update: U,
+        }
+
+        let u: Update<D> = serde_json::from_str(self.as_ref())?;
+        Ok(u.update)
+    }
+}
+
+/// A request that is receiving incremental updates from Arti.
+///
+/// Created with [`RpcConn::execute_with_update_stream`].
+///
+/// Each update is decoded as an instance of `U`
+/// (by default, as an arbitrary JSON value).
+/// The final response is kept separately, and is available from
+/// [`final_response`](UpdateStream::final_response) or
+/// [`finish`](UpdateStream::finish).
+///
+/// You can receive updates with [`next_update`](UpdateStream::next_update),
+/// or by using this type as an [`Iterator`].
+///
+/// If this object is dropped before the request has finished,
+/// we ask Arti to cancel the request, and discard any further responses to it.
+#[derive(educe::Educe)]
+#[educe(Debug)]
+pub struct UpdateStream<'a, U = serde_json::Value> {
+    /// The connection that we used to send the request.
+    #[educe(Debug(ignore))]
+    conn: &'a RpcConn,
+    /// The handle for the request.
+    hnd: RequestHandle,
+    /// True if we will not receive any more messages for this request.
+    finished: bool,
+    /// The final response for this request, if we have received it.
+    final_response: Option<FinalResponse>,
+    /// Marker for the type of our updates.
+    #[educe(Debug(ignore))]
+    phantom: PhantomData<fn() -> U>,
+}
+
+impl RpcConn {
+    /// Send a request, asking Arti for incremental updates,
+    /// and return an [`UpdateStream`] to receive them.
+    ///
+    /// We always ask for updates, even if `cmd` does not set `updates` in its `meta` field.
+    pub fn execute_with_update_stream<U: DeserializeOwned>(
+        &self,
+        cmd: &str,
+    ) -> Result<UpdateStream<'_, U>, ProtoError> {
+        let hnd = self.send_request(cmd, true)?;
+        Ok(UpdateStream {
+            conn: self,
+            hnd,
+            finished: false,
+            final_response: None,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Ask Arti to cancel the request with the given `id`.
+    ///
+    /// On success, Arti has stopped working on the request,
+    /// and (unless it had already finished) sent it an error response.
+    pub fn cancel(&self, id: &AnyRequestId) -> Result<(), RequestError> {
+        let _: SuccessResponse =
+            self.call_on_session("rpc:cancel", CancelParams { request_id: id })?;
+        Ok(())
+    }
+
+    /// Ask Arti to cancel the request with the given `id`, without waiting
+    /// for it to do so.
+    ///
+    /// Any response to the cancellation is discarded.
+    fn cancel_nowait(&self, id: &AnyRequestId) -> Result<(), RequestError> {
+        let cmd = self.session_request("rpc:cancel", CancelParams { request_id: id }, false)?;
+        let hnd = self.execute_with_handle(&cmd)?;
+        hnd.conn.forget(&hnd.id);
+        Ok(())
+    }
+}
+
+impl<U: DeserializeOwned> UpdateStream<'_, U> {
+    /// Return the ID of this request.
+    pub fn id(&self) -> &AnyRequestId {
+        self.hnd.id()
+    }
+
+    /// Wait for the next update from this request.
+    ///
+    /// Return `Ok(None)` once the request has finished:
+    /// its final response is then available from [`final_response`](Self::final_response).
+    ///
+    /// If we can't decode an update as a `U`, we return
+    /// [`RequestError::BadMessage`]; it is fine to keep waiting for
+    /// more updates after that.
+    pub fn next_update(&mut self) -> Result<Option<U>, RequestError> {
+        match self.next_raw_update()? {
+            Some(u) => Ok(Some(u.deserialize_as()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Helper: Wait for the next update from this request, without decoding it.
+    ///
+    /// Return `Ok(None)` once the request has finished.
+    fn next_raw_update(&mut self) -> Result<Option<UpdateResponse>, ProtoError> {
+        if self.finished {
+            return Ok(None);
+        }
+        let response = self.hnd.wait_with_updates().map_err(|e| {
+            // The request is gone; nothing more will arrive.
+            self.finished = true;
+            e
+        })?;
+        match response {
+            AnyResponse::Update(u) => Ok(Some(u)),
+            AnyResponse::Success(s) => {
+                self.finish_with(Ok(s));
+                Ok(None)
+            }
+            AnyResponse::Error(e) => {
+                self.finish_with(Err(e));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Helper: Record that the request has finished with `response`.
+    fn finish_with(&mut self, response: FinalResponse) {
+        self.finished = true;
+        self.final_response = Some(response);
+    }
+
+    /// Return the final response for this request,
+    /// if we have received it.
+    pub fn final_response(&self) -> Option<&FinalResponse> {
+        self.final_response.as_ref()
+    }
+
+    /// Wait for this request to finish, discarding any remaining updates,
+    /// and return its final response.
+    ///
+    /// Note that this function will return `Err(.)` only if getting a
+    /// response failed.  If Arti reported an error in response to the request,
+    /// this function returns `Ok(Err(.))`.
+    pub fn finish(mut self) -> Result<FinalResponse, ProtoError> {
+        while self.next_raw_update()?.is_some() {}
+        // If there's no final response, we must have already reported an error.
+        self.final_response
+            .take()
+            .ok_or(ProtoError::RequestCancelled)
+    }
+}
+
+impl<U: DeserializeOwned> Iterator for UpdateStream<'_, U> {
+    type Item = Result<U, RequestError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_update().transpose()
+    }
+}
+
+impl<U> Drop for UpdateStream<'_, U> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        // We can't report an error from here, and we'd rather not block.
+        // If we can't ask Arti to cancel the request,
+        // the connection is probably closed anyway.
+        let _ignore = self.conn.cancel_nowait(self.hnd.id());
+        self.hnd.conn.forget(&self.hnd.id);
+    }
+}
@@ -0,0 +1,600 @@
+//! Exposed C APIs for arti-rpc-client-core.
+//!
+//! These functions are a thin layer over [`RpcConn`];
+//! see `include/arti-rpc-client-core.h` for the documentation
+//! that C users will actually see.
+//!
+//! ## Conventions
+//!
+//! * All strings passed in or out are NUL-terminated UTF-8.
+//! * Every fallible function returns an [`ArtiRpcStatus`].
+//!   On failure, if the caller provided a non-NULL `error_out`,
+//!   it is set to a newly allocated [`ArtiRpcError`] describing the problem.
+//! * Every object returned to the caller must be released with
+//!   its corresponding `_free` function.
+//! * None of these functions panic across the FFI boundary:
+//!   any panic is reported as `ARTI_RPC_STATUS_INTERNAL`.
+
+// Every function here takes raw pointers from C, so we document their safety
+// requirements in the header, and in the `# Safety` sections below.
+#![deny(unsafe_op_in_unsafe_fn)]
+
+use std::{
+    ffi::{c_char, CStr, CString},
+    fmt::Display,
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+    time::Duration,
+};
+
+use crate::{
+    conn::{ErrorResponse, ShutdownError},
+    BuilderError, ConnectError, ProtoError, RpcConn, RpcConnBuilder,
+};
+
+/// A status code returned by an Arti RPC function.
+///
+/// On success, a function will return `ARTI_RPC_STATUS_SUCCESS`.
+/// On failure, a function will return some other status code.
+pub type ArtiRpcStatus = u32;
+
+/// The function has returned successfully.
+pub const ARTI_RPC_STATUS_SUCCESS: ArtiRpcStatus = 0;
+/// One or more of the inputs to the function was invalid.
+pub const ARTI_RPC_STATUS_INVALID_INPUT: ArtiRpcStatus = 1;
+/// Tried to use some functionality (for example, a connection scheme)
+/// that isn't supported in this build.
+pub const ARTI_RPC_STATUS_NOT_SUPPORTED: ArtiRpcStatus = 2;
+/// An IO error occurred while trying to connect to Arti.
+pub const ARTI_RPC_STATUS_CONNECT_IO: ArtiRpcStatus = 3;
+/// We could not authenticate, or Arti rejected our negotiation attempts.
+pub const ARTI_RPC_STATUS_BAD_AUTH: ArtiRpcStatus = 4;
+/// Arti sent a message that didn't conform to the RPC protocol.
+pub const ARTI_RPC_STATUS_PEER_PROTOCOL_VIOLATION: ArtiRpcStatus = 5;
+/// The RPC connection was closed, or failed.
+pub const ARTI_RPC_STATUS_SHUTDOWN: ArtiRpcStatus = 6;
+/// An internal error occurred in the Arti RPC client.
+pub const ARTI_RPC_STATUS_INTERNAL: ArtiRpcStatus = 7;
+/// The request was sent successfully, but Arti reported an error in response.
+///
+/// The error's response (see `arti_rpc_err_response`) holds Arti's reply.
+pub const ARTI_RPC_STATUS_REQUEST_FAILED: ArtiRpcStatus = 8;
+/// The request was cancelled before it could complete.
+pub const ARTI_RPC_STATUS_REQUEST_CANCELLED: ArtiRpcStatus = 9;
+/// We gave up waiting for a response, since our timeout expired.
+pub const ARTI_RPC_STATUS_TIMEOUT: ArtiRpcStatus = 10;
+
+/// An open connection to Arti over the RPC protocol.
+///
+/// It is safe to use one of these from multiple threads at once.
+pub struct ArtiRpcConn {
+    /// The underlying connection.
+    conn: RpcConn,
+    /// The session ID from `conn`, in a form we can hand out to C.
+    session_id: Option<CString>,
+}
+
+/// An error returned by the Arti RPC code.
+#[derive(Debug)]
+pub struct ArtiRpcError {
+    /// The status code for this error.
+    status: ArtiRpcStatus,
+    /// A human-readable description of this error.
+    message: CString,
+    /// If this error was caused by an error reply from Arti,
+    /// that reply.
+    response: Option<CString>,
+}
+
+impl ArtiRpcError {
+    /// Construct a new ArtiRpcError with a given status, describing `err`.
+    fn new(status: ArtiRpcStatus, err: impl Display, response: Option<&ErrorResponse>) -> Self {
+        Self {
+            status,
+            message: cstring_lossy(err.to_string()),
+            response: response.map(|r| cstring_lossy(r.as_ref().clone())),
+        }
+    }
+}
+
+/// Convert `s` into a CString, replacing any NULs.
+///
+/// (None of our strings should contain NULs, but we'd rather not
+/// lose an error message if one somehow does.)
+fn cstring_lossy(s: String) -> CString {
+    CString::new(s).unwrap_or_else(|e| {
+        let s = String::from_utf8_lossy(&e.into_vec()).replace('\0', "\u{FFFD}");
+        CString::new(s).expect("Still had a NUL after removing NULs?")
+    })
+}
+
+impl From<BuilderError> for ArtiRpcError {
+    fn from(e: BuilderError) -> Self {
+        let status = match &e {
+            BuilderError::InvalidConnectString => ARTI_RPC_STATUS_INVALID_INPUT,
+        };
+        ArtiRpcError::new(status, e, None)
+    }
+}
+
+impl From<ConnectError> for ArtiRpcError {
+    fn from(e: ConnectError) -> Self {
+        use ConnectError as E;
+        let (status, response) = match &e {
+            E::SchemeNotSupported | E::FramingNotSupported(_) => {
+                (ARTI_RPC_STATUS_NOT_SUPPORTED, None)
+            }
+            E::CannotConnect(_) => (ARTI_RPC_STATUS_CONNECT_IO, None),
+            E::NegotiationRejected(r) | E::AuthenticationRejected(r) => {
+                (ARTI_RPC_STATUS_BAD_AUTH, Some(r))
+            }
+            E::NoSupportedAuth | E::CannotReadCookie(_) | E::CookieMismatch => {
+                (ARTI_RPC_STATUS_BAD_AUTH, None)
+            }
+            E::BadMessage(_) => (ARTI_RPC_STATUS_PEER_PROTOCOL_VIOLATION, None),
+            E::ProtoError(p) => (proto_error_status(p), None),
+        };
+        ArtiRpcError::new(status, &e, response)
+    }
+}
+
+impl From<ProtoError> for ArtiRpcError {
+    fn from(e: ProtoError) -> Self {
+        ArtiRpcError::new(proto_error_status(&e), e, None)
+    }
+}
+
+/// Return the status code to use for a given [`ProtoError`].
+fn proto_error_status(e: &ProtoError) -> ArtiRpcStatus {
+    use ProtoError as E;
+    match e {
+        E::Shutdown(ShutdownError::ProtocolViolated(_))
+        | E::Shutdown(ShutdownError::ProtocolViolationReport(_)) => {
+            ARTI_RPC_STATUS_PEER_PROTOCOL_VIOLATION
+        }
+        E::Shutdown(_) => ARTI_RPC_STATUS_SHUTDOWN,
+        E::InvalidRequest(_) | E::RequestIdInUse => ARTI_RPC_STATUS_INVALID_INPUT,
+        E::RequestCancelled => ARTI_RPC_STATUS_REQUEST_CANCELLED,
+        E::DuplicateWait | E::CouldNotEncode(_) => ARTI_RPC_STATUS_INTERNAL,
+        E::Timeout => ARTI_RPC_STATUS_TIMEOUT,
+    }
+}
+
+/// Helper: Run `body`, and convert its outcome into an [`ArtiRpcStatus`].
+///
+/// If `body` fails or panics, and `error_out` is not NULL,
+/// set `*error_out` to a newly allocated [`ArtiRpcError`].
+///
+/// # Safety
+///
+/// `error_out` must be NULL, or a valid pointer to a `*mut ArtiRpcError`.
+unsafe fn ffi_body<F>(error_out: *mut *mut ArtiRpcError, body: F) -> ArtiRpcStatus
+where
+    F: FnOnce() -> Result<(), ArtiRpcError>,
+{
+    if !error_out.is_null() {
+        // SAFETY: The caller promises that error_out is valid if non-NULL.
+        unsafe { *error_out = ptr::null_mut() };
+    }
+
+    // We never touch any state from `body` again after a panic,
+    // so it's okay to assert unwind safety here.
+    let err = match catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => return ARTI_RPC_STATUS_SUCCESS,
+        Ok(Err(e)) => e,
+        Err(_) => ArtiRpcError::new(ARTI_RPC_STATUS_INTERNAL, "Internal panic in Arti RPC", None),
+    };
+    let status = err.status;
+    if !error_out.is_null() {
+        // SAFETY: The caller promises that error_out is valid if non-NULL.
+        unsafe { *error_out = Box::into_raw(Box::new(err)) };
+    }
+    status
+}
+
+/// Helper: Return an error for an invalid input called `what`.
+fn invalid_input(what: &str) -> ArtiRpcError {
+    ArtiRpcError::new(
+        ARTI_RPC_STATUS_INVALID_INPUT,
+        format!("Invalid input: {}", what),
+        None,
+    )
+}
+
+/// Helper: Convert a pointer to a C string into a `&str`.
+///
+/// # Safety
+///
+/// `p` must be NULL, or a valid pointer to a NUL-terminated string
+/// that outlives `'a`.
+unsafe fn str_arg<'a>(p: *const c_char, what: &str) -> Result<&'a str, ArtiRpcError> {
+    if p.is_null() {
+        return Err(invalid_input(what));
+    }
+    // SAFETY: The caller promises that p is a valid NUL-terminated string.
+    unsafe { CStr::from_ptr(p) }
+        .to_str()
+        .map_err(|_| invalid_input(what))
+}
+
+/// Helper: Convert a pointer to an output pointer into a mutable reference,
+/// and set it to NULL.
+///
+/// # Safety
+///
+/// `p` must be NULL, or a valid pointer to a `*mut T`.
+unsafe fn out_arg<'a, T>(p: *mut *mut T, what: &str) -> Result<&'a mut *mut T, ArtiRpcError> {
+    // SAFETY: The caller promises that p is valid if non-NULL.
+    let out = unsafe { p.as_mut() }.ok_or_else(|| invalid_input(what))?;
+    *out = ptr::null_mut();
+    Ok(out)
+}
+
+/// Try to open a new connection to Arti.
+///
+/// The connection string is as described for
+/// [`RpcConnBuilder::from_connect_string`].
+///
+/// On success, set `*rpc_conn_out` to a new `ArtiRpcConn`,
+/// which must later be released with [`arti_rpc_conn_free`].
+///
+/// # Safety
+///
+/// `connection_string` must be a valid NUL-terminated string.
+/// `rpc_conn_out` must be a valid pointer.
+/// `error_out` must be NULL or a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn arti_rpc_connect(
+    connection_string: *const c_char,
+    rpc_conn_out: *mut *mut ArtiRpcConn,
+    error_out: *mut *mut ArtiRpcError,
+) -> ArtiRpcStatus {
+    // SAFETY: We pass on the caller's promises about each pointer.
+    unsafe {
+        ffi_body(error_out, || {
+            let out = out_arg(rpc_conn_out, "rpc_conn_out")?;
+            let s = str_arg(connection_string, "connection_string")?;
+            let conn = RpcConnBuilder::from_connect_string(s)?.connect()?;
+            let session_id = conn
+                .session()
+                .map(|id| cstring_lossy(id.as_ref().to_owned()));
+            *out = Box::into_raw(Box::new(ArtiRpcConn { conn, session_id }));
+            Ok(())
+        })
+    }
+}
+
+/// Return the session ID for an `ArtiRpcConn`, as a NUL-terminated string.
+///
+/// The returned string is owned by `rpc_conn`, and remains valid until
+/// `rpc_conn` is freed.  Do not free it yourself.
+///
+/// Return NULL if `rpc_conn` is NULL, or if the connection has no session.
+///
+/// # Safety
+///
+/// `rpc_conn` must be NULL or a valid pointer to an `ArtiRpcConn`.
+#[no_mangle]
+pub unsafe extern "C" fn arti_rpc_conn_get_session_id(
+    rpc_conn: *const ArtiRpcConn,
+) -> *const c_char {
+    // SAFETY: The caller promises that rpc_conn is valid if non-NULL.
+    match unsafe { rpc_conn.as_ref() }.and_then(|c| c.session_id.as_ref()) {
+        Some(id) => id.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Run an RPC request over `rpc_conn`, and wait for a final response.
+///
+/// The request must be a JSON object, as described in the RPC specification.
+/// It may omit the `id` field; if it does, one will be generated.
+///
+/// On success, set `*response_out` to a newly allocated string holding
+/// Arti's entire reply, which must later be released with [`arti_rpc_str_free`].
+///
+/// If Arti replies with an error, return `ARTI_RPC_STATUS_REQUEST_FAILED`,
+/// and include the reply in the error (see [`arti_rpc_err_response`]).
+///
+/// Any updates that Arti sends for this request are ignored.
+///
+/// # Safety
+///
+/// `rpc_conn` must be a valid pointer to an `ArtiRpcConn`.
+/// `msg` must be a valid NUL-terminated string.
+/// `response_out` must be a valid pointer.
+/// `error_out` must be NULL or a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn arti_rpc_conn_execute(
+    rpc_conn: *const ArtiRpcConn,
+    msg: *const c_char,
+    response_out: *mut *mut c_char,
+    error_out: *mut *mut ArtiRpcError,
+) -> ArtiRpcStatus {
+    // SAFETY: We pass on the caller's promises about each pointer.
+    unsafe { execute_impl(rpc_conn, msg, None, response_out, error_out) }
+}
+
+/// As [`arti_rpc_conn_execute`], but give up with `ARTI_RPC_STATUS_TIMEOUT`
+/// if no final response arrives within `timeout_msec` milliseconds.
+///
+/// Note that Arti may still be running the request after a timeout.
+///
+/// # Safety
+///
+/// As for [`arti_rpc_conn_execute`].
+#[no_mangle]
+pub unsafe extern "C" fn arti_rpc_conn_execute_with_timeout(
+    rpc_conn: *const ArtiRpcConn,
+    msg: *const c_char,
+    timeout_msec: u64,
+    response_out: *mut *mut c_char,
+    error_out: *mut *mut ArtiRpcError,
+) -> ArtiRpcStatus {
+    let timeout = Some(Duration::from_millis(timeout_msec));
+    // SAFETY: We pass on the caller's promises about each pointer.
+    unsafe { execute_impl(rpc_conn, msg, timeout, response_out, error_out) }
+}
+
+/// Helper: Implement [`arti_rpc_conn_execute`] and
+/// [`arti_rpc_conn_execute_with_timeout`].
+///
+/// # Safety
+///
+/// As for [`arti_rpc_conn_execute`].
+unsafe fn execute_impl(
+    rpc_conn: *const ArtiRpcConn,
+    msg: *const c_char,
+    timeout: Option<Duration>,
+    response_out: *mut *mut c_char,
+    error_out: *mut *mut ArtiRpcError,
+) -> ArtiRpcStatus {
+    // SAFETY: We pass on the caller's promises about each pointer.
+    unsafe {
+        ffi_body(error_out, || {
+            let out = out_arg(response_out, "response_out")?;
+            let conn = &rpc_conn
+                .as_ref()
+                .ok_or_else(|| invalid_input("rpc_conn"))?
+                .conn;
+            let msg = str_arg(msg, "msg")?;
+            let response = match timeout {
+                None => conn.execute(msg)?,
+                Some(t) => conn.execute_with_timeout(msg, t)?,
+            };
+            match response {
+                Ok(success) => {
+                    *out = cstring_lossy(success.as_ref().clone()).into_raw();
+                    Ok(())
+                }
+                Err(error) => Err(ArtiRpcError::new(
+                    ARTI_RPC_STATUS_REQUEST_FAILED,
+                    error.decode().message(),
+                    Some(&error),
+                )),
+            }
+        })
+    }
+}
+
+/// Close and free an open `ArtiRpcConn`.
+///
+/// Does nothing if `rpc_conn` is NULL.
+///
+/// # Safety
+///
+/// `rpc_conn` must be NULL, or a pointer returned by [`arti_rpc_connect`]
+/// that has not already been freed.
+/// No other thread may be using it.
+#[no_mangle]
+pub unsafe extern "C" fn arti_rpc_conn_free(rpc_conn: *mut ArtiRpcConn) {
+    if !rpc_conn.is_null() {
+        // SAFETY: The caller promises that this pointer came from Box::into_raw.
+        drop(unsafe { Box::from_raw(rpc_conn) });
+    }
+}
+
+/// Free a string returned by the Arti RPC API.
+///
+/// Does nothing if `string` is NULL.
+///
+/// # Safety
+///
+/// `string` must be NULL, or a string that was returned to the caller
+/// by an Arti RPC function as something to free, and that has not already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn arti_rpc_str_free(string: *mut c_char) {
+    if !string.is_null() {
+        // SAFETY: The caller promises that this pointer came from CString::into_raw.
+        drop(unsafe { CString::from_raw(string) });
+    }
+}
+
+/// Return the status code associated with a given `ArtiRpcError`.
+///
+/// Return `ARTI_RPC_STATUS_INVALID_INPUT` if `err` is NULL.
+///
+/// # Safety
+///
+/// `err` must be NULL or a valid pointer to an `ArtiRpcError`.
+#[no_mangle]
+pub unsafe extern "C" fn arti_rpc_err_status(err: *const ArtiRpcError) -> ArtiRpcStatus {
+    // SAFETY: The caller promises that err is valid if non-NULL.
+    match unsafe { err.as_ref() } {
+        Some(e) => e.status,
+        None => ARTI_RPC_STATUS_INVALID_INPUT,
+    }
+}
+
+/// Return a human-readable error message associated with a given `ArtiRpcError`.
+///
+/// The returned string is owned by `err`, and remains valid until
+/// `err` is freed.  Do not free it yourself.
+///
+/// Return NULL if `err` is NULL.
+///
+/// # Safety
+///
+/// `err` must be NULL or a valid pointer to an `ArtiRpcError`.
+#[no_mangle]
+pub unsafe extern "C" fn arti_rpc_err_message(err: *const ArtiRpcError) -> *const c_char {
+    // SAFETY: The caller promises that err is valid if non-NULL.
+    match unsafe { err.as_ref() } {
+        Some(e) => e.message.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// If the `ArtiRpcError` was caused by an error reply from Arti,
+/// return that reply, as a JSON string.
+///
+/// The returned string is owned by `err`, and remains valid until
+/// `err` is freed.  Do not free it yourself.
+///
+/// Return NULL if `err` is NULL, or if it was not caused by an error reply.
+///
+/// # Safety
+///
+/// `err` must be NULL or a valid pointer to an `ArtiRpcError`.
+#[no_mangle]
+pub unsafe extern "C" fn arti_rpc_err_response(err: *const ArtiRpcError) -> *const c_char {
+    // SAFETY: The caller promises that err is valid if non-NULL.
+    match unsafe { err.as_ref() }.and_then(|e| e.response.as_ref()) {
+        Some(r) => r.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Free an `ArtiRpcError`.
+///
+/// Does nothing if `err` is NULL.
+///
+/// # Safety
+///
+/// `err` must be NULL, or a pointer returned by an Arti RPC function
+/// that has not already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn arti_rpc_err_free(err: *mut ArtiRpcError) {
+    if !err.is_null() {
+        // SAFETY: The caller promises that this pointer came from Box::into_raw.
+        drop(unsafe { Box::from_raw(err) });
+    }
+}
+
+/// Return a short, static, human-readable name for an `ArtiRpcStatus`.
+///
+/// The returned string is statically allocated.  Do not free it.
+#[no_mangle]
+pub extern "C" fn arti_rpc_status_to_str(status: ArtiRpcStatus) -> *const c_char {
+    let s: &'static [u8] = match status {
+        ARTI_RPC_STATUS_SUCCESS => b"success\0",
+        ARTI_RPC_STATUS_INVALID_INPUT => b"invalid input\0",
+        ARTI_RPC_STATUS_NOT_SUPPORTED => b"not supported\0",
+        ARTI_RPC_STATUS_CONNECT_IO => b"unable to connect\0",
+        ARTI_RPC_STATUS_BAD_AUTH => b"authentication failed\0",
+        ARTI_RPC_STATUS_PEER_PROTOCOL_VIOLATION => b"arti violated the rpc protocol\0",
+        ARTI_RPC_STATUS_SHUTDOWN => b"rpc connection is shut down\0",
+        ARTI_RPC_STATUS_INTERNAL => b"internal error\0",
+        ARTI_RPC_STATUS_REQUEST_FAILED => b"request failed\0",
+        ARTI_RPC_STATUS_REQUEST_CANCELLED => b"request cancelled\0",
+        ARTI_RPC_STATUS_TIMEOUT => b"timed out\0",
+        _ => b"(unrecognized status)\0",
+    };
+    s.as_ptr().cast()
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+
+    /// Helper: Convert a C string that we returned into a `&str`.
+    fn s<'a>(p: *const c_char) -> &'a str {
+        assert!(!p.is_null());
+        unsafe { CStr::from_ptr(p) }.to_str().unwrap()
+    }
+
+    #[test]
+    fn bad_inputs() {
+        unsafe {
+            let mut conn: *mut ArtiRpcConn = ptr::null_mut();
+            let mut err: *mut ArtiRpcError = ptr::null_mut();
+
+            // NULL connection string.
+            let st = arti_rpc_connect(ptr::null(), &mut conn, &mut err);
+            assert_eq!(st, ARTI_RPC_STATUS_INVALID_INPUT);
+            assert!(conn.is_null());
+            assert_eq!(arti_rpc_err_status(err), ARTI_RPC_STATUS_INVALID_INPUT);
+            assert_eq!(
+                s(arti_rpc_err_message(err)),
+                "Invalid input: connection_string"
+            );
+            assert!(arti_rpc_err_response(err).is_null());
+            arti_rpc_err_free(err);
+
+            // Unparseable connection string.
+            let st = arti_rpc_connect(b"fred\0".as_ptr().cast(), &mut conn, &mut err);
+            assert_eq!(st, ARTI_RPC_STATUS_INVALID_INPUT);
+            assert_eq!(s(arti_rpc_err_message(err)), "Invalid connect string.");
+            arti_rpc_err_free(err);
+
+            // Nothing to connect to.  (It's okay not to ask for an error.)
+            let st = arti_rpc_connect(
+                b"unix:/this/path/does/not/exist\0".as_ptr().cast(),
+                &mut conn,
+                ptr::null_mut(),
+            );
+            #[cfg(unix)]
+            assert_eq!(st, ARTI_RPC_STATUS_CONNECT_IO);
+            #[cfg(not(unix))]
+            assert_eq!(st, ARTI_RPC_STATUS_NOT_SUPPORTED);
+            assert!(conn.is_null());
+
+            // NULL output pointer.
+            let mut resp: *mut c_char = ptr::null_mut();
+            let st = execute_impl(
+                ptr::null(),
+                b"{}\0".as_ptr().cast(),
+                None,
+                &mut resp,
+                &mut err,
+            );
+            assert_eq!(st, ARTI_RPC_STATUS_INVALID_INPUT);
+            assert_eq!(s(arti_rpc_err_message(err)), "Invalid input: rpc_conn");
+            assert!(resp.is_null());
+            arti_rpc_err_free(err);
+
+            // Freeing NULL is harmless.
+            arti_rpc_conn_free(ptr::null_mut());
+            arti_rpc_str_free(ptr::null_mut());
+            arti_rpc_err_free(ptr::null_mut());
+            assert!(arti_rpc_conn_get_session_id(ptr::null()).is_null());
+        }
+    }
+
+    #[test]
+    fn status_names() {
+        assert_eq!(
+            s(arti_rpc_status_to_str(ARTI_RPC_STATUS_SUCCESS)),
+            "success"
+        );
+        assert_eq!(
+            s(arti_rpc_status_to_str(ARTI_RPC_STATUS_TIMEOUT)),
+            "timed out"
+        );
+        assert_eq!(s(arti_rpc_status_to_str(9999)), "(unrecognized status)");
+    }
+}
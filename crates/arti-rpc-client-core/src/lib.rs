@@ -41,10 +41,16 @@
 //! <!-- @@ end lint list maintained by maint/add_warning @@ -->
 //!
 mod conn;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod llconn;
 mod msgs;
 #[macro_use]
 mod util;
 
-pub use conn::{BuilderError, ConnectError, ProtoError, RpcConn, RpcConnBuilder};
-pub use msgs::{response::RpcError, AnyRequestId, ObjectId};
+pub use conn::{
+    BootstrapProblem, BootstrapStatus, BuilderError, ConnectError, Event, EventKind, EventStream,
+    OnionServiceProblem, OnionServiceProblemKind, OnionServiceState, ProtoError, RequestError,
+    RpcAuth, RpcConn, RpcConnBuilder, StreamError, UpdateStream,
+};
+pub use msgs::{response::RpcError, AnyRequestId, ObjectId, WeakObjectId};
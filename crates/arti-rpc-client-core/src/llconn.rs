@@ -1,6 +1,13 @@
 //! Lowest-level API interface to an active RPC connection.
 //!
 //! Treats messages as unrelated strings, and validates outgoing messages for correctness.
+//!
+//! By default, messages are sent as jsonlines.  A connection can instead
+//! [`negotiate`] a [`Framing`] that puts a length prefix on each message,
+//! and optionally encodes it as CBOR.  Either way, the messages passed to and
+//! returned from this module are JSON strings.
+//!
+//! The definitions of the framings here are shared with Arti's RPC server.
 
 use crate::{
     msgs::{
@@ -9,7 +16,62 @@ use crate::{
     },
     util::define_from_for_arc,
 };
-use std::{io, sync::Arc};
+use std::{io, sync::Arc, time::Duration};
+
+/// A wire encoding for the messages on an RPC connection.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Framing {
+    /// Each message is a JSON object on a single line.
+    ///
+    /// This is the default, and the only framing that needs no negotiation.
+    #[default]
+    JsonLines,
+    /// Each message is a JSON object, preceded by its length as a
+    /// 4-byte big-endian integer.
+    LengthPrefixedJson,
+    /// Each message is a CBOR item, preceded by its length as a
+    /// 4-byte big-endian integer.
+    Cbor,
+}
+
+impl Framing {
+    /// Return the byte that identifies this framing during negotiation.
+    pub fn code(self) -> u8 {
+        match self {
+            Framing::JsonLines => b'J',
+            Framing::LengthPrefixedJson => b'L',
+            Framing::Cbor => b'C',
+        }
+    }
+
+    /// Return the framing that `code` identifies during negotiation,
+    /// if it is one that we know.
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            b'J' => Some(Framing::JsonLines),
+            b'L' => Some(Framing::LengthPrefixedJson),
+            b'C' => Some(Framing::Cbor),
+            _ => None,
+        }
+    }
+}
+
+/// Bytes that start a framing negotiation preamble.
+///
+/// The preamble is these bytes, followed by the [code](Framing::code) of a framing.
+/// A jsonlines connection always starts with whitespace or `{`,
+/// so there is no ambiguity.
+pub const FRAMING_MAGIC: &[u8; 4] = b"ARPC";
+
+/// The largest frame that either side will accept in a length-prefixed framing.
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// Length of the big-endian length prefix on each frame.
+pub const LEN_PREFIX: usize = 4;
+
+/// How long [`negotiate`] waits for Arti to answer, if the reader supports timeouts.
+const NEGOTIATE_TIMEOUT: Duration = Duration::from_secs(30);
 
 /// A low-level reader type, wrapping a boxed [`Read`](io::Read).
 ///
@@ -18,6 +80,16 @@ use std::{io, sync::Arc};
 pub struct Reader {
     /// The underlying reader.
     backend: Box<dyn io::BufRead + Send>,
+    /// Bytes of a partially received reply.
+    ///
+    /// We keep these across calls to `read_msg`, so that a read that times out
+    /// in the middle of a line does not lose any data.
+    partial: Vec<u8>,
+    /// The framing in use on this connection.
+    framing: Framing,
+    /// If present, a function we can use to set a read timeout on `backend`.
+    #[allow(clippy::type_complexity)]
+    set_timeout_fn: Option<Box<dyn FnMut(Option<Duration>) -> io::Result<()> + Send>>,
 }
 
 /// A low-level writer type, wrapping a boxed [`Write`](io::Write).
@@ -26,6 +98,8 @@ pub struct Reader {
 pub struct Writer {
     /// The underlying writer.
     backend: Box<dyn io::Write + Send>,
+    /// The framing in use on this connection.
+    framing: Framing,
 }
 
 impl Reader {
@@ -36,6 +110,35 @@ impl Reader {
     {
         Self {
             backend: Box::new(backend),
+            partial: Vec::new(),
+            framing: Framing::default(),
+            set_timeout_fn: None,
+        }
+    }
+
+    /// Return the framing that this reader expects.
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Crate-internal: Use `f` to set read timeouts on the underlying reader.
+    ///
+    /// The function should behave like [`UnixStream::set_read_timeout`](std::os::unix::net::UnixStream::set_read_timeout).
+    pub(crate) fn with_read_timeout_fn<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(Option<Duration>) -> io::Result<()> + Send + 'static,
+    {
+        self.set_timeout_fn = Some(Box::new(f));
+        self
+    }
+
+    /// Crate-internal: Try to set the read timeout on the underlying reader.
+    ///
+    /// Return `Ok(false)` if this reader does not support timeouts.
+    pub(crate) fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<bool> {
+        match &mut self.set_timeout_fn {
+            Some(f) => f(timeout).map(|()| true),
+            None => Ok(false),
         }
     }
 
@@ -44,18 +147,90 @@ impl Reader {
     /// Blocks as needed until the reply is available.
     ///
     /// Returns `Ok(None)` on end-of-stream.
+    ///
+    /// If the underlying reader returns an error (for example, because it timed out)
+    /// after we have received part of a reply, we remember what we have received,
+    /// and continue from there on the next call.
     pub fn read_msg(&mut self) -> io::Result<Option<UnparsedResponse>> {
-        let mut s = String::new();
+        match self.framing {
+            Framing::JsonLines => self.read_line(),
+            Framing::LengthPrefixedJson => match self.read_frame()? {
+                None => Ok(None),
+                Some(frame) => {
+                    let mut s = String::from_utf8(frame)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    s.push('\n');
+                    Ok(Some(UnparsedResponse::new(s)))
+                }
+            },
+            Framing::Cbor => match self.read_frame()? {
+                None => Ok(None),
+                Some(frame) => {
+                    // Applications get every response as a JSON string, so we have
+                    // to produce one; but we keep the decoded value too,
+                    // so that we never need to parse that string again.
+                    let value: serde_json::Value = ciborium::from_reader(&frame[..])
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                    Ok(Some(UnparsedResponse::from_value(value)?))
+                }
+            },
+        }
+    }
 
+    /// Helper for `read_msg`: read a single newline-terminated message.
+    fn read_line(&mut self) -> io::Result<Option<UnparsedResponse>> {
         // TODO: possibly ensure that the value is legit?
-        match self.backend.read_line(&mut s) {
+        match self.backend.read_until(b'\n', &mut self.partial) {
             Err(e) => Err(e),
             Ok(0) => Ok(None),
-            Ok(_) if s.ends_with('\n') => Ok(Some(UnparsedResponse::new(s))),
+            Ok(_) if self.partial.ends_with(b"\n") => {
+                let line = std::mem::take(&mut self.partial);
+                let s = String::from_utf8(line)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Ok(Some(UnparsedResponse::new(s)))
+            }
             // NOTE: This can happen if we hit EOF.
             //
             // We discard any truncated lines in this case.
-            Ok(_) => Ok(None),
+            Ok(_) => {
+                self.partial.clear();
+                Ok(None)
+            }
+        }
+    }
+
+    /// Helper for `read_msg`: read a single length-prefixed frame,
+    /// and return its body.
+    ///
+    /// As with `read_line`, a partially received frame survives errors,
+    /// and is discarded on EOF.
+    fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let needed = match self.partial.get(..LEN_PREFIX) {
+                None => LEN_PREFIX,
+                Some(prefix) => {
+                    let len =
+                        u32::from_be_bytes(prefix.try_into().expect("wrong slice length")) as usize;
+                    if len > MAX_FRAME_LEN {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "Frame too long"));
+                    }
+                    LEN_PREFIX + len
+                }
+            };
+            if self.partial.len() >= LEN_PREFIX && self.partial.len() == needed {
+                let mut frame = std::mem::take(&mut self.partial);
+                frame.drain(..LEN_PREFIX);
+                return Ok(Some(frame));
+            }
+
+            let available = self.backend.fill_buf()?;
+            if available.is_empty() {
+                self.partial.clear();
+                return Ok(None);
+            }
+            let n = std::cmp::min(available.len(), needed - self.partial.len());
+            self.partial.extend_from_slice(&available[..n]);
+            self.backend.consume(n);
         }
     }
 }
@@ -68,9 +243,15 @@ impl Writer {
     {
         Self {
             backend: Box::new(backend),
+            framing: Framing::default(),
         }
     }
 
+    /// Return the framing that this writer uses.
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
     /// Send an outbound request.
     ///
     /// Return an error if an IO problems occurred, or if the request was not well-formed.
@@ -89,7 +270,29 @@ impl Writer {
     /// (This is reliable since we never construct a `ValidRequest` except by encoding a
     /// known-correct object.)
     pub(crate) fn send_valid(&mut self, request: &ValidatedRequest) -> io::Result<()> {
-        self.backend.write_all(request.as_ref().as_bytes())
+        let msg: &str = request.as_ref();
+        match self.framing {
+            Framing::JsonLines => self.backend.write_all(msg.as_bytes()),
+            Framing::LengthPrefixedJson => self.send_frame(msg.trim_end_matches('\n').as_bytes()),
+            Framing::Cbor => {
+                let mut body = Vec::new();
+                ciborium::into_writer(request.request(), &mut body)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                self.send_frame(&body)
+            }
+        }
+    }
+
+    /// Helper: Send `body` with a length prefix.
+    fn send_frame(&mut self, body: &[u8]) -> io::Result<()> {
+        let len = u32::try_from(body.len())
+            .ok()
+            .filter(|len| *len as usize <= MAX_FRAME_LEN)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Request too long"))?;
+        let mut frame = Vec::with_capacity(LEN_PREFIX + body.len());
+        frame.extend_from_slice(&len.to_be_bytes());
+        frame.extend_from_slice(body);
+        self.backend.write_all(&frame)
     }
 
     /// Flush any queued data in this writer.
@@ -98,6 +301,60 @@ impl Writer {
     }
 }
 
+/// Ask Arti to use `framing` on a newly opened connection, and configure
+/// `reader` and `writer` to use it.
+///
+/// This must happen before any other messages are sent or received.
+/// Asking for [`Framing::JsonLines`] sends nothing, and always succeeds.
+///
+/// Return an error with kind [`Unsupported`](io::ErrorKind::Unsupported)
+/// if Arti declines the framing, or does not understand the request,
+/// and one with kind [`TimedOut`](io::ErrorKind::TimedOut)
+/// if Arti does not answer in time.
+///
+/// (We can only time out if `reader` supports read timeouts;
+/// otherwise, we wait for as long as it takes.)
+pub fn negotiate(reader: &mut Reader, writer: &mut Writer, framing: Framing) -> io::Result<()> {
+    if framing == Framing::JsonLines {
+        return Ok(());
+    }
+    let mut preamble = [0_u8; 5];
+    preamble[..4].copy_from_slice(FRAMING_MAGIC);
+    preamble[4] = framing.code();
+    writer.backend.write_all(&preamble)?;
+    writer.backend.flush()?;
+
+    let mut reply = [0_u8; 5];
+    let can_time_out = reader.set_read_timeout(Some(NEGOTIATE_TIMEOUT))?;
+    let outcome = io::Read::read_exact(&mut reader.backend, &mut reply);
+    if can_time_out {
+        reader.set_read_timeout(None)?;
+    }
+    match outcome {
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Arti did not answer our framing request",
+            ));
+        }
+        other => other?,
+    }
+    if reply != preamble {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Arti did not accept the requested framing",
+        ));
+    }
+    reader.framing = framing;
+    writer.framing = framing;
+    Ok(())
+}
+
 /// An error that has occurred while sending a request.
 #[derive(Clone, Debug, thiserror::Error)]
 #[non_exhaustive]
@@ -182,6 +439,43 @@ mod test {
         assert_eq!(m.unwrap_err().kind(), io::ErrorKind::NotConnected);
     }
 
+    /// A reader that yields a fixed series of chunks and errors.
+    struct Chunks(std::collections::VecDeque<io::Result<&'static [u8]>>);
+    impl io::Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.pop_front() {
+                None => Ok(0),
+                Some(Err(e)) => Err(e),
+                Some(Ok(chunk)) => {
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    Ok(chunk.len())
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reading_interrupted() {
+        // A reply that arrives in pieces, with timeouts in between,
+        // including one in the middle of a multibyte character.
+        let chunks = vec![
+            Ok(&br#"{"id":7,"#[..]),
+            Err(io::ErrorKind::WouldBlock.into()),
+            Ok(&b"\"result\":{\"x\":\"\xc3"[..]),
+            Err(io::ErrorKind::TimedOut.into()),
+            Ok(&b"\xa9\"}}\n"[..]),
+        ];
+        let mut r = Reader::new(BufReader::new(Chunks(chunks.into())));
+        assert_eq!(r.read_msg().unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(r.read_msg().unwrap_err().kind(), io::ErrorKind::TimedOut);
+        let msg = r.read_msg().unwrap().unwrap();
+        assert_eq!(msg.as_ref(), "{\"id\":7,\"result\":{\"x\":\"é\"}}\n");
+        assert!(r.read_msg().unwrap().is_none());
+
+        // Readers without a timeout function don't support timeouts.
+        assert!(!r.set_read_timeout(None).unwrap());
+    }
+
     #[test]
     fn write_success() {
         let (r, w) = socketpair::socketpair_stream().unwrap();
@@ -235,4 +529,108 @@ mod test {
             matches!(r, Err(SendRequestError::Io(e)) if e.kind() == io::ErrorKind::NotConnected)
         );
     }
+
+    /// A writer that appends to a shared buffer.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<std::sync::Mutex<Vec<u8>>>);
+    impl io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn framed() {
+        let msg = r#"{"id":7,"obj":"foo","method":"arti:x-frob","params":{}}"#;
+
+        for framing in [Framing::LengthPrefixedJson, Framing::Cbor] {
+            // Negotiate with a peer that agrees, then send a request.
+            let mut reply = FRAMING_MAGIC.to_vec();
+            reply.push(framing.code());
+            let out = SharedBuf::default();
+            let mut r = Reader::new(Cursor::new(reply.clone()));
+            let mut w = Writer::new(out.clone());
+            negotiate(&mut r, &mut w, framing).unwrap();
+            assert_eq!(r.framing(), framing);
+            assert_eq!(w.framing(), framing);
+            w.send_request(msg).unwrap();
+
+            // We should have sent the preamble, then a single frame.
+            let sent = out.0.lock().unwrap().clone();
+            assert_eq!(&sent[..5], &reply[..]);
+            let len = u32::from_be_bytes(sent[5..9].try_into().unwrap()) as usize;
+            assert_eq!(sent.len(), 9 + len);
+
+            // Reading that frame back should give us the same JSON,
+            // even if it arrives in pieces.
+            let chunks: Vec<io::Result<&'static [u8]>> = sent[5..]
+                .chunks(3)
+                .map(|c| Ok(&*Vec::leak(c.to_vec())))
+                .flat_map(|c| [c, Err(io::ErrorKind::WouldBlock.into())])
+                .collect();
+            let mut r = Reader::new(BufReader::new(Chunks(chunks.into())));
+            r.framing = framing;
+            let got = loop {
+                match r.read_msg() {
+                    Ok(m) => break m.unwrap(),
+                    Err(e) => assert_eq!(e.kind(), io::ErrorKind::WouldBlock),
+                }
+            };
+            // (CBOR doesn't preserve the order of the fields, so we compare values.)
+            assert!(got.as_ref().ends_with('\n'));
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(got.as_ref()).unwrap(),
+                serde_json::from_str::<serde_json::Value>(msg).unwrap()
+            );
+            assert!(r.read_msg().unwrap().is_none());
+        }
+
+        // A peer that doesn't understand us.
+        let mut r = Reader::new(Cursor::new(r#"{"error":{}}"#));
+        let mut w = Writer::new(SharedBuf::default());
+        let e = negotiate(&mut r, &mut w, Framing::Cbor).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(r.framing(), Framing::JsonLines);
+
+        // Oversized frames.
+        let mut r = Reader::new(Cursor::new(u32::MAX.to_be_bytes()));
+        r.framing = Framing::LengthPrefixedJson;
+        assert_eq!(r.read_msg().unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        for framing in [
+            Framing::JsonLines,
+            Framing::LengthPrefixedJson,
+            Framing::Cbor,
+        ] {
+            assert_eq!(Framing::from_code(framing.code()), Some(framing));
+        }
+        assert_eq!(Framing::from_code(b'Z'), None);
+    }
+
+    #[test]
+    fn negotiate_timeout() {
+        // A peer that never answers.
+        let timeouts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let timeouts2 = Arc::clone(&timeouts);
+        let silent = Chunks(vec![Err(io::ErrorKind::WouldBlock.into())].into());
+        let mut r = Reader::new(BufReader::new(silent)).with_read_timeout_fn(move |t| {
+            timeouts2.lock().unwrap().push(t);
+            Ok(())
+        });
+        let mut w = Writer::new(SharedBuf::default());
+        let e = negotiate(&mut r, &mut w, Framing::Cbor).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(r.framing(), Framing::JsonLines);
+
+        // We set a timeout for the negotiation, and cleared it afterwards.
+        assert_eq!(
+            timeouts.lock().unwrap().as_slice(),
+            &[Some(NEGOTIATE_TIMEOUT), None]
+        );
+    }
 }
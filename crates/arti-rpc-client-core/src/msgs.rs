@@ -48,3 +48,24 @@ pub enum AnyRequestId {
 )]
 #[serde(transparent)]
 pub struct ObjectId(String);
+
+/// An identifier for an object that Arti does not keep alive on our behalf.
+///
+/// Arti may drop the underlying object at any time,
+/// after which requests that use this identifier will fail.
+/// Use [`RpcConn::watch_expiry`](crate::RpcConn::watch_expiry)
+/// to learn when that happens.
+///
+/// Created with [`RpcConn::downgrade`](crate::RpcConn::downgrade).
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Hash, Eq, PartialEq, derive_more::AsRef, derive_more::Into,
+)]
+#[serde(transparent)]
+pub struct WeakObjectId(ObjectId);
+
+impl WeakObjectId {
+    /// Return the identifier to use when sending requests about this object.
+    pub fn as_object_id(&self) -> &ObjectId {
+        &self.0
+    }
+}
@@ -52,30 +52,37 @@ impl<T: Serialize> Request<T> {
 pub(crate) type ParsedRequest = Request<JsonMap>;
 
 /// A known-valid request, encoded as a string (in a single line, with a terminating newline).
-#[derive(derive_more::AsRef, Debug, Clone)]
+///
+/// We keep the request in its decoded form too,
+/// so that we can encode it in other ways without parsing the string again.
+#[derive(derive_more::AsRef, Debug)]
 pub(crate) struct ValidatedRequest {
     /// The message itself, as encoded.
     #[as_ref]
     msg: String,
-    /// The ID for this request.
-    id: AnyRequestId,
+    /// The request, as decoded.
+    req: ParsedRequest,
 }
 
 impl ParsedRequest {
     /// Convert a ParsedRequest into a string that is known to be valid.
-    pub(crate) fn format(&self) -> Result<ValidatedRequest, serde_json::Error> {
-        let id = self.id.clone();
-        let mut msg = serde_json::to_string(self)?;
+    pub(crate) fn format(self) -> Result<ValidatedRequest, serde_json::Error> {
+        let mut msg = serde_json::to_string(&self)?;
         debug_assert!(!msg.contains('\n'));
         msg.push('\n');
-        Ok(ValidatedRequest { id, msg })
+        Ok(ValidatedRequest { msg, req: self })
     }
 }
 
 impl ValidatedRequest {
     /// Return the Id associated with this request.
     pub(crate) fn id(&self) -> &AnyRequestId {
-        &self.id
+        &self.req.id
+    }
+
+    /// Return this request in its decoded form.
+    pub(crate) fn request(&self) -> &ParsedRequest {
+        &self.req
     }
 }
 
@@ -120,6 +127,11 @@ impl LooseParsedRequest {
             params: self.params,
         }
     }
+
+    /// Ask Arti to send us incremental updates about this request.
+    pub(crate) fn request_updates(&mut self) {
+        self.meta.get_or_insert_with(RequestMeta::default).updates = true;
+    }
 }
 
 /// A helper to return unique Request identifiers.
@@ -198,7 +210,7 @@ mod test {
             let r: ParsedRequest = serde_json::from_str(r).unwrap();
             let v = r.format().unwrap();
             let r2: ParsedRequest = serde_json::from_str(v.as_ref()).unwrap();
-            assert_eq!(r, r2);
+            assert_eq!(v.request(), &r2);
         }
     }
 
@@ -234,6 +246,6 @@ mod test {
         let req = loose.into_request(|| 7.into());
         let with_id = req.format().unwrap();
         let req2: ParsedRequest = serde_json::from_str(with_id.as_ref()).unwrap();
-        assert_eq!(req, req2);
+        assert_eq!(with_id.request(), &req2);
     }
 }
@@ -13,13 +13,28 @@ use crate::{conn::ErrorResponse, util::define_from_for_arc};
 #[derive(Clone, Debug, derive_more::AsRef)]
 pub struct UnparsedResponse {
     /// The body of this response.
+    #[as_ref]
     msg: String,
+    /// The body of this response, already decoded.
+    ///
+    /// We have this if the response did not arrive as JSON text.
+    value: Option<serde_json::Value>,
 }
 
 impl UnparsedResponse {
     /// Construct a new UnparsedResponse.
     pub(crate) fn new(msg: String) -> Self {
-        Self { msg }
+        Self { msg, value: None }
+    }
+
+    /// Construct a new UnparsedResponse from a value that we have already decoded.
+    pub(crate) fn from_value(value: serde_json::Value) -> Result<Self, serde_json::Error> {
+        let mut msg = serde_json::to_string(&value)?;
+        msg.push('\n');
+        Ok(Self {
+            msg,
+            value: Some(value),
+        })
     }
 }
 
@@ -57,7 +72,10 @@ impl UnparsedResponse {
     /// If this response is well-formed, and it corresponds to a single request,
     /// return it as a ValidatedResponse.
     pub(crate) fn try_validate(self) -> Result<ValidatedResponse, DecodeResponseError> {
-        let meta = response_meta(self.as_ref())?;
+        let meta = match &self.value {
+            Some(value) => check_response_meta(ResponseMetaDe::deserialize(value)?, &self.msg)?,
+            None => response_meta(self.as_ref())?,
+        };
         Ok(ValidatedResponse {
             msg: self.msg,
             meta,
@@ -153,9 +171,15 @@ impl<'a> From<&'a ResponseMetaBodyDe> for ResponseKind {
 
 /// Try to extract metadata for a request in `s`, and make sure it is well-formed.
 pub(crate) fn response_meta(s: &str) -> Result<ResponseMeta, DecodeResponseError> {
+    check_response_meta(serde_json::from_str(s)?, s)
+}
+
+/// Helper: Make sure that `meta`, as decoded from the response `s`, is well-formed,
+/// and return the metadata for the response.
+fn check_response_meta(meta: ResponseMetaDe, s: &str) -> Result<ResponseMeta, DecodeResponseError> {
     use DecodeResponseError as E;
     use ResponseMetaBodyDe as Body;
-    let ResponseMetaDe { id, body } = serde_json::from_str(s)?;
+    let ResponseMetaDe { id, body } = meta;
     match (id, body) {
         (None, Body::Error(_ignore)) => {
             Err(E::Fatal(ErrorResponse::from_validated_string(s.to_owned())))
@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use arti_rpcserver::testing::decode_frames;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() > 0 {
+        let cbor = (data[0] & 1) == 1;
+        let _ = decode_frames(cbor, &data[1..]);
+    }
+});
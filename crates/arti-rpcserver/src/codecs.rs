@@ -1,14 +1,21 @@
 //! Helper types for framing Json objects into async read/writes
 
+use std::io;
 use std::marker::PhantomData;
 
-use asynchronous_codec::JsonCodec;
-use bytes::BytesMut;
+use asynchronous_codec::{JsonCodec, JsonCodecError};
+use bytes::{Buf as _, BufMut as _, BytesMut};
+use futures::{
+    AsyncBufRead, AsyncBufReadExt as _, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _,
+};
 use serde::Serialize;
 
 use crate::msgs::BoxedResponse;
 use crate::msgs::FlexibleRequest;
 
+pub(crate) use arti_rpc_client_core::llconn::Framing;
+use arti_rpc_client_core::llconn::{FRAMING_MAGIC, LEN_PREFIX, MAX_FRAME_LEN};
+
 /// A stream of [`Request`](crate::msgs::Request)
 /// taken from `T` (an `AsyncRead`) and deserialized from Json.
 #[allow(dead_code)] // TODO RPC
@@ -55,6 +62,134 @@ where
 pub(crate) type ResponseSink<T> =
     asynchronous_codec::FramedWrite<T, JsonLinesEncoder<BoxedResponse>>;
 
+/// Decide which framing to use on a new connection.
+///
+/// If the client starts with [`FRAMING_MAGIC`] followed by a framing code,
+/// consume that preamble and echo back the framing that we will use:
+/// either the one requested, or `J` if we didn't recognize it.
+/// Otherwise, consume nothing, and use jsonlines.
+pub(crate) async fn negotiate_framing<R, W>(input: &mut R, output: &mut W) -> io::Result<Framing>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let first = input.fill_buf().await?;
+    if first.first() != Some(&FRAMING_MAGIC[0]) {
+        return Ok(Framing::JsonLines);
+    }
+
+    let mut preamble = [0_u8; 5];
+    input.read_exact(&mut preamble).await?;
+    if &preamble[..4] != FRAMING_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Unrecognized framing preamble",
+        ));
+    }
+    let framing = Framing::from_code(preamble[4]).unwrap_or(Framing::JsonLines);
+
+    let mut reply = [0_u8; 5];
+    reply[..4].copy_from_slice(FRAMING_MAGIC);
+    reply[4] = framing.code();
+    output.write_all(&reply).await?;
+    output.flush().await?;
+
+    Ok(framing)
+}
+
+/// A codec for length-prefixed frames, each holding a single JSON or CBOR
+/// encoded message.
+///
+/// Decodes [`FlexibleRequest`]s and encodes any `T`.
+pub(crate) struct LengthPrefixedCodec<T> {
+    /// If true, frames are CBOR; otherwise, they are JSON.
+    cbor: bool,
+    /// We consume objects of type T.
+    _phantom: PhantomData<fn(T) -> ()>,
+}
+
+impl<T> LengthPrefixedCodec<T> {
+    /// Construct a new codec for a given (non-jsonlines) framing.
+    pub(crate) fn new(framing: Framing) -> Self {
+        debug_assert_ne!(framing, Framing::JsonLines);
+        Self {
+            cbor: framing == Framing::Cbor,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// Convert a CBOR decoding or encoding problem into the error type that the
+/// rest of our connection code expects.
+fn cbor_err(e: impl std::fmt::Display) -> JsonCodecError {
+    JsonCodecError::Json(<serde_json::Error as serde::de::Error>::custom(e))
+}
+
+impl<T> asynchronous_codec::Decoder for LengthPrefixedCodec<T> {
+    type Item = FlexibleRequest;
+
+    type Error = JsonCodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let Some(prefix) = src.get(..LEN_PREFIX) else {
+            return Ok(None);
+        };
+        let len = u32::from_be_bytes(prefix.try_into().expect("wrong slice length")) as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(JsonCodecError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Frame too long",
+            )));
+        }
+        if src.len() < LEN_PREFIX + len {
+            src.reserve(LEN_PREFIX + len - src.len());
+            return Ok(None);
+        }
+        src.advance(LEN_PREFIX);
+        let body = src.split_to(len);
+
+        let request = if self.cbor {
+            let value: serde_json::Value = ciborium::from_reader(&body[..]).map_err(cbor_err)?;
+            serde_json::from_value(value)?
+        } else {
+            serde_json::from_slice(&body[..])?
+        };
+        Ok(Some(request))
+    }
+}
+
+impl<T> asynchronous_codec::Encoder for LengthPrefixedCodec<T>
+where
+    T: Serialize + 'static,
+{
+    type Item<'a> = T;
+
+    type Error = JsonCodecError;
+
+    fn encode(&mut self, item: Self::Item<'_>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let body = if self.cbor {
+            let mut body = Vec::new();
+            ciborium::into_writer(&item, &mut body).map_err(cbor_err)?;
+            body
+        } else {
+            serde_json::to_vec(&item)?
+        };
+        let len = u32::try_from(body.len())
+            .ok()
+            .filter(|len| *len as usize <= MAX_FRAME_LEN)
+            .ok_or_else(|| {
+                JsonCodecError::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Response too long to frame",
+                ))
+            })?;
+        dst.reserve(LEN_PREFIX + body.len());
+        dst.put_u32(len);
+        dst.put_slice(&body);
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     // @@ begin test lint list maintained by maint/add_warning @@
@@ -74,6 +209,7 @@ mod test {
     use super::*;
     use crate::msgs::*;
     use futures::sink::SinkExt as _;
+    use futures::AsyncReadExt as _;
     use futures_await_test::async_test;
     use tor_rpcbase as rpc;
 
@@ -119,4 +255,88 @@ mod test {
         // Make sure that the output is what we expected.
         assert_eq!(std::str::from_utf8(&buf).unwrap(), &expect);
     }
+
+    #[async_test]
+    async fn negotiation() {
+        async fn negotiate(input: &[u8]) -> (io::Result<Framing>, Vec<u8>, Vec<u8>) {
+            let mut input = futures::io::BufReader::new(input);
+            let mut output = Vec::new();
+            let r = negotiate_framing(&mut input, &mut output).await;
+            let mut rest = Vec::new();
+            input.read_to_end(&mut rest).await.unwrap();
+            (r, output, rest)
+        }
+
+        // No preamble: jsonlines, and nothing is consumed.
+        let (r, out, rest) = negotiate(b"{\"id\":1}\n").await;
+        assert_eq!(r.unwrap(), Framing::JsonLines);
+        assert!(out.is_empty());
+        assert_eq!(&rest, b"{\"id\":1}\n");
+
+        let (r, out, rest) = negotiate(b"ARPCCxyz").await;
+        assert_eq!(r.unwrap(), Framing::Cbor);
+        assert_eq!(&out, b"ARPCC");
+        assert_eq!(&rest, b"xyz");
+
+        let (r, out, _) = negotiate(b"ARPCL").await;
+        assert_eq!(r.unwrap(), Framing::LengthPrefixedJson);
+        assert_eq!(&out, b"ARPCL");
+
+        // Unknown framing: we fall back to jsonlines, and say so.
+        let (r, out, _) = negotiate(b"ARPCZ").await;
+        assert_eq!(r.unwrap(), Framing::JsonLines);
+        assert_eq!(&out, b"ARPCJ");
+
+        // Garbage.
+        let (r, _, _) = negotiate(b"Abcde").await;
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn length_prefixed() {
+        use asynchronous_codec::{Decoder as _, Encoder as _};
+
+        for framing in [Framing::LengthPrefixedJson, Framing::Cbor] {
+            let mut codec = LengthPrefixedCodec::<BoxedResponse>::new(framing);
+
+            // Encode a response, and check that the prefix is right.
+            let mut buf = BytesMut::new();
+            let resp = BoxedResponse {
+                id: Some(RequestId::Int(7)),
+                body: ResponseBody::Success(Box::new(Empty {})),
+            };
+            codec.encode(resp, &mut buf).unwrap();
+            let len = u32::from_be_bytes(buf[..4].try_into().unwrap()) as usize;
+            assert_eq!(len + 4, buf.len());
+
+            // Decode a request, a byte at a time.
+            let json: serde_json::Value = serde_json::from_str(
+                r#"{"id": 7, "obj": "hello", "method": "x-test:dummy", "params": {} }"#,
+            )
+            .unwrap();
+            let body = if framing == Framing::Cbor {
+                let mut v = Vec::new();
+                ciborium::into_writer(&json, &mut v).unwrap();
+                v
+            } else {
+                serde_json::to_vec(&json).unwrap()
+            };
+            let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+            frame.extend_from_slice(&body);
+            let mut src = BytesMut::new();
+            for (i, b) in frame.iter().enumerate() {
+                src.put_u8(*b);
+                let r = codec.decode(&mut src).unwrap();
+                assert_eq!(r.is_some(), i == frame.len() - 1);
+                if let Some(req) = r {
+                    assert!(matches!(req, FlexibleRequest::Valid(_)));
+                }
+            }
+            assert!(src.is_empty());
+
+            // Oversized frames are rejected.
+            let mut src = BytesMut::from(&u32::MAX.to_be_bytes()[..]);
+            assert!(matches!(codec.decode(&mut src), Err(JsonCodecError::Io(_))));
+        }
+    }
 }
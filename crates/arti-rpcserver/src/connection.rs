@@ -12,7 +12,7 @@ use asynchronous_codec::JsonCodecError;
 use derive_deftly::Deftly;
 use futures::{
     channel::mpsc,
-    stream::{FusedStream, FuturesUnordered},
+    stream::{BoxStream, FusedStream, FuturesUnordered},
     FutureExt, Sink, SinkExt as _, StreamExt,
 };
 use rpc::dispatch::BoxedUpdateSink;
@@ -21,6 +21,7 @@ use tor_async_utils::SinkExt as _;
 
 use crate::{
     cancel::{Cancel, CancelHandle},
+    codecs::{Framing, LengthPrefixedCodec},
     err::RequestParseError,
     globalid::{GlobalId, MacKey},
     msgs::{BoxedResponse, FlexibleRequest, Request, RequestId, ResponseBody},
@@ -66,6 +67,9 @@ pub struct Connection {
     /// A reference to the manager associated with this session.
     mgr: Weak<RpcMgr>,
 
+    /// What we know about the process on the other end of this connection.
+    peer: auth::RpcPeer,
+
     /// A reference to this connection itself.
     ///
     /// Used when we're looking up the connection within the RPC system as an object.
@@ -86,6 +90,49 @@ struct Inner {
     /// An object map used to look up most objects by ID, and keep track of
     /// which objects are owned by this connection.
     objects: ObjMap,
+
+    /// Requests that want to know when some of our objects go away.
+    expiry_watchers: Vec<ExpiryWatcher>,
+
+    /// The challenge from the client's most recent `auth:cookie_begin`, if any.
+    ///
+    /// Each challenge may be answered at most once.
+    cookie_challenge: Option<auth::CookieChallenge>,
+}
+
+/// A request to be told when some objects can no longer be looked up.
+///
+/// See [`rpc::Context::watch_expiry`].
+struct ExpiryWatcher {
+    /// The objects that haven't expired yet, with the IDs that the client used for them.
+    watched: Vec<(rpc::ObjectId, GenIdx)>,
+    /// A channel on which we report the ID of each object that has expired.
+    ///
+    /// When we drop this, the watching request finishes.
+    tx: mpsc::UnboundedSender<rpc::ObjectId>,
+}
+
+impl Inner {
+    /// Tell every [`ExpiryWatcher`] about the watched objects that have expired.
+    ///
+    /// We can't learn about a weak reference expiring as soon as it happens,
+    /// so we call this whenever something happens on the connection:
+    /// when a request starts or finishes, and when a reference is released.
+    fn check_expiry(&mut self) {
+        let objects = &self.objects;
+        self.expiry_watchers
+            .retain_mut(|ExpiryWatcher { watched, tx }| {
+                watched.retain(|(id, idx)| {
+                    let present = objects.lookup(*idx).is_some();
+                    if !present {
+                        // If this fails, the request is gone; we'll drop it below.
+                        let _ = tx.unbounded_send(id.clone());
+                    }
+                    present
+                });
+                !watched.is_empty() && !tx.is_closed()
+            });
+    }
 }
 
 /// How many updates can be pending, per connection, before they start to block?
@@ -132,16 +179,20 @@ impl Connection {
         dispatch_table: Arc<RwLock<rpc::DispatchTable>>,
         global_id_mac_key: MacKey,
         mgr: Weak<RpcMgr>,
+        peer: auth::RpcPeer,
     ) -> Arc<Self> {
         Arc::new_cyclic(|this_connection| Self {
             inner: Mutex::new(Inner {
                 inflight: HashMap::new(),
                 objects: ObjMap::new(),
+                expiry_watchers: Vec::new(),
+                cookie_challenge: None,
             }),
             dispatch_table,
             connection_id,
             global_id_mac_key,
             mgr,
+            peer,
             this_connection: Weak::clone(this_connection),
         })
     }
@@ -210,37 +261,77 @@ impl Connection {
     fn remove_request(&self, id: &RequestId) {
         let mut inner = self.inner.lock().expect("lock poisoned");
         inner.inflight.remove(id);
+        inner.check_expiry();
+    }
+
+    /// Cancel the in-progress request `id`, in response to an `rpc:cancel` request
+    /// addressed to `obj`.
+    fn cancel_request(&self, obj: &rpc::ObjectId, id: &RequestId) -> Result<(), rpc::RpcError> {
+        // Any object will do, but it has to be one that the client can name.
+        let _obj = self.lookup_object(obj)?;
+        let handle = {
+            let inner = self.inner.lock().expect("lock poisoned");
+            inner.inflight.get(id).cloned().ok_or(RequestNotFound)?
+        };
+        handle.cancel();
+        Ok(())
     }
 
     /// Register the request `id` as a cancellable request.
     fn register_request(&self, id: RequestId, handle: CancelHandle) {
         let mut inner = self.inner.lock().expect("lock poisoned");
         inner.inflight.insert(id, handle);
+        inner.check_expiry();
     }
 
     /// Run in a loop, decoding JSON requests from `input` and
     /// writing JSON responses onto `output`.
+    ///
+    /// If the client opens with a framing preamble, we use the
+    /// framing that it asks for (if we support it) instead of jsonlines.
     pub async fn run<IN, OUT>(
         self: Arc<Self>,
         input: IN,
-        output: OUT,
+        mut output: OUT,
     ) -> Result<(), ConnectionError>
     where
         IN: futures::AsyncRead + Send + Sync + Unpin + 'static,
         OUT: futures::AsyncWrite + Send + Sync + Unpin + 'static,
     {
-        let write = Box::pin(asynchronous_codec::FramedWrite::new(
-            output,
-            crate::codecs::JsonLinesEncoder::<BoxedResponse>::default(),
-        ));
-
-        let read = Box::pin(
-            asynchronous_codec::FramedRead::new(
-                input,
-                asynchronous_codec::JsonCodec::<(), FlexibleRequest>::new(),
-            )
-            .fuse(),
-        );
+        let mut input = futures::io::BufReader::new(input);
+        let framing = crate::codecs::negotiate_framing(&mut input, &mut output)
+            .await
+            .map_err(|_| ConnectionError::ReadFailed)?;
+
+        let (read, write): (BoxedRequestStream, BoxedResponseSink) = match framing {
+            Framing::JsonLines => (
+                Box::pin(
+                    asynchronous_codec::FramedRead::new(
+                        input,
+                        asynchronous_codec::JsonCodec::<(), FlexibleRequest>::new(),
+                    )
+                    .fuse(),
+                ),
+                Box::pin(asynchronous_codec::FramedWrite::new(
+                    output,
+                    crate::codecs::JsonLinesEncoder::<BoxedResponse>::default(),
+                )),
+            ),
+            // Every other framing is length-prefixed.
+            _ => (
+                Box::pin(
+                    asynchronous_codec::FramedRead::new(
+                        input,
+                        LengthPrefixedCodec::<BoxedResponse>::new(framing),
+                    )
+                    .fuse(),
+                ),
+                Box::pin(asynchronous_codec::FramedWrite::new(
+                    output,
+                    LengthPrefixedCodec::<BoxedResponse>::new(framing),
+                )),
+            ),
+        };
 
         self.run_loop(read, write).await
     }
@@ -367,6 +458,19 @@ impl Connection {
             Box::pin(sink)
         };
 
+        let method = method.upcast_box();
+        if let Some(cancel) = method.downcast_ref::<RpcCancel>() {
+            // We handle cancellation here, rather than dispatching it,
+            // since it acts on the connection's table of requests.
+            let body = match self.cancel_request(&obj, &cancel.request_id) {
+                Ok(()) => ResponseBody::Success(Box::new(rpc::Nil::default())),
+                Err(err) => ResponseBody::Error(Box::new(err)),
+            };
+            // (As below, an error here means that the connection has closed.)
+            let _ignore_err = tx_response.send(BoxedResponse { id: Some(id), body }).await;
+            return;
+        }
+
         // Create `run_method_lowlevel` future, and make it cancellable.
         let fut = self.run_method_lowlevel(update_sender, obj, method);
         let (handle, fut) = Cancel::new(fut);
@@ -415,12 +519,12 @@ impl Connection {
         self: &Arc<Self>,
         tx_updates: rpc::dispatch::BoxedUpdateSink,
         obj: rpc::ObjectId,
-        method: Box<dyn rpc::DeserMethod>,
+        method: Box<dyn rpc::DynMethod>,
     ) -> Result<Box<dyn erased_serde::Serialize + Send + 'static>, rpc::RpcError> {
         let obj = self.lookup_object(&obj)?;
 
         let context: Arc<dyn rpc::Context> = self.clone() as Arc<_>;
-        let invoke_future = rpc::invoke_rpc_method(context, obj, method.upcast_box(), tx_updates)?;
+        let invoke_future = rpc::invoke_rpc_method(context, obj, method, tx_updates)?;
 
         // Note that we drop the read lock before we await this future!
         invoke_future.await
@@ -433,6 +537,27 @@ impl Connection {
             .upgrade()
             .ok_or(MgrDisappearedError::RpcMgrDisappeared)
     }
+
+    /// Return what we know about the process on the other end of this connection.
+    pub(crate) fn peer(&self) -> &auth::RpcPeer {
+        &self.peer
+    }
+
+    /// Remember `challenge` as the one that the client must answer for `fs:cookie`
+    /// authentication, replacing any earlier one.
+    pub(crate) fn set_cookie_challenge(&self, challenge: auth::CookieChallenge) {
+        self.inner.lock().expect("lock poisoned").cookie_challenge = Some(challenge);
+    }
+
+    /// Remove and return the challenge that the client must answer for `fs:cookie`
+    /// authentication, if there is one.
+    pub(crate) fn take_cookie_challenge(&self) -> Option<auth::CookieChallenge> {
+        self.inner
+            .lock()
+            .expect("lock poisoned")
+            .cookie_challenge
+            .take()
+    }
 }
 
 /// A failure that results in closing a [`Connection`].
@@ -513,19 +638,99 @@ impl rpc::Context for Connection {
             .expect("Lock poisoned")
             .objects
             .remove(idx);
+        let found = removed.is_some();
+        // Drop the object (if this was the last reference) before we look for
+        // expired weak references.
+        drop(removed);
+        self.inner.lock().expect("Lock poisoned").check_expiry();
 
-        if removed.is_some() {
+        if found {
             Ok(())
         } else {
             Err(rpc::LookupError::NoObject(id.clone()))
         }
     }
 
+    fn downgrade_owned(&self, id: &rpc::ObjectId) -> Result<rpc::ObjectId, rpc::LookupError> {
+        let idx = self.id_into_local_idx(id)?;
+
+        if !idx.is_strong() {
+            return Err(rpc::LookupError::WrongType(id.clone()));
+        }
+
+        let (object, new_idx) = {
+            let mut inner = self.inner.lock().expect("Lock poisoned");
+            let object = inner
+                .objects
+                .remove(idx)
+                .ok_or_else(|| rpc::LookupError::NoObject(id.clone()))?;
+            let new_idx = inner.objects.insert_weak(object.clone());
+            (object, new_idx)
+        };
+        let use_global_id = object.expose_outside_of_session();
+        // As in release_owned, drop the object before checking for expiry.
+        drop(object);
+        self.inner.lock().expect("Lock poisoned").check_expiry();
+
+        Ok(if use_global_id {
+            GlobalId::new(self.connection_id, new_idx).encode(&self.global_id_mac_key)
+        } else {
+            new_idx.encode()
+        })
+    }
+
+    fn watch_expiry(
+        &self,
+        ids: Vec<rpc::ObjectId>,
+    ) -> Result<BoxStream<'static, rpc::ObjectId>, rpc::LookupError> {
+        let watched = ids
+            .into_iter()
+            .map(|id| Ok((id.clone(), self.id_into_local_idx(&id)?)))
+            .collect::<Result<Vec<_>, rpc::LookupError>>()?;
+        let (tx, rx) = mpsc::unbounded();
+
+        let mut inner = self.inner.lock().expect("Lock poisoned");
+        inner.expiry_watchers.push(ExpiryWatcher { watched, tx });
+        // Report anything that has already gone away.
+        inner.check_expiry();
+
+        Ok(rx.boxed())
+    }
+
     fn dispatch_table(&self) -> &Arc<std::sync::RwLock<rpc::DispatchTable>> {
         &self.dispatch_table
     }
 }
 
+/// RPC method to cancel a request that is in progress.
+///
+/// The cancelled request finishes with an error.
+///
+/// This method is handled by the [`Connection`] itself, whichever object
+/// it is addressed to.  (Conventionally, clients send it to their session.)
+#[derive(Debug, serde::Deserialize, Deftly)]
+#[derive_deftly(DynMethod)]
+#[deftly(rpc(method_name = "rpc:cancel"))]
+struct RpcCancel {
+    /// The ID of the request to cancel.
+    request_id: RequestId,
+}
+
+impl rpc::RpcMethod for RpcCancel {
+    type Output = rpc::Nil;
+    type Update = rpc::NoUpdates;
+}
+
+/// An error given when asked to cancel a request that is not in progress.
+#[derive(thiserror::Error, Clone, Debug, serde::Serialize)]
+#[error("No such request is in progress")]
+pub(crate) struct RequestNotFound;
+impl tor_error::HasKind for RequestNotFound {
+    fn kind(&self) -> tor_error::ErrorKind {
+        tor_error::ErrorKind::BadApiUsage
+    }
+}
+
 /// An error given when an RPC request is cancelled.
 ///
 /// This is a separate type from [`crate::cancel::Cancelled`] since eventually
@@ -8,8 +8,10 @@ use std::sync::Arc;
 
 use super::Connection;
 use derive_deftly::Deftly;
+use tor_llcrypto::util::ct::CtByteArray;
 use tor_rpcbase as rpc;
 use tor_rpcbase::templates::*;
+use zeroize::Zeroizing;
 
 /*
     TODO RPC: This is disabled because the design isn't really useful.
@@ -70,24 +72,276 @@ mod get_rpc_protocol {
 
 /// Information about how an RPC session has been authenticated.
 ///
-/// Currently, this isn't actually used for anything, since there's only one way
-/// to authenticate a connection.  It exists so that later we can pass
+/// Currently, this isn't actually used for anything: every authenticated
+/// session has the same powers.  It exists so that later we can pass
 /// information to the session-creator function.
 #[derive(Clone, Debug)]
 #[non_exhaustive]
 pub struct RpcAuthentication {}
 
+/// What we know about the process on the other end of an RPC connection.
+///
+/// Used by the `inherent:peer_uid` authentication scheme.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct RpcPeer {
+    /// The user ID of the peer process, if the transport told us.
+    pub uid: Option<u32>,
+}
+
+impl RpcPeer {
+    /// Return a new `RpcPeer` for a peer whose user ID is `uid`.
+    pub fn with_uid(uid: u32) -> Self {
+        RpcPeer { uid: Some(uid) }
+    }
+}
+
+/// A secret that an RPC client can present to authenticate.
+///
+/// Used for cookie and pre-shared token authentication.
+#[derive(Clone)]
+pub struct RpcSecret(Zeroizing<String>);
+
+impl RpcSecret {
+    /// Generate a new random secret, suitable for storing in a cookie file.
+    pub fn new_random() -> Self {
+        use base64ct::{Base64Unpadded as B64, Encoding};
+        use rand::Rng as _;
+        let bytes: Zeroizing<[u8; 32]> = Zeroizing::new(rand::thread_rng().gen());
+        RpcSecret(Zeroizing::new(B64::encode_string(&bytes[..])))
+    }
+
+    /// Return the text of this secret, as a client should present it.
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    /// Return true if `other` is the same as this secret.
+    ///
+    /// Runs in time that does not depend on where the secrets differ,
+    /// or on their lengths.
+    fn matches(&self, other: &str) -> bool {
+        /// Return a digest of `s`, so that we can compare fixed-length values.
+        fn digest(s: &str) -> CtByteArray<32> {
+            use tiny_keccak::{Hasher as _, Sha3};
+            let mut d = Sha3::v256();
+            d.update(s.as_bytes());
+            let mut out = [0_u8; 32];
+            d.finalize(&mut out);
+            out.into()
+        }
+        digest(self.as_str()) == digest(other)
+    }
+}
+
+impl From<String> for RpcSecret {
+    fn from(value: String) -> Self {
+        RpcSecret(Zeroizing::new(value))
+    }
+}
+
+impl std::fmt::Debug for RpcSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RpcSecret(..)")
+    }
+}
+
+/// Which ways of authenticating are we willing to accept?
+///
+/// The default policy accepts only `inherent:unix_path`:
+/// anybody who can connect to us is authorized.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct RpcAuthPolicy {
+    /// If true, we accept `inherent:unix_path`.
+    ///
+    /// (That is, we assume that anybody who can open the socket may use us.)
+    pub inherent_unix_path: bool,
+
+    /// If nonempty, we accept `inherent:peer_uid` from peers with these user IDs.
+    pub allowed_peer_uids: Vec<u32>,
+
+    /// If present, we accept `fs:cookie` from clients that can prove they know this secret.
+    ///
+    /// The client never sends the cookie itself;
+    /// see [`CookieChallenge`] for how the proof works.
+    /// We don't tell clients where the cookie file is:
+    /// they have to be configured with its location.
+    pub cookie: Option<RpcSecret>,
+
+    /// If present, we accept `preshared:token` with this secret.
+    pub token: Option<RpcSecret>,
+}
+
+impl Default for RpcAuthPolicy {
+    fn default() -> Self {
+        RpcAuthPolicy {
+            inherent_unix_path: true,
+            allowed_peer_uids: Vec::new(),
+            cookie: None,
+            token: None,
+        }
+    }
+}
+
+impl RpcAuthPolicy {
+    /// Return true if `peer` may use `inherent:peer_uid`.
+    fn peer_uid_allowed(&self, peer: &RpcPeer) -> bool {
+        peer.uid
+            .is_some_and(|uid| self.allowed_peer_uids.contains(&uid))
+    }
+
+    /// Return the list of schemes that `peer` might be able to use.
+    fn schemes_for(&self, peer: &RpcPeer) -> Vec<AuthenticationScheme> {
+        let mut schemes = Vec::new();
+        // We list these in our order of preference.
+        if self.cookie.is_some() {
+            schemes.push(AuthenticationScheme::Cookie);
+        }
+        if self.token.is_some() {
+            schemes.push(AuthenticationScheme::Token);
+        }
+        if self.peer_uid_allowed(peer) {
+            schemes.push(AuthenticationScheme::InherentPeerUid);
+        }
+        if self.inherent_unix_path {
+            schemes.push(AuthenticationScheme::InherentUnixPath);
+        }
+        schemes
+    }
+
+    /// Check whether `peer` may authenticate with `scheme`, presenting `secret`.
+    ///
+    /// For `fs:cookie`, `secret` is the client's MAC,
+    /// which must answer `challenge`.
+    fn check(
+        &self,
+        peer: &RpcPeer,
+        scheme: AuthenticationScheme,
+        secret: Option<&str>,
+        challenge: Option<CookieChallenge>,
+    ) -> Result<(), AuthenticationFailure> {
+        use AuthenticationFailure as AF;
+        use AuthenticationScheme as S;
+
+        /// Check `secret` against `expected`, if we have one.
+        fn check_secret(
+            expected: Option<&RpcSecret>,
+            secret: Option<&str>,
+        ) -> Result<(), AuthenticationFailure> {
+            let expected = expected.ok_or(AF::SchemeNotEnabled)?;
+            let secret = secret.ok_or(AF::MissingSecret)?;
+            if expected.matches(secret) {
+                Ok(())
+            } else {
+                Err(AF::IncorrectSecret)
+            }
+        }
+
+        match scheme {
+            S::InherentUnixPath if self.inherent_unix_path => Ok(()),
+            S::InherentUnixPath => Err(AF::SchemeNotEnabled),
+            S::InherentPeerUid if self.allowed_peer_uids.is_empty() => Err(AF::SchemeNotEnabled),
+            S::InherentPeerUid if self.peer_uid_allowed(peer) => Ok(()),
+            S::InherentPeerUid => Err(AF::PeerNotPermitted),
+            S::Cookie => {
+                let cookie = self.cookie.as_ref().ok_or(AF::SchemeNotEnabled)?;
+                let challenge = challenge.ok_or(AF::NoCookieChallenge)?;
+                let client_mac = secret.ok_or(AF::MissingSecret)?;
+                if challenge.client_mac(cookie) == decode_cookie_field(client_mac)? {
+                    Ok(())
+                } else {
+                    Err(AF::IncorrectSecret)
+                }
+            }
+            S::Token => check_secret(self.token.as_ref(), secret),
+        }
+    }
+}
+
+/// Length of the nonces in `fs:cookie` authentication, in bytes.
+const COOKIE_NONCE_LEN: usize = 32;
+
+/// Customization string for the MAC by which Arti proves that it knows the cookie.
+const COOKIE_SERVER_MAC_CUSTOM: &[u8] = b"arti-rpc-cookie-v1-server";
+
+/// Customization string for the MAC by which a client proves that it knows the cookie.
+const COOKIE_CLIENT_MAC_CUSTOM: &[u8] = b"arti-rpc-cookie-v1-client";
+
+/// A challenge for `fs:cookie` authentication, begun with `auth:cookie_begin`.
+///
+/// This works like C Tor's `SAFECOOKIE`, so the cookie never goes on the wire:
+/// the client sends a random nonce;
+/// we reply with a random nonce of our own, and a MAC over both nonces keyed with the cookie,
+/// which tells the client that we really know the cookie;
+/// then the client authenticates with its own MAC over both nonces.
+/// The two MACs are KMAC256 with different customization strings.
+#[derive(Clone, Debug)]
+pub(crate) struct CookieChallenge {
+    /// The nonce that the client sent.
+    client_nonce: [u8; COOKIE_NONCE_LEN],
+    /// The nonce that we chose.
+    server_nonce: [u8; COOKIE_NONCE_LEN],
+}
+
+impl CookieChallenge {
+    /// Return the MAC, with `custom` as the customization string, over our nonces.
+    fn mac(&self, cookie: &RpcSecret, custom: &[u8]) -> CtByteArray<32> {
+        use tiny_keccak::{Hasher as _, Kmac};
+        let mut mac = Kmac::v256(cookie.as_str().as_bytes(), custom);
+        mac.update(&self.client_nonce[..]);
+        mac.update(&self.server_nonce[..]);
+        let mut out = [0_u8; 32];
+        mac.finalize(&mut out);
+        out.into()
+    }
+
+    /// Return the MAC that we send, to prove that we know `cookie`.
+    fn server_mac(&self, cookie: &RpcSecret) -> CtByteArray<32> {
+        self.mac(cookie, COOKIE_SERVER_MAC_CUSTOM)
+    }
+
+    /// Return the MAC that the client must send, to prove that it knows `cookie`.
+    fn client_mac(&self, cookie: &RpcSecret) -> CtByteArray<32> {
+        self.mac(cookie, COOKIE_CLIENT_MAC_CUSTOM)
+    }
+}
+
+/// Encode a nonce or MAC for `fs:cookie` authentication, as it goes on the wire.
+fn encode_cookie_field(bytes: &[u8]) -> String {
+    use base64ct::{Base64Unpadded as B64, Encoding};
+    B64::encode_string(bytes)
+}
+
+/// Decode a nonce or MAC for `fs:cookie` authentication, as it goes on the wire.
+fn decode_cookie_field<const N: usize>(s: &str) -> Result<CtByteArray<N>, AuthenticationFailure> {
+    use base64ct::{Base64Unpadded as B64, Encoding};
+    let mut out = [0_u8; N];
+    match B64::decode(s, &mut out[..]) {
+        Ok(decoded) if decoded.len() == N => Ok(out.into()),
+        _ => Err(AuthenticationFailure::MalformedCookieField),
+    }
+}
+
 /// The authentication scheme as enumerated in the spec.
 ///
 /// Conceptually, an authentication scheme answers the question "How can the
 /// Arti process know you have permissions to use or administer it?"
-///
-/// TODO RPC: The only supported one for now is "inherent:unix_path"
-#[derive(Debug, Copy, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
 enum AuthenticationScheme {
     /// Inherent authority based on the ability to access an AF_UNIX address.
     #[serde(rename = "inherent:unix_path")]
     InherentUnixPath,
+    /// Inherent authority based on the user ID of the connecting process.
+    #[serde(rename = "inherent:peer_uid")]
+    InherentPeerUid,
+    /// Knowledge of a secret that we wrote to a cookie file,
+    /// proven with a [`CookieChallenge`].
+    #[serde(rename = "fs:cookie")]
+    Cookie,
+    /// Knowledge of a secret that was configured in advance.
+    #[serde(rename = "preshared:token")]
+    Token,
 }
 
 /// Method to ask which authentication methods are supported.
@@ -99,12 +353,7 @@ struct AuthQuery {}
 /// A list of supported authentication schemes and their parameters.
 #[derive(Debug, serde::Serialize)]
 struct SupportedAuth {
-    /// A list of the supported authentication schemes.
-    ///
-    /// TODO RPC: Actually, this should be able to contain strings _or_ maps,
-    /// where the maps are additional information about the parameters needed
-    /// for a particular scheme.  But I think that's a change we can make later
-    /// once we have a scheme that takes parameters.
+    /// A list of the supported authentication schemes, most preferred first.
     ///
     /// TODO RPC: Should we indicate which schemes get you additional privileges?
     schemes: Vec<AuthenticationScheme>,
@@ -116,29 +365,82 @@ impl rpc::RpcMethod for AuthQuery {
 }
 /// Implement `auth:AuthQuery` on a connection.
 async fn conn_authquery(
-    _conn: Arc<Connection>,
+    conn: Arc<Connection>,
     _query: Box<AuthQuery>,
     _ctx: Arc<dyn rpc::Context>,
 ) -> Result<SupportedAuth, rpc::RpcError> {
-    // Right now, every connection supports the same scheme.
+    let policy = conn.mgr()?.auth_policy();
     Ok(SupportedAuth {
-        schemes: vec![AuthenticationScheme::InherentUnixPath],
+        schemes: policy.schemes_for(conn.peer()),
     })
 }
 rpc::static_rpc_invoke_fn! {
     conn_authquery;
 }
 
-/// Method to implement basic authentication.  Right now only "I connected to
-/// you so I must have permission!" is supported.
+/// Method to begin `fs:cookie` authentication.
+///
+/// See [`CookieChallenge`].
+#[derive(Debug, serde::Deserialize, Deftly)]
+#[derive_deftly(DynMethod)]
+#[deftly(rpc(method_name = "auth:cookie_begin"))]
+struct CookieBegin {
+    /// A random nonce chosen by the client.
+    client_nonce: String,
+}
+
+/// A reply from the `CookieBegin` method.
+#[derive(Debug, serde::Serialize)]
+struct CookieBeginReply {
+    /// A random nonce that we chose.
+    server_nonce: String,
+    /// Our MAC over both nonces, keyed with the cookie.
+    server_mac: String,
+}
+
+impl rpc::RpcMethod for CookieBegin {
+    type Output = CookieBeginReply;
+    type Update = rpc::NoUpdates;
+}
+
+/// Invoke the "cookie_begin" method on a connection.
+async fn conn_cookie_begin(
+    conn: Arc<Connection>,
+    method: Box<CookieBegin>,
+    _ctx: Arc<dyn rpc::Context>,
+) -> Result<CookieBeginReply, rpc::RpcError> {
+    use rand::Rng as _;
+    let policy = conn.mgr()?.auth_policy();
+    let cookie = policy
+        .cookie
+        .as_ref()
+        .ok_or(AuthenticationFailure::SchemeNotEnabled)?;
+    let challenge = CookieChallenge {
+        client_nonce: decode_cookie_field(&method.client_nonce)?.into(),
+        server_nonce: rand::thread_rng().gen(),
+    };
+    let reply = CookieBeginReply {
+        server_nonce: encode_cookie_field(&challenge.server_nonce[..]),
+        server_mac: encode_cookie_field(challenge.server_mac(cookie).as_ref()),
+    };
+    conn.set_cookie_challenge(challenge);
+    Ok(reply)
+}
+rpc::static_rpc_invoke_fn! {
+    conn_cookie_begin;
+}
+
+/// Method to authenticate, yielding a session.
 #[derive(Debug, serde::Deserialize, Deftly)]
 #[derive_deftly(DynMethod)]
 #[deftly(rpc(method_name = "auth:authenticate"))]
 struct Authenticate {
     /// The authentication scheme as enumerated in the spec.
-    ///
-    /// TODO RPC: The only supported one for now is "inherent:unix_path"
     scheme: AuthenticationScheme,
+    /// The secret that we're presenting, for `preshared:token`,
+    /// or our answer to the challenge from `auth:cookie_begin`, for `fs:cookie`.
+    #[serde(default)]
+    secret: Option<String>,
 }
 
 /// A reply from the `Authenticate` method.
@@ -155,8 +457,26 @@ impl rpc::RpcMethod for Authenticate {
 
 /// An error during authentication.
 #[derive(Debug, Clone, thiserror::Error, serde::Serialize)]
-#[allow(dead_code)] // TODO RPC
-enum AuthenticationFailure {}
+enum AuthenticationFailure {
+    /// The client asked for a scheme that isn't enabled.
+    #[error("Authentication scheme not enabled")]
+    SchemeNotEnabled,
+    /// The client's scheme needs a secret, but none was provided.
+    #[error("No secret provided")]
+    MissingSecret,
+    /// The client provided the wrong secret.
+    #[error("Incorrect secret")]
+    IncorrectSecret,
+    /// The client's user ID is unknown or not on our list.
+    #[error("Peer user ID not permitted")]
+    PeerNotPermitted,
+    /// The client tried `fs:cookie` without first calling `auth:cookie_begin`.
+    #[error("No cookie challenge in progress")]
+    NoCookieChallenge,
+    /// The client sent a nonce or MAC that we couldn't decode.
+    #[error("Malformed cookie nonce or MAC")]
+    MalformedCookieField,
+}
 
 impl tor_error::HasKind for AuthenticationFailure {
     fn kind(&self) -> tor_error::ErrorKind {
@@ -174,21 +494,125 @@ async fn authenticate_connection(
     method: Box<Authenticate>,
     ctx: Arc<dyn rpc::Context>,
 ) -> Result<AuthenticateReply, rpc::RpcError> {
-    match method.scheme {
-        // For now, we only support AF_UNIX connections, and we assume that if
-        // you have permission to open such a connection to us, you have
-        // permission to use Arti. We will refine this later on!
-        AuthenticationScheme::InherentUnixPath => {}
-    }
+    let mgr = unauth.mgr()?;
+    mgr.auth_policy().check(
+        unauth.peer(),
+        method.scheme,
+        method.secret.as_deref(),
+        unauth.take_cookie_challenge(),
+    )?;
 
     let auth = RpcAuthentication {};
-    let session = {
-        let mgr = unauth.mgr()?;
-        mgr.create_session(&auth)
-    };
+    let session = mgr.create_session(&auth);
     let session = ctx.register_owned(session);
     Ok(AuthenticateReply { session })
 }
 rpc::static_rpc_invoke_fn! {
     authenticate_connection;
 }
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+    use AuthenticationFailure as AF;
+    use AuthenticationScheme as S;
+
+    #[test]
+    fn policy() {
+        let cookie = RpcSecret::new_random();
+        let mut policy = RpcAuthPolicy::default();
+        let anyone = RpcPeer::default();
+        let me = RpcPeer::with_uid(1000);
+        let them = RpcPeer::with_uid(1001);
+
+        assert_eq!(policy.schemes_for(&me), vec![S::InherentUnixPath]);
+        assert!(policy
+            .check(&anyone, S::InherentUnixPath, None, None)
+            .is_ok());
+        assert!(matches!(
+            policy.check(&me, S::InherentPeerUid, None, None),
+            Err(AF::SchemeNotEnabled)
+        ));
+
+        policy.inherent_unix_path = false;
+        policy.allowed_peer_uids = vec![1000];
+        policy.cookie = Some(cookie.clone());
+        policy.token = Some("hunter2".to_string().into());
+
+        assert_eq!(
+            policy.schemes_for(&me),
+            vec![S::Cookie, S::Token, S::InherentPeerUid]
+        );
+        assert_eq!(policy.schemes_for(&them), vec![S::Cookie, S::Token]);
+
+        assert!(policy.check(&me, S::InherentUnixPath, None, None).is_err());
+        assert!(policy.check(&me, S::InherentPeerUid, None, None).is_ok());
+        assert!(matches!(
+            policy.check(&them, S::InherentPeerUid, None, None),
+            Err(AF::PeerNotPermitted)
+        ));
+        assert!(matches!(
+            policy.check(&anyone, S::InherentPeerUid, None, None),
+            Err(AF::PeerNotPermitted)
+        ));
+
+        let challenge = CookieChallenge {
+            client_nonce: [1; COOKIE_NONCE_LEN],
+            server_nonce: [2; COOKIE_NONCE_LEN],
+        };
+        let client_mac = encode_cookie_field(challenge.client_mac(&cookie).as_ref());
+        let server_mac = encode_cookie_field(challenge.server_mac(&cookie).as_ref());
+        assert!(policy
+            .check(&them, S::Cookie, Some(&client_mac), Some(challenge.clone()))
+            .is_ok());
+        // The cookie itself is no good, and neither is our own MAC.
+        for wrong in [cookie.as_str(), &server_mac] {
+            assert!(matches!(
+                policy.check(&them, S::Cookie, Some(wrong), Some(challenge.clone())),
+                Err(AF::IncorrectSecret | AF::MalformedCookieField)
+            ));
+        }
+        assert!(matches!(
+            policy.check(&them, S::Cookie, Some(&client_mac), None),
+            Err(AF::NoCookieChallenge)
+        ));
+        assert!(matches!(
+            policy.check(&them, S::Cookie, None, Some(challenge)),
+            Err(AF::MissingSecret)
+        ));
+        assert!(policy.check(&them, S::Token, Some("hunter2"), None).is_ok());
+        assert!(policy.check(&them, S::Token, Some("hunter"), None).is_err());
+    }
+
+    #[test]
+    fn cookie_macs() {
+        // Known-answer test: arti-rpc-client-core computes the same values.
+        let cookie = RpcSecret::from("cookie-for-testing".to_string());
+        let challenge = CookieChallenge {
+            client_nonce: [1; COOKIE_NONCE_LEN],
+            server_nonce: [2; COOKIE_NONCE_LEN],
+        };
+        assert_eq!(
+            hex::encode(challenge.server_mac(&cookie).as_ref()),
+            "b8fe49dce33f50bd76174f1761381759277c9397709e315467cd41660e1e2c6b"
+        );
+        assert_eq!(
+            hex::encode(challenge.client_mac(&cookie).as_ref()),
+            "aed2f80427211f2da148a371219bc6b614d85d1598d0c334371d854e7dde9514"
+        );
+    }
+}
@@ -48,10 +48,16 @@ mod globalid;
 mod mgr;
 mod msgs;
 mod objmap;
+mod proxyinfo;
 mod session;
 mod stream;
+#[cfg(feature = "testing")]
+pub mod testing;
 
-pub use connection::{auth::RpcAuthentication, Connection, ConnectionError};
+pub use connection::{
+    auth::{RpcAuthPolicy, RpcAuthentication, RpcPeer, RpcSecret},
+    Connection, ConnectionError,
+};
 pub use mgr::RpcMgr;
 pub use session::RpcSession;
 
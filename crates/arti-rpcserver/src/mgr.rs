@@ -1,6 +1,9 @@
 //! Top-level `RpcMgr` to launch sessions.
 
-use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex, RwLock, Weak},
+};
 
 use rand::Rng;
 use rpc::InvalidMethodName;
@@ -11,7 +14,7 @@ use weak_table::WeakValueHashMap;
 use crate::{
     connection::{Connection, ConnectionId},
     globalid::{GlobalId, MacKey},
-    RpcAuthentication,
+    RpcAuthPolicy, RpcAuthentication, RpcPeer,
 };
 
 /// A function we use to construct Session objects in response to authentication.
@@ -91,6 +94,12 @@ pub(crate) struct Inner {
     /// MACing anything derived from them, which in turn makes the overhead of a
     /// HashMap negligible.
     connections: WeakValueHashMap<ConnectionId, Weak<Connection>>,
+
+    /// The ways in which we let clients authenticate.
+    auth_policy: Arc<RpcAuthPolicy>,
+
+    /// The SOCKS listeners through which clients can open data streams.
+    socks_proxies: Arc<Vec<SocketAddr>>,
 }
 
 /// An error from creating or using an RpcMgr.
@@ -132,6 +141,8 @@ impl RpcMgr {
             session_factory: Box::new(make_session),
             inner: Mutex::new(Inner {
                 connections: WeakValueHashMap::new(),
+                auth_policy: Arc::new(RpcAuthPolicy::default()),
+                socks_proxies: Arc::new(Vec::new()),
             }),
         }))
     }
@@ -166,14 +177,49 @@ impl RpcMgr {
         func(&mut table)
     }
 
+    /// Replace the policy that we use to decide how clients may authenticate.
+    ///
+    /// The new policy applies to every authentication attempt from now on,
+    /// including those on existing connections.
+    pub fn set_auth_policy(&self, policy: RpcAuthPolicy) {
+        self.inner.lock().expect("poisoned lock").auth_policy = Arc::new(policy);
+    }
+
+    /// Return the policy that we use to decide how clients may authenticate.
+    pub(crate) fn auth_policy(&self) -> Arc<RpcAuthPolicy> {
+        Arc::clone(&self.inner.lock().expect("poisoned lock").auth_policy)
+    }
+
+    /// Replace the list of SOCKS listeners that we tell clients about.
+    ///
+    /// Clients use these listeners to open data streams from the stream handles
+    /// that they create with `arti:new_stream_handle`.
+    pub fn set_socks_proxies(&self, proxies: Vec<SocketAddr>) {
+        self.inner.lock().expect("poisoned lock").socks_proxies = Arc::new(proxies);
+    }
+
+    /// Return the list of SOCKS listeners that we tell clients about.
+    pub(crate) fn socks_proxies(&self) -> Arc<Vec<SocketAddr>> {
+        Arc::clone(&self.inner.lock().expect("poisoned lock").socks_proxies)
+    }
+
     /// Start a new session based on this RpcMgr, with a given TorClient.
+    ///
+    /// We don't know anything about the peer on the other end of this connection;
+    /// see [`new_connection_with_peer`](RpcMgr::new_connection_with_peer).
     pub fn new_connection(self: &Arc<Self>) -> Arc<Connection> {
+        self.new_connection_with_peer(RpcPeer::default())
+    }
+
+    /// Start a new session based on this RpcMgr, for a connection from `peer`.
+    pub fn new_connection_with_peer(self: &Arc<Self>, peer: RpcPeer) -> Arc<Connection> {
         let connection_id = ConnectionId::from(rand::thread_rng().gen::<[u8; 16]>());
         let connection = Connection::new(
             connection_id,
             self.dispatch_table.clone(),
             self.global_id_mac_key.clone(),
             Arc::downgrade(self),
+            peer,
         );
 
         let mut inner = self.inner.lock().expect("poisoned lock");
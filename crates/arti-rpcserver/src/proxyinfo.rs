@@ -0,0 +1,108 @@
+//! Tell RPC clients where they can open data streams.
+//!
+//! An RPC client that wants a data stream asks for a stream handle with
+//! `arti:new_stream_handle`, and then opens a SOCKS connection to one of
+//! the proxies listed here, presenting that handle as its credentials.
+//! The SOCKS connection then carries the stream's data.
+//
+// TODO RPC: We should also let clients on a Unix socket receive the stream
+// directly, as a file descriptor passed with SCM_RIGHTS.  That needs access
+// to the underlying socket, which `Connection::run` does not currently have.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use derive_deftly::Deftly;
+use tor_rpcbase as rpc;
+use tor_rpcbase::templates::*;
+
+use crate::Connection;
+
+/// Method to ask where the proxy listeners are.
+#[derive(Debug, serde::Deserialize, Deftly)]
+#[derive_deftly(DynMethod)]
+#[deftly(rpc(method_name = "arti:get_rpc_proxy_info"))]
+struct GetRpcProxyInfo {}
+
+/// A list of proxies that an RPC client can use to open data streams.
+#[derive(Debug, serde::Serialize)]
+struct RpcProxyInfo {
+    /// The proxies, in no particular order.
+    proxies: Vec<Proxy>,
+}
+
+/// A single proxy listener.
+#[derive(Debug, serde::Serialize)]
+struct Proxy {
+    /// Where the proxy is listening, and what protocol it speaks.
+    listener: ProxyListener,
+}
+
+/// A description of a proxy listener.
+#[derive(Debug, serde::Serialize)]
+enum ProxyListener {
+    /// A SOCKS5 proxy, reachable over TCP.
+    #[serde(rename = "socks5")]
+    Socks5 {
+        /// The address of the proxy.
+        tcp_address: SocketAddr,
+    },
+}
+
+impl rpc::RpcMethod for GetRpcProxyInfo {
+    type Output = RpcProxyInfo;
+    type Update = rpc::NoUpdates;
+}
+
+/// Implement `arti:get_rpc_proxy_info` on a connection.
+async fn conn_get_rpc_proxy_info(
+    conn: Arc<Connection>,
+    _method: Box<GetRpcProxyInfo>,
+    _ctx: Arc<dyn rpc::Context>,
+) -> Result<RpcProxyInfo, rpc::RpcError> {
+    let proxies = conn
+        .mgr()?
+        .socks_proxies()
+        .iter()
+        .map(|addr| Proxy {
+            listener: ProxyListener::Socks5 { tcp_address: *addr },
+        })
+        .collect();
+    Ok(RpcProxyInfo { proxies })
+}
+rpc::static_rpc_invoke_fn! {
+    conn_get_rpc_proxy_info;
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        let info = RpcProxyInfo {
+            proxies: vec![Proxy {
+                listener: ProxyListener::Socks5 {
+                    tcp_address: "127.0.0.1:9150".parse().unwrap(),
+                },
+            }],
+        };
+        assert_eq!(
+            serde_json::to_string(&info).unwrap(),
+            r#"{"proxies":[{"listener":{"socks5":{"tcp_address":"127.0.0.1:9150"}}}]}"#
+        );
+    }
+}
@@ -8,6 +8,7 @@ use arti_client::{
     TorClient,
 };
 use derive_deftly::Deftly;
+use futures::{SinkExt as _, StreamExt as _};
 use std::{net::IpAddr, sync::Arc};
 use tor_rtcompat::Runtime;
 
@@ -83,13 +84,81 @@ struct RpcRelease {
 }
 /// RPC method to release a single strong reference, creating a weak reference
 /// in its place.
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-#[allow(unused)] //TODO RPC
+#[derive(Debug, serde::Deserialize, Deftly)]
+#[derive_deftly(DynMethod)]
+#[deftly(rpc(method_name = "rpc:downgrade"))]
 struct RpcDowngrade {
-    /// The object to downgrade
+    /// The object to downgrade. Must be a strong reference.
+    obj: rpc::ObjectId,
+}
+
+/// Reply to an [`RpcDowngrade`] method.
+#[derive(Debug, serde::Serialize)]
+struct DowngradeReply {
+    /// The new weak reference.
     obj: rpc::ObjectId,
 }
 
+impl rpc::RpcMethod for RpcDowngrade {
+    type Output = DowngradeReply;
+    type Update = rpc::NoUpdates;
+}
+
+/// Implementation for calling "downgrade" on a Session.
+async fn rpc_downgrade(
+    _obj: Arc<RpcSession>,
+    method: Box<RpcDowngrade>,
+    ctx: Arc<dyn rpc::Context>,
+) -> Result<DowngradeReply, rpc::RpcError> {
+    let obj = ctx.downgrade_owned(&method.obj)?;
+    Ok(DowngradeReply { obj })
+}
+
+/// RPC method to be told when some references stop working.
+///
+/// This sends an update for each object in `objs` once it can no longer be
+/// used: because a strong reference was released, or because the target of
+/// a weak reference has gone away.
+/// It finishes once every object has been reported.
+///
+/// Expiry is reported when Arti notices it, which is not necessarily
+/// as soon as it happens: at the latest, it is noticed the next time a request
+/// starts or finishes on this connection.
+#[derive(Debug, serde::Deserialize, Deftly)]
+#[derive_deftly(DynMethod)]
+#[deftly(rpc(method_name = "rpc:watch_expiry"))]
+struct RpcWatchExpiry {
+    /// The objects to watch, as strong or weak references.
+    objs: Vec<rpc::ObjectId>,
+}
+
+/// Update from an [`RpcWatchExpiry`] method.
+#[derive(Debug, serde::Serialize)]
+struct ObjectExpired {
+    /// The object that can no longer be used,
+    /// exactly as it was given in the request.
+    expired: rpc::ObjectId,
+}
+
+impl rpc::RpcMethod for RpcWatchExpiry {
+    type Output = rpc::Nil;
+    type Update = ObjectExpired;
+}
+
+/// Implementation for calling "watch_expiry" on a Session.
+async fn rpc_watch_expiry(
+    _obj: Arc<RpcSession>,
+    method: Box<RpcWatchExpiry>,
+    ctx: Arc<dyn rpc::Context>,
+    mut updates: rpc::UpdateSink<ObjectExpired>,
+) -> Result<rpc::Nil, rpc::RpcError> {
+    let mut expired = ctx.watch_expiry(method.objs)?;
+    while let Some(id) = expired.next().await {
+        updates.send(ObjectExpired { expired: id }).await?;
+    }
+    Ok(rpc::Nil::default())
+}
+
 impl rpc::RpcMethod for RpcRelease {
     type Output = rpc::Nil;
     type Update = rpc::NoUpdates;
@@ -204,6 +273,8 @@ async fn session_resolve_ptr_with_prefs(
 
 static_rpc_invoke_fn! {
     rpc_release;
+    rpc_downgrade;
+    rpc_watch_expiry;
     echo_on_session;
     get_client_on_session;
     isolated_client_on_session;
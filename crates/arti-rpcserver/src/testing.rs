@@ -0,0 +1,41 @@
+//! Entry points into our parsers, for fuzzing.
+//!
+//! Nothing here is covered by semantic versioning.
+
+use asynchronous_codec::Decoder as _;
+use bytes::BytesMut;
+
+use crate::codecs::{Framing, LengthPrefixedCodec};
+use crate::msgs::FlexibleRequest;
+
+/// Try to parse `s` as a single RPC request, as received on a jsonlines connection.
+///
+/// Return true if it is a well-formed request,
+/// and false if it is malformed in a way that we can report to the client.
+///
+/// Return an error if it could not be parsed at all.
+pub fn parse_request(s: &str) -> Result<bool, serde_json::Error> {
+    Ok(match serde_json::from_str::<FlexibleRequest>(s)? {
+        FlexibleRequest::Valid(_) => true,
+        FlexibleRequest::Invalid(_) => false,
+    })
+}
+
+/// Decode every complete length-prefixed frame in `data`,
+/// as received on a connection using the CBOR (if `cbor` is true) or JSON framing.
+///
+/// Return the number of frames that were decoded, or an error if any frame was invalid.
+pub fn decode_frames(cbor: bool, data: &[u8]) -> Result<usize, asynchronous_codec::JsonCodecError> {
+    let framing = if cbor {
+        Framing::Cbor
+    } else {
+        Framing::LengthPrefixedJson
+    };
+    let mut codec = LengthPrefixedCodec::<()>::new(framing);
+    let mut buf = BytesMut::from(data);
+    let mut n_frames = 0;
+    while codec.decode(&mut buf)?.is_some() {
+        n_frames += 1;
+    }
+    Ok(n_frames)
+}
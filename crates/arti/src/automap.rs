@@ -0,0 +1,391 @@
+//! Map `.onion` hostnames to synthetic addresses, for applications that insist
+//! on resolving a hostname before they connect to it.
+//!
+//! This is what C Tor calls `AutomapHostsOnResolve`: when we're asked (via
+//! SOCKS RESOLVE) for the address of an onion service, we hand out an unused
+//! address from a configured "virtual" network, and remember it.  When an
+//! application later asks to connect to that address, we connect to the onion
+//! service instead.
+//!
+//! Mappings are kept separately for each isolation group, so that one client
+//! can't learn (for example, with a reverse lookup) which onion services
+//! another has asked about.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// The largest number of hostnames that we will map at once, over all
+/// isolation groups.
+///
+/// We never forget a mapping, since an application may use it at any time, so
+/// we need some limit to keep the table from growing forever.
+const MAX_AUTOMAPPINGS: usize = 65536;
+
+/// The suffix of the hostnames that we map.
+const ONION_SUFFIX: &str = ".onion";
+
+/// A range of IP addresses from which we hand out synthetic addresses.
+///
+/// Written as an address and a prefix length, like `127.192.0.0/10` or
+/// `fe80::/10`.  The range must contain at least 256 addresses.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct VirtualAddrNetwork {
+    /// The first address in the network.
+    base: IpAddr,
+    /// The number of leading bits that every address in the network shares.
+    prefix_len: u8,
+}
+
+/// An error from parsing a [`VirtualAddrNetwork`].
+#[derive(Clone, Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum VirtualAddrNetworkError {
+    /// The network wasn't written as `ADDRESS/PREFIX_LEN`.
+    #[error("Virtual address network {0:?} is not of the form ADDRESS/PREFIX_LEN")]
+    Syntax(String),
+    /// The network is too small (or the prefix length is out of range).
+    #[error("Virtual address network {0:?} must contain at least 256 addresses")]
+    TooSmall(String),
+}
+
+impl VirtualAddrNetwork {
+    /// Return the number of bits in an address of this network's family.
+    fn addr_bits(&self) -> u32 {
+        match self.base {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+
+    /// Return the number of bits that vary between addresses in this network.
+    fn host_bits(&self) -> u32 {
+        self.addr_bits() - u32::from(self.prefix_len)
+    }
+
+    /// Return the number of addresses in this network.
+    ///
+    /// (If the network is too large to count, we pretend it has `u128::MAX` addresses.)
+    fn n_addrs(&self) -> u128 {
+        1_u128.checked_shl(self.host_bits()).unwrap_or(u128::MAX)
+    }
+
+    /// Return a mask with every host bit of this network set.
+    fn host_mask(&self) -> u128 {
+        // (We can't just use `n_addrs() - 1`: for a network with 128 host
+        // bits, that would leave the lowest bit clear.)
+        u128::MAX.checked_shr(128 - self.host_bits()).unwrap_or(0)
+    }
+
+    /// Return true if `addr` is in this network.
+    pub(crate) fn contains(&self, addr: IpAddr) -> bool {
+        let (Some(a), Some(b)) = (
+            addr_to_bits(addr, &self.base),
+            addr_to_bits(self.base, &self.base),
+        ) else {
+            return false;
+        };
+        a.checked_shr(self.host_bits()).unwrap_or(0) == b.checked_shr(self.host_bits()).unwrap_or(0)
+    }
+
+    /// Return the address at `offset` from the start of this network.
+    ///
+    /// `offset` must be less than `n_addrs()`.
+    fn addr_at(&self, offset: u128) -> IpAddr {
+        let base = addr_to_bits(self.base, &self.base).expect("base in wrong family");
+        match self.base {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from((base | offset) as u32)),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(base | offset)),
+        }
+    }
+}
+
+/// Return the bits of `addr`, if it's in the same family as `family`.
+fn addr_to_bits(addr: IpAddr, family: &IpAddr) -> Option<u128> {
+    match (addr, family) {
+        (IpAddr::V4(a), IpAddr::V4(_)) => Some(u32::from(a).into()),
+        (IpAddr::V6(a), IpAddr::V6(_)) => Some(u128::from(a)),
+        (_, _) => None,
+    }
+}
+
+impl Default for VirtualAddrNetwork {
+    fn default() -> Self {
+        VirtualAddrNetwork {
+            base: Ipv4Addr::new(127, 192, 0, 0).into(),
+            prefix_len: 10,
+        }
+    }
+}
+
+impl FromStr for VirtualAddrNetwork {
+    type Err = VirtualAddrNetworkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let syntax = || VirtualAddrNetworkError::Syntax(s.to_owned());
+        let (addr, prefix_len) = s.split_once('/').ok_or_else(syntax)?;
+        let addr: IpAddr = addr
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+            .map_err(|_| syntax())?;
+        let prefix_len: u8 = prefix_len.parse().map_err(|_| syntax())?;
+
+        let mut net = VirtualAddrNetwork {
+            base: addr,
+            prefix_len,
+        };
+        if u32::from(prefix_len) + 8 > net.addr_bits() {
+            return Err(VirtualAddrNetworkError::TooSmall(s.to_owned()));
+        }
+        // Clear any host bits from the base address.
+        let mask = net.host_mask();
+        let bits = addr_to_bits(addr, &addr).expect("address in wrong family");
+        net.base = match addr {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from((bits & !mask) as u32)),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(bits & !mask)),
+        };
+        Ok(net)
+    }
+}
+
+impl fmt::Display for VirtualAddrNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.base, self.prefix_len)
+    }
+}
+
+impl TryFrom<String> for VirtualAddrNetwork {
+    type Error = VirtualAddrNetworkError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<VirtualAddrNetwork> for String {
+    fn from(net: VirtualAddrNetwork) -> String {
+        net.to_string()
+    }
+}
+
+/// Return true if `hostname` is one that we should map to a synthetic address.
+pub(crate) fn should_automap(hostname: &str) -> bool {
+    let hostname = hostname.trim_end_matches('.');
+    hostname.len() > ONION_SUFFIX.len()
+        && hostname
+            .get(hostname.len() - ONION_SUFFIX.len()..)
+            .is_some_and(|suffix| suffix.eq_ignore_ascii_case(ONION_SUFFIX))
+}
+
+/// A set of mappings between hostnames and synthetic addresses,
+/// for each isolation group `K`.
+///
+/// Each group has its own mappings: the same address may stand for different
+/// hostnames in different groups, and a group can only look up the
+/// addresses that were handed out to it.
+pub(crate) struct AutomapTable<K> {
+    /// The network from which we hand out addresses.
+    network: VirtualAddrNetwork,
+    /// The mappings that we've made so far.
+    inner: Mutex<AutomapInner<K>>,
+}
+
+/// The mutable part of an [`AutomapTable`].
+struct AutomapInner<K> {
+    /// The mappings for each isolation group.
+    groups: HashMap<K, AutomapGroup>,
+    /// The total number of mappings in `groups`.
+    n_mappings: usize,
+}
+
+/// The mappings that we've made for a single isolation group.
+struct AutomapGroup {
+    /// The address we've given to each (lowercased) hostname.
+    by_name: HashMap<String, IpAddr>,
+    /// The hostname we've given each address to.
+    by_addr: HashMap<IpAddr, String>,
+    /// The offset within the network at which we'll look for the next free address.
+    next_offset: u128,
+}
+
+impl<K: Hash + Eq> AutomapTable<K> {
+    /// Make a new table, to hand out addresses from `network`.
+    pub(crate) fn new(network: VirtualAddrNetwork) -> Self {
+        AutomapTable {
+            network,
+            inner: Mutex::new(AutomapInner {
+                groups: HashMap::new(),
+                n_mappings: 0,
+            }),
+        }
+    }
+
+    /// Return the network from which this table hands out addresses.
+    pub(crate) fn network(&self) -> VirtualAddrNetwork {
+        self.network
+    }
+
+    /// Return the synthetic address for `hostname` in the isolation group
+    /// `group`, assigning one if necessary.
+    ///
+    /// Returns `None` if we have run out of addresses.
+    pub(crate) fn map(&self, group: K, hostname: &str) -> Option<IpAddr> {
+        let hostname = hostname.trim_end_matches('.').to_ascii_lowercase();
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        let AutomapInner { groups, n_mappings } = &mut *inner;
+        let group = groups.entry(group).or_insert_with(|| AutomapGroup {
+            by_name: HashMap::new(),
+            by_addr: HashMap::new(),
+            next_offset: 1,
+        });
+        if let Some(addr) = group.by_name.get(&hostname) {
+            return Some(*addr);
+        }
+
+        // We never hand out the first or last address in the network.
+        let n_usable = self.network.n_addrs() - 2;
+        if u128::try_from(group.by_name.len()).unwrap_or(u128::MAX) >= n_usable
+            || *n_mappings >= MAX_AUTOMAPPINGS
+        {
+            warn!("Ran out of virtual addresses to map onion services to.");
+            return None;
+        }
+        let addr = loop {
+            let offset = group.next_offset;
+            group.next_offset = if offset >= n_usable { 1 } else { offset + 1 };
+            let addr = self.network.addr_at(offset);
+            if !group.by_addr.contains_key(&addr) {
+                break addr;
+            }
+        };
+        group.by_addr.insert(addr, hostname.clone());
+        group.by_name.insert(hostname, addr);
+        *n_mappings += 1;
+        Some(addr)
+    }
+
+    /// If `addr` is a synthetic address that we've handed out to the
+    /// isolation group `group`, return the hostname that it stands for.
+    pub(crate) fn lookup(&self, group: &K, addr: IpAddr) -> Option<String> {
+        if !self.network.contains(addr) {
+            return None;
+        }
+        let inner = self.inner.lock().expect("lock poisoned");
+        inner.groups.get(group)?.by_addr.get(&addr).cloned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    #[test]
+    fn parse_network() {
+        let net: VirtualAddrNetwork = "127.192.0.0/10".parse().unwrap();
+        assert_eq!(net, VirtualAddrNetwork::default());
+        assert_eq!(net.to_string(), "127.192.0.0/10");
+        assert!(net.contains("127.200.1.2".parse().unwrap()));
+        assert!(!net.contains("127.0.0.1".parse().unwrap()));
+        assert!(!net.contains("::1".parse().unwrap()));
+
+        // Host bits are ignored.
+        let net: VirtualAddrNetwork = "10.1.2.3/16".parse().unwrap();
+        assert_eq!(net.to_string(), "10.1.0.0/16");
+
+        let net: VirtualAddrNetwork = "[fe80::]/10".parse().unwrap();
+        assert_eq!(net.to_string(), "fe80::/10");
+        assert!(net.contains("fe80::1234".parse().unwrap()));
+        assert!(!net.contains("127.192.0.1".parse().unwrap()));
+
+        // Networks that cover a whole address family.
+        let net: VirtualAddrNetwork = "::1/0".parse().unwrap();
+        assert_eq!(net.to_string(), "::/0");
+        assert!(net.contains("::".parse().unwrap()));
+        assert!(net.contains("ffff::1".parse().unwrap()));
+        assert!(!net.contains("127.0.0.1".parse().unwrap()));
+        let net: VirtualAddrNetwork = "10.1.2.3/0".parse().unwrap();
+        assert_eq!(net.to_string(), "0.0.0.0/0");
+        assert!(net.contains("255.255.255.255".parse().unwrap()));
+        let net: VirtualAddrNetwork = "ffff::ffff/1".parse().unwrap();
+        assert_eq!(net.to_string(), "8000::/1");
+
+        for bad in [
+            "127.0.0.1",
+            "127.0.0.0/x",
+            "bananas/8",
+            "10.0.0.0/25",
+            "::/121",
+        ] {
+            assert!(bad.parse::<VirtualAddrNetwork>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn which_hosts() {
+        assert!(should_automap("example.onion"));
+        assert!(should_automap("www.EXAMPLE.ONION."));
+        assert!(!should_automap(".onion"));
+        assert!(!should_automap("example.com"));
+        assert!(!should_automap("onion"));
+    }
+
+    #[test]
+    fn mappings() {
+        let table = AutomapTable::new("10.0.0.0/24".parse().unwrap());
+        let a = table.map(1, "a.onion").unwrap();
+        let b = table.map(1, "B.onion").unwrap();
+        assert_ne!(a, b);
+        assert_eq!(a, "10.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(table.map(1, "A.ONION."), Some(a));
+        assert_eq!(table.lookup(&1, a).as_deref(), Some("a.onion"));
+        assert_eq!(table.lookup(&1, b).as_deref(), Some("b.onion"));
+        assert_eq!(table.lookup(&1, "10.0.0.99".parse().unwrap()), None);
+        assert_eq!(table.lookup(&1, "127.0.0.1".parse().unwrap()), None);
+
+        // Fill up the network: 254 usable addresses.
+        for i in 2..254 {
+            let addr = table.map(1, &format!("{}.onion", i)).unwrap();
+            assert!(addr != "10.0.0.0".parse::<IpAddr>().unwrap());
+            assert!(addr != "10.0.0.255".parse::<IpAddr>().unwrap());
+        }
+        assert_eq!(table.map(1, "full.onion"), None);
+        // Existing mappings still work.
+        assert_eq!(table.map(1, "a.onion"), Some(a));
+    }
+
+    #[test]
+    fn isolation() {
+        let table = AutomapTable::new("10.0.0.0/24".parse().unwrap());
+        let a = table.map(1, "a.onion").unwrap();
+
+        // Another group can't see the first group's mappings...
+        assert_eq!(table.lookup(&2, a), None);
+        // ...and gets its own.
+        let b = table.map(2, "b.onion").unwrap();
+        assert_eq!(a, b);
+        assert_eq!(table.lookup(&1, a).as_deref(), Some("a.onion"));
+        assert_eq!(table.lookup(&2, b).as_deref(), Some("b.onion"));
+        assert_eq!(table.map(2, "a.onion"), Some("10.0.0.2".parse().unwrap()));
+    }
+}
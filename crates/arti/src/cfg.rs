@@ -17,11 +17,14 @@ use arti_client::TorClientConfig;
 #[cfg(feature = "onion-service-service")]
 use tor_config::define_list_builder_accessors;
 use tor_config::resolve_alternative_specs;
-#[cfg(feature = "rpc")]
 use tor_config::CfgPath;
+#[cfg(feature = "rpc")]
+use tor_config::CfgSecret;
 pub(crate) use tor_config::{impl_standard_builder, ConfigBuildError, Listen};
 
-use crate::{LoggingConfig, LoggingConfigBuilder};
+#[cfg(feature = "metrics")]
+use crate::metrics::{MetricsConfig, MetricsConfigBuilder};
+use crate::{LoggingConfig, LoggingConfigBuilder, VirtualAddrNetwork};
 
 /// Example file demonstrating our configuration and the default options.
 ///
@@ -75,14 +78,91 @@ pub struct ApplicationConfig {
     #[builder(default)]
     pub(crate) permit_debugging: bool,
 
+    /// If true, then once we have started up, we ask the operating system to
+    /// prevent us from doing anything that we don't need for normal operation.
+    ///
+    /// This uses seccomp on Linux, and `pledge` and `unveil` on OpenBSD.
+    /// It requires the `sandbox` feature: if that feature is not enabled,
+    /// or we are on some other platform, setting this option is an error.
+    ///
+    /// While the sandbox is enabled, we can't launch pluggable transports,
+    /// and (on OpenBSD) we can't use any files or directories that were not
+    /// in our configuration when we started.
+    #[builder(default)]
+    pub(crate) sandbox: bool,
+
     /// If true, then we do not exit when we are running as `root`.
     ///
     /// This has no effect on Windows.
     #[builder(default)]
     pub(crate) allow_running_as_root: bool,
+
+    /// If set, the file to which we write our process ID when we run as a proxy.
+    ///
+    /// We remove this file when we exit.
+    #[builder(default)]
+    pub(crate) pid_file: Option<CfgPath>,
+
+    /// How long to wait, when shutting down, for proxy connections that are
+    /// still open to finish.
+    ///
+    /// We stop accepting new connections as soon as we are asked to shut down.
+    /// If we are asked to shut down again while we are waiting, we stop
+    /// waiting.
+    ///
+    /// The default is "10s".
+    #[builder(default = "std::time::Duration::new(10, 0)")]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    pub(crate) shutdown_timeout: std::time::Duration,
+
+    /// How long to keep the state of an onion service after it has been
+    /// removed from our configuration.
+    ///
+    /// (The service's keys are kept regardless, so re-adding the service
+    /// later will still give it the same address.)
+    ///
+    /// The default is "30 days".
+    #[builder(default = "Retention::For(std::time::Duration::from_secs(30 * 86400))")]
+    #[builder_field_attr(serde(default))]
+    pub(crate) onion_service_state_retention: Retention,
 }
 impl_standard_builder! { ApplicationConfig }
 
+/// How long to keep some data that we no longer need, before deleting it.
+///
+/// In the configuration, this is either a duration (like `"30 days"`),
+/// or `"never"`, meaning that we never delete the data.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+#[non_exhaustive]
+pub enum Retention {
+    /// Delete the data once it has been unused for this long.
+    For(std::time::Duration),
+    /// Never delete the data.
+    Never,
+}
+
+impl TryFrom<String> for Retention {
+    type Error = humantime::DurationError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        if s.trim() == "never" {
+            Ok(Retention::Never)
+        } else {
+            humantime::parse_duration(&s).map(Retention::For)
+        }
+    }
+}
+
+impl From<Retention> for String {
+    fn from(r: Retention) -> String {
+        match r {
+            Retention::For(d) => humantime::format_duration(d).to_string(),
+            Retention::Never => "never".into(),
+        }
+    }
+}
+
 /// Resolves values from `$field_listen` and `$field_port` (compat) into a `Listen`
 ///
 /// For `dns` and `proxy`.
@@ -147,6 +227,75 @@ pub struct ProxyConfig {
     )]
     #[builder_setter_attr(deprecated)]
     pub(crate) dns_port: (),
+
+    /// How long a SOCKS client may take to finish its handshake before we
+    /// close its connection.
+    ///
+    /// The default is "30s".
+    #[builder(default = "std::time::Duration::new(30, 0)")]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    pub(crate) socks_handshake_timeout: std::time::Duration,
+
+    /// How long we may take to answer a SOCKS request (by connecting or
+    /// resolving over Tor) before we give up and send the client an error.
+    ///
+    /// The default is "2 min".
+    #[builder(default = "std::time::Duration::new(120, 0)")]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    pub(crate) socks_request_timeout: std::time::Duration,
+
+    /// How long a SOCKS connection may go without any data in either
+    /// direction before we close it.
+    ///
+    /// The default is "0", which means no limit: interactive protocols like
+    /// SSH can legitimately stay idle for a long time.
+    #[builder(default = "std::time::Duration::ZERO")]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    pub(crate) socks_idle_timeout: std::time::Duration,
+
+    /// The largest number of SOCKS connections that may be in the middle of
+    /// their handshake at once.
+    ///
+    /// We close any connection that would go over this limit as soon as we
+    /// accept it.  0 means no limit.
+    #[builder(default = "256")]
+    pub(crate) socks_max_pending_handshakes: usize,
+
+    /// The largest number of SOCKS connections that we will handle at once
+    /// from any single IP address.
+    ///
+    /// We close any connection that would go over this limit as soon as we
+    /// accept it.  0 (the default) means no limit.
+    #[builder(default)]
+    pub(crate) socks_max_conns_per_ip: usize,
+
+    /// If true, report onion service failures to SOCKS5 clients with Tor's
+    /// extended error codes (`X'F0'` through `X'F7'`), like C Tor's
+    /// `ExtendedErrors` flag.
+    ///
+    /// Set this to false if your SOCKS clients can't handle reply codes that
+    /// aren't in RFC 1928: we will send "Host unreachable" instead.
+    #[builder(default = "true")]
+    pub(crate) socks_extended_errors: bool,
+
+    /// If true, answer SOCKS RESOLVE requests for `.onion` hostnames with a
+    /// synthetic address from `virtual_addr_network`, and treat a later
+    /// connection to that address as a connection to the onion service.
+    ///
+    /// This is for applications that insist on resolving a hostname before
+    /// connecting to it.
+    ///
+    /// Synthetic addresses are only recognized on connections that are
+    /// isolated in the same way as the request that they were handed out for.
+    #[builder(default)]
+    pub(crate) automap_hosts_on_resolve: bool,
+
+    /// The network from which we take synthetic addresses for
+    /// `automap_hosts_on_resolve`.
+    ///
+    /// The default is "127.192.0.0/10".
+    #[builder(default)]
+    pub(crate) virtual_addr_network: VirtualAddrNetwork,
 }
 impl_standard_builder! { ProxyConfig }
 
@@ -182,6 +331,39 @@ pub struct RpcConfig {
     /// Location to listen for incoming RPC connections.
     #[builder(default = "default_rpc_path()")]
     pub(crate) rpc_listen: Option<CfgPath>,
+
+    /// If true, anybody who can connect to `rpc_listen` may use Arti,
+    /// without any further authentication.
+    #[builder(default = "true")]
+    pub(crate) inherent_auth: bool,
+
+    /// Location at which to write a cookie file for clients to authenticate with.
+    ///
+    /// We write a new cookie every time we start.
+    /// Clients must be told this location (for example, with `cookie_path=`
+    /// in their connect string): we don't tell them,
+    /// since a client that trusted us to name the file could be tricked
+    /// by another process listening in our place.
+    #[builder(default = "default_rpc_cookie_path()")]
+    pub(crate) cookie_path: Option<CfgPath>,
+
+    /// User IDs of local processes that may use Arti without further authentication.
+    ///
+    /// Only supported on Unix.
+    #[builder(default)]
+    pub(crate) allowed_peer_uids: Vec<u32>,
+
+    /// A file containing a pre-shared token that clients may present to authenticate.
+    #[builder(default)]
+    pub(crate) token_file: Option<CfgPath>,
+
+    /// Where to find a pre-shared token that clients may present to authenticate.
+    ///
+    /// This is an alternative to `token_file`, for tokens that should not be stored on disk:
+    /// it names an environment variable, or a command whose output is the token.
+    /// It is looked up when Arti starts.
+    #[builder(default)]
+    pub(crate) token: Option<CfgSecret>,
 }
 
 /// Return the default value for our configuration path.
@@ -196,6 +378,15 @@ fn default_rpc_path() -> Option<CfgPath> {
     Some(CfgPath::new(s.to_string()))
 }
 
+/// Return the default location for our RPC cookie file.
+#[cfg(feature = "rpc")]
+#[allow(clippy::unnecessary_wraps)]
+fn default_rpc_cookie_path() -> Option<CfgPath> {
+    Some(CfgPath::new(
+        "${ARTI_LOCAL_DATA}/rpc/arti_rpc_cookie".to_string(),
+    ))
+}
+
 /// Structure to hold Arti's configuration options, whether from a
 /// configuration file or the command line.
 //
@@ -235,6 +426,12 @@ pub struct ArtiConfig {
     #[builder_field_attr(serde(default))]
     rpc: RpcConfig,
 
+    /// Configuration for the metrics exporter
+    #[cfg(feature = "metrics")]
+    #[builder(sub_builder)]
+    #[builder_field_attr(serde(default))]
+    metrics: MetricsConfig,
+
     /// Information on system resources used by Arti.
     #[builder(sub_builder)]
     #[builder_field_attr(serde(default))]
@@ -293,6 +490,12 @@ impl ArtiConfig {
     pub fn rpc(&self) -> &RpcConfig {
         &self.rpc
     }
+
+    /// Return the [`MetricsConfig`] for this configuration.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> &MetricsConfig {
+        &self.metrics
+    }
 }
 
 #[cfg(test)]
@@ -473,12 +676,41 @@ mod test {
             Recognized,
             &[
                 // Keys that are newer than the oldest-supported example, but otherwise normal.
+                "address_filter.ip_literals",
                 "application.allow_running_as_root",
+                "application.sandbox",
+                "application.onion_service_state_retention",
+                "application.shutdown_timeout",
                 "bridges",
+                "channel.dead_channel_timeout",
+                "channel.keepalive_interval",
+                "channel.prefer_ipv6",
+                "channel.use_ipv4",
+                "channel.use_ipv6",
+                "circuit_timing.max_client_streams_per_circuit",
+                "circuit_timing.max_pending_begins_per_circuit",
+                "circuit_timing.reachability_self_test",
+                "circuit_timing.reachability_self_test_interval",
+                "directory_tolerance.circuit_post_valid_tolerance",
+                "directory_tolerance.onion_service_post_valid_tolerance",
+                "download_schedule.microdesc_batch_size",
+                "logging.log_sensitive_information_targets",
                 "logging.time_granularity",
                 "path_rules.long_lived_ports",
                 "proxy.socks_listen",
                 "proxy.dns_listen",
+                "proxy.socks_handshake_timeout",
+                "proxy.socks_request_timeout",
+                "proxy.socks_idle_timeout",
+                "proxy.socks_max_pending_handshakes",
+                "proxy.socks_max_conns_per_ip",
+                "proxy.socks_extended_errors",
+                "proxy.automap_hosts_on_resolve",
+                "proxy.virtual_addr_network",
+                "storage.permissions.explain_rejections",
+                "stream_buffers",
+                "stream_buffers.high_watermark",
+                "stream_buffers.low_watermark",
             ],
         );
 
@@ -488,6 +720,13 @@ mod test {
             Recognized,
             &[
                 // Examples exist but are not auto-testable
+                "application.pid_file",
+                "channel.outbound_bind_ipv4",
+                "channel.outbound_bind_ipv6",
+                "channel.outbound_interface",
+                "channel.private_address_rewrite",
+                "channel.relay_address_overrides",
+                "storage.keystore.master_seed_file",
                 "tor_network.authorities",
                 "tor_network.fallback_caches",
             ],
@@ -515,6 +754,8 @@ mod test {
             &[
                 // Settings only available with bridge support
                 "bridges.transports", // we recognise this so we can reject it
+                "bridges.moat_bridge",
+                "bridges.in_process_transports",
             ],
         );
 
@@ -544,8 +785,22 @@ mod test {
             &[
                 // HS client settings
                 "address_filter.allow_onion_addrs",
+                "circuit_timing.hs_desc_failure_cache_time",
                 "circuit_timing.hs_desc_fetch_attempts",
                 "circuit_timing.hs_intro_rend_attempts",
+                "circuit_timing.hs_max_circuits_per_service",
+                "circuit_timing.hs_max_streams_per_circuit",
+                "circuit_timing.hs_strict_isolation",
+            ],
+        );
+
+        declare_exceptions(
+            None,
+            None,
+            FeatureDependent,
+            &[
+                // Testing-only HS client setting, deliberately not in the example.
+                "path_rules.hs_rendezvous_point",
             ],
         );
 
@@ -557,6 +812,11 @@ mod test {
                 // RPC-only settings
                 "rpc",
                 "rpc.rpc_listen",
+                "rpc.inherent_auth",
+                "rpc.cookie_path",
+                "rpc.allowed_peer_uids",
+                "rpc.token_file",
+                "rpc.token",
             ],
         );
 
@@ -571,6 +831,17 @@ mod test {
             ],
         );
 
+        declare_exceptions(
+            None,
+            Some(InNew),
+            FeatureDependent,
+            &[
+                // Metrics-only settings
+                "metrics",
+                "metrics.prometheus_listen",
+            ],
+        );
+
         declare_exceptions(
             None,
             Some(InNew),
@@ -1151,6 +1422,13 @@ example config file {which:?}, uncommented={uncommented:?}
                         TargetAddr::Inet("127.0.0.1:10080".parse().unwrap()),
                     ),
                 ));
+                b.proxy().proxy_ports().push(ProxyRule::new(
+                    ProxyPattern::one_port(443).unwrap(),
+                    ProxyAction::Forward(
+                        Encapsulation::HaProxy,
+                        TargetAddr::Inet("127.0.0.1:10443".parse().unwrap()),
+                    ),
+                ));
                 b.proxy().proxy_ports().push(ProxyRule::new(
                     ProxyPattern::one_port(22).unwrap(),
                     ProxyAction::DestroyCircuit,
@@ -1388,6 +1666,31 @@ example config file {which:?}, uncommented={uncommented:?}
         assert_eq!(&config.application, application);
     }
 
+    #[test]
+    fn onion_service_state_retention() {
+        let parse = |s: &str| -> Retention {
+            let cfg: ApplicationConfigBuilder = toml::from_str(s).unwrap();
+            cfg.build().unwrap().onion_service_state_retention
+        };
+        assert_eq!(
+            parse(""),
+            Retention::For(std::time::Duration::from_secs(30 * 86400))
+        );
+        assert_eq!(
+            parse(r#"onion_service_state_retention = "2 hours""#),
+            Retention::For(std::time::Duration::from_secs(7200))
+        );
+        assert_eq!(
+            parse(r#"onion_service_state_retention = "never""#),
+            Retention::Never
+        );
+        assert!(toml::from_str::<ApplicationConfigBuilder>(
+            r#"onion_service_state_retention = "sometimes""#
+        )
+        .is_err());
+        assert_eq!(String::from(Retention::Never), "never");
+    }
+
     #[test]
     fn articonfig_logging() {
         let config = ArtiConfig::default();
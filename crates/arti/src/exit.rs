@@ -2,12 +2,16 @@
 
 use crate::Result;
 
+use futures::future::{select_all, BoxFuture, FutureExt as _};
+
 /// Wait until a control-c notification is received, using an appropriate
 /// runtime mechanism.
 ///
 /// This function can have pretty kludgy side-effects: see
-/// documentation for `tokio::signal::ctrl_c` and `async_ctrlc` for
-/// caveats.  Notably, you can only call this once with async_std.
+/// documentation for `tokio::signal::ctrl_c` and `ctrlc` for
+/// caveats.
+///
+/// Each call waits for the next notification after it was made.
 #[cfg_attr(feature = "experimental-api", visibility::make(pub))]
 pub(crate) async fn wait_for_ctrl_c() -> Result<()> {
     #[cfg(feature = "tokio")]
@@ -16,7 +20,96 @@ pub(crate) async fn wait_for_ctrl_c() -> Result<()> {
     }
     #[cfg(all(feature = "async-std", not(feature = "tokio")))]
     {
-        async_ctrlc::CtrlC::new().unwrap().await;
+        next_ctrl_c().await?;
     }
     Ok(())
 }
+
+/// Wait for the next control-c notification, using the `ctrlc` crate.
+///
+/// `ctrlc` only lets us install a single handler for the whole process,
+/// so we install it the first time we're called,
+/// and have it wake up everybody who is waiting.
+#[cfg(all(feature = "async-std", not(feature = "tokio")))]
+async fn next_ctrl_c() -> Result<()> {
+    use futures::channel::oneshot;
+    use std::sync::{Mutex, OnceLock};
+
+    /// The tasks that are waiting for the next control-c.
+    static WAITERS: Mutex<Vec<oneshot::Sender<()>>> = Mutex::new(Vec::new());
+    /// The outcome of installing our handler.
+    static HANDLER: OnceLock<std::result::Result<(), String>> = OnceLock::new();
+
+    let (tx, rx) = oneshot::channel();
+    WAITERS.lock().expect("poisoned lock").push(tx);
+
+    HANDLER
+        .get_or_init(|| {
+            ctrlc::set_handler(|| {
+                for tx in WAITERS.lock().expect("poisoned lock").drain(..) {
+                    // The waiter may have given up; that's fine.
+                    let _ = tx.send(());
+                }
+            })
+            .map_err(|e| e.to_string())
+        })
+        .clone()
+        .map_err(|e| anyhow::anyhow!("Unable to install a control-c handler: {e}"))?;
+
+    // The sender is only ever dropped after sending.
+    let _ = rx.await;
+    Ok(())
+}
+
+/// Wait until we are asked to exit: by a control-c notification,
+/// by a `SIGTERM` signal (on Unix),
+/// or by the Windows service control manager (when running as a service).
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) async fn wait_for_exit_request() -> Result<()> {
+    #[allow(unused_mut)]
+    let mut requests: Vec<BoxFuture<'static, Result<()>>> = vec![wait_for_signal().boxed()];
+
+    #[cfg(windows)]
+    requests.push(
+        async {
+            crate::service::windows::stop_requested().await;
+            Ok(())
+        }
+        .boxed(),
+    );
+
+    select_all(requests).await.0
+}
+
+/// Wait until we are asked to exit again, after an earlier exit request:
+/// by a control-c notification, or by a `SIGTERM` signal (on Unix).
+///
+/// We use this to cut a graceful shutdown short.  Unlike
+/// [`wait_for_exit_request`], this ignores the Windows service control
+/// manager, which only asks us to stop once.
+pub(crate) async fn wait_for_another_exit_request() -> Result<()> {
+    wait_for_signal().await
+}
+
+/// Wait until we receive a control-c notification,
+/// or a `SIGTERM` signal (on Unix).
+async fn wait_for_signal() -> Result<()> {
+    #[allow(unused_mut)]
+    let mut requests: Vec<BoxFuture<'static, Result<()>>> = vec![wait_for_ctrl_c().boxed()];
+
+    #[cfg(target_family = "unix")]
+    {
+        use futures::StreamExt as _;
+        let mut sigterm = crate::process::sigterm_stream()?;
+        requests.push(
+            async move {
+                sigterm.next().await;
+                tracing::info!("Received SIGTERM");
+                Ok(())
+            }
+            .boxed(),
+        );
+    }
+
+    select_all(requests).await.0
+}
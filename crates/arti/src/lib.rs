@@ -45,6 +45,7 @@
 #![allow(clippy::print_stderr)]
 #![allow(clippy::print_stdout)]
 
+mod automap;
 pub mod cfg;
 pub mod logging;
 #[cfg(not(feature = "onion-service-service"))]
@@ -82,11 +83,17 @@ semipublic_mod! {
     #[cfg(feature = "dns-proxy")]
     mod dns;
     mod exit;
+    #[cfg(feature = "metrics")]
+    mod metrics;
     #[cfg(feature="onion-service-service")]
     mod onion_proxy;
     mod process;
     mod reload_cfg;
+    #[cfg(feature = "sandbox")]
+    mod sandbox;
+    mod service;
     mod socks;
+    mod torrc;
 }
 
 #[cfg(feature = "rpc")]
@@ -96,9 +103,11 @@ use std::ffi::OsString;
 use std::fmt::Write;
 use std::sync::Arc;
 
+pub use automap::{VirtualAddrNetwork, VirtualAddrNetworkError};
 pub use cfg::{
     ApplicationConfig, ApplicationConfigBuilder, ArtiCombinedConfig, ArtiConfig, ArtiConfigBuilder,
-    ProxyConfig, ProxyConfigBuilder, SystemConfig, SystemConfigBuilder, ARTI_EXAMPLE_CONFIG,
+    ProxyConfig, ProxyConfigBuilder, Retention, SystemConfig, SystemConfigBuilder,
+    ARTI_EXAMPLE_CONFIG,
 };
 pub use logging::{LoggingConfig, LoggingConfigBuilder};
 
@@ -106,6 +115,7 @@ use arti_client::config::default_config_files;
 use arti_client::{TorClient, TorClientConfig};
 use safelog::with_safe_logging_suppressed;
 use tor_config::{ConfigurationSources, Listen};
+use tor_error::warn_report;
 use tor_rtcompat::{BlockOn, Runtime};
 
 use anyhow::{Context, Error, Result};
@@ -206,13 +216,32 @@ async fn run<R: Runtime>(
             if path.exists() {
                 std::fs::remove_file(&path)?;
             }
+            let auth_policy =
+                rpc::make_auth_policy(arti_config.rpc(), client_config.fs_mistrust())?;
 
-            Some(path)
+            Some((path, auth_policy))
         } else {
             None
         }
     };
 
+    #[cfg(feature = "sandbox")]
+    let sandbox_paths = if arti_config.application().sandbox {
+        Some(sandbox::SandboxPaths::from_config(
+            &arti_config,
+            &client_config,
+            &config_sources,
+        )?)
+    } else {
+        None
+    };
+    #[cfg(not(feature = "sandbox"))]
+    if arti_config.application().sandbox {
+        return Err(anyhow::anyhow!(
+            "application.sandbox is set, but Arti was built without the sandbox feature"
+        ));
+    }
+
     let client_builder = TorClient::with_runtime(runtime.clone())
         .config(client_config)
         .bootstrap_behavior(OnDemand);
@@ -226,32 +255,23 @@ async fn run<R: Runtime>(
 
     #[cfg(feature = "onion-service-service")]
     {
-        let onion_services =
-            onion_proxy::ProxySet::launch_new(&client, arti_config.onion_services.clone())?;
+        let onion_services = onion_proxy::ProxySet::launch_new(
+            &client,
+            arti_config.onion_services.clone(),
+            arti_config.application().onion_service_state_retention,
+        )?;
         reconfigurable_modules.push(Arc::new(onion_services));
     }
 
-    // We weak references here to prevent the thread spawned by watch_for_config_changes from
-    // keeping these modules alive after this function exits.
-    //
-    // NOTE: reconfigurable_modules stores the only strong references to these modules,
-    // so we must keep the variable alive until the end of the function
-    let weak_modules = reconfigurable_modules.iter().map(Arc::downgrade).collect();
-    reload_cfg::watch_for_config_changes(
-        client.runtime(),
-        config_sources,
-        &arti_config,
-        weak_modules,
-    )?;
-
     #[cfg(all(feature = "rpc", feature = "tokio"))]
     let rpc_mgr = {
         // TODO RPC This code doesn't really belong here; it's just an example.
-        if let Some(listen_path) = rpc_path {
+        if let Some((listen_path, auth_policy)) = rpc_path {
             // TODO Conceivably this listener belongs on a renamed "proxy" list.
             Some(rpc::launch_rpc_listener(
                 &runtime,
                 listen_path,
+                auth_policy,
                 client.clone(),
             )?)
         } else {
@@ -260,20 +280,27 @@ async fn run<R: Runtime>(
     };
 
     let mut proxy: Vec<PinnedFuture<(Result<()>, &str)>> = Vec::new();
+    let mut socks_conns = None;
     if !socks_listen.is_empty() {
-        let runtime = runtime.clone();
-        let client = client.isolated_client();
-        proxy.push(Box::pin(async move {
-            let res = socks::run_socks_proxy(
-                runtime,
-                client,
-                socks_listen,
-                #[cfg(all(feature = "rpc", feature = "tokio"))]
-                rpc_mgr,
-            )
-            .await;
-            (res, "SOCKS")
-        }));
+        // If our SOCKS listeners came from the configuration (rather than
+        // from the command line), changes to the configuration rebind them.
+        let follow_config = socks_listen == arti_config.proxy().socks_listen;
+        let (handle, socks) = socks::launch_socks_proxy(
+            runtime.clone(),
+            client.isolated_client(),
+            socks_listen,
+            socks::SocksLimits::from_config(arti_config.proxy()),
+            socks::automap_network(arti_config.proxy()),
+            #[cfg(all(feature = "rpc", feature = "tokio"))]
+            rpc_mgr,
+        )
+        .await
+        .context("SOCKS proxy failure")?;
+        socks_conns = Some(handle.active_conns());
+        if follow_config {
+            reconfigurable_modules.push(Arc::new(handle));
+        }
+        proxy.push(Box::pin(async move { (socks.await, "SOCKS") }));
     }
 
     #[cfg(feature = "dns-proxy")]
@@ -299,26 +326,129 @@ async fn run<R: Runtime>(
         return Ok(());
     }
 
+    // We weak references here to prevent the thread spawned by watch_for_config_changes from
+    // keeping these modules alive after this function exits.
+    //
+    // NOTE: reconfigurable_modules stores the only strong references to these modules,
+    // so we must keep the variable alive until the end of the function
+    let weak_modules = reconfigurable_modules.iter().map(Arc::downgrade).collect();
+    reload_cfg::watch_for_config_changes(
+        client.runtime(),
+        config_sources,
+        &arti_config,
+        weak_modules,
+    )?;
+
+    #[cfg(feature = "metrics")]
+    {
+        let prometheus_listen = arti_config.metrics().prometheus_listen.clone();
+        if !prometheus_listen.is_empty() {
+            let runtime = runtime.clone();
+            let client = client.clone();
+            proxy.push(Box::pin(async move {
+                let res = metrics::run_metrics_exporter(runtime, client, prometheus_listen).await;
+                (res, "metrics")
+            }));
+        }
+    }
+
+    // Everything that we need to open or declare up front is open now.
+    #[cfg(feature = "sandbox")]
+    if let Some(paths) = sandbox_paths {
+        sandbox::enable_sandbox(&paths).context("Unable to enable sandbox")?;
+        info!("Sandbox enabled.");
+    }
+
+    service::spawn_watchdog(&runtime)?;
+    service::notify_ready();
+    service::notify_status("Bootstrapping");
+
     let proxy = futures::future::select_all(proxy).map(|(finished, _index, _others)| finished);
     futures::select!(
-        r = exit::wait_for_ctrl_c().fuse()
+        r = exit::wait_for_exit_request().fuse()
             => r.context("waiting for termination signal"),
         r = proxy.fuse()
             => r.0.context(format!("{} proxy failure", r.1)),
         r = async {
             client.bootstrap().await?;
             info!("Sufficiently bootstrapped; system SOCKS now functional.");
+            service::notify_status("Bootstrapped");
             futures::future::pending::<Result<()>>().await
         }.fuse()
             => r.context("bootstrap"),
     )?;
 
+    service::notify_stopping();
+
+    // We've been asked to shut down.  Our listeners closed when `proxy` was
+    // dropped, so give the connections we already have a chance to finish.
+    if let Some(conns) = socks_conns {
+        let n_open = conns.count();
+        if n_open > 0 {
+            let timeout = arti_config.application().shutdown_timeout;
+            info!(
+                "Waiting up to {} for {} open SOCKS connection(s) to finish.",
+                humantime::format_duration(timeout),
+                n_open
+            );
+            // If we're asked to exit again, stop waiting.
+            let interrupted = async {
+                match exit::wait_for_another_exit_request().await {
+                    Ok(()) => info!("Asked to exit again; not waiting any longer."),
+                    Err(e) => {
+                        // TODO: warn_report does not work on anyhow::Error.
+                        warn!(
+                            "Unable to wait for another termination signal: {}",
+                            tor_error::Report(e)
+                        );
+                        futures::future::pending::<()>().await;
+                    }
+                }
+            };
+            let n_open = futures::select!(
+                n_open = conns.drain(&runtime, timeout).fuse() => n_open,
+                () = interrupted.fuse() => conns.count(),
+            );
+            if n_open > 0 {
+                info!(
+                    "Closing {} SOCKS connection(s) that are still open.",
+                    n_open
+                );
+            }
+        }
+    }
+
+    // Other tasks may still hold a handle to the client, so don't rely on
+    // our state being saved when it's dropped.
+    if let Err(e) = client.flush_state() {
+        warn_report!(e, "Unable to save persistent state at exit");
+    }
+
     // The modules can be dropped now, because we are exiting.
     drop(reconfigurable_modules);
 
     Ok(())
 }
 
+/// Read the C Tor `torrc` file at `path`, and translate it into an Arti
+/// configuration document.
+///
+/// Warns about every option that could not be translated exactly.
+fn read_torrc(path: &std::path::Path, mistrust: &fs_mistrust::Mistrust) -> Result<String> {
+    mistrust
+        .verifier()
+        .permit_readable()
+        .check(path)
+        .with_context(|| format!("check permissions on torrc {}", path.display()))?;
+    let torrc =
+        std::fs::read_to_string(path).with_context(|| format!("read torrc {}", path.display()))?;
+    let translated = torrc::translate(&torrc);
+    for problem in &translated.problems {
+        warn!("{}", problem);
+    }
+    Ok(translated.to_toml())
+}
+
 /// Inner function, to handle a set of CLI arguments and return a single
 /// `Result<()>` for convenient handling.
 ///
@@ -396,6 +526,15 @@ where
                     .global(true)
                     .help("Override config file parameters, using TOML-like syntax."),
             )
+            .arg(
+                Arg::new("torrc")
+                    .long("torrc")
+                    .action(ArgAction::Set)
+                    .value_name("FILE")
+                    .value_parser(value_parser!(OsString))
+                    .global(true)
+                    .help("Translate a C Tor torrc file, and apply it after the config file(s)."),
+            )
             .arg(
                 Arg::new("loglevel")
                     .short('l')
@@ -435,6 +574,15 @@ where
             .subcommand_required(true)
             .arg_required_else_help(true);
 
+    #[cfg(windows)]
+    let clap_app = clap_app.arg(
+        Arg::new("windows-service")
+            .long("windows-service")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .help("Run as a Windows service. (Only the service control manager should use this.)"),
+    );
+
     // When adding a subcommand, it may be necessary to add an entry in
     // `maint/check-cli-help`, to the function `help_arg`.
 
@@ -473,6 +621,12 @@ where
         }
     }
 
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "experimental-api")] {
+            let clap_app = subcommands::storage::StorageSubcommands::augment_subcommands(clap_app);
+        }
+    }
+
     // Relay subcommand
     cfg_if::cfg_if! {
         if #[cfg(feature = "relay")] {
@@ -534,6 +688,15 @@ where
                     .unwrap_or_default(),
                 override_options,
             )?;
+            if let Some(torrc) = matches.get_one::<OsString>("torrc") {
+                cfg_sources.push_source(
+                    tor_config::ConfigurationSource::from_verbatim(read_torrc(
+                        torrc.as_ref(),
+                        &cfg_mistrust,
+                    )?),
+                    tor_config::sources::MustRead::MustRead,
+                );
+            }
             cfg_sources.set_mistrust(cfg_mistrust);
             cfg_sources
         };
@@ -590,6 +753,14 @@ where
 
         process::use_max_file_limit(&config);
 
+        let _pid_file = match &config.application().pid_file {
+            Some(path) => Some(service::PidFile::create(
+                &path.path()?,
+                client_config.fs_mistrust(),
+            )?),
+            None => None,
+        };
+
         let rt_copy = runtime.clone();
         rt_copy.block_on(run(
             runtime,
@@ -620,6 +791,15 @@ where
         }
     }
 
+    // Check for the optional "storage" subcommand.
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "experimental-api")] {
+            if let Some(storage_matches) = matches.subcommand_matches("storage") {
+                return subcommands::storage::run(storage_matches, &client_config);
+            }
+        }
+    }
+
     // Check for the optional "relay" subcommand.
     cfg_if::cfg_if! {
         if #[cfg(feature = "relay")] {
@@ -659,6 +839,15 @@ where
 /// function. Please reach out to the Arti developers, so we can work together
 /// to get you the stable API you need.
 pub fn main() {
+    #[cfg(windows)]
+    if service::windows::requested() {
+        // The service control manager will call `main_main` for us.
+        if let Err(e) = service::windows::run() {
+            with_safe_logging_suppressed(|| tor_error::report_and_exit(e));
+        }
+        return;
+    }
+
     match main_main(std::env::args_os()) {
         Ok(()) => {}
         Err(e) => {
@@ -4,7 +4,7 @@ use anyhow::{anyhow, Context, Result};
 use derive_builder::Builder;
 use fs_mistrust::Mistrust;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use tor_config::impl_standard_builder;
 use tor_config::{define_list_builder_accessors, define_list_builder_helper};
@@ -16,8 +16,11 @@ use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::{filter::Targets, fmt, registry, Layer};
 
+mod sensitive;
 mod time;
 
+use sensitive::SensitiveFormat;
+
 /// Structure to hold our logging configuration options
 #[derive(Debug, Clone, Builder, Eq, PartialEq)]
 #[non_exhaustive] // TODO(nickm) remove public elements when I revise this.
@@ -64,6 +67,25 @@ pub struct LoggingConfig {
     #[builder(default)]
     log_sensitive_information: bool,
 
+    /// Filtering directives that select messages for which we should disable
+    /// safe logging, even if `log_sensitive_information` is false.
+    ///
+    /// This uses the same syntax as `console`.  A message is logged with
+    /// sensitive information if these directives would enable it.
+    ///
+    /// This is intended for debugging a particular module without exposing
+    /// sensitive information from all of them.  It applies to the console and
+    /// to logfiles, but not to journald.
+    ///
+    /// Example: "tor_proto::circuit=debug,arti::socks"
+    #[builder(
+        setter(into),
+        field(
+            build = r#"tor_config::resolve_option(&self.log_sensitive_information_targets, || None)"#
+        )
+    )]
+    log_sensitive_information_targets: Option<String>,
+
     /// An approximate granularity with which log times should be displayed.
     ///
     /// This value controls every log time that arti outputs; it doesn't have any
@@ -118,6 +140,31 @@ pub struct LogfileConfig {
 
 impl_standard_builder! { LogfileConfig: !Default }
 
+impl LogfileConfig {
+    /// Return the directory in which this logfile is written,
+    /// and the name of the file within that directory.
+    fn directory_and_name(&self) -> Result<(PathBuf, PathBuf)> {
+        let path = self.path.path()?;
+        let directory = path.parent().unwrap_or_else(|| Path::new("."));
+        let fname = path
+            .file_name()
+            .ok_or_else(|| anyhow!("No path for log file"))?;
+        Ok((directory.to_owned(), fname.into()))
+    }
+}
+
+/// Return every directory in which the logfiles configured in `config` are written.
+///
+/// (When log files are rotated, new files are created in these directories.)
+#[cfg(feature = "sandbox")]
+pub(crate) fn logfile_directories(config: &LoggingConfig) -> Result<Vec<PathBuf>> {
+    config
+        .files
+        .iter()
+        .map(|file| Ok(file.directory_and_name()?.0))
+        .collect()
+}
+
 /// How often to rotate a log file
 #[derive(Debug, Default, Clone, Serialize, Deserialize, Copy, Eq, PartialEq)]
 #[non_exhaustive]
@@ -155,6 +202,7 @@ where
     S: Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
 {
     let timer = time::new_formatter(config.time_granularity);
+    let unredacted = unredacted_targets(config)?;
     let filter = cli
         .map(|s| filt_from_str_verbose(s, "--log-level command line parameter"))
         .or_else(|| filt_from_opt_str(&config.console, "logging.console").transpose())
@@ -163,7 +211,19 @@ where
     // feature: we cannot be certain that the console really is volatile. Even
     // if isatty() returns true on the console, we can't be sure that the
     // terminal isn't saving backlog to disk or something like that.
-    Ok(fmt::Layer::default().with_timer(timer).with_filter(filter))
+    Ok(fmt::Layer::default()
+        .with_timer(timer)
+        .map_event_format(|f| SensitiveFormat::new(f, unredacted))
+        .with_filter(filter))
+}
+
+/// Return the filter selecting messages for which safe logging should be
+/// disabled, if there is one.
+fn unredacted_targets(config: &LoggingConfig) -> Result<Option<Targets>> {
+    filt_from_opt_str(
+        &config.log_sensitive_information_targets,
+        "logging.log_sensitive_information_targets",
+    )
 }
 
 /// Try to construct a tracing [`Layer`] for logging to journald, if one is
@@ -189,6 +249,7 @@ where
 fn logfile_layer<S>(
     config: &LogfileConfig,
     granularity: std::time::Duration,
+    unredacted: Option<Targets>,
     mistrust: &Mistrust,
 ) -> Result<(impl Layer<S> + Send + Sync + Sized, WorkerGuard)>
 where
@@ -206,13 +267,8 @@ where
         LogRotation::Hourly => Rotation::HOURLY,
         _ => Rotation::NEVER,
     };
-    let path = config.path.path()?;
-    let directory = path.parent().unwrap_or_else(|| Path::new("."));
-    mistrust.make_directory(directory)?;
-    let fname = path
-        .file_name()
-        .ok_or_else(|| anyhow!("No path for log file"))
-        .map(Path::new)?;
+    let (directory, fname) = config.directory_and_name()?;
+    mistrust.make_directory(&directory)?;
 
     let appender = RollingFileAppender::new(rotation, directory, fname);
     let (nonblocking, guard) = non_blocking(appender);
@@ -220,6 +276,7 @@ where
         .with_ansi(false)
         .with_writer(nonblocking)
         .with_timer(timer)
+        .map_event_format(|f| SensitiveFormat::new(f, unredacted))
         .with_filter(filter);
     Ok((layer, guard))
 }
@@ -242,7 +299,13 @@ where
         return Ok((None, guards));
     }
 
-    let (layer, guard) = logfile_layer(&config.files[0], config.time_granularity, mistrust)?;
+    let unredacted = unredacted_targets(config)?;
+    let (layer, guard) = logfile_layer(
+        &config.files[0],
+        config.time_granularity,
+        unredacted.clone(),
+        mistrust,
+    )?;
     guards.push(guard);
 
     // We have to use a dyn pointer here so we can build up linked list of
@@ -250,7 +313,12 @@ where
     let mut layer: Box<dyn Layer<S> + Send + Sync + 'static> = Box::new(layer);
 
     for logfile in &config.files[1..] {
-        let (new_layer, guard) = logfile_layer(logfile, config.time_granularity, mistrust)?;
+        let (new_layer, guard) = logfile_layer(
+            logfile,
+            config.time_granularity,
+            unredacted.clone(),
+            mistrust,
+        )?;
         layer = Box::new(layer.and_then(new_layer));
         guards.push(guard);
     }
@@ -0,0 +1,133 @@
+//! Support for disabling safe logging for selected log targets only.
+//
+// Sensitive values (addresses, hostnames, onion services, and so on) are
+// wrapped in `safelog::Sensitive` or `safelog::Redacted` by the code that logs
+// them.  Those types check, at the time they are formatted, whether safe
+// logging has been suppressed.  So all we need to do here is format the
+// events for the selected targets with safe logging suppressed.
+
+use tracing::{Event, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// An event formatter that wraps another, and displays sensitive information
+/// for events whose target and level match a given filter.
+///
+/// Events that don't match are formatted normally,
+/// with safe logging (if enabled) in effect.
+///
+/// Note that this only affects the fields of the event itself:
+/// fields recorded on enclosing spans are formatted when the span is created.
+#[derive(Debug, Clone)]
+pub(super) struct SensitiveFormat<E> {
+    /// The formatter we're wrapping.
+    inner: E,
+    /// Which events should be displayed with safe logging suppressed.
+    ///
+    /// If this is `None`, we never suppress safe logging.
+    unredacted: Option<Targets>,
+}
+
+impl<E> SensitiveFormat<E> {
+    /// Wrap `inner` so that events matching `unredacted` are logged with safe logging
+    /// suppressed.
+    pub(super) fn new(inner: E, unredacted: Option<Targets>) -> Self {
+        SensitiveFormat { inner, unredacted }
+    }
+
+    /// Return true if `event` should be displayed with safe logging suppressed.
+    fn is_unredacted(&self, event: &Event<'_>) -> bool {
+        let meta = event.metadata();
+        self.unredacted
+            .as_ref()
+            .is_some_and(|t| t.would_enable(meta.target(), meta.level()))
+    }
+}
+
+impl<S, N, E> FormatEvent<S, N> for SensitiveFormat<E>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    E: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        if self.is_unredacted(event) {
+            safelog::with_safe_logging_suppressed(|| self.inner.format_event(ctx, writer, event))
+        } else {
+            self.inner.format_event(ctx, writer, event)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::fmt::MakeWriter;
+    use tracing_subscriber::prelude::*;
+
+    /// A writer that appends everything to a shared buffer.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Capture {
+        type Writer = Capture;
+        fn make_writer(&'a self) -> Capture {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn unredact_selected_targets() {
+        let capture = Capture::default();
+        let unredacted = Targets::from_str("unredacted_target=info").unwrap();
+        let layer = tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(capture.clone())
+            .map_event_format(|f| SensitiveFormat::new(f, Some(unredacted)));
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        let addr = safelog::sensitive("192.0.2.7");
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: "unredacted_target", "first {}", addr);
+            tracing::info!(target: "other_target", "second {}", addr);
+        });
+
+        let output = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("first 192.0.2.7"));
+        assert!(lines[1].ends_with("second [scrubbed]"));
+    }
+}
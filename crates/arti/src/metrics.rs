@@ -0,0 +1,626 @@
+//! Export Arti's internal metrics for Prometheus (or any other system
+//! that understands the Prometheus text exposition format).
+//!
+//! When `metrics.prometheus_listen` is configured, we listen on the given
+//! addresses and answer every HTTP request with a snapshot of our metrics.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use derive_builder::Builder;
+use futures::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use futures::stream::StreamExt as _;
+use futures::task::SpawnExt as _;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info};
+
+use arti_client::TorClient;
+use tor_circmgr::{CircBuildTelemetry, CircPurpose, LatencyHistogram};
+use tor_config::{impl_standard_builder, ConfigBuildError, Listen};
+use tor_error::warn_report;
+use tor_rtcompat::{Runtime, SleepProviderExt as _, TcpListener as _};
+
+/// Configuration for Arti's metrics exporter.
+#[derive(Debug, Clone, Builder, Eq, PartialEq)]
+#[builder(build_fn(error = "ConfigBuildError"))]
+#[builder(derive(Debug, Serialize, Deserialize))]
+#[non_exhaustive]
+pub struct MetricsConfig {
+    /// Addresses on which to serve our metrics, in the Prometheus text format.
+    ///
+    /// The default is not to serve metrics at all.
+    ///
+    /// Anybody who can connect to these addresses can learn about your
+    /// Tor usage, so don't expose them to untrusted parties.
+    #[builder(default)]
+    pub(crate) prometheus_listen: Listen,
+}
+impl_standard_builder! { MetricsConfig }
+
+/// A monotonically increasing counter.
+#[derive(Debug)]
+struct Counter(AtomicU64);
+
+impl Counter {
+    /// Return a new counter, set to zero.
+    const fn new() -> Self {
+        Counter(AtomicU64::new(0))
+    }
+
+    /// Increment this counter by one.
+    fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Return the current value of this counter.
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The set of metrics that we export.
+#[derive(Debug)]
+struct Metrics {
+    /// Number of SOCKS connections that we handled successfully.
+    socks_succeeded: Counter,
+    /// Number of SOCKS connections that ended with an error.
+    socks_failed: Counter,
+    /// Our bootstrap progress, in thousandths.
+    bootstrap_permille: AtomicU64,
+    /// Whether we are bootstrapped enough to handle user traffic.
+    bootstrap_ready: AtomicBool,
+    /// The traffic that our channels have carried.
+    channel_totals: Mutex<ChannelTotals>,
+}
+
+/// The global set of metrics for this process.
+static METRICS: Metrics = Metrics {
+    socks_succeeded: Counter::new(),
+    socks_failed: Counter::new(),
+    bootstrap_permille: AtomicU64::new(0),
+    bootstrap_ready: AtomicBool::new(false),
+    channel_totals: Mutex::new(ChannelTotals::new()),
+};
+
+/// Running totals of the traffic on all of our channels,
+/// including the ones that have since closed.
+///
+/// We only report these totals, rather than the traffic on each channel,
+/// so that the number of time series that we export stays bounded.
+#[derive(Debug)]
+struct ChannelTotals {
+    /// The traffic that each open channel had carried when we last looked,
+    /// as (sent, received), by channel identifier.
+    last_seen: BTreeMap<String, (u64, u64)>,
+    /// The total number of bytes of cells that we have sent.
+    sent: u64,
+    /// The total number of bytes of cells that we have received.
+    received: u64,
+}
+
+impl ChannelTotals {
+    /// Return a new set of totals, with no traffic.
+    const fn new() -> Self {
+        ChannelTotals {
+            last_seen: BTreeMap::new(),
+            sent: 0,
+            received: 0,
+        }
+    }
+
+    /// Add the traffic that `channels` have carried since we last looked at
+    /// them to our totals, and return the new totals as (sent, received).
+    ///
+    /// `channels` should be all of the channels that are currently open.
+    fn update(&mut self, channels: &[ChannelMetrics]) -> (u64, u64) {
+        let mut last_seen = BTreeMap::new();
+        for chan in channels {
+            let (sent, received) = self.last_seen.get(&chan.id).copied().unwrap_or_default();
+            self.sent += chan.sent.saturating_sub(sent);
+            self.received += chan.received.saturating_sub(received);
+            last_seen.insert(chan.id.clone(), (chan.sent, chan.received));
+        }
+        // This forgets the channels that have closed.  (We miss whatever they
+        // carried between the last time we looked at them and their closing.)
+        self.last_seen = last_seen;
+        (self.sent, self.received)
+    }
+}
+
+/// Record that we finished handling a SOCKS connection.
+pub(crate) fn note_socks_conn(succeeded: bool) {
+    if succeeded {
+        METRICS.socks_succeeded.inc();
+    } else {
+        METRICS.socks_failed.inc();
+    }
+}
+
+/// Metrics that we take from a [`TorClient`] each time we are asked for them.
+#[derive(Debug, Default)]
+struct ClientMetrics {
+    /// Information about the circuits that the client has built.
+    circuits: CircBuildTelemetry,
+    /// Traffic on each of the client's open channels.
+    channels: Vec<ChannelMetrics>,
+    /// How long it took to fetch each onion service descriptor, if we can
+    /// connect to onion services.
+    hs_desc_fetch: Option<LatencyHistogram>,
+}
+
+/// The traffic that an open channel has carried.
+#[derive(Debug)]
+struct ChannelMetrics {
+    /// The channel's (process-unique) identifier.
+    id: String,
+    /// The number of bytes of cells that we have sent on the channel.
+    sent: u64,
+    /// The number of bytes of cells that we have received on the channel.
+    received: u64,
+}
+
+impl ClientMetrics {
+    /// Take the current metrics from `tor_client`.
+    fn collect<R: Runtime>(tor_client: &TorClient<R>) -> Self {
+        let circuits = tor_client.circmgr().build_telemetry();
+
+        let channels = match tor_client.chanmgr().list_channels() {
+            Ok(channels) => channels
+                .iter()
+                .map(|entry| ChannelMetrics {
+                    id: entry.channel.unique_id().to_string(),
+                    sent: entry.channel.bytes_sent(),
+                    received: entry.channel.bytes_received(),
+                })
+                .collect(),
+            Err(e) => {
+                warn_report!(e, "Couldn't list channels for metrics");
+                Vec::new()
+            }
+        };
+
+        #[cfg(feature = "onion-service-client")]
+        let hs_desc_fetch = match tor_client.onion_service_descriptor_fetch_times() {
+            Ok(times) => Some(times),
+            Err(e) => {
+                warn_report!(e, "Couldn't get onion service metrics");
+                None
+            }
+        };
+        #[cfg(not(feature = "onion-service-client"))]
+        let hs_desc_fetch = None;
+
+        ClientMetrics {
+            circuits,
+            channels,
+            hs_desc_fetch,
+        }
+    }
+
+    /// Write these metrics into `out`, in the Prometheus text exposition format.
+    ///
+    /// `channel_bytes` is the total traffic on our channels, as (sent, received).
+    fn render_to(&self, out: &mut String, channel_bytes: (u64, u64)) -> std::fmt::Result {
+        let mut outcomes = self.circuits.outcomes_by_purpose().collect::<Vec<_>>();
+        outcomes.sort_by_key(|(purpose, _)| purpose_label(*purpose));
+        writeln!(
+            out,
+            "# HELP arti_circuits_built_total Circuits we tried to build, by purpose and outcome."
+        )?;
+        writeln!(out, "# TYPE arti_circuits_built_total counter")?;
+        for (purpose, outcome) in outcomes {
+            let purpose = purpose_label(purpose);
+            writeln!(
+                out,
+                "arti_circuits_built_total{{purpose=\"{purpose}\",status=\"succeeded\"}} {}",
+                outcome.succeeded()
+            )?;
+            writeln!(
+                out,
+                "arti_circuits_built_total{{purpose=\"{purpose}\",status=\"failed\"}} {}",
+                outcome.failed()
+            )?;
+        }
+
+        write_histogram(
+            out,
+            "arti_circuit_build_seconds",
+            "Time taken to build each circuit.",
+            self.circuits.total(),
+        )?;
+
+        let (sent, received) = channel_bytes;
+        writeln!(
+            out,
+            "# HELP arti_channel_bytes_total Bytes of cells carried by all our channels."
+        )?;
+        writeln!(out, "# TYPE arti_channel_bytes_total counter")?;
+        writeln!(out, "arti_channel_bytes_total{{direction=\"sent\"}} {sent}")?;
+        writeln!(
+            out,
+            "arti_channel_bytes_total{{direction=\"received\"}} {received}"
+        )?;
+
+        if let Some(hs_desc_fetch) = &self.hs_desc_fetch {
+            write_histogram(
+                out,
+                "arti_hs_descriptor_fetch_seconds",
+                "Time taken to fetch each onion service descriptor.",
+                hs_desc_fetch,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Return the label that we use for circuits built for `purpose`.
+fn purpose_label(purpose: Option<CircPurpose>) -> &'static str {
+    match purpose {
+        Some(CircPurpose::Exit) => "exit",
+        Some(CircPurpose::Directory) => "directory",
+        Some(CircPurpose::HsDir) => "hs_dir",
+        Some(CircPurpose::HsIntro) => "hs_intro",
+        Some(CircPurpose::HsRendezvous) => "hs_rendezvous",
+        Some(CircPurpose::Measurement) => "measurement",
+        Some(_) => "other",
+        // Onion service circuits only get a purpose when they are handed out.
+        None => "onion_service",
+    }
+}
+
+/// Write `histogram` into `out` as a Prometheus histogram called `name`.
+fn write_histogram(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    histogram: &LatencyHistogram,
+) -> std::fmt::Result {
+    writeln!(out, "# HELP {name} {help}")?;
+    writeln!(out, "# TYPE {name} histogram")?;
+    // Prometheus buckets are cumulative; ours aren't.
+    let mut cumulative = 0;
+    for (bound, count) in histogram.buckets() {
+        cumulative += count;
+        match bound {
+            Some(bound) => writeln!(
+                out,
+                "{name}_bucket{{le=\"{}\"}} {cumulative}",
+                bound.as_secs_f64()
+            )?,
+            None => writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {cumulative}")?,
+        }
+    }
+    writeln!(out, "{name}_sum {}", histogram.sum().as_secs_f64())?;
+    writeln!(out, "{name}_count {}", histogram.count())
+}
+
+impl Metrics {
+    /// Render these metrics, and those in `client`, in the Prometheus text
+    /// exposition format.
+    fn render(&self, client: &ClientMetrics) -> String {
+        let channel_bytes = self
+            .channel_totals
+            .lock()
+            .expect("poisoned lock")
+            .update(&client.channels);
+        let mut out = String::new();
+        // Writing to a String can't fail.
+        let _ = self.render_to(&mut out);
+        let _ = client.render_to(&mut out, channel_bytes);
+        out
+    }
+
+    /// Helper for `render`: write our metrics into `out`.
+    fn render_to(&self, out: &mut String) -> std::fmt::Result {
+        writeln!(
+            out,
+            "# HELP arti_socks_connections_total SOCKS connections handled, by outcome."
+        )?;
+        writeln!(out, "# TYPE arti_socks_connections_total counter")?;
+        writeln!(
+            out,
+            "arti_socks_connections_total{{status=\"succeeded\"}} {}",
+            self.socks_succeeded.get()
+        )?;
+        writeln!(
+            out,
+            "arti_socks_connections_total{{status=\"failed\"}} {}",
+            self.socks_failed.get()
+        )?;
+
+        let permille = self.bootstrap_permille.load(Ordering::Relaxed);
+        writeln!(
+            out,
+            "# HELP arti_bootstrap_progress Fraction of bootstrapping that is complete."
+        )?;
+        writeln!(out, "# TYPE arti_bootstrap_progress gauge")?;
+        writeln!(
+            out,
+            "arti_bootstrap_progress {}.{:03}",
+            permille / 1000,
+            permille % 1000
+        )?;
+
+        let ready = self.bootstrap_ready.load(Ordering::Relaxed);
+        writeln!(
+            out,
+            "# HELP arti_bootstrap_ready Whether we can handle user traffic."
+        )?;
+        writeln!(out, "# TYPE arti_bootstrap_ready gauge")?;
+        writeln!(out, "arti_bootstrap_ready {}", u8::from(ready))?;
+        Ok(())
+    }
+}
+
+/// The largest HTTP request header that we'll read before answering.
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// How long we give a client to send its request, and then to read our answer.
+///
+/// This stops slow (or malicious) clients from holding connections open
+/// indefinitely.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Launch a metrics exporter listening on `listen`, and run indefinitely.
+///
+/// Also keeps our bootstrap metrics up-to-date, based on `tor_client`.
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) async fn run_metrics_exporter<R: Runtime>(
+    runtime: R,
+    tor_client: TorClient<R>,
+    listen: Listen,
+) -> Result<()> {
+    let mut listeners = Vec::new();
+    for addrgroup in listen
+        .ip_addrs()
+        .map_err(|e| anyhow!("Invalid listen spec for metrics: {e}"))?
+    {
+        for addr in addrgroup {
+            let listener = runtime
+                .listen(&addr)
+                .await
+                .with_context(|| format!("Can't listen for metrics on {addr}"))?;
+            info!("Serving metrics on {:?}.", addr);
+            listeners.push(listener);
+        }
+    }
+    if listeners.is_empty() {
+        error!("Couldn't open any metrics listeners.");
+        return Err(anyhow!("Couldn't open metrics listeners"));
+    }
+
+    let mut events = tor_client.bootstrap_events();
+    runtime.spawn(async move {
+        while let Some(status) = events.next().await {
+            // as_frac() is in [0,1], so this can't overflow.
+            let permille = (status.as_frac() * 1000.0) as u64;
+            METRICS
+                .bootstrap_permille
+                .store(permille.min(1000), Ordering::Relaxed);
+            METRICS
+                .bootstrap_ready
+                .store(status.ready_for_traffic(), Ordering::Relaxed);
+        }
+    })?;
+
+    let mut incoming = futures::stream::select_all(listeners.into_iter().map(|l| l.incoming()));
+    while let Some(stream) = incoming.next().await {
+        let (stream, _addr) = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                warn_report!(e, "Incoming metrics connection failed");
+                continue;
+            }
+        };
+        let tor_client = tor_client.clone();
+        runtime.spawn(async move {
+            let render = || METRICS.render(&ClientMetrics::collect(&tor_client));
+            if let Err(e) = serve_metrics(tor_client.runtime(), stream, render).await {
+                debug!("Error while serving metrics: {e}");
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Answer a single HTTP request on `stream` with the metrics from `render`.
+///
+/// Gives up, with an error, if the client takes longer than [`REQUEST_TIMEOUT`]
+/// to send its request, or then to read our answer.
+async fn serve_metrics<R, S, F>(runtime: &R, mut stream: S, render: F) -> std::io::Result<()>
+where
+    R: Runtime,
+    S: futures::io::AsyncRead + futures::io::AsyncWrite + Unpin,
+    F: FnOnce() -> String,
+{
+    let got_request = runtime
+        .timeout(REQUEST_TIMEOUT, read_request_headers(&mut stream))
+        .await??;
+    if !got_request {
+        return Ok(());
+    }
+
+    let body = render();
+    let response = format!(
+        "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    runtime
+        .timeout(REQUEST_TIMEOUT, async {
+            stream.write_all(response.as_bytes()).await?;
+            stream.flush().await?;
+            stream.close().await
+        })
+        .await?
+}
+
+/// Read an HTTP request's headers from `stream`.
+///
+/// We don't care what the request is: we only read until the end of the
+/// headers.  Return false if the stream closed first, or if the headers were
+/// too long.
+async fn read_request_headers<S>(stream: &mut S) -> std::io::Result<bool>
+where
+    S: futures::io::AsyncRead + Unpin,
+{
+    let mut buf = Vec::new();
+    let mut chunk = [0_u8; 512];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 || buf.len() + n > MAX_REQUEST_LEN {
+            return Ok(false);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    use futures::io::{AsyncReadExt as _, AsyncWriteExt as _};
+    use tor_rtmock::io::stream_pair;
+    use tor_rtmock::MockRuntime;
+
+    #[test]
+    fn render() {
+        let m = Metrics {
+            socks_succeeded: Counter::new(),
+            socks_failed: Counter::new(),
+            bootstrap_permille: AtomicU64::new(425),
+            bootstrap_ready: AtomicBool::new(false),
+            channel_totals: Mutex::new(ChannelTotals::new()),
+        };
+        m.socks_succeeded.inc();
+        m.socks_succeeded.inc();
+        m.socks_failed.inc();
+
+        let mut hs_desc_fetch = LatencyHistogram::default();
+        hs_desc_fetch.record(Duration::from_millis(30));
+        hs_desc_fetch.record(Duration::from_millis(70));
+        hs_desc_fetch.record(Duration::from_secs(100));
+        let client = ClientMetrics {
+            channels: vec![ChannelMetrics {
+                id: "Chan 3".into(),
+                sent: 5140,
+                received: 1028,
+            }],
+            hs_desc_fetch: Some(hs_desc_fetch),
+            ..Default::default()
+        };
+
+        let text = m.render(&client);
+        assert!(text.contains("arti_socks_connections_total{status=\"succeeded\"} 2\n"));
+        assert!(text.contains("arti_socks_connections_total{status=\"failed\"} 1\n"));
+        assert!(text.contains("arti_bootstrap_progress 0.425\n"));
+        assert!(text.contains("arti_bootstrap_ready 0\n"));
+        assert!(text.contains("arti_circuit_build_seconds_count 0\n"));
+        assert!(text.contains("arti_channel_bytes_total{direction=\"sent\"} 5140\n"));
+        assert!(text.contains("arti_channel_bytes_total{direction=\"received\"} 1028\n"));
+        assert!(text.contains("arti_hs_descriptor_fetch_seconds_bucket{le=\"0.05\"} 1\n"));
+        assert!(text.contains("arti_hs_descriptor_fetch_seconds_bucket{le=\"0.1\"} 2\n"));
+        assert!(text.contains("arti_hs_descriptor_fetch_seconds_bucket{le=\"51.2\"} 2\n"));
+        assert!(text.contains("arti_hs_descriptor_fetch_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("arti_hs_descriptor_fetch_seconds_sum 100.1\n"));
+        assert!(text.contains("arti_hs_descriptor_fetch_seconds_count 3\n"));
+        for line in text.lines() {
+            assert!(line.starts_with("# ") || line.starts_with("arti_"));
+        }
+    }
+
+    #[test]
+    fn channel_totals() {
+        let chan = |id: &str, sent, received| ChannelMetrics {
+            id: id.into(),
+            sent,
+            received,
+        };
+        let mut totals = ChannelTotals::new();
+        assert_eq!(totals.update(&[]), (0, 0));
+        assert_eq!(
+            totals.update(&[chan("Chan 1", 100, 10), chan("Chan 2", 50, 5)]),
+            (150, 15)
+        );
+        assert_eq!(
+            totals.update(&[chan("Chan 1", 120, 12), chan("Chan 2", 50, 5)]),
+            (170, 17)
+        );
+        // Chan 1 closes, and Chan 3 opens: the totals keep what Chan 1 carried.
+        assert_eq!(
+            totals.update(&[chan("Chan 2", 60, 6), chan("Chan 3", 7, 1)]),
+            (187, 24)
+        );
+        assert_eq!(totals.last_seen.len(), 2);
+    }
+
+    #[test]
+    fn purposes() {
+        assert_eq!(purpose_label(Some(CircPurpose::Exit)), "exit");
+        assert_eq!(purpose_label(None), "onion_service");
+    }
+
+    #[test]
+    fn serve() {
+        MockRuntime::test_with_various(|rt| async move {
+            let (mut client, server) = stream_pair();
+            let server = rt.spawn_join("serve", {
+                let rt = rt.clone();
+                async move { serve_metrics(&rt, server, || "hello".into()).await }
+            });
+
+            client
+                .write_all(b"GET /metrics HTTP/1.0\r\n\r\n")
+                .await
+                .unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).await.unwrap();
+            assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+            assert!(response.ends_with("\r\n\r\nhello"));
+            server.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn slow_request() {
+        MockRuntime::test_with_various(|rt| async move {
+            let (mut client, server) = stream_pair();
+            let server = rt.spawn_join("serve", {
+                let rt = rt.clone();
+                async move { serve_metrics(&rt, server, || "hello".into()).await }
+            });
+
+            // Send part of a request, and then nothing more.
+            client
+                .write_all(b"GET /metrics HTTP/1.0\r\n")
+                .await
+                .unwrap();
+            rt.advance_by(REQUEST_TIMEOUT - Duration::from_secs(1))
+                .await;
+            client.write_all(b"Host: localhost\r\n").await.unwrap();
+            rt.advance_by(Duration::from_secs(2)).await;
+
+            let err = server.await.unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        });
+    }
+}
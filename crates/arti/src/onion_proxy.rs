@@ -17,6 +17,9 @@ use tor_hsservice::{HsNickname, RunningOnionService};
 use tor_rtcompat::Runtime;
 use tracing::debug;
 
+use crate::cfg::Retention;
+use crate::reload_cfg::ReconfigureReport;
+
 /// Configuration for running an onion service from `arti`.
 ///
 /// This onion service will forward incoming connections to one or more local
@@ -248,19 +251,52 @@ pub(crate) struct ProxySet<R: Runtime> {
 
 impl<R: Runtime> ProxySet<R> {
     /// Create and launch a set of onion service proxies.
+    ///
+    /// The state of services that are not in `config_list` is deleted
+    /// according to `state_retention`.
     pub(crate) fn launch_new(
         client: &arti_client::TorClient<R>,
         config_list: OnionServiceProxyConfigMap,
+        state_retention: Retention,
     ) -> anyhow::Result<Self> {
         let proxies: BTreeMap<_, _> = config_list
             .into_iter()
             .map(|(nickname, cfg)| Ok((nickname, Proxy::launch_new(client, cfg)?)))
             .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
 
-        Ok(Self {
+        let set = Self {
             client: client.clone(),
             proxies: Mutex::new(proxies),
-        })
+        };
+        set.expire_unused_state(
+            set.proxies.lock().expect("lock poisoned").keys(),
+            state_retention,
+        );
+
+        Ok(set)
+    }
+
+    /// Delete the state of services that have not been configured for longer
+    /// than `retention`.
+    ///
+    /// `configured` should list every currently configured service,
+    /// whether or not we managed to launch it.
+    /// Failures are logged, but are otherwise ignored.
+    fn expire_unused_state<'n>(
+        &self,
+        configured: impl IntoIterator<Item = &'n HsNickname>,
+        retention: Retention,
+    ) {
+        let max_age = match retention {
+            Retention::For(max_age) => max_age,
+            Retention::Never => return,
+        };
+        if let Err(err) = self
+            .client
+            .expire_unused_onion_service_state(configured, max_age)
+        {
+            warn_report!(err, "Unable to expire state of unconfigured onion services");
+        }
     }
 
     /// Try to reconfigure the set of onion proxies according to the
@@ -268,9 +304,16 @@ impl<R: Runtime> ProxySet<R> {
     ///
     /// Launches or closes proxies as necessary.  Does not close existing
     /// connections.
+    ///
+    /// Records the services that were launched or stopped in `report`.
+    ///
+    /// The state of services that are no longer configured is deleted
+    /// according to `state_retention`.
     pub(crate) fn reconfigure(
         &self,
         new_config: OnionServiceProxyConfigMap,
+        state_retention: Retention,
+        report: &mut ReconfigureReport,
         // TODO: this should probably take `how: Reconfigure` and implement an all-or-nothing mode.
         // See #1156.
     ) -> Result<(), anyhow::Error> {
@@ -278,6 +321,8 @@ impl<R: Runtime> ProxySet<R> {
 
         // Set of the nicknames of defunct proxies.
         let mut defunct_nicknames: HashSet<_> = proxy_map.keys().map(Clone::clone).collect();
+        // Nicknames of all the services in the new configuration.
+        let configured: Vec<HsNickname> = new_config.keys().cloned().collect();
 
         for cfg in new_config.into_values() {
             let nickname = cfg.svc_cfg.nickname().clone();
@@ -298,6 +343,7 @@ impl<R: Runtime> ProxySet<R> {
                     // one.
                     match Proxy::launch_new(&self.client, cfg) {
                         Ok(new_proxy) => {
+                            report.applied(format!("launched onion service {}", ent.key()));
                             ent.insert(new_proxy);
                         }
                         Err(err) => {
@@ -316,15 +362,27 @@ impl<R: Runtime> ProxySet<R> {
                 .expect("Somehow a proxy disappeared from the map");
             // This "drop" should shut down the proxy.
             drop(defunct_proxy);
+            report.applied(format!("stopped onion service {}", nickname));
         }
 
+        self.expire_unused_state(&configured, state_retention);
+
         Ok(())
     }
 }
 
 impl<R: Runtime> crate::reload_cfg::ReconfigurableModule for ProxySet<R> {
-    fn reconfigure(&self, new: &crate::ArtiCombinedConfig) -> anyhow::Result<()> {
-        ProxySet::reconfigure(self, new.0.onion_services.clone())?;
+    fn reconfigure(
+        &self,
+        new: &crate::ArtiCombinedConfig,
+        report: &mut ReconfigureReport,
+    ) -> anyhow::Result<()> {
+        ProxySet::reconfigure(
+            self,
+            new.0.onion_services.clone(),
+            new.0.application().onion_service_state_retention,
+            report,
+        )?;
         Ok(())
     }
 }
@@ -95,3 +95,27 @@ pub(crate) fn sighup_stream() -> crate::Result<impl futures::Stream<Item = ()>>
         }
     }
 }
+
+/// Return an async stream that reports an event whenever we get a `SIGTERM`
+/// signal.
+///
+/// This is how service managers (like systemd) ask us to shut down.
+#[cfg(target_family = "unix")]
+pub(crate) fn sigterm_stream() -> crate::Result<impl futures::Stream<Item = ()>> {
+    cfg_if::cfg_if! {
+        if #[cfg(feature="tokio")] {
+            use tokio_crate::signal::unix as s;
+            let mut signal = s::signal(s::SignalKind::terminate())?;
+            Ok(futures::stream::poll_fn(move |ctx| signal.poll_recv(ctx)))
+        } else if #[cfg(feature="async-std")] {
+            use signal_hook_async_std as s;
+            use signal_hook::consts::signal;
+            use futures::stream::StreamExt as _;
+            let signal = s::Signals::new(&[signal::SIGTERM])?;
+            Ok(signal.map(|_| ()))
+        } else {
+            // Not backend, so we won't ever get a SIGTERM.
+            Ok(futures::stream::pending())
+        }
+    }
+}
@@ -25,6 +25,9 @@ enum Event {
     /// SIGHUP has been received.
     #[cfg(target_family = "unix")]
     SigHup,
+    /// The Windows service control manager has told us that our parameters have changed.
+    #[cfg(windows)]
+    ServiceParamChange,
     /// Some files may have been modified.
     FileChanged,
     /// Some filesystem events may have been missed.
@@ -44,24 +47,78 @@ pub(crate) trait ReconfigurableModule: Send + Sync {
     ///
     /// By convention, this should only return fatal errors; any such error
     /// should cause the program to exit.  For other cases, we should just warn.
+    ///
+    /// Changes that were applied, and changes that can't take effect until
+    /// Arti is restarted, should be recorded in `report`.
     //
     // TODO: This should probably take "how: Reconfigure" as an argument, and
     // pass it down as appropriate. See issue #1156.
-    fn reconfigure(&self, new: &ArtiCombinedConfig) -> anyhow::Result<()>;
+    fn reconfigure(
+        &self,
+        new: &ArtiCombinedConfig,
+        report: &mut ReconfigureReport,
+    ) -> anyhow::Result<()>;
+}
+
+/// A description of what happened when we applied a new configuration.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) struct ReconfigureReport {
+    /// Changes that took effect immediately.
+    applied: Vec<String>,
+    /// Changes that will not take effect until Arti is restarted.
+    needs_restart: Vec<String>,
+}
+
+impl ReconfigureReport {
+    /// Record that we applied `change`.
+    #[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+    pub(crate) fn applied(&mut self, change: impl Into<String>) {
+        self.applied.push(change.into());
+    }
+
+    /// Record that `change` won't take effect until Arti is restarted.
+    #[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+    pub(crate) fn needs_restart(&mut self, change: impl Into<String>) {
+        self.needs_restart.push(change.into());
+    }
+
+    /// Return the changes that took effect immediately.
+    #[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+    pub(crate) fn applied_changes(&self) -> &[String] {
+        &self.applied
+    }
+
+    /// Return the changes that will not take effect until Arti is restarted.
+    #[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+    pub(crate) fn restart_required(&self) -> &[String] {
+        &self.needs_restart
+    }
+
+    /// Log the contents of this report.
+    fn log(&self) {
+        for change in &self.applied {
+            info!("Reconfigured: {}", change);
+        }
+        for change in &self.needs_restart {
+            warn!("Can't (yet) change while arti is running: {}", change);
+        }
+    }
 }
 
 /// Launch a thread to reload our configuration files.
 ///
 /// If current configuration requires it, watch for changes in `sources`
 /// and try to reload our configuration. On unix platforms, also watch
-/// for SIGHUP and reload configuration then.
+/// for SIGHUP and reload configuration then; when running as a Windows
+/// service, do the same when the service control manager tells us that our
+/// parameters have changed.
 ///
 /// The modules are `Weak` references to prevent this background task
 /// from keeping them alive.
 #[cfg_attr(feature = "experimental-api", visibility::make(pub))]
 pub(crate) fn watch_for_config_changes<R: Runtime>(
-    #[cfg_attr(not(target_family = "unix"), allow(unused_variables))]
-    runtime: &R,
+    #[cfg_attr(not(any(target_family = "unix", windows)), allow(unused_variables))] runtime: &R,
     sources: ConfigurationSources,
     config: &ArtiConfig,
     modules: Vec<Weak<dyn ReconfigurableModule>>,
@@ -101,6 +158,24 @@ pub(crate) fn watch_for_config_changes<R: Runtime>(
         })?;
     }
 
+    #[cfg(windows)]
+    {
+        use futures::task::SpawnExt;
+        use futures::StreamExt;
+
+        let mut param_changes = crate::service::windows::reload_requests();
+        let tx = tx.clone();
+        runtime.spawn(async move {
+            while let Some(()) = param_changes.next().await {
+                info!("Service parameters changed");
+                if tx.send(Event::ServiceParamChange).is_err() {
+                    warn!("Failed to reload configuration");
+                    break;
+                }
+            }
+        })?;
+    }
+
     #[allow(clippy::cognitive_complexity)]
     std::thread::spawn(move || {
         // TODO: If someday we make this facility available outside of the
@@ -138,8 +213,12 @@ pub(crate) fn watch_for_config_changes<R: Runtime>(
                         .context("FS watch: failed to rescan config")?
                 };
 
-                match reconfigure(found_files, &modules) {
-                    Ok(watch) => {
+                crate::service::notify_reloading();
+                let result = reconfigure(found_files, &modules);
+                crate::service::notify_ready();
+                match result {
+                    Ok((watch, report)) => {
+                        report.log();
                         info!("Successfully reloaded configuration.");
                         if watch && watcher.is_none() {
                             info!("Starting watching over configuration.");
@@ -181,7 +260,16 @@ pub(crate) fn watch_for_config_changes<R: Runtime>(
 }
 
 impl<R: Runtime> ReconfigurableModule for TorClient<R> {
-    fn reconfigure(&self, new: &ArtiCombinedConfig) -> anyhow::Result<()> {
+    fn reconfigure(
+        &self,
+        new: &ArtiCombinedConfig,
+        report: &mut ReconfigureReport,
+    ) -> anyhow::Result<()> {
+        // Find out first whether everything can be changed in place, so that
+        // we can say what can't.
+        if let Err(e) = TorClient::reconfigure(self, &new.1, Reconfigure::CheckAllOrNothing) {
+            report.needs_restart(format!("client settings: {}", tor_error::Report(e)));
+        }
         TorClient::reconfigure(self, &new.1, Reconfigure::WarnOnFailures)?;
         Ok(())
     }
@@ -210,18 +298,35 @@ impl ReconfigurableModule for Application {
     // TODO: This should probably take "how: Reconfigure" as an argument, and
     // pass it down as appropriate. See issue #1156.
     #[allow(clippy::cognitive_complexity)]
-    fn reconfigure(&self, new: &ArtiCombinedConfig) -> anyhow::Result<()> {
+    fn reconfigure(
+        &self,
+        new: &ArtiCombinedConfig,
+        report: &mut ReconfigureReport,
+    ) -> anyhow::Result<()> {
         let original = &self.original_config;
         let config = &new.0;
 
-        if config.proxy() != original.proxy() {
-            warn!("Can't (yet) reconfigure proxy settings while arti is running.");
+        // A running SOCKS proxy rebinds its own listeners; we can only
+        // start one at startup.
+        if original.proxy().socks_listen.is_empty()
+            && config.proxy().socks_listen != original.proxy().socks_listen
+        {
+            report.needs_restart("SOCKS listeners (no SOCKS proxy was running)");
+        }
+        if config.proxy().dns_listen != original.proxy().dns_listen {
+            report.needs_restart("DNS listeners");
         }
         if config.logging() != original.logging() {
-            warn!("Can't (yet) reconfigure logging settings while arti is running.");
+            report.needs_restart("logging settings");
         }
         if config.application().permit_debugging && !original.application().permit_debugging {
-            warn!("Cannot disable application hardening when it has already been enabled.");
+            report.needs_restart("application hardening, once enabled, can't be disabled");
+        }
+        if config.application().sandbox != original.application().sandbox {
+            report.needs_restart("sandbox settings");
+        }
+        if config.application().pid_file != original.application().pid_file {
+            report.needs_restart("PID file");
         }
 
         // Note that this is the only config transition we actually perform so far.
@@ -237,14 +342,15 @@ impl ReconfigurableModule for Application {
 /// Reload the configuration files, apply the runtime configuration, and
 /// reconfigure the client as much as we can.
 ///
-/// Return true if we should be watching for configuration changes.
+/// Return true if we should be watching for configuration changes, along with
+/// a report of what we changed.
 //
 // TODO: This should probably take "how: Reconfigure" as an argument, and
 // pass it down as appropriate. See issue #1156.
 fn reconfigure(
     found_files: FoundConfigFiles<'_>,
     reconfigurable: &[Weak<dyn ReconfigurableModule>],
-) -> anyhow::Result<bool> {
+) -> anyhow::Result<(bool, ReconfigureReport)> {
     let _ = reconfigurable;
     let config = found_files.load()?;
     let config = tor_config::resolve::<ArtiCombinedConfig>(config)?;
@@ -253,13 +359,17 @@ fn reconfigure(
     let reconfigurable = reconfigurable.iter().flat_map(Weak::upgrade);
     // If there are no more modules, we should exit.
     let mut has_modules = false;
+    let mut report = ReconfigureReport::default();
 
     for module in reconfigurable {
         has_modules = true;
-        module.reconfigure(&config)?;
+        module.reconfigure(&config, &mut report)?;
     }
 
-    Ok(has_modules && config.0.application().watch_configuration)
+    Ok((
+        has_modules && config.0.application().watch_configuration,
+        report,
+    ))
 }
 
 /// A wrapper around `notify::RecommendedWatcher` to watch a set of parent
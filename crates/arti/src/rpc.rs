@@ -1,10 +1,13 @@
 //! Experimental RPC support.
 
-use anyhow::Result;
-use arti_rpcserver::{RpcMgr, RpcSession};
+use anyhow::{Context as _, Result};
+use arti_rpcserver::{RpcAuthPolicy, RpcMgr, RpcPeer, RpcSecret, RpcSession};
+use fs_mistrust::Mistrust;
 use futures::task::SpawnExt;
 use std::{path::Path, sync::Arc};
 
+use crate::cfg::RpcConfig;
+
 use arti_client::TorClient;
 use tor_rtcompat::Runtime;
 
@@ -12,10 +15,10 @@ pub(crate) mod conntarget;
 
 cfg_if::cfg_if! {
     if #[cfg(all(feature="tokio", not(target_os="windows")))] {
-        use tokio_crate::net::UnixListener ;
+        use tokio_crate::net::{UnixListener, UnixStream};
         use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
     } else if #[cfg(all(feature="async-std", not(target_os="windows")))] {
-        use async_std::os::unix::net::UnixListener;
+        use async_std::os::unix::net::{UnixListener, UnixStream};
     } else if #[cfg(target_os="windows")] {
         compile_error!("Sorry, no windows support for RPC yet.");
         // TODO RPC: Tokio has a named pipe API; AsyncStd should let us construct
@@ -25,11 +28,70 @@ cfg_if::cfg_if! {
     }
 }
 
+/// Construct the policy for authenticating RPC clients described by `config`.
+///
+/// If cookie authentication is enabled, this writes a new cookie file.
+pub(crate) fn make_auth_policy(config: &RpcConfig, mistrust: &Mistrust) -> Result<RpcAuthPolicy> {
+    let mut policy = RpcAuthPolicy::default();
+    policy.inherent_unix_path = config.inherent_auth;
+    policy.allowed_peer_uids = config.allowed_peer_uids.clone();
+
+    if let Some(cookie_path) = &config.cookie_path {
+        let cookie_path = cookie_path.path()?;
+        let (parent, file_name) = match (cookie_path.parent(), cookie_path.file_name()) {
+            (Some(parent), Some(file_name)) => (parent, file_name),
+            _ => anyhow::bail!("Invalid RPC cookie_path {}", cookie_path.display()),
+        };
+        let cookie = RpcSecret::new_random();
+        mistrust
+            .verifier()
+            .make_secure_dir(parent)?
+            .write_and_replace(file_name, cookie.as_str())
+            .with_context(|| format!("Unable to write RPC cookie to {}", cookie_path.display()))?;
+        policy.cookie = Some(cookie);
+    }
+
+    if config.token.is_some() && config.token_file.is_some() {
+        anyhow::bail!("Only one of rpc.token and rpc.token_file may be set");
+    }
+
+    if let Some(token_file) = &config.token_file {
+        let token_file = token_file.path()?;
+        mistrust.verifier().require_file().check(&token_file)?;
+        let token = std::fs::read_to_string(&token_file)
+            .with_context(|| format!("Unable to read RPC token from {}", token_file.display()))?;
+        policy.token = Some(rpc_token(&token, &token_file.display())?);
+    }
+
+    if let Some(token) = &config.token {
+        let resolved = token
+            .resolve()
+            .with_context(|| format!("Unable to read RPC token from {}", token))?;
+        policy.token = Some(rpc_token(&resolved, token)?);
+    }
+
+    Ok(policy)
+}
+
+/// Return `token`, which we read from `source`, as a pre-shared RPC token.
+///
+/// Surrounding whitespace is not part of the token.
+/// A token that is empty, or only whitespace, is an error:
+/// any client could present it.
+fn rpc_token(token: &str, source: &dyn std::fmt::Display) -> Result<RpcSecret> {
+    let token = token.trim();
+    if token.is_empty() {
+        anyhow::bail!("RPC token from {} is empty", source);
+    }
+    Ok(token.to_string().into())
+}
+
 /// Run an RPC listener task to accept incoming connections at the Unix
 /// socket address of `path`.
 pub(crate) fn launch_rpc_listener<R: Runtime>(
     runtime: &R,
     path: impl AsRef<Path>,
+    auth_policy: RpcAuthPolicy,
     client: TorClient<R>,
 ) -> Result<Arc<RpcMgr>> {
     // TODO RPC: there should be an error return instead.
@@ -45,6 +107,7 @@ pub(crate) fn launch_rpc_listener<R: Runtime>(
     // TODO: If we accumulate a large number of generics like this, we should do this elsewhere.
     rpc_mgr.register_rpc_methods(TorClient::<R>::rpc_methods());
     rpc_mgr.register_rpc_methods(arti_rpcserver::rpc_methods::<R>());
+    rpc_mgr.set_auth_policy(auth_policy);
 
     let rt_clone = runtime.clone();
     let rpc_mgr_clone = rpc_mgr.clone();
@@ -69,8 +132,9 @@ async fn run_rpc_listener<R: Runtime>(
 ) -> Result<()> {
     loop {
         let (stream, _addr) = listener.accept().await?;
+        let peer = peer_of(&stream);
         // TODO RPC: Perhaps we should have rpcmgr hold the client reference?
-        let connection = rpc_mgr.new_connection();
+        let connection = rpc_mgr.new_connection_with_peer(peer);
         let (input, output) = stream.into_split();
 
         #[cfg(feature = "tokio")]
@@ -85,6 +149,25 @@ async fn run_rpc_listener<R: Runtime>(
     }
 }
 
+/// Return what we can learn about the process on the other end of `stream`.
+fn peer_of(stream: &UnixStream) -> RpcPeer {
+    cfg_if::cfg_if! {
+        if #[cfg(feature="tokio")] {
+            match stream.peer_cred() {
+                Ok(cred) => RpcPeer::with_uid(cred.uid()),
+                Err(e) => {
+                    tracing::debug!("Unable to get credentials of RPC peer: {}", e);
+                    RpcPeer::default()
+                }
+            }
+        } else {
+            // async-std doesn't expose the peer's credentials.
+            let _ = stream;
+            RpcPeer::default()
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     // @@ begin test lint list maintained by maint/add_warning @@
@@ -101,6 +184,16 @@ mod test {
     #![allow(clippy::needless_pass_by_value)]
     //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
 
+    use super::*;
+
+    #[test]
+    fn empty_token() {
+        assert_eq!(rpc_token("hunter2\n", &"test").unwrap().as_str(), "hunter2");
+        for bad in ["", "\n", " \t\r\n "] {
+            assert!(rpc_token(bad, &"test").is_err(), "{bad:?}");
+        }
+    }
+
     #[test]
     fn rpc_method_names() {
         // We run this from a nice high level module, to ensure that as many method names as
@@ -0,0 +1,495 @@
+//! An optional sandbox, to limit what Arti can do once it is running.
+//!
+//! Once Arti has read its configuration, created its client, and opened its
+//! listeners, it doesn't need to do very much that is new: it reads and
+//! writes sockets, and files in a few directories that it knows about in
+//! advance.  [`enable_sandbox`] asks the operating system to enforce that,
+//! so that an attacker who compromises the process can do less with it.
+//!
+//! On Linux, we install a seccomp-bpf filter that only permits the system
+//! calls that we need.  Any other system call fails with `EPERM`.
+//! For a few powerful system calls, we also check their arguments: we can
+//! create threads but not processes, and only use the `ioctl` and `prctl`
+//! requests that we need.
+//! (Seccomp can't see the paths that we pass to the kernel, so it can't
+//! restrict _which_ files we open.)
+//!
+//! On OpenBSD, we use `unveil` to hide every file and directory except the
+//! ones in our [`SandboxPaths`], and `pledge` to restrict our system calls.
+//!
+//! On other platforms, we have no sandbox: asking for one is an error.
+//!
+//! # Limitations
+//!
+//! Anything that is not part of Arti's steady-state operation will fail once
+//! the sandbox is enabled.  In particular:
+//!
+//!  * We can't launch other programs, so managed pluggable transports
+//!    won't work.
+//!  * On OpenBSD, configuration changes that name new files or directories
+//!    (for example, a new log file) won't work.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use arti_client::TorClientConfig;
+use tor_config::ConfigurationSources;
+
+use crate::ArtiConfig;
+
+/// The files and directories that Arti will need once it is sandboxed.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) struct SandboxPaths {
+    /// Directories in which we may create, read, write, and remove files.
+    read_write: Vec<PathBuf>,
+    /// Files and directories that we may only read.
+    read_only: Vec<PathBuf>,
+}
+
+impl SandboxPaths {
+    /// Return the paths that Arti will need, given its configuration.
+    ///
+    /// These are the client's storage directories, the directories that
+    /// hold our logfiles, PID file, and RPC socket, and (so that we can
+    /// reload them) our configuration files.
+    #[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+    pub(crate) fn from_config(
+        arti_config: &ArtiConfig,
+        client_config: &TorClientConfig,
+        config_sources: &ConfigurationSources,
+    ) -> Result<Self> {
+        let mut paths = SandboxPaths::default();
+        paths.read_write.extend(client_config.storage_dirs()?);
+        paths
+            .read_write
+            .extend(crate::logging::logfile_directories(arti_config.logging())?);
+        if let Some(path) = &arti_config.application().pid_file {
+            if let Some(parent) = path.path()?.parent() {
+                paths.read_write.push(parent.to_owned());
+            }
+        }
+        #[cfg(feature = "rpc")]
+        if let Some(path) = &arti_config.rpc().rpc_listen {
+            if let Some(parent) = path.path()?.parent() {
+                paths.read_write.push(parent.to_owned());
+            }
+        }
+        paths.read_only.extend(
+            config_sources
+                .iter()
+                .filter_map(|source| source.as_path())
+                .map(ToOwned::to_owned),
+        );
+        Ok(paths)
+    }
+}
+
+/// Restrict this process to the operations that Arti needs in normal
+/// operation, and (where the platform allows) to the files and directories
+/// in `paths`.
+///
+/// This applies to every thread in the process, and can't be undone.
+/// It should be called once, after Arti has created its client and opened
+/// its listeners.
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) fn enable_sandbox(paths: &SandboxPaths) -> Result<()> {
+    imp::enable_sandbox(paths)
+}
+
+/// Sandbox implementation for Linux, using seccomp.
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::collections::BTreeMap;
+
+    use anyhow::{Context as _, Result};
+    use seccompiler::{
+        BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter,
+        SeccompRule, TargetArch,
+    };
+
+    use super::SandboxPaths;
+
+    /// The system calls that we permit on every architecture.
+    ///
+    /// This is everything that we need for network I/O, for reading and
+    /// writing files in our storage directories (including SQLite), for
+    /// watching our configuration files, and for the threads of our runtime.
+    ///
+    /// `clone`, `ioctl` and `prctl` are permitted too, but only with the
+    /// arguments in [`argument_rules`].
+    const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+        // Memory
+        libc::SYS_brk,
+        libc::SYS_madvise,
+        libc::SYS_mmap,
+        libc::SYS_mprotect,
+        libc::SYS_mremap,
+        libc::SYS_munmap,
+        // Threads and synchronization
+        libc::SYS_exit,
+        libc::SYS_exit_group,
+        libc::SYS_futex,
+        libc::SYS_getpid,
+        libc::SYS_gettid,
+        libc::SYS_rseq,
+        libc::SYS_sched_getaffinity,
+        libc::SYS_sched_yield,
+        libc::SYS_set_robust_list,
+        libc::SYS_sigaltstack,
+        libc::SYS_tgkill,
+        // Signals
+        libc::SYS_restart_syscall,
+        libc::SYS_rt_sigaction,
+        libc::SYS_rt_sigprocmask,
+        libc::SYS_rt_sigreturn,
+        // Time and randomness
+        libc::SYS_clock_gettime,
+        libc::SYS_clock_nanosleep,
+        libc::SYS_getrandom,
+        libc::SYS_nanosleep,
+        // Polling
+        libc::SYS_epoll_create1,
+        libc::SYS_epoll_ctl,
+        libc::SYS_epoll_pwait,
+        libc::SYS_eventfd2,
+        libc::SYS_pipe2,
+        libc::SYS_ppoll,
+        libc::SYS_pselect6,
+        // Networking
+        libc::SYS_accept4,
+        libc::SYS_bind,
+        libc::SYS_connect,
+        libc::SYS_getpeername,
+        libc::SYS_getsockname,
+        libc::SYS_getsockopt,
+        libc::SYS_listen,
+        libc::SYS_recvfrom,
+        libc::SYS_recvmmsg,
+        libc::SYS_recvmsg,
+        libc::SYS_sendmmsg,
+        libc::SYS_sendmsg,
+        libc::SYS_sendto,
+        libc::SYS_setsockopt,
+        libc::SYS_shutdown,
+        libc::SYS_socket,
+        libc::SYS_socketpair,
+        // File descriptors
+        libc::SYS_close,
+        libc::SYS_dup,
+        libc::SYS_dup3,
+        libc::SYS_fcntl,
+        libc::SYS_lseek,
+        libc::SYS_pread64,
+        libc::SYS_pwrite64,
+        libc::SYS_read,
+        libc::SYS_readv,
+        libc::SYS_write,
+        libc::SYS_writev,
+        // Files and directories
+        libc::SYS_faccessat,
+        libc::SYS_fchmod,
+        libc::SYS_fchmodat,
+        libc::SYS_fdatasync,
+        libc::SYS_flock,
+        libc::SYS_fstat,
+        libc::SYS_fstatfs,
+        libc::SYS_fsync,
+        libc::SYS_ftruncate,
+        libc::SYS_getcwd,
+        libc::SYS_getdents64,
+        libc::SYS_mkdirat,
+        libc::SYS_newfstatat,
+        libc::SYS_openat,
+        libc::SYS_readlinkat,
+        libc::SYS_renameat,
+        libc::SYS_renameat2,
+        libc::SYS_statfs,
+        libc::SYS_statx,
+        libc::SYS_unlinkat,
+        libc::SYS_utimensat,
+        // Watching configuration files
+        libc::SYS_inotify_add_watch,
+        libc::SYS_inotify_init1,
+        libc::SYS_inotify_rm_watch,
+        // Identity and limits
+        libc::SYS_getegid,
+        libc::SYS_geteuid,
+        libc::SYS_getgid,
+        libc::SYS_getuid,
+        libc::SYS_prlimit64,
+        libc::SYS_sysinfo,
+        libc::SYS_uname,
+    ];
+
+    /// Older system calls that we also permit on x86_64,
+    /// where the C library may use them instead of the ones above.
+    #[cfg(target_arch = "x86_64")]
+    const ALLOWED_LEGACY_SYSCALLS: &[libc::c_long] = &[
+        libc::SYS_access,
+        libc::SYS_epoll_wait,
+        libc::SYS_getdents,
+        libc::SYS_lstat,
+        libc::SYS_mkdir,
+        libc::SYS_open,
+        libc::SYS_pipe,
+        libc::SYS_poll,
+        libc::SYS_readlink,
+        libc::SYS_rename,
+        libc::SYS_stat,
+        libc::SYS_unlink,
+    ];
+
+    /// Older system calls that we also permit on this architecture.
+    ///
+    /// (There are none here: this architecture only has the newer calls.)
+    #[cfg(not(target_arch = "x86_64"))]
+    const ALLOWED_LEGACY_SYSCALLS: &[libc::c_long] = &[];
+
+    /// The flags that `clone` must have for us to permit it.
+    ///
+    /// Together, these mean that `clone` creates a thread in this process,
+    /// rather than a new process.
+    const THREAD_CLONE_FLAGS: u64 =
+        (libc::CLONE_VM | libc::CLONE_THREAD | libc::CLONE_SIGHAND) as u64;
+
+    /// The `ioctl` requests that we permit.
+    ///
+    /// These set non-blocking and close-on-exec mode, count the bytes that
+    /// are ready to read, and check whether our output is a terminal.
+    const ALLOWED_IOCTLS: &[u64] = &[
+        libc::FIONBIO as u64,
+        libc::FIOCLEX as u64,
+        libc::FIONREAD as u64,
+        libc::TCGETS as u64,
+    ];
+
+    /// The `prctl` operations that we permit: getting and setting the names
+    /// of our threads.
+    const ALLOWED_PRCTLS: &[u64] = &[libc::PR_GET_NAME as u64, libc::PR_SET_NAME as u64];
+
+    /// Return the system calls that we permit only with certain arguments,
+    /// with the rules for those arguments.
+    ///
+    /// (A system call is permitted if _any_ of its rules match.)
+    fn argument_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>> {
+        /// Return a rule that matches when argument `arg` is `value`.
+        fn arg_is(arg: u8, value: u64) -> Result<SeccompRule> {
+            let cond =
+                SeccompCondition::new(arg, SeccompCmpArgLen::Dword, SeccompCmpOp::Eq, value)?;
+            Ok(SeccompRule::new(vec![cond])?)
+        }
+
+        // On x86_64 and aarch64, the flags are the first argument to clone.
+        let clone_cond = SeccompCondition::new(
+            0,
+            SeccompCmpArgLen::Qword,
+            SeccompCmpOp::MaskedEq(THREAD_CLONE_FLAGS),
+            THREAD_CLONE_FLAGS,
+        )?;
+        let clone_rules = vec![SeccompRule::new(vec![clone_cond])?];
+        let ioctl_rules = ALLOWED_IOCTLS
+            .iter()
+            .map(|&request| arg_is(1, request))
+            .collect::<Result<_>>()?;
+        let prctl_rules = ALLOWED_PRCTLS
+            .iter()
+            .map(|&option| arg_is(0, option))
+            .collect::<Result<_>>()?;
+
+        Ok(vec![
+            (libc::SYS_clone.into(), clone_rules),
+            (libc::SYS_ioctl.into(), ioctl_rules),
+            (libc::SYS_prctl.into(), prctl_rules),
+        ])
+    }
+
+    /// Compile a seccomp filter from `rules`, and apply it to every thread.
+    fn apply_filter(
+        rules: BTreeMap<i64, Vec<SeccompRule>>,
+        mismatch_action: SeccompAction,
+        match_action: SeccompAction,
+    ) -> Result<()> {
+        let arch: TargetArch = std::env::consts::ARCH
+            .try_into()
+            .context("seccomp is not supported on this architecture")?;
+        let filter = SeccompFilter::new(rules, mismatch_action, match_action, arch)
+            .context("Unable to build seccomp filter")?;
+        let program: BpfProgram = filter
+            .try_into()
+            .context("Unable to compile seccomp filter")?;
+        seccompiler::apply_filter_all_threads(&program)
+            .context("Unable to apply seccomp filter")?;
+        Ok(())
+    }
+
+    /// Install a seccomp filter permitting only [`ALLOWED_SYSCALLS`],
+    /// [`ALLOWED_LEGACY_SYSCALLS`], and the calls in [`argument_rules`],
+    /// on every thread.
+    ///
+    /// Seccomp can't restrict paths, so we don't use `_paths`.
+    pub(super) fn enable_sandbox(_paths: &SandboxPaths) -> Result<()> {
+        let mut rules: BTreeMap<i64, Vec<SeccompRule>> = ALLOWED_SYSCALLS
+            .iter()
+            .chain(ALLOWED_LEGACY_SYSCALLS)
+            .map(|&syscall| (i64::from(syscall), vec![]))
+            .collect();
+        rules.extend(argument_rules().context("Unable to build seccomp rules")?);
+        apply_filter(
+            rules,
+            SeccompAction::Errno(libc::EPERM as u32),
+            SeccompAction::Allow,
+        )?;
+
+        // We can't check the flags of clone3, since they are passed in
+        // memory.  When it fails with ENOSYS, the C library falls back to
+        // clone, whose flags we can check.  (Where filters disagree, the
+        // most recently installed one decides the errno.)
+        let clone3 = BTreeMap::from([(i64::from(libc::SYS_clone3), vec![])]);
+        apply_filter(
+            clone3,
+            SeccompAction::Allow,
+            SeccompAction::Errno(libc::ENOSYS as u32),
+        )?;
+        Ok(())
+    }
+}
+
+/// Sandbox implementation for OpenBSD, using `unveil` and `pledge`.
+#[cfg(target_os = "openbsd")]
+mod imp {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt as _;
+    use std::path::Path;
+    use std::ptr;
+
+    use anyhow::{Context as _, Result};
+    use tracing::debug;
+
+    use super::SandboxPaths;
+
+    /// The promises that we pass to `pledge`.
+    ///
+    /// See `pledge(2)` for what each of these permits.
+    const PROMISES: &str = "stdio rpath wpath cpath fattr flock inet unix";
+
+    /// Files and directories outside our configuration that we need to read.
+    ///
+    /// (Our TLS library loads its trusted certificates lazily.)
+    const SYSTEM_READ_ONLY: &[&str] = &["/etc/ssl"];
+
+    /// Reveal only `paths` to this process, and then restrict its system calls.
+    pub(super) fn enable_sandbox(paths: &SandboxPaths) -> Result<()> {
+        let read_only = paths
+            .read_only
+            .iter()
+            .map(|p| p.as_path())
+            .chain(SYSTEM_READ_ONLY.iter().map(Path::new));
+        for path in read_only {
+            unveil(path, "r")?;
+        }
+        for path in &paths.read_write {
+            unveil(path, "rwc")?;
+        }
+        // Forbid any further calls to unveil.
+        // SAFETY: Two null pointers are a valid argument to unveil.
+        if unsafe { libc::unveil(ptr::null(), ptr::null()) } == -1 {
+            return Err(io::Error::last_os_error()).context("Unable to lock unveil list");
+        }
+
+        let promises = CString::new(PROMISES).expect("promises contain a NUL");
+        // SAFETY: `promises` is a valid C string, and a null pointer is a
+        // valid second argument to pledge.
+        if unsafe { libc::pledge(promises.as_ptr(), ptr::null()) } == -1 {
+            return Err(io::Error::last_os_error()).context("Unable to pledge");
+        }
+        Ok(())
+    }
+
+    /// Make `path` visible to this process, with the `unveil` permissions `perms`.
+    ///
+    /// Paths that don't exist are skipped, since we wouldn't be able to use them anyway.
+    fn unveil(path: &Path, perms: &str) -> Result<()> {
+        if !path.exists() {
+            debug!("Not unveiling {:?}, which does not exist", path);
+            return Ok(());
+        }
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .with_context(|| format!("Path {:?} contains a NUL", path))?;
+        let c_perms = CString::new(perms).expect("permissions contain a NUL");
+        // SAFETY: Both arguments are valid C strings.
+        if unsafe { libc::unveil(c_path.as_ptr(), c_perms.as_ptr()) } == -1 {
+            return Err(io::Error::last_os_error())
+                .with_context(|| format!("Unable to unveil {:?}", path));
+        }
+        Ok(())
+    }
+}
+
+/// Placeholder sandbox implementation for platforms where we don't have one.
+#[cfg(not(any(target_os = "linux", target_os = "openbsd")))]
+mod imp {
+    use anyhow::{anyhow, Result};
+
+    use super::SandboxPaths;
+
+    /// Report that we can't sandbox this process.
+    pub(super) fn enable_sandbox(_paths: &SandboxPaths) -> Result<()> {
+        Err(anyhow!(
+            "application.sandbox is not supported on this platform"
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+    use crate::ArtiCombinedConfig;
+    use tor_config::sources::MustRead;
+    use tor_config::ConfigurationSource;
+
+    #[test]
+    fn paths_from_config() {
+        let toml = r#"
+            [storage]
+            state_dir = "/var/lib/arti"
+            cache_dir = "/var/cache/arti"
+
+            [[logging.files]]
+            path = "/var/log/arti/arti.log"
+            filter = "info"
+        "#;
+        let mut sources = ConfigurationSources::new_empty();
+        sources.push_source(
+            ConfigurationSource::from_verbatim(toml.to_owned()),
+            MustRead::MustRead,
+        );
+        sources.push_source(
+            ConfigurationSource::from_path("/etc/arti/arti.toml"),
+            MustRead::TolerateAbsence,
+        );
+        let (arti_config, client_config) =
+            tor_config::resolve::<ArtiCombinedConfig>(sources.load().unwrap()).unwrap();
+
+        let paths = SandboxPaths::from_config(&arti_config, &client_config, &sources).unwrap();
+        assert!(paths.read_write.contains(&PathBuf::from("/var/lib/arti")));
+        assert!(paths.read_write.contains(&PathBuf::from("/var/cache/arti")));
+        assert!(paths.read_write.contains(&PathBuf::from("/var/log/arti")));
+        assert_eq!(paths.read_only, vec![PathBuf::from("/etc/arti/arti.toml")]);
+    }
+}
@@ -0,0 +1,303 @@
+//! Integration with service managers.
+//!
+//! When Arti runs as a system service, the program that supervises it wants to
+//! know some things about it:
+//!
+//!  * Its process ID, which we can write to a PID file ([`PidFile`]).
+//!  * Whether it is ready, reloading, or stopping, and whether it is still
+//!    alive.  Under systemd, we report these with `sd_notify`
+//!    ([`notify_ready`], [`notify_status`], [`notify_reloading`],
+//!    [`notify_stopping`], and [`spawn_watchdog`]).
+//!    Elsewhere, these functions do nothing.
+//!  * On Windows, the service control manager needs us to run a
+//!    "service main" function, and tells us to stop (or to reload our
+//!    configuration) through a control handler: see the `windows` module.
+//!
+//! Shutting down and reloading are done with our usual mechanisms:
+//! see `exit::wait_for_exit_request` and `reload_cfg`.
+//!
+//! We don't fork into the background ourselves: service managers
+//! expect to supervise a process that stays in the foreground.
+
+use std::ffi::OsString;
+use std::path::Path;
+
+use anyhow::{anyhow, Context as _, Result};
+use fs_mistrust::{CheckedDir, Mistrust};
+use tor_error::warn_report;
+use tor_rtcompat::Runtime;
+
+/// A PID file, which we remove when this object is dropped.
+#[derive(Debug)]
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) struct PidFile {
+    /// The directory that holds the file.
+    dir: CheckedDir,
+    /// The name of the file within `dir`.
+    name: OsString,
+}
+
+impl PidFile {
+    /// Write our process ID to a file at `path`, replacing any file that is
+    /// already there.
+    ///
+    /// The directory that holds the file must pass `mistrust`'s checks
+    /// (though it may be readable by others).  We write the file under a
+    /// temporary name, and then rename it into place, so we never follow a
+    /// symlink at `path`, and nobody ever sees a partially written file.
+    #[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+    pub(crate) fn create(path: &Path, mistrust: &Mistrust) -> Result<Self> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("PID file {} has no file name", path.display()))?;
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let dir = mistrust
+            .verifier()
+            .permit_readable()
+            .secure_dir(parent)
+            .with_context(|| format!("Unable to use directory for PID file {}", path.display()))?;
+        dir.write_and_replace(name, format!("{}\n", std::process::id()))
+            .with_context(|| format!("Unable to write PID file {}", path.display()))?;
+        Ok(PidFile {
+            dir,
+            name: name.to_owned(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = self.dir.remove_file(&self.name) {
+            warn_report!(
+                e,
+                "Unable to remove PID file {}",
+                self.dir.as_path().join(&self.name).display()
+            );
+        }
+    }
+}
+
+/// Tell the service manager that we are ready.
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) fn notify_ready() {
+    #[cfg(target_family = "unix")]
+    notify(&[sd_notify::NotifyState::Ready]);
+}
+
+/// Tell the service manager how we are doing, in a human-readable `status`.
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) fn notify_status(status: &str) {
+    #[cfg(target_family = "unix")]
+    notify(&[sd_notify::NotifyState::Status(status)]);
+    #[cfg(not(target_family = "unix"))]
+    let _ = status;
+}
+
+/// Tell the service manager that we are reloading our configuration.
+///
+/// Call [`notify_ready`] once we are done.
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) fn notify_reloading() {
+    #[cfg(target_family = "unix")]
+    notify(&[sd_notify::NotifyState::Reloading]);
+}
+
+/// Tell the service manager that we are shutting down.
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) fn notify_stopping() {
+    #[cfg(target_family = "unix")]
+    notify(&[sd_notify::NotifyState::Stopping]);
+}
+
+/// Send `state` to systemd, if we were started by systemd.
+///
+/// Failures are logged, but otherwise ignored: they don't stop us working.
+#[cfg(target_family = "unix")]
+fn notify(state: &[sd_notify::NotifyState<'_>]) {
+    // If NOTIFY_SOCKET is not set, this does nothing.
+    if let Err(e) = sd_notify::notify(false, state) {
+        warn_report!(e, "Unable to notify service manager");
+    }
+}
+
+/// If the service manager has asked for watchdog keep-alives,
+/// spawn a task on `runtime` to send them.
+///
+/// We send them from our runtime, so that if the runtime stops making
+/// progress, the service manager will notice.
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) fn spawn_watchdog<R: Runtime>(
+    #[cfg_attr(not(target_family = "unix"), allow(unused_variables))] runtime: &R,
+) -> Result<()> {
+    #[cfg(target_family = "unix")]
+    {
+        use futures::task::SpawnExt as _;
+        use std::time::Duration;
+
+        let mut usec = 0;
+        if !sd_notify::watchdog_enabled(false, &mut usec) {
+            return Ok(());
+        }
+        // systemd recommends that we send keep-alives at half the timeout.
+        let interval = Duration::from_micros(usec) / 2;
+        tracing::debug!("Sending watchdog keep-alives every {:?}", interval);
+        let rt = runtime.clone();
+        runtime.spawn(async move {
+            loop {
+                notify(&[sd_notify::NotifyState::Watchdog]);
+                rt.sleep(interval).await;
+            }
+        })?;
+    }
+    Ok(())
+}
+
+/// Support for running as a Windows service.
+///
+/// To run Arti as a service, register it with the service control manager
+/// with `--windows-service` in its command line: for example,
+/// `sc.exe create arti binPath= "C:\path\to\arti.exe proxy -c C:\path\to\arti.toml --windows-service"`.
+/// (Since a service has no console, configure logging to a file.)
+///
+/// When the service control manager asks us to stop, or the system is
+/// shutting down, we shut down as if we had got a control-c.
+/// When it tells us that our parameters have changed, we reload our
+/// configuration, as if we had got a `SIGHUP` on Unix.
+#[cfg(windows)]
+pub(crate) mod windows {
+    use std::ffi::OsString;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use futures::channel::{mpsc, oneshot};
+    use futures::Stream;
+    use tracing::error;
+    use windows_service::service::{
+        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+        ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    /// The command-line flag that tells us to run as a service.
+    const SERVICE_FLAG: &str = "--windows-service";
+
+    /// The name under which we register our control handler.
+    ///
+    /// (For a service that runs in its own process, Windows ignores this.)
+    const SERVICE_NAME: &str = "arti";
+
+    /// True if we have been asked to stop.
+    static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+    /// Senders to notify when we are asked to stop.
+    static STOP_LISTENERS: Mutex<Vec<oneshot::Sender<()>>> = Mutex::new(Vec::new());
+
+    /// Senders to notify when we are asked to reload our configuration.
+    static RELOAD_LISTENERS: Mutex<Vec<mpsc::UnboundedSender<()>>> = Mutex::new(Vec::new());
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    /// Return true if our command line tells us to run as a service.
+    pub(crate) fn requested() -> bool {
+        std::env::args_os().any(|arg| arg == SERVICE_FLAG)
+    }
+
+    /// Hand this thread over to the service control manager, which will run
+    /// Arti on another thread, and return once Arti has exited.
+    pub(crate) fn run() -> anyhow::Result<()> {
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+        Ok(())
+    }
+
+    /// Wait until the service control manager asks us to stop.
+    ///
+    /// (If we aren't running as a service, this never happens.)
+    pub(crate) async fn stop_requested() {
+        let (tx, rx) = oneshot::channel();
+        STOP_LISTENERS.lock().expect("lock poisoned").push(tx);
+        if STOP_REQUESTED.load(Ordering::SeqCst) {
+            return;
+        }
+        let _ = rx.await;
+    }
+
+    /// Return a stream that yields whenever the service control manager
+    /// tells us that our parameters have changed.
+    pub(crate) fn reload_requests() -> impl Stream<Item = ()> {
+        let (tx, rx) = mpsc::unbounded();
+        RELOAD_LISTENERS.lock().expect("lock poisoned").push(tx);
+        rx
+    }
+
+    /// Run Arti as a service: called by the service control manager,
+    /// on a thread of its own.
+    ///
+    /// `_arguments` are the service's start parameters: we take our
+    /// arguments from our command line instead, as usual.
+    fn service_main(_arguments: Vec<OsString>) {
+        let handler = |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                STOP_REQUESTED.store(true, Ordering::SeqCst);
+                for tx in STOP_LISTENERS.lock().expect("lock poisoned").drain(..) {
+                    let _ = tx.send(());
+                }
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::ParamChange => {
+                RELOAD_LISTENERS
+                    .lock()
+                    .expect("lock poisoned")
+                    .retain(|tx| tx.unbounded_send(()).is_ok());
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        };
+        let status_handle = match service_control_handler::register(SERVICE_NAME, handler) {
+            Ok(handle) => handle,
+            Err(e) => {
+                error!("Unable to register service control handler: {}", e);
+                return;
+            }
+        };
+        let set_status = |state, controls_accepted, exit_code| {
+            let status = ServiceStatus {
+                service_type: ServiceType::OWN_PROCESS,
+                current_state: state,
+                controls_accepted,
+                exit_code,
+                checkpoint: 0,
+                wait_hint: Duration::default(),
+                process_id: None,
+            };
+            if let Err(e) = status_handle.set_service_status(status) {
+                error!("Unable to report service status: {}", e);
+            }
+        };
+
+        set_status(
+            ServiceState::Running,
+            ServiceControlAccept::STOP
+                | ServiceControlAccept::SHUTDOWN
+                | ServiceControlAccept::PARAM_CHANGE,
+            ServiceExitCode::Win32(0),
+        );
+        let exit_code = match crate::main_main(std::env::args_os()) {
+            Ok(()) => ServiceExitCode::Win32(0),
+            Err(e) => {
+                error!("Arti exited with an error: {}", tor_error::Report(e));
+                ServiceExitCode::ServiceSpecific(1)
+            }
+        };
+        set_status(
+            ServiceState::Stopped,
+            ServiceControlAccept::empty(),
+            exit_code,
+        );
+    }
+}
@@ -3,29 +3,37 @@
 //! A proxy is launched with [`run_socks_proxy()`], which listens for new
 //! connections and then runs
 
-use futures::future::FutureExt;
+use futures::channel::{mpsc, oneshot};
+use futures::future::Future;
 use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Error as IoError};
 use futures::stream::StreamExt;
 use futures::task::SpawnExt;
 use safelog::sensitive;
+use std::collections::HashMap;
 use std::io::Result as IoResult;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
-#[cfg(feature = "rpc")]
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
 use tracing::{debug, error, info, warn};
 
 #[allow(unused)]
 use arti_client::HasKind;
 use arti_client::{ErrorKind, IntoTorAddr as _, StreamPrefs, TorClient};
+use tor_async_utils::{relay, RelayConfig, RelayEnd};
 use tor_config::Listen;
 use tor_error::warn_report;
 #[cfg(feature = "rpc")]
 use tor_rpcbase::{self as rpc};
-use tor_rtcompat::{Runtime, TcpListener};
+use tor_rtcompat::{Runtime, SleepProvider, TcpListener};
 use tor_socksproto::{SocksAddr, SocksAuth, SocksCmd, SocksRequest};
 
 use anyhow::{anyhow, Context, Result};
 
+use crate::automap::{self, AutomapTable};
+use crate::{ProxyConfig, VirtualAddrNetwork};
+
 /// Payload to return when an HTTP connection arrive on a Socks port
 const WRONG_PROTOCOL_PAYLOAD: &[u8] = br#"HTTP/1.0 501 Tor is not an HTTP Proxy
 Content-Type: text/html; charset=utf-8
@@ -78,10 +86,10 @@ fn stream_preference(req: &SocksRequest, addr: &str) -> StreamPrefs {
 /// Composed of an usize (representing which listener socket accepted
 /// the connection, the source IpAddr of the client, and the
 /// authentication string provided by the client).
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct SocksIsolationKey(ConnIsolation, ProvidedIsolation);
 /// Isolation information provided through the socks connection
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 enum ProvidedIsolation {
     /// The socks isolation itself.
     Auth(SocksAuth),
@@ -165,6 +173,7 @@ fn interpret_socks_auth(auth: &SocksAuth) -> Result<AuthInterpretation> {
 }
 
 /// Information used to implement a SOCKS connection.
+#[derive(Clone)]
 struct SocksConnContext<R: Runtime> {
     /// A TorClient to use (by default) to anonymize requests.
     tor_client: TorClient<R>,
@@ -172,6 +181,189 @@ struct SocksConnContext<R: Runtime> {
     /// sessions.
     #[cfg(feature = "rpc")]
     rpc_mgr: Option<Arc<arti_rpcserver::RpcMgr>>,
+    /// The connections that we are currently handling.
+    active: ActiveConns,
+    /// The limits that we enforce on our connections.
+    limits: Arc<Mutex<SocksLimits>>,
+    /// The connections that we are currently handling, by state and source address.
+    limiter: ConnLimiter,
+    /// If present, the synthetic addresses that we hand out for onion services.
+    automap: SharedAutomap,
+}
+
+/// The synthetic addresses that a SOCKS proxy hands out for onion services,
+/// if it does so, for each isolation group.
+///
+/// This is shared with the [`SocksProxyHandle`], so that it can be replaced
+/// on reconfiguration.
+type SharedAutomap = Arc<Mutex<Option<Arc<AutomapTable<SocksIsolationKey>>>>>;
+
+/// Return the network from which `config` says to hand out synthetic
+/// addresses for onion services, if it says to do so.
+pub(crate) fn automap_network(config: &ProxyConfig) -> Option<VirtualAddrNetwork> {
+    config
+        .automap_hosts_on_resolve
+        .then_some(config.virtual_addr_network)
+}
+
+/// Limits on the SOCKS connections that a proxy will handle, to protect it
+/// from slow or abusive clients.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) struct SocksLimits {
+    /// How long a client may take to finish its SOCKS handshake.
+    handshake_timeout: Duration,
+    /// How long we may take to answer a request.
+    request_timeout: Duration,
+    /// How long a connection may go without any data in either direction;
+    /// `None` for no limit.
+    idle_timeout: Option<Duration>,
+    /// How many connections may be in their handshake at once; 0 for no limit.
+    max_pending_handshakes: usize,
+    /// How many connections we handle at once from one IP; 0 for no limit.
+    max_conns_per_ip: usize,
+    /// Whether our clients understand Tor's extended SOCKS5 error codes.
+    extended_errors: bool,
+}
+
+impl SocksLimits {
+    /// Return the limits configured in `config`.
+    #[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+    pub(crate) fn from_config(config: &ProxyConfig) -> Self {
+        SocksLimits {
+            handshake_timeout: config.socks_handshake_timeout,
+            request_timeout: config.socks_request_timeout,
+            idle_timeout: Some(config.socks_idle_timeout).filter(|t| !t.is_zero()),
+            max_pending_handshakes: config.socks_max_pending_handshakes,
+            max_conns_per_ip: config.socks_max_conns_per_ip,
+            extended_errors: config.socks_extended_errors,
+        }
+    }
+}
+
+impl Default for SocksLimits {
+    fn default() -> Self {
+        Self::from_config(&ProxyConfig::default())
+    }
+}
+
+/// Tracks the SOCKS connections that a proxy is handling, to enforce
+/// the connection limits in [`SocksLimits`].
+#[derive(Clone, Default)]
+struct ConnLimiter(Arc<Mutex<ConnLimiterState>>);
+
+/// The state of a [`ConnLimiter`].
+#[derive(Default)]
+struct ConnLimiterState {
+    /// The number of connections that haven't finished their handshake.
+    pending: usize,
+    /// The number of connections from each source address.
+    by_ip: HashMap<IpAddr, usize>,
+}
+
+/// A guard that counts one connection in a [`ConnLimiter`] until it is dropped.
+struct AdmittedConn {
+    /// The limiter that admitted this connection.
+    limiter: ConnLimiter,
+    /// The connection's source address.
+    ip: IpAddr,
+    /// True if the connection hasn't yet finished its handshake.
+    pending: bool,
+}
+
+impl ConnLimiter {
+    /// Count a new connection from `ip`, if doing so wouldn't go over `limits`.
+    ///
+    /// The connection counts as pending until [`AdmittedConn::handshake_done`]
+    /// is called, and counts against `ip` until the returned guard is dropped.
+    fn try_admit(&self, ip: IpAddr, limits: &SocksLimits) -> Option<AdmittedConn> {
+        let mut state = self.0.lock().expect("lock poisoned");
+        let from_ip = state.by_ip.get(&ip).copied().unwrap_or(0);
+        if limits.max_pending_handshakes != 0 && state.pending >= limits.max_pending_handshakes {
+            return None;
+        }
+        if limits.max_conns_per_ip != 0 && from_ip >= limits.max_conns_per_ip {
+            return None;
+        }
+        state.pending += 1;
+        *state.by_ip.entry(ip).or_insert(0) += 1;
+        Some(AdmittedConn {
+            limiter: self.clone(),
+            ip,
+            pending: true,
+        })
+    }
+}
+
+impl AdmittedConn {
+    /// Note that this connection has finished its SOCKS handshake.
+    fn handshake_done(&mut self) {
+        if std::mem::replace(&mut self.pending, false) {
+            let mut state = self.limiter.0.lock().expect("lock poisoned");
+            state.pending -= 1;
+        }
+    }
+}
+
+impl Drop for AdmittedConn {
+    fn drop(&mut self) {
+        let mut state = self.limiter.0.lock().expect("lock poisoned");
+        if self.pending {
+            state.pending -= 1;
+        }
+        if let std::collections::hash_map::Entry::Occupied(mut ent) = state.by_ip.entry(self.ip) {
+            *ent.get_mut() -= 1;
+            if *ent.get() == 0 {
+                ent.remove();
+            }
+        }
+    }
+}
+
+/// A count of the SOCKS connections that a proxy is currently handling.
+///
+/// A connection counts from when we accept it until we have finished relaying
+/// its data (or, for other requests, until we have sent our reply).
+#[derive(Clone, Default)]
+pub(crate) struct ActiveConns(Arc<AtomicUsize>);
+
+/// A guard that counts one connection in an [`ActiveConns`] until it is dropped.
+struct ActiveConnGuard(Arc<AtomicUsize>);
+
+/// How often to check whether our connections have finished, while draining.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+impl ActiveConns {
+    /// Count a new connection, until the returned guard is dropped.
+    fn enter(&self) -> ActiveConnGuard {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        ActiveConnGuard(self.0.clone())
+    }
+
+    /// Return the number of connections that we are handling.
+    pub(crate) fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Wait until we have no connections, or until `timeout` has elapsed.
+    ///
+    /// Returns the number of connections that are still open.
+    pub(crate) async fn drain<SP: SleepProvider>(&self, runtime: &SP, timeout: Duration) -> usize {
+        let start = runtime.now();
+        loop {
+            let n = self.count();
+            if n == 0 || runtime.now().saturating_duration_since(start) >= timeout {
+                return n;
+            }
+            runtime.sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    }
+}
+
+impl Drop for ActiveConnGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 /// Type alias for the isolation information associated with a given SOCKS
@@ -239,88 +431,75 @@ impl<R: Runtime> SocksConnContext<R> {
 /// Uses `isolation_info` to decide which circuits this connection
 /// may use.  Requires that `isolation_info` is a pair listing the listener
 /// id and the source address for the socks request.
+///
+/// `admitted` counts this connection against our connection limits.
 async fn handle_socks_conn<R, S>(
     runtime: R,
     context: SocksConnContext<R>,
     socks_stream: S,
     isolation_info: ConnIsolation,
+    mut admitted: AdmittedConn,
 ) -> Result<()>
 where
     R: Runtime,
     S: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static,
 {
+    let limits = context.limits.lock().expect("lock poisoned").clone();
+    let active = context.active.enter();
+    let (mut socks_r, mut socks_w) = socks_stream.split();
+
     // Part 1: Perform the SOCKS handshake, to learn where we are
     // being asked to connect, and what we're being asked to do once
     // we connect there.
-    //
-    // The SOCKS handshake can require multiple round trips (SOCKS5
-    // always does) so we we need to run this part of the process in a
-    // loop.
-    let mut handshake = tor_socksproto::SocksProxyHandshake::new();
-
-    let (mut socks_r, mut socks_w) = socks_stream.split();
-    let mut inbuf = [0_u8; 1024];
-    let mut n_read = 0;
-    let request = loop {
-        if n_read == inbuf.len() {
-            // We would like to read more of this SOCKS request, but there is no
-            // more space in the buffer.  If we try to keep reading into an
-            // empty buffer, we'll just read nothing, try to parse it, and learn
-            // that we still wish we had more to read.
-            //
-            // In theory we might want to resize the buffer.  Right now, though,
-            // we just reject handshakes that don't fit into 1k.
-            return Err(anyhow!("Socks handshake did not fit in 1KiB buffer"));
-        }
-        // Read some more stuff.
-        n_read += socks_r
-            .read(&mut inbuf[n_read..])
-            .await
-            .context("Error while reading SOCKS handshake")?;
-
-        // try to advance the handshake to the next state.
-        let action = match handshake.handshake(&inbuf[..n_read]) {
-            Err(_) => continue, // Message truncated.
-            Ok(Err(e)) => {
-                if let tor_socksproto::Error::BadProtocol(version) = e {
-                    // check for HTTP methods: CONNECT, DELETE, GET, HEAD, OPTION, PUT, POST, PATCH and
-                    // TRACE.
-                    // To do so, check the first byte of the connection, which happen to be placed
-                    // where SOCKs version field is.
-                    if [b'C', b'D', b'G', b'H', b'O', b'P', b'T'].contains(&version) {
-                        write_all_and_close(&mut socks_w, WRONG_PROTOCOL_PAYLOAD).await?;
-                    }
-                }
-                // if there is an handshake error, don't reply with a Socks error, remote does not
-                // seems to speak Socks.
-                return Err(e.into());
-            }
-            Ok(Ok(action)) => action,
-        };
-
-        // reply if needed.
-        if action.drain > 0 {
-            inbuf.copy_within(action.drain..action.drain + n_read, 0);
-            n_read -= action.drain;
-        }
-        if !action.reply.is_empty() {
-            write_all_and_flush(&mut socks_w, &action.reply).await?;
-        }
-        if action.finished {
-            break handshake.into_request();
-        }
-    };
-    let request = match request {
+    let request = runtime
+        .timeout(
+            limits.handshake_timeout,
+            read_socks_request(&mut socks_r, &mut socks_w),
+        )
+        .await
+        .map_err(|_| anyhow!("SOCKS handshake timed out"))??;
+    admitted.handshake_done();
+    let mut request = match request {
         Some(r) => r,
         None => {
             warn!("SOCKS handshake succeeded, but couldn't convert into a request.");
             return Ok(());
         }
     };
+    request.set_extended_errors(limits.extended_errors);
 
     // Unpack the socks request and find out where we're connecting to.
-    let addr = request.addr().to_string();
+    let mut addr = request.addr().to_string();
     let port = request.port();
+    if let SocksAddr::Hostname(_) = request.addr() {
+        // Everything below (including our isolation and automap decisions)
+        // should see the same name that the exit will.
+        match arti_client::canonicalize_hostname(&addr) {
+            Ok(canonical) => addr = canonical,
+            Err(e) => {
+                debug!("Rejecting SOCKS request for {}: {}", sensitive(&addr), e);
+                return reply_error(&mut socks_w, &request, ErrorKind::InvalidStreamTarget, None)
+                    .await;
+            }
+        }
+    }
+    // Synthetic addresses are only meaningful within the isolation group
+    // that they were handed out to.
+    let automap = context.automap.lock().expect("lock poisoned").clone();
+    let automap = match automap {
+        Some(table) => {
+            let interp = interpret_socks_auth(request.auth())?;
+            Some((table, SocksIsolationKey(isolation_info, interp.isolation)))
+        }
+        None => None,
+    };
+    if let (Some((table, group)), SocksCmd::CONNECT) = (&automap, request.command()) {
+        // If this is an address that we handed out for an onion service,
+        // connect to the onion service instead.
+        if let Some(host) = addr.parse().ok().and_then(|ip| table.lookup(group, ip)) {
+            addr = host;
+        }
+    }
     debug!(
         "Got a socks request: {} {}:{}",
         request.command(),
@@ -335,27 +514,64 @@ where
             // The SOCKS request wants us to connect to a given address.
             // So, launch a connection over Tor.
             let tor_addr = (addr.clone(), port).into_tor_addr()?;
-            let tor_stream = tor_client.connect_with_prefs(&tor_addr, &prefs).await;
+            let tor_stream = runtime
+                .timeout(
+                    limits.request_timeout,
+                    tor_client.connect_with_prefs(&tor_addr, &prefs),
+                )
+                .await;
             let tor_stream = match tor_stream {
-                Ok(s) => s,
-                Err(e) => return reply_error(&mut socks_w, &request, e.kind()).await,
+                Ok(Ok(s)) => s,
+                Ok(Err(e)) => {
+                    let status = onion_failure_status(&e);
+                    return reply_error(&mut socks_w, &request, e.kind(), status).await;
+                }
+                Err(_) => return reply_timeout(&mut socks_w, &request).await,
             };
             // Okay, great! We have a connection over the Tor network.
             debug!("Got a stream for {}:{}", sensitive(&addr), port);
 
             // Send back a SOCKS response, telling the client that it
-            // successfully connected.
-            let reply = request
-                .reply(tor_socksproto::SocksStatus::SUCCEEDED, None)
-                .context("Encoding socks reply")?;
+            // successfully connected.  If the exit told us what address it
+            // connected to, we pass that on as the bound address.  (The exit
+            // doesn't tell us a port, so we use the one that was requested.)
+            let reply = match tor_stream.connected_addr() {
+                Some(bound) => request.encode_reply(&tor_socksproto::SocksReply::new(
+                    tor_socksproto::SocksStatus::SUCCEEDED,
+                    SocksAddr::Ip(bound),
+                    port,
+                )),
+                None => request.reply(tor_socksproto::SocksStatus::SUCCEEDED, None),
+            }
+            .context("Encoding socks reply")?;
             write_all_and_flush(&mut socks_w, &reply[..]).await?;
 
             let (tor_r, tor_w) = tor_stream.split();
 
-            // Finally, spawn two background tasks to relay traffic between
+            // Finally, spawn a background task to relay traffic between
             // the socks stream and the tor stream.
-            runtime.spawn(copy_interactive(socks_r, tor_w).map(|_| ()))?;
-            runtime.spawn(copy_interactive(tor_r, socks_w).map(|_| ()))?;
+            //
+            // The connection stays active (and keeps counting against our
+            // connection limits) until the relay is done.
+            let relay_config = RelayConfig::new().idle_timeout(limits.idle_timeout);
+            let relay_runtime = runtime.clone();
+            runtime.spawn(async move {
+                let _active = (active, admitted);
+                match relay(
+                    &relay_runtime,
+                    &relay_config,
+                    (socks_r, socks_w),
+                    (tor_r, tor_w),
+                )
+                .await
+                {
+                    Ok(RelayEnd::IdleTimeout) => {
+                        debug!("Closing idle stream to {}:{}", sensitive(&addr), port)
+                    }
+                    Ok(_) => {}
+                    Err(e) => debug!("Stream to {}:{} failed: {}", sensitive(&addr), port, e),
+                }
+            })?;
         }
         SocksCmd::RESOLVE => {
             // We've been asked to perform a regular hostname lookup.
@@ -364,12 +580,26 @@ where
             let addr = if let Ok(addr) = addr.parse() {
                 // if this is a valid ip address, just parse it and reply.
                 Ok(addr)
+            } else if let Some((table, group)) = automap
+                .as_ref()
+                .filter(|_| automap::should_automap(&addr))
+            {
+                // Hand out a synthetic address, which we'll recognize if we're
+                // later asked to connect to it.
+                table.map(group.clone(), &addr).ok_or(ErrorKind::Other)
             } else {
-                tor_client
-                    .resolve_with_prefs(&addr, &prefs)
+                match runtime
+                    .timeout(
+                        limits.request_timeout,
+                        tor_client.resolve_with_prefs(&addr, &prefs),
+                    )
                     .await
-                    .map_err(|e| e.kind())
-                    .and_then(|addrs| addrs.first().copied().ok_or(ErrorKind::Other))
+                {
+                    Ok(addrs) => addrs
+                        .map_err(|e| e.kind())
+                        .and_then(|addrs| addrs.first().copied().ok_or(ErrorKind::Other)),
+                    Err(_) => return reply_timeout(&mut socks_w, &request).await,
+                }
             };
             match addr {
                 Ok(addr) => {
@@ -381,7 +611,7 @@ where
                         .context("Encoding socks reply")?;
                     write_all_and_close(&mut socks_w, &reply[..]).await?;
                 }
-                Err(e) => return reply_error(&mut socks_w, &request, e).await,
+                Err(e) => return reply_error(&mut socks_w, &request, e, None).await,
             }
         }
         SocksCmd::RESOLVE_PTR => {
@@ -397,9 +627,22 @@ where
                     return Err(anyhow!(e));
                 }
             };
-            let hosts = match tor_client.resolve_ptr_with_prefs(addr, &prefs).await {
-                Ok(hosts) => hosts,
-                Err(e) => return reply_error(&mut socks_w, &request, e.kind()).await,
+            let automapped = automap
+                .as_ref()
+                .and_then(|(table, group)| table.lookup(group, addr));
+            let hosts = match automapped {
+                Some(host) => vec![host],
+                None => match runtime
+                    .timeout(
+                        limits.request_timeout,
+                        tor_client.resolve_ptr_with_prefs(addr, &prefs),
+                    )
+                    .await
+                {
+                    Ok(Ok(hosts)) => hosts,
+                    Ok(Err(e)) => return reply_error(&mut socks_w, &request, e.kind(), None).await,
+                    Err(_) => return reply_timeout(&mut socks_w, &request).await,
+                },
             };
             if let Some(host) = hosts.into_iter().next() {
                 // this conversion should never fail, legal DNS names len must be <= 253 but Socks
@@ -427,6 +670,75 @@ where
     Ok(())
 }
 
+/// Read a SOCKS handshake from `socks_r`, replying on `socks_w` as needed,
+/// and return the request that it contains.
+///
+/// The SOCKS handshake can require multiple round trips (SOCKS5
+/// always does) so we we need to run this in a loop.
+async fn read_socks_request<R, W>(socks_r: &mut R, socks_w: &mut W) -> Result<Option<SocksRequest>>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut handshake = tor_socksproto::SocksProxyHandshake::new();
+    let mut inbuf = [0_u8; 1024];
+    let mut n_read = 0;
+    let request = loop {
+        if n_read == inbuf.len() {
+            // We would like to read more of this SOCKS request, but there is no
+            // more space in the buffer.  If we try to keep reading into an
+            // empty buffer, we'll just read nothing, try to parse it, and learn
+            // that we still wish we had more to read.
+            //
+            // In theory we might want to resize the buffer.  Right now, though,
+            // we just reject handshakes that don't fit into 1k.
+            return Err(anyhow!("Socks handshake did not fit in 1KiB buffer"));
+        }
+        // Read some more stuff.
+        let n = socks_r
+            .read(&mut inbuf[n_read..])
+            .await
+            .context("Error while reading SOCKS handshake")?;
+        if n == 0 {
+            return Err(anyhow!("Connection closed during SOCKS handshake"));
+        }
+        n_read += n;
+
+        // try to advance the handshake to the next state.
+        let action = match handshake.handshake(&inbuf[..n_read]) {
+            Err(_) => continue, // Message truncated.
+            Ok(Err(e)) => {
+                if let tor_socksproto::Error::BadProtocol(version) = e {
+                    // check for HTTP methods: CONNECT, DELETE, GET, HEAD, OPTION, PUT, POST, PATCH and
+                    // TRACE.
+                    // To do so, check the first byte of the connection, which happen to be placed
+                    // where SOCKs version field is.
+                    if [b'C', b'D', b'G', b'H', b'O', b'P', b'T'].contains(&version) {
+                        write_all_and_close(socks_w, WRONG_PROTOCOL_PAYLOAD).await?;
+                    }
+                }
+                // if there is an handshake error, don't reply with a Socks error, remote does not
+                // seems to speak Socks.
+                return Err(e.into());
+            }
+            Ok(Ok(action)) => action,
+        };
+
+        // reply if needed.
+        if action.drain > 0 {
+            inbuf.copy_within(action.drain..action.drain + n_read, 0);
+            n_read -= action.drain;
+        }
+        if !action.reply.is_empty() {
+            write_all_and_flush(socks_w, &action.reply).await?;
+        }
+        if action.finished {
+            break handshake.into_request();
+        }
+    };
+    Ok(request)
+}
+
 /// write_all the data to the writer & flush the writer if write_all is successful.
 async fn write_all_and_flush<W>(writer: &mut W, buf: &[u8]) -> Result<()>
 where
@@ -457,35 +769,80 @@ where
         .context("Error while closing SOCKS stream")
 }
 
+/// Return the extended SOCKS status for `error`, if it was a failure to
+/// connect to an onion service.
+///
+/// These are the `X'F0'` through `X'F7'` codes from proposal 304, which are
+/// more specific than anything we can derive from an `ErrorKind`.
+fn onion_failure_status(error: &arti_client::Error) -> Option<tor_socksproto::SocksStatus> {
+    #[cfg(feature = "onion-service-client")]
+    {
+        use {arti_client::HsConnFailure as F, tor_socksproto::SocksStatus as S};
+        Some(match error.onion_service_failure()? {
+            F::DescriptorNotFound => S::HS_DESC_NOT_FOUND,
+            F::DescriptorInvalid => S::HS_DESC_INVALID,
+            F::IntroFailed => S::HS_INTRO_FAILED,
+            F::RendezvousFailed => S::HS_REND_FAILED,
+            F::MissingClientAuth => S::HS_MISSING_CLIENT_AUTH,
+            F::WrongClientAuth => S::HS_WRONG_CLIENT_AUTH,
+            F::BadAddress => S::HS_BAD_ADDRESS,
+            F::IntroTimeout => S::HS_INTRO_TIMEOUT,
+            _ => return None,
+        })
+    }
+    #[cfg(not(feature = "onion-service-client"))]
+    {
+        let _ = error;
+        None
+    }
+}
+
 /// Reply a Socks error based on an arti-client Error and close the stream.
 /// Returns the error provided in parameter
+///
+/// If `status` is provided, we send it; otherwise, we pick a status based on
+/// `error`.
 async fn reply_error<W>(
     writer: &mut W,
     request: &SocksRequest,
     error: arti_client::ErrorKind,
+    status: Option<tor_socksproto::SocksStatus>,
 ) -> Result<()>
 where
     W: AsyncWrite + Unpin,
 {
+    // We need to send an error. See what kind it is.
+    let status = status.unwrap_or_else(|| error_status(error));
+    let reply = request
+        .reply(status, None)
+        .context("Encoding socks reply")?;
+    // if writing back the error fail, still return the original error
+    let _ = write_all_and_close(writer, &reply[..]).await;
+
+    Err(anyhow!(error))
+}
+
+/// Return the SOCKS status that best describes a failure of kind `error`.
+fn error_status(error: ErrorKind) -> tor_socksproto::SocksStatus {
     use {tor_socksproto::SocksStatus as S, ErrorKind as EK};
 
-    // TODO: Currently we _always_ try to return extended SOCKS return values
-    // for onion service failures from proposal 304 when they are appropriate.
-    // But according to prop 304, this is something we should only do when it's
-    // requested, for compatibility with SOCKS implementations that can't handle
-    // unexpected REP codes.
-    //
-    // I suggest we make these extended error codes "always-on" for now, and
-    // later add a feature to disable them if it's needed. -nickm
+    // We always pick the extended SOCKS return values for onion service
+    // failures from proposal 304 when they are appropriate.  If
+    // `proxy.socks_extended_errors` is off, the request knows that its client
+    // can't handle them, and replaces them when it encodes the reply.
 
     // TODO: Perhaps we should map the extended SOCKS return values for onion
     // service failures unconditionally, even if we haven't compiled in onion
     // service client support.  We can make that change after the relevant
     // ErrorKinds are no longer `experimental-api` in `tor-error`.
 
-    // We need to send an error. See what kind it is.
-    let status = match error {
-        EK::RemoteNetworkFailed => S::TTL_EXPIRED,
+    match error {
+        // These are mostly the ways that an exit reports, with the reason in
+        // its END message, that it couldn't make the connection.
+        EK::RemoteHostResolutionFailed => S::HOST_UNREACHABLE,
+        EK::RemoteConnectionRefused | EK::RemoteStreamReset => S::CONNECTION_REFUSED,
+        EK::ExitPolicyRejected => S::NOT_ALLOWED,
+        EK::RemoteNetworkFailed | EK::ExitTimeout => S::TTL_EXPIRED,
 
         #[cfg(feature = "onion-service-client")]
         EK::OnionServiceNotFound => S::HS_DESC_NOT_FOUND,
@@ -507,73 +864,22 @@ where
         | EK::OnionServiceProtocolViolation => S::HS_INTRO_FAILED,
 
         _ => S::GENERAL_FAILURE,
-    };
-    let reply = request
-        .reply(status, None)
-        .context("Encoding socks reply")?;
-    // if writing back the error fail, still return the original error
-    let _ = write_all_and_close(writer, &reply[..]).await;
-
-    Err(anyhow!(error))
+    }
 }
 
-/// Copy all the data from `reader` into `writer` until we encounter an EOF or
-/// an error.
-///
-/// Unlike as futures::io::copy(), this function is meant for use with
-/// interactive readers and writers, where the reader might pause for
-/// a while, but where we want to send data on the writer as soon as
-/// it is available.
-///
-/// This function assumes that the writer might need to be flushed for
-/// any buffered data to be sent.  It tries to minimize the number of
-/// flushes, however, by only flushing the writer when the reader has no data.
-async fn copy_interactive<R, W>(mut reader: R, mut writer: W) -> IoResult<()>
+/// Reply to `request` with an error, because we took too long to answer it,
+/// and close the stream.
+async fn reply_timeout<W>(writer: &mut W, request: &SocksRequest) -> Result<()>
 where
-    R: AsyncRead + Unpin,
     W: AsyncWrite + Unpin,
 {
-    use futures::{poll, task::Poll};
-
-    let mut buf = [0_u8; 1024];
-
-    // At this point we could just loop, calling read().await,
-    // write_all().await, and flush().await.  But we want to be more
-    // clever than that: we only want to flush when the reader is
-    // stalled.  That way we can pack our data into as few cells as
-    // possible, but flush it immediately whenever there's no more
-    // data coming.
-    let loop_result: IoResult<()> = loop {
-        let mut read_future = reader.read(&mut buf[..]);
-        match poll!(&mut read_future) {
-            Poll::Ready(Err(e)) => break Err(e),
-            Poll::Ready(Ok(0)) => break Ok(()), // EOF
-            Poll::Ready(Ok(n)) => {
-                writer.write_all(&buf[..n]).await?;
-                continue;
-            }
-            Poll::Pending => writer.flush().await?,
-        }
-
-        // The read future is pending, so we should wait on it.
-        match read_future.await {
-            Err(e) => break Err(e),
-            Ok(0) => break Ok(()),
-            Ok(n) => writer.write_all(&buf[..n]).await?,
-        }
-    };
-
-    // Make sure that we flush any lingering data if we can.
-    //
-    // If there is a difference between closing and dropping, then we
-    // only want to do a "proper" close if the reader closed cleanly.
-    let flush_result = if loop_result.is_ok() {
-        writer.close().await
-    } else {
-        writer.flush().await
-    };
-
-    loop_result.or(flush_result)
+    reply_error(
+        writer,
+        request,
+        ErrorKind::TorNetworkTimeout,
+        Some(tor_socksproto::SocksStatus::TTL_EXPIRED),
+    )
+    .await
 }
 
 /// Return true if a given IoError, when received from accept, is a fatal
@@ -608,83 +914,438 @@ pub(crate) async fn run_socks_proxy<R: Runtime>(
     runtime: R,
     tor_client: TorClient<R>,
     listen: Listen,
+    limits: SocksLimits,
+    automap: Option<VirtualAddrNetwork>,
     // TODO RPC: This is not a good way to make an API conditional. We MUST
     // refactor this before the RPC feature becomes non-experimental.
     #[cfg(feature = "rpc")] rpc_mgr: Option<Arc<arti_rpcserver::RpcMgr>>,
 ) -> Result<()> {
-    let mut listeners = Vec::new();
+    let (_handle, proxy) = launch_socks_proxy(
+        runtime,
+        tor_client,
+        listen,
+        limits,
+        automap,
+        #[cfg(feature = "rpc")]
+        rpc_mgr,
+    )
+    .await?;
+    proxy.await
+}
+
+/// Launch a SOCKS proxy to listen on the addresses in `listen`, enforcing
+/// `limits` on its connections.
+///
+/// If `automap` is present, the proxy answers RESOLVE requests for onion
+/// services with synthetic addresses from that network.
+///
+/// Returns a [`SocksProxyHandle`] that can be used to change those addresses
+/// while the proxy is running, and a future that runs the proxy indefinitely.
+///
+/// The proxy stops if the future is dropped, or if it encounters a fatal
+/// error.
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) async fn launch_socks_proxy<R: Runtime>(
+    runtime: R,
+    tor_client: TorClient<R>,
+    listen: Listen,
+    limits: SocksLimits,
+    automap: Option<VirtualAddrNetwork>,
+    #[cfg(feature = "rpc")] rpc_mgr: Option<Arc<arti_rpcserver::RpcMgr>>,
+) -> Result<(SocksProxyHandle, impl Future<Output = Result<()>> + Send)> {
+    let (errors_tx, mut errors_rx) = mpsc::unbounded();
+    let active = ActiveConns::default();
+    let limits = Arc::new(Mutex::new(limits));
+    let automap: SharedAutomap = Arc::new(Mutex::new(
+        automap.map(|net| Arc::new(AutomapTable::new(net))),
+    ));
+    let mut listeners = SocksListeners {
+        runtime,
+        context: SocksConnContext {
+            tor_client,
+            #[cfg(feature = "rpc")]
+            rpc_mgr,
+            active: active.clone(),
+            limits: limits.clone(),
+            limiter: ConnLimiter::default(),
+            automap: automap.clone(),
+        },
+        running: HashMap::new(),
+        next_id: 0,
+        errors: errors_tx,
+    };
 
     // Try to bind to the SOCKS ports.
-    match listen.ip_addrs() {
-        Ok(addrgroups) => {
-            for addrgroup in addrgroups {
-                for addr in addrgroup {
-                    match runtime.listen(&addr).await {
-                        Ok(listener) => {
-                            info!("Listening on {:?}.", addr);
-                            listeners.push(listener);
-                        }
-                        #[cfg(unix)]
-                        Err(ref e) if e.raw_os_error() == Some(libc::EAFNOSUPPORT) => {
-                            warn_report!(e, "Address family not supported {}", addr);
-                        }
-                        Err(ref e) => {
-                            return Err(anyhow!("Can't listen on {}: {e}", addr));
-                        }
-                    }
-                }
+    if let Err(e) = listeners.set_addrs(listen_addrs(&listen)).await {
+        error!("Couldn't open any SOCKS listeners.");
+        return Err(e);
+    }
+
+    let (requests_tx, mut requests_rx) = mpsc::unbounded::<ListenRequest>();
+    let handle = SocksProxyHandle {
+        requests: requests_tx,
+        listen: Mutex::new(listen),
+        active,
+        limits,
+        automap,
+    };
+
+    let proxy = async move {
+        loop {
+            futures::select! {
+                req = requests_rx.next() => if let Some(req) = req {
+                    let res = listeners.set_addrs(req.addrs).await;
+                    let _ignore_closed = req.reply.send(res);
+                },
+                err = errors_rx.next() => if let Some(err) = err {
+                    return Err(err).context("Failed to receive incoming stream on SOCKS port");
+                },
+                complete => return Ok(()),
             }
         }
-        Err(e) => warn_report!(e, "Invalid listen spec"),
+    };
+
+    Ok((handle, proxy))
+}
+
+/// Return every address that `listen` asks us to listen on.
+fn listen_addrs(listen: &Listen) -> Vec<SocketAddr> {
+    match listen.ip_addrs() {
+        Ok(addrgroups) => addrgroups.flatten().collect(),
+        Err(e) => {
+            warn_report!(e, "Invalid listen spec");
+            vec![]
+        }
     }
+}
 
-    // We weren't able to bind any ports: There's nothing to do.
-    if listeners.is_empty() {
-        error!("Couldn't open any SOCKS listeners.");
-        return Err(anyhow!("Couldn't open SOCKS listeners"));
+/// A request to change the addresses that a running SOCKS proxy listens on.
+struct ListenRequest {
+    /// The addresses to listen on.
+    addrs: Vec<SocketAddr>,
+    /// Where to send the outcome.
+    reply: oneshot::Sender<Result<()>>,
+}
+
+/// A handle to a running SOCKS proxy, used to change its listeners.
+///
+/// Dropping this handle does not stop the proxy.
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) struct SocksProxyHandle {
+    /// Channel to the task running the proxy.
+    requests: mpsc::UnboundedSender<ListenRequest>,
+    /// The listeners that the proxy was most recently told to use.
+    listen: Mutex<Listen>,
+    /// The connections that the proxy is handling.
+    active: ActiveConns,
+    /// The limits that the proxy enforces on new connections.
+    limits: Arc<Mutex<SocksLimits>>,
+    /// The synthetic addresses that the proxy hands out for onion services.
+    automap: SharedAutomap,
+}
+
+impl SocksProxyHandle {
+    /// Return a count of the connections that this proxy is handling.
+    ///
+    /// Connections that have already been accepted keep running after the
+    /// proxy future is dropped; use this to wait for them to finish.
+    pub(crate) fn active_conns(&self) -> ActiveConns {
+        self.active.clone()
     }
 
-    // Create a stream of (incoming socket, listener_id) pairs, selected
-    // across all the listeners.
-    let mut incoming = futures::stream::select_all(
-        listeners
-            .into_iter()
-            .map(TcpListener::incoming)
-            .enumerate()
-            .map(|(listener_id, incoming_conns)| {
-                incoming_conns.map(move |socket| (socket, listener_id))
-            }),
-    );
+    /// Make the proxy listen on exactly the addresses in `listen`.
+    ///
+    /// Listeners whose address is unchanged are kept, and connections that
+    /// have already been accepted are never affected.  If we can't bind to
+    /// one of the new addresses, the proxy keeps its old listeners.
+    ///
+    /// Blocks until the proxy has rebound its listeners: do not call this
+    /// from within an async task.
+    #[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+    pub(crate) fn set_listen(&self, listen: &Listen) -> Result<()> {
+        let mut current = self.listen.lock().expect("lock poisoned");
+        if *current == *listen {
+            return Ok(());
+        }
+        let (reply, outcome) = oneshot::channel();
+        self.requests
+            .unbounded_send(ListenRequest {
+                addrs: listen_addrs(listen),
+                reply,
+            })
+            .map_err(|_| anyhow!("SOCKS proxy is not running"))?;
+        futures::executor::block_on(outcome)
+            .map_err(|_| anyhow!("SOCKS proxy exited while changing listeners"))??;
+        *current = listen.clone();
+        Ok(())
+    }
+}
+
+impl crate::reload_cfg::ReconfigurableModule for SocksProxyHandle {
+    fn reconfigure(
+        &self,
+        new: &crate::ArtiCombinedConfig,
+        report: &mut crate::reload_cfg::ReconfigureReport,
+    ) -> Result<()> {
+        let limits = SocksLimits::from_config(new.0.proxy());
+        {
+            let mut current = self.limits.lock().expect("lock poisoned");
+            if *current != limits {
+                *current = limits;
+                report.applied("SOCKS connection limits changed");
+            }
+        }
+
+        let network = automap_network(new.0.proxy());
+        {
+            let mut current = self.automap.lock().expect("lock poisoned");
+            if current.as_ref().map(|table| table.network()) != network {
+                // Existing mappings are forgotten: their addresses may not
+                // even be in the new network.
+                *current = network.map(|net| Arc::new(AutomapTable::new(net)));
+                report.applied("SOCKS automapping of onion services changed");
+            }
+        }
+
+        let listen = &new.0.proxy().socks_listen;
+        if *self.listen.lock().expect("lock poisoned") == *listen {
+            return Ok(());
+        }
+        match self.set_listen(listen) {
+            Ok(()) => report.applied(format!("SOCKS listeners changed to {}", listen)),
+            Err(e) => report.needs_restart(format!(
+                "SOCKS listeners {}: {}",
+                listen,
+                tor_error::Report(e)
+            )),
+        }
+        Ok(())
+    }
+}
+
+/// The set of listeners for a running SOCKS proxy.
+struct SocksListeners<R: Runtime> {
+    /// The runtime to use for binding and launching tasks.
+    runtime: R,
+    /// Information to handle each incoming connection.
+    context: SocksConnContext<R>,
+    /// The addresses we're listening on.
+    ///
+    /// Each listener runs in its own task, which exits when the corresponding
+    /// sender is dropped.
+    running: HashMap<SocketAddr, oneshot::Sender<()>>,
+    /// The identifier to give the next listener we open.
+    ///
+    /// We never reuse these, since they're used for stream isolation.
+    next_id: usize,
+    /// Channel on which the listener tasks report fatal errors.
+    errors: mpsc::UnboundedSender<IoError>,
+}
+
+impl<R: Runtime> SocksListeners<R> {
+    /// Listen on exactly `addrs`.
+    ///
+    /// On error, leave the existing listeners in place.
+    async fn set_addrs(&mut self, addrs: Vec<SocketAddr>) -> Result<()> {
+        let mut opened = Vec::new();
+        for addr in &addrs {
+            if self.running.contains_key(addr) {
+                continue;
+            }
+            match self.runtime.listen(addr).await {
+                Ok(listener) => {
+                    info!("Listening on {:?}.", addr);
+                    opened.push((*addr, listener));
+                }
+                #[cfg(unix)]
+                Err(ref e) if e.raw_os_error() == Some(libc::EAFNOSUPPORT) => {
+                    warn_report!(e, "Address family not supported {}", addr);
+                }
+                Err(ref e) => {
+                    return Err(anyhow!("Can't listen on {}: {e}", addr));
+                }
+            }
+        }
+
+        let n_kept = self.running.keys().filter(|a| addrs.contains(a)).count();
+        if !addrs.is_empty() && n_kept + opened.len() == 0 {
+            return Err(anyhow!("Couldn't open SOCKS listeners"));
+        }
 
-    // Loop over all incoming connections.  For each one, call
-    // handle_socks_conn() in a new task.
-    while let Some((stream, sock_id)) = incoming.next().await {
+        // Dropping the senders stops the listeners that we no longer want.
+        self.running.retain(|addr, _| {
+            let keep = addrs.contains(addr);
+            if !keep {
+                info!("No longer listening on {:?}.", addr);
+            }
+            keep
+        });
+        for (addr, listener) in opened {
+            let (stop_tx, stop_rx) = oneshot::channel();
+            let listener_id = self.next_id;
+            self.next_id += 1;
+            let runtime = self.runtime.clone();
+            let context = self.context.clone();
+            let errors = self.errors.clone();
+            self.runtime.spawn(async move {
+                if let Err(e) = accept_loop(runtime, context, listener, listener_id, stop_rx).await
+                {
+                    let _ignore_closed = errors.unbounded_send(e);
+                }
+            })?;
+            self.running.insert(addr, stop_tx);
+        }
+
+        // Tell RPC clients where they can open their streams.
+        #[cfg(feature = "rpc")]
+        if let Some(mgr) = &self.context.rpc_mgr {
+            mgr.set_socks_proxies(self.running.keys().copied().collect());
+        }
+        Ok(())
+    }
+}
+
+/// Accept connections on `listener` until `stop` is cancelled, and handle each
+/// one in a new task.
+async fn accept_loop<R: Runtime>(
+    runtime: R,
+    context: SocksConnContext<R>,
+    listener: R::TcpListener,
+    listener_id: usize,
+    mut stop: oneshot::Receiver<()>,
+) -> IoResult<()> {
+    let mut incoming = listener.incoming().fuse();
+    loop {
+        let stream = futures::select_biased! {
+            _ = stop => return Ok(()),
+            stream = incoming.next() => match stream {
+                Some(stream) => stream,
+                None => return Ok(()),
+            },
+        };
         let (stream, addr) = match stream {
             Ok((s, a)) => (s, a),
             Err(err) => {
                 if accept_err_is_fatal(&err) {
-                    return Err(err).context("Failed to receive incoming stream on SOCKS port");
+                    return Err(err);
                 } else {
                     warn_report!(err, "Incoming stream failed");
                     continue;
                 }
             }
         };
-        let socks_context = SocksConnContext {
-            tor_client: tor_client.clone(),
-            #[cfg(feature = "rpc")]
-            rpc_mgr: rpc_mgr.clone(),
+        let admitted = {
+            let limits = context.limits.lock().expect("lock poisoned");
+            context.limiter.try_admit(addr.ip(), &limits)
+        };
+        let Some(admitted) = admitted else {
+            debug!(
+                "Closing SOCKS connection from {}: too many connections",
+                sensitive(addr.ip())
+            );
+            continue;
         };
+        let socks_context = context.clone();
         let runtime_copy = runtime.clone();
-        runtime.spawn(async move {
-            let res =
-                handle_socks_conn(runtime_copy, socks_context, stream, (sock_id, addr.ip())).await;
-            if let Err(e) = res {
-                // TODO: warn_report doesn't work on anyhow::Error.
-                warn!("connection exited with error: {}", tor_error::Report(e));
-            }
-        })?;
+        runtime
+            .spawn(async move {
+                let res = handle_socks_conn(
+                    runtime_copy,
+                    socks_context,
+                    stream,
+                    (listener_id, addr.ip()),
+                    admitted,
+                )
+                .await;
+                #[cfg(feature = "metrics")]
+                crate::metrics::note_socks_conn(res.is_ok());
+                if let Err(e) = res {
+                    // TODO: warn_report doesn't work on anyhow::Error.
+                    warn!("connection exited with error: {}", tor_error::Report(e));
+                }
+            })
+            .map_err(|e| IoError::new(std::io::ErrorKind::Other, e))?;
     }
+}
 
-    Ok(())
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    #[test]
+    fn conn_limits() {
+        let limits = SocksLimits {
+            max_pending_handshakes: 2,
+            max_conns_per_ip: 2,
+            ..SocksLimits::default()
+        };
+        let limiter = ConnLimiter::default();
+        let ip1: IpAddr = "127.0.0.1".parse().unwrap();
+        let ip2: IpAddr = "127.0.0.2".parse().unwrap();
+
+        let mut a = limiter.try_admit(ip1, &limits).unwrap();
+        let b = limiter.try_admit(ip1, &limits).unwrap();
+        // Too many pending handshakes.
+        assert!(limiter.try_admit(ip2, &limits).is_none());
+
+        a.handshake_done();
+        // Too many connections from ip1, but ip2 is fine.
+        assert!(limiter.try_admit(ip1, &limits).is_none());
+        let c = limiter.try_admit(ip2, &limits).unwrap();
+
+        drop(b);
+        drop(c);
+        let _d = limiter.try_admit(ip1, &limits).unwrap();
+        drop(a);
+        let state = limiter.0.lock().unwrap();
+        assert_eq!(state.pending, 1);
+        assert_eq!(state.by_ip.get(&ip1), Some(&1));
+        assert_eq!(state.by_ip.get(&ip2), None);
+    }
+
+    #[test]
+    fn unlimited() {
+        let limits = SocksLimits {
+            max_pending_handshakes: 0,
+            max_conns_per_ip: 0,
+            ..SocksLimits::default()
+        };
+        let limiter = ConnLimiter::default();
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let conns: Vec<_> = (0..1000)
+            .map(|_| limiter.try_admit(ip, &limits).unwrap())
+            .collect();
+        assert_eq!(conns.len(), 1000);
+    }
+
+    #[test]
+    fn end_reason_status() {
+        use tor_cell::relaycell::msg::EndReason;
+        use tor_socksproto::SocksStatus as S;
+
+        // The error kinds that we get when an exit ends a stream with a
+        // given reason.
+        let status = |reason: EndReason| error_status(reason.kind());
+
+        assert_eq!(status(EndReason::RESOLVEFAILED), S::HOST_UNREACHABLE);
+        assert_eq!(status(EndReason::CONNECTREFUSED), S::CONNECTION_REFUSED);
+        assert_eq!(status(EndReason::CONNRESET), S::CONNECTION_REFUSED);
+        assert_eq!(status(EndReason::EXITPOLICY), S::NOT_ALLOWED);
+        assert_eq!(status(EndReason::TIMEOUT), S::TTL_EXPIRED);
+        assert_eq!(status(EndReason::NOROUTE), S::TTL_EXPIRED);
+        assert_eq!(status(EndReason::MISC), S::GENERAL_FAILURE);
+    }
 }
@@ -9,3 +9,6 @@ pub(crate) mod hss;
     feature = "keymgr"
 ))]
 pub(crate) mod hsc;
+
+#[cfg(feature = "experimental-api")]
+pub(crate) mod storage;
@@ -0,0 +1,77 @@
+//! The `storage` subcommand.
+
+use crate::{Result, TorClient};
+
+use anyhow::anyhow;
+use arti_client::{CheckSeverity, TorClientConfig};
+use clap::{ArgMatches, Args, FromArgMatches, Parser, Subcommand};
+
+/// The storage subcommands the arti CLI will be augmented with.
+#[derive(Parser, Debug)]
+pub(crate) enum StorageSubcommands {
+    /// Run maintenance commands for Arti's state directory and keystores.
+    #[command(subcommand)]
+    Storage(StorageSubcommand),
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum StorageSubcommand {
+    /// Check the state directory and keystores for problems.
+    Check(CheckArgs),
+}
+
+/// The arguments of the [`Check`](StorageSubcommand::Check)
+/// subcommand.
+#[derive(Debug, Clone, Args)]
+pub(crate) struct CheckArgs {
+    /// Fix trivial problems, such as stale temporary files and lockfiles.
+    ///
+    /// State that is in use by a running Arti is never changed.
+    #[arg(long)]
+    repair: bool,
+
+    /// Print the report as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+/// Run the `storage` subcommand.
+pub(crate) fn run(storage_matches: &ArgMatches, config: &TorClientConfig) -> Result<()> {
+    let subcommand = StorageSubcommand::from_arg_matches(storage_matches)
+        .expect("Could not parse storage subcommand");
+
+    match subcommand {
+        StorageSubcommand::Check(args) => check(&args, config),
+    }
+}
+
+/// Run the `storage check` subcommand.
+fn check(args: &CheckArgs, config: &TorClientConfig) -> Result<()> {
+    // TODO: PreferredRuntime is unused here, as in the `hss` subcommand.
+    let report = TorClient::<tor_rtcompat::PreferredRuntime>::check_storage(config, args.repair)?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else if report.problems.is_empty() {
+        println!("No problems found.");
+    } else {
+        for problem in &report.problems {
+            let repaired = if problem.repaired() {
+                " (repaired)"
+            } else {
+                ""
+            };
+            println!(
+                "{}: {}: {}{repaired}",
+                problem.severity(),
+                problem.path().display(),
+                problem.description(),
+            );
+        }
+    }
+
+    if report.worst() == Some(CheckSeverity::Error) {
+        return Err(anyhow!("Storage check found errors"));
+    }
+    Ok(())
+}
@@ -0,0 +1,898 @@
+//! Translate a C Tor `torrc` file into Arti configuration.
+//!
+//! Only a subset of C Tor's options have Arti equivalents.  We translate the
+//! ones that do, and report every other option as a [`Problem`], so that
+//! a user migrating from C Tor can see exactly what was (and wasn't) carried
+//! over.
+//!
+//! The result is a TOML document, suitable for use as a
+//! [`ConfigurationSource`](tor_config::ConfigurationSource).
+
+use std::fmt::{self, Display};
+use std::net::SocketAddr;
+
+use toml::{Table, Value};
+
+/// The result of translating a `torrc`.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) struct Translated {
+    /// The Arti configuration equivalent to the options we could translate.
+    pub(crate) config: Table,
+    /// Every option that we could not translate exactly.
+    pub(crate) problems: Vec<Problem>,
+}
+
+impl Translated {
+    /// Return the translated configuration as a TOML document.
+    pub(crate) fn to_toml(&self) -> String {
+        toml::to_string(&self.config).expect("Could not serialize a TOML table")
+    }
+}
+
+/// A `torrc` option that we could not translate exactly.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) struct Problem {
+    /// The line on which the option began (counting from 1).
+    pub(crate) line: usize,
+    /// The option's keyword, as written in the `torrc`.
+    pub(crate) keyword: String,
+    /// What was wrong with it.
+    pub(crate) kind: ProblemKind,
+}
+
+/// The kind of a [`Problem`].
+#[derive(Clone, Debug, Eq, PartialEq, thiserror::Error)]
+#[non_exhaustive]
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) enum ProblemKind {
+    /// We know this option, but Arti has no equivalent; it was ignored.
+    #[error("not supported by Arti: {0}")]
+    Unsupported(&'static str),
+    /// We translated this option, but some of its meaning was lost.
+    #[error("only partially translated: {0}")]
+    Partial(String),
+    /// We don't know this option at all; it was ignored.
+    #[error("unrecognized option")]
+    Unrecognized,
+    /// We could not parse this option's value; it was ignored.
+    #[error("invalid value: {0}")]
+    Invalid(String),
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "torrc line {}: {}: {}",
+            self.line, self.keyword, self.kind
+        )
+    }
+}
+
+/// Options which we recognize, but for which Arti has no equivalent.
+const UNSUPPORTED: &[(&str, &str)] = &[
+    ("exitnodes", "Arti cannot yet restrict which relays it uses"),
+    (
+        "entrynodes",
+        "Arti cannot yet restrict which relays it uses",
+    ),
+    (
+        "middlenodes",
+        "Arti cannot yet restrict which relays it uses",
+    ),
+    (
+        "excludenodes",
+        "Arti cannot yet restrict which relays it uses",
+    ),
+    (
+        "excludeexitnodes",
+        "Arti cannot yet restrict which relays it uses",
+    ),
+    (
+        "strictnodes",
+        "Arti cannot yet restrict which relays it uses",
+    ),
+    ("controlport", "Arti does not implement the control port"),
+    ("controlsocket", "Arti does not implement the control port"),
+    (
+        "cookieauthentication",
+        "Arti does not implement the control port",
+    ),
+    (
+        "hashedcontrolpassword",
+        "Arti does not implement the control port",
+    ),
+    ("runasdaemon", "Arti does not daemonize itself"),
+    ("user", "Arti does not change its user ID"),
+    ("geoipfile", "Arti does not use a GeoIP database"),
+    ("geoipv6file", "Arti does not use a GeoIP database"),
+    (
+        "clientonionauthdir",
+        "use the `arti hsc` subcommands instead",
+    ),
+    ("orport", "Arti cannot yet run as a relay"),
+    ("dirport", "Arti cannot yet run as a relay"),
+    ("nickname", "Arti cannot yet run as a relay"),
+    ("contactinfo", "Arti cannot yet run as a relay"),
+    ("myfamily", "Arti cannot yet run as a relay"),
+    ("exitpolicy", "Arti cannot yet run as a relay"),
+    ("exitrelay", "Arti cannot yet run as a relay"),
+    ("bridgerelay", "Arti cannot yet run as a relay"),
+    ("relaybandwidthrate", "Arti cannot yet run as a relay"),
+    ("relaybandwidthburst", "Arti cannot yet run as a relay"),
+];
+
+/// Translate the contents of a `torrc` file into Arti configuration.
+///
+/// Never fails: anything that can't be translated is reported in
+/// [`Translated::problems`] and otherwise ignored.
+#[cfg_attr(feature = "experimental-api", visibility::make(pub))]
+pub(crate) fn translate(torrc: &str) -> Translated {
+    let mut t = Translator::default();
+    for (line, keyword, value) in torrc_lines(torrc) {
+        t.option(line, &keyword, &value);
+    }
+    t.finish()
+}
+
+/// Split `torrc` into `(line number, keyword, value)` triples.
+///
+/// Handles comments, blank lines, and lines continued with a trailing `\`.
+fn torrc_lines(torrc: &str) -> Vec<(usize, String, String)> {
+    /// Split a complete logical line into an entry in the output.
+    fn entry(start: usize, text: &str) -> Option<(usize, String, String)> {
+        let text = text.trim();
+        if text.is_empty() {
+            return None;
+        }
+        let (keyword, value) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        Some((start, keyword.to_owned(), value.trim().to_owned()))
+    }
+
+    let mut out = vec![];
+    let mut pending: Option<(usize, String)> = None;
+
+    for (idx, raw) in torrc.lines().enumerate() {
+        let line = raw.split('#').next().unwrap_or("");
+        let (line, continued) = match line.trim_end().strip_suffix('\\') {
+            Some(l) => (l, true),
+            None => (line, false),
+        };
+        let (start, mut text) = pending.take().unwrap_or((idx + 1, String::new()));
+        text.push(' ');
+        text.push_str(line);
+        if continued {
+            pending = Some((start, text));
+        } else {
+            out.extend(entry(start, &text));
+        }
+    }
+    if let Some((start, text)) = pending {
+        out.extend(entry(start, &text));
+    }
+    out
+}
+
+/// State used while translating a `torrc`.
+#[derive(Default)]
+struct Translator {
+    /// The configuration built so far.
+    config: Table,
+    /// The problems found so far.
+    problems: Vec<Problem>,
+    /// Listen addresses from `SocksPort` lines.
+    socks: Listeners,
+    /// Listen addresses from `DNSPort` lines.
+    dns: Listeners,
+    /// The nickname of the onion service from the most recent `HiddenServiceDir`.
+    onion_service: Option<String>,
+}
+
+/// Listen addresses accumulated from one kind of `...Port` option.
+#[derive(Default)]
+struct Listeners {
+    /// Did we see this option at all?
+    seen: bool,
+    /// The addresses to listen on.
+    addrs: Vec<Value>,
+}
+
+impl Listeners {
+    /// Return the value for this listener in Arti's configuration, if any.
+    fn into_value(self) -> Option<Value> {
+        if !self.seen {
+            None
+        } else if self.addrs.is_empty() {
+            Some(Value::Integer(0))
+        } else {
+            Some(Value::Array(self.addrs))
+        }
+    }
+}
+
+impl Translator {
+    /// Translate a single option.
+    fn option(&mut self, line: usize, keyword: &str, value: &str) {
+        // C Tor allows "+" and "/" prefixes to control how options from
+        // several files are combined.  We only ever read one file.
+        let name = keyword.trim_start_matches(['+', '/']).to_ascii_lowercase();
+
+        // Each arm returns `Ok(None)` if the option was translated exactly,
+        // `Ok(Some(_))` to report a problem, or `Err(_)` if the value was bad.
+        let result = match name.as_str() {
+            "socksport" => Self::port(&mut self.socks, value),
+            "dnsport" => Self::port(&mut self.dns, value),
+            "usebridges" => parse_bool(value).map(|b| {
+                self.set(&["bridges", "enabled"], Value::Boolean(b));
+                None
+            }),
+            "bridge" => {
+                self.push(&["bridges", "bridges"], Value::String(value.to_owned()));
+                Ok(None)
+            }
+            "clienttransportplugin" => self.transport(value),
+            "hiddenservicedir" => self.onion_service_dir(value),
+            "hiddenserviceport" => self.onion_service_port(value),
+            "hiddenservicenumintroductionpoints" => {
+                self.onion_service_int("num_intro_points", value)
+            }
+            "hiddenservicemaxstreams" => {
+                self.onion_service_int("max_concurrent_streams_per_circuit", value)
+            }
+            "hiddenservicemaxstreamsclosecircuit" => {
+                self.onion_service_bool("max_concurrent_streams_close_circuit", value)
+            }
+            "hiddenserviceversion" => match value {
+                "3" => Ok(None),
+                _ => Err("only version 3 onion services are supported".to_owned()),
+            },
+            "safelogging" => self.safe_logging(value),
+            "log" => self.log(value),
+            "longlivedports" => self.long_lived_ports(value),
+            "reachableaddresses" => self.reachable_addresses(value),
+            "fascistfirewall" => parse_bool(value).map(|b| {
+                if b && !self.has(&["path_rules", "reachable_addrs"]) {
+                    self.set(
+                        &["path_rules", "reachable_addrs"],
+                        Value::Array(vec!["*:80".into(), "*:443".into()]),
+                    );
+                }
+                None
+            }),
+            "datadirectory" => {
+                self.set(&["storage", "state_dir"], Value::String(value.to_owned()));
+                Ok(Some(ProblemKind::Partial(
+                    "Arti's state is not compatible with C Tor's; existing state will not be reused"
+                        .to_owned(),
+                )))
+            }
+            "cachedirectory" => {
+                self.set(&["storage", "cache_dir"], Value::String(value.to_owned()));
+                Ok(None)
+            }
+            "pidfile" => {
+                self.set(
+                    &["application", "pid_file"],
+                    Value::String(value.to_owned()),
+                );
+                Ok(None)
+            }
+            _ => Ok(Some(match UNSUPPORTED.iter().find(|(k, _)| *k == name) {
+                Some((_, why)) => ProblemKind::Unsupported(why),
+                None => ProblemKind::Unrecognized,
+            })),
+        };
+
+        let kind = match result {
+            Ok(None) => return,
+            Ok(Some(kind)) => kind,
+            Err(e) => ProblemKind::Invalid(e),
+        };
+        self.problems.push(Problem {
+            line,
+            keyword: keyword.to_owned(),
+            kind,
+        });
+    }
+
+    /// Finish translating, and return the result.
+    fn finish(mut self) -> Translated {
+        let socks = std::mem::take(&mut self.socks);
+        let dns = std::mem::take(&mut self.dns);
+        if let Some(v) = socks.into_value() {
+            self.set(&["proxy", "socks_listen"], v);
+        }
+        if let Some(v) = dns.into_value() {
+            self.set(&["proxy", "dns_listen"], v);
+        }
+        Translated {
+            config: self.config,
+            problems: self.problems,
+        }
+    }
+
+    /// Return the table at `path`, creating it (and its parents) if needed.
+    fn table(&mut self, path: &[&str]) -> &mut Table {
+        let mut table = &mut self.config;
+        for k in path {
+            let entry = table
+                .entry(*k)
+                .or_insert_with(|| Value::Table(Table::new()));
+            if !entry.is_table() {
+                *entry = Value::Table(Table::new());
+            }
+            table = match entry {
+                Value::Table(t) => t,
+                _ => unreachable!(),
+            };
+        }
+        table
+    }
+
+    /// Return true if there is a value at `path`.
+    fn has(&self, path: &[&str]) -> bool {
+        let mut value = None;
+        let mut table = Some(&self.config);
+        for k in path {
+            value = table.and_then(|t| t.get(*k));
+            table = value.and_then(Value::as_table);
+        }
+        value.is_some()
+    }
+
+    /// Set the value at `path`, replacing any previous value.
+    fn set(&mut self, path: &[&str], value: Value) {
+        let (last, parents) = path.split_last().expect("empty path");
+        self.table(parents).insert((*last).to_owned(), value);
+    }
+
+    /// Append `value` to the array at `path`, creating it if needed.
+    fn push(&mut self, path: &[&str], value: Value) {
+        let (last, parents) = path.split_last().expect("empty path");
+        let entry = self
+            .table(parents)
+            .entry(*last)
+            .or_insert_with(|| Value::Array(vec![]));
+        if let Value::Array(a) = entry {
+            a.push(value);
+        } else {
+            *entry = Value::Array(vec![value]);
+        }
+    }
+
+    /// Handle `SocksPort` or `DNSPort`.
+    fn port(listeners: &mut Listeners, value: &str) -> Result<Option<ProblemKind>, String> {
+        let mut words = value.split_whitespace();
+        let addr = words.next().unwrap_or("");
+        let flags = words.collect::<Vec<_>>();
+
+        if addr == "auto" {
+            return Ok(Some(ProblemKind::Unsupported(
+                "Arti cannot choose a port automatically",
+            )));
+        }
+        if addr.starts_with("unix:") {
+            return Ok(Some(ProblemKind::Unsupported(
+                "Arti cannot yet listen on AF_UNIX sockets",
+            )));
+        }
+        let item = if let Ok(port) = addr.parse::<u16>() {
+            if port == 0 {
+                // "0" disables the listener, unless another line enables it.
+                listeners.seen = true;
+                return Ok(None);
+            }
+            Value::Integer(port.into())
+        } else if let Ok(sa) = addr.parse::<SocketAddr>() {
+            Value::String(sa.to_string())
+        } else {
+            return Err(format!("{:?} is not a port or address", addr));
+        };
+        listeners.seen = true;
+        listeners.addrs.push(item);
+
+        Ok((!flags.is_empty()).then(|| {
+            ProblemKind::Partial(format!(
+                "Arti's listeners do not take flags; ignoring {}",
+                flags.join(" ")
+            ))
+        }))
+    }
+
+    /// Handle `ClientTransportPlugin`.
+    fn transport(&mut self, value: &str) -> Result<Option<ProblemKind>, String> {
+        let mut words = value.split_whitespace();
+        let (Some(protocols), Some(method)) = (words.next(), words.next()) else {
+            return Err("expected a transport list and a method".to_owned());
+        };
+        let protocols = protocols
+            .split(',')
+            .map(|p| Value::String(p.to_owned()))
+            .collect();
+
+        let mut transport = Table::new();
+        transport.insert("protocols".into(), Value::Array(protocols));
+        match method {
+            "exec" => {
+                let Some(path) = words.next() else {
+                    return Err("missing path to transport binary".to_owned());
+                };
+                transport.insert("path".into(), Value::String(path.to_owned()));
+                let arguments = words.map(|a| Value::String(a.to_owned())).collect();
+                transport.insert("arguments".into(), Value::Array(arguments));
+            }
+            "socks5" => {
+                let Some(addr) = words.next() else {
+                    return Err("missing proxy address".to_owned());
+                };
+                transport.insert("proxy_addr".into(), Value::String(addr.to_owned()));
+            }
+            "socks4" => {
+                return Ok(Some(ProblemKind::Unsupported(
+                    "Arti only supports unmanaged transports over SOCKS5",
+                )));
+            }
+            other => return Err(format!("unknown transport method {:?}", other)),
+        }
+        self.push(&["bridges", "transports"], Value::Table(transport));
+        Ok(None)
+    }
+
+    /// Handle `HiddenServiceDir`, which begins a new onion service.
+    fn onion_service_dir(&mut self, value: &str) -> Result<Option<ProblemKind>, String> {
+        let base = value
+            .trim_end_matches(['/', '\\'])
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or("");
+        let nickname = base
+            .chars()
+            .map(|c| match c.to_ascii_lowercase() {
+                c @ ('a'..='z' | '0'..='9' | '_' | '-') => c,
+                _ => '_',
+            })
+            .collect::<String>();
+        let nickname = nickname.trim_start_matches('-').to_owned();
+        if nickname.is_empty() {
+            self.onion_service = None;
+            return Err(format!("can't derive a nickname from {:?}", value));
+        }
+        self.table(&["onion_services", nickname.as_str()]);
+        let note = format!(
+            "configured as onion service {:?}; its keys in {:?} are not imported, \
+             so it will have a new .onion address",
+            nickname, value
+        );
+        self.onion_service = Some(nickname);
+        Ok(Some(ProblemKind::Partial(note)))
+    }
+
+    /// Return the nickname of the current onion service, or an error.
+    fn current_onion_service(&self) -> Result<String, String> {
+        self.onion_service
+            .clone()
+            .ok_or_else(|| "must follow a valid HiddenServiceDir".to_owned())
+    }
+
+    /// Handle `HiddenServicePort`.
+    fn onion_service_port(&mut self, value: &str) -> Result<Option<ProblemKind>, String> {
+        let nickname = self.current_onion_service()?;
+        let mut words = value.split_whitespace();
+        let Some(virt) = words.next() else {
+            return Err("missing virtual port".to_owned());
+        };
+        let virt_port: u16 = virt
+            .parse()
+            .map_err(|_| format!("{:?} is not a port", virt))?;
+        let target = match words.next() {
+            None => format!("127.0.0.1:{}", virt_port),
+            Some(t) if t.starts_with("unix:") => {
+                return Ok(Some(ProblemKind::Unsupported(
+                    "Arti cannot yet forward onion service connections to AF_UNIX sockets",
+                )));
+            }
+            Some(t) => match t.parse::<u16>() {
+                Ok(port) => format!("127.0.0.1:{}", port),
+                Err(_) => t.to_owned(),
+            },
+        };
+        let rule = Value::Array(vec![
+            Value::String(virt_port.to_string()),
+            Value::String(target),
+        ]);
+        self.push(&["onion_services", nickname.as_str(), "proxy_ports"], rule);
+        Ok(None)
+    }
+
+    /// Handle an onion service option that takes an integer.
+    fn onion_service_int(&mut self, key: &str, value: &str) -> Result<Option<ProblemKind>, String> {
+        let nickname = self.current_onion_service()?;
+        let n: u32 = value
+            .parse()
+            .map_err(|_| format!("{:?} is not a number", value))?;
+        self.set(
+            &["onion_services", nickname.as_str(), key],
+            Value::Integer(n.into()),
+        );
+        Ok(None)
+    }
+
+    /// Handle an onion service option that takes a boolean,
+    /// and translates directly to `key`.
+    fn onion_service_bool(
+        &mut self,
+        key: &str,
+        value: &str,
+    ) -> Result<Option<ProblemKind>, String> {
+        let nickname = self.current_onion_service()?;
+        let b = parse_bool(value)?;
+        self.set(
+            &["onion_services", nickname.as_str(), key],
+            Value::Boolean(b),
+        );
+        Ok(None)
+    }
+
+    /// Handle `SafeLogging`.
+    fn safe_logging(&mut self, value: &str) -> Result<Option<ProblemKind>, String> {
+        let (safe, problem) = match value {
+            "relay" => (
+                true,
+                Some(ProblemKind::Partial(
+                    "Arti has no \"relay\" mode; logs will be scrubbed".to_owned(),
+                )),
+            ),
+            _ => (parse_bool(value)?, None),
+        };
+        self.set(
+            &["logging", "log_sensitive_information"],
+            Value::Boolean(!safe),
+        );
+        Ok(problem)
+    }
+
+    /// Handle `Log`.
+    fn log(&mut self, value: &str) -> Result<Option<ProblemKind>, String> {
+        let mut words = value.split_whitespace();
+        let Some(severity) = words.next() else {
+            return Err("missing severity".to_owned());
+        };
+        if severity.starts_with('[') {
+            return Ok(Some(ProblemKind::Unsupported(
+                "Arti does not support per-domain log severities",
+            )));
+        }
+        // C Tor logs everything from the minimum severity upwards; a maximum
+        // can't be expressed in Arti, so we drop it.
+        let (min, max) = match severity.split_once('-') {
+            Some((min, max)) => (min, Some(max)),
+            None => (severity, None),
+        };
+        let (filter, mut problem) = match min {
+            "debug" | "info" | "warn" => (min, None),
+            "err" => ("error", None),
+            "notice" => (
+                "info",
+                Some("Arti has no \"notice\" level; using \"info\"".to_owned()),
+            ),
+            _ => return Err(format!("unknown severity {:?}", min)),
+        };
+        if max.is_some_and(|m| m != "err") {
+            problem = Some(format!(
+                "Arti cannot limit the maximum severity; ignoring {:?}",
+                severity
+            ));
+        }
+
+        match words.next() {
+            Some("stdout" | "stderr") | None => {
+                self.set(&["logging", "console"], Value::String(filter.to_owned()));
+            }
+            Some("file") => {
+                let Some(path) = words.next() else {
+                    return Err("missing log file name".to_owned());
+                };
+                let mut file = Table::new();
+                file.insert("path".into(), Value::String(path.to_owned()));
+                file.insert("filter".into(), Value::String(filter.to_owned()));
+                self.push(&["logging", "files"], Value::Table(file));
+            }
+            Some("syslog") => {
+                return Ok(Some(ProblemKind::Unsupported(
+                    "Arti cannot log to syslog; consider logging.journald",
+                )));
+            }
+            Some(other) => return Err(format!("unknown log destination {:?}", other)),
+        }
+        Ok(problem.map(ProblemKind::Partial))
+    }
+
+    /// Handle `LongLivedPorts`.
+    fn long_lived_ports(&mut self, value: &str) -> Result<Option<ProblemKind>, String> {
+        let ports = value
+            .split(',')
+            .map(|p| {
+                let p = p.trim();
+                p.parse::<u16>()
+                    .map(|p| Value::Integer(p.into()))
+                    .map_err(|_| format!("{:?} is not a port", p))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.set(&["path_rules", "long_lived_ports"], Value::Array(ports));
+        Ok(None)
+    }
+
+    /// Handle `ReachableAddresses`.
+    ///
+    /// Arti only has a list of permitted addresses, so we can translate
+    /// `accept` rules, and a final `reject *:*`.
+    fn reachable_addresses(&mut self, value: &str) -> Result<Option<ProblemKind>, String> {
+        let mut addrs = vec![];
+        let mut problem = None;
+        for rule in value.split(',').map(str::trim) {
+            let (action, pattern) = match rule.split_once(char::is_whitespace) {
+                Some((a, p)) => (a.to_ascii_lowercase(), p.trim()),
+                None => ("accept".to_owned(), rule),
+            };
+            match (action.as_str(), pattern) {
+                ("accept", p) => addrs.push(Value::String(p.to_owned())),
+                ("reject", "*:*") => {}
+                ("reject", p) => {
+                    problem = Some(ProblemKind::Partial(format!(
+                        "Arti cannot express \"reject {}\"; ignoring it",
+                        p
+                    )));
+                }
+                (a, _) => return Err(format!("unknown policy action {:?}", a)),
+            }
+        }
+        self.set(&["path_rules", "reachable_addrs"], Value::Array(addrs));
+        Ok(problem)
+    }
+}
+
+/// Parse a C Tor boolean.
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "1" => Ok(true),
+        "0" => Ok(false),
+        _ => Err(format!("expected 0 or 1, not {:?}", value)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use crate::ArtiCombinedConfig;
+
+    /// Translate `torrc`, and return the result as a `toml::Value` for easy comparison.
+    fn tr(torrc: &str) -> (Value, Vec<Problem>) {
+        let t = translate(torrc);
+        (Value::Table(t.config), t.problems)
+    }
+
+    /// Parse `s` as a TOML value.
+    fn v(s: &str) -> Value {
+        Value::Table(toml::from_str(s).unwrap())
+    }
+
+    /// Check that `t` is accepted by Arti without any unrecognized or deprecated keys.
+    fn check_resolves(t: &Translated) {
+        let mut sources = tor_config::ConfigurationSources::new_empty();
+        sources.push_source(
+            tor_config::ConfigurationSource::from_verbatim(t.to_toml()),
+            tor_config::sources::MustRead::MustRead,
+        );
+        let cfg = sources.load().unwrap();
+        let res = tor_config::resolve_return_results::<ArtiCombinedConfig>(cfg).unwrap();
+        assert!(res.unrecognized.is_empty(), "{:?}", res.unrecognized);
+        assert!(res.deprecated.is_empty(), "{:?}", res.deprecated);
+    }
+
+    #[test]
+    fn lines() {
+        let got = torrc_lines(
+            "# comment\n\
+             \n\
+             SocksPort 9050 # trailing\n\
+             Bridge obfs4 \\\n  192.0.2.1:443   \\\n  cert=x\n\
+             UseBridges",
+        );
+        assert_eq!(
+            got,
+            vec![
+                (3, "SocksPort".into(), "9050".into()),
+                (4, "Bridge".into(), "obfs4 192.0.2.1:443 cert=x".into()),
+                (7, "UseBridges".into(), "".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn ports() {
+        let (cfg, problems) = tr("socksport 9150\nSocksPort [::1]:9050 IsolateDestAddr\nDNSPort 0");
+        assert_eq!(
+            cfg,
+            v(r#"proxy = { socks_listen = [9150, "[::1]:9050"], dns_listen = 0 }"#)
+        );
+        assert_eq!(
+            problems,
+            vec![Problem {
+                line: 2,
+                keyword: "SocksPort".into(),
+                kind: ProblemKind::Partial(
+                    "Arti's listeners do not take flags; ignoring IsolateDestAddr".into()
+                ),
+            }]
+        );
+
+        let (cfg, problems) = tr("SocksPort auto\nDNSPort bogus");
+        assert_eq!(cfg, v(""));
+        assert!(matches!(problems[0].kind, ProblemKind::Unsupported(_)));
+        assert!(matches!(problems[1].kind, ProblemKind::Invalid(_)));
+    }
+
+    #[test]
+    fn bridges() {
+        let torrc = "\
+UseBridges 1
+Bridge 192.0.2.66:443 8C00000DFE0046ABCDFAD191144399CB520C29E8
+Bridge obfs4 192.0.2.55:38114 316E643333645F6D79216558614D3931657A5F5F cert=YXJlIGZyZXF1ZW50bHkgZnVsbCBvZiBsaXR0bGUgbWVzc2FnZXMgeW91IGNhbiBmaW5kLg iat-mode=0
+ClientTransportPlugin obfs4,obfs5 exec /usr/bin/obfs4proxy -enableLogging
+ClientTransportPlugin meek socks5 127.0.0.1:31337
+";
+        let t = translate(torrc);
+        assert_eq!(t.problems, vec![]);
+        assert_eq!(
+            Value::Table(t.config.clone()),
+            v(r#"
+[bridges]
+enabled = true
+bridges = [
+  "192.0.2.66:443 8C00000DFE0046ABCDFAD191144399CB520C29E8",
+  "obfs4 192.0.2.55:38114 316E643333645F6D79216558614D3931657A5F5F cert=YXJlIGZyZXF1ZW50bHkgZnVsbCBvZiBsaXR0bGUgbWVzc2FnZXMgeW91IGNhbiBmaW5kLg iat-mode=0",
+]
+[[bridges.transports]]
+protocols = ["obfs4", "obfs5"]
+path = "/usr/bin/obfs4proxy"
+arguments = ["-enableLogging"]
+[[bridges.transports]]
+protocols = ["meek"]
+proxy_addr = "127.0.0.1:31337"
+"#)
+        );
+        #[cfg(feature = "pt-client")]
+        check_resolves(&t);
+    }
+
+    #[test]
+    fn onion_services() {
+        let torrc = "\
+HiddenServicePort 80
+HiddenServiceDir /var/lib/tor/My.Service/
+HiddenServiceVersion 3
+HiddenServicePort 80
+HiddenServicePort 443 192.0.2.1:8443
+HiddenServiceNumIntroductionPoints 5
+HiddenServiceMaxStreamsCloseCircuit 1
+";
+        let t = translate(torrc);
+        assert_eq!(
+            Value::Table(t.config.clone()),
+            v(r#"
+[onion_services.my_service]
+proxy_ports = [["80", "127.0.0.1:80"], ["443", "192.0.2.1:8443"]]
+num_intro_points = 5
+max_concurrent_streams_close_circuit = true
+"#)
+        );
+        let kinds = t
+            .problems
+            .iter()
+            .map(|p| (p.line, std::mem::discriminant(&p.kind)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                (
+                    1,
+                    std::mem::discriminant(&ProblemKind::Invalid(String::new()))
+                ),
+                (
+                    2,
+                    std::mem::discriminant(&ProblemKind::Partial(String::new()))
+                ),
+            ]
+        );
+        #[cfg(feature = "onion-service-service")]
+        check_resolves(&t);
+    }
+
+    #[test]
+    fn logging() {
+        let torrc = "\
+Log notice stdout
+Log debug-warn file /var/log/tor/debug.log
+Log [circ]info stderr
+SafeLogging 0
+";
+        let t = translate(torrc);
+        assert_eq!(
+            Value::Table(t.config.clone()),
+            v(r#"
+[logging]
+console = "info"
+log_sensitive_information = true
+files = [{ path = "/var/log/tor/debug.log", filter = "debug" }]
+"#)
+        );
+        assert_eq!(t.problems.len(), 3);
+        assert!(matches!(t.problems[0].kind, ProblemKind::Partial(_)));
+        assert!(matches!(t.problems[1].kind, ProblemKind::Partial(_)));
+        assert!(matches!(t.problems[2].kind, ProblemKind::Unsupported(_)));
+        check_resolves(&t);
+    }
+
+    #[test]
+    fn path_rules() {
+        let t = translate(
+            "LongLivedPorts 22, 6667\n\
+             ReachableAddresses accept *:80, accept 192.0.2.0/24:*, reject *:*",
+        );
+        assert_eq!(t.problems, vec![]);
+        assert_eq!(
+            Value::Table(t.config.clone()),
+            v(
+                r#"path_rules = { long_lived_ports = [22, 6667], reachable_addrs = ["*:80", "192.0.2.0/24:*"] }"#
+            )
+        );
+        check_resolves(&t);
+
+        let (cfg, _) = tr("FascistFirewall 1");
+        assert_eq!(
+            cfg,
+            v(r#"path_rules = { reachable_addrs = ["*:80", "*:443"] }"#)
+        );
+    }
+
+    #[test]
+    fn pid_file() {
+        let t = translate("PidFile /run/tor/tor.pid");
+        assert_eq!(t.problems, vec![]);
+        assert_eq!(
+            Value::Table(t.config.clone()),
+            v(r#"application = { pid_file = "/run/tor/tor.pid" }"#)
+        );
+        check_resolves(&t);
+    }
+
+    #[test]
+    fn unsupported() {
+        let (cfg, problems) = tr("ExitNodes {de}\nStrictNodes 1\nControlPort 9051\nFrobnicate 7");
+        assert_eq!(cfg, v(""));
+        assert_eq!(
+            problems.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+            vec![
+                "torrc line 1: ExitNodes: not supported by Arti: Arti cannot yet restrict which relays it uses",
+                "torrc line 2: StrictNodes: not supported by Arti: Arti cannot yet restrict which relays it uses",
+                "torrc line 3: ControlPort: not supported by Arti: Arti does not implement the control port",
+                "torrc line 4: Frobnicate: unrecognized option",
+            ]
+        );
+    }
+}
@@ -240,30 +240,77 @@ impl CheckedDir {
     /// This function may give incorrect behavior if multiple threads or
     /// processes are writing to the same file at the same time: it is the
     /// programmer's responsibility to use appropriate locking to avoid this.
+    ///
+    /// On Windows, renaming over a file fails if some other program has it
+    /// open without permitting deletion.  In that case we remove `path` and
+    /// try the rename again.  This is not atomic: a crash at the wrong moment
+    /// can leave no file at `path`, though the new contents will still be in
+    /// the temporary file.
     pub fn write_and_replace<P: AsRef<Path>, C: AsRef<[u8]>>(
         &self,
         path: P,
         contents: C,
     ) -> Result<()> {
-        let path = path.as_ref();
+        self.write_and_replace_inner(path.as_ref(), contents.as_ref(), false)
+    }
+
+    /// As [`write_and_replace`](CheckedDir::write_and_replace), but don't
+    /// return until the new contents have reached the disk.
+    ///
+    /// We `fsync` the temporary file before renaming it, and (on Unix) the
+    /// directory containing `path` afterwards, so that the new contents
+    /// survive a crash of the operating system or a power failure.  (Windows
+    /// offers no way to `fsync` a directory, so there we only sync the file.)
+    ///
+    /// This is much slower than `write_and_replace`.
+    pub fn write_and_replace_synced<P: AsRef<Path>, C: AsRef<[u8]>>(
+        &self,
+        path: P,
+        contents: C,
+    ) -> Result<()> {
+        self.write_and_replace_inner(path.as_ref(), contents.as_ref(), true)
+    }
+
+    /// Helper: Implement `write_and_replace` and `write_and_replace_synced`.
+    fn write_and_replace_inner(&self, path: &Path, contents: &[u8], sync: bool) -> Result<()> {
         self.check_path(path)?;
 
         let tmp_name = path.with_extension("tmp");
-        let mut tmp_file = self.open(
-            &tmp_name,
-            OpenOptions::new().create(true).truncate(true).write(true),
-        )?;
-
-        // Write the data.
-        tmp_file
-            .write_all(contents.as_ref())
-            .map_err(|e| Error::io(e, &tmp_name, "write to file"))?;
-        // Flush and close.
-        drop(tmp_file);
-
-        // Replace the old file.
-        std::fs::rename(self.location.join(tmp_name), self.location.join(path))
-            .map_err(|e| Error::io(e, path, "replace file"))?;
+        let result = (|| {
+            let mut tmp_file = self.open(
+                &tmp_name,
+                OpenOptions::new().create(true).truncate(true).write(true),
+            )?;
+
+            // Write the data.
+            tmp_file
+                .write_all(contents)
+                .map_err(|e| Error::io(e, &tmp_name, "write to file"))?;
+            if sync {
+                tmp_file
+                    .sync_all()
+                    .map_err(|e| Error::io(e, &tmp_name, "sync file"))?;
+            }
+            // Flush and close.  (Windows won't rename an open file.)
+            drop(tmp_file);
+
+            // Replace the old file.
+            rename_replacing(&self.location.join(&tmp_name), &self.location.join(path))
+                .map_err(|e| Error::io(e, path, "replace file"))
+        })();
+
+        if result.is_err() {
+            // Don't leave the temporary file behind.  We're already failing,
+            // so ignore any error here.
+            let _ = std::fs::remove_file(self.location.join(&tmp_name));
+            return result;
+        }
+
+        if sync {
+            if let Some(parent) = self.location.join(path).parent() {
+                sync_dir(parent).map_err(|e| Error::io(e, parent, "sync directory"))?;
+            }
+        }
         Ok(())
     }
 
@@ -299,6 +346,37 @@ impl CheckedDir {
     }
 }
 
+/// Rename `from` to `to`, replacing `to` if it exists.
+///
+/// On Windows, if `to` can't be replaced (probably because it is open), we
+/// remove it and try again.
+fn rename_replacing(from: &Path, to: &Path) -> std::io::Result<()> {
+    match std::fs::rename(from, to) {
+        #[cfg(windows)]
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied && to.exists() => {
+            std::fs::remove_file(to)?;
+            std::fs::rename(from, to)
+        }
+        other => other,
+    }
+}
+
+/// Make sure that changes to the entries in the directory `dir` have reached
+/// the disk.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Make sure that changes to the entries in the directory `dir` have reached
+/// the disk.
+///
+/// (This platform can't sync directories, so we do nothing.)
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     // @@ begin test lint list maintained by maint/add_warning @@
@@ -441,6 +519,21 @@ mod test {
         assert!(!checked.join("bar.tmp").unwrap().exists());
         let s4 = checked.read_to_string("bar.txt").unwrap();
         assert_eq!(s4, "its hard and nobody understands");
+
+        // Synced writes work the same way.
+        checked
+            .write_and_replace_synced("bar.txt", "but we do it anyway")
+            .unwrap();
+        assert!(!checked.join("bar.tmp").unwrap().exists());
+        let s5 = checked.read_to_string("bar.txt").unwrap();
+        assert_eq!(s5, "but we do it anyway");
+
+        // If we can't replace the file, the temp file should be gone too.
+        checked.make_directory("baz/inner").unwrap();
+        checked
+            .write_and_replace("baz", "not a directory")
+            .unwrap_err();
+        assert!(!checked.join("baz.tmp").unwrap().exists());
     }
 
     #[test]
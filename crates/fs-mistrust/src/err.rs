@@ -57,6 +57,28 @@ pub enum Error {
     #[error("Bad owner (UID {1}) on file or directory {}", _0.anonymize_home())]
     BadOwner(PathBuf, u32),
 
+    /// A target (or one of its ancestors) had an untrusted owner.
+    ///
+    /// Only generated on Windows.
+    ///
+    /// The string contains the owner's security identifier (SID).
+    #[error("Bad owner ({1}) on file or directory {}", _0.anonymize_home())]
+    BadOwnerSid(PathBuf, String),
+
+    /// A target (or one of its ancestors) had an access control entry that
+    /// grants access to an untrusted user or group.
+    ///
+    /// Only generated on Windows.
+    #[error("Incorrect access control: {} grants {rights} to {sid}", path.anonymize_home())]
+    BadAccessControl {
+        /// The file or directory with the problem.
+        path: PathBuf,
+        /// The security identifier (SID) of the untrusted user or group.
+        sid: String,
+        /// A description of the access that it is granted.
+        rights: String,
+    },
+
     /// A target (or one of its ancestors) had the wrong type.
     ///
     /// Ordinarily, the target may be anything at all, though you can override
@@ -81,7 +103,7 @@ pub enum Error {
     /// it by calling [`all_errors`](crate::Verifier::all_errors).
     ///
     /// We will never construct an instance of this variant with an empty `Vec`.
-    #[error("Multiple errors found")]
+    #[error("Multiple errors found: {}", join_errors(.0))]
     Multiple(Vec<Box<Error>>),
 
     /// We've realized that we can't finish resolving our path without taking
@@ -174,6 +196,8 @@ impl Error {
                 Error::NotFound(pb) => pb,
                 Error::BadPermission(pb, ..) => pb,
                 Error::BadOwner(pb, _) => pb,
+                Error::BadOwnerSid(pb, _) => pb,
+                Error::BadAccessControl { path: pb, .. } => pb,
                 Error::BadType(pb) => pb,
                 Error::CouldNotInspect(pb, _) => pb,
                 Error::Io { filename: pb, .. } => pb,
@@ -200,7 +224,11 @@ impl Error {
     /// us from looking at permissions in the first place)
     pub fn is_bad_permission(&self) -> bool {
         match self {
-            Error::BadPermission(..) | Error::BadOwner(_, _) | Error::BadType(_) => true,
+            Error::BadPermission(..)
+            | Error::BadOwner(_, _)
+            | Error::BadOwnerSid(_, _)
+            | Error::BadAccessControl { .. }
+            | Error::BadType(_) => true,
 
             Error::NotFound(_)
             | Error::CouldNotInspect(_, _)
@@ -256,6 +284,15 @@ impl std::iter::FromIterator<Error> for Option<Error> {
     }
 }
 
+/// Helper: describe every error in `errors`, for `Error::Multiple`.
+fn join_errors(errors: &[Box<Error>]) -> String {
+    errors
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
 /// Convert the low 9 bits of `bits` into a unix-style string describing its
 /// access permission. Insert `c` between the ugo and perm.
 ///
@@ -13,6 +13,10 @@ use crate::{
     Error, Result, Type,
 };
 
+#[cfg(target_family = "windows")]
+#[allow(unsafe_code)]
+mod windows;
+
 /// Definition for the "sticky bit", which on Unix means that the contents of
 /// directory may not be renamed, deleted, or otherwise modified by a non-owner
 /// of those contents, even if the user has write permissions on the directory.
@@ -134,6 +138,8 @@ impl<'a> super::Verifier<'a> {
         self.check_type(path, path_type, meta, &mut errors);
         #[cfg(target_family = "unix")]
         self.check_permissions(path, path_type, meta, &mut errors);
+        #[cfg(target_family = "windows")]
+        self.check_windows_acl(path, path_type, meta, &mut errors);
         errors
     }
 
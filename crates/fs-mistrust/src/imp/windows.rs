@@ -0,0 +1,473 @@
+//! Windows-specific ownership and access-control checks.
+//!
+//! On Windows, the Unix permission bits in [`Metadata`] are meaningless;
+//! instead, we look at the owner and the discretionary access control list
+//! (DACL) of each object.
+//!
+//! We treat the following principals as trusted, in the same way that we trust
+//! root and the current user on Unix:
+//!  * the user running this process,
+//!  * `BUILTIN\Administrators`,
+//!  * `NT AUTHORITY\SYSTEM`,
+//!  * `NT SERVICE\TrustedInstaller`.
+//!
+//! Any "allow" access control entry that grants write access (or, where
+//! relevant, read access) to any other principal is an error.
+//!
+//! # Limitations
+//!
+//! We only look at "allow" entries: we don't try to work out whether a "deny"
+//! entry cancels out a problematic "allow" entry.  This can cause us to
+//! reject some objects that are actually safe, but never to accept an unsafe
+//! one.
+//!
+//! There is no way yet to configure additional trusted principals.
+
+use std::ffi::c_void;
+use std::fs::Metadata;
+use std::io::Error as IoError;
+use std::os::windows::ffi::OsStrExt as _;
+use std::path::Path;
+use std::ptr;
+
+use once_cell::sync::Lazy;
+use windows_sys::Win32::Foundation::{CloseHandle, LocalFree, ERROR_SUCCESS, HANDLE};
+use windows_sys::Win32::Security::Authorization::{
+    ConvertSidToStringSidW, GetNamedSecurityInfoW, SE_FILE_OBJECT,
+};
+use windows_sys::Win32::Security::{
+    GetAce, GetTokenInformation, TokenUser, ACCESS_ALLOWED_ACE, ACE_HEADER, ACL,
+    DACL_SECURITY_INFORMATION, OWNER_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR, PSID, TOKEN_QUERY,
+    TOKEN_USER,
+};
+use windows_sys::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+use crate::{walk::PathType, Error};
+
+// Constants from `winnt.h`.  We define them here, rather than taking them from
+// `windows-sys`, since they are spread across many modules and types there.
+
+/// ACE type: grants access to a SID.
+const ACCESS_ALLOWED_ACE_TYPE: u8 = 0x0;
+/// ACE flag: the ACE is inherited by child objects that are not containers.
+const OBJECT_INHERIT_ACE: u8 = 0x1;
+/// ACE flag: the ACE is inherited by child containers (directories).
+const CONTAINER_INHERIT_ACE: u8 = 0x2;
+/// ACE flag: the ACE does not apply to this object, only to its children.
+const INHERIT_ONLY_ACE: u8 = 0x8;
+
+/// Read a file, or list a directory.
+const FILE_READ_DATA: u32 = 0x0001;
+/// Write to a file, or add a file to a directory.
+const FILE_WRITE_DATA: u32 = 0x0002;
+/// Append to a file, or add a subdirectory to a directory.
+const FILE_APPEND_DATA: u32 = 0x0004;
+/// Delete any entry in a directory.
+const FILE_DELETE_CHILD: u32 = 0x0040;
+/// Delete this object.
+const DELETE: u32 = 0x0001_0000;
+/// Change this object's DACL.
+const WRITE_DAC: u32 = 0x0004_0000;
+/// Change this object's owner.
+const WRITE_OWNER: u32 = 0x0008_0000;
+/// Every kind of access.
+const GENERIC_ALL: u32 = 0x1000_0000;
+/// Generic write access.
+const GENERIC_WRITE: u32 = 0x4000_0000;
+/// Generic read access.
+const GENERIC_READ: u32 = 0x8000_0000;
+
+/// Rights that would let an untrusted principal add entries to a directory.
+///
+/// Like the sticky bit on Unix, these are acceptable on an _intermediate_
+/// directory, since they don't let the principal modify or remove entries
+/// that belong to somebody else.
+const ADD_ENTRY_RIGHTS: u32 = FILE_WRITE_DATA | FILE_APPEND_DATA;
+
+/// Rights that would let an untrusted principal change an object, or change
+/// which object a path refers to.
+const WRITE_RIGHTS: u32 = ADD_ENTRY_RIGHTS
+    | FILE_DELETE_CHILD
+    | DELETE
+    | WRITE_DAC
+    | WRITE_OWNER
+    | GENERIC_ALL
+    | GENERIC_WRITE;
+
+/// Rights that would let an untrusted principal read an object.
+const READ_RIGHTS: u32 = FILE_READ_DATA | GENERIC_ALL | GENERIC_READ;
+
+/// String SIDs for the well-known principals that we always trust.
+const TRUSTED_SIDS: &[&str] = &[
+    // NT AUTHORITY\SYSTEM
+    "S-1-5-18",
+    // BUILTIN\Administrators
+    "S-1-5-32-544",
+    // NT SERVICE\TrustedInstaller
+    "S-1-5-80-956008885-3418522649-1831038044-1853292631-2271478464",
+];
+
+/// String SIDs for placeholder principals in inheritable ACEs.
+///
+/// When an ACE for one of these is inherited, it is replaced with the creator
+/// of the new object, so it does not grant anything to an untrusted principal.
+const CREATOR_SIDS: &[&str] = &[
+    // CREATOR OWNER
+    "S-1-3-0", // CREATOR GROUP
+    "S-1-3-1",
+];
+
+/// The string SID of the user running this process, if we could find it.
+static CURRENT_USER_SID: Lazy<Option<String>> = Lazy::new(current_user_sid);
+
+/// Return true if `sid` (in string form) is a principal that we trust.
+fn sid_is_trusted(sid: &str) -> bool {
+    TRUSTED_SIDS.contains(&sid) || CURRENT_USER_SID.as_deref() == Some(sid)
+}
+
+/// Describe the rights in `mask`, for use in an error message.
+fn describe_rights(mask: u32) -> String {
+    let mut names = Vec::new();
+    for (bit, name) in [
+        (GENERIC_ALL, "full control"),
+        (GENERIC_WRITE, "write"),
+        (GENERIC_READ, "read"),
+        (FILE_READ_DATA, "read data"),
+        (FILE_WRITE_DATA, "write data"),
+        (FILE_APPEND_DATA, "append data"),
+        (FILE_DELETE_CHILD, "delete children"),
+        (DELETE, "delete"),
+        (WRITE_DAC, "change permissions"),
+        (WRITE_OWNER, "take ownership"),
+    ] {
+        if mask & bit != 0 {
+            names.push(name);
+        }
+    }
+    names.join(", ")
+}
+
+impl<'a> crate::Verifier<'a> {
+    /// Check whether a given file has a trusted owner and DACL,
+    /// and push errors into `errors` if not.
+    /// Other inputs are as for `check_one`.
+    pub(super) fn check_windows_acl(
+        &self,
+        path: &Path,
+        path_type: PathType,
+        meta: &Metadata,
+        errors: &mut Vec<Error>,
+    ) {
+        // As on Unix, the permissions of a symlink don't matter.
+        if path_type == PathType::Symlink {
+            return;
+        }
+
+        let sd = match SecurityInfo::get(path) {
+            Ok(sd) => sd,
+            Err(e) => {
+                errors.push(Error::inspecting(e, path));
+                return;
+            }
+        };
+
+        match sd.owner() {
+            Some(owner) if sid_is_trusted(&owner) => {}
+            owner => errors.push(Error::BadOwnerSid(
+                path.into(),
+                owner.unwrap_or_else(|| "(unknown)".into()),
+            )),
+        }
+
+        let mut forbidden = if !self.readable_okay && path_type == PathType::Final {
+            WRITE_RIGHTS | READ_RIGHTS
+        } else {
+            WRITE_RIGHTS
+        };
+        if meta.is_dir() && path_type == PathType::Intermediate {
+            forbidden &= !ADD_ENTRY_RIGHTS;
+        }
+
+        // For our target directory and its contents, we also care about entries
+        // that only apply to the objects that will be created inside them.
+        let check_inheritable = meta.is_dir() && path_type != PathType::Intermediate;
+
+        let aces = match sd.allow_aces() {
+            Some(aces) => aces,
+            None => {
+                // A missing DACL grants everybody full access.
+                errors.push(Error::BadAccessControl {
+                    path: path.into(),
+                    sid: "Everyone".into(),
+                    rights: "full control (no access control list)".into(),
+                });
+                return;
+            }
+        };
+        for ace in aces {
+            let inherit_only = ace.flags & INHERIT_ONLY_ACE != 0;
+            let inheritable = ace.flags & (OBJECT_INHERIT_ACE | CONTAINER_INHERIT_ACE) != 0;
+            if inherit_only && !(check_inheritable && inheritable) {
+                continue;
+            }
+            if inherit_only && CREATOR_SIDS.contains(&ace.sid.as_str()) {
+                continue;
+            }
+            let bad = ace.mask & forbidden;
+            if bad != 0 && !sid_is_trusted(&ace.sid) {
+                let mut rights = describe_rights(bad);
+                if inherit_only {
+                    rights.push_str(" (on new children)");
+                }
+                errors.push(Error::BadAccessControl {
+                    path: path.into(),
+                    sid: ace.sid,
+                    rights,
+                });
+            }
+        }
+    }
+}
+
+/// An "allow" ACE from a DACL, in a form we can use safely.
+struct AllowAce {
+    /// The ACE flags (`*_INHERIT_ACE` etc).
+    flags: u8,
+    /// The access rights granted.
+    mask: u32,
+    /// The SID of the principal to which they are granted, in string form.
+    sid: String,
+}
+
+/// The owner and DACL of a file, as returned by `GetNamedSecurityInfoW`.
+///
+/// Frees the underlying security descriptor on drop.
+struct SecurityInfo {
+    /// The security descriptor.  All the other pointers point into this.
+    sd: PSECURITY_DESCRIPTOR,
+    /// The owner SID, or null.
+    owner: PSID,
+    /// The DACL, or null if there is none.
+    dacl: *const ACL,
+}
+
+impl SecurityInfo {
+    /// Look up the owner and DACL of `path`.
+    fn get(path: &Path) -> Result<Self, IoError> {
+        let wide: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+        let mut owner: PSID = ptr::null_mut();
+        let mut dacl: *mut ACL = ptr::null_mut();
+        let mut sd: PSECURITY_DESCRIPTOR = ptr::null_mut();
+        // SAFETY: `wide` is a NUL-terminated wide string that outlives the call,
+        // and every out-pointer refers to a live local variable.
+        let status = unsafe {
+            GetNamedSecurityInfoW(
+                wide.as_ptr(),
+                SE_FILE_OBJECT,
+                OWNER_SECURITY_INFORMATION | DACL_SECURITY_INFORMATION,
+                &mut owner,
+                ptr::null_mut(),
+                &mut dacl,
+                ptr::null_mut(),
+                &mut sd,
+            )
+        };
+        if status != ERROR_SUCCESS {
+            // Win32 error codes are small positive integers.
+            return Err(IoError::from_raw_os_error(status as i32));
+        }
+        Ok(SecurityInfo { sd, owner, dacl })
+    }
+
+    /// Return the owner of this object, as a string SID.
+    fn owner(&self) -> Option<String> {
+        // SAFETY: `self.owner` is null or points into `self.sd`, which is alive.
+        unsafe { sid_to_string(self.owner) }
+    }
+
+    /// Return every "allow" ACE in this object's DACL.
+    ///
+    /// Return `None` if the object has no DACL at all.
+    fn allow_aces(&self) -> Option<Vec<AllowAce>> {
+        if self.dacl.is_null() {
+            return None;
+        }
+        // SAFETY: `self.dacl` is non-null and points into `self.sd`.
+        let count = unsafe { (*self.dacl).AceCount };
+        let mut result = Vec::new();
+        for idx in 0..count {
+            let mut ace: *mut c_void = ptr::null_mut();
+            // SAFETY: `self.dacl` is a valid ACL, and `idx` is in range.
+            if unsafe { GetAce(self.dacl, u32::from(idx), &mut ace) } == 0 || ace.is_null() {
+                continue;
+            }
+            // SAFETY: GetAce succeeded, so `ace` points to an ACE within the DACL,
+            // and every ACE begins with an ACE_HEADER.
+            let header = unsafe { *(ace as *const ACE_HEADER) };
+            if header.AceType != ACCESS_ALLOWED_ACE_TYPE {
+                continue;
+            }
+            let ace = ace as *const ACCESS_ALLOWED_ACE;
+            // SAFETY: This is an ACCESS_ALLOWED_ACE, whose SID begins at
+            // `SidStart` and lies within the ACE.
+            let (mask, sid) = unsafe {
+                (
+                    (*ace).Mask,
+                    sid_to_string(ptr::addr_of!((*ace).SidStart) as PSID),
+                )
+            };
+            result.push(AllowAce {
+                flags: header.AceFlags,
+                mask,
+                sid: sid.unwrap_or_else(|| "(unknown)".into()),
+            });
+        }
+        Some(result)
+    }
+}
+
+impl Drop for SecurityInfo {
+    fn drop(&mut self) {
+        // SAFETY: `self.sd` was allocated by GetNamedSecurityInfoW, which says to
+        // free it with LocalFree, and we never free it anywhere else.
+        unsafe {
+            LocalFree(self.sd as _);
+        }
+    }
+}
+
+/// Convert `sid` to its string form (like `S-1-5-18`).
+///
+/// # Safety
+///
+/// `sid` must be null or point to a valid SID.
+unsafe fn sid_to_string(sid: PSID) -> Option<String> {
+    if sid.is_null() {
+        return None;
+    }
+    let mut out: *mut u16 = ptr::null_mut();
+    // SAFETY: `sid` is valid (by our precondition), and `out` is a live local.
+    if unsafe { ConvertSidToStringSidW(sid, &mut out) } == 0 || out.is_null() {
+        return None;
+    }
+    // SAFETY: On success, `out` is a NUL-terminated wide string.
+    let s = unsafe {
+        let len = (0..).take_while(|&i| *out.add(i) != 0).count();
+        String::from_utf16_lossy(std::slice::from_raw_parts(out, len))
+    };
+    // SAFETY: ConvertSidToStringSidW says to free the string with LocalFree.
+    unsafe {
+        LocalFree(out as _);
+    }
+    Some(s)
+}
+
+/// Return the string SID of the user running this process.
+fn current_user_sid() -> Option<String> {
+    let mut token: HANDLE = 0 as _;
+    // SAFETY: GetCurrentProcess returns a pseudo-handle that needs no closing;
+    // `token` is a live local.
+    if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) } == 0 {
+        return None;
+    }
+
+    let mut len = 0_u32;
+    // SAFETY: Querying the needed length with a null buffer is permitted.
+    unsafe {
+        GetTokenInformation(token, TokenUser, ptr::null_mut(), 0, &mut len);
+    }
+    // Use a u64 buffer so that the TOKEN_USER is suitably aligned.
+    let mut buf = vec![0_u64; (len as usize + 7) / 8];
+    // SAFETY: `buf` is at least `len` bytes long.
+    let ok = unsafe {
+        GetTokenInformation(
+            token,
+            TokenUser,
+            buf.as_mut_ptr() as *mut c_void,
+            len,
+            &mut len,
+        )
+    };
+    let result = if ok != 0 {
+        // SAFETY: On success, `buf` holds a TOKEN_USER, whose SID points into `buf`.
+        unsafe {
+            let user = &*(buf.as_ptr() as *const TOKEN_USER);
+            sid_to_string(user.User.Sid)
+        }
+    } else {
+        None
+    };
+
+    // SAFETY: `token` was opened above, and is not used after this.
+    unsafe {
+        CloseHandle(token);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use crate::testing::Dir;
+
+    #[test]
+    fn trusted_sids() {
+        let me = CURRENT_USER_SID.as_deref().unwrap();
+        assert!(me.starts_with("S-1-"), "{}", me);
+        assert!(sid_is_trusted(me));
+        assert!(sid_is_trusted("S-1-5-18"));
+        assert!(sid_is_trusted("S-1-5-32-544"));
+        // Everyone
+        assert!(!sid_is_trusted("S-1-1-0"));
+        // BUILTIN\Users
+        assert!(!sid_is_trusted("S-1-5-32-545"));
+    }
+
+    #[test]
+    fn rights() {
+        assert_eq!(describe_rights(0), "");
+        assert_eq!(
+            describe_rights(FILE_WRITE_DATA | DELETE),
+            "write data, delete"
+        );
+        assert_eq!(
+            describe_rights(GENERIC_ALL | WRITE_OWNER),
+            "full control, take ownership"
+        );
+    }
+
+    #[test]
+    fn security_info() {
+        let d = Dir::new();
+        d.dir("a");
+        d.file("a/b");
+
+        for p in ["a", "a/b"] {
+            let info = SecurityInfo::get(&d.path(p)).unwrap();
+            // Depending on the token, new objects are owned either by us or
+            // by BUILTIN\Administrators.
+            let owner = info.owner().unwrap();
+            assert!(sid_is_trusted(&owner), "{}", owner);
+
+            let aces = info.allow_aces().unwrap();
+            assert!(!aces.is_empty());
+            assert!(aces.iter().all(|ace| ace.sid.starts_with("S-1-")));
+        }
+
+        let err = SecurityInfo::get(&d.path("nonexistent")).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+}
@@ -51,9 +51,10 @@
 // Nowadays we use pwd_grp, which is tested with miri.
 // This #[forbid] assures us that we have removed all direct unsafe libc access.
 //
-// If this crate grows some other reason to want some unsafe, it is OK to remove this,
-// subject to all the usual considerations when writing unsafe.
-#![forbid(unsafe_code)]
+// On Windows, we need unsafe code to inspect access control lists:
+// that is confined to `imp::windows`.
+#![cfg_attr(not(target_family = "windows"), forbid(unsafe_code))]
+#![cfg_attr(target_family = "windows", deny(unsafe_code))]
 
 mod dir;
 mod disable;
@@ -169,6 +170,15 @@ pub struct Mistrust {
         field(type = "TrustedGroup", build = "self.trust_group.get_gid()?")
     )]
     trust_group: Option<u32>,
+
+    /// Should we report every problem that we find with a path,
+    /// rather than stopping at the first one?
+    ///
+    /// This makes it easier to understand why a file or directory was
+    /// rejected.  It has the same effect as calling
+    /// [`Verifier::all_errors`] on every `Verifier`.
+    #[builder(default)]
+    explain_rejections: bool,
 }
 
 /// Compute the canonical prefix for a given path prefix.
@@ -390,7 +400,7 @@ impl Mistrust {
         Verifier {
             mistrust: self,
             readable_okay: false,
-            collect_multiple_errors: false,
+            collect_multiple_errors: self.explain_rejections,
             enforce_type: Type::DirOrFile,
             check_contents: false,
         }
@@ -767,6 +777,17 @@ mod test {
         assert_eq!(2, errs.len());
         assert!(matches!(&errs[0], Error::BadPermission(..)));
         assert!(matches!(&errs[1], Error::NotFound(_)));
+        assert!(e.to_string().contains("Incorrect permissions"));
+
+        // With explain_rejections, we get every error without asking.
+        let m = Mistrust::builder()
+            .ignore_prefix(d.canonical_root())
+            .trust_no_group_id()
+            .explain_rejections(true)
+            .build()
+            .unwrap();
+        let e = m.verifier().check(d.path("a/b/c")).unwrap_err();
+        assert_eq!(2, e.errors().count());
     }
 
     #[cfg(target_family = "unix")]
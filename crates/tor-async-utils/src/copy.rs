@@ -0,0 +1,112 @@
+//! Copying data between interactive readers and writers.
+
+use futures::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+use std::io::Result as IoResult;
+
+/// Copy all the data from `reader` into `writer` until we encounter an EOF or
+/// an error.
+///
+/// Unlike as futures::io::copy(), this function is meant for use with
+/// interactive readers and writers, where the reader might pause for
+/// a while, but where we want to send data on the writer as soon as
+/// it is available.
+///
+/// This function assumes that the writer might need to be flushed for
+/// any buffered data to be sent.  It tries to minimize the number of
+/// flushes, however, by only flushing the writer when the reader has no data.
+///
+/// On a clean EOF the writer is closed, so that the other side sees the
+/// end of the stream.  To relay a bidirectional stream, use
+/// [`relay`](crate::relay) instead of running one `copy_interactive` in each
+/// direction.
+//
+// TODO: It would be better to change the behaviour in `DataStream` that makes
+// this necessary.  See arti#786 for a fuller discussion.
+pub async fn copy_interactive<R, W>(mut reader: R, mut writer: W) -> IoResult<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    use futures::{poll, task::Poll};
+
+    let mut buf = [0_u8; 1024];
+
+    // At this point we could just loop, calling read().await,
+    // write_all().await, and flush().await.  But we want to be more
+    // clever than that: we only want to flush when the reader is
+    // stalled.  That way we can pack our data into as few cells as
+    // possible, but flush it immediately whenever there's no more
+    // data coming.
+    let loop_result: IoResult<()> = loop {
+        let mut read_future = reader.read(&mut buf[..]);
+        match poll!(&mut read_future) {
+            Poll::Ready(Err(e)) => break Err(e),
+            Poll::Ready(Ok(0)) => break Ok(()), // EOF
+            Poll::Ready(Ok(n)) => {
+                writer.write_all(&buf[..n]).await?;
+                continue;
+            }
+            Poll::Pending => writer.flush().await?,
+        }
+
+        // The read future is pending, so we should wait on it.
+        match read_future.await {
+            Err(e) => break Err(e),
+            Ok(0) => break Ok(()),
+            Ok(n) => writer.write_all(&buf[..n]).await?,
+        }
+    };
+
+    // Make sure that we flush any lingering data if we can.
+    //
+    // If there is a difference between closing and dropping, then we
+    // only want to do a "proper" close if the reader closed cleanly.
+    let flush_result = if loop_result.is_ok() {
+        writer.close().await
+    } else {
+        writer.flush().await
+    };
+
+    loop_result.or(flush_result)
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+    use futures::io::Cursor;
+    use futures_await_test::async_test;
+
+    #[async_test]
+    async fn copy() {
+        // More than one buffer's worth, so that we go round the loop.
+        let data: Vec<u8> = (0..5000_u32).map(|n| (n % 251) as u8).collect();
+        let mut out = Vec::new();
+        copy_interactive(Cursor::new(&data), &mut out)
+            .await
+            .unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[async_test]
+    async fn copy_empty() {
+        let mut out = Vec::new();
+        copy_interactive(Cursor::new(&[][..]), &mut out)
+            .await
+            .unwrap();
+        assert!(out.is_empty());
+    }
+}
@@ -41,15 +41,21 @@
 #![allow(clippy::needless_raw_string_hashes)] // complained-about code is fine, often best
 //! <!-- @@ end lint list maintained by maint/add_warning @@ -->
 
+mod copy;
 mod join_read_write;
 mod prepare_send;
+mod relay;
 mod sinkext;
 mod watch;
 
 pub mod oneshot;
 
+pub use copy::copy_interactive;
+
 pub use join_read_write::*;
 
+pub use relay::{relay, RelayConfig, RelayEnd};
+
 pub use prepare_send::{SinkPrepareExt, SinkPrepareSendFuture, SinkSendable};
 
 pub use sinkext::SinkExt;
@@ -0,0 +1,366 @@
+//! Relaying data in both directions between two streams.
+
+use std::io::Result as IoResult;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use futures::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+use futures::{future, pin_mut, select_biased, FutureExt as _};
+use tor_rtcompat::SleepProvider;
+
+/// The default size of the buffer that [`relay`] uses in each direction.
+const DEFAULT_BUF_SIZE: usize = 4096;
+
+/// Settings for [`relay`].
+#[derive(Clone, Debug)]
+pub struct RelayConfig {
+    /// The size of the buffer used in each direction.
+    buf_size: usize,
+    /// How long the relay may go without any data in either direction.
+    idle_timeout: Option<Duration>,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        RelayConfig {
+            buf_size: DEFAULT_BUF_SIZE,
+            idle_timeout: None,
+        }
+    }
+}
+
+impl RelayConfig {
+    /// Return the default settings: a 4 KiB buffer in each direction, and no
+    /// idle timeout.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use a buffer of `buf_size` bytes in each direction.
+    ///
+    /// This bounds the amount of data the relay holds: it does not read more
+    /// from one side until it has written everything it read to the other.
+    /// A `buf_size` of zero is treated as one.
+    pub fn buf_size(mut self, buf_size: usize) -> Self {
+        self.buf_size = buf_size.max(1);
+        self
+    }
+
+    /// Give up if no data flows in either direction for `idle_timeout`.
+    ///
+    /// `None`, the default, means that the relay may stay idle forever.
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+}
+
+/// How a [`relay`] finished, when it did not fail.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum RelayEnd {
+    /// The remote side reached EOF, and both sides were closed.
+    Closed,
+    /// No data flowed in either direction for the idle timeout, and both
+    /// sides were closed.
+    IdleTimeout,
+}
+
+/// Relay data in both directions between a `local` and a `remote` stream,
+/// each given as its read and write halves.
+///
+/// This is what a proxy does once a connection is set up, and replaces running
+/// [`copy_interactive`](crate::copy_interactive) in each direction.
+///
+/// The two sides are treated differently at EOF, since `remote` is assumed to
+/// be a stream, like a Tor stream, on which closing the writer ends the whole
+/// stream:
+///
+///  * When `local` reaches EOF (for example, when a TCP client shuts down its
+///    sending side), we flush `remote`, but keep relaying data from `remote`
+///    to `local`, so that the response still gets through.
+///  * When `remote` reaches EOF, the connection is over: we close both
+///    writers and return [`RelayEnd::Closed`].
+///
+/// If `config` has an idle timeout, and no data flows in either direction for
+/// that long, we close both writers and return [`RelayEnd::IdleTimeout`].
+///
+/// If reading or writing fails, we flush both writers if we can, but do not
+/// close them, so that the connection does not look as though it ended
+/// cleanly, and we return the error.  For a Tor stream, the kind of the error
+/// reflects the reason that the stream was ended.
+pub async fn relay<SP, LR, LW, RR, RW>(
+    sleep_prov: &SP,
+    config: &RelayConfig,
+    (local_r, mut local_w): (LR, LW),
+    (remote_r, mut remote_w): (RR, RW),
+) -> IoResult<RelayEnd>
+where
+    SP: SleepProvider,
+    LR: AsyncRead + Unpin,
+    LW: AsyncWrite + Unpin,
+    RR: AsyncRead + Unpin,
+    RW: AsyncWrite + Unpin,
+{
+    let activity = Activity(Mutex::new(sleep_prov.now()));
+
+    let result = {
+        let outbound = copy_direction(
+            sleep_prov,
+            &activity,
+            config.buf_size,
+            local_r,
+            &mut remote_w,
+        )
+        .fuse();
+        let inbound = copy_direction(
+            sleep_prov,
+            &activity,
+            config.buf_size,
+            remote_r,
+            &mut local_w,
+        )
+        .fuse();
+        let idle = wait_until_idle(sleep_prov, &activity, config.idle_timeout).fuse();
+        pin_mut!(outbound, inbound, idle);
+
+        loop {
+            select_biased! {
+                r = inbound => break r.map(|()| RelayEnd::Closed),
+                r = outbound => match r {
+                    // `local` is done sending, but `remote` may not be:
+                    // keep going until it finishes too.
+                    Ok(()) => continue,
+                    Err(e) => break Err(e),
+                },
+                () = idle => break Ok(RelayEnd::IdleTimeout),
+            }
+        }
+    };
+
+    // If there is a difference between closing and dropping, then we only
+    // want to do a "proper" close if the relay ended cleanly.
+    //
+    // Errors here are not interesting: the relay is over either way.
+    if result.is_ok() {
+        let _ = local_w.close().await;
+        let _ = remote_w.close().await;
+    } else {
+        let _ = local_w.flush().await;
+        let _ = remote_w.flush().await;
+    }
+
+    result
+}
+
+/// The last time that any data was read by a [`relay`].
+struct Activity(Mutex<Instant>);
+
+impl Activity {
+    /// Record that data was read at `now`.
+    fn note(&self, now: Instant) {
+        *self.0.lock().expect("lock poisoned") = now;
+    }
+
+    /// Return the last time that data was read.
+    fn last(&self) -> Instant {
+        *self.0.lock().expect("lock poisoned")
+    }
+}
+
+/// Copy data from `reader` to `writer`, with a buffer of `buf_size` bytes,
+/// until `reader` reaches EOF, noting each read in `activity`.
+///
+/// Like [`copy_interactive`](crate::copy_interactive), this only flushes the
+/// writer when the reader has no data for the moment.  At EOF, the writer is
+/// flushed but not closed: that is up to the caller.
+async fn copy_direction<SP, R, W>(
+    sleep_prov: &SP,
+    activity: &Activity,
+    buf_size: usize,
+    mut reader: R,
+    writer: &mut W,
+) -> IoResult<()>
+where
+    SP: SleepProvider,
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    use futures::{poll, task::Poll};
+
+    let mut buf = vec![0_u8; buf_size];
+
+    loop {
+        let mut read_future = reader.read(&mut buf[..]);
+        let n = match poll!(&mut read_future) {
+            Poll::Ready(r) => r?,
+            Poll::Pending => {
+                writer.flush().await?;
+                read_future.await?
+            }
+        };
+        if n == 0 {
+            return writer.flush().await;
+        }
+        activity.note(sleep_prov.now());
+        writer.write_all(&buf[..n]).await?;
+    }
+}
+
+/// Return once nothing has been noted in `activity` for `idle_timeout`.
+///
+/// If `idle_timeout` is `None`, never return.
+async fn wait_until_idle<SP: SleepProvider>(
+    sleep_prov: &SP,
+    activity: &Activity,
+    idle_timeout: Option<Duration>,
+) {
+    let Some(idle_timeout) = idle_timeout else {
+        return future::pending().await;
+    };
+
+    loop {
+        let deadline = activity.last() + idle_timeout;
+        let now = sleep_prov.now();
+        if now >= deadline {
+            return;
+        }
+        sleep_prov
+            .sleep(deadline.saturating_duration_since(now))
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+    use futures::io::Cursor;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// A reader that never has any data, and never reaches EOF.
+    struct Stalled;
+
+    impl AsyncRead for Stalled {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Pending
+        }
+    }
+
+    /// A reader that always fails.
+    struct Failing;
+
+    impl AsyncRead for Failing {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()))
+        }
+    }
+
+    /// Return some test data, of more than one buffer's worth.
+    fn data(seed: u32) -> Vec<u8> {
+        (0..5000_u32).map(|n| ((n + seed) % 251) as u8).collect()
+    }
+
+    #[test]
+    fn relay_both_ways() {
+        tor_rtcompat::test_with_one_runtime!(|rt| async move {
+            let (request, response) = (data(0), data(7));
+            let (mut local_out, mut remote_out) = (Vec::new(), Vec::new());
+
+            let end = relay(
+                &rt,
+                &RelayConfig::new().buf_size(1000),
+                (Cursor::new(&request), &mut local_out),
+                (Cursor::new(&response), &mut remote_out),
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(end, RelayEnd::Closed);
+            assert_eq!(remote_out, request);
+            assert_eq!(local_out, response);
+        });
+    }
+
+    #[test]
+    fn local_eof_is_half_close() {
+        tor_rtcompat::test_with_one_runtime!(|rt| async move {
+            // The local side finishes straight away, but the response must
+            // still be relayed in full.
+            let response = data(3);
+            let (mut local_out, mut remote_out) = (Vec::new(), Vec::new());
+
+            let end = relay(
+                &rt,
+                &RelayConfig::new(),
+                (Cursor::new(&[][..]), &mut local_out),
+                (Cursor::new(&response), &mut remote_out),
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(end, RelayEnd::Closed);
+            assert!(remote_out.is_empty());
+            assert_eq!(local_out, response);
+        });
+    }
+
+    #[test]
+    fn idle_timeout() {
+        tor_rtcompat::test_with_one_runtime!(|rt| async move {
+            let (mut local_out, mut remote_out) = (Vec::new(), Vec::new());
+
+            let end = relay(
+                &rt,
+                &RelayConfig::new().idle_timeout(Some(Duration::from_millis(50))),
+                (Stalled, &mut local_out),
+                (Stalled, &mut remote_out),
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(end, RelayEnd::IdleTimeout);
+        });
+    }
+
+    #[test]
+    fn remote_error() {
+        tor_rtcompat::test_with_one_runtime!(|rt| async move {
+            let (mut local_out, mut remote_out) = (Vec::new(), Vec::new());
+
+            let err = relay(
+                &rt,
+                &RelayConfig::new(),
+                (Stalled, &mut local_out),
+                (Failing, &mut remote_out),
+            )
+            .await
+            .unwrap_err();
+
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        });
+    }
+}
@@ -237,21 +237,24 @@ pub trait IoErrorExt: Sealed {
 impl Sealed for std::io::Error {}
 impl IoErrorExt for std::io::Error {
     fn is_not_a_directory(&self) -> bool {
-        self.raw_os_error()
-            == Some(
-                #[cfg(target_family = "unix")]
-                libc::ENOTDIR,
-                #[cfg(target_family = "windows")]
-                {
-                    /// Obtained from Rust stdlib source code
-                    /// See also:
-                    ///   <https://docs.microsoft.com/en-us/windows/win32/debug/system-error-codes--0-499->
-                    /// (although the documentation is anaemic) and
-                    /// <https://github.com/rust-lang/rust/pull/79965>
-                    const ERROR_DIRECTORY: i32 = 267;
-                    ERROR_DIRECTORY
-                },
-            )
+        #[cfg(target_family = "unix")]
+        let not_a_directory = Some(libc::ENOTDIR);
+        #[cfg(target_family = "windows")]
+        let not_a_directory = {
+            /// Obtained from Rust stdlib source code
+            /// See also:
+            ///   <https://docs.microsoft.com/en-us/windows/win32/debug/system-error-codes--0-499->
+            /// (although the documentation is anaemic) and
+            /// <https://github.com/rust-lang/rust/pull/79965>
+            const ERROR_DIRECTORY: i32 = 267;
+            Some(ERROR_DIRECTORY)
+        };
+        // Elsewhere (for example, wasm32-unknown-unknown) there is no filesystem,
+        // and no OS error codes.
+        #[cfg(not(any(target_family = "unix", target_family = "windows")))]
+        let not_a_directory = None;
+
+        not_a_directory.is_some() && self.raw_os_error() == not_a_directory
     }
 }
 
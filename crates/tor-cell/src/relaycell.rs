@@ -1,5 +1,6 @@
 //! Implementation for parsing and encoding relay cells
 
+use std::collections::VecDeque;
 use std::num::NonZeroU16;
 
 use crate::chancell::{BoxedCellBody, CELL_DATA_LEN};
@@ -84,6 +85,11 @@ caret_int! {
         PADDING_NEGOTIATE = 41,
         /// Padding: reply to a PADDING_NEGOTIATE
         PADDING_NEGOTIATED = 42,
+
+        /// Flow control: ask the other side to stop sending data on a stream
+        XOFF = 43,
+        /// Flow control: ask the other side to resume sending data on a stream
+        XON = 44,
     }
 }
 
@@ -108,7 +114,9 @@ impl RelayCmd {
             | RelayCmd::CONNECTED
             | RelayCmd::RESOLVE
             | RelayCmd::RESOLVED
-            | RelayCmd::BEGIN_DIR => StreamIdReq::WantSome,
+            | RelayCmd::BEGIN_DIR
+            | RelayCmd::XOFF
+            | RelayCmd::XON => StreamIdReq::WantSome,
             #[cfg(feature = "experimental-udp")]
             RelayCmd::CONNECT_UDP | RelayCmd::CONNECTED_UDP | RelayCmd::DATAGRAM => {
                 StreamIdReq::WantSome
@@ -200,6 +208,16 @@ impl StreamId {
 pub enum RelayCellFormat {
     /// This is the "legacy" pre-prop340 format. No packing or fragmentation.
     V0,
+    /// A format based on proposal 340, with a 16-byte tag and
+    /// per-message headers.
+    ///
+    /// Several small messages can be packed into a single cell, and a
+    /// message that does not fit in the remainder of a cell continues in
+    /// the next one.
+    ///
+    /// Relays advertise support for this format with a subprotocol
+    /// version; nothing should use it with a relay that does not.
+    V1,
 }
 
 /// Specifies a relay cell format and associated types.
@@ -243,11 +261,111 @@ impl RelayCellFields for RelayCellFieldsV0 {
     const EMPTY_DIGEST: &'static [u8] = &[0, 0, 0, 0];
 }
 
+/// Format type corresponding to `RelayCellFormat::V1`.
+#[non_exhaustive]
+pub struct RelayCellFormatV1;
+
+impl RelayCellFormatTrait for RelayCellFormatV1 {
+    const FORMAT: RelayCellFormat = RelayCellFormat::V1;
+    type FIELDS = RelayCellFieldsV1;
+}
+
+/// Specifies fields for `RelayCellFormat::V1`.
+///
+/// In this format, the first [`V1_TAG_LEN`] bytes of the cell are reserved
+/// for the cell-crypto layer.  With the tor1 cell crypto, we use them as a
+/// 2-byte `recognized` field followed by a 14-byte digest.
+#[non_exhaustive]
+pub struct RelayCellFieldsV1;
+
+impl RelayCellFields for RelayCellFieldsV1 {
+    const RECOGNIZED_RANGE: std::ops::Range<usize> = 0..2;
+    const DIGEST_RANGE: std::ops::Range<usize> = 2..V1_TAG_LEN;
+    const EMPTY_DIGEST: &'static [u8] = &[0; V1_TAG_LEN - 2];
+}
+
+/// The length of the tag at the start of every `RelayCellFormat::V1` cell.
+const V1_TAG_LEN: usize = 16;
+
+/// The length of the shortest message header in `RelayCellFormat::V1`:
+/// a command and a length.
+const V1_MIN_HEADER_LEN: usize = 3;
+
+/// Return true if a `RelayCellFormat::V1` message header for `cmd` includes
+/// a stream ID.
+fn v1_cmd_has_stream_id(cmd: RelayCmd) -> bool {
+    !matches!(cmd.expects_streamid(), StreamIdReq::WantNone)
+}
+
+/// Return the length of the `RelayCellFormat::V1` message header for `cmd`.
+fn v1_header_len(cmd: RelayCmd) -> usize {
+    if v1_cmd_has_stream_id(cmd) {
+        V1_MIN_HEADER_LEN + 2
+    } else {
+        V1_MIN_HEADER_LEN
+    }
+}
+
 /// Internal decoder state.
 #[derive(Clone, Debug)]
 enum RelayCellDecoderInternal {
     /// Internal state for `RelayCellFormat::V0`
     V0,
+    /// Internal state for `RelayCellFormat::V1`
+    V1 {
+        /// The message whose body we are still receiving, if any.
+        pending: Option<PartialRelayMsgV1>,
+    },
+}
+
+/// A `RelayCellFormat::V1` message for which we have seen the header, and
+/// possibly part of the body.
+#[derive(Clone, Debug)]
+struct PartialRelayMsgV1 {
+    /// The message's command.
+    cmd: RelayCmd,
+    /// The message's stream ID, if any.
+    stream_id: Option<StreamId>,
+    /// The length of the complete body, as declared in the header.
+    total_len: usize,
+    /// The part of the body that we've received so far.
+    body: Vec<u8>,
+}
+
+impl PartialRelayMsgV1 {
+    /// Add as much of the body as we can from `r`.
+    fn take_body_from(&mut self, r: &mut Reader<'_>) -> Result<()> {
+        let n = std::cmp::min(self.total_len - self.body.len(), r.remaining());
+        self.body.extend_from_slice(r.take(n)?);
+        Ok(())
+    }
+
+    /// Return true if we have the entire body of this message.
+    fn is_complete(&self) -> bool {
+        self.body.len() == self.total_len
+    }
+
+    /// Describe this message as an `IncompleteRelayMsgInfo`.
+    fn info(&self) -> IncompleteRelayMsgInfo {
+        IncompleteRelayMsgInfo {
+            cmd: self.cmd,
+            stream_id: self.stream_id,
+            total_msg_len: self.total_len,
+            num_bytes_present: self.body.len(),
+        }
+    }
+
+    /// Convert this (complete) message into an `UnparsedRelayMsg`.
+    fn into_unparsed(self) -> UnparsedRelayMsg {
+        debug_assert!(self.is_complete());
+        UnparsedRelayMsg {
+            internal: UnparsedRelayMsgInternal::V1 {
+                cmd: self.cmd,
+                stream_id: self.stream_id,
+                body: self.body,
+            },
+        }
+    }
 }
 
 // TODO prop340: We should also fuzz RelayCellDecoder, but not in this fuzzer.
@@ -267,6 +385,9 @@ impl RelayCellDecoder {
             RelayCellFormat::V0 => Self {
                 internal: RelayCellDecoderInternal::V0,
             },
+            RelayCellFormat::V1 => Self {
+                internal: RelayCellDecoderInternal::V1 { pending: None },
+            },
         }
     }
     /// Parse a RELAY or RELAY_EARLY cell body.
@@ -274,14 +395,76 @@ impl RelayCellDecoder {
     /// Requires that the cryptographic checks on the message have already been
     /// performed
     pub fn decode(&mut self, cell: BoxedCellBody) -> Result<RelayCellDecoderResult> {
-        match &self.internal {
+        match &mut self.internal {
             RelayCellDecoderInternal::V0 => Ok(RelayCellDecoderResult {
                 msgs: smallvec![UnparsedRelayMsg {
                     internal: UnparsedRelayMsgInternal::V0(cell)
                 }],
                 incomplete: None,
             }),
+            RelayCellDecoderInternal::V1 { pending } => Self::decode_v1(pending, &cell[..]),
+        }
+    }
+
+    /// Parse a `RelayCellFormat::V1` cell body, continuing the message in
+    /// `pending` (if any), and leaving any message that this cell does not
+    /// complete in `pending`.
+    fn decode_v1(
+        pending: &mut Option<PartialRelayMsgV1>,
+        cell: &[u8],
+    ) -> Result<RelayCellDecoderResult> {
+        let mut r = Reader::from_slice(cell);
+        r.advance(V1_TAG_LEN)?;
+        let mut msgs = SmallVec::new();
+
+        if let Some(partial) = pending.as_mut() {
+            partial.take_body_from(&mut r)?;
+            if !partial.is_complete() {
+                return Ok(RelayCellDecoderResult {
+                    msgs,
+                    incomplete: Some(partial.info()),
+                });
+            }
+            if let Some(partial) = pending.take() {
+                msgs.push(partial.into_unparsed());
+            }
+        }
+
+        // A message header never spans cells, so anything too short to hold
+        // one is padding.  So is anything starting with a zero command.
+        while r.remaining() >= V1_MIN_HEADER_LEN && r.peek(1)?[0] != 0 {
+            let cmd: RelayCmd = r.take_u8()?.into();
+            let total_len = r.take_u16()?.into();
+            let stream_id = if v1_cmd_has_stream_id(cmd) {
+                StreamId::new(r.take_u16()?)
+            } else {
+                None
+            };
+            let mut partial = PartialRelayMsgV1 {
+                cmd,
+                stream_id,
+                total_len,
+                body: Vec::with_capacity(total_len),
+            };
+            partial.take_body_from(&mut r)?;
+            if partial.is_complete() {
+                msgs.push(partial.into_unparsed());
+            } else {
+                let incomplete = Some(partial.info());
+                *pending = Some(partial);
+                return Ok(RelayCellDecoderResult { msgs, incomplete });
+            }
+        }
+
+        if msgs.is_empty() {
+            return Err(Error::InvalidMessage(
+                "Relay cell contained no messages".into(),
+            ));
         }
+        Ok(RelayCellDecoderResult {
+            msgs,
+            incomplete: None,
+        })
     }
     /// Returns the `IncompleteRelayMsgInfo` describing the partial
     /// (fragmented) relay message at the end of the so-far-processed relay cell
@@ -290,8 +473,139 @@ impl RelayCellDecoder {
         match &self.internal {
             // V0 doesn't support fragmentation, so there is never a pending fragment.
             RelayCellDecoderInternal::V0 => None,
+            RelayCellDecoderInternal::V1 { pending } => pending.as_ref().map(|p| p.info()),
+        }
+    }
+}
+
+/// Internal encoder state.
+#[derive(Clone, Debug)]
+enum RelayCellEncoderInternal {
+    /// Internal state for `RelayCellFormat::V0`
+    V0 {
+        /// Encoded cells, and the number of bytes used in each, in the order
+        /// they should be sent.  Padding is added when they are taken.
+        pending: VecDeque<(BoxedCellBody, usize)>,
+    },
+    /// Internal state for `RelayCellFormat::V1`
+    V1 {
+        /// Encoded messages (header and body), and the length of the header
+        /// for each, in the order they should be sent.
+        pending: VecDeque<(Vec<u8>, usize)>,
+        /// The number of bytes of the first message in `pending` that we have
+        /// already put in a cell.
+        offset: usize,
+    },
+}
+
+/// Encodes a stream of relay messages into relay cell bodies.
+///
+/// In `RelayCellFormat::V0`, every message takes exactly one cell.  In later
+/// formats, messages are packed into cells in the order they were pushed:
+/// a message may share a cell with the ones around it, or continue over
+/// several cells.
+#[derive(Clone, Debug)]
+pub struct RelayCellEncoder {
+    /// Internal representation.
+    internal: RelayCellEncoderInternal,
+}
+
+impl RelayCellEncoder {
+    /// Return a new `RelayCellEncoder`, producing relay cells of the given
+    /// `version`.
+    pub fn new(version: RelayCellFormat) -> Self {
+        let internal = match version {
+            RelayCellFormat::V0 => RelayCellEncoderInternal::V0 {
+                pending: VecDeque::new(),
+            },
+            RelayCellFormat::V1 => RelayCellEncoderInternal::V1 {
+                pending: VecDeque::new(),
+                offset: 0,
+            },
+        };
+        Self { internal }
+    }
+
+    /// Encode `msg`, and queue it to be put into cells.
+    pub fn push<M: RelayMsg>(&mut self, msg: RelayMsgOuter<M>) -> crate::Result<()> {
+        match &mut self.internal {
+            RelayCellEncoderInternal::V0 { pending } => {
+                pending.push_back(msg.encode_to_cell()?);
+            }
+            RelayCellEncoderInternal::V1 { pending, .. } => {
+                pending.push_back(msg.encode_v1_msg()?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Return true if there are queued messages that have not yet been
+    /// (completely) put into a cell.
+    pub fn has_pending(&self) -> bool {
+        match &self.internal {
+            RelayCellEncoderInternal::V0 { pending } => !pending.is_empty(),
+            RelayCellEncoderInternal::V1 { pending, .. } => !pending.is_empty(),
         }
     }
+
+    /// Return the next cell body, filled with as much of the queued data as
+    /// will fit and padded with random bytes.
+    ///
+    /// Returns `None` if there is nothing queued.
+    pub fn take_cell<R: Rng + CryptoRng>(&mut self, rng: &mut R) -> Option<BoxedCellBody> {
+        match &mut self.internal {
+            RelayCellEncoderInternal::V0 { pending } => {
+                let (body, enc_len) = pending.pop_front()?;
+                Some(pad_cell_body(body, enc_len, rng))
+            }
+            RelayCellEncoderInternal::V1 { pending, offset } => {
+                if pending.is_empty() {
+                    return None;
+                }
+                let mut body = Box::new([0_u8; CELL_DATA_LEN]);
+                let mut pos = V1_TAG_LEN;
+                while let Some((msg, header_len)) = pending.front() {
+                    let space = CELL_DATA_LEN - pos;
+                    if *offset == 0 && *header_len > space {
+                        // Headers may not span cells.
+                        break;
+                    }
+                    let n = std::cmp::min(msg.len() - *offset, space);
+                    body[pos..pos + n].copy_from_slice(&msg[*offset..*offset + n]);
+                    pos += n;
+                    *offset += n;
+                    if *offset < msg.len() {
+                        // The rest of this message goes in the next cell.
+                        break;
+                    }
+                    pending.pop_front();
+                    *offset = 0;
+                }
+                Some(pad_cell_body(body, pos, rng))
+            }
+        }
+    }
+}
+
+/// Fill the unused part of a relay cell body with random padding, leaving
+/// some zero bytes after the first `enc_len` bytes.
+///
+/// The zero bytes tell the recipient that there are no more messages in the
+/// cell.
+fn pad_cell_body<R: Rng + CryptoRng>(
+    mut body: BoxedCellBody,
+    enc_len: usize,
+    rng: &mut R,
+) -> BoxedCellBody {
+    /// We skip this much space before adding any random padding to the
+    /// end of the cell
+    const MIN_SPACE_BEFORE_PADDING: usize = 4;
+
+    debug_assert!(enc_len <= CELL_DATA_LEN);
+    if enc_len < CELL_DATA_LEN - MIN_SPACE_BEFORE_PADDING {
+        rng.fill_bytes(&mut body[enc_len + MIN_SPACE_BEFORE_PADDING..]);
+    }
+    body
 }
 
 /// Result of calling `RelayCellDecoder::decode`.
@@ -391,6 +705,16 @@ enum UnparsedRelayMsgInternal {
     // It *is* a bit ugly to have to encode so much knowledge about the format in
     // different functions here, but that information shouldn't leak out of this module.
     V0(BoxedCellBody),
+    /// For `RelayCellFormat::V1`, a message may have come from several
+    /// cells, so we reassemble its body.
+    V1 {
+        /// The message's command.
+        cmd: RelayCmd,
+        /// The message's stream ID, if any.
+        stream_id: Option<StreamId>,
+        /// The message's body.
+        body: Vec<u8>,
+    },
 }
 
 /// An enveloped relay message that has not yet been fully parsed, but where we
@@ -441,6 +765,7 @@ impl UnparsedRelayMsg {
                 const CMD_OFFSET: usize = 0;
                 body[CMD_OFFSET].into()
             }
+            UnparsedRelayMsgInternal::V1 { cmd, .. } => *cmd,
         }
     }
     /// Return the stream ID for the stream that this msg corresponds to, if any.
@@ -451,6 +776,7 @@ impl UnparsedRelayMsg {
                     .try_into()
                     .expect("two-byte slice was not two bytes long!?"),
             )),
+            UnparsedRelayMsgInternal::V1 { stream_id, .. } => *stream_id,
         }
     }
     /// Decode this unparsed cell into a given cell type.
@@ -460,6 +786,18 @@ impl UnparsedRelayMsg {
                 let mut reader = Reader::from_slice(body.as_ref());
                 RelayMsgOuter::decode_v0_from_reader(&mut reader)
             }
+            UnparsedRelayMsgInternal::V1 {
+                cmd,
+                stream_id,
+                body,
+            } => {
+                let mut reader = Reader::from_slice(&body[..]);
+                let msg = M::decode_from_reader(cmd, &mut reader)?;
+                Ok(RelayMsgOuter {
+                    streamid: stream_id,
+                    msg,
+                })
+            }
         }
     }
 }
@@ -534,18 +872,38 @@ impl<M: RelayMsg> RelayMsgOuter<M> {
     }
     /// Consume this relay message and encode it as a 509-byte padded cell
     /// body.
+    ///
+    /// This always uses `RelayCellFormat::V0`; use [`RelayCellEncoder`] for
+    /// other formats.
     pub fn encode<R: Rng + CryptoRng>(self, rng: &mut R) -> crate::Result<BoxedCellBody> {
-        /// We skip this much space before adding any random padding to the
-        /// end of the cell
-        const MIN_SPACE_BEFORE_PADDING: usize = 4;
-
-        let (mut body, enc_len) = self.encode_to_cell()?;
-        debug_assert!(enc_len <= CELL_DATA_LEN);
-        if enc_len < CELL_DATA_LEN - MIN_SPACE_BEFORE_PADDING {
-            rng.fill_bytes(&mut body[enc_len + MIN_SPACE_BEFORE_PADDING..]);
-        }
+        let (body, enc_len) = self.encode_to_cell()?;
+        Ok(pad_cell_body(body, enc_len, rng))
+    }
 
-        Ok(body)
+    /// Consume this relay message and encode it as a `RelayCellFormat::V1`
+    /// message: a header followed by the body.
+    ///
+    /// Return the encoded message and the length of its header.
+    fn encode_v1_msg(self) -> EncodeResult<(Vec<u8>, usize)> {
+        let cmd = self.msg.cmd();
+        let header_len = v1_header_len(cmd);
+        let mut w = Vec::with_capacity(CELL_DATA_LEN);
+        w.write_u8(cmd.into());
+        w.write_u16(0); // Length; filled in below.
+        if v1_cmd_has_stream_id(cmd) {
+            w.write_u16(StreamId::get_or_zero(self.streamid));
+        } else if self.streamid.is_some() {
+            return Err(EncodeError::Bug(internal!(
+                "Tried to encode a {} message with a stream ID",
+                cmd
+            )));
+        }
+        debug_assert_eq!(w.len(), header_len);
+        self.msg.encode_onto(&mut w)?;
+        let body_len =
+            u16::try_from(w.len() - header_len).map_err(|_| EncodeError::BadLengthValue)?;
+        w[1..3].copy_from_slice(&body_len.to_be_bytes());
+        Ok((w, header_len))
     }
 
     /// Consume a relay cell and return its contents, encoded for use
@@ -64,6 +64,10 @@ pub enum AnyRelayMsg : RelayMsg {
     Resolved,
     /// Start a directory stream
     BeginDir,
+    /// Ask the other side to stop sending data on a stream
+    Xoff,
+    /// Ask the other side to resume sending data on a stream
+    Xon,
     /// Start a UDP stream.
     [feature = "experimental-udp"]
     ConnectUdp,
@@ -561,6 +565,14 @@ impl Connected {
             addr: Some((addr, ttl)),
         }
     }
+    /// Return the address that the exit connected to, if it told us.
+    pub fn addr(&self) -> Option<IpAddr> {
+        self.addr.map(|(addr, _ttl)| addr)
+    }
+    /// Return the time-to-live value for the address, in seconds, if there is one.
+    pub fn ttl(&self) -> Option<u32> {
+        self.addr.map(|(_addr, ttl)| ttl)
+    }
 }
 impl Body for Connected {
     fn decode_from_reader(r: &mut Reader<'_>) -> Result<Self> {
@@ -676,6 +688,79 @@ impl Body for Sendme {
     }
 }
 
+/// An Xoff message asks the other side of a stream to stop sending data.
+///
+/// Once congestion control is in use on a circuit, Xoff and Xon replace
+/// stream-level Sendme messages: instead of granting credit, a reader
+/// tells the sender to pause when its buffers are too full.
+///
+/// See proposal 324 for more information.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Xoff {}
+
+impl Xoff {
+    /// Construct a new Xoff message.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Body for Xoff {
+    fn decode_from_reader(r: &mut Reader<'_>) -> Result<Self> {
+        match r.take_u8()? {
+            0 => Ok(Xoff {}),
+            _ => Err(Error::InvalidMessage("Unrecognized XOFF version.".into())),
+        }
+    }
+    fn encode_onto<W: Writer + ?Sized>(self, w: &mut W) -> EncodeResult<()> {
+        w.write_u8(0); // version
+        Ok(())
+    }
+}
+
+/// An Xon message asks the other side of a stream to resume sending data,
+/// after an earlier [`Xoff`].
+///
+/// It also tells the sender how fast the reader has recently been draining
+/// the stream, so that the sender can adjust its own rate.
+#[derive(Debug, Clone)]
+pub struct Xon {
+    /// The rate at which the reader has been consuming data from the
+    /// stream, in kilobits per second, or 0 for "unlimited."
+    kbps_ewma: u32,
+}
+
+impl Xon {
+    /// Construct a new Xon message reporting a drain rate of `kbps_ewma`.
+    ///
+    /// A rate of 0 means that the sender may send as fast as it likes.
+    pub fn new(kbps_ewma: u32) -> Self {
+        Xon { kbps_ewma }
+    }
+    /// Return the drain rate advertised by this message, in kilobits per
+    /// second.  (0 means "unlimited.")
+    pub fn kbps_ewma(&self) -> u32 {
+        self.kbps_ewma
+    }
+}
+
+impl Body for Xon {
+    fn decode_from_reader(r: &mut Reader<'_>) -> Result<Self> {
+        match r.take_u8()? {
+            0 => Ok(Xon {
+                kbps_ewma: r.take_u32()?,
+            }),
+            _ => Err(Error::InvalidMessage("Unrecognized XON version.".into())),
+        }
+    }
+    fn encode_onto<W: Writer + ?Sized>(self, w: &mut W) -> EncodeResult<()> {
+        w.write_u8(0); // version
+        w.write_u32(self.kbps_ewma);
+        Ok(())
+    }
+}
+
 /// Extend was an obsolete circuit extension message format.
 ///
 /// This format only handled IPv4 addresses, RSA identities, and the
@@ -1267,7 +1352,7 @@ macro_rules! msg_impl_relaymsg {
 
 msg_impl_relaymsg!(
     Begin, Data, End, Connected, Sendme, Extend, Extended, Extend2, Extended2, Truncate, Truncated,
-    Drop, Resolve, Resolved, BeginDir,
+    Drop, Resolve, Resolved, BeginDir, Xoff, Xon,
 );
 
 #[cfg(feature = "experimental-udp")]
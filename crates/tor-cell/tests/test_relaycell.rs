@@ -4,7 +4,8 @@
 use tor_bytes::Error;
 use tor_cell::relaycell::{
     msg::{self, AnyRelayMsg},
-    AnyRelayMsgOuter, RelayCellFormat, RelayCmd, RelayMsg, StreamId, UnparsedRelayMsg,
+    AnyRelayMsgOuter, RelayCellDecoder, RelayCellEncoder, RelayCellFormat, RelayCmd, RelayMsg,
+    StreamId, UnparsedRelayMsg,
 };
 
 #[cfg(feature = "experimental-udp")]
@@ -208,3 +209,130 @@ fn test_address() {
         Some(Error::InvalidMessage("Nul byte not permitted".into()))
     );
 }
+
+/// Decode every cell in `cells` with a fresh `RelayCellDecoder`, and return
+/// the Debug representation of every complete message.
+fn decode_all(
+    format: RelayCellFormat,
+    cells: Vec<tor_cell::chancell::BoxedCellBody>,
+) -> Vec<String> {
+    let mut decoder = RelayCellDecoder::new(format);
+    let mut out = Vec::new();
+    for cell in cells {
+        let (msgs, _) = decoder.decode(cell).unwrap().into_parts();
+        for m in msgs {
+            out.push(format!("{:?}", m.decode::<AnyRelayMsg>().unwrap()));
+        }
+    }
+    assert!(decoder.incomplete_info().is_none());
+    out
+}
+
+/// Push every message in `msgs` onto a new encoder, and return all the cells.
+fn encode_all(
+    format: RelayCellFormat,
+    msgs: &[AnyRelayMsgOuter],
+) -> Vec<tor_cell::chancell::BoxedCellBody> {
+    let mut encoder = RelayCellEncoder::new(format);
+    for m in msgs {
+        let m = AnyRelayMsgOuter::new(m.stream_id(), m.msg().clone());
+        encoder.push(m).unwrap();
+    }
+    let mut cells = Vec::new();
+    while let Some(cell) = encoder.take_cell(&mut BadRng) {
+        cells.push(cell);
+    }
+    assert!(!encoder.has_pending());
+    cells
+}
+
+fn example_msgs() -> Vec<AnyRelayMsgOuter> {
+    let sid = StreamId::new(7);
+    vec![
+        AnyRelayMsgOuter::new(sid, msg::Data::new(b"hello world").unwrap().into()),
+        AnyRelayMsgOuter::new(None, msg::Sendme::new_empty().into()),
+        AnyRelayMsgOuter::new(sid, msg::Data::new(&[b'x'; 400]).unwrap().into()),
+        AnyRelayMsgOuter::new(sid, msg::Data::new(&[b'y'; 400]).unwrap().into()),
+        AnyRelayMsgOuter::new(None, msg::Drop::default().into()),
+        AnyRelayMsgOuter::new(sid, msg::Data::new(&[b'z'; 498]).unwrap().into()),
+    ]
+}
+
+#[test]
+fn encoder_v0() {
+    // In V0, every message gets its own cell, encoded the same way as
+    // `AnyRelayMsgOuter::encode` would.
+    let msgs = example_msgs();
+    let cells = encode_all(RelayCellFormat::V0, &msgs);
+    assert_eq!(cells.len(), msgs.len());
+    for (m, cell) in msgs.iter().zip(cells.iter()) {
+        let m = AnyRelayMsgOuter::new(m.stream_id(), m.msg().clone());
+        assert_eq!(&m.encode(&mut BadRng).unwrap()[..], &cell[..]);
+    }
+    let expected: Vec<_> = msgs.iter().map(|m| format!("{:?}", m)).collect();
+    assert_eq!(decode_all(RelayCellFormat::V0, cells), expected);
+}
+
+#[test]
+fn encoder_v1_packing() {
+    let msgs = example_msgs();
+    let cells = encode_all(RelayCellFormat::V1, &msgs);
+    // 1309 bytes of bodies and 28 bytes of headers: they fit in three cells
+    // of 493 usable bytes each.
+    assert_eq!(cells.len(), 3);
+
+    // The first three messages share a cell, and the fourth starts there too.
+    let mut decoder = RelayCellDecoder::new(RelayCellFormat::V1);
+    let res = decoder.decode(cells[0].clone()).unwrap();
+    let cmds: Vec<_> = res.cmds().collect();
+    assert_eq!(
+        cmds,
+        vec![
+            RelayCmd::DATA,
+            RelayCmd::SENDME,
+            RelayCmd::DATA,
+            RelayCmd::DATA
+        ]
+    );
+    let incomplete = res.incomplete_info().unwrap();
+    assert_eq!(incomplete.cmd(), RelayCmd::DATA);
+    assert_eq!(incomplete.stream_id(), StreamId::new(7));
+    assert_eq!(incomplete.total_msg_len(), 400);
+    assert_eq!(incomplete.num_bytes_present(), 62);
+    assert_eq!(incomplete.num_bytes_missing(), 338);
+
+    let expected: Vec<_> = msgs.iter().map(|m| format!("{:?}", m)).collect();
+    assert_eq!(decode_all(RelayCellFormat::V1, cells), expected);
+}
+
+#[test]
+fn encoder_v1_fragmentation() {
+    // A message that doesn't fit in the rest of a cell continues in the
+    // next one, with no header.
+    let sid = StreamId::new(1);
+    let msgs: Vec<_> = (0..5_u8)
+        .map(|n| AnyRelayMsgOuter::new(sid, msg::Data::new(&[n; 498]).unwrap().into()))
+        .collect();
+    let cells = encode_all(RelayCellFormat::V1, &msgs);
+    assert_eq!(cells.len(), 6);
+
+    let expected: Vec<_> = msgs.iter().map(|m| format!("{:?}", m)).collect();
+    assert_eq!(decode_all(RelayCellFormat::V1, cells), expected);
+}
+
+#[test]
+fn decoder_v1_bad_cells() {
+    // A cell with no messages at all.
+    let mut decoder = RelayCellDecoder::new(RelayCellFormat::V1);
+    let cell = Box::new([0_u8; CELL_BODY_LEN]);
+    assert!(decoder.decode(cell).is_err());
+
+    // A DROP message, followed by a DATA header that runs off the end of the
+    // cell.
+    let mut cell = Box::new([0_u8; CELL_BODY_LEN]);
+    cell[16] = RelayCmd::DROP.into();
+    cell[17..19].copy_from_slice(&487_u16.to_be_bytes());
+    cell[506] = RelayCmd::DATA.into();
+    let mut decoder = RelayCellDecoder::new(RelayCellFormat::V1);
+    assert!(decoder.decode(cell).is_err());
+}
@@ -149,6 +149,13 @@ fn test_connected() {
         &msg::Connected::new_with_addr(addr, 0xe10).into(),
     );
 
+    let c = msg::Connected::new_with_addr(addr, 0xe10);
+    assert_eq!(c.addr(), Some(addr));
+    assert_eq!(c.ttl(), Some(0xe10));
+    let c = msg::Connected::new_empty();
+    assert_eq!(c.addr(), None);
+    assert_eq!(c.ttl(), None);
+
     // hand-generated: bogus address type.
     msg_error(
         cmd,
@@ -443,6 +450,37 @@ fn test_sendme() {
     )
 }
 
+#[test]
+fn test_xoff() {
+    // these values are hand-generated.
+    let cmd = RelayCmd::XOFF;
+    assert_eq!(Into::<u8>::into(cmd), 43_u8);
+
+    msg(cmd, "00", &msg::Xoff::new().into());
+    msg_error(
+        cmd,
+        "01",
+        BytesError::InvalidMessage("Unrecognized XOFF version.".into()),
+    );
+    msg_error(cmd, "", BytesError::Truncated);
+}
+
+#[test]
+fn test_xon() {
+    // these values are hand-generated.
+    let cmd = RelayCmd::XON;
+    assert_eq!(Into::<u8>::into(cmd), 44_u8);
+
+    msg(cmd, "00 00000000", &msg::Xon::new(0).into());
+    msg(cmd, "00 00012345", &msg::Xon::new(0x12345).into());
+    msg_error(
+        cmd,
+        "01 00000000",
+        BytesError::InvalidMessage("Unrecognized XON version.".into()),
+    );
+    msg_error(cmd, "00 0000", BytesError::Truncated);
+}
+
 #[test]
 fn test_truncate() {
     let cmd = RelayCmd::TRUNCATE;
@@ -1,7 +1,8 @@
 //! Implement a concrete type to build channels over a transport.
 
 use std::io;
-use std::sync::{Arc, Mutex};
+use std::result::Result as StdResult;
+use std::sync::{Arc, Mutex, RwLock};
 
 use crate::factory::{BootstrapReporter, ChannelFactory};
 use crate::transport::TransportImplHelper;
@@ -11,11 +12,35 @@ use std::time::Duration;
 use tor_error::internal;
 use tor_linkspec::{BridgeAddr, HasChanMethod, IntoOwnedChanTarget, OwnedChanTarget};
 use tor_proto::channel::params::ChannelPaddingInstructionsUpdates;
+use tor_proto::channel::ChannelHealth;
 use tor_rtcompat::{tls::TlsConnector, Runtime, TlsProvider};
 
 use async_trait::async_trait;
 use futures::task::SpawnExt;
 
+/// An observer that is told about the TLS certificate each relay presents
+/// when we open a channel to it.
+///
+/// This is meant for diagnostics and research: a hook can log the link
+/// certificates it sees, or pin them and refuse channels whose certificates
+/// it doesn't like.  It does _not_ replace any of the usual checks: the hook
+/// only runs once the relay has proven that it has the identity we wanted.
+///
+/// Install one with [`ChanMgr::set_link_cert_hook`](crate::ChanMgr::set_link_cert_hook).
+pub trait LinkCertHook: Send + Sync {
+    /// Inspect `cert`, the DER-encoded TLS certificate that `peer` presented.
+    ///
+    /// Return an error, with a human-readable reason, to make the channel
+    /// attempt fail with [`Error::LinkCertRejected`].
+    fn check_link_cert(&self, peer: &OwnedChanTarget, cert: &[u8]) -> StdResult<(), String>;
+}
+
+/// A shared, replaceable [`LinkCertHook`].
+///
+/// Every `ChanBuilder` made by a `ChanMgr` shares the same slot, so that
+/// replacing the hook takes effect for all transports at once.
+pub(crate) type LinkCertHookSlot = Arc<RwLock<Option<Arc<dyn LinkCertHook>>>>;
+
 /// TLS-based channel builder.
 ///
 /// This is a separate type so that we can keep our channel management code
@@ -38,6 +63,8 @@ where
     transport: H,
     /// Object to build TLS connections.
     tls_connector: <R as TlsProvider<H::Stream>>::Connector,
+    /// Hook to tell about the link certificates we receive, if any.
+    link_cert_hook: LinkCertHookSlot,
 }
 
 impl<R: Runtime, H: TransportImplHelper> ChanBuilder<R, H>
@@ -51,8 +78,15 @@ where
             runtime,
             transport,
             tls_connector,
+            link_cert_hook: LinkCertHookSlot::default(),
         }
     }
+
+    /// Report link certificates to whatever hook is installed in `slot`.
+    pub(crate) fn with_link_cert_hook(mut self, slot: LinkCertHookSlot) -> Self {
+        self.link_cert_hook = slot;
+        self
+    }
 }
 #[async_trait]
 impl<R: Runtime, H: TransportImplHelper> ChannelFactory for ChanBuilder<R, H>
@@ -181,6 +215,17 @@ where
                 }
                 _ => Error::from_proto_no_skew(source, &using_target),
             })?;
+
+        // The relay has proven its identity; let the hook (if any) have a look
+        // at the certificate it used.
+        let hook = self.link_cert_hook.read()?.clone();
+        if let Some(hook) = hook {
+            hook.check_link_cert(target, &peer_cert)
+                .map_err(|reason| Error::LinkCertRejected {
+                    peer: target.to_logged(),
+                    reason,
+                })?;
+        }
         let (chan, reactor) = chan.finish().await.map_err(|source| Error::Proto {
             source,
             peer: target.to_logged(),
@@ -206,7 +251,7 @@ where
 
 impl crate::mgr::AbstractChannel for tor_proto::channel::Channel {
     fn is_usable(&self) -> bool {
-        !self.is_closing()
+        !self.is_closing() && self.health() == ChannelHealth::Healthy
     }
     fn duration_unused(&self) -> Option<Duration> {
         self.duration_unused()
@@ -220,6 +265,9 @@ impl crate::mgr::AbstractChannel for tor_proto::channel::Channel {
     fn engage_padding_activities(&self) {
         tor_proto::channel::Channel::engage_padding_activities(self);
     }
+    fn terminate(&self) {
+        tor_proto::channel::Channel::terminate(self);
+    }
 }
 
 #[cfg(test)]
@@ -297,7 +345,8 @@ mod test {
             client_rt.jump_to(now);
 
             // Create the channel builder that we want to test.
-            let transport = crate::transport::DefaultTransport::new(client_rt.clone());
+            let transport =
+                crate::transport::DefaultTransport::new(client_rt.clone(), Default::default());
             let builder = ChanBuilder::new(client_rt, transport);
 
             let (r1, r2): (Result<Arc<Channel>>, Result<LocalStream>) = futures::join!(
@@ -335,5 +384,88 @@ mod test {
         })
     }
 
+    /// A LinkCertHook that remembers what it saw, and accepts or rejects.
+    struct RecordingHook {
+        /// The certificates we were shown.
+        seen: Mutex<Vec<Vec<u8>>>,
+        /// Whether to accept them.
+        accept: bool,
+    }
+
+    impl LinkCertHook for RecordingHook {
+        fn check_link_cert(&self, _peer: &OwnedChanTarget, cert: &[u8]) -> StdResult<(), String> {
+            self.seen.lock().unwrap().push(cert.to_vec());
+            if self.accept {
+                Ok(())
+            } else {
+                Err("not pinned".into())
+            }
+        }
+    }
+
+    #[test]
+    fn link_cert_hook() {
+        use crate::testing::msgs;
+        let orport: SocketAddr = msgs::ADDR.parse().unwrap();
+        let client_addr = "192.0.2.17".parse().unwrap();
+        let target = OwnedChanTarget::builder()
+            .addrs(vec![orport])
+            .method(ChannelMethod::Direct(vec![orport]))
+            .ed_identity(msgs::ED_ID.into())
+            .rsa_identity(msgs::RSA_ID.into())
+            .build()
+            .unwrap();
+        let now = SystemTime::UNIX_EPOCH + Duration::new(msgs::NOW, 0);
+
+        for accept in [true, false] {
+            let hook = Arc::new(RecordingHook {
+                seen: Mutex::new(vec![]),
+                accept,
+            });
+            let slot = LinkCertHookSlot::default();
+            *slot.write().unwrap() = Some(hook.clone());
+            let target = target.clone();
+
+            test_with_one_runtime!(|rt| async move {
+                let network = MockNetwork::new();
+                let client_rt = network
+                    .builder()
+                    .add_address(client_addr)
+                    .runtime(rt.clone());
+                let client_rt = MockSleepRuntime::new(client_rt);
+                let relay_rt = network
+                    .builder()
+                    .add_address(orport.ip())
+                    .runtime(rt.clone());
+                let lis = relay_rt
+                    .mock_net()
+                    .listen_tls(&orport, msgs::X509_CERT.into())
+                    .unwrap();
+                client_rt.jump_to(now);
+
+                let transport =
+                    crate::transport::DefaultTransport::new(client_rt.clone(), Default::default());
+                let builder = ChanBuilder::new(client_rt, transport).with_link_cert_hook(slot);
+
+                let (r1, _) = futures::join!(
+                    builder.build_channel(&target, BootstrapReporter::fake()),
+                    async {
+                        let (mut con, _) = lis.accept().await.expect("accept failed");
+                        let _ = crate::testing::answer_channel_req(&mut con).await;
+                        con
+                    }
+                );
+
+                if accept {
+                    assert!(r1.is_ok());
+                } else {
+                    assert!(matches!(r1, Err(Error::LinkCertRejected { .. })));
+                }
+            });
+
+            assert_eq!(*hook.seen.lock().unwrap(), vec![msgs::X509_CERT.to_vec()]);
+        }
+    }
+
     // TODO: Write tests for timeout logic, once there is smarter logic.
 }
@@ -4,8 +4,15 @@
 //!
 //! Most types in this module are re-exported by `arti-client`.
 
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
 use tor_config::impl_standard_builder;
 use tor_config::{ConfigBuildError, PaddingLevel};
+use tor_linkspec::{HasRelayIds, RelayId};
+use tor_proto::channel::liveness::Parameters as LivenessParameters;
+use tor_rtcompat::TcpBind;
 
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
@@ -15,15 +22,271 @@ use serde::{Deserialize, Serialize};
 /// This type is immutable once constructed.  To build one, use
 /// [`ChannelConfigBuilder`], or deserialize it from a string.
 #[derive(Debug, Clone, Builder, Eq, PartialEq)]
-#[builder(build_fn(error = "ConfigBuildError"))]
+#[builder(build_fn(error = "ConfigBuildError", validate = "Self::validate"))]
 #[builder(derive(Debug, Serialize, Deserialize))]
 pub struct ChannelConfig {
     /// Control of channel padding
     #[builder(default)]
     pub(crate) padding: PaddingLevel,
+
+    /// Whether we may connect to relays over IPv4.
+    #[builder(default = "true")]
+    pub(crate) use_ipv4: bool,
+
+    /// Whether we may connect to relays over IPv6.
+    #[builder(default = "true")]
+    pub(crate) use_ipv6: bool,
+
+    /// Whether to try a relay's IPv6 addresses before its IPv4 ones.
+    #[builder(default)]
+    pub(crate) prefer_ipv6: bool,
+
+    /// If set, make our IPv4 connections to relays from this local address.
+    ///
+    /// This is for hosts with more than one address, where Tor traffic must
+    /// leave through a particular one.
+    #[builder(default)]
+    pub(crate) outbound_bind_ipv4: Option<Ipv4Addr>,
+
+    /// If set, make our IPv6 connections to relays from this local address.
+    #[builder(default)]
+    pub(crate) outbound_bind_ipv6: Option<Ipv6Addr>,
+
+    /// If true, never connect to relays over an address family for which
+    /// we have no outbound bind address.
+    ///
+    /// Without this, connections in such a family leave from whatever
+    /// address the operating system chooses, over the default route.
+    #[builder(default)]
+    pub(crate) outbound_bind_strict: bool,
+
+    /// If set, make all our connections to relays through the network
+    /// interface with this name.
+    ///
+    /// Unlike binding to a local address, this makes sure that our traffic
+    /// can't leave through any other interface, even if the routing table
+    /// changes.  Only supported on Linux and Android.
+    #[builder(default)]
+    pub(crate) outbound_interface: Option<String>,
+
+    /// Addresses to dial for particular relays, instead of the ones listed
+    /// in the directory.
+    ///
+    /// This is meant for test networks behind NAT or port forwarding, where
+    /// the addresses that relays advertise are not the ones we can reach.
+    #[builder(default)]
+    pub(crate) relay_address_overrides: BTreeMap<RelayId, SocketAddr>,
+
+    /// If set, dial this address (keeping the port) instead of any private
+    /// RFC 1918 address that a relay advertises.
+    ///
+    /// Overrides in `relay_address_overrides` take precedence over this.
+    #[builder(default)]
+    pub(crate) private_address_rewrite: Option<IpAddr>,
+
+    /// How long we may go without sending anything on a channel before we
+    /// send a `PADDING` cell on it to keep it alive.
+    ///
+    /// Zero disables keepalives.
+    #[builder(default = "default_keepalive_interval()")]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    pub(crate) keepalive_interval: Duration,
+
+    /// How long we wait for a relay to respond on a channel before we
+    /// declare the channel dead and close it.
+    ///
+    /// Once we have waited for half this long, we stop using the channel
+    /// for new circuits.
+    ///
+    /// We only wait for responses to cells that are sure to get one,
+    /// such as requests to build circuits or open streams.
+    ///
+    /// Zero disables dead-channel detection.
+    #[builder(default = "default_dead_channel_timeout()")]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    pub(crate) dead_channel_timeout: Duration,
 }
 impl_standard_builder! { ChannelConfig }
 
+/// Return the default value for `keepalive_interval`.
+fn default_keepalive_interval() -> Duration {
+    Duration::from_secs(5 * 60)
+}
+
+/// Return the default value for `dead_channel_timeout`.
+///
+/// This is twice the default circuit build timeout: a channel that has gone
+/// this long without answering a request has already made us give up on
+/// every circuit we tried to build through it.
+fn default_dead_channel_timeout() -> Duration {
+    Duration::from_secs(2 * 60)
+}
+
+impl ChannelConfigBuilder {
+    /// Check that this builder will give a reasonable configuration.
+    fn validate(&self) -> Result<(), ConfigBuildError> {
+        if self.use_ipv4 == Some(false) && self.use_ipv6 == Some(false) {
+            return Err(ConfigBuildError::Inconsistent {
+                fields: vec!["use_ipv4".into(), "use_ipv6".into()],
+                problem: "at least one address family must be enabled".into(),
+            });
+        }
+        if self.outbound_bind_strict == Some(true) {
+            let ipv4 =
+                self.use_ipv4 != Some(false) && matches!(self.outbound_bind_ipv4, Some(Some(_)));
+            let ipv6 =
+                self.use_ipv6 != Some(false) && matches!(self.outbound_bind_ipv6, Some(Some(_)));
+            if !(ipv4 || ipv6) {
+                return Err(ConfigBuildError::Inconsistent {
+                    fields: vec![
+                        "outbound_bind_strict".into(),
+                        "outbound_bind_ipv4".into(),
+                        "outbound_bind_ipv6".into(),
+                    ],
+                    problem:
+                        "outbound_bind_strict needs a bind address for an enabled address family"
+                            .into(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ChannelConfig {
+    /// Return true if we may connect to relays over IPv4.
+    ///
+    /// This is false if `use_ipv4` is false, or if `outbound_bind_strict` is
+    /// set and we have no IPv4 bind address.
+    pub fn use_ipv4(&self) -> bool {
+        self.use_ipv4 && !(self.outbound_bind_strict && self.outbound_bind_ipv4.is_none())
+    }
+
+    /// Return true if we may connect to relays over IPv6.
+    ///
+    /// This is false if `use_ipv6` is false, or if `outbound_bind_strict` is
+    /// set and we have no IPv6 bind address.
+    pub fn use_ipv6(&self) -> bool {
+        self.use_ipv6 && !(self.outbound_bind_strict && self.outbound_bind_ipv6.is_none())
+    }
+
+    /// Return the local address, if any, from which we make IPv4 connections to relays.
+    pub fn outbound_bind_ipv4(&self) -> Option<Ipv4Addr> {
+        self.outbound_bind_ipv4
+    }
+
+    /// Return the local address, if any, from which we make IPv6 connections to relays.
+    pub fn outbound_bind_ipv6(&self) -> Option<Ipv6Addr> {
+        self.outbound_bind_ipv6
+    }
+
+    /// Return true if we should try IPv6 addresses before IPv4 ones.
+    pub fn prefer_ipv6(&self) -> bool {
+        self.prefer_ipv6
+    }
+
+    /// Return the local addresses and interface from which we should connect to relays.
+    pub(crate) fn outbound_bind(&self) -> OutboundBind {
+        OutboundBind {
+            ipv4: self.outbound_bind_ipv4,
+            ipv6: self.outbound_bind_ipv6,
+            interface: self.outbound_interface.clone(),
+        }
+    }
+
+    /// Return the keepalive and dead-channel parameters that our channels should use.
+    pub(crate) fn liveness_parameters(&self) -> LivenessParameters {
+        LivenessParameters::builder()
+            .keepalive_interval(Some(self.keepalive_interval).filter(|d| !d.is_zero()))
+            .dead_after(Some(self.dead_channel_timeout).filter(|d| !d.is_zero()))
+            .build()
+            .expect("liveness parameters builder failed")
+    }
+
+    /// Replace `addrs`, the addresses listed for `relay`, with the ones we
+    /// should actually dial according to our address overrides.
+    pub(crate) fn rewrite_addrs<T: HasRelayIds + ?Sized>(
+        &self,
+        relay: &T,
+        addrs: &mut Vec<SocketAddr>,
+    ) {
+        if let Some(addr) = relay
+            .identities()
+            .find_map(|id| self.relay_address_overrides.get(&id.to_owned()))
+        {
+            *addrs = vec![*addr];
+            return;
+        }
+        if let Some(rewrite) = self.private_address_rewrite {
+            for addr in addrs.iter_mut() {
+                if matches!(addr.ip(), IpAddr::V4(ip) if ip.is_private()) {
+                    addr.set_ip(rewrite);
+                }
+            }
+        }
+    }
+
+    /// Return true if we may connect to `addr`.
+    pub(crate) fn permits_addr(&self, addr: &SocketAddr) -> bool {
+        match addr {
+            SocketAddr::V4(_) => self.use_ipv4(),
+            SocketAddr::V6(_) => self.use_ipv6(),
+        }
+    }
+
+    /// Remove from `addrs` every address we may not connect to, and put the
+    /// rest in the order we should try them.
+    pub(crate) fn filter_and_order_addrs(&self, addrs: &mut Vec<SocketAddr>) {
+        addrs.retain(|a| self.permits_addr(a));
+        if self.prefer_ipv6 {
+            // This sort is stable, so we otherwise keep the order we were given.
+            addrs.sort_by_key(|a| a.is_ipv4());
+        }
+    }
+}
+
+/// The local addresses and interface, if any, from which we make connections to relays.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct OutboundBind {
+    /// The address for IPv4 connections.
+    ipv4: Option<Ipv4Addr>,
+    /// The address for IPv6 connections.
+    ipv6: Option<Ipv6Addr>,
+    /// The network interface for all connections.
+    interface: Option<String>,
+}
+
+impl OutboundBind {
+    /// Return the local address from which we should connect to `addr`, or
+    /// None if we should let the operating system choose.
+    ///
+    /// The port is always 0, so that the operating system picks one.
+    pub(crate) fn local_addr_for(&self, addr: &SocketAddr) -> Option<SocketAddr> {
+        let ip: IpAddr = match addr {
+            SocketAddr::V4(_) => self.ipv4?.into(),
+            SocketAddr::V6(_) => self.ipv6?.into(),
+        };
+        Some(SocketAddr::new(ip, 0))
+    }
+
+    /// Return how we should bind our connection to `addr`, or None if we
+    /// shouldn't bind it at all.
+    pub(crate) fn tcp_bind_for(&self, addr: &SocketAddr) -> Option<TcpBind> {
+        let local_addr = self.local_addr_for(addr);
+        if local_addr.is_none() && self.interface.is_none() {
+            return None;
+        }
+        let mut bind = TcpBind::new();
+        if let Some(local_addr) = local_addr {
+            bind = bind.with_local_addr(local_addr);
+        }
+        if let Some(interface) = &self.interface {
+            bind = bind.with_interface(interface.clone());
+        }
+        Some(bind)
+    }
+}
+
 #[cfg(feature = "testing")]
 impl ChannelConfig {
     /// The padding level (accessor for testing)
@@ -54,5 +317,194 @@ mod test {
         let config = ChannelConfig::default();
 
         assert_eq!(PaddingLevel::Normal, config.padding);
+        assert!(config.use_ipv4());
+        assert!(config.use_ipv6());
+        assert!(!config.prefer_ipv6());
+        assert_eq!(
+            config.liveness_parameters(),
+            LivenessParameters::builder()
+                .keepalive_interval(Some(Duration::from_secs(300)))
+                .dead_after(Some(Duration::from_secs(120)))
+                .build()
+                .unwrap()
+        );
+
+        let no_keepalive = ChannelConfig::builder()
+            .keepalive_interval(Duration::ZERO)
+            .dead_channel_timeout(Duration::from_secs(120))
+            .build()
+            .unwrap();
+        assert_eq!(
+            no_keepalive.liveness_parameters(),
+            LivenessParameters::builder()
+                .dead_after(Some(Duration::from_secs(120)))
+                .build()
+                .unwrap()
+        );
+
+        let neither = ChannelConfig::builder()
+            .keepalive_interval(Duration::ZERO)
+            .dead_channel_timeout(Duration::ZERO)
+            .build()
+            .unwrap();
+        assert_eq!(
+            neither.liveness_parameters(),
+            LivenessParameters::disabled()
+        );
+    }
+
+    #[test]
+    fn address_families() {
+        let v4a: SocketAddr = "192.0.2.1:9001".parse().unwrap();
+        let v6a: SocketAddr = "[2001:db8::1]:9001".parse().unwrap();
+        let v4b: SocketAddr = "192.0.2.2:443".parse().unwrap();
+        let addrs = vec![v4a, v6a, v4b];
+
+        let filtered = |config: &ChannelConfig| {
+            let mut addrs = addrs.clone();
+            config.filter_and_order_addrs(&mut addrs);
+            addrs
+        };
+
+        assert_eq!(filtered(&ChannelConfig::default()), addrs);
+
+        let prefer6 = ChannelConfig::builder().prefer_ipv6(true).build().unwrap();
+        assert_eq!(filtered(&prefer6), vec![v6a, v4a, v4b]);
+
+        let only6 = ChannelConfig::builder().use_ipv4(false).build().unwrap();
+        assert_eq!(filtered(&only6), vec![v6a]);
+
+        let only4 = ChannelConfig::builder().use_ipv6(false).build().unwrap();
+        assert_eq!(filtered(&only4), vec![v4a, v4b]);
+
+        let neither = ChannelConfig::builder()
+            .use_ipv4(false)
+            .use_ipv6(false)
+            .build();
+        assert!(neither.is_err());
+    }
+
+    #[test]
+    fn outbound_bind() {
+        let v4: SocketAddr = "192.0.2.1:9001".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:9001".parse().unwrap();
+
+        let bind = ChannelConfig::default().outbound_bind();
+        assert_eq!(bind.local_addr_for(&v4), None);
+        assert_eq!(bind.local_addr_for(&v6), None);
+
+        let config = ChannelConfig::builder()
+            .outbound_bind_ipv4(Some("198.51.100.3".parse().unwrap()))
+            .build()
+            .unwrap();
+        let bind = config.outbound_bind();
+        assert_eq!(
+            bind.local_addr_for(&v4),
+            Some("198.51.100.3:0".parse().unwrap())
+        );
+        assert_eq!(bind.local_addr_for(&v6), None);
+
+        let config = ChannelConfig::builder()
+            .outbound_bind_ipv6(Some("2001:db8::77".parse().unwrap()))
+            .build()
+            .unwrap();
+        let bind = config.outbound_bind();
+        assert_eq!(bind.local_addr_for(&v4), None);
+        assert_eq!(
+            bind.local_addr_for(&v6),
+            Some("[2001:db8::77]:0".parse().unwrap())
+        );
+        assert_eq!(bind.tcp_bind_for(&v4), None);
+
+        let config = ChannelConfig::builder()
+            .outbound_bind_ipv6(Some("2001:db8::77".parse().unwrap()))
+            .outbound_interface(Some("wg0".into()))
+            .build()
+            .unwrap();
+        let bind = config.outbound_bind();
+        assert_eq!(
+            bind.tcp_bind_for(&v4),
+            Some(TcpBind::new().with_interface("wg0"))
+        );
+        assert_eq!(
+            bind.tcp_bind_for(&v6),
+            Some(
+                TcpBind::new()
+                    .with_local_addr("[2001:db8::77]:0".parse().unwrap())
+                    .with_interface("wg0")
+            )
+        );
+    }
+
+    #[test]
+    fn outbound_bind_strict() {
+        let v4: SocketAddr = "192.0.2.1:9001".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:9001".parse().unwrap();
+
+        // Only the bound family is permitted.
+        let config = ChannelConfig::builder()
+            .outbound_bind_ipv4(Some("198.51.100.3".parse().unwrap()))
+            .outbound_bind_strict(true)
+            .build()
+            .unwrap();
+        assert!(config.use_ipv4());
+        assert!(!config.use_ipv6());
+        assert!(config.permits_addr(&v4));
+        assert!(!config.permits_addr(&v6));
+
+        // Strictness without any usable bind address leaves nothing to use.
+        assert!(ChannelConfig::builder()
+            .outbound_bind_strict(true)
+            .build()
+            .is_err());
+        assert!(ChannelConfig::builder()
+            .outbound_bind_ipv4(Some("198.51.100.3".parse().unwrap()))
+            .use_ipv4(false)
+            .outbound_bind_strict(true)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn address_overrides() {
+        use tor_linkspec::OwnedChanTarget;
+
+        use tor_llcrypto::pk::rsa::RsaIdentity;
+
+        let rsa = RsaIdentity::from([1; 20]);
+        let other = RsaIdentity::from([2; 20]);
+        let relay = |id: &RsaIdentity| {
+            OwnedChanTarget::builder()
+                .rsa_identity(*id)
+                .build()
+                .unwrap()
+        };
+        let private: SocketAddr = "10.0.0.5:5000".parse().unwrap();
+        let public: SocketAddr = "192.0.2.1:9001".parse().unwrap();
+        let forwarded: SocketAddr = "198.51.100.7:6000".parse().unwrap();
+        let nat: IpAddr = "203.0.113.3".parse().unwrap();
+
+        let mut overrides = BTreeMap::new();
+        overrides.insert(rsa.into(), forwarded);
+        let config = ChannelConfig::builder()
+            .relay_address_overrides(overrides)
+            .private_address_rewrite(Some(nat))
+            .build()
+            .unwrap();
+
+        // A relay with an override is dialed at the override address.
+        let mut addrs = vec![private, public];
+        config.rewrite_addrs(&relay(&rsa), &mut addrs);
+        assert_eq!(addrs, vec![forwarded]);
+
+        // Other relays get their private addresses rewritten.
+        let mut addrs = vec![private, public];
+        config.rewrite_addrs(&relay(&other), &mut addrs);
+        assert_eq!(addrs, vec![SocketAddr::new(nat, 5000), public]);
+
+        // By default, nothing changes.
+        let mut addrs = vec![private, public];
+        ChannelConfig::default().rewrite_addrs(&relay(&rsa), &mut addrs);
+        assert_eq!(addrs, vec![private, public]);
     }
 }
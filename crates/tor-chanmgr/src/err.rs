@@ -101,6 +101,14 @@ pub enum Error {
     #[error("Relay identity keys were only a partial match for what we wanted.")]
     IdentityConflict,
 
+    /// None of a relay's addresses belong to an address family that we are
+    /// configured to use.
+    #[error("No address for {peer} that we are configured to use")]
+    NoPermittedAddress {
+        /// Who we were trying to talk to
+        peer: LoggedChanTarget,
+    },
+
     /// Tried to connect via a transport that we don't support.
     #[error("No plugin available for the transport {0}")]
     NoSuchTransport(tor_linkspec::TransportId),
@@ -123,6 +131,16 @@ pub enum Error {
     #[error("Pluggable transport error: {0}")]
     Pt(#[source] Arc<dyn AbstractPtError>),
 
+    /// The installed [`LinkCertHook`](crate::builder::LinkCertHook) refused
+    /// the TLS certificate that a relay presented.
+    #[error("Link certificate from {peer} rejected: {reason}")]
+    LinkCertRejected {
+        /// Who we were talking to
+        peer: LoggedChanTarget,
+        /// Why the hook rejected the certificate.
+        reason: String,
+    },
+
     /// An internal error of some kind that should never occur.
     #[error("Internal error")]
     Internal(#[from] tor_error::Bug),
@@ -156,9 +174,11 @@ impl tor_error::HasKind for Error {
             E::Proto { source, .. } => source.kind(),
             E::PendingFailed { .. } => EK::TorAccessFailed,
             E::NoSuchTransport(_) => EK::InvalidConfig,
+            E::NoPermittedAddress { .. } => EK::NoPath,
             E::UnusableTarget(_) | E::Internal(_) => EK::Internal,
             E::MissingId => EK::BadApiUsage,
             E::IdentityConflict => EK::TorAccessFailed,
+            E::LinkCertRejected { .. } => EK::TorAccessFailed,
             E::ChannelBuild { .. } => EK::TorAccessFailed,
             E::RequestCancelled => EK::TransientFailure,
             E::Proxy(e) => e.kind(),
@@ -199,10 +219,18 @@ impl tor_error::HasRetryTime for Error {
             // This can't succeed until the relay is reconfigured.
             E::IdentityConflict => RT::Never,
 
+            // The hook will presumably keep rejecting this certificate.
+            E::LinkCertRejected { .. } => RT::Never,
+
             // This one can't succeed until the bridge, or our set of
             // transports, is reconfigured.
             E::NoSuchTransport(_) => RT::Never,
 
+            // This relay's addresses won't change until we get a new
+            // directory, and our address families won't change until we
+            // are reconfigured.
+            E::NoPermittedAddress { .. } => RT::Never,
+
             E::RequestCancelled => RT::Never,
 
             // These aren't recoverable at all.
@@ -1,6 +1,8 @@
 //! Traits and code to define different mechanisms for building Channels to
 //! different kinds of targets.
 
+#[cfg(feature = "pt-client")]
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
 use crate::event::ChanMgrEventSender;
@@ -141,6 +143,11 @@ pub(crate) struct CompoundFactory {
     #[cfg(feature = "pt-client")]
     /// The PtMgr to use for pluggable transports
     ptmgr: Option<Arc<dyn AbstractPtMgr + 'static>>,
+    #[cfg(feature = "pt-client")]
+    /// Factories for pluggable transports that run inside this process.
+    ///
+    /// These take precedence over the PtMgr.
+    in_process: HashMap<PtTransportName, Arc<dyn ChannelFactory + Send + Sync + 'static>>,
     /// The factory to use for everything else
     default_factory: Arc<dyn ChannelFactory + 'static>,
 }
@@ -156,6 +163,10 @@ impl ChannelFactory for CompoundFactory {
         let factory = match target.chan_method() {
             Direct(_) => self.default_factory.clone(),
             #[cfg(feature = "pt-client")]
+            Pluggable(a) if self.in_process.contains_key(a.transport()) => {
+                self.in_process[a.transport()].clone()
+            }
+            #[cfg(feature = "pt-client")]
             Pluggable(a) => match self.ptmgr.as_ref() {
                 Some(mgr) => mgr
                     .factory_for_transport(a.transport())
@@ -187,6 +198,8 @@ impl CompoundFactory {
             default_factory,
             #[cfg(feature = "pt-client")]
             ptmgr,
+            #[cfg(feature = "pt-client")]
+            in_process: HashMap::new(),
         }
     }
 
@@ -195,4 +208,15 @@ impl CompoundFactory {
     pub(crate) fn replace_ptmgr(&mut self, ptmgr: Arc<dyn AbstractPtMgr + 'static>) {
         self.ptmgr = Some(ptmgr);
     }
+
+    #[cfg(feature = "pt-client")]
+    /// Use `factory` for the pluggable transport called `transport`,
+    /// instead of asking the PtMgr.
+    pub(crate) fn add_in_process(
+        &mut self,
+        transport: PtTransportName,
+        factory: Arc<dyn ChannelFactory + Send + Sync + 'static>,
+    ) {
+        self.in_process.insert(transport, factory);
+    }
 }
@@ -55,7 +55,7 @@ use futures::select_biased;
 use futures::task::SpawnExt;
 use futures::StreamExt;
 use std::result::Result as StdResult;
-use std::sync::{Arc, Weak};
+use std::sync::{Arc, RwLock, Weak};
 use std::time::Duration;
 use tor_config::ReconfigureError;
 use tor_error::error_report;
@@ -65,6 +65,7 @@ use tor_proto::channel::Channel;
 use tracing::debug;
 use void::{ResultVoidErrExt, Void};
 
+pub use builder::LinkCertHook;
 pub use err::Error;
 
 pub use config::{ChannelConfig, ChannelConfigBuilder};
@@ -118,9 +119,34 @@ pub struct ChanMgr<R: Runtime> {
     /// Stream of [`ConnStatus`] events.
     bootstrap_status: event::ConnStatusEvents,
 
-    /// This currently isn't actually used, but we're keeping a PhantomData here
-    /// since probably we'll want it again, sooner or later.
-    runtime: std::marker::PhantomData<fn(R) -> R>,
+    /// The runtime, used to build channel factories for in-process
+    /// pluggable transports.
+    #[cfg_attr(not(feature = "pt-client"), allow(dead_code))]
+    runtime: R,
+
+    /// The [`LinkCertHook`] shared by every channel builder we create.
+    link_cert_hook: builder::LinkCertHookSlot,
+
+    /// The configuration used by our default transport to decide which
+    /// addresses it may dial.
+    dial_config: Arc<RwLock<ChannelConfig>>,
+}
+
+/// A channel that a [`ChanMgr`] is keeping track of, as returned by
+/// [`ChanMgr::list_channels`].
+///
+/// This is meant for debugging and diagnostics.
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct ChannelListEntry {
+    /// The channel itself.
+    pub channel: Arc<Channel>,
+    /// True if this is the canonical channel to its relay:
+    /// the one that we give out for new requests.
+    ///
+    /// If this is false, we have a better channel to the same relay,
+    /// and we will close this one once it has been idle for a little while.
+    pub canonical: bool,
 }
 
 /// Description of how we got a channel.
@@ -199,8 +225,11 @@ impl<R: Runtime> ChanMgr<R> {
         let (sender, receiver) = event::channel();
         let sender = Arc::new(std::sync::Mutex::new(sender));
         let reporter = BootstrapReporter(sender);
-        let transport = transport::DefaultTransport::new(runtime.clone());
-        let builder = builder::ChanBuilder::new(runtime, transport);
+        let dial_config = Arc::new(RwLock::new(config.clone()));
+        let transport = transport::DefaultTransport::new(runtime.clone(), dial_config.clone());
+        let link_cert_hook = builder::LinkCertHookSlot::default();
+        let builder = builder::ChanBuilder::new(runtime.clone(), transport)
+            .with_link_cert_hook(link_cert_hook.clone());
         let factory = factory::CompoundFactory::new(
             Arc::new(builder),
             #[cfg(feature = "pt-client")]
@@ -210,7 +239,9 @@ impl<R: Runtime> ChanMgr<R> {
         ChanMgr {
             mgr,
             bootstrap_status: receiver,
-            runtime: std::marker::PhantomData,
+            runtime,
+            link_cert_hook,
+            dial_config,
         }
     }
 
@@ -277,6 +308,30 @@ impl<R: Runtime> ChanMgr<R> {
         self.mgr.expire_channels()
     }
 
+    /// Return every open channel that we are keeping track of.
+    ///
+    /// When we have more than one channel to the same relay,
+    /// only one of them is canonical; see [`ChannelListEntry::canonical`].
+    pub fn list_channels(&self) -> Result<Vec<ChannelListEntry>> {
+        Ok(self
+            .mgr
+            .list_channels()?
+            .into_iter()
+            .map(|(channel, canonical)| ChannelListEntry { channel, canonical })
+            .collect())
+    }
+
+    /// Install `hook` to inspect the TLS certificate of every relay we open a
+    /// channel to, replacing any previous hook.  Pass `None` to remove it.
+    ///
+    /// This affects channels launched after this call.  Channels built by a
+    /// pluggable transport manager (see `set_pt_mgr`)
+    /// are not covered.
+    pub fn set_link_cert_hook(&self, hook: Option<Arc<dyn LinkCertHook>>) -> Result<()> {
+        *self.link_cert_hook.write()? = hook;
+        Ok(())
+    }
+
     /// Notifies the chanmgr to be dormant like dormancy
     pub fn set_dormancy(
         &self,
@@ -294,6 +349,7 @@ impl<R: Runtime> ChanMgr<R> {
         netparams: Arc<dyn AsRef<NetParameters>>,
     ) -> StdResult<(), ReconfigureError> {
         let r = self.mgr.reconfigure(config, netparams);
+        *self.dial_config.write().expect("Lock poisoned") = config.clone();
 
         // We don't care about how, because reconfiguration can only fail due to bugs
         let _ = how;
@@ -314,6 +370,26 @@ impl<R: Runtime> ChanMgr<R> {
         self.mgr.with_mut_builder(|f| f.replace_ptmgr(ptmgr));
     }
 
+    /// Use `transport`, which runs inside this process, for bridges that use
+    /// the pluggable transport called `name`.
+    ///
+    /// In-process transports take precedence over any transport of the same
+    /// name that the PtMgr (see [`set_pt_mgr`](ChanMgr::set_pt_mgr)) knows about.
+    #[cfg(feature = "pt-client")]
+    pub fn register_in_process_transport(
+        &self,
+        name: tor_linkspec::PtTransportName,
+        transport: Arc<dyn transport::InProcessTransport>,
+    ) where
+        R: tor_rtcompat::TlsProvider<transport::BoxedPtStream>,
+    {
+        let helper = transport::in_process::InProcessHelper(transport);
+        let factory = builder::ChanBuilder::new(self.runtime.clone(), helper)
+            .with_link_cert_hook(self.link_cert_hook.clone());
+        self.mgr
+            .with_mut_builder(|f| f.add_in_process(name, Arc::new(factory)));
+    }
+
     /// Try to create a new, unmanaged channel to `target`.
     ///
     /// Unlike [`get_or_launch`](ChanMgr::get_or_launch), this function always
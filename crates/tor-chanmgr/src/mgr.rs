@@ -25,7 +25,8 @@ pub(crate) trait AbstractChannel: HasRelayIds {
     /// Return true if this channel is usable.
     ///
     /// A channel might be unusable because it is closed, because it has
-    /// hit a bug, or for some other reason.  We don't return unusable
+    /// hit a bug, because its relay has stopped responding on it,
+    /// or for some other reason.  We don't return unusable
     /// channels back to the user.
     fn is_usable(&self) -> bool;
     /// Return the amount of time a channel has not been in use.
@@ -47,6 +48,11 @@ pub(crate) trait AbstractChannel: HasRelayIds {
     ///
     /// [`Channel::engage_padding_activities`]: tor_proto::channel::Channel::engage_padding_activities
     fn engage_padding_activities(&self);
+
+    /// Close this channel, along with any circuits on it.
+    ///
+    /// We use this to close duplicate channels once they are no longer needed.
+    fn terminate(&self);
 }
 
 /// Trait to describe how channels-like objects are created.
@@ -378,53 +384,67 @@ impl<CF: AbstractChannelFactory + Clone> AbstractChanMgr<CF> {
             Ok(chan) => {
                 // The channel got built: remember it, tell the
                 // others, and return it.
-                self.channels
-                    .with_channels_and_params(|channel_map, channels_params| {
-                        match channel_map.remove_exact(target) {
-                            Some(Building(_)) => {
-                                // We successfully removed our pending
-                                // action. great!  Fall through and add
-                                // the channel we just built.
-                            }
-                            None => {
-                                // Something removed our entry from the list.
-                                // Time to retry.
-                                return Ok(None);
-                            }
-                            Some(ent @ Open(_)) => {
-                                // Oh no. Something else built an entry
-                                // here, and replaced us.  Put that
-                                // something back, and retry.
 
-                                channel_map.insert(ent);
-                                return Ok(None);
+                // If we end up with two channels to this relay,
+                // this is the one that isn't canonical, which we retire.
+                let mut duplicate = None;
+                let result =
+                    self.channels
+                        .with_channels_and_params(|channel_map, channels_params| {
+                            match channel_map.remove_exact(target) {
+                                Some(Building(_)) => {
+                                    // We successfully removed our pending
+                                    // action. great!  Fall through and add
+                                    // the channel we just built.
+                                }
+                                None => {
+                                    // Something removed our entry from the list.
+                                    // Time to retry.
+                                    return Ok(None);
+                                }
+                                Some(Open(existing)) => {
+                                    // Oh no. Something else built a channel
+                                    // to this relay, and replaced us.
+                                    // Keep whichever channel is canonical.
+                                    if !state::prefer_channel(&*chan, &*existing.channel) {
+                                        // Put that something back, and retry.
+                                        duplicate = Some(chan.clone());
+                                        channel_map.insert(Open(existing));
+                                        return Ok(None);
+                                    }
+                                    // Ours is better: fall through and add it.
+                                    duplicate = Some(existing.channel);
+                                }
                             }
-                        }
 
-                        // This isn't great.  We context switch to the newly-created
-                        // channel just to tell it how and whether to do padding.  Ideally
-                        // we would pass the params at some suitable point during
-                        // building.  However, that would involve the channel taking a
-                        // copy of the params, and that must happen in the same channel
-                        // manager lock acquisition span as the one where we insert the
-                        // channel into the table so it will receive updates.  I.e.,
-                        // here.
-                        let update = channels_params.initial_update();
-                        if let Some(update) = update {
-                            chan.reparameterize(update.into())
-                                .map_err(|_| internal!("failure on new channel"))?;
-                        }
-                        let new_entry = Open(OpenEntry {
-                            channel: chan.clone(),
-                            max_unused_duration: Duration::from_secs(
-                                rand::thread_rng()
-                                    .gen_range_checked(180..270)
-                                    .expect("not 180 < 270 !"),
-                            ),
-                        });
-                        channel_map.insert(new_entry);
-                        Ok(Some(chan))
-                    })?
+                            // This isn't great.  We context switch to the newly-created
+                            // channel just to tell it how and whether to do padding.  Ideally
+                            // we would pass the params at some suitable point during
+                            // building.  However, that would involve the channel taking a
+                            // copy of the params, and that must happen in the same channel
+                            // manager lock acquisition span as the one where we insert the
+                            // channel into the table so it will receive updates.  I.e.,
+                            // here.
+                            let update = channels_params.initial_update();
+                            if let Some(update) = update {
+                                chan.reparameterize(update.into())
+                                    .map_err(|_| internal!("failure on new channel"))?;
+                            }
+                            let new_entry = Open(OpenEntry {
+                                channel: chan.clone(),
+                                max_unused_duration: Duration::from_secs(
+                                    rand::thread_rng()
+                                        .gen_range_checked(180..270)
+                                        .expect("not 180 < 270 !"),
+                                ),
+                            });
+                            channel_map.insert(new_entry);
+                            Ok(Some(chan))
+                        })?;
+                if let Some(duplicate) = duplicate {
+                    self.channels.add_duplicate(duplicate)?;
+                }
+                result
             }
             Err(e) => {
                 // The channel failed. Make it non-pending, tell the
@@ -488,6 +508,11 @@ impl<CF: AbstractChannelFactory + Clone> AbstractChanMgr<CF> {
         self.channels.expire_channels()
     }
 
+    /// Return every open channel we know about, and whether it is canonical.
+    pub(crate) fn list_channels(&self) -> Result<Vec<(Arc<CF::Channel>, bool)>> {
+        self.channels.list_channels()
+    }
+
     /// Test only: return the current open usable channel with a given
     /// `ident`, if any.
     #[cfg(test)]
@@ -581,6 +606,9 @@ mod test {
             Ok(())
         }
         fn engage_padding_activities(&self) {}
+        fn terminate(&self) {
+            self.start_closing();
+        }
     }
 
     impl HasRelayIds for FakeChannel {
@@ -63,6 +63,14 @@ struct Inner<C: AbstractChannelFactory> {
     /// The configuration (from the config file or API caller)
     config: ChannelConfig,
 
+    /// Channels that are no longer canonical, because we have a better channel
+    /// to the same relay in `channels`.
+    ///
+    /// We don't give these out for new requests,
+    /// and we close each of them once it has been unused for
+    /// [`DUPLICATE_CHANNEL_GRACE_PERIOD`].
+    duplicates: Vec<Arc<C::Channel>>,
+
     /// Dormancy
     ///
     /// The last dormancy information we have been told about and passed on to our channels.
@@ -71,6 +79,27 @@ struct Inner<C: AbstractChannelFactory> {
     dormancy: Dormancy,
 }
 
+/// How long a duplicate channel may go unused before we close it.
+///
+/// This gives any circuits on the duplicate a chance to finish up,
+/// since we won't put new ones there.
+const DUPLICATE_CHANNEL_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+/// Return true if `a` should be the canonical channel to its relay,
+/// rather than `b` (another channel to the same relay).
+///
+/// We prefer a usable channel to an unusable one,
+/// and then a channel that's in use to one that is idle.
+/// Otherwise we prefer `b`, which should be the channel we had already,
+/// so that we don't move new circuits between channels without need.
+pub(crate) fn prefer_channel<C: AbstractChannel>(a: &C, b: &C) -> bool {
+    /// How much we like a channel: higher is better.
+    fn rank<C: AbstractChannel>(c: &C) -> (bool, bool) {
+        (c.is_usable(), c.duration_unused().is_none())
+    }
+    rank(a) > rank(b)
+}
+
 /// The state of a channel (or channel build attempt) within a map.
 ///
 /// A ChannelState can be Open (representing a fully negotiated channel) or
@@ -243,6 +272,7 @@ impl<C: AbstractChannelFactory> MgrState<C> {
             inner: std::sync::Mutex::new(Inner {
                 builder,
                 channels: ByRelayIds::new(),
+                duplicates: Vec::new(),
                 config,
                 channels_params,
                 dormancy,
@@ -304,6 +334,27 @@ impl<C: AbstractChannelFactory> MgrState<C> {
         Ok(func(channels, channels_params))
     }
 
+    /// Remember `channel` as a duplicate: one that is no longer canonical.
+    pub(crate) fn add_duplicate(&self, channel: Arc<C::Channel>) -> Result<()> {
+        let mut inner = self.inner.lock()?;
+        inner.duplicates.push(channel);
+        Ok(())
+    }
+
+    /// Return every open channel in this state, and whether it is canonical.
+    pub(crate) fn list_channels(&self) -> Result<Vec<(Arc<C::Channel>, bool)>> {
+        let inner = self.inner.lock()?;
+        let canonical = inner.channels.values().filter_map(|state| match state {
+            ChannelState::Open(ent) => Some((ent.channel.clone(), true)),
+            ChannelState::Building(_) => None,
+        });
+        let duplicates = inner
+            .duplicates
+            .iter()
+            .map(|channel| (channel.clone(), false));
+        Ok(canonical.chain(duplicates).collect())
+    }
+
     /// Remove every unusable state from the map in this state..
     #[cfg(test)]
     pub(crate) fn remove_unusable(&self) -> Result<()> {
@@ -382,13 +433,34 @@ impl<C: AbstractChannelFactory> MgrState<C> {
     ///
     /// Return a Duration until the next time at which
     /// a channel _could_ expire.
+    ///
+    /// Also close any duplicate channels that have been unused
+    /// for long enough.
     pub(crate) fn expire_channels(&self) -> Duration {
         let mut ret = Duration::from_secs(180);
-        self.inner
-            .lock()
-            .expect("Poisoned lock")
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner
             .channels
             .retain(|chan| !chan.ready_to_expire(&mut ret));
+        inner.duplicates.retain(|chan| {
+            if !chan.is_usable() {
+                return false;
+            }
+            let Some(unused) = chan.duration_unused() else {
+                // still in use
+                return true;
+            };
+            match DUPLICATE_CHANNEL_GRACE_PERIOD.checked_sub(unused) {
+                Some(remaining) if !remaining.is_zero() => {
+                    ret = std::cmp::min(ret, remaining);
+                    true
+                }
+                _ => {
+                    chan.terminate();
+                    false
+                }
+            }
+        });
         ret
     }
 }
@@ -458,7 +530,8 @@ fn parameterize(
     let mut update = channels_params
         .start_update()
         .padding_enable(send_padding.is_some())
-        .padding_negotiate(padding_negotiate);
+        .padding_negotiate(padding_negotiate)
+        .liveness(config.liveness_parameters());
     if let Some(params) = send_padding {
         update = update.padding_parameters(params);
     }
@@ -544,6 +617,7 @@ mod test {
     use super::*;
     use crate::factory::BootstrapReporter;
     use async_trait::async_trait;
+    use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::{Arc, Mutex};
     use tor_llcrypto::pk::ed25519::Ed25519Identity;
     use tor_proto::channel::params::ChannelPaddingInstructionsUpdates;
@@ -582,6 +656,7 @@ mod test {
         usable: bool,
         unused_duration: Option<u64>,
         params_update: Arc<Mutex<Option<Arc<ChannelPaddingInstructionsUpdates>>>>,
+        terminated: Arc<AtomicBool>,
     }
     impl AbstractChannel for FakeChannel {
         fn is_usable(&self) -> bool {
@@ -598,6 +673,9 @@ mod test {
             Ok(())
         }
         fn engage_padding_activities(&self) {}
+        fn terminate(&self) {
+            self.terminated.store(true, Ordering::SeqCst);
+        }
     }
     impl tor_linkspec::HasRelayIds for FakeChannel {
         fn identity(
@@ -621,6 +699,7 @@ mod test {
             usable: true,
             unused_duration: None,
             params_update: Arc::new(Mutex::new(None)),
+            terminated: Arc::new(AtomicBool::new(false)),
         };
         ChannelState::Open(OpenEntry {
             channel: Arc::new(channel),
@@ -637,6 +716,7 @@ mod test {
             usable: true,
             unused_duration,
             params_update: Arc::new(Mutex::new(None)),
+            terminated: Arc::new(AtomicBool::new(false)),
         };
         ChannelState::Open(OpenEntry {
             channel: Arc::new(channel),
@@ -649,6 +729,7 @@ mod test {
             usable: false,
             unused_duration: None,
             params_update: Arc::new(Mutex::new(None)),
+            terminated: Arc::new(AtomicBool::new(false)),
         };
         ChannelState::Open(OpenEntry {
             channel: Arc::new(channel),
@@ -724,7 +805,8 @@ mod test {
                     padding_parameters: Some(Parameters { \
                         low: IntegerMilliseconds { value: 1500 }, \
                         high: IntegerMilliseconds { value: 9500 } }), \
-                    padding_negotiate: None }"
+                    padding_negotiate: None, \
+                    liveness: None }"
             );
         });
         eprintln!();
@@ -736,6 +818,48 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn canonical_choice() {
+        let unwrap = |st: ChannelState<FakeChannel>| st.unwrap_open().clone();
+        let in_use = unwrap(ch("a"));
+        let idle = unwrap(ch_with_details("a", Duration::from_secs(180), Some(10)));
+        let unusable = unwrap(closed("a"));
+
+        assert!(prefer_channel(&in_use, &idle));
+        assert!(!prefer_channel(&idle, &in_use));
+        assert!(prefer_channel(&idle, &unusable));
+        assert!(!prefer_channel(&unusable, &idle));
+        // Other things being equal, we keep the one we had.
+        assert!(!prefer_channel(&in_use, &in_use.clone()));
+    }
+
+    #[test]
+    fn duplicates() -> Result<()> {
+        let map = new_test_state();
+        let unwrap = |st: ChannelState<FakeChannel>| Arc::new(st.unwrap_open().clone());
+        map.with_channels(|map| map.insert(ch("a")))?;
+        let busy = unwrap(ch("a"));
+        let recent = unwrap(ch_with_details("a", Duration::from_secs(180), Some(50)));
+        let old = unwrap(ch_with_details("a", Duration::from_secs(180), Some(61)));
+        let broken = unwrap(closed("a"));
+        for dup in [&busy, &recent, &old, &broken] {
+            map.add_duplicate(dup.clone())?;
+        }
+
+        let listed = map.list_channels()?;
+        assert_eq!(listed.len(), 5);
+        assert_eq!(listed.iter().filter(|(_, canonical)| *canonical).count(), 1);
+
+        // Duplicates go once they have been unused for the grace period.
+        assert_eq!(map.expire_channels().as_secs(), 10);
+        let listed = map.list_channels()?;
+        assert_eq!(listed.len(), 3);
+        assert!(old.terminated.load(Ordering::SeqCst));
+        assert!(!recent.terminated.load(Ordering::SeqCst));
+        assert!(!busy.terminated.load(Ordering::SeqCst));
+        Ok(())
+    }
+
     #[test]
     fn expire_channels() -> Result<()> {
         let map = new_test_state();
@@ -209,6 +209,15 @@ impl CaseContext {
             Ok(None) => Some(Err(())),
             Err(_) => None,
         })
+        // Keepalive settings are sent to every channel, whatever its usage;
+        // we're not interested in them here.
+        .filter(|m| {
+            !matches!(m, Ok(CtrlMsg::ConfigUpdate(u))
+                if u.liveness().is_some()
+                    && u.padding_enable().is_none()
+                    && u.padding_parameters().is_none()
+                    && u.padding_negotiate().is_none())
+        })
         .collect_vec();
 
         eprintln!("{:#?}", &messages);
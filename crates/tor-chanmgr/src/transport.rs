@@ -5,6 +5,8 @@ use futures::{AsyncRead, AsyncWrite};
 use tor_linkspec::OwnedChanTarget;
 
 pub(crate) mod default;
+#[cfg(feature = "pt-client")]
+pub mod in_process;
 pub mod proxied;
 
 pub(crate) use default::DefaultTransport;
@@ -14,6 +16,9 @@ pub(crate) use default::DefaultTransport;
 pub use proxied::ExternalProxyPlugin;
 pub use proxied::ProxyError;
 
+#[cfg(feature = "pt-client")]
+pub use in_process::{BoxedPtStream, InProcessTransport, PtStream};
+
 /// A convenient API for defining transports for use in Tor and elsewhere.
 ///
 /// This type's role is to let the implementor just define a replacement way to
@@ -1,17 +1,22 @@
 //! Implement the default transport, which opens TCP connections using a
 //! happy-eyeballs style parallel algorithm.
 
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 use async_trait::async_trait;
-use futures::{stream::FuturesUnordered, FutureExt, StreamExt, TryFutureExt};
+use futures::{stream::FuturesUnordered, FutureExt, StreamExt};
 use safelog::sensitive as sv;
 use tor_error::bad_api_usage;
-use tor_linkspec::{ChannelMethod, HasChanMethod, OwnedChanTarget};
+use tor_linkspec::{ChannelMethod, HasChanMethod, IntoOwnedChanTarget, OwnedChanTarget};
 use tor_rtcompat::{Runtime, TcpProvider};
 use tracing::trace;
 
-use crate::Error;
+use crate::config::OutboundBind;
+use crate::{ChannelConfig, Error};
 
 /// A default transport object that opens TCP connections for a
 /// `ChannelMethod::Direct`.
@@ -22,12 +27,16 @@ use crate::Error;
 pub(crate) struct DefaultTransport<R: Runtime> {
     /// The runtime that we use for connecting.
     runtime: R,
+    /// The configuration that tells us which address families we may use.
+    ///
+    /// This is shared with the `ChanMgr`, which replaces it on reconfiguration.
+    config: Arc<RwLock<ChannelConfig>>,
 }
 
 impl<R: Runtime> DefaultTransport<R> {
     /// Construct a new DefaultTransport
-    pub(crate) fn new(runtime: R) -> Self {
-        Self { runtime }
+    pub(crate) fn new(runtime: R, config: Arc<RwLock<ChannelConfig>>) -> Self {
+        Self { runtime, config }
     }
 }
 
@@ -41,7 +50,7 @@ impl<R: Runtime> crate::transport::TransportImplHelper for DefaultTransport<R> {
         &self,
         target: &OwnedChanTarget,
     ) -> crate::Result<(OwnedChanTarget, Self::Stream)> {
-        let direct_addrs: Vec<_> = match target.chan_method() {
+        let mut direct_addrs: Vec<_> = match target.chan_method() {
             ChannelMethod::Direct(addrs) => addrs,
             #[allow(unreachable_patterns)]
             _ => {
@@ -51,11 +60,26 @@ impl<R: Runtime> crate::transport::TransportImplHelper for DefaultTransport<R> {
             }
         };
 
+        let bind = self.config.read()?.outbound_bind();
+        if !direct_addrs.is_empty() {
+            let config = self.config.read()?;
+            config.rewrite_addrs(target, &mut direct_addrs);
+            config.filter_and_order_addrs(&mut direct_addrs);
+            drop(config);
+            if direct_addrs.is_empty() {
+                return Err(Error::NoPermittedAddress {
+                    peer: target.to_logged(),
+                });
+            }
+        }
+
         trace!("Launching direct connection for {}", target);
 
-        let (stream, addr) = connect_to_one(&self.runtime, &direct_addrs).await?;
+        let (stream, addr) = connect_to_one(&self.runtime, &direct_addrs, &bind).await?;
+        // Record the address we actually dialed, which may differ from the
+        // relay's listed addresses if we have an override for it.
         let mut using_target = target.clone();
-        let _ignore = using_target.chan_method_mut().retain_addrs(|a| a == &addr);
+        *using_target.chan_method_mut() = ChannelMethod::Direct(vec![addr]);
 
         Ok((using_target, stream))
     }
@@ -67,9 +91,12 @@ static CONNECTION_DELAY: Duration = Duration::from_millis(150);
 /// Connect to one of the addresses in `addrs` by running connections in parallel until one works.
 ///
 /// This implements a basic version of RFC 8305 "happy eyeballs".
+///
+/// Each connection is bound as `bind` says for it, if at all.
 async fn connect_to_one<R: Runtime>(
     rt: &R,
     addrs: &[SocketAddr],
+    bind: &OutboundBind,
 ) -> crate::Result<(<R as TcpProvider>::TcpStream, SocketAddr)> {
     // We need *some* addresses to connect to.
     if addrs.is_empty() {
@@ -90,11 +117,18 @@ async fn connect_to_one<R: Runtime>(
         .enumerate()
         .map(|(i, a)| {
             let delay = rt.sleep(CONNECTION_DELAY * i as u32);
-            delay.then(move |_| {
-                tracing::debug!("Connecting to {}", a);
-                rt.connect(a)
-                    .map_ok(move |stream| (stream, *a))
-                    .map_err(move |e| (e, *a))
+            delay.then(move |_| async move {
+                let result = match bind.tcp_bind_for(a) {
+                    Some(tcp_bind) => {
+                        tracing::debug!("Connecting to {} from {:?}", a, tcp_bind);
+                        rt.connect_from(&tcp_bind, a).await
+                    }
+                    None => {
+                        tracing::debug!("Connecting to {}", a);
+                        rt.connect(a).await
+                    }
+                };
+                result.map(|stream| (stream, *a)).map_err(|e| (e, *a))
             })
         })
         .collect::<FuturesUnordered<_>>();
@@ -145,9 +179,10 @@ mod test {
     #![allow(clippy::needless_pass_by_value)]
     //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
 
+    use std::net::IpAddr;
     use std::str::FromStr;
 
-    use tor_rtcompat::{test_with_one_runtime, SleepProviderExt};
+    use tor_rtcompat::{test_with_one_runtime, SleepProviderExt, TcpListener as _};
     use tor_rtmock::net::MockNetwork;
 
     use super::*;
@@ -185,9 +220,10 @@ mod test {
             // would be good to use MockSleepProvider instead, once we figure
             // out how to make it both reliable and convenient.
             network.add_blackhole(addr3).unwrap();
+            let no_bind = OutboundBind::default();
 
             // No addresses? Can't succeed.
-            let failure = connect_to_one(&client_rt, &[]).await;
+            let failure = connect_to_one(&client_rt, &[], &no_bind).await;
             assert!(failure.is_err());
 
             // Connect to a set of addresses including addr1? That's a success.
@@ -200,7 +236,9 @@ mod test {
                 &[addr1, addr2, addr3][..],
                 &[addr3, addr2, addr1][..],
             ] {
-                let (_conn, addr) = connect_to_one(&client_rt, addresses).await.unwrap();
+                let (_conn, addr) = connect_to_one(&client_rt, addresses, &no_bind)
+                    .await
+                    .unwrap();
                 assert_eq!(addr, addr1);
             }
 
@@ -216,7 +254,7 @@ mod test {
                 let failure = rt
                     .timeout(
                         Duration::from_millis(300),
-                        connect_to_one(&client_rt, addresses),
+                        connect_to_one(&client_rt, addresses, &no_bind),
                     )
                     .await;
                 if expect_timeout {
@@ -227,10 +265,54 @@ mod test {
             }
 
             // Connect to addr1 and addr4?  The first one should win.
-            let (_conn, addr) = connect_to_one(&client_rt, &[addr1, addr4]).await.unwrap();
+            let (_conn, addr) = connect_to_one(&client_rt, &[addr1, addr4], &no_bind)
+                .await
+                .unwrap();
             assert_eq!(addr, addr1);
-            let (_conn, addr) = connect_to_one(&client_rt, &[addr4, addr1]).await.unwrap();
+            let (_conn, addr) = connect_to_one(&client_rt, &[addr4, addr1], &no_bind)
+                .await
+                .unwrap();
             assert_eq!(addr, addr4);
         });
     }
+
+    #[test]
+    fn test_connect_bound() {
+        let client_addr1: IpAddr = "192.0.1.16".parse().unwrap();
+        let client_addr2: IpAddr = "192.0.1.17".parse().unwrap();
+        let relay_addr = SocketAddr::from_str("192.0.2.17:443").unwrap();
+
+        test_with_one_runtime!(|rt| async move {
+            let network = MockNetwork::new();
+            let client_rt = network
+                .builder()
+                .add_address(client_addr1)
+                .add_address(client_addr2)
+                .runtime(rt.clone());
+            let server_rt = network
+                .builder()
+                .add_address(relay_addr.ip())
+                .runtime(rt.clone());
+            let listener = server_rt.mock_net().listen(&relay_addr).await.unwrap();
+
+            let config = ChannelConfig::builder()
+                .outbound_bind_ipv4(Some("192.0.1.17".parse().unwrap()))
+                .build()
+                .unwrap();
+            let (_conn, addr) = connect_to_one(&client_rt, &[relay_addr], &config.outbound_bind())
+                .await
+                .unwrap();
+            assert_eq!(addr, relay_addr);
+            let (_conn, peer) = listener.accept().await.unwrap();
+            assert_eq!(peer.ip(), client_addr2);
+
+            // We can't connect from an address that isn't ours.
+            let config = ChannelConfig::builder()
+                .outbound_bind_ipv4(Some("192.0.1.99".parse().unwrap()))
+                .build()
+                .unwrap();
+            let failure = connect_to_one(&client_rt, &[relay_addr], &config.outbound_bind()).await;
+            assert!(failure.is_err());
+        });
+    }
 }
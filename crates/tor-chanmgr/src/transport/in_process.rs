@@ -0,0 +1,184 @@
+//! Support for pluggable transports that run inside this process.
+//!
+//! Most pluggable transports are separate programs, which we launch and then
+//! talk to over a local SOCKS proxy (see [`ExternalProxyPlugin`](super::ExternalProxyPlugin)).
+//! A transport written in Rust can instead implement [`InProcessTransport`],
+//! and be registered with [`ChanMgr::register_in_process_transport`](crate::ChanMgr::register_in_process_transport).
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncWrite};
+use tor_error::bad_api_usage;
+use tor_linkspec::{BridgeAddr, ChannelMethod, HasChanMethod, OwnedChanTarget, PtTarget};
+
+use super::TransportImplHelper;
+
+/// A stream returned by an [`InProcessTransport`].
+///
+/// This is implemented for every suitable type.
+pub trait PtStream: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static {}
+
+impl<T> PtStream for T where T: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static {}
+
+/// A boxed [`PtStream`].
+pub type BoxedPtStream = Box<dyn PtStream>;
+
+/// A pluggable transport, implemented inside this process.
+///
+/// Each transport handles one or more transport names (like `obfs4`); when
+/// we need to connect to a bridge whose bridge line names one of those
+/// transports, we call [`connect`](InProcessTransport::connect), and then
+/// run the Tor channel protocol (TLS and all) over the stream it returns.
+#[async_trait]
+pub trait InProcessTransport: Send + Sync {
+    /// Open a connection to the bridge at `target`, tunnelled through this
+    /// transport.
+    ///
+    /// `target.addr()` is the address from the bridge line, and
+    /// `target.settings()` holds the `key=value` parameters from the bridge
+    /// line (for example, `cert` and `iat-mode` for `obfs4`).
+    ///
+    /// This method does not need to handle retries or timeouts: the channel
+    /// manager does that.
+    async fn connect(&self, target: &PtTarget) -> std::io::Result<BoxedPtStream>;
+}
+
+/// A [`TransportImplHelper`] that uses an [`InProcessTransport`].
+pub(crate) struct InProcessHelper(pub(crate) Arc<dyn InProcessTransport>);
+
+#[async_trait]
+impl TransportImplHelper for InProcessHelper {
+    type Stream = BoxedPtStream;
+
+    async fn connect(
+        &self,
+        target: &OwnedChanTarget,
+    ) -> crate::Result<(OwnedChanTarget, BoxedPtStream)> {
+        let pt_target = match target.chan_method() {
+            ChannelMethod::Pluggable(target) => target,
+            other => {
+                return Err(crate::Error::UnusableTarget(bad_api_usage!(
+                    "Used in-process pluggable transport for non-PT method {:?}",
+                    other,
+                )))
+            }
+        };
+
+        let stream = self.0.connect(&pt_target).await.map_err(|e| {
+            let peer: Option<BridgeAddr> = pt_target.addr().clone().into();
+            crate::Error::Io {
+                action: "connecting via in-process pluggable transport",
+                peer: peer.map(Into::into),
+                source: Arc::new(e),
+            }
+        })?;
+        Ok((target.clone(), stream))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use futures_await_test::async_test;
+    use std::sync::Mutex;
+    use tor_linkspec::{PtTargetAddr, PtTransportName};
+
+    /// A transport that records the settings it was asked to use.
+    #[derive(Default)]
+    struct FakeTransport {
+        /// The settings from the most recent call to `connect`.
+        settings: Mutex<Vec<(String, String)>>,
+        /// If true, fail every connection.
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl InProcessTransport for FakeTransport {
+        async fn connect(&self, target: &PtTarget) -> std::io::Result<BoxedPtStream> {
+            *self.settings.lock().unwrap() = target
+                .settings()
+                .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                .collect();
+            if self.fail {
+                return Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused));
+            }
+            let (s1, _s2) = tor_rtmock::io::stream_pair();
+            Ok(Box::new(s1))
+        }
+    }
+
+    fn target(method: ChannelMethod) -> OwnedChanTarget {
+        OwnedChanTarget::builder()
+            .method(method)
+            .ed_identity([42; 32].into())
+            .rsa_identity([45; 20].into())
+            .build()
+            .unwrap()
+    }
+
+    fn pt_target() -> PtTarget {
+        let name: PtTransportName = "obfs4".parse().unwrap();
+        let mut pt = PtTarget::new(name, PtTargetAddr::None);
+        pt.push_setting("cert", "AAAA").unwrap();
+        pt.push_setting("iat-mode", "0").unwrap();
+        pt
+    }
+
+    #[async_test]
+    async fn connect() {
+        let transport = Arc::new(FakeTransport::default());
+        let helper = InProcessHelper(transport.clone());
+
+        let (got_target, _stream) = helper
+            .connect(&target(ChannelMethod::Pluggable(pt_target())))
+            .await
+            .unwrap();
+        assert!(matches!(
+            got_target.chan_method(),
+            ChannelMethod::Pluggable(_)
+        ));
+        assert_eq!(
+            *transport.settings.lock().unwrap(),
+            vec![
+                ("cert".to_owned(), "AAAA".to_owned()),
+                ("iat-mode".to_owned(), "0".to_owned()),
+            ]
+        );
+    }
+
+    #[async_test]
+    async fn connect_errors() {
+        let helper = InProcessHelper(Arc::new(FakeTransport {
+            fail: true,
+            ..Default::default()
+        }));
+        let e = helper
+            .connect(&target(ChannelMethod::Pluggable(pt_target())))
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(e, crate::Error::Io { .. }), "{e:?}");
+
+        let addr = "127.0.0.1:9001".parse().unwrap();
+        let e = helper
+            .connect(&target(ChannelMethod::Direct(vec![addr])))
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(e, crate::Error::UnusableTarget(_)), "{e:?}");
+    }
+}
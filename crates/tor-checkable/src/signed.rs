@@ -36,6 +36,31 @@ impl<T> SignatureGated<T> {
             signatures: self.signatures,
         }
     }
+
+    /// Check the signatures on every one of the objects in `gated`,
+    /// and return the results, in the same order.
+    ///
+    /// This gives the same results as calling
+    /// [`check_signature`](super::SelfSigned::check_signature)
+    /// on each object in turn, but verifies all of their Ed25519
+    /// signatures in a single batch when possible.
+    /// That is much faster when we have many objects to check.
+    pub fn check_all_signatures(gated: Vec<Self>) -> Vec<Result<T, signature::Error>> {
+        let groups: Vec<_> = gated.iter().map(|g| &g.signatures[..]).collect();
+        let valid = pk::validate_sig_groups(&groups[..]);
+
+        gated
+            .into_iter()
+            .zip(valid)
+            .map(|(g, valid)| {
+                if valid {
+                    Ok(g.obj)
+                } else {
+                    Err(signature::Error::new())
+                }
+            })
+            .collect()
+    }
 }
 
 impl<T> super::SelfSigned<T> for SignatureGated<T> {
@@ -120,4 +145,19 @@ mod test {
         let still_bad = bad.dangerously_map(|s| &s[..11]);
         assert!(still_bad.check_signature().is_err());
     }
+
+    #[test]
+    fn test_check_all() {
+        let gated: Vec<SignatureGated<u32>> = vec![
+            SignatureGated::new(1, Vec::new()),
+            SignatureGated::new(2, vec![Box::new(GoodSig), Box::new(BadSig)]),
+            SignatureGated::new(3, vec![Box::new(GoodSig), Box::new(GoodSig)]),
+            SignatureGated::new(4, vec![Box::new(BadSig)]),
+        ];
+        let checked = SignatureGated::check_all_signatures(gated);
+        let checked: Vec<_> = checked.into_iter().map(Result::ok).collect();
+        assert_eq!(checked, vec![Some(1), None, Some(3), None]);
+
+        assert!(SignatureGated::<u32>::check_all_signatures(Vec::new()).is_empty());
+    }
 }
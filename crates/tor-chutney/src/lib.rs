@@ -0,0 +1,286 @@
+#![cfg_attr(docsrs, feature(doc_auto_cfg, doc_cfg))]
+#![doc = include_str!("../README.md")]
+// @@ begin lint list maintained by maint/add_warning @@
+#![allow(renamed_and_removed_lints)] // @@REMOVE_WHEN(ci_arti_stable)
+#![allow(unknown_lints)] // @@REMOVE_WHEN(ci_arti_nightly)
+#![warn(missing_docs)]
+#![warn(noop_method_call)]
+#![warn(unreachable_pub)]
+#![warn(clippy::all)]
+#![deny(clippy::await_holding_lock)]
+#![deny(clippy::cargo_common_metadata)]
+#![deny(clippy::cast_lossless)]
+#![deny(clippy::checked_conversions)]
+#![warn(clippy::cognitive_complexity)]
+#![deny(clippy::debug_assert_with_mut_call)]
+#![deny(clippy::exhaustive_enums)]
+#![deny(clippy::exhaustive_structs)]
+#![deny(clippy::expl_impl_clone_on_copy)]
+#![deny(clippy::fallible_impl_from)]
+#![deny(clippy::implicit_clone)]
+#![deny(clippy::large_stack_arrays)]
+#![warn(clippy::manual_ok_or)]
+#![deny(clippy::missing_docs_in_private_items)]
+#![warn(clippy::needless_borrow)]
+#![warn(clippy::needless_pass_by_value)]
+#![warn(clippy::option_option)]
+#![deny(clippy::print_stderr)]
+#![deny(clippy::print_stdout)]
+#![warn(clippy::rc_buffer)]
+#![deny(clippy::ref_option_ref)]
+#![warn(clippy::semicolon_if_nothing_returned)]
+#![warn(clippy::trait_duplication_in_bounds)]
+#![deny(clippy::unchecked_duration_subtraction)]
+#![deny(clippy::unnecessary_wraps)]
+#![warn(clippy::unseparated_literal_suffix)]
+#![deny(clippy::unwrap_used)]
+#![allow(clippy::let_unit_value)] // This can reasonably be done for explicitness
+#![allow(clippy::uninlined_format_args)]
+#![allow(clippy::significant_drop_in_scrutinee)] // arti/-/merge_requests/588/#note_2812945
+#![allow(clippy::result_large_err)] // temporary workaround for arti#587
+#![allow(clippy::needless_raw_string_hashes)] // complained-about code is fine, often best
+//! <!-- @@ end lint list maintained by maint/add_warning @@ -->
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context as _};
+use tracing::{info, warn};
+
+use arti_client::{TorClient, TorClientConfig};
+use tor_config::{ConfigurationSource, ConfigurationSources};
+use tor_dirmgr::{NetworkConfig, NetworkConfigBuilder};
+use tor_netdir::NetDirProvider;
+use tor_rtcompat::Runtime;
+
+/// The environment variable that tells us where chutney is.
+const CHUTNEY_PATH_VAR: &str = "CHUTNEY_PATH";
+
+/// How long do we give a chutney network to bootstrap, by default?
+const DEFAULT_BOOTSTRAP_TIMEOUT: Duration = Duration::from_secs(180);
+
+/// A running chutney network.
+///
+/// The network is stopped when this object is dropped.
+/// (Use [`stop`](ChutneyNetwork::stop) if you want to know whether
+/// that succeeded.)
+#[derive(Debug)]
+pub struct ChutneyNetwork {
+    /// The location of the chutney checkout.
+    chutney_dir: PathBuf,
+    /// The network description file that we launched.
+    network: PathBuf,
+    /// The directory in which chutney keeps the nodes' data.
+    nodes_dir: PathBuf,
+    /// True if we have already stopped this network.
+    stopped: bool,
+}
+
+impl ChutneyNetwork {
+    /// Return true if chutney is available (that is, if `CHUTNEY_PATH` is set).
+    ///
+    /// Tests that need chutney should do nothing when this returns false.
+    pub fn available() -> bool {
+        std::env::var_os(CHUTNEY_PATH_VAR).is_some()
+    }
+
+    /// Launch the chutney network called `name` (for example, `"basic"`),
+    /// using the chutney at `CHUTNEY_PATH`, and wait for it to bootstrap.
+    pub fn launch(name: &str) -> anyhow::Result<Self> {
+        let chutney_dir = std::env::var_os(CHUTNEY_PATH_VAR)
+            .ok_or_else(|| anyhow!("{} is not set", CHUTNEY_PATH_VAR))?;
+        Self::launch_from(chutney_dir, name, DEFAULT_BOOTSTRAP_TIMEOUT)
+    }
+
+    /// Launch the chutney network called `name` using the chutney checkout at
+    /// `chutney_dir`, and wait up to `timeout` for it to bootstrap.
+    pub fn launch_from(
+        chutney_dir: impl Into<PathBuf>,
+        name: &str,
+        timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        let chutney_dir = chutney_dir.into();
+        let network = chutney_dir.join("networks").join(name);
+        if !network.is_file() {
+            return Err(anyhow!(
+                "chutney network description {} not found",
+                network.display()
+            ));
+        }
+        let nodes_dir = match std::env::var_os("CHUTNEY_DATA_DIR") {
+            Some(d) => PathBuf::from(d).join("nodes"),
+            None => chutney_dir.join("net").join("nodes"),
+        };
+
+        // From here on, if anything goes wrong, dropping `net` will try to
+        // stop whatever we started.
+        let mut net = ChutneyNetwork {
+            chutney_dir,
+            network,
+            nodes_dir,
+            stopped: true,
+        };
+        info!("Launching chutney network {}", name);
+        net.run("configure", &[])?;
+        net.stopped = false;
+        net.run("start", &[])?;
+        net.run(
+            "wait_for_bootstrap",
+            &[("CHUTNEY_START_TIME", &timeout.as_secs().to_string())],
+        )?;
+        Ok(net)
+    }
+
+    /// Run the chutney command `cmd` on our network, with the extra
+    /// environment variables in `env`.
+    fn run(&self, cmd: &str, env: &[(&str, &str)]) -> anyhow::Result<()> {
+        let status = Command::new(self.chutney_dir.join("chutney"))
+            .arg(cmd)
+            .arg(&self.network)
+            .envs(env.iter().map(|(k, v)| (OsStr::new(k), OsStr::new(v))))
+            .status()
+            .with_context(|| format!("couldn't run chutney {}", cmd))?;
+        if !status.success() {
+            return Err(anyhow!("chutney {} failed: {}", cmd, status));
+        }
+        Ok(())
+    }
+
+    /// Return the directory in which chutney keeps the data for this
+    /// network's nodes.
+    pub fn nodes_dir(&self) -> &Path {
+        &self.nodes_dir
+    }
+
+    /// Return the location of the Arti configuration file that chutney
+    /// generated for this network.
+    pub fn arti_config_path(&self) -> PathBuf {
+        self.nodes_dir.join("arti.toml")
+    }
+
+    /// Return the configuration for this network's authorities and fallback
+    /// directories.
+    pub fn network_config(&self) -> anyhow::Result<NetworkConfig> {
+        let path = self.arti_config_path();
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("couldn't read {}", path.display()))?;
+        let mut config: toml::Table =
+            toml::from_str(&text).with_context(|| format!("couldn't parse {}", path.display()))?;
+        let network = config
+            .remove("tor_network")
+            .ok_or_else(|| anyhow!("no [tor_network] section in {}", path.display()))?;
+        let builder: NetworkConfigBuilder = network
+            .try_into()
+            .context("bad [tor_network] section in chutney's arti.toml")?;
+        Ok(builder.build()?)
+    }
+
+    /// Return an Arti client configuration for connecting to this network.
+    pub fn client_config(&self) -> anyhow::Result<TorClientConfig> {
+        let mut sources = ConfigurationSources::new_empty();
+        sources.push_source(
+            ConfigurationSource::from_path(self.arti_config_path()),
+            tor_config::sources::MustRead::MustRead,
+        );
+        // The file will contain settings for the arti binary too; we don't
+        // care about those.
+        Ok(tor_config::resolve_ignore_warnings(sources.load()?)?)
+    }
+
+    /// Create an Arti client connected to this network, and wait for it to
+    /// bootstrap.
+    pub async fn bootstrapped_client<R: Runtime>(
+        &self,
+        runtime: R,
+    ) -> anyhow::Result<TorClient<R>> {
+        let client = TorClient::with_runtime(runtime)
+            .config(self.client_config()?)
+            .create_bootstrapped()
+            .await?;
+        Ok(client)
+    }
+
+    /// Stop this network, and report whether we succeeded.
+    pub fn stop(mut self) -> anyhow::Result<()> {
+        self.stopped = true;
+        self.run("stop", &[])
+    }
+}
+
+impl Drop for ChutneyNetwork {
+    fn drop(&mut self) {
+        if !self.stopped {
+            if let Err(e) = self.run("stop", &[]) {
+                warn!("Couldn't stop chutney network: {:#}", e);
+            }
+        }
+    }
+}
+
+/// Return the [`NetDirProvider`] for a client returned by
+/// [`ChutneyNetwork::bootstrapped_client`].
+pub fn netdir_provider<R: Runtime>(client: &TorClient<R>) -> Arc<dyn NetDirProvider> {
+    Arc::clone(client.dirmgr()).upcast_arc()
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    #[test]
+    fn missing_network() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = ChutneyNetwork::launch_from(dir.path(), "nonesuch", Duration::from_secs(1))
+            .unwrap_err();
+        assert!(err.to_string().contains("not found"));
+    }
+
+    #[test]
+    fn network_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let nodes_dir = dir.path().join("nodes");
+        std::fs::create_dir(&nodes_dir).unwrap();
+        std::fs::write(
+            nodes_dir.join("arti.toml"),
+            r#"
+[proxy]
+socks_listen = 9150
+
+[tor_network]
+authorities = [
+    { name = "test000a", v3ident = "0000000000000000000000000000000000000000" },
+]
+fallback_caches = [
+    { rsa_identity = "0101010101010101010101010101010101010101", ed_identity = "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE", orports = [ "127.0.0.1:5000" ] },
+]
+"#,
+        )
+        .unwrap();
+
+        // Construct the network by hand, so we don't need chutney.
+        let net = ChutneyNetwork {
+            chutney_dir: dir.path().into(),
+            network: dir.path().join("networks/basic"),
+            nodes_dir,
+            stopped: true,
+        };
+        let config = net.network_config().unwrap();
+        assert_eq!(config.fallback_caches().len(), 1);
+    }
+}
@@ -1,21 +1,26 @@
 //! Facilities to build circuits directly, instead of via a circuit manager.
 
 use crate::path::{OwnedPath, TorPath};
+use crate::telemetry::CircBuildTelemetry;
 use crate::timeouts::{self, Action};
+use crate::CircPurpose;
 use crate::{Error, Result};
 use async_trait::async_trait;
 use futures::task::SpawnExt;
 use futures::Future;
 use std::sync::{
     atomic::{AtomicU32, Ordering},
-    Arc,
+    Arc, Mutex,
 };
 use std::time::{Duration, Instant};
 use tor_async_utils::oneshot;
 use tor_chanmgr::{ChanMgr, ChanProvenance, ChannelUsage};
 use tor_error::warn_report;
+use tor_events::events::{TorEvent, TorEventKind};
 use tor_guardmgr::GuardStatus;
-use tor_linkspec::{ChanTarget, IntoOwnedChanTarget, OwnedChanTarget, OwnedCircTarget};
+use tor_linkspec::{
+    ChanTarget, HasRelayIds as _, IntoOwnedChanTarget, OwnedChanTarget, OwnedCircTarget,
+};
 use tor_netdir::params::NetParameters;
 use tor_proto::circuit::{CircParameters, ClientCirc, PendingClientCirc};
 use tor_rtcompat::{Runtime, SleepProviderExt};
@@ -227,6 +232,8 @@ struct Builder<R: Runtime, C: Buildable + Sync + Send + 'static> {
     chanmgr: Arc<ChanMgr<R>>,
     /// An estimator to determine the correct timeouts for circuit building.
     timeouts: timeouts::Estimator,
+    /// Timing information about the circuits we have built.
+    telemetry: Mutex<CircBuildTelemetry>,
     /// We don't actually hold any clientcircs, so we need to put this
     /// type here so the compiler won't freak out.
     _phantom: std::marker::PhantomData<C>,
@@ -239,6 +246,7 @@ impl<R: Runtime, C: Buildable + Sync + Send + 'static> Builder<R, C> {
             runtime,
             chanmgr,
             timeouts,
+            telemetry: Mutex::new(CircBuildTelemetry::default()),
             _phantom: std::marker::PhantomData,
         }
     }
@@ -273,8 +281,12 @@ impl<R: Runtime, C: Buildable + Sync + Send + 'static> Builder<R, C> {
                     usage,
                 )
                 .await?;
-                self.timeouts
-                    .note_hop_completed(0, self.runtime.now() - start_time, true);
+                let elapsed = self.runtime.now() - start_time;
+                self.timeouts.note_hop_completed(0, elapsed, true);
+                self.note_telemetry(|t| {
+                    t.note_hop(0, elapsed);
+                    t.note_circuit(elapsed);
+                });
                 n_hops_built.fetch_add(1, Ordering::SeqCst);
                 Ok(circ)
             }
@@ -292,8 +304,9 @@ impl<R: Runtime, C: Buildable + Sync + Send + 'static> Builder<R, C> {
                     usage,
                 )
                 .await?;
-                self.timeouts
-                    .note_hop_completed(0, self.runtime.now() - start_time, n_hops == 0);
+                let mut elapsed = self.runtime.now() - start_time;
+                self.timeouts.note_hop_completed(0, elapsed, n_hops == 0);
+                self.note_telemetry(|t| t.note_hop(0, elapsed));
                 // If we fail after this point, we can't tell whether it's
                 // the fault of the guard or some later relay.
                 guard_status.pending(GuardStatus::Indeterminate);
@@ -302,13 +315,16 @@ impl<R: Runtime, C: Buildable + Sync + Send + 'static> Builder<R, C> {
                 for relay in p[1..].iter() {
                     circ.extend(&self.runtime, relay, &params).await?;
                     n_hops_built.fetch_add(1, Ordering::SeqCst);
-                    self.timeouts.note_hop_completed(
-                        hop_num,
-                        self.runtime.now() - start_time,
-                        hop_num == (n_hops - 1),
-                    );
+                    let prev_elapsed = elapsed;
+                    elapsed = self.runtime.now() - start_time;
+                    self.timeouts
+                        .note_hop_completed(hop_num, elapsed, hop_num == (n_hops - 1));
+                    self.note_telemetry(|t| {
+                        t.note_hop(hop_num.into(), elapsed.saturating_sub(prev_elapsed));
+                    });
                     hop_num += 1;
                 }
+                self.note_telemetry(|t| t.note_circuit(elapsed));
                 Ok(circ)
             }
         }
@@ -355,6 +371,16 @@ impl<R: Runtime, C: Buildable + Sync + Send + 'static> Builder<R, C> {
         }
     }
 
+    /// Update our [`CircBuildTelemetry`] with `f`.
+    fn note_telemetry(&self, f: impl FnOnce(&mut CircBuildTelemetry)) {
+        f(&mut self.telemetry.lock().expect("poisoned lock"));
+    }
+
+    /// Return a copy of the timing information about the circuits we have built.
+    pub(crate) fn telemetry(&self) -> CircBuildTelemetry {
+        self.telemetry.lock().expect("poisoned lock").clone()
+    }
+
     /// Return a reference to this Builder runtime.
     pub(crate) fn runtime(&self) -> &R {
         &self.runtime
@@ -436,6 +462,20 @@ impl<R: Runtime> CircuitBuilder<R> {
         Ok(true)
     }
 
+    /// Like [`save_state`](Self::save_state), but store all of our state as
+    /// snapshots, leaving nothing in the journals.
+    ///
+    /// We do this before exiting, so that versions of Arti which don't
+    /// read the journals still see our latest state.
+    pub(crate) fn compact_state(&self) -> Result<bool> {
+        if !self.storage.can_store() {
+            return Ok(false);
+        }
+        self.builder.timeouts.compact_state(&self.storage)?;
+        self.guardmgr.compact_persistent_state()?;
+        Ok(true)
+    }
+
     /// Replace our state with a new owning state, assuming we have
     /// storage permission.
     pub(crate) fn upgrade_to_owned_state(&self) -> Result<()> {
@@ -471,9 +511,14 @@ impl<R: Runtime> CircuitBuilder<R> {
         guard_status: Arc<GuardStatusHandle>,
         usage: ChannelUsage,
     ) -> Result<Arc<ClientCirc>> {
-        self.builder
+        let circ = self
+            .builder
             .build_owned(path, params, guard_status, usage)
-            .await
+            .await?;
+        if tor_events::event_has_subscribers(TorEventKind::CircuitOpened) {
+            tor_events::broadcast(circuit_opened_event(&circ));
+        }
+        Ok(circ)
     }
 
     /// Try to construct a new circuit from a given path, using appropriate
@@ -518,6 +563,18 @@ impl<R: Runtime> CircuitBuilder<R> {
     pub(crate) fn estimator(&self) -> &timeouts::Estimator {
         self.builder.estimator()
     }
+
+    /// Return timing information about the circuits that this builder has built.
+    pub fn build_telemetry(&self) -> CircBuildTelemetry {
+        self.builder.telemetry()
+    }
+
+    /// Record that we finished trying to build a circuit for `purpose`,
+    /// and whether we `succeeded`.
+    pub(crate) fn note_build_outcome(&self, purpose: Option<CircPurpose>, succeeded: bool) {
+        self.builder
+            .note_telemetry(|t| t.note_outcome(purpose, succeeded));
+    }
 }
 
 /// Extract a [`CircParameters`] from the [`NetParameters`] from a consensus.
@@ -530,6 +587,26 @@ pub fn circparameters_from_netparameters(inp: &NetParameters) -> CircParameters
     p
 }
 
+/// Return a [`TorEvent::CircuitOpened`] describing `circ`.
+///
+/// We describe each hop by its identities only, since its addresses would be
+/// sensitive.
+fn circuit_opened_event(circ: &ClientCirc) -> TorEvent {
+    let path = circ
+        .path_ref()
+        .hops()
+        .iter()
+        .map(|hop| match hop.as_chan_target() {
+            Some(target) => target.display_relay_ids().to_string(),
+            None => "<virtual hop>".to_string(),
+        })
+        .collect();
+    TorEvent::CircuitOpened {
+        circuit: circ.unique_id().to_string(),
+        path,
+    }
+}
+
 /// Helper function: spawn a future as a background task, and run it with
 /// two separate timeouts.
 ///
@@ -597,7 +674,6 @@ mod test {
     use super::*;
     use crate::timeouts::TimeoutEstimator;
     use futures::FutureExt;
-    use std::sync::Mutex;
     use tor_chanmgr::ChannelConfig;
     use tor_chanmgr::ChannelUsage as CU;
     use tor_linkspec::{HasRelayIds, RelayIdType, RelayIds};
@@ -925,7 +1001,11 @@ mod test {
         path: OwnedPath,
         advance_on_timeout: Option<(Duration, Duration)>,
         usage: ChannelUsage,
-    ) -> (Result<FakeCirc>, Vec<(bool, u8, Duration)>) {
+    ) -> (
+        Result<FakeCirc>,
+        Vec<(bool, u8, Duration)>,
+        CircBuildTelemetry,
+    ) {
         let chanmgr = Arc::new(ChanMgr::new(
             rt.clone(),
             &ChannelConfig::default(),
@@ -938,16 +1018,16 @@ mod test {
             None => TimeoutRecorder::new(rt.clone()),
         };
         let timeouts = Arc::new(Mutex::new(timeouts));
-        let builder: Builder<_, Mutex<FakeCirc>> = Builder::new(
+        let builder: Arc<Builder<_, Mutex<FakeCirc>>> = Arc::new(Builder::new(
             rt.clone(),
             chanmgr,
             timeouts::Estimator::new(Arc::clone(&timeouts)),
-        );
+        ));
 
         rt.block_advance("manually controlling advances");
         rt.allow_one_advance(advance_initial);
+        let arcbuilder = Arc::clone(&builder);
         let outcome = rt.spawn_join("build-owned", async move {
-            let arcbuilder = Arc::new(builder);
             let params = CircParameters::default();
             arcbuilder.build_owned(path, &params, gs(), usage).await
         });
@@ -964,7 +1044,7 @@ mod test {
         let circ = outcome.map(|m| Ok(m?.lock().unwrap().clone())).await;
         let timeouts = timeouts.lock().unwrap().hist.clone();
 
-        (circ, timeouts)
+        (circ, timeouts, builder.telemetry())
     }
 
     #[test]
@@ -973,7 +1053,7 @@ mod test {
             let id_100ms = key_from_timeouts(Duration::from_millis(100), Duration::from_millis(0));
             let path = OwnedPath::ChannelOnly(chan_t(id_100ms));
 
-            let (outcome, timeouts) =
+            let (outcome, timeouts, telemetry) =
                 run_builder_test(rt, Duration::from_millis(100), path, None, CU::UserTraffic).await;
             let circ = outcome.unwrap();
             assert!(circ.onehop);
@@ -984,6 +1064,13 @@ mod test {
             assert!(timeouts[0].0); // success
             assert_eq!(timeouts[0].1, 0); // one-hop
             assert_eq!(timeouts[0].2, Duration::from_millis(100));
+
+            assert_eq!(telemetry.n_hops(), 1);
+            assert_eq!(
+                telemetry.hop(0).unwrap().mean(),
+                Some(Duration::from_millis(100))
+            );
+            assert_eq!(telemetry.total().mean(), Some(Duration::from_millis(100)));
         });
     }
 
@@ -998,7 +1085,7 @@ mod test {
             let path =
                 OwnedPath::Normal(vec![circ_t(id_100ms), circ_t(id_200ms), circ_t(id_300ms)]);
 
-            let (outcome, timeouts) =
+            let (outcome, timeouts, telemetry) =
                 run_builder_test(rt, Duration::from_millis(100), path, None, CU::UserTraffic).await;
             let circ = outcome.unwrap();
             assert!(!circ.onehop);
@@ -1011,6 +1098,15 @@ mod test {
             assert!(timeouts[0].0); // success
             assert_eq!(timeouts[0].1, 2); // three-hop
             assert_eq!(timeouts[0].2, Duration::from_millis(600));
+
+            assert_eq!(telemetry.n_hops(), 3);
+            for (hop, ms) in [(0, 100), (1, 200), (2, 300)] {
+                let hist = telemetry.hop(hop).unwrap();
+                assert_eq!(hist.count(), 1);
+                assert_eq!(hist.mean(), Some(Duration::from_millis(ms)));
+            }
+            assert_eq!(telemetry.total().count(), 1);
+            assert_eq!(telemetry.total().mean(), Some(Duration::from_millis(600)));
         });
     }
 
@@ -1025,7 +1121,7 @@ mod test {
 
             let path = OwnedPath::Normal(vec![circ_t(id_100ms), circ_t(id_200ms), circ_t(id_hour)]);
 
-            let (outcome, timeouts) =
+            let (outcome, timeouts, telemetry) =
                 run_builder_test(rt, Duration::from_millis(100), path, None, CU::UserTraffic).await;
             assert!(matches!(outcome, Err(Error::CircTimeout(_))));
 
@@ -1035,6 +1131,9 @@ mod test {
             // BUG: Sometimes this is 1 and sometimes this is 2.
             // assert_eq!(timeouts[0].1, 2); // at third hop.
             assert_eq!(timeouts[0].2, Duration::from_millis(3000));
+
+            // We never finished the circuit, so it isn't counted.
+            assert_eq!(telemetry.total().count(), 0);
         });
     }
 
@@ -1051,7 +1150,7 @@ mod test {
 
             let path = OwnedPath::Normal(vec![circ_t(id_100ms), circ_t(id_200ms), circ_t(id_3sec)]);
 
-            let (outcome, timeouts) = run_builder_test(
+            let (outcome, timeouts, _) = run_builder_test(
                 rt.clone(),
                 Duration::from_millis(100),
                 path,
@@ -5,9 +5,12 @@
 //! Most types in this module are re-exported by `arti-client`.
 
 use tor_basic_utils::define_accessor_trait;
+use tor_chanmgr::ChannelConfig;
 use tor_config::impl_standard_builder;
 use tor_config::{define_list_builder_accessors, define_list_builder_helper, ConfigBuildError};
 use tor_guardmgr::{GuardFilter, GuardMgrConfig};
+#[cfg(feature = "hs-client")]
+use tor_llcrypto::pk::rsa::RsaIdentity;
 
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
@@ -15,6 +18,7 @@ use tor_netdoc::types::policy::AddrPortPattern;
 use tor_relay_selection::RelaySelectionConfig;
 
 use std::collections::HashSet;
+use std::num::NonZeroU32;
 use std::time::Duration;
 
 /// Rules for building paths over the network.
@@ -53,6 +57,18 @@ pub struct PathConfig {
     #[builder(sub_builder, setter(custom))]
     #[builder_field_attr(serde(default))]
     pub(crate) reachable_addrs: ReachableAddrs,
+
+    /// If set, always use the relay with this RSA identity as the rendezvous point
+    /// when connecting to an onion service, rather than choosing one at random.
+    ///
+    /// This is intended for testing, and for reproducing failures against a
+    /// particular relay.  Using it in normal operation makes your connections
+    /// easier to fingerprint, and makes them fail if that relay is unavailable.
+    //
+    // This parameter is honoured by tor-hsclient, not here.
+    #[cfg(feature = "hs-client")]
+    #[builder(default)]
+    pub(crate) hs_rendezvous_point: Option<RsaIdentity>,
 }
 impl_standard_builder! { PathConfig }
 
@@ -135,13 +151,30 @@ impl PathConfig {
             && self.reachable_addrs == other.reachable_addrs
     }
 
-    /// Return a new [`GuardFilter`] reflecting the rules in this configuration.
-    pub(crate) fn build_guard_filter(&self) -> GuardFilter {
+    /// Return a new [`GuardFilter`] reflecting the rules in this configuration,
+    /// and the address families permitted by `channel`.
+    pub(crate) fn build_guard_filter(&self, channel: &ChannelConfig) -> GuardFilter {
         let mut filt = GuardFilter::default();
         filt.push_reachable_addresses(self.reachable_addrs.clone());
+        if !(channel.use_ipv4() && channel.use_ipv6()) {
+            let family = if channel.use_ipv4() {
+                "0.0.0.0/0:*"
+            } else {
+                "[::]/0:*"
+            };
+            let family: AddrPortPattern = family.parse().expect("Could not parse address pattern");
+            filt.push_reachable_addresses([family]);
+        }
         filt
     }
 
+    /// Return the relay that onion service clients should always use as their
+    /// rendezvous point, if there is one.
+    #[cfg(feature = "hs-client")]
+    pub fn hs_rendezvous_point(&self) -> Option<RsaIdentity> {
+        self.hs_rendezvous_point
+    }
+
     /// Return a new [`RelaySelectionConfig`] reflecting the rules in this
     /// configuration.
     pub(crate) fn relay_selection_config(&self) -> RelaySelectionConfig<'_> {
@@ -246,6 +279,36 @@ pub struct CircuitTiming {
     #[getter(skip)]
     pub(crate) request_loyalty: Duration,
 
+    /// If true, we periodically build a throwaway circuit and make a small
+    /// request over it, to find out whether we can still reach the Tor network.
+    ///
+    /// The results are reported as a
+    /// [`NetworkReachability`](crate::NetworkReachability).
+    #[builder(default)]
+    #[getter(skip)]
+    pub(crate) reachability_self_test: bool,
+
+    /// How often to run the reachability self-test, if it is enabled.
+    #[builder(default = "default_reachability_self_test_interval()")]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    #[getter(skip)]
+    pub(crate) reachability_self_test_interval: Duration,
+
+    /// How many streams we will open on one circuit before we stop giving it
+    /// out for new requests, and use (or build) another circuit instead.
+    ///
+    /// This keeps a single busy application from opening so many streams on
+    /// one circuit that the exit starts refusing or rate-limiting them.
+    #[builder(default = "default_max_client_streams_per_circuit()")]
+    #[getter(skip)]
+    pub(crate) max_client_streams_per_circuit: NonZeroU32,
+
+    /// How many streams on one circuit may be waiting for a reply to their
+    /// BEGIN message before we stop giving it out for new requests.
+    #[builder(default = "default_max_pending_begins_per_circuit()")]
+    #[getter(skip)]
+    pub(crate) max_pending_begins_per_circuit: NonZeroU32,
+
     /// When an HS connection is attempted, we stop trying more hsdirs after this many attempts
     //
     // This parameter is honoured by tor-hsclient, not here.
@@ -264,6 +327,69 @@ pub struct CircuitTiming {
     #[builder(default = "default_hs_max_attempts()")]
     #[getter(as_copy)]
     pub(crate) hs_intro_rend_attempts: u32,
+
+    /// After every hsdir we asked tells us that it doesn't have an onion
+    /// service's descriptor, how long we remember that failure.
+    ///
+    /// Failures to reach the hsdirs (for example, timeouts) are not remembered.
+    ///
+    /// Until then, further requests to connect to that service (in the same
+    /// time period) fail straight away, rather than asking the hsdirs again.
+    /// Set this to zero to always ask the hsdirs.
+    //
+    // This parameter is honoured by tor-hsclient, not here.
+    #[cfg(feature = "hs-client")]
+    #[builder(default = "default_hs_desc_failure_cache_time()")]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    #[getter(as_copy)]
+    pub(crate) hs_desc_failure_cache_time: Duration,
+
+    /// How many streams we will open on one rendezvous circuit to an onion service
+    /// before we build another circuit to the same service.
+    ///
+    /// Streams only share a rendezvous circuit if their isolation is compatible.
+    //
+    // This parameter is honoured by tor-hsclient, not here.
+    #[cfg(feature = "hs-client")]
+    #[builder(default = "default_hs_max_streams_per_circuit()")]
+    #[getter(as_copy)]
+    pub(crate) hs_max_streams_per_circuit: NonZeroU32,
+
+    /// How many rendezvous circuits we will keep open to one onion service,
+    /// for requests with compatible isolation.
+    ///
+    /// Once every one of these circuits has `hs_max_streams_per_circuit` streams,
+    /// new streams are put on whichever circuit has the fewest.
+    ///
+    /// If building an additional circuit fails, we wait a while before trying
+    /// again, waiting longer after each failure.
+    //
+    // This parameter is honoured by tor-hsclient, not here.
+    #[cfg(feature = "hs-client")]
+    #[builder(default = "default_hs_max_circuits_per_service()")]
+    #[getter(as_copy)]
+    pub(crate) hs_max_circuits_per_service: NonZeroU32,
+
+    /// If true, never share a rendezvous circuit between separate connection
+    /// requests, even to the same onion service with compatible isolation.
+    ///
+    /// Each request then gets its own rendezvous circuit,
+    /// and its own descriptor download and introduction attempts.
+    /// This is more expensive, but makes it harder for a service to link
+    /// connections that would otherwise have been allowed to share a circuit.
+    ///
+    /// This is off by default, since it is only for users with stricter
+    /// unlinkability requirements than stream isolation expresses:
+    /// requests that are isolated from each other never share a circuit
+    /// anyway, and requests that are not isolated have said that they don't
+    /// mind being linked.  Turning it on makes every request to an onion
+    /// service pay for a full rendezvous, which takes several round trips.
+    //
+    // This parameter is honoured by tor-hsclient, not here.
+    #[cfg(feature = "hs-client")]
+    #[builder(default)]
+    #[getter(as_copy)]
+    pub(crate) hs_strict_isolation: bool,
 }
 impl_standard_builder! { CircuitTiming }
 
@@ -329,6 +455,39 @@ fn default_hs_max_attempts() -> u32 {
     6
 }
 
+/// Return the default value for `hs_max_streams_per_circuit`.
+#[cfg(feature = "hs-client")]
+fn default_hs_max_streams_per_circuit() -> NonZeroU32 {
+    NonZeroU32::new(64).expect("Impossibly got 0 value")
+}
+
+/// Return the default value for `hs_desc_failure_cache_time`.
+#[cfg(feature = "hs-client")]
+fn default_hs_desc_failure_cache_time() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// Return the default value for `hs_max_circuits_per_service`.
+#[cfg(feature = "hs-client")]
+fn default_hs_max_circuits_per_service() -> NonZeroU32 {
+    NonZeroU32::new(4).expect("Impossibly got 0 value")
+}
+
+/// Return the default value for `max_client_streams_per_circuit`.
+fn default_max_client_streams_per_circuit() -> NonZeroU32 {
+    NonZeroU32::new(256).expect("Impossibly got 0 value")
+}
+
+/// Return the default value for `max_pending_begins_per_circuit`.
+fn default_max_pending_begins_per_circuit() -> NonZeroU32 {
+    NonZeroU32::new(32).expect("Impossibly got 0 value")
+}
+
+/// Return the default interval between reachability self-tests.
+fn default_reachability_self_test_interval() -> Duration {
+    Duration::from_secs(5 * 60)
+}
+
 /// Return the default request loyalty timeout.
 fn default_request_loyalty() -> Duration {
     Duration::from_millis(50)
@@ -366,6 +525,7 @@ define_accessor_trait! {
         path_rules: PathConfig,
         circuit_timing: CircuitTiming,
         preemptive_circuits: PreemptiveCircuitConfig,
+        channel: ChannelConfig,
         +
         // Note: ideally this would be defined in the same way as `path_rules`,
         // `circuit_timing`, etc., but define_accessor_trait unconditionally adds
@@ -396,6 +556,7 @@ pub(crate) mod test_config {
         pub path_rules: PathConfig,
         pub circuit_timing: CircuitTiming,
         pub preemptive_circuits: PreemptiveCircuitConfig,
+        pub channel: ChannelConfig,
         pub guardmgr: tor_guardmgr::TestConfig,
         #[cfg(all(feature = "vanguards", feature = "hs-common"))]
         pub vanguard_config: VanguardConfig,
@@ -425,6 +586,9 @@ pub(crate) mod test_config {
         fn preemptive_circuits(&self) -> &PreemptiveCircuitConfig {
             &self.preemptive_circuits
         }
+        fn channel(&self) -> &ChannelConfig {
+            &self.channel
+        }
         #[cfg(all(feature = "vanguards", feature = "hs-common"))]
         fn vanguard_config(&self) -> &tor_guardmgr::VanguardConfig {
             &self.vanguard_config
@@ -471,4 +635,36 @@ mod test {
         assert!(!pc1.at_least_as_permissive_as(&pc3));
         assert!(!pc3.at_least_as_permissive_as(&pc2));
     }
+
+    #[test]
+    fn guard_filter_families() {
+        let pc = PathConfig::default();
+        let all: AddrPortPattern = "*:*".parse().unwrap();
+
+        let both = ChannelConfig::default();
+        let mut expected = GuardFilter::default();
+        expected.push_reachable_addresses([all.clone()]);
+        assert_eq!(pc.build_guard_filter(&both), expected);
+
+        let only6 = ChannelConfig::builder().use_ipv4(false).build().unwrap();
+        let mut expected = GuardFilter::default();
+        expected.push_reachable_addresses([all]);
+        expected.push_reachable_addresses(["[::]/0:*".parse().unwrap()]);
+        assert_eq!(pc.build_guard_filter(&only6), expected);
+    }
+
+    #[cfg(feature = "hs-client")]
+    #[test]
+    fn hs_rendezvous_point() {
+        assert_eq!(PathConfig::default().hs_rendezvous_point(), None);
+
+        let id: RsaIdentity = [0x42; 20].into();
+        let path = PathConfig::builder()
+            .hs_rendezvous_point(Some(id))
+            .build()
+            .unwrap();
+        assert_eq!(path.hs_rendezvous_point(), Some(id));
+        // Changing it doesn't make us discard any circuits.
+        assert!(path.at_least_as_permissive_as(&PathConfig::default()));
+    }
 }
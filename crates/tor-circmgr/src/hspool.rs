@@ -10,7 +10,7 @@ use std::{
     time::Duration,
 };
 
-use crate::{timeouts, CircMgr, Error, Result};
+use crate::{timeouts, CircMgr, CircPathDescription, CircPurpose, Error, Result};
 use futures::{task::SpawnExt, StreamExt, TryFutureExt};
 use once_cell::sync::OnceCell;
 use tor_error::{bad_api_usage, internal};
@@ -19,6 +19,7 @@ use tor_guardmgr::VanguardMode;
 use tor_linkspec::{
     CircTarget, HasRelayIds as _, IntoOwnedChanTarget, OwnedChanTarget, OwnedCircTarget,
 };
+use tor_llcrypto::pk::rsa::RsaIdentity;
 use tor_netdir::{NetDir, NetDirProvider, Relay};
 use tor_proto::circuit::{self, CircParameters, ClientCirc};
 use tor_relay_selection::{LowLevelRelayPredicate, RelayExclusion};
@@ -61,6 +62,15 @@ pub enum HsCircKind {
 }
 
 impl HsCircKind {
+    /// Return the [`CircPurpose`] of a circuit of this kind.
+    pub fn purpose(&self) -> CircPurpose {
+        match self {
+            HsCircKind::SvcHsDir | HsCircKind::ClientHsDir => CircPurpose::HsDir,
+            HsCircKind::SvcIntro | HsCircKind::ClientIntro => CircPurpose::HsIntro,
+            HsCircKind::SvcRend | HsCircKind::ClientRend => CircPurpose::HsRendezvous,
+        }
+    }
+
     /// Return the [`HsCircStubKind`] needed to build this type of circuit.
     fn stub_kind(&self) -> HsCircStubKind {
         match self {
@@ -208,6 +218,18 @@ pub struct HsCircPool<R: Runtime> {
 struct Inner {
     /// A collection of pre-constructed circuits.
     pool: pool::Pool,
+    /// The circuits we have handed out, and the purposes we handed them out for.
+    ///
+    /// Entries for circuits that have gone away are removed lazily.
+    issued: Vec<(CircPurpose, Weak<ClientCirc>)>,
+}
+
+impl Inner {
+    /// Remember that we have handed out `circ` for `purpose`.
+    fn note_issued(&mut self, purpose: CircPurpose, circ: &Arc<ClientCirc>) {
+        self.issued.retain(|(_, c)| c.strong_count() > 0);
+        self.issued.push((purpose, Arc::downgrade(circ)));
+    }
 }
 
 impl<R: Runtime> HsCircPool<R> {
@@ -220,7 +242,10 @@ impl<R: Runtime> HsCircPool<R> {
         Arc::new(Self {
             circmgr,
             launcher_handle: OnceCell::new(),
-            inner: Mutex::new(Inner { pool }),
+            inner: Mutex::new(Inner {
+                pool,
+                issued: Vec::new(),
+            }),
         })
     }
 
@@ -293,7 +318,10 @@ impl<R: Runtime> HsCircPool<R> {
                     );
                 };
                 match netdir.by_ids(ct) {
-                    Some(relay) => Ok((circ.circ, relay)),
+                    Some(relay) => {
+                        self.note_issued(CircPurpose::HsRendezvous, &circ.circ);
+                        Ok((circ.circ, relay))
+                    }
                     // This can't happen, since launch_hs_unmanaged() only takes relays from the netdir
                     // it is given, and circuit_compatible_with_target() ensures that
                     // every relay in the circuit is listed.
@@ -307,6 +335,49 @@ impl<R: Runtime> HsCircPool<R> {
         }
     }
 
+    /// Create a circuit suitable for use as a rendezvous circuit by a client,
+    /// using the relay with identity `rend_pt` as the rendezvous point.
+    ///
+    /// This is like [`get_or_launch_client_rend`](HsCircPool::get_or_launch_client_rend),
+    /// but bypasses the random selection of the rendezvous point.
+    /// It is meant for testing and for reproducing failures against a particular relay.
+    ///
+    /// Only makes  a single attempt; the caller needs to loop if they want to retry.
+    pub async fn get_or_launch_client_rend_at<'a>(
+        &self,
+        netdir: &'a NetDir,
+        rend_pt: &RsaIdentity,
+    ) -> Result<(Arc<ClientCirc>, Relay<'a>)> {
+        let relay = netdir.by_id(rend_pt).ok_or_else(|| Error::NoRelay {
+            path_kind: "onion service rendezvous",
+            role: "rendezvous point",
+            problem: format!("{} is not listed in the consensus", rend_pt),
+        })?;
+
+        let wanted_kind = HsCircKind::ClientRend.stub_kind();
+        let circ = self
+            .take_or_launch_stub_circuit(netdir, Some(&relay), wanted_kind)
+            .await?;
+
+        #[cfg(all(feature = "vanguards", feature = "hs-common"))]
+        if matches!(
+            self.vanguard_mode(),
+            VanguardMode::Full | VanguardMode::Lite
+        ) && circ.kind != wanted_kind
+        {
+            return Err(internal!(
+                "take_or_launch_stub_circuit() returned {:?}, but we need {wanted_kind:?}",
+                circ.kind
+            )
+            .into());
+        }
+
+        let params = crate::DirInfo::from(netdir).circ_params();
+        let circ = self.extend_circ(circ, params, relay.clone()).await?;
+        self.note_issued(CircPurpose::HsRendezvous, &circ);
+        Ok((circ, relay))
+    }
+
     /// Create a circuit suitable for use for `kind`, ending at the chosen hop `target`.
     ///
     /// Only makes  a single attempt; the caller needs to loop if they want to retry.
@@ -351,7 +422,47 @@ impl<R: Runtime> HsCircPool<R> {
         }
 
         let params = crate::DirInfo::from(netdir).circ_params();
-        self.extend_circ(circ, params, target).await
+        let circ = self.extend_circ(circ, params, target).await?;
+        self.note_issued(kind.purpose(), &circ);
+        Ok(circ)
+    }
+
+    /// Remember that we have handed out `circ` for `purpose`.
+    fn note_issued(&self, purpose: CircPurpose, circ: &Arc<ClientCirc>) {
+        self.inner
+            .lock()
+            .expect("poisoned lock")
+            .note_issued(purpose, circ);
+    }
+
+    /// Return every circuit that we have handed out for `purpose`,
+    /// and which is still open.
+    pub fn circuits_by_purpose(&self, purpose: CircPurpose) -> Vec<Arc<ClientCirc>> {
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        inner.issued.retain(|(_, c)| c.strong_count() > 0);
+        inner
+            .issued
+            .iter()
+            .filter(|(p, _)| *p == purpose)
+            .filter_map(|(_, c)| c.upgrade())
+            .filter(|c| !c.is_closing())
+            .collect()
+    }
+
+    /// Describe the path of `circ`, which we handed out,
+    /// looking up the flags of its relays in `netdir`.
+    ///
+    /// If we didn't hand out `circ`, its purpose is unknown.
+    pub fn describe_circuit(&self, circ: &ClientCirc, netdir: &NetDir) -> CircPathDescription {
+        let purpose = {
+            let inner = self.inner.lock().expect("poisoned lock");
+            inner
+                .issued
+                .iter()
+                .find(|(_, c)| std::ptr::eq(c.as_ptr(), circ))
+                .map(|(p, _)| *p)
+        };
+        CircPathDescription::new(circ, purpose, netdir)
     }
 
     /// Try to extend a circuit to the specified target hop.
@@ -863,7 +974,7 @@ async fn launch_hs_circuits_as_needed<R: Runtime>(
                 warn!("Too many preemptive onion service circuits failed; waiting a while.");
                 break 'inner;
             }
-            if let Ok(netdir) = provider.netdir(tor_netdir::Timeliness::Timely) {
+            if let Ok(netdir) = provider.netdir_for(tor_netdir::DirUsage::OnionServices) {
                 // We want to launch a circuit, and we have a netdir that we can use
                 // to launch it.
                 //
@@ -928,7 +1039,7 @@ async fn remove_unusable_circuits<R: Runtime>(
             }
         };
         pool.remove_closed();
-        if let Ok(netdir) = provider.netdir(tor_netdir::Timeliness::Timely) {
+        if let Ok(netdir) = provider.netdir_for(tor_netdir::DirUsage::OnionServices) {
             pool.remove_unlisted(&netdir);
         }
     }
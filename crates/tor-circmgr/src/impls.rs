@@ -21,6 +21,12 @@ impl mgr::AbstractCirc for tor_proto::circuit::ClientCirc {
     fn usable(&self) -> bool {
         !self.is_closing()
     }
+    fn has_stream_capacity(&self, limits: &mgr::StreamLimits, reserved: usize) -> bool {
+        // A reserved slot will soon be a stream that is waiting for its BEGIN
+        // to be answered, so it counts against both limits.
+        self.n_client_streams() + reserved < limits.max_streams
+            && self.n_pending_begins() + reserved < limits.max_pending_begins
+    }
 }
 
 /// The information generated by circuit planning, and used to build a
@@ -96,13 +102,15 @@ impl<R: Runtime> crate::mgr::AbstractCircBuilder for crate::build::CircuitBuilde
 
         guard_status.pending(GuardStatus::AttemptAbandoned);
 
+        let purpose = final_spec.purpose();
+
         // TODO: We may want to lower the logic for handling
         // guard_status and guard_usable into build.rs, so that they
         // can be handled correctly on user-selected paths as well.
         //
         // This will probably require a different API for circuit
         // construction.
-        match self
+        let result = match self
             .build_owned(
                 path,
                 &params,
@@ -121,13 +129,10 @@ impl<R: Runtime> crate::mgr::AbstractCircBuilder for crate::build::CircuitBuilde
                 // it is a speculative guard that we're only trying out
                 // in case some preferable guard won't meet our needs.
                 match guard_usable.await {
-                    Some(Ok(true)) | None => (),
-                    Some(Ok(false)) => return Err(Error::GuardNotUsable(circuit.unique_id())),
-                    Some(Err(_)) => {
-                        return Err(internal!("Guard usability status cancelled").into());
-                    }
+                    Some(Ok(true)) | None => Ok((final_spec, circuit)),
+                    Some(Ok(false)) => Err(Error::GuardNotUsable(circuit.unique_id())),
+                    Some(Err(_)) => Err(internal!("Guard usability status cancelled").into()),
                 }
-                Ok((final_spec, circuit))
             }
             Err(e) => {
                 // The attempt failed; the builder should have set the
@@ -137,7 +142,10 @@ impl<R: Runtime> crate::mgr::AbstractCircBuilder for crate::build::CircuitBuilde
 
                 Err(e)
             }
-        }
+        };
+
+        self.note_build_outcome(purpose, result.is_ok());
+        result
     }
 
     fn launch_parallelism(&self, spec: &TargetCircUsage) -> usize {
@@ -43,12 +43,12 @@
 
 use tor_basic_utils::retry::RetryDelay;
 use tor_chanmgr::ChanMgr;
-use tor_error::{error_report, warn_report};
+use tor_error::{debug_report, error_report, warn_report};
 use tor_guardmgr::RetireCircuits;
 use tor_linkspec::ChanTarget;
 use tor_netdir::{DirEvent, NetDir, NetDirProvider, Timeliness};
 use tor_proto::circuit::{CircParameters, ClientCirc, UniqId};
-use tor_rtcompat::Runtime;
+use tor_rtcompat::{Runtime, SleepProviderExt as _};
 
 #[cfg(any(feature = "specific-relay", feature = "hs-common"))]
 use tor_linkspec::IntoOwnedChanTarget;
@@ -72,11 +72,21 @@ pub mod isolation;
 mod mgr;
 pub(crate) mod path;
 mod preemptive;
+mod purpose;
+mod reachability;
+mod retire;
+mod telemetry;
 pub mod timeouts;
 mod usage;
 
 pub use err::Error;
 pub use isolation::IsolationToken;
+pub use mgr::StreamSlot;
+pub use path::{CircPathDescription, HopDescription};
+pub use purpose::CircPurpose;
+pub use reachability::{NetworkReachability, ReachabilityEvents};
+pub use retire::{RetireReason, RetiredCircuit, RetirementEvents};
+pub use telemetry::{BuildOutcomes, CircBuildTelemetry, LatencyHistogram};
 use tor_guardmgr::fallback::FallbackList;
 pub use tor_guardmgr::{ClockSkewEvents, GuardMgrConfig, SkewEstimate};
 pub use usage::{TargetPort, TargetPorts};
@@ -89,13 +99,14 @@ pub use config::{
 use crate::isolation::StreamIsolation;
 use crate::mgr::CircProvenance;
 use crate::preemptive::PreemptiveCircuitPredictor;
+use crate::reachability::SelfTestOutcome;
 use usage::TargetCircUsage;
 
 use safelog::sensitive as sv;
 #[cfg(feature = "geoip")]
 use tor_geoip::CountryCode;
 pub use tor_guardmgr::{ExternalActivity, FirstHopId};
-use tor_persist::{FsStateMgr, StateMgr};
+use tor_persist::StateMgr;
 use tor_rtcompat::scheduler::{TaskHandle, TaskSchedule};
 
 #[cfg(feature = "hs-common")]
@@ -106,12 +117,30 @@ use tor_guardmgr::vanguards::VanguardMgr;
 /// A Result type as returned from this crate.
 pub type Result<T> = std::result::Result<T, Error>;
 
-/// Type alias for dynamic StorageHandle that can handle our timeout state.
-type TimeoutStateHandle = tor_persist::DynStorageHandle<timeouts::pareto::ParetoTimeoutState>;
+/// Type alias for dynamic JournaledStorageHandle that can handle our timeout state.
+type TimeoutStateHandle =
+    tor_persist::DynJournaledStorageHandle<timeouts::pareto::ParetoTimeoutState>;
 
 /// Key used to load timeout state information.
 const PARETO_TIMEOUT_DATA_KEY: &str = "circuit_timeouts";
 
+/// How many changes to our timeout state we journal before we store the
+/// whole state again.
+///
+/// We save our state about once a minute, so this is about once an hour.
+const PARETO_TIMEOUT_JOURNAL_LEN: usize = 60;
+
+/// The hostname we ask an exit to look up, during a reachability self-test.
+const REACHABILITY_SELF_TEST_HOSTNAME: &str = "www.torproject.org";
+
+/// Maximum random extra delay between flushes of our persistent state.
+const STATE_FLUSH_JITTER: Duration = Duration::from_secs(10);
+
+/// Granularity to which we round the times at which we flush our persistent state.
+///
+/// See [`TaskSchedule::set_coalescing`].
+const STATE_FLUSH_COALESCING: Duration = Duration::from_secs(15);
+
 /// Represents what we know about the Tor network.
 ///
 /// This can either be a complete directory, or a list of fallbacks.
@@ -170,6 +199,10 @@ pub struct CircMgr<R: Runtime> {
     mgr: Arc<mgr::AbstractCircMgr<build::CircuitBuilder<R>, R>>,
     /// A preemptive circuit predictor, for, uh, building circuits preemptively.
     predictor: Arc<Mutex<PreemptiveCircuitPredictor>>,
+    /// The results of our reachability self-tests.
+    reachability: reachability::ReachabilityMonitor,
+    /// Where we report circuits that we retire after a change in the network directory.
+    retirements: Arc<retire::RetirementMonitor>,
 }
 
 impl<R: Runtime> CircMgr<R> {
@@ -192,7 +225,7 @@ impl<R: Runtime> CircMgr<R> {
             config.preemptive_circuits().clone(),
         )));
 
-        guardmgr.set_filter(config.path_rules().build_guard_filter());
+        guardmgr.set_filter(config.path_rules().build_guard_filter(config.channel()));
 
         #[cfg(all(feature = "vanguards", feature = "hs-common"))]
         let vanguardmgr = {
@@ -209,7 +242,8 @@ impl<R: Runtime> CircMgr<R> {
             )?
         };
 
-        let storage_handle = storage.create_handle(PARETO_TIMEOUT_DATA_KEY);
+        let storage_handle =
+            storage.create_journaled_handle(PARETO_TIMEOUT_DATA_KEY, PARETO_TIMEOUT_JOURNAL_LEN);
 
         let builder = build::CircuitBuilder::new(
             runtime.clone(),
@@ -225,6 +259,8 @@ impl<R: Runtime> CircMgr<R> {
         let circmgr = Arc::new(CircMgr {
             mgr: Arc::new(mgr),
             predictor: preemptive,
+            reachability: reachability::ReachabilityMonitor::new(),
+            retirements: Arc::new(retire::RetirementMonitor::new()),
         });
 
         Ok(circmgr)
@@ -235,14 +271,15 @@ impl<R: Runtime> CircMgr<R> {
     /// Returns a set of [`TaskHandle`]s that can be used to manage the daemon tasks.
     //
     // NOTE(eta): The ?Sized on D is so we can pass a trait object in.
-    pub fn launch_background_tasks<D>(
+    pub fn launch_background_tasks<D, S>(
         self: &Arc<Self>,
         runtime: &R,
         dir_provider: &Arc<D>,
-        state_mgr: FsStateMgr,
+        state_mgr: S,
     ) -> Result<Vec<TaskHandle>>
     where
         D: NetDirProvider + 'static + ?Sized,
+        S: StateMgr + Send + Sync + 'static,
     {
         let mut ret = vec![];
 
@@ -254,7 +291,11 @@ impl<R: Runtime> CircMgr<R> {
             ))
             .map_err(|e| Error::from_spawn("circmgr parameter updater", e))?;
 
-        let (sched, handle) = TaskSchedule::new(runtime.clone());
+        let (mut sched, handle) = TaskSchedule::new(runtime.clone());
+        // Flushing state needn't happen at any precise time, so let it share
+        // wakeups with other periodic tasks.
+        sched.set_jitter(STATE_FLUSH_JITTER);
+        sched.set_coalescing(STATE_FLUSH_COALESCING);
         ret.push(handle);
 
         runtime
@@ -287,10 +328,20 @@ impl<R: Runtime> CircMgr<R> {
             ))
             .map_err(|e| Error::from_spawn("preemptive circuit launcher", e))?;
 
-        self.mgr
-            .peek_builder()
-            .guardmgr()
-            .install_netdir_provider(&dir_provider.clone().upcast_arc())?;
+        let (sched, handle) = TaskSchedule::new(runtime.clone());
+        ret.push(handle);
+
+        runtime
+            .spawn(Self::continually_test_reachability(
+                sched,
+                Arc::downgrade(self),
+                Arc::downgrade(dir_provider),
+            ))
+            .map_err(|e| Error::from_spawn("reachability self-test", e))?;
+
+        let guardmgr = self.mgr.peek_builder().guardmgr();
+        guardmgr.install_netdir_provider(&dir_provider.clone().upcast_arc())?;
+        ret.push(guardmgr.periodic_task_handle());
 
         #[cfg(all(feature = "vanguards", feature = "hs-common"))]
         {
@@ -338,11 +389,12 @@ impl<R: Runtime> CircMgr<R> {
             .vanguardmgr()
             .reconfigure(new_config.vanguard_config())?;
 
-        let new_reachable = &new_config.path_rules().reachable_addrs;
-        if new_reachable != &old_path_rules.reachable_addrs {
-            let filter = new_config.path_rules().build_guard_filter();
-            self.mgr.peek_builder().guardmgr().set_filter(filter);
-        }
+        // We don't remember the old channel configuration, so we always
+        // replace the filter.  This is cheap if nothing has changed.
+        let filter = new_config
+            .path_rules()
+            .build_guard_filter(new_config.channel());
+        self.mgr.peek_builder().guardmgr().set_filter(filter);
 
         let discard_all_circuits = !new_config
             .path_rules()
@@ -396,6 +448,17 @@ impl<R: Runtime> CircMgr<R> {
         self.mgr.peek_builder().save_state()
     }
 
+    /// Flush all of our state to the state manager as complete snapshots,
+    /// if we have the lock, so that nothing is left only in a journal.
+    ///
+    /// Call this before exiting.  (It also happens when this circuit manager
+    /// is dropped.)
+    ///
+    /// Return true if we saved something; false if we didn't have the lock.
+    pub fn compact_persistent_state(&self) -> Result<bool> {
+        self.mgr.peek_builder().compact_state()
+    }
+
     /// Reconfigure this circuit manager using the latest set of
     /// network parameters.
     ///
@@ -443,6 +506,31 @@ impl<R: Runtime> CircMgr<R> {
         //             additive. The function should be refactored to be builder-like.
         #[cfg(feature = "geoip")] country_code: Option<CountryCode>,
     ) -> Result<Arc<ClientCirc>> {
+        self.get_or_launch_exit_reserved(
+            netdir,
+            ports,
+            isolation,
+            #[cfg(feature = "geoip")]
+            country_code,
+        )
+        .await
+        .map(|(circ, _slot)| circ)
+    }
+
+    /// As [`get_or_launch_exit`](CircMgr::get_or_launch_exit), but also return
+    /// a [`StreamSlot`] reserved on the circuit for the caller's stream.
+    ///
+    /// Until the slot is dropped, it counts against the circuit's stream
+    /// limits, so that concurrent requests don't all pick the same circuit.
+    /// Callers should hold it until they have opened their stream, or
+    /// failed to.
+    pub async fn get_or_launch_exit_reserved(
+        &self,
+        netdir: DirInfo<'_>, // TODO: This has to be a NetDir.
+        ports: &[TargetPort],
+        isolation: StreamIsolation,
+        #[cfg(feature = "geoip")] country_code: Option<CountryCode>,
+    ) -> Result<(Arc<ClientCirc>, StreamSlot)> {
         self.expire_circuits();
         let time = Instant::now();
         {
@@ -471,7 +559,10 @@ impl<R: Runtime> CircMgr<R> {
             country_code,
             require_stability,
         };
-        self.mgr.get_or_launch(&usage, netdir).await.map(|(c, _)| c)
+        self.mgr
+            .get_or_launch_reserved(&usage, netdir)
+            .await
+            .map(|(c, _, slot)| (c, slot))
     }
 
     /// Return a circuit to a specific relay, suitable for using for direct
@@ -594,6 +685,96 @@ impl<R: Runtime> CircMgr<R> {
         let _ = self.mgr.take_circ(circ_id);
     }
 
+    /// Retire every open circuit that `netdir` says we should no longer use.
+    ///
+    /// A circuit is retired if any relay in it (other than the first hop) is
+    /// no longer listed in `netdir`, or if it was built for exit traffic and
+    /// its exit may no longer be used to exit (for example, because it has been
+    /// flagged `BadExit`).
+    ///
+    /// Retired circuits are not given out for any future requests.  Retired
+    /// circuits that nothing else is using are closed; the others stay open
+    /// until their existing streams are done.
+    ///
+    /// We call this whenever we get a new consensus; the results are
+    /// reported on [`retirement_events`](CircMgr::retirement_events).
+    pub fn retire_circuits_for_netdir(&self, netdir: &NetDir) -> Vec<RetiredCircuit> {
+        let retired = self.mgr.retire_circs_matching(|spec, circ| {
+            let is_exit = matches!(spec, usage::SupportedCircUsage::Exit { .. });
+            let path = circ.path_ref();
+            retire::retire_reason(
+                path.iter().filter_map(|hop| hop.as_chan_target()),
+                is_exit,
+                netdir,
+            )
+        });
+
+        let retired: Vec<_> = retired
+            .into_iter()
+            .map(|(circ, reason)| {
+                // If nothing but us holds a reference to this circuit, nobody
+                // is using it, and nobody will: close it now.
+                let closed = Arc::strong_count(&circ) == 1;
+                if closed {
+                    circ.terminate();
+                }
+                let circ_id = circ.unique_id();
+                debug!(
+                    "Retired circuit {} ({}){}",
+                    circ_id,
+                    reason,
+                    if closed { "; closed it" } else { "" }
+                );
+                RetiredCircuit {
+                    circ_id,
+                    reason,
+                    closed,
+                }
+            })
+            .collect();
+
+        if !retired.is_empty() {
+            info!(
+                "Retired {} circuit(s) after a change in the network directory.",
+                retired.len()
+            );
+        }
+        self.retirements.publish(retired.clone());
+        retired
+    }
+
+    /// Return a stream of the circuits that we retire because a new network
+    /// directory says we shouldn't use them.
+    ///
+    /// See [`retire_circuits_for_netdir`](CircMgr::retire_circuits_for_netdir).
+    pub fn retirement_events(&self) -> RetirementEvents {
+        self.retirements.events()
+    }
+
+    /// Return every open circuit we're keeping track of that was built for `purpose`.
+    ///
+    /// This only includes circuits that we might still give out for new requests.
+    /// Onion service circuits are not listed here: they are tracked by the
+    /// `HsCircPool` instead.
+    pub fn circuits_by_purpose(&self, purpose: CircPurpose) -> Vec<Arc<ClientCirc>> {
+        self.mgr
+            .open_circs_matching(|spec| spec.purpose() == Some(purpose))
+    }
+
+    /// Describe the path of `circ`, looking up the flags of its relays in
+    /// `netdir`.
+    ///
+    /// The purpose is only known if we are still keeping track of `circ`:
+    /// not if it has been retired, or if it is an onion service circuit
+    /// (for those, use `HsCircPool::describe_circuit`).
+    pub fn describe_circuit(&self, circ: &ClientCirc, netdir: &NetDir) -> CircPathDescription {
+        let purpose = self
+            .mgr
+            .open_circ_spec(&circ.unique_id())
+            .and_then(|spec| spec.purpose());
+        CircPathDescription::new(circ, purpose, netdir)
+    }
+
     /// Mark every circuit that we have launched so far as unsuitable for
     /// any future requests.  This won't close existing circuits that have
     /// streams attached to them, but it will prevent any future streams from
@@ -635,6 +816,16 @@ impl<R: Runtime> CircMgr<R> {
         timeout
     }
 
+    /// Return timing information about the circuits that this manager has built.
+    ///
+    /// This includes the time taken to add each hop, for each position in
+    /// the path, and the total time taken to build each circuit.
+    /// To see how long a single circuit took to build, use
+    /// [`ClientCirc::hop_timings`] and [`ClientCirc::build_duration`].
+    pub fn build_telemetry(&self) -> CircBuildTelemetry {
+        self.mgr.peek_builder().build_telemetry()
+    }
+
     /// Expire every circuit that has been dirty for too long.
     ///
     /// Expired circuits are not closed while they still have users,
@@ -683,8 +874,63 @@ impl<R: Runtime> CircMgr<R> {
         Ok(())
     }
 
+    /// Build a throwaway circuit, and make a small request over it,
+    /// to find out whether we can reach the Tor network.
+    ///
+    /// # Note
+    ///
+    /// This function is invoked periodically from
+    /// `continually_test_reachability`.
+    async fn reachability_self_test(&self, netdir: &NetDir) -> SelfTestOutcome {
+        // We want an exit circuit, so that we can ask it to do a DNS lookup.
+        let usage = TargetCircUsage::Preemptive {
+            port: None,
+            circs: 1,
+            require_stability: false,
+        };
+        let circ = match self.mgr.launch_unmanaged(&usage, netdir.into()).await {
+            Ok((_, circ)) => circ,
+            Err(e) => {
+                debug_report!(e, "Reachability self-test couldn't build a circuit");
+                return SelfTestOutcome::BuildFailed;
+            }
+        };
+
+        let timeout = self.estimate_timeout(&timeouts::Action::RoundTrip {
+            length: circ.n_hops(),
+        });
+        let outcome = match self
+            .mgr
+            .peek_runtime()
+            .timeout(timeout, circ.resolve(REACHABILITY_SELF_TEST_HOSTNAME))
+            .await
+        {
+            Ok(Ok(_)) => SelfTestOutcome::Success,
+            Ok(Err(e)) => {
+                debug_report!(e, "Reachability self-test request failed");
+                SelfTestOutcome::RequestFailed
+            }
+            Err(_) => {
+                debug!("Reachability self-test request timed out");
+                SelfTestOutcome::RequestFailed
+            }
+        };
+        circ.terminate();
+        outcome
+    }
+
+    /// Return a stream of changes to our opinion of whether we can reach the
+    /// Tor network, based on our reachability self-tests.
+    ///
+    /// If the self-test is disabled (see [`CircuitTiming`]),
+    /// this is always [`NetworkReachability::Unknown`].
+    pub fn reachability_events(&self) -> ReachabilityEvents {
+        self.reachability.events()
+    }
+
     /// Whenever a [`DirEvent::NewConsensus`] arrives on `events`, update
-    /// `circmgr` with the consensus parameters from `dirmgr`.
+    /// `circmgr` with the consensus parameters from `dirmgr`, and retire any
+    /// circuits that the new consensus says we shouldn't use.
     ///
     /// Exit when `events` is closed, or one of `circmgr` or `dirmgr` becomes
     /// dangling.
@@ -704,6 +950,7 @@ impl<R: Runtime> CircMgr<R> {
                     if let Ok(netdir) = dm.netdir(Timeliness::Timely) {
                         #[allow(deprecated)]
                         cm.update_network_parameters(netdir.params());
+                        let _ = cm.retire_circuits_for_netdir(&netdir);
                     }
                 } else {
                     debug!("Circmgr or dirmgr has disappeared; task exiting.");
@@ -752,16 +999,56 @@ impl<R: Runtime> CircMgr<R> {
         }
     }
 
+    /// Run indefinitely, periodically testing whether we can reach the network,
+    /// if the self-test is enabled.
+    ///
+    /// Exit when we notice that `circmgr` or `dirmgr` has been dropped.
+    ///
+    /// This is a daemon task: it runs indefinitely in the background.
+    async fn continually_test_reachability<D>(
+        mut sched: TaskSchedule<R>,
+        circmgr: Weak<Self>,
+        dirmgr: Weak<D>,
+    ) where
+        D: NetDirProvider + 'static + ?Sized,
+    {
+        while sched.next().await.is_some() {
+            let (Some(cm), Some(dm)) = (Weak::upgrade(&circmgr), Weak::upgrade(&dirmgr)) else {
+                return;
+            };
+            let timing = cm.mgr.circuit_timing();
+            if timing.reachability_self_test {
+                if let Ok(netdir) = dm.netdir(Timeliness::Timely) {
+                    let outcome = cm.reachability_self_test(&netdir).await;
+                    cm.reachability.note(outcome);
+                } else {
+                    // Wait for the provider to announce some event, as in
+                    // continually_launch_timeout_testing_circuits.
+                    drop(cm);
+                    let _ = dm.events().next().await;
+                    sched.fire();
+                    continue;
+                }
+            } else {
+                cm.reachability.reset();
+            }
+            // If the self-test is disabled, we still wake up from time to time,
+            // in case it has been enabled since.
+            drop((cm, dm));
+            sched.fire_in(timing.reachability_self_test_interval);
+        }
+    }
+
     /// Run forever, periodically telling `circmgr` to update its persistent
     /// state.
     ///
     /// Exit when we notice that `circmgr` has been dropped.
     ///
     /// This is a daemon task: it runs indefinitely in the background.
-    async fn update_persistent_state(
+    async fn update_persistent_state<S: StateMgr>(
         mut sched: TaskSchedule<R>,
         circmgr: Weak<Self>,
-        statemgr: FsStateMgr,
+        statemgr: S,
     ) {
         while sched.next().await.is_some() {
             if let Some(circmgr) = Weak::upgrade(&circmgr) {
@@ -898,7 +1185,7 @@ impl<R: Runtime> CircMgr<R> {
 
 impl<R: Runtime> Drop for CircMgr<R> {
     fn drop(&mut self) {
-        match self.store_persistent_state() {
+        match self.compact_persistent_state() {
             Ok(true) => info!("Flushed persistent state at exit."),
             Ok(false) => debug!("Lock not held; no state to flush."),
             Err(e) => error_report!(e, "Unable to flush state on circuit manager drop"),
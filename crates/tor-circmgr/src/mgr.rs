@@ -162,8 +162,50 @@ pub(crate) trait AbstractCirc: Debug {
     ///
     /// Reasons a circuit might be unusable include being closed.
     fn usable(&self) -> bool;
+
+    /// Return true if this circuit has room for another stream under `limits`,
+    /// given that `reserved` more streams have been promised to requests
+    /// which have not yet opened them.
+    fn has_stream_capacity(&self, limits: &StreamLimits, reserved: usize) -> bool;
+}
+
+/// Limits on how many streams we will put on a single circuit.
+///
+/// A circuit that has reached either limit is not given out for new
+/// requests, so that those requests use (or build) another circuit instead.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct StreamLimits {
+    /// The number of open streams at which a circuit is full.
+    pub(crate) max_streams: usize,
+    /// The number of streams awaiting a reply to their BEGIN at which a
+    /// circuit is full.
+    pub(crate) max_pending_begins: usize,
+}
+
+impl From<&CircuitTiming> for StreamLimits {
+    fn from(timing: &CircuitTiming) -> Self {
+        let to_usize = |n: std::num::NonZeroU32| n.get().try_into().unwrap_or(usize::MAX);
+        StreamLimits {
+            max_streams: to_usize(timing.max_client_streams_per_circuit),
+            max_pending_begins: to_usize(timing.max_pending_begins_per_circuit),
+        }
+    }
 }
 
+/// A stream slot that we have reserved on a circuit for a single request.
+///
+/// While this object exists, the slot counts against the circuit's
+/// [`StreamLimits`], so that concurrent requests cannot all pick the same
+/// circuit and overshoot them.  Hold on to it until the stream has been opened
+/// (or has failed to open), and then drop it.
+#[derive(Debug)]
+#[must_use = "dropping a StreamSlot releases it immediately"]
+pub struct StreamSlot(
+    /// A clone of the `slots` field in the circuit's `OpenEntry`.
+    #[allow(dead_code)] // We only hold this for its reference count.
+    Arc<()>,
+);
+
 /// A plan for an `AbstractCircBuilder` that can maybe be mutated by tests.
 ///
 /// You should implement this trait using all default methods for all code that isn't test code.
@@ -320,6 +362,11 @@ pub(crate) struct OpenEntry<S, C> {
     /// which does not actually close them until there are no more
     /// references to them.)
     expiration: ExpirationInfo,
+    /// Reference-counted token for the stream slots handed out on this circuit.
+    ///
+    /// Every [`StreamSlot`] holds a clone of this, so the number of
+    /// outstanding reservations is its strong count, less one.
+    slots: Arc<()>,
 }
 
 impl<S: AbstractSpec, C: AbstractCirc> OpenEntry<S, C> {
@@ -329,9 +376,25 @@ impl<S: AbstractSpec, C: AbstractCirc> OpenEntry<S, C> {
             spec,
             circ,
             expiration,
+            slots: Arc::new(()),
         }
     }
 
+    /// Return true if this circuit has room for another stream under
+    /// `limits`, counting the stream slots that are currently reserved on it.
+    fn has_stream_capacity(&self, limits: &StreamLimits) -> bool {
+        let reserved = Arc::strong_count(&self.slots) - 1;
+        self.circ.has_stream_capacity(limits, reserved)
+    }
+
+    /// Reserve a stream slot on this circuit.
+    ///
+    /// The caller should hold the lock on the `CircList` containing this
+    /// entry, so that nobody else can pick the same slot.
+    fn reserve_slot(&self) -> StreamSlot {
+        StreamSlot(Arc::clone(&self.slots))
+    }
+
     /// Return true if this circuit can be used for `usage`.
     fn supports(&self, usage: &<S as AbstractSpec>::Usage) -> bool {
         self.circ.usable() && self.spec.supports(usage)
@@ -546,14 +609,19 @@ impl<B: AbstractCircBuilder> CircList<B> {
         self.open_circs.insert(id, e);
     }
 
-    /// Find all the usable open circuits that support `usage`.
+    /// Find all the usable open circuits that support `usage`, and that
+    /// have room for another stream under `limits`.
     ///
     /// Return None if there are no such circuits.
     fn find_open(
         &mut self,
         usage: &<B::Spec as AbstractSpec>::Usage,
+        limits: &StreamLimits,
     ) -> Option<Vec<&mut OpenEntry<B::Spec, B::Circ>>> {
-        let list = self.open_circs.values_mut();
+        let list = self
+            .open_circs
+            .values_mut()
+            .filter(|ent| ent.has_stream_capacity(limits));
         let v = <B::Spec as AbstractSpec>::find_supported(list, usage);
         if v.is_empty() {
             None
@@ -744,8 +812,9 @@ pub(crate) struct AbstractCircMgr<B: AbstractCircBuilder, R: Runtime> {
 
 /// An action to take in order to satisfy a request for a circuit.
 enum Action<B: AbstractCircBuilder> {
-    /// We found an open circuit: return immediately.
-    Open(Arc<B::Circ>),
+    /// We found an open circuit, and reserved a stream slot on it: return
+    /// immediately.
+    Open(Arc<B::Circ>, StreamSlot),
     /// We found one or more pending circuits: wait until one succeeds,
     /// or all fail.
     Wait(FuturesUnordered<Shared<oneshot::Receiver<PendResult<B>>>>),
@@ -792,11 +861,30 @@ impl<B: AbstractCircBuilder + 'static, R: Runtime> AbstractCircMgr<B, R> {
     /// under the assumption that it will be used for that spec.
     ///
     /// This is the primary entry point for AbstractCircMgr.
+    ///
+    /// The stream slot reserved for this request is released immediately;
+    /// use [`get_or_launch_reserved`](Self::get_or_launch_reserved) to keep it
+    /// until the stream is open.
     pub(crate) async fn get_or_launch(
         self: &Arc<Self>,
         usage: &<B::Spec as AbstractSpec>::Usage,
         dir: DirInfo<'_>,
     ) -> Result<(Arc<B::Circ>, CircProvenance)> {
+        self.get_or_launch_reserved(usage, dir)
+            .await
+            .map(|(circ, provenance, _slot)| (circ, provenance))
+    }
+
+    /// As [`get_or_launch`](Self::get_or_launch), but also return the stream
+    /// slot that we reserved on the circuit for this request.
+    ///
+    /// The slot is taken while we hold the lock on our circuit list, so
+    /// concurrent requests never see the same free capacity twice.
+    pub(crate) async fn get_or_launch_reserved(
+        self: &Arc<Self>,
+        usage: &<B::Spec as AbstractSpec>::Usage,
+        dir: DirInfo<'_>,
+    ) -> Result<(Arc<B::Circ>, CircProvenance, StreamSlot)> {
         /// Return CEIL(a/b).
         ///
         /// Requires that a+b is less than usize::MAX.
@@ -960,9 +1048,10 @@ impl<B: AbstractCircBuilder + 'static, R: Runtime> AbstractCircMgr<B, R> {
         dir: DirInfo<'_>,
         restrict_circ: bool,
     ) -> Result<Action<B>> {
+        let limits = StreamLimits::from(&*self.circuit_timing());
         let mut list = self.circs.lock().expect("poisoned lock");
 
-        if let Some(mut open) = list.find_open(usage) {
+        if let Some(mut open) = list.find_open(usage, &limits) {
             // We have open circuits that meet the spec: return the best one.
             let parallelism = self.builder.select_parallelism(usage);
             let best = OpenEntry::find_best(&mut open, usage, parallelism);
@@ -973,7 +1062,7 @@ impl<B: AbstractCircBuilder + 'static, R: Runtime> AbstractCircMgr<B, R> {
             // TODO: If we have fewer circuits here than our select
             // parallelism, perhaps we should launch more?
 
-            return Ok(Action::Open(best.circ.clone()));
+            return Ok(Action::Open(best.circ.clone(), best.reserve_slot()));
         }
 
         if let Some(pending) = list.find_pending_circs(usage) {
@@ -1028,7 +1117,8 @@ impl<B: AbstractCircBuilder + 'static, R: Runtime> AbstractCircMgr<B, R> {
         self: Arc<Self>,
         act: Action<B>,
         usage: &<B::Spec as AbstractSpec>::Usage,
-    ) -> std::result::Result<(Arc<B::Circ>, CircProvenance), RetryError<Box<Error>>> {
+    ) -> std::result::Result<(Arc<B::Circ>, CircProvenance, StreamSlot), RetryError<Box<Error>>>
+    {
         /// Store the error `err` into `retry_err`, as appropriate.
         fn record_error(
             retry_err: &mut RetryError<Box<Error>>,
@@ -1060,10 +1150,10 @@ impl<B: AbstractCircBuilder + 'static, R: Runtime> AbstractCircMgr<B, R> {
 
         // Get or make a stream of futures to wait on.
         let (building, wait_on_stream) = match act {
-            Action::Open(c) => {
+            Action::Open(c, slot) => {
                 // There's already a perfectly good open circuit; we can return
                 // it now.
-                return Ok((c, CircProvenance::Preexisting));
+                return Ok((c, CircProvenance::Preexisting, slot));
             }
             Action::Wait(f) => {
                 // There is one or more pending circuit that we're waiting for.
@@ -1114,6 +1204,7 @@ impl<B: AbstractCircBuilder + 'static, R: Runtime> AbstractCircMgr<B, R> {
         let mut incoming = streams::select_biased(wait_on_stream, additional_stream.map(Ok));
 
         let mut retry_error = RetryError::in_attempt_to("wait for circuits");
+        let limits = StreamLimits::from(&*self.circuit_timing());
 
         while let Some((src, id)) = incoming.next().await {
             match id {
@@ -1121,6 +1212,18 @@ impl<B: AbstractCircBuilder + 'static, R: Runtime> AbstractCircMgr<B, R> {
                     // Great, we have a circuit. See if we can use it!
                     let mut list = self.circs.lock().expect("poisoned lock");
                     if let Some(ent) = list.get_open_mut(id) {
+                        if !ent.has_stream_capacity(&limits) {
+                            // Other requests that were waiting for this
+                            // circuit have already taken all of its slots.
+                            debug!(
+                                "{} suggested we use {:?}, but it has no room for another stream",
+                                describe_source(building, src),
+                                id,
+                            );
+                            let e = Error::LostUsabilityRace(RestrictionFailed::NotSupported);
+                            record_error(&mut retry_error, src, building, e);
+                            continue;
+                        }
                         let now = self.runtime.now();
                         match ent.restrict_mut(usage, now) {
                             Ok(()) => {
@@ -1137,7 +1240,11 @@ impl<B: AbstractCircBuilder + 'static, R: Runtime> AbstractCircMgr<B, R> {
                                         now + self.circuit_timing().max_dirtiness,
                                     );
                                 }
-                                return Ok((ent.circ.clone(), CircProvenance::NewlyCreated));
+                                return Ok((
+                                    ent.circ.clone(),
+                                    CircProvenance::NewlyCreated,
+                                    ent.reserve_slot(),
+                                ));
                             }
                             Err(e) => {
                                 // In this case, a `UsageMismatched` error just means that we lost the race
@@ -1418,6 +1525,28 @@ impl<B: AbstractCircBuilder + 'static, R: Runtime> AbstractCircMgr<B, R> {
         list.clear_all_circuits();
     }
 
+    /// Remove every open circuit for which `test` returns `Some`, so that it
+    /// can't be given out for any more requests.
+    ///
+    /// Return each removed circuit, along with the value that `test` returned
+    /// for it.  The circuits are not closed.
+    pub(crate) fn retire_circs_matching<T>(
+        &self,
+        mut test: impl FnMut(&B::Spec, &B::Circ) -> Option<T>,
+    ) -> Vec<(Arc<B::Circ>, T)> {
+        let mut list = self.circs.lock().expect("poisoned lock");
+        let mut retired = Vec::new();
+        list.open_circs
+            .retain(|_, entry| match test(&entry.spec, &entry.circ) {
+                Some(t) => {
+                    retired.push((Arc::clone(&entry.circ), t));
+                    false
+                }
+                None => true,
+            });
+        retired
+    }
+
     /// Expire circuits according to the rules in `config` and the
     /// current time `now`.
     ///
@@ -1445,6 +1574,26 @@ impl<B: AbstractCircBuilder + 'static, R: Runtime> AbstractCircMgr<B, R> {
         list.open_circs.len()
     }
 
+    /// Return every open circuit held by this circuit manager whose spec matches `filter`.
+    pub(crate) fn open_circs_matching(
+        &self,
+        filter: impl Fn(&B::Spec) -> bool,
+    ) -> Vec<Arc<B::Circ>> {
+        let list = self.circs.lock().expect("poisoned lock");
+        list.open_circs
+            .values()
+            .filter(|entry| filter(&entry.spec))
+            .map(|entry| Arc::clone(&entry.circ))
+            .collect()
+    }
+
+    /// Return the spec of the open circuit with the given ID, if we are
+    /// keeping track of it.
+    pub(crate) fn open_circ_spec(&self, id: &<B::Circ as AbstractCirc>::Id) -> Option<B::Spec> {
+        let list = self.circs.lock().expect("poisoned lock");
+        list.open_circs.get(id).map(|entry| entry.spec.clone())
+    }
+
     /// Return the number of pending circuits tracked by this circuit manager.
     #[cfg(test)]
     pub(crate) fn n_pending_circs(&self) -> usize {
@@ -1572,12 +1721,15 @@ mod test {
         }
     }
 
-    #[derive(Debug, PartialEq, Clone, Eq)]
+    #[derive(Debug, Clone)]
     struct FakeCirc {
         id: FakeId,
     }
 
     impl FakeCirc {
+        fn new() -> Self {
+            FakeCirc { id: FakeId::next() }
+        }
         fn eq(&self, other: &Self) -> bool {
             self.id == other.id
         }
@@ -1591,6 +1743,9 @@ mod test {
         fn usable(&self) -> bool {
             true
         }
+        fn has_stream_capacity(&self, limits: &StreamLimits, reserved: usize) -> bool {
+            reserved < limits.max_streams
+        }
     }
 
     #[derive(Clone, Debug, Eq, PartialEq, Hash)]
@@ -1716,8 +1871,8 @@ mod test {
             self.runtime.allow_one_advance(FAKE_CIRC_DELAY);
             sl.await;
             match op {
-                FakeOp::Succeed => Ok((plan.spec, Arc::new(FakeCirc { id: FakeId::next() }))),
-                FakeOp::WrongSpec(s) => Ok((s, Arc::new(FakeCirc { id: FakeId::next() }))),
+                FakeOp::Succeed => Ok((plan.spec, Arc::new(FakeCirc::new()))),
+                FakeOp::WrongSpec(s) => Ok((s, Arc::new(FakeCirc::new()))),
                 FakeOp::Fail => Err(Error::CircTimeout(None)),
                 FakeOp::Delay(d) => {
                     let sl = self.runtime.sleep(d);
@@ -1863,6 +2018,58 @@ mod test {
         });
     }
 
+    #[test]
+    fn concurrent_requests_reserve_stream_slots() {
+        tor_rtmock::MockRuntime::test_with_various(|rt| async move {
+            use crate::config::CircuitTimingBuilder;
+            use std::num::NonZeroU32;
+
+            let rt = MockSleepRuntime::new(rt);
+            let builder = FakeBuilder::new(&rt);
+            let circuit_timing = CircuitTimingBuilder::default()
+                .max_client_streams_per_circuit(NonZeroU32::new(2).unwrap())
+                .build()
+                .unwrap();
+            let mgr = Arc::new(AbstractCircMgr::new(builder, rt.clone(), circuit_timing));
+            let webports = FakeSpec::new(vec![80_u16, 443]);
+
+            // Four requests arrive at once: none of the circuits may be given
+            // to more than two of them.
+            let results = rt
+                .wait_for(futures::future::join_all(
+                    (0..4).map(|_| mgr.get_or_launch_reserved(&webports, di())),
+                ))
+                .await;
+            let mut got: Vec<_> = results
+                .into_iter()
+                .map(|r| {
+                    let (circ, _, slot) = r.unwrap();
+                    (circ, slot)
+                })
+                .collect();
+            for (c1, _) in &got {
+                let n_sharing = got.iter().filter(|(c2, _)| FakeCirc::eq(c1, c2)).count();
+                assert!(n_sharing <= 2);
+            }
+            assert_eq!(mgr.n_circs(), 2);
+
+            // While their slots are held, the next request needs a new circuit.
+            let (c5, _, _slot5) = rt
+                .wait_for(mgr.get_or_launch_reserved(&webports, di()))
+                .await
+                .unwrap();
+            assert!(got.iter().all(|(c, _)| !FakeCirc::eq(c, &c5)));
+            assert_eq!(mgr.n_circs(), 3);
+
+            // Once a request is done with its slot, another can have it.
+            let (c1, slot1) = got.remove(0);
+            drop(slot1);
+            let (c6, _, _slot6) = mgr.get_or_launch_reserved(&webports, di()).await.unwrap();
+            assert!(FakeCirc::eq(&c1, &c6) || FakeCirc::eq(&c5, &c6));
+            assert_eq!(mgr.n_circs(), 3);
+        });
+    }
+
     #[test]
     fn request_timeout() {
         tor_rtmock::MockRuntime::test_with_various(|rt| async move {
@@ -2220,6 +2427,47 @@ mod test {
         });
     }
 
+    #[test]
+    fn retire_matching() {
+        tor_rtmock::MockRuntime::test_with_various(|rt| async move {
+            let rt = MockSleepRuntime::new(rt);
+            let builder = FakeBuilder::new(&rt);
+            let mgr = Arc::new(AbstractCircMgr::new(
+                builder,
+                rt.clone(),
+                CircuitTiming::default(),
+            ));
+
+            let imap = FakeSpec::new(vec![993_u16]);
+            let pop = FakeSpec::new(vec![995_u16]);
+
+            let (imap1, pop1) = rt
+                .wait_for(futures::future::join(
+                    mgr.get_or_launch(&imap, di()),
+                    mgr.get_or_launch(&pop, di()),
+                ))
+                .await;
+            let imap1 = imap1.unwrap().0;
+            let pop1 = pop1.unwrap().0;
+
+            let retired =
+                mgr.retire_circs_matching(|spec, _circ| spec.ports.contains(&995).then_some("pop"));
+            assert_eq!(retired.len(), 1);
+            assert!(FakeCirc::eq(&retired[0].0, &pop1));
+            assert_eq!(retired[0].1, "pop");
+
+            // The retired circuit isn't handed out any more; the other one is.
+            let (imap2, pop2) = rt
+                .wait_for(futures::future::join(
+                    mgr.get_or_launch(&imap, di()),
+                    mgr.get_or_launch(&pop, di()),
+                ))
+                .await;
+            assert!(FakeCirc::eq(&imap2.unwrap().0, &imap1));
+            assert!(!FakeCirc::eq(&pop2.unwrap().0, &pop1));
+        });
+    }
+
     /// Returns three exit policies; one that permits nothing, one that permits ports 80
     /// and 443 only, and one that permits all ports.
     fn get_exit_policies() -> (ExitPolicy, ExitPolicy, ExitPolicy) {
@@ -2247,7 +2495,7 @@ mod test {
     #[test]
     fn test_find_supported() {
         let (ep_none, ep_web, ep_full) = get_exit_policies();
-        let fake_circ = Arc::new(FakeCirc { id: FakeId::next() });
+        let fake_circ = Arc::new(FakeCirc::new());
         let expiration = ExpirationInfo::Unused {
             use_before: Instant::now() + Duration::from_secs(60 * 60),
         };
@@ -17,7 +17,7 @@ pub(crate) mod exitpath;
 pub(crate) mod hspath;
 
 use std::result::Result as StdResult;
-use std::time::SystemTime;
+use std::time::{Instant, SystemTime};
 
 use rand::Rng;
 
@@ -28,6 +28,8 @@ use tor_guardmgr::fallback::FallbackDir;
 use tor_guardmgr::{GuardMgr, GuardMonitor, GuardUsable};
 use tor_linkspec::{HasAddrs, HasRelayIds, OwnedChanTarget, OwnedCircTarget, RelayIdSet};
 use tor_netdir::{NetDir, Relay};
+use tor_netdoc::doc::netstatus::RelayFlags;
+use tor_proto::circuit::ClientCirc;
 use tor_relay_selection::{RelayExclusion, RelaySelectionConfig, RelaySelector, RelayUsage};
 use tor_rtcompat::Runtime;
 
@@ -35,7 +37,7 @@ use tor_rtcompat::Runtime;
 use tor_guardmgr::vanguards::Vanguard;
 
 use crate::usage::ExitPolicy;
-use crate::{DirInfo, Error, PathConfig, Result};
+use crate::{CircPurpose, DirInfo, Error, PathConfig, Result};
 
 /// A list of Tor relays through the network.
 pub struct TorPath<'a> {
@@ -495,6 +497,63 @@ fn select_guard<'a, R: Rng, RT: Runtime>(
     }
 }
 
+/// A description of one hop in an open circuit's path.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct HopDescription {
+    /// This hop's relay identities and addresses.
+    ///
+    /// This is `None` for the virtual hop at the end of a circuit
+    /// to an onion service.
+    pub target: Option<OwnedChanTarget>,
+    /// The flags that the authorities gave this hop's relay.
+    ///
+    /// This is `None` if the relay isn't listed in the network directory
+    /// we looked it up in (for example, because it is a bridge),
+    /// or if this is a virtual hop.
+    pub flags: Option<RelayFlags>,
+}
+
+/// A description of an open circuit's path, for showing to a user
+/// ("your circuit goes through X → Y → Z") or for checking in tests.
+///
+/// This is a snapshot: it is not updated if the circuit is extended,
+/// or if the network directory changes.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct CircPathDescription {
+    /// What the circuit was built for, if we know.
+    pub purpose: Option<CircPurpose>,
+    /// When we started building the circuit.
+    pub created: Instant,
+    /// The circuit's hops, starting with the first hop.
+    pub hops: Vec<HopDescription>,
+}
+
+impl CircPathDescription {
+    /// Describe the path of `circ`, which was built for `purpose`,
+    /// looking up the flags of its relays in `netdir`.
+    pub fn new(circ: &ClientCirc, purpose: Option<CircPurpose>, netdir: &NetDir) -> Self {
+        let hops = circ
+            .path_ref()
+            .iter()
+            .map(|hop| {
+                let target = hop.as_chan_target().map(OwnedChanTarget::from_chan_target);
+                let flags = target
+                    .as_ref()
+                    .and_then(|target| netdir.by_ids(target))
+                    .map(|relay| relay.low_level_details().flags());
+                HopDescription { target, flags }
+            })
+            .collect();
+        CircPathDescription {
+            purpose,
+            created: circ.creation_time(),
+            hops,
+        }
+    }
+}
+
 /// For testing: make sure that `path` is the same when it is an owned
 /// path.
 #[cfg(test)]
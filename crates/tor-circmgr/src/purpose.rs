@@ -0,0 +1,67 @@
+//! Code for describing why a circuit was built.
+
+/// The purpose for which a circuit was built.
+///
+/// Every circuit that the circuit manager hands out has a purpose,
+/// and a circuit is only ever reused for the purpose it was built for
+/// (see [`CircPurpose::may_be_reused_for`]).
+///
+/// Circuits for onion services are built by the
+/// `HsCircPool`;
+/// they get their purpose when they are handed out.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum CircPurpose {
+    /// A circuit for exiting to the internet.
+    Exit,
+    /// A circuit for making BEGINDIR directory requests.
+    Directory,
+    /// A circuit for fetching or uploading an onion service descriptor.
+    HsDir,
+    /// A circuit to an onion service introduction point.
+    HsIntro,
+    /// A circuit to an onion service rendezvous point.
+    HsRendezvous,
+    /// A circuit built only to measure how long circuits take to build.
+    Measurement,
+}
+
+impl CircPurpose {
+    /// Return true if a circuit built for this purpose may be reused for `wanted`.
+    ///
+    /// Circuits are only reused for their own purpose,
+    /// except that any exit circuit can serve to take a measurement.
+    pub fn may_be_reused_for(self, wanted: CircPurpose) -> bool {
+        self == wanted || (self == CircPurpose::Exit && wanted == CircPurpose::Measurement)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    #[test]
+    fn reuse() {
+        use CircPurpose as P;
+        assert!(P::Exit.may_be_reused_for(P::Exit));
+        assert!(P::Exit.may_be_reused_for(P::Measurement));
+        assert!(P::Measurement.may_be_reused_for(P::Measurement));
+        assert!(!P::Measurement.may_be_reused_for(P::Exit));
+        assert!(!P::Directory.may_be_reused_for(P::Exit));
+        assert!(!P::Exit.may_be_reused_for(P::Directory));
+        assert!(!P::HsIntro.may_be_reused_for(P::HsRendezvous));
+    }
+}
@@ -0,0 +1,213 @@
+//! Code for a background self-test of whether we can reach the Tor network.
+//!
+//! When the self-test is enabled, we periodically build a throwaway circuit
+//! and make a small request over it.  From the results, we keep a rough
+//! [`NetworkReachability`] signal, so that a user interface can tell
+//! "Tor is not working" apart from "your connection to the internet is down".
+
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+use educe::Educe;
+use futures::{Stream, StreamExt as _};
+use tor_basic_utils::skip_fmt;
+
+/// How many self-tests in a row must fail to build a circuit
+/// before we say that the network is down.
+const BUILD_FAILURES_BEFORE_DOWN: u32 = 3;
+
+/// Our best guess about whether we can use the Tor network.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, derive_more::Display)]
+#[non_exhaustive]
+pub enum NetworkReachability {
+    /// We haven't run a self-test yet, or the self-test is disabled.
+    #[default]
+    #[display(fmt = "unknown")]
+    Unknown,
+    /// Our last self-test succeeded.
+    #[display(fmt = "up")]
+    Up,
+    /// Our last self-test failed, but we still seem to be able to reach
+    /// some of the network.
+    #[display(fmt = "degraded")]
+    Degraded,
+    /// Several self-tests in a row have been unable to build a circuit at all.
+    #[display(fmt = "down")]
+    Down,
+}
+
+/// The outcome of a single reachability self-test.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum SelfTestOutcome {
+    /// We built a circuit and got an answer over it.
+    Success,
+    /// We built a circuit, but our request over it failed.
+    RequestFailed,
+    /// We couldn't build a circuit.
+    BuildFailed,
+}
+
+/// The history we use to turn self-test outcomes into a [`NetworkReachability`].
+#[derive(Debug, Default)]
+struct ReachabilityState {
+    /// How many self-tests in a row have failed to build a circuit.
+    consecutive_build_failures: u32,
+}
+
+impl ReachabilityState {
+    /// Record `outcome`, and return our new opinion of the network.
+    fn note(&mut self, outcome: SelfTestOutcome) -> NetworkReachability {
+        match outcome {
+            SelfTestOutcome::Success => {
+                self.consecutive_build_failures = 0;
+                NetworkReachability::Up
+            }
+            SelfTestOutcome::RequestFailed => {
+                self.consecutive_build_failures = 0;
+                NetworkReachability::Degraded
+            }
+            SelfTestOutcome::BuildFailed => {
+                self.consecutive_build_failures = self.consecutive_build_failures.saturating_add(1);
+                if self.consecutive_build_failures >= BUILD_FAILURES_BEFORE_DOWN {
+                    NetworkReachability::Down
+                } else {
+                    NetworkReachability::Degraded
+                }
+            }
+        }
+    }
+}
+
+/// Tracks the results of our reachability self-tests, and publishes them.
+pub(crate) struct ReachabilityMonitor {
+    /// The mutable part of this monitor.
+    inner: Mutex<MonitorInner>,
+    /// A receiver that we clone to make new [`ReachabilityEvents`].
+    receiver: postage::watch::Receiver<NetworkReachability>,
+}
+
+/// The mutable part of a [`ReachabilityMonitor`].
+struct MonitorInner {
+    /// Our history of self-test outcomes.
+    state: ReachabilityState,
+    /// Where we publish changes to our opinion of the network.
+    sender: postage::watch::Sender<NetworkReachability>,
+}
+
+impl ReachabilityMonitor {
+    /// Make a new monitor, which does not yet know anything.
+    pub(crate) fn new() -> Self {
+        let (sender, receiver) = postage::watch::channel();
+        ReachabilityMonitor {
+            inner: Mutex::new(MonitorInner {
+                state: ReachabilityState::default(),
+                sender,
+            }),
+            receiver,
+        }
+    }
+
+    /// Record the outcome of a self-test, and publish our new opinion if it has changed.
+    pub(crate) fn note(&self, outcome: SelfTestOutcome) {
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        let new = inner.state.note(outcome);
+        if *inner.sender.borrow() != new {
+            *inner.sender.borrow_mut() = new;
+        }
+    }
+
+    /// Forget our history, and publish that we don't know whether the network is reachable.
+    ///
+    /// Used when the self-test is disabled.
+    pub(crate) fn reset(&self) {
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        inner.state = ReachabilityState::default();
+        if *inner.sender.borrow() != NetworkReachability::Unknown {
+            *inner.sender.borrow_mut() = NetworkReachability::Unknown;
+        }
+    }
+
+    /// Return a new stream of changes to our opinion of the network.
+    pub(crate) fn events(&self) -> ReachabilityEvents {
+        ReachabilityEvents {
+            inner: self.receiver.clone(),
+        }
+    }
+}
+
+/// A stream of [`NetworkReachability`] values, as our opinion of the network changes.
+///
+/// The stream yields the current value first.
+/// If several changes happen before you read from the stream,
+/// you might only get the most recent one.
+#[derive(Clone, Educe)]
+#[educe(Debug)]
+pub struct ReachabilityEvents {
+    /// The `postage::watch::Receiver` that we're wrapping.
+    #[educe(Debug(method = "skip_fmt"))]
+    inner: postage::watch::Receiver<NetworkReachability>,
+}
+
+impl Stream for ReachabilityEvents {
+    type Item = NetworkReachability;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+impl ReachabilityEvents {
+    /// Return our current opinion of whether the network is reachable.
+    pub fn get(&self) -> NetworkReachability {
+        *self.inner.borrow()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use NetworkReachability as NR;
+    use SelfTestOutcome as O;
+
+    #[test]
+    fn state() {
+        let mut st = ReachabilityState::default();
+        assert_eq!(st.note(O::Success), NR::Up);
+        assert_eq!(st.note(O::RequestFailed), NR::Degraded);
+        assert_eq!(st.note(O::BuildFailed), NR::Degraded);
+        assert_eq!(st.note(O::BuildFailed), NR::Degraded);
+        assert_eq!(st.note(O::BuildFailed), NR::Down);
+        assert_eq!(st.note(O::BuildFailed), NR::Down);
+        // A circuit that works, even if the request doesn't, means we aren't down.
+        assert_eq!(st.note(O::RequestFailed), NR::Degraded);
+        assert_eq!(st.note(O::BuildFailed), NR::Degraded);
+        assert_eq!(st.note(O::Success), NR::Up);
+    }
+
+    #[test]
+    fn monitor() {
+        let mon = ReachabilityMonitor::new();
+        let events = mon.events();
+        assert_eq!(events.get(), NR::Unknown);
+        mon.note(O::Success);
+        assert_eq!(events.get(), NR::Up);
+        assert_eq!(mon.events().get(), NR::Up);
+        mon.reset();
+        assert_eq!(events.get(), NR::Unknown);
+        assert_eq!(NR::Degraded.to_string(), "degraded");
+    }
+}
@@ -0,0 +1,246 @@
+//! Code to retire circuits that a new network directory says we shouldn't use.
+//!
+//! When a new consensus arrives, a relay that some of our circuits use may
+//! have been removed from it, or an exit that some of our circuits use may have
+//! been flagged `BadExit`.  We stop giving such circuits out for new requests,
+//! and tell anybody who is listening.
+
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use educe::Educe;
+use futures::{Stream, StreamExt as _};
+use tor_basic_utils::skip_fmt;
+use tor_linkspec::HasRelayIds;
+use tor_netdir::NetDir;
+use tor_proto::circuit::UniqId;
+
+/// Why we retired a circuit after a change in the network directory.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, derive_more::Display)]
+#[non_exhaustive]
+pub enum RetireReason {
+    /// One of the relays in the circuit is no longer listed in the consensus.
+    #[display(fmt = "relay no longer listed")]
+    RelayUnlisted,
+    /// The circuit's exit relay may no longer be used to exit (for example,
+    /// because it has been flagged `BadExit`).
+    #[display(fmt = "exit no longer usable")]
+    ExitUnusable,
+}
+
+/// A circuit that we retired after a change in the network directory.
+///
+/// A retired circuit is no longer given out for new requests.
+/// If nothing was using it, it has been closed;
+/// otherwise, it stays open until its existing streams are done.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct RetiredCircuit {
+    /// The circuit's process-unique identifier.
+    pub circ_id: UniqId,
+    /// Why we retired it.
+    pub reason: RetireReason,
+    /// Whether we closed the circuit, because nothing was using it.
+    pub closed: bool,
+}
+
+/// Decide whether a circuit through `hops` should be retired, given `netdir`.
+///
+/// `is_exit` is true if the circuit was built for exit traffic, in which case
+/// the last hop must still be usable as an exit.
+///
+/// We don't look at the first hop: it may be a bridge, which the consensus
+/// doesn't list, and the guard manager keeps track of our guards' status.
+pub(crate) fn retire_reason<'h, T>(
+    hops: impl IntoIterator<Item = &'h T>,
+    is_exit: bool,
+    netdir: &NetDir,
+) -> Option<RetireReason>
+where
+    T: HasRelayIds + ?Sized + 'h,
+{
+    let hops: Vec<&T> = hops.into_iter().collect();
+
+    if hops
+        .iter()
+        .skip(1)
+        .any(|hop| netdir.ids_listed(*hop) == Some(false))
+    {
+        return Some(RetireReason::RelayUnlisted);
+    }
+
+    if is_exit && hops.len() > 1 {
+        let exit = hops.last().and_then(|hop| netdir.by_ids(*hop));
+        if let Some(exit) = exit {
+            if !exit.low_level_details().policies_allow_some_port() {
+                return Some(RetireReason::ExitUnusable);
+            }
+        }
+    }
+
+    None
+}
+
+/// Publishes the circuits that we retire, to any [`RetirementEvents`].
+pub(crate) struct RetirementMonitor {
+    /// Where we publish each batch of retired circuits.
+    sender: Mutex<postage::watch::Sender<Arc<Vec<RetiredCircuit>>>>,
+    /// A receiver that we clone to make new [`RetirementEvents`].
+    receiver: postage::watch::Receiver<Arc<Vec<RetiredCircuit>>>,
+}
+
+impl RetirementMonitor {
+    /// Make a new monitor, which has not yet published anything.
+    pub(crate) fn new() -> Self {
+        let (sender, receiver) = postage::watch::channel();
+        RetirementMonitor {
+            sender: Mutex::new(sender),
+            receiver,
+        }
+    }
+
+    /// Publish a batch of circuits that we have just retired.
+    ///
+    /// Does nothing if `retired` is empty.
+    pub(crate) fn publish(&self, retired: Vec<RetiredCircuit>) {
+        if retired.is_empty() {
+            return;
+        }
+        let sender = self.sender.lock().expect("poisoned lock");
+        *sender.borrow_mut() = Arc::new(retired);
+    }
+
+    /// Return a new stream of the circuits that we retire.
+    pub(crate) fn events(&self) -> RetirementEvents {
+        RetirementEvents {
+            inner: self.receiver.clone(),
+        }
+    }
+}
+
+/// A stream of the circuits that we retire after changes in the network directory.
+///
+/// Each item is the set of circuits that we retired because of a single
+/// new directory.
+/// If several batches are retired before you read from the stream,
+/// you might only get the most recent one.
+#[derive(Clone, Educe)]
+#[educe(Debug)]
+pub struct RetirementEvents {
+    /// The `postage::watch::Receiver` that we're wrapping.
+    #[educe(Debug(method = "skip_fmt"))]
+    inner: postage::watch::Receiver<Arc<Vec<RetiredCircuit>>>,
+}
+
+impl Stream for RetirementEvents {
+    type Item = Vec<RetiredCircuit>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match self.inner.poll_next_unpin(cx) {
+                // The initial value of the channel is empty: skip it.
+                Poll::Ready(Some(batch)) if batch.is_empty() => continue,
+                Poll::Ready(Some(batch)) => return Poll::Ready(Some(batch.to_vec())),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use tor_linkspec::RelayIds;
+    use tor_llcrypto::pk::{ed25519::Ed25519Identity, rsa::RsaIdentity};
+    use tor_netdir::testnet;
+    use tor_netdoc::doc::netstatus::RelayFlags;
+
+    /// Return the identities of relay `idx` in the test network.
+    fn ids(idx: u8) -> RelayIds {
+        RelayIds::builder()
+            .ed_identity(Ed25519Identity::from([idx; 32]))
+            .rsa_identity(RsaIdentity::from([idx; 20]))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn reasons() {
+        // Relay 5 is gone, and exit 35 is a BadExit.
+        let netdir = testnet::construct_custom_netdir(|idx, nb| {
+            nb.omit_rs = idx == 5;
+            if idx == 35 {
+                nb.rs.add_flags(RelayFlags::BAD_EXIT);
+            }
+        })
+        .unwrap()
+        .unwrap_if_sufficient()
+        .unwrap();
+
+        let reason = |hops: &[u8], is_exit| {
+            let hops: Vec<_> = hops.iter().map(|&i| ids(i)).collect();
+            retire_reason(&hops, is_exit, &netdir)
+        };
+
+        assert_eq!(reason(&[20, 1, 32], true), None);
+        assert_eq!(
+            reason(&[20, 5, 32], true),
+            Some(RetireReason::RelayUnlisted)
+        );
+        assert_eq!(reason(&[20, 1, 35], true), Some(RetireReason::ExitUnusable));
+        // A non-exit circuit doesn't care whether its last hop is a good exit.
+        assert_eq!(reason(&[20, 1, 35], false), None);
+        // Relay 5 is gone, but it's our first hop: that's the guard manager's business.
+        assert_eq!(reason(&[5, 1, 32], true), None);
+
+        // We can't tell whether a relay with only an unrecognized ed25519 identity is listed.
+        let unknown = RelayIds::builder()
+            .ed_identity(Ed25519Identity::from([99; 32]))
+            .build()
+            .unwrap();
+        assert_eq!(retire_reason(&[ids(20), unknown], false, &netdir), None);
+    }
+
+    #[test]
+    fn monitor() {
+        use futures::FutureExt as _;
+
+        let mon = RetirementMonitor::new();
+        let mut events = mon.events();
+        // Nothing yet.
+        assert!(events.next().now_or_never().is_none());
+
+        mon.publish(vec![]);
+        assert!(events.next().now_or_never().is_none());
+
+        let retired = RetiredCircuit {
+            circ_id: UniqId::new(1, 2),
+            reason: RetireReason::ExitUnusable,
+            closed: true,
+        };
+        mon.publish(vec![retired.clone()]);
+        assert_eq!(
+            events.next().now_or_never().unwrap().unwrap(),
+            vec![retired]
+        );
+        assert_eq!(
+            RetireReason::RelayUnlisted.to_string(),
+            "relay no longer listed"
+        );
+    }
+}
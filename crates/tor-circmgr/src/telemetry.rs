@@ -0,0 +1,294 @@
+//! Aggregated timing information about the circuits that we build.
+//!
+//! Every circuit that a [`CircMgr`](crate::CircMgr) builds reports how long
+//! each of its hops took to add, and how long the whole circuit took to build.
+//! We collect those times into [`LatencyHistogram`]s, so that an operator can
+//! see how quickly the network is responding to us.
+//! We also count how many circuits we built, and failed to build, for each
+//! [`CircPurpose`].
+//!
+//! (The timing of a single circuit is available from the circuit itself:
+//! see [`ClientCirc::hop_timings`](tor_proto::circuit::ClientCirc::hop_timings).)
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::CircPurpose;
+
+/// The upper bound of the first bucket in a [`LatencyHistogram`].
+const FIRST_BUCKET_BOUND: Duration = Duration::from_millis(50);
+
+/// The number of buckets in a [`LatencyHistogram`] that have an upper bound.
+///
+/// Each bucket's bound is twice that of the one before, so the last bounded
+/// bucket ends at 51.2 seconds: that's longer than we'd ever wait for a circuit.
+const N_BOUNDED_BUCKETS: usize = 11;
+
+/// A histogram of latencies.
+///
+/// Latencies are counted in buckets of exponentially increasing width:
+/// the first bucket holds latencies below 50 milliseconds, and each bucket
+/// after it ends at twice the latency of the one before.
+/// A final bucket holds every latency that is too large for the others.
+#[derive(Clone, Debug, Default)]
+pub struct LatencyHistogram {
+    /// The number of latencies in each bucket.
+    ///
+    /// The last entry is the overflow bucket.
+    buckets: [u64; N_BOUNDED_BUCKETS + 1],
+    /// The total of all the latencies that we have recorded.
+    total: Duration,
+}
+
+impl LatencyHistogram {
+    /// Add `latency` to this histogram.
+    pub fn record(&mut self, latency: Duration) {
+        let idx = bucket_bounds()
+            .position(|bound| latency < bound)
+            .unwrap_or(N_BOUNDED_BUCKETS);
+        self.buckets[idx] += 1;
+        self.total = self.total.saturating_add(latency);
+    }
+
+    /// Return the number of latencies in this histogram.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Return the total of all the latencies in this histogram.
+    pub fn sum(&self) -> Duration {
+        self.total
+    }
+
+    /// Return the mean of the latencies in this histogram,
+    /// or `None` if it is empty.
+    pub fn mean(&self) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let mean_nanos = self.total.as_nanos() / u128::from(count);
+        Some(Duration::from_nanos(
+            mean_nanos.try_into().unwrap_or(u64::MAX),
+        ))
+    }
+
+    /// Return an iterator over the buckets of this histogram, in order.
+    ///
+    /// Each item is the exclusive upper bound of a bucket, and the number of
+    /// latencies in that bucket.
+    /// The bound of the last bucket is `None`: it holds every latency that
+    /// is too large for the others.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        bucket_bounds()
+            .map(Some)
+            .chain(std::iter::once(None))
+            .zip(self.buckets.iter().copied())
+    }
+}
+
+/// Return an iterator over the upper bounds of the bounded buckets
+/// of a [`LatencyHistogram`].
+fn bucket_bounds() -> impl Iterator<Item = Duration> {
+    (0..N_BOUNDED_BUCKETS as u32).map(|i| FIRST_BUCKET_BOUND * (1 << i))
+}
+
+/// The number of circuits that we built, and failed to build, for some purpose.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BuildOutcomes {
+    /// The number of circuits that we built and could use.
+    succeeded: u64,
+    /// The number of circuits that we failed to build, or built but couldn't use.
+    failed: u64,
+}
+
+impl BuildOutcomes {
+    /// Return the number of circuits that we built and could use.
+    pub fn succeeded(&self) -> u64 {
+        self.succeeded
+    }
+
+    /// Return the number of circuits that we failed to build.
+    ///
+    /// This includes circuits that timed out, and circuits that we built
+    /// but couldn't use because the guard manager told us not to use
+    /// their guard.
+    pub fn failed(&self) -> u64 {
+        self.failed
+    }
+}
+
+/// Aggregated timing information about the circuits that we have built.
+///
+/// Returned by [`CircMgr::build_telemetry`](crate::CircMgr::build_telemetry).
+#[derive(Clone, Debug, Default)]
+pub struct CircBuildTelemetry {
+    /// For each hop position, how long it took to add a hop at that position.
+    hops: Vec<LatencyHistogram>,
+    /// How long it took to build each circuit that we finished building.
+    total: LatencyHistogram,
+    /// How many circuits we built, and failed to build, for each purpose.
+    ///
+    /// The key is `None` for onion service circuits, which don't have a
+    /// purpose until they are handed out.
+    outcomes: HashMap<Option<CircPurpose>, BuildOutcomes>,
+}
+
+impl CircBuildTelemetry {
+    /// Record that it took `latency` to add the hop at position `hop`
+    /// (counting from 0) to a circuit.
+    pub(crate) fn note_hop(&mut self, hop: usize, latency: Duration) {
+        if self.hops.len() <= hop {
+            self.hops.resize_with(hop + 1, Default::default);
+        }
+        self.hops[hop].record(latency);
+    }
+
+    /// Record that it took `latency` to build a circuit.
+    pub(crate) fn note_circuit(&mut self, latency: Duration) {
+        self.total.record(latency);
+    }
+
+    /// Record that we finished trying to build a circuit for `purpose`,
+    /// and whether we `succeeded`.
+    pub(crate) fn note_outcome(&mut self, purpose: Option<CircPurpose>, succeeded: bool) {
+        let outcomes = self.outcomes.entry(purpose).or_default();
+        if succeeded {
+            outcomes.succeeded += 1;
+        } else {
+            outcomes.failed += 1;
+        }
+    }
+
+    /// Return the histogram of the time it took to add the hop at position `hop`
+    /// (counting from 0) to our circuits, if we have added any hops there.
+    ///
+    /// For the first hop, this time includes the time it took to get a channel
+    /// to the first relay, as well as the CREATE handshake.
+    /// For every later hop, it is the time from adding the previous hop until
+    /// adding this one.
+    pub fn hop(&self, hop: usize) -> Option<&LatencyHistogram> {
+        self.hops.get(hop)
+    }
+
+    /// Return the number of hop positions for which we have recorded any latencies.
+    pub fn n_hops(&self) -> usize {
+        self.hops.len()
+    }
+
+    /// Return the histogram of the time it took to build each of our circuits,
+    /// from launch until the last hop was added.
+    ///
+    /// Only circuits that we finished building are counted here.
+    pub fn total(&self) -> &LatencyHistogram {
+        &self.total
+    }
+
+    /// Return how many circuits we built, and failed to build, for `purpose`.
+    ///
+    /// Pass `None` to count the circuits that we built for onion services:
+    /// those don't have a purpose until they are handed out.
+    ///
+    /// Only circuits that the circuit manager planned itself are counted here,
+    /// not circuits built along paths that the caller chose.
+    pub fn outcomes(&self, purpose: Option<CircPurpose>) -> BuildOutcomes {
+        self.outcomes.get(&purpose).copied().unwrap_or_default()
+    }
+
+    /// Return an iterator over every purpose for which we have tried to build
+    /// any circuits, and how many we built and failed to build.
+    ///
+    /// See [`outcomes`](CircBuildTelemetry::outcomes).
+    pub fn outcomes_by_purpose(
+        &self,
+    ) -> impl Iterator<Item = (Option<CircPurpose>, BuildOutcomes)> + '_ {
+        self.outcomes.iter().map(|(p, o)| (*p, *o))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+
+    #[test]
+    fn histogram() {
+        let mut h = LatencyHistogram::default();
+        assert_eq!(h.count(), 0);
+        assert_eq!(h.mean(), None);
+
+        let ms = Duration::from_millis;
+        for latency in [ms(10), ms(49), ms(50), ms(150), ms(300), ms(100_000)] {
+            h.record(latency);
+        }
+        assert_eq!(h.count(), 6);
+        assert_eq!(h.sum(), ms(100_559));
+        // (10 + 49 + 50 + 150 + 300 + 100000) / 6 ms, rounded down to the nanosecond.
+        assert_eq!(h.mean(), Some(Duration::from_nanos(16_759_833_333)));
+
+        let buckets: Vec<_> = h.buckets().collect();
+        assert_eq!(buckets.len(), N_BOUNDED_BUCKETS + 1);
+        assert_eq!(buckets[0], (Some(ms(50)), 2));
+        assert_eq!(buckets[1], (Some(ms(100)), 1));
+        assert_eq!(buckets[2], (Some(ms(200)), 1));
+        assert_eq!(buckets[3], (Some(ms(400)), 1));
+        assert_eq!(buckets[N_BOUNDED_BUCKETS - 1], (Some(ms(51_200)), 0));
+        assert_eq!(buckets[N_BOUNDED_BUCKETS], (None, 1));
+    }
+
+    #[test]
+    fn telemetry() {
+        let ms = Duration::from_millis;
+        let mut t = CircBuildTelemetry::default();
+        assert_eq!(t.n_hops(), 0);
+        assert!(t.hop(0).is_none());
+
+        t.note_hop(0, ms(300));
+        t.note_hop(1, ms(120));
+        t.note_hop(2, ms(80));
+        t.note_circuit(ms(500));
+        t.note_hop(0, ms(20));
+
+        assert_eq!(t.n_hops(), 3);
+        assert_eq!(t.hop(0).unwrap().count(), 2);
+        assert_eq!(t.hop(0).unwrap().mean(), Some(ms(160)));
+        assert_eq!(t.hop(2).unwrap().count(), 1);
+        assert!(t.hop(3).is_none());
+        assert_eq!(t.total().count(), 1);
+        assert_eq!(t.total().mean(), Some(ms(500)));
+    }
+
+    #[test]
+    fn outcomes() {
+        let mut t = CircBuildTelemetry::default();
+        assert_eq!(
+            t.outcomes(Some(CircPurpose::Exit)),
+            BuildOutcomes::default()
+        );
+
+        t.note_outcome(Some(CircPurpose::Exit), true);
+        t.note_outcome(Some(CircPurpose::Exit), true);
+        t.note_outcome(Some(CircPurpose::Exit), false);
+        t.note_outcome(None, false);
+
+        let exit = t.outcomes(Some(CircPurpose::Exit));
+        assert_eq!((exit.succeeded(), exit.failed()), (2, 1));
+        let hs = t.outcomes(None);
+        assert_eq!((hs.succeeded(), hs.failed()), (0, 1));
+        assert_eq!(t.outcomes(Some(CircPurpose::Directory)).failed(), 0);
+        assert_eq!(t.outcomes_by_purpose().count(), 2);
+    }
+}
@@ -116,15 +116,26 @@ impl Estimator {
 
     /// Store any state associated with this timeout estimator into `storage`.
     pub(crate) fn save_state(&self, storage: &TimeoutStateHandle) -> crate::Result<()> {
-        let state = {
-            let mut inner = self.inner.lock().expect("Timeout estimator lock poisoned.");
-            inner.build_state()
-        };
-        if let Some(state) = state {
+        if let Some(state) = self.build_state() {
             storage.store(&state)?;
         }
         Ok(())
     }
+
+    /// Store all the state associated with this timeout estimator into
+    /// `storage` as a snapshot, and clear its journal.
+    pub(crate) fn compact_state(&self, storage: &TimeoutStateHandle) -> crate::Result<()> {
+        if let Some(state) = self.build_state() {
+            storage.compact(&state)?;
+        }
+        Ok(())
+    }
+
+    /// Return the state that this timeout estimator would store, if any.
+    fn build_state(&self) -> Option<ParetoTimeoutState> {
+        let mut inner = self.inner.lock().expect("Timeout estimator lock poisoned.");
+        inner.build_state()
+    }
 }
 
 /// Try to construct a new boxed TimeoutEstimator based on the contents of
@@ -176,7 +187,7 @@ mod test {
         // Construct an estimator with write access to a state manager.
         let storage = tor_persist::TestingStateMgr::new();
         assert!(storage.try_lock().unwrap().held());
-        let handle = storage.clone().create_handle("paretorama");
+        let handle = storage.clone().create_journaled_handle("paretorama", 10);
 
         let est = Estimator::from_storage(&handle);
         assert!(est.learning_timeouts());
@@ -186,7 +197,7 @@ mod test {
         // but which only gets read-only access
         let storage2 = storage.new_manager();
         assert!(!storage2.try_lock().unwrap().held());
-        let handle2 = storage2.clone().create_handle("paretorama");
+        let handle2 = storage2.clone().create_journaled_handle("paretorama", 10);
 
         let est2 = Estimator::from_storage(&handle2);
         assert!(!est2.learning_timeouts());
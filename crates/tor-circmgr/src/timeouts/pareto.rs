@@ -497,6 +497,67 @@ impl ParetoTimeoutState {
     }
 }
 
+/// A change to a [`ParetoTimeoutState`], as stored in the journal.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct ParetoTimeoutDelta {
+    /// The new version field.
+    version: usize,
+    /// The histogram buckets whose counts have changed, with their new counts.
+    ///
+    /// A count of zero means that the bucket has been removed.
+    changed_buckets: Vec<(MsecDuration, u16)>,
+    /// The new timeout estimate.
+    current_timeout: Option<MsecDuration>,
+}
+
+impl tor_persist::Journaled for ParetoTimeoutState {
+    type Delta = ParetoTimeoutDelta;
+
+    fn apply(&mut self, delta: ParetoTimeoutDelta) {
+        // Each delta sets buckets to new counts, rather than adjusting them,
+        // so replaying a delta that is already reflected here does nothing.
+        let mut histogram: BTreeMap<_, _> = self.histogram.drain(..).collect();
+        for (bucket, count) in delta.changed_buckets {
+            if count == 0 {
+                histogram.remove(&bucket);
+            } else {
+                histogram.insert(bucket, count);
+            }
+        }
+        self.histogram = histogram.into_iter().collect();
+        self.version = delta.version;
+        self.current_timeout = delta.current_timeout;
+    }
+
+    fn delta_to(&self, newer: &Self) -> Option<ParetoTimeoutDelta> {
+        // We don't record changes to `unknown_fields`: the states we build
+        // never have any, so they only ever go away, when we next compact.
+        let old: BTreeMap<_, _> = self.histogram.iter().copied().collect();
+        let new: BTreeMap<_, _> = newer.histogram.iter().copied().collect();
+        let removed = old
+            .keys()
+            .filter(|bucket| !new.contains_key(bucket))
+            .map(|bucket| (*bucket, 0));
+        let changed = new
+            .iter()
+            .filter(|(bucket, count)| old.get(bucket) != Some(count))
+            .map(|(bucket, count)| (*bucket, *count));
+        let changed_buckets: Vec<_> = removed.chain(changed).collect();
+
+        if changed_buckets.is_empty()
+            && self.version == newer.version
+            && self.current_timeout == newer.current_timeout
+        {
+            return None;
+        }
+        Some(ParetoTimeoutDelta {
+            version: newer.version,
+            changed_buckets,
+            current_timeout: newer.current_timeout,
+        })
+    }
+}
+
 impl ParetoTimeoutEstimator {
     /// Construct a new ParetoTimeoutEstimator from the provided history
     /// object.
@@ -946,5 +1007,54 @@ mod test {
         assert!((ms1 - ms2).abs() < 50);
     }
 
+    #[test]
+    fn state_journal() {
+        use tor_persist::Journaled as _;
+
+        /// Return the parts of `state` that a delta can change.
+        fn contents(
+            state: &ParetoTimeoutState,
+        ) -> (usize, Vec<(MsecDuration, u16)>, Option<MsecDuration>) {
+            (
+                state.version,
+                state.histogram.clone(),
+                state.current_timeout,
+            )
+        }
+
+        let mut est = ParetoTimeoutEstimator::default();
+        let mut rng = testing_rng();
+        let mut states = vec![ParetoTimeoutState::default()];
+        for _ in 0..20 {
+            for _ in 0..100 {
+                let d = Duration::from_millis(rng.gen_range_checked(10..3_000).unwrap());
+                est.note_hop_completed(2, d, true);
+            }
+            states.push(est.build_state().unwrap());
+        }
+        // Nothing has changed since the last state.
+        let last = states.last().unwrap();
+        assert!(last.delta_to(&est.build_state().unwrap()).is_none());
+
+        let deltas: Vec<_> = states
+            .windows(2)
+            .map(|w| w[0].delta_to(&w[1]).unwrap())
+            .collect();
+        for (i, delta) in deltas.iter().enumerate() {
+            let mut state = states[i].clone();
+            state.apply(delta.clone());
+            assert_eq!(contents(&state), contents(&states[i + 1]));
+        }
+
+        // Replaying any suffix of the journal over the final state is a no-op.
+        for start in 0..=deltas.len() {
+            let mut state = last.clone();
+            for delta in &deltas[start..] {
+                state.apply(delta.clone());
+            }
+            assert_eq!(contents(&state), contents(last));
+        }
+    }
+
     // TODO: add tests from Tor.
 }
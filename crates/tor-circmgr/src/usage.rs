@@ -9,6 +9,7 @@ use tracing::trace;
 use void::Void;
 
 use crate::path::{dirpath::DirPathBuilder, exitpath::ExitPathBuilder, TorPath};
+use crate::CircPurpose;
 use tor_chanmgr::ChannelUsage;
 #[cfg(feature = "geoip")]
 use tor_error::internal;
@@ -212,6 +213,24 @@ pub(crate) enum SupportedCircUsage {
 }
 
 impl TargetCircUsage {
+    /// Return the purpose of the circuit we want, if it has one yet.
+    ///
+    /// Onion service circuits don't get a purpose until the
+    /// `HsCircPool` hands them out.
+    pub(crate) fn purpose(&self) -> Option<CircPurpose> {
+        match self {
+            TargetCircUsage::Dir => Some(CircPurpose::Directory),
+            #[cfg(feature = "specific-relay")]
+            TargetCircUsage::DirSpecificTarget(_) => Some(CircPurpose::Directory),
+            TargetCircUsage::Exit { .. } | TargetCircUsage::Preemptive { .. } => {
+                Some(CircPurpose::Exit)
+            }
+            TargetCircUsage::TimeoutTesting => Some(CircPurpose::Measurement),
+            #[cfg(feature = "hs-common")]
+            TargetCircUsage::HsCircBase { .. } => None,
+        }
+    }
+
     /// Construct path for a given circuit purpose; return it and the
     /// usage that it _actually_ supports.
     pub(crate) fn build_path<'a, R: Rng, RT: Runtime>(
@@ -359,6 +378,31 @@ impl TargetCircUsage {
     }
 }
 
+impl SupportedCircUsage {
+    /// Return the purpose for which this circuit was built, if it has one yet.
+    pub(crate) fn purpose(&self) -> Option<CircPurpose> {
+        match self {
+            SupportedCircUsage::Dir => Some(CircPurpose::Directory),
+            #[cfg(feature = "specific-relay")]
+            SupportedCircUsage::DirSpecificTarget(_) => Some(CircPurpose::Directory),
+            SupportedCircUsage::Exit { .. } => Some(CircPurpose::Exit),
+            SupportedCircUsage::NoUsage => Some(CircPurpose::Measurement),
+            #[cfg(feature = "hs-common")]
+            SupportedCircUsage::HsOnly => None,
+        }
+    }
+
+    /// Return true if our purpose lets us be reused for `target`.
+    ///
+    /// This is checked before anything else about `target`.
+    fn purpose_permits(&self, target: &TargetCircUsage) -> bool {
+        match (self.purpose(), target.purpose()) {
+            (Some(have), Some(want)) => have.may_be_reused_for(want),
+            (_, _) => false,
+        }
+    }
+}
+
 /// Return true if `a` and `b` count as the same target for the purpose of
 /// comparing `DirSpecificTarget` values.
 #[cfg(feature = "specific-relay")]
@@ -374,6 +418,9 @@ impl crate::mgr::AbstractSpec for SupportedCircUsage {
 
     fn supports(&self, target: &TargetCircUsage) -> bool {
         use SupportedCircUsage::*;
+        if !self.purpose_permits(target) {
+            return false;
+        }
         match (self, target) {
             (Dir, TargetCircUsage::Dir) => true,
             (
@@ -756,6 +803,57 @@ pub(crate) mod test {
         assert!(supp_none.supports(&targ_testing));
     }
 
+    #[test]
+    fn purposes() {
+        use crate::mgr::AbstractSpec;
+        let policy = ExitPolicy {
+            v4: Arc::new("accept 80,443".parse().unwrap()),
+            v6: Arc::new("reject 1-65535".parse().unwrap()),
+        };
+        let supp_exit = SupportedCircUsage::Exit {
+            policy,
+            isolation: None,
+            country_code: None,
+            all_relays_stable: true,
+        };
+        let targ_exit = TargetCircUsage::Exit {
+            ports: vec![],
+            isolation: StreamIsolationBuilder::new()
+                .owner_token(IsolationToken::new())
+                .build()
+                .unwrap(),
+            country_code: None,
+            require_stability: false,
+        };
+
+        assert_eq!(
+            SupportedCircUsage::Dir.purpose(),
+            Some(CircPurpose::Directory)
+        );
+        assert_eq!(supp_exit.purpose(), Some(CircPurpose::Exit));
+        assert_eq!(
+            SupportedCircUsage::NoUsage.purpose(),
+            Some(CircPurpose::Measurement)
+        );
+        assert_eq!(TargetCircUsage::Dir.purpose(), Some(CircPurpose::Directory));
+        assert_eq!(targ_exit.purpose(), Some(CircPurpose::Exit));
+        assert_eq!(
+            TargetCircUsage::TimeoutTesting.purpose(),
+            Some(CircPurpose::Measurement)
+        );
+
+        // A circuit is never reused for a different purpose...
+        assert!(!SupportedCircUsage::Dir.supports(&targ_exit));
+        assert!(!supp_exit.supports(&TargetCircUsage::Dir));
+        assert!(!SupportedCircUsage::NoUsage.supports(&targ_exit));
+        assert!(SupportedCircUsage::Dir
+            .clone()
+            .restrict_mut(&targ_exit)
+            .is_err());
+        // ...except that exit circuits can be used for measurement.
+        assert!(supp_exit.supports(&TargetCircUsage::TimeoutTesting));
+    }
+
     #[test]
     fn restrict_mut() {
         use crate::mgr::AbstractSpec;
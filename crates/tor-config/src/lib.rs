@@ -49,6 +49,7 @@ pub mod load;
 mod misc;
 mod mut_cfg;
 mod path;
+mod secret;
 pub mod sources;
 
 #[doc(hidden)]
@@ -70,6 +71,7 @@ pub use load::{resolve, resolve_ignore_warnings, resolve_return_results};
 pub use misc::*;
 pub use mut_cfg::MutCfg;
 pub use path::{CfgPath, CfgPathError};
+pub use secret::{CfgSecret, CfgSecretError};
 pub use sources::{ConfigurationSource, ConfigurationSources};
 
 use itertools::Itertools;
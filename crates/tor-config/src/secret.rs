@@ -0,0 +1,272 @@
+//! A configuration type for secrets that are kept outside the configuration file
+//!
+//! Some configuration values (passphrases, authentication tokens, and so on)
+//! are sensitive enough that users may not want to write them in a
+//! configuration file.  A [`CfgSecret`] lets the configuration say where to
+//! find such a value instead: in an environment variable, or in the output
+//! of a command.
+
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use tor_error::{ErrorKind, HasKind};
+
+/// A reference, in a configuration file, to a secret value stored elsewhere.
+///
+/// In a configuration file, this is written as one of:
+///   * `{ env = "NAME" }`: the value of the environment variable `NAME`.
+///   * `{ command = ["program", "arg", ...] }`: the standard output of running
+///     `program` with the given arguments (without a shell),
+///     with any trailing newlines removed.
+///     The command inherits our standard error, but not our standard input.
+///
+/// The secret itself is never stored in this type:
+/// it is looked up every time [`resolve`](CfgSecret::resolve) is called,
+/// and returned in a buffer that is erased when it is dropped.
+/// It is therefore safe to log a `CfgSecret`, or to compare it with another.
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(transparent)]
+pub struct CfgSecret(SecretInner);
+
+/// Inner implementation of CfgSecret
+///
+/// `SecretInner` exists to avoid making the variants part of the public Rust API
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(untagged)]
+enum SecretInner {
+    /// A secret taken from an environment variable.
+    Env {
+        /// The name of the variable.
+        env: String,
+    },
+    /// A secret taken from the output of a command.
+    Command {
+        /// The program to run, followed by its arguments.
+        command: Vec<String>,
+    },
+}
+
+/// An error that has occurred while looking up the value of a [`CfgSecret`].
+#[derive(thiserror::Error, Debug, Clone)]
+#[non_exhaustive]
+pub enum CfgSecretError {
+    /// The environment variable was not set.
+    #[error("Environment variable {0} is not set")]
+    EnvNotSet(String),
+    /// The environment variable's value was not valid UTF-8.
+    #[error("Value of environment variable {0} is not valid UTF-8")]
+    EnvNotUtf8(String),
+    /// The command was empty.
+    #[error("Secret command is empty")]
+    EmptyCommand,
+    /// We couldn't run the command.
+    #[error("Unable to run secret command {command:?}")]
+    CommandFailed {
+        /// The program that we tried to run.
+        command: String,
+        /// The error that we got.
+        #[source]
+        error: Arc<std::io::Error>,
+    },
+    /// The command ran, but reported failure.
+    #[error("Secret command {command:?} failed: {status}")]
+    CommandExit {
+        /// The program that we ran.
+        command: String,
+        /// The way in which it exited.
+        status: std::process::ExitStatus,
+    },
+    /// The command's output was not valid UTF-8.
+    #[error("Output of secret command {0:?} is not valid UTF-8")]
+    CommandNotUtf8(String),
+    /// The secret was empty.
+    #[error("Secret from {0} is empty")]
+    Empty(String),
+}
+
+impl HasKind for CfgSecretError {
+    fn kind(&self) -> ErrorKind {
+        use CfgSecretError as E;
+        use ErrorKind as EK;
+        match self {
+            E::EnvNotSet(_)
+            | E::EnvNotUtf8(_)
+            | E::EmptyCommand
+            | E::CommandNotUtf8(_)
+            | E::Empty(_) => EK::InvalidConfig,
+            E::CommandFailed { .. } | E::CommandExit { .. } => EK::ExternalToolFailed,
+        }
+    }
+}
+
+impl CfgSecret {
+    /// Return a `CfgSecret` that reads the environment variable `name`.
+    pub fn from_env(name: impl Into<String>) -> Self {
+        CfgSecret(SecretInner::Env { env: name.into() })
+    }
+
+    /// Return a `CfgSecret` that runs `command` (a program, followed by its arguments)
+    /// and reads its output.
+    pub fn from_command<I, S>(command: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        CfgSecret(SecretInner::Command {
+            command: command.into_iter().map(Into::into).collect(),
+        })
+    }
+
+    /// Look up the value of this secret.
+    ///
+    /// If the secret comes from a command, this runs the command,
+    /// and waits for it to finish.
+    ///
+    /// A secret that is empty, or only whitespace, is an error.
+    pub fn resolve(&self) -> Result<Zeroizing<String>, CfgSecretError> {
+        self.resolve_with_env(|name| std::env::var(name))
+    }
+
+    /// As `resolve`, but look up environment variables with `getenv`.
+    ///
+    /// This lets tests avoid changing our real environment,
+    /// which would race with anything else that reads it.
+    fn resolve_with_env(
+        &self,
+        getenv: impl FnOnce(&str) -> Result<String, std::env::VarError>,
+    ) -> Result<Zeroizing<String>, CfgSecretError> {
+        let secret = match &self.0 {
+            SecretInner::Env { env } => resolve_env(env, getenv)?,
+            SecretInner::Command { command } => resolve_command(command)?,
+        };
+        if secret.trim().is_empty() {
+            return Err(CfgSecretError::Empty(self.to_string()));
+        }
+        Ok(secret)
+    }
+}
+
+impl std::fmt::Display for CfgSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            SecretInner::Env { env } => write!(f, "environment variable {env}"),
+            SecretInner::Command { command } => write!(f, "command {command:?}"),
+        }
+    }
+}
+
+/// Return the value of the environment variable `name`, as looked up by `getenv`.
+fn resolve_env(
+    name: &str,
+    getenv: impl FnOnce(&str) -> Result<String, std::env::VarError>,
+) -> Result<Zeroizing<String>, CfgSecretError> {
+    match getenv(name) {
+        Ok(value) => Ok(Zeroizing::new(value)),
+        Err(std::env::VarError::NotPresent) => Err(CfgSecretError::EnvNotSet(name.to_owned())),
+        Err(std::env::VarError::NotUnicode(_)) => Err(CfgSecretError::EnvNotUtf8(name.to_owned())),
+    }
+}
+
+/// Run `command`, and return its output, without any trailing newlines.
+fn resolve_command(command: &[String]) -> Result<Zeroizing<String>, CfgSecretError> {
+    let (program, args) = command.split_first().ok_or(CfgSecretError::EmptyCommand)?;
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| CfgSecretError::CommandFailed {
+            command: program.clone(),
+            error: Arc::new(e),
+        })?;
+    let stdout = Zeroizing::new(output.stdout);
+    if !output.status.success() {
+        return Err(CfgSecretError::CommandExit {
+            command: program.clone(),
+            status: output.status,
+        });
+    }
+    let stdout = std::str::from_utf8(&stdout)
+        .map_err(|_| CfgSecretError::CommandNotUtf8(program.clone()))?;
+    Ok(Zeroizing::new(
+        stdout.trim_end_matches(['\r', '\n']).to_owned(),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+
+    #[derive(Deserialize, Debug)]
+    struct Test {
+        secret: CfgSecret,
+    }
+
+    #[test]
+    fn deserialize() {
+        let t: Test = toml::from_str(r#"secret = { env = "ARTI_TEST_SECRET" }"#).unwrap();
+        assert_eq!(t.secret, CfgSecret::from_env("ARTI_TEST_SECRET"));
+
+        let t: Test = toml::from_str(r#"secret = { command = ["pass", "show", "arti"] }"#).unwrap();
+        assert_eq!(t.secret, CfgSecret::from_command(["pass", "show", "arti"]));
+
+        // Literal secrets are deliberately not supported.
+        assert!(toml::from_str::<Test>(r#"secret = "hunter2""#).is_err());
+    }
+
+    #[test]
+    fn env() {
+        use std::env::VarError;
+        /// A fake environment.
+        fn getenv(name: &str) -> Result<String, VarError> {
+            match name {
+                "SET" => Ok("swordfish".into()),
+                "EMPTY" => Ok("".into()),
+                "BLANK" => Ok(" \n".into()),
+                _ => Err(VarError::NotPresent),
+            }
+        }
+        let resolve = |name| CfgSecret::from_env(name).resolve_with_env(getenv);
+
+        assert_eq!(resolve("SET").unwrap().as_str(), "swordfish");
+        assert!(matches!(
+            resolve("UNSET"),
+            Err(CfgSecretError::EnvNotSet(_))
+        ));
+        assert!(matches!(resolve("EMPTY"), Err(CfgSecretError::Empty(_))));
+        assert!(matches!(resolve("BLANK"), Err(CfgSecretError::Empty(_))));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn command() {
+        let secret = CfgSecret::from_command(["sh", "-c", "printf 'correct horse\\n\\n'"]);
+        assert_eq!(secret.resolve().unwrap().as_str(), "correct horse");
+
+        let e = CfgSecret::from_command(["sh", "-c", "echo oops; exit 3"]).resolve();
+        assert!(matches!(e, Err(CfgSecretError::CommandExit { .. })));
+
+        let e = CfgSecret::from_command(["/nonexistent/arti-test-program"]).resolve();
+        assert!(matches!(e, Err(CfgSecretError::CommandFailed { .. })));
+
+        let e = CfgSecret::from_command(Vec::<String>::new()).resolve();
+        assert!(matches!(e, Err(CfgSecretError::EmptyCommand)));
+    }
+}
@@ -3,7 +3,7 @@
 use std::sync::Arc;
 
 use thiserror::Error;
-use tor_error::{Bug, ErrorKind, HasKind};
+use tor_error::{Bug, ErrorKind, HasKind, HasRetryTime, RetryTime};
 use tor_linkspec::OwnedChanTarget;
 use tor_rtcompat::TimeoutError;
 
@@ -69,6 +69,16 @@ pub enum RequestError {
     #[error("response too long; gave up after {0} bytes")]
     ResponseTooLong(usize),
 
+    /// Received a compressed response that decompressed to a suspiciously
+    /// large size, relative to its compressed size.
+    #[error("response looks like a compression bomb: {uncompressed} bytes from {compressed}")]
+    CompressionBomb {
+        /// The number of compressed bytes that we had read.
+        compressed: usize,
+        /// The number of bytes that they had decompressed to.
+        uncompressed: usize,
+    },
+
     /// Data received was not UTF-8 encoded.
     #[error("Couldn't decode data as UTF-8.")]
     Utf8Encoding(#[from] std::string::FromUtf8Error),
@@ -181,6 +191,7 @@ impl HasKind for RequestError {
             E::DirTimeout => EK::TorNetworkTimeout,
             E::TruncatedHeaders => EK::TorProtocolViolation,
             E::ResponseTooLong(_) => EK::TorProtocolViolation,
+            E::CompressionBomb { .. } => EK::TorProtocolViolation,
             E::Utf8Encoding(_) => EK::TorProtocolViolation,
             // TODO: it would be good to get more information out of the IoError
             // in this case, but that would require a bunch of gnarly
@@ -213,3 +224,50 @@ impl HasKind for Error {
         }
     }
 }
+
+impl HasRetryTime for RequestError {
+    fn retry_time(&self) -> RetryTime {
+        use RequestError as E;
+        match self {
+            // We built a request that can't be sent: that is a bug in our own
+            // code, and sending the same request again will fail the same way.
+            E::HttpError(_) | E::EmptyRequest => RetryTime::Never,
+
+            // Our clock is too far off for the answer to be useful.  This won't
+            // go away by itself, but somebody may fix the clock at any time.
+            E::TooMuchClockSkew => RetryTime::AfterWaiting,
+
+            // Everything else is a problem with this particular directory
+            // cache or with the network path to it.  Trying again (probably
+            // elsewhere) may well succeed.
+            E::DirTimeout
+            | E::TruncatedHeaders
+            | E::ResponseTooLong(_)
+            | E::CompressionBomb { .. }
+            | E::Utf8Encoding(_)
+            | E::IoError(_)
+            | E::Proto(_)
+            | E::HttparseError(_)
+            | E::ContentEncoding(_)
+            | E::HttpStatus(_, _) => RetryTime::AfterWaiting,
+        }
+    }
+}
+
+impl HasRetryTime for RequestFailedError {
+    fn retry_time(&self) -> RetryTime {
+        self.error.retry_time()
+    }
+}
+
+impl HasRetryTime for Error {
+    fn retry_time(&self) -> RetryTime {
+        use Error as E;
+        match self {
+            E::CircMgr(e) => e.retry_time(),
+            E::RequestFailed(e) => e.retry_time(),
+            // An internal error won't fix itself if we try again.
+            E::Bug(_) => RetryTime::Never,
+        }
+    }
+}
@@ -68,12 +68,13 @@ use futures::io::{
 };
 use futures::FutureExt;
 use memchr::memchr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use tracing::info;
 
 pub use err::{Error, RequestError, RequestFailedError};
-pub use response::{DirResponse, SourceInfo};
+pub use response::{DirResponse, PartialDownload, SourceInfo};
 
 /// Type for results returned in this crate.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -221,6 +222,8 @@ where
     let partial_ok = req.partial_response_body_ok();
     let maxlen = req.max_response_len();
     let anonymized = req.anonymized();
+    let resumable = req.resumable();
+    let resume = req.resume_prefix().cloned();
     let req = req.make_request().map_err(wrap_err)?;
     let encoded = util::encode_request(&req);
 
@@ -241,7 +244,16 @@ where
     // Handle the response
     // TODO: should there be a separate timeout here?
     let header = read_headers(&mut buffered).await.map_err(wrap_err)?;
-    if header.status != Some(200) {
+    // A 206 means that the cache honored our Range header: the body is the
+    // rest of the document that we already have the start of.  We can only
+    // use it if it has the same encoding as the start.
+    let resume = match (header.status, resume) {
+        (Some(206), Some(prefix)) if prefix.has_encoding(header.encoding.as_deref()) => {
+            Some(prefix)
+        }
+        _ => None,
+    };
+    if header.status != Some(200) && resume.is_none() {
         return Ok(DirResponse::new(
             header.status.unwrap_or(0),
             header.status_message,
@@ -251,11 +263,27 @@ where
         ));
     }
 
+    if resumable {
+        return read_resumable(
+            runtime,
+            buffered,
+            resume,
+            header.encoding,
+            anonymized,
+            maxlen,
+            source.clone(),
+        )
+        .await
+        .map_err(wrap_err);
+    }
+
+    let n_compressed = Arc::new(AtomicUsize::new(0));
+    let counted = util::CountingReader::new(buffered, Arc::clone(&n_compressed));
     let mut decoder =
-        get_decoder(buffered, header.encoding.as_deref(), anonymized).map_err(wrap_err)?;
+        get_decoder(counted, header.encoding.as_deref(), anonymized).map_err(wrap_err)?;
 
     let mut result = Vec::new();
-    let ok = read_and_decompress(runtime, &mut decoder, maxlen, &mut result).await;
+    let ok = read_and_decompress(runtime, &mut decoder, maxlen, &n_compressed, &mut result).await;
 
     let ok = match (partial_ok, ok, result.len()) {
         (true, Err(e), n) if n > 0 => {
@@ -271,6 +299,82 @@ where
     Ok(DirResponse::new(200, None, ok.err(), result, source))
 }
 
+/// Helper for `send_request`: read the body of a response to a resumable
+/// request from `stream`, and then decompress it.
+///
+/// We read the whole raw body before decompressing it, so that if the
+/// download is interrupted, we can return what we got as a
+/// [`PartialDownload`].  If `resume` is present, the body is the rest of the
+/// document that it is the start of.
+async fn read_resumable<S, SP>(
+    runtime: &SP,
+    stream: S,
+    resume: Option<PartialDownload>,
+    encoding: Option<String>,
+    anonymized: AnonymizedRequest,
+    maxlen: usize,
+    source: Option<SourceInfo>,
+) -> RequestResult<DirResponse>
+where
+    S: AsyncBufRead + Unpin + Send,
+    SP: SleepProvider,
+{
+    // Make sure that we could decode this before we spend time downloading it.
+    let _ = get_decoder(&b""[..], encoding.as_deref(), anonymized)?;
+
+    let mut raw = resume.map(|p| p.body().to_vec()).unwrap_or_default();
+    let mut rest = Vec::new();
+    // (We aren't decompressing yet, so this is just to keep
+    // `read_and_decompress` from thinking that we got a compression bomb.)
+    let n_read = Arc::new(AtomicUsize::new(0));
+    let counted = util::CountingReader::new(stream, Arc::clone(&n_read));
+    let ok = read_and_decompress(
+        runtime,
+        counted,
+        maxlen.saturating_sub(raw.len()),
+        &n_read,
+        &mut rest,
+    )
+    .await;
+    raw.append(&mut rest);
+
+    if let Err(e) = ok {
+        if raw.is_empty() {
+            return Err(e);
+        }
+        let partial = PartialDownload::new(encoding, raw);
+        return Ok(
+            DirResponse::new(200, None, Some(e), vec![], source).with_partial_download(partial)
+        );
+    }
+
+    let n_compressed = Arc::new(AtomicUsize::new(0));
+    let counted = util::CountingReader::new(
+        futures::io::Cursor::new(&raw[..]),
+        Arc::clone(&n_compressed),
+    );
+    let mut decoder = get_decoder(counted, encoding.as_deref(), anonymized)?;
+    let mut result = Vec::new();
+    let ok = read_and_decompress(runtime, &mut decoder, maxlen, &n_compressed, &mut result).await;
+    drop(decoder);
+    match ok {
+        Ok(()) => Ok(DirResponse::new(200, None, None, result, source)),
+        // The compressed body stopped early: the cache closed the stream
+        // before it was done.
+        Err(RequestError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            let partial = PartialDownload::new(encoding, raw);
+            Ok(
+                DirResponse::new(200, None, Some(RequestError::IoError(e)), result, source)
+                    .with_partial_download(partial),
+            )
+        }
+        // Otherwise, what we have is garbage: perhaps the start of one
+        // document, and the rest of another.  So we don't return a
+        // `PartialDownload`, and our caller should start from scratch.
+        Err(e) => Ok(DirResponse::new(200, None, Some(e), result, source)),
+    }
+}
+
 /// Read and parse HTTP/1 headers from `stream`.
 async fn read_headers<S>(stream: &mut S) -> RequestResult<HeaderStatus>
 where
@@ -303,7 +407,7 @@ where
                 }
             }
             httparse::Status::Complete(n_parsed) => {
-                if response.code != Some(200) {
+                if !matches!(response.code, Some(200 | 206)) {
                     return Ok(HeaderStatus {
                         status: response.code,
                         status_message: response.reason.map(str::to_owned),
@@ -327,7 +431,7 @@ where
                  */
                 assert!(n_parsed == buf.len());
                 return Ok(HeaderStatus {
-                    status: Some(200),
+                    status: response.code,
                     status_message: None,
                     encoding,
                 });
@@ -350,11 +454,33 @@ struct HeaderStatus {
     encoding: Option<String>,
 }
 
+/// How many bytes of decompressed output do we allow before we start checking
+/// for compression bombs?
+///
+/// (Small documents can legitimately compress very well.)
+const CHECK_FOR_COMPRESSION_BOMB_AFTER: usize = 64 * 1024;
+
+/// The largest ratio of decompressed to compressed size that we allow, once
+/// we have more than [`CHECK_FOR_COMPRESSION_BOMB_AFTER`] bytes of output.
+///
+/// (This matches the value that the C Tor implementation uses.)
+const MAX_UNCOMPRESSION_FACTOR: usize = 25;
+
+/// Return true if getting `uncompressed` bytes of output from `compressed`
+/// bytes of input suggests that we're decoding a compression bomb.
+fn is_compression_bomb(compressed: usize, uncompressed: usize) -> bool {
+    uncompressed > CHECK_FOR_COMPRESSION_BOMB_AFTER
+        && uncompressed / std::cmp::max(compressed, 1) > MAX_UNCOMPRESSION_FACTOR
+}
+
 /// Helper: download directory information from `stream` and
 /// decompress it into a result buffer.  Assumes that `buf` is empty.
 ///
 /// If we get more than maxlen bytes after decompression, give an error.
 ///
+/// `n_compressed` must track the number of bytes that `stream` has read from
+/// the network; we use it to detect compression bombs.
+///
 /// Returns the status of our download attempt, stores any data that
 /// we were able to download into `result`.  Existing contents of
 /// `result` are overwritten.
@@ -362,6 +488,7 @@ async fn read_and_decompress<S, SP>(
     runtime: &SP,
     mut stream: S,
     maxlen: usize,
+    n_compressed: &AtomicUsize,
     result: &mut Vec<u8>,
 ) -> RequestResult<()>
 where
@@ -412,15 +539,24 @@ where
             return Ok(());
         }
 
-        // TODO: It would be good to detect compression bombs, but
-        // that would require access to the internal stream, which
-        // would in turn require some tricky programming.  For now, we
-        // use the maximum length here to prevent an attacker from
+        // We use the maximum length here to prevent an attacker from
         // filling our RAM.
         if written_total > maxlen {
             result.resize(maxlen, 0);
             return Err(RequestError::ResponseTooLong(written_total));
         }
+
+        // And we give up early on anything that looks like a compression
+        // bomb, so an attacker can't make us burn CPU (and RAM, up to maxlen)
+        // for very little bandwidth.
+        let compressed = n_compressed.load(Ordering::Relaxed);
+        if is_compression_bomb(compressed, written_total) {
+            result.resize(written_total, 0);
+            return Err(RequestError::CompressionBomb {
+                compressed,
+                uncompressed: written_total,
+            });
+        }
     }
 }
 
@@ -566,12 +702,15 @@ mod test {
         let mock_time = MockSleepProvider::new(std::time::SystemTime::now());
 
         let mut output = Vec::new();
+        let n_compressed = Arc::new(AtomicUsize::new(0));
+        let data = util::CountingReader::new(data, Arc::clone(&n_compressed));
         let mut stream = match get_decoder(data, encoding, AnonymizedRequest::Direct) {
             Ok(s) => s,
             Err(e) => return (Err(e), output),
         };
 
-        let r = read_and_decompress(&mock_time, &mut stream, maxlen, &mut output).await;
+        let r =
+            read_and_decompress(&mock_time, &mut stream, maxlen, &n_compressed, &mut output).await;
 
         (r, output)
     }
@@ -639,6 +778,40 @@ mod test {
         Ok(())
     }
 
+    #[async_test]
+    async fn decomp_bomb() {
+        use async_compression::futures::bufread::ZlibEncoder;
+
+        // A megabyte of zeros compresses _very_ well.
+        let zeros = vec![0_u8; 1 << 20];
+        let mut compressed = Vec::new();
+        ZlibEncoder::new(&zeros[..])
+            .read_to_end(&mut compressed)
+            .await
+            .unwrap();
+        assert!(compressed.len() < 4096);
+
+        let limit = 10 << 20;
+        let (s, r) = decomp_basic(Some("deflate"), &compressed, limit).await;
+        assert!(matches!(s, Err(RequestError::CompressionBomb { .. })));
+        assert!(r.len() < zeros.len());
+
+        // But the same data, uncompressed, is fine.
+        let (s, r) = decomp_basic(None, &zeros, limit).await;
+        s.unwrap();
+        assert_eq!(r, zeros);
+    }
+
+    #[test]
+    fn bomb_detection() {
+        // Small outputs are always okay.
+        assert!(!is_compression_bomb(1, 1000));
+        assert!(!is_compression_bomb(0, CHECK_FOR_COMPRESSION_BOMB_AFTER));
+        // Large outputs are okay if the ratio is reasonable.
+        assert!(!is_compression_bomb(100_000, 1_000_000));
+        assert!(is_compression_bomb(10_000, 1_000_000));
+    }
+
     #[async_test]
     async fn decomp_unknown() {
         let compressed = hex::decode("28b52ffd24250d0100c84f6e6520666973682054776f526564426c756520666973680a0200600c0e2509478352cb").unwrap();
@@ -816,6 +989,70 @@ mod test {
         assert!(response.output_unchecked().starts_with(b"One fish"));
     }
 
+    #[test]
+    fn test_download_resumed() {
+        // "One fish Two fish Red fish Blue fish", compressed.
+        let compressed =
+            hex::decode("789cf3cf4b5548cb2cce500829cf8730825253200ca79c52881c00e5970c88").unwrap();
+        let (start, rest) = compressed.split_at(20);
+
+        // An interrupted download gives us the raw body.
+        let mut req = request::ConsensusRequest::default();
+        req.set_keep_partial(true);
+        let mut response_text: Vec<u8> =
+            (*b"HTTP/1.0 200 OK\r\nContent-Encoding: deflate\r\n\r\n").into();
+        response_text.extend(start);
+        let (response, _) = run_download_test(req, &response_text);
+        let response = response.unwrap();
+        assert!(response.is_partial());
+        let partial = response.partial_download().unwrap().clone();
+        assert_eq!(partial.encoding(), Some("deflate"));
+        assert_eq!(partial.body(), start);
+
+        // When we resume it, we ask for the rest with the same encoding, and
+        // reassemble the compressed body.
+        let mut req = request::ConsensusRequest::default();
+        req.set_resume(partial.clone());
+        let mut response_text: Vec<u8> =
+            (*b"HTTP/1.0 206 Partial Content\r\nContent-Encoding: deflate\r\n\r\n").into();
+        response_text.extend(rest);
+        let (response, request) = run_download_test(req, &response_text);
+        let request = String::from_utf8(request.unwrap()).unwrap();
+        assert!(
+            request.starts_with("GET /tor/status-vote/current/consensus-microdesc.z HTTP/1.0\r\n")
+        );
+        assert!(request.contains("accept-encoding: deflate\r\n"));
+        assert!(request.contains("range: bytes=20-\r\n"));
+        let response = response.unwrap();
+        assert_eq!(response.status_code(), 200);
+        assert!(response.error().is_none());
+        assert_eq!(
+            response.output().unwrap(),
+            b"One fish Two fish Red fish Blue fish"
+        );
+
+        // If the cache ignores our Range header, we get the whole thing.
+        let mut req = request::ConsensusRequest::default();
+        req.set_resume(partial.clone());
+        let mut response_text: Vec<u8> =
+            (*b"HTTP/1.0 200 OK\r\nContent-Encoding: deflate\r\n\r\n").into();
+        response_text.extend(&compressed);
+        let (response, _) = run_download_test(req, &response_text);
+        assert_eq!(
+            response.unwrap().output().unwrap(),
+            b"One fish Two fish Red fish Blue fish"
+        );
+
+        // We can't use the rest of the document in a different encoding.
+        let mut req = request::ConsensusRequest::default();
+        req.set_resume(partial);
+        let response_text = b"HTTP/1.0 206 Partial Content\r\n\r\nTwo fish Red fish Blue fish";
+        let (response, _) = run_download_test(req, response_text);
+        let response = response.unwrap();
+        assert_eq!(response.status_code(), 206);
+        assert!(response.output().is_err());
+    }
+
     #[test]
     fn test_404() {
         let req: request::MicrodescRequest = vec![[9; 32]].into_iter().collect();
@@ -23,11 +23,12 @@ use std::time::{Duration, SystemTime};
 use itertools::Itertools;
 
 use crate::err::RequestError;
+use crate::response::PartialDownload;
 use crate::AnonymizedRequest;
 
 /// Declare an inaccessible public type.
 pub(crate) mod sealed {
-    use super::{AnonymizedRequest, ClientCirc, Result};
+    use super::{AnonymizedRequest, ClientCirc, PartialDownload, Result};
     /// Sealed trait to help implement [`Requestable`](super::Requestable): not
     /// visible outside this crate, so we can change its methods however we like.
     pub trait RequestableInner: Send + Sync {
@@ -64,6 +65,18 @@ pub(crate) mod sealed {
 
         /// Return a value to say whether this request must be anonymized.
         fn anonymized(&self) -> AnonymizedRequest;
+
+        /// Return true if we should keep the raw body of this request's
+        /// response if the download is interrupted, so that we can resume it.
+        fn resumable(&self) -> bool {
+            false
+        }
+
+        /// If we are resuming an interrupted download, return what we got
+        /// last time.
+        fn resume_prefix(&self) -> Option<&PartialDownload> {
+            None
+        }
     }
 }
 
@@ -134,6 +147,12 @@ pub struct ConsensusRequest {
     last_consensus_sha3_256: Vec<[u8; 32]>,
     /// If present, the largest amount of clock skew to allow between ourself and a directory cache.
     skew_limit: Option<SkewLimit>,
+    /// If true, we want to keep whatever part of the consensus we receive,
+    /// even if the download is interrupted.
+    keep_partial: bool,
+    /// If present, the raw body of an earlier download of this consensus
+    /// that was interrupted: we only want the rest of it.
+    resume: Option<PartialDownload>,
 }
 
 impl ConsensusRequest {
@@ -145,9 +164,16 @@ impl ConsensusRequest {
             last_consensus_published: None,
             last_consensus_sha3_256: Vec::new(),
             skew_limit: None,
+            keep_partial: false,
+            resume: None,
         }
     }
 
+    /// Return the flavor of consensus that this request is asking for.
+    pub fn flavor(&self) -> ConsensusFlavor {
+        self.flavor
+    }
+
     /// Add `id` to the list of authorities that this request should
     /// say we believe in.
     pub fn push_authority_id(&mut self, id: RsaIdentity) {
@@ -196,6 +222,42 @@ impl ConsensusRequest {
     pub fn set_skew_limit(&mut self, max_fast: Duration, max_slow: Duration) {
         self.skew_limit = Some(SkewLimit { max_fast, max_slow });
     }
+
+    /// If `keep` is true, then return whatever we received of the consensus
+    /// if the download is interrupted, so that we can later resume it
+    /// with [`set_resume`](Self::set_resume).
+    ///
+    /// (The partial body is returned as a [`DirResponse`](crate::DirResponse)
+    /// with an error set: see
+    /// [`DirResponse::partial_download`](crate::DirResponse::partial_download).)
+    pub fn set_keep_partial(&mut self, keep: bool) {
+        self.keep_partial = keep;
+    }
+
+    /// Tell the directory cache that we already have `partial`, the start
+    /// of the consensus, and only want the rest.
+    ///
+    /// We ask for the rest with an HTTP `Range` header, and we only accept
+    /// the encoding that `partial` used, so that byte offsets still line up.
+    /// (So a compressed download stays compressed when we resume it.)
+    ///
+    /// If the cache honors our request, it sends a `206 Partial Content`
+    /// response, and we reassemble the full document before decompressing
+    /// it.  Otherwise, it sends the whole document, and we discard
+    /// `partial`.  Either way, the response is an ordinary `200 OK`
+    /// response containing the whole document.
+    ///
+    /// This implies [`set_keep_partial(true)`](Self::set_keep_partial).
+    pub fn set_resume(&mut self, partial: PartialDownload) {
+        self.resume = Some(partial);
+        self.keep_partial = true;
+    }
+
+    /// Return the number of (raw) bytes of the consensus that we're saying we
+    /// already have, if we are resuming a download.
+    pub fn resume_from(&self) -> Option<usize> {
+        self.resume.as_ref().map(|p| p.body().len())
+    }
 }
 
 /// Convert a list of digests in some format to a string, for use in a request
@@ -252,7 +314,20 @@ impl sealed::RequestableInner for ConsensusRequest {
         uri.push_str(".z");
 
         let mut req = http::Request::builder().method("GET").uri(uri);
-        req = add_common_headers(req, self.anonymized());
+        req = match &self.resume {
+            // When resuming, we only accept the encoding we got last time,
+            // so that the byte offset means the same thing.
+            Some(partial) => req
+                .header(
+                    http::header::ACCEPT_ENCODING,
+                    partial.encoding().unwrap_or("identity"),
+                )
+                .header(
+                    http::header::RANGE,
+                    format!("bytes={}-", partial.body().len()),
+                ),
+            None => add_common_headers(req, self.anonymized()),
+        };
 
         // Possibly, add an if-modified-since header.
         if let Some(when) = self.last_consensus_date() {
@@ -271,7 +346,15 @@ impl sealed::RequestableInner for ConsensusRequest {
     }
 
     fn partial_response_body_ok(&self) -> bool {
-        false
+        self.keep_partial
+    }
+
+    fn resumable(&self) -> bool {
+        self.keep_partial
+    }
+
+    fn resume_prefix(&self) -> Option<&PartialDownload> {
+        self.resume.as_ref()
     }
 
     fn check_circuit(&self, circ: &ClientCirc) -> Result<()> {
@@ -664,18 +747,21 @@ impl sealed::RequestableInner for HsDescUploadRequest {
 /// Encodings that all Tor clients support.
 const UNIVERSAL_ENCODINGS: &str = "deflate, identity";
 
-/// List all the encodings we accept
+/// List all the encodings we accept, most preferred first.
+///
+/// Directory caches pick the first encoding in our list that they have the
+/// document available in, so we list the ones that compress best first.
 fn all_encodings() -> String {
-    #[allow(unused_mut)]
-    let mut encodings = UNIVERSAL_ENCODINGS.to_string();
-    #[cfg(feature = "xz")]
+    let mut encodings = String::new();
+    #[cfg(feature = "zstd")]
     {
-        encodings += ", x-tor-lzma";
+        encodings += "x-zstd, ";
     }
-    #[cfg(feature = "zstd")]
+    #[cfg(feature = "xz")]
     {
-        encodings += ", x-zstd";
+        encodings += "x-tor-lzma, ";
     }
+    encodings += UNIVERSAL_ENCODINGS;
 
     encodings
 }
@@ -817,6 +903,32 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_consensus_request_resume() -> Result<()> {
+        let mut req = ConsensusRequest::default();
+        req.set_keep_partial(true);
+        assert!(req.partial_response_body_ok());
+        assert_eq!(req.resume_from(), None);
+
+        let mut req = ConsensusRequest::new(ConsensusFlavor::Ns);
+        req.set_resume(PartialDownload::new(None, vec![b'x'; 12345]));
+        assert!(req.partial_response_body_ok());
+        assert_eq!(req.resume_from(), Some(12345));
+        assert_eq!(req.flavor(), ConsensusFlavor::Ns);
+
+        let encoded = crate::util::encode_request(&req.make_request()?);
+        assert_eq!(encoded,
+                   "GET /tor/status-vote/current/consensus.z HTTP/1.0\r\naccept-encoding: identity\r\nrange: bytes=12345-\r\n\r\n");
+
+        // A compressed download stays compressed.
+        req.set_resume(PartialDownload::new(Some("deflate".into()), vec![0; 99]));
+        let encoded = crate::util::encode_request(&req.make_request()?);
+        assert_eq!(encoded,
+                   "GET /tor/status-vote/current/consensus.z HTTP/1.0\r\naccept-encoding: deflate\r\nrange: bytes=99-\r\n\r\n");
+
+        Ok(())
+    }
+
     #[test]
     #[cfg(feature = "routerdesc")]
     fn test_rd_request_all() -> Result<()> {
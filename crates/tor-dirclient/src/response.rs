@@ -22,6 +22,22 @@ pub struct DirResponse {
     error: Option<RequestError>,
     /// Information about the directory cache we used.
     source: Option<SourceInfo>,
+    /// If this response was interrupted, and the request was one that we
+    /// can resume, the raw bytes we received.
+    partial_download: Option<PartialDownload>,
+}
+
+/// The raw body of an interrupted download, as the directory cache sent it.
+///
+/// The body is stored exactly as we received it (that is, still compressed),
+/// so that we can ask the cache for the rest of it with an HTTP `Range`
+/// request, using the same encoding.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PartialDownload {
+    /// The `Content-Encoding` of the body, or `None` for the identity encoding.
+    encoding: Option<String>,
+    /// The raw body that we received.
+    body: Vec<u8>,
 }
 
 /// Information about the source of a directory response.
@@ -52,9 +68,16 @@ impl DirResponse {
             output,
             error,
             source,
+            partial_download: None,
         }
     }
 
+    /// Attach the raw body of an interrupted download to this response.
+    pub(crate) fn with_partial_download(mut self, partial: PartialDownload) -> Self {
+        self.partial_download = Some(partial);
+        self
+    }
+
     /// Construct a new successful DirResponse from its body.
     pub fn from_body(body: impl AsRef<[u8]>) -> Self {
         Self::new(200, None, None, body.as_ref().to_vec(), None)
@@ -75,6 +98,15 @@ impl DirResponse {
         self.error.as_ref()
     }
 
+    /// If this response was interrupted, return what we need to resume it.
+    ///
+    /// This is only present for requests that can be resumed (see
+    /// [`ConsensusRequest::set_keep_partial`](crate::request::ConsensusRequest::set_keep_partial)),
+    /// and only if we received some of the body.
+    pub fn partial_download(&self) -> Option<&PartialDownload> {
+        self.partial_download.as_ref()
+    }
+
     /// Return the output from this response.
     ///
     /// Returns some output, even if the response indicates truncation or an error.
@@ -155,6 +187,31 @@ impl DirResponse {
     }
 }
 
+impl PartialDownload {
+    /// Construct a new `PartialDownload` from its encoding and raw body.
+    pub fn new(encoding: Option<String>, body: Vec<u8>) -> Self {
+        let encoding = encoding.filter(|e| e != "identity");
+        PartialDownload { encoding, body }
+    }
+
+    /// Return the `Content-Encoding` of the body, or `None` for the identity
+    /// encoding.
+    pub fn encoding(&self) -> Option<&str> {
+        self.encoding.as_deref()
+    }
+
+    /// Return the raw body that we received.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Return true if `encoding` (a `Content-Encoding` header, if any) is
+    /// the encoding of this download.
+    pub(crate) fn has_encoding(&self, encoding: Option<&str>) -> bool {
+        self.encoding() == encoding.filter(|e| *e != "identity")
+    }
+}
+
 impl SourceInfo {
     /// Construct a new SourceInfo
     pub(crate) fn from_circuit(circuit: &ClientCirc) -> Self {
@@ -228,4 +285,19 @@ mod test {
 
         with_error(&response);
     }
+
+    #[test]
+    fn partial_download() {
+        let partial = PartialDownload::new(Some("identity".into()), b"One fish".to_vec());
+        assert_eq!(partial.encoding(), None);
+        assert_eq!(partial.body(), b"One fish");
+        let partial = PartialDownload::new(Some("deflate".into()), b"xyz".to_vec());
+        assert_eq!(partial.encoding(), Some("deflate"));
+
+        let response = DirResponse::new(200, None, Some(RequestError::DirTimeout), vec![], None);
+        assert!(response.partial_download().is_none());
+        let response = response.with_partial_download(partial.clone());
+        assert!(response.is_partial());
+        assert_eq!(response.partial_download(), Some(&partial));
+    }
 }
@@ -1,6 +1,12 @@
 //! Helper functions for the directory client code
 
 use std::fmt::Write;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::io::{AsyncBufRead, AsyncRead};
 
 /// Encode an HTTP request in a quick and dirty HTTP 1.0 format.
 pub(crate) fn encode_request(req: &http::Request<String>) -> String {
@@ -28,6 +34,50 @@ pub(crate) fn encode_request(req: &http::Request<String>) -> String {
     s
 }
 
+/// A wrapper around an [`AsyncBufRead`] that counts how many bytes have been
+/// read from it.
+///
+/// We use this to find out how many compressed bytes a decoder has consumed.
+#[derive(Debug)]
+pub(crate) struct CountingReader<S> {
+    /// The underlying stream.
+    inner: S,
+    /// The number of bytes that have been read (or consumed) from `inner`.
+    count: Arc<AtomicUsize>,
+}
+
+impl<S> CountingReader<S> {
+    /// Wrap `inner`, and add the number of bytes read from it to `count`.
+    pub(crate) fn new(inner: S, count: Arc<AtomicUsize>) -> Self {
+        CountingReader { inner, count }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountingReader<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let r = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = &r {
+            self.count.fetch_add(*n, Ordering::Relaxed);
+        }
+        r
+    }
+}
+
+impl<S: AsyncBufRead + Unpin> AsyncBufRead for CountingReader<S> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().inner).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        self.count.fetch_add(amt, Ordering::Relaxed);
+        Pin::new(&mut self.inner).consume(amt);
+    }
+}
+
 #[cfg(test)]
 mod test {
     // @@ begin test lint list maintained by maint/add_warning @@
@@ -44,6 +94,7 @@ mod test {
     #![allow(clippy::needless_pass_by_value)]
     //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
     use super::*;
+    use futures_await_test::async_test;
 
     fn build_request(body: String, headers: &[(&str, &str)]) -> http::Request<String> {
         let mut builder = http::Request::builder().method("GET").uri("/index.html");
@@ -77,4 +128,24 @@ mod test {
         chk_format("", "");
         chk_format("hello", "Content-Length: 5\r\n");
     }
+
+    #[async_test]
+    async fn counting() {
+        use futures::io::{AsyncBufReadExt as _, AsyncReadExt as _};
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let mut r = CountingReader::new(&b"hello world"[..], Arc::clone(&count));
+
+        let mut buf = [0_u8; 5];
+        r.read_exact(&mut buf).await.unwrap();
+        assert_eq!(count.load(Ordering::Relaxed), 5);
+
+        let data = r.fill_buf().await.unwrap();
+        assert_eq!(data, b" world");
+        // Peeking at data doesn't count as reading it...
+        assert_eq!(count.load(Ordering::Relaxed), 5);
+        // ...but consuming it does.
+        r.consume_unpin(3);
+        assert_eq!(count.load(Ordering::Relaxed), 8);
+    }
 }
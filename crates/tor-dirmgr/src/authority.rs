@@ -5,7 +5,13 @@
 
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
-use tor_config::{define_list_builder_helper, impl_standard_builder, ConfigBuildError};
+use std::net::SocketAddr;
+use tor_config::{
+    define_list_builder_accessors, define_list_builder_helper, impl_standard_builder,
+    list_builder::VecBuilder, ConfigBuildError,
+};
+use tor_guardmgr::fallback::FallbackDir;
+use tor_llcrypto::pk::ed25519::Ed25519Identity;
 use tor_llcrypto::pk::rsa::RsaIdentity;
 
 /// A single authority that signs a consensus directory.
@@ -29,10 +35,51 @@ pub struct Authority {
     /// A SHA1 digest of the DER-encoded long-term v3 RSA identity key for
     /// this authority.
     pub v3ident: RsaIdentity,
+
+    /// The RSA identity of this authority's relay, if we want to contact it
+    /// directly.
+    ///
+    /// (This is not the same as `v3ident`.)
+    #[builder(default)]
+    rsa_identity: Option<RsaIdentity>,
+    /// The Ed25519 identity of this authority's relay, if we want to contact
+    /// it directly.
+    #[builder(default)]
+    ed_identity: Option<Ed25519Identity>,
+    /// A list of ORPorts on which this authority can be reached.
+    ///
+    /// The default authorities do not list any: we never contact them directly.
+    /// On a private or testing network, it's often convenient to list them,
+    /// so that the authorities themselves can be used as fallback directories.
+    #[builder(sub_builder(fn_name = "build"), setter(custom))]
+    #[builder_field_attr(serde(default))]
+    orports: Vec<SocketAddr>,
 }
 
 impl_standard_builder! { Authority: !Default }
 
+define_list_builder_accessors! {
+    struct AuthorityBuilder {
+        pub orports: [SocketAddr],
+    }
+}
+
+impl Authority {
+    /// If this authority has enough contact information to be used as a
+    /// fallback directory, return it as a [`FallbackDir`].
+    ///
+    /// We need an RSA identity, an Ed25519 identity, and at least one ORPort.
+    pub fn as_fallback(&self) -> Option<FallbackDir> {
+        let mut bld = FallbackDir::builder();
+        bld.rsa_identity(self.rsa_identity?)
+            .ed_identity(self.ed_identity?);
+        for addr in &self.orports {
+            bld.orports().push(*addr);
+        }
+        bld.build().ok()
+    }
+}
+
 /// Authority list, built
 pub(crate) type AuthorityList = Vec<Authority>;
 
@@ -124,6 +171,36 @@ mod test {
             sk_fingerprint: key2,
         };
         assert!(!auth.matches_keyid(&keyids2));
+        assert!(auth.as_fallback().is_none());
+    }
+
+    #[test]
+    fn authority_as_fallback() {
+        let v3ident: RsaIdentity = [9_u8; 20].into();
+        let rsa: RsaIdentity = [11_u8; 20].into();
+        let ed: Ed25519Identity = [12_u8; 32].into();
+        let addr: SocketAddr = "192.0.2.1:5000".parse().unwrap();
+
+        let mut bld = Authority::builder();
+        bld.name("test000a")
+            .v3ident(v3ident)
+            .rsa_identity(Some(rsa));
+        // No ed25519 identity or ORPort yet.
+        assert!(bld.build().unwrap().as_fallback().is_none());
+
+        bld.ed_identity(Some(ed));
+        // No ORPort yet.
+        assert!(bld.build().unwrap().as_fallback().is_none());
+
+        bld.orports().push(addr);
+        let fb = bld.build().unwrap().as_fallback().unwrap();
+        let expected = {
+            let mut fb = FallbackDir::builder();
+            fb.rsa_identity(rsa).ed_identity(ed);
+            fb.orports().push(addr);
+            fb.build().unwrap()
+        };
+        assert_eq!(fb, expected);
     }
 
     #[test]
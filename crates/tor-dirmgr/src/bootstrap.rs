@@ -4,7 +4,7 @@
 use std::num::NonZeroUsize;
 use std::ops::Deref;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::{Arc, Weak},
     time::{Duration, SystemTime},
 };
@@ -22,7 +22,7 @@ use futures::FutureExt;
 use futures::StreamExt;
 use tor_async_utils::oneshot;
 use tor_dirclient::DirResponse;
-use tor_error::{info_report, warn_report};
+use tor_error::{info_report, warn_report, HasKind as _};
 use tor_rtcompat::scheduler::TaskSchedule;
 use tor_rtcompat::Runtime;
 use tracing::{debug, info, trace, warn};
@@ -232,9 +232,69 @@ pub(crate) fn make_consensus_request(
         config.tolerance.pre_valid_tolerance,
     );
 
+    // If an earlier download was interrupted, try to pick up where it left
+    // off.  Either way, keep whatever we get if this one is interrupted too.
+    request.set_keep_partial(true);
+    match store.partial_consensus(flavor) {
+        Ok(Some(partial)) if !partial.body().is_empty() => {
+            debug!(
+                "Resuming {} consensus download after {} bytes",
+                flavor.name(),
+                partial.body().len()
+            );
+            request.set_resume(partial);
+        }
+        Ok(_) => {}
+        Err(e) => warn_report!(e, "Error loading partial consensus"),
+    }
+
     Ok(ClientRequest::Consensus(request))
 }
 
+/// Handle `response`, which we got in answer to the consensus request `request`,
+/// taking care of interrupted downloads.
+///
+/// If the download was interrupted, save what we got so that we can resume
+/// it later, and return the error that interrupted it.
+///
+/// Otherwise, return the response that we should use.
+fn handle_consensus_response(
+    store: &mut dyn Store,
+    request: &tor_dirclient::request::ConsensusRequest,
+    response: DirResponse,
+) -> std::result::Result<DirResponse, tor_dirclient::RequestError> {
+    let flavor = request.flavor();
+
+    if response.status_code() != 200 {
+        return Ok(response);
+    }
+
+    let outcome = match (response.error(), response.partial_download()) {
+        (Some(_), Some(partial)) => {
+            // Note that if the cache ignored our Range header, this replaces
+            // our old partial consensus with a new one.
+            debug!(
+                "Saving {} bytes of interrupted {} consensus download",
+                partial.body().len(),
+                flavor.name()
+            );
+            store.store_partial_consensus(flavor, partial)
+        }
+        // Either we have the whole document now, or what we got can't be
+        // resumed (say, because the cache gave us the tail of a different
+        // consensus).  Either way, we start from scratch next time.
+        (_, _) => store.delete_partial_consensus(flavor),
+    };
+    if let Err(e) = outcome {
+        warn_report!(e, "Error updating partial consensus");
+    }
+
+    match response.error() {
+        Some(e) => Err(e.clone()),
+        None => Ok(response),
+    }
+}
+
 /// Construct a set of `ClientRequest`s in order to fetch the documents in `docs`.
 pub(crate) fn make_requests_for_documents<R: Runtime>(
     rt: &R,
@@ -242,10 +302,15 @@ pub(crate) fn make_requests_for_documents<R: Runtime>(
     store: &dyn Store,
     config: &DirMgrConfig,
 ) -> Result<Vec<ClientRequest>> {
+    let md_batch_size = config.schedule.microdesc_batch_size.get();
+    let md_parallelism = config.schedule.retry_microdescs.parallelism().into();
     let mut res = Vec::new();
     for q in docid::partition_by_type(docs.iter().copied())
         .into_iter()
-        .flat_map(|(_, x)| x.split_for_download().into_iter())
+        .flat_map(|(_, x)| {
+            x.split_for_download(md_batch_size, md_parallelism)
+                .into_iter()
+        })
     {
         match q {
             DocQuery::LatestConsensus { flavor, .. } => {
@@ -330,7 +395,7 @@ async fn fetch_multiple<R: Runtime>(
 
     let circmgr = dirmgr.circmgr()?;
     // Only use timely directories for bootstrapping directories; otherwise, we'll try fallbacks.
-    let netdir = dirmgr.netdir(tor_netdir::Timeliness::Timely).ok();
+    let netdir = dirmgr.netdir_for(tor_netdir::DirUsage::DirFetch).ok();
 
     // TODO: instead of waiting for all the queries to finish, we
     // could stream the responses back or something.
@@ -345,6 +410,20 @@ async fn fetch_multiple<R: Runtime>(
         // TODO: on some error cases we might want to stop using this source.
         match r {
             Ok((request, response)) => {
+                let response = match &request {
+                    ClientRequest::Consensus(req) => {
+                        let mut store = dirmgr.store.lock().expect("store lock poisoned");
+                        match handle_consensus_response(&mut **store, req, response) {
+                            Ok(response) => response,
+                            Err(e) => {
+                                dirmgr.note_error_kind(attempt_id, e.kind());
+                                warn_report!(e, "consensus download interrupted");
+                                continue;
+                            }
+                        }
+                    }
+                    _ => response,
+                };
                 if response.status_code() == 200 {
                     useful_responses.push((request, response));
                 } else {
@@ -352,9 +431,16 @@ async fn fetch_multiple<R: Runtime>(
                         "cache declined request; reported status {:?}",
                         response.status_code()
                     );
+                    // Make sure that we ask somebody else next time.
+                    if let Some(source) = response.source() {
+                        circmgr.retire_circ(source.unique_circ_id());
+                    }
                 }
             }
-            Err(e) => warn_report!(e, "error while downloading"),
+            Err(e) => {
+                dirmgr.note_error_kind(attempt_id, e.kind());
+                warn_report!(e, "error while downloading");
+            }
         }
     }
 
@@ -383,6 +469,7 @@ async fn load_once<R: Runtime>(
 
         load_and_apply_documents(&missing, dirmgr, state, &mut changed)
     };
+    dirmgr.note_signature_warning(state);
 
     // We have to update the status here regardless of the outcome, if we got
     // any information: even if there was an error, we might have received
@@ -462,14 +549,28 @@ async fn download_attempt<R: Runtime>(
 ) -> Result<()> {
     let missing = state.missing_docs();
     let fetched = fetch_multiple(Arc::clone(dirmgr), attempt_id, &missing, parallelism).await?;
+    // The clock skew that the relays we have connected to report, if any.  We
+    // only believe a cache that makes our clock look wrong if this agrees.
+    let other_skew = dirmgr
+        .circmgr()
+        .ok()
+        .and_then(|circmgr| circmgr.skew_events().get())
+        .filter(|estimate| estimate.noteworthy())
+        .map(|estimate| estimate.skew());
     let mut n_errors = 0;
+    // The microdescriptors that we asked each cache for.
+    let mut md_requests = Vec::new();
     for (client_req, dir_response) in fetched {
         let source = dir_response.source().cloned();
+        if let (ClientRequest::Microdescs(req), Some(source)) = (&client_req, &source) {
+            md_requests.push((source.clone(), req.digests().copied().collect::<Vec<_>>()));
+        }
         let text = match String::from_utf8(dir_response.into_output_unchecked())
             .map_err(Error::BadUtf8FromDirectory)
         {
             Ok(t) => t,
             Err(e) => {
+                dirmgr.note_error_kind(attempt_id, e.kind());
                 if let Some(source) = source {
                     n_errors += 1;
                     note_cache_error(dirmgr.circmgr()?.deref(), &source, &e);
@@ -483,13 +584,16 @@ async fn download_attempt<R: Runtime>(
                     source: source.clone(),
                 };
                 let mut changed = false;
-                let outcome = state.add_from_download(
-                    &text,
-                    &client_req,
-                    doc_source,
-                    Some(&dirmgr.store),
-                    &mut changed,
-                );
+                let outcome = state
+                    .add_from_download(
+                        &text,
+                        &client_req,
+                        doc_source,
+                        Some(&dirmgr.store),
+                        &mut changed,
+                    )
+                    .map_err(|e| e.corroborate_clock_skew(other_skew));
+                dirmgr.note_signature_warning(state);
 
                 if !changed {
                     debug_assert!(outcome.is_err());
@@ -506,12 +610,20 @@ async fn download_attempt<R: Runtime>(
 
                 if let Err(e) = &outcome {
                     dirmgr.note_errors(attempt_id, 1);
+                    dirmgr.note_error_kind(attempt_id, e.kind());
+                    if let Error::ClockSkew {
+                        estimated_offset, ..
+                    } = e
+                    {
+                        dirmgr.note_clock_skew(attempt_id, *estimated_offset);
+                    }
                     warn_report!(e, "error while adding directory info");
                 }
                 propagate_fatal_errors!(outcome);
             }
             Err(e) => {
                 warn_report!(e, "Error when expanding directory text");
+                dirmgr.note_error_kind(attempt_id, e.kind());
                 if let Some(source) = source {
                     n_errors += 1;
                     note_cache_error(dirmgr.circmgr()?.deref(), &source, &e);
@@ -523,6 +635,25 @@ async fn download_attempt<R: Runtime>(
     if n_errors != 0 {
         dirmgr.note_errors(attempt_id, n_errors);
     }
+    if !md_requests.is_empty() {
+        // A cache that didn't give us every microdescriptor we asked it for
+        // probably doesn't have the rest.  Retire its circuit, so that our
+        // retries for them go to a different cache.
+        let still_missing: HashSet<DocId> = state.missing_docs().into_iter().collect();
+        let circmgr = dirmgr.circmgr()?;
+        for (source, digests) in md_requests {
+            if digests
+                .into_iter()
+                .any(|d| still_missing.contains(&DocId::Microdesc(d)))
+            {
+                debug!(
+                    "{:?} didn't have every microdescriptor we asked for; will retry elsewhere",
+                    source
+                );
+                circmgr.retire_circ(source.unique_circ_id());
+            }
+        }
+    }
     dirmgr.update_progress(attempt_id, state.bootstrap_progress());
 
     Ok(())
@@ -738,6 +869,7 @@ mod test {
     use crate::test::new_mgr;
     use crate::DownloadSchedule;
     use std::sync::Mutex;
+    use tor_dirclient::PartialDownload;
     use tor_netdoc::doc::microdesc::MdDigest;
     use tor_rtcompat::SleepProvider;
 
@@ -761,6 +893,54 @@ mod test {
         );
     }
 
+    #[test]
+    fn resume_consensus() {
+        tor_rtcompat::test_with_one_runtime!(|rt| async {
+            let now = rt.wallclock();
+            let (_tempdir, mgr) = new_mgr(rt);
+            let config = DirMgrConfig::default();
+            let flavor = ConsensusFlavor::Microdesc;
+
+            let make_request = || {
+                let store = mgr.store.lock().unwrap();
+                match make_consensus_request(now, flavor, &**store, &config).unwrap() {
+                    ClientRequest::Consensus(r) => r,
+                    _ => panic!("Wrong request type"),
+                }
+            };
+
+            // Nothing to resume.
+            let req = make_request();
+            assert_eq!(req.resume_from(), None);
+
+            // Pretend that an earlier download was interrupted.
+            mgr.store
+                .lock()
+                .unwrap()
+                .store_partial_consensus(
+                    flavor,
+                    &PartialDownload::new(None, b"network-status-version 3 micro".to_vec()),
+                )
+                .unwrap();
+            let req = make_request();
+            assert_eq!(req.resume_from(), Some(30));
+
+            // A complete response (even one that ignored our range request)
+            // means that we no longer need the partial consensus.
+            let response = DirResponse::from_body("network-status-version 3 microdesc\n");
+            let response = {
+                let mut store = mgr.store.lock().unwrap();
+                handle_consensus_response(&mut **store, &req, response).unwrap()
+            };
+            assert_eq!(
+                response.output().unwrap(),
+                b"network-status-version 3 microdesc\n"
+            );
+            let req = make_request();
+            assert_eq!(req.resume_from(), None);
+        });
+    }
+
     /// A fake implementation of DirState that just wants a fixed set
     /// of microdescriptors.  It doesn't care if it gets them: it just
     /// wants to be told that the IDs exist.
@@ -23,7 +23,7 @@ use tracing::{debug, error, info, trace};
 use safelog::sensitive;
 use tor_basic_utils::retry::RetryDelay;
 use tor_basic_utils::BinaryHeapExt as _;
-use tor_checkable::{SelfSigned, Timebound};
+use tor_checkable::{timed::TimerangeBound, SelfSigned, Timebound};
 use tor_circmgr::CircMgr;
 use tor_error::{error_report, internal, ErrorKind, HasKind};
 use tor_error::{AbsRetryTime, HasRetryTime, RetryTime};
@@ -694,6 +694,31 @@ impl<R: Runtime, M: Mockable<R>> BridgeDescProvider for BridgeDescMgr<R, M> {
             "previously downloaded",
         );
 
+        // Bridges whose cached descriptors are still fresh can be used straight away.
+        // We load them all here, rather than in each bridge's download task,
+        // so that we can check all of their signatures in a single batch.
+        let cached = self
+            .mgr
+            .load_cached_descriptors(&new_bridges, &state.config);
+        if !cached.is_empty() {
+            let mut inserts = Vec::with_capacity(cached.len());
+            for (bridge, Downloaded { desc, refetch }) in cached {
+                debug!(r#" added bridge, using cached descriptor "{}""#, &bridge);
+                new_bridges.remove(&bridge);
+                state.refetch_schedule.push(RefetchEntry {
+                    when: refetch,
+                    bridge: bridge.clone(),
+                    retry_delay: (),
+                });
+                inserts.push((bridge, desc));
+            }
+            state.modify_current(|current| {
+                for (bridge, desc) in inserts {
+                    current.insert(bridge, Ok(desc));
+                }
+            });
+        }
+
         // OK now we have the list of bridges to add (if any).
         state.queued.extend(new_bridges.into_iter().map(|bridge| {
             debug!(r#" added bridge, queueing for download "{}""#, &bridge);
@@ -990,6 +1015,72 @@ impl<R: Runtime, M: Mockable<R>> StateGuard<'_, R, M> {
 }
 
 impl<R: Runtime, M: Mockable<R>> Manager<R, M> {
+    /// Look up cached descriptors for `bridges`, and return those that we can
+    /// use without contacting their bridges at all.
+    ///
+    /// The signatures of all these descriptors are checked in a single batch.
+    ///
+    /// Bridges whose cached descriptors are missing, unusable,
+    /// or fetched longer than `max_refetch` ago, are left out:
+    /// their download tasks will consult the cache again for themselves.
+    fn load_cached_descriptors(
+        &self,
+        bridges: &HashSet<BridgeKey>,
+        config: &BridgeDescDownloadConfig,
+    ) -> Vec<(BridgeKey, Downloaded)> {
+        let now = self.runtime.wallclock();
+        let mut found = Vec::new();
+        {
+            let Ok(store) = self.store.lock() else {
+                error!("bridge descriptor store poisoned");
+                return Vec::new();
+            };
+            for bridge in bridges {
+                let cached = match store.lookup_bridgedesc(bridge) {
+                    Ok(Some(cached)) => cached,
+                    Ok(None) => continue,
+                    Err(err) => {
+                        error_report!(
+                            err,
+                            r#"bridge descriptor cache lookup failed, for "{}""#,
+                            sensitive(bridge),
+                        );
+                        continue;
+                    }
+                };
+                // This is the same test that `download_descriptor` uses
+                // to decide whether it can reuse a cached document.
+                if cached.fetched > now
+                    || now.duration_since(cached.fetched).ok() > Some(config.max_refetch)
+                {
+                    continue;
+                }
+                match RouterDesc::parse(&cached.document) {
+                    Ok(desc) => found.push((bridge.clone(), desc)),
+                    Err(err) => trace!(r#"cached document for "{}" invalid: {}"#, &bridge, err),
+                }
+            }
+        }
+
+        let (bridges, descs): (Vec<_>, Vec<_>) = found.into_iter().unzip();
+        bridges
+            .into_iter()
+            .zip(RouterDesc::check_all_signatures(descs))
+            .filter_map(|(bridge, desc)| {
+                let got = desc
+                    .map_err(|e| Error::from(Arc::new(e)))
+                    .and_then(|desc| process_checked_document(&self.runtime, config, desc));
+                match got {
+                    Ok(got) => Some((bridge, got)),
+                    Err(err) => {
+                        trace!(r#"cached document for "{}" invalid: {}"#, &bridge, err);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
     /// Downloads a descriptor.
     ///
     /// The core of the descriptor download task
@@ -1141,6 +1232,18 @@ fn process_document<R: Runtime>(
     // `check_signature` checks the self-signature.
     let desc = desc.check_signature().map_err(Arc::new)?;
 
+    process_checked_document(runtime, config, desc)
+}
+
+/// Processes a descriptor whose signatures have been checked into a `Downloaded`
+///
+/// Checks the document validity times,
+/// and if they're good, calculates when will want to refetch it.
+fn process_checked_document<R: Runtime>(
+    runtime: &R,
+    config: &BridgeDescDownloadConfig,
+    desc: TimerangeBound<RouterDesc>,
+) -> Result<Downloaded, Error> {
     let now = runtime.wallclock();
     desc.is_valid_at(&now)?;
 
@@ -424,6 +424,8 @@ fn cache() -> Result<(), anyhow::Error> {
         eprintln!("----- forget the descriptor and try to reload it from the cache -----");
 
         clear_and_re_request(&bdm, &mut events, &bridge).await;
+        // The cached descriptor is used straight away, without a download task.
+        assert!(queues_are_empty(&bdm).is_some());
         stream_drain_until(3, &mut events, || async { in_results(Some(Ok(()))) }).await;
 
         // Should not have been re-downloaded, since the fetch time is great.
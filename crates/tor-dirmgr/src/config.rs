@@ -15,12 +15,14 @@ use crate::Result;
 use tor_checkable::timed::TimerangeBound;
 use tor_config::{define_list_builder_accessors, impl_standard_builder, ConfigBuildError};
 use tor_guardmgr::fallback::FallbackDirBuilder;
+use tor_netdir::{DirLiveness, DirUsage};
 use tor_netdoc::doc::netstatus::{self, Lifetime};
 
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 /// Configuration information about the Tor network itself; used as
 /// part of Arti's configuration.
@@ -45,7 +47,15 @@ pub struct NetworkConfig {
     ///
     /// The default is to use a set of compiled-in fallback directories,
     /// whose addresses and public keys are shipped as part of the Arti source code.
-    #[builder(sub_builder, setter(custom))]
+    ///
+    /// If non-default `authorities` are configured, but this list is not,
+    /// then every authority must have its relay identities and ORPorts listed:
+    /// in that case, we use the authorities themselves as fallback directories.
+    #[builder(
+        sub_builder,
+        setter(custom),
+        field(build = "self.build_fallback_caches()?")
+    )]
     pub(crate) fallback_caches: tor_guardmgr::fallback::FallbackList,
 
     /// List of directory authorities which we expect to sign consensus
@@ -54,6 +64,9 @@ pub struct NetworkConfig {
     /// (If none are specified, we use a default list of authorities shipped
     /// with Arti.)
     ///
+    /// Each authority may optionally list its relay identities and ORPorts;
+    /// this is useful on testing and private networks.
+    ///
     /// This section cannot be changed in a running Arti client.
     ///
     /// The default is to use a set of compiled-in authorities,
@@ -81,16 +94,48 @@ impl NetworkConfig {
 impl NetworkConfigBuilder {
     /// Check that this builder will give a reasonable network.
     fn validate(&self) -> std::result::Result<(), ConfigBuildError> {
-        if self.opt_authorities().is_some() && self.opt_fallback_caches().is_none() {
-            return Err(ConfigBuildError::Inconsistent {
-                fields: vec!["authorities".to_owned(), "fallbacks".to_owned()],
-                problem: "Non-default authorities are use, but the fallback list is not overridden"
-                    .to_owned(),
-            });
+        if self.opt_fallback_caches().is_none() {
+            if let Some(authorities) = self.opt_authorities() {
+                // Non-default authorities with default fallbacks make no sense,
+                // unless we can use the authorities as fallbacks.
+                for auth in authorities {
+                    if auth.build()?.as_fallback().is_none() {
+                        return Err(ConfigBuildError::Inconsistent {
+                            fields: vec!["authorities".to_owned(), "fallbacks".to_owned()],
+                            problem: "Non-default authorities are used, but the fallback list is not overridden, and not every authority has its ORPorts listed".to_owned(),
+                        });
+                    }
+                }
+            }
         }
 
         Ok(())
     }
+
+    /// Build the list of fallback caches.
+    ///
+    /// If the fallbacks were not set, but the authorities were, use the
+    /// authorities themselves as fallbacks.  (`validate` has already checked
+    /// that this is possible.)
+    fn build_fallback_caches(
+        &self,
+    ) -> std::result::Result<tor_guardmgr::fallback::FallbackList, ConfigBuildError> {
+        match (self.opt_fallback_caches(), self.opt_authorities()) {
+            (None, Some(authorities)) => authorities
+                .iter()
+                .map(|auth| {
+                    auth.build()?
+                        .as_fallback()
+                        .ok_or_else(|| ConfigBuildError::Invalid {
+                            field: "authorities".to_owned(),
+                            problem: "authority can't be used as a fallback".to_owned(),
+                        })
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map(Into::into),
+            _ => self.fallback_caches.build(),
+        }
+    }
 }
 
 /// Configuration information for how exactly we download documents from the
@@ -127,6 +172,17 @@ pub struct DownloadScheduleConfig {
     )]
     #[builder_field_attr(serde(default))]
     pub(crate) retry_microdescs: DownloadSchedule,
+
+    /// The largest number of microdescriptors to ask for in a single request.
+    ///
+    /// When we need fewer microdescriptors than would fill one request for
+    /// each of the `parallelism` requests in `retry_microdescs`, we use
+    /// smaller requests, so that we can fetch from several caches at once.
+    ///
+    /// Values above 500 are treated as 500.  Defaults to 500.
+    #[builder(default = "500.try_into().expect(\"batch size is zero\")")]
+    #[builder_field_attr(serde(default))]
+    pub(crate) microdesc_batch_size: NonZeroUsize,
 }
 
 impl_standard_builder! { DownloadScheduleConfig }
@@ -165,6 +221,28 @@ pub struct DirTolerance {
     #[builder(default = "Duration::from_secs(3 * 24 * 60 * 60)")]
     #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
     pub(crate) post_valid_tolerance: Duration,
+
+    /// For how long after a directory is valid should we use it to build new
+    /// circuits?
+    ///
+    /// Values larger than `post_valid_tolerance` are treated as equal to it.
+    ///
+    /// Defaults to 3 days.
+    #[builder(default = "Duration::from_secs(3 * 24 * 60 * 60)")]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    pub(crate) circuit_post_valid_tolerance: Duration,
+
+    /// For how long after a directory is valid should we use it for onion
+    /// services?
+    ///
+    /// This is shorter than the other tolerances, since onion services depend
+    /// on time periods and shared random values derived from the consensus.
+    /// Values larger than `post_valid_tolerance` are treated as equal to it.
+    ///
+    /// Defaults to 1 day.
+    #[builder(default = "Duration::from_secs(24 * 60 * 60)")]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    pub(crate) onion_service_post_valid_tolerance: Duration,
 }
 
 impl_standard_builder! { DirTolerance }
@@ -188,6 +266,64 @@ impl DirTolerance {
         )
         .expect("Logic error when constructing lifetime")
     }
+
+    /// Return how long after the end of its validity interval we are willing
+    /// to use a directory for `usage`.
+    ///
+    /// Directory fetches use the full `post_valid_tolerance`: any other
+    /// tolerance is clamped to it, since we discard directories that are older
+    /// than that.
+    pub(crate) fn post_valid_tolerance_for(&self, usage: DirUsage) -> Duration {
+        let tolerance = match usage {
+            DirUsage::DirFetch => self.post_valid_tolerance,
+            DirUsage::NewCircuits => self.circuit_post_valid_tolerance,
+            DirUsage::OnionServices => self.onion_service_post_valid_tolerance,
+            _ => self.post_valid_tolerance,
+        };
+        std::cmp::min(tolerance, self.post_valid_tolerance)
+    }
+
+    /// Return a new consensus [`Lifetime`] that extends the validity intervals
+    /// of `lifetime` as appropriate for `usage`.
+    pub(crate) fn extend_lifetime_for(&self, lifetime: &Lifetime, usage: DirUsage) -> Lifetime {
+        Lifetime::new(
+            lifetime.valid_after() - self.pre_valid_tolerance,
+            lifetime.fresh_until(),
+            lifetime.valid_until() + self.post_valid_tolerance_for(usage),
+        )
+        .expect("Logic error when constructing lifetime")
+    }
+
+    /// Return the [`DirLiveness`] at `now` of a directory with the given `lifetime`.
+    pub(crate) fn liveness(&self, lifetime: &Lifetime, now: SystemTime) -> DirLiveness {
+        let extended = self.extend_lifetime(lifetime);
+        if lifetime.valid_after() <= now && now <= lifetime.valid_until() {
+            DirLiveness::Live
+        } else if extended.valid_after() <= now && now <= extended.valid_until() {
+            DirLiveness::ReasonablyLive
+        } else {
+            DirLiveness::Stale
+        }
+    }
+
+    /// Return the next time after `now` at which the [`DirLiveness`] of a
+    /// directory with the given `lifetime` will change, if there is one.
+    pub(crate) fn next_liveness_change(
+        &self,
+        lifetime: &Lifetime,
+        now: SystemTime,
+    ) -> Option<SystemTime> {
+        let extended = self.extend_lifetime(lifetime);
+        [
+            extended.valid_after(),
+            lifetime.valid_after(),
+            // Liveness changes just _after_ the end of each interval.
+            lifetime.valid_until() + Duration::from_secs(1),
+            extended.valid_until() + Duration::from_secs(1),
+        ]
+        .into_iter()
+        .find(|t| *t > now)
+    }
 }
 
 /// Configuration type for network directory operations.
@@ -399,6 +535,48 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn build_network_authorities_as_fallbacks() -> Result<()> {
+        let auth = |name: &str, n: u8| {
+            let mut bld = Authority::builder();
+            bld.name(name)
+                .v3ident([n; 20].into())
+                .rsa_identity(Some([n + 1; 20].into()))
+                .ed_identity(Some([n + 2; 32].into()));
+            bld.orports().push(
+                format!("127.0.0.1:{}", 5000 + u16::from(n))
+                    .parse()
+                    .unwrap(),
+            );
+            bld
+        };
+
+        // If every authority has contact information, we can use them as
+        // fallbacks.
+        let mut bld = NetworkConfig::builder();
+        bld.set_authorities(vec![auth("test000a", 1), auth("test001a", 10)]);
+        let cfg = bld.build().unwrap();
+        assert_eq!(cfg.authorities.len(), 2);
+        assert_eq!(cfg.fallback_caches.len(), 2);
+
+        // But not if any of them lacks it.
+        bld.authorities().push(
+            Authority::builder()
+                .name("test002a")
+                .v3ident([20; 20].into())
+                .clone(),
+        );
+        assert!(bld.build().is_err());
+
+        // Explicit fallbacks take precedence.
+        bld.set_fallback_caches(vec![]);
+        let cfg = bld.build().unwrap();
+        assert_eq!(cfg.authorities.len(), 3);
+        assert_eq!(cfg.fallback_caches.len(), 0);
+
+        Ok(())
+    }
+
     #[test]
     fn build_schedule() -> Result<()> {
         use std::time::Duration;
@@ -408,6 +586,7 @@ mod test {
         assert_eq!(cfg.retry_microdescs.parallelism(), 4);
         assert_eq!(cfg.retry_microdescs.n_attempts(), 3);
         assert_eq!(cfg.retry_bootstrap.n_attempts(), 128);
+        assert_eq!(cfg.microdesc_batch_size.get(), 500);
 
         bld.retry_consensus().attempts(7);
         bld.retry_consensus().initial_delay(Duration::new(86400, 0));
@@ -422,6 +601,7 @@ mod test {
         bld.retry_microdescs().attempts(6);
         bld.retry_microdescs().initial_delay(Duration::new(3600, 0));
         bld.retry_microdescs().parallelism(1);
+        bld.microdesc_batch_size(100.try_into().unwrap());
 
         let cfg = bld.build().unwrap();
         assert_eq!(cfg.retry_microdescs.parallelism(), 1);
@@ -429,6 +609,7 @@ mod test {
         assert_eq!(cfg.retry_bootstrap.n_attempts(), 4);
         assert_eq!(cfg.retry_consensus.n_attempts(), 7);
         assert_eq!(cfg.retry_certs.n_attempts(), 5);
+        assert_eq!(cfg.microdesc_batch_size.get(), 100);
 
         Ok(())
     }
@@ -445,4 +626,59 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn tolerance_liveness() {
+        let hour = Duration::from_secs(3600);
+        let day = hour * 24;
+        let t0 = SystemTime::UNIX_EPOCH + day * 10_000;
+        let lifetime = Lifetime::new(t0, t0 + hour, t0 + hour * 3).unwrap();
+        let tol = DirTolerance::default();
+
+        assert_eq!(tol.post_valid_tolerance_for(DirUsage::DirFetch), day * 3);
+        assert_eq!(tol.post_valid_tolerance_for(DirUsage::NewCircuits), day * 3);
+        assert_eq!(tol.post_valid_tolerance_for(DirUsage::OnionServices), day);
+
+        let expires = lifetime.valid_until();
+        let hs = tol.extend_lifetime_for(&lifetime, DirUsage::OnionServices);
+        assert_eq!(hs.valid_until(), expires + day);
+        assert_eq!(hs.valid_after(), t0 - day);
+
+        assert_eq!(tol.liveness(&lifetime, t0 - day * 2), DirLiveness::Stale);
+        assert_eq!(
+            tol.liveness(&lifetime, t0 - hour),
+            DirLiveness::ReasonablyLive
+        );
+        assert_eq!(tol.liveness(&lifetime, t0), DirLiveness::Live);
+        assert_eq!(tol.liveness(&lifetime, expires), DirLiveness::Live);
+        assert_eq!(
+            tol.liveness(&lifetime, expires + day),
+            DirLiveness::ReasonablyLive
+        );
+        assert_eq!(
+            tol.liveness(&lifetime, expires + day * 4),
+            DirLiveness::Stale
+        );
+
+        assert_eq!(
+            tol.next_liveness_change(&lifetime, t0 - day * 2),
+            Some(t0 - day)
+        );
+        assert_eq!(tol.next_liveness_change(&lifetime, t0 - hour), Some(t0));
+        let next = tol.next_liveness_change(&lifetime, t0).unwrap();
+        assert_eq!(tol.liveness(&lifetime, next), DirLiveness::ReasonablyLive);
+        let next = tol.next_liveness_change(&lifetime, next).unwrap();
+        assert_eq!(tol.liveness(&lifetime, next), DirLiveness::Stale);
+        assert_eq!(tol.next_liveness_change(&lifetime, next), None);
+
+        // Per-usage tolerances can't exceed the general one.
+        let tol = DirTolerance::builder()
+            .post_valid_tolerance(day)
+            .onion_service_post_valid_tolerance(day * 2)
+            .circuit_post_valid_tolerance(hour)
+            .build()
+            .unwrap();
+        assert_eq!(tol.post_valid_tolerance_for(DirUsage::OnionServices), day);
+        assert_eq!(tol.post_valid_tolerance_for(DirUsage::NewCircuits), hour);
+    }
 }
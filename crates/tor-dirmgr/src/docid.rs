@@ -177,10 +177,15 @@ impl DocQuery {
 
     /// If this query contains too many documents to download with a single
     /// request, divide it up.
-    pub(crate) fn split_for_download(self) -> Vec<Self> {
+    ///
+    /// We put at most `md_batch_size` microdescriptors in each request.  If
+    /// there are only a few microdescriptors, we divide them into up to
+    /// `parallelism` smaller requests, so that they can be fetched in
+    /// parallel.
+    pub(crate) fn split_for_download(self, md_batch_size: usize, parallelism: usize) -> Vec<Self> {
         use DocQuery::*;
         /// How many objects can be put in a single HTTP GET line?
-        const N: usize = 500;
+        const N: usize = MAX_DOCS_PER_REQUEST;
         match self {
             LatestConsensus { .. } => vec![self],
             AuthCert(mut v) => {
@@ -189,7 +194,8 @@ impl DocQuery {
             }
             Microdesc(mut v) => {
                 v.sort_unstable();
-                v[..].chunks(N).map(|s| Microdesc(s.to_vec())).collect()
+                let size = microdesc_chunk_size(v.len(), md_batch_size, parallelism);
+                v[..].chunks(size).map(|s| Microdesc(s.to_vec())).collect()
             }
             #[cfg(feature = "routerdesc")]
             RouterDesc(mut v) => {
@@ -280,6 +286,48 @@ where
     result
 }
 
+/// How many documents can we put in a single request?
+///
+/// (This is limited by how long an HTTP GET line can be.)
+pub(crate) const MAX_DOCS_PER_REQUEST: usize = 500;
+
+/// When we split microdescriptors into parallel requests, what's the smallest
+/// number that we'll put in one request?
+///
+/// Below this, the cost of an extra request outweighs the benefit of
+/// spreading the microdescriptors across caches.
+const MIN_PARALLEL_MICRODESC_BATCH: usize = 32;
+
+/// Return how many microdescriptors to put in each request, if we need `n`
+/// of them, and we can make up to `parallelism` requests at once, each for
+/// no more than `batch_size`.
+///
+/// We use requests of equal size, so that none of them finishes much later
+/// than the others.
+fn microdesc_chunk_size(n: usize, batch_size: usize, parallelism: usize) -> usize {
+    /// Return CEIL(a/b).
+    ///
+    /// This can be removed once the MSRV is >= 1.73.0, which is the version
+    /// that stabilized `std::usize::div_ceil`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if b is 0.
+    fn div_ceil(a: usize, b: usize) -> usize {
+        (a + b - 1) / b
+    }
+    let batch_size = batch_size.clamp(1, MAX_DOCS_PER_REQUEST);
+    let n_chunks = std::cmp::max(
+        div_ceil(n, batch_size),
+        std::cmp::min(parallelism, n / MIN_PARALLEL_MICRODESC_BATCH),
+    );
+    if n_chunks == 0 {
+        // There's nothing to fetch; any nonzero size will do.
+        return batch_size;
+    }
+    div_ceil(n, n_chunks)
+}
+
 #[cfg(test)]
 mod test {
     // @@ begin test lint list maintained by maint/add_warning @@
@@ -361,6 +409,29 @@ mod test {
         assert!(matches!(q, DocQuery::AuthCert(v) if v.len() == 256));
     }
 
+    #[test]
+    fn microdesc_chunks() {
+        // Plenty of microdescriptors: we're limited by the batch size, and
+        // we use equal-sized requests.
+        assert_eq!(microdesc_chunk_size(7000, 500, 4), 500);
+        assert_eq!(microdesc_chunk_size(7001, 500, 4), 467);
+        assert_eq!(microdesc_chunk_size(1000, 96, 4), 91);
+        // Batch sizes that are too large are clamped.
+        assert_eq!(microdesc_chunk_size(7000, 5000, 4), 500);
+        // A few hundred: we spread them over `parallelism` requests.
+        assert_eq!(microdesc_chunk_size(400, 500, 4), 100);
+        // Just a few: we don't make requests that are too small.
+        assert_eq!(microdesc_chunk_size(64, 500, 4), 32);
+        assert_eq!(microdesc_chunk_size(10, 500, 4), 10);
+        assert_eq!(microdesc_chunk_size(0, 500, 4), 500);
+
+        use rand::Rng;
+        let mut rng = testing_rng();
+        let ids: Vec<MdDigest> = (0..400).map(|_| rng.gen()).collect();
+        let split = DocQuery::Microdesc(ids).split_for_download(MAX_DOCS_PER_REQUEST, 4);
+        assert_eq!(split.len(), 4);
+    }
+
     #[test]
     fn split_into_chunks() {
         use std::collections::HashSet;
@@ -372,7 +443,8 @@ mod test {
         let ids: HashSet<MdDigest> = (0..3400).map(|_| rng.gen()).collect();
 
         // Test microdescs.
-        let split = DocQuery::Microdesc(ids.clone().into_iter().collect()).split_for_download();
+        let split = DocQuery::Microdesc(ids.clone().into_iter().collect())
+            .split_for_download(MAX_DOCS_PER_REQUEST, 1);
         assert_eq!(split.len(), 7);
         let mut found_ids = HashSet::new();
         for q in split {
@@ -390,8 +462,8 @@ mod test {
         #[cfg(feature = "routerdesc")]
         {
             let ids: HashSet<RdDigest> = (0..1001).map(|_| rng.gen()).collect();
-            let split =
-                DocQuery::RouterDesc(ids.clone().into_iter().collect()).split_for_download();
+            let split = DocQuery::RouterDesc(ids.clone().into_iter().collect())
+                .split_for_download(MAX_DOCS_PER_REQUEST, 1);
             assert_eq!(split.len(), 3);
             let mut found_ids = HashSet::new();
             for q in split {
@@ -417,7 +489,8 @@ mod test {
                 }
             })
             .collect();
-        let split = DocQuery::AuthCert(ids.clone().into_iter().collect()).split_for_download();
+        let split = DocQuery::AuthCert(ids.clone().into_iter().collect())
+            .split_for_download(MAX_DOCS_PER_REQUEST, 1);
         assert_eq!(split.len(), 5);
         let mut found_ids = HashSet::new();
         for q in split {
@@ -436,7 +509,7 @@ mod test {
             flavor: ConsensusFlavor::Microdesc,
             cache_usage: CacheUsage::CacheOkay,
         };
-        let split = query.clone().split_for_download();
+        let split = query.clone().split_for_download(MAX_DOCS_PER_REQUEST, 1);
         assert_eq!(split, vec![query]);
     }
 
@@ -7,8 +7,11 @@ use crate::DocSource;
 use fs_mistrust::anon_home::PathExt as _;
 use futures::task::SpawnError;
 use thiserror::Error;
-use tor_error::{ErrorKind, HasKind};
+use tor_checkable::TimeValidityError;
+use tor_error::{ErrorKind, HasKind, HasRetryTime, RetryTime};
+use tor_netdoc::doc::netstatus::Lifetime;
 use tor_persist::FsMistrustErrorExt as _;
+use tor_proto::ClockSkew;
 
 /// An error originated by the directory manager code
 #[derive(Error, Debug, Clone)]
@@ -55,6 +58,9 @@ pub enum Error {
     /// Error while accessing a lockfile.
     #[error("Unable to access lock file")]
     LockFile(Arc<std::io::Error>),
+    /// We tried to modify the cache, but another process has it locked.
+    #[error("Directory cache is in use by another process")]
+    CacheReadOnly,
     /// Error while accessing a file in the store.
     #[error("Error while {action} cache file {}", fname.anonymize_home())]
     CacheFile {
@@ -103,6 +109,18 @@ pub enum Error {
     /// An error caused by an expired or not-yet-valid object.
     #[error("Directory object expired or not yet valid")]
     UntimelyObject(#[from] tor_checkable::TimeValidityError),
+    /// We received a consensus that is so far from being valid at our
+    /// current time that our own clock is probably wrong.
+    #[error("Consensus not valid at our current time: is our clock skewed?")]
+    ClockSkew {
+        /// How far we think our clock is from the rest of the network's.
+        ///
+        /// This is a lower bound, based on the consensus's validity interval.
+        estimated_offset: ClockSkew,
+        /// The error we got when checking the consensus.
+        #[source]
+        cause: TimeValidityError,
+    },
     /// An error given by dirclient
     #[error("Problem downloading directory object")]
     DirClientError(#[from] tor_dirclient::Error),
@@ -212,6 +230,66 @@ impl Error {
         Error::LockFile(Arc::new(err))
     }
 
+    /// Construct a new `Error` for a consensus with the declared `lifetime`,
+    /// which failed its timeliness check at `now` with `cause`.
+    ///
+    /// Since consensus documents already come with generous tolerances, we
+    /// suspect our own clock when this happens, and estimate how far off it is.
+    /// But a cache with a stale consensus looks just the same, so a consensus
+    /// from a directory cache should then be checked with
+    /// [`corroborate_clock_skew`](Error::corroborate_clock_skew).
+    pub(crate) fn from_untimely_consensus(
+        cause: TimeValidityError,
+        lifetime: &Lifetime,
+        now: std::time::SystemTime,
+    ) -> Error {
+        let estimated_offset = match &cause {
+            TimeValidityError::NotYetValid(_) => lifetime
+                .valid_after()
+                .duration_since(now)
+                .map_or(ClockSkew::None, ClockSkew::Slow),
+            TimeValidityError::Expired(_) => now
+                .duration_since(lifetime.valid_until())
+                .map_or(ClockSkew::None, ClockSkew::Fast),
+            _ => return Error::UntimelyObject(cause),
+        };
+        Error::ClockSkew {
+            estimated_offset,
+            cause,
+        }
+    }
+
+    /// Check an [`Error::ClockSkew`] against `other_skew`: the clock skew that
+    /// other sources (such as the relays we have connected to) report, if they
+    /// think that it is noteworthy.
+    ///
+    /// Unless the other sources agree that our clock is wrong in the same
+    /// direction, we assume that the cache that sent us the consensus is at
+    /// fault, and return an [`Error::UntimelyObject`] so that we blame it.
+    /// Other errors are returned unchanged.
+    pub(crate) fn corroborate_clock_skew(self, other_skew: Option<ClockSkew>) -> Error {
+        match (self, other_skew) {
+            (
+                Error::ClockSkew {
+                    estimated_offset,
+                    cause,
+                },
+                Some(other),
+            ) if matches!(
+                (estimated_offset, other),
+                (ClockSkew::Slow(_), ClockSkew::Slow(_)) | (ClockSkew::Fast(_), ClockSkew::Fast(_))
+            ) =>
+            {
+                Error::ClockSkew {
+                    estimated_offset,
+                    cause,
+                }
+            }
+            (Error::ClockSkew { cause, .. }, _) => Error::UntimelyObject(cause),
+            (e, _) => e,
+        }
+    }
+
     /// Return true if this error is serious enough that we should mark this
     /// cache as having failed.
     pub(crate) fn indicates_cache_failure(&self) -> bool {
@@ -226,6 +304,9 @@ impl Error {
             | Error::ConsensusInvalid { .. }
             | Error::UntimelyObject(_) => true,
 
+            // Other sources agree that this is a problem with our own clock.
+            Error::ClockSkew { .. } => false,
+
             // These errors cannot come from a directory cache.
             Error::NoDownloadSupport
             | Error::CacheCorruption(_)
@@ -237,6 +318,7 @@ impl Error {
             | Error::ManagerDropped
             | Error::CantAdvanceState
             | Error::LockFile { .. }
+            | Error::CacheReadOnly
             | Error::CacheFile { .. }
             | Error::BadUtf8InCache(_)
             | Error::BadHexInCache(_)
@@ -292,6 +374,7 @@ impl Error {
             | Error::ConsensusDiffError(_)
             | Error::BadUtf8FromDirectory(_)
             | Error::UntimelyObject(_)
+            | Error::ClockSkew { .. }
             | Error::DirClientError(_)
             | Error::SignatureError(_)
             | Error::NetDocError { .. } => BootstrapAction::Nonfatal,
@@ -305,6 +388,7 @@ impl Error {
             | Error::UnrecognizedSchema { .. }
             | Error::ManagerDropped
             | Error::LockFile { .. }
+            | Error::CacheReadOnly
             | Error::CacheFile { .. }
             | Error::BadUtf8InCache(_)
             | Error::BadHexInCache(_)
@@ -355,6 +439,7 @@ impl HasKind for Error {
             E::ManagerDropped => EK::ArtiShuttingDown,
             E::CantAdvanceState => EK::TorAccessFailed,
             E::LockFile { .. } => EK::CacheAccessFailed,
+            E::CacheReadOnly => EK::LocalResourceAlreadyInUse,
             E::CacheFile { .. } => EK::CacheAccessFailed,
             E::ConsensusDiffError(_) => EK::TorProtocolViolation,
             E::NetDocError { source, .. } => match source {
@@ -366,6 +451,7 @@ impl HasKind for Error {
                 DocSource::DirServer { .. } => EK::TorProtocolViolation,
             },
             E::UntimelyObject(_) => EK::TorProtocolViolation,
+            E::ClockSkew { .. } => EK::ClockSkew,
             E::DirClientError(e) => e.kind(),
             E::SignatureError(_) => EK::TorProtocolViolation,
             E::OfflineMode => EK::BadApiUsage,
@@ -376,6 +462,66 @@ impl HasKind for Error {
     }
 }
 
+impl HasRetryTime for Error {
+    fn retry_time(&self) -> RetryTime {
+        use Error as E;
+        #[allow(deprecated)]
+        match self {
+            // These indicate a problem with some particular directory cache,
+            // or with a document that a cache gave us: asking again (probably
+            // from someone else) may help.
+            E::Unwanted(_)
+            | E::NetDirOlder
+            | E::UnrecognizedAuthorities
+            | E::ConsensusDiffError(_)
+            | E::BadUtf8FromDirectory(_)
+            | E::UntimelyObject(_)
+            | E::SignatureError(_)
+            | E::CantAdvanceState
+            | E::DirectoryNotPresent => RetryTime::AfterWaiting,
+
+            // A bad document from a directory server may be replaced by a good
+            // one from somewhere else.  A bad document in our own cache will
+            // still be there, unchanged, next time we look.
+            E::NetDocError { source, .. } | E::ConsensusInvalid { source, .. } => match source {
+                DocSource::LocalCache => RetryTime::Never,
+                DocSource::DirServer { .. } => RetryTime::AfterWaiting,
+            },
+
+            // For this one, we delegate.
+            E::DirClientError(e) => e.retry_time(),
+
+            // We will keep getting this until somebody fixes our clock; but
+            // that might happen at any time.
+            E::ClockSkew { .. } => RetryTime::AfterWaiting,
+
+            // Another process has the cache locked.  It may let go of it later.
+            E::CacheReadOnly => RetryTime::AfterWaiting,
+
+            // These are problems with our configuration, our cache, or our
+            // own code, or they mean we are shutting down.  They are the same
+            // errors that `bootstrap_action` treats as fatal: nothing changes
+            // on its own between one attempt and the next, so retrying won't
+            // fix them.
+            E::NoDownloadSupport
+            | E::OfflineMode
+            | E::CacheCorruption(_)
+            | E::SqliteError(_)
+            | E::UnrecognizedSchema { .. }
+            | E::ManagerDropped
+            | E::LockFile { .. }
+            | E::CacheFile { .. }
+            | E::BadUtf8InCache(_)
+            | E::BadHexInCache(_)
+            | E::CachePermissions(_)
+            | E::CacheAccess(_)
+            | E::Spawn { .. }
+            | E::ExternalDirProvider { .. }
+            | E::Bug(_) => RetryTime::Never,
+        }
+    }
+}
+
 /// Convert a sqlite error code into a real ErrorKind.
 fn sqlite_error_kind(e: &rusqlite::Error) -> ErrorKind {
     use rusqlite::ErrorCode as RE;
@@ -416,3 +562,57 @@ fn sqlite_error_kind(e: &rusqlite::Error) -> ErrorKind {
         _ => EK::Internal,
     }
 }
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn corroborate_clock_skew() {
+        let now = SystemTime::now();
+        let day = Duration::from_secs(86400);
+        // A consensus that expired two days ago: our clock looks fast.
+        let lifetime = Lifetime::new(now - day * 4, now - day * 3, now - day * 2).unwrap();
+        let untimely =
+            || Error::from_untimely_consensus(TimeValidityError::Expired(day * 2), &lifetime, now);
+        assert!(matches!(
+            untimely(),
+            Error::ClockSkew {
+                estimated_offset: ClockSkew::Fast(_),
+                ..
+            }
+        ));
+
+        // Nobody else thinks our clock is wrong: blame the cache.
+        let e = untimely().corroborate_clock_skew(None);
+        assert!(matches!(e, Error::UntimelyObject(_)));
+        assert!(e.indicates_cache_failure());
+
+        // Somebody else thinks our clock is slow: still blame the cache.
+        let e = untimely().corroborate_clock_skew(Some(ClockSkew::Slow(day)));
+        assert!(matches!(e, Error::UntimelyObject(_)));
+
+        // Somebody else agrees that our clock is fast.
+        let e = untimely().corroborate_clock_skew(Some(ClockSkew::Fast(day)));
+        assert!(matches!(e, Error::ClockSkew { .. }));
+        assert!(!e.indicates_cache_failure());
+
+        // Other errors are unchanged.
+        let e = Error::Unwanted("test").corroborate_clock_skew(Some(ClockSkew::Fast(day)));
+        assert!(matches!(e, Error::Unwanted(_)));
+    }
+}
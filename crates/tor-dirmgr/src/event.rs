@@ -22,8 +22,10 @@ use itertools::chain;
 use paste::paste;
 use time::OffsetDateTime;
 use tor_basic_utils::skip_fmt;
+use tor_error::ErrorKind;
 use tor_netdir::DirEvent;
 use tor_netdoc::doc::netstatus;
+use tor_proto::ClockSkew;
 
 #[cfg(feature = "bridge-client")]
 use tor_guardmgr::bridge::BridgeDescEvent;
@@ -347,6 +349,16 @@ pub(crate) struct DirStatus {
     /// How many times has an `update_progress` call not actually moved us
     /// forward since we last advanced the 'progress' on this directory?
     n_stalls: usize,
+    /// The clock skew implied by the most recent consensus we rejected as
+    /// untimely, if we have rejected one since we last advanced the
+    /// 'progress' on this directory.
+    clock_skew: Option<ClockSkew>,
+    /// The kinds of the errors we have encountered since we last advanced the
+    /// 'progress' on this directory, with the number of times we have seen
+    /// each.
+    ///
+    /// This includes failed requests, which are not counted in `n_errors`.
+    error_kinds: Vec<(ErrorKind, usize)>,
 }
 
 /// How much progress have we made in downloading a given directory?
@@ -437,6 +449,30 @@ pub enum DirBlockage {
     /// also indicate a bug in our retry logic.
     #[display(fmt = "Had to reset bootstrapping too many times.")]
     TooManyResets,
+    /// The consensus documents we've received aren't valid at our current
+    /// time, by far more than the usual tolerances allow.
+    ///
+    /// This almost always means that our clock is set incorrectly.
+    #[display(fmt = "Clock seems to be {}.", "describe_skew(estimated_offset)")]
+    ClockSkew {
+        /// How far we think our clock is from the rest of the network's.
+        ///
+        /// This is a lower bound.
+        estimated_offset: ClockSkew,
+    },
+}
+
+/// Return a human-readable description of `skew`.
+fn describe_skew(skew: &ClockSkew) -> String {
+    /// Format the whole-second part of `d`.
+    fn fmt_secs(d: std::time::Duration) -> humantime::FormattedDuration {
+        humantime::format_duration(std::time::Duration::from_secs(d.as_secs()))
+    }
+    match skew {
+        ClockSkew::Slow(d) => format!("slow by at least {}", fmt_secs(*d)),
+        ClockSkew::None => "skewed".into(),
+        ClockSkew::Fast(d) => format!("fast by at least {}", fmt_secs(*d)),
+    }
 }
 
 impl fmt::Display for DirProgress {
@@ -578,6 +614,56 @@ impl DirBootstrapStatus {
         self.statuses().filter_map(|st| st.blockage()).next()
     }
 
+    /// Return the kinds of error that we have encountered while trying to
+    /// make progress on our directory, along with how many times we have seen
+    /// each kind.
+    ///
+    /// Only errors since the last time we made progress are included.
+    /// The result is sorted with the most frequent kind first.
+    pub fn recent_error_kinds(&self) -> Vec<(ErrorKind, usize)> {
+        let mut kinds: Vec<(ErrorKind, usize)> = Vec::new();
+        for (kind, n) in self.statuses().flat_map(|st| st.error_kinds.iter()) {
+            match kinds.iter_mut().find(|(k, _)| k == kind) {
+                Some((_, total)) => *total += n,
+                None => kinds.push((*kind, *n)),
+            }
+        }
+        kinds.sort_by(|a, b| b.1.cmp(&a.1));
+        kinds
+    }
+
+    /// Return true if the most recent consensus that we have is too old to
+    /// use at `now`, even allowing for our clock skew tolerances.
+    ///
+    /// Returns false if we don't have a consensus at all.
+    pub fn consensus_expired_at(&self, now: SystemTime) -> bool {
+        self.statuses()
+            .rev()
+            .filter_map(|st| st.usable_lifetime())
+            .next()
+            .map(|lt| lt.valid_until() < now)
+            .unwrap_or(false)
+    }
+
+    /// Return how many microdescriptors we have for the consensus that we are
+    /// currently fetching them for, and how many it lists.
+    ///
+    /// If we are replacing one directory with another, this describes the
+    /// new one.  Returns None if we aren't fetching microdescriptors for any
+    /// consensus.
+    pub fn microdescs_present(&self) -> Option<MicrodescProgress> {
+        self.statuses().rev().find_map(|st| match &st.progress {
+            DirProgress::Validated {
+                n_mds: (present, total),
+                ..
+            } => Some(MicrodescProgress {
+                present: *present,
+                total: *total,
+            }),
+            _ => None,
+        })
+    }
+
     /// Return the appropriate DirStatus for `AttemptId`, constructing it if
     /// necessary.
     ///
@@ -638,6 +724,8 @@ impl DirBootstrapStatus {
                 // and stalls.
                 status.n_errors = 0;
                 status.n_stalls = 0;
+                status.clock_skew = None;
+                status.error_kinds.clear();
             } else {
                 // This download didn't make progress; increment the stall
                 // count.
@@ -655,6 +743,25 @@ impl DirBootstrapStatus {
         }
     }
 
+    /// Update this status by noting that an error of a given kind has occurred
+    /// in a given download attempt.
+    pub(crate) fn note_error_kind(&mut self, attempt_id: AttemptId, kind: ErrorKind) {
+        if let Some(status) = self.mut_status_for(attempt_id) {
+            match status.error_kinds.iter_mut().find(|(k, _)| *k == kind) {
+                Some((_, n)) => *n += 1,
+                None => status.error_kinds.push((kind, 1)),
+            }
+        }
+    }
+
+    /// Update this status by noting that a consensus we got in a given
+    /// download attempt implied that our clock is off by `skew`.
+    pub(crate) fn note_clock_skew(&mut self, attempt_id: AttemptId, skew: ClockSkew) {
+        if let Some(status) = self.mut_status_for(attempt_id) {
+            status.clock_skew = Some(skew);
+        }
+    }
+
     /// Update this status by noting that we had to reset a given download attempt;
     pub(crate) fn note_reset(&mut self, attempt_id: AttemptId) {
         if let Some(status) = self.mut_status_for(attempt_id) {
@@ -773,7 +880,11 @@ impl DirStatus {
         /// report a blockage?
         const STALL_THRESHOLD: usize = 8;
 
-        if self.n_resets >= RESET_THRESHOLD {
+        if let Some(estimated_offset) = self.clock_skew {
+            // This is the most specific diagnosis we can give, and it tells
+            // the user what to fix.
+            Some(DirBlockage::ClockSkew { estimated_offset })
+        } else if self.n_resets >= RESET_THRESHOLD {
             Some(DirBlockage::TooManyResets)
         } else if self.n_errors >= ERROR_THRESHOLD {
             Some(DirBlockage::TooManyErrors)
@@ -792,6 +903,41 @@ impl DirProgress {
     }
 }
 
+/// How many of the microdescriptors listed in a consensus we have.
+///
+/// Reported by [`DirMgr::microdesc_events`](crate::DirMgr::microdesc_events).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct MicrodescProgress {
+    /// The number of listed microdescriptors that we have.
+    pub present: u32,
+    /// The number of microdescriptors that the consensus lists.
+    pub total: u32,
+}
+
+impl MicrodescProgress {
+    /// Return the percentage of the listed microdescriptors that we have.
+    pub fn percent_present(&self) -> f32 {
+        if self.total == 0 {
+            100.0
+        } else {
+            (self.present as f32) * 100.0 / (self.total as f32)
+        }
+    }
+}
+
+impl fmt::Display for MicrodescProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} microdescriptors ({:.0}%)",
+            self.present,
+            self.total,
+            self.percent_present()
+        )
+    }
+}
+
 /// A stream of [`DirBootstrapStatus`] events.
 #[derive(Clone, Educe)]
 #[educe(Debug)]
@@ -1116,6 +1262,11 @@ mod test {
             0.35 + 0.65 * 0.125,
             abs <= TOL
         );
+        let mds = bs.microdescs_present().unwrap();
+        assert_eq!((mds.present, mds.total), (5, 40));
+        assert_eq!(mds.percent_present(), 12.5);
+        assert_eq!(mds.to_string(), "5/40 microdescriptors (12%)");
+        assert_eq!(DirBootstrapStatus::default().microdescs_present(), None);
 
         // Now try updating.
 
@@ -1180,4 +1331,77 @@ mod test {
         bs.update_progress(attempt2, dp2);
         assert!(bs.current().unwrap().usable_lifetime().is_some());
     }
+    #[test]
+    fn clock_skew_blockage() {
+        use time::macros::datetime;
+        let t1: SystemTime = datetime!(2022-01-17 11:00:00 UTC).into();
+        let hour = Duration::new(3600, 0);
+        let lifetime = netstatus::Lifetime::new(t1, t1 + hour, t1 + hour * 3).unwrap();
+        let attempt = AttemptId::next();
+
+        let mut bs = DirBootstrapStatus::default();
+        bs.note_errors(attempt, 1);
+        assert!(bs.blockage(t1).is_none());
+
+        bs.note_clock_skew(attempt, ClockSkew::Slow(hour * 50));
+        let blockage = bs.blockage(t1).unwrap();
+        assert!(matches!(
+            blockage,
+            DirBlockage::ClockSkew {
+                estimated_offset: ClockSkew::Slow(_)
+            }
+        ));
+        assert_eq!(
+            blockage.to_string(),
+            "Clock seems to be slow by at least 2days 2h."
+        );
+
+        // Once we make progress, we forget about the skew.
+        bs.update_progress(
+            attempt,
+            DirProgress::FetchingCerts {
+                lifetime: lifetime.clone(),
+                usable_lifetime: lifetime,
+                n_certs: (1, 3),
+            },
+        );
+        assert!(bs.blockage(t1).is_none());
+    }
+
+    #[test]
+    fn error_kinds_and_expiry() {
+        use time::macros::datetime;
+        let t1: SystemTime = datetime!(2022-01-17 11:00:00 UTC).into();
+        let hour = Duration::new(3600, 0);
+        let lifetime = netstatus::Lifetime::new(t1, t1 + hour, t1 + hour * 3).unwrap();
+        let attempt = AttemptId::next();
+
+        let mut bs = DirBootstrapStatus::default();
+        assert!(bs.recent_error_kinds().is_empty());
+        assert!(!bs.consensus_expired_at(t1));
+
+        bs.note_error_kind(attempt, ErrorKind::TorProtocolViolation);
+        bs.note_error_kind(attempt, ErrorKind::TorAccessFailed);
+        bs.note_error_kind(attempt, ErrorKind::TorAccessFailed);
+        assert_eq!(
+            bs.recent_error_kinds(),
+            vec![
+                (ErrorKind::TorAccessFailed, 2),
+                (ErrorKind::TorProtocolViolation, 1)
+            ]
+        );
+
+        // Once we make progress, we forget about the errors.
+        bs.update_progress(
+            attempt,
+            DirProgress::FetchingCerts {
+                lifetime: lifetime.clone(),
+                usable_lifetime: lifetime,
+                n_certs: (1, 3),
+            },
+        );
+        assert!(bs.recent_error_kinds().is_empty());
+        assert!(!bs.consensus_expired_at(t1 + hour * 2));
+        assert!(bs.consensus_expired_at(t1 + hour * 4));
+    }
 }
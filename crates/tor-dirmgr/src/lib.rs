@@ -77,9 +77,12 @@ pub use retry::{DownloadSchedule, DownloadScheduleBuilder};
 use scopeguard::ScopeGuard;
 use tor_circmgr::CircMgr;
 use tor_dirclient::SourceInfo;
-use tor_error::{info_report, into_internal, warn_report};
+use tor_error::{info_report, into_internal, warn_report, ErrorKind};
+use tor_events::events::{TorEvent, TorEventKind};
 use tor_netdir::params::NetParameters;
 use tor_netdir::{DirEvent, MdReceiver, NetDir, NetDirProvider};
+use tor_netdoc::doc::netstatus::Lifetime;
+pub use tor_netdoc::doc::netstatus::SignatureWarning;
 
 use async_trait::async_trait;
 use futures::{stream::BoxStream, task::SpawnExt};
@@ -103,10 +106,10 @@ pub use config::{
 };
 pub use docid::DocId;
 pub use err::Error;
-pub use event::{DirBlockage, DirBootstrapEvents, DirBootstrapStatus};
+pub use event::{DirBlockage, DirBootstrapEvents, DirBootstrapStatus, MicrodescProgress};
 pub use storage::DocumentText;
 pub use tor_guardmgr::fallback::{FallbackDir, FallbackDirBuilder};
-pub use tor_netdir::Timeliness;
+pub use tor_netdir::{DirLiveness, DirUsage, Timeliness};
 
 /// Re-export of `strum` crate for use by an internal macro
 use strum;
@@ -182,14 +185,21 @@ impl<R: Runtime> NetDirProvider for DirMgr<R> {
                 .extend_lifetime(netdir.lifetime()),
             Timeliness::Unchecked => return Ok(netdir),
         };
-        let now = SystemTime::now();
-        if lifetime.valid_after() > now {
-            Err(NetDirError::DirNotYetValid)
-        } else if lifetime.valid_until() < now {
-            Err(NetDirError::DirExpired)
-        } else {
-            Ok(netdir)
-        }
+        check_lifetime(netdir, &lifetime, SystemTime::now())
+    }
+
+    fn netdir_for(&self, usage: DirUsage) -> tor_netdir::Result<Arc<NetDir>> {
+        let netdir = self.netdir.get().ok_or(tor_netdir::Error::NoInfo)?;
+        let lifetime = self
+            .config
+            .get()
+            .tolerance
+            .extend_lifetime_for(netdir.lifetime(), usage);
+        check_lifetime(netdir, &lifetime, SystemTime::now())
+    }
+
+    fn liveness(&self) -> DirLiveness {
+        self.liveness_at(SystemTime::now())
     }
 
     fn events(&self) -> BoxStream<'static, DirEvent> {
@@ -291,6 +301,14 @@ pub struct DirMgr<R: Runtime> {
     /// to discard unread events.
     receive_status: DirBootstrapEvents,
 
+    /// A publisher handle that we notify whenever we notice a problem with
+    /// the signatures on a consensus.
+    send_signature_warning: Mutex<watch::Sender<Option<SignatureWarning>>>,
+
+    /// A receiver handle that gets notified whenever we notice a problem with
+    /// the signatures on a consensus.
+    receive_signature_warning: watch::Receiver<Option<SignatureWarning>>,
+
     /// A circuit manager, if this DirMgr supports downloading.
     circmgr: Option<Arc<CircMgr<R>>>,
 
@@ -318,6 +336,15 @@ pub struct DirMgr<R: Runtime> {
 
     /// A task handle that we return to anybody who needs to manage our download process.
     task_handle: TaskHandle,
+
+    /// True if we have been told to conserve power and bandwidth.
+    ///
+    /// See [`DirMgr::set_conserve_resources`].
+    conserve_resources: AtomicBool,
+
+    /// The time at which we next plan to fetch a new consensus, if we are
+    /// currently waiting to do so.
+    next_fetch: Mutex<Option<SystemTime>>,
 }
 
 /// The possible origins of a document.
@@ -520,6 +547,10 @@ impl<R: Runtime> DirMgr<R> {
             })
             .map_err(|e| Error::from_spawn("directory updater task", e))?;
 
+        self.runtime
+            .spawn(Self::monitor_liveness(Arc::downgrade(self)))
+            .map_err(|e| Error::from_spawn("directory liveness monitor", e))?;
+
         if let Some(receiver) = receiver {
             match receiver.await {
                 Ok(()) => {
@@ -733,8 +764,13 @@ impl<R: Runtime> DirMgr<R> {
             let reset_at = state.reset_time();
             match reset_at {
                 Some(t) => {
+                    let t = upgrade_weak_ref(&weak)?.schedule_next_fetch(t);
                     trace!("Sleeping until {}", time::OffsetDateTime::from(t));
                     schedule.sleep_until_wallclock(t).await?;
+                    *upgrade_weak_ref(&weak)?
+                        .next_fetch
+                        .lock()
+                        .expect("poisoned lock") = None;
                 }
                 None => return Ok(()),
             }
@@ -804,6 +840,52 @@ impl<R: Runtime> DirMgr<R> {
         self.receive_status.clone()
     }
 
+    /// Return a stream that tells us about problems with
+    /// the signatures on the consensus documents we try to validate.
+    ///
+    /// We report a [`SignatureWarning`] when a consensus has too few valid
+    /// signatures to be used, or when the authority certificates we need to
+    /// validate it are about to expire.
+    /// When we later validate a consensus without any such problem,
+    /// we report `None`, to say that the warning no longer applies.
+    ///
+    /// The stream starts with the current state of affairs.
+    /// Like [`bootstrap_events`](DirMgr::bootstrap_events), it is
+    /// lossy: if several changes happen before the caller reads the stream,
+    /// only the latest one is observed.
+    pub fn signature_warnings(&self) -> impl futures::Stream<Item = Option<SignatureWarning>> {
+        self.receive_signature_warning.clone()
+    }
+
+    /// Return a stream of [`MicrodescProgress`] events, telling us how many of
+    /// the microdescriptors listed in the consensus we are bootstrapping we
+    /// have so far.
+    ///
+    /// We report an event whenever that number changes.  Like
+    /// [`bootstrap_events`](DirMgr::bootstrap_events), this stream is lossy.
+    pub fn microdesc_events(&self) -> impl futures::Stream<Item = MicrodescProgress> {
+        use futures::StreamExt as _;
+        let mut last = None;
+        self.bootstrap_events().filter_map(move |status| {
+            let progress = status.microdescs_present();
+            let changed = progress.is_some() && progress != last;
+            last = progress;
+            futures::future::ready(progress.filter(|_| changed))
+        })
+    }
+
+    /// If `state` has checked the signatures on its consensus,
+    /// broadcast the outcome to anybody watching via [`DirMgr::signature_warnings`].
+    fn note_signature_warning(&self, state: &mut Box<dyn DirState>) {
+        if let Some(warning) = state.take_signature_warning() {
+            let mut sender = self.send_signature_warning.lock().expect("poisoned lock");
+            // Don't wake up our watchers if nothing has changed.
+            if *sender.borrow() != warning {
+                *sender.borrow_mut() = warning;
+            }
+        }
+    }
+
     /// Replace the latest status with `progress` and broadcast to anybody
     /// watching via a [`DirBootstrapEvents`] stream.
     fn update_progress(&self, attempt_id: AttemptId, progress: DirProgress) {
@@ -826,6 +908,142 @@ impl<R: Runtime> DirMgr<R> {
         status.note_errors(attempt_id, n_errors);
     }
 
+    /// Update our status tracker to note that an error of a given kind has
+    /// occurred.
+    fn note_error_kind(&self, attempt_id: AttemptId, kind: ErrorKind) {
+        let mut sender = self.send_status.lock().expect("poisoned lock");
+        let mut status = sender.borrow_mut();
+
+        status.note_error_kind(attempt_id, kind);
+    }
+
+    /// Tell this `DirMgr` whether the platform wants us to conserve power and
+    /// bandwidth: for example, because we are running on battery power, or
+    /// over a metered connection.
+    ///
+    /// While this is set, we replace each consensus at a random time in the
+    /// last part of the range that the directory protocol allows, rather than
+    /// at a random time anywhere in that range.
+    /// That means fewer downloads, and fewer wakeups.
+    ///
+    /// The change takes effect the next time we schedule a consensus fetch.
+    pub fn set_conserve_resources(&self, conserve: bool) {
+        self.conserve_resources.store(conserve, Ordering::Relaxed);
+    }
+
+    /// Return the time at which we next plan to fetch a new consensus.
+    ///
+    /// Returns `None` if we are not currently waiting to fetch one: for
+    /// example, if we are still bootstrapping, or are in the middle of a
+    /// download.
+    pub fn next_consensus_fetch(&self) -> Option<SystemTime> {
+        *self.next_fetch.lock().expect("poisoned lock")
+    }
+
+    /// Delete everything from our on-disk directory cache.
+    ///
+    /// This is meant for recovering from a cache that is broken in some way
+    /// we don't detect by ourselves.  It doesn't change the directory that
+    /// we are currently using: we will download what we need again
+    /// the next time we fetch a consensus, or the next time we start.
+    ///
+    /// Gives an error if another process is using the cache.
+    pub fn purge_cache(&self) -> Result<()> {
+        if !self.try_upgrade_to_readwrite()? {
+            return Err(Error::CacheReadOnly);
+        }
+        info!("Purging directory cache");
+        self.store
+            .lock()
+            .expect("Directory storage lock poisoned")
+            .purge_all()
+    }
+
+    /// Return the [`DirLiveness`] of our current directory at `now`.
+    fn liveness_at(&self, now: SystemTime) -> DirLiveness {
+        match self.netdir.get() {
+            Some(netdir) => self.config.get().tolerance.liveness(netdir.lifetime(), now),
+            None => DirLiveness::Stale,
+        }
+    }
+
+    /// Broadcast a [`DirEvent::LivenessChanged`] event whenever the
+    /// [`DirLiveness`] of our directory changes, until the `DirMgr` is dropped.
+    async fn monitor_liveness(dirmgr: Weak<Self>) {
+        use futures::{FutureExt as _, StreamExt as _};
+
+        /// The longest we wait before checking our liveness again.
+        ///
+        /// (We also check whenever the directory changes.)
+        const MAX_RECHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+        let mut events = match Weak::upgrade(&dirmgr) {
+            Some(dm) => NetDirProvider::events(&*dm).fuse(),
+            None => return,
+        };
+        let mut last = None;
+        loop {
+            let (runtime, delay) = {
+                let Some(dm) = Weak::upgrade(&dirmgr) else {
+                    return;
+                };
+                let now = dm.runtime.wallclock();
+                let liveness = dm.liveness_at(now);
+                if last.is_some_and(|last| last != liveness) {
+                    debug!("Directory liveness is now {:?}", liveness);
+                    dm.events.publish(DirEvent::LivenessChanged);
+                }
+                last = Some(liveness);
+
+                let next_change = dm.netdir.get().and_then(|netdir| {
+                    dm.config
+                        .get()
+                        .tolerance
+                        .next_liveness_change(netdir.lifetime(), now)
+                });
+                let delay = next_change
+                    .and_then(|t| t.duration_since(now).ok())
+                    .map_or(MAX_RECHECK_INTERVAL, |d| d.min(MAX_RECHECK_INTERVAL));
+                (dm.runtime.clone(), delay)
+            };
+
+            futures::select! {
+                () = runtime.sleep(delay).fuse() => {},
+                event = events.next() => {
+                    if event.is_none() {
+                        return;
+                    }
+                },
+            }
+        }
+    }
+
+    /// Record that the download task wants to fetch a new consensus at
+    /// `planned`, adjusting that time if we have been asked to conserve
+    /// resources.
+    ///
+    /// Return the time at which we should actually fetch.
+    fn schedule_next_fetch(&self, planned: SystemTime) -> SystemTime {
+        let when = match self.netdir.get() {
+            Some(netdir) if self.conserve_resources.load(Ordering::Relaxed) => std::cmp::max(
+                planned,
+                state::pick_conserving_download_time(netdir.lifetime()),
+            ),
+            _ => planned,
+        };
+        *self.next_fetch.lock().expect("poisoned lock") = Some(when);
+        when
+    }
+
+    /// Update our status tracker to note that a consensus we downloaded implied
+    /// that our clock is off by `skew`.
+    fn note_clock_skew(&self, attempt_id: AttemptId, skew: tor_proto::ClockSkew) {
+        let mut sender = self.send_status.lock().expect("poisoned lock");
+        let mut status = sender.borrow_mut();
+
+        status.note_clock_skew(attempt_id, skew);
+    }
+
     /// Update our status tracker to note that we've needed to reset our download attempt.
     fn note_reset(&self, attempt_id: AttemptId) {
         let mut sender = self.send_status.lock().expect("poisoned lock");
@@ -885,6 +1103,8 @@ impl<R: Runtime> DirMgr<R> {
         let receive_status = DirBootstrapEvents {
             inner: receive_status,
         };
+        let (send_signature_warning, receive_signature_warning) = postage::watch::channel();
+        let send_signature_warning = Mutex::new(send_signature_warning);
         #[cfg(feature = "dirfilter")]
         let filter = config.extensions.filter.clone();
 
@@ -900,6 +1120,8 @@ impl<R: Runtime> DirMgr<R> {
             events,
             send_status,
             receive_status,
+            send_signature_warning,
+            receive_signature_warning,
             circmgr,
             runtime,
             offline,
@@ -908,6 +1130,8 @@ impl<R: Runtime> DirMgr<R> {
             filter,
             task_schedule,
             task_handle,
+            conserve_resources: AtomicBool::new(false),
+            next_fetch: Mutex::new(None),
         })
     }
 
@@ -1059,8 +1283,13 @@ impl<R: Runtime> DirMgr<R> {
                     let cfg = self.config.get();
                     let mut netdir = netdir.take().expect("AttemptReplace had None");
                     netdir.replace_overridden_parameters(&cfg.override_net_params);
+                    let event = tor_events::event_has_subscribers(TorEventKind::ConsensusUpdated)
+                        .then(|| consensus_updated_event(netdir.lifetime()));
                     self.netdir.replace(netdir);
                     self.events.publish(DirEvent::NewConsensus);
+                    if let Some(event) = event {
+                        tor_events::broadcast(event);
+                    }
                     self.events.publish(DirEvent::NewDescriptors);
 
                     info!("Marked consensus usable.");
@@ -1098,6 +1327,36 @@ enum Readiness {
     Usable,
 }
 
+/// Return `netdir` if `now` is within `lifetime`, or an error explaining why not.
+fn check_lifetime(
+    netdir: Arc<NetDir>,
+    lifetime: &Lifetime,
+    now: SystemTime,
+) -> tor_netdir::Result<Arc<NetDir>> {
+    use tor_netdir::Error as NetDirError;
+    if lifetime.valid_after() > now {
+        Err(NetDirError::DirNotYetValid)
+    } else if lifetime.valid_until() < now {
+        Err(NetDirError::DirExpired)
+    } else {
+        Ok(netdir)
+    }
+}
+
+/// Return a [`TorEvent::ConsensusUpdated`] for a new consensus with `lifetime`.
+fn consensus_updated_event(lifetime: &Lifetime) -> TorEvent {
+    /// Return `t` as a number of seconds since the Unix epoch.
+    fn unix_secs(t: SystemTime) -> u64 {
+        t.duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+    TorEvent::ConsensusUpdated {
+        valid_after: unix_secs(lifetime.valid_after()),
+        valid_until: unix_secs(lifetime.valid_until()),
+    }
+}
+
 /// Try to upgrade a weak reference to a DirMgr, and give an error on
 /// failure.
 fn upgrade_weak_ref<T>(weak: &Weak<T>) -> Result<Arc<T>> {
@@ -1177,6 +1436,43 @@ mod test {
         });
     }
 
+    #[test]
+    fn fetch_schedule() {
+        tor_rtcompat::test_with_one_runtime!(|rt| async {
+            let (_tempdir, mgr) = new_mgr(rt);
+            assert_eq!(mgr.next_consensus_fetch(), None);
+
+            let planned = mgr.runtime.wallclock() + Duration::from_secs(3600);
+            assert_eq!(mgr.schedule_next_fetch(planned), planned);
+            assert_eq!(mgr.next_consensus_fetch(), Some(planned));
+
+            // Without a directory, we have no lifetime to stretch.
+            mgr.set_conserve_resources(true);
+            assert_eq!(mgr.schedule_next_fetch(planned), planned);
+
+            // With one, we choose a time late in its download range...
+            let netdir = tor_netdir::testnet::construct_netdir()
+                .unwrap_if_sufficient()
+                .unwrap();
+            let lifetime = netdir.lifetime().clone();
+            mgr.netdir.replace(netdir);
+            let (start, range) = state::client_download_range(&lifetime);
+            let early = start - Duration::from_secs(60);
+            let when = mgr.schedule_next_fetch(early);
+            assert!(when >= start + range - range / 4);
+            assert!(when <= start + range);
+            assert_eq!(mgr.next_consensus_fetch(), Some(when));
+
+            // ...unless the download task already planned a later one.
+            let late = start + range + Duration::from_secs(60);
+            assert_eq!(mgr.schedule_next_fetch(late), late);
+
+            // When we stop conserving resources, we keep to the plan.
+            mgr.set_conserve_resources(false);
+            assert_eq!(mgr.schedule_next_fetch(early), early);
+        });
+    }
+
     #[test]
     fn load_and_store_internals() {
         tor_rtcompat::test_with_one_runtime!(|rt| async {
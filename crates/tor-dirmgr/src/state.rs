@@ -20,7 +20,7 @@ use tor_basic_utils::RngExt as _;
 use tor_error::{internal, warn_report};
 use tor_netdir::{MdReceiver, NetDir, PartialNetDir};
 use tor_netdoc::doc::authcert::UncheckedAuthCert;
-use tor_netdoc::doc::netstatus::Lifetime;
+use tor_netdoc::doc::netstatus::{Lifetime, SignatureWarning};
 use tracing::{debug, warn};
 
 use crate::event::DirProgress;
@@ -142,6 +142,14 @@ pub(crate) trait DirState: Send {
     ) -> Result<()>;
     /// Return a summary of this state as a [`DirProgress`].
     fn bootstrap_progress(&self) -> event::DirProgress;
+    /// If this state has checked the signatures on a consensus since the last
+    /// time this was called, return (and forget) the problem it found, if any.
+    ///
+    /// Returns `Some(None)` if the latest check found no problem,
+    /// and `None` if there has been no check.
+    fn take_signature_warning(&mut self) -> Option<Option<SignatureWarning>> {
+        None
+    }
     /// Return a configuration for attempting downloads.
     fn dl_config(&self) -> DownloadSchedule;
     /// If possible, advance to the next state.
@@ -362,7 +370,10 @@ impl<R: Runtime> GetConsensusState<R> {
             let parsed = self.filter.filter_consensus(parsed)?;
             let parsed = self.config.tolerance.extend_tolerance(parsed);
             let now = self.rt.wallclock();
-            let timely = parsed.check_valid_at(&now)?;
+            let lifetime = parsed.dangerously_peek().peek_lifetime().clone();
+            let timely = parsed
+                .check_valid_at(&now)
+                .map_err(|e| Error::from_untimely_consensus(e, &lifetime, now))?;
             if let Some(cutoff) = cutoff {
                 if timely.peek_lifetime().valid_after() < cutoff {
                     return Err(Error::Unwanted("consensus was older than requested"));
@@ -399,6 +410,7 @@ impl<R: Runtime> GetConsensusState<R> {
             consensus_meta,
             missing_certs: desired_certs,
             certs: Vec::new(),
+            signature_warning: None,
             rt: self.rt.clone(),
             config: self.config.clone(),
             prev_netdir: self.prev_netdir.take(),
@@ -430,6 +442,10 @@ enum GetCertsConsensus {
     Failed,
 }
 
+/// How far ahead should we look when warning that the certificates used to
+/// validate a consensus are about to expire?
+const CERT_EXPIRY_WARNING: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
 /// Second state: fetching or loading authority certificates.
 ///
 /// TODO: we should probably do what C tor does, and try to use the
@@ -454,6 +470,9 @@ struct GetCertsState<R: Runtime> {
     missing_certs: HashSet<AuthCertKeyIds>,
     /// A list of the certificates we've been able to load or download.
     certs: Vec<AuthCert>,
+    /// The outcome of our last check of the consensus signatures
+    /// (that is, the problem we found, if any), if we have not yet reported it.
+    signature_warning: Option<Option<SignatureWarning>>,
 
     /// A `Runtime` implementation.
     rt: R,
@@ -517,7 +536,37 @@ impl<R: Runtime> GetCertsState<R> {
             }
         };
 
-        let (new_consensus, outcome) = match unvalidated.check_signature(&self.certs[..]) {
+        let (report, outcome) = unvalidated.check_signature_with_report(
+            &self.certs[..],
+            self.rt.wallclock() + CERT_EXPIRY_WARNING,
+        );
+        let warning = report.warning();
+        match warning {
+            Some(SignatureWarning::BelowThreshold { n_valid, n_needed }) => warn!(
+                "Consensus has valid signatures from only {}/{} of the authorities we need \
+                 ({} invalid, {} with missing certificates, {} unsupported).",
+                n_valid,
+                n_needed,
+                report.invalid.len(),
+                report.missing_cert.len(),
+                report.unsupported.len(),
+            ),
+            Some(SignatureWarning::CertsExpiring {
+                n_valid_after_expiry,
+                n_needed,
+                first_expiry,
+            }) => warn!(
+                "Authority certificates will expire at {}; after that, only {}/{} of the \
+                 authorities we need will have usable certificates.",
+                humantime::format_rfc3339_seconds(first_expiry),
+                n_valid_after_expiry,
+                n_needed,
+            ),
+            _ => {}
+        }
+        self.signature_warning = Some(warning);
+
+        let (new_consensus, outcome) = match outcome {
             Ok(validated) => (C::Validated(validated), Ok(())),
             Err(cause) => (
                 C::Failed,
@@ -561,6 +610,9 @@ impl<R: Runtime> DirState for GetCertsState<R> {
     fn can_advance(&self) -> bool {
         matches!(self.consensus, GetCertsConsensus::Validated(_))
     }
+    fn take_signature_warning(&mut self) -> Option<Option<SignatureWarning>> {
+        self.signature_warning.take()
+    }
     fn bootstrap_progress(&self) -> DirProgress {
         let n_certs = self.certs.len();
         let n_missing_certs = self.missing_certs.len();
@@ -1025,13 +1077,16 @@ impl<R: Runtime> DirState for GetMicrodescsState<R> {
         let mut microdescs = Vec::new();
         for (id, text) in docs {
             if let DocId::Microdesc(digest) = id {
-                if let Ok(md) = Microdesc::parse(text.as_str().map_err(Error::BadUtf8InCache)?) {
+                let parsed = text.as_str().ok().and_then(|t| Microdesc::parse(t).ok());
+                if let Some(md) = parsed {
                     if md.digest() == &digest {
                         microdescs.push(md);
                         continue;
                     }
                 }
-                warn!("Found a mismatched microdescriptor in cache; ignoring");
+                // We treat this microdescriptor as missing, so we'll download
+                // it again; storing the new one will replace this one.
+                warn!("Found a corrupt or mismatched microdescriptor in cache; ignoring");
             }
         }
 
@@ -1162,9 +1217,26 @@ fn pick_download_time(lifetime: &Lifetime) -> SystemTime {
     lowbound + rand::thread_rng().gen_range_infallible(..=uncertainty)
 }
 
+/// The fraction of the download range, at its end, from which we choose a
+/// download time when we have been asked to conserve resources.
+const CONSERVING_FRACTION: u32 = 4;
+
+/// Choose a random download time in the later part of the range in which we
+/// should replace a consensus whose lifetime is `lifetime`.
+///
+/// We use this instead of [`pick_download_time`] when we have been asked to
+/// conserve resources.  We still choose at random, so that clients that are
+/// all conserving resources don't all fetch the next consensus at the same
+/// moment.
+pub(crate) fn pick_conserving_download_time(lifetime: &Lifetime) -> SystemTime {
+    let (lowbound, uncertainty) = client_download_range(lifetime);
+    let late_part = uncertainty / CONSERVING_FRACTION;
+    lowbound + (uncertainty - late_part) + rand::thread_rng().gen_range_infallible(..=late_part)
+}
+
 /// Based on the lifetime for a consensus, return the time range during which
 /// clients should fetch the next one.
-fn client_download_range(lt: &Lifetime) -> (SystemTime, Duration) {
+pub(crate) fn client_download_range(lt: &Lifetime) -> (SystemTime, Duration) {
     let valid_after = lt.valid_after();
     let valid_until = lt.valid_until();
     let voting_interval = lt.voting_period();
@@ -1293,6 +1365,13 @@ mod test {
             assert!(when < vu);
             assert!(when <= expected_start + range);
         }
+
+        let late_start = expected_start + range - range / 4;
+        for _ in 0..100 {
+            let when = pick_conserving_download_time(&lifetime);
+            assert!(when >= late_start);
+            assert!(when <= expected_start + range);
+        }
     }
 
     /// Makes a memory-backed storage.
@@ -1645,6 +1724,9 @@ mod test {
             let missing3 = state.missing_docs();
             assert!(missing3.is_empty());
             assert!(state.can_advance());
+            // Both certificates are good for another year.
+            assert_eq!(state.take_signature_warning(), Some(None));
+            assert_eq!(state.take_signature_warning(), None);
             assert!(!store
                 .lock()
                 .unwrap()
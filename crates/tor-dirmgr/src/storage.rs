@@ -6,6 +6,7 @@
 // storage: Search the git history for tor-dirmgr/src/storage/legacy.rs
 // if you ever need to reinstate it.)
 
+use tor_dirclient::PartialDownload;
 use tor_netdoc::doc::authcert::AuthCertKeyIds;
 use tor_netdoc::doc::microdesc::MdDigest;
 use tor_netdoc::doc::netstatus::ConsensusFlavor;
@@ -248,6 +249,12 @@ pub(crate) trait Store: Send + 'static {
     /// definitely past their good-by date.
     fn expire_all(&mut self, expiration: &ExpirationConfig) -> Result<()>;
 
+    /// Delete every object from the store, including any partial or
+    /// quarantined documents.
+    ///
+    /// Gives an error if the store is read-only.
+    fn purge_all(&mut self) -> Result<()>;
+
     /// Load the latest consensus from disk.
     ///
     /// If `pending` is given, we will only return a consensus with
@@ -287,6 +294,26 @@ pub(crate) trait Store: Send + 'static {
     #[allow(dead_code)] // see also allow on REMOVE_CONSENSUS
     fn delete_consensus(&mut self, cmeta: &ConsensusMeta) -> Result<()>;
 
+    /// Load the part of a consensus of flavor `flavor` that we downloaded
+    /// before our download was interrupted, if we have one.
+    ///
+    /// The partial consensus is stored as the cache sent it (that is,
+    /// possibly compressed), along with its encoding.
+    fn partial_consensus(&self, flavor: ConsensusFlavor) -> Result<Option<PartialDownload>>;
+    /// Save the part of a consensus of flavor `flavor` that we downloaded
+    /// before our download was interrupted, so that we can resume it later.
+    ///
+    /// Replaces any previous partial consensus of the same flavor.
+    fn store_partial_consensus(
+        &mut self,
+        flavor: ConsensusFlavor,
+        partial: &PartialDownload,
+    ) -> Result<()>;
+    /// Forget about any partial consensus of flavor `flavor`.
+    ///
+    /// It's not an error if there isn't one.
+    fn delete_partial_consensus(&mut self, flavor: ConsensusFlavor) -> Result<()>;
+
     /// Read all of the specified authority certs from the cache.
     fn authcerts(&self, certs: &[AuthCertKeyIds]) -> Result<HashMap<AuthCertKeyIds, String>>;
     /// Save a list of authority certificates to the cache.
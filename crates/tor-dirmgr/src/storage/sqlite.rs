@@ -2,15 +2,26 @@
 //!
 //! We store most objects in sqlite tables, except for very large ones,
 //! which we store as "blob" files in a separate directory.
+//!
+//! Since blob files live outside the database, we record a checksum of each
+//! one when we write it, and check it whenever we read the file back.  If a
+//! blob turns out to be corrupt, we move it aside (renaming it with a
+//! `corrupt_` prefix, so that it can be inspected later) and forget about it,
+//! so that we will download the document again.  Quarantined files are
+//! eventually removed by [`Store::expire_all`], like any other file that
+//! isn't listed in the database.
 
 use super::ExpirationConfig;
 use crate::docmeta::{AuthCertMeta, ConsensusMeta};
 use crate::storage::{InputString, Store};
 use crate::{Error, Result};
 
+use digest::Digest;
 use fs_mistrust::CheckedDir;
 use tor_basic_utils::PathExt as _;
+use tor_dirclient::PartialDownload;
 use tor_error::warn_report;
+use tor_llcrypto::d::Sha3_256;
 use tor_netdoc::doc::authcert::AuthCertKeyIds;
 use tor_netdoc::doc::microdesc::MdDigest;
 use tor_netdoc::doc::netstatus::{ConsensusFlavor, Lifetime};
@@ -59,6 +70,9 @@ impl SqliteStore {
     /// read-only or read-write, depending on whether we can acquire
     /// the lock.
     ///
+    /// If the database is corrupt and we have the lock, we move it out of the
+    /// way and start over with an empty one.
+    ///
     /// # Limitations:
     ///
     /// The file locking that we use to ensure that only one dirmgr is
@@ -73,6 +87,7 @@ impl SqliteStore {
     ) -> Result<Self> {
         let path = path.as_ref();
         let sqlpath = path.join("dir.sqlite3");
+        let quarantined_sqlpath = path.join(format!("{}dir.sqlite3", QUARANTINE_PREFIX));
         let blobpath = path.join("dir_blobs/");
         let lockpath = path.join("dir.lock");
 
@@ -107,8 +122,18 @@ impl SqliteStore {
         } else {
             OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE
         };
-        let conn = rusqlite::Connection::open_with_flags(&sqlpath, flags)?;
-        let mut store = SqliteStore::from_conn(conn, blob_dir)?;
+        let open = || {
+            let conn = rusqlite::Connection::open_with_flags(&sqlpath, flags)?;
+            SqliteStore::from_conn(conn, blob_dir.clone())
+        };
+        let mut store = match open() {
+            Err(e) if !readonly && is_corruption(&e) => {
+                warn_report!(e, "The directory cache database is corrupt; starting over");
+                quarantine_db(&sqlpath, &quarantined_sqlpath)?;
+                open()?
+            }
+            other => other?,
+        };
         store.sql_path = Some(sqlpath);
         store.lockfile = Some(lockfile);
         Ok(store)
@@ -195,7 +220,8 @@ impl SqliteStore {
 
     /// Read a blob from disk, mapping it if possible.
     ///
-    /// Return `Ok(None)` if the file for the blob was not found on disk;
+    /// Return `Ok(None)` if the file for the blob was not found on disk,
+    /// or if it did not match its checksum (in which case we quarantine it);
     /// returns an error in other cases.
     fn read_blob(&self, path: &str) -> Result<Option<InputString>> {
         let file = match self.blob_dir.open(path, OpenOptions::new().read(true)) {
@@ -212,13 +238,63 @@ impl SqliteStore {
             Err(e) => return Err(e.into()),
         };
 
-        InputString::load(file)
-            .map_err(|err| Error::CacheFile {
-                action: "loading",
+        let contents = InputString::load(file).map_err(|err| Error::CacheFile {
+            action: "loading",
+            fname: PathBuf::from(path),
+            error: Arc::new(err),
+        })?;
+
+        // Blobs saved before we started recording checksums don't have one.
+        let checksum: Option<String> = self
+            .conn
+            .query_row(FIND_EXTDOC_CHECKSUM, params![path], |row| row.get(0))
+            .optional()?
+            .flatten();
+        if let Some(checksum) = checksum {
+            if hex::encode(Sha3_256::digest(contents.as_ref())) != checksum {
+                self.quarantine_blob(path)?;
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(contents))
+    }
+
+    /// Move the corrupt blob at `path` out of the way, and remove it from the
+    /// database, so that we will download its document again.
+    ///
+    /// If this store is read-only, we only log a warning: whoever has the
+    /// lock will deal with the blob when they next try to read it.
+    fn quarantine_blob(&self, path: &str) -> Result<()> {
+        warn!(
+            "{:?} did not match its checksum; the directory cache is corrupt",
+            path
+        );
+        if self.is_readonly() {
+            return Ok(());
+        }
+
+        let quarantined = format!("{}{}", QUARANTINE_PREFIX, path);
+        let rename = || -> Result<()> {
+            let from = self.blob_dir.join(path)?;
+            let to = self.blob_dir.join(&quarantined)?;
+            std::fs::rename(from, to).map_err(|e| Error::CacheFile {
+                action: "quarantining",
                 fname: PathBuf::from(path),
-                error: Arc::new(err),
+                error: Arc::new(e),
             })
-            .map(Some)
+        };
+        match rename() {
+            Ok(()) => warn!("Moved corrupt file to {:?}", quarantined),
+            Err(e) => {
+                warn_report!(e, "Unable to quarantine {:?}; removing it instead", path);
+                self.remove_blob_or_warn(path);
+            }
+        }
+
+        self.conn
+            .execute(DELETE_EXTDOC_BY_FILENAME, params![path])?;
+        Ok(())
     }
 
     /// Write a file to disk as a blob, and record it in the ExtDocs table.
@@ -250,8 +326,12 @@ impl SqliteStore {
                 err => err.into(),
             })?;
 
+        let checksum = hex::encode(Sha3_256::digest(contents));
         let tx = self.conn.unchecked_transaction()?;
-        tx.execute(INSERT_EXTDOC, params![digeststr, expires, dtype, fname])?;
+        tx.execute(
+            INSERT_EXTDOC,
+            params![digeststr, expires, dtype, fname, checksum],
+        )?;
 
         Ok(SavedBlobHandle {
             tx,
@@ -430,33 +510,57 @@ impl Store for SqliteStore {
         Ok(())
     }
 
+    fn purge_all(&mut self) -> Result<()> {
+        if self.is_readonly() {
+            return Err(Error::CacheReadOnly);
+        }
+
+        let tx = self.conn.transaction()?;
+        tx.execute_batch(PURGE_ALL)?;
+        tx.commit()?;
+
+        // Now that nothing refers to them, remove every file in the blob
+        // directory: this includes partial and quarantined documents.
+        for ent in self.blob_dir.read_directory(".")?.flatten() {
+            self.remove_blob_or_warn(ent.file_name());
+        }
+
+        Ok(())
+    }
+
     fn latest_consensus(
         &self,
         flavor: ConsensusFlavor,
         pending: Option<bool>,
     ) -> Result<Option<InputString>> {
         trace!(?flavor, ?pending, "Loading latest consensus from cache");
-        let rv: Option<(OffsetDateTime, OffsetDateTime, String)> = match pending {
-            None => self
-                .conn
-                .query_row(FIND_CONSENSUS, params![flavor.name()], |row| row.try_into())
-                .optional()?,
-            Some(pending_val) => self
-                .conn
-                .query_row(
-                    FIND_CONSENSUS_P,
-                    params![pending_val, flavor.name()],
-                    |row| row.try_into(),
-                )
-                .optional()?,
-        };
+        loop {
+            let rv: Option<(OffsetDateTime, OffsetDateTime, String)> = match pending {
+                None => self
+                    .conn
+                    .query_row(FIND_CONSENSUS, params![flavor.name()], |row| row.try_into())
+                    .optional()?,
+                Some(pending_val) => self
+                    .conn
+                    .query_row(
+                        FIND_CONSENSUS_P,
+                        params![pending_val, flavor.name()],
+                        |row| row.try_into(),
+                    )
+                    .optional()?,
+            };
 
-        if let Some((_va, _vu, filename)) = rv {
-            // TODO: If the cache is corrupt (because this blob is missing), and the cache has not yet
-            // been cleaned, this may fail to find the latest consensus that we actually have.
-            self.read_blob(&filename)
-        } else {
-            Ok(None)
+            let Some((_va, _vu, filename)) = rv else {
+                return Ok(None);
+            };
+            match self.read_blob(&filename)? {
+                Some(text) => return Ok(Some(text)),
+                // The blob was missing or corrupt, and read_blob has removed
+                // it from the database: try the next-latest consensus.
+                None if !self.is_readonly() => continue,
+                // We can't have removed it, so we would find it again.
+                None => return Ok(None),
+            }
         }
     }
     fn latest_consensus_meta(&self, flavor: ConsensusFlavor) -> Result<Option<ConsensusMeta>> {
@@ -568,6 +672,60 @@ impl Store for SqliteStore {
         Ok(())
     }
 
+    fn partial_consensus(&self, flavor: ConsensusFlavor) -> Result<Option<PartialDownload>> {
+        let contents = match self.blob_dir.read(partial_consensus_fname(flavor)) {
+            Ok(contents) => contents,
+            Err(fs_mistrust::Error::NotFound(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        // The first line is the encoding; the rest is the raw body.
+        let Some(newline) = contents.iter().position(|b| *b == b'\n') else {
+            warn!("Ignoring partial consensus with no encoding");
+            return Ok(None);
+        };
+        let Ok(encoding) = std::str::from_utf8(&contents[..newline]) else {
+            warn!("Ignoring partial consensus with a garbled encoding");
+            return Ok(None);
+        };
+        let encoding = encoding.to_owned();
+        let body = contents[newline + 1..].to_vec();
+        Ok(Some(PartialDownload::new(Some(encoding), body)))
+    }
+    fn store_partial_consensus(
+        &mut self,
+        flavor: ConsensusFlavor,
+        partial: &PartialDownload,
+    ) -> Result<()> {
+        if self.is_readonly() {
+            // Saving a partial consensus is only an optimization; if we
+            // can't write, we just don't do it.
+            return Ok(());
+        }
+        let fname = partial_consensus_fname(flavor);
+        let mut contents = partial.encoding().unwrap_or("identity").as_bytes().to_vec();
+        contents.push(b'\n');
+        contents.extend_from_slice(partial.body());
+        self.blob_dir
+            .write_and_replace(&fname, contents)
+            .map_err(|e| match e {
+                fs_mistrust::Error::Io { err, .. } => Error::CacheFile {
+                    action: "saving",
+                    fname: PathBuf::from(&fname),
+                    error: err,
+                },
+                err => err.into(),
+            })
+    }
+    fn delete_partial_consensus(&mut self, flavor: ConsensusFlavor) -> Result<()> {
+        if self.is_readonly() {
+            return Ok(());
+        }
+        match self.blob_dir.remove_file(partial_consensus_fname(flavor)) {
+            Ok(()) | Err(fs_mistrust::Error::NotFound(_)) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     fn authcerts(&self, certs: &[AuthCertKeyIds]) -> Result<HashMap<AuthCertKeyIds, String>> {
         let mut result = HashMap::new();
         // TODO(nickm): Do I need to get a transaction here for performance?
@@ -779,6 +937,15 @@ impl Drop for Unlinker {
     }
 }
 
+/// Return the name of the file in which we store a partial consensus of a
+/// given flavor.
+///
+/// This file is not listed in the ExtDocs table, so `expire_all` will
+/// remove it once it is old enough to be useless.
+fn partial_consensus_fname(flavor: ConsensusFlavor) -> String {
+    format!("partial_consensus_{}", flavor.name())
+}
+
 /// Convert a hexadecimal sha3-256 digest from the database into an array.
 fn digest_from_hex(s: &str) -> Result<[u8; 32]> {
     let mut bytes = [0_u8; 32];
@@ -889,6 +1056,11 @@ const UPDATE_SCHEMA: &[&str] = &["
     until DATE NOT NULL,
     contents BLOB NOT NULL
   );
+","
+  -- Update the database schema from version 2 to version 3.
+  -- A sha3-256 checksum of the contents of each file, in hex.
+  -- This is NULL for files that we saved before version 3.
+  ALTER TABLE ExtDocs ADD COLUMN checksum TEXT;
 "];
 
 /// Update the database schema version tracking, from each version to the next
@@ -980,6 +1152,11 @@ const FIND_EXPIRED_EXTDOCS: &str = "
   SELECT filename FROM ExtDocs where expires < datetime('now');
 ";
 
+/// Query: find the checksum of the ExtDoc with a given path.
+const FIND_EXTDOC_CHECKSUM: &str = "
+  SELECT checksum FROM ExtDocs WHERE filename = ?;
+";
+
 /// Query: find whether an ExtDoc is listed.
 const COUNT_EXTDOC_BY_PATH: &str = "
   SELECT COUNT(*) FROM ExtDocs WHERE filename = ?;
@@ -987,8 +1164,8 @@ const COUNT_EXTDOC_BY_PATH: &str = "
 
 /// Query: Add a new entry to ExtDocs.
 const INSERT_EXTDOC: &str = "
-  INSERT OR REPLACE INTO ExtDocs ( digest, created, expires, type, filename )
-  VALUES ( ?, datetime('now'), ?, ?, ? );
+  INSERT OR REPLACE INTO ExtDocs ( digest, created, expires, type, filename, checksum )
+  VALUES ( ?, datetime('now'), ?, ?, ?, ? );
 ";
 
 /// Query: Add a new consensus.
@@ -1048,6 +1225,66 @@ const DROP_OLD_EXTDOCS: &str = "DELETE FROM ExtDocs WHERE expires < datetime('no
 /// Query: Discard an extdoc with a given path.
 const DELETE_EXTDOC_BY_FILENAME: &str = "DELETE FROM ExtDocs WHERE filename = ?;";
 
+/// Query: Discard everything we have stored.
+///
+/// (We remove the BridgeDescs rows even if the bridge-client feature is
+/// disabled, since the table always exists.)
+const PURGE_ALL: &str = "
+  DELETE FROM Consensuses;
+  DELETE FROM ExtDocs;
+  DELETE FROM Microdescs;
+  DELETE FROM Authcerts;
+  DELETE FROM RouterDescs;
+  DELETE FROM BridgeDescs;
+";
+
+/// Prefix that we add to the name of a blob file when we quarantine it.
+///
+/// We use it for the database file too.
+const QUARANTINE_PREFIX: &str = "corrupt_";
+
+/// Return true if `e` tells us that the database file is corrupt, or isn't a
+/// database at all.
+fn is_corruption(e: &Error) -> bool {
+    use rusqlite::ErrorCode as RE;
+    match e {
+        Error::SqliteError(e) => matches!(
+            &**e,
+            rusqlite::Error::SqliteFailure(code, _)
+                if matches!(code.code, RE::DatabaseCorrupt | RE::NotADatabase)
+        ),
+        _ => false,
+    }
+}
+
+/// Move the corrupt database at `sqlpath` to `quarantined`, so that we can
+/// build a new one in its place.
+///
+/// We also remove its journal files, if any, since sqlite would otherwise
+/// try to apply them to the new database.
+///
+/// The caller must hold the lock on the store.
+fn quarantine_db(sqlpath: &Path, quarantined: &Path) -> Result<()> {
+    let io_err = |e| Error::CacheFile {
+        action: "quarantining",
+        fname: sqlpath.to_owned(),
+        error: Arc::new(e),
+    };
+    std::fs::rename(sqlpath, quarantined).map_err(io_err)?;
+    warn!("Moved corrupt database to {}", quarantined.display_lossy());
+
+    for suffix in ["-journal", "-wal", "-shm"] {
+        let mut journal = sqlpath.as_os_str().to_owned();
+        journal.push(suffix);
+        match std::fs::remove_file(journal) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(io_err(e)),
+        }
+    }
+    Ok(())
+}
+
 /// Query: Discard every router descriptor that hasn't been listed for 3
 /// months.
 // TODO: Choose a more realistic time.
@@ -1302,6 +1539,134 @@ pub(crate) mod test {
         Ok(())
     }
 
+    #[test]
+    fn corrupt_consensus() -> Result<()> {
+        use tor_netdoc::doc::netstatus;
+
+        let (_tmp_dir, mut store) = new_empty()?;
+        let now = OffsetDateTime::now_utc();
+        let one_hour = 1.hours();
+
+        let cmeta_at = |start: OffsetDateTime, digest: u8| {
+            ConsensusMeta::new(
+                netstatus::Lifetime::new(
+                    start.into(),
+                    (start + one_hour).into(),
+                    SystemTime::from(start + one_hour * 2),
+                )
+                .unwrap(),
+                [digest; 32],
+                [digest; 32],
+            )
+        };
+        let older = cmeta_at(now - one_hour, 0x11);
+        let newer = cmeta_at(now, 0x22);
+        store.store_consensus(&older, ConsensusFlavor::Microdesc, false, "Older consensus")?;
+        store.store_consensus(&newer, ConsensusFlavor::Microdesc, false, "Newer consensus")?;
+
+        let newer_fname: String = store.conn.query_row(
+            "SELECT filename FROM ExtDocs WHERE digest = ?",
+            params![format!("sha3-256-{}", hex::encode([0x22; 32]))],
+            |row| row.get(0),
+        )?;
+        std::fs::write(store.blob_dir.join(&newer_fname)?, "Corrupted consensus").unwrap();
+
+        // We skip the corrupt consensus, and find the older one.
+        let consensus = store
+            .latest_consensus(ConsensusFlavor::Microdesc, None)?
+            .unwrap();
+        assert_eq!(consensus.as_str()?, "Older consensus");
+        assert!(store
+            .consensus_by_sha3_digest_of_signed_part(&[0x22; 32])?
+            .is_none());
+
+        // The corrupt file was moved aside.
+        assert!(std::fs::read(store.blob_dir.join(&newer_fname)?).is_err());
+        let quarantined = format!("{}{}", QUARANTINE_PREFIX, newer_fname);
+        assert_eq!(
+            &std::fs::read(store.blob_dir.join(quarantined)?).unwrap()[..],
+            b"Corrupted consensus"
+        );
+
+        // A file without a checksum (from an older version) is still accepted.
+        store
+            .conn
+            .execute("UPDATE ExtDocs SET checksum = NULL", [])?;
+        let older_fname: String =
+            store
+                .conn
+                .query_row("SELECT filename FROM ExtDocs", [], |row| row.get(0))?;
+        std::fs::write(store.blob_dir.join(&older_fname)?, "Unchecked consensus").unwrap();
+        let consensus = store
+            .latest_consensus(ConsensusFlavor::Microdesc, None)?
+            .unwrap();
+        assert_eq!(consensus.as_str()?, "Unchecked consensus");
+
+        Ok(())
+    }
+
+    #[test]
+    fn purge() -> Result<()> {
+        use tor_netdoc::doc::netstatus;
+
+        let (_tmp_dir, mut store) = new_empty()?;
+        let now = OffsetDateTime::now_utc();
+        let one_hour = 1.hours();
+
+        let cmeta = ConsensusMeta::new(
+            netstatus::Lifetime::new(
+                now.into(),
+                (now + one_hour).into(),
+                SystemTime::from(now + one_hour * 2),
+            )
+            .unwrap(),
+            [0xAB; 32],
+            [0xBC; 32],
+        );
+        store.store_consensus(&cmeta, ConsensusFlavor::Microdesc, false, "A consensus")?;
+        store.store_partial_consensus(
+            ConsensusFlavor::Microdesc,
+            &PartialDownload::new(None, b"A partial".to_vec()),
+        )?;
+        store.store_microdescs(&[("Fake micro 1", &[5; 32])], now.into())?;
+
+        store.purge_all()?;
+
+        assert!(store
+            .latest_consensus(ConsensusFlavor::Microdesc, None)?
+            .is_none());
+        assert!(store
+            .partial_consensus(ConsensusFlavor::Microdesc)?
+            .is_none());
+        assert!(store.microdescs(&[[5; 32]])?.is_empty());
+        assert_eq!(store.blob_dir.read_directory(".")?.count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn partial_consensus() -> Result<()> {
+        let (_tmp_dir, mut store) = new_empty()?;
+        let flavor = ConsensusFlavor::Microdesc;
+
+        assert!(store.partial_consensus(flavor)?.is_none());
+        // Deleting something that isn't there is fine.
+        store.delete_partial_consensus(flavor)?;
+
+        let uncompressed = PartialDownload::new(None, b"network-status-version 3 micro".to_vec());
+        let compressed = PartialDownload::new(Some("x-zstd".into()), vec![0x28, 0xb5, b'\n', 0]);
+        store.store_partial_consensus(flavor, &uncompressed)?;
+        assert_eq!(store.partial_consensus(flavor)?.unwrap(), uncompressed);
+        store.store_partial_consensus(flavor, &compressed)?;
+        assert_eq!(store.partial_consensus(flavor)?.unwrap(), compressed);
+        assert!(store.partial_consensus(ConsensusFlavor::Ns)?.is_none());
+
+        store.delete_partial_consensus(flavor)?;
+        assert!(store.partial_consensus(flavor)?.is_none());
+
+        Ok(())
+    }
+
     #[test]
     fn authcerts() -> Result<()> {
         let (_tmp_dir, mut store) = new_empty()?;
@@ -1436,6 +1801,32 @@ pub(crate) mod test {
         Ok(())
     }
 
+    #[test]
+    fn corrupt_database() -> Result<()> {
+        let tmp = tempdir().unwrap();
+        let mistrust = fs_mistrust::Mistrust::new_dangerously_trust_everyone();
+        let garbage = "This is not a sqlite database.  ".repeat(64);
+        std::fs::write(tmp.path().join("dir.sqlite3"), &garbage).unwrap();
+        std::fs::create_dir(tmp.path().join("dir_blobs")).unwrap();
+
+        // Read-only, we can't do anything about it.
+        let r = SqliteStore::from_path_and_mistrust(tmp.path(), &mistrust, true);
+        assert!(is_corruption(&r.err().unwrap()));
+
+        // Read-write, we move it aside and start over.
+        let mut store = SqliteStore::from_path_and_mistrust(tmp.path(), &mistrust, false)?;
+        assert!(!store.is_readonly());
+        assert!(store
+            .latest_consensus_meta(ConsensusFlavor::Microdesc)?
+            .is_none());
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("corrupt_dir.sqlite3")).unwrap(),
+            garbage
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn orphaned_blobs() -> Result<()> {
         let (_tmp_dir, mut store) = new_empty()?;
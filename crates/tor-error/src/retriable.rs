@@ -127,6 +127,14 @@ pub trait HasRetryTime {
     /// See all caveats and explanations on [`RetryTime`].
     fn retry_time(&self) -> RetryTime;
 
+    /// Return true if retrying the operation that gave this error might help.
+    ///
+    /// This is a convenience wrapper: it returns false only when
+    /// [`retry_time`](HasRetryTime::retry_time) is [`RetryTime::Never`].
+    fn is_retriable(&self) -> bool {
+        self.retry_time().is_retriable()
+    }
+
     /// Return an absolute retry when the operation that gave this error can be
     /// retried.
     ///
@@ -167,6 +175,11 @@ impl AbsRetryTime {
 }
 
 impl RetryTime {
+    /// Return true unless this is [`RetryTime::Never`].
+    pub fn is_retriable(&self) -> bool {
+        !matches!(self, RetryTime::Never)
+    }
+
     /// Convert this [`RetryTime`] in to an absolute time.
     ///
     /// Requires that `now` is the current time, and `choose_delay` is a
@@ -336,6 +349,23 @@ mod test {
         );
     }
 
+    #[test]
+    fn is_retriable() {
+        struct E(RetryTime);
+        impl HasRetryTime for E {
+            fn retry_time(&self) -> RetryTime {
+                self.0
+            }
+        }
+
+        let sec = Duration::from_secs(1);
+        assert!(E(RetryTime::Immediate).is_retriable());
+        assert!(E(RetryTime::AfterWaiting).is_retriable());
+        assert!(E(RetryTime::After(sec)).is_retriable());
+        assert!(E(RetryTime::At(Instant::now() + sec)).is_retriable());
+        assert!(!E(RetryTime::Never).is_retriable());
+    }
+
     #[test]
     fn abs_from_sum() {
         let base = Instant::now();
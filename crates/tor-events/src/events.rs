@@ -2,11 +2,56 @@
 use serde::{Deserialize, Serialize};
 
 /// An event emitted by some Tor-related crate.
+///
+/// # Sensitive information
+///
+/// Events are safe by default: they never contain IP addresses, onion
+/// addresses, or stream targets in the clear.  Fields that might contain such
+/// information are constructed with [`scrubbed`], and so contain the string
+/// `[scrubbed]` unless safe logging has been disabled (see the `safelog`
+/// crate).
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum TorEvent {
     /// An event with no data, used for testing purposes.
     Empty,
+    /// A circuit has been successfully built.
+    CircuitOpened {
+        /// A process-unique identifier for the circuit.
+        circuit: String,
+        /// The identities of the relays in the circuit, in order.
+        path: Vec<String>,
+    },
+    /// A circuit has been closed, or has failed to build.
+    CircuitClosed {
+        /// A process-unique identifier for the circuit.
+        circuit: String,
+        /// A human-readable explanation of why the circuit was closed.
+        reason: String,
+    },
+    /// The set of primary guards we are using has changed.
+    GuardChanged {
+        /// The identities of the current primary guards, in order of preference.
+        primary: Vec<String>,
+    },
+    /// We have a new consensus directory.
+    ConsensusUpdated {
+        /// The `valid-after` time of the new consensus, in seconds since the Unix epoch.
+        valid_after: u64,
+        /// The `valid-until` time of the new consensus, in seconds since the Unix epoch.
+        valid_until: u64,
+    },
+    /// An onion service descriptor has been uploaded to a hidden service directory.
+    HsDescriptorUploaded {
+        /// The onion service whose descriptor was uploaded.
+        ///
+        /// This is [scrubbed](scrubbed) unless safe logging is disabled.
+        service: String,
+        /// The identity of the HsDir that received the descriptor.
+        hsdir: String,
+        /// The revision counter of the uploaded descriptor.
+        revision: u64,
+    },
 }
 
 /// An opaque type describing a variant of `TorEvent`.
@@ -16,13 +61,35 @@ pub enum TorEvent {
 /// variants you want to receive.
 //
 // Internally, these are indices into the `EVENT_SUBSCRIBERS` array.
-// NOTE: Update EVENT_KIND_COUNT when adding new events!!
+// NOTE: Update EVENT_KIND_COUNT and TorEventKind::ALL when adding new events!!
 #[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord, Hash)]
 #[repr(usize)]
 #[non_exhaustive]
 pub enum TorEventKind {
     /// Identifier for [`TorEvent::Empty`].
     Empty = 0,
+    /// Identifier for [`TorEvent::CircuitOpened`].
+    CircuitOpened = 1,
+    /// Identifier for [`TorEvent::CircuitClosed`].
+    CircuitClosed = 2,
+    /// Identifier for [`TorEvent::GuardChanged`].
+    GuardChanged = 3,
+    /// Identifier for [`TorEvent::ConsensusUpdated`].
+    ConsensusUpdated = 4,
+    /// Identifier for [`TorEvent::HsDescriptorUploaded`].
+    HsDescriptorUploaded = 5,
+}
+
+impl TorEventKind {
+    /// Every `TorEventKind`, in order.
+    pub const ALL: &'static [TorEventKind] = &[
+        TorEventKind::Empty,
+        TorEventKind::CircuitOpened,
+        TorEventKind::CircuitClosed,
+        TorEventKind::GuardChanged,
+        TorEventKind::ConsensusUpdated,
+        TorEventKind::HsDescriptorUploaded,
+    ];
 }
 
 impl TorEvent {
@@ -30,6 +97,22 @@ impl TorEvent {
     pub fn kind(&self) -> TorEventKind {
         match self {
             TorEvent::Empty => TorEventKind::Empty,
+            TorEvent::CircuitOpened { .. } => TorEventKind::CircuitOpened,
+            TorEvent::CircuitClosed { .. } => TorEventKind::CircuitClosed,
+            TorEvent::GuardChanged { .. } => TorEventKind::GuardChanged,
+            TorEvent::ConsensusUpdated { .. } => TorEventKind::ConsensusUpdated,
+            TorEvent::HsDescriptorUploaded { .. } => TorEventKind::HsDescriptorUploaded,
         }
     }
 }
+
+/// Format `value` for inclusion in a [`TorEvent`], respecting safe logging.
+///
+/// Returns `[scrubbed]` unless safe logging is disabled, in which case
+/// returns the `Display` representation of `value`.
+///
+/// Event emitters should use this for any field that might contain
+/// an address or other sensitive information.
+pub fn scrubbed<T: std::fmt::Display>(value: T) -> String {
+    safelog::sensitive(value).to_string()
+}
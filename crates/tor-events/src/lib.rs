@@ -46,22 +46,27 @@ pub mod events;
 use crate::events::{TorEvent, TorEventKind};
 use async_broadcast::{InactiveReceiver, Receiver, Sender, TrySendError};
 use futures::channel::mpsc;
-use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
 use futures::future::Either;
 use futures::StreamExt;
 use once_cell::sync::OnceCell;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 use std::task::{Context, Poll};
 use thiserror::Error;
 use tracing::{error, warn};
 
-/// Pointer to an `UnboundedSender`, used to send events into the `EventReactor`.
-static EVENT_SENDER: OnceCell<UnboundedSender<TorEvent>> = OnceCell::new();
+/// Pointer to an `mpsc::Sender`, used to send events into the `EventReactor`.
+///
+/// This is behind a `Mutex`, since sending requires mutable access.
+static EVENT_SENDER: OnceCell<Mutex<mpsc::Sender<TorEvent>>> = OnceCell::new();
+/// The number of events that we have discarded because the queue to the
+/// `EventReactor` was full, since the reactor last reported them.
+static DROPPED_EVENTS: AtomicUsize = AtomicUsize::new(0);
 /// An inactive receiver for the currently active broadcast channel, if there is one.
 static CURRENT_RECEIVER: OnceCell<InactiveReceiver<TorEvent>> = OnceCell::new();
 /// The number of `TorEventKind`s there are.
-const EVENT_KIND_COUNT: usize = 1;
+const EVENT_KIND_COUNT: usize = 6;
 /// An array containing one `AtomicUsize` for each `TorEventKind`, used to track subscriptions.
 ///
 /// When a `TorEventReceiver` subscribes to a `TorEventKind`, it uses its `usize` value to index
@@ -71,10 +76,33 @@ const EVENT_KIND_COUNT: usize = 1;
 static EVENT_SUBSCRIBERS: [AtomicUsize; EVENT_KIND_COUNT] = [AtomicUsize::new(0); EVENT_KIND_COUNT];
 
 /// The size of the internal broadcast channel used to implement event subscription.
+///
+/// A receiver that falls this many events behind starts to miss the oldest
+/// ones: see [`EventReactor`].
 pub static BROADCAST_CAPACITY: usize = 512;
 
+/// The number of events that can be waiting for the [`EventReactor`] to
+/// forward them.
+///
+/// Once this many are waiting, further events are discarded: see
+/// [`EventReactor`].
+pub static QUEUE_CAPACITY: usize = 512;
+
 /// A reactor used to forward events to make the event reporting system work.
 ///
+/// # Overflow policy
+///
+/// Emitting an event never blocks, and the event system never holds more
+/// than a bounded number of events.  Instead, when events arrive faster than
+/// they can be handled, some are lost:
+///
+///  * If more than [`QUEUE_CAPACITY`] events are waiting for the reactor
+///    (for example, because it isn't running), new events are discarded.
+///    The reactor logs a warning saying how many, once it catches up.
+///  * If a [`TorEventReceiver`] falls more than [`BROADCAST_CAPACITY`] events
+///    behind, the oldest events that it hasn't read are discarded, so that it
+///    can't hold up the other receivers.
+///
 /// # Note
 ///
 /// Currently, this type is a singleton; there is one event reporting system used for the entire
@@ -82,11 +110,15 @@ pub static BROADCAST_CAPACITY: usize = 512;
 pub struct EventReactor {
     /// A receiver that the reactor uses to learn about incoming events.
     ///
-    /// This is unbounded so that event publication doesn't have to be async.
-    receiver: UnboundedReceiver<TorEvent>,
+    /// This is bounded, so that events can't pile up if the reactor isn't
+    /// running; senders discard events rather than waiting.
+    receiver: mpsc::Receiver<TorEvent>,
     /// A sender that the reactor uses to publish events.
     ///
     /// Events are only sent here if at least one subscriber currently wants them.
+    ///
+    /// This is in overflow mode, so that publishing never waits for
+    /// receivers that are falling behind.
     broadcast: Sender<TorEvent>,
 }
 
@@ -98,11 +130,13 @@ impl EventReactor {
     /// # Warnings
     ///
     /// The returned reactor *must* be run with `EventReactor::run`, in a background async task.
-    /// If it is not, the event system might consume unbounded amounts of memory.
+    /// If it is not, no events will be delivered.
     pub fn new() -> Option<Self> {
-        let (tx, rx) = mpsc::unbounded();
-        if EVENT_SENDER.set(tx).is_ok() {
-            let (btx, brx) = async_broadcast::broadcast(BROADCAST_CAPACITY);
+        // (The channel has one more slot than we ask for, for its only sender.)
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY.saturating_sub(1));
+        if EVENT_SENDER.set(Mutex::new(tx)).is_ok() {
+            let (mut btx, brx) = async_broadcast::broadcast(BROADCAST_CAPACITY);
+            btx.set_overflow(true);
             CURRENT_RECEIVER
                 .set(brx.deactivate())
                 .expect("CURRENT_RECEIVER can't be set if EVENT_SENDER is unset!");
@@ -128,16 +162,23 @@ impl EventReactor {
     /// You *must* call this function once a reactor is created.
     pub async fn run(mut self) {
         while let Some(event) = self.receiver.next().await {
+            let dropped = DROPPED_EVENTS.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                warn!(
+                    "Event queue was full: discarded {} event(s). Is the event reactor running?",
+                    dropped
+                );
+            }
             match self.broadcast.try_broadcast(event) {
-                Ok(_) => {}
+                Ok(None) => {}
+                Ok(Some(_oldest)) => {
+                    // The channel was full, so we discarded the oldest event in it.  Some
+                    // receivers will miss it.
+                }
                 Err(TrySendError::Closed(_)) => break,
-                Err(TrySendError::Full(event)) => {
-                    // If the channel is full, do a blocking broadcast to wait for it to be
-                    // not full, and log a warning about receivers lagging behind.
-                    warn!("TorEventReceivers aren't receiving events fast enough!");
-                    if self.broadcast.broadcast(event).await.is_err() {
-                        break;
-                    }
+                Err(TrySendError::Full(_)) => {
+                    // This can't happen in overflow mode.
+                    error!("Event broadcast channel full despite overflow mode");
                 }
                 Err(TrySendError::Inactive(_)) => {
                     // no active receivers, so just drop the event on the floor.
@@ -171,7 +212,8 @@ pub enum ReceiverError {
 /// # Warning
 ///
 /// Once interest in events has been signalled with `subscribe`, events must be continuously
-/// read from the receiver in order to avoid excessive memory consumption.
+/// read from the receiver.  A receiver that falls more than [`BROADCAST_CAPACITY`] events
+/// behind silently misses the oldest ones.
 #[derive(Clone, Debug)]
 pub struct TorEventReceiver {
     /// If no events have been subscribed to yet, this is an `InactiveReceiver`; otherwise,
@@ -229,6 +271,15 @@ impl TorEventReceiver {
             self.inner = Either::Left(inactive.activate());
         }
     }
+    /// Subscribe to every kind of `TorEvent`.
+    ///
+    /// This is equivalent to calling `TorEventReceiver::subscribe` for every
+    /// member of [`TorEventKind::ALL`].
+    pub fn subscribe_all(&mut self) {
+        for kind in TorEventKind::ALL {
+            self.subscribe(*kind);
+        }
+    }
     /// Unsubscribe from a given kind of `TorEvent`.
     ///
     /// After calling this function, `TorEventReceiver::recv` will no longer emit events of that
@@ -275,6 +326,9 @@ pub fn event_has_subscribers(kind: TorEventKind) -> bool {
 /// As an optimization, does nothing if the event has no subscribers (`event_has_subscribers`
 /// returns false). (also does nothing if the event subsystem hasn't been initialized yet)
 ///
+/// This never blocks: if too many events are already waiting to be forwarded,
+/// `event` is discarded.  See [`EventReactor`] for details.
+///
 /// This function isn't intended for use outside Arti crates (as in, library consumers of Arti
 /// shouldn't broadcast events!).
 pub fn broadcast(event: TorEvent) {
@@ -282,8 +336,15 @@ pub fn broadcast(event: TorEvent) {
         return;
     }
     if let Some(sender) = EVENT_SENDER.get() {
-        // If this fails, there isn't much we can really do about it!
-        let _ = sender.unbounded_send(event);
+        let result = sender.lock().expect("lock poisoned").try_send(event);
+        match result {
+            Ok(()) => {}
+            Err(e) if e.is_full() => {
+                DROPPED_EVENTS.fetch_add(1, Ordering::Relaxed);
+            }
+            // If the reactor is gone, there isn't much we can really do about it!
+            Err(_) => {}
+        }
     }
 }
 
@@ -304,6 +365,7 @@ mod test {
     //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
     use crate::{
         broadcast, event_has_subscribers, EventReactor, StreamExt, TorEvent, TorEventKind,
+        BROADCAST_CAPACITY,
     };
     use once_cell::sync::OnceCell;
     use std::sync::{Mutex, MutexGuard};
@@ -368,6 +430,75 @@ mod test {
         });
     }
 
+    #[test]
+    fn subscribe_all() {
+        let rt = test_setup();
+
+        rt.block_on(async move {
+            let mut rx = EventReactor::receiver().unwrap();
+            rx.subscribe_all();
+            for kind in TorEventKind::ALL {
+                assert!(event_has_subscribers(*kind));
+            }
+
+            rx.unsubscribe(TorEventKind::GuardChanged);
+            assert!(!event_has_subscribers(TorEventKind::GuardChanged));
+            assert!(event_has_subscribers(TorEventKind::CircuitOpened));
+
+            std::mem::drop(rx);
+            for kind in TorEventKind::ALL {
+                assert!(!event_has_subscribers(*kind));
+            }
+        });
+    }
+
+    #[test]
+    fn kinds() {
+        // Every kind must be a valid index, and must appear in ALL in order.
+        assert_eq!(TorEventKind::ALL.len(), super::EVENT_KIND_COUNT);
+        for (i, kind) in TorEventKind::ALL.iter().enumerate() {
+            assert_eq!(*kind as usize, i);
+        }
+
+        let ev = TorEvent::ConsensusUpdated {
+            valid_after: 1000,
+            valid_until: 4600,
+        };
+        assert_eq!(ev.kind(), TorEventKind::ConsensusUpdated);
+    }
+
+    #[test]
+    fn scrubbing() {
+        use crate::events::scrubbed;
+        let addr: std::net::IpAddr = "192.0.2.7".parse().unwrap();
+        assert_eq!(scrubbed(addr), "[scrubbed]");
+        let shown = safelog::with_safe_logging_suppressed(|| scrubbed(addr));
+        assert_eq!(shown, "192.0.2.7");
+    }
+
+    #[test]
+    fn filters_events() {
+        let rt = test_setup();
+
+        rt.block_on(async move {
+            let mut rx = EventReactor::receiver().unwrap();
+            rx.subscribe(TorEventKind::CircuitClosed);
+            // HACK(eta): give the event reactor time to run
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            // We aren't subscribed to this, so we shouldn't see it.
+            broadcast(TorEvent::GuardChanged { primary: vec![] });
+            let closed = TorEvent::CircuitClosed {
+                circuit: "Circ 7.1".into(),
+                reason: "timeout".into(),
+            };
+            broadcast(closed.clone());
+
+            let result = rx.next().await;
+            assert_eq!(result, Some(closed));
+        });
+    }
+
     #[test]
     fn empty_recv() {
         let rt = test_setup();
@@ -412,4 +543,36 @@ mod test {
             assert!(result.is_err());
         });
     }
+
+    #[test]
+    fn lagging_receiver_loses_oldest() {
+        let rt = test_setup();
+
+        rt.block_on(async move {
+            let mut rx = EventReactor::receiver().unwrap();
+            rx.subscribe(TorEventKind::CircuitClosed);
+            // HACK(eta): give the event reactor time to run
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            let closed = |n: usize| TorEvent::CircuitClosed {
+                circuit: format!("Circ {}", n),
+                reason: "closed".into(),
+            };
+            // Send twice as many events as the receiver can hold, without reading any.
+            // This must not block.
+            for n in 0..BROADCAST_CAPACITY * 2 {
+                broadcast(closed(n));
+                if n % 64 == 63 {
+                    // Let the reactor keep up, so that nothing is lost from its queue.
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            // The oldest events were discarded; the newest are all there.
+            for n in BROADCAST_CAPACITY..BROADCAST_CAPACITY * 2 {
+                assert_eq!(rx.next().await, Some(closed(n)));
+            }
+        });
+    }
 }
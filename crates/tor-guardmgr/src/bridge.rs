@@ -9,6 +9,7 @@
 //! regular set of guards in building the first hop of its circuits.
 mod config;
 mod descs;
+pub mod moat;
 mod relay;
 
 pub use config::{BridgeConfig, BridgeConfigBuilder, BridgeParseError};
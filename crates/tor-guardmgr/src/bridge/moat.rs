@@ -0,0 +1,483 @@
+//! Support for learning bridges from a "moat" bridge distribution server.
+//!
+//! Moat is the JSON-over-HTTPS protocol that the Tor Project's bridge
+//! distributor (rdsys, formerly BridgeDB) offers to clients that cannot
+//! reach the Tor network.  Since the distributor is itself likely to be
+//! blocked, clients usually reach it through a domain-fronted CDN.
+//!
+//! This module knows how to ask a moat server for "circumvention settings",
+//! over a stream that is already connected to it, and how to turn the
+//! server's reply into [`BridgeConfigBuilder`]s that can be added to a
+//! client's bridge configuration.
+//!
+//! It does not make the connection itself.  Usually, the stream goes through
+//! a `meek` pluggable transport, which takes care of the domain fronting and
+//! of TLS: see [`fetch_settings`].
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+use safelog::sensitive;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tor_error::{ErrorKind, HasKind};
+use tor_rtcompat::{SleepProvider, SleepProviderExt as _};
+use tracing::warn;
+
+use super::{BridgeConfigBuilder, BridgeParseError};
+
+/// The path, on a moat server, of the "circumvention settings" endpoint.
+pub const SETTINGS_PATH: &str = "/moat/circumvention/settings";
+
+/// The host name that we put in the `Host` header of our requests.
+///
+/// The stream to the moat server decides which server we actually reach:
+/// this is only for the server's benefit.
+pub const MOAT_HOST: &str = "bridges.torproject.org";
+
+/// The largest reply that we accept from a moat server.
+const MAX_REPLY_LEN: usize = 1024 * 1024;
+
+/// A request for the bridges that we should use to get around censorship.
+#[derive(Debug, Clone, Default, Serialize)]
+#[non_exhaustive]
+pub struct SettingsRequest {
+    /// The two-letter country code where we are, if we know it.
+    ///
+    /// If this is not given, the server guesses based on our address
+    /// (which, if we are using a domain front, will be wrong).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+
+    /// The pluggable transports that we support, such as `obfs4`.
+    ///
+    /// If this is empty, the server assumes that we support every transport
+    /// it knows about.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub transports: Vec<String>,
+}
+
+impl SettingsRequest {
+    /// Return the body of this request, as JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Couldn't encode a SettingsRequest as JSON")
+    }
+}
+
+/// An error from trying to learn bridges from a moat server.
+#[derive(Error, Clone, Debug)]
+#[non_exhaustive]
+pub enum MoatError {
+    /// We couldn't send our request or read the reply.
+    #[error("Unable to talk to bridge distributor")]
+    Io(#[source] Arc<io::Error>),
+
+    /// The server's reply wasn't valid HTTP.
+    #[error("Invalid HTTP reply from bridge distributor: {0}")]
+    InvalidHttp(&'static str),
+
+    /// The server's reply had an HTTP status other than 200.
+    #[error("Bridge distributor replied with HTTP status {0}")]
+    HttpStatus(u16),
+
+    /// The server's reply wasn't the JSON that we expected.
+    #[error("Invalid reply from bridge distributor")]
+    InvalidJson(#[source] Arc<serde_json::Error>),
+
+    /// The server said that it couldn't help us.
+    #[error("Bridge distributor reported error {code}: {detail}")]
+    Server {
+        /// The error code, which is usually an HTTP status code.
+        code: u16,
+        /// The server's explanation.
+        detail: String,
+    },
+
+    /// The server took too long to answer.
+    #[error("Timed out waiting for bridge distributor")]
+    Timeout,
+}
+
+impl HasKind for MoatError {
+    fn kind(&self) -> ErrorKind {
+        use ErrorKind as EK;
+        use MoatError as E;
+        match self {
+            E::Io(_) | E::HttpStatus(_) | E::Server { .. } | E::Timeout => EK::TorAccessFailed,
+            E::InvalidHttp(_) | E::InvalidJson(_) => EK::RemoteProtocolViolation,
+        }
+    }
+}
+
+/// The bridges that a moat server told us to use.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct MoatBridges {
+    /// The bridges that we could parse.
+    pub bridges: Vec<BridgeConfigBuilder>,
+
+    /// The bridge lines that we could not parse, and why.
+    ///
+    /// For example, lines for pluggable transports end up here if we were
+    /// built without pluggable transport support.
+    pub rejected: Vec<(String, BridgeParseError)>,
+}
+
+/// A reply to a [`SettingsRequest`].
+#[derive(Deserialize)]
+struct SettingsResponse {
+    /// The ways to reach the Tor network that the server suggests.
+    ///
+    /// This is missing or empty if the server thinks we are not censored.
+    #[serde(default)]
+    settings: Vec<Setting>,
+    /// Problems that the server reported instead.
+    #[serde(default)]
+    errors: Vec<ServerError>,
+}
+
+/// One suggested way of reaching the Tor network.
+#[derive(Deserialize)]
+struct Setting {
+    /// The bridges to use.
+    bridges: BridgeSet,
+}
+
+/// A set of bridges from a moat server.
+#[derive(Deserialize)]
+struct BridgeSet {
+    /// The bridge lines.
+    ///
+    /// This is missing when the server tells us to use the bridges that are
+    /// built into our client (`"source": "builtin"`), since it expects that we
+    /// know them already.
+    #[serde(default)]
+    bridge_strings: Vec<String>,
+}
+
+/// An error as reported by a moat server.
+#[derive(Deserialize)]
+struct ServerError {
+    /// The error code.
+    code: u16,
+    /// A description of what went wrong.
+    #[serde(default)]
+    detail: String,
+}
+
+/// Send `request` to a moat server over `stream`, and return the bridges that
+/// the server lists in its reply.
+///
+/// `stream` must already be connected to the server.  Usually it is a stream
+/// through a `meek` pluggable transport, whose `url` and `front` parameters
+/// say how to reach the server, and which takes care of domain fronting and
+/// TLS.  We speak plain HTTP/1.0 over it, and read the reply until the server
+/// closes the stream.
+///
+/// If the whole exchange takes longer than `timeout`, we give up, and return
+/// [`MoatError::Timeout`].
+pub async fn fetch_settings<SP, S>(
+    sleep_prov: &SP,
+    stream: S,
+    request: &SettingsRequest,
+    timeout: Duration,
+) -> Result<MoatBridges, MoatError>
+where
+    SP: SleepProvider,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let reply = sleep_prov
+        .timeout(timeout, exchange(stream, request))
+        .await
+        .map_err(|_| MoatError::Timeout)??;
+
+    parse_settings_response(http_reply_body(&reply)?)
+}
+
+/// Send `request` as an HTTP request over `stream`, and return the raw reply.
+async fn exchange<S>(mut stream: S, request: &SettingsRequest) -> Result<Vec<u8>, MoatError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let io_err = |e| MoatError::Io(Arc::new(e));
+
+    let body = request.to_json();
+    let head = format!(
+        "POST {SETTINGS_PATH} HTTP/1.0\r\n\
+         Host: {MOAT_HOST}\r\n\
+         Content-Type: application/vnd.api+json\r\n\
+         Content-Length: {}\r\n\
+         \r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await.map_err(io_err)?;
+    stream.write_all(body.as_bytes()).await.map_err(io_err)?;
+    stream.flush().await.map_err(io_err)?;
+
+    let mut reply = Vec::new();
+    (&mut stream)
+        .take(MAX_REPLY_LEN as u64 + 1)
+        .read_to_end(&mut reply)
+        .await
+        .map_err(io_err)?;
+    if reply.len() > MAX_REPLY_LEN {
+        return Err(MoatError::InvalidHttp("reply too long"));
+    }
+
+    Ok(reply)
+}
+
+/// Check that `reply` is a successful HTTP reply, and return its body.
+fn http_reply_body(reply: &[u8]) -> Result<&str, MoatError> {
+    let reply = std::str::from_utf8(reply).map_err(|_| MoatError::InvalidHttp("not UTF-8"))?;
+    let (head, body) = reply
+        .split_once("\r\n\r\n")
+        .ok_or(MoatError::InvalidHttp("truncated reply"))?;
+
+    // The status line looks like "HTTP/1.1 200 OK".
+    let status_line = head.split("\r\n").next().unwrap_or_default();
+    let mut words = status_line.splitn(3, ' ');
+    let version = words.next().unwrap_or_default();
+    let status = words.next().and_then(|s| s.parse::<u16>().ok());
+    match status {
+        Some(200) if version.starts_with("HTTP/1.") => Ok(body),
+        Some(status) if version.starts_with("HTTP/1.") => Err(MoatError::HttpStatus(status)),
+        _ => Err(MoatError::InvalidHttp("bad status line")),
+    }
+}
+
+/// Parse the body of a reply to a [`SettingsRequest`], and return the bridges
+/// that it lists.
+///
+/// The bridge lines are parsed in the same way as the lines in our
+/// configuration file, including any pluggable transport parameters.  Lines
+/// that we can't parse are skipped, logged, and listed in
+/// [`MoatBridges::rejected`]: one bad line doesn't stop us from using the
+/// others.
+///
+/// Returns no bridges if the server thinks we don't need bridges, or only
+/// suggests bridges that it expects us to have built in.
+pub fn parse_settings_response(body: &str) -> Result<MoatBridges, MoatError> {
+    let response: SettingsResponse =
+        serde_json::from_str(body).map_err(|e| MoatError::InvalidJson(Arc::new(e)))?;
+
+    if let Some(error) = response.errors.into_iter().next() {
+        return Err(MoatError::Server {
+            code: error.code,
+            detail: error.detail,
+        });
+    }
+
+    let mut bridges = MoatBridges::default();
+    for line in response
+        .settings
+        .into_iter()
+        .flat_map(|setting| setting.bridges.bridge_strings)
+    {
+        match line.parse() {
+            Ok(bridge) => bridges.bridges.push(bridge),
+            Err(e) => {
+                warn!(
+                    "Ignoring unusable bridge line {} from bridge distributor: {}",
+                    sensitive(&line),
+                    e
+                );
+                bridges.rejected.push((line, e));
+            }
+        }
+    }
+    Ok(bridges)
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use tor_rtmock::MockRuntime;
+
+    #[test]
+    fn request() {
+        assert_eq!(SettingsRequest::default().to_json(), "{}");
+
+        let req = SettingsRequest {
+            country: Some("cn".into()),
+            transports: vec!["obfs4".into(), "snowflake".into()],
+        };
+        assert_eq!(
+            req.to_json(),
+            r#"{"country":"cn","transports":["obfs4","snowflake"]}"#
+        );
+    }
+
+    #[test]
+    fn response() {
+        let body = r#"{
+          "settings": [
+            {
+              "bridges": {
+                "type": "obfs4",
+                "source": "bridgedb",
+                "bridge_strings": [
+                  "38.229.33.83:80 0BAC39417268B96B9F514E7F63FA6FBA1A788955",
+                  "192.0.2.7:443 7DD62766BF2052432051D7B7E08A22F7E34A4543"
+                ]
+              }
+            },
+            {
+              "bridges": { "type": "snowflake", "source": "builtin" }
+            }
+          ],
+          "country": "cn"
+        }"#;
+        let bridges = parse_settings_response(body).unwrap();
+        assert_eq!(bridges.bridges.len(), 2);
+        assert!(bridges.rejected.is_empty());
+        for bridge in bridges.bridges {
+            bridge.build().unwrap();
+        }
+    }
+
+    #[cfg(feature = "pt-client")]
+    #[test]
+    fn response_pt() {
+        let body = r#"{"settings": [{"bridges": {"type": "obfs4", "source": "bridgedb",
+            "bridge_strings": ["obfs4 192.0.2.7:443 7DD62766BF2052432051D7B7E08A22F7E34A4543 cert=AAAA iat-mode=0"]
+        }}]}"#;
+        let bridges = parse_settings_response(body).unwrap();
+        assert_eq!(bridges.bridges.len(), 1);
+        let bridge = bridges.bridges[0].build().unwrap();
+        let s = bridge.to_string();
+        assert!(s.starts_with("obfs4 192.0.2.7:443"), "{s}");
+        assert!(s.contains("cert=AAAA"), "{s}");
+        assert!(s.contains("iat-mode=0"), "{s}");
+    }
+
+    #[test]
+    fn response_uncensored() {
+        assert!(parse_settings_response(r#"{"settings": []}"#)
+            .unwrap()
+            .bridges
+            .is_empty());
+        assert!(parse_settings_response("{}").unwrap().bridges.is_empty());
+    }
+
+    #[test]
+    fn response_errors() {
+        let body = r#"{"errors": [{"code": 406, "detail": "Country not supported"}]}"#;
+        match parse_settings_response(body) {
+            Err(MoatError::Server { code, detail }) => {
+                assert_eq!(code, 406);
+                assert_eq!(detail, "Country not supported");
+            }
+            other => panic!("{other:?}"),
+        }
+
+        assert!(matches!(
+            parse_settings_response("<html>"),
+            Err(MoatError::InvalidJson(_))
+        ));
+    }
+
+    #[test]
+    fn response_bad_lines() {
+        let body = r#"{"settings": [{"bridges": {"bridge_strings": [
+            "192.0.2.7:443 NOTAFINGERPRINT",
+            "38.229.33.83:80 0BAC39417268B96B9F514E7F63FA6FBA1A788955"
+        ]}}]}"#;
+        let bridges = parse_settings_response(body).unwrap();
+        assert_eq!(bridges.bridges.len(), 1);
+        assert_eq!(bridges.rejected.len(), 1);
+        assert_eq!(bridges.rejected[0].0, "192.0.2.7:443 NOTAFINGERPRINT");
+    }
+
+    #[cfg(not(feature = "pt-client"))]
+    #[test]
+    fn response_pt_unsupported() {
+        let body = r#"{"settings": [{"bridges": {"bridge_strings": [
+            "obfs4 192.0.2.7:443 7DD62766BF2052432051D7B7E08A22F7E34A4543 cert=AAAA iat-mode=0",
+            "38.229.33.83:80 0BAC39417268B96B9F514E7F63FA6FBA1A788955"
+        ]}}]}"#;
+        let bridges = parse_settings_response(body).unwrap();
+        assert_eq!(bridges.bridges.len(), 1);
+        assert_eq!(bridges.rejected.len(), 1);
+    }
+
+    #[test]
+    fn http() {
+        let reply = "HTTP/1.1 200 OK\r\nContent-Type: application/vnd.api+json\r\n\r\n{}";
+        assert_eq!(http_reply_body(reply.as_bytes()).unwrap(), "{}");
+
+        let reply = "HTTP/1.1 404 Not Found\r\n\r\n";
+        assert!(matches!(
+            http_reply_body(reply.as_bytes()),
+            Err(MoatError::HttpStatus(404))
+        ));
+
+        for reply in ["HTTP/1.1 200 OK\r\n", "SSH-2.0-OpenSSH\r\n\r\n", ""] {
+            assert!(matches!(
+                http_reply_body(reply.as_bytes()),
+                Err(MoatError::InvalidHttp(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn fetch() {
+        use futures::io::Cursor;
+        use tor_async_utils::JoinReadWrite;
+
+        let reply = "HTTP/1.0 200 OK\r\n\r\n\
+            {\"settings\": [{\"bridges\": {\"bridge_strings\": \
+            [\"38.229.33.83:80 0BAC39417268B96B9F514E7F63FA6FBA1A788955\"]}}]}";
+        let request = SettingsRequest {
+            transports: vec!["obfs4".into()],
+            ..SettingsRequest::default()
+        };
+
+        MockRuntime::test_with_various(|rt| async move {
+            let mut sent = Vec::new();
+            let stream = JoinReadWrite::new(Cursor::new(reply.as_bytes()), &mut sent);
+            let bridges = fetch_settings(&rt, stream, &request, Duration::from_secs(60))
+                .await
+                .unwrap();
+            assert_eq!(bridges.bridges.len(), 1);
+
+            let sent = String::from_utf8(sent).unwrap();
+            assert!(sent.starts_with("POST /moat/circumvention/settings HTTP/1.0\r\n"));
+            assert!(sent.contains("Host: bridges.torproject.org\r\n"));
+            assert!(sent.ends_with("\r\n\r\n{\"transports\":[\"obfs4\"]}"));
+        });
+    }
+
+    #[test]
+    fn fetch_timeout() {
+        MockRuntime::test_with_various(|rt| async move {
+            // The server accepts our request, but never answers.
+            let (stream, _server) = tor_rtmock::io::stream_pair();
+            let fetch = rt.spawn_join("fetch", {
+                let rt = rt.clone();
+                async move {
+                    let request = SettingsRequest::default();
+                    fetch_settings(&rt, stream, &request, Duration::from_secs(60)).await
+                }
+            });
+
+            rt.advance_by(Duration::from_secs(61)).await;
+            assert!(matches!(fetch.await, Err(MoatError::Timeout)));
+        });
+    }
+}
@@ -10,8 +10,11 @@ use futures::{channel::mpsc, stream::StreamExt};
 #[cfg(test)]
 use tor_async_utils::oneshot;
 use tor_proto::ClockSkew;
+use tor_rtcompat::scheduler::TaskSchedule;
+use tor_rtcompat::SleepProvider;
 
 use std::sync::{Mutex, Weak};
+use std::time::Duration;
 
 /// A message sent by to the [`report_status_events()`] task.
 #[derive(Debug)]
@@ -62,19 +65,29 @@ pub(crate) async fn report_status_events(
     }
 }
 
+/// How much random delay to add to each run of [`run_periodic`].
+pub(crate) const PERIODIC_JITTER: Duration = Duration::from_millis(250);
+
+/// The granularity onto which we coalesce runs of [`run_periodic`].
+///
+/// Guard housekeeping (including retiring guards) needn't happen at any precise
+/// time, so we let it share wakeups with other periodic tasks.
+pub(crate) const PERIODIC_COALESCING: Duration = Duration::from_secs(1);
+
 /// Background task to run periodic events on the guard manager.
 ///
 /// The only role of this task is to invoke
 /// [`GuardMgrInner::run_periodic_events`] from time to time, so that
-/// it can perform housekeeping tasks.
+/// it can perform housekeeping tasks, such as retiring old guards.
 ///
 /// Takes the [`GuardMgrInner`] by weak reference; if the guard
-/// manager goes away, then this task exits.
-pub(crate) async fn run_periodic<R: tor_rtcompat::SleepProvider>(
+/// manager goes away, or every handle to `schedule` is dropped, then this task exits.
+pub(crate) async fn run_periodic<R: SleepProvider>(
     runtime: R,
+    mut schedule: TaskSchedule<R>,
     inner: Weak<Mutex<GuardMgrInner>>,
 ) {
-    loop {
+    while schedule.next().await.is_some() {
         let delay = if let Some(inner) = inner.upgrade() {
             let mut inner = inner.lock().expect("Poisoned lock");
             let wallclock = runtime.wallclock();
@@ -84,7 +97,7 @@ pub(crate) async fn run_periodic<R: tor_rtcompat::SleepProvider>(
             // The guard manager has gone away.
             return;
         };
-        runtime.sleep(delay).await;
+        schedule.fire_in(delay);
     }
 }
 
@@ -7,6 +7,7 @@ use crate::skew::SkewEstimate;
 use educe::Educe;
 use futures::{Stream, StreamExt};
 use tor_basic_utils::skip_fmt;
+use tor_linkspec::RelayIds;
 
 /// A stream of [`SkewEstimate`] events.
 ///
@@ -42,3 +43,36 @@ impl ClockSkewEvents {
         self.inner.borrow().clone()
     }
 }
+
+/// A stream of events describing our primary guards.
+///
+/// Each event is the list of identities of our primary guards, in preference
+/// order.  A new event is sent whenever that list changes.
+///
+/// Note that this stream can be lossy: if multiple events trigger before you
+/// read from it, you will only get the most recent list.
+#[derive(Clone, Educe)]
+#[educe(Debug)]
+pub struct PrimaryGuardEvents {
+    /// The `postage::watch::Receiver` that we're wrapping.
+    #[educe(Debug(method = "skip_fmt"))]
+    pub(crate) inner: postage::watch::Receiver<Vec<RelayIds>>,
+}
+
+impl Stream for PrimaryGuardEvents {
+    type Item = Vec<RelayIds>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+impl PrimaryGuardEvents {
+    /// Return the identities of our current primary guards, in preference order.
+    pub fn get(&self) -> Vec<RelayIds> {
+        self.inner.borrow().clone()
+    }
+}
@@ -49,6 +49,7 @@
 
 use futures::channel::mpsc;
 use futures::task::SpawnExt;
+use itertools::Itertools as _;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::SocketAddr;
@@ -56,7 +57,10 @@ use std::sync::{Arc, Mutex, Weak};
 use std::time::{Duration, Instant, SystemTime};
 #[cfg(feature = "bridge-client")]
 use tor_error::internal;
-use tor_linkspec::{OwnedChanTarget, OwnedCircTarget, RelayId, RelayIdSet};
+use tor_events::events::{TorEvent, TorEventKind};
+use tor_linkspec::{
+    HasRelayIds as _, OwnedChanTarget, OwnedCircTarget, RelayId, RelayIdSet, RelayIds,
+};
 use tor_netdir::NetDirProvider;
 use tor_proto::ClockSkew;
 use tor_units::BoundedInt32;
@@ -66,7 +70,8 @@ use tor_config::{define_list_builder_accessors, define_list_builder_helper};
 use tor_config::{impl_not_auto_value, ReconfigureError};
 use tor_config::{impl_standard_builder, ExplicitOrAuto};
 use tor_netdir::{params::NetParameters, NetDir, Relay};
-use tor_persist::{DynStorageHandle, StateMgr};
+use tor_persist::{DynJournaledStorageHandle, Journaled, StateMgr};
+use tor_rtcompat::scheduler::{TaskHandle, TaskSchedule};
 use tor_rtcompat::Runtime;
 
 #[cfg(feature = "bridge-client")]
@@ -99,7 +104,7 @@ use tor_async_utils::oneshot;
 
 pub use config::GuardMgrConfig;
 pub use err::{GuardMgrConfigError, GuardMgrError, PickGuardError};
-pub use events::ClockSkewEvents;
+pub use events::{ClockSkewEvents, PrimaryGuardEvents};
 pub use filter::GuardFilter;
 pub use ids::FirstHopId;
 pub use pending::{GuardMonitor, GuardStatus, GuardUsable};
@@ -130,6 +135,9 @@ pub struct GuardMgr<R: Runtime> {
 
     /// Internal state for the guard manager.
     inner: Arc<Mutex<GuardMgrInner>>,
+
+    /// Handle for the task that runs our periodic housekeeping.
+    periodic_task: TaskHandle,
 }
 
 /// Helper type that holds the data used by a [`GuardMgr`].
@@ -196,7 +204,7 @@ struct GuardMgrInner {
     fallbacks: fallback::FallbackState,
 
     /// Location in which to store persistent state.
-    storage: DynStorageHandle<GuardSets>,
+    storage: DynJournaledStorageHandle<GuardSets>,
 
     /// A sender object to publish changes in our estimated clock skew.
     send_skew: postage::watch::Sender<Option<SkewEstimate>>,
@@ -205,6 +213,13 @@ struct GuardMgrInner {
     /// changes in our estimated clock skew.
     recv_skew: events::ClockSkewEvents,
 
+    /// A sender object to publish changes in our list of primary guards.
+    send_primary: postage::watch::Sender<Vec<RelayIds>>,
+
+    /// A receiver object to hand out to observers who want to know about
+    /// changes in our list of primary guards.
+    recv_primary: events::PrimaryGuardEvents,
+
     /// A netdir provider that we can use for adding new guards when
     /// insufficient guards are available.
     ///
@@ -299,6 +314,81 @@ struct GuardSets {
 /// "default_guards" (before Arti 0.1.0).
 const STORAGE_KEY: &str = "guards";
 
+/// How many changes to our persistent guard state we journal before we store
+/// the whole state again.
+///
+/// We're usually asked to save our state about once a minute, so this is
+/// about once an hour.
+const STORAGE_JOURNAL_LEN: usize = 60;
+
+/// A change to our persistent guard state, as stored in the journal.
+///
+/// For each guard set that has changed, we store only the guards within it
+/// that have changed; see [`sample::GuardSetDelta`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct GuardSetsDelta {
+    /// The changes to the default guard set, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default: Option<sample::GuardSetDelta>,
+
+    /// The changes to the restricted guard set, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    restricted: Option<sample::GuardSetDelta>,
+
+    /// The changes to the bridge guard set, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg(feature = "bridge-client")]
+    bridges: Option<sample::GuardSetDelta>,
+}
+
+impl Journaled for GuardSets {
+    type Delta = GuardSetsDelta;
+
+    fn apply(&mut self, delta: GuardSetsDelta) {
+        // Each delta sets guards and guard lists to new values, so the last
+        // one to mention each of them wins, and replaying deltas that are
+        // already reflected here does nothing.
+        let GuardSetsDelta {
+            default,
+            restricted,
+            #[cfg(feature = "bridge-client")]
+            bridges,
+        } = delta;
+        if let Some(default) = default {
+            self.default.apply(default);
+        }
+        if let Some(restricted) = restricted {
+            self.restricted.apply(restricted);
+        }
+        #[cfg(feature = "bridge-client")]
+        if let Some(bridges) = bridges {
+            self.bridges.apply(bridges);
+        }
+    }
+
+    fn delta_to(&self, newer: &Self) -> Option<GuardSetsDelta> {
+        let delta = GuardSetsDelta {
+            default: self.default.delta_to(&newer.default),
+            restricted: self.restricted.delta_to(&newer.restricted),
+            #[cfg(feature = "bridge-client")]
+            bridges: self.bridges.delta_to(&newer.bridges),
+        };
+        let GuardSetsDelta {
+            default,
+            restricted,
+            #[cfg(feature = "bridge-client")]
+            bridges,
+        } = &delta;
+        #[allow(unused_mut)]
+        let mut any_changed = default.is_some() || restricted.is_some();
+        #[cfg(feature = "bridge-client")]
+        {
+            any_changed |= bridges.is_some();
+        }
+        any_changed.then_some(delta)
+    }
+}
+
 /// A description of which circuits to retire because of a configuration change.
 ///
 /// TODO(nickm): Eventually we will want to add a "Some" here, to support
@@ -327,7 +417,8 @@ impl<R: Runtime> GuardMgr<R> {
         S: StateMgr + Send + Sync + 'static,
     {
         let (ctrl, rcv) = mpsc::unbounded();
-        let storage: DynStorageHandle<GuardSets> = state_mgr.create_handle(STORAGE_KEY);
+        let storage: DynJournaledStorageHandle<GuardSets> =
+            state_mgr.create_journaled_handle(STORAGE_KEY, STORAGE_JOURNAL_LEN);
         // TODO(nickm): We should do something about the old state in
         // `default_guards`.  Probably it would be best to delete it.  We could
         // try to migrate it instead, but that's beyond the stability guarantee
@@ -336,6 +427,10 @@ impl<R: Runtime> GuardMgr<R> {
 
         let (send_skew, recv_skew) = postage::watch::channel();
         let recv_skew = ClockSkewEvents { inner: recv_skew };
+        let (send_primary, recv_primary) = postage::watch::channel();
+        let recv_primary = PrimaryGuardEvents {
+            inner: recv_primary,
+        };
 
         let inner = Arc::new(Mutex::new(GuardMgrInner {
             guards: state,
@@ -349,6 +444,8 @@ impl<R: Runtime> GuardMgr<R> {
             storage,
             send_skew,
             recv_skew,
+            send_primary,
+            recv_primary,
             netdir_provider: None,
             #[cfg(feature = "bridge-client")]
             bridge_desc_provider: None,
@@ -370,14 +467,28 @@ impl<R: Runtime> GuardMgr<R> {
                 .spawn(daemon::report_status_events(rt_clone, weak_inner, rcv))
                 .map_err(|e| GuardMgrError::from_spawn("guard status event reporter", e))?;
         }
+        let (mut sched, periodic_task) = TaskSchedule::new(runtime.clone());
+        sched.set_jitter(daemon::PERIODIC_JITTER);
+        sched.set_coalescing(daemon::PERIODIC_COALESCING);
         {
             let rt_clone = runtime.clone();
             let weak_inner = Arc::downgrade(&inner);
             runtime
-                .spawn(daemon::run_periodic(rt_clone, weak_inner))
+                .spawn(daemon::run_periodic(rt_clone, sched, weak_inner))
                 .map_err(|e| GuardMgrError::from_spawn("periodic guard updater", e))?;
         }
-        Ok(GuardMgr { runtime, inner })
+        Ok(GuardMgr {
+            runtime,
+            inner,
+            periodic_task,
+        })
+    }
+
+    /// Return a handle for the task that runs this guard manager's periodic housekeeping.
+    ///
+    /// The housekeeping includes retiring guards that we have kept for too long.
+    pub fn periodic_task_handle(&self) -> TaskHandle {
+        self.periodic_task.clone()
     }
 
     /// Install a [`NetDirProvider`] for use by this guard manager.
@@ -464,6 +575,18 @@ impl<R: Runtime> GuardMgr<R> {
         Ok(())
     }
 
+    /// Flush our current guard state to the state manager as a complete
+    /// snapshot, and clear the journal.
+    ///
+    /// Call this before exiting, so that versions of Arti that don't read
+    /// the journal still see our latest guard state.
+    pub fn compact_persistent_state(&self) -> Result<(), GuardMgrError> {
+        let inner = self.inner.lock().expect("Poisoned lock");
+        trace!("Compacting guard state on disk.");
+        inner.storage.compact(&inner.guards)?;
+        Ok(())
+    }
+
     /// Reload state from the state manager.
     ///
     /// We only call this method if we _don't_ have the lock on the state
@@ -697,6 +820,17 @@ impl<R: Runtime> GuardMgr<R> {
         inner.recv_skew.clone()
     }
 
+    /// Return a stream of events about our primary guards; each event is the
+    /// list of identities of our primary guards, in preference order.
+    ///
+    /// Note that this stream can be lossy: if the list changes more than
+    /// once before you read from the stream, you might only get the most recent
+    /// list.
+    pub fn primary_guard_events(&self) -> PrimaryGuardEvents {
+        let inner = self.inner.lock().expect("Poisoned lock");
+        inner.recv_primary.clone()
+    }
+
     /// Ensure that the message queue is flushed before proceeding to
     /// the next step.  Used for testing.
     #[cfg(test)]
@@ -887,6 +1021,7 @@ impl GuardMgrInner {
             #[cfg(not(feature = "bridge-client"))]
             let _ = now;
         });
+        self.publish_primary_guards();
     }
 
     /// Replace our bridge configuration with the one from `new_config`.
@@ -1257,6 +1392,7 @@ impl GuardMgrInner {
         self.guards
             .active_guards_mut()
             .select_primary_guards(&self.params);
+        self.publish_primary_guards();
 
         // Some waiting request may just have become ready (usable or
         // not); we need to give them the information they're waiting
@@ -1303,6 +1439,28 @@ impl GuardMgrInner {
             .chain(self.guards.active_guards().skew_observations())
     }
 
+    /// Publish our current list of primary guards to anybody who cares,
+    /// if it has changed.
+    fn publish_primary_guards(&mut self) {
+        let primary = self
+            .guards
+            .active_guards()
+            .primary_guard_ids()
+            .cloned()
+            .collect_vec();
+        if *self.recv_primary.inner.borrow() != primary {
+            if tor_events::event_has_subscribers(TorEventKind::GuardChanged) {
+                tor_events::broadcast(TorEvent::GuardChanged {
+                    primary: primary
+                        .iter()
+                        .map(|ids| ids.display_relay_ids().to_string())
+                        .collect(),
+                });
+            }
+            *self.send_primary.borrow_mut() = primary;
+        }
+    }
+
     /// Recalculate our estimated clock skew, and publish it to anybody who
     /// cares.
     fn update_skew(&mut self, now: Instant) {
@@ -2012,6 +2170,97 @@ mod test {
         });
     }
 
+    #[test]
+    fn journaled_state() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, statemgr, netdir) = init(rt.clone());
+            guardmgr.install_test_netdir(&netdir);
+
+            /// Return the serialized form of `guards`.
+            fn serialized(guards: &GuardSets) -> tor_persist::JsonValue {
+                serde_json::to_value(guards).unwrap()
+            }
+            let journal = || -> Vec<GuardSetsDelta> { statemgr.load_journal(STORAGE_KEY).unwrap() };
+
+            // We didn't have any state, so the first time we store, we store all of it.
+            guardmgr.store_persistent_state().unwrap();
+            assert!(journal().is_empty());
+            guardmgr.store_persistent_state().unwrap();
+            assert!(journal().is_empty());
+
+            // After that, we only store the guards that changed.
+            let (id, mon, usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            mon.succeeded();
+            assert!(usable.await.unwrap());
+            guardmgr.flush_msg_queue().await;
+            guardmgr.store_persistent_state().unwrap();
+            let deltas = journal();
+            assert_eq!(deltas.len(), 1);
+            assert!(deltas[0].default.is_some());
+            assert!(deltas[0].restricted.is_none());
+            let n_stored =
+                |v: &tor_persist::JsonValue| v["default"]["guards"].as_array().unwrap().len();
+            let snapshot_json: tor_persist::JsonValue =
+                statemgr.load(STORAGE_KEY).unwrap().unwrap();
+            let delta_json = serde_json::to_value(&deltas[0]).unwrap();
+            assert!(n_stored(&delta_json) < n_stored(&snapshot_json));
+
+            // Replaying any suffix of the journal over our current state does nothing.
+            let current = guardmgr.inner.lock().unwrap().guards.clone();
+            let snapshot: GuardSets = statemgr.load(STORAGE_KEY).unwrap().unwrap();
+            assert_ne!(serialized(&snapshot), serialized(&current));
+            for start in 0..=deltas.len() {
+                let mut replayed = current.clone();
+                for delta in &deltas[start..] {
+                    replayed.apply(delta.clone());
+                }
+                assert_eq!(serialized(&replayed), serialized(&current));
+            }
+            drop(guardmgr);
+
+            // When we reload, we get the snapshot with the journal applied.
+            let guardmgr2 =
+                GuardMgr::new(rt.clone(), statemgr.clone(), &TestConfig::default()).unwrap();
+            assert_eq!(
+                serialized(&guardmgr2.inner.lock().unwrap().guards),
+                serialized(&current)
+            );
+            guardmgr2.install_test_netdir(&netdir);
+            let (id2, _mon, _usable) = guardmgr2.select_guard(GuardUsage::default()).unwrap();
+            assert!(id2.same_relay_ids(&id));
+
+            // Compacting leaves everything in the snapshot.
+            guardmgr2.compact_persistent_state().unwrap();
+            assert!(journal().is_empty());
+            let snapshot: GuardSets = statemgr.load(STORAGE_KEY).unwrap().unwrap();
+            assert_eq!(
+                serialized(&snapshot),
+                serialized(&guardmgr2.inner.lock().unwrap().guards)
+            );
+        });
+    }
+
+    #[test]
+    fn primary_guard_events() {
+        test_with_all_runtimes!(|rt| async move {
+            let (guardmgr, _statemgr, netdir) = init(rt.clone());
+            let events = guardmgr.primary_guard_events();
+            assert!(events.get().is_empty());
+
+            guardmgr.install_test_netdir(&netdir);
+            let primary = events.get();
+            // We asked for two primary guards in init().
+            assert_eq!(primary.len(), 2);
+            for ids in &primary {
+                assert!(netdir.by_ids(ids).is_some());
+            }
+
+            // Our guard is one of the primary guards.
+            let (id, _mon, _usable) = guardmgr.select_guard(GuardUsage::default()).unwrap();
+            assert!(primary.iter().any(|ids| id.same_relay_ids(ids)));
+        });
+    }
+
     #[test]
     fn simple_waiting() {
         // TODO(nickm): This test fails in rare cases; I suspect a
@@ -475,6 +475,11 @@ impl GuardSet {
         self.primary_guards_invalidated = true;
     }
 
+    /// Return the identities of our primary guards, in preference order.
+    pub(crate) fn primary_guard_ids(&self) -> impl Iterator<Item = &tor_linkspec::RelayIds> + '_ {
+        self.primary.iter().map(|id| &id.0)
+    }
+
     /// Return the number of our primary guards that are missing directory
     /// information in `universe`.
     ///
@@ -993,6 +998,90 @@ pub(crate) struct GuardSample<'a> {
     remaining: HashMap<String, JsonValue>,
 }
 
+/// A change to a [`GuardSet`], as stored in the journal.
+///
+/// We only record the guards whose stored state has changed, and the lists
+/// of guard identities if they have changed: most changes affect only one
+/// guard, so this is much smaller than the whole set.
+///
+/// Every field sets part of the `GuardSet` to a new value, so that replaying
+/// a delta that is already reflected in a `GuardSet` does nothing.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct GuardSetDelta {
+    /// The new stored state for every guard that was added or changed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    guards: Vec<Guard>,
+    /// The identities of the guards in the sample, in sample order,
+    /// if they have changed.
+    ///
+    /// Guards that aren't listed here have been removed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sample: Option<Vec<GuardId>>,
+    /// The identities of the confirmed guards, in confirmed order,
+    /// if they have changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    confirmed: Option<Vec<GuardId>>,
+}
+
+impl GuardSet {
+    /// Return a delta that turns the stored state of this `GuardSet` into
+    /// that of `newer`, or `None` if they would be stored identically.
+    ///
+    /// (Like the stored state, this doesn't record unrecognized fields.)
+    pub(crate) fn delta_to(&self, newer: &GuardSet) -> Option<GuardSetDelta> {
+        /// Return true if `older` and `newer` would be stored identically.
+        fn same_when_stored(older: &Guard, newer: &Guard) -> bool {
+            match (serde_json::to_value(older), serde_json::to_value(newer)) {
+                (Ok(older), Ok(newer)) => older == newer,
+                _ => false,
+            }
+        }
+
+        let guards: Vec<Guard> = newer
+            .sample
+            .iter()
+            .filter_map(|id| newer.guards.by_all_ids(id))
+            .filter(|guard| match self.guards.by_all_ids(guard.guard_id()) {
+                Some(old) => !same_when_stored(old, guard),
+                None => true,
+            })
+            .cloned()
+            .collect();
+        let sample = (self.sample != newer.sample).then(|| newer.sample.clone());
+        let confirmed = (self.confirmed != newer.confirmed).then(|| newer.confirmed.clone());
+
+        if guards.is_empty() && sample.is_none() && confirmed.is_none() {
+            return None;
+        }
+        Some(GuardSetDelta {
+            guards,
+            sample,
+            confirmed,
+        })
+    }
+
+    /// Apply a delta from [`delta_to`](GuardSet::delta_to) to this `GuardSet`.
+    pub(crate) fn apply(&mut self, delta: GuardSetDelta) {
+        let GuardSetDelta {
+            guards,
+            sample,
+            confirmed,
+        } = delta;
+        for guard in guards {
+            let _: Vec<Guard> = self.guards.insert(guard);
+        }
+        if let Some(sample) = sample {
+            self.sample = sample;
+        }
+        if let Some(confirmed) = confirmed {
+            self.confirmed = confirmed;
+        }
+        // This drops any guards that are no longer in the sample.
+        self.fix_consistency();
+        self.primary_guards_invalidated = true;
+    }
+}
+
 impl Serialize for GuardSet {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
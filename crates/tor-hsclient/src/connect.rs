@@ -5,8 +5,9 @@ use std::time::Duration;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::marker::PhantomData;
-use std::sync::Arc;
-use std::time::Instant;
+use std::ops::{Bound, RangeBounds as _};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
 
 use async_trait::async_trait;
 use educe::Educe;
@@ -17,6 +18,7 @@ use tor_bytes::Writeable;
 use tor_cell::relaycell::hs::intro_payload::{self, IntroduceHandshakePayload};
 use tor_cell::relaycell::msg::{AnyRelayMsg, Introduce1, Rendezvous2};
 use tor_error::{debug_report, warn_report, Bug};
+use tor_hscrypto::cache::SubcredentialCache;
 use tor_hscrypto::Subcredential;
 use tor_proto::circuit::handshake::hs_ntor;
 use tracing::{debug, trace};
@@ -31,6 +33,7 @@ use tor_checkable::{timed::TimerangeBound, Timebound};
 use tor_circmgr::build::circparameters_from_netparameters;
 use tor_circmgr::hspool::{HsCircKind, HsCircPool};
 use tor_circmgr::timeouts::Action as TimeoutsAction;
+use tor_circmgr::LatencyHistogram;
 use tor_dirclient::request::Requestable as _;
 use tor_error::{internal, into_internal};
 use tor_error::{HasRetryTime as _, RetryTime};
@@ -38,6 +41,7 @@ use tor_hscrypto::pk::{HsBlindId, HsId, HsIdKey};
 use tor_hscrypto::RendCookie;
 use tor_linkspec::{CircTarget, HasRelayIds, OwnedCircTarget, RelayId};
 use tor_llcrypto::pk::ed25519::Ed25519Identity;
+use tor_llcrypto::pk::rsa::RsaIdentity;
 use tor_netdir::{NetDir, Relay};
 use tor_netdoc::doc::hsdesc::{HsDesc, IntroPointDesc};
 use tor_proto::circuit::{
@@ -47,7 +51,7 @@ use tor_rtcompat::{Runtime, SleepProviderExt as _, TimeoutError};
 
 use crate::proto_oneshot;
 use crate::relay_info::ipt_to_circtarget;
-use crate::state::MockableConnectorData;
+use crate::state::{CachedDescriptorInfo, MockableConnectorData};
 use crate::Config;
 use crate::{rend_pt_identity_for_error, FailedAttemptError, IntroPtIndex, RendPtIdentityForError};
 use crate::{ConnError, DescriptorError, DescriptorErrorDetail};
@@ -96,7 +100,16 @@ pub struct Data {
 }
 
 /// Part of `Data` that relates to the HS descriptor
-type DataHsDesc = Option<TimerangeBound<HsDesc>>;
+type DataHsDesc = Option<FetchedHsDesc>;
+
+/// An onion service descriptor that we have downloaded, and when we got it
+#[derive(Debug)]
+struct FetchedHsDesc {
+    /// When we downloaded the descriptor (by the wallclock)
+    fetched: SystemTime,
+    /// The descriptor itself, bounded by its validity period
+    desc: TimerangeBound<HsDesc>,
+}
 
 /// Part of `Data` that relates to our information about introduction points
 type DataIpts = HashMap<RelayIdForExperience, IptExperience>;
@@ -158,6 +171,8 @@ pub(crate) async fn connect<R: Runtime>(
     Context::new(
         &connector.runtime,
         &*connector.circpool,
+        &connector.desc_fetch_times,
+        &connector.subcredentials,
         netdir,
         config,
         hsid,
@@ -181,6 +196,8 @@ struct Context<'c, R: Runtime, M: MocksForConnect<R>> {
     runtime: &'c R,
     /// Circpool
     circpool: &'c M::HsCircPool,
+    /// Where to record how long it took to fetch each descriptor
+    desc_fetch_times: &'c Mutex<LatencyHistogram>,
     /// Netdir
     //
     // TODO holding onto the netdir for the duration of our attempts is not ideal
@@ -359,6 +376,8 @@ impl<'c, R: Runtime, M: MocksForConnect<R>> Context<'c, R, M> {
     fn new(
         runtime: &'c R,
         circpool: &'c M::HsCircPool,
+        desc_fetch_times: &'c Mutex<LatencyHistogram>,
+        subcredentials: &SubcredentialCache,
         netdir: Arc<NetDir>,
         config: Arc<Config>,
         hsid: HsId,
@@ -366,9 +385,9 @@ impl<'c, R: Runtime, M: MocksForConnect<R>> Context<'c, R, M> {
         mocks: M,
     ) -> Result<Self, ConnError> {
         let time_period = netdir.hs_time_period();
-        let (hs_blind_id_key, subcredential) = HsIdKey::try_from(hsid)
-            .map_err(|_| CE::InvalidHsId)?
-            .compute_blinded_key(time_period)
+        let hsid_key = HsIdKey::try_from(hsid).map_err(|_| CE::InvalidHsId)?;
+        let (hs_blind_id_key, subcredential) = subcredentials
+            .compute_blinded_key(&hsid_key, time_period)
             .map_err(
                 // TODO HS what on earth do these errors mean, in practical terms ?
                 // In particular, we'll want to convert them to a ConnError variant,
@@ -384,6 +403,7 @@ impl<'c, R: Runtime, M: MocksForConnect<R>> Context<'c, R, M> {
             hs_blind_id,
             subcredential,
             circpool,
+            desc_fetch_times,
             runtime,
             secret_keys,
             mocks,
@@ -455,12 +475,13 @@ impl<'c, R: Runtime, M: MocksForConnect<R>> Context<'c, R, M> {
         // TODO SPEC: Discuss HS descriptor lifetime and expiry client behaviour
         if let Some(previously) = data {
             let now = self.runtime.wallclock();
-            if let Ok(_desc) = previously.as_ref().check_valid_at(&now) {
+            if let Ok(_desc) = previously.desc.as_ref().check_valid_at(&now) {
                 // Ideally we would just return desc but that confuses borrowck.
                 // https://github.com/rust-lang/rust/issues/51545
                 return Ok(data
                     .as_ref()
                     .expect("Some but now None")
+                    .desc
                     .as_ref()
                     .check_valid_at(&now)
                     .expect("Ok but now Err"));
@@ -486,6 +507,7 @@ impl<'c, R: Runtime, M: MocksForConnect<R>> Context<'c, R, M> {
         //   https://gitlab.torproject.org/tpo/core/arti/-/issues/913#note_2914436
         // (Additionally, making multiple HSDir requests at once may make us
         // more vulnerable to traffic analysis.)
+        let started = self.runtime.now();
         let mut attempts = hs_dirs.iter().cycle().take(max_total_attempts);
         let mut errors = RetryError::in_attempt_to("retrieve hidden service descriptor");
         let desc = loop {
@@ -522,6 +544,10 @@ impl<'c, R: Runtime, M: MocksForConnect<R>> Context<'c, R, M> {
             }
         };
 
+        if let Ok(mut times) = self.desc_fetch_times.lock() {
+            times.record(self.runtime.now().saturating_duration_since(started));
+        }
+
         // Store the bounded value in the cache for reuse,
         // but return a reference to the unwrapped `HsDesc`.
         //
@@ -531,8 +557,11 @@ impl<'c, R: Runtime, M: MocksForConnect<R>> Context<'c, R, M> {
         //
         // It is safe to dangerously_assume_timely,
         // as descriptor_fetch_attempt has already checked the timeliness of the descriptor.
-        let ret = data.insert(desc);
-        Ok(ret.as_ref().dangerously_assume_timely())
+        let ret = data.insert(FetchedHsDesc {
+            fetched: self.runtime.wallclock(),
+            desc,
+        });
+        Ok(ret.desc.as_ref().dangerously_assume_timely())
     }
 
     /// Make one attempt to fetch the descriptor from a specific hsdir
@@ -950,9 +979,17 @@ impl<'c, R: Runtime, M: MocksForConnect<R>> Context<'c, R, M> {
         &'c self,
         using_rend_pt: &mut Option<RendPtIdentityForError>,
     ) -> Result<Rendezvous<R, M>, FAE> {
+        let pinned_rend_pt = self.config.rend_pt;
+        if let Some(pinned) = &pinned_rend_pt {
+            debug!(
+                "hs conn to {}: using configured rendezvous point {}",
+                &self.hsid, pinned,
+            );
+        }
+
         let (rend_circ, rend_relay) = self
             .circpool
-            .m_get_or_launch_client_rend(&self.netdir)
+            .m_get_or_launch_client_rend(&self.netdir, pinned_rend_pt.as_ref())
             .await
             .map_err(|error| FAE::RendezvousCircuitObtain { error })?;
 
@@ -1357,9 +1394,12 @@ trait MockableCircPool<R> {
     ) -> tor_circmgr::Result<Arc<Self::ClientCirc>>;
 
     /// Client circuit
+    ///
+    /// If `rend_pt` is provided, the circuit ends at that relay.
     async fn m_get_or_launch_client_rend<'a>(
         &self,
         netdir: &'a NetDir,
+        rend_pt: Option<&RsaIdentity>,
     ) -> tor_circmgr::Result<(Arc<Self::ClientCirc>, Relay<'a>)>;
 
     /// Estimate timeout
@@ -1415,8 +1455,12 @@ impl<R: Runtime> MockableCircPool<R> for HsCircPool<R> {
     async fn m_get_or_launch_client_rend<'a>(
         &self,
         netdir: &'a NetDir,
+        rend_pt: Option<&RsaIdentity>,
     ) -> tor_circmgr::Result<(Arc<ClientCirc>, Relay<'a>)> {
-        HsCircPool::get_or_launch_client_rend(self, netdir).await
+        match rend_pt {
+            Some(rend_pt) => HsCircPool::get_or_launch_client_rend_at(self, netdir, rend_pt).await,
+            None => HsCircPool::get_or_launch_client_rend(self, netdir).await,
+        }
     }
     fn m_estimate_timeout(&self, action: &TimeoutsAction) -> Duration {
         HsCircPool::estimate_timeout(self, action)
@@ -1469,6 +1513,32 @@ impl MockableConnectorData for Data {
     fn circuit_is_ok(circuit: &Self::ClientCirc) -> bool {
         !circuit.is_closing()
     }
+
+    fn circuit_n_streams(circuit: &Arc<Self::ClientCirc>) -> usize {
+        circuit.n_client_streams()
+    }
+
+    fn cached_descriptor(&self, hs_id: HsId) -> Option<CachedDescriptorInfo> {
+        let FetchedHsDesc { fetched, desc } = self.desc.as_ref()?;
+        let valid_until = match desc.end_bound() {
+            Bound::Included(t) | Bound::Excluded(t) => Some(*t),
+            Bound::Unbounded => None,
+        };
+        Some(CachedDescriptorInfo {
+            hs_id,
+            fetched: *fetched,
+            valid_until,
+            n_intro_points: desc
+                .as_ref()
+                .dangerously_assume_timely()
+                .intro_points()
+                .len(),
+        })
+    }
+
+    fn forget_descriptor(&mut self) {
+        self.desc = None;
+    }
 }
 
 #[cfg(test)]
@@ -1507,6 +1577,7 @@ mod test {
     #[derive(Debug, Default)]
     struct MocksGlobal {
         hsdirs_asked: Vec<OwnedCircTarget>,
+        rend_pts_asked: Vec<Option<RsaIdentity>>,
         got_desc: Option<HsDesc>,
     }
     #[derive(Clone, Debug)]
@@ -1559,8 +1630,18 @@ mod test {
         async fn m_get_or_launch_client_rend<'a>(
             &self,
             netdir: &'a NetDir,
+            rend_pt: Option<&RsaIdentity>,
         ) -> tor_circmgr::Result<(Arc<ClientCirc!(R, Self)>, Relay<'a>)> {
-            todo!()
+            self.mglobal
+                .lock()
+                .unwrap()
+                .rend_pts_asked
+                .push(rend_pt.copied());
+            let relay = match rend_pt {
+                Some(rend_pt) => netdir.by_id(rend_pt).expect("no such rendezvous point"),
+                None => netdir.relays().next().expect("empty netdir"),
+            };
+            Ok((Arc::new(self.clone()), relay))
         }
 
         fn m_estimate_timeout(&self, action: &TimeoutsAction) -> Duration {
@@ -1606,9 +1687,10 @@ mod test {
         }
     }
 
-    #[traced_test]
-    #[tokio::test]
-    async fn test_connect() {
+    /// Make a runtime and a netdir for testing connections
+    ///
+    /// Must be called from within a tokio runtime.
+    fn setup() -> (impl Runtime, Arc<NetDir>) {
         let valid_after = humantime::parse_rfc3339("2023-02-09T12:00:00Z").unwrap();
         let fresh_until = valid_after + humantime::parse_duration("1 hours").unwrap();
         let valid_until = valid_after + humantime::parse_duration("24 hours").unwrap();
@@ -1633,6 +1715,23 @@ mod test {
             runtime.clone(),
             runtime,
         );
+        (runtime, netdir)
+    }
+
+    /// The client keys that let us decrypt `test_data::TEST_DATA_2`
+    fn test_secret_keys() -> HsClientSecretKeys {
+        let pk: HsClientDescEncKey = curve25519::PublicKey::from(test_data::TEST_PUBKEY_2).into();
+        let sk = curve25519::StaticSecret::from(test_data::TEST_SECKEY_2).into();
+        let mut secret_keys_builder = HsClientSecretKeysBuilder::default();
+        secret_keys_builder.ks_hsc_desc_enc(HsClientDescEncKeypair::new(pk, sk));
+        secret_keys_builder.build().unwrap()
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_connect() {
+        let (runtime, netdir) = setup();
+        let now = humantime::parse_rfc3339("2023-02-09T12:00:00Z").unwrap();
         let time_period = netdir.hs_time_period();
 
         let mglobal = Arc::new(Mutex::new(MocksGlobal::default()));
@@ -1642,14 +1741,15 @@ mod test {
         let mut data = Data::default();
 
         let pk: HsClientDescEncKey = curve25519::PublicKey::from(test_data::TEST_PUBKEY_2).into();
-        let sk = curve25519::StaticSecret::from(test_data::TEST_SECKEY_2).into();
-        let mut secret_keys_builder = HsClientSecretKeysBuilder::default();
-        secret_keys_builder.ks_hsc_desc_enc(HsClientDescEncKeypair::new(pk.clone(), sk));
-        let secret_keys = secret_keys_builder.build().unwrap();
+        let secret_keys = test_secret_keys();
 
+        let desc_fetch_times = Mutex::default();
+        let subcredentials = SubcredentialCache::new();
         let ctx = Context::new(
             &runtime,
             &mocks,
+            &desc_fetch_times,
+            &subcredentials,
             netdir,
             Default::default(),
             hsid,
@@ -1679,6 +1779,7 @@ mod test {
         )
         .unwrap()
         .dangerously_assume_timely();
+        let n_intro_points = hsdesc.intro_points().len();
 
         let mglobal = mocks.mglobal.lock().unwrap();
         assert_eq!(mglobal.hsdirs_asked.len(), 1);
@@ -1690,7 +1791,7 @@ mod test {
         );
 
         // Check how long the descriptor is valid for
-        let bounds = data.desc.as_ref().unwrap().bounds();
+        let bounds = data.desc.as_ref().unwrap().desc.bounds();
         assert_eq!(bounds.start_bound(), Bound::Unbounded);
 
         let desc_valid_until = humantime::parse_rfc3339("2023-02-11T20:00:00Z").unwrap();
@@ -1699,11 +1800,62 @@ mod test {
             Bound::Included(desc_valid_until).as_ref()
         );
 
+        // Check what we report about the cached descriptor
+        let info = data.cached_descriptor(hsid).unwrap();
+        assert_eq!(info.hs_id, hsid);
+        assert_eq!(info.fetched, now);
+        assert_eq!(info.valid_until, Some(desc_valid_until));
+        assert_eq!(info.n_intro_points, n_intro_points);
+
+        data.forget_descriptor();
+        assert!(data.cached_descriptor(hsid).is_none());
+
+        // We chose the rendezvous point ourselves.
+        assert_eq!(mglobal.rend_pts_asked, vec![None]);
+
         // TODO HS TESTS: check the circuit in got is the one we gave out
 
         // TODO HS TESTS: continue with this
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn test_connect_pinned_rend_pt() {
+        let (runtime, netdir) = setup();
+        let rend_pt = *netdir.relays().nth(3).unwrap().rsa_id();
+
+        let mocks = Mocks {
+            mglobal: Default::default(),
+            id: (),
+        };
+        let config = Config {
+            rend_pt: Some(rend_pt),
+            ..Config::default()
+        };
+        let desc_fetch_times = Mutex::default();
+        let subcredentials = SubcredentialCache::new();
+        let ctx = Context::new(
+            &runtime,
+            &mocks,
+            &desc_fetch_times,
+            &subcredentials,
+            netdir,
+            Arc::new(config),
+            test_data::TEST_HSID_2.into(),
+            test_secret_keys(),
+            mocks.clone(),
+        )
+        .unwrap();
+
+        let _got = AssertUnwindSafe(ctx.connect(&mut Data::default()))
+            .catch_unwind() // TODO HS TESTS: remove this and the AssertUnwindSafe
+            .await;
+
+        // We asked the circuit pool for a circuit to the configured rendezvous point.
+        let mglobal = mocks.mglobal.lock().unwrap();
+        assert_eq!(mglobal.rend_pts_asked, vec![Some(rend_pt)]);
+    }
+
     // TODO HS TESTS: Test IPT state management and expiry:
     //   - obtain a test descriptor with only a broken ipt
     //     (broken in the sense that intro can be attempted, but will fail somehow)
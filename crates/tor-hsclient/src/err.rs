@@ -77,6 +77,73 @@ pub enum ConnError {
     Bug(#[from] Bug),
 }
 
+/// Which step of connecting to a hidden service failed
+///
+/// This is a coarser classification than [`ConnError`],
+/// corresponding to the extended SOCKS5 reply codes of
+/// [proposal 304](https://spec.torproject.org/proposals/304-socks5-extending-hs-error-codes.html)
+/// (and `tor(1)`), which Tor Browser uses to explain onion service failures.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum HsConnFailure {
+    /// The descriptor could not be found (`X'F0'`)
+    DescriptorNotFound,
+    /// The descriptor was found, but was invalid (`X'F1'`)
+    DescriptorInvalid,
+    /// Introduction to the service failed (`X'F2'`)
+    IntroFailed,
+    /// Rendezvous with the service failed (`X'F3'`)
+    RendezvousFailed,
+    /// The service requires client authorization, and we have none (`X'F4'`)
+    MissingClientAuth,
+    /// The service requires client authorization, and ours was rejected (`X'F5'`)
+    WrongClientAuth,
+    /// The `.onion` address was invalid (`X'F6'`)
+    BadAddress,
+    /// Introduction to the service timed out (`X'F7'`)
+    IntroTimeout,
+}
+
+impl ConnError {
+    /// Return which step of connecting to the hidden service failed, if we know.
+    ///
+    /// Returns `None` for errors that aren't specific to hidden services,
+    /// such as internal errors.
+    pub fn failure(&self) -> Option<HsConnFailure> {
+        use ConnError as CE;
+        use HsConnFailure as F;
+        match self {
+            CE::InvalidHsId => Some(F::BadAddress),
+            CE::NoHsDirs => Some(F::DescriptorNotFound),
+            CE::NoUsableIntroPoints => Some(F::IntroFailed),
+            CE::Spawn { .. } | CE::Bug(_) => None,
+
+            // As with our `HasKind` implementation, report the most interesting attempt.
+            CE::DescriptorDownload(attempts) => attempts
+                .sources()
+                .max_by_key(|attempt| DescriptorErrorDetailDiscriminants::from(&attempt.0.error))
+                .and_then(|attempt| attempt.0.error.failure()),
+            CE::Failed(attempts) => attempts
+                .sources()
+                .max_by_key(|attempt| FailedAttemptErrorDiscriminants::from(*attempt))
+                .and_then(FailedAttemptError::failure),
+        }
+    }
+
+    /// Return true if every hsdir that we asked for the descriptor told us
+    /// that it doesn't have it.
+    ///
+    /// Unlike a timeout or a failed circuit, this tells us something about the
+    /// service itself, rather than about our own connectivity.
+    pub(crate) fn is_descriptor_absent(&self) -> bool {
+        let ConnError::DescriptorDownload(attempts) = self else {
+            return false;
+        };
+        let mut attempts = attempts.sources().peekable();
+        attempts.peek().is_some() && attempts.all(|attempt| attempt.0.error.is_not_found())
+    }
+}
+
 /// Error that occurred attempting to download a descriptor
 #[derive(Error, Clone, Debug)]
 #[non_exhaustive]
@@ -130,6 +197,38 @@ pub enum DescriptorErrorDetail {
     Bug(#[from] Bug),
 }
 
+impl DescriptorErrorDetail {
+    /// Return true if this is the hsdir telling us that it doesn't have the descriptor.
+    fn is_not_found(&self) -> bool {
+        use tor_dirclient::RequestError as RE;
+        matches!(
+            self,
+            DescriptorErrorDetail::Directory(RE::HttpStatus(404, _))
+        )
+    }
+
+    /// Return which step of connecting to the hidden service this error prevented, if we know.
+    fn failure(&self) -> Option<HsConnFailure> {
+        use tor_dirclient::RequestError as RE;
+        use DescriptorErrorDetail as DED;
+        use HsConnFailure as F;
+        match self {
+            DED::Directory(RE::ResponseTooLong(_))
+            | DED::Directory(RE::CompressionBomb { .. })
+            | DED::Directory(RE::Utf8Encoding(_)) => Some(F::DescriptorInvalid),
+            DED::Timeout | DED::Circuit(_) | DED::Stream(_) | DED::Directory(_) => {
+                Some(F::DescriptorNotFound)
+            }
+            DED::Descriptor(e) => Some(match e.kind() {
+                ErrorKind::OnionServiceMissingClientAuth => F::MissingClientAuth,
+                ErrorKind::OnionServiceWrongClientAuth => F::WrongClientAuth,
+                _ => F::DescriptorInvalid,
+            }),
+            DED::Bug(_) => None,
+        }
+    }
+}
+
 /// Error that occurred making one attempt to connect to a hidden service using an IP and RP
 #[derive(Error, Clone, Debug)]
 #[non_exhaustive]
@@ -302,6 +401,28 @@ impl FailedAttemptError {
     }
 }
 
+impl FailedAttemptError {
+    /// Return which step of connecting to the hidden service this error prevented, if we know.
+    fn failure(&self) -> Option<HsConnFailure> {
+        use FailedAttemptError as FAE;
+        use HsConnFailure as F;
+        match self {
+            FAE::UnusableIntro { .. }
+            | FAE::IntroductionCircuitObtain { .. }
+            | FAE::IntroductionExchange { .. }
+            | FAE::IntroductionFailed { .. } => Some(F::IntroFailed),
+            FAE::IntroductionTimeout { .. } => Some(F::IntroTimeout),
+            FAE::RendezvousCircuitObtain { .. }
+            | FAE::RendezvousEstablishTimeout { .. }
+            | FAE::RendezvousEstablish { .. }
+            | FAE::RendezvousCompletionTimeout { .. }
+            | FAE::RendezvousCompletionCircuitError { .. }
+            | FAE::RendezvousCompletionHandshake { .. } => Some(F::RendezvousFailed),
+            FAE::Bug(_) => None,
+        }
+    }
+}
+
 /// When *an attempt like this* should be retried.
 ///
 /// For error variants with an introduction point index
@@ -345,6 +466,58 @@ impl HasRetryTime for FailedAttemptError {
     }
 }
 
+impl HasRetryTime for ConnError {
+    fn retry_time(&self) -> RetryTime {
+        use ConnError as CE;
+        use RetryTime as RT;
+        match self {
+            // The address itself is wrong; it will stay wrong.
+            CE::InvalidHsId => RT::Never,
+            // Spawning only fails when the runtime is shutting down;
+            // and an internal error won't fix itself.
+            CE::Spawn { .. } | CE::Bug(_) => RT::Never,
+            // We might get a better consensus later.
+            CE::NoHsDirs => RT::AfterWaiting,
+            // The service may publish a descriptor with other introduction points.
+            CE::NoUsableIntroPoints => RT::AfterWaiting,
+
+            // We made several attempts; report the soonest any of them could be retried.
+            // We only build these after at least one failed attempt; if the list
+            // is somehow empty, that's a bug in our own code, so treat it like one.
+            CE::DescriptorDownload(attempts) => {
+                RT::earliest_approx(attempts.sources().map(|attempt| attempt.0.retry_time()))
+                    .unwrap_or(RT::Never)
+            }
+            CE::Failed(attempts) => {
+                RT::earliest_approx(attempts.sources().map(|attempt| attempt.retry_time()))
+                    .unwrap_or(RT::Never)
+            }
+        }
+    }
+}
+
+impl HasRetryTime for DescriptorError {
+    fn retry_time(&self) -> RetryTime {
+        self.error.retry_time()
+    }
+}
+
+impl HasRetryTime for DescriptorErrorDetail {
+    fn retry_time(&self) -> RetryTime {
+        use DescriptorErrorDetail as DED;
+        use RetryTime as RT;
+        match self {
+            DED::Timeout | DED::Stream(_) => RT::AfterWaiting,
+            DED::Circuit(e) => e.retry_time(),
+            DED::Directory(e) => e.retry_time(),
+            // A descriptor that was wrong once will stay wrong until the service republishes.
+            DED::Descriptor(_) => RT::AfterWaiting,
+            // An internal error won't fix itself if we try again.
+            DED::Bug(_) => RT::Never,
+        }
+    }
+}
+
 impl HasKind for ConnError {
     fn kind(&self) -> ErrorKind {
         use ConnError as CE;
@@ -396,6 +569,7 @@ impl HasKind for DescriptorErrorDetail {
             DED::Stream(e) => e.kind(),
             DED::Directory(RE::HttpStatus(st, _)) if *st == 404 => EK::OnionServiceNotFound,
             DED::Directory(RE::ResponseTooLong(_)) => EK::OnionServiceProtocolViolation,
+            DED::Directory(RE::CompressionBomb { .. }) => EK::OnionServiceProtocolViolation,
             DED::Directory(RE::Utf8Encoding(_)) => EK::OnionServiceProtocolViolation,
             DED::Directory(other_re) => other_re.kind(),
             DED::Descriptor(e) => e.kind(),
@@ -456,3 +630,101 @@ impl HasKind for StartupError {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use HsConnFailure as F;
+
+    #[test]
+    fn failure() {
+        assert_eq!(ConnError::InvalidHsId.failure(), Some(F::BadAddress));
+        assert_eq!(ConnError::NoHsDirs.failure(), Some(F::DescriptorNotFound));
+        assert_eq!(ConnError::Bug(internal!("oops")).failure(), None);
+
+        let hsdir = Sensitive::new(Ed25519Identity::new([7; 32]));
+        let mut attempts = RetryError::in_attempt_to("download descriptor");
+        attempts.push(tor_error::Report(DescriptorError {
+            hsdir: hsdir.clone(),
+            error: DescriptorErrorDetail::Timeout,
+        }));
+        assert_eq!(
+            ConnError::DescriptorDownload(attempts.clone()).failure(),
+            Some(F::DescriptorNotFound)
+        );
+        attempts.push(tor_error::Report(DescriptorError {
+            hsdir,
+            error: DescriptorErrorDetail::Directory(tor_dirclient::RequestError::ResponseTooLong(
+                1 << 20,
+            )),
+        }));
+        assert_eq!(
+            ConnError::DescriptorDownload(attempts).failure(),
+            Some(F::DescriptorInvalid)
+        );
+
+        let mut attempts = RetryError::in_attempt_to("connect");
+        attempts.push(FailedAttemptError::IntroductionTimeout {
+            intro_index: IntroPtIndex(0),
+        });
+        assert_eq!(
+            ConnError::Failed(attempts.clone()).failure(),
+            Some(F::IntroTimeout)
+        );
+        attempts.push(FailedAttemptError::IntroductionFailed {
+            status: IntroduceAckStatus::NOT_RECOGNIZED,
+            intro_index: IntroPtIndex(1),
+        });
+        assert_eq!(ConnError::Failed(attempts).failure(), Some(F::IntroFailed));
+    }
+
+    #[test]
+    fn retry_time() {
+        assert!(!ConnError::InvalidHsId.is_retriable());
+        assert!(!ConnError::Bug(internal!("oops")).is_retriable());
+        assert_eq!(ConnError::NoHsDirs.retry_time(), RetryTime::AfterWaiting);
+
+        let hsdir = Sensitive::new(Ed25519Identity::new([7; 32]));
+        let mut attempts = RetryError::in_attempt_to("download descriptor");
+        attempts.push(tor_error::Report(DescriptorError {
+            hsdir: hsdir.clone(),
+            error: DescriptorErrorDetail::Bug(internal!("oops")),
+        }));
+        assert!(!ConnError::DescriptorDownload(attempts.clone()).is_retriable());
+        attempts.push(tor_error::Report(DescriptorError {
+            hsdir,
+            error: DescriptorErrorDetail::Timeout,
+        }));
+        assert_eq!(
+            ConnError::DescriptorDownload(attempts).retry_time(),
+            RetryTime::AfterWaiting
+        );
+
+        let mut attempts = RetryError::in_attempt_to("connect");
+        attempts.push(FailedAttemptError::IntroductionFailed {
+            status: IntroduceAckStatus::BAD_MESSAGE_FORMAT,
+            intro_index: IntroPtIndex(0),
+        });
+        assert!(!ConnError::Failed(attempts.clone()).is_retriable());
+        attempts.push(FailedAttemptError::IntroductionTimeout {
+            intro_index: IntroPtIndex(1),
+        });
+        assert!(ConnError::Failed(attempts).is_retriable());
+
+        let empty = RetryError::in_attempt_to("connect");
+        assert!(!ConnError::Failed(empty).is_retriable());
+    }
+}
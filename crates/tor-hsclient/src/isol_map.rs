@@ -93,10 +93,14 @@ where
     }
 
     /// Obtain a reference to this record's isolation
-    #[allow(dead_code)] // TODO remove if and when we make this all pub
     pub(crate) fn isolation(&self) -> &dyn Isolation {
         &*self.isolation
     }
+
+    /// Obtain a copy of this record's isolation
+    pub(crate) fn clone_isolation(&self) -> Box<dyn Isolation> {
+        self.isolation.clone()
+    }
 }
 
 impl<I, K1, K2, V> MultikeyIsolatedMap<I, K1, K2, V>
@@ -158,6 +162,61 @@ where
         }
     }
 
+    /// Insert a new entry, without looking for an existing one to share
+    ///
+    /// The new entry is kept separate even from entries with compatible isolations:
+    /// later lookups with [`index_or_insert_with`](Self::index_or_insert_with)
+    /// will find (and narrow) whichever compatible entry comes first.
+    pub(crate) fn insert_separate(
+        &mut self,
+        k1: &K1,
+        k2: &K2,
+        isolation: Box<dyn Isolation>,
+        value: V,
+    ) -> I
+    where
+        K1: Clone,
+        K2: Clone,
+    {
+        let record = Record {
+            k2: k2.clone(),
+            isolation,
+            value,
+        };
+        let table_index = self.table.insert(record);
+        self.index.entry(k1.clone()).or_default().push(table_index);
+        table_index
+    }
+
+    /// Iterate over the entries with first key `k1`
+    pub(crate) fn by_k1<'s>(&'s self, k1: &K1) -> impl Iterator<Item = &'s Record<K2, V>> + 's {
+        self.index
+            .get(k1)
+            .into_iter()
+            .flatten()
+            .filter_map(|&t_index| self.table.get(t_index))
+    }
+
+    /// Iterate over the entries with first key `k1` (mutably)
+    pub(crate) fn by_k1_mut<'s>(
+        &'s mut self,
+        k1: &K1,
+    ) -> impl Iterator<Item = &'s mut Record<K2, V>> + 's {
+        let indices = self.index.get(k1).map(Vec::as_slice).unwrap_or_default();
+        self.table
+            .iter_mut()
+            .filter_map(move |(t_index, record)| indices.contains(&t_index).then_some(record))
+    }
+
+    /// Iterate over all the entries, with their first keys
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&K1, &Record<K2, V>)> + '_ {
+        self.index.iter().flat_map(move |(k1, indices)| {
+            indices
+                .iter()
+                .filter_map(move |&t_index| Some((k1, self.table.get(t_index)?)))
+        })
+    }
+
     /// Look up an existing entry by index
     ///
     /// If the entry was removed in the meantime, will return `None`
@@ -196,6 +255,10 @@ where
 
     /// Checks that the structure is consistent
     ///
+    /// Entries made with [`insert_separate`](Self::insert_separate)
+    /// are not expected to be found here:
+    /// they are allowed to have compatible isolations.
+    ///
     /// # Panics
     ///
     /// If it is found not to be.
@@ -283,4 +346,20 @@ mod test {
         dbg!(&m);
         m.check_or_panic();
     }
+
+    #[test]
+    fn separate() {
+        let mut m = mk();
+        let ti_a = m.index_or_insert_with(&1, &22, mk_isol("a"), || "a".into());
+        let ti_b = m.insert_separate(&1, &22, mk_isol("a"), "b".into());
+        assert_ne!(ti_a, ti_b);
+        assert_eq!(m.by_k1(&1).count(), 2);
+        assert_eq!(m.by_k1(&2).count(), 0);
+        assert_eq!(&**m.by_index(ti_b).unwrap(), "b");
+
+        // Ordinary lookups still find, and narrow, the first compatible entry.
+        let ti = m.index_or_insert_with(&1, &22, mk_isol("ab"), || "c".into());
+        assert_eq!(ti, ti_a);
+        assert_eq!(m.by_k1(&1).count(), 2);
+    }
 }
@@ -60,18 +60,20 @@ use educe::Educe;
 use tracing::debug;
 
 use tor_circmgr::hspool::HsCircPool;
-use tor_circmgr::isolation::StreamIsolation;
+use tor_circmgr::isolation::{Isolation, StreamIsolation};
+use tor_circmgr::LatencyHistogram;
 use tor_error::{internal, Bug};
+use tor_hscrypto::cache::SubcredentialCache;
 use tor_hscrypto::pk::HsId;
 use tor_netdir::NetDir;
 use tor_proto::circuit::ClientCirc;
 use tor_rtcompat::Runtime;
 
 pub use err::FailedAttemptError;
-pub use err::{ConnError, DescriptorError, DescriptorErrorDetail, StartupError};
+pub use err::{ConnError, DescriptorError, DescriptorErrorDetail, HsConnFailure, StartupError};
 pub use keys::{HsClientDescEncKeypairSpecifier, HsClientSecretKeys, HsClientSecretKeysBuilder};
 pub use relay_info::InvalidTarget;
-pub use state::HsClientConnectorConfig;
+pub use state::{CachedDescriptorInfo, HsClientConnectorConfig};
 
 use err::{rend_pt_identity_for_error, IntroPtIndex, RendPtIdentityForError};
 use state::{Config, MockableConnectorData, Services};
@@ -97,6 +99,10 @@ pub struct HsClientConnector<R: Runtime, D: state::MockableConnectorData = conne
     circpool: Arc<HsCircPool<R>>,
     /// Information we are remembering about different onion services.
     services: Arc<Mutex<state::Services<D>>>,
+    /// How long it took to fetch each descriptor that we have downloaded.
+    desc_fetch_times: Arc<Mutex<LatencyHistogram>>,
+    /// The blinded keys and subcredentials of the services we have connected to.
+    subcredentials: Arc<SubcredentialCache>,
     /// For mocking in tests of `state.rs`
     mock_for_state: D::MockGlobalState,
 }
@@ -120,13 +126,13 @@ impl<R: Runtime> HsClientConnector<R, connect::Data> {
         config: &impl HsClientConnectorConfig,
         housekeeping_prompt: BoxStream<'static, ()>,
     ) -> Result<Self, StartupError> {
-        let config = Config {
-            retry: config.as_ref().clone(),
-        };
+        let config = Config::new(config);
         let connector = HsClientConnector {
             runtime,
             circpool,
             services: Arc::new(Mutex::new(Services::new(config))),
+            desc_fetch_times: Default::default(),
+            subcredentials: Default::default(),
             mock_for_state: (),
         };
         connector.spawn_housekeeping_task(housekeeping_prompt)?;
@@ -141,6 +147,15 @@ impl<R: Runtime> HsClientConnector<R, connect::Data> {
     /// is compatible with `isolation`, that circuit may be returned; otherwise,
     /// a new circuit will be created.
     ///
+    /// Concurrent requests share a circuit until it has
+    /// `hs_max_streams_per_circuit` users (see [`CircuitTiming`](tor_circmgr::CircuitTiming)).
+    /// Then we build another, up to `hs_max_circuits_per_service` of them.
+    ///
+    /// If we recently failed to find the service's descriptor on any hsdir,
+    /// we fail at once with the same error, for `hs_desc_failure_cache_time`.
+    /// To try again sooner (for example, because the user asked to retry),
+    /// call [`flush_service`](HsClientConnector::flush_service) first.
+    ///
     /// Once a circuit is returned, the caller can use it to open new streams to the
     /// onion service. To do so, call [`ClientCirc::begin_stream`] on it.
     ///
@@ -193,6 +208,87 @@ impl<R: Runtime, D: MockableConnectorData> HsClientConnector<R, D> {
             .map_err(|_| internal!("HS connector poisoned"))
     }
 
+    /// Replace the configuration of this connector
+    ///
+    /// Every setting can be changed; the new values apply to connection attempts
+    /// that start after this call.
+    pub fn reconfigure(
+        &self,
+        config: &impl HsClientConnectorConfig,
+        how: tor_config::Reconfigure,
+    ) -> Result<(), tor_config::ReconfigureError> {
+        if how == tor_config::Reconfigure::CheckAllOrNothing {
+            return Ok(());
+        }
+        self.services()?.reconfigure(Config::new(config));
+        Ok(())
+    }
+
+    /// Return the isolation that applies to `circuit`, a rendezvous circuit to `hs_id`
+    ///
+    /// `circuit` should be one returned by
+    /// [`get_or_launch_circuit`](HsClientConnector::get_or_launch_circuit).
+    /// The returned isolation is the one shared by every request that may be given
+    /// `circuit`: normally, that is the join of the isolations of those requests.
+    /// If `hs_strict_isolation` is set (see [`CircuitTiming`](tor_circmgr::CircuitTiming)),
+    /// it is just the isolation of the request that caused `circuit` to be built.
+    ///
+    /// Returns `None` if we no longer hold `circuit` for reuse,
+    /// for example because it has expired.
+    pub fn circuit_isolation(
+        &self,
+        hs_id: &HsId,
+        circuit: &Arc<D::ClientCirc>,
+    ) -> Result<Option<Box<dyn Isolation>>, Bug> {
+        Ok(self.services()?.circuit_isolation(hs_id, circuit))
+    }
+
+    /// Return information about the onion service descriptors we have cached
+    ///
+    /// There may be more than one entry for the same service,
+    /// if it has been used with different client authentication keys
+    /// or incompatible isolation.
+    ///
+    /// Descriptors which are being fetched or used by a connection attempt
+    /// that is currently in progress are not listed.
+    pub fn cached_descriptors(&self) -> Result<Vec<CachedDescriptorInfo>, Bug> {
+        Ok(self.services()?.cached_descriptors())
+    }
+
+    /// Forget what we have cached about `hs_id`, so that we start afresh next time
+    ///
+    /// Discards our copies of the service's descriptor,
+    /// and stops handing out our existing rendezvous circuits to it:
+    /// the next request will download a fresh descriptor and build a new circuit.
+    /// We also forget any recent failure to find the service's descriptor,
+    /// so the next request will ask the hsdirs for it again.
+    /// This is suitable for implementing a "new circuit for this site" or "retry" action.
+    ///
+    /// Circuits that have already been returned by
+    /// [`get_or_launch_circuit`](HsClientConnector::get_or_launch_circuit),
+    /// and streams on them, are not closed.
+    /// Connection attempts which are already in progress are not affected.
+    pub fn flush_service(&self, hs_id: &HsId) -> Result<(), Bug> {
+        self.services()?.flush_service(hs_id)?;
+        self.subcredentials.forget(hs_id);
+        Ok(())
+    }
+
+    /// Return a histogram of how long it took to fetch each onion service
+    /// descriptor that we have downloaded.
+    ///
+    /// Each time is measured from when we started looking for the descriptor
+    /// until we had a valid copy, including any attempts at other hsdirs that
+    /// failed first.
+    /// Attempts that found no descriptor at all aren't counted.
+    pub fn descriptor_fetch_times(&self) -> Result<LatencyHistogram, Bug> {
+        Ok(self
+            .desc_fetch_times
+            .lock()
+            .map_err(|_| internal!("descriptor fetch times lock poisoned"))?
+            .clone())
+    }
+
     /// Spawn a task which watches `prompt` and calls [`Services::run_housekeeping`]
     fn spawn_housekeeping_task(
         &self,
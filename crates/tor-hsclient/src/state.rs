@@ -1,11 +1,12 @@
 //! Implement a cache for onion descriptors and the facility to remember a bit
 //! about onion service history.
 
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::mem;
 use std::panic::AssertUnwindSafe;
 use std::sync::{Arc, Mutex, MutexGuard};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 use futures::task::{SpawnError, SpawnExt as _};
 use futures::FutureExt as _;
@@ -18,9 +19,12 @@ use tracing::{debug, error, trace};
 
 use safelog::sensitive as sv;
 use tor_basic_utils::define_accessor_trait;
+use tor_basic_utils::retry::RetryDelay;
 use tor_circmgr::isolation::Isolation;
 use tor_error::{debug_report, error_report, internal, Bug, ErrorReport as _};
 use tor_hscrypto::pk::HsId;
+use tor_hscrypto::time::TimePeriod;
+use tor_llcrypto::pk::rsa::RsaIdentity;
 use tor_netdir::NetDir;
 use tor_rtcompat::Runtime;
 
@@ -31,7 +35,7 @@ slotmap::new_key_type! {
     struct TableIndex;
 }
 
-/// Configuration, currently just some retry parameters
+/// Configuration: some retry and circuit sharing parameters, and the pinned rendezvous point
 #[derive(Default, Debug)]
 // This is not really public.
 // It has to be `pub` because it appears in one of the methods in `MockableConnectorData`.
@@ -42,6 +46,46 @@ slotmap::new_key_type! {
 pub struct Config {
     /// Retry parameters
     pub(crate) retry: tor_circmgr::CircuitTiming,
+    /// The relay to always use as our rendezvous point, if any
+    pub(crate) rend_pt: Option<RsaIdentity>,
+}
+
+impl Config {
+    /// Extract the parts of `config` that the HS client connector uses
+    pub(crate) fn new(config: &impl HsClientConnectorConfig) -> Self {
+        Config {
+            retry: config.circuit_timing().clone(),
+            rend_pt: config.path_rules().hs_rendezvous_point(),
+        }
+    }
+
+    /// How many streams we put on one rendezvous circuit before building another
+    fn max_streams_per_circuit(&self) -> usize {
+        self.retry
+            .hs_max_streams_per_circuit()
+            .get()
+            .try_into()
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Whether every request gets its own record, regardless of isolation
+    fn strict_isolation(&self) -> bool {
+        self.retry.hs_strict_isolation()
+    }
+
+    /// How many rendezvous circuits we keep to one service (with compatible isolation)
+    fn max_circuits_per_service(&self) -> usize {
+        self.retry
+            .hs_max_circuits_per_service()
+            .get()
+            .try_into()
+            .unwrap_or(usize::MAX)
+    }
+
+    /// How long we remember that we couldn't find a service's descriptor
+    fn desc_failure_cache_time(&self) -> Duration {
+        self.retry.hs_desc_failure_cache_time()
+    }
 }
 
 define_accessor_trait! {
@@ -55,9 +99,29 @@ define_accessor_trait! {
     // This arrangement is very like that for `CircMgrConfig`.
     pub trait HsClientConnectorConfig {
         circuit_timing: tor_circmgr::CircuitTiming,
+        path_rules: tor_circmgr::PathConfig,
     }
 }
 
+/// Information about an onion service descriptor held in an HS client connector's cache
+///
+/// Returned by
+/// [`HsClientConnector::cached_descriptors`](crate::HsClientConnector::cached_descriptors).
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct CachedDescriptorInfo {
+    /// The onion service that the descriptor is for
+    pub hs_id: HsId,
+    /// When we downloaded the descriptor
+    pub fetched: SystemTime,
+    /// When the descriptor stops being valid, and we will discard it
+    ///
+    /// `None` if the descriptor has no expiry time.
+    pub valid_until: Option<SystemTime>,
+    /// How many introduction points the descriptor lists
+    pub n_intro_points: usize,
+}
+
 /// Number of times we're willing to iterate round the state machine loop
 ///
 /// **Not** the number of retries of failed descriptor downloads, circuits, etc.
@@ -148,12 +212,36 @@ pub(crate) struct Services<D: MockableConnectorData> {
     /// The actual records of our connections/attempts for each service, as separated
     records: isol_map::MultikeyIsolatedMap<TableIndex, HsId, HsClientSecretKeys, ServiceState<D>>,
 
+    /// Services whose descriptor we recently failed to find
+    ///
+    /// This is not keyed by secret keys or isolation:
+    /// if the hsdirs don't have the descriptor, they don't have it for anyone.
+    desc_failures: HashMap<HsId, DescFailure>,
+
     /// Configuration
     ///
     /// `Arc` so that it can be shared with individual hs connector tasks
     config: Arc<Config>,
 }
 
+/// A recent failure to find a service's descriptor
+///
+/// Until `until`, requests to connect to the service during `time_period`
+/// fail with `error`, rather than asking the hsdirs again.
+/// See [`Services::note_desc_failure`].
+#[derive(Debug)]
+struct DescFailure {
+    /// The time period in which we failed to find the descriptor
+    ///
+    /// Each time period has a different blinded identity, and so different hsdirs:
+    /// a failure in one says nothing about the next.
+    time_period: TimePeriod,
+    /// When we will forget this failure
+    until: Instant,
+    /// The error that we got
+    error: ConnError,
+}
+
 /// Entry in the 2nd-level lookup array
 #[allow(dead_code)] // This alias is here for documentation if nothing else
 type ServiceRecord<D> = isol_map::Record<HsClientSecretKeys, ServiceState<D>>;
@@ -175,13 +263,16 @@ enum ServiceState<D: MockableConnectorData> {
         /// Last time we touched this, including reuse
         last_used: Instant,
     },
-    /// We have an open circuit, which we can (hopefully) just use
+    /// We have open circuit(s), which we can (hopefully) just use
     Open {
         /// The state
         data: D,
-        /// The circuit
+        /// The circuits
+        ///
+        /// Never empty.
+        /// We share requests between these; see [`least_busy_circuit`].
         #[educe(Debug(ignore))]
-        circuit: Arc<D::ClientCirc>,
+        circuits: Vec<Arc<D::ClientCirc>>,
         /// Last time we touched this, including reuse
         ///
         /// This is set when we created the circuit, and updated when we
@@ -196,6 +287,8 @@ enum ServiceState<D: MockableConnectorData> {
         /// to demonstrate that there *is* an expiry task.
         /// In the future, it may also serve to cancel old expiry tasks.
         circuit_expiry_task: CircuitExpiryTask,
+        /// When we may next try to build an additional circuit
+        extra_circuit_backoff: ExtraCircuitBackoff,
     },
     /// We have a task trying to find the service and establish the circuit
     ///
@@ -207,6 +300,17 @@ enum ServiceState<D: MockableConnectorData> {
         ///
         /// Lock hierarchy: this lock is "inside" the big lock on `Services`.
         error: Arc<Mutex<Option<ConnError>>>,
+        /// Circuits we already had, if the task is building an additional one
+        ///
+        /// Requests use these, rather than waiting for the task.
+        /// If the task fails, we go back to `Open` with these circuits,
+        /// and the error is not reported to anyone.
+        #[educe(Debug(ignore))]
+        circuits: Vec<Arc<D::ClientCirc>>,
+        /// When we may next try to build an additional circuit
+        ///
+        /// Only meaningful if `circuits` is nonempty; carried back to `Open` with them.
+        extra_circuit_backoff: ExtraCircuitBackoff,
     },
     /// Dummy value for use with temporary mem replace
     Dummy,
@@ -222,6 +326,53 @@ impl<D: MockableConnectorData> ServiceState<D> {
     }
 }
 
+/// Return the circuit in `circuits` with the fewest users, and its number of users
+///
+/// A circuit's users are the streams we have opened on it,
+/// as counted by the circuit itself (see [`MockableConnectorData::circuit_n_streams`]).
+///
+/// When a service is busy, we share requests between several circuits:
+/// a request gets the least busy circuit;
+/// if even that has `max_streams_per_circuit` users,
+/// we build another one in the background (up to `max_circuits_per_service`).
+fn least_busy_circuit<D: MockableConnectorData>(
+    circuits: &[Arc<D::ClientCirc>],
+) -> Option<(&Arc<D::ClientCirc>, usize)> {
+    circuits
+        .iter()
+        .map(|circuit| (circuit, D::circuit_n_streams(circuit)))
+        .min_by_key(|(_, users)| *users)
+}
+
+/// Backoff for building additional circuits to one service
+///
+/// If building an additional circuit fails, we don't try again until a delay
+/// (chosen by [`RetryDelay`]) has passed, even if the circuits we have stay busy.
+/// Otherwise every request to a busy service whose circuits can't be built
+/// would launch yet another build.
+///
+/// The delay grows with each failure, and is reset when a build succeeds,
+/// or when we lose all our circuits to the service.
+#[derive(Debug, Default)]
+struct ExtraCircuitBackoff {
+    /// Schedule of delays after failures
+    delay: RetryDelay,
+    /// If the last build failed, when we may try again
+    retry_at: Option<Instant>,
+}
+
+impl ExtraCircuitBackoff {
+    /// Whether we may launch an additional circuit build at `now`
+    fn may_launch(&self, now: Instant) -> bool {
+        self.retry_at.map_or(true, |retry_at| now >= retry_at)
+    }
+
+    /// Note that an additional circuit build failed at `now`
+    fn note_failure(&mut self, now: Instant) {
+        self.retry_at = Some(now + self.delay.next_delay(&mut rand::thread_rng()));
+    }
+}
+
 /// "Continuation" return type from `obtain_circuit_or_continuation_info`
 type Continuation = (Arc<Mutex<Option<ConnError>>>, postage::barrier::Receiver);
 
@@ -309,6 +460,10 @@ fn obtain_circuit_or_continuation_info<D: MockableConnectorData>(
     let blank_state = || ServiceState::blank(&connector.runtime);
 
     for _recheck in rechecks {
+        let max_streams_per_circuit = guard.config.max_streams_per_circuit();
+        let max_circuits_per_service = guard.config.max_circuits_per_service();
+        let desc_failure =
+            guard.desc_failure(hsid, netdir.hs_time_period(), connector.runtime.now());
         let record = guard
             .records
             .by_index_mut(table_index)
@@ -317,22 +472,26 @@ fn obtain_circuit_or_continuation_info<D: MockableConnectorData>(
 
         trace!("HS conn state: {state:?}");
 
-        let (data, barrier_send) = match state {
+        // `Some` if we are building an additional circuit, but the caller can have this one now
+        let (data, barrier_send, use_meanwhile) = match state {
             ServiceState::Open {
                 data: _,
-                circuit,
+                circuits,
                 last_used,
                 circuit_expiry_task: _,
+                extra_circuit_backoff,
             } => {
                 let now = connector.runtime.now();
-                if !D::circuit_is_ok(circuit) {
+                circuits.retain(|circuit| D::circuit_is_ok(circuit));
+                let Some((circuit, users)) = least_busy_circuit::<D>(circuits) else {
                     // Well that's no good, we need a fresh one, but keep the data
                     let data = match mem::replace(state, ServiceState::Dummy) {
                         ServiceState::Open {
                             data,
                             last_used: _,
-                            circuit: _,
+                            circuits: _,
                             circuit_expiry_task: _,
+                            extra_circuit_backoff: _,
                         } => data,
                         _ => panic!("state changed between matches"),
                     };
@@ -341,16 +500,45 @@ fn obtain_circuit_or_continuation_info<D: MockableConnectorData>(
                         last_used: now,
                     };
                     continue;
-                }
+                };
+                let circuit = circuit.clone();
                 *last_used = now;
                 // No need to tell expiry task about revised expiry time;
                 // it will see the new last_used when it wakes up at the old expiry time.
 
-                return Ok::<_, ConnError>(Right(circuit.clone()));
+                if users < max_streams_per_circuit
+                    || circuits.len() >= max_circuits_per_service
+                    || !extra_circuit_backoff.may_launch(now)
+                {
+                    return Ok::<_, ConnError>(Right(circuit));
+                }
+
+                // All our circuits are busy.  Build another one, but don't make
+                // this request wait for it.
+                let (barrier_send, barrier_recv) = postage::barrier::channel();
+                let (data, circuits, extra_circuit_backoff) =
+                    match mem::replace(state, ServiceState::Dummy) {
+                        ServiceState::Open {
+                            data,
+                            circuits,
+                            extra_circuit_backoff,
+                            ..
+                        } => (data, circuits, extra_circuit_backoff),
+                        _ => panic!("state changed between matches"),
+                    };
+                *state = ServiceState::Working {
+                    barrier_recv,
+                    error: Arc::new(Mutex::new(None)),
+                    circuits,
+                    extra_circuit_backoff,
+                };
+                (data, barrier_send, Some(circuit))
             }
             ServiceState::Working {
                 barrier_recv,
                 error,
+                circuits,
+                extra_circuit_backoff: _,
             } => {
                 if !matches!(
                     barrier_recv.try_recv(),
@@ -361,6 +549,13 @@ fn obtain_circuit_or_continuation_info<D: MockableConnectorData>(
                     *state = blank_state();
                     continue;
                 }
+
+                // If the task is building an additional circuit, use one we have already.
+                circuits.retain(|circuit| D::circuit_is_ok(circuit));
+                if let Some((circuit, _users)) = least_busy_circuit::<D>(circuits) {
+                    return Ok(Right(circuit.clone()));
+                }
+
                 let barrier_recv = barrier_recv.clone();
 
                 // This clone of the error field Arc<Mutex<..>> allows us to collect errors
@@ -373,18 +568,28 @@ fn obtain_circuit_or_continuation_info<D: MockableConnectorData>(
                 return Ok(Left((error, barrier_recv)));
             }
             ServiceState::Closed { .. } => {
+                if let Some(error) = desc_failure {
+                    // We only just failed to find the descriptor; don't ask the hsdirs again yet.
+                    debug!(
+                        "HS connection to {}: descriptor recently not found, not retrying yet",
+                        sv(hsid)
+                    );
+                    return Err(error);
+                }
                 let (barrier_send, barrier_recv) = postage::barrier::channel();
                 let data = match mem::replace(
                     state,
                     ServiceState::Working {
                         barrier_recv,
                         error: Arc::new(Mutex::new(None)),
+                        circuits: vec![],
+                        extra_circuit_backoff: ExtraCircuitBackoff::default(),
                     },
                 ) {
                     ServiceState::Closed { data, .. } => data,
                     _ => panic!("state changed between matches"),
                 };
-                (data, barrier_send)
+                (data, barrier_send, None)
             }
             ServiceState::Dummy => {
                 *state = blank_state();
@@ -397,6 +602,7 @@ fn obtain_circuit_or_continuation_info<D: MockableConnectorData>(
         let connector = (*connector).clone();
         let config = guard.config.clone();
         let netdir = netdir.clone();
+        let time_period = netdir.hs_time_period();
         let secret_keys = secret_keys.clone();
         let hsid = *hsid;
         let connect_future = async move {
@@ -445,21 +651,64 @@ fn obtain_circuit_or_continuation_info<D: MockableConnectorData>(
                     .ok_or_else(|| internal!("HS table entry removed while task running"))?;
                 // Always match this, so we check what we're overwriting
                 let state = &mut **record;
-                let error_store = match state {
-                    ServiceState::Working { error, .. } => error,
+                let (error_store, mut circuits, mut extra_circuit_backoff) = match state {
+                    ServiceState::Working {
+                        error,
+                        circuits,
+                        extra_circuit_backoff,
+                        ..
+                    } => (
+                        error.clone(),
+                        mem::take(circuits),
+                        mem::take(extra_circuit_backoff),
+                    ),
                     _ => return Err(internal!("HS task found state other than Working")),
                 };
+                circuits.retain(|circuit| D::circuit_is_ok(circuit));
 
                 match got {
                     Ok((circuit, circuit_expiry_task)) => {
+                        circuits.push(circuit);
                         *state = ServiceState::Open {
                             data,
-                            circuit,
+                            circuits,
                             last_used,
                             circuit_expiry_task,
+                            extra_circuit_backoff: ExtraCircuitBackoff::default(),
+                        }
+                    }
+                    Err(_) if !circuits.is_empty() => {
+                        // We were building an additional circuit; nobody is waiting for it.
+                        // Carry on with the circuits we have, and don't try again for a while.
+                        extra_circuit_backoff.note_failure(now);
+                        match ServiceState::spawn_circuit_expiry_task(
+                            &connector,
+                            hsid,
+                            table_index,
+                            last_used,
+                            now,
+                        ) {
+                            Ok(circuit_expiry_task) => {
+                                *state = ServiceState::Open {
+                                    data,
+                                    circuits,
+                                    last_used,
+                                    circuit_expiry_task,
+                                    extra_circuit_backoff,
+                                }
+                            }
+                            Err(cause) => {
+                                let error = ConnError::Spawn {
+                                    spawning: "circuit expiry task",
+                                    cause: cause.into(),
+                                };
+                                error_report!(error, "HS connection failure for {}", sv(hsid));
+                                *state = ServiceState::Closed { data, last_used };
+                            }
                         }
                     }
                     Err(error) => {
+                        guard.note_desc_failure(hsid, time_period, now, &error);
                         let mut error_store = error_store
                             .lock()
                             .map_err(|_| internal!("Working error poisoned, cannot store error"))?;
@@ -502,6 +751,10 @@ fn obtain_circuit_or_continuation_info<D: MockableConnectorData>(
                 spawning: "connection task",
                 cause: cause.into(),
             })?;
+
+        if let Some(circuit) = use_meanwhile {
+            return Ok(Right(circuit));
+        }
     }
 
     Err(internal!("HS connector state management malfunction (exceeded MAX_RECHECKS").into())
@@ -512,10 +765,18 @@ impl<D: MockableConnectorData> Services<D> {
     pub(crate) fn new(config: Config) -> Self {
         Services {
             records: Default::default(),
+            desc_failures: Default::default(),
             config: Arc::new(config),
         }
     }
 
+    /// Replace our configuration
+    ///
+    /// Connection attempts that are already in progress keep using the old one.
+    pub(crate) fn reconfigure(&mut self, config: Config) {
+        self.config = Arc::new(config);
+    }
+
     /// Connect to a hidden service
     // We *do* drop guard.  There is *one* await point, just after drop(guard).
     pub(crate) async fn get_or_launch_connection(
@@ -550,10 +811,15 @@ impl<D: MockableConnectorData> Services<D> {
             trace!("HS conn get_or_launch: {hs_id:?} {isolation:?} {secret_keys:?}");
             //trace!("HS conn services: {services:?}");
 
-            table_index =
+            table_index = if services.config.strict_isolation() {
                 services
                     .records
-                    .index_or_insert_with(&hs_id, &secret_keys, isolation, blank_state);
+                    .insert_separate(&hs_id, &secret_keys, isolation, blank_state())
+            } else {
+                services
+                    .records
+                    .index_or_insert_with(&hs_id, &secret_keys, isolation, blank_state)
+            };
 
             let guard = guard;
             got = obtain(table_index, guard);
@@ -594,10 +860,135 @@ impl<D: MockableConnectorData> Services<D> {
         self.expire_old_data(now);
     }
 
+    /// Remember, if every hsdir told us it doesn't have the descriptor, that connecting to `hs_id` failed
+    ///
+    /// For the configured `hs_desc_failure_cache_time`,
+    /// requests which would otherwise ask the hsdirs again for the descriptor
+    /// (in the same time period) fail with a copy of `error` instead.
+    /// This stops a flood of requests for a service which is down
+    /// from turning into a flood of requests to its hsdirs.
+    ///
+    /// Other failures are not remembered.
+    /// If we couldn't reach an hsdir (for example, because of a timeout or a failed circuit),
+    /// the problem may well be ours, and over by the next attempt;
+    /// and if we couldn't introduce ourselves to the service,
+    /// we'd want to try a different introduction point next time.
+    fn note_desc_failure(
+        &mut self,
+        hs_id: HsId,
+        time_period: TimePeriod,
+        now: Instant,
+        error: &ConnError,
+    ) {
+        if !error.is_descriptor_absent() {
+            return;
+        }
+        let cache_time = self.config.desc_failure_cache_time();
+        if cache_time.is_zero() {
+            return;
+        }
+        let Some(until) = now.checked_add(cache_time) else {
+            return;
+        };
+        self.desc_failures.insert(
+            hs_id,
+            DescFailure {
+                time_period,
+                until,
+                error: error.clone(),
+            },
+        );
+    }
+
+    /// Return the error to report if we recently failed to find the descriptor for `hs_id`
+    fn desc_failure(
+        &self,
+        hs_id: &HsId,
+        time_period: TimePeriod,
+        now: Instant,
+    ) -> Option<ConnError> {
+        self.desc_failures
+            .get(hs_id)
+            .filter(|failure| failure.time_period == time_period && now < failure.until)
+            .map(|failure| failure.error.clone())
+    }
+
+    /// Return the isolation of the record which owns `circuit`, a circuit to `hs_id`
+    pub(crate) fn circuit_isolation(
+        &self,
+        hs_id: &HsId,
+        circuit: &Arc<D::ClientCirc>,
+    ) -> Option<Box<dyn Isolation>> {
+        self.records
+            .by_k1(hs_id)
+            .find(|record| match &***record {
+                ServiceState::Open { circuits, .. } | ServiceState::Working { circuits, .. } => {
+                    circuits.iter().any(|c| Arc::ptr_eq(c, circuit))
+                }
+                ServiceState::Closed { .. } | ServiceState::Dummy => false,
+            })
+            .map(|record| record.clone_isolation())
+    }
+
+    /// Return information about every descriptor we have cached
+    ///
+    /// Descriptors held by a connection task that is currently running are not included.
+    pub(crate) fn cached_descriptors(&self) -> Vec<CachedDescriptorInfo> {
+        self.records
+            .iter()
+            .filter_map(|(hs_id, record)| match &**record {
+                ServiceState::Closed { data, .. } | ServiceState::Open { data, .. } => {
+                    data.cached_descriptor(*hs_id)
+                }
+                ServiceState::Working { .. } | ServiceState::Dummy => None,
+            })
+            .collect()
+    }
+
+    /// Forget the descriptors we have for `hs_id`, and stop reusing our circuits to it
+    ///
+    /// Circuits that have already been handed out keep working,
+    /// but subsequent requests will fetch a fresh descriptor and build a new circuit,
+    /// even if we recently failed to find the descriptor.
+    /// Connection tasks that are already running are not affected.
+    pub(crate) fn flush_service(&mut self, hs_id: &HsId) -> Result<(), Bug> {
+        self.desc_failures.remove(hs_id);
+        for record in self.records.by_k1_mut(hs_id) {
+            let state = &mut **record;
+            match state {
+                ServiceState::Closed { data, .. } => data.forget_descriptor(),
+                ServiceState::Open { .. } => match mem::replace(state, ServiceState::Dummy) {
+                    ServiceState::Open {
+                        mut data,
+                        circuits,
+                        last_used,
+                        circuit_expiry_task,
+                        extra_circuit_backoff: _,
+                    } => {
+                        drop(circuits);
+                        // The expiry task will see that we are Closed, and exit.
+                        drop(circuit_expiry_task);
+                        data.forget_descriptor();
+                        *state = ServiceState::Closed { data, last_used };
+                    }
+                    _ => return Err(internal!("state changed between matches")),
+                },
+                ServiceState::Working { .. } | ServiceState::Dummy => {}
+            }
+        }
+        Ok(())
+    }
+
     /// Delete data we aren't interested in any more
     fn expire_old_data(&mut self, now: Instant) {
+        self.desc_failures.retain(|_, failure| now < failure.until);
+
+        // With strict isolation, nothing will ever look up a record again
+        // once its circuits have gone.
+        let strict_isolation = self.config.strict_isolation();
         self.records
             .retain(|hsid, record, _table_index| match &**record {
+                ServiceState::Closed { .. } if strict_isolation => false,
                 ServiceState::Closed { data: _, last_used } => {
                     let Some(expiry_time) = last_used.checked_add(RETAIN_DATA_AFTER_LAST_USE)
                     else {
@@ -676,12 +1067,13 @@ impl<D: MockableConnectorData> ServiceState<D> {
                         match mem::replace(state, ServiceState::Dummy) {
                             ServiceState::Open {
                                 data,
-                                circuit,
+                                circuits,
                                 last_used,
                                 circuit_expiry_task,
+                                extra_circuit_backoff: _,
                             } => {
                                 debug!("HS connection expires: {hsid}");
-                                drop(circuit);
+                                drop(circuits);
                                 drop(circuit_expiry_task); // that's us
                                 *state = ServiceState::Closed { data, last_used };
                                 break;
@@ -724,6 +1116,15 @@ pub trait MockableConnectorData: Default + Debug + Send + Sync + 'static {
 
     /// Is circuit OK?  Ie, not `.is_closing()`.
     fn circuit_is_ok(circuit: &Self::ClientCirc) -> bool;
+
+    /// How many streams are open on this circuit?  Ie, `.n_client_streams()`.
+    fn circuit_n_streams(circuit: &Arc<Self::ClientCirc>) -> usize;
+
+    /// Describe the descriptor we have cached for `hs_id`, if any
+    fn cached_descriptor(&self, hs_id: HsId) -> Option<CachedDescriptorInfo>;
+
+    /// Discard any cached descriptor, so that the next connection fetches a fresh one
+    fn forget_descriptor(&mut self);
 }
 
 #[cfg(test)]
@@ -744,10 +1145,14 @@ pub(crate) mod test {
     use super::*;
     use crate::*;
     use futures::{poll, SinkExt};
+    use retry_error::RetryError;
+    use std::collections::HashSet;
     use std::fmt;
+    use std::num::NonZeroU32;
     use std::task::Poll::{self, *};
     use tokio::pin;
     use tokio_crate as tokio;
+    use tor_llcrypto::pk::ed25519::Ed25519Identity;
     use tor_rtcompat::{test_with_one_runtime, SleepProvider};
     use tor_rtmock::MockRuntime;
     use tracing_test::traced_test;
@@ -757,6 +1162,8 @@ pub(crate) mod test {
     #[derive(Debug, Default)]
     struct MockData {
         connect_called: usize,
+        /// When we "fetched" our "descriptor", if we have one
+        desc_fetched: Option<SystemTime>,
     }
 
     /// Type indicating what our `connect()` should return; it always makes a fresh MockCirc
@@ -814,6 +1221,8 @@ pub(crate) mod test {
             _secret_keys: HsClientSecretKeys,
         ) -> Result<Arc<Self::ClientCirc>, E> {
             data.connect_called += 1;
+            data.desc_fetched
+                .get_or_insert_with(|| connector.runtime.wallclock());
             let make = {
                 let connect_called = data.connect_called;
                 move |()| Arc::new(MockCirc::new(connect_called))
@@ -833,6 +1242,25 @@ pub(crate) mod test {
         fn circuit_is_ok(circuit: &Self::ClientCirc) -> bool {
             *circuit.ok.lock().unwrap()
         }
+
+        fn circuit_n_streams(circuit: &Arc<Self::ClientCirc>) -> usize {
+            // In these tests, each caller holding on to the circuit stands for
+            // one open stream.
+            Arc::strong_count(circuit) - 1
+        }
+
+        fn cached_descriptor(&self, hs_id: HsId) -> Option<CachedDescriptorInfo> {
+            Some(CachedDescriptorInfo {
+                hs_id,
+                fetched: self.desc_fetched?,
+                valid_until: None,
+                n_intro_points: 3,
+            })
+        }
+
+        fn forget_descriptor(&mut self) {
+            self.desc_fetched = None;
+        }
     }
 
     /// Makes a non-empty `HsClientSecretKeys`, containing (somehow) `kk`
@@ -882,6 +1310,8 @@ pub(crate) mod test {
             runtime,
             circpool,
             services: Default::default(),
+            desc_fetch_times: Default::default(),
+            subcredentials: Default::default(),
             mock_for_state,
         };
         let keys = HsClientSecretKeysBuilder::default().build().unwrap();
@@ -1047,4 +1477,296 @@ pub(crate) mod test {
             assert_ne!(c1, c_isol_2);
         });
     }
+
+    #[test]
+    #[traced_test]
+    fn multiplex() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let (mut hsconn, keys, _give_send) = mk_hsconn(runtime.clone());
+            let retry = tor_circmgr::CircuitTimingBuilder::default()
+                .hs_max_streams_per_circuit(NonZeroU32::new(2).unwrap())
+                .hs_max_circuits_per_service(NonZeroU32::new(2).unwrap())
+                .build()
+                .unwrap();
+            hsconn.services = Arc::new(Mutex::new(Services::new(Config {
+                retry,
+                ..Config::default()
+            })));
+
+            // Up to the limit, requests share a circuit
+            let c1 = launch_one(&hsconn, 0, &keys, None).await.unwrap();
+            let c2 = launch_one(&hsconn, 0, &keys, None).await.unwrap();
+            assert_eq!(c1, c2);
+
+            // When it's full, we still get it, but another is built in the background
+            let c3 = launch_one(&hsconn, 0, &keys, None).await.unwrap();
+            assert_eq!(c1, c3);
+            runtime.progress_until_stalled().await;
+
+            // Now new requests go to the new circuit
+            let c4 = launch_one(&hsconn, 0, &keys, None).await.unwrap();
+            assert_ne!(c1, c4);
+            assert_eq!(c4.connect_called, 2);
+            let c5 = launch_one(&hsconn, 0, &keys, None).await.unwrap();
+            assert_eq!(c4, c5);
+
+            // At the circuit limit, we use the least busy circuit, even though it's full
+            let c6 = launch_one(&hsconn, 0, &keys, None).await.unwrap();
+            assert_eq!(c4, c6);
+            runtime.progress_until_stalled().await;
+
+            // Once the first circuit's users go away, it's the least busy again
+            drop((c1, c2, c3));
+            let c7 = launch_one(&hsconn, 0, &keys, None).await.unwrap();
+            assert_eq!(c7.connect_called, 1);
+
+            // Incompatible isolation never shares
+            let c_isol = launch_one(&hsconn, 0, &keys, mk_isol("a")).await.unwrap();
+            let c_isol_other = launch_one(&hsconn, 0, &keys, mk_isol("b")).await.unwrap();
+            assert_ne!(c_isol, c_isol_other);
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn strict_isolation() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let (mut hsconn, keys, _give_send) = mk_hsconn(runtime.clone());
+            let retry = tor_circmgr::CircuitTimingBuilder::default()
+                .hs_strict_isolation(true)
+                .build()
+                .unwrap();
+            hsconn.services = Arc::new(Mutex::new(Services::new(Config {
+                retry,
+                ..Config::default()
+            })));
+            let hs_id: HsId = [0_u8; 32].into();
+
+            // Even identical isolations don't share
+            let c1 = launch_one(&hsconn, 0, &keys, mk_isol("a")).await.unwrap();
+            let c2 = launch_one(&hsconn, 0, &keys, mk_isol("a")).await.unwrap();
+            assert_ne!(c1, c2);
+            assert_eq!(c2.connect_called, 1);
+
+            // And nothing is narrowed
+            let c3 = launch_one(&hsconn, 0, &keys, mk_isol("ab")).await.unwrap();
+            let isol = |c: &Arc<MockCirc>| {
+                let isol = hsconn.circuit_isolation(&hs_id, c).unwrap().unwrap();
+                isol.downcast_ref::<NarrowableIsolation>()
+                    .unwrap()
+                    .0
+                    .clone()
+            };
+            assert_eq!(isol(&c1), "a");
+            assert_eq!(isol(&c3), "ab");
+
+            // Records without circuits are discarded at once
+            drop((c1, c2, c3));
+            runtime.progress_until_stalled().await;
+            runtime
+                .mock_sleep()
+                .advance(RETAIN_CIRCUIT_AFTER_LAST_USE * 2);
+            runtime.progress_until_stalled().await;
+            hsconn.services().unwrap().run_housekeeping(runtime.now());
+            assert_eq!(hsconn.services().unwrap().records.by_k1(&hs_id).count(), 0);
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn circuit_isolation() {
+        test_with_one_runtime!(|runtime| async {
+            let (hsconn, keys, _give_send) = mk_hsconn(runtime);
+            let hs_id: HsId = [0_u8; 32].into();
+
+            let c1 = launch_one(&hsconn, 0, &keys, mk_isol("a")).await.unwrap();
+            let c2 = launch_one(&hsconn, 0, &keys, mk_isol("ab")).await.unwrap();
+            assert_eq!(c1, c2);
+
+            // The isolation has been narrowed to suit both requests
+            let isol = hsconn.circuit_isolation(&hs_id, &c1).unwrap().unwrap();
+            let isol = isol.downcast_ref::<NarrowableIsolation>().unwrap();
+            assert_eq!(isol.0, "ab");
+
+            // We don't know about circuits to other services
+            let other: HsId = [1_u8; 32].into();
+            assert!(hsconn.circuit_isolation(&other, &c1).unwrap().is_none());
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn descriptor_cache() {
+        test_with_one_runtime!(|runtime| async {
+            let (hsconn, keys, _give_send) = mk_hsconn(runtime);
+            let hs_id_0: HsId = [0_u8; 32].into();
+            let hs_id_1: HsId = [1_u8; 32].into();
+
+            let c0 = launch_one(&hsconn, 0, &keys, None).await.unwrap();
+            let c1 = launch_one(&hsconn, 1, &keys, None).await.unwrap();
+
+            let cached_ids = |hsconn: &HsClientConnector<_, MockData>| {
+                hsconn
+                    .cached_descriptors()
+                    .unwrap()
+                    .into_iter()
+                    .map(|info| info.hs_id)
+                    .collect::<HashSet<_>>()
+            };
+            let both = HashSet::from([hs_id_0, hs_id_1]);
+            assert_eq!(cached_ids(&hsconn), both);
+
+            // Flushing one service forgets its descriptor and its circuit
+            hsconn.flush_service(&hs_id_0).unwrap();
+            assert_eq!(cached_ids(&hsconn), HashSet::from([hs_id_1]));
+            let c0b = launch_one(&hsconn, 0, &keys, None).await.unwrap();
+            assert_ne!(c0, c0b);
+            assert_eq!(c0b.connect_called, 2);
+            assert_eq!(cached_ids(&hsconn), both);
+
+            // The other service is unaffected
+            assert_eq!(c1, launch_one(&hsconn, 1, &keys, None).await.unwrap());
+
+            // Flushing a service we know nothing about is fine
+            hsconn.flush_service(&[2_u8; 32].into()).unwrap();
+        });
+    }
+
+    /// Make an error like the one we get when we fail to download a descriptor
+    ///
+    /// Each of `errors` is what happened when we asked one hsdir.
+    fn desc_download_failed(errors: impl IntoIterator<Item = DescriptorErrorDetail>) -> ConnError {
+        let mut attempts = RetryError::in_attempt_to("retrieve hidden service descriptor");
+        for (i, error) in errors.into_iter().enumerate() {
+            attempts.push(tor_error::Report(DescriptorError {
+                hsdir: Ed25519Identity::from([i as u8; 32]).into(),
+                error,
+            }));
+        }
+        E::DescriptorDownload(attempts)
+    }
+
+    /// Make an error like the one we get when an hsdir doesn't have the descriptor
+    fn hsdir_not_found() -> DescriptorErrorDetail {
+        tor_dirclient::RequestError::HttpStatus(404, "Not found".into()).into()
+    }
+
+    /// Make an error like the one we get when no hsdir has the descriptor
+    fn desc_not_found() -> ConnError {
+        desc_download_failed([hsdir_not_found(), hsdir_not_found()])
+    }
+
+    #[test]
+    #[traced_test]
+    fn desc_failure_cache() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let (hsconn, keys, mut give_send) = mk_hsconn(runtime.clone());
+            let hs_id: HsId = [0_u8; 32].into();
+            let cache_time = hsconn.services().unwrap().config.desc_failure_cache_time();
+
+            give_send.send(Ready(Err(desc_not_found()))).await.unwrap();
+            let e = launch_one(&hsconn, 0, &keys, None).await.unwrap_err();
+            assert!(matches!(e, E::DescriptorDownload(_)));
+
+            // Even once the service is back, we don't ask again for a while,
+            // whatever our isolation.
+            give_send.send(Ready(Ok(()))).await.unwrap();
+            let e = launch_one(&hsconn, 0, &keys, mk_isol("a"))
+                .await
+                .unwrap_err();
+            assert!(matches!(e, E::DescriptorDownload(_)));
+            // Other services are unaffected.
+            launch_one(&hsconn, 1, &keys, None).await.unwrap();
+
+            // When the failure is forgotten, we ask again.
+            runtime.mock_sleep().advance(cache_time);
+            launch_one(&hsconn, 0, &keys, None).await.unwrap();
+
+            // Flushing the service lets the user retry at once.
+            hsconn.flush_service(&hs_id).unwrap();
+            give_send.send(Ready(Err(desc_not_found()))).await.unwrap();
+            launch_one(&hsconn, 0, &keys, None).await.unwrap_err();
+            give_send.send(Ready(Ok(()))).await.unwrap();
+            launch_one(&hsconn, 0, &keys, None).await.unwrap_err();
+            hsconn.flush_service(&hs_id).unwrap();
+            launch_one(&hsconn, 0, &keys, None).await.unwrap();
+
+            // Other failures aren't remembered.
+            hsconn.flush_service(&hs_id).unwrap();
+            give_send
+                .send(Ready(Err(E::NoUsableIntroPoints)))
+                .await
+                .unwrap();
+            launch_one(&hsconn, 0, &keys, None).await.unwrap_err();
+            give_send.send(Ready(Ok(()))).await.unwrap();
+            launch_one(&hsconn, 0, &keys, None).await.unwrap();
+
+            // Nor are failures to reach the hsdirs, even if some of them didn't
+            // have the descriptor.
+            for error in [
+                desc_download_failed([DescriptorErrorDetail::Timeout]),
+                desc_download_failed([hsdir_not_found(), DescriptorErrorDetail::Timeout]),
+                desc_download_failed([]),
+            ] {
+                hsconn.flush_service(&hs_id).unwrap();
+                give_send.send(Ready(Err(error))).await.unwrap();
+                launch_one(&hsconn, 0, &keys, None).await.unwrap_err();
+                assert!(hsconn.services().unwrap().desc_failures.is_empty());
+                give_send.send(Ready(Ok(()))).await.unwrap();
+                launch_one(&hsconn, 0, &keys, None).await.unwrap();
+            }
+
+            // Housekeeping discards expired failures.
+            hsconn.flush_service(&hs_id).unwrap();
+            give_send.send(Ready(Err(desc_not_found()))).await.unwrap();
+            launch_one(&hsconn, 0, &keys, None).await.unwrap_err();
+            assert_eq!(hsconn.services().unwrap().desc_failures.len(), 1);
+            runtime.mock_sleep().advance(cache_time);
+            hsconn.services().unwrap().run_housekeeping(runtime.now());
+            assert!(hsconn.services().unwrap().desc_failures.is_empty());
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn multiplex_build_fails() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let (mut hsconn, keys, mut give_send) = mk_hsconn(runtime.clone());
+            let retry = tor_circmgr::CircuitTimingBuilder::default()
+                .hs_max_streams_per_circuit(NonZeroU32::new(1).unwrap())
+                .build()
+                .unwrap();
+            hsconn.services = Arc::new(Mutex::new(Services::new(Config {
+                retry,
+                ..Config::default()
+            })));
+
+            let c1 = launch_one(&hsconn, 0, &keys, None).await.unwrap();
+
+            // The additional circuit can't be built; we carry on with the one we have
+            give_send.send(Ready(Err(E::NoHsDirs))).await.unwrap();
+            let c2 = launch_one(&hsconn, 0, &keys, None).await.unwrap();
+            assert_eq!(c1, c2);
+            runtime.progress_until_stalled().await;
+
+            // We don't try again straight away, even once we could succeed
+            give_send.send(Ready(Ok(()))).await.unwrap();
+            let c3 = launch_one(&hsconn, 0, &keys, None).await.unwrap();
+            assert_eq!(c1, c3);
+            runtime.progress_until_stalled().await;
+            let c4 = launch_one(&hsconn, 0, &keys, None).await.unwrap();
+            assert_eq!(c1, c4);
+            runtime.progress_until_stalled().await;
+
+            // But once the retry delay has passed, we build it
+            runtime.mock_sleep().advance(Duration::from_secs(60));
+            let c5 = launch_one(&hsconn, 0, &keys, None).await.unwrap();
+            assert_eq!(c1, c5);
+            runtime.progress_until_stalled().await;
+            let c6 = launch_one(&hsconn, 0, &keys, None).await.unwrap();
+            assert_ne!(c1, c6);
+            // One successful build, one failed one, and this one
+            assert_eq!(c6.connect_called, 3);
+        });
+    }
 }
@@ -0,0 +1,269 @@
+//! A cache of blinded keys and subcredentials.
+//!
+//! Deriving the blinded key for an onion service identity involves a scalar
+//! multiplication, and deriving its subcredential takes two SHA3 digests.
+//! Busy clients and services would otherwise repeat this work for every
+//! connection or introduction request, even though the results only change
+//! once per time period.
+//!
+//! Each onion service client or service keeps its own cache: since the cache
+//! lists the services that a client has visited, it must not outlive the
+//! client, or be shared with anybody else.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use tor_llcrypto::pk::keymanip::BlindingError;
+
+use crate::pk::{HsBlindIdKey, HsId, HsIdKey};
+use crate::time::TimePeriod;
+use crate::Subcredential;
+
+/// The largest number of entries we keep in a [`SubcredentialCache`].
+///
+/// If we would go over this, we forget everything and start again:
+/// anybody who needs more keys than this at once can afford to recompute them.
+const MAX_ENTRIES: usize = 1024;
+
+/// A cache of the blinded keys and subcredentials of onion services,
+/// keyed by identity and time period.
+///
+/// When we are first asked about a time period that is later than any we
+/// have seen before, we forget every entry for a period before the one
+/// preceding it.  (Services still need the previous period's keys for a
+/// while after the rollover.)
+#[derive(Debug, Default)]
+pub struct SubcredentialCache {
+    /// The cache's contents.
+    inner: Mutex<Inner>,
+}
+
+/// The contents of a [`SubcredentialCache`].
+#[derive(Debug, Default)]
+struct Inner {
+    /// The latest time period we have been asked about.
+    latest: Option<TimePeriod>,
+    /// The keys that we have derived.
+    entries: HashMap<(HsId, TimePeriod), Entry>,
+}
+
+/// The keys derived for one identity in one time period.
+#[derive(Clone, Debug)]
+struct Entry {
+    /// The blinded key.
+    blinded_key: HsBlindIdKey,
+    /// The subcredential.
+    subcredential: Subcredential,
+}
+
+impl SubcredentialCache {
+    /// Return a new, empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Derive the blinded key and subcredential for `id` during `period`,
+    /// or return the ones that we derived before.
+    ///
+    /// This is a caching version of [`HsIdKey::compute_blinded_key`].
+    pub fn compute_blinded_key(
+        &self,
+        id: &HsIdKey,
+        period: TimePeriod,
+    ) -> Result<(HsBlindIdKey, Subcredential), BlindingError> {
+        let key = (id.id(), period);
+        if let Some(entry) = self.lookup(&key) {
+            return Ok((entry.blinded_key, entry.subcredential));
+        }
+        let (blinded_key, subcredential) = id.compute_blinded_key(period)?;
+        self.insert(
+            key,
+            Entry {
+                blinded_key: blinded_key.clone(),
+                subcredential,
+            },
+        );
+        Ok((blinded_key, subcredential))
+    }
+
+    /// Compute the subcredential for `id` during `period`, given its
+    /// `blinded_key` for that period, or return the one that we computed before.
+    ///
+    /// This is a caching version of [`HsIdKey::compute_subcredential`].
+    pub fn compute_subcredential(
+        &self,
+        id: &HsIdKey,
+        blinded_key: &HsBlindIdKey,
+        period: TimePeriod,
+    ) -> Subcredential {
+        let key = (id.id(), period);
+        match self.lookup(&key) {
+            // Only trust the cached value if it was computed from the same
+            // blinded key.
+            Some(entry) if entry.blinded_key.id() == blinded_key.id() => entry.subcredential,
+            _ => {
+                let subcredential = id.compute_subcredential(blinded_key, period);
+                self.insert(
+                    key,
+                    Entry {
+                        blinded_key: blinded_key.clone(),
+                        subcredential,
+                    },
+                );
+                subcredential
+            }
+        }
+    }
+
+    /// Return the number of entries in this cache.
+    pub fn len(&self) -> usize {
+        self.inner.lock().expect("lock poisoned").entries.len()
+    }
+
+    /// Return true if this cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget every entry for the onion service `id`.
+    pub fn forget(&self, id: &HsId) {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        inner.entries.retain(|(entry_id, _), _| entry_id != id);
+    }
+
+    /// Forget every entry in this cache.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        inner.entries.clear();
+    }
+
+    /// Look up the entry for `key`, expiring old entries if its period is
+    /// new.
+    fn lookup(&self, key: &(HsId, TimePeriod)) -> Option<Entry> {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        inner.note_period(key.1);
+        inner.entries.get(key).cloned()
+    }
+
+    /// Remember `entry` for `key`.
+    fn insert(&self, key: (HsId, TimePeriod), entry: Entry) {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        if inner.entries.len() >= MAX_ENTRIES {
+            inner.entries.clear();
+        }
+        inner.entries.insert(key, entry);
+    }
+}
+
+impl Inner {
+    /// Record that we have been asked about `period`, and forget any entries
+    /// that are too old if it is the latest one yet.
+    fn note_period(&mut self, period: TimePeriod) {
+        match self.latest {
+            Some(latest) if period <= latest => return,
+            // Periods of different lengths can't be compared: this only
+            // happens on test networks, and we don't expire anything then.
+            Some(latest) if period.partial_cmp(&latest).is_none() => return,
+            _ => {}
+        }
+        self.latest = Some(period);
+        let oldest = period.prev().unwrap_or(period);
+        self.entries
+            .retain(|(_, p), _| p.partial_cmp(&oldest) != Some(Ordering::Less));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use tor_llcrypto::pk::ed25519;
+
+    /// Return the identity key derived from `seed`.
+    fn id_key(seed: u8) -> HsIdKey {
+        let keypair = ed25519::Keypair::from_bytes(&[seed; 32]);
+        HsIdKey::from(keypair.verifying_key())
+    }
+
+    #[test]
+    fn cached() {
+        let cache = SubcredentialCache::new();
+        let id = id_key(1);
+        let period = TimePeriod::from_parts(1440, 19000, 43200);
+
+        let (blinded, subcred) = cache.compute_blinded_key(&id, period).unwrap();
+        let (blinded2, subcred2) = cache.compute_blinded_key(&id, period).unwrap();
+        let (expect_blinded, expect_subcred) = id.compute_blinded_key(period).unwrap();
+        assert_eq!(blinded.id(), expect_blinded.id());
+        assert_eq!(blinded2.id(), expect_blinded.id());
+        assert_eq!(subcred.as_ref(), expect_subcred.as_ref());
+        assert_eq!(subcred2.as_ref(), expect_subcred.as_ref());
+        assert_eq!(cache.len(), 1);
+
+        let subcred3 = cache.compute_subcredential(&id, &blinded, period);
+        assert_eq!(subcred3.as_ref(), expect_subcred.as_ref());
+        assert_eq!(cache.len(), 1);
+
+        // A different blinded key doesn't get the cached subcredential.
+        let other_period = TimePeriod::from_parts(1440, 19001, 43200);
+        let (other_blinded, _) = id.compute_blinded_key(other_period).unwrap();
+        let subcred4 = cache.compute_subcredential(&id, &other_blinded, period);
+        assert_eq!(
+            subcred4.as_ref(),
+            id.compute_subcredential(&other_blinded, period).as_ref()
+        );
+    }
+
+    #[test]
+    fn expiry() {
+        let cache = SubcredentialCache::new();
+        let id = id_key(1);
+        let id2 = id_key(2);
+        let p1 = TimePeriod::from_parts(1440, 19000, 43200);
+        let p2 = p1.next().unwrap();
+        let p3 = p2.next().unwrap();
+
+        let _ = cache.compute_blinded_key(&id, p1).unwrap();
+        let _ = cache.compute_blinded_key(&id2, p1).unwrap();
+        let _ = cache.compute_blinded_key(&id, p2).unwrap();
+        // We keep the previous period's entries.
+        assert_eq!(cache.len(), 3);
+
+        // Asking about an older period doesn't expire anything.
+        let _ = cache.compute_blinded_key(&id, p1).unwrap();
+        assert_eq!(cache.len(), 3);
+
+        // Once we move on to p3, p1 is too old.
+        let _ = cache.compute_blinded_key(&id, p3).unwrap();
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn forget() {
+        let cache = SubcredentialCache::new();
+        let id = id_key(1);
+        let id2 = id_key(2);
+        let period = TimePeriod::from_parts(1440, 19000, 43200);
+
+        let _ = cache.compute_blinded_key(&id, period).unwrap();
+        let _ = cache.compute_blinded_key(&id2, period).unwrap();
+        cache.forget(&id.id());
+        assert_eq!(cache.len(), 1);
+
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
@@ -42,6 +42,7 @@
 //! <!-- @@ end lint list maintained by maint/add_warning @@ -->
 #![allow(dead_code, unused_variables)]
 
+pub mod cache;
 mod macros;
 #[cfg(feature = "ope")]
 pub mod ope;
@@ -9,6 +9,7 @@ use std::str::FromStr;
 
 use digest::Digest;
 use itertools::{chain, Itertools};
+use subtle::{Choice, ConstantTimeEq};
 use thiserror::Error;
 use tor_basic_utils::{impl_debug_hex, StrExt as _};
 use tor_llcrypto::d::Sha3_256;
@@ -119,16 +120,12 @@ const HSID_ONION_VERSION: u8 = 0x03;
 /// The fixed string `.onion`
 pub const HSID_ONION_SUFFIX: &str = ".onion";
 
+/// The length of the base32 part of a v3 `.onion` address
+pub const HSID_BASE32_LEN: usize = 56;
+
 impl Display for HsId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // rend-spec-v3 s.6 [ONIONADDRESS]
-        let checksum = self.onion_checksum();
-        let binary = chain!(self.0.as_ref(), &checksum, &[HSID_ONION_VERSION],)
-            .cloned()
-            .collect_vec();
-        let mut b32 = data_encoding::BASE32_NOPAD.encode(&binary);
-        b32.make_ascii_lowercase();
-        write!(f, "{}{}", b32, HSID_ONION_SUFFIX)
+        write!(f, "{}{}", self.to_base32(), HSID_ONION_SUFFIX)
     }
 }
 
@@ -139,7 +136,7 @@ impl safelog::Redactable for HsId {
     fn display_redacted(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let unredacted = self.to_string();
         /// Length of the base32 data part of the address
-        const DATA: usize = 56;
+        const DATA: usize = HSID_BASE32_LEN;
         assert_eq!(unredacted.len(), DATA + HSID_ONION_SUFFIX.len());
 
         // We show this part of the domain:
@@ -167,6 +164,24 @@ impl FromStr for HsId {
             return Err(PE::HsIdContainsSubdomain);
         }
 
+        HsId::from_base32(s)
+    }
+}
+
+impl HsId {
+    /// Parse the base32 part of a v3 `.onion` address (without the `.onion` suffix)
+    ///
+    /// This is the canonical parser for onion addresses:
+    /// [`FromStr`] uses it after stripping the suffix,
+    /// and it should be used wherever an address appears without its suffix
+    /// (for example, in key paths).
+    ///
+    /// The input is case-insensitive.
+    /// The version and checksum are verified;
+    /// the checksum is compared in constant time.
+    pub fn from_base32(s: &str) -> Result<Self, HsIdParseError> {
+        use HsIdParseError as PE;
+
         // We must convert to uppercase because RFC4648 says so and that's what Rust
         // ecosystem libraries for base32 expect.  All this allocation and copying is
         // still probably less work than the SHA3 for the checksum.
@@ -191,11 +206,32 @@ impl FromStr for HsId {
         if version != HSID_ONION_VERSION {
             return Err(PE::UnsupportedVersion(version));
         }
-        if checksum != tentative.onion_checksum() {
+        if !bool::from(checksum.ct_eq(&tentative.onion_checksum())) {
             return Err(PE::WrongChecksum);
         }
         Ok(tentative)
     }
+
+    /// Return the base32 part of this `HsId`'s `.onion` address (without the `.onion` suffix)
+    ///
+    /// The result is in lowercase, and is [`HSID_BASE32_LEN`] characters long.
+    /// [`Display`] is this, followed by [`HSID_ONION_SUFFIX`].
+    pub fn to_base32(&self) -> String {
+        // rend-spec-v3 s.6 [ONIONADDRESS]
+        let checksum = self.onion_checksum();
+        let binary = chain!(self.0.as_ref(), &checksum, &[HSID_ONION_VERSION],)
+            .cloned()
+            .collect_vec();
+        let mut b32 = data_encoding::BASE32_NOPAD.encode(&binary);
+        b32.make_ascii_lowercase();
+        b32
+    }
+}
+
+impl ConstantTimeEq for HsId {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.ct_eq(&other.0)
+    }
 }
 
 /// Error that can occur parsing an `HsId` from a v3 `.onion` domain name
@@ -679,6 +715,11 @@ mod test {
 
         assert_eq!(onion.parse::<HsId>().unwrap(), hsid);
         assert_eq!(hsid.to_string(), onion);
+        assert_eq!(HsId::from_base32(b32).unwrap(), hsid);
+        assert_eq!(HsId::from_base32(&b32.to_uppercase()).unwrap(), hsid);
+        assert_eq!(hsid.to_base32(), b32);
+        assert_eq!(b32.len(), HSID_BASE32_LEN);
+        assert!(bool::from(hsid.ct_eq(&HsId::from_base32(b32).unwrap())));
 
         let weird_case: String = izip!(onion.chars(), [false, true].iter().cloned().cycle(),)
             .map(|(c, swap)| if swap { c.to_ascii_uppercase() } else { c })
@@ -18,6 +18,14 @@ pub struct ProxyConfig {
     /// matches, we take the DestroyCircuit action.
     #[builder(sub_builder, setter(custom))]
     pub(crate) proxy_ports: ProxyRuleList,
+
+    /// How long a forwarded connection may go without any data in either
+    /// direction before we close it.
+    ///
+    /// The default is "0", which means no limit.
+    #[builder(default = "std::time::Duration::ZERO")]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    pub(crate) idle_timeout: std::time::Duration,
     //
     // TODO: Someday we may want to allow udp, resolve, etc.  If we do, it will
     // be via another option, rather than adding another subtype to ProxySource.
@@ -95,6 +103,11 @@ impl ProxyConfig {
             .find(|rule| rule.source.matches_port(port))
             .map(|rule| &rule.target)
     }
+
+    /// Return the idle timeout to use for forwarded connections, if any.
+    pub(crate) fn idle_timeout(&self) -> Option<std::time::Duration> {
+        Some(self.idle_timeout).filter(|t| !t.is_zero())
+    }
 }
 
 /// A single rule in a `ProxyConfig`.
@@ -332,8 +345,8 @@ impl std::fmt::Display for TargetAddr {
 
 /// The method by which we encapsulate a forwarded request.
 ///
-/// (Right now, only `Simple` is supported, but we may later support
-/// "HTTP CONNECT", "HAProxy", or others.)
+/// (Right now, we support `Simple` and `HaProxy`, but we may later support
+/// "HTTP CONNECT" or others.)
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 #[non_exhaustive]
 pub enum Encapsulation {
@@ -344,6 +357,13 @@ pub enum Encapsulation {
     /// only the local port will distinguish one request from another.
     #[default]
     Simple,
+    /// As `Simple`, but begin by sending the target a HAProxy PROXY protocol
+    /// (version 2) header, whose source address identifies the rendezvous
+    /// circuit that the request arrived on.
+    ///
+    /// This lets the target tell clients apart (for example, to rate-limit
+    /// them), like C Tor's `HiddenServiceExportCircuitID haproxy`.
+    HaProxy,
 }
 
 impl FromStr for ProxyAction {
@@ -358,6 +378,8 @@ impl FromStr for ProxyAction {
             Ok(Self::IgnoreStream)
         } else if let Some(addr) = s.strip_prefix("simple:") {
             Ok(Self::Forward(Encapsulation::Simple, addr.parse()?))
+        } else if let Some(addr) = s.strip_prefix("haproxy:") {
+            Ok(Self::Forward(Encapsulation::HaProxy, addr.parse()?))
         } else {
             Ok(Self::Forward(Encapsulation::Simple, s.parse()?))
         }
@@ -369,6 +391,7 @@ impl std::fmt::Display for ProxyAction {
         match self {
             ProxyAction::DestroyCircuit => write!(f, "destroy"),
             ProxyAction::Forward(Encapsulation::Simple, addr) => write!(f, "simple:{}", addr),
+            ProxyAction::Forward(Encapsulation::HaProxy, addr) => write!(f, "haproxy:{}", addr),
             ProxyAction::RejectStream => write!(f, "reject"),
             ProxyAction::IgnoreStream => write!(f, "ignore"),
         }
@@ -466,6 +489,10 @@ mod test {
         assert!(
             matches!(T::from_str("inet:[::1]:999"), Ok(T::Forward(Simple, A::Inet(a))) if a == sa)
         );
+        assert!(matches!(
+            T::from_str("haproxy:inet:[::1]:999"),
+            Ok(T::Forward(Encapsulation::HaProxy, A::Inet(a))) if a == sa
+        ));
         /* TODO (#1246)
         let pb = PathBuf::from("/var/run/hs/socket");
         assert!(
@@ -491,6 +518,14 @@ mod test {
             T::Forward(Simple, A::Inet("[::1]:999".parse().unwrap())).to_string(),
             "simple:inet:[::1]:999"
         );
+        assert_eq!(
+            T::Forward(
+                Encapsulation::HaProxy,
+                A::Inet("127.0.0.1:80".parse().unwrap())
+            )
+            .to_string(),
+            "haproxy:inet:127.0.0.1:80"
+        );
         /* TODO (#1246)
         assert_eq!(
             T::Forward(Simple, A::Unix("/var/run/hs/socket".into())).to_string(),
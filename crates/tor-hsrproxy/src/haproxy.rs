@@ -0,0 +1,98 @@
+//! Encode HAProxy PROXY protocol headers for forwarded connections.
+//!
+//! See <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>.
+//!
+//! Like C Tor's `HiddenServiceExportCircuitID haproxy`, we don't have a real
+//! client address to give the backend.  Instead, we encode the identifier of
+//! the rendezvous circuit in the source address, so that the backend can tell
+//! clients apart.
+
+use std::net::Ipv6Addr;
+
+/// The signature at the start of every version 2 PROXY header.
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Protocol version 2, with the `PROXY` command.
+const VERSION_AND_COMMAND: u8 = 0x21;
+
+/// Address family `AF_INET6`, with transport protocol `STREAM`.
+const FAMILY_AND_PROTOCOL: u8 = 0x21;
+
+/// Return the fake source address that we use for `circuit_id`, the value of
+/// a `tor_hsservice::RendCircuitId`.
+///
+/// This is `fc00:dead:beef:4dad::/96`, with the circuit ID in the low 32 bits:
+/// the same encoding that C Tor uses.
+pub(crate) fn source_addr(circuit_id: u32) -> Ipv6Addr {
+    let id = circuit_id;
+    Ipv6Addr::new(
+        0xfc00,
+        0xdead,
+        0xbeef,
+        0x4dad,
+        0,
+        0,
+        (id >> 16) as u16,
+        (id & 0xffff) as u16,
+    )
+}
+
+/// Return a version 2 PROXY header describing a connection on the
+/// rendezvous circuit `circuit_id`, to the onion service port `port`.
+pub(crate) fn encode_v2_header(circuit_id: u32, port: u16) -> Vec<u8> {
+    let src = source_addr(circuit_id);
+    let dst = Ipv6Addr::LOCALHOST;
+    // The source port carries the low bits of the circuit ID again, for
+    // backends that only log ports.
+    let src_port = (circuit_id & 0xffff) as u16;
+
+    let mut header = Vec::with_capacity(16 + 36);
+    header.extend_from_slice(&SIGNATURE);
+    header.push(VERSION_AND_COMMAND);
+    header.push(FAMILY_AND_PROTOCOL);
+    header.extend_from_slice(&36_u16.to_be_bytes());
+    header.extend_from_slice(&src.octets());
+    header.extend_from_slice(&dst.octets());
+    header.extend_from_slice(&src_port.to_be_bytes());
+    header.extend_from_slice(&port.to_be_bytes());
+    header
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    #[test]
+    fn addr() {
+        assert_eq!(
+            source_addr(0x0102_0304),
+            "fc00:dead:beef:4dad::102:304".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn header() {
+        let h = encode_v2_header(0x0102_0304, 443);
+        let mut expected = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+        expected.extend([0x21, 0x21, 0x00, 0x24]);
+        expected.extend([0xfc, 0x00, 0xde, 0xad, 0xbe, 0xef, 0x4d, 0xad]);
+        expected.extend([0, 0, 0, 0, 0x01, 0x02, 0x03, 0x04]);
+        expected.extend([0; 15]);
+        expected.push(1);
+        expected.extend([0x03, 0x04, 0x01, 0xbb]);
+        assert_eq!(h, expected);
+    }
+}
@@ -42,6 +42,7 @@
 //! <!-- @@ end lint list maintained by maint/add_warning @@ -->
 
 pub mod config;
+mod haproxy;
 mod proxy;
 
 pub use config::ProxyConfig;
@@ -1,14 +1,15 @@
 //! A simple reverse-proxy implementation for onion services.
 
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use futures::{
-    select_biased, task::SpawnExt as _, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Future,
-    FutureExt as _, Stream, StreamExt as _,
+    select_biased, task::SpawnExt as _, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt as _,
+    Future, FutureExt as _, Stream, StreamExt as _,
 };
 use safelog::sensitive as sv;
-use std::io::{Error as IoError, Result as IoResult};
-use tor_async_utils::oneshot;
+use std::io::Error as IoError;
+use tor_async_utils::{oneshot, relay, RelayConfig, RelayEnd};
 use tor_cell::relaycell::msg as relaymsg;
 use tor_error::{debug_report, ErrorKind, HasKind};
 use tor_hsservice::{HsNickname, RendRequest, StreamRequest};
@@ -131,6 +132,12 @@ impl OnionServiceReverseProxy {
             };
 
             let action = self.choose_action(stream_request.request());
+            let idle_timeout = self
+                .state
+                .lock()
+                .expect("poisoned lock")
+                .config
+                .idle_timeout();
             let a_clone = action.clone();
             let rt_clone = runtime.clone();
             let nn_clone = Arc::clone(&nickname);
@@ -138,8 +145,14 @@ impl OnionServiceReverseProxy {
 
             runtime
                 .spawn(async move {
-                    let outcome =
-                        run_action(rt_clone, nn_clone.as_ref(), action, stream_request).await;
+                    let outcome = run_action(
+                        rt_clone,
+                        nn_clone.as_ref(),
+                        action,
+                        idle_timeout,
+                        stream_request,
+                    )
+                    .await;
 
                     log_ratelim!(
                         "Performing action on {}", nn_clone;
@@ -181,10 +194,13 @@ impl OnionServiceReverseProxy {
 }
 
 /// Take the configured action from `action` on the incoming request `request`.
+///
+/// Forwarded connections are closed once they have been idle for `idle_timeout`.
 async fn run_action<R: Runtime>(
     runtime: R,
     nickname: &HsNickname,
     action: ProxyAction,
+    idle_timeout: Option<Duration>,
     request: StreamRequest,
 ) -> Result<(), RequestFailed> {
     match action {
@@ -196,7 +212,36 @@ async fn run_action<R: Runtime>(
         ProxyAction::Forward(encap, target) => match (encap, target) {
             (Encapsulation::Simple, ref addr @ TargetAddr::Inet(a)) => {
                 let rt_clone = runtime.clone();
-                forward_connection(rt_clone, request, runtime.connect(&a), nickname, addr).await?;
+                forward_connection(
+                    rt_clone,
+                    request,
+                    runtime.connect(&a),
+                    nickname,
+                    addr,
+                    None,
+                    idle_timeout,
+                )
+                .await?;
+            }
+            (Encapsulation::HaProxy, ref addr @ TargetAddr::Inet(a)) => {
+                let port = match request.request() {
+                    IncomingStreamRequest::Begin(begin) => begin.port(),
+                    // choose_action only forwards BEGIN requests.
+                    _ => 0,
+                };
+                let header =
+                    crate::haproxy::encode_v2_header(request.rend_circuit_id().as_u32(), port);
+                let rt_clone = runtime.clone();
+                forward_connection(
+                    rt_clone,
+                    request,
+                    runtime.connect(&a),
+                    nickname,
+                    addr,
+                    Some(header),
+                    idle_timeout,
+                )
+                .await?;
             } /* TODO (#1246)
                 (Encapsulation::Simple, TargetAddr::Unix(_)) => {
                     // TODO: We need to implement unix connections.
@@ -254,6 +299,11 @@ impl HasKind for RequestFailed {
 /// and transmit data between the two stream indefinitely.  On failure, close
 /// `request`.
 ///
+/// If `header` is provided, send it to the local target before anything else.
+///
+/// If `idle_timeout` is provided, close the connection once no data has been
+/// transmitted in either direction for that long.
+///
 /// Only return an error if we were unable to behave as intended due to a
 /// problem we did not already report.
 async fn forward_connection<R, FUT, TS>(
@@ -262,13 +312,23 @@ async fn forward_connection<R, FUT, TS>(
     target_stream_future: FUT,
     nickname: &HsNickname,
     addr: &TargetAddr,
+    header: Option<Vec<u8>>,
+    idle_timeout: Option<Duration>,
 ) -> Result<(), RequestFailed>
 where
     R: Runtime,
     FUT: Future<Output = Result<TS, IoError>>,
-    TS: AsyncRead + AsyncWrite + Send + 'static,
+    TS: AsyncRead + AsyncWrite + Send + Unpin + 'static,
 {
-    let local_stream = target_stream_future.await.map_err(Arc::new);
+    let local_stream = async {
+        let mut stream = target_stream_future.await?;
+        if let Some(header) = header {
+            stream.write_all(&header).await?;
+        }
+        Ok::<_, IoError>(stream)
+    }
+    .await
+    .map_err(Arc::new);
 
     // TODO: change this to "log_ratelim!(nickname=%nickname, ..." when log_ratelim can do that
     // (we should search for HSS log messages and make them all be in the same form)
@@ -305,75 +365,86 @@ where
     let (svc_r, svc_w) = onion_service_stream.split();
     let (local_r, local_w) = local_stream.split();
 
+    let relay_runtime = runtime.clone();
     runtime
-        .spawn(copy_interactive(local_r, svc_w).map(|_| ()))
-        .map_err(|e| RequestFailed::Spawn(Arc::new(e)))?;
-    runtime
-        .spawn(copy_interactive(svc_r, local_w).map(|_| ()))
+        .spawn(async move {
+            let _ = relay_connection(
+                &relay_runtime,
+                idle_timeout,
+                (local_r, local_w),
+                (svc_r, svc_w),
+            )
+            .await;
+        })
         .map_err(|e| RequestFailed::Spawn(Arc::new(e)))?;
 
     Ok(())
 }
 
-/// Copy all the data from `reader` into `writer` until we encounter an EOF or
-/// an error.
-///
-/// Unlike as futures::io::copy(), this function is meant for use with
-/// interactive readers and writers, where the reader might pause for
-/// a while, but where we want to send data on the writer as soon as
-/// it is available.
-///
-/// This function assumes that the writer might need to be flushed for
-/// any buffered data to be sent.  It tries to minimize the number of
-/// flushes, however, by only flushing the writer when the reader has no data.
+/// Transmit data between a `local` target and an onion service stream `svc`,
+/// each given as its read and write halves, until the connection is over.
 ///
-/// NOTE: This is duplicate code from `arti::socks`.  But instead of
-/// deduplicating it, we should change the behavior in `DataStream` that makes
-/// it necessary. See arti#786 for a fuller discussion.
-async fn copy_interactive<R, W>(mut reader: R, mut writer: W) -> IoResult<()>
+/// If `idle_timeout` is provided, give up once no data has been transmitted
+/// in either direction for that long.
+async fn relay_connection<R, LR, LW, SR, SW>(
+    runtime: &R,
+    idle_timeout: Option<Duration>,
+    local: (LR, LW),
+    svc: (SR, SW),
+) -> Result<RelayEnd, IoError>
 where
-    R: AsyncRead + Unpin,
-    W: AsyncWrite + Unpin,
+    R: Runtime,
+    LR: AsyncRead + Unpin,
+    LW: AsyncWrite + Unpin,
+    SR: AsyncRead + Unpin,
+    SW: AsyncWrite + Unpin,
 {
-    use futures::{poll, task::Poll};
-
-    let mut buf = [0_u8; 1024];
-
-    // At this point we could just loop, calling read().await,
-    // write_all().await, and flush().await.  But we want to be more
-    // clever than that: we only want to flush when the reader is
-    // stalled.  That way we can pack our data into as few cells as
-    // possible, but flush it immediately whenever there's no more
-    // data coming.
-    let loop_result: IoResult<()> = loop {
-        let mut read_future = reader.read(&mut buf[..]);
-        match poll!(&mut read_future) {
-            Poll::Ready(Err(e)) => break Err(e),
-            Poll::Ready(Ok(0)) => break Ok(()), // EOF
-            Poll::Ready(Ok(n)) => {
-                writer.write_all(&buf[..n]).await?;
-                continue;
-            }
-            Poll::Pending => writer.flush().await?,
-        }
-
-        // The read future is pending, so we should wait on it.
-        match read_future.await {
-            Err(e) => break Err(e),
-            Ok(0) => break Ok(()),
-            Ok(n) => writer.write_all(&buf[..n]).await?,
-        }
-    };
-
-    // Make sure that we flush any lingering data if we can.
-    //
-    // If there is a difference between closing and dropping, then we
-    // only want to do a "proper" close if the reader closed cleanly.
-    let flush_result = if loop_result.is_ok() {
-        writer.close().await
-    } else {
-        writer.flush().await
-    };
+    let config = RelayConfig::new().idle_timeout(idle_timeout);
+    relay(runtime, &config, local, svc).await
+}
 
-    loop_result.or(flush_result)
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+    use tor_rtmock::io::stream_pair;
+
+    #[test]
+    fn idle_backend_is_closed() {
+        tor_rtcompat::test_with_one_runtime!(|rt| async move {
+            let cfg: ProxyConfig = toml::from_str::<crate::config::ProxyConfigBuilder>(
+                r#"
+proxy_ports = [ ["80", "127.0.0.1:10080"] ]
+idle_timeout = "50 ms"
+"#,
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+            assert_eq!(cfg.idle_timeout(), Some(Duration::from_millis(50)));
+
+            // Neither the backend nor the client ever sends anything, and
+            // neither of them closes its end.
+            let (local, _backend) = stream_pair();
+            let (svc, _client) = stream_pair();
+
+            let end = relay_connection(&rt, cfg.idle_timeout(), local.split(), svc.split())
+                .await
+                .unwrap();
+            assert_eq!(end, RelayEnd::IdleTimeout);
+        });
+    }
 }
@@ -45,6 +45,15 @@ pub struct OnionServiceConfig {
     /// this service?
     #[builder(default = "65535")]
     max_concurrent_streams_per_circuit: u32,
+
+    /// What should we do when a client asks for more than
+    /// `max_concurrent_streams_per_circuit` streams on one circuit?
+    ///
+    /// If this is false, we refuse the new stream with an `END` message,
+    /// and keep the circuit open.
+    /// If this is true, we close the whole circuit, along with its other streams.
+    #[builder(default)]
+    max_concurrent_streams_close_circuit: bool,
     // TODO POW: The POW items are disabled for now, since they aren't implemented.
     // /// If true, we will require proof-of-work when we're under heavy load.
     // // enable_pow: bool,
@@ -174,6 +183,7 @@ impl OnionServiceConfig {
 
             // We extract this on every introduction request.
             max_concurrent_streams_per_circuit: simply_update,
+            max_concurrent_streams_close_circuit: simply_update,
         }
 
         Ok(other)
@@ -195,6 +205,7 @@ impl OnionServiceConfig {
     pub(crate) fn filter_settings(&self) -> crate::rend_handshake::RequestFilter {
         crate::rend_handshake::RequestFilter {
             max_concurrent_streams: self.max_concurrent_streams_per_circuit as usize,
+            close_circuit_on_excess_streams: self.max_concurrent_streams_close_circuit,
         }
     }
 }
@@ -358,3 +369,64 @@ impl std::str::FromStr for AuthorizedClientConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    fn builder() -> OnionServiceConfigBuilder {
+        let mut b = OnionServiceConfigBuilder::default();
+        b.nickname("allium".to_string().try_into().unwrap());
+        b
+    }
+
+    #[test]
+    fn intro_dos_params() {
+        // By default, we leave the limits up to the introduction point.
+        let cfg = builder().build().unwrap();
+        assert_eq!(cfg.dos_extension().unwrap(), None);
+
+        let cfg = builder()
+            .rate_limit_at_intro(Some(TokenBucketConfig::new(200, 400)))
+            .build()
+            .unwrap();
+        assert_eq!(
+            cfg.dos_extension().unwrap(),
+            Some(est_intro::DosParams::new(Some(200), Some(400)).unwrap())
+        );
+
+        // Values that don't fit in the DOS_PARAMS extension are rejected up front.
+        let err = builder()
+            .rate_limit_at_intro(Some(TokenBucketConfig::new(u32::MAX, 400)))
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().contains("rate_limit_at_intro"), "{err}");
+    }
+
+    #[test]
+    fn intro_dos_params_serde() {
+        let b: OnionServiceConfigBuilder = serde_json::from_value(serde_json::json!({
+            "nickname": "allium",
+            "rate_limit_at_intro": { "rate": 20, "burst": 30 },
+        }))
+        .unwrap();
+        let cfg = b.build().unwrap();
+        assert_eq!(
+            cfg.dos_extension().unwrap(),
+            Some(est_intro::DosParams::new(Some(20), Some(30)).unwrap())
+        );
+    }
+}
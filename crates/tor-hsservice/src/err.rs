@@ -108,6 +108,49 @@ impl From<Bug> for StartupError {
     }
 }
 
+/// An error which occurs exporting or restoring an onion service's state
+///
+/// Returned by [`export_service_state`](crate::export_service_state)
+/// and [`restore_service_state`](crate::restore_service_state).
+#[derive(Clone, Debug, Error)]
+#[non_exhaustive]
+pub enum ServiceArchiveError {
+    /// Unable to access the service's state, or the archive was malformed
+    #[error("Unable to export or restore the service's state")]
+    State(#[from] tor_persist::Error),
+
+    /// A keystore operation failed.
+    #[error("Keystore error while attempting to {action}")]
+    Keystore {
+        /// The action we were trying to perform.
+        action: &'static str,
+        /// The underlying error
+        #[source]
+        cause: tor_keymgr::Error,
+    },
+
+    /// The keys in the archive were malformed, or didn't belong to this service
+    #[error("Invalid keys in archive: {0}")]
+    BadKeys(String),
+
+    /// Programming error
+    #[error("Programming error")]
+    Bug(#[from] Bug),
+}
+
+impl HasKind for ServiceArchiveError {
+    fn kind(&self) -> ErrorKind {
+        use ErrorKind as EK;
+        use ServiceArchiveError as E;
+        match self {
+            E::State(e) => e.kind(),
+            E::Keystore { cause, .. } => cause.kind(),
+            E::BadKeys(_) => EK::PersistentStateCorrupted,
+            E::Bug(e) => e.kind(),
+        }
+    }
+}
+
 /// An error which occurs trying to communicate with a particular client.
 ///
 /// This is returned by `RendRequest::accept` and `StreamRequest::accept`.
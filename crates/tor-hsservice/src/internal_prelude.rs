@@ -84,6 +84,7 @@ pub(crate) use {
     tor_error::{bad_api_usage, internal, into_bad_api_usage, into_internal},
     tor_error::{debug_report, error_report, info_report, warn_report},
     tor_error::{Bug, ErrorKind, ErrorReport as _, HasKind},
+    tor_hscrypto::cache::SubcredentialCache,
     tor_hscrypto::ope::AesOpeKey,
     tor_hscrypto::pk::{
         HsBlindId, HsBlindIdKey, HsBlindIdKeypair, HsClientDescEncKey, HsDescSigningKeypair, HsId,
@@ -101,7 +102,7 @@ pub(crate) use {
     },
     tor_llcrypto::pk::{curve25519, ed25519},
     tor_log_ratelim::log_ratelim,
-    tor_netdir::{HsDirParams, NetDir, NetDirProvider, Relay, Timeliness},
+    tor_netdir::{DirUsage, HsDirParams, NetDir, NetDirProvider, Relay},
     tor_netdoc::doc::hsdesc::{create_desc_sign_key_cert, HsDescBuilder},
     tor_netdoc::NetdocBuilder,
     tor_persist::slug::Slug,
@@ -137,6 +138,7 @@ pub(crate) use {
     crate::status::State,
     crate::status::{IptMgrStatusSender, State as IptMgrState},
     crate::status::{OnionServiceStatus, OnionServiceStatusStream, StatusSender},
+    crate::storage::ServiceStateHandle,
     crate::time_store,
     crate::timeout_track::{TrackingInstantOffsetNow, TrackingNow, Update as _},
     crate::OnionServiceConfig,
@@ -149,7 +151,10 @@ pub(crate) use {
         ClientError,
     },
     crate::{req::RendRequestContext, HsNickname, LinkSpecs, NtorPublicKey},
-    crate::{BlindIdKeypairSpecifier, DescSigningKeypairSpecifier, HsIdKeypairSpecifier},
+    crate::{
+        BlindIdKeypairSpecifier, DescSigningKeyInterval, DescSigningKeypairSpecifier,
+        HsIdKeypairSpecifier,
+    },
     crate::{DescUploadError, IptError},
     crate::{FatalError, RendRequest},
     ipt_establish::{IptEstablisher, IptParameters, IptStatus, IptStatusStatus, IptWantsToRetire},
@@ -255,6 +255,9 @@ pub(crate) struct IptParameters {
     /// form a shared key set of keys with the client, and decrypt information
     /// about the client's chosen rendezvous point and extensions.
     pub(crate) k_ntor: Arc<HsSvcNtorKeypair>,
+    /// The service's cache of subcredentials, used to answer INTRODUCE2 requests.
+    #[educe(Debug(ignore))]
+    pub(crate) subcredentials: Arc<SubcredentialCache>,
 }
 
 impl IptEstablisher {
@@ -291,6 +294,7 @@ impl IptEstablisher {
             k_ntor,
             accepting_requests,
             replay_log,
+            subcredentials,
         } = params;
         let config = Arc::clone(&config_rx.borrow());
         let nickname = config.nickname().clone();
@@ -307,6 +311,7 @@ impl IptEstablisher {
         let request_context = Arc::new(RendRequestContext {
             nickname: nickname.clone(),
             keymgr: Arc::clone(keymgr),
+            subcredentials,
             kp_hss_ntor: Arc::clone(&k_ntor),
             kp_hs_ipt_sid: k_sid.as_ref().as_ref().verifying_key().into(),
             filter: config.filter_settings(),
@@ -703,11 +708,8 @@ impl<R: Runtime> Reactor<R> {
         &self,
     ) -> Result<(IntroPtSession, GoodIptDetails), IptEstablisherError> {
         let (protovers, circuit, ipt_details) = {
-            let netdir = wait_for_netdir(
-                self.netdir_provider.as_ref(),
-                tor_netdir::Timeliness::Timely,
-            )
-            .await?;
+            let netdir =
+                wait_for_netdir(self.netdir_provider.as_ref(), DirUsage::OnionServices).await?;
             let circ_target = netdir
                 .by_ids(&self.target)
                 .ok_or(IptError::IntroPointNotListed)?;
@@ -22,7 +22,7 @@ pub use crate::ipt_establish::IptError;
 /// much good to have a short expiration time. This expiration time only affects
 /// caches, and we can supersede an old descriptor just by publishing it. Thus,
 /// we pick a uniform publication time as done by the C tor implementation.)
-const IPT_PUBLISH_UNCERTAIN: Duration = Duration::from_secs(3 * 60 * 60); // 3 hours
+pub(crate) const IPT_PUBLISH_UNCERTAIN: Duration = Duration::from_secs(3 * 60 * 60); // 3 hours
 /// Expiry time to put on a final descriptor (IPT publication set Certain
 const IPT_PUBLISH_CERTAIN: Duration = IPT_PUBLISH_UNCERTAIN;
 
@@ -74,13 +74,18 @@ pub(crate) struct Immutable<R> {
 
     /// Replay log directory
     ///
-    /// Files are named after the (bare) IptLocalId
+    /// Files are named after the (bare) IptLocalId.
+    /// `None` for an ephemeral service, whose replay logs are kept in memory.
     #[educe(Debug(ignore))]
-    replay_log_dir: tor_persist::state_dir::InstanceRawSubdir,
+    replay_log_dir: Option<tor_persist::state_dir::InstanceRawSubdir>,
 
     /// A sender for updating the status of the onion service.
     #[educe(Debug(ignore))]
     status_tx: IptMgrStatusSender,
+
+    /// The service's cache of subcredentials.
+    #[educe(Debug(ignore))]
+    subcredentials: Arc<SubcredentialCache>,
 }
 
 /// State of an IPT Manager
@@ -300,6 +305,10 @@ pub(crate) enum CreateIptError {
     #[error("problems with keystores")]
     Keystore(#[from] tor_keymgr::Error),
 
+    /// Error creating a new intro request replay log
+    #[error("unable to create the intro req replay log")]
+    CreateReplayLog(#[source] tor_persist::Error),
+
     /// Error opening the intro request replay log
     #[error("unable to open the intro req replay log: {file:?}")]
     OpenReplayLog {
@@ -464,8 +473,10 @@ impl Ipt {
             started: imm.runtime.now(),
         };
 
-        // TODO #1186 Support ephemeral services (without persistent replay log)
-        let replay_log = ReplayLog::new_logged(&imm.replay_log_dir, &lid)?;
+        let replay_log = match &imm.replay_log_dir {
+            Some(dir) => ReplayLog::new_logged(dir, &lid)?,
+            None => ReplayLog::new_ephemeral(),
+        };
 
         let params = IptParameters {
             replay_log,
@@ -477,6 +488,7 @@ impl Ipt {
             k_sid: k_sid.clone(),
             k_ntor: Arc::clone(&k_hss_ntor),
             accepting_requests: ipt_establish::RequestDisposition::NotAdvertised,
+            subcredentials: Arc::clone(&imm.subcredentials),
         };
         let (establisher, mut watch_rx) = mockable.make_new_ipt(imm, params)?;
 
@@ -613,10 +625,11 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
         config: watch::Receiver<Arc<OnionServiceConfig>>,
         output_rend_reqs: mpsc::Sender<RendRequest>,
         shutdown: broadcast::Receiver<Void>,
-        state_handle: &tor_persist::state_dir::InstanceStateHandle,
+        state_handle: &ServiceStateHandle,
         mockable: M,
         keymgr: Arc<KeyMgr>,
         status_tx: IptMgrStatusSender,
+        subcredentials: Arc<SubcredentialCache>,
     ) -> Result<Self, StartupError> {
         let irelays = vec![]; // See TODO near persist::load call, in launch_background_tasks
 
@@ -641,6 +654,7 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
             keymgr,
             replay_log_dir,
             status_tx,
+            subcredentials,
         };
         let current_config = config.borrow().clone();
 
@@ -868,7 +882,9 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
                     Ok(()) => return CONTINUE,
                     Err(CreateIptError::Fatal(fatal)) => return Err(fatal),
                     Err(
-                        e @ (CreateIptError::Keystore(_) | CreateIptError::OpenReplayLog { .. }),
+                        e @ (CreateIptError::Keystore(_)
+                        | CreateIptError::CreateReplayLog(_)
+                        | CreateIptError::OpenReplayLog { .. }),
                     ) => {
                         error_report!(e, "HS {}: failed to prepare new IPT", &self.imm.nick);
                         // Let's not try any more of this.
@@ -1376,9 +1392,14 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
             }
         };
 
+        let Some(replay_log_dir) = &self.imm.replay_log_dir else {
+            // Ephemeral service: the replay logs are dropped along with their IPTs.
+            return Ok(());
+        };
+
         // fs-mistrust doesn't offer CheckedDir::read_this_directory.
         // But, we probably don't mind that we're not doing many checks here.
-        let replay_logs = self.imm.replay_log_dir.as_path();
+        let replay_logs = replay_log_dir.as_path();
         let replay_logs_dir =
             fs::read_dir(replay_logs).map_err(handle_rl_err("open dir", replay_logs))?;
 
@@ -1606,7 +1627,7 @@ impl<R: Runtime, M: Mockable<R>> State<R, M> {
         imm: &Immutable<R>,
         now: Instant,
     ) -> Result<(), ChooseIptError> {
-        let netdir = imm.dirprovider.timely_netdir()?;
+        let netdir = imm.dirprovider.netdir_for(DirUsage::OnionServices)?;
 
         let mut rng = self.mockable.thread_rng();
 
@@ -1951,6 +1972,7 @@ mod test {
                 mocks,
                 keymgr,
                 status_tx,
+                Default::default(),
             )
             .unwrap();
 
@@ -7,7 +7,7 @@ use super::*;
 use crate::time_store;
 
 /// Handle for a suitable persistent storage manager
-pub(crate) type IptStorageHandle = tor_persist::state_dir::StorageHandle<StateRecord>;
+pub(crate) type IptStorageHandle = crate::storage::StorageHandle<StateRecord>;
 
 //---------- On disk data structures, done with serde ----------
 
@@ -169,6 +169,7 @@ impl IptRecord {
                 action: "load IPT key(s)",
                 cause,
             },
+            CreateIptError::CreateReplayLog(e) => StartupError::StateDirectoryInaccessible(e),
             CreateIptError::OpenReplayLog { file, error } => {
                 StartupError::StateDirectoryInaccessibleIo {
                     source: error,
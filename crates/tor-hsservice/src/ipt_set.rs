@@ -3,7 +3,7 @@
 use crate::internal_prelude::*;
 
 /// Handle for a suitable persistent storage manager
-pub(crate) type IptSetStorageHandle = tor_persist::state_dir::StorageHandle<StateRecord>;
+pub(crate) type IptSetStorageHandle = crate::storage::StorageHandle<StateRecord>;
 
 /// Information shared between the IPT manager and the IPT publisher
 ///
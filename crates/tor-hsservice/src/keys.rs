@@ -8,6 +8,11 @@
 //! make arrangements to delete old ones.
 //! For TP-based keys, that involves deriving [`HsTimePeriodKeySpecifier`]
 //! and adding a call to `remove_if_expired!` in [`expire_publisher_keys`].
+//!
+//! Descriptor signing keys are additionally rotated
+//! every [`DESC_SIGNING_KEY_ROTATION`] (see [`DescSigningKeyInterval`]),
+//! and are removed by [`expire_publisher_keys`]
+//! once no descriptor signed with them can still be in use.
 
 use crate::internal_prelude::*;
 
@@ -100,6 +105,166 @@ pub struct DescSigningKeypairSpecifier {
     #[deftly(denotator)]
     /// The time period associated with this key.
     pub(crate) period: TimePeriod,
+    #[deftly(denotator)]
+    /// The rotation interval during which this key is used to sign descriptors.
+    pub(crate) interval: DescSigningKeyInterval,
+}
+
+impl DescSigningKeypairSpecifier {
+    /// Return the specifier of the descriptor signing key for the interval
+    /// that contains the start of `period`.
+    ///
+    /// In offline mode, this key is used to compute the OPE key for `period`,
+    /// which must not change during the period;
+    /// so [`expire_publisher_keys`] keeps it for as long as `period` is relevant.
+    pub(crate) fn for_period_start(nickname: HsNickname, period: TimePeriod) -> Result<Self, Bug> {
+        let start = period
+            .range()
+            .map_err(into_internal!("invalid time period"))?
+            .start;
+        Ok(Self::new(
+            nickname,
+            period,
+            DescSigningKeyInterval::containing(start),
+        ))
+    }
+
+    /// Whether this is the key for the interval that contains the start of its time period.
+    ///
+    /// See [`DescSigningKeypairSpecifier::for_period_start`].
+    fn is_for_period_start(&self) -> bool {
+        self.period
+            .range()
+            .is_ok_and(|range| DescSigningKeyInterval::containing(range.start) == self.interval)
+    }
+}
+
+#[derive(Deftly, PartialEq, Debug, Constructor)]
+#[derive_deftly(KeySpecifier, HsTimePeriodKeySpecifier)]
+#[deftly(prefix = "hss")]
+#[deftly(role = "KS_hs_desc_sign")]
+#[deftly(summary = "Descriptor signing key (old format)")]
+/// A descriptor signing key generated before we started rotating them.
+///
+/// We no longer use these keys; [`expire_publisher_keys`] removes them.
+pub(crate) struct LegacyDescSigningKeypairSpecifier {
+    /// The nickname of the  hidden service.
+    pub(crate) nickname: HsNickname,
+    #[deftly(denotator)]
+    /// The time period associated with this key.
+    pub(crate) period: TimePeriod,
+}
+
+/// How often we generate a new descriptor signing key.
+///
+/// This is the lifetime of our descriptors
+/// (the same as `IPT_PUBLISH_CERTAIN`),
+/// so that each key signs descriptors for no longer than a descriptor can live.
+pub(crate) const DESC_SIGNING_KEY_ROTATION: Duration = crate::ipt_mgr::IPT_PUBLISH_UNCERTAIN;
+
+/// How long we keep a descriptor signing key after we have stopped using it.
+///
+/// A descriptor can remain at the HSDirs for its lifetime after it was uploaded,
+/// and its upload may be retried for up to
+/// [`OVERALL_UPLOAD_TIMEOUT`](crate::publish::OVERALL_UPLOAD_TIMEOUT) after we built it.
+/// We must keep the key (and its certificate must remain valid) until then.
+pub(crate) const DESC_SIGNING_KEY_OVERLAP: Duration = Duration::from_secs(
+    crate::ipt_mgr::IPT_PUBLISH_UNCERTAIN.as_secs()
+        + crate::publish::OVERALL_UPLOAD_TIMEOUT.as_secs(),
+);
+
+/// The rotation interval of a descriptor signing key.
+///
+/// We sign the descriptors we build during an interval with a fresh
+/// descriptor signing key for that interval (and time period).
+/// Intervals are as long as the lifetime of our descriptors (3 hours),
+/// and are numbered from the Unix epoch.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct DescSigningKeyInterval(u64);
+
+impl DescSigningKeyInterval {
+    /// Return the interval containing `when`.
+    pub fn containing(when: SystemTime) -> Self {
+        let secs = when
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Self(secs / DESC_SIGNING_KEY_ROTATION.as_secs())
+    }
+
+    /// Return the interval with index `n`, if all of its times are representable.
+    fn checked_from_index(n: u64) -> Option<Self> {
+        let interval = Self(n);
+        interval.checked_start_plus(DESC_SIGNING_KEY_ROTATION + DESC_SIGNING_KEY_OVERLAP)?;
+        Some(interval)
+    }
+
+    /// Return the time `offset` after the start of this interval,
+    /// or `None` if it isn't representable.
+    fn checked_start_plus(&self, offset: Duration) -> Option<SystemTime> {
+        let start = self.0.checked_mul(DESC_SIGNING_KEY_ROTATION.as_secs())?;
+        SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(start).checked_add(offset)?)
+    }
+
+    /// Return the time `offset` after the start of this interval.
+    ///
+    /// Panics if it isn't representable,
+    /// which can only happen for intervals billions of years in the future.
+    fn start_plus(&self, offset: Duration) -> SystemTime {
+        self.checked_start_plus(offset)
+            .expect("descriptor signing key interval out of range")
+    }
+
+    /// Return the time at which this interval starts.
+    pub fn start(&self) -> SystemTime {
+        self.start_plus(Duration::ZERO)
+    }
+
+    /// Return the time at which this interval ends (and the next one starts).
+    pub fn end(&self) -> SystemTime {
+        self.start_plus(DESC_SIGNING_KEY_ROTATION)
+    }
+
+    /// Return the time after which the descriptor signing key for this interval
+    /// is no longer needed.
+    ///
+    /// Descriptors signed during this interval may still be in use
+    /// for a while after it ends:
+    /// at most their lifetime, plus the time it can take to upload them.
+    pub fn retire_at(&self) -> SystemTime {
+        self.start_plus(DESC_SIGNING_KEY_ROTATION + DESC_SIGNING_KEY_OVERLAP)
+    }
+}
+
+impl KeySpecifierComponent for DescSigningKeyInterval {
+    fn to_slug(&self) -> Result<Slug, Bug> {
+        Slug::new(self.0.to_string()).map_err(into_internal!("interval formatting went wrong"))
+    }
+
+    fn from_slug(s: &Slug) -> Result<Self, tor_keymgr::InvalidKeyPathComponentValue>
+    where
+        Self: Sized,
+    {
+        let err_ctx = |e: &str| tor_keymgr::InvalidKeyPathComponentValue::Slug(e.to_string());
+        let n = s
+            .as_str()
+            .parse()
+            .map_err(|_| err_ctx("invalid interval number"))?;
+        Self::checked_from_index(n).ok_or_else(|| err_ctx("interval number out of range"))
+    }
+
+    fn fmt_pretty(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use humantime::format_rfc3339_seconds as f3339;
+        let mins = DESC_SIGNING_KEY_ROTATION.as_secs() / 60;
+        write!(
+            f,
+            "#{} {}..+{}:{:02}",
+            self.0,
+            f3339(self.start()),
+            mins / 60,
+            mins % 60
+        )
+    }
 }
 
 /// Denotates one of the keys, in the context of a particular HS and intro point
@@ -132,10 +297,14 @@ pub(crate) struct IptKeySpecifier {
 }
 
 /// Expire publisher keys for no-longer relevant TPs
+///
+/// Also removes the descriptor signing keys that we have rotated away from,
+/// once descriptors signed with them can no longer be in use at `now`.
 pub(crate) fn expire_publisher_keys(
     keymgr: &KeyMgr,
     nickname: &HsNickname,
     relevant_periods: &[HsDirParams],
+    now: SystemTime,
 ) -> tor_keymgr::Result<()> {
     // Only remove the keys of the hidden service
     // that concerns us
@@ -145,16 +314,19 @@ pub(crate) fn expire_publisher_keys(
     for entry in possibly_relevant_keys {
         let key_path = entry.key_path();
         // Remove the key identified by `spec` if it's no longer relevant
-        let remove_if_expired = |spec: &dyn HsTimePeriodKeySpecifier| {
+        //
+        // `retired` is true if the key is no longer needed regardless of its TP.
+        let remove_if_expired = |spec: &dyn HsTimePeriodKeySpecifier, retired: bool| {
             if spec.nickname() != nickname {
                 return Err(internal!(
                     "keymgr gave us key {spec:?} that doesn't match our pattern {arti_pat:?}"
                 )
                 .into());
             }
-            let is_expired = relevant_periods
-                .iter()
-                .all(|p| &p.time_period() != spec.period());
+            let is_expired = retired
+                || relevant_periods
+                    .iter()
+                    .all(|p| &p.time_period() != spec.period());
 
             if is_expired {
                 keymgr.remove_entry(&entry)?;
@@ -164,10 +336,16 @@ pub(crate) fn expire_publisher_keys(
         };
 
         /// Remove the specified key, if it's no longer relevant.
+        ///
+        /// `$retired`, if specified, says whether the key (bound to `$spec`)
+        /// has been retired.
         macro_rules! remove_if_expired {
-            ($K:ty) => {{
-                if let Ok(spec) = <$K>::try_from(key_path) {
-                    remove_if_expired(&spec)?;
+            ($K:ty) => {
+                remove_if_expired!($K, |_spec| false)
+            };
+            ($K:ty, |$spec:ident| $retired:expr) => {{
+                if let Ok($spec) = <$K>::try_from(key_path) {
+                    remove_if_expired(&$spec, $retired)?;
                 }
             }};
         }
@@ -176,7 +354,12 @@ pub(crate) fn expire_publisher_keys(
         // removed).
         remove_if_expired!(BlindIdPublicKeySpecifier);
         remove_if_expired!(BlindIdKeypairSpecifier);
-        remove_if_expired!(DescSigningKeypairSpecifier);
+        remove_if_expired!(DescSigningKeypairSpecifier, |spec| {
+            // The key for the start of the period is needed for the whole period
+            // (see `DescSigningKeypairSpecifier::for_period_start`).
+            spec.interval.retire_at() <= now && !spec.is_for_period_start()
+        });
+        remove_if_expired!(LegacyDescSigningKeypairSpecifier, |_spec| true);
     }
 
     Ok(())
@@ -198,6 +381,7 @@ mod test {
     #![allow(clippy::needless_pass_by_value)]
     //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
     use super::*;
+    use crate::ipt_mgr::IPT_PUBLISH_UNCERTAIN;
     use tor_keymgr::test_utils::check_key_specifier;
     use tor_keymgr::KeySpecifier;
 
@@ -232,8 +416,64 @@ mod test {
     fn desc_signing_key_specifiers() {
         let nickname = HsNickname::try_from("shallot".to_string()).unwrap();
         let period = TimePeriod::from_parts(1, 2, 3);
-        let key_spec = DescSigningKeypairSpecifier::new(nickname, period);
-        check_key_specifier(&key_spec, "hss/shallot/ks_hs_desc_sign+2_1_3");
+        let interval = DescSigningKeyInterval(4);
+        let key_spec = DescSigningKeypairSpecifier::new(nickname.clone(), period, interval);
+        check_key_specifier(&key_spec, "hss/shallot/ks_hs_desc_sign+2_1_3+4");
+
+        let legacy_key_spec = LegacyDescSigningKeypairSpecifier::new(nickname, period);
+        check_key_specifier(&legacy_key_spec, "hss/shallot/ks_hs_desc_sign+2_1_3");
+
+        // The two formats can't be mistaken for each other.
+        let path = KeyPath::Arti(legacy_key_spec.arti_path().unwrap());
+        assert!(DescSigningKeypairSpecifier::try_from(&path).is_err());
+        let path = KeyPath::Arti(key_spec.arti_path().unwrap());
+        assert!(LegacyDescSigningKeypairSpecifier::try_from(&path).is_err());
+    }
+
+    #[test]
+    fn desc_signing_key_interval() {
+        let rotation = DESC_SIGNING_KEY_ROTATION;
+        let t = SystemTime::UNIX_EPOCH + rotation * 1000 + Duration::from_secs(10);
+        let interval = DescSigningKeyInterval::containing(t);
+        assert_eq!(interval, DescSigningKeyInterval(1000));
+        assert_eq!(interval.start(), SystemTime::UNIX_EPOCH + rotation * 1000);
+        assert_eq!(interval.end(), SystemTime::UNIX_EPOCH + rotation * 1001);
+        assert_eq!(
+            DescSigningKeyInterval::containing(interval.end()),
+            DescSigningKeyInterval(1001)
+        );
+        assert!(interval.retire_at() > interval.end() + IPT_PUBLISH_UNCERTAIN);
+
+        let slug = interval.to_slug().unwrap();
+        assert_eq!(slug.as_str(), "1000");
+        assert_eq!(DescSigningKeyInterval::from_slug(&slug).unwrap(), interval);
+
+        for bad in ["x", "1x", "18446744073709551615"] {
+            let slug = Slug::new(bad.to_string()).unwrap();
+            assert!(DescSigningKeyInterval::from_slug(&slug).is_err());
+        }
+    }
+
+    #[test]
+    fn desc_signing_key_for_period_start() {
+        let nickname = HsNickname::try_from("shallot".to_string()).unwrap();
+        let day = Duration::from_secs(86400);
+        let when = SystemTime::UNIX_EPOCH + day * 20000;
+        let period = TimePeriod::new(day, when, Duration::from_secs(12 * 3600)).unwrap();
+        let start = period.range().unwrap().start;
+
+        let first =
+            DescSigningKeypairSpecifier::for_period_start(nickname.clone(), period).unwrap();
+        assert_eq!(first.interval, DescSigningKeyInterval::containing(start));
+        assert!(first.is_for_period_start());
+
+        // The keys for the other intervals of the period are rotated away as usual.
+        let later = DescSigningKeypairSpecifier::new(
+            nickname,
+            period,
+            DescSigningKeyInterval::containing(first.interval.end()),
+        );
+        assert!(!later.is_for_period_start());
     }
 
     #[test]
@@ -62,6 +62,7 @@ mod rend_handshake;
 mod replay;
 mod req;
 pub mod status;
+mod storage;
 mod timeout_track;
 
 // rustdoc doctests can't use crate-public APIs, so are broken if provided for private items.
@@ -89,15 +90,19 @@ use internal_prelude::*;
 pub use crate::netdir::NetdirProviderShutdown;
 pub use anon_level::Anonymity;
 pub use config::OnionServiceConfig;
-pub use err::{ClientError, EstablishSessionError, FatalError, IntroRequestError, StartupError};
+pub use err::{
+    ClientError, EstablishSessionError, FatalError, IntroRequestError, ServiceArchiveError,
+    StartupError,
+};
 pub use ipt_mgr::IptError;
 pub use keys::{
-    BlindIdKeypairSpecifier, BlindIdPublicKeySpecifier, DescSigningKeypairSpecifier,
-    HsIdKeypairSpecifier, HsIdPublicKeySpecifier,
+    BlindIdKeypairSpecifier, BlindIdPublicKeySpecifier, DescSigningKeyInterval,
+    DescSigningKeypairSpecifier, HsIdKeypairSpecifier, HsIdPublicKeySpecifier,
 };
 pub use nickname::{HsNickname, InvalidNickname};
 pub use publish::UploadError as DescUploadError;
-pub use req::{RendRequest, StreamRequest};
+pub use req::{RendCircuitId, RendRequest, StreamRequest};
+pub use storage::{expire_unused_service_state, export_service_state, restore_service_state};
 
 pub use helpers::handle_rend_requests;
 
@@ -126,6 +131,8 @@ pub struct RunningOnionService {
     nickname: HsNickname,
     /// The key manager, used for accessing the underlying key stores.
     keymgr: Arc<KeyMgr>,
+    /// The record of which HsDirs have our descriptor.
+    upload_record: publish::SharedUploadRecord,
 }
 
 /// Implementation details for an onion service.
@@ -205,9 +212,23 @@ impl From<oneshot::Canceled> for ShutdownStatus {
 
 /// A handle to an instance of an onion service, which may or may not be running.
 ///
-/// To construct an `OnionService`, call [`OnionService::new`].
+/// To construct an `OnionService`, use an [`OnionServiceBuilder`].
 /// It will not start handling requests until you call its
 /// [``.launch()``](OnionService::launch) method.
+///
+/// # Ephemeral services
+///
+/// An onion service is normally given a [`StateDirectory`],
+/// where it keeps its state so that it can be restarted.
+/// Instead, you can ask for an
+/// [`ephemeral`](OnionServiceBuilder::ephemeral) service,
+/// which keeps all of its non-key state in memory and never writes it to disk.
+///
+/// To keep the service's keys off the disk too, give it a [`KeyMgr`]
+/// whose default keystore is a
+/// [`tor_keymgr::ArtiEphemeralKeystore`].
+/// If that keystore already contains an identity key for the service's nickname,
+/// the service will use it; otherwise, a new identity key will be generated.
 //
 // TODO (#1228): Write more.
 // TODO (#1247): Choose a better name for this struct
@@ -220,7 +241,17 @@ pub struct OnionService {
     /// The key manager, used for accessing the underlying key stores.
     keymgr: Arc<KeyMgr>,
     /// The location on disk where the persistent data is stored.
-    state_dir: StateDirectory,
+    ///
+    /// Required, unless this is an [`ephemeral`](OnionServiceBuilder::ephemeral) service.
+    #[builder(default, setter(strip_option))]
+    state_dir: Option<StateDirectory>,
+    /// If true, keep all of this service's non-key state in memory.
+    ///
+    /// An ephemeral service must not have a `state_dir`.
+    /// Its state is lost when it shuts down,
+    /// so if it is restarted, it will need to establish new introduction points.
+    #[builder(default)]
+    ephemeral: bool,
 }
 
 impl OnionService {
@@ -255,7 +286,8 @@ impl OnionService {
         Ok(OnionService {
             config,
             keymgr,
-            state_dir: state_dir.clone(),
+            state_dir: Some(state_dir.clone()),
+            ephemeral: false,
         })
     }
 
@@ -286,13 +318,20 @@ impl OnionService {
             config,
             keymgr,
             state_dir,
+            ephemeral: _,
         } = self;
 
         let nickname = config.nickname.clone();
 
-        let state_handle = state_dir
-            .acquire_instance(&config.nickname)
-            .map_err(StartupError::StateDirectoryInaccessible)?;
+        // `build` checked that we have a state directory unless we're ephemeral.
+        let state_handle = match state_dir {
+            Some(state_dir) => ServiceStateHandle::Persistent(
+                state_dir
+                    .acquire_instance(&config.nickname)
+                    .map_err(StartupError::StateDirectoryInaccessible)?,
+            ),
+            None => ServiceStateHandle::Ephemeral,
+        };
 
         // We pass the "cooked" handle, with the storage key embedded, to ipt_set,
         // since the ipt_set code doesn't otherwise have access to the HS nickname.
@@ -307,8 +346,20 @@ impl OnionService {
         let (ipt_mgr_view, publisher_view) =
             crate::ipt_set::ipts_channel(&runtime, iptpub_storage_handle)?;
 
+        let upload_record_storage_handle = state_handle
+            .storage_handle("descpub")
+            .map_err(StartupError::StateDirectoryInaccessible)?;
+        let upload_record = Arc::new(Mutex::new(publish::UploadRecord::load(
+            upload_record_storage_handle,
+            &runtime,
+        )?));
+
         let status_tx = StatusSender::new(OnionServiceStatus::new_shutdown());
 
+        // This service's blinded keys and subcredentials, which we need both
+        // to publish our descriptors and to answer introduction requests.
+        let subcredentials = Arc::new(SubcredentialCache::new());
+
         let ipt_mgr = IptManager::new(
             runtime.clone(),
             netdir_provider.clone(),
@@ -322,6 +373,7 @@ impl OnionService {
             },
             keymgr.clone(),
             status_tx.clone().into(),
+            Arc::clone(&subcredentials),
         )?;
 
         let publisher: Publisher<R, publish::Real<R>> = Publisher::new(
@@ -333,11 +385,14 @@ impl OnionService {
             config_rx,
             status_tx.clone().into(),
             Arc::clone(&keymgr),
+            Arc::clone(&upload_record),
+            subcredentials,
         );
 
         let svc = Arc::new(RunningOnionService {
             nickname,
             keymgr,
+            upload_record,
             inner: Mutex::new(SvcInner {
                 config_tx,
                 _shutdown_tx: shutdown_tx,
@@ -374,6 +429,23 @@ impl OnionServiceBuilder {
     pub fn build(&self) -> Result<OnionService, StartupError> {
         let svc = self.build_unvalidated()?;
 
+        match (&svc.state_dir, svc.ephemeral) {
+            (Some(_), false) | (None, true) => {}
+            (None, false) => {
+                return Err(
+                    FatalError::MissingField(derive_builder::UninitializedFieldError::new(
+                        "state_dir",
+                    ))
+                    .into(),
+                )
+            }
+            (Some(_), true) => {
+                return Err(
+                    bad_api_usage!("An ephemeral onion service cannot have a state_dir").into(),
+                )
+            }
+        }
+
         // TODO (#1194): add a config option for specifying whether to expect the KS_hsid to be stored
         // offline
         //let offline_hsid = config.offline_hsid;
@@ -434,6 +506,12 @@ impl RunningOnionService {
             .subscribe()
     }
 
+    /// Return the status of our descriptor on each HsDir we have tried to upload it to,
+    /// for each time period that is currently relevant.
+    pub fn descriptor_upload_status(&self) -> Vec<status::DescriptorUploadStatus> {
+        self.upload_record.lock().expect("poisoned lock").statuses()
+    }
+
     /// Tell this onion service to begin running, and return a
     /// stream of rendezvous requests on the service.
     ///
@@ -510,19 +588,18 @@ fn maybe_generate_hsid(
 
     // TODO (#1106): make this configurable
     let selector = KeystoreSelector::Default;
-    let mut rng = rand::thread_rng();
     let (keypair, generated) = match kp {
         Some(kp) => (kp, false),
         None => {
             // Note: there is a race here. If the HsId is generated through some other means
             // (e.g. via the CLI) at some point between the time we looked up the keypair and
             // now, we will return an error.
-            let kp = keymgr
-                .generate::<HsIdKeypair>(&hsid_spec, selector, &mut rng, false /* overwrite */)
-                .map_err(|cause| StartupError::Keystore {
+            let kp = create_hsid(keymgr, &hsid_spec, selector).map_err(|cause| {
+                StartupError::Keystore {
                     action: "generate",
                     cause,
-                })?;
+                }
+            })?;
 
             (kp, true)
         }
@@ -546,6 +623,24 @@ fn maybe_generate_hsid(
     Ok(())
 }
 
+/// Create the identity key of a service.
+///
+/// If the key manager has a master seed, the key is derived from it;
+/// otherwise, it is generated at random.
+fn create_hsid(
+    keymgr: &KeyMgr,
+    hsid_spec: &HsIdKeypairSpecifier,
+    selector: KeystoreSelector,
+) -> Result<HsIdKeypair, tor_keymgr::Error> {
+    #[cfg(feature = "key-derivation")]
+    if keymgr.has_master_seed() {
+        return keymgr.derive(hsid_spec, selector, false /* overwrite */);
+    }
+
+    let mut rng = rand::thread_rng();
+    keymgr.generate(hsid_spec, selector, &mut rng, false /* overwrite */)
+}
+
 /// Return the onion address of this service.
 ///
 /// Clients must know the service's onion address in order to discover or
@@ -592,7 +687,7 @@ pub(crate) mod test {
     use test_temp_dir::{test_temp_dir, TestTempDir, TestTempDirGuard};
 
     use tor_basic_utils::test_rng::testing_rng;
-    use tor_keymgr::{ArtiNativeKeystore, KeyMgrBuilder};
+    use tor_keymgr::{ArtiEphemeralKeystore, ArtiNativeKeystore, KeyMgrBuilder};
     use tor_llcrypto::pk::ed25519;
     use tor_persist::state_dir::InstanceStateHandle;
 
@@ -630,12 +725,7 @@ pub(crate) mod test {
         instance
     }
 
-    pub(crate) fn create_storage_handles(
-        dir: &Path,
-    ) -> (
-        tor_persist::state_dir::InstanceStateHandle,
-        IptSetStorageHandle,
-    ) {
+    pub(crate) fn create_storage_handles(dir: &Path) -> (ServiceStateHandle, IptSetStorageHandle) {
         let nick = HsNickname::try_from("allium".to_owned()).unwrap();
         create_storage_handles_from_state_dir(dir, &nick)
     }
@@ -643,11 +733,8 @@ pub(crate) mod test {
     pub(crate) fn create_storage_handles_from_state_dir(
         state_dir: &Path,
         nick: &HsNickname,
-    ) -> (
-        tor_persist::state_dir::InstanceStateHandle,
-        IptSetStorageHandle,
-    ) {
-        let instance = mk_state_instance(state_dir, nick);
+    ) -> (ServiceStateHandle, IptSetStorageHandle) {
+        let instance = ServiceStateHandle::Persistent(mk_state_instance(state_dir, nick));
         let iptpub_state_handle = instance.storage_handle("iptpub").unwrap();
         (instance, iptpub_state_handle)
     }
@@ -791,4 +878,89 @@ pub(crate) mod test {
 
         drop(temp_dir); // prove that this is still live
     }
+
+    /// Make a fresh `KeyMgr` (containing no keys) that keeps its keys in memory
+    fn create_ephemeral_keymgr() -> Arc<KeyMgr> {
+        let keystore = ArtiEphemeralKeystore::new("ephemeral".to_string());
+        Arc::new(
+            KeyMgrBuilder::default()
+                .default_store(Box::new(keystore))
+                .build()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn ephemeral_service() {
+        let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+        let hsid_spec = HsIdKeypairSpecifier::new(nickname.clone());
+        let config = OnionServiceConfigBuilder::default()
+            .nickname(nickname)
+            .build()
+            .unwrap();
+
+        // With no identity key, we generate one in memory.
+        let keymgr = create_ephemeral_keymgr();
+        let service = OnionService::builder()
+            .config(config.clone())
+            .keymgr(Arc::clone(&keymgr))
+            .ephemeral(true)
+            .build()
+            .unwrap();
+        assert!(keymgr.get::<HsIdKeypair>(&hsid_spec).unwrap().is_some());
+        assert!(service.onion_name().is_some());
+
+        // Or we can supply one.
+        let keymgr = create_ephemeral_keymgr();
+        let (hsid_keypair, hsid_public) = create_hsid();
+        keymgr
+            .insert(hsid_keypair, &hsid_spec, KeystoreSelector::Default)
+            .unwrap();
+        let service = OnionService::builder()
+            .config(config)
+            .keymgr(keymgr)
+            .ephemeral(true)
+            .build()
+            .unwrap();
+        assert_eq!(service.onion_name().unwrap(), HsId::from(hsid_public));
+    }
+
+    #[test]
+    fn state_dir_or_ephemeral() {
+        let temp_dir = test_temp_dir!();
+        let config = OnionServiceConfigBuilder::default()
+            .nickname(HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap())
+            .build()
+            .unwrap();
+        let state_dir = StateDirectory::new(
+            temp_dir.as_path_untracked(),
+            &fs_mistrust::Mistrust::new_dangerously_trust_everyone(),
+        )
+        .unwrap();
+
+        // Neither a state directory nor ephemeral.
+        let err = OnionService::builder()
+            .config(config.clone())
+            .keymgr(create_ephemeral_keymgr())
+            .build()
+            .map(|_| ())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            StartupError::Fatal(FatalError::MissingField(_))
+        ));
+
+        // Both.
+        let err = OnionService::builder()
+            .config(config)
+            .keymgr(create_ephemeral_keymgr())
+            .state_dir(state_dir)
+            .ephemeral(true)
+            .build()
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BadApiUsage);
+
+        drop(temp_dir);
+    }
 }
@@ -2,14 +2,14 @@
 
 use crate::internal_prelude::*;
 
-/// Get a NetDir from `provider`, waiting until one exists.
+/// Get a NetDir that is live enough for `usage` from `provider`, waiting until one exists.
 ///
 /// TODO: perhaps this function would be more generally useful if it were not here?
 pub(crate) async fn wait_for_netdir(
     provider: &dyn NetDirProvider,
-    timeliness: tor_netdir::Timeliness,
+    usage: DirUsage,
 ) -> Result<Arc<NetDir>, NetdirProviderShutdown> {
-    if let Ok(nd) = provider.netdir(timeliness) {
+    if let Ok(nd) = provider.netdir_for(usage) {
         return Ok(nd);
     }
 
@@ -21,7 +21,7 @@ pub(crate) async fn wait_for_netdir(
         // We ignore all errors here: they can all potentially be fixed by
         // getting a fresh consensus, and they will all get warned about
         // by the NetDirProvider itself.
-        if let Ok(nd) = provider.netdir(timeliness) {
+        if let Ok(nd) = provider.netdir_for(usage) {
             return Ok(nd);
         }
         match stream.next().await {
@@ -47,7 +47,7 @@ pub(crate) async fn wait_for_netdir_to_list(
         //
         // We do this before waiting for any events, to avoid race conditions.
         {
-            let netdir = wait_for_netdir(provider, tor_netdir::Timeliness::Timely).await?;
+            let netdir = wait_for_netdir(provider, DirUsage::OnionServices).await?;
             if netdir.ids_listed(target) == Some(true) {
                 return Ok(());
             }
@@ -3,6 +3,7 @@ mod backoff;
 mod descriptor;
 mod reactor;
 mod reupload_timer;
+mod upload_record;
 
 use crate::internal_prelude::*;
 
@@ -14,6 +15,7 @@ use reupload_timer::ReuploadTimer;
 
 pub use reactor::UploadError;
 pub(crate) use reactor::{Mockable, Real, OVERALL_UPLOAD_TIMEOUT};
+pub(crate) use upload_record::{SharedUploadRecord, UploadRecord};
 
 /// A handle for the Hsdir Publisher for an onion service.
 ///
@@ -43,6 +45,10 @@ pub(crate) struct Publisher<R: Runtime, M: Mockable> {
     keymgr: Arc<KeyMgr>,
     /// A sender for updating the status of the onion service.
     status_tx: PublisherStatusSender,
+    /// The record of which HsDirs have our descriptor.
+    upload_record: SharedUploadRecord,
+    /// The service's cache of subcredentials.
+    subcredentials: Arc<SubcredentialCache>,
 }
 
 impl<R: Runtime, M: Mockable> Publisher<R, M> {
@@ -62,6 +68,8 @@ impl<R: Runtime, M: Mockable> Publisher<R, M> {
         config_rx: watch::Receiver<Arc<OnionServiceConfig>>,
         status_tx: PublisherStatusSender,
         keymgr: Arc<KeyMgr>,
+        upload_record: SharedUploadRecord,
+        subcredentials: Arc<SubcredentialCache>,
     ) -> Self {
         let config = config_rx.borrow().clone();
         Self {
@@ -74,6 +82,8 @@ impl<R: Runtime, M: Mockable> Publisher<R, M> {
             config_rx,
             status_tx,
             keymgr,
+            upload_record,
+            subcredentials,
         }
     }
 
@@ -89,6 +99,8 @@ impl<R: Runtime, M: Mockable> Publisher<R, M> {
             config_rx,
             status_tx,
             keymgr,
+            upload_record,
+            subcredentials,
         } = self;
 
         let reactor = Reactor::new(
@@ -101,6 +113,8 @@ impl<R: Runtime, M: Mockable> Publisher<R, M> {
             config_rx,
             status_tx,
             keymgr,
+            upload_record,
+            subcredentials,
         );
 
         runtime
@@ -171,11 +185,14 @@ mod test {
     use tor_basic_utils::test_rng::{testing_rng, TestingRng};
     use tor_circmgr::hspool::HsCircKind;
     use tor_hscrypto::pk::{HsBlindId, HsDescSigningKeypair, HsId, HsIdKey, HsIdKeypair};
-    use tor_keymgr::{ArtiNativeKeystore, KeyMgrBuilder, KeySpecifier, ToEncodableKey};
+    use tor_keymgr::{
+        ArtiNativeKeystore, KeyMgrBuilder, KeyPathPattern, KeySpecifier, ToEncodableKey,
+    };
     use tor_llcrypto::pk::{ed25519, rsa};
     use tor_netdir::testprovider::TestNetDirProvider;
     use tor_netdir::{testnet, NetDir};
     use tor_netdoc::doc::hsdesc::test_data;
+    use tor_netdoc::doc::netstatus::Lifetime;
     use tor_rtcompat::BlockOn;
     use tor_rtmock::MockRuntime;
 
@@ -186,8 +203,8 @@ mod test {
     use crate::test::create_storage_handles;
     use crate::{Anonymity, HsNickname};
     use crate::{
-        BlindIdKeypairSpecifier, BlindIdPublicKeySpecifier, DescSigningKeypairSpecifier,
-        HsIdKeypairSpecifier, HsIdPublicKeySpecifier,
+        BlindIdKeypairSpecifier, BlindIdPublicKeySpecifier, DescSigningKeyInterval,
+        DescSigningKeypairSpecifier, HsIdKeypairSpecifier, HsIdPublicKeySpecifier,
     };
 
     /// The nickname of the test service.
@@ -423,7 +440,11 @@ mod test {
         insert_svc_key(
             HsDescSigningKeypair::from(ed25519::Keypair::generate(&mut rng)),
             &keymgr,
-            &DescSigningKeypairSpecifier::new(nickname.clone(), period),
+            &DescSigningKeypairSpecifier::new(
+                nickname.clone(),
+                period,
+                DescSigningKeyInterval::containing(netdir.lifetime().valid_after()),
+            ),
         );
 
         let hs_id = id_pub.into();
@@ -472,6 +493,10 @@ mod test {
                 config_rx,
                 status_tx,
                 keymgr,
+                Arc::new(Mutex::new(
+                    UploadRecord::load(crate::storage::StorageHandle::Ephemeral, &runtime).unwrap(),
+                )),
+                Default::default(),
             );
 
             publisher.launch().unwrap();
@@ -494,7 +519,9 @@ mod test {
 
             if republish_count > 0 {
                 /// The latest time the descriptor can be republished.
-                const MAX_TIMEOUT: Duration = Duration::from_secs(60 * 120);
+                const MAX_TIMEOUT: Duration = Duration::from_secs(60 * 120)
+                    .saturating_add(reactor::REUPLOAD_COALESCING)
+                    .saturating_add(reactor::REUPLOAD_JITTER);
 
                 // Wait until the reactor triggers the necessary number of reuploads.
                 runtime
@@ -631,6 +658,256 @@ mod test {
             .used_by(|dir| publish_after_ipt_change(dir, poll_reads, 1, REUPLOAD_COUNT));
     }
 
+    /// Build a test `NetDir` whose consensus is valid-after `valid_after`,
+    /// with the specified previous and current shared random values.
+    ///
+    /// Each SRV is given as a `(value, timestamp)` pair.
+    fn netdir_with_srvs(
+        valid_after: SystemTime,
+        srv_prev: ([u8; 32], SystemTime),
+        srv_cur: ([u8; 32], SystemTime),
+    ) -> NetDir {
+        const ONE_HOUR: Duration = Duration::from_secs(60 * 60);
+        let lifetime = Lifetime::new(
+            valid_after,
+            valid_after + ONE_HOUR,
+            valid_after + 3 * ONE_HOUR,
+        )
+        .unwrap();
+
+        testnet::construct_custom_netdir_with_consensus(
+            testnet::simple_net_func,
+            |bld| {
+                bld.shared_rand_prev(7, srv_prev.0.into(), Some(srv_prev.1));
+                bld.shared_rand_cur(7, srv_cur.0.into(), Some(srv_cur.1));
+            },
+            Some(lifetime),
+        )
+        .unwrap()
+        .unwrap_if_sufficient()
+        .unwrap()
+    }
+
+    /// Return the number of HsDirs `netdir` says we should upload to for each of its time periods.
+    fn hsdir_counts(netdir: &NetDir, keymgr: &KeyMgr, nickname: &HsNickname) -> Vec<usize> {
+        netdir
+            .hs_all_time_periods()
+            .iter()
+            .map(|params| {
+                let period = params.time_period();
+                let blind_id_kp = keymgr
+                    .get::<HsBlindIdKeypair>(&BlindIdKeypairSpecifier::new(
+                        nickname.clone(),
+                        period,
+                    ))
+                    .unwrap()
+                    .unwrap();
+                let blind_id: HsBlindIdKey = (&blind_id_kp).into();
+                netdir
+                    .hs_dirs_upload(blind_id.into(), period)
+                    .unwrap()
+                    .count()
+            })
+            .collect()
+    }
+
+    /// Test that the publisher maintains descriptors for all the relevant time periods as the
+    /// consensus moves across a time period boundary, and that it stops publishing for (and
+    /// expires the keys of) the time periods that are no longer relevant.
+    #[test]
+    fn publish_across_time_period_transition() {
+        test_temp_dir!().used_by(|temp_dir| {
+            let t = |s: &str| humantime::parse_rfc3339(s).unwrap();
+            let srv1 = ([1; 32], t("2024-10-24T00:00:00Z"));
+            let srv2 = ([2; 32], t("2024-10-25T00:00:00Z"));
+            let srv3 = ([3; 32], t("2024-10-26T00:00:00Z"));
+
+            // With the default 1-day time periods and 1h voting intervals, the time periods
+            // start at 12:00 UTC.
+            //
+            // At 07:00, we are in the time period that started yesterday at 12:00, and we
+            // already know the SRV for the next one.
+            let netdir_a = netdir_with_srvs(t("2024-10-25T07:00:00Z"), srv1, srv2);
+            // Later that day, we've entered the next time period, and the set of relevant time
+            // periods hasn't changed.
+            let netdir_b = netdir_with_srvs(t("2024-10-25T13:00:00Z"), srv1, srv2);
+            // After midnight, there is a new SRV: the first time period is no longer relevant,
+            // and the one that starts today at 12:00 is.
+            let netdir_c = netdir_with_srvs(t("2024-10-26T01:00:00Z"), srv2, srv3);
+
+            let periods = |netdir: &NetDir| {
+                netdir
+                    .hs_all_time_periods()
+                    .iter()
+                    .map(|params| params.time_period())
+                    .collect_vec()
+            };
+            let periods_a = periods(&netdir_a);
+            let periods_c = periods(&netdir_c);
+            assert_eq!(periods_a.len(), 2);
+            assert_eq!(periods(&netdir_b), periods_a);
+            assert_eq!(periods_c.len(), 2);
+            let (tp_a, tp_b) = (periods_a[0], periods_a[1]);
+            let tp_c = periods_c[1];
+            assert_eq!(periods_c[0], tp_b);
+            assert_eq!(tp_a.next(), Some(tp_b));
+            assert_eq!(tp_b.next(), Some(tp_c));
+
+            let runtime = MockRuntime::builder()
+                .starting_wallclock(t("2024-10-25T07:00:00Z"))
+                .build();
+            let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+            let config = build_test_config(nickname.clone());
+            let (_config_tx, config_rx) = watch::channel_with(Arc::new(config));
+            let (mut mv, pv) = ipts_channel(&runtime, create_storage_handles(temp_dir).1).unwrap();
+            let keystore_dir = tempdir().unwrap();
+            let (_hsid, _blind_id, keymgr) = init_keymgr(&keystore_dir, &nickname, &netdir_a);
+            let status_tx = StatusSender::new(OnionServiceStatus::new_shutdown()).into();
+
+            let has_keys = |period: TimePeriod| {
+                keymgr
+                    .get::<HsBlindIdKeypair>(&BlindIdKeypairSpecifier::new(
+                        nickname.clone(),
+                        period,
+                    ))
+                    .unwrap()
+                    .is_some()
+            };
+            // The intervals of the descriptor signing keys we have for `period`.
+            let desc_sign_intervals = |period: TimePeriod| {
+                let pat = KeyPathPattern::Arti(format!(
+                    "hss/{nickname}/ks_hs_desc_sign+{}+*",
+                    period.to_slug().unwrap()
+                ));
+                keymgr
+                    .list_matching(&pat)
+                    .unwrap()
+                    .iter()
+                    .map(|entry| {
+                        DescSigningKeypairSpecifier::try_from(entry.key_path())
+                            .unwrap()
+                            .interval
+                    })
+                    .sorted()
+                    .collect_vec()
+            };
+            let interval = |s: &str| DescSigningKeyInterval::containing(t(s));
+
+            runtime.clone().block_on(async {
+                let netdir_provider = Arc::new(TestNetDirProvider::from(netdir_a.clone()));
+                let publish_count = Arc::new(AtomicUsize::default());
+                let circpool = MockReactorState {
+                    publish_count: Arc::clone(&publish_count),
+                    poll_read_responses: [Ok(OK_RESPONSE.into())].into_iter(),
+                    responses_for_hsdir: Arc::new(Mutex::new(Default::default())),
+                };
+
+                let publisher: Publisher<MockRuntime, MockReactorState<_>> = Publisher::new(
+                    runtime.clone(),
+                    nickname.clone(),
+                    Arc::clone(&netdir_provider) as Arc<dyn NetDirProvider>,
+                    circpool,
+                    pv,
+                    config_rx,
+                    status_tx,
+                    Arc::clone(&keymgr),
+                    Arc::new(Mutex::new(
+                        UploadRecord::load(crate::storage::StorageHandle::Ephemeral, &runtime)
+                            .unwrap(),
+                    )),
+                    Default::default(),
+                );
+
+                publisher.launch().unwrap();
+                runtime.progress_until_stalled().await;
+
+                let ipts: Vec<IptInSet> = test_data::test_parsed_hsdesc()
+                    .unwrap()
+                    .intro_points()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, ipt)| IptInSet {
+                        ipt: ipt.clone(),
+                        lid: [i.try_into().unwrap(); 32].into(),
+                    })
+                    .collect();
+                mv.borrow_for_update(runtime.clone()).ipts = Some(IptSet {
+                    ipts,
+                    lifetime: Duration::from_secs(20),
+                });
+                runtime.progress_until_stalled().await;
+                runtime.advance_by(Duration::from_secs(1)).await;
+                runtime.progress_until_stalled().await;
+
+                // We publish a descriptor for each of the two time periods, deriving the keys
+                // for the second one.
+                let counts_a = hsdir_counts(&netdir_a, &keymgr, &nickname);
+                assert!(counts_a.iter().all(|n| *n > 0));
+                let expected = counts_a.iter().sum::<usize>();
+                assert_eq!(publish_count.load(Ordering::SeqCst), expected);
+                assert!(has_keys(tp_a));
+                assert!(has_keys(tp_b));
+                // Both descriptors were signed with a key for the current interval.
+                let interval_a = interval("2024-10-25T07:00:00Z");
+                assert_eq!(desc_sign_intervals(tp_a), [interval_a]);
+                assert_eq!(desc_sign_intervals(tp_b), [interval_a]);
+
+                // Move the clock forward a little (past the upload rate limit, but not far
+                // enough for any reupload timer to fire), without changing the wallclock by
+                // much.
+                let step = |wallclock: &str| {
+                    let runtime = runtime.clone();
+                    let wallclock = t(wallclock);
+                    async move {
+                        runtime.jump_wallclock(wallclock);
+                        runtime.advance_by(Duration::from_secs(5 * 60)).await;
+                        runtime.progress_until_stalled().await;
+                    }
+                };
+
+                // The new consensus doesn't change the relevant time periods or their HsDirs,
+                // so there is nothing to reupload.
+                step("2024-10-25T13:00:00Z").await;
+                netdir_provider.set_netdir_and_notify(netdir_b).await;
+                runtime.progress_until_stalled().await;
+                runtime.advance_by(Duration::from_secs(1)).await;
+                runtime.progress_until_stalled().await;
+                assert_eq!(publish_count.load(Ordering::SeqCst), expected);
+                // The descriptor signing keys we used at 07:00 have been retired: any
+                // descriptors signed with them have expired by now.
+                assert!(has_keys(tp_a));
+                assert!(has_keys(tp_b));
+                assert!(desc_sign_intervals(tp_a).is_empty());
+                assert!(desc_sign_intervals(tp_b).is_empty());
+
+                // Crossing into the next SRV protocol run: we only need to publish the
+                // descriptor for the new time period; the HsDirs for the time period we've
+                // already published for already have our descriptor.
+                step("2024-10-26T01:00:00Z").await;
+                netdir_provider
+                    .set_netdir_and_notify(netdir_c.clone())
+                    .await;
+                runtime.progress_until_stalled().await;
+                runtime.advance_by(Duration::from_secs(1)).await;
+                runtime.progress_until_stalled().await;
+
+                let counts_c = hsdir_counts(&netdir_c, &keymgr, &nickname);
+                assert_eq!(counts_c[0], counts_a[1]);
+                let expected = expected + counts_c[1];
+                assert_eq!(publish_count.load(Ordering::SeqCst), expected);
+
+                // The keys for the time period that has ended were removed.
+                assert!(!has_keys(tp_a));
+                assert!(has_keys(tp_b));
+                assert!(has_keys(tp_c));
+                assert_eq!(
+                    desc_sign_intervals(tp_c),
+                    [interval("2024-10-26T01:00:00Z")]
+                );
+            });
+        });
+    }
+
     // TODO (#1120): test that the descriptor is republished when the config changes
 
     // TODO (#1120): test that the descriptor is reuploaded only to the HSDirs that need it (i.e. the
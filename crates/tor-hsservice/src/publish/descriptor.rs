@@ -8,10 +8,16 @@ use tor_cell::chancell::msg::HandshakeType;
 /// The `now` argument is used for computing the expiry of the `intro_{auth, enc}_key_cert`
 /// certificates included in the descriptor. The expiry will be set to 54 hours from `now`.
 ///
+/// The descriptor is signed with the descriptor signing key
+/// for the [`DescSigningKeyInterval`] containing `now`, which is generated if necessary.
+/// Its certificate expires 54 hours after the end of that interval,
+/// so that it outlives every descriptor signed with the key.
+///
 /// Note: `blind_id_kp` is the blinded hidden service signing keypair used to sign descriptor
 /// signing keys (KP_hs_blind_id, KS_hs_blind_id).
 pub(super) fn build_sign<Rng: RngCore + CryptoRng>(
     keymgr: &Arc<KeyMgr>,
+    subcredentials: &SubcredentialCache,
     config: &Arc<OnionServiceConfig>,
     ipt_set: &IptSet,
     period: TimePeriod,
@@ -54,9 +60,11 @@ pub(super) fn build_sign<Rng: RngCore + CryptoRng>(
         .ok_or_else(|| internal!("hidden service offline mode not supported"))?;
 
     let blind_id_key = HsBlindIdKey::from(&blind_id_kp);
-    let subcredential = hsid.compute_subcredential(&blind_id_key, period);
+    let subcredential = subcredentials.compute_subcredential(&hsid, &blind_id_key, period);
 
-    let hs_desc_sign_key_spec = DescSigningKeypairSpecifier::new(nickname.clone(), period);
+    let interval = DescSigningKeyInterval::containing(now);
+    let hs_desc_sign_key_spec =
+        DescSigningKeypairSpecifier::new(nickname.clone(), period, interval);
     let hs_desc_sign = keymgr.get_or_generate::<HsDescSigningKeypair>(
         &hs_desc_sign_key_spec,
         keystore_selector,
@@ -73,7 +81,9 @@ pub(super) fn build_sign<Rng: RngCore + CryptoRng>(
     // when building the descriptor. See #1048
     let intro_auth_key_cert_expiry = now + HS_DESC_CERT_LIFETIME_SEC;
     let intro_enc_key_cert_expiry = now + HS_DESC_CERT_LIFETIME_SEC;
-    let hs_desc_sign_cert_expiry = now + HS_DESC_CERT_LIFETIME_SEC;
+    // The same key signs descriptors throughout its interval,
+    // so its certificate must cover all of them.
+    let hs_desc_sign_cert_expiry = interval.end() + HS_DESC_CERT_LIFETIME_SEC;
 
     // TODO (#1206): Temporarily disabled while we figure out how we want the client auth config to
     // work; see #1028
@@ -50,7 +50,9 @@
 //! For the time being, the publisher never sets the status to `Recovering`, and uses the `Broken`
 //! status for reporting fatal errors (crashes).
 
+use tor_events::events::{TorEvent, TorEventKind};
 use tor_netdir::DirEvent;
+use tor_rtcompat::scheduler::{TaskHandle, TaskSchedule};
 
 use super::*;
 
@@ -81,6 +83,25 @@ const MAX_CONCURRENT_UPLOADS: usize = 16;
 /// across all attempts.
 pub(crate) const OVERALL_UPLOAD_TIMEOUT: Duration = Duration::from_secs(5 * 60);
 
+/// The initial delay before retrying uploads that failed.
+///
+/// (Each upload has already been retried for up to [`OVERALL_UPLOAD_TIMEOUT`] by then.)
+const FAILED_UPLOAD_RETRY_BASE_DELAY: Duration = Duration::from_secs(60);
+
+/// The maximum delay before retrying uploads that failed.
+///
+/// This is the shortest interval at which we reupload our descriptor anyway.
+const FAILED_UPLOAD_RETRY_MAX_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// The granularity onto which we coalesce descriptor reuploads.
+///
+/// Reuploads happen at a random time anyway, so they needn't happen at any
+/// precise moment, and can share wakeups with other periodic tasks.
+pub(super) const REUPLOAD_COALESCING: Duration = Duration::from_secs(60);
+
+/// How much random delay to add to each descriptor reupload, before coalescing.
+pub(super) const REUPLOAD_JITTER: Duration = Duration::from_secs(60);
+
 /// A reactor for the HsDir [`Publisher`]
 ///
 /// The entrypoint is [`Reactor::run`].
@@ -130,6 +151,17 @@ pub(super) struct Reactor<R: Runtime, M: Mockable> {
     ///
     /// Closing this channel will cause any pending upload tasks to be dropped.
     shutdown_tx: broadcast::Sender<Void>,
+    /// The schedule that wakes us up when it's time to reupload the descriptor,
+    /// or to retry the uploads that failed.
+    reupload_schedule: TaskSchedule<R>,
+    /// The handle for `reupload_schedule`.
+    ///
+    /// We must keep this alive: the schedule stops firing once its last handle is dropped.
+    _reupload_task: TaskHandle,
+    /// The time for which `reupload_schedule` is currently set, if any.
+    ///
+    /// Used to avoid rescheduling (and re-jittering) the same wakeup on every iteration.
+    reupload_scheduled_for: Option<Instant>,
 }
 
 /// The immutable, shared state of the descriptor publisher reactor.
@@ -147,6 +179,10 @@ struct Immutable<R: Runtime, M: Mockable> {
     keymgr: Arc<KeyMgr>,
     /// A sender for updating the status of the onion service.
     status_tx: PublisherStatusSender,
+    /// The record of which HsDirs have our descriptor.
+    upload_record: SharedUploadRecord,
+    /// The service's cache of subcredentials.
+    subcredentials: Arc<SubcredentialCache>,
 }
 
 impl<R: Runtime, M: Mockable> Immutable<R, M> {
@@ -173,8 +209,13 @@ impl<R: Runtime, M: Mockable> Immutable<R, M> {
             None => {
                 // TODO (#1194): we don't support externally provisioned keys (yet), so this branch
                 // is unreachable (for now).
+                //
+                // The OPE key must not change during the time period, so we can't use
+                // whichever descriptor signing key is current: we use the one for the interval
+                // at the start of the period, which is kept until the period is no longer
+                // relevant.  Offline mode will need to make sure that key is provisioned.
                 let desc_sign_key_spec =
-                    DescSigningKeypairSpecifier::new(self.nickname.clone(), period);
+                    DescSigningKeypairSpecifier::for_period_start(self.nickname.clone(), period)?;
                 let key: ed25519::Keypair = self
                     .keymgr
                     .get::<HsDescSigningKeypair>(&desc_sign_key_spec)?
@@ -356,6 +397,22 @@ struct Inner {
     //
     // See https://gitlab.torproject.org/tpo/core/arti/-/merge_requests/1971#note_2994950
     reupload_timers: BinaryHeap<ReuploadTimer>,
+    /// Whether we still need to consult the [`UploadRecord`](super::upload_record::UploadRecord)
+    /// loaded from disk.
+    ///
+    /// We do this once, when we first learn which introduction points to publish,
+    /// so that after a restart we don't reupload our descriptor to HsDirs that already have it.
+    restore_pending: bool,
+    /// When to retry the uploads that failed, if any did.
+    ///
+    /// The HsDirs that we failed to upload to are still marked dirty,
+    /// so scheduling an upload at this time will retry just those.
+    retry_failed_at: Option<Instant>,
+    /// The delay before retrying failed uploads.
+    ///
+    /// This grows with each batch of uploads that has failures,
+    /// and is reset when a batch succeeds completely.
+    retry_delay: RetryDelay,
 }
 
 /// The part of the reactor state that changes with every time period.
@@ -473,6 +530,8 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
         config_rx: watch::Receiver<Arc<OnionServiceConfig>>,
         status_tx: PublisherStatusSender,
         keymgr: Arc<KeyMgr>,
+        upload_record: SharedUploadRecord,
+        subcredentials: Arc<SubcredentialCache>,
     ) -> Self {
         /// The maximum size of the upload completion notifier channel.
         ///
@@ -489,12 +548,18 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
         // since we never actually send anything on this channel.
         let (shutdown_tx, _shutdown_rx) = broadcast::channel(0);
 
+        let (mut reupload_schedule, reupload_task) = TaskSchedule::new(runtime.clone());
+        reupload_schedule.set_coalescing(REUPLOAD_COALESCING);
+        reupload_schedule.set_jitter(REUPLOAD_JITTER);
+
         let imm = Immutable {
             runtime,
             mockable,
             nickname,
             keymgr,
             status_tx,
+            upload_record,
+            subcredentials,
         };
 
         let inner = Inner {
@@ -503,6 +568,9 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
             netdir: None,
             last_uploaded: None,
             reupload_timers: Default::default(),
+            restore_pending: true,
+            retry_failed_at: None,
+            retry_delay: RetryDelay::from_duration(FAILED_UPLOAD_RETRY_BASE_DELAY),
         };
 
         Self {
@@ -516,6 +584,9 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
             upload_task_complete_rx,
             upload_task_complete_tx,
             shutdown_tx,
+            reupload_schedule,
+            _reupload_task: reupload_task,
+            reupload_scheduled_for: None,
         }
     }
 
@@ -529,7 +600,8 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
         debug!(nickname=%self.imm.nickname, "starting descriptor publisher reactor");
 
         {
-            let netdir = wait_for_netdir(self.dir_provider.as_ref(), Timeliness::Timely).await?;
+            let netdir =
+                wait_for_netdir(self.dir_provider.as_ref(), DirUsage::OnionServices).await?;
             let time_periods = self.compute_time_periods(&netdir, &[])?;
 
             let mut inner = self.inner.lock().expect("poisoned lock");
@@ -576,25 +648,52 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
             }
         }
 
-        let reupload_tracking = TrackingNow::now(&self.imm.runtime);
+        let now = self.imm.runtime.now();
         let mut reupload_periods = vec![];
-        {
+        let mut retry_failed = false;
+        let next_wakeup = {
             let mut inner = self.inner.lock().expect("poisoned lock");
             let inner = &mut *inner;
+            if let Some(when) = inner.retry_failed_at {
+                if when <= now {
+                    inner.retry_failed_at = None;
+                    retry_failed = true;
+                }
+            }
             while let Some(reupload) = inner.reupload_timers.peek().copied() {
                 // First, extract all the timeouts that already elapsed.
-                if reupload.when <= reupload_tracking {
+                if reupload.when <= now {
                     inner.reupload_timers.pop();
                     reupload_periods.push(reupload.period);
                 } else {
                     // We are not ready to schedule any more reuploads.
-                    //
-                    // How much we need to sleep is implicitly
-                    // tracked in reupload_tracking (through
-                    // the TrackingNow implementation)
                     break;
                 }
             }
+            let next_reupload = inner.reupload_timers.peek().map(|reupload| reupload.when);
+            [inner.retry_failed_at, next_reupload]
+                .into_iter()
+                .flatten()
+                .min()
+        };
+
+        // Arrange to be woken when the next reupload or retry is due.
+        //
+        // The schedule coalesces and jitters this wakeup, but never makes it earlier.
+        // If nothing is due, we leave any pending wakeup alone:
+        // when it fires, we'll just go round the loop again.
+        if let Some(when) = next_wakeup {
+            if self.reupload_scheduled_for != Some(when) {
+                self.reupload_scheduled_for = Some(when);
+                self.reupload_schedule
+                    .fire_in(when.saturating_duration_since(now));
+            }
+        }
+
+        if retry_failed {
+            debug!("retrying failed descriptor uploads");
+            self.update_publish_status_unless_rate_lim(PublishStatus::UploadScheduled)
+                .await?;
         }
 
         // Check if it's time to schedule any reuploads.
@@ -620,11 +719,12 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
             () = upload_rate_lim.wait_for_earliest(&self.imm.runtime).fuse() => {
                 self.expire_rate_limit().await?;
             },
-            () = reupload_tracking.wait_for_earliest(&self.imm.runtime).fuse() => {
+            _ = self.reupload_schedule.next().fuse() => {
                 // Run another iteration, executing run_once again. This time, we will remove the
                 // expired reupload from self.reupload_timers, mark the descriptor dirty for all
                 // relevant HsDirs, and schedule the upload by setting our status to
                 // UploadScheduled.
+                self.reupload_scheduled_for = None;
                 return Ok(ShutdownStatus::Continue);
             },
             netdir_event = netdir_events.next().fuse() => {
@@ -638,7 +738,7 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
                 };
 
                 // The consensus changed. Grab a new NetDir.
-                let netdir = match self.dir_provider.netdir(Timeliness::Timely) {
+                let netdir = match self.dir_provider.netdir_for(DirUsage::OnionServices) {
                     Ok(y) => y,
                     Err(e) => {
                         error_report!(e, "HS service {}: netdir unavailable. Retrying...", self.imm.nickname);
@@ -652,7 +752,7 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
                         //
                         // Probably this should be fixed by moving the logging
                         // out of the reactor, where it won't be blocked.
-                        wait_for_netdir(self.dir_provider.as_ref(), Timeliness::Timely)
+                        wait_for_netdir(self.dir_provider.as_ref(), DirUsage::OnionServices)
                             .await?
                     }
                 };
@@ -662,6 +762,7 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
                     &self.imm.keymgr,
                     &self.imm.nickname,
                     &relevant_periods,
+                    self.imm.runtime.wallclock(),
                 ).unwrap_or_else(|e| {
                     error_report!(e, "failed to remove expired keys");
                 });
@@ -740,6 +841,41 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
             when: reupload_when,
         });
 
+        let mut any_failed = false;
+        {
+            let mut upload_record = self.imm.upload_record.lock().expect("poisoned lock");
+            for upload_res in &results.hsdir_result {
+                let success = upload_res.upload_res == UploadStatus::Success;
+                any_failed |= !success;
+                upload_record.note_upload(
+                    &self.imm.runtime,
+                    time_period,
+                    &upload_res.relay_ids,
+                    upload_res.ipts.clone(),
+                    success,
+                );
+            }
+        }
+
+        if any_failed {
+            let delay = std::cmp::min(
+                inner.retry_delay.next_delay(&mut rng),
+                FAILED_UPLOAD_RETRY_MAX_DELAY,
+            );
+            debug!(
+                time_period=?time_period,
+                "some descriptor uploads failed; retrying in {}",
+                humantime::format_duration(delay),
+            );
+            let when = self.imm.runtime.now() + delay;
+            inner.retry_failed_at = Some(match inner.retry_failed_at {
+                Some(existing) => std::cmp::min(existing, when),
+                None => when,
+            });
+        } else {
+            inner.retry_delay.reset();
+        }
+
         for upload_res in results.hsdir_result {
             let relay = period
                 .hs_dirs
@@ -804,7 +940,41 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
 
         // Update our list of relevant time periods.
         let new_time_periods = self.compute_time_periods(&netdir, &inner.time_periods)?;
-        inner.time_periods = new_time_periods;
+        let old_time_periods = std::mem::replace(&mut inner.time_periods, new_time_periods);
+
+        let relevant_periods = inner
+            .time_periods
+            .iter()
+            .map(|ctx| ctx.params.time_period())
+            .collect_vec();
+
+        // During the overlap between two time periods, we publish descriptors for both of them.
+        // Once a time period stops being relevant, we stop maintaining its descriptor.
+        for old in &old_time_periods {
+            let period = old.params.time_period();
+            if !relevant_periods.contains(&period) {
+                debug!(nickname=%self.imm.nickname, time_period=?period,
+                       "time period is no longer relevant; no longer publishing descriptors for it");
+            }
+        }
+        for period in &relevant_periods {
+            if !old_time_periods
+                .iter()
+                .any(|ctx| ctx.params.time_period() == *period)
+            {
+                debug!(nickname=%self.imm.nickname, time_period=?period,
+                       "started publishing descriptors for a new time period");
+            }
+        }
+        inner
+            .reupload_timers
+            .retain(|timer| relevant_periods.contains(&timer.period));
+
+        self.imm
+            .upload_record
+            .lock()
+            .expect("poisoned lock")
+            .retain_periods(&relevant_periods);
 
         Ok(())
     }
@@ -845,12 +1015,16 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
                     .iter()
                     .find(|ctx| ctx.params.time_period() == period)
                 {
-                    TimePeriodContext::new(
+                    let mut new_ctx = TimePeriodContext::new(
                         params.clone(),
                         blind_id.into(),
                         netdir,
                         ctx.hs_dirs.iter(),
-                    )
+                    )?;
+                    // The HsDirs that already have our descriptor still have the same revision
+                    // counter for it, so we must not forget it.
+                    new_ctx.last_successful = ctx.last_successful;
+                    Ok(new_ctx)
                 } else {
                     // Passing an empty iterator here means all HsDirs in this TimePeriodContext
                     // will be marked as dirty, meaning we will need to upload our descriptor to them.
@@ -875,6 +1049,12 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
         let mut inner = self.inner.lock().expect("poisoned lock");
         let old_config = &mut inner.config;
 
+        // This is the very configuration we already have (for example, the initial value
+        // of config_rx), so nothing has changed.
+        if Arc::ptr_eq(old_config, &new_config) {
+            return false;
+        }
+
         // The fields we're interested in haven't changed, so there's no need to update
         // `inner.config`.
         //
@@ -922,6 +1102,7 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
                 debug!(nickname=%self.imm.nickname, "the introduction points have changed");
 
                 self.mark_all_dirty();
+                self.restore_upload_record();
                 self.update_publish_status_unless_rate_lim(should_upload)
                     .await?;
                 Ok(ShutdownStatus::Continue)
@@ -1014,6 +1195,58 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
             .for_each(|tp| tp.mark_all_dirty());
     }
 
+    /// If we haven't yet done so, mark clean any HsDirs that our [`UploadRecord`]
+    /// says already have an up-to-date descriptor.
+    ///
+    /// This must only be called when we know which introduction points to publish;
+    /// it does nothing if there are none.
+    ///
+    /// For each time period where we find such HsDirs,
+    /// we also schedule a reupload, as if we had just uploaded to them.
+    ///
+    /// [`UploadRecord`]: super::upload_record::UploadRecord
+    fn restore_upload_record(&self) {
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        let inner = &mut *inner;
+        if !inner.restore_pending {
+            return;
+        }
+
+        let ipts = {
+            let ipt_set = self.ipt_watcher.borrow_for_publish();
+            let Some(ipts) = ipt_set.ipts.as_ref() else {
+                return;
+            };
+            ipts.ipts.iter().map(|ipt| ipt.lid).collect_vec()
+        };
+        inner.restore_pending = false;
+
+        let now = self.imm.runtime.now();
+        let upload_record = self.imm.upload_record.lock().expect("poisoned lock");
+        for period_ctx in &mut inner.time_periods {
+            let period = period_ctx.params.time_period();
+            let mut n_restored = 0;
+            let mut reupload_when: Option<Instant> = None;
+            for (relay_ids, status) in &mut period_ctx.hs_dirs {
+                if let Some(until) = upload_record.fresh_until(now, period, relay_ids, &ipts) {
+                    *status = DescriptorStatus::Clean;
+                    n_restored += 1;
+                    reupload_when = Some(reupload_when.map_or(until, |when| when.min(until)));
+                }
+            }
+
+            if let Some(when) = reupload_when {
+                debug!(
+                    time_period=?period,
+                    "{}/{} HSDirs already have our descriptor; not uploading it to them again",
+                    n_restored,
+                    period_ctx.hs_dirs.len(),
+                );
+                inner.reupload_timers.push(ReuploadTimer { period, when });
+            }
+        }
+    }
+
     /// Mark the descriptor dirty for the specified time period.
     ///
     /// Returns `true` if the specified period is still relevant, and `false` otherwise.
@@ -1079,7 +1312,7 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
 
             if hs_dirs.is_empty() {
                 trace!("the descriptor is clean for all HSDirs. Nothing to do");
-                return Ok(());
+                continue;
             }
 
             let time_period = period_ctx.params.time_period();
@@ -1254,6 +1487,7 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
                         let Some(ipts) = ipt_set.ipts.as_mut() else {
                             return Err(PublishError::NoIpts);
                         };
+                        let ipt_lids = ipts.ipts.iter().map(|ipt| ipt.lid).collect_vec();
 
                         let hsdesc = {
                             trace!(
@@ -1269,6 +1503,7 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
 
                             build_sign(
                                 &imm.keymgr,
+                                &imm.subcredentials,
                                 &config,
                                 ipts,
                                 time_period,
@@ -1289,8 +1524,9 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
                             .into());
                         }
 
-                        hsdesc
+                        (hsdesc, ipt_lids)
                     };
+                    let (hsdesc, ipt_lids) = hsdesc;
 
                     let VersionedDescriptor {
                         desc,
@@ -1327,6 +1563,18 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
                         res = run_upload(desc.clone()).fuse() => res,
                     };
 
+                    if upload_res == UploadStatus::Success
+                        && tor_events::event_has_subscribers(TorEventKind::HsDescriptorUploaded)
+                    {
+                        if let Some(hsid) = crate::onion_name(&imm.keymgr, &imm.nickname) {
+                            tor_events::broadcast(TorEvent::HsDescriptorUploaded {
+                                service: tor_events::events::scrubbed(hsid),
+                                hsdir: relay_ids.display_relay_ids().to_string(),
+                                revision: revision_counter.into(),
+                            });
+                        }
+                    }
+
                     // Note: UploadStatus::Failure is only returned when
                     // upload_descriptor_with_retries fails, i.e. if all our retry
                     // attempts have failed
@@ -1334,6 +1582,7 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
                         relay_ids,
                         upload_res,
                         revision_counter,
+                        ipts: ipt_lids,
                     })
                 }
             })
@@ -1646,6 +1895,8 @@ struct HsDirUploadStatus {
     upload_res: UploadStatus,
     /// The revision counter of the descriptor we tried to upload.
     revision_counter: RevisionCounter,
+    /// The introduction points listed in the descriptor we tried to upload.
+    ipts: Vec<IptLocalId>,
 }
 
 /// The outcome of uploading a descriptor.
@@ -0,0 +1,364 @@
+//! Record of which HsDirs have our descriptor
+//!
+//! The publisher notes the outcome of every upload here.
+//! We use this to report the status of each HsDir,
+//! and we save the successful uploads to disk,
+//! so that after a restart we need not upload the descriptor again
+//! to HsDirs that already have an up-to-date copy.
+//!
+//! An HsDir's copy of the descriptor is up to date
+//! if it lists the same introduction points that we want to publish now,
+//! and if it is not yet due to be reuploaded
+//! (see [`RESTORED_UPLOAD_FRESHNESS`]).
+
+use crate::internal_prelude::*;
+use crate::status::DescriptorUploadStatus;
+
+/// Handle for a suitable persistent storage manager
+pub(crate) type UploadRecordStorageHandle = crate::storage::StorageHandle<StateRecord>;
+
+/// An [`UploadRecord`], shared between the publisher and the service's status API
+pub(crate) type SharedUploadRecord = Arc<Mutex<UploadRecord>>;
+
+/// How long after a successful upload can we rely on it, after a restart?
+///
+/// We reupload the descriptor to each HsDir at a random time between 60 and 120 minutes
+/// after the previous upload.  We use the lower bound here,
+/// so that restarting never delays a reupload.
+const RESTORED_UPLOAD_FRESHNESS: Duration = Duration::from_secs(60 * 60);
+
+/// Record of the uploads to each HsDir, for each relevant time period
+pub(crate) struct UploadRecord {
+    /// The HsDirs we have tried to upload to, by time period
+    periods: Vec<(TimePeriod, HashMap<RelayIds, HsDirState>)>,
+
+    /// The on-disk state storage handle.
+    storage: UploadRecordStorageHandle,
+}
+
+/// What we know about the descriptor we uploaded to one HsDir
+#[derive(Clone, Debug, Default)]
+struct HsDirState {
+    /// When we last uploaded the descriptor successfully, in this run of the service
+    last_success: Option<Instant>,
+
+    /// The number of uploads that have failed since the last successful one
+    consecutive_failures: u32,
+
+    /// The HsDir has a descriptor listing these IPTs, which it is not yet due to replace
+    ///
+    /// The IPTs are sorted.
+    fresh: Option<FreshUpload>,
+}
+
+/// A descriptor that an HsDir has, and that we don't yet need to replace
+#[derive(Clone, Debug)]
+struct FreshUpload {
+    /// The introduction points listed in the descriptor (sorted)
+    ipts: Vec<IptLocalId>,
+    /// When we should no longer rely on this upload, after a restart
+    until: Instant,
+}
+
+impl UploadRecord {
+    /// Note the outcome of an upload to `hsdir` for time period `period`
+    ///
+    /// `ipts` are the introduction points listed in the descriptor we uploaded.
+    /// If `success` is true, the record is saved to disk.
+    pub(crate) fn note_upload(
+        &mut self,
+        runtime: &impl SleepProvider,
+        period: TimePeriod,
+        hsdir: &RelayIds,
+        mut ipts: Vec<IptLocalId>,
+        success: bool,
+    ) {
+        let now = runtime.now();
+        let state = self.hsdir_state(period, hsdir);
+        if success {
+            ipts.sort();
+            state.last_success = Some(now);
+            state.consecutive_failures = 0;
+            state.fresh = Some(FreshUpload {
+                ipts,
+                until: now + RESTORED_UPLOAD_FRESHNESS,
+            });
+            self.save_or_warn(runtime);
+        } else {
+            state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        }
+    }
+
+    /// If `hsdir` has an up-to-date descriptor for time period `period`,
+    /// listing exactly `ipts`, return the time when we should next reupload to it
+    pub(crate) fn fresh_until(
+        &self,
+        now: Instant,
+        period: TimePeriod,
+        hsdir: &RelayIds,
+        ipts: &[IptLocalId],
+    ) -> Option<Instant> {
+        let mut ipts = ipts.to_vec();
+        ipts.sort();
+        self.periods
+            .iter()
+            .filter(|(p, _)| *p == period)
+            .filter_map(|(_, hsdirs)| hsdirs.get(hsdir)?.fresh.as_ref())
+            .find(|fresh| fresh.until > now && fresh.ipts == ipts)
+            .map(|fresh| fresh.until)
+    }
+
+    /// Forget about any time periods other than `relevant`
+    pub(crate) fn retain_periods(&mut self, relevant: &[TimePeriod]) {
+        self.periods.retain(|(p, _)| relevant.contains(p));
+    }
+
+    /// Return the status of every HsDir we have tried to upload to
+    pub(crate) fn statuses(&self) -> Vec<DescriptorUploadStatus> {
+        self.periods
+            .iter()
+            .flat_map(|(period, hsdirs)| {
+                hsdirs.iter().map(|(hsdir, state)| DescriptorUploadStatus {
+                    time_period: *period,
+                    hsdir: hsdir.clone(),
+                    last_success: state.last_success,
+                    consecutive_failures: state.consecutive_failures,
+                })
+            })
+            .collect()
+    }
+
+    /// Return the state for `hsdir` in time period `period`, creating it if necessary
+    fn hsdir_state(&mut self, period: TimePeriod, hsdir: &RelayIds) -> &mut HsDirState {
+        let index = match self.periods.iter().position(|(p, _)| *p == period) {
+            Some(index) => index,
+            None => {
+                self.periods.push((period, HashMap::new()));
+                self.periods.len() - 1
+            }
+        };
+        self.periods[index].1.entry(hsdir.clone()).or_default()
+    }
+
+    /// Save the fresh uploads to the persistent state, logging any error
+    ///
+    /// Failing to save is not fatal: it only means that after a restart,
+    /// we may upload our descriptor to some HsDirs unnecessarily.
+    fn save_or_warn(&mut self, runtime: &impl SleepProvider) {
+        if let Err(e) = self.save(runtime) {
+            warn_report!(e, "failed to save record of descriptor uploads");
+        }
+    }
+
+    /// Save the fresh uploads to the persistent state
+    fn save(&mut self, runtime: &impl SleepProvider) -> Result<(), tor_persist::Error> {
+        let tstoring = time_store::Storing::start(runtime);
+        let now = runtime.now();
+
+        let storing = &tstoring;
+        let mut uploads = self
+            .periods
+            .iter()
+            .flat_map(|(period, hsdirs)| {
+                hsdirs.iter().filter_map(move |(hsdir, state)| {
+                    let fresh = state.fresh.as_ref().filter(|f| f.until > now)?;
+                    Some(UploadRecordEntry {
+                        period: (*period).into(),
+                        hsdir: hsdir.clone(),
+                        ipts: fresh.ipts.clone(),
+                        until: storing.store_future(fresh.until),
+                    })
+                })
+            })
+            .collect_vec();
+        uploads.sort_by(|a, b| a.period.cmp(&b.period)); // normalise
+
+        let on_disk = StateRecord {
+            uploads,
+            stored: tstoring.store_ref(),
+        };
+        self.storage.store(&on_disk)
+    }
+
+    /// Load the record of fresh uploads from the persistent state
+    pub(crate) fn load(
+        storage: UploadRecordStorageHandle,
+        runtime: &impl SleepProvider,
+    ) -> Result<UploadRecord, StartupError> {
+        let on_disk = storage.load().map_err(StartupError::LoadState)?;
+        let mut record = UploadRecord {
+            periods: vec![],
+            storage,
+        };
+        if let Some(on_disk) = on_disk {
+            let StateRecord { uploads, stored } = on_disk;
+            let tloading = time_store::Loading::start(runtime, stored);
+            for upload in uploads {
+                let UploadRecordEntry {
+                    period,
+                    hsdir,
+                    ipts,
+                    until,
+                } = upload;
+                let until = tloading.load_future(until);
+                record.hsdir_state(period.into(), &hsdir).fresh = Some(FreshUpload { ipts, until });
+            }
+        }
+        Ok(record)
+    }
+}
+
+//---------- On disk data structures, done with serde ----------
+
+/// Record of descriptor uploads, as stored on disk
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct StateRecord {
+    /// Uploads that were fresh when we saved them
+    uploads: Vec<UploadRecordEntry>,
+    /// Reference time
+    stored: time_store::Reference,
+}
+
+/// Record of one upload, as stored on disk
+#[derive(Serialize, Deserialize, Debug)]
+struct UploadRecordEntry {
+    /// The time period of the descriptor
+    period: TimePeriodRecord,
+    /// The HsDir we uploaded it to
+    hsdir: RelayIds,
+    /// The introduction points listed in the descriptor (sorted)
+    ipts: Vec<IptLocalId>,
+    /// Until when we can rely on this upload
+    until: time_store::FutureTimestamp,
+}
+
+/// A [`TimePeriod`], as stored on disk
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+struct TimePeriodRecord {
+    /// The length of the time period, in minutes
+    length: u32,
+    /// The index of the time period
+    interval_num: u64,
+    /// The offset of the time period epoch from the Unix epoch, in seconds
+    epoch_offset: u32,
+}
+
+impl From<TimePeriod> for TimePeriodRecord {
+    fn from(period: TimePeriod) -> Self {
+        TimePeriodRecord {
+            length: period.length().as_minutes(),
+            interval_num: period.interval_num(),
+            epoch_offset: period.epoch_offset_in_sec(),
+        }
+    }
+}
+
+impl From<TimePeriodRecord> for TimePeriod {
+    fn from(record: TimePeriodRecord) -> Self {
+        TimePeriod::from_parts(record.length, record.interval_num, record.epoch_offset)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use crate::storage::StorageHandle;
+    use crate::test::mk_state_instance;
+    use test_temp_dir::test_temp_dir;
+    use tor_rtmock::MockRuntime;
+
+    fn hsdir(n: u8) -> RelayIds {
+        RelayIds::builder()
+            .rsa_identity([n; 20].into())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn persist() {
+        // We don't bother with MockRuntime::test_with_various
+        // since this test doesn't depend much on the scheduling of tasks.
+        let runtime = MockRuntime::new();
+        let temp_dir_owned = test_temp_dir!();
+        let temp_dir = temp_dir_owned.as_path_untracked();
+
+        runtime.clone().block_on(async move {
+            let period = TimePeriod::from_parts(1440, 19000, 43200);
+            let other_period = TimePeriod::from_parts(1440, 19001, 43200);
+            let lid = |n: u8| IptLocalId::from([n; 32]);
+
+            let instance = mk_state_instance(temp_dir, "allium");
+            let storage = || -> UploadRecordStorageHandle {
+                StorageHandle::Persistent(instance.storage_handle("descpub").unwrap())
+            };
+
+            let mut record = UploadRecord::load(storage(), &runtime).unwrap();
+            assert!(record.statuses().is_empty());
+
+            record.note_upload(&runtime, period, &hsdir(1), vec![lid(2), lid(1)], true);
+            record.note_upload(&runtime, period, &hsdir(2), vec![lid(1), lid(2)], false);
+            record.note_upload(&runtime, other_period, &hsdir(1), vec![lid(3)], true);
+
+            let now = runtime.now();
+            assert!(record
+                .fresh_until(now, period, &hsdir(1), &[lid(1), lid(2)])
+                .is_some());
+            assert!(record
+                .fresh_until(now, period, &hsdir(1), &[lid(1)])
+                .is_none());
+            assert!(record
+                .fresh_until(now, period, &hsdir(2), &[lid(1), lid(2)])
+                .is_none());
+            assert!(record
+                .fresh_until(now, period, &hsdir(3), &[lid(1), lid(2)])
+                .is_none());
+
+            let failed = record
+                .statuses()
+                .into_iter()
+                .find(|s| s.hsdir == hsdir(2))
+                .unwrap();
+            assert_eq!(failed.consecutive_failures, 1);
+            assert_eq!(failed.last_success, None);
+
+            // Reload, as if we had restarted.
+            runtime.advance_by(Duration::from_secs(10 * 60)).await;
+            let mut record = UploadRecord::load(storage(), &runtime).unwrap();
+            let now = runtime.now();
+            assert!(record
+                .fresh_until(now, period, &hsdir(1), &[lid(2), lid(1)])
+                .is_some());
+            assert!(record
+                .fresh_until(now, other_period, &hsdir(1), &[lid(3)])
+                .is_some());
+            assert!(record
+                .fresh_until(now, period, &hsdir(2), &[lid(1), lid(2)])
+                .is_none());
+
+            record.retain_periods(&[period]);
+            assert!(record
+                .fresh_until(now, other_period, &hsdir(1), &[lid(3)])
+                .is_none());
+
+            // Eventually we need to reupload anyway.
+            runtime.advance_by(RESTORED_UPLOAD_FRESHNESS).await;
+            let record = UploadRecord::load(storage(), &runtime).unwrap();
+            assert!(record
+                .fresh_until(runtime.now(), period, &hsdir(1), &[lid(1), lid(2)])
+                .is_none());
+        });
+    }
+}
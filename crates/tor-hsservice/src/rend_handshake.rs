@@ -5,7 +5,7 @@ use super::*;
 // These imports just here, because they have names unsuitable for importing widely.
 use tor_cell::relaycell::{
     hs::intro_payload::{IntroduceHandshakePayload, OnionKey},
-    msg::{Introduce2, Rendezvous1},
+    msg::{End, EndReason, Introduce2, Rendezvous1},
 };
 use tor_linkspec::{decode::Strictness, verbatim::VerbatimLinkSpecCircTarget};
 use tor_proto::{
@@ -192,6 +192,27 @@ pub(crate) struct RequestFilter {
     // value of the setting every time.  Instead, we currently only copy this
     // setting when an intro request is accepted.
     pub(crate) max_concurrent_streams: usize,
+    /// If true, we close the circuit when a client asks for more than
+    /// `max_concurrent_streams` streams; otherwise we only refuse the stream.
+    pub(crate) close_circuit_on_excess_streams: bool,
+}
+impl RequestFilter {
+    /// Decide what to do with a new stream request, on a circuit that already
+    /// has `n_open_streams` streams open.
+    fn disposition_for(
+        &self,
+        n_open_streams: usize,
+    ) -> tor_proto::stream::IncomingStreamRequestDisposition {
+        use tor_proto::stream::IncomingStreamRequestDisposition as D;
+        if n_open_streams < self.max_concurrent_streams {
+            D::Accept
+        } else if self.close_circuit_on_excess_streams {
+            D::CloseCircuit
+        } else {
+            // Like C Tor, we say DONE, so that this refusal looks like any other.
+            D::RejectRequest(End::new_with_reason(EndReason::DONE))
+        }
+    }
 }
 impl IncomingStreamRequestFilter for RequestFilter {
     fn disposition(
@@ -199,13 +220,7 @@ impl IncomingStreamRequestFilter for RequestFilter {
         _ctx: &tor_proto::stream::IncomingStreamRequestContext<'_>,
         circ: &tor_proto::circuit::ClientCircSyncView<'_>,
     ) -> tor_proto::Result<tor_proto::stream::IncomingStreamRequestDisposition> {
-        if circ.n_open_streams() >= self.max_concurrent_streams {
-            // TODO: We may want to have a way to send back an END message as
-            // well and not tear down the circuit.
-            Ok(tor_proto::stream::IncomingStreamRequestDisposition::CloseCircuit)
-        } else {
-            Ok(tor_proto::stream::IncomingStreamRequestDisposition::Accept)
-        }
+        Ok(self.disposition_for(circ.n_open_streams()))
     }
 }
 
@@ -278,7 +293,7 @@ impl IntroRequest {
         // Find a netdir.  Note that we _won't_ try to wait or retry if the
         // netdir isn't there: we probably can't answer this user's request.
         let netdir = provider
-            .netdir(tor_netdir::Timeliness::Timely)
+            .netdir_for(tor_netdir::DirUsage::OnionServices)
             .map_err(E::NetdirUnavailable)?;
 
         // Try to construct a CircTarget for rendezvous point based on the
@@ -385,3 +400,43 @@ impl IntroRequest {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use tor_proto::stream::IncomingStreamRequestDisposition as D;
+
+    #[test]
+    fn request_filter() {
+        let mut filter = RequestFilter {
+            max_concurrent_streams: 2,
+            close_circuit_on_excess_streams: false,
+        };
+        assert!(matches!(filter.disposition_for(0), D::Accept));
+        assert!(matches!(filter.disposition_for(1), D::Accept));
+        for n in [2, 3] {
+            match filter.disposition_for(n) {
+                D::RejectRequest(end) => assert_eq!(end.reason(), EndReason::DONE),
+                other => panic!("{:?}", other),
+            }
+        }
+
+        filter.close_circuit_on_excess_streams = true;
+        assert!(matches!(filter.disposition_for(1), D::Accept));
+        assert!(matches!(filter.disposition_for(2), D::CloseCircuit));
+        assert!(matches!(filter.disposition_for(3), D::CloseCircuit));
+    }
+}
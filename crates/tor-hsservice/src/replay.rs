@@ -15,6 +15,7 @@
 use crate::internal_prelude::*;
 
 use hash::{hash, H, HASH_LEN};
+use tor_persist::atomic_write::{self, Durability};
 
 // This has rather a generic name.
 use tor_cell::relaycell::msg::Introduce2;
@@ -69,7 +70,6 @@ const REPLAY_LOG_SUFFIX: &str = ".bin";
 
 impl ReplayLog {
     /// Create a new ReplayLog not backed by any data storage.
-    #[allow(dead_code)] // TODO #1186 Remove once something uses ReplayLog.
     pub(crate) fn new_ephemeral() -> Self {
         Self {
             seen: data::Filter::new(),
@@ -94,9 +94,16 @@ impl ReplayLog {
         lid: &IptLocalId,
     ) -> Result<Self, CreateIptError> {
         let leaf = format!("{lid}{REPLAY_LOG_SUFFIX}");
-        let path = dir.as_path().join(leaf);
+        let path = dir.as_path().join(&leaf);
         let lock_guard = dir.raw_lock_guard();
 
+        // Create a new log atomically, so that a crash can't leave behind
+        // one that has only part of the magic string (which we would reject).
+        if !path.exists() {
+            atomic_write::write_and_replace(dir, &leaf, MAGIC, Durability::Synced)
+                .map_err(CreateIptError::CreateReplayLog)?;
+        }
+
         Self::new_logged_inner(&path, lock_guard).map_err(|error| CreateIptError::OpenReplayLog {
             file: path,
             error: error.into(),
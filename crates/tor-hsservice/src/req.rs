@@ -5,6 +5,8 @@
 
 use crate::internal_prelude::*;
 
+use std::sync::atomic::{AtomicU32, Ordering};
+
 use tor_cell::relaycell::msg::{Connected, End, Introduce2};
 use tor_hscrypto::Subcredential;
 use tor_proto::stream::{IncomingStream, IncomingStreamRequest};
@@ -57,6 +59,37 @@ pub struct StreamRequest {
 
     /// The circuit that made this request.
     on_circuit: Arc<ClientCirc>,
+
+    /// The pseudonymous identifier for `on_circuit`.
+    circuit_id: RendCircuitId,
+}
+
+/// A pseudonymous identifier for the rendezvous circuit that a
+/// [`StreamRequest`] arrived on.
+///
+/// All the streams that a client opens on one rendezvous circuit have the
+/// same `RendCircuitId`, and streams on different circuits have different
+/// ones (until the 32-bit counter wraps around).  The identifier tells you
+/// nothing else about the client: it is just a counter, local to this process.
+///
+/// This is meant to let backends tell clients apart, for example to rate-limit
+/// them.  It corresponds to the global circuit ID that C Tor exports with
+/// `HiddenServiceExportCircuitID`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, derive_more::Display)]
+pub struct RendCircuitId(u32);
+
+impl RendCircuitId {
+    /// Allocate a new `RendCircuitId`, different from the recently allocated ones.
+    fn next() -> Self {
+        /// The next identifier to hand out.
+        static NEXT: AtomicU32 = AtomicU32::new(1);
+        RendCircuitId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Return this identifier as a number.
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
 }
 
 /// Keys and objects needed to answer a RendRequest.
@@ -67,6 +100,9 @@ pub(crate) struct RendRequestContext {
     /// The key manager, used for looking up subcredentials.
     pub(crate) keymgr: Arc<KeyMgr>,
 
+    /// The service's cache of subcredentials.
+    pub(crate) subcredentials: Arc<SubcredentialCache>,
+
     /// Key we'll use to decrypt the rendezvous request.
     pub(crate) kp_hss_ntor: Arc<HsSvcNtorKeypair>,
 
@@ -129,7 +165,10 @@ impl RendRequestContext {
 
         Ok(blind_id_kps
             .iter()
-            .map(|(blind_id_key, period)| hsid.compute_subcredential(&blind_id_key.into(), *period))
+            .map(|(blind_id_key, period)| {
+                self.subcredentials
+                    .compute_subcredential(&hsid, &blind_id_key.into(), *period)
+            })
             .collect())
     }
 
@@ -226,6 +265,8 @@ impl RendRequest {
             .await
             .map_err(ClientError::EstablishSession)?;
 
+        let circuit_id = RendCircuitId::next();
+
         // Note that we move circuit (which is an Arc<ClientCirc>) into this
         // closure, which lives for as long as the stream of StreamRequest, and
         // for as long as each individual StreamRequest.  This is how we keep
@@ -234,6 +275,7 @@ impl RendRequest {
         Ok(stream_requests.map(move |stream| StreamRequest {
             stream,
             on_circuit: circuit.clone(),
+            circuit_id,
         }))
     }
 
@@ -257,6 +299,12 @@ impl StreamRequest {
         self.stream.request()
     }
 
+    /// Return the pseudonymous identifier of the rendezvous circuit that this
+    /// request arrived on.
+    pub fn rend_circuit_id(&self) -> RendCircuitId {
+        self.circuit_id
+    }
+
     /// Accept this request and send the client a `CONNECTED` message.
     pub async fn accept(self, connected_message: Connected) -> Result<DataStream, ClientError> {
         self.stream
@@ -202,6 +202,50 @@ impl OnionServiceStatus {
     }
 }
 
+/// The status of our descriptor on a single HsDir.
+///
+/// Returned by
+/// [`RunningOnionService::descriptor_upload_status`](crate::RunningOnionService::descriptor_upload_status).
+#[derive(Debug, Clone)]
+pub struct DescriptorUploadStatus {
+    /// The time period of the descriptor.
+    pub(crate) time_period: TimePeriod,
+    /// The HsDir.
+    pub(crate) hsdir: RelayIds,
+    /// When we last uploaded the descriptor to this HsDir successfully.
+    pub(crate) last_success: Option<Instant>,
+    /// How many uploads to this HsDir have failed since the last successful one.
+    pub(crate) consecutive_failures: u32,
+}
+
+impl DescriptorUploadStatus {
+    /// Return the time period of the descriptor.
+    pub fn time_period(&self) -> TimePeriod {
+        self.time_period
+    }
+
+    /// Return the identities of the HsDir.
+    pub fn hsdir(&self) -> &RelayIds {
+        &self.hsdir
+    }
+
+    /// Return the time when we last uploaded the descriptor to this HsDir successfully.
+    ///
+    /// Returns `None` if we have not done so since the service was launched.
+    /// (Even so, the HsDir may have a copy of the descriptor from before a restart.)
+    pub fn last_success(&self) -> Option<Instant> {
+        self.last_success
+    }
+
+    /// Return the number of uploads to this HsDir that have failed since the last
+    /// successful one.
+    ///
+    /// Failed uploads are retried with an increasing delay.
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+}
+
 /// A stream of OnionServiceStatus events, returned by an onion service.
 ///
 /// Note that multiple status change events may be coalesced into one if the
@@ -0,0 +1,484 @@
+//! Storage for an onion service's non-key state, on disk or only in memory
+//!
+//! An ordinary onion service keeps its state in a [`StateDirectory`],
+//! so that it can pick up where it left off when it is restarted.
+//! An ephemeral service (see [`OnionServiceBuilder::ephemeral`](crate::OnionServiceBuilder::ephemeral))
+//! keeps all of its state in memory, and forgets it when it shuts down.
+//!
+//! The components of a service only *load* their state during startup.
+//! Afterwards, the authoritative copy is in their own in-memory data structures,
+//! and they *store* it just so that a later instance of the service can load it.
+//! So for an ephemeral service, there is never anything to load,
+//! and storing can simply discard the data.
+
+use crate::internal_prelude::*;
+use crate::ServiceArchiveError;
+
+use serde::de::DeserializeOwned;
+use tor_persist::slug::SlugRef;
+use tor_persist::state_dir::{
+    self, InstancePurgeHandler, InstancePurgeInfo, InstanceStateHandle, Liveness, StateArchive,
+};
+
+/// Where a service keeps its state.
+///
+/// Either an instance in a state directory, or nowhere at all.
+#[derive(Debug)]
+pub(crate) enum ServiceStateHandle {
+    /// The state is kept persistently in this instance directory.
+    Persistent(InstanceStateHandle),
+    /// The state is kept only in memory.
+    Ephemeral,
+}
+
+impl ServiceStateHandle {
+    /// Obtain a [`StorageHandle`] for the state named `key`
+    pub(crate) fn storage_handle<T>(
+        &self,
+        key: &str,
+    ) -> Result<StorageHandle<T>, tor_persist::Error> {
+        Ok(match self {
+            Self::Persistent(instance) => StorageHandle::Persistent(instance.storage_handle(key)?),
+            Self::Ephemeral => StorageHandle::Ephemeral,
+        })
+    }
+
+    /// Obtain the raw subdirectory `key`, if this service has a state directory
+    pub(crate) fn raw_subdir(
+        &self,
+        key: &str,
+    ) -> Result<Option<InstanceRawSubdir>, tor_persist::Error> {
+        Ok(match self {
+            Self::Persistent(instance) => Some(instance.raw_subdir(key)?),
+            Self::Ephemeral => None,
+        })
+    }
+}
+
+/// A place where we can load and store a serialisable `T`, or a sink that discards it
+///
+/// Like [`tor_persist::state_dir::StorageHandle`], which it (usually) wraps.
+#[derive(Debug)]
+pub(crate) enum StorageHandle<T> {
+    /// Backed by a file in the service's state directory.
+    Persistent(tor_persist::state_dir::StorageHandle<T>),
+    /// Not backed by anything: this is an ephemeral service.
+    Ephemeral,
+}
+
+impl<T> From<tor_persist::state_dir::StorageHandle<T>> for StorageHandle<T> {
+    fn from(handle: tor_persist::state_dir::StorageHandle<T>) -> Self {
+        StorageHandle::Persistent(handle)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> StorageHandle<T> {
+    /// Load the stored state
+    ///
+    /// An ephemeral service never has any stored state.
+    pub(crate) fn load(&self) -> Result<Option<T>, tor_persist::Error> {
+        match self {
+            Self::Persistent(h) => h.load(),
+            Self::Ephemeral => Ok(None),
+        }
+    }
+
+    /// Store `v`
+    ///
+    /// For an ephemeral service, this does nothing.
+    pub(crate) fn store(&mut self, v: &T) -> Result<(), tor_persist::Error> {
+        match self {
+            Self::Persistent(h) => h.store(v),
+            Self::Ephemeral => Ok(()),
+        }
+    }
+}
+
+/// Delete the stored state of onion services that are no longer configured.
+///
+/// Every service whose nickname is not in `configured`,
+/// and whose state has not been modified for at least `retain_for`,
+/// has its instance removed from `state_dir`.
+/// Services that are currently running are never affected.
+///
+/// This only deletes the non-key state (introduction points, replay logs, and so on).
+/// The service's keys are left in the keystore,
+/// so that the service keeps its `.onion` address if it is configured again.
+pub fn expire_unused_service_state<'n>(
+    state_dir: &StateDirectory,
+    configured: impl IntoIterator<Item = &'n HsNickname>,
+    retain_for: Duration,
+    now: SystemTime,
+) -> Result<(), tor_persist::Error> {
+    /// Purge handler that retains configured or recently used services.
+    struct Handler<'n> {
+        /// The nicknames of the services in the current configuration.
+        configured: HashSet<&'n str>,
+        /// How long to keep the state of a service after it was last used.
+        retain_for: Duration,
+    }
+
+    impl InstancePurgeHandler for Handler<'_> {
+        fn kind(&self) -> &'static str {
+            <HsNickname as state_dir::InstanceIdentity>::kind()
+        }
+
+        fn name_filter(&mut self, identity: &SlugRef) -> state_dir::Result<Liveness> {
+            Ok(if self.configured.contains(identity.as_str()) {
+                Liveness::Live
+            } else {
+                Liveness::PossiblyUnused
+            })
+        }
+
+        fn age_filter(
+            &mut self,
+            _identity: &SlugRef,
+            age: Duration,
+        ) -> state_dir::Result<Liveness> {
+            Ok(if age >= self.retain_for {
+                Liveness::PossiblyUnused
+            } else {
+                Liveness::Live
+            })
+        }
+
+        fn dispose(
+            &mut self,
+            info: &InstancePurgeInfo,
+            handle: InstanceStateHandle,
+        ) -> state_dir::Result<()> {
+            info!(
+                "Deleting state of onion service {}, which is no longer configured",
+                info.identity()
+            );
+            handle.purge()
+        }
+    }
+
+    let mut handler = Handler {
+        configured: configured.into_iter().map(|n| n.as_ref()).collect(),
+        retain_for,
+    };
+    state_dir.purge_instances(now, &mut handler)
+}
+
+/// The name of the extra archive item containing a service's keys
+const KEYS_ARCHIVE_ITEM: &str = "keys";
+
+/// A key, as recorded in a service state archive
+#[derive(Debug, Serialize, Deserialize)]
+struct ArchivedKey {
+    /// The key's `ArtiPath`
+    path: String,
+    /// The key's type, as its `arti_extension`
+    key_type: String,
+    /// The key, in OpenSSH format
+    openssh: String,
+}
+
+/// Write the state of the onion service `nickname` to `out`, as an archive
+///
+/// If `keymgr` is supplied, the service's keys are included too.
+/// **The archive then contains the service's private keys, unencrypted**:
+/// anyone who obtains it can impersonate the service.
+///
+/// The service must not be running, since we need exclusive access to its state.
+///
+/// The archive can be restored, for example on another machine,
+/// with [`restore_service_state`].
+pub fn export_service_state(
+    state_dir: &StateDirectory,
+    keymgr: Option<&KeyMgr>,
+    nickname: &HsNickname,
+    out: impl Write,
+) -> Result<(), ServiceArchiveError> {
+    let keystore_err = |action| move |cause| ServiceArchiveError::Keystore { action, cause };
+
+    // Hold the lock until we're done, so the service can't start meanwhile.
+    let instance = state_dir.acquire_instance(nickname)?;
+
+    let keys = keymgr
+        .map(|keymgr| -> Result<_, ServiceArchiveError> {
+            let pat = tor_keymgr::KeyPathPattern::Arti(format!("hss/{nickname}/**"));
+            let mut seen = HashSet::new();
+            let mut keys = vec![];
+            for entry in keymgr
+                .list_matching(&pat)
+                .map_err(keystore_err("list keys"))?
+            {
+                let Some(path) = entry.key_path().arti() else {
+                    continue;
+                };
+                // Keys in earlier keystores shadow those in later ones.
+                if !seen.insert((path.clone(), entry.key_type().clone())) {
+                    continue;
+                }
+                // Certificates aren't OpenSSH keys, so we can't archive them;
+                // they can be made again from the keys that sign them.
+                if entry.key_type() == &tor_keymgr::KeyType::Ed25519TorCert {
+                    continue;
+                }
+                let Some(openssh) = keymgr
+                    .export_entry(&entry)
+                    .map_err(keystore_err("export key"))?
+                else {
+                    continue;
+                };
+                keys.push(ArchivedKey {
+                    path: path.to_string(),
+                    key_type: entry.key_type().arti_extension(),
+                    openssh: openssh.to_string(),
+                });
+            }
+            Ok(serde_json::to_vec(&keys).map_err(into_internal!("failed to encode keys"))?)
+        })
+        .transpose()?;
+
+    let extras = keys
+        .iter()
+        .map(|keys| (KEYS_ARCHIVE_ITEM, &keys[..]))
+        .collect_vec();
+    instance.export_archive(out, &extras)?;
+    Ok(())
+}
+
+/// Restore the state of the onion service `nickname` from an archive
+///
+/// The archive must have been made by [`export_service_state`] for a service
+/// with the same nickname, and the service must not have any state here yet.
+///
+/// If `keymgr` is supplied, and the archive contains keys,
+/// they are inserted into its default keystore,
+/// replacing any keys the service already had.
+/// Otherwise, keys in the archive are ignored.
+///
+/// The whole archive, including the keys, is checked before anything is written,
+/// and the service's state is then put in place all at once.
+/// If importing the keys fails after that, the restored state is removed again,
+/// so that the restore can be retried.
+pub fn restore_service_state(
+    state_dir: &StateDirectory,
+    keymgr: Option<&KeyMgr>,
+    nickname: &HsNickname,
+    input: impl Read,
+) -> Result<(), ServiceArchiveError> {
+    let archive = state_dir.read_archive(nickname, input)?;
+    let keys = match keymgr {
+        Some(keymgr) => archived_keys(keymgr, nickname, &archive)?,
+        None => vec![],
+    };
+
+    // Hold the lock until we're done, so the service can't start meanwhile.
+    let instance = state_dir.restore_archive(nickname, archive)?;
+
+    let Some(keymgr) = keymgr else {
+        return Ok(());
+    };
+    for (path, key_type, openssh) in &keys {
+        if let Err(cause) = keymgr.import_entry(path, key_type, openssh, KeystoreSelector::Default)
+        {
+            if let Err(e) = instance.purge() {
+                warn_report!(e, "Failed to remove partially restored state of {nickname}");
+            }
+            return Err(ServiceArchiveError::Keystore {
+                action: "import key",
+                cause,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Return the keys in `archive`, for the service `nickname`, checking that `keymgr` can import them
+fn archived_keys(
+    keymgr: &KeyMgr,
+    nickname: &HsNickname,
+    archive: &StateArchive,
+) -> Result<Vec<(tor_keymgr::ArtiPath, tor_keymgr::KeyType, String)>, ServiceArchiveError> {
+    let Some((_, keys)) = archive
+        .extras()
+        .find(|(name, _)| *name == KEYS_ARCHIVE_ITEM)
+    else {
+        return Ok(vec![]);
+    };
+
+    let bad_keys = |msg: String| ServiceArchiveError::BadKeys(msg);
+    let keys: Vec<ArchivedKey> =
+        serde_json::from_slice(keys).map_err(|e| bad_keys(e.report().to_string()))?;
+    let prefix = format!("hss/{nickname}/");
+    keys.into_iter()
+        .map(|key| {
+            if !key.path.starts_with(&prefix) {
+                return Err(bad_keys(format!(
+                    "key {:?} is for another service",
+                    key.path
+                )));
+            }
+            let path = tor_keymgr::ArtiPath::new(key.path)
+                .map_err(|e| bad_keys(e.report().to_string()))?;
+            let key_type = tor_keymgr::KeyType::from(key.key_type.as_str());
+            keymgr
+                .check_import_entry(&path, &key_type, &key.openssh, KeystoreSelector::Default)
+                .map_err(|cause| ServiceArchiveError::Keystore {
+                    action: "check key",
+                    cause,
+                })?;
+            Ok((path, key_type, key.openssh))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use crate::test::mk_state_instance;
+    use test_temp_dir::test_temp_dir;
+
+    #[test]
+    fn ephemeral() {
+        let state = ServiceStateHandle::Ephemeral;
+        let mut h = state.storage_handle::<u32>("thing").unwrap();
+        assert_eq!(h.load().unwrap(), None);
+        h.store(&42).unwrap();
+        assert_eq!(h.load().unwrap(), None);
+        assert!(state.raw_subdir("things").unwrap().is_none());
+    }
+
+    #[test]
+    fn persistent() {
+        test_temp_dir!().used_by(|dir| {
+            let state = ServiceStateHandle::Persistent(mk_state_instance(dir, "allium"));
+            let mut h = state.storage_handle::<u32>("thing").unwrap();
+            assert_eq!(h.load().unwrap(), None);
+            h.store(&42).unwrap();
+            assert_eq!(h.load().unwrap(), Some(42));
+            assert!(state.raw_subdir("things").unwrap().is_some());
+        });
+    }
+
+    #[test]
+    fn expire() {
+        test_temp_dir!().used_by(|dir| {
+            let mistrust = fs_mistrust::Mistrust::new_dangerously_trust_everyone();
+            let state_dir = StateDirectory::new(dir, &mistrust).unwrap();
+            let nick = |s: &str| HsNickname::new(s.to_string()).unwrap();
+            for n in ["allium", "cepa", "porrum"] {
+                let instance = state_dir.acquire_instance(&nick(n)).unwrap();
+                instance
+                    .storage_handle::<u32>("thing")
+                    .unwrap()
+                    .store(&7)
+                    .unwrap();
+            }
+            let running = state_dir.acquire_instance(&nick("porrum")).unwrap();
+            let list = || {
+                let mut l = state_dir
+                    .list_instances::<HsNickname>()
+                    .map(|s| s.unwrap().to_string())
+                    .collect::<Vec<_>>();
+                l.sort();
+                l
+            };
+            let retain = Duration::from_secs(86400);
+            let configured = [nick("allium")];
+
+            // Recently used: nothing is deleted yet.
+            expire_unused_service_state(&state_dir, &configured, retain, SystemTime::now())
+                .unwrap();
+            assert_eq!(list(), ["allium", "cepa", "porrum"]);
+
+            // Much later, unconfigured services are deleted, unless they are running.
+            let later = SystemTime::now() + retain * 2;
+            expire_unused_service_state(&state_dir, &configured, retain, later).unwrap();
+            assert_eq!(list(), ["allium", "porrum"]);
+            drop(running);
+        });
+    }
+
+    #[test]
+    fn archive() {
+        use tor_basic_utils::test_rng::testing_rng;
+        use tor_keymgr::{ArtiEphemeralKeystore, KeyMgrBuilder};
+
+        let mk_keymgr = || {
+            KeyMgrBuilder::default()
+                .default_store(Box::new(ArtiEphemeralKeystore::new("eph".into())))
+                .build()
+                .unwrap()
+        };
+
+        test_temp_dir!().used_by(|dir| {
+            let mistrust = fs_mistrust::Mistrust::new_dangerously_trust_everyone();
+            let src = StateDirectory::new(dir.join("src"), &mistrust).unwrap();
+            let dst = StateDirectory::new(dir.join("dst"), &mistrust).unwrap();
+            let nick = HsNickname::new("allium".to_string()).unwrap();
+            let hsid_spec = crate::HsIdKeypairSpecifier::new(nick.clone());
+
+            let src_keymgr = mk_keymgr();
+            let hsid: HsIdKeypair = src_keymgr
+                .generate(
+                    &hsid_spec,
+                    KeystoreSelector::Default,
+                    &mut testing_rng(),
+                    false,
+                )
+                .unwrap();
+            src.acquire_instance(&nick)
+                .unwrap()
+                .storage_handle::<u32>("thing")
+                .unwrap()
+                .store(&42)
+                .unwrap();
+
+            let mut archive = vec![];
+            export_service_state(&src, Some(&src_keymgr), &nick, &mut archive).unwrap();
+
+            let dst_keymgr = mk_keymgr();
+            restore_service_state(&dst, Some(&dst_keymgr), &nick, &archive[..]).unwrap();
+
+            let restored: HsIdKeypair = dst_keymgr.get(&hsid_spec).unwrap().unwrap();
+            let onion = |kp: &HsIdKeypair| HsId::from(HsIdKey::from(kp));
+            assert_eq!(onion(&restored), onion(&hsid));
+            let instance = dst.acquire_instance(&nick).unwrap();
+            let h = instance.storage_handle::<u32>("thing").unwrap();
+            assert_eq!(h.load().unwrap(), Some(42));
+
+            // A running service can't be exported.
+            let running = src.acquire_instance(&nick).unwrap();
+            assert!(export_service_state(&src, None, &nick, io::sink()).is_err());
+
+            // An archive with a bad key is rejected without restoring any state.
+            let keys = serde_json::to_vec(&[ArchivedKey {
+                path: "hss/allium/ks_hs_id".into(),
+                key_type: "ed25519_expanded_private".into(),
+                openssh: "not a key".into(),
+            }])
+            .unwrap();
+            let mut archive = vec![];
+            running
+                .export_archive(&mut archive, &[(KEYS_ARCHIVE_ITEM, &keys[..])])
+                .unwrap();
+            drop(running);
+            let dst = StateDirectory::new(dir.join("dst2"), &mistrust).unwrap();
+            let e = restore_service_state(&dst, Some(&mk_keymgr()), &nick, &archive[..]);
+            assert!(matches!(e, Err(ServiceArchiveError::Keystore { .. })));
+            let instance = dst.acquire_instance(&nick).unwrap();
+            let h = instance.storage_handle::<u32>("thing").unwrap();
+            assert_eq!(h.load().unwrap(), None);
+        });
+    }
+}
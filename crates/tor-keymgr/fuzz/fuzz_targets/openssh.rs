@@ -0,0 +1,20 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use tor_keymgr::test_utils::parse_openssh_key;
+use tor_keymgr::KeyType;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() > 0 {
+        let key_type = match data[0] % 5 {
+            0 => KeyType::Ed25519Keypair,
+            1 => KeyType::Ed25519PublicKey,
+            2 => KeyType::Ed25519ExpandedKeypair,
+            3 => KeyType::X25519StaticKeypair,
+            4 => KeyType::X25519PublicKey,
+            _ => panic!("uh oh, math broke"),
+        };
+        if let Ok(s) = std::str::from_utf8(&data[1..]) {
+            let _ = parse_openssh_key(s, &key_type);
+        }
+    }
+});
@@ -4,7 +4,11 @@ pub use tor_config::{CfgPath, CfgPathError, ConfigBuildError, ConfigurationSourc
 
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
-use tor_config::{impl_standard_builder, BoolOrAuto};
+use tor_config::{
+    define_list_builder_accessors, define_list_builder_helper, impl_standard_builder, BoolOrAuto,
+};
+
+use crate::KeystoreId;
 
 /// [`ArtiNativeKeystore`](crate::ArtiNativeKeystore) configuration
 #[derive(Debug, Clone, Builder, Eq, PartialEq, Serialize, Deserialize)]
@@ -17,10 +21,87 @@ pub struct ArtiNativeKeystoreConfig {
     #[builder_field_attr(serde(default))]
     #[builder(default)]
     enabled: BoolOrAuto,
+
+    /// Additional key stores, used alongside the primary key store.
+    ///
+    /// Keys are looked up in the primary key store first,
+    /// and then in each of these key stores, in order.
+    /// New keys are always written to the primary key store.
+    #[builder(sub_builder, setter(custom))]
+    #[builder_field_attr(serde(default))]
+    secondary: SecondaryKeystoreList,
+
+    /// A file containing the master seed from which keys are derived, if any.
+    ///
+    /// The file holds the 32-byte seed, hex-encoded.
+    /// If set, new onion service identity keys and client authorization keys
+    /// are derived from the seed instead of being generated at random,
+    /// so that backing up the seed is enough to recover them.
+    ///
+    /// Requires the `key-derivation` feature.
+    #[builder_field_attr(serde(default))]
+    #[builder(default)]
+    master_seed_file: Option<CfgPath>,
 }
 
 impl_standard_builder! { ArtiNativeKeystoreConfig }
 
+/// A list of secondary key stores (type alias for macrology).
+type SecondaryKeystoreList = Vec<SecondaryKeystoreConfig>;
+
+define_list_builder_helper! {
+    pub struct SecondaryKeystoreListBuilder {
+        stores: [SecondaryKeystoreConfigBuilder],
+    }
+    built: SecondaryKeystoreList = stores;
+    default = vec![];
+}
+
+define_list_builder_accessors! {
+    struct ArtiNativeKeystoreConfigBuilder {
+        pub secondary: [SecondaryKeystoreConfigBuilder],
+    }
+}
+
+/// Configuration for a secondary [`ArtiNativeKeystore`](crate::ArtiNativeKeystore).
+#[derive(Debug, Clone, Builder, Eq, PartialEq, Serialize, Deserialize, amplify::Getters)]
+#[builder(derive(Serialize, Deserialize, Debug))]
+#[builder(build_fn(validate = "Self::validate", error = "ConfigBuildError"))]
+#[non_exhaustive]
+#[builder_struct_attr(non_exhaustive)]
+pub struct SecondaryKeystoreConfig {
+    /// The identifier of this key store.
+    ///
+    /// Must be unique, and must not be `arti` (the identifier of the primary key store).
+    id: KeystoreId,
+
+    /// The directory containing the keys.
+    path: CfgPath,
+
+    /// Whether the key store is read-only.
+    ///
+    /// Read-only key stores are never modified, and their directory is never created.
+    #[builder(default)]
+    #[getter(as_copy)]
+    read_only: bool,
+}
+
+impl_standard_builder! { SecondaryKeystoreConfig: !Default }
+
+impl SecondaryKeystoreConfigBuilder {
+    /// Check that the key store configuration is valid
+    fn validate(&self) -> Result<(), ConfigBuildError> {
+        if matches!(&self.id, Some(id) if id.to_string() == "arti") {
+            return Err(ConfigBuildError::Invalid {
+                field: "id".into(),
+                problem: "the keystore ID \"arti\" is reserved for the primary keystore".into(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
 impl ArtiNativeKeystoreConfigBuilder {
     /// Check that the keystore configuration is valid
     #[cfg(not(feature = "keymgr"))]
@@ -36,13 +117,51 @@ impl ArtiNativeKeystoreConfigBuilder {
             });
         }
 
-        Ok(())
+        self.validate_secondary()?;
+        self.validate_master_seed()
     }
 
     /// Check that the keystore configuration is valid
     #[cfg(feature = "keymgr")]
     #[allow(clippy::unnecessary_wraps)]
     fn validate(&self) -> Result<(), ConfigBuildError> {
+        self.validate_secondary()?;
+        self.validate_master_seed()
+    }
+
+    /// Check that a master seed is only configured if keys can be derived from it
+    #[allow(clippy::unnecessary_wraps)]
+    fn validate_master_seed(&self) -> Result<(), ConfigBuildError> {
+        if cfg!(not(feature = "key-derivation")) && matches!(self.master_seed_file, Some(Some(_))) {
+            return Err(ConfigBuildError::NoCompileTimeSupport {
+                field: "master_seed_file".into(),
+                problem: "key-derivation feature not enabled".into(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Check that the secondary key stores have distinct IDs
+    fn validate_secondary(&self) -> Result<(), ConfigBuildError> {
+        use itertools::Itertools as _;
+
+        let Some(secondary) = self.opt_secondary() else {
+            return Ok(());
+        };
+
+        if let Some(id) = secondary
+            .iter()
+            .filter_map(|store| store.id.as_ref())
+            .duplicates()
+            .next()
+        {
+            return Err(ConfigBuildError::Invalid {
+                field: "secondary".into(),
+                problem: format!("multiple keystores with ID {id}"),
+            });
+        }
+
         Ok(())
     }
 }
@@ -54,4 +173,14 @@ impl ArtiNativeKeystoreConfig {
 
         self.enabled.as_bool().unwrap_or(default)
     }
+
+    /// The secondary key stores, in the order in which they should be searched.
+    pub fn secondary(&self) -> &[SecondaryKeystoreConfig] {
+        &self.secondary
+    }
+
+    /// The file containing the master seed from which keys are derived, if any.
+    pub fn master_seed_file(&self) -> Option<&CfgPath> {
+        self.master_seed_file.as_ref()
+    }
 }
@@ -0,0 +1,302 @@
+//! Deterministic derivation of keys from a master seed.
+//!
+//! A [`KeyMgr`](crate::KeyMgr) that has a [`MasterSeed`] can
+//! [derive](crate::KeyMgr::derive) keys instead of generating them at random.
+//! A derived key is a function of the seed, its derivation path, and its [`KeyType`],
+//! so the key store only needs to hold a [`KeyDerivationRecord`] for it;
+//! backing up the seed is enough to recover every derived key.
+//!
+//! The derivation is modelled on the hardened derivation of [SLIP-0010],
+//! except that each step is labelled by a component of an [`ArtiPath`]
+//! rather than by an integer index:
+//!
+//! ```text
+//! I = HMAC-SHA512(Key = "arti key derivation v1", Data = seed)
+//! for each label in [path components..., key type]:
+//!     I = HMAC-SHA512(Key = I[32..64], Data = 0x00 || I[0..32] || label)
+//! secret = I[0..32]
+//! ```
+//!
+//! where the key type label is the [`KeyType::arti_extension`] of the key.
+//! The 32-byte `secret` is then turned into a key of the requested type
+//! (see [`DerivableKey`]).
+//!
+//! [SLIP-0010]: https://github.com/satoshilabs/slips/blob/master/slip-0010.md
+
+use std::fmt;
+use std::result::Result as StdResult;
+use std::str::FromStr;
+
+use hmac::{Hmac, Mac};
+use tor_llcrypto::d::Sha512;
+use tor_llcrypto::pk::{curve25519, ed25519};
+use zeroize::Zeroizing;
+
+use crate::{ArtiPath, EncodableKey, ErasedKey, KeyDerivationRecord, KeyType, KeygenRng, Result};
+
+/// The length of a [`MasterSeed`], in bytes.
+pub const MASTER_SEED_LEN: usize = 32;
+
+/// The HMAC key used to compute the root of the derivation tree from a seed.
+const ROOT_HMAC_KEY: &[u8] = b"arti key derivation v1";
+
+/// A secret from which keys can be derived.
+///
+/// Anybody who has the seed can compute every key derived from it,
+/// so it must be protected (and backed up) as carefully as those keys.
+#[derive(Clone)]
+pub struct MasterSeed(Zeroizing<[u8; MASTER_SEED_LEN]>);
+
+impl MasterSeed {
+    /// Create a `MasterSeed` from its bytes.
+    pub fn from_bytes(bytes: [u8; MASTER_SEED_LEN]) -> Self {
+        Self(Zeroizing::new(bytes))
+    }
+
+    /// Generate a new random `MasterSeed`.
+    pub fn generate(rng: &mut dyn KeygenRng) -> Self {
+        let mut bytes = Zeroizing::new([0; MASTER_SEED_LEN]);
+        rng.fill_bytes(&mut bytes[..]);
+        Self(bytes)
+    }
+
+    /// Return the bytes of this seed (for example, in order to back it up).
+    pub fn as_bytes(&self) -> &[u8; MASTER_SEED_LEN] {
+        &self.0
+    }
+
+    /// Derive the secret for a key of type `key_type` with the derivation path `path`.
+    fn derive_secret(&self, path: &ArtiPath, key_type: &KeyType) -> Zeroizing<[u8; 32]> {
+        let key_type = key_type.arti_extension();
+        let labels = path
+            .as_ref()
+            .split(crate::arti_path::PATH_SEP)
+            .chain(std::iter::once(key_type.as_str()));
+
+        let mut node = Node::from_hmac(ROOT_HMAC_KEY, &[&self.0[..]]);
+        for label in labels {
+            node = Node::from_hmac(
+                &node.chain_code[..],
+                &[&[0], &node.secret[..], label.as_bytes()],
+            );
+        }
+        node.secret
+    }
+}
+
+impl FromStr for MasterSeed {
+    type Err = InvalidMasterSeed;
+
+    /// Parse a hex-encoded `MasterSeed`, ignoring any surrounding whitespace.
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        let mut bytes = Zeroizing::new([0; MASTER_SEED_LEN]);
+        hex::decode_to_slice(s.trim(), &mut bytes[..]).map_err(|_| InvalidMasterSeed)?;
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Debug for MasterSeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MasterSeed").finish_non_exhaustive()
+    }
+}
+
+/// An error returned when parsing a malformed [`MasterSeed`].
+///
+/// This deliberately does not describe the input, since it might be (most of) a seed.
+#[derive(thiserror::Error, Debug, Clone)]
+#[error("Invalid master seed: expected {MASTER_SEED_LEN} hex-encoded bytes")]
+#[non_exhaustive]
+pub struct InvalidMasterSeed;
+
+/// A node in the derivation tree.
+struct Node {
+    /// The secret of this node.
+    secret: Zeroizing<[u8; 32]>,
+    /// The key with which the children of this node are computed.
+    chain_code: Zeroizing<[u8; 32]>,
+}
+
+impl Node {
+    /// Compute a node from the HMAC-SHA512 of the concatenation of `data`, keyed with `key`.
+    fn from_hmac(key: &[u8], data: &[&[u8]]) -> Self {
+        let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("Hmac allows keys of any size");
+        for d in data {
+            mac.update(d);
+        }
+        let output = mac.finalize().into_bytes();
+
+        let mut secret = Zeroizing::new([0; 32]);
+        let mut chain_code = Zeroizing::new([0; 32]);
+        secret.copy_from_slice(&output[..32]);
+        chain_code.copy_from_slice(&output[32..]);
+        Self { secret, chain_code }
+    }
+}
+
+/// A key that can be derived from a [`MasterSeed`].
+pub trait DerivableKey: EncodableKey + Sized {
+    /// Construct a key of this type from 32 bytes of derived secret material.
+    fn from_derived_secret(secret: &[u8; 32]) -> Self;
+}
+
+impl DerivableKey for ed25519::Keypair {
+    fn from_derived_secret(secret: &[u8; 32]) -> Self {
+        ed25519::Keypair::from_bytes(secret)
+    }
+}
+
+impl DerivableKey for ed25519::ExpandedKeypair {
+    fn from_derived_secret(secret: &[u8; 32]) -> Self {
+        (&ed25519::Keypair::from_derived_secret(secret)).into()
+    }
+}
+
+impl DerivableKey for curve25519::StaticKeypair {
+    fn from_derived_secret(secret: &[u8; 32]) -> Self {
+        let secret = curve25519::StaticSecret::from(*secret);
+        let public = curve25519::PublicKey::from(&secret);
+
+        curve25519::StaticKeypair { secret, public }
+    }
+}
+
+/// Derive the key of type `K` described by `record` from `seed`.
+pub(crate) fn derive_key<K: DerivableKey>(seed: &MasterSeed, record: &KeyDerivationRecord) -> K {
+    K::from_derived_secret(&seed.derive_secret(record.path(), record.key_type()))
+}
+
+/// Derive the key described by `record` from `seed`, and return it type-erased.
+///
+/// Returns an error if keys of the type named in `record` cannot be derived.
+pub(crate) fn derive_erased(seed: &MasterSeed, record: &KeyDerivationRecord) -> Result<ErasedKey> {
+    /// Helper: derive a key of type `K`, and erase its type.
+    fn erased<K: DerivableKey>(seed: &MasterSeed, record: &KeyDerivationRecord) -> ErasedKey {
+        Box::new(derive_key::<K>(seed, record))
+    }
+
+    match record.key_type() {
+        KeyType::Ed25519Keypair => Ok(erased::<ed25519::Keypair>(seed, record)),
+        KeyType::Ed25519ExpandedKeypair => Ok(erased::<ed25519::ExpandedKeypair>(seed, record)),
+        KeyType::X25519StaticKeypair => Ok(erased::<curve25519::StaticKeypair>(seed, record)),
+        key_type => Err(crate::Error::NotDerivable {
+            path: record.path().clone(),
+            key_type: key_type.clone(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+    use tor_basic_utils::test_rng::testing_rng;
+
+    /// Return a record for a key of type `key_type` at `path`.
+    fn record(key_type: KeyType, path: &str) -> KeyDerivationRecord {
+        KeyDerivationRecord::new(key_type, ArtiPath::new(path.into()).unwrap())
+    }
+
+    #[test]
+    fn deterministic() {
+        let seed = MasterSeed::from_bytes([7; 32]);
+        let same_seed = MasterSeed::from_bytes([7; 32]);
+        let other_seed = MasterSeed::from_bytes([8; 32]);
+
+        let id = record(KeyType::Ed25519ExpandedKeypair, "hs/allium/ks_hs_id");
+        let k1: ed25519::ExpandedKeypair = derive_key(&seed, &id);
+        let k2: ed25519::ExpandedKeypair = derive_key(&same_seed, &id);
+        let k3: ed25519::ExpandedKeypair = derive_key(&other_seed, &id);
+        assert_eq!(k1.to_secret_key_bytes(), k2.to_secret_key_bytes());
+        assert_ne!(k1.to_secret_key_bytes(), k3.to_secret_key_bytes());
+
+        // Different paths, and different key types, give different keys.
+        let other_path = record(KeyType::Ed25519ExpandedKeypair, "hs/allium2/ks_hs_id");
+        let k4: ed25519::ExpandedKeypair = derive_key(&seed, &other_path);
+        assert_ne!(k1.to_secret_key_bytes(), k4.to_secret_key_bytes());
+        assert_ne!(
+            seed.derive_secret(id.path(), &KeyType::Ed25519ExpandedKeypair),
+            seed.derive_secret(id.path(), &KeyType::Ed25519Keypair),
+        );
+
+        // Moving a label across a path separator changes the key.
+        assert_ne!(
+            seed.derive_secret(&"a/bc".parse().unwrap(), &KeyType::X25519StaticKeypair),
+            seed.derive_secret(&"ab/c".parse().unwrap(), &KeyType::X25519StaticKeypair),
+        );
+
+        let auth = record(
+            KeyType::X25519StaticKeypair,
+            "client/allium/ks_hsc_desc_enc",
+        );
+        let kp: curve25519::StaticKeypair = derive_key(&seed, &auth);
+        assert_eq!(kp.public, curve25519::PublicKey::from(&kp.secret));
+    }
+
+    #[test]
+    fn erased() {
+        let seed = MasterSeed::generate(&mut testing_rng());
+        let id = record(KeyType::Ed25519Keypair, "hs/allium/ks_hs_id");
+        let key = derive_erased(&seed, &id).unwrap();
+        let key = key.downcast::<ed25519::Keypair>().unwrap();
+        assert_eq!(
+            key.to_bytes(),
+            derive_key::<ed25519::Keypair>(&seed, &id).to_bytes()
+        );
+
+        let cert = record(KeyType::Ed25519TorCert, "hs/allium/ks_hs_id");
+        assert!(matches!(
+            derive_erased(&seed, &cert),
+            Err(crate::Error::NotDerivable {
+                key_type: KeyType::Ed25519TorCert,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn record_encoding() {
+        let rec = record(
+            KeyType::X25519StaticKeypair,
+            "client/allium/ks_hsc_desc_enc",
+        );
+        let ssh = rec.as_ssh_key_data().unwrap();
+        assert_eq!(ssh.key_type().unwrap(), KeyType::KeyDerivationRecord);
+        let decoded = ssh
+            .into_erased()
+            .unwrap()
+            .downcast::<KeyDerivationRecord>()
+            .unwrap();
+        assert_eq!(*decoded, rec);
+    }
+
+    #[test]
+    fn parse() {
+        let hex = "42".repeat(MASTER_SEED_LEN);
+        let seed: MasterSeed = format!(" {hex}\n").parse().unwrap();
+        assert_eq!(seed.as_bytes(), &[0x42; MASTER_SEED_LEN]);
+
+        assert!("42".parse::<MasterSeed>().is_err());
+        assert!(format!("{hex}42").parse::<MasterSeed>().is_err());
+        assert!("zz".repeat(MASTER_SEED_LEN).parse::<MasterSeed>().is_err());
+    }
+
+    #[test]
+    fn debug_redacted() {
+        let seed = MasterSeed::from_bytes([0x42; 32]);
+        assert_eq!(format!("{seed:?}"), "MasterSeed { .. }");
+    }
+}
@@ -112,6 +112,17 @@ impl ArtiNativeKeystore {
     pub fn from_path_and_mistrust(_: impl AsRef<Path>, _: &Mistrust) -> Result<Self> {
         Ok(Self)
     }
+
+    /// Open an existing [`ArtiNativeKeystore`] in read-only mode.
+    #[allow(clippy::unnecessary_wraps)]
+    pub fn open_read_only(_: impl AsRef<Path>, _: &Mistrust) -> Result<Self> {
+        Ok(Self)
+    }
+
+    /// Set the ID of this [`ArtiNativeKeystore`].
+    pub fn with_id(self, _: crate::KeystoreId) -> Self {
+        self
+    }
 }
 
 impl Keystore for ArtiNativeKeystore {}
@@ -10,7 +10,7 @@ use std::fmt;
 use std::sync::Arc;
 
 use crate::ssh::SshKeyAlgorithm;
-use crate::KeyPathError;
+use crate::{ArtiPath, KeyPathError, KeyType, KeystoreId};
 
 /// An Error type for this crate.
 #[derive(thiserror::Error, Debug, Clone)]
@@ -35,10 +35,38 @@ pub enum Error {
     #[error("Key already exists")]
     KeyAlreadyExists,
 
+    /// Attempted to modify a read-only [`Keystore`](crate::Keystore).
+    #[error("Keystore {0} is read-only")]
+    ReadOnlyKeystore(KeystoreId),
+
+    /// Attempted to use a key that is derived from a master seed,
+    /// but no master seed is available.
+    ///
+    /// Either the [`KeyMgr`](crate::KeyMgr) has no master seed,
+    /// or this crate was built without the `key-derivation` feature.
+    #[error("Key {0} is derived from a master seed, but no master seed is available")]
+    NoMasterSeed(ArtiPath),
+
+    /// A key store says that a key is derived from a master seed,
+    /// but keys of its type cannot be derived.
+    #[cfg(feature = "key-derivation")]
+    #[error("Key {path} is recorded as derived, but keys of type {key_type:?} cannot be derived")]
+    NotDerivable {
+        /// The derivation path of the key.
+        path: ArtiPath,
+        /// The type of the key.
+        key_type: KeyType,
+    },
+
     /// Attempted to use an unsupported key.
     #[error("Unsupported key algorithm {0}")]
     UnsupportedKeyAlgorithm(SshKeyAlgorithm),
 
+    /// Attempted to use an OpenSSH encoding for an entry that isn't stored as an OpenSSH key
+    /// (such as a certificate).
+    #[error("Entries of type {0:?} are not OpenSSH keys")]
+    NotOpenSshKey(KeyType),
+
     /// An internal error.
     #[error("Internal error")]
     Bug(#[from] tor_error::Bug),
@@ -59,7 +87,12 @@ impl HasKind for Error {
             E::Keystore(e) => e.kind(),
             E::Corruption(_) => EK::KeystoreCorrupted,
             E::KeyAlreadyExists => EK::BadApiUsage, // TODO: not strictly right
+            E::ReadOnlyKeystore(_) => EK::BadApiUsage,
             E::UnsupportedKeyAlgorithm(_) => EK::BadApiUsage,
+            E::NotOpenSshKey(_) => EK::BadApiUsage,
+            E::NoMasterSeed(_) => EK::BadApiUsage,
+            #[cfg(feature = "key-derivation")]
+            E::NotDerivable { .. } => EK::KeystoreCorrupted,
             E::Bug(e) => e.kind(),
         }
     }
@@ -9,7 +9,7 @@ use std::str::FromStr;
 use derive_more::{Deref, DerefMut, Display, From, Into};
 use thiserror::Error;
 use tor_error::{internal, into_internal, Bug};
-use tor_hscrypto::pk::{HsId, HsIdParseError, HSID_ONION_SUFFIX};
+use tor_hscrypto::pk::{HsId, HsIdParseError};
 use tor_hscrypto::time::TimePeriod;
 use tor_persist::slug::Slug;
 
@@ -482,32 +482,16 @@ impl KeySpecifierComponent for HsId {
         // We can't implement KeySpecifierComponentViaDisplayFromStr for HsId,
         // because its Display impl contains the `.onion` suffix, and Slugs can't
         // contain `.`.
-        let hsid = self.to_string();
-        let hsid_slug = hsid
-            .strip_suffix(HSID_ONION_SUFFIX)
-            .ok_or_else(|| internal!("HsId Display impl missing .onion suffix?!"))?;
-        hsid_slug
-            .to_owned()
+        self.to_base32()
             .try_into()
-            .map_err(into_internal!("Display generated bad Slug"))
+            .map_err(into_internal!("HsId::to_base32 generated bad Slug"))
     }
 
     fn from_slug(s: &Slug) -> StdResult<Self, InvalidKeyPathComponentValue>
     where
         Self: Sized,
     {
-        // Note: HsId::from_str expects the string to have a .onion suffix,
-        // but the string representation of our slug doesn't have it
-        // (because we manually strip it away, see to_slug()).
-        //
-        // We have to manually add it for this to work.
-        //
-        // TODO: HsId should have some facilities for converting base32 HsIds (sans suffix)
-        // to and from string.
-        let onion = format!("{}{HSID_ONION_SUFFIX}", s.as_str());
-
-        onion
-            .parse()
+        HsId::from_base32(s.as_str())
             .map_err(|e: HsIdParseError| InvalidKeyPathComponentValue::Slug(e.to_string()))
     }
 
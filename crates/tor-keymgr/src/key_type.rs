@@ -42,7 +42,9 @@ use ssh_key::Algorithm;
 use thiserror::Error;
 use tor_error::internal;
 
-use crate::ssh::{ED25519_EXPANDED_ALGORITHM_NAME, X25519_ALGORITHM_NAME};
+use crate::ssh::{
+    ED25519_EXPANDED_ALGORITHM_NAME, KEY_DERIVATION_ALGORITHM_NAME, X25519_ALGORITHM_NAME,
+};
 use crate::Result;
 
 /// Declare and implement the `KeyType` enum.
@@ -132,6 +134,9 @@ impl KeyType {
             Algorithm::Other(algo) if algo.as_str() == X25519_ALGORITHM_NAME => {
                 Ok(KeyType::X25519PublicKey)
             }
+            Algorithm::Other(algo) if algo.as_str() == KEY_DERIVATION_ALGORITHM_NAME => {
+                Ok(KeyType::KeyDerivationRecord)
+            }
             _ => Err(internal!("invalid key data").into()),
         }
     }
@@ -169,6 +174,14 @@ declare_key_type! {
         X25519PublicKey => "x25519_public",
         /// An expanded Ed25519 keypair.
         Ed25519ExpandedKeypair => "ed25519_expanded_private",
+        /// A Tor ed25519 certificate (see `cert-spec.txt`).
+        ///
+        /// Unlike the other entries, certificates are not OpenSSH keys:
+        /// they are stored in the encoding given in `cert-spec.txt`.
+        Ed25519TorCert => "tor_ed25519_cert",
+        /// A record of how to derive a key from a master seed,
+        /// stored instead of the key itself.
+        KeyDerivationRecord => "derived_key",
     }
 }
 
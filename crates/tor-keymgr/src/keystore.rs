@@ -15,8 +15,11 @@ use tor_hscrypto::pk::{
 use tor_llcrypto::pk::{curve25519, ed25519};
 
 use crate::key_type::KeyType;
-use crate::ssh::{SshKeyAlgorithm, ED25519_EXPANDED_ALGORITHM_NAME, X25519_ALGORITHM_NAME};
-use crate::{Error, KeyPath, KeySpecifier, KeystoreId, Result};
+use crate::ssh::{
+    SshKeyAlgorithm, ED25519_EXPANDED_ALGORITHM_NAME, KEY_DERIVATION_ALGORITHM_NAME,
+    X25519_ALGORITHM_NAME,
+};
+use crate::{ArtiPath, Error, KeyMetadata, KeyPath, KeySpecifier, KeystoreId, Result};
 
 use downcast_rs::{impl_downcast, Downcast};
 
@@ -36,6 +39,16 @@ pub trait Keystore: Send + Sync + 'static {
     /// store.
     fn id(&self) -> &KeystoreId;
 
+    /// Whether this key store is read-only.
+    ///
+    /// The [`KeyMgr`](crate::KeyMgr) never tries to [`insert`](Keystore::insert) keys into,
+    /// or [`remove`](Keystore::remove) keys from, a read-only key store.
+    ///
+    /// Key stores are writable unless they say otherwise.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
     /// Check if the key identified by `key_spec` exists in this key store.
     fn contains(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<bool>;
 
@@ -69,6 +82,37 @@ pub trait Keystore: Send + Sync + 'static {
         key_type: &KeyType,
     ) -> Result<()>;
 
+    /// Write `key` to the key store, recording `metadata` alongside it.
+    ///
+    /// Key stores that cannot record metadata just store the key,
+    /// like [`insert`](Keystore::insert).
+    fn insert_with_metadata(
+        &self,
+        key: &dyn EncodableKey,
+        key_spec: &dyn KeySpecifier,
+        key_type: &KeyType,
+        metadata: &KeyMetadata,
+    ) -> Result<()> {
+        let _: &KeyMetadata = metadata;
+        self.insert(key, key_spec, key_type)
+    }
+
+    /// Retrieve the metadata recorded for the key identified by `key_spec`.
+    ///
+    /// Returns `Ok(None)` if the key does not exist in this key store.
+    ///
+    /// Key stores that cannot record metadata return an empty [`KeyMetadata`]
+    /// for every key they contain.
+    fn metadata(
+        &self,
+        key_spec: &dyn KeySpecifier,
+        key_type: &KeyType,
+    ) -> Result<Option<KeyMetadata>> {
+        Ok(self
+            .contains(key_spec, key_type)?
+            .then(KeyMetadata::default))
+    }
+
     /// Remove the specified key.
     ///
     /// A return value of `Ok(None)` indicates the key doesn't exist in this key store, whereas
@@ -98,6 +142,7 @@ macro_rules! ssh_to_internal_erased {
             convert_ed25519_kp,
             convert_expanded_ed25519_kp,
             convert_x25519_kp,
+            convert_key_derivation_record_kp,
             KeypairData
         )
     }};
@@ -109,11 +154,12 @@ macro_rules! ssh_to_internal_erased {
             convert_ed25519_pk,
             convert_expanded_ed25519_pk,
             convert_x25519_pk,
+            convert_key_derivation_record,
             KeyData
         )
     }};
 
-    ($key:expr, $algo:expr, $ed25519_fn:path, $expanded_ed25519_fn:path, $x25519_fn:path, $key_data_ty:tt) => {{
+    ($key:expr, $algo:expr, $ed25519_fn:path, $expanded_ed25519_fn:path, $x25519_fn:path, $record_fn:path, $key_data_ty:tt) => {{
         let key = $key;
         let algo = SshKeyAlgorithm::from($algo);
 
@@ -124,6 +170,7 @@ macro_rules! ssh_to_internal_erased {
             $key_data_ty::Other(other) => match algo {
                 SshKeyAlgorithm::X25519 => Ok($x25519_fn(&other).map(Box::new)?),
                 SshKeyAlgorithm::Ed25519Expanded => Ok($expanded_ed25519_fn(&other).map(Box::new)?),
+                SshKeyAlgorithm::KeyDerivationRecord => Ok($record_fn(&other).map(Box::new)?),
                 _ => Err(Error::UnsupportedKeyAlgorithm(algo)),
             },
             _ => Err(Error::UnsupportedKeyAlgorithm(algo)),
@@ -214,6 +261,24 @@ fn convert_x25519_pk(key: &ssh_key::public::OpaquePublicKey) -> Result<curve2551
     Ok(curve25519::PublicKey::from(public))
 }
 
+/// Try to convert an [`OpaquePublicKey`] to a [`KeyDerivationRecord`].
+fn convert_key_derivation_record(
+    key: &ssh_key::public::OpaquePublicKey,
+) -> Result<KeyDerivationRecord> {
+    KeyDerivationRecord::decode(key.as_ref())
+}
+
+/// Try to convert an [`OpaqueKeypair`] to a [`KeyDerivationRecord`].
+///
+/// This function always returns an error because key derivation records don't contain any
+/// secrets: the custom `key-derivation@spec.torproject.org` SSH algorithm should only be used
+/// for OpenSSH public keys. This function is needed for the [`ssh_to_internal_erased!`] macro.
+fn convert_key_derivation_record_kp(
+    _key: &ssh_key::private::OpaqueKeypair,
+) -> Result<KeyDerivationRecord> {
+    Err(internal!("invalid key derivation record (records should be stored as public keys)").into())
+}
+
 /// A public key or a keypair.
 #[derive(Clone, Debug)]
 #[non_exhaustive]
@@ -238,7 +303,7 @@ impl SshKeyData {
         let () = match key {
             KeyData::Ed25519(_) => Ok(()),
             KeyData::Other(_) => match algo {
-                SshKeyAlgorithm::X25519 => Ok(()),
+                SshKeyAlgorithm::X25519 | SshKeyAlgorithm::KeyDerivationRecord => Ok(()),
                 _ => Err(Error::UnsupportedKeyAlgorithm(algo)),
             },
             _ => Err(Error::UnsupportedKeyAlgorithm(algo)),
@@ -351,6 +416,9 @@ pub trait EncodableKey: Downcast + Sealed {
         Self: Sized;
 
     /// Return the [`SshKeyData`] of this key.
+    ///
+    /// Returns [`Error::NotOpenSshKey`] for an [`EncodedEd25519TorCert`],
+    /// which isn't stored as an OpenSSH key.
     fn as_ssh_key_data(&self) -> Result<SshKeyData>;
 }
 
@@ -495,6 +563,119 @@ impl EncodableKey for ed25519::ExpandedKeypair {
     }
 }
 
+/// An encoded Tor ed25519 certificate, in the format specified in `cert-spec.txt`.
+///
+/// Certificates are stored alongside the keys they certify.
+/// They are not OpenSSH keys: key stores keep them in the encoding given in
+/// `cert-spec.txt`, and they have no [`KeyMetadata`].
+///
+/// The key store does not interpret the certificate: it is stored and returned as-is.
+/// Callers must decode it (for example, using `tor_cert::Ed25519Cert::decode`),
+/// and check its signature and validity period, before relying on it.
+#[derive(Clone, Debug, PartialEq, Eq, derive_more::From, derive_more::Into, derive_more::AsRef)]
+pub struct EncodedEd25519TorCert(Vec<u8>);
+
+impl Sealed for EncodedEd25519TorCert {}
+
+impl EncodableKey for EncodedEd25519TorCert {
+    fn key_type() -> KeyType
+    where
+        Self: Sized,
+    {
+        KeyType::Ed25519TorCert
+    }
+
+    fn as_ssh_key_data(&self) -> Result<SshKeyData> {
+        Err(Error::NotOpenSshKey(KeyType::Ed25519TorCert))
+    }
+}
+
+/// The version of the key derivation scheme described by a [`KeyDerivationRecord`].
+///
+/// There is only one scheme so far.
+const KEY_DERIVATION_SCHEME_V1: &str = "v1";
+
+/// A record saying that a key is derived from a master seed, rather than stored.
+///
+/// When a key is derived (see `KeyMgr::derive`), the key store holds one of these
+/// instead of the key itself: whoever has the master seed can recompute the key from
+/// the derivation path and key type recorded here.
+///
+/// Records are encoded as OpenSSH public keys with the custom
+/// `key-derivation@spec.torproject.org` algorithm.
+/// They contain no secrets.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyDerivationRecord {
+    /// The type of the derived key.
+    key_type: KeyType,
+    /// The derivation path of the key.
+    ///
+    /// This is the [`ArtiPath`] at which the key was first derived.
+    path: ArtiPath,
+}
+
+impl KeyDerivationRecord {
+    /// Create a record for a key of type `key_type`, derived using the derivation path `path`.
+    pub fn new(key_type: KeyType, path: ArtiPath) -> Self {
+        Self { key_type, path }
+    }
+
+    /// Return the type of the derived key.
+    pub fn key_type(&self) -> &KeyType {
+        &self.key_type
+    }
+
+    /// Return the derivation path of the key.
+    pub fn path(&self) -> &ArtiPath {
+        &self.path
+    }
+
+    /// Encode this record as `<scheme> <key type> <derivation path>`.
+    fn encode(&self) -> Vec<u8> {
+        format!(
+            "{KEY_DERIVATION_SCHEME_V1} {} {}",
+            self.key_type.arti_extension(),
+            self.path
+        )
+        .into_bytes()
+    }
+
+    /// Decode a record encoded with [`encode`](Self::encode).
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let invalid = || internal!("invalid key derivation record");
+        let s = std::str::from_utf8(bytes).map_err(|_| invalid())?;
+        let mut fields = s.split(' ');
+        let (Some(KEY_DERIVATION_SCHEME_V1), Some(key_type), Some(path), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid().into());
+        };
+        let path = ArtiPath::new(path.into()).map_err(|_| invalid())?;
+
+        Ok(Self::new(KeyType::from(key_type), path))
+    }
+}
+
+impl Sealed for KeyDerivationRecord {}
+
+impl EncodableKey for KeyDerivationRecord {
+    fn key_type() -> KeyType
+    where
+        Self: Sized,
+    {
+        KeyType::KeyDerivationRecord
+    }
+
+    fn as_ssh_key_data(&self) -> Result<SshKeyData> {
+        let algorithm_name = AlgorithmName::new(KEY_DERIVATION_ALGORITHM_NAME)
+            .map_err(|_| internal!("invalid algorithm name"))?;
+
+        let ssh_public = OpaquePublicKey::new(self.encode(), Algorithm::Other(algorithm_name));
+
+        SshKeyData::try_from_key_data(KeyData::Other(ssh_public))
+    }
+}
+
 /// A key that can be converted to an [`EncodableKey`].
 //
 // NOTE: Conceptually, the `ToEncodableKey` and `EncodableKey` traits serve the same purpose (they
@@ -615,3 +796,27 @@ impl ToEncodableKey for HsSvcNtorKeypair {
         key.into()
     }
 }
+
+impl ToEncodableKey for EncodedEd25519TorCert {
+    type Key = Self;
+
+    fn to_encodable_key(self) -> Self::Key {
+        self
+    }
+
+    fn from_encodable_key(key: Self::Key) -> Self {
+        key
+    }
+}
+
+impl ToEncodableKey for KeyDerivationRecord {
+    type Key = Self;
+
+    fn to_encodable_key(self) -> Self::Key {
+        self
+    }
+
+    fn from_encodable_key(key: Self::Key) -> Self {
+        key
+    }
+}
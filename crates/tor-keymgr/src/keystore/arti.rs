@@ -10,8 +10,11 @@ use std::path::{Path, PathBuf};
 use std::result::Result as StdResult;
 use std::str::FromStr;
 
-use crate::keystore::{EncodableKey, ErasedKey, KeySpecifier, Keystore};
-use crate::{arti_path, ArtiPath, ArtiPathUnavailableError, KeyPath, KeyType, KeystoreId, Result};
+use crate::keystore::{EncodableKey, EncodedEd25519TorCert, ErasedKey, KeySpecifier, Keystore};
+use crate::{
+    arti_path, ArtiPath, ArtiPathUnavailableError, KeyMetadata, KeyPath, KeyType, KeystoreId,
+    Result,
+};
 use err::{ArtiNativeKeystoreError, FilesystemAction};
 use ssh::UnparsedOpenSshKey;
 
@@ -35,6 +38,14 @@ use tor_basic_utils::PathExt as _;
 ///   * `x25519@spec.torproject.org`, for x25519 keys
 ///   * `ed25519-expanded@spec.torproject.org`, for expanded ed25519 keys
 ///
+/// Besides private keys, the key store can hold public keys and certificates
+/// (such as the public identity keys of other onion services,
+/// or the certificates of our own descriptor signing keys).
+/// Public keys are stored in the OpenSSH public key format.
+/// Certificates are not OpenSSH keys:
+/// they are stored in the encoding given in `cert-spec.txt`, without any metadata.
+/// Both are subject to the same permission checks as private keys.
+///
 /// See [SSH protocol extensions] for more details.
 ///
 /// [algorithm name]: https://www.iana.org/assignments/ssh-parameters/ssh-parameters.xhtml#ssh-parameters-19
@@ -48,6 +59,8 @@ pub struct ArtiNativeKeystore {
     keystore_dir: CheckedDir,
     /// The unique identifier of this instance.
     id: KeystoreId,
+    /// Whether this key store is read-only.
+    read_only: bool,
 }
 
 impl ArtiNativeKeystore {
@@ -72,9 +85,49 @@ impl ArtiNativeKeystore {
                 err: e.into(),
             })?;
 
-        // TODO: load the keystore ID from config.
         let id = KeystoreId::from_str("arti")?;
-        Ok(Self { keystore_dir, id })
+        Ok(Self {
+            keystore_dir,
+            id,
+            read_only: false,
+        })
+    }
+
+    /// Open an existing [`ArtiNativeKeystore`] rooted at `keystore_dir`, in read-only mode.
+    ///
+    /// Unlike [`from_path_and_mistrust`](ArtiNativeKeystore::from_path_and_mistrust),
+    /// this does not create `keystore_dir`: it is an error for the directory not to exist.
+    ///
+    /// Read-only key stores refuse to [`insert`](Keystore::insert) or
+    /// [`remove`](Keystore::remove) keys.
+    /// This is meant for key stores that are provisioned out of band
+    /// (for example, a system-wide key store installed by a package).
+    pub fn open_read_only(keystore_dir: impl AsRef<Path>, mistrust: &Mistrust) -> Result<Self> {
+        let keystore_dir = mistrust
+            .verifier()
+            .check_content()
+            .secure_dir(&keystore_dir)
+            .map_err(|e| ArtiNativeKeystoreError::FsMistrust {
+                action: FilesystemAction::Init,
+                path: keystore_dir.as_ref().into(),
+                err: e.into(),
+            })?;
+
+        let id = KeystoreId::from_str("arti")?;
+        Ok(Self {
+            keystore_dir,
+            id,
+            read_only: true,
+        })
+    }
+
+    /// Use `id` as the [`KeystoreId`] of this key store.
+    ///
+    /// The default ID is `arti`.
+    /// Each key store used by a [`KeyMgr`](crate::KeyMgr) must have a distinct ID.
+    pub fn with_id(mut self, id: KeystoreId) -> Self {
+        self.id = id;
+        self
     }
 
     /// The path on disk of the key with the specified identity and type, relative to
@@ -109,11 +162,69 @@ macro_rules! rel_path_if_supported {
     }};
 }
 
+impl ArtiNativeKeystore {
+    /// Read the OpenSSH key file of the specified key, without parsing it.
+    ///
+    /// Returns `Ok(None)` if the key does not exist.
+    fn read_unparsed(
+        &self,
+        key_spec: &dyn KeySpecifier,
+        key_type: &KeyType,
+    ) -> Result<Option<UnparsedOpenSshKey>> {
+        Ok(self
+            .read_file(key_spec, key_type, |dir, path| dir.read_to_string(path))?
+            .map(|(inner, path)| UnparsedOpenSshKey::new(inner, path)))
+    }
+
+    /// Read the certificate file of the specified certificate.
+    ///
+    /// Returns `Ok(None)` if the certificate does not exist.
+    fn read_cert(
+        &self,
+        key_spec: &dyn KeySpecifier,
+        key_type: &KeyType,
+    ) -> Result<Option<EncodedEd25519TorCert>> {
+        Ok(self
+            .read_file(key_spec, key_type, |dir, path| dir.read(path))?
+            .map(|(cert, _)| cert.into()))
+    }
+
+    /// Read the file of the specified key using `read`, and return its contents and path.
+    ///
+    /// Returns `Ok(None)` if the key does not exist.
+    fn read_file<T>(
+        &self,
+        key_spec: &dyn KeySpecifier,
+        key_type: &KeyType,
+        read: impl FnOnce(&CheckedDir, &Path) -> fs_mistrust::Result<T>,
+    ) -> Result<Option<(T, PathBuf)>> {
+        let path = rel_path_if_supported!(self.rel_path(key_spec, key_type), Ok(None));
+
+        let inner = match read(&self.keystore_dir, &path) {
+            Err(fs_mistrust::Error::NotFound(_)) => return Ok(None),
+            Err(fs_mistrust::Error::Io { err, .. }) if err.kind() == ErrorKind::NotFound => {
+                return Ok(None);
+            }
+            res => res.map_err(|err| ArtiNativeKeystoreError::FsMistrust {
+                action: FilesystemAction::Read,
+                path: path.clone(),
+                err: err.into(),
+            })?,
+        };
+
+        Ok(Some((inner, path)))
+    }
+}
+
 impl Keystore for ArtiNativeKeystore {
     fn id(&self) -> &KeystoreId {
         &self.id
     }
 
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
     fn contains(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<bool> {
         let path = rel_path_if_supported!(self.rel_path(key_spec, key_type), Ok(false));
         let abs_path =
@@ -129,23 +240,15 @@ impl Keystore for ArtiNativeKeystore {
     }
 
     fn get(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<Option<ErasedKey>> {
-        let path = rel_path_if_supported!(self.rel_path(key_spec, key_type), Ok(None));
-
-        let inner = match self.keystore_dir.read_to_string(&path) {
-            Err(fs_mistrust::Error::NotFound(_)) => return Ok(None),
-            Err(fs_mistrust::Error::Io { err, .. }) if err.kind() == ErrorKind::NotFound => {
-                return Ok(None);
-            }
-            res => res.map_err(|err| ArtiNativeKeystoreError::FsMistrust {
-                action: FilesystemAction::Read,
-                path: path.clone(),
-                err: err.into(),
-            })?,
-        };
+        if key_type == &KeyType::Ed25519TorCert {
+            return Ok(self
+                .read_cert(key_spec, key_type)?
+                .map(|cert| Box::new(cert) as ErasedKey));
+        }
 
-        UnparsedOpenSshKey::new(inner, path)
-            .parse_ssh_format_erased(key_type)
-            .map(Some)
+        self.read_unparsed(key_spec, key_type)?
+            .map(|key| key.parse_ssh_format_erased(key_type))
+            .transpose()
     }
 
     fn insert(
@@ -154,6 +257,20 @@ impl Keystore for ArtiNativeKeystore {
         key_spec: &dyn KeySpecifier,
         key_type: &KeyType,
     ) -> Result<()> {
+        self.insert_with_metadata(key, key_spec, key_type, &KeyMetadata::default())
+    }
+
+    fn insert_with_metadata(
+        &self,
+        key: &dyn EncodableKey,
+        key_spec: &dyn KeySpecifier,
+        key_type: &KeyType,
+        metadata: &KeyMetadata,
+    ) -> Result<()> {
+        if self.read_only {
+            return Err(crate::Error::ReadOnlyKeystore(self.id.clone()));
+        }
+
         let path = self
             .rel_path(key_spec, key_type)
             .map_err(|e| tor_error::internal!("{e}"))?;
@@ -169,15 +286,20 @@ impl Keystore for ArtiNativeKeystore {
             })?;
         }
 
-        let key = key.as_ssh_key_data()?;
-        // TODO (#1095): decide what information, if any, to put in the comment
-        let comment = "";
+        let contents = match key.downcast_ref::<EncodedEd25519TorCert>() {
+            // Certificates aren't OpenSSH keys, and have nowhere to keep the metadata.
+            Some(cert) => Vec::from(cert.clone()),
+            None => {
+                let key = key.as_ssh_key_data()?;
+                let comment = metadata.to_openssh_comment();
 
-        let openssh_key = key.to_openssh_string(comment)?;
+                key.to_openssh_string(&comment)?.into_bytes()
+            }
+        };
 
         Ok(self
             .keystore_dir
-            .write_and_replace(&path, openssh_key)
+            .write_and_replace(&path, contents)
             .map_err(|err| ArtiNativeKeystoreError::FsMistrust {
                 action: FilesystemAction::Write,
                 path,
@@ -185,7 +307,30 @@ impl Keystore for ArtiNativeKeystore {
             })?)
     }
 
+    fn metadata(
+        &self,
+        key_spec: &dyn KeySpecifier,
+        key_type: &KeyType,
+    ) -> Result<Option<KeyMetadata>> {
+        if key_type == &KeyType::Ed25519TorCert {
+            return Ok(self
+                .contains(key_spec, key_type)?
+                .then(KeyMetadata::default));
+        }
+
+        self.read_unparsed(key_spec, key_type)?
+            .map(|key| {
+                let comment = key.parse_comment(key_type)?;
+                Ok(KeyMetadata::from_openssh_comment(&comment))
+            })
+            .transpose()
+    }
+
     fn remove(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<Option<()>> {
+        if self.read_only {
+            return Err(crate::Error::ReadOnlyKeystore(self.id.clone()));
+        }
+
         let rel_path = self
             .rel_path(key_spec, key_type)
             .map_err(|e| tor_error::internal!("{e}"))?;
@@ -301,6 +446,7 @@ mod tests {
     use crate::test_utils::TestSpecifier;
     use crate::{ArtiPath, KeyPath};
     use std::fs;
+    use std::time::Duration;
     use tempfile::{tempdir, TempDir};
     use tor_llcrypto::pk::ed25519;
 
@@ -523,6 +669,50 @@ mod tests {
         assert_contains_arti_paths!([TestSpecifier::path_prefix(),], key_store.list().unwrap());
     }
 
+    #[test]
+    fn metadata() {
+        // A key written by another tool: its comment is preserved as-is.
+        let (key_store, _keystore_dir) = init_keystore(true);
+        let key_spec = TestSpecifier::default();
+        let ed_key_type = &KeyType::Ed25519Keypair;
+        let md = key_store.metadata(&key_spec, ed_key_type).unwrap().unwrap();
+        assert!(md.comment().is_some());
+        assert_eq!(md.created_at(), None);
+        assert_eq!(md.created_by(), None);
+
+        // Not found
+        assert!(key_store
+            .metadata(&key_spec, &KeyType::X25519StaticKeypair)
+            .unwrap()
+            .is_none());
+
+        let key = key_store.get(&key_spec, ed_key_type).unwrap().unwrap();
+
+        // A key inserted without metadata has none.
+        key_store.insert(&*key, &key_spec, ed_key_type).unwrap();
+        let md = key_store.metadata(&key_spec, ed_key_type).unwrap().unwrap();
+        assert_eq!(md, KeyMetadata::default());
+
+        // Metadata is stored alongside the key.
+        let md = KeyMetadata::new_generated()
+            .with_origin("imported from C Tor")
+            .with_comment("the main onion service");
+        key_store
+            .insert_with_metadata(&*key, &key_spec, ed_key_type, &md)
+            .unwrap();
+        let found = key_store.metadata(&key_spec, ed_key_type).unwrap().unwrap();
+        // The creation time is only stored to the nearest second.
+        assert_eq!(found.created_by(), md.created_by());
+        assert_eq!(found.origin(), Some("imported from C Tor"));
+        assert_eq!(found.comment(), Some("the main onion service"));
+        let created = md.created_at().unwrap();
+        let found_created = found.created_at().unwrap();
+        assert!(created.duration_since(found_created).unwrap() < Duration::from_secs(1));
+
+        // And the key itself is still readable.
+        assert_found!(key_store, &key_spec, ed_key_type, true);
+    }
+
     #[test]
     fn remove() {
         // Initialize the key store
@@ -589,4 +779,90 @@ mod tests {
             key_store.list().unwrap()
         );
     }
+
+    #[test]
+    fn read_only() {
+        // Populate a key store, and then reopen it in read-only mode
+        let (_, keystore_dir) = init_keystore(true);
+        let id = KeystoreId::from_str("system").unwrap();
+        let key_store = ArtiNativeKeystore::open_read_only(&keystore_dir, &Mistrust::default())
+            .unwrap()
+            .with_id(id.clone());
+
+        assert!(key_store.is_read_only());
+        assert_eq!(key_store.id(), &id);
+        assert_found!(
+            key_store,
+            &TestSpecifier::default(),
+            &KeyType::Ed25519Keypair,
+            true
+        );
+
+        // We can't remove keys from a read-only store...
+        let err = key_store
+            .remove(&TestSpecifier::default(), &KeyType::Ed25519Keypair)
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::ReadOnlyKeystore(ref i) if i == &id));
+
+        // ...or insert any.
+        let key = UnparsedOpenSshKey::new(OPENSSH_ED25519.into(), PathBuf::from("/test/path"));
+        let erased_kp = key
+            .parse_ssh_format_erased(&KeyType::Ed25519Keypair)
+            .unwrap();
+        let Ok(key) = erased_kp.downcast::<ed25519::Keypair>() else {
+            panic!("failed to downcast key to ed25519::Keypair")
+        };
+        let key_spec = TestSpecifier::new("-i-am-a-suffix");
+        let err = key_store
+            .insert(&*key, &key_spec, &KeyType::Ed25519Keypair)
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::ReadOnlyKeystore(_)));
+
+        // The key store is unchanged.
+        assert_contains_arti_paths!([TestSpecifier::path_prefix(),], key_store.list().unwrap());
+
+        // Read-only key stores are never created.
+        let missing = keystore_dir.path().join("missing");
+        assert!(ArtiNativeKeystore::open_read_only(&missing, &Mistrust::default()).is_err());
+        assert!(!missing.exists());
+    }
+
+    #[test]
+    fn certs() {
+        let (key_store, _keystore_dir) = init_keystore(false);
+        let cert_type = KeyType::Ed25519TorCert;
+        let key_spec = TestSpecifier::default();
+        // The keystore doesn't try to interpret the certificate.
+        let cert = crate::EncodedEd25519TorCert::from(vec![1, 4, 0, 7, 8, 3]);
+
+        assert_found!(key_store, &key_spec, &cert_type, false);
+        key_store.insert(&cert, &key_spec, &cert_type).unwrap();
+        assert_found!(key_store, &key_spec, &cert_type, true);
+
+        // The certificate is stored as-is, not as an OpenSSH key.
+        let contents = fs::read(key_path(&key_store, &cert_type)).unwrap();
+        assert_eq!(contents, [1, 4, 0, 7, 8, 3]);
+        assert!(key_path(&key_store, &cert_type)
+            .to_string_lossy()
+            .ends_with(".tor_ed25519_cert"));
+
+        let found = key_store
+            .get(&key_spec, &cert_type)
+            .unwrap()
+            .unwrap()
+            .downcast::<crate::EncodedEd25519TorCert>()
+            .unwrap();
+        assert_eq!(*found, cert);
+        assert_eq!(
+            key_store.metadata(&key_spec, &cert_type).unwrap(),
+            Some(KeyMetadata::default())
+        );
+        assert_eq!(
+            key_store.list().unwrap(),
+            vec![(
+                KeyPath::Arti(ArtiPath::new(TestSpecifier::path_prefix().into()).unwrap()),
+                cert_type
+            )]
+        );
+    }
 }
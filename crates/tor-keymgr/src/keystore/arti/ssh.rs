@@ -5,7 +5,7 @@
 
 use crate::keystore::arti::err::ArtiNativeKeystoreError;
 use crate::ssh::SshKeyAlgorithm;
-use crate::{ErasedKey, KeyType, Result, SshKeyData};
+use crate::{ErasedKey, Error, KeyType, Result, SshKeyData};
 
 use zeroize::Zeroizing;
 
@@ -19,7 +19,7 @@ use crate::UnknownKeyTypeError;
 /// value is unchecked/unvalidated, and might not actually be a valid OpenSSH key.
 ///
 /// The inner value is zeroed on drop.
-pub(super) struct UnparsedOpenSshKey {
+pub(crate) struct UnparsedOpenSshKey {
     /// The contents of an OpenSSH key file.
     inner: Zeroizing<String>,
     /// The path of the file (for error reporting).
@@ -76,6 +76,8 @@ fn ssh_algorithm(key_type: &KeyType) -> Result<SshKeyAlgorithm> {
         KeyType::Ed25519Keypair | KeyType::Ed25519PublicKey => Ok(SshKeyAlgorithm::Ed25519),
         KeyType::X25519StaticKeypair | KeyType::X25519PublicKey => Ok(SshKeyAlgorithm::X25519),
         KeyType::Ed25519ExpandedKeypair => Ok(SshKeyAlgorithm::Ed25519Expanded),
+        KeyType::Ed25519TorCert => Err(Error::NotOpenSshKey(key_type.clone())),
+        KeyType::KeyDerivationRecord => Ok(SshKeyAlgorithm::KeyDerivationRecord),
         KeyType::Unknown { arti_extension } => Err(ArtiNativeKeystoreError::UnknownKeyType(
             UnknownKeyTypeError {
                 arti_extension: arti_extension.clone(),
@@ -107,9 +109,39 @@ impl UnparsedOpenSshKey {
             | KeyType::Ed25519ExpandedKeypair => {
                 parse_openssh!(PRIVATE self, key_type).into_erased()
             }
-            KeyType::Ed25519PublicKey | KeyType::X25519PublicKey => {
+            KeyType::Ed25519PublicKey | KeyType::X25519PublicKey | KeyType::KeyDerivationRecord => {
                 parse_openssh!(PUBLIC self, key_type).into_erased()
             }
+            KeyType::Ed25519TorCert => Err(Error::NotOpenSshKey(key_type.clone())),
+            KeyType::Unknown { arti_extension } => Err(ArtiNativeKeystoreError::UnknownKeyType(
+                UnknownKeyTypeError {
+                    arti_extension: arti_extension.clone(),
+                },
+            )
+            .into()),
+        }
+    }
+
+    /// Parse an OpenSSH key, and return its comment.
+    pub(crate) fn parse_comment(self, key_type: &KeyType) -> Result<String> {
+        match key_type {
+            KeyType::Ed25519Keypair
+            | KeyType::X25519StaticKeypair
+            | KeyType::Ed25519ExpandedKeypair => {
+                Ok(
+                    parse_openssh!(self, key_type, ssh_key::private::PrivateKey::from_openssh)
+                        .comment()
+                        .to_owned(),
+                )
+            }
+            KeyType::Ed25519PublicKey | KeyType::X25519PublicKey | KeyType::KeyDerivationRecord => {
+                Ok(
+                    parse_openssh!(self, key_type, ssh_key::public::PublicKey::from_openssh)
+                        .comment()
+                        .to_owned(),
+                )
+            }
+            KeyType::Ed25519TorCert => Err(Error::NotOpenSshKey(key_type.clone())),
             KeyType::Unknown { arti_extension } => Err(ArtiNativeKeystoreError::UnknownKeyType(
                 UnknownKeyTypeError {
                     arti_extension: arti_extension.clone(),
@@ -10,23 +10,33 @@ use tor_error::internal;
 use crate::keystore::ephemeral::err::ArtiEphemeralKeystoreError;
 use crate::Error;
 use crate::{
-    ArtiPath, EncodableKey, ErasedKey, KeyPath, KeySpecifier, KeyType, Keystore, KeystoreId,
-    SshKeyData,
+    ArtiPath, EncodableKey, EncodedEd25519TorCert, ErasedKey, KeyMetadata, KeyPath, KeySpecifier,
+    KeyType, Keystore, KeystoreId, SshKeyData,
 };
 
 /// The identifier of a key stored in the `ArtiEphemeralKeystore`.
 type KeyIdent = (ArtiPath, KeyType);
 
+/// An entry stored in the `ArtiEphemeralKeystore`.
+#[derive(Clone)]
+enum StoredEntry {
+    /// A key, stored as [`SshKeyData`].
+    Key(SshKeyData),
+    /// A certificate, which isn't an OpenSSH key, and is stored as-is.
+    Cert(EncodedEd25519TorCert),
+}
+
 /// The Ephemeral Arti key store
 ///
 /// This is a purely in-memory key store. Keys written to this store
-/// are never written to disk, and are stored in-memory as [`SshKeyData`].
+/// are never written to disk, and are stored in-memory as [`SshKeyData`]
+/// (or, for certificates, as an [`EncodedEd25519TorCert`]).
 /// Keys saved in this Keystore do not persist between restarts!
 pub struct ArtiEphemeralKeystore {
     /// Identifier hard-coded to 'ephemeral'
     id: KeystoreId,
-    /// Keys stored as [`SshKeyData`].
-    key_dictionary: Arc<Mutex<HashMap<KeyIdent, SshKeyData>>>,
+    /// The stored entries, along with their metadata.
+    key_dictionary: Arc<Mutex<HashMap<KeyIdent, (StoredEntry, KeyMetadata)>>>,
 }
 
 impl ArtiEphemeralKeystore {
@@ -63,10 +73,11 @@ impl Keystore for ArtiEphemeralKeystore {
             .map_err(ArtiEphemeralKeystoreError::ArtiPathUnavailableError)?;
         let key_dictionary = self.key_dictionary.lock().expect("lock poisoned");
         match key_dictionary.get(&(arti_path.clone(), key_type.clone())) {
-            Some(key) => {
+            Some((StoredEntry::Key(key), _)) => {
                 let key: ErasedKey = key.clone().into_erased()?;
                 Ok(Some(key))
             }
+            Some((StoredEntry::Cert(cert), _)) => Ok(Some(Box::new(cert.clone()))),
             None => Ok(None),
         }
     }
@@ -76,11 +87,28 @@ impl Keystore for ArtiEphemeralKeystore {
         key: &dyn EncodableKey,
         key_spec: &dyn KeySpecifier,
         key_type: &KeyType,
+    ) -> Result<(), Error> {
+        self.insert_with_metadata(key, key_spec, key_type, &KeyMetadata::default())
+    }
+
+    fn insert_with_metadata(
+        &self,
+        key: &dyn EncodableKey,
+        key_spec: &dyn KeySpecifier,
+        key_type: &KeyType,
+        metadata: &KeyMetadata,
     ) -> Result<(), Error> {
         let arti_path = key_spec
             .arti_path()
             .map_err(ArtiEphemeralKeystoreError::ArtiPathUnavailableError)?;
-        let key_data = key.as_ssh_key_data()?;
+        let (entry, entry_type) = match key.downcast_ref::<EncodedEd25519TorCert>() {
+            Some(cert) => (StoredEntry::Cert(cert.clone()), KeyType::Ed25519TorCert),
+            None => {
+                let key_data = key.as_ssh_key_data()?;
+                let key_type = key_data.key_type()?;
+                (StoredEntry::Key(key_data), key_type)
+            }
+        };
 
         // TODO: add key_type validation to Keystore::get and Keystore::remove.
         // The presence of a key with a mismatched key_type can be either due to keystore
@@ -88,7 +116,7 @@ impl Keystore for ArtiEphemeralKeystore {
         // that).
         //
         // TODO: add key_type validation to ArtiNativeKeystore
-        if &key_data.key_type()? != key_type {
+        if &entry_type != key_type {
             // This can never happen unless:
             //   * Keystore::insert is called directly with an incorrect KeyType for `key`, or
             //   * Keystore::insert is called via KeyMgr, but the EncodableKey implementation of
@@ -102,10 +130,24 @@ impl Keystore for ArtiEphemeralKeystore {
 
         // save to dictionary
         let mut key_dictionary = self.key_dictionary.lock().expect("lock poisoned");
-        let _ = key_dictionary.insert((arti_path, key_type.clone()), key_data);
+        let _ = key_dictionary.insert((arti_path, key_type.clone()), (entry, metadata.clone()));
         Ok(())
     }
 
+    fn metadata(
+        &self,
+        key_spec: &dyn KeySpecifier,
+        key_type: &KeyType,
+    ) -> Result<Option<KeyMetadata>, Error> {
+        let arti_path = key_spec
+            .arti_path()
+            .map_err(ArtiEphemeralKeystoreError::ArtiPathUnavailableError)?;
+        let key_dictionary = self.key_dictionary.lock().expect("lock poisoned");
+        Ok(key_dictionary
+            .get(&(arti_path, key_type.clone()))
+            .map(|(_, metadata)| metadata.clone()))
+    }
+
     fn remove(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<Option<()>, Error> {
         let arti_path = key_spec
             .arti_path()
@@ -280,4 +322,63 @@ mod tests {
             .is_ok());
         assert_eq!(key_store.list().unwrap().len(), 1);
     }
+
+    #[test]
+    fn metadata() {
+        let key_store = ArtiEphemeralKeystore::new("test-ephemeral".to_string());
+
+        // verify no metadata for a missing key
+        assert!(key_store
+            .metadata(key_spec().as_ref(), key_type())
+            .unwrap()
+            .is_none());
+
+        // verify the metadata is kept along with the key
+        let md = KeyMetadata::new().with_comment("ephemeral key");
+        key_store
+            .insert_with_metadata(key().as_ref(), key_spec().as_ref(), key_type(), &md)
+            .unwrap();
+        assert_eq!(
+            key_store
+                .metadata(key_spec().as_ref(), key_type())
+                .unwrap()
+                .unwrap(),
+            md
+        );
+
+        // verify a plain insert replaces it with empty metadata
+        key_store
+            .insert(key().as_ref(), key_spec().as_ref(), key_type())
+            .unwrap();
+        assert_eq!(
+            key_store
+                .metadata(key_spec().as_ref(), key_type())
+                .unwrap()
+                .unwrap(),
+            KeyMetadata::default()
+        );
+    }
+
+    #[test]
+    fn certs() {
+        let key_store = ArtiEphemeralKeystore::new("test-ephemeral".to_string());
+        let cert_type = &KeyType::Ed25519TorCert;
+        let cert = EncodedEd25519TorCert::from(vec![1, 4, 0, 7, 8, 3]);
+
+        // a certificate can't be inserted as some other type
+        assert!(key_store
+            .insert(&cert, key_spec().as_ref(), key_type())
+            .is_err());
+
+        key_store
+            .insert(&cert, key_spec().as_ref(), cert_type)
+            .unwrap();
+        let found = key_store
+            .get(key_spec().as_ref(), cert_type)
+            .unwrap()
+            .unwrap()
+            .downcast::<EncodedEd25519TorCert>()
+            .unwrap();
+        assert_eq!(*found, cert);
+    }
 }
@@ -52,11 +52,15 @@ pub(crate) mod ssh;
 #[cfg(any(test, feature = "testing"))]
 pub mod test_utils;
 
+#[cfg(feature = "key-derivation")]
+mod derivation;
 #[cfg(feature = "keymgr")]
 mod key_type;
 #[cfg(feature = "keymgr")]
 mod keystore;
 #[cfg(feature = "keymgr")]
+mod metadata;
+#[cfg(feature = "keymgr")]
 mod mgr;
 
 #[cfg(not(feature = "keymgr"))]
@@ -78,11 +82,19 @@ pub use {
     key_type::{KeyType, UnknownKeyTypeError},
     keystore::arti::ArtiNativeKeystore,
     keystore::ephemeral::ArtiEphemeralKeystore,
-    keystore::{EncodableKey, ErasedKey, Keygen, KeygenRng, Keystore, SshKeyData, ToEncodableKey},
+    keystore::{
+        EncodableKey, EncodedEd25519TorCert, ErasedKey, KeyDerivationRecord, Keygen, KeygenRng,
+        Keystore, SshKeyData, ToEncodableKey,
+    },
+    metadata::KeyMetadata,
     mgr::{KeyMgr, KeyMgrBuilder, KeyMgrBuilderError, KeystoreEntry},
     ssh_key,
 };
 
+#[cfg(feature = "key-derivation")]
+#[cfg_attr(docsrs, doc(cfg(feature = "key-derivation")))]
+pub use derivation::{DerivableKey, InvalidMasterSeed, MasterSeed, MASTER_SEED_LEN};
+
 #[doc(hidden)]
 pub use key_specifier::derive as key_specifier_derive;
 
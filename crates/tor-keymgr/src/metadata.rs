@@ -0,0 +1,270 @@
+//! Metadata stored alongside keys.
+
+use std::time::SystemTime;
+
+/// The tool that created a key generated by [`KeyMgr`](crate::KeyMgr).
+const THIS_TOOL: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Information about a key entry, for operational auditing.
+///
+/// Keystores that support it record this alongside the key.
+/// [`ArtiNativeKeystore`](crate::ArtiNativeKeystore) stores it in the comment field
+/// of the OpenSSH key file.
+///
+/// Every field is optional:
+/// keys written by older versions of Arti, or by other tools, have no metadata
+/// (or, in the case of keys with a free-form OpenSSH comment, only a [`comment`](Self::comment)).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct KeyMetadata {
+    /// When the key was created.
+    created_at: Option<SystemTime>,
+    /// The tool (and version) that created the key.
+    created_by: Option<String>,
+    /// Where the key was imported from, if it was not generated by us.
+    origin: Option<String>,
+    /// A human-readable comment.
+    comment: Option<String>,
+}
+
+impl KeyMetadata {
+    /// Create a new, empty, `KeyMetadata`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create the metadata for a key that is being generated by this crate right now.
+    pub fn new_generated() -> Self {
+        Self::new()
+            .with_created_at(SystemTime::now())
+            .with_created_by(THIS_TOOL)
+    }
+
+    /// Set the time at which the key was created.
+    pub fn with_created_at(mut self, created_at: SystemTime) -> Self {
+        self.created_at = Some(created_at);
+        self
+    }
+
+    /// Set the tool (and version) that created the key.
+    pub fn with_created_by(mut self, created_by: impl Into<String>) -> Self {
+        self.created_by = Some(created_by.into());
+        self
+    }
+
+    /// Set the origin of an imported key (for example, the file it was imported from).
+    pub fn with_origin(mut self, origin: impl Into<String>) -> Self {
+        self.origin = Some(origin.into());
+        self
+    }
+
+    /// Set a human-readable comment.
+    pub fn with_comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    /// Return the time at which the key was created, if known.
+    pub fn created_at(&self) -> Option<SystemTime> {
+        self.created_at
+    }
+
+    /// Return the tool (and version) that created the key, if known.
+    pub fn created_by(&self) -> Option<&str> {
+        self.created_by.as_deref()
+    }
+
+    /// Return the origin of the key, if it was imported.
+    pub fn origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+
+    /// Return the human-readable comment of the key, if any.
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    /// Encode this metadata as an OpenSSH key comment.
+    ///
+    /// The comment is a `; `-separated list of `name=value` fields,
+    /// in which any `\`, `;` or newline in a value is escaped with a `\`.
+    /// Empty metadata is encoded as an empty comment.
+    pub(crate) fn to_openssh_comment(&self) -> String {
+        let created_at = self
+            .created_at
+            .map(|t| humantime::format_rfc3339_seconds(t).to_string());
+        let fields = [
+            (FIELD_CREATED_AT, created_at.as_deref()),
+            (FIELD_CREATED_BY, self.created_by()),
+            (FIELD_ORIGIN, self.origin()),
+            (FIELD_COMMENT, self.comment()),
+        ];
+
+        fields
+            .into_iter()
+            .filter_map(|(name, value)| Some(format!("{name}={}", escape(value?))))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    /// Decode the metadata from an OpenSSH key comment.
+    ///
+    /// A comment that was not written by [`to_openssh_comment`](Self::to_openssh_comment)
+    /// (for example, the `user@host` comment of a key generated by `ssh-keygen`)
+    /// is returned as the [`comment`](Self::comment) field.
+    pub(crate) fn from_openssh_comment(comment: &str) -> Self {
+        if comment.is_empty() {
+            return Self::default();
+        }
+
+        Self::parse_fields(comment).unwrap_or_else(|| Self::new().with_comment(comment))
+    }
+
+    /// Helper for `from_openssh_comment`: parse a comment made of our fields.
+    ///
+    /// Returns `None` if the comment has any other form.
+    fn parse_fields(comment: &str) -> Option<Self> {
+        let mut md = Self::default();
+        for field in split_unescaped(comment) {
+            let (name, value) = field.trim_start().split_once('=')?;
+            let value = unescape(value)?;
+            match name {
+                FIELD_CREATED_AT => {
+                    md.created_at = Some(humantime::parse_rfc3339(&value).ok()?);
+                }
+                FIELD_CREATED_BY => md.created_by = Some(value),
+                FIELD_ORIGIN => md.origin = Some(value),
+                FIELD_COMMENT => md.comment = Some(value),
+                _ => return None,
+            }
+        }
+        Some(md)
+    }
+}
+
+/// The name of the `created_at` field in an encoded comment.
+const FIELD_CREATED_AT: &str = "created";
+/// The name of the `created_by` field in an encoded comment.
+const FIELD_CREATED_BY: &str = "by";
+/// The name of the `origin` field in an encoded comment.
+const FIELD_ORIGIN: &str = "origin";
+/// The name of the `comment` field in an encoded comment.
+const FIELD_COMMENT: &str = "comment";
+
+/// Escape the `\`, `;` and newline characters in `value`.
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' | ';' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Undo [`escape`].
+///
+/// Returns `None` if `value` contains an invalid escape sequence.
+fn unescape(value: &str) -> Option<String> {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => out.push('\n'),
+            c @ ('\\' | ';') => out.push(c),
+            _ => return None,
+        }
+    }
+    Some(out)
+}
+
+/// Split `s` at every `;` that is not escaped with a `\`.
+fn split_unescaped(s: &str) -> impl Iterator<Item = &str> {
+    let mut escaped = false;
+    s.split(move |c| {
+        let split = !escaped && c == ';';
+        escaped = !escaped && c == '\\';
+        split
+    })
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn roundtrip() {
+        let when = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let md = KeyMetadata::new()
+            .with_created_at(when)
+            .with_created_by("arti-keygen/1.0")
+            .with_origin("/var/lib/tor/hs/hs_ed25519_secret_key")
+            .with_comment("backup; do not \\ lose\nsecond line");
+
+        let comment = md.to_openssh_comment();
+        assert_eq!(
+            comment,
+            "created=2023-11-14T22:13:20Z; by=arti-keygen/1.0; \
+             origin=/var/lib/tor/hs/hs_ed25519_secret_key; \
+             comment=backup\\; do not \\\\ lose\\nsecond line"
+        );
+        assert_eq!(KeyMetadata::from_openssh_comment(&comment), md);
+
+        let md = KeyMetadata::new().with_comment("");
+        assert_eq!(
+            KeyMetadata::from_openssh_comment(&md.to_openssh_comment()),
+            md
+        );
+
+        assert_eq!(KeyMetadata::new().to_openssh_comment(), "");
+        assert_eq!(KeyMetadata::from_openssh_comment(""), KeyMetadata::new());
+    }
+
+    #[test]
+    fn generated() {
+        let md = KeyMetadata::new_generated();
+        assert!(md.created_at().is_some());
+        assert!(md.created_by().unwrap().starts_with("tor-keymgr/"));
+        assert_eq!(md.origin(), None);
+        assert_eq!(md.comment(), None);
+    }
+
+    #[test]
+    fn foreign_comment() {
+        for c in [
+            "user@host",
+            "x=y",
+            "created=yesterday",
+            "comment=bad\\escape",
+        ] {
+            assert_eq!(
+                KeyMetadata::from_openssh_comment(c),
+                KeyMetadata::new().with_comment(c),
+            );
+        }
+    }
+}
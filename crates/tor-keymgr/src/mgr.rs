@@ -2,16 +2,20 @@
 //!
 //! See the [`KeyMgr`] docs for more details.
 
+use crate::keystore::arti::ssh::UnparsedOpenSshKey;
 use crate::{
-    BoxedKeystore, EncodableKey, KeyPath, KeyPathError, KeyPathInfo, KeyPathInfoExtractor,
-    KeyPathPattern, KeySpecifier, KeyType, Keygen, KeygenRng, KeystoreId, KeystoreSelector, Result,
-    ToEncodableKey,
+    ArtiPath, BoxedKeystore, EncodableKey, ErasedKey, KeyDerivationRecord, KeyMetadata, KeyPath,
+    KeyPathError, KeyPathInfo, KeyPathInfoExtractor, KeyPathPattern, KeySpecifier, KeyType, Keygen,
+    KeygenRng, KeystoreId, KeystoreSelector, Result, ToEncodableKey,
 };
+#[cfg(feature = "key-derivation")]
+use crate::{DerivableKey, MasterSeed};
 
 use itertools::Itertools;
 use std::iter;
 use std::result::Result as StdResult;
 use tor_error::{bad_api_usage, internal};
+use zeroize::Zeroizing;
 
 /// A key manager that acts as a frontend to a default [`Keystore`](crate::Keystore) and
 /// any number of secondary [`Keystore`](crate::Keystore)s.
@@ -19,10 +23,44 @@ use tor_error::{bad_api_usage, internal};
 /// Note: [`KeyMgr`] is a low-level utility and does not implement caching (the key stores are
 /// accessed for every read/write).
 ///
+/// ## Key store precedence
+///
 /// The `KeyMgr` accessors - currently just [`get()`](KeyMgr::get) -
 /// search the configured key stores in order: first the default key store,
-/// and then the secondary stores, in order.
+/// and then the secondary stores, in the order they were added to the [`KeyMgrBuilder`].
+/// The first key store that contains the requested key wins,
+/// so a key in the default key store shadows any key with the same [`KeyPath`]
+/// from the secondary stores.
+/// [`list_matching()`](KeyMgr::list_matching) returns the entries of all the key stores,
+/// in the same order; each [`KeystoreEntry`] says which key store it came from.
+///
+/// ## Read-only key stores
+///
+/// Secondary key stores may be [read-only](crate::Keystore::is_read_only)
+/// (for example, a system-wide key store whose keys were installed by a package).
+/// The default key store is the designated primary key store,
+/// and must be writable.
+///
+/// Operations that modify a key store ([`generate()`](KeyMgr::generate),
+/// [`insert()`](KeyMgr::insert), [`remove()`](KeyMgr::remove), etc.)
+/// act on the key store selected by their [`KeystoreSelector`];
+/// [`KeystoreSelector::Default`] selects the default key store.
+/// They return [`Error::ReadOnlyKeystore`](crate::Error::ReadOnlyKeystore)
+/// if the selected key store is read-only.
 ///
+/// ## Derived keys
+///
+/// With the `key-derivation` feature, a `KeyMgr` built with a master seed
+/// (see `KeyMgrBuilder::master_seed`) can derive keys from that seed
+/// (see `KeyMgr::derive`), instead of generating them at random.
+/// For a derived key, the key store holds only a [`KeyDerivationRecord`]:
+/// the accessors recompute the key from the seed whenever it is needed,
+/// so backing up the seed is enough to recover every derived key.
+///
+/// A key stored in a key store shadows a derived key with the same [`KeyPath`]
+/// in the same key store.
+/// Using a derived key with a `KeyMgr` that has no master seed
+/// is an error ([`Error::NoMasterSeed`](crate::Error::NoMasterSeed)).
 ///
 /// ## Concurrent key store access
 ///
@@ -50,6 +88,10 @@ pub struct KeyMgr {
     /// using `inventory`.
     #[builder(default, setter(skip))]
     key_info_extractors: Vec<&'static dyn KeyPathInfoExtractor>,
+    /// The seed from which [derived](KeyMgr::derive) keys are computed, if any.
+    #[cfg(feature = "key-derivation")]
+    #[builder(default, setter(strip_option))]
+    master_seed: Option<MasterSeed>,
 }
 
 /// A keystore entry descriptor.
@@ -64,6 +106,9 @@ pub struct KeystoreEntry<'a> {
     /// The [`KeyPath`] of the key.
     key_path: KeyPath,
     /// The [`KeyType`] of the key.
+    ///
+    /// For a key derived from a master seed, this is the type of the derived key
+    /// (not [`KeyType::KeyDerivationRecord`]).
     key_type: KeyType,
     /// The [`KeystoreId`] that of the keystore where the key was found.
     #[getter(as_copy)]
@@ -72,9 +117,30 @@ pub struct KeystoreEntry<'a> {
 
 impl KeyMgrBuilder {
     /// Construct a [`KeyMgr`] from this builder.
+    ///
+    /// Returns an error if the default key store is read-only,
+    /// or if two of the key stores have the same [`KeystoreId`].
     pub fn build(self) -> StdResult<KeyMgr, KeyMgrBuilderError> {
         let mut keymgr = self.build_unvalidated()?;
 
+        if keymgr.default_store.is_read_only() {
+            return Err(KeyMgrBuilderError::ValidationError(format!(
+                "the default keystore ({}) must not be read-only",
+                keymgr.default_store.id()
+            )));
+        }
+
+        if let Some(id) = keymgr
+            .all_stores()
+            .map(|store| store.id())
+            .duplicates()
+            .next()
+        {
+            return Err(KeyMgrBuilderError::ValidationError(format!(
+                "multiple keystores with ID {id}"
+            )));
+        }
+
         keymgr.key_info_extractors = inventory::iter::<&'static dyn KeyPathInfoExtractor>
             .into_iter()
             .copied()
@@ -179,9 +245,15 @@ impl KeyMgr {
     ///
     /// On success, this function returns the newly generated key.
     ///
+    /// The key is stored along with [`KeyMetadata::new_generated`],
+    /// recording when and by what it was created.
+    ///
     /// Returns [`Error::KeyAlreadyExists`](crate::Error::KeyAlreadyExists)
     /// if the key already exists in the specified key store and `overwrite` is `false`.
     ///
+    /// Returns [`Error::ReadOnlyKeystore`](crate::Error::ReadOnlyKeystore)
+    /// if the specified key store is read-only.
+    ///
     /// **IMPORTANT**: using this function concurrently with any other `KeyMgr` operation that
     /// mutates the key store state is **not** recommended, as it can yield surprising results! The
     /// outcome of [`KeyMgr::generate`] depends on whether the selected key store
@@ -203,12 +275,12 @@ impl KeyMgr {
         K: ToEncodableKey,
         K::Key: Keygen,
     {
-        let store = self.select_keystore(&selector)?;
+        let store = self.select_writable_keystore(&selector)?;
         let key_type = K::Key::key_type();
 
         if overwrite || !store.contains(key_spec, &key_type)? {
             let key = K::Key::generate(rng)?;
-            store.insert(&key, key_spec, &key_type)?;
+            store.insert_with_metadata(&key, key_spec, &key_type, &KeyMetadata::new_generated())?;
 
             Ok(K::from_encodable_key(key))
         } else {
@@ -216,6 +288,79 @@ impl KeyMgr {
         }
     }
 
+    /// Derive the key of type `K` identified by `key_spec` from the master seed,
+    /// and record in the key store specified by `selector` that it is derived.
+    ///
+    /// Unlike [`generate()`](KeyMgr::generate), this does not store the key itself:
+    /// the key store only holds a [`KeyDerivationRecord`],
+    /// from which the accessors recompute the key as needed.
+    /// The [`ArtiPath`] of `key_spec` is used as the derivation path,
+    /// so a given seed always yields the same key for the same `key_spec`.
+    ///
+    /// If the key already exists in the specified key store (whether stored or derived),
+    /// the `overwrite` flag is used to decide whether to replace it with the derived key.
+    ///
+    /// On success, this function returns the derived key.
+    ///
+    /// Returns [`Error::NoMasterSeed`](crate::Error::NoMasterSeed)
+    /// if this `KeyMgr` has no master seed,
+    /// and [`Error::KeyAlreadyExists`](crate::Error::KeyAlreadyExists)
+    /// if the key already exists in the specified key store and `overwrite` is `false`.
+    ///
+    /// Like [`KeyMgr::generate`], this function suffers from a TOCTOU race
+    /// if it is used concurrently with other operations that mutate the same key.
+    #[cfg(feature = "key-derivation")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "key-derivation")))]
+    pub fn derive<K>(
+        &self,
+        key_spec: &dyn KeySpecifier,
+        selector: KeystoreSelector,
+        overwrite: bool,
+    ) -> Result<K>
+    where
+        K: ToEncodableKey,
+        K::Key: DerivableKey,
+    {
+        let path = key_spec
+            .arti_path()
+            .map_err(|e| bad_api_usage!("cannot derive a key without an ArtiPath: {e}"))?;
+        let seed = self
+            .master_seed
+            .as_ref()
+            .ok_or_else(|| crate::Error::NoMasterSeed(path.clone()))?;
+        let store = self.select_writable_keystore(&selector)?;
+        let key_type = K::Key::key_type();
+
+        if store.contains(key_spec, &key_type)? {
+            if !overwrite {
+                return Err(crate::Error::KeyAlreadyExists);
+            }
+            // The stored key would shadow the derived one.
+            store.remove(key_spec, &key_type)?;
+        } else if !overwrite && store.contains(key_spec, &KeyType::KeyDerivationRecord)? {
+            return Err(crate::Error::KeyAlreadyExists);
+        }
+
+        let record = KeyDerivationRecord::new(key_type, path);
+        let key = crate::derivation::derive_key::<K::Key>(seed, &record);
+        store.insert_with_metadata(
+            &record,
+            key_spec,
+            &KeyType::KeyDerivationRecord,
+            &KeyMetadata::new_generated(),
+        )?;
+
+        Ok(K::from_encodable_key(key))
+    }
+
+    /// Whether this `KeyMgr` has a master seed,
+    /// from which it can [derive](KeyMgr::derive) keys.
+    #[cfg(feature = "key-derivation")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "key-derivation")))]
+    pub fn has_master_seed(&self) -> bool {
+        self.master_seed.is_some()
+    }
+
     /// Insert `key` into the [`Keystore`](crate::Keystore) specified by `selector`.
     ///
     /// If this key is not already in the keystore, `None` is returned.
@@ -224,18 +369,35 @@ impl KeyMgr {
     /// and the old value is returned.
     ///
     /// Returns an error if the selected keystore is not the default keystore or one of the
-    /// configured secondary stores.
+    /// configured secondary stores, or if it is read-only.
     pub fn insert<K: ToEncodableKey>(
         &self,
         key: K,
         key_spec: &dyn KeySpecifier,
         selector: KeystoreSelector,
+    ) -> Result<Option<K>> {
+        self.insert_with_metadata(key, key_spec, selector, &KeyMetadata::default())
+    }
+
+    /// Insert `key` into the [`Keystore`](crate::Keystore) specified by `selector`,
+    /// recording `metadata` alongside it.
+    ///
+    /// This is like [`KeyMgr::insert`], except it lets the caller describe the key
+    /// (for example, the [origin](KeyMetadata::with_origin) of an imported key).
+    ///
+    /// Key stores that cannot record metadata ignore `metadata`.
+    pub fn insert_with_metadata<K: ToEncodableKey>(
+        &self,
+        key: K,
+        key_spec: &dyn KeySpecifier,
+        selector: KeystoreSelector,
+        metadata: &KeyMetadata,
     ) -> Result<Option<K>> {
         let key = key.to_encodable_key();
-        let store = self.select_keystore(&selector)?;
+        let store = self.select_writable_keystore(&selector)?;
         let key_type = K::Key::key_type();
         let old_key: Option<K> = self.get_from_store(key_spec, &key_type, [store].into_iter())?;
-        let () = store.insert(&key, key_spec, &key_type)?;
+        let () = store.insert_with_metadata(&key, key_spec, &key_type, metadata)?;
 
         Ok(old_key)
     }
@@ -244,22 +406,27 @@ impl KeyMgr {
     /// specified by `selector`.
     ///
     /// Returns an error if the selected keystore is not the default keystore or one of the
-    /// configured secondary stores.
+    /// configured secondary stores, or if it is read-only.
     ///
     /// Returns the value of the removed key,
     /// or `Ok(None)` if the key does not exist in the requested keystore.
     ///
+    /// If the key is derived from a master seed, its [`KeyDerivationRecord`] is removed.
+    ///
     /// Returns `Err` if an error occurred while trying to remove the key.
     pub fn remove<K: ToEncodableKey>(
         &self,
         key_spec: &dyn KeySpecifier,
         selector: KeystoreSelector,
     ) -> Result<Option<K>> {
-        let store = self.select_keystore(&selector)?;
+        let store = self.select_writable_keystore(&selector)?;
         let key_type = K::Key::key_type();
         let old_key: Option<K> = self.get_from_store(key_spec, &key_type, [store].into_iter())?;
 
         store.remove(key_spec, &key_type)?;
+        if Self::derivation_record(store, key_spec, &key_type)?.is_some() {
+            store.remove(key_spec, &KeyType::KeyDerivationRecord)?;
+        }
 
         Ok(old_key)
     }
@@ -267,6 +434,7 @@ impl KeyMgr {
     /// Remove the specified keystore entry.
     ///
     /// Like [`KeyMgr::remove`], except this function does not return the value of the removed key.
+    /// As with [`KeyMgr::remove`], removing a derived key removes its [`KeyDerivationRecord`].
     ///
     /// A return value of `Ok(None)` indicates the key was not found in the specified key store,
     /// whereas `Ok(Some(())` means the key was successfully removed.
@@ -277,25 +445,55 @@ impl KeyMgr {
     // to Result<Option<ErasedKey>>.
     pub fn remove_entry(&self, entry: &KeystoreEntry) -> Result<Option<()>> {
         let selector = entry.keystore_id().into();
-        let store = self.select_keystore(&selector)?;
+        let store = self.select_writable_keystore(&selector)?;
+
+        let removed = store.remove(entry.key_path(), entry.key_type())?;
+        if Self::derivation_record(store, entry.key_path(), entry.key_type())?.is_some() {
+            let record = store.remove(entry.key_path(), &KeyType::KeyDerivationRecord)?;
+            return Ok(removed.or(record));
+        }
 
-        store.remove(entry.key_path(), entry.key_type())
+        Ok(removed)
     }
 
     /// Return the keystore entry descriptors of the keys matching the specified [`KeyPathPattern`].
     ///
     /// NOTE: This searches for matching keys in _all_ keystores.
+    ///
+    /// A key derived from a master seed is listed with the type of the derived key,
+    /// unless a key of that type stored in the same key store shadows it.
+    /// (A [`KeyDerivationRecord`] that cannot be read is listed as such,
+    /// so that [`KeyMgr::check_matching`] can report it.)
     pub fn list_matching(&self, pat: &KeyPathPattern) -> Result<Vec<KeystoreEntry>> {
         self.all_stores()
             .map(|store| -> Result<Vec<_>> {
-                Ok(store
-                    .list()?
-                    .into_iter()
-                    .filter(|(key_path, _): &(KeyPath, KeyType)| key_path.matches(pat).is_some())
-                    .map(|(path, key_type)| KeystoreEntry {
-                        key_path: path.clone(),
-                        key_type,
-                        keystore_id: store.id(),
+                let list = store.list()?;
+                Ok(list
+                    .iter()
+                    .filter(|(key_path, _): &&(KeyPath, KeyType)| key_path.matches(pat).is_some())
+                    .filter_map(|(path, key_type)| {
+                        let key_type = match key_type {
+                            KeyType::KeyDerivationRecord => {
+                                match Self::read_derivation_record(store, path) {
+                                    Ok(Some(record)) => {
+                                        let key_type = record.key_type().clone();
+                                        if list.contains(&(path.clone(), key_type.clone())) {
+                                            return None;
+                                        }
+                                        key_type
+                                    }
+                                    // The record was removed after we listed it.
+                                    Ok(None) => return None,
+                                    Err(_) => KeyType::KeyDerivationRecord,
+                                }
+                            }
+                            key_type => key_type.clone(),
+                        };
+                        Some(KeystoreEntry {
+                            key_path: path.clone(),
+                            key_type,
+                            keystore_id: store.id(),
+                        })
                     })
                     .collect::<Vec<_>>())
             })
@@ -303,6 +501,117 @@ impl KeyMgr {
             .collect::<Result<Vec<_>>>()
     }
 
+    /// Return the metadata recorded for the specified keystore entry.
+    ///
+    /// Returns `Ok(None)` if the key store no longer contains the entry.
+    ///
+    /// Keys that were stored without metadata (for example, by older versions of Arti)
+    /// have an empty [`KeyMetadata`].
+    /// The metadata of a derived key is that of its [`KeyDerivationRecord`].
+    pub fn get_entry_metadata(&self, entry: &KeystoreEntry) -> Result<Option<KeyMetadata>> {
+        let selector = entry.keystore_id().into();
+        let store = self.select_keystore(&selector)?;
+        Self::metadata_from_store(store, entry.key_path(), entry.key_type())
+    }
+
+    /// Export the specified keystore entry as an OpenSSH-formatted key.
+    ///
+    /// The key's [`KeyMetadata`] is recorded in the comment field,
+    /// so that it is preserved by [`KeyMgr::import_entry`].
+    ///
+    /// Returns `Ok(None)` if the key store no longer contains the entry,
+    /// and [`Error::NotOpenSshKey`](crate::Error::NotOpenSshKey) if the entry is a certificate.
+    ///
+    /// **IMPORTANT**: if the entry is a private key,
+    /// the returned string contains the unencrypted key material.
+    pub fn export_entry(&self, entry: &KeystoreEntry) -> Result<Option<Zeroizing<String>>> {
+        let selector = entry.keystore_id().into();
+        let store = self.select_keystore(&selector)?;
+        let Some(key) = self.get_erased(store, entry.key_path(), entry.key_type())? else {
+            return Ok(None);
+        };
+        let metadata = Self::metadata_from_store(store, entry.key_path(), entry.key_type())?
+            .unwrap_or_default();
+        let openssh = key
+            .as_ssh_key_data()?
+            .to_openssh_string(&metadata.to_openssh_comment())?;
+
+        Ok(Some(Zeroizing::new(openssh)))
+    }
+
+    /// Import an OpenSSH-formatted key, as returned by [`KeyMgr::export_entry`],
+    /// into the [`Keystore`](crate::Keystore) specified by `selector`.
+    ///
+    /// The key is stored at `key_path`, with its metadata taken from its comment field.
+    /// Any existing key with the same path and type is replaced.
+    ///
+    /// Returns an error if `openssh` is not a valid key of type `key_type`,
+    /// or if the selected keystore is read-only.
+    pub fn import_entry(
+        &self,
+        key_path: &ArtiPath,
+        key_type: &KeyType,
+        openssh: &str,
+        selector: KeystoreSelector,
+    ) -> Result<()> {
+        let (store, key, metadata) = self.parse_import(key_path, key_type, openssh, &selector)?;
+        store.insert_with_metadata(&*key, key_path, key_type, &metadata)
+    }
+
+    /// Check whether [`KeyMgr::import_entry`] would accept the specified key,
+    /// without importing it.
+    ///
+    /// This lets a caller check a whole set of keys before importing any of them.
+    pub fn check_import_entry(
+        &self,
+        key_path: &ArtiPath,
+        key_type: &KeyType,
+        openssh: &str,
+        selector: KeystoreSelector,
+    ) -> Result<()> {
+        self.parse_import(key_path, key_type, openssh, &selector)
+            .map(|_| ())
+    }
+
+    /// Helper for `import_entry` and `check_import_entry`:
+    /// select the keystore, and parse the key and its metadata.
+    fn parse_import(
+        &self,
+        key_path: &ArtiPath,
+        key_type: &KeyType,
+        openssh: &str,
+        selector: &KeystoreSelector,
+    ) -> Result<(&BoxedKeystore, ErasedKey, KeyMetadata)> {
+        let store = self.select_writable_keystore(selector)?;
+        let unparsed = || UnparsedOpenSshKey::new(openssh.to_owned(), key_path.to_string().into());
+        let key = unparsed().parse_ssh_format_erased(key_type)?;
+        let metadata = KeyMetadata::from_openssh_comment(&unparsed().parse_comment(key_type)?);
+        Ok((store, key, metadata))
+    }
+
+    /// Check that every key matching the specified [`KeyPathPattern`] can be read and parsed.
+    ///
+    /// Returns the entries that could not be loaded, along with the reason.
+    ///
+    /// Returns an error if any of the keystores could not be listed.
+    ///
+    /// NOTE: This checks the matching keys in _all_ keystores.
+    pub fn check_matching(
+        &self,
+        pat: &KeyPathPattern,
+    ) -> Result<Vec<(KeystoreEntry, crate::Error)>> {
+        let mut bad = vec![];
+        for entry in self.list_matching(pat)? {
+            let store = self.select_keystore(&entry.keystore_id().into())?;
+            match self.get_erased(store, entry.key_path(), entry.key_type()) {
+                // Ok(None) means the key was removed after we listed it, which is fine.
+                Ok(_) => {}
+                Err(e) => bad.push((entry, e)),
+            }
+        }
+        Ok(bad)
+    }
+
     /// Describe the specified key.
     ///
     /// Returns [`KeyPathError::Unrecognized`] if none of the registered
@@ -341,7 +650,7 @@ impl KeyMgr {
         }
 
         for store in stores {
-            let key = match store.get(key_spec, &K::Key::key_type()) {
+            let key = match self.get_erased(store, key_spec, key_type) {
                 Ok(None) => {
                     // The key doesn't exist in this store, so we check the next one...
                     continue;
@@ -365,6 +674,88 @@ impl KeyMgr {
         Ok(None)
     }
 
+    /// Read the key identified by `key_spec` and `key_type` from `store`,
+    /// recomputing it from the master seed if `store` says that it is derived.
+    ///
+    /// Returns `Ok(None)` if `store` has neither the key nor a record of its derivation.
+    fn get_erased(
+        &self,
+        store: &BoxedKeystore,
+        key_spec: &dyn KeySpecifier,
+        key_type: &KeyType,
+    ) -> Result<Option<ErasedKey>> {
+        match store.get(key_spec, key_type)? {
+            Some(key) => Ok(Some(key)),
+            None => self.get_derived(store, key_spec, key_type),
+        }
+    }
+
+    /// Return the metadata of the key identified by `key_spec` and `key_type` from `store`,
+    /// or that of its [`KeyDerivationRecord`], if `store` says that it is derived.
+    fn metadata_from_store(
+        store: &BoxedKeystore,
+        key_spec: &dyn KeySpecifier,
+        key_type: &KeyType,
+    ) -> Result<Option<KeyMetadata>> {
+        if let Some(metadata) = store.metadata(key_spec, key_type)? {
+            return Ok(Some(metadata));
+        }
+        if Self::derivation_record(store, key_spec, key_type)?.is_some() {
+            return store.metadata(key_spec, &KeyType::KeyDerivationRecord);
+        }
+
+        Ok(None)
+    }
+
+    /// Recompute the key identified by `key_spec` and `key_type`,
+    /// if `store` has a record saying that it is derived from a master seed.
+    ///
+    /// Returns `Ok(None)` if `store` has no such record.
+    fn get_derived(
+        &self,
+        store: &BoxedKeystore,
+        key_spec: &dyn KeySpecifier,
+        key_type: &KeyType,
+    ) -> Result<Option<ErasedKey>> {
+        let Some(record) = Self::derivation_record(store, key_spec, key_type)? else {
+            return Ok(None);
+        };
+
+        #[cfg(feature = "key-derivation")]
+        if let Some(seed) = &self.master_seed {
+            return crate::derivation::derive_erased(seed, &record).map(Some);
+        }
+
+        Err(crate::Error::NoMasterSeed(record.path().clone()))
+    }
+
+    /// Return the record saying that the key identified by `key_spec` and `key_type`
+    /// is derived from a master seed, if `store` has one.
+    fn derivation_record(
+        store: &BoxedKeystore,
+        key_spec: &dyn KeySpecifier,
+        key_type: &KeyType,
+    ) -> Result<Option<KeyDerivationRecord>> {
+        let record = Self::read_derivation_record(store, key_spec)?;
+
+        Ok(record.filter(|record| record.key_type() == key_type))
+    }
+
+    /// Return the [`KeyDerivationRecord`] stored at `key_spec` in `store`, if there is one.
+    fn read_derivation_record(
+        store: &BoxedKeystore,
+        key_spec: &dyn KeySpecifier,
+    ) -> Result<Option<KeyDerivationRecord>> {
+        let Some(record) = store.get(key_spec, &KeyType::KeyDerivationRecord)? else {
+            return Ok(None);
+        };
+        let record = record
+            .downcast::<KeyDerivationRecord>()
+            .map_err(|_| internal!("failed to downcast key derivation record"))?;
+
+        Ok(Some(*record))
+    }
+
     /// Return an iterator over all configured stores.
     fn all_stores(&self) -> impl Iterator<Item = &BoxedKeystore> {
         iter::once(&self.default_store).chain(self.secondary_stores.iter())
@@ -381,6 +772,20 @@ impl KeyMgr {
         }
     }
 
+    /// Return the [`Keystore`](crate::Keystore) matching the specified `selector`,
+    /// if it is writable.
+    ///
+    /// Like [`KeyMgr::select_keystore`], except this also returns an error
+    /// if the selected keystore is read-only.
+    fn select_writable_keystore(&self, selector: &KeystoreSelector) -> Result<&BoxedKeystore> {
+        let store = self.select_keystore(selector)?;
+        if store.is_read_only() {
+            return Err(crate::Error::ReadOnlyKeystore(store.id().clone()));
+        }
+
+        Ok(store)
+    }
+
     /// Return the [`Keystore`](crate::Keystore) with the specified `id`.
     ///
     /// Returns an error if the specified ID is not the ID of the default keystore or
@@ -515,6 +920,10 @@ mod tests {
 
     macro_rules! impl_keystore {
         ($name:tt, $id:expr) => {
+            impl_keystore!($name, $id, false);
+        };
+
+        ($name:tt, $id:expr, $read_only:expr) => {
             struct $name {
                 inner: RwLock<HashMap<(ArtiPath, KeyType), TestKey>>,
                 id: KeystoreId,
@@ -529,7 +938,7 @@ mod tests {
                 }
             }
 
-            #[allow(dead_code)] // this is only dead code for Keystore1
+            #[allow(dead_code)] // this is dead code for some of the test keystores
             impl $name {
                 fn new_boxed() -> BoxedKeystore {
                     Box::<Self>::default()
@@ -553,6 +962,10 @@ mod tests {
                     &self.id
                 }
 
+                fn is_read_only(&self) -> bool {
+                    $read_only
+                }
+
                 fn get(
                     &self,
                     key_spec: &dyn KeySpecifier,
@@ -634,6 +1047,7 @@ mod tests {
     impl_keystore!(Keystore1, "keystore1");
     impl_keystore!(Keystore2, "keystore2");
     impl_keystore!(Keystore3, "keystore3");
+    impl_keystore!(ReadOnlyKeystore, "read_only_keystore", true);
 
     impl_specifier!(TestKeySpecifier1, "spec1");
     impl_specifier!(TestKeySpecifier2, "spec2");
@@ -966,4 +1380,219 @@ mod tests {
         assert!(mgr.get_entry::<TestKey>(&entry_desc2).unwrap().is_none());
         assert!(mgr.remove_entry(&entry_desc2).unwrap().is_none());
     }
+
+    #[test]
+    fn read_only_stores() {
+        // Provision the read-only store with a key, as a package would
+        let read_only = ReadOnlyKeystore::default();
+        read_only.inner.write().unwrap().insert(
+            (TestKeySpecifier1.arti_path().unwrap(), TestKey::key_type()),
+            TestKey::new("read_only_kittiwake"),
+        );
+
+        let mut builder = KeyMgrBuilder::default().default_store(Box::<Keystore1>::default());
+        builder
+            .secondary_stores()
+            .extend([Box::new(read_only) as BoxedKeystore]);
+        let mgr = builder.build().unwrap();
+        let read_only_id = KeystoreId::from_str("read_only_keystore").unwrap();
+
+        // Keys from the read-only store are visible...
+        assert_eq!(
+            mgr.get::<TestKey>(&TestKeySpecifier1)
+                .unwrap()
+                .map(|k| k.meta),
+            Some("read_only_kittiwake".to_string())
+        );
+
+        // ...but it cannot be modified.
+        let err = mgr
+            .insert(
+                TestKey::new("coot"),
+                &TestKeySpecifier2,
+                KeystoreSelector::Id(&read_only_id),
+            )
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::ReadOnlyKeystore(ref id) if id == &read_only_id));
+        assert!(mgr
+            .remove::<TestKey>(&TestKeySpecifier1, KeystoreSelector::Id(&read_only_id))
+            .is_err());
+        assert!(mgr
+            .generate::<TestKey>(
+                &TestKeySpecifier2,
+                KeystoreSelector::Id(&read_only_id),
+                &mut testing_rng(),
+                true,
+            )
+            .is_err());
+        let entry = entry_descriptor(TestKeySpecifier1, &read_only_id);
+        assert!(mgr.remove_entry(&entry).is_err());
+        assert!(mgr.get_entry::<TestKey>(&entry).unwrap().is_some());
+
+        // Writes to the default store shadow the key from the read-only store.
+        mgr.insert(
+            TestKey::new("coot"),
+            &TestKeySpecifier1,
+            KeystoreSelector::Default,
+        )
+        .unwrap();
+        assert_eq!(
+            mgr.get::<TestKey>(&TestKeySpecifier1)
+                .unwrap()
+                .map(|k| k.meta),
+            Some("keystore1_coot".to_string())
+        );
+
+        // Each listed entry says which store it came from.
+        let mut stores = mgr
+            .list_matching(&KeyPathPattern::Arti("*".into()))
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.keystore_id().to_string())
+            .collect::<Vec<_>>();
+        stores.sort();
+        assert_eq!(stores, ["keystore1", "read_only_keystore"]);
+    }
+
+    #[test]
+    #[cfg(feature = "key-derivation")]
+    fn derive() {
+        use crate::ArtiNativeKeystore;
+        use fs_mistrust::Mistrust;
+        use tor_hscrypto::pk::{HsIdKey, HsIdKeypair};
+
+        let dir = tempfile::tempdir().unwrap();
+        let open_store = || -> BoxedKeystore {
+            let mistrust = Mistrust::new_dangerously_trust_everyone();
+            Box::new(ArtiNativeKeystore::from_path_and_mistrust(dir.path(), &mistrust).unwrap())
+        };
+        let hs_id = |k: &HsIdKeypair| HsIdKey::from(k).id();
+        let seed = MasterSeed::from_bytes([3; 32]);
+
+        let mgr = KeyMgrBuilder::default()
+            .default_store(open_store())
+            .master_seed(seed.clone())
+            .build()
+            .unwrap();
+        let key: HsIdKeypair = mgr
+            .derive(&TestKeySpecifier1, KeystoreSelector::Default, false)
+            .unwrap();
+        assert!(matches!(
+            mgr.derive::<HsIdKeypair>(&TestKeySpecifier1, KeystoreSelector::Default, false),
+            Err(crate::Error::KeyAlreadyExists)
+        ));
+
+        assert!(mgr.has_master_seed());
+
+        // Only the derivation record is stored...
+        let record = KeyMgr::read_derivation_record(&mgr.default_store, &TestKeySpecifier1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.key_type(), &KeyType::Ed25519ExpandedKeypair);
+        assert_eq!(record.path().to_string(), "spec1");
+        assert!(mgr
+            .default_store
+            .get(&TestKeySpecifier1, &KeyType::Ed25519ExpandedKeypair)
+            .unwrap()
+            .is_none());
+
+        // ...but the key is listed (and can be read, and exported) as a key of its real type.
+        let entries = mgr
+            .list_matching(&KeyPathPattern::Arti("*".into()))
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key_type(), &KeyType::Ed25519ExpandedKeypair);
+        let got = mgr.get_entry::<HsIdKeypair>(&entries[0]).unwrap().unwrap();
+        assert_eq!(hs_id(&got), hs_id(&key));
+        assert!(mgr.get_entry_metadata(&entries[0]).unwrap().is_some());
+        assert!(mgr.export_entry(&entries[0]).unwrap().is_some());
+        assert!(mgr
+            .check_matching(&KeyPathPattern::Arti("*".into()))
+            .unwrap()
+            .is_empty());
+
+        // Another KeyMgr with the same seed recomputes the same key from the record...
+        let same_seed = KeyMgrBuilder::default()
+            .default_store(open_store())
+            .master_seed(seed.clone())
+            .build()
+            .unwrap();
+        let got: HsIdKeypair = same_seed.get(&TestKeySpecifier1).unwrap().unwrap();
+        assert_eq!(hs_id(&got), hs_id(&key));
+
+        // ...but a KeyMgr without a seed can't.
+        let no_seed = KeyMgrBuilder::default()
+            .default_store(open_store())
+            .build()
+            .unwrap();
+        assert!(matches!(
+            no_seed.get::<HsIdKeypair>(&TestKeySpecifier1),
+            Err(crate::Error::NoMasterSeed(_))
+        ));
+
+        // A different seed gives a different key.
+        let other_dir = tempfile::tempdir().unwrap();
+        let other_seed = KeyMgrBuilder::default()
+            .default_store(Box::new(
+                ArtiNativeKeystore::from_path_and_mistrust(
+                    other_dir.path(),
+                    &Mistrust::new_dangerously_trust_everyone(),
+                )
+                .unwrap(),
+            ))
+            .master_seed(MasterSeed::from_bytes([4; 32]))
+            .build()
+            .unwrap();
+        let other: HsIdKeypair = other_seed
+            .derive(&TestKeySpecifier1, KeystoreSelector::Default, false)
+            .unwrap();
+        assert_ne!(hs_id(&other), hs_id(&key));
+
+        // Removing the key removes its record.
+        let removed = mgr
+            .remove::<HsIdKeypair>(&TestKeySpecifier1, KeystoreSelector::Default)
+            .unwrap()
+            .unwrap();
+        assert_eq!(hs_id(&removed), hs_id(&key));
+        assert!(mgr
+            .get::<HsIdKeypair>(&TestKeySpecifier1)
+            .unwrap()
+            .is_none());
+        assert!(mgr
+            .list_matching(&KeyPathPattern::Arti("*".into()))
+            .unwrap()
+            .is_empty());
+
+        // So does removing its entry.
+        let _: HsIdKeypair = mgr
+            .derive(&TestKeySpecifier1, KeystoreSelector::Default, false)
+            .unwrap();
+        let entries = mgr
+            .list_matching(&KeyPathPattern::Arti("*".into()))
+            .unwrap();
+        assert_eq!(mgr.remove_entry(&entries[0]).unwrap(), Some(()));
+        assert!(mgr
+            .list_matching(&KeyPathPattern::Arti("*".into()))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn bad_store_configuration() {
+        // The default store must be writable.
+        let err = KeyMgrBuilder::default()
+            .default_store(Box::<ReadOnlyKeystore>::default())
+            .build()
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("must not be read-only"), "{err}");
+
+        // Keystore IDs must be unique.
+        let mut builder = KeyMgrBuilder::default().default_store(Box::<Keystore1>::default());
+        builder
+            .secondary_stores()
+            .extend([Keystore2::new_boxed(), Keystore1::new_boxed()]);
+        let err = builder.build().err().unwrap();
+        assert!(err.to_string().contains("multiple keystores"), "{err}");
+    }
 }
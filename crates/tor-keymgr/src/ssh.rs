@@ -12,10 +12,13 @@ pub(crate) const X25519_ALGORITHM_NAME: &str = "x25519@spec.torproject.org";
 /// See <https://spec.torproject.org/ssh-protocols.html>
 pub(crate) const ED25519_EXPANDED_ALGORITHM_NAME: &str = "ed25519-expanded@spec.torproject.org";
 
+/// The algorithm string for key derivation records, encoded as SSH public keys.
+pub(crate) const KEY_DERIVATION_ALGORITHM_NAME: &str = "key-derivation@spec.torproject.org";
+
 /// SSH key algorithms.
 //
 // Note: this contains all the types supported by ssh_key, plus variants representing
-// x25519 and expanded ed25519 keys.
+// x25519 keys, expanded ed25519 keys, and key derivation records.
 #[derive(Clone, Debug, PartialEq, derive_more::Display)]
 #[non_exhaustive]
 pub enum SshKeyAlgorithm {
@@ -29,6 +32,8 @@ pub enum SshKeyAlgorithm {
     Ed25519Expanded,
     /// X25519
     X25519,
+    /// Key derivation record
+    KeyDerivationRecord,
     /// RSA
     Rsa,
     /// FIDO/U2F key with ECDSA/NIST-P256 + SHA-256
@@ -51,6 +56,7 @@ impl From<Algorithm> for SshKeyAlgorithm {
             Algorithm::Other(name) => match name.as_str() {
                 X25519_ALGORITHM_NAME => SshKeyAlgorithm::X25519,
                 ED25519_EXPANDED_ALGORITHM_NAME => SshKeyAlgorithm::Ed25519Expanded,
+                KEY_DERIVATION_ALGORITHM_NAME => SshKeyAlgorithm::KeyDerivationRecord,
                 _ => SshKeyAlgorithm::Unknown(algo),
             },
             // Note: ssh_key::Algorithm is non_exhaustive, so we need this catch-all variant
@@ -34,6 +34,18 @@ where
     assert_eq!(&S::try_from(&KeyPath::Arti(apath)).unwrap(), spec, "{path}");
 }
 
+/// Try to parse `key` as an OpenSSH key of type `key_type`, discarding the result.
+///
+/// This is the parser that the Arti keystore applies to the contents of its key files.
+/// It is exposed here so that it can be fuzzed.
+#[cfg(feature = "keymgr")]
+pub fn parse_openssh_key(key: &str, key_type: &crate::KeyType) -> crate::Result<()> {
+    use crate::keystore::arti::ssh::UnparsedOpenSshKey;
+
+    let key = UnparsedOpenSshKey::new(key.to_string(), "<input>".into());
+    key.parse_ssh_format_erased(key_type).map(|_| ())
+}
+
 /// OpenSSH keys used for testing.
 #[cfg(test)]
 pub(crate) mod ssh_keys {
@@ -87,6 +87,47 @@ pub fn validate_all_sigs(v: &[Box<dyn ValidatableSignature>]) -> bool {
     ed_batch_is_valid && non_ed_sigs.iter().all(|b| b.is_valid())
 }
 
+/// Check the signatures of several independent objects at once.
+///
+/// Each element of `groups` holds the signatures of one object
+/// (for example, one router descriptor).
+/// Return a `Vec` with one entry per group, in the same order:
+/// `true` if every signature in that group is valid,
+/// and `false` otherwise.
+///
+/// This function should typically give the same result as calling
+/// [`validate_all_sigs`] on each group, while verifying the ed25519
+/// signatures of _all_ the groups in a single batch.
+/// If that batch fails, we fall back to checking each group separately,
+/// to find out which of them are invalid.
+///
+/// (See [`ed25519::validate_batch`] for caveats.)
+pub fn validate_sig_groups(groups: &[&[Box<dyn ValidatableSignature>]]) -> Vec<bool> {
+    let ed_sigs: Vec<_> = groups
+        .iter()
+        .flat_map(|group| group.iter())
+        .filter_map(|sig| sig.as_ed25519())
+        .collect();
+
+    if crate::pk::ed25519::validate_batch(&ed_sigs[..]) {
+        // All the ed25519 signatures are fine; we only need to check the others.
+        groups
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .filter(|sig| sig.as_ed25519().is_none())
+                    .all(|sig| sig.is_valid())
+            })
+            .collect()
+    } else {
+        groups
+            .iter()
+            .map(|group| validate_all_sigs(group))
+            .collect()
+    }
+}
+
 #[cfg(test)]
 mod test {
     // @@ begin test lint list maintained by maint/add_warning @@
@@ -333,9 +333,7 @@ impl ValidatableEd25519Signature {
 
 impl super::ValidatableSignature for ValidatableEd25519Signature {
     fn is_valid(&self) -> bool {
-        self.key
-            .verify(&self.entire_text_of_signed_thing[..], &self.sig)
-            .is_ok()
+        verify_one(&self.key, &self.entire_text_of_signed_thing[..], &self.sig)
     }
 
     fn as_ed25519(&self) -> Option<&ValidatableEd25519Signature> {
@@ -343,6 +341,61 @@ impl super::ValidatableSignature for ValidatableEd25519Signature {
     }
 }
 
+/// Check whether `sig` is a valid signature on `msg` by `key`.
+#[cfg(not(feature = "with-openssl"))]
+fn verify_one(key: &PublicKey, msg: &[u8], sig: &Signature) -> bool {
+    key.verify(msg, sig).is_ok()
+}
+
+/// How many OpenSSL public key objects we keep for reuse, in each thread.
+#[cfg(feature = "with-openssl")]
+const OPENSSL_KEY_CACHE_SIZE: usize = 64;
+
+#[cfg(feature = "with-openssl")]
+std::thread_local! {
+    /// OpenSSL public key objects for the keys that we have used recently.
+    ///
+    /// Building one of these is a noticeable part of the cost of a verification,
+    /// and we tend to check many signatures by the same few keys.
+    static OPENSSL_KEYS: std::cell::RefCell<
+        std::collections::HashMap<[u8; 32], openssl::pkey::PKey<openssl::pkey::Public>>,
+    > = Default::default();
+}
+
+/// Check whether `sig` is a valid signature on `msg` by `key`.
+///
+/// This version uses OpenSSL's Ed25519 implementation.  Any error from
+/// OpenSSL (for example, a public key that is not a valid point) is
+/// treated as a failed verification.
+#[cfg(feature = "with-openssl")]
+fn verify_one(key: &PublicKey, msg: &[u8], sig: &Signature) -> bool {
+    use openssl::pkey::{Id, PKey};
+    use openssl::sign::Verifier;
+    use std::collections::hash_map::Entry;
+
+    let key = key.to_bytes();
+    OPENSSL_KEYS.with(|keys| {
+        let mut keys = keys.borrow_mut();
+        if keys.len() >= OPENSSL_KEY_CACHE_SIZE && !keys.contains_key(&key) {
+            // Nothing cleverer than starting over seems worthwhile here.
+            keys.clear();
+        }
+        let pkey = match keys.entry(key) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => match PKey::public_key_from_raw_bytes(&key, Id::ED25519) {
+                Ok(pkey) => e.insert(pkey),
+                Err(_) => return false,
+            },
+        };
+        let Ok(mut verifier) = Verifier::new_without_digest(pkey) else {
+            return false;
+        };
+        verifier
+            .verify_oneshot(&sig.to_bytes(), msg)
+            .unwrap_or(false)
+    })
+}
+
 /// Perform a batch verification operation on the provided signatures
 ///
 /// Return `true` if _every_ signature is valid; otherwise return `false`.
@@ -354,6 +407,10 @@ impl super::ValidatableSignature for ValidatableEd25519Signature {
 /// signatures generated by a correct Ed25519 implementation will
 /// always pass both kinds of validation, and an attacker should not
 /// be able to forge a signature that passes either kind.)
+///
+/// OpenSSL has no batch verification API, so even when the `with-openssl`
+/// feature is enabled, we use `ed25519_dalek` to check batches of more
+/// than one signature: that is faster than checking them one at a time.
 pub fn validate_batch(sigs: &[&ValidatableEd25519Signature]) -> bool {
     use crate::pk::ValidatableSignature;
     if sigs.is_empty() {
@@ -388,3 +445,87 @@ impl Ed25519PublicKey for Keypair {
         self.as_ref()
     }
 }
+
+impl Ed25519PublicKey for ExpandedKeypair {
+    fn public_key(&self) -> &PublicKey {
+        self.public()
+    }
+}
+
+impl Signer<Signature> for ExpandedKeypair {
+    fn try_sign(&self, message: &[u8]) -> Result<Signature, signature::Error> {
+        Ok(ExpandedKeypair::sign(self, message))
+    }
+}
+
+#[cfg(all(test, feature = "with-openssl"))]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use rand::{Rng, RngCore};
+    use tor_basic_utils::test_rng::testing_rng;
+
+    /// Check that OpenSSL and `ed25519_dalek` agree about `sig`.
+    fn check_agree(key: &PublicKey, msg: &[u8], sig: &Signature) -> bool {
+        let dalek = key.verify(msg, sig).is_ok();
+        assert_eq!(verify_one(key, msg, sig), dalek);
+        dalek
+    }
+
+    #[test]
+    fn openssl_agrees_with_dalek() {
+        let mut rng = testing_rng();
+        let keys: Vec<Keypair> = (0..5).map(|_| Keypair::generate(&mut rng)).collect();
+
+        for n in 0..200 {
+            // Reuse keys, so that we also exercise the key cache.
+            let kp = &keys[n % keys.len()];
+            let mut msg = vec![0_u8; rng.gen_range(0..300)];
+            rng.fill_bytes(&mut msg);
+            let sig = kp.sign(&msg);
+            assert!(check_agree(&kp.verifying_key(), &msg, &sig));
+
+            // A corrupted signature.
+            let mut bad_sig = sig.to_bytes();
+            bad_sig[rng.gen_range(0..64)] ^= 1 << rng.gen_range(0..8);
+            let bad_sig = Signature::from_bytes(&bad_sig);
+            assert!(!check_agree(&kp.verifying_key(), &msg, &bad_sig));
+
+            // A different message.
+            msg.push(n as u8);
+            assert!(!check_agree(&kp.verifying_key(), &msg, &sig));
+
+            // A different key.
+            let other = &keys[(n + 1) % keys.len()];
+            assert!(!check_agree(
+                &other.verifying_key(),
+                &msg[..msg.len() - 1],
+                &sig
+            ));
+        }
+    }
+
+    #[test]
+    fn openssl_key_cache() {
+        // More keys than the cache holds.
+        let mut rng = testing_rng();
+        for _ in 0..OPENSSL_KEY_CACHE_SIZE * 2 + 1 {
+            let kp = Keypair::generate(&mut rng);
+            let sig = kp.sign(b"hello");
+            assert!(check_agree(&kp.verifying_key(), b"hello", &sig));
+        }
+        OPENSSL_KEYS.with(|keys| assert!(keys.borrow().len() <= OPENSSL_KEY_CACHE_SIZE));
+    }
+}
@@ -206,7 +206,17 @@ impl PrivateKey {
     pub fn from_der(der: &[u8]) -> Option<Self> {
         Some(PrivateKey(rsa::RsaPrivateKey::from_pkcs1_der(der).ok()?))
     }
-    // ....
+    /// Sign a hash using this key (as used in Tor).  The hash to sign
+    /// should be in `hashed`.
+    ///
+    /// As with [`PublicKey::verify`], this makes an RSA-PKCSv1 signature
+    /// with the hash algorithm OID omitted.
+    pub fn sign(&self, hashed: &[u8]) -> Result<Vec<u8>, signature::Error> {
+        let padding = rsa::pkcs1v15::Pkcs1v15Sign::new_unprefixed();
+        self.0
+            .sign(padding, hashed)
+            .map_err(|_| signature::Error::new())
+    }
 }
 impl PublicKey {
     /// Return true iff the exponent for this key is the same
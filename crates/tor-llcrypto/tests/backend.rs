@@ -0,0 +1,181 @@
+//! Conformance tests for the swappable cryptographic backends.
+//!
+//! Some of our primitives can come from more than one implementation,
+//! depending on which acceleration features are enabled (see the
+//! "Acceleration features" section of the crate documentation).  These
+//! tests only use the public API, so they exercise whichever backend
+//! was compiled in.  CI runs them with the default features and with
+//! `--all-features`, so that every backend has to give the same answers.
+
+#![allow(clippy::uninlined_format_args)]
+
+use cipher::{KeyIvInit, StreamCipher};
+use digest::Digest;
+use hex_literal::hex;
+use tor_llcrypto as ll;
+
+use ll::pk::ed25519::{
+    validate_batch, Keypair, PublicKey, Signature, Signer, ValidatableEd25519Signature,
+};
+use ll::pk::ValidatableSignature;
+
+/// Return a key, message, and signature from RFC 8032, section 7.1, TEST 3.
+fn rfc8032_test3() -> (PublicKey, Vec<u8>, Signature) {
+    let pk = PublicKey::from_bytes(&hex!(
+        "fc51cd8e6218a1a38da47ed00230f058
+         0816ed13ba3303ac5deb911548908025"
+    ))
+    .unwrap();
+    let sig = Signature::from_bytes(&hex!(
+        "6291d657deec24024827e69c3abe01a3
+         0ce548a284743a445e3680d7db5ac3ac
+         18ff9b538d16f290ae67f760984dc659
+         4a7c15e9716ed28dc027beceea1ec40a"
+    ));
+    (pk, hex!("af82").to_vec(), sig)
+}
+
+/// Secret keys, public keys, messages, and signatures from RFC 8032, section 7.1.
+const RFC8032_VECTORS: &[(&str, &str, &str, &str)] = &[
+    (
+        "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+        "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+        "",
+        "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+    ),
+    (
+        "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+        "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+        "72",
+        "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+    ),
+    (
+        "c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+        "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+        "af82",
+        "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a",
+    ),
+];
+
+#[test]
+fn ed25519_rfc8032() {
+    for (sk, pk, msg, sig) in RFC8032_VECTORS {
+        let sk: [u8; 32] = hex::decode(sk).unwrap().try_into().unwrap();
+        let pk: [u8; 32] = hex::decode(pk).unwrap().try_into().unwrap();
+        let msg = hex::decode(msg).unwrap();
+        let sig: [u8; 64] = hex::decode(sig).unwrap().try_into().unwrap();
+
+        // Key derivation and signing.
+        let kp = Keypair::from(&sk);
+        assert_eq!(kp.verifying_key().to_bytes(), pk);
+        assert_eq!(kp.sign(&msg).to_bytes(), sig);
+
+        // Verification, alone and in a batch.
+        let pk = PublicKey::from_bytes(&pk).unwrap();
+        let sig = Signature::from_bytes(&sig);
+        let v = ValidatableEd25519Signature::new(pk, sig, &msg);
+        assert!(v.is_valid());
+        assert!(validate_batch(&[&v, &v]));
+    }
+}
+
+#[test]
+fn ed25519_known_answer() {
+    let (pk, msg, sig) = rfc8032_test3();
+    assert!(ValidatableEd25519Signature::new(pk, sig, &msg).is_valid());
+
+    // Wrong message.
+    assert!(!ValidatableEd25519Signature::new(pk, sig, b"af83").is_valid());
+    assert!(!ValidatableEd25519Signature::new(pk, sig, b"").is_valid());
+
+    // Corrupted signature.
+    let mut bad_sig = sig.to_bytes();
+    bad_sig[5] ^= 0x10;
+    let bad_sig = Signature::from_bytes(&bad_sig);
+    assert!(!ValidatableEd25519Signature::new(pk, bad_sig, &msg).is_valid());
+
+    // Wrong key.
+    let other = Keypair::from(&[7_u8; 32]).verifying_key();
+    assert!(!ValidatableEd25519Signature::new(other, sig, &msg).is_valid());
+}
+
+#[test]
+fn ed25519_batch() {
+    let (pk, msg, sig) = rfc8032_test3();
+    let kp = Keypair::from(&[42_u8; 32]);
+
+    let mut good: Vec<ValidatableEd25519Signature> = (0_u8..5)
+        .map(|n| {
+            let msg = vec![n; usize::from(n) * 13];
+            ValidatableEd25519Signature::new(kp.verifying_key(), kp.sign(&msg), &msg)
+        })
+        .collect();
+    good.push(ValidatableEd25519Signature::new(pk, sig, &msg));
+
+    let refs: Vec<_> = good.iter().collect();
+    assert!(validate_batch(&[]));
+    assert!(validate_batch(&refs[..1]));
+    assert!(validate_batch(&refs[..]));
+
+    let bad = ValidatableEd25519Signature::new(pk, sig, b"not the message");
+    assert!(!validate_batch(&[&bad]));
+    let mut with_bad = refs.clone();
+    with_bad.insert(3, &bad);
+    assert!(!validate_batch(&with_bad[..]));
+}
+
+#[test]
+fn aes_ctr_streaming() {
+    // The keystream must not depend on how the input is split up.
+    use ll::cipher::aes::{Aes128Ctr, Aes256Ctr};
+
+    let k128 = hex!("2b7e151628aed2a6abf7158809cf4f3c");
+    let k256 = hex!(
+        "603deb1015ca71be2b73aef0857d7781
+         1f352c073b6108d72d9810a30914dff4"
+    );
+    let iv = hex!("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff");
+    let input: Vec<u8> = (0..1000_u32).map(|n| (n * 7 % 251) as u8).collect();
+
+    let mut whole128 = input.clone();
+    Aes128Ctr::new(&k128.into(), &iv.into()).apply_keystream(&mut whole128);
+    let mut whole256 = input.clone();
+    Aes256Ctr::new(&k256.into(), &iv.into()).apply_keystream(&mut whole256);
+    assert_ne!(whole128, input);
+    assert_ne!(whole128, whole256);
+
+    for chunk in [1, 3, 15, 16, 17, 509] {
+        let mut pieces128 = input.clone();
+        let mut c128 = Aes128Ctr::new(&k128.into(), &iv.into());
+        pieces128
+            .chunks_mut(chunk)
+            .for_each(|c| c128.apply_keystream(c));
+        assert_eq!(pieces128, whole128, "chunk size {}", chunk);
+
+        let mut pieces256 = input.clone();
+        let mut c256 = Aes256Ctr::new(&k256.into(), &iv.into());
+        pieces256
+            .chunks_mut(chunk)
+            .for_each(|c| c256.apply_keystream(c));
+        assert_eq!(pieces256, whole256, "chunk size {}", chunk);
+    }
+
+    // Applying the keystream twice gives back the input.
+    let mut again = whole128.clone();
+    Aes128Ctr::new(&k128.into(), &iv.into()).apply_keystream(&mut again);
+    assert_eq!(again, input);
+}
+
+#[test]
+fn sha1_streaming() {
+    // From RFC 3174.
+    let input = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+    let expected = hex!("84983E441C3BD26EBAAE4AA1F95129E5E54670F1");
+
+    assert_eq!(ll::d::Sha1::digest(input)[..], expected[..]);
+    for chunk in [1, 7, 64] {
+        let mut h = ll::d::Sha1::new();
+        input.chunks(chunk).for_each(|c| h.update(c));
+        assert_eq!(h.finalize()[..], expected[..], "chunk size {}", chunk);
+    }
+}
@@ -126,6 +126,48 @@ fn batch_verify() {
     assert!(!validate_batch(&sigrefs[..]));
 }
 
+#[test]
+fn batch_verify_groups() {
+    use ll::pk::ed25519::*;
+    use ll::pk::{validate_sig_groups, ValidatableSignature};
+    use tor_basic_utils::test_rng::testing_rng;
+
+    /// A signature that isn't an ed25519 signature.
+    struct OtherSig(bool);
+    impl ValidatableSignature for OtherSig {
+        fn is_valid(&self) -> bool {
+            self.0
+        }
+    }
+
+    let mut rng = testing_rng();
+    let kp = Keypair::generate(&mut rng);
+    let ed_sig = |msg: &[u8], signed: &[u8]| -> Box<dyn ValidatableSignature> {
+        let sig = kp.sign(signed);
+        Box::new(ValidatableEd25519Signature::new(
+            kp.verifying_key(),
+            sig,
+            msg,
+        ))
+    };
+
+    let good: Vec<Box<dyn ValidatableSignature>> =
+        vec![ed_sig(b"one", b"one"), ed_sig(b"two", b"two")];
+    let good_mixed = vec![ed_sig(b"three", b"three"), Box::new(OtherSig(true))];
+    let bad_other = vec![ed_sig(b"four", b"four"), Box::new(OtherSig(false))];
+    let bad_ed = vec![ed_sig(b"Apples", b"Oranges!"), Box::new(OtherSig(true))];
+
+    assert!(validate_sig_groups(&[]).is_empty());
+    assert_eq!(
+        validate_sig_groups(&[&good, &good_mixed, &bad_other]),
+        vec![true, true, false]
+    );
+    assert_eq!(
+        validate_sig_groups(&[&good, &bad_ed, &good_mixed, &bad_other]),
+        vec![true, false, true, false]
+    );
+}
+
 #[test]
 fn serde_rsaid() {
     use serde_test::{assert_tokens, Configure, Token};
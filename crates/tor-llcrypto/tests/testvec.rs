@@ -558,6 +558,14 @@ qiBHRBGbtkF/Re5pb438HC/CGyuujp43oZ3CUYosJOfY/X+sD0aVAgMBAAE";
     assert_eq!(public.to_der(), to_der(pk_pem));
     assert_eq!(public.bits(), 1024);
     assert!(public.exponent_is(65537));
+
+    let hashed = hex!("9b0cd7a6d7dd6f4a0bea2a3fd4b7fcd0a7a6d2b1");
+    let sig = secret.sign(&hashed).unwrap();
+    assert_eq!(sig.len(), 128);
+    assert!(public.verify(&hashed, &sig).is_ok());
+    assert!(public
+        .verify(&hex!("0000000000000000000000000000000000000000"), &sig)
+        .is_err());
     assert!(!public.exponent_is(3));
 
     let digest = hex!("8c28f494a3ca4522926b177124a51158a790bec0");
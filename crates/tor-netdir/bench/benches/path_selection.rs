@@ -0,0 +1,45 @@
+//! This is a wallclock time microbenchmark for weighted relay selection,
+//! using the Criterion framework.
+//!
+//! It runs against the fake network from `tor_netdir::testnet`, so the
+//! absolute numbers are small; what matters is how they change over time.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::{rngs::StdRng, SeedableRng};
+use std::hint::black_box;
+use tor_netdir::{testnet, WeightRole};
+
+fn path_selection_bench(c: &mut Criterion) {
+    let netdir = testnet::construct_netdir()
+        .unwrap_if_sufficient()
+        .expect("testnet netdir was not sufficient");
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut group = c.benchmark_group("pick_relay");
+
+    for (name, role) in [
+        ("guard", WeightRole::Guard),
+        ("middle", WeightRole::Middle),
+        ("exit", WeightRole::Exit),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter(|| black_box(netdir.pick_relay(&mut rng, role, |_| true)));
+        });
+    }
+
+    group.bench_function("exit-port-443", |b| {
+        b.iter(|| {
+            black_box(netdir.pick_relay(&mut rng, WeightRole::Exit, |r| {
+                r.low_level_details().supports_exit_port_ipv4(443)
+            }))
+        });
+    });
+
+    group.bench_function("n-middle-3", |b| {
+        b.iter(|| black_box(netdir.pick_n_relays(&mut rng, 3, WeightRole::Middle, |_| true)));
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, path_selection_bench);
+criterion_main!(benches);
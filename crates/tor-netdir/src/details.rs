@@ -49,11 +49,11 @@ pub struct RelayDetails<'a>(pub(crate) &'a super::Relay<'a>);
 impl<'a> RelayDetails<'a> {
     /// Return true if this relay allows exiting to `port` on IPv4.
     pub fn supports_exit_port_ipv4(&self, port: u16) -> bool {
-        self.ipv4_policy().allows_port(port)
+        !self.0.rs.is_flagged_bad_exit() && self.0.exit_ports.allows_ipv4(port)
     }
     /// Return true if this relay allows exiting to `port` on IPv6.
     pub fn supports_exit_port_ipv6(&self, port: u16) -> bool {
-        self.ipv6_policy().allows_port(port)
+        !self.0.rs.is_flagged_bad_exit() && self.0.exit_ports.allows_ipv6(port)
     }
     /// Return true if this relay is suitable for use as a directory
     /// cache.
@@ -74,6 +74,13 @@ impl<'a> RelayDetails<'a> {
     pub fn is_flagged_stable(&self) -> bool {
         self.0.rs.is_flagged_stable()
     }
+    /// Return all the flags that the authorities gave this relay.
+    ///
+    /// This is meant for describing the relay (for example, to a user);
+    /// to decide whether to use it for something, use a higher-level check.
+    pub fn flags(&self) -> netstatus::RelayFlags {
+        *self.0.rs.flags()
+    }
     /// Return true if this relay is a potential HS introduction point
     pub fn is_hs_intro_point(&self) -> bool {
         self.is_flagged_fast() && self.0.rs.is_flagged_stable()
@@ -0,0 +1,182 @@
+//! Precomputed bitmaps of the ports that each relay allows exiting to.
+//!
+//! A [`PortPolicy`] answers "does this policy allow port P?" with a binary
+//! search over its list of ranges.  That's cheap, but a busy proxy asks that
+//! question for every relay in the consensus, for every distinct target port,
+//! every time it picks an exit.  Here we expand each distinct policy into a
+//! 65536-bit bitmap once, when the microdescriptor is added to the
+//! [`NetDir`](crate::NetDir), so that each later check is a single bit lookup.
+//!
+//! Since there are only a few hundred distinct exit policies on the network,
+//! and relays that share a policy share its bitmap, this costs a few
+//! megabytes at most.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use tor_netdoc::doc::microdesc::Microdesc;
+use tor_netdoc::types::policy::PortPolicy;
+
+/// Number of bits in each word of a [`PortBitmap`].
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// Number of words in a [`PortBitmap`]: enough for one bit per possible port.
+const N_WORDS: usize = (u16::MAX as usize + 1) / WORD_BITS;
+
+/// The set of ports allowed by a [`PortPolicy`], stored as one bit per port.
+#[derive(Clone, Eq, PartialEq)]
+pub(crate) struct PortBitmap {
+    /// The bits themselves; bit `p % 64` of word `p / 64` is set if port `p`
+    /// is allowed.
+    ///
+    /// Always has exactly `N_WORDS` entries.
+    bits: Box<[u64]>,
+}
+
+impl PortBitmap {
+    /// Expand `policy` into a bitmap.
+    pub(crate) fn from_policy(policy: &PortPolicy) -> Self {
+        let mut bits = vec![0_u64; N_WORDS].into_boxed_slice();
+        if policy.allows_some_port() {
+            for port in 1..=u16::MAX {
+                if policy.allows_port(port) {
+                    let port = usize::from(port);
+                    bits[port / WORD_BITS] |= 1 << (port % WORD_BITS);
+                }
+            }
+        }
+        PortBitmap { bits }
+    }
+
+    /// Return true if `port` is allowed.
+    pub(crate) fn allows_port(&self, port: u16) -> bool {
+        let port = usize::from(port);
+        self.bits[port / WORD_BITS] & (1 << (port % WORD_BITS)) != 0
+    }
+}
+
+impl fmt::Debug for PortBitmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n_allowed: u32 = self.bits.iter().map(|w| w.count_ones()).sum();
+        f.debug_struct("PortBitmap")
+            .field("n_allowed", &n_allowed)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The precomputed exit ports for a single relay.
+#[derive(Clone, Debug)]
+pub(crate) struct ExitPorts {
+    /// Ports that the relay allows exiting to on IPv4.
+    ipv4: Arc<PortBitmap>,
+    /// Ports that the relay allows exiting to on IPv6.
+    ipv6: Arc<PortBitmap>,
+}
+
+impl ExitPorts {
+    /// Return true if these ports include `port` on IPv4.
+    pub(crate) fn allows_ipv4(&self, port: u16) -> bool {
+        self.ipv4.allows_port(port)
+    }
+
+    /// Return true if these ports include `port` on IPv6.
+    pub(crate) fn allows_ipv6(&self, port: u16) -> bool {
+        self.ipv6.allows_port(port)
+    }
+
+    /// Return true if these ports include `port`, for the address family of `addr`.
+    pub(crate) fn allows(&self, addr: &IpAddr, port: u16) -> bool {
+        match addr {
+            IpAddr::V4(_) => self.allows_ipv4(port),
+            IpAddr::V6(_) => self.allows_ipv6(port),
+        }
+    }
+}
+
+/// A cache mapping each distinct [`PortPolicy`] to its [`PortBitmap`].
+///
+/// Used while building a [`NetDir`](crate::NetDir), so that relays with the
+/// same policy share a single bitmap.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PortBitmapCache {
+    /// The bitmaps we've computed so far, indexed by the policy they came from.
+    bitmaps: HashMap<Arc<PortPolicy>, Arc<PortBitmap>>,
+}
+
+impl PortBitmapCache {
+    /// Return the bitmap for `policy`, computing it if we haven't seen
+    /// this policy before.
+    fn bitmap_for(&mut self, policy: &Arc<PortPolicy>) -> Arc<PortBitmap> {
+        Arc::clone(
+            self.bitmaps
+                .entry(Arc::clone(policy))
+                .or_insert_with(|| Arc::new(PortBitmap::from_policy(policy))),
+        )
+    }
+
+    /// Return the precomputed exit ports for the relay described by `md`.
+    pub(crate) fn exit_ports_for(&mut self, md: &Microdesc) -> ExitPorts {
+        ExitPorts {
+            ipv4: self.bitmap_for(md.ipv4_policy()),
+            ipv6: self.bitmap_for(md.ipv6_policy()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+
+    #[test]
+    fn bitmap_matches_policy() {
+        for s in [
+            "accept 80,443",
+            "accept 1-65535",
+            "reject 1-65535",
+            "reject 25,119,135-139,445,563,1214,4661-4666,6346-6429,6699,6881-6999",
+            "accept 1-1023,8000-8999,60000-65535",
+        ] {
+            let policy: PortPolicy = s.parse().unwrap();
+            let bitmap = PortBitmap::from_policy(&policy);
+            for port in 0..=u16::MAX {
+                assert_eq!(
+                    bitmap.allows_port(port),
+                    policy.allows_port(port),
+                    "{s} {port}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cache_shares_bitmaps() {
+        let mut cache = PortBitmapCache::default();
+        let p1 = Arc::new("accept 80,443".parse::<PortPolicy>().unwrap());
+        let p2 = Arc::new("accept 80,443".parse::<PortPolicy>().unwrap());
+        let p3 = Arc::new("accept 22".parse::<PortPolicy>().unwrap());
+
+        let b1 = cache.bitmap_for(&p1);
+        let b2 = cache.bitmap_for(&p2);
+        let b3 = cache.bitmap_for(&p3);
+        assert!(Arc::ptr_eq(&b1, &b2));
+        assert!(!Arc::ptr_eq(&b1, &b3));
+        assert!(b1.allows_port(443));
+        assert!(!b3.allows_port(443));
+    }
+}
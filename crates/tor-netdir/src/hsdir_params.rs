@@ -17,8 +17,9 @@
 //!      * and until then, you have to compute the start of the UTC day when the
 //!        consensus became valid.
 //!
-//! This module could conceivably be part of `tor-netdoc`, but it seems better
-//! to make it part of `tor-netdir`: this is where we put our complexity.
+//! The consensus itself knows when each of its SRVs was the most recent
+//! (see `Consensus::shared_rand_for_period` in `tor-netdoc`); the rest of
+//! the complexity lives here.
 ///
 /// (Here in Arti we use the word "ring" in types and variable names only
 /// to refer to the actual actual reified ring, not to HSDir parameters, or
@@ -26,7 +27,6 @@
 use std::time::{Duration, SystemTime};
 
 use crate::{params::NetParameters, Error, HsDirs, Result};
-use time::{OffsetDateTime, UtcOffset};
 use tor_hscrypto::time::TimePeriod;
 use tor_netdoc::doc::netstatus::{MdConsensus, SharedRandVal};
 
@@ -57,15 +57,6 @@ pub struct HsDirParams {
 /// not rotating.
 const VOTING_PERIODS_IN_OFFSET: u32 = 12;
 
-/// How many voting periods make up an entire round of the shared random value
-/// commit-and-reveal protocol?
-///
-/// We use this to compute an SRV lifetime if one of the SRV values is missing.
-const VOTING_PERIODS_IN_SRV_ROUND: u32 = 24;
-
-/// One day.
-const ONE_DAY: Duration = Duration::new(86400, 0);
-
 impl HsDirParams {
     /// Return the time period for which these parameters are valid.
     ///
@@ -76,6 +67,14 @@ impl HsDirParams {
         self.time_period
     }
 
+    /// Return the shared random value for this time period.
+    ///
+    /// This determines the position of each HsDir within the ring:
+    /// see [`responsible_hsdirs`](crate::responsible_hsdirs).
+    pub fn shared_rand(&self) -> &SharedRandVal {
+        &self.shared_rand
+    }
+
     /// Return the starting time for the shared-random-value protocol that
     /// produced the SRV for this time period.
     pub fn start_of_shard_rand_period(&self) -> SystemTime {
@@ -124,7 +123,6 @@ impl HsDirParams {
         consensus: &MdConsensus,
         params: &NetParameters,
     ) -> Result<HsDirs<HsDirParams>> {
-        let srvs = extract_srvs(consensus);
         let tp_length: Duration = params.hsdir_timeperiod_length.try_into().map_err(|_| {
             // Note that this error should be impossible:
             // The type of hsdir_timeperiod_length() is IntegerMinutes<BoundedInt32<30, 14400>>...
@@ -147,7 +145,7 @@ impl HsDirParams {
                 Error::InvalidConsensus("Consensus valid-after did not fall in a time period")
             })?;
 
-        let current = find_params_for_time(&srvs[..], cur_period)?
+        let current = find_params_for_time(consensus, cur_period)?
             .unwrap_or_else(|| disaster_params(cur_period));
 
         // When computing secondary rings, we don't try so many fallback operations:
@@ -156,7 +154,7 @@ impl HsDirParams {
         let secondary = [cur_period.prev(), cur_period.next()]
             .iter()
             .flatten()
-            .flat_map(|period| find_params_for_time(&srvs[..], *period).ok().flatten())
+            .flat_map(|period| find_params_for_time(consensus, *period).ok().flatten())
             .collect();
 
         Ok(HsDirs {
@@ -193,91 +191,27 @@ fn disaster_srv(period: TimePeriod) -> SharedRandVal {
     v.into()
 }
 
-/// Helper type: A `SharedRandVal`, and the time range over which it is the most
-/// recent.
-type SrvInfo = (SharedRandVal, std::ops::Range<SystemTime>);
-
-/// Given a list of SrvInfo, return an HsRingParams instance for a given time
-/// period, if possible.
-fn find_params_for_time(info: &[SrvInfo], period: TimePeriod) -> Result<Option<HsDirParams>> {
-    let start = period
-        .range()
-        .map_err(|_| {
-            Error::InvalidConsensus(
-                "HsDir time period in consensus could not be represented as a SystemTime range.",
-            )
-        })?
-        .start;
-
-    Ok(find_srv_for_time(info, start).map(|srv| HsDirParams {
-        time_period: period,
-        shared_rand: srv.0,
-        srv_lifespan: srv.1.clone(),
-    }))
-}
-
-/// Given a list of SrvInfo, return the SrvInfo (if any) that is the most
-/// recent SRV at `when`.
-fn find_srv_for_time(info: &[SrvInfo], when: SystemTime) -> Option<&SrvInfo> {
-    info.iter().find(|(_, range)| range.contains(&when))
-}
-
-/// Return every SRV from a consensus, along with a duration over which it is
-/// most recent SRV.
-fn extract_srvs(consensus: &MdConsensus) -> Vec<SrvInfo> {
-    let mut v = Vec::new();
-    let consensus_ts = consensus.lifetime().valid_after();
-    let srv_interval = srv_interval(consensus);
-
-    if let Some(cur) = consensus.shared_rand_cur() {
-        let ts_begin = cur
-            .timestamp()
-            .unwrap_or_else(|| start_of_day_containing(consensus_ts));
-        let ts_end = ts_begin + srv_interval;
-        v.push((*cur.value(), ts_begin..ts_end));
-    }
-    if let Some(prev) = consensus.shared_rand_prev() {
-        let ts_begin = prev
-            .timestamp()
-            .unwrap_or_else(|| start_of_day_containing(consensus_ts) - ONE_DAY);
-        let ts_end = ts_begin + srv_interval;
-        v.push((*prev.value(), ts_begin..ts_end));
-    }
-
-    v
-}
-
-/// Return the length of time for which a single SRV value is valid.
-fn srv_interval(consensus: &MdConsensus) -> Duration {
-    // What we _want_ to do, ideally, is is to learn the duration from the
-    // difference between the declared time for the previous value and the
-    // declared time for the current one.
-    //
-    // (This assumes that proposal 342 is implemented.)
-    if let (Some(cur), Some(prev)) = (consensus.shared_rand_cur(), consensus.shared_rand_prev()) {
-        if let (Some(cur_ts), Some(prev_ts)) = (cur.timestamp(), prev.timestamp()) {
-            if let Ok(d) = cur_ts.duration_since(prev_ts) {
-                return d;
-            }
-        }
-    }
-
-    // But if one of those values is missing, or if it has no timestamp, we have
-    // to fall back to admitting that we know the schedule for the voting
-    // algorithm.
-    consensus.lifetime().voting_period() * VOTING_PERIODS_IN_SRV_ROUND
-}
+/// Return an HsRingParams instance for a given time period, using the shared
+/// random values in `consensus`, if possible.
+fn find_params_for_time(
+    consensus: &MdConsensus,
+    period: TimePeriod,
+) -> Result<Option<HsDirParams>> {
+    // Make sure that we can represent the period: if we can't, that's an
+    // error, not just a missing SRV.
+    period.range().map_err(|_| {
+        Error::InvalidConsensus(
+            "HsDir time period in consensus could not be represented as a SystemTime range.",
+        )
+    })?;
 
-/// Return the length of the voting period in the consensus.
-///
-/// (The "voting period" is the length of time between between one consensus and the next.)
-
-/// Return a time at the start of the UTC day containing `t`.
-fn start_of_day_containing(t: SystemTime) -> SystemTime {
-    OffsetDateTime::from(t)
-        .to_offset(UtcOffset::UTC)
-        .replace_time(time::macros::time!(00:00))
-        .into()
+    Ok(consensus
+        .shared_rand_for_period(period)
+        .map(|(srv, srv_lifespan)| HsDirParams {
+            time_period: period,
+            shared_rand: *srv.value(),
+            srv_lifespan,
+        }))
 }
 
 #[cfg(test)]
@@ -342,22 +276,6 @@ mod test {
         bld
     }
 
-    #[test]
-    fn start_of_day() {
-        assert_eq!(
-            start_of_day_containing(t("1985-10-25T07:00:00Z")),
-            t("1985-10-25T00:00:00Z")
-        );
-        assert_eq!(
-            start_of_day_containing(t("1985-10-25T00:00:00Z")),
-            t("1985-10-25T00:00:00Z")
-        );
-        assert_eq!(
-            start_of_day_containing(t("1985-10-25T23:59:59.999Z")),
-            t("1985-10-25T00:00:00Z")
-        );
-    }
-
     #[test]
     fn vote_period() {
         assert_eq!(example_lifetime().voting_period(), d("1 hour"));
@@ -376,7 +294,7 @@ mod test {
     fn srv_period() {
         // In a basic consensus with no SRV timestamps, we'll assume 24 voting periods.
         let consensus = example_consensus_builder().testing_consensus().unwrap();
-        assert_eq!(srv_interval(&consensus), d("1 day"));
+        assert_eq!(consensus.shared_rand_interval(), d("1 day"));
 
         // If there are timestamps, we look at the difference between them.
         let consensus = example_consensus_builder()
@@ -384,7 +302,7 @@ mod test {
             .shared_rand_cur(7, SRV2.into(), Some(t("1985-10-25T06:00:05Z")))
             .testing_consensus()
             .unwrap();
-        assert_eq!(srv_interval(&consensus), d("6 hours 5 sec"));
+        assert_eq!(consensus.shared_rand_interval(), d("6 hours 5 sec"));
 
         // Note that if the timestamps are in reversed order, we fall back to 24 hours.
         let consensus = example_consensus_builder()
@@ -392,75 +310,42 @@ mod test {
             .shared_rand_prev(7, SRV2.into(), Some(t("1985-10-25T06:00:05Z")))
             .testing_consensus()
             .unwrap();
-        assert_eq!(srv_interval(&consensus), d("1 day"));
+        assert_eq!(consensus.shared_rand_interval(), d("1 day"));
     }
 
     #[test]
-    fn srvs_extract_and_find() {
+    fn find_params() {
         let consensus = example_consensus_builder().testing_consensus().unwrap();
-        let srvs = extract_srvs(&consensus);
-        assert_eq!(
-            srvs,
-            vec![
-                // Since no timestamps are given in the example, the current srv
-                // is valid from midnight to midnight...
-                (
-                    SRV2.into(),
-                    t("1985-10-25T00:00:00Z")..t("1985-10-26T00:00:00Z")
-                ),
-                // ...and the previous SRV is valid midnight-to-midnight on the
-                // previous day.
-                (
-                    SRV1.into(),
-                    t("1985-10-24T00:00:00Z")..t("1985-10-25T00:00:00Z")
-                )
-            ]
-        );
+        // Time periods in these tests start at noon.
+        let period = |when| TimePeriod::new(d("1 day"), t(when), d("12 hours")).unwrap();
 
-        // Now try with explicit timestamps on the SRVs.
-        let consensus = example_consensus_builder()
-            .shared_rand_prev(7, SRV1.into(), Some(t("1985-10-25T00:00:00Z")))
-            .shared_rand_cur(7, SRV2.into(), Some(t("1985-10-25T06:00:05Z")))
-            .testing_consensus()
+        // Since no timestamps are given in the example, the previous SRV is
+        // the most recent one from midnight to midnight on the previous day...
+        let params = find_params_for_time(&consensus, period("1985-10-25T07:00:00Z"))
+            .unwrap()
             .unwrap();
-        let srvs = extract_srvs(&consensus);
+        assert_eq!(params.shared_rand, SRV1.into());
         assert_eq!(
-            srvs,
-            vec![
-                (
-                    SRV2.into(),
-                    t("1985-10-25T06:00:05Z")..t("1985-10-25T12:00:10Z")
-                ),
-                (
-                    SRV1.into(),
-                    t("1985-10-25T00:00:00Z")..t("1985-10-25T06:00:05Z")
-                )
-            ]
+            params.srv_lifespan,
+            t("1985-10-24T00:00:00Z")..t("1985-10-25T00:00:00Z")
         );
 
-        // See if we can look up SRVs in that period.
-        assert_eq!(None, find_srv_for_time(&srvs, t("1985-10-24T23:59:00Z")));
-        assert_eq!(
-            Some(&srvs[1]),
-            find_srv_for_time(&srvs, t("1985-10-25T00:00:00Z"))
-        );
-        assert_eq!(
-            Some(&srvs[1]),
-            find_srv_for_time(&srvs, t("1985-10-25T03:59:00Z"))
-        );
-        assert_eq!(
-            Some(&srvs[1]),
-            find_srv_for_time(&srvs, t("1985-10-25T00:00:00Z"))
-        );
+        // ...and the current SRV is the most recent one from midnight to
+        // midnight on the day when the consensus became valid.
+        let params = find_params_for_time(&consensus, period("1985-10-25T13:00:00Z"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(params.shared_rand, SRV2.into());
         assert_eq!(
-            Some(&srvs[0]),
-            find_srv_for_time(&srvs, t("1985-10-25T06:00:05Z"))
+            params.srv_lifespan,
+            t("1985-10-25T00:00:00Z")..t("1985-10-26T00:00:00Z")
         );
+
+        // We don't know the SRV for any earlier period.
         assert_eq!(
-            Some(&srvs[0]),
-            find_srv_for_time(&srvs, t("1985-10-25T12:00:00Z"))
+            find_params_for_time(&consensus, period("1985-10-24T07:00:00Z")).unwrap(),
+            None
         );
-        assert_eq!(None, find_srv_for_time(&srvs, t("1985-10-25T12:00:30Z")));
     }
 
     #[test]
@@ -13,7 +13,7 @@
 //! position or later. ("N" is a "number of replicas" parameter, and "S" is a
 //! "Spread" parameter.)
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 
 use derive_more::{AsRef, From, Into};
@@ -24,6 +24,7 @@ use tor_basic_utils::impl_debug_hex;
 use tor_hscrypto::{pk::HsBlindId, time::TimePeriod};
 use tor_llcrypto::d::Sha3_256;
 use tor_llcrypto::pk::ed25519::Ed25519Identity;
+use tor_netdoc::doc::netstatus::SharedRandVal;
 
 use crate::hsdir_params::HsDirParams;
 use crate::{NetDir, RouterStatusIdx};
@@ -78,7 +79,8 @@ pub(crate) struct HsDirRing {
 /// Compute the [`HsDirIndex`] for a given relay.
 pub(crate) fn relay_hsdir_index(
     kp_relayid_ed: &Ed25519Identity,
-    params: &HsDirParams,
+    shared_rand: &SharedRandVal,
+    time_period: TimePeriod,
 ) -> HsDirIndex {
     // rend-spec-v3 2.2.3 "hsdir_index(node)"
     //
@@ -92,9 +94,9 @@ pub(crate) fn relay_hsdir_index(
     let mut h = Sha3_256::default();
     h.update(b"node-idx");
     h.update(kp_relayid_ed.as_bytes());
-    h.update(params.shared_rand.as_ref());
-    h.update(params.time_period.interval_num().to_be_bytes());
-    h.update(u64::from(params.time_period.length().as_minutes()).to_be_bytes());
+    h.update(shared_rand.as_ref());
+    h.update(time_period.interval_num().to_be_bytes());
+    h.update(u64::from(time_period.length().as_minutes()).to_be_bytes());
     HsDirIndex(h.finalize().into())
 }
 
@@ -102,7 +104,7 @@ pub(crate) fn relay_hsdir_index(
 pub(crate) fn service_hsdir_index(
     kp_hs_blind_id: &HsBlindId,
     replica: u8,
-    params: &HsDirParams,
+    time_period: TimePeriod,
 ) -> HsDirIndex {
     // rend-spec-v3 2.2.3 "hs_index(replicanum)"
     //
@@ -118,11 +120,106 @@ pub(crate) fn service_hsdir_index(
     h.update(b"store-at-idx");
     h.update(kp_hs_blind_id.as_ref());
     h.update(u64::from(replica).to_be_bytes());
-    h.update(u64::from(params.time_period.length().as_minutes()).to_be_bytes());
-    h.update(params.time_period.interval_num().to_be_bytes());
+    h.update(u64::from(time_period.length().as_minutes()).to_be_bytes());
+    h.update(time_period.interval_num().to_be_bytes());
     HsDirIndex(h.finalize().into())
 }
 
+/// Return the onion service directories responsible for the descriptor of the
+/// service with blinded key `kp_hs_blind_id`, in the time period `time_period`.
+///
+/// `shared_rand` is the shared random value for `time_period`
+/// (see [`HsDirParams`]), and `hsdirs` lists the ed25519 identities of every
+/// relay with the `HSDir` flag.
+/// `n_replicas` and `spread` are the values of the `hsdir_n_replicas`
+/// consensus parameter, and of `hsdir_spread_store` or `hsdir_spread_fetch`.
+///
+/// This is the algorithm of rend-spec-v3 section 2.2.3:
+/// for each replica, we find the replica's position on the ring of HsDirs,
+/// and take the next `spread` HsDirs from there that were not already taken
+/// for an earlier replica.
+/// The result is in that order: by replica, and then by position on the ring.
+/// (Clients should shuffle it before use.)
+///
+/// [`NetDir::hs_dirs_download`] (and, with the `hs-service` feature,
+/// `NetDir::hs_dirs_upload`) use the same implementation;
+/// this function is for callers that don't have a `NetDir`,
+/// or want to compute the ring for some other shared random value.
+pub fn responsible_hsdirs(
+    kp_hs_blind_id: &HsBlindId,
+    time_period: TimePeriod,
+    shared_rand: &SharedRandVal,
+    hsdirs: impl IntoIterator<Item = Ed25519Identity>,
+    n_replicas: u8,
+    spread: usize,
+) -> Vec<Ed25519Identity> {
+    let mut ring: Vec<_> = hsdirs
+        .into_iter()
+        .map(|id| (relay_hsdir_index(&id, shared_rand, time_period), id))
+        .collect();
+    ring.sort_unstable();
+    ring.dedup();
+
+    select_from_ring(&ring, kp_hs_blind_id, time_period, n_replicas, spread)
+        .into_iter()
+        .copied()
+        .collect()
+}
+
+/// Select the entries of `ring` (sorted by [`HsDirIndex`]) that are responsible
+/// for the service `kp_hs_blind_id` in `time_period`.
+///
+/// See [`responsible_hsdirs`] for the algorithm.
+pub(crate) fn select_from_ring<'r, T>(
+    ring: &'r [(HsDirIndex, T)],
+    kp_hs_blind_id: &HsBlindId,
+    time_period: TimePeriod,
+    n_replicas: u8,
+    spread: usize,
+) -> Vec<&'r T> {
+    let mut selected_nodes = HashSet::new();
+
+    (1..=n_replicas) // 1-indexed !
+        .flat_map(|replica| {
+            let hsdir_idx = service_hsdir_index(kp_hs_blind_id, replica, time_period);
+
+            ring_items_at(ring, hsdir_idx, spread, |(hsdir_idx, _)| {
+                // According to rend-spec 2.2.3:
+                //                                                  ... If any of those
+                // nodes have already been selected for a lower-numbered replica of the
+                // service, any nodes already chosen are disregarded (i.e. skipped over)
+                // when choosing a replica's hsdir_spread_store nodes.
+                selected_nodes.insert(*hsdir_idx)
+            })
+            .collect::<Vec<_>>()
+        })
+        .map(|(_hsdir_idx, item)| item)
+        .collect()
+}
+
+/// Yield `spread` items from `ring` (sorted by [`HsDirIndex`]) that satisfy
+/// the filter `f`, starting with the first at or after `hsdir_index`.
+///
+/// Wraps around once when we reach the end.
+///
+/// Yields no element more than once, even if the ring is smaller than `spread`.
+fn ring_items_at<'r, T>(
+    ring: &'r [(HsDirIndex, T)],
+    hsdir_index: HsDirIndex,
+    spread: usize,
+    f: impl FnMut(&&(HsDirIndex, T)) -> bool,
+) -> impl Iterator<Item = &'r (HsDirIndex, T)> {
+    // Find the location or (notional) insertion point for `hsdir_index` within `ring`.
+    let pos = ring
+        .binary_search_by_key(&hsdir_index, |(hsdir_index, _)| *hsdir_index)
+        .unwrap_or_else(|pos| pos);
+    ring[pos..]
+        .iter()
+        .chain(&ring[..pos])
+        .filter(f)
+        .take(spread)
+}
+
 impl HsDirRing {
     /// Return a new empty HsDirRing from a given set of parameters.
     pub(crate) fn empty_from_params(params: HsDirParams) -> Self {
@@ -192,7 +289,9 @@ impl HsDirRing {
                     .get(ed_id)
                     .cloned()
                     .cloned()
-                    .unwrap_or_else(|| relay_hsdir_index(ed_id, &new_params));
+                    .unwrap_or_else(|| {
+                        relay_hsdir_index(ed_id, &new_params.shared_rand, new_params.time_period)
+                    });
                 (hsdir_index, rsidx)
             })
             .collect();
@@ -211,36 +310,26 @@ impl HsDirRing {
         &self.params
     }
 
-    /// Find the location or (notional) insertion point for `hsdir_index` within `ring`.
-    fn find_pos(&self, hsdir_index: HsDirIndex) -> HsDirPos {
-        self.ring
-            .binary_search_by_key(&hsdir_index, |(hsdir_index, _rs_idx)| *hsdir_index)
-            .unwrap_or_else(|pos| pos)
-    }
-
-    /// Yield `spread` items from `ring` that satisfy the specified filter, starting with
-    /// `hsdir_index`.
-    ///
-    /// Wraps around once when we reach the end.
+    /// Return the consensus indices of the relays on this ring that are responsible
+    /// for the service `kp_hs_blind_id`.
     ///
-    /// The specified filter function `f` is applied to each item, and determines whether the item
-    /// should be yielded or not. This filtering functionality is used by [`NetDir::hs_dirs`] to
-    /// prevent nodes that have already been selected for a lowered-numbered replica to be
-    /// considered again when choosing `spread` nodes for a higher-numbered replicas.
-    ///
-    /// Yields no element more than once, even if the ring is smaller than `spread`.
-    pub(crate) fn ring_items_at(
+    /// See [`responsible_hsdirs`].
+    pub(crate) fn select(
         &self,
-        hsdir_index: HsDirIndex,
+        kp_hs_blind_id: &HsBlindId,
+        n_replicas: u8,
         spread: usize,
-        f: impl FnMut(&&(HsDirIndex, RouterStatusIdx)) -> bool,
-    ) -> impl Iterator<Item = &(HsDirIndex, RouterStatusIdx)> {
-        let pos = self.find_pos(hsdir_index);
-        self.ring[pos..]
-            .iter()
-            .chain(&self.ring[..pos])
-            .filter(f)
-            .take(spread)
+    ) -> Vec<RouterStatusIdx> {
+        select_from_ring(
+            &self.ring.raw,
+            kp_hs_blind_id,
+            self.params.time_period,
+            n_replicas,
+            spread,
+        )
+        .into_iter()
+        .copied()
+        .collect()
     }
 
     /// Return the time period for which this ring applies.
@@ -294,7 +383,7 @@ mod test {
         {
             let kp_hs_blind_id = [0x42; 32].into();
             let replica = 1;
-            let got = service_hsdir_index(&kp_hs_blind_id, replica, &params);
+            let got = service_hsdir_index(&kp_hs_blind_id, replica, params.time_period);
             assert_eq!(
                 hex::encode(got.as_ref()),
                 "37e5cbbd56a22823714f18f1623ece5983a0d64c78495a8cfab854245e5f9a8a",
@@ -304,11 +393,142 @@ mod test {
         // relay_index AKA hsdir_index
         {
             let kp_relayid_ed = [0x42; 32].into();
-            let got = relay_hsdir_index(&kp_relayid_ed, &params);
+            let got = relay_hsdir_index(&kp_relayid_ed, &params.shared_rand, params.time_period);
             assert_eq!(
                 hex::encode(got.as_ref()),
                 "db475361014a09965e7e5e4d4a25b8f8d4b8f16cb1d8a7e95eed50249cc1a2d5",
             );
         }
     }
+
+    #[test]
+    fn ring_items_skip_and_wrap() {
+        // The ring is [A, B, C, D, E, F], in that order.
+        let ring: Vec<(HsDirIndex, char)> = "ABCDEF"
+            .chars()
+            .enumerate()
+            .map(|(i, c)| (HsDirIndex([(i as u8 + 1) * 0x20; 32]), c))
+            .collect();
+        let at = |pos: u8| HsDirIndex([pos; 32]);
+        let take = |start, spread, selected: &mut HashSet<HsDirIndex>| {
+            ring_items_at(&ring, start, spread, |(idx, _)| selected.insert(*idx))
+                .map(|(_, c)| *c)
+                .collect::<String>()
+        };
+
+        // If replica 1 gets [A, B, C], and replica 2 starts at E,
+        // then replica 2 must get [E, F, D].
+        let mut selected = HashSet::new();
+        assert_eq!(take(at(0x20), 3, &mut selected), "ABC");
+        assert_eq!(take(at(0xa0), 3, &mut selected), "EFD");
+
+        // A position between two entries starts at the later one,
+        // and a position after the last entry wraps around.
+        assert_eq!(take(at(0x21), 2, &mut HashSet::new()), "BC");
+        assert_eq!(take(at(0xff), 2, &mut HashSet::new()), "AB");
+
+        // We never yield an entry twice.
+        assert_eq!(take(at(0x60), 10, &mut HashSet::new()), "CDEFAB");
+    }
+
+    #[test]
+    fn responsible() {
+        let time_period = TimePeriod::new(
+            Duration::from_secs(24 * 3600),
+            humantime::parse_rfc3339("1970-02-13T01:00:00Z").unwrap(),
+            Duration::from_secs(12 * 3600),
+        )
+        .unwrap();
+        let shared_rand = [0x43; 32].into();
+        let hsid = [0x42; 32].into();
+        let hsdirs: Vec<Ed25519Identity> = (0..20_u8).map(|i| [i; 32].into()).collect();
+
+        let got = responsible_hsdirs(
+            &hsid,
+            time_period,
+            &shared_rand,
+            hsdirs.iter().copied(),
+            2,
+            3,
+        );
+        assert_eq!(got.len(), 6);
+        assert_eq!(got.iter().collect::<HashSet<_>>().len(), 6);
+
+        // The order in which we list the HsDirs, and duplicates, don't matter.
+        let again = responsible_hsdirs(
+            &hsid,
+            time_period,
+            &shared_rand,
+            hsdirs.iter().rev().chain(&hsdirs).copied(),
+            2,
+            3,
+        );
+        assert_eq!(got, again);
+
+        // Each replica starts at its own position on the ring.
+        let mut ring: Vec<_> = hsdirs
+            .iter()
+            .map(|id| (relay_hsdir_index(id, &shared_rand, time_period), *id))
+            .collect();
+        ring.sort();
+        let start = service_hsdir_index(&hsid, 1, time_period);
+        let first = ring
+            .iter()
+            .find(|(idx, _)| *idx >= start)
+            .unwrap_or(&ring[0]);
+        assert_eq!(got[0], first.1);
+
+        // Different shared random values give different rings.
+        let other = responsible_hsdirs(
+            &hsid,
+            time_period,
+            &[0x44; 32].into(),
+            hsdirs.iter().copied(),
+            2,
+            3,
+        );
+        assert_ne!(got, other);
+
+        // With too few HsDirs, we get each of them once.
+        let few = responsible_hsdirs(&hsid, time_period, &shared_rand, hsdirs[..4].to_vec(), 2, 3);
+        assert_eq!(few.len(), 4);
+    }
+
+    // Uses the same inputs as C Tor's test_hs_indexes (above).  The expected
+    // results were computed outside of Arti, by a direct transliteration of C
+    // Tor's hs_get_responsible_hsdirs() (src/feature/hs/hs_common.c), which
+    // also reproduces the index values that test_hs_indexes checks.
+    #[test]
+    fn responsible_vectors() {
+        let time_period = TimePeriod::new(
+            Duration::from_secs(24 * 3600),
+            humantime::parse_rfc3339("1970-02-13T01:00:00Z").unwrap(),
+            Duration::from_secs(12 * 3600),
+        )
+        .unwrap();
+        let hsid = [0x42; 32].into();
+        let hsdirs: Vec<Ed25519Identity> = (0..20_u8).map(|i| [i; 32].into()).collect();
+
+        let check = |shared_rand: [u8; 32], n_hsdirs: usize, spread, expected: &[u8]| {
+            let got = responsible_hsdirs(
+                &hsid,
+                time_period,
+                &shared_rand.into(),
+                hsdirs[..n_hsdirs].iter().copied(),
+                2,
+                spread,
+            );
+            let expected: Vec<Ed25519Identity> = expected.iter().map(|i| [*i; 32].into()).collect();
+            assert_eq!(got, expected);
+        };
+
+        // hsdir_spread_store = 4
+        check([0x43; 32], 20, 4, &[5, 17, 8, 11, 6, 3, 1, 14]);
+        // hsdir_spread_fetch = 3
+        check([0x43; 32], 20, 3, &[5, 17, 8, 11, 6, 3]);
+        // Another shared random value.
+        check([0x44; 32], 20, 4, &[6, 5, 10, 9, 8, 0, 3, 18]);
+        // Fewer HsDirs than we want: each of them once.
+        check([0x43; 32], 4, 3, &[3, 1, 2, 0]);
+    }
 }
@@ -43,6 +43,7 @@
 
 pub mod details;
 mod err;
+mod exit_ports;
 #[cfg(feature = "hs-common")]
 mod hsdir_params;
 #[cfg(feature = "hs-common")]
@@ -73,7 +74,7 @@ use futures::stream::BoxStream;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use rand::seq::SliceRandom;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::IpAddr;
 use std::ops::Deref;
 use std::sync::Arc;
@@ -84,7 +85,6 @@ use typed_index_collections::{TiSlice, TiVec};
 #[cfg(feature = "hs-common")]
 use {
     itertools::Itertools,
-    std::collections::HashSet,
     tor_error::{internal, Bug},
     tor_hscrypto::{pk::HsBlindId, time::TimePeriod},
 };
@@ -97,13 +97,14 @@ pub type Result<T> = std::result::Result<T, Error>;
 #[cfg(feature = "hs-common")]
 pub use err::OnionDirLookupError;
 
+use exit_ports::{ExitPorts, PortBitmapCache};
 use params::NetParameters;
 #[cfg(feature = "geoip")]
 use tor_geoip::{CountryCode, GeoipDb, HasCountryCode};
 
 #[cfg(feature = "hs-common")]
 #[cfg_attr(docsrs, doc(cfg(feature = "hs-common")))]
-pub use hsdir_params::HsDirParams;
+pub use {hsdir_params::HsDirParams, hsdir_ring::responsible_hsdirs};
 
 /// Index into the consensus relays
 ///
@@ -139,6 +140,22 @@ impl ConsensusRelays for NetDir {
     }
 }
 
+/// A way in which two relays are related,
+/// such that they should not be used together in the same circuit.
+///
+/// Returned by [`Relay::relation_to`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum RelayRelation {
+    /// The two relays are the same relay.
+    SameRelay,
+    /// The two relays are in the same family: each one lists the other
+    /// as a family member.
+    SameFamily,
+    /// The two relays have addresses in the same subnet.
+    SameSubnet,
+}
+
 /// Configuration for determining when two relays have addresses "too close" in
 /// the network.
 ///
@@ -343,6 +360,15 @@ pub struct NetDir {
     params: NetParameters,
     /// Map from routerstatus index, to that routerstatus's microdescriptor (if we have one.)
     mds: TiVec<RouterStatusIdx, Option<Arc<Microdesc>>>,
+    /// Map from routerstatus index, to the precomputed exit ports for that
+    /// routerstatus's microdescriptor (if we have one.)
+    ///
+    /// Always has the same length as `mds`, and an entry here is present
+    /// exactly when the corresponding entry in `mds` is.
+    exit_ports: TiVec<RouterStatusIdx, Option<ExitPorts>>,
+    /// Cache of the port bitmaps we've computed for this directory, so that
+    /// relays with identical exit policies can share them.
+    port_bitmaps: PortBitmapCache,
     /// Map from SHA256 of _missing_ microdescriptors to the index of their
     /// corresponding routerstatus.
     rsidx_by_missing: HashMap<MdDigest, RouterStatusIdx>,
@@ -499,6 +525,13 @@ pub enum DirEvent {
     /// (This event is _not_ broadcast when receiving new descriptors for a
     /// consensus which is not yet ready to replace the current consensus.)
     NewDescriptors,
+
+    /// The [`DirLiveness`] of the current directory has changed.
+    ///
+    /// This happens, for example, when our consensus expires and we start
+    /// relying on it only as "reasonably live", or when it ages past the point
+    /// where we are willing to use it at all.
+    LivenessChanged,
 }
 
 /// How "timely" must a network directory be?
@@ -531,6 +564,45 @@ pub enum Timeliness {
     Unchecked,
 }
 
+/// What is a network directory going to be used for?
+///
+/// A [`NetDirProvider`] may be willing to hand out a slightly expired directory
+/// for some of these purposes, but not for others:
+/// see [`NetDirProvider::netdir_for`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[non_exhaustive]
+pub enum DirUsage {
+    /// Choosing directory caches from which to fetch more directory information.
+    ///
+    /// This is the most forgiving usage: even an old directory is a better
+    /// source of caches than the fallback list.
+    DirFetch,
+    /// Building new circuits for general traffic.
+    NewCircuits,
+    /// Anything to do with onion services, such as finding HsDirs or
+    /// choosing introduction points.
+    ///
+    /// Onion services depend on time periods and shared random values that
+    /// are derived from the consensus, so they should not use an old one for
+    /// as long.
+    OnionServices,
+}
+
+/// How live is a network directory, relative to the current time?
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+#[allow(clippy::exhaustive_enums)]
+pub enum DirLiveness {
+    /// The directory is within its official validity interval.
+    Live,
+    /// The directory is outside its official validity interval, but within the
+    /// tolerances that we use to survive clock skew and failures of the
+    /// directory authorities to reach consensus.
+    ReasonablyLive,
+    /// The directory is too old or too new to use,
+    /// or there is no directory at all.
+    Stale,
+}
+
 /// An object that can provide [`NetDir`]s, as well as inform consumers when
 /// they might have changed.
 ///
@@ -553,6 +625,30 @@ pub trait NetDirProvider: UpcastArcNetDirProvider + Send + Sync {
         self.netdir(Timeliness::Timely)
     }
 
+    /// Return a network directory that is live enough to be used for `usage`.
+    ///
+    /// Providers may apply a different grace period after the end of the
+    /// consensus' validity interval for each [`DirUsage`].
+    /// By default, this is the same as [`NetDirProvider::timely_netdir`].
+    fn netdir_for(&self, usage: DirUsage) -> Result<Arc<NetDir>> {
+        let _ = usage;
+        self.timely_netdir()
+    }
+
+    /// Return the current [`DirLiveness`] of this provider's directory.
+    ///
+    /// Whenever this changes, providers should broadcast a
+    /// [`DirEvent::LivenessChanged`] event.
+    fn liveness(&self) -> DirLiveness {
+        if self.netdir(Timeliness::Strict).is_ok() {
+            DirLiveness::Live
+        } else if self.netdir(Timeliness::Timely).is_ok() {
+            DirLiveness::ReasonablyLive
+        } else {
+            DirLiveness::Stale
+        }
+    }
+
     /// Return a new asynchronous stream that will receive notification
     /// whenever the consensus has changed.
     ///
@@ -579,6 +675,14 @@ where
         self.deref().timely_netdir()
     }
 
+    fn netdir_for(&self, usage: DirUsage) -> Result<Arc<NetDir>> {
+        self.deref().netdir_for(usage)
+    }
+
+    fn liveness(&self) -> DirLiveness {
+        self.deref().liveness()
+    }
+
     fn events(&self) -> BoxStream<'static, DirEvent> {
         self.deref().events()
     }
@@ -643,6 +747,8 @@ pub struct Relay<'a> {
     rs: &'a netstatus::MdConsensusRouterStatus,
     /// A microdescriptor for this relay.
     md: &'a Microdesc,
+    /// The ports that this relay allows exiting to, according to `md`.
+    exit_ports: &'a ExitPorts,
     /// The country code this relay is in, if we know one.
     #[cfg(feature = "geoip")]
     cc: Option<CountryCode>,
@@ -656,6 +762,8 @@ pub struct UncheckedRelay<'a> {
     rs: &'a netstatus::MdConsensusRouterStatus,
     /// A microdescriptor for this relay, if there is one.
     md: Option<&'a Microdesc>,
+    /// The ports that this relay allows exiting to, if we have a microdescriptor.
+    exit_ports: Option<&'a ExitPorts>,
     /// The country code this relay is in, if we know one.
     #[cfg(feature = "geoip")]
     cc: Option<CountryCode>,
@@ -778,6 +886,8 @@ impl PartialNetDir {
             consensus: Arc::new(consensus),
             params,
             mds: vec![None; n_relays].into(),
+            exit_ports: vec![None; n_relays].into(),
+            port_bitmaps: PortBitmapCache::default(),
             rsidx_by_missing,
             rsidx_by_rsa: Arc::new(rsidx_by_rsa),
             rsidx_by_ed: HashMap::with_capacity(n_relays),
@@ -882,6 +992,7 @@ impl NetDir {
             self.rsidx_by_ed.insert(*md.ed25519_id(), rsidx);
 
             // Happy path: we did indeed want this one.
+            self.exit_ports[rsidx] = Some(self.port_bitmaps.exit_ports_for(&md));
             self.mds[rsidx] = Some(md);
 
             // Save some space in the missing-descriptor list.
@@ -912,6 +1023,7 @@ impl NetDir {
         UncheckedRelay {
             rs,
             md,
+            exit_ports: self.exit_ports[rsidx].as_ref(),
             #[cfg(feature = "geoip")]
             cc: self.country_codes.get(rsidx.0).copied().flatten(),
         }
@@ -954,38 +1066,17 @@ impl NetDir {
     ///         adding them to Dirs until we have added `spread` new elements
     ///         that were not there before.
     #[cfg(feature = "hs-common")]
-    fn select_hsdirs<'h, 'r: 'h>(
+    fn select_hsdirs<'r>(
         &'r self,
         hsid: HsBlindId,
-        ring: &'h HsDirRing,
+        ring: &HsDirRing,
         spread: usize,
-    ) -> impl Iterator<Item = Relay<'r>> + 'h {
-        let n_replicas = self.n_replicas();
-
-        (1..=n_replicas) // 1-indexed !
-            .flat_map({
-                let mut selected_nodes = HashSet::new();
-
-                move |replica: u8| {
-                    let hsdir_idx = hsdir_ring::service_hsdir_index(&hsid, replica, ring.params());
-
-                    let items = ring
-                        .ring_items_at(hsdir_idx, spread, |(hsdir_idx, _)| {
-                            // According to rend-spec 2.2.3:
-                            //                                                  ... If any of those
-                            // nodes have already been selected for a lower-numbered replica of the
-                            // service, any nodes already chosen are disregarded (i.e. skipped over)
-                            // when choosing a replica's hsdir_spread_store nodes.
-                            selected_nodes.insert(*hsdir_idx)
-                        })
-                        .collect::<Vec<_>>();
-
-                    items
-                }
-            })
-            .filter_map(move |(_hsdir_idx, rs_idx)| {
+    ) -> impl Iterator<Item = Relay<'r>> + 'r {
+        ring.select(&hsid, self.n_replicas(), spread)
+            .into_iter()
+            .filter_map(move |rs_idx| {
                 // This ought not to be None but let's not panic or bail if it is
-                self.relay_by_rs_idx(*rs_idx)
+                self.relay_by_rs_idx(rs_idx)
             })
     }
 
@@ -1019,6 +1110,20 @@ impl NetDir {
         self.all_relays().filter_map(UncheckedRelay::into_relay)
     }
 
+    /// Return an iterator over every [usable](NetDir#usable) relay that allows
+    /// exiting to all of `ports` on IPv4.
+    ///
+    /// Relays flagged as bad exits are never returned.
+    ///
+    /// This uses the same precomputed bitmaps as [`Relay::allows`], so it is
+    /// considerably faster than checking each relay's exit policy directly.
+    /// For IPv6, filter [`relays`](NetDir::relays) with [`Relay::allows`].
+    pub fn exits_supporting<'a>(&'a self, ports: &'a [u16]) -> impl Iterator<Item = Relay<'a>> {
+        self.relays().filter(move |r| {
+            !r.rs.is_flagged_bad_exit() && ports.iter().all(|p| r.exit_ports.allows_ipv4(*p))
+        })
+    }
+
     /// Look up a relay's `MicroDesc` by its `RouterStatusIdx`
     #[cfg_attr(not(feature = "hs-common"), allow(dead_code))]
     pub(crate) fn md_by_rsidx(&self, rsidx: RouterStatusIdx) -> Option<&Microdesc> {
@@ -1069,9 +1174,11 @@ impl NetDir {
     pub(crate) fn relay_by_rs_idx(&self, rs_idx: RouterStatusIdx) -> Option<Relay<'_>> {
         let rs = self.c_relays().get(rs_idx)?;
         let md = self.mds.get(rs_idx)?.as_deref();
+        let exit_ports = self.exit_ports.get(rs_idx)?.as_ref();
         UncheckedRelay {
             rs,
             md,
+            exit_ports,
             #[cfg(feature = "geoip")]
             cc: self.country_codes.get(rs_idx.0).copied().flatten(),
         }
@@ -1541,6 +1648,38 @@ impl NetDir {
         })
     }
 
+    /// Return every relay in this NetDir that is connected to `relay` by
+    /// a chain of mutual family declarations.
+    ///
+    /// This is the transitive closure of
+    /// [`known_family_members`](NetDir::known_family_members):
+    /// it includes the family members of `relay`,
+    /// their family members, and so on.
+    /// The returned list does **not** include `relay` itself.
+    ///
+    /// Note that Tor's family relationship is not transitive:
+    /// path selection only avoids relays that are _directly_ in the same family
+    /// (see [`Relay::relation_to`]).
+    /// This function is meant for tools that need to audit a whole family
+    /// of relays run by a single operator.
+    ///
+    /// The same limitations apply as for `known_family_members`.
+    pub fn family_closure<'a>(&'a self, relay: &Relay<'a>) -> Vec<Relay<'a>> {
+        let mut seen: HashSet<RsaIdentity> = HashSet::new();
+        seen.insert(*relay.rsa_id());
+        let mut result = Vec::new();
+        let mut to_visit = vec![relay.clone()];
+        while let Some(r) = to_visit.pop() {
+            for member in self.known_family_members(&r) {
+                if seen.insert(*member.rsa_id()) {
+                    result.push(member.clone());
+                    to_visit.push(member);
+                }
+            }
+        }
+        result
+    }
+
     /// Return the current hidden service directory "time period".
     ///
     /// Specifically, this returns the time period that contains the beginning
@@ -1733,30 +1872,10 @@ impl NetDir {
         let mut hs_dirs = self
             .hsdir_rings
             .iter_for_op(op)
-            .cartesian_product(1..=n_replicas) // 1-indexed !
-            .flat_map({
-                let mut selected_nodes = HashSet::new();
-
-                move |(ring, replica): (&HsDirRing, u8)| {
-                    let hsdir_idx = hsdir_ring::service_hsdir_index(hsid, replica, ring.params());
-
-                    let items = ring
-                        .ring_items_at(hsdir_idx, spread, |(hsdir_idx, _)| {
-                            // According to rend-spec 2.2.3:
-                            //                                                  ... If any of those
-                            // nodes have already been selected for a lower-numbered replica of the
-                            // service, any nodes already chosen are disregarded (i.e. skipped over)
-                            // when choosing a replica's hsdir_spread_store nodes.
-                            selected_nodes.insert(*hsdir_idx)
-                        })
-                        .collect::<Vec<_>>();
-
-                    items
-                }
-            })
-            .filter_map(|(_hsdir_idx, rs_idx)| {
+            .flat_map(|ring| ring.select(hsid, n_replicas, spread))
+            .filter_map(|rs_idx| {
                 // This ought not to be None but let's not panic or bail if it is
-                self.relay_by_rs_idx(*rs_idx)
+                self.relay_by_rs_idx(rs_idx)
             })
             .collect_vec();
 
@@ -1811,6 +1930,7 @@ impl<'a> UncheckedRelay<'a> {
             Some(Relay {
                 rs: self.rs,
                 md: self.md?,
+                exit_ports: self.exit_ports?,
                 #[cfg(feature = "geoip")]
                 cc: self.cc,
             })
@@ -1843,6 +1963,51 @@ impl<'a> Relay<'a> {
         details::RelayDetails(self)
     }
 
+    /// Return how this relay is related to `other`, if they are related in a
+    /// way that means they should not be used in the same circuit.
+    ///
+    /// Two relays are related if they are the same relay,
+    /// if they are in the same family,
+    /// or if they have addresses in the same subnet according to `subnet_config`.
+    /// (The default `SubnetConfig` puts IPv4 addresses in the same /16,
+    /// and IPv6 addresses in the same /32, in the same subnet.)
+    ///
+    /// If the relays are related in more than one way,
+    /// returns the first applicable relationship in the order listed above.
+    /// Returns `None` if the relays are unrelated.
+    pub fn relation_to(
+        &self,
+        other: &Relay<'_>,
+        subnet_config: &SubnetConfig,
+    ) -> Option<RelayRelation> {
+        let details = self.low_level_details();
+        if self.same_relay_ids(other) {
+            Some(RelayRelation::SameRelay)
+        } else if details.in_same_family(other) {
+            Some(RelayRelation::SameFamily)
+        } else if details.in_same_subnet(other, subnet_config) {
+            Some(RelayRelation::SameSubnet)
+        } else {
+            None
+        }
+    }
+
+    /// Return true if this relay's exit policy allows connections to `port`
+    /// on addresses of the same family as `addr`.
+    ///
+    /// Returns false if this relay has been flagged as a bad exit.
+    ///
+    /// This check uses bitmaps that were precomputed when the relay's
+    /// microdescriptor was added, so it is cheap enough to call for every
+    /// relay, for every port, when selecting exits.
+    ///
+    /// Note that microdescriptors only summarize a relay's exit policy by
+    /// port: the relay may still refuse a connection to some particular
+    /// address that its full policy rejects.
+    pub fn allows(&self, addr: &IpAddr, port: u16) -> bool {
+        !self.rs.is_flagged_bad_exit() && self.exit_ports.allows(addr, port)
+    }
+
     /// Return the Ed25519 ID for this relay.
     pub fn id(&self) -> &Ed25519Identity {
         self.md.ed25519_id()
@@ -2410,6 +2575,39 @@ mod test {
             .allows_some_port());
     }
 
+    #[test]
+    fn test_exits_supporting() {
+        let netdir = construct_netdir().unwrap_if_sufficient().unwrap();
+        let v4: IpAddr = "203.0.113.7".parse().unwrap();
+        let v6: IpAddr = "2001:db8::7".parse().unwrap();
+
+        // Exits are relays 10..=19 and 30..=39: odd ones allow 80 and 443,
+        // even ones allow everything.
+        let ids = |ports: &[u16]| {
+            let mut ids: Vec<u8> = netdir
+                .exits_supporting(ports)
+                .map(|r| r.id().as_bytes()[0])
+                .collect();
+            ids.sort();
+            ids
+        };
+        let all_exits: Vec<u8> = (10..20).chain(30..40).collect();
+        let even_exits: Vec<u8> = all_exits.iter().copied().filter(|i| i % 2 == 0).collect();
+        assert_eq!(ids(&[443]), all_exits);
+        assert_eq!(ids(&[80, 443]), all_exits);
+        assert_eq!(ids(&[80, 22]), even_exits);
+        assert_eq!(ids(&[]).len(), netdir.relays().count());
+
+        let r11 = netdir.by_id(&Ed25519Identity::from([11; 32])).unwrap();
+        let r12 = netdir.by_id(&Ed25519Identity::from([12; 32])).unwrap();
+        let r4 = netdir.by_id(&Ed25519Identity::from([4; 32])).unwrap();
+        assert!(r11.allows(&v4, 443));
+        assert!(!r11.allows(&v4, 22));
+        assert!(r12.allows(&v4, 22));
+        assert!(!r12.allows(&v6, 22));
+        assert!(!r4.allows(&v4, 443));
+    }
+
     #[cfg(feature = "experimental-api")]
     #[test]
     fn test_accessors() {
@@ -2628,6 +2826,81 @@ mod test {
         // Note that 13 doesn't get put in, even though it's listed, since it doesn't claim
         //  membership with 10.
     }
+
+    #[test]
+    fn family_closure() {
+        let netdir = construct_custom_netdir(|pos, n| {
+            if pos == 0x0a {
+                n.md.family(
+                    "$0B0B0B0B0B0B0B0B0B0B0B0B0B0B0B0B0B0B0B0B \
+                     $0C0C0C0C0C0C0C0C0C0C0C0C0C0C0C0C0C0C0C0C"
+                        .parse()
+                        .unwrap(),
+                );
+            } else if pos == 0x0c {
+                n.md.family(
+                    "$0A0A0A0A0A0A0A0A0A0A0A0A0A0A0A0A0A0A0A0A \
+                     $0D0D0D0D0D0D0D0D0D0D0D0D0D0D0D0D0D0D0D0D"
+                        .parse()
+                        .unwrap(),
+                );
+            }
+        })
+        .unwrap()
+        .unwrap_if_sufficient()
+        .unwrap();
+
+        // 0x0d is only in the family of 0x0c, not in the family of 0x0a...
+        let r10 = netdir.by_id(&Ed25519Identity::from([10; 32])).unwrap();
+        let direct: HashSet<_> = netdir.known_family_members(&r10).map(|r| *r.id()).collect();
+        assert_eq!(direct.len(), 2);
+        assert!(!direct.contains(&Ed25519Identity::from([13; 32])));
+
+        // ... but it's in the closure.
+        let closure: HashSet<_> = netdir
+            .family_closure(&r10)
+            .iter()
+            .map(|r| *r.id())
+            .collect();
+        assert_eq!(closure.len(), 3);
+        for id in [11, 12, 13] {
+            assert!(closure.contains(&Ed25519Identity::from([id; 32])));
+        }
+
+        // Relays with only the default families have a closure of size 1.
+        let r0 = netdir.by_id(&Ed25519Identity::from([0; 32])).unwrap();
+        assert_eq!(netdir.family_closure(&r0).len(), 1);
+    }
+
+    #[test]
+    fn relations() {
+        let netdir = construct_netdir().unwrap_if_sufficient().unwrap();
+        let r0 = netdir.by_id(&Ed25519Identity::from([0; 32])).unwrap();
+        let r1 = netdir.by_id(&Ed25519Identity::from([1; 32])).unwrap();
+        let r2 = netdir.by_id(&Ed25519Identity::from([2; 32])).unwrap();
+
+        // In the test network, every relay has the same address.
+        let subnets = SubnetConfig::default();
+        let no_subnets = SubnetConfig::no_addresses_match();
+        assert_eq!(
+            r0.relation_to(&r0, &subnets),
+            Some(RelayRelation::SameRelay)
+        );
+        assert_eq!(
+            r0.relation_to(&r1, &subnets),
+            Some(RelayRelation::SameFamily)
+        );
+        assert_eq!(
+            r0.relation_to(&r2, &subnets),
+            Some(RelayRelation::SameSubnet)
+        );
+        assert_eq!(r0.relation_to(&r2, &no_subnets), None);
+        assert_eq!(
+            r0.relation_to(&r0, &no_subnets),
+            Some(RelayRelation::SameRelay)
+        );
+    }
+
     #[test]
     #[cfg(feature = "geoip")]
     fn relay_has_country_code() {
@@ -2709,10 +2982,9 @@ mod test {
 
             assert_eq!(relays.len(), *relay_count);
 
-            // There should be no duplicates (the filtering function passed to
-            // HsDirRing::ring_items_at() ensures the relays that are already in use for
-            // lower-numbered replicas aren't considered a second time for a higher-numbered
-            // replica).
+            // There should be no duplicates (hsdir_ring::select_from_ring() ensures the
+            // relays that are already in use for lower-numbered replicas aren't considered
+            // a second time for a higher-numbered replica).
             let unique = relays
                 .iter()
                 .map(|relay| relay.ed_identity())
@@ -2720,23 +2992,57 @@ mod test {
             assert_eq!(unique.len(), relays.len());
         }
 
-        // TODO: come up with a test that checks that HsDirRing::ring_items_at() skips over the
-        // expected relays.
-        //
-        // For example, let's say we have the following hsdir ring:
-        //
-        //         A  -  B
-        //        /       \
-        //       F         C
-        //        \       /
-        //         E  -  D
-        //
-        // Let's also assume that:
-        //
-        //   * hsdir_spread_store = 3
-        //   * the ordering of the relays on the ring is [A, B, C, D, E, F]
-        //
-        // If we use relays [A, B, C] for replica 1, and hs_index(2) = E, then replica 2 _must_ get
-        // relays [E, F, D]. We should have a test that checks this.
+        // (hsdir_ring::test::ring_items_skip_and_wrap checks that we skip over the
+        // expected relays.)
+    }
+
+    #[test]
+    #[cfg(feature = "hs-common")]
+    fn hs_dirs_match_responsible_hsdirs() {
+        use tor_basic_utils::test_rng::testing_rng;
+
+        let netdir: Arc<NetDir> = crate::testnet::construct_netdir()
+            .unwrap_if_sufficient()
+            .unwrap()
+            .into();
+        let hsid = dummy_hs_blind_id();
+        let ring = &netdir.hsdir_rings.current;
+        let period = ring.time_period();
+        let all_hsdirs = netdir
+            .all_hsdirs()
+            .map(|(_, relay)| *relay.md.ed25519_id())
+            .collect_vec();
+
+        let expected = |spread| {
+            responsible_hsdirs(
+                &hsid,
+                period,
+                ring.params().shared_rand(),
+                all_hsdirs.iter().copied(),
+                netdir.n_replicas(),
+                spread,
+            )
+        };
+
+        let mut download = netdir
+            .hs_dirs_download(hsid, period, &mut testing_rng())
+            .unwrap()
+            .iter()
+            .map(|r| *r.ed_identity())
+            .collect_vec();
+        let mut want = expected(netdir.spread(HsDirOp::Download));
+        download.sort();
+        want.sort();
+        assert_eq!(download, want);
+
+        #[cfg(feature = "hs-service")]
+        {
+            let upload = netdir
+                .hs_dirs_upload(hsid, period)
+                .unwrap()
+                .map(|r| *r.ed_identity())
+                .collect_vec();
+            assert_eq!(upload, expected(netdir.spread(HsDirOp::Upload)));
+        }
     }
 }
@@ -86,6 +86,58 @@ impl FromInt32Saturating for SendMeVersion {
     }
 }
 
+/// An object that can be turned back into the i32 it was constructed from.
+///
+/// This is the inverse of [`FromInt32Saturating`] (for in-range values);
+/// we only need it to test that our parameters match other tables of them.
+#[cfg(test)]
+trait ToInt32 {
+    /// Return the value of this object, as an i32.
+    fn to_i32(self) -> i32;
+}
+#[cfg(test)]
+impl<const L: i32, const H: i32> ToInt32 for BoundedInt32<L, H> {
+    fn to_i32(self) -> i32 {
+        self.get()
+    }
+}
+#[cfg(test)]
+impl<T: Copy + Into<f64> + ToInt32> ToInt32 for Percentage<T> {
+    fn to_i32(self) -> i32 {
+        self.as_percent().to_i32()
+    }
+}
+#[cfg(test)]
+impl<T: ToInt32> ToInt32 for IntegerMilliseconds<T> {
+    fn to_i32(self) -> i32 {
+        self.as_millis().to_i32()
+    }
+}
+#[cfg(test)]
+impl<T: ToInt32> ToInt32 for IntegerSeconds<T> {
+    fn to_i32(self) -> i32 {
+        self.as_secs().to_i32()
+    }
+}
+#[cfg(test)]
+impl<T: ToInt32> ToInt32 for IntegerMinutes<T> {
+    fn to_i32(self) -> i32 {
+        self.as_minutes().to_i32()
+    }
+}
+#[cfg(test)]
+impl<T: ToInt32> ToInt32 for IntegerDays<T> {
+    fn to_i32(self) -> i32 {
+        self.as_days().to_i32()
+    }
+}
+#[cfg(test)]
+impl ToInt32 for SendMeVersion {
+    fn to_i32(self) -> i32 {
+        self.get().into()
+    }
+}
+
 /// A macro to help us declare the net parameters object.  It lets us
 /// put the information about each parameter in just one place, even
 /// though it will later get split between the struct declaration, the
@@ -135,6 +187,20 @@ macro_rules! declare_net_parameters {
                 }
                 true
             }
+
+            /// The names, in the consensus, of every parameter that we recognize.
+            #[cfg(test)]
+            const NAMES: &'static [&'static str] = &[ $( $p_string ),* ];
+
+            /// Return the current value for the parameter identified in the
+            /// consensus with `key`, or `None` if the key is not recognized.
+            #[cfg(test)]
+            fn get_i32(&self, key: &str) -> Option<i32> {
+                match key {
+                    $( $p_string => Some(self.$p_name.to_i32()), )*
+                    _ => None,
+                }
+            }
         }
     }
 }
@@ -553,6 +619,33 @@ mod test {
     use super::*;
     use std::string::String;
 
+    #[test]
+    fn agrees_with_netdoc() {
+        use tor_netdoc::doc::netstatus::params::find_param;
+
+        // tor-netdoc has its own table of parameters, for callers who
+        // only have a consensus.  It must give the same defaults and bounds.
+        for name in NetParameters::NAMES {
+            let spec = find_param(name)
+                .unwrap_or_else(|| panic!("{name} is missing from tor-netdoc's table"));
+            assert_eq!(
+                NetParameters::default().get_i32(name),
+                Some(spec.default_value()),
+                "default for {name}"
+            );
+            for (val, expected) in [
+                (i32::MIN, spec.min()),
+                (spec.min(), spec.min()),
+                (spec.max(), spec.max()),
+                (i32::MAX, spec.max()),
+            ] {
+                let mut params = NetParameters::default();
+                assert!(params.set_saturating(name, val));
+                assert_eq!(params.get_i32(name), Some(expected), "{name}={val}");
+            }
+        }
+    }
+
     #[test]
     fn empty_list() {
         let mut x = NetParameters::default();
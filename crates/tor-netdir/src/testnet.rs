@@ -19,7 +19,7 @@ use std::time::{Duration, SystemTime};
 #[cfg(feature = "geoip")]
 use tor_geoip::GeoipDb;
 use tor_netdoc::doc::microdesc::{Microdesc, MicrodescBuilder};
-use tor_netdoc::doc::netstatus::MdConsensus;
+use tor_netdoc::doc::netstatus::{ConsensusBuilder, MdConsensus, MdConsensusRouterStatus};
 use tor_netdoc::doc::netstatus::{Lifetime, RelayFlags, RelayWeight, RouterStatusBuilder};
 
 pub use tor_netdoc::{BuildError, BuildResult};
@@ -104,6 +104,30 @@ where
     Ok(dir)
 }
 
+/// As [`construct_custom_netdir_with_params()`], but also call `consensus_func`
+/// on the consensus builder before the consensus is built.
+///
+/// This lets tests customize properties of the consensus itself, such as
+/// its shared random values.
+pub fn construct_custom_netdir_with_consensus<F, C>(
+    func: F,
+    consensus_func: C,
+    lifetime: Option<Lifetime>,
+) -> BuildResult<PartialNetDir>
+where
+    F: FnMut(usize, &mut NodeBuilders),
+    C: FnOnce(&mut ConsensusBuilder<MdConsensusRouterStatus>),
+{
+    let (consensus, microdescs) =
+        construct_custom_network_with_consensus(func, consensus_func, lifetime)?;
+    let mut dir = PartialNetDir::new(consensus, None);
+    for md in microdescs {
+        dir.add_microdesc(md);
+    }
+
+    Ok(dir)
+}
+
 /// As [`construct_custom_network()`], but return a [`PartialNetDir`].
 pub fn construct_custom_netdir<F>(func: F) -> BuildResult<PartialNetDir>
 where
@@ -181,11 +205,25 @@ pub fn construct_network() -> BuildResult<(MdConsensus, Vec<Microdesc>)> {
 /// description of what kind of network to build, and then builds it from
 /// that description.
 pub fn construct_custom_network<F>(
+    func: F,
+    lifetime: Option<Lifetime>,
+) -> BuildResult<(MdConsensus, Vec<Microdesc>)>
+where
+    F: FnMut(usize, &mut NodeBuilders),
+{
+    construct_custom_network_with_consensus(func, |_| {}, lifetime)
+}
+
+/// As [`construct_custom_network()`], but also call `consensus_func` on the
+/// consensus builder once all the relays have been added to it.
+pub fn construct_custom_network_with_consensus<F, C>(
     mut func: F,
+    consensus_func: C,
     lifetime: Option<Lifetime>,
 ) -> BuildResult<(MdConsensus, Vec<Microdesc>)>
 where
     F: FnMut(usize, &mut NodeBuilders),
+    C: FnOnce(&mut ConsensusBuilder<MdConsensusRouterStatus>),
 {
     let f = RelayFlags::RUNNING
         | RelayFlags::VALID
@@ -277,6 +315,7 @@ where
         }
     }
 
+    consensus_func(&mut bld);
     let consensus = bld.testing_consensus()?;
 
     Ok((consensus, microdescs))
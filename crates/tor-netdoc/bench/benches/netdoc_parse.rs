@@ -0,0 +1,96 @@
+//! This is a wallclock time microbenchmark for parsing directory documents,
+//! using the Criterion framework.
+//!
+//! The documents are the ones in the crate's testdata directory.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use hex_literal::hex;
+use tor_checkable::{SelfSigned as _, Timebound as _};
+use tor_netdoc::doc::hsdesc::HsDesc;
+use tor_netdoc::doc::microdesc::MicrodescReader;
+use tor_netdoc::doc::netstatus::{MdConsensus, NsConsensus};
+use tor_netdoc::AllowAnnotations;
+
+/// A microdescriptor consensus.
+const MD_CONSENSUS: &str = include_str!("../../testdata/mdconsensus1.txt");
+/// A full ("ns") consensus.
+const NS_CONSENSUS: &str = include_str!("../../testdata/nsconsensus1.txt");
+/// A set of microdescriptors.
+const MICRODESCS: &str = include_str!("../../testdata/microdesc2.txt");
+/// An onion service descriptor, without client authorization.
+const HSDESC: &str = include_str!("../../testdata/hsdesc1.txt");
+
+/// The blinded identity for [`HSDESC`].
+const HSDESC_BLIND_ID: [u8; 32] =
+    hex!("43cc0d62fc6252f578705ca645a46109e265290343b1137e90189744b20b3f2d");
+/// The subcredential for [`HSDESC`].
+const HSDESC_SUBCREDENTIAL: [u8; 32] =
+    hex!("78210A0D2C72BB7A0CAF606BCD938B9A3696894FDDDBC3B87D424753A7E3DF37");
+
+fn consensus_bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("consensus");
+
+    group.throughput(Throughput::Bytes(MD_CONSENSUS.len() as u64));
+    group.bench_function("parse-md", |b| {
+        b.iter(|| MdConsensus::parse(black_box(MD_CONSENSUS)).unwrap());
+    });
+
+    group.throughput(Throughput::Bytes(NS_CONSENSUS.len() as u64));
+    group.bench_function("parse-ns", |b| {
+        b.iter(|| NsConsensus::parse(black_box(NS_CONSENSUS)).unwrap());
+    });
+
+    group.finish();
+}
+
+fn microdesc_bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("microdesc");
+    group.throughput(Throughput::Bytes(MICRODESCS.len() as u64));
+    group.bench_function("parse", |b| {
+        b.iter(|| {
+            MicrodescReader::new(
+                black_box(MICRODESCS),
+                &AllowAnnotations::AnnotationsNotAllowed,
+            )
+            .count()
+        });
+    });
+    group.finish();
+}
+
+fn hsdesc_bench(c: &mut Criterion) {
+    let blinded_id = HSDESC_BLIND_ID.into();
+    let subcredential = HSDESC_SUBCREDENTIAL.into();
+    let outer_valid_at = humantime::parse_rfc3339("2023-01-23T15:00:00Z").unwrap();
+    let inner_valid_at = humantime::parse_rfc3339("2023-01-24T03:00:00Z").unwrap();
+
+    let mut group = c.benchmark_group("hsdesc");
+    group.throughput(Throughput::Bytes(HSDESC.len() as u64));
+
+    group.bench_function("parse-outer", |b| {
+        b.iter(|| HsDesc::parse(black_box(HSDESC), &blinded_id).unwrap());
+    });
+
+    let outer = HsDesc::parse(HSDESC, &blinded_id)
+        .unwrap()
+        .check_signature()
+        .unwrap()
+        .check_valid_at(&outer_valid_at)
+        .unwrap();
+    group.bench_function("decrypt", |b| {
+        b.iter(|| {
+            outer
+                .decrypt(&subcredential, None)
+                .unwrap()
+                .check_valid_at(&inner_valid_at)
+                .unwrap()
+                .check_signature()
+                .unwrap()
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, consensus_bench, microdesc_bench, hsdesc_bench);
+criterion_main!(benches);
@@ -95,11 +95,11 @@ impl Microdesc {
     /// This function is only available when the crate is built with the
     /// `build_docs` feature.
     ///
-    /// # Limitations
-    ///
-    /// The generated microdescriptors cannot yet be encoded, and do
-    /// not yet have correct sha256 digests. As such they are only
-    /// useful for testing.
+    /// The builder can be encoded into a microdescriptor string with
+    /// [`NetdocBuilder::build_sign`](crate::NetdocBuilder::build_sign).
+    /// The microdescriptors returned by
+    /// [`MicrodescBuilder::testing_md`] do not have correct sha256 digests;
+    /// parse an encoded microdescriptor if you need one.
     #[cfg(feature = "build_docs")]
     pub fn builder() -> MicrodescBuilder {
         MicrodescBuilder::new()
@@ -1,16 +1,18 @@
-//! Facilities to construct microdescriptor objects.
-//!
-//! (These are only for testing right now, since we don't yet
-//! support encoding.)
+//! Facilities to construct and encode microdescriptor objects.
 
-use super::Microdesc;
+use super::{Microdesc, MicrodescKwd};
 
+use crate::build::{NetdocBuilder, NetdocEncoder};
 use crate::types::family::RelayFamily;
 use crate::types::policy::PortPolicy;
 use crate::{BuildError as Error, BuildResult as Result, Error as ParseError};
+use tor_bytes::EncodeError;
+use tor_error::bad_api_usage;
 use tor_llcrypto::pk::{curve25519, ed25519};
 
-use rand::Rng;
+use base64ct::{Base64Unpadded, Encoding};
+use itertools::Itertools as _;
+use rand::{CryptoRng, Rng, RngCore};
 
 /// A builder object used to construct a microdescriptor.
 ///
@@ -116,12 +118,12 @@ impl MicrodescBuilder {
     ///
     /// # Limitations
     ///
-    /// This is only for testing, since it does actually encode the
+    /// This is only for testing, since it does not actually encode the
     /// information in a string, and since it sets the sha256 digest
     /// field at random.
     ///
-    /// In the future, when we have authority support, we'll need an
-    /// encoder function instead.
+    /// To make a microdescriptor whose digest matches its contents,
+    /// encode it with [`NetdocBuilder::build_sign`] and parse the result.
     pub fn testing_md(&self) -> Result<Microdesc> {
         let ntor_onion_key = self
             .ntor_onion_key
@@ -145,6 +147,51 @@ impl MicrodescBuilder {
     }
 }
 
+/// Encode a microdescriptor.
+///
+/// Microdescriptors are not signed, so the random number generator is unused.
+///
+/// The TAP `onion-key` is emitted without its (optional) object,
+/// since we never use it.
+impl NetdocBuilder for MicrodescBuilder {
+    fn build_sign<R: RngCore + CryptoRng>(
+        self,
+        _: &mut R,
+    ) -> std::result::Result<String, EncodeError> {
+        use MicrodescKwd::*;
+
+        let ntor_onion_key = self
+            .ntor_onion_key
+            .ok_or_else(|| bad_api_usage!("Missing ntor_key"))?;
+        let ed25519_id = self
+            .ed25519_id
+            .ok_or_else(|| bad_api_usage!("Missing ed25519_id"))?;
+
+        let mut encoder = NetdocEncoder::new();
+        encoder.item(ONION_KEY);
+        encoder
+            .item(NTOR_ONION_KEY)
+            .arg(&Base64Unpadded::encode_string(ntor_onion_key.as_bytes()));
+        if !self.family.is_empty() {
+            encoder
+                .item(FAMILY)
+                .args_raw_string(&self.family.members().join(" "));
+        }
+        if self.ipv4_policy.allows_some_port() {
+            encoder.item(P).args_raw_string(&self.ipv4_policy);
+        }
+        if self.ipv6_policy.allows_some_port() {
+            encoder.item(P6).args_raw_string(&self.ipv6_policy);
+        }
+        encoder
+            .item(ID)
+            .arg(&"ed25519")
+            .arg(&Base64Unpadded::encode_string(ed25519_id.as_bytes()));
+
+        encoder.finish().map_err(|e| e.into())
+    }
+}
+
 #[cfg(test)]
 mod test {
     // @@ begin test lint list maintained by maint/add_warning @@
@@ -161,6 +208,9 @@ mod test {
     #![allow(clippy::needless_pass_by_value)]
     //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
     use super::*;
+    use digest::Digest as _;
+    use tor_basic_utils::test_rng::testing_rng;
+    use tor_llcrypto::d::Sha256;
 
     #[test]
     fn minimal() {
@@ -224,4 +274,55 @@ mod test {
             assert!(builder.testing_md().is_err()); // no ed id.
         }
     }
+
+    #[test]
+    fn encode() -> Result<()> {
+        let ed: ed25519::Ed25519Identity = (*b"this is not much of a public key").into();
+        let ntor: curve25519::PublicKey = (*b"but fortunately nothing cares...").into();
+
+        let mut builder = Microdesc::builder();
+        builder.ed25519_id(ed).ntor_key(ntor);
+        let text = builder.clone().build_sign(&mut testing_rng()).unwrap();
+        assert_eq!(
+            text,
+            "onion-key\n\
+             ntor-onion-key YnV0IGZvcnR1bmF0ZWx5IG5vdGhpbmcgY2FyZXMuLi4\n\
+             id ed25519 dGhpcyBpcyBub3QgbXVjaCBvZiBhIHB1YmxpYyBrZXk\n"
+        );
+
+        builder
+            .parse_ipv4_policy("accept 80,443")?
+            .parse_ipv6_policy("accept 22-80")?
+            .parse_family("$aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa $bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb")?;
+        let text = builder.build_sign(&mut testing_rng()).unwrap();
+        let md = Microdesc::parse(&text).unwrap();
+
+        assert_eq!(md.ed25519_id(), &ed);
+        assert_eq!(md.ntor_key(), &ntor);
+        assert_eq!(md.family().members().count(), 2);
+        assert!(md.family().contains(&[0xbb; 20].into()));
+        assert!(md.ipv4_policy().allows_port(443));
+        assert!(!md.ipv4_policy().allows_port(55));
+        assert!(md.ipv6_policy().allows_port(55));
+        assert!(!md.ipv6_policy().allows_port(443));
+
+        let digest: [u8; 32] = Sha256::digest(text.as_bytes()).into();
+        assert_eq!(md.digest(), &digest);
+
+        Ok(())
+    }
+
+    #[test]
+    fn encode_failing() {
+        let ed: ed25519::Ed25519Identity = (*b"this is not much of a public key").into();
+        let ntor: curve25519::PublicKey = (*b"but fortunately nothing cares...").into();
+
+        let mut builder = Microdesc::builder();
+        builder.ed25519_id(ed);
+        assert!(builder.build_sign(&mut testing_rng()).is_err()); // no ntor
+
+        let mut builder = Microdesc::builder();
+        builder.ntor_key(ntor);
+        assert!(builder.build_sign(&mut testing_rng()).is_err()); // no ed id.
+    }
 }
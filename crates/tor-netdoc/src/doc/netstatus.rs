@@ -43,6 +43,7 @@
 //! As with the other tor-netdoc types, I'm deferring those till I know what
 //! they should be.
 
+pub mod params;
 mod rs;
 
 #[cfg(feature = "build_docs")]
@@ -57,6 +58,7 @@ use crate::util::private::Sealed;
 use crate::util::PeekableIterator;
 use crate::{Error, NetdocErrorKind as EK, Pos, Result};
 use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use std::{net, result, time};
 use tor_error::internal;
 use tor_protover::Protocols;
@@ -75,6 +77,7 @@ pub use build::ConsensusBuilder;
 #[cfg(feature = "build_docs")]
 pub use rs::build::RouterStatusBuilder;
 
+pub use params::{ConsensusParams, ParamSpec};
 pub use rs::MdConsensusRouterStatus;
 #[cfg(feature = "ns_consensus")]
 pub use rs::NsConsensusRouterStatus;
@@ -344,6 +347,23 @@ pub struct SharedRandStatus {
     timestamp: Option<time::SystemTime>,
 }
 
+/// How many voting periods make up an entire round of the shared random value
+/// commit-and-reveal protocol?
+///
+/// We use this to compute an SRV lifetime if one of the SRV values is missing.
+const VOTING_PERIODS_IN_SRV_ROUND: u32 = 24;
+
+/// One day.
+const ONE_DAY: time::Duration = time::Duration::new(86400, 0);
+
+/// Return a time at the start of the UTC day containing `t`.
+fn start_of_day_containing(t: time::SystemTime) -> time::SystemTime {
+    ::time::OffsetDateTime::from(t)
+        .to_offset(::time::UtcOffset::UTC)
+        .replace_time(::time::macros::time!(00:00))
+        .into()
+}
+
 /// Parts of the networkstatus header that are present in every networkstatus.
 ///
 /// NOTE: this type is separate from the header parts that are only in
@@ -680,6 +700,14 @@ impl<RS> Consensus<RS> {
         &self.header.hdr.params
     }
 
+    /// Return a typed view of the network parameters that this consensus advertises.
+    ///
+    /// Unlike [`params`](Consensus::params), this applies the defaults and bounds
+    /// from the parameter specification.
+    pub fn typed_params(&self) -> ConsensusParams<'_> {
+        ConsensusParams::new(&self.header.hdr.params)
+    }
+
     /// Return the latest shared random value, if the consensus
     /// contains one.
     pub fn shared_rand_cur(&self) -> Option<&SharedRandStatus> {
@@ -692,6 +720,87 @@ impl<RS> Consensus<RS> {
         self.header.shared_rand_prev.as_ref()
     }
 
+    /// Return the length of time for which each shared random value in this
+    /// consensus is the most recent one.
+    pub fn shared_rand_interval(&self) -> time::Duration {
+        // What we _want_ to do, ideally, is is to learn the duration from the
+        // difference between the declared time for the previous value and the
+        // declared time for the current one.
+        //
+        // (This assumes that proposal 342 is implemented.)
+        if let (Some(cur), Some(prev)) = (self.shared_rand_cur(), self.shared_rand_prev()) {
+            if let (Some(cur_ts), Some(prev_ts)) = (cur.timestamp(), prev.timestamp()) {
+                if let Ok(d) = cur_ts.duration_since(prev_ts) {
+                    return d;
+                }
+            }
+        }
+
+        // But if one of those values is missing, or if it has no timestamp, we have
+        // to fall back to admitting that we know the schedule for the voting
+        // algorithm.
+        self.lifetime().voting_period() * VOTING_PERIODS_IN_SRV_ROUND
+    }
+
+    /// Return the range of times over which the current shared random value
+    /// is the most recent one, if the consensus contains one.
+    ///
+    /// If the value has no timestamp, we assume that it became current at
+    /// the start of the UTC day when this consensus became valid.
+    pub fn shared_rand_cur_lifespan(&self) -> Option<Range<time::SystemTime>> {
+        let cur = self.shared_rand_cur()?;
+        let begin = cur
+            .timestamp()
+            .unwrap_or_else(|| start_of_day_containing(self.lifetime().valid_after()));
+        Some(begin..begin + self.shared_rand_interval())
+    }
+
+    /// Return the range of times over which the previous shared random value
+    /// was the most recent one, if the consensus contains one.
+    ///
+    /// If the value has no timestamp, we assume that it became current at
+    /// the start of the UTC day before the one when this consensus became valid.
+    pub fn shared_rand_prev_lifespan(&self) -> Option<Range<time::SystemTime>> {
+        let prev = self.shared_rand_prev()?;
+        let begin = prev
+            .timestamp()
+            .unwrap_or_else(|| start_of_day_containing(self.lifetime().valid_after()) - ONE_DAY);
+        Some(begin..begin + self.shared_rand_interval())
+    }
+
+    /// Return the shared random value that was the most recent one at `when`,
+    /// along with the range of times over which it was the most recent one.
+    ///
+    /// Return None if that value is not one of the ones in this consensus.
+    pub fn shared_rand_at(
+        &self,
+        when: time::SystemTime,
+    ) -> Option<(&SharedRandStatus, Range<time::SystemTime>)> {
+        [
+            (self.shared_rand_cur(), self.shared_rand_cur_lifespan()),
+            (self.shared_rand_prev(), self.shared_rand_prev_lifespan()),
+        ]
+        .into_iter()
+        .find_map(|(srv, lifespan)| match (srv, lifespan) {
+            (Some(srv), Some(lifespan)) if lifespan.contains(&when) => Some((srv, lifespan)),
+            _ => None,
+        })
+    }
+
+    /// Return the shared random value to use with the onion service time
+    /// period `period`, along with the range of times over which it was the
+    /// most recent one.
+    ///
+    /// This is the value that was the most recent one at the start of `period`.
+    /// Return None if that value is not one of the ones in this consensus.
+    #[cfg(feature = "hs-common")]
+    pub fn shared_rand_for_period(
+        &self,
+        period: tor_hscrypto::time::TimePeriod,
+    ) -> Option<(&SharedRandStatus, Range<time::SystemTime>)> {
+        self.shared_rand_at(period.range().ok()?.start)
+    }
+
     /// Return a [`ProtoStatus`] that lists the network's current requirements and
     /// recommendations for the list of protocols that every relay must implement.  
     pub fn relay_protocol_status(&self) -> &ProtoStatus {
@@ -1091,6 +1200,12 @@ impl SharedRandStatus {
         })
     }
 
+    /// Return the number of authorities that revealed shares that
+    /// contributed to this value.
+    pub fn n_reveals(&self) -> u8 {
+        self.n_reveals
+    }
+
     /// Return the actual shared random value.
     pub fn value(&self) -> &SharedRandVal {
         &self.value
@@ -1316,6 +1431,101 @@ enum SigCheckResult {
     MissingCert,
 }
 
+/// A detailed account of which signatures on a consensus we could check.
+///
+/// Unlike [`ExternallySigned::is_well_signed`], which only says whether
+/// a consensus is well-signed, this type says what we learned about each
+/// signature, so that callers can explain what went wrong, or warn
+/// about problems that are about to happen.
+///
+/// Returned by [`UnvalidatedConsensus::signature_report`].
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct SignatureReport {
+    /// Signatures that we checked, and found to be valid.
+    pub valid: Vec<AuthCertKeyIds>,
+    /// Signatures that were not valid for the matching certificate.
+    pub invalid: Vec<AuthCertKeyIds>,
+    /// Signatures for which we had no matching certificate.
+    pub missing_cert: Vec<AuthCertKeyIds>,
+    /// Signatures made with a digest algorithm that we can't check.
+    pub unsupported: Vec<AuthCertKeyIds>,
+    /// Certificates used to make valid signatures that will expire before
+    /// the deadline given to [`UnvalidatedConsensus::signature_report`],
+    /// along with their expiry times.
+    pub expiring: Vec<(AuthCertKeyIds, time::SystemTime)>,
+    /// The number of distinct authorities that made at least one valid
+    /// signature.
+    pub n_valid_authorities: usize,
+    /// The number of authorities that need to have signed for the consensus
+    /// to be well-signed, if we know how many authorities there are.
+    pub n_needed: Option<usize>,
+}
+
+/// A problem with the signatures on a consensus that we should warn about.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum SignatureWarning {
+    /// Too few authorities made valid signatures on the consensus.
+    BelowThreshold {
+        /// The number of authorities that made valid signatures.
+        n_valid: usize,
+        /// The number of authorities we need.
+        n_needed: usize,
+    },
+    /// Enough authorities made valid signatures, but some of their
+    /// certificates expire soon.  Once they do, we will no longer have
+    /// enough, unless we can get newer certificates.
+    CertsExpiring {
+        /// The number of authorities that made valid signatures using
+        /// certificates that are not about to expire.
+        n_valid_after_expiry: usize,
+        /// The number of authorities we need.
+        n_needed: usize,
+        /// The earliest expiry time among the expiring certificates.
+        first_expiry: time::SystemTime,
+    },
+}
+
+impl SignatureReport {
+    /// Return true if enough authorities made valid signatures for the
+    /// consensus to be well-signed.
+    pub fn is_sufficient(&self) -> bool {
+        matches!(self.n_needed, Some(n) if self.n_valid_authorities >= n)
+    }
+
+    /// Return the most serious problem described by this report, if any.
+    ///
+    /// Returns `None` if we don't know how many authorities there are.
+    pub fn warning(&self) -> Option<SignatureWarning> {
+        let n_needed = self.n_needed?;
+        if self.n_valid_authorities < n_needed {
+            return Some(SignatureWarning::BelowThreshold {
+                n_valid: self.n_valid_authorities,
+                n_needed,
+            });
+        }
+
+        let first_expiry = self.expiring.iter().map(|(_, when)| *when).min()?;
+        let expiring_ids: HashSet<&RsaIdentity> = self
+            .expiring
+            .iter()
+            .map(|(ids, _)| &ids.id_fingerprint)
+            .collect();
+        let lasting_ids: HashSet<&RsaIdentity> = self
+            .valid
+            .iter()
+            .map(|ids| &ids.id_fingerprint)
+            .filter(|id| !expiring_ids.contains(id))
+            .collect();
+        (lasting_ids.len() < n_needed).then_some(SignatureWarning::CertsExpiring {
+            n_valid_after_expiry: lasting_ids.len(),
+            n_needed,
+            first_expiry,
+        })
+    }
+}
+
 impl Signature {
     /// Parse a Signature from a directory-signature section
     fn from_item(item: &Item<'_, NetstatusKwd>) -> Result<Signature> {
@@ -1642,6 +1852,48 @@ impl<RS> UnvalidatedConsensus<RS> {
         .into_iter()
     }
 
+    /// Check every signature on this consensus against `certs`, and report
+    /// the outcome for each one.
+    ///
+    /// Any certificate used for a valid signature that expires before
+    /// `expiry_deadline` is listed in [`SignatureReport::expiring`].
+    ///
+    /// As with [`check_signature`](ExternallySigned::check_signature), every
+    /// cert in `certs` must belong to a real authority.
+    pub fn signature_report(
+        &self,
+        certs: &[AuthCert],
+        expiry_deadline: time::SystemTime,
+    ) -> SignatureReport {
+        self.siggroup
+            .report(self.n_authorities, certs, expiry_deadline)
+    }
+
+    /// Check the signatures on this consensus against `certs`, and return
+    /// both a [`SignatureReport`] and the validated consensus.
+    ///
+    /// This checks each signature only once: use it instead of calling
+    /// [`signature_report`](Self::signature_report) and then
+    /// [`check_signature`](ExternallySigned::check_signature).
+    ///
+    /// The consensus is valid if and only if [`SignatureReport::is_sufficient`]
+    /// returns true.
+    pub fn check_signature_with_report(
+        self,
+        certs: &[AuthCert],
+        expiry_deadline: time::SystemTime,
+    ) -> (SignatureReport, Result<Consensus<RS>>) {
+        let report = self.signature_report(certs, expiry_deadline);
+        let outcome = match self.n_authorities {
+            None => Err(Error::from(internal!(
+                "Didn't set authorities on consensus"
+            ))),
+            Some(_) if report.is_sufficient() => Ok(self.consensus),
+            Some(_) => Err(EK::BadSignature.err()),
+        };
+        (report, outcome)
+    }
+
     /// Return the lifetime of this unvalidated consensus
     pub fn peek_lifetime(&self) -> &Lifetime {
         self.consensus.lifetime()
@@ -1801,6 +2053,51 @@ impl SignatureGroup {
 
         ok.len() > (n_authorities / 2) as usize
     }
+
+    /// Check every signature in this group, and describe the outcome.
+    ///
+    /// See [`UnvalidatedConsensus::signature_report`].
+    fn report(
+        &self,
+        n_authorities: Option<u16>,
+        certs: &[AuthCert],
+        expiry_deadline: time::SystemTime,
+    ) -> SignatureReport {
+        let mut report = SignatureReport {
+            n_needed: n_authorities.map(|n| (n / 2) as usize + 1),
+            ..Default::default()
+        };
+        let mut ok: HashSet<RsaIdentity> = HashSet::new();
+
+        for sig in &self.signatures {
+            let d: Option<&[u8]> = match sig.digestname.as_ref() {
+                "sha256" => self.sha256.as_ref().map(|a| &a[..]),
+                "sha1" => self.sha1.as_ref().map(|a| &a[..]),
+                _ => None,
+            };
+            let Some(d) = d else {
+                report.unsupported.push(sig.key_ids);
+                continue;
+            };
+
+            match sig.check_signature(d, certs) {
+                SigCheckResult::Valid => {
+                    ok.insert(sig.key_ids.id_fingerprint);
+                    report.valid.push(sig.key_ids);
+                    if let Some(cert) = sig.find_cert(certs) {
+                        if cert.expires() < expiry_deadline {
+                            report.expiring.push((sig.key_ids, cert.expires()));
+                        }
+                    }
+                }
+                SigCheckResult::Invalid => report.invalid.push(sig.key_ids),
+                SigCheckResult::MissingCert => report.missing_cert.push(sig.key_ids),
+            }
+        }
+
+        report.n_valid_authorities = ok.len();
+        report
+    }
 }
 
 #[cfg(test)]
@@ -1906,6 +2203,72 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn signature_report() -> Result<()> {
+        use tor_checkable::{SelfSigned, Timebound};
+        let certs: Vec<AuthCert> = AuthCert::parse_multiple(CERTS)
+            .map(|cert| Ok(cert?.check_signature()?.dangerously_assume_timely()))
+            .collect::<Result<_>>()?;
+        let (_, _, consensus) = MdConsensus::parse(CONSENSUS)?;
+        let consensus = consensus.dangerously_assume_timely();
+        let early = humantime::parse_rfc3339("2021-01-01T00:00:00Z").unwrap();
+        let late = humantime::parse_rfc3339("2021-08-07T12:40:26Z").unwrap();
+
+        // We don't know how many authorities there are yet.
+        let report = consensus.signature_report(&certs, early);
+        assert_eq!(report.n_valid_authorities, 3);
+        assert_eq!(report.n_needed, None);
+        assert!(!report.is_sufficient());
+        assert_eq!(report.warning(), None);
+
+        let consensus = consensus.set_n_authorities(3);
+        let report = consensus.signature_report(&certs, early);
+        assert_eq!(report.valid.len(), 3);
+        assert!(report.invalid.is_empty());
+        assert!(report.missing_cert.is_empty());
+        assert!(report.expiring.is_empty());
+        assert_eq!(report.n_needed, Some(2));
+        assert!(report.is_sufficient());
+        assert_eq!(report.warning(), None);
+
+        // Two of the certs expire a second before the third.
+        let report = consensus.signature_report(&certs, late);
+        assert_eq!(report.expiring.len(), 2);
+        assert_eq!(
+            report.warning(),
+            Some(SignatureWarning::CertsExpiring {
+                n_valid_after_expiry: 1,
+                n_needed: 2,
+                first_expiry: humantime::parse_rfc3339("2021-08-07T12:40:25Z").unwrap(),
+            })
+        );
+
+        let report = consensus.signature_report(&certs[0..1], early);
+        assert_eq!(report.valid.len(), 1);
+        assert_eq!(report.missing_cert.len(), 2);
+        assert!(!report.is_sufficient());
+        assert_eq!(
+            report.warning(),
+            Some(SignatureWarning::BelowThreshold {
+                n_valid: 1,
+                n_needed: 2
+            })
+        );
+
+        let (report, outcome) = consensus
+            .clone()
+            .check_signature_with_report(&certs[0..1], early);
+        assert!(!report.is_sufficient());
+        assert!(outcome.is_err());
+
+        let (report, outcome) = consensus.check_signature_with_report(&certs, late);
+        assert!(report.is_sufficient());
+        assert_eq!(report.expiring.len(), 2);
+        assert!(outcome.is_ok());
+
+        Ok(())
+    }
+
     #[test]
     #[cfg(feature = "ns_consensus")]
     fn parse_and_validate_ns() -> Result<()> {
@@ -2034,6 +2397,23 @@ mod test {
         assert!(p.is_err());
     }
 
+    #[test]
+    fn start_of_day() {
+        let t = |s| humantime::parse_rfc3339(s).unwrap();
+        assert_eq!(
+            start_of_day_containing(t("1985-10-25T07:00:00Z")),
+            t("1985-10-25T00:00:00Z")
+        );
+        assert_eq!(
+            start_of_day_containing(t("1985-10-25T00:00:00Z")),
+            t("1985-10-25T00:00:00Z")
+        );
+        assert_eq!(
+            start_of_day_containing(t("1985-10-25T23:59:59.999Z")),
+            t("1985-10-25T00:00:00Z")
+        );
+    }
+
     #[test]
     fn test_sharedrand() {
         let sr =
@@ -2041,7 +2421,7 @@ mod test {
                 .unwrap();
         let sr = SharedRandStatus::from_item(&sr).unwrap();
 
-        assert_eq!(sr.n_reveals, 9);
+        assert_eq!(sr.n_reveals(), 9);
         assert_eq!(
             sr.value.0,
             hex!("e4ba1d638c96c458532adc6957dc0080d03d37c7e5854087d0da90bf5ff4e72e")
@@ -460,4 +460,47 @@ mod test {
 
         // TODO: Check actual members of `cons` above.
     }
+
+    #[test]
+    fn shared_rand_lifespans() {
+        let t = |s| humantime::parse_rfc3339(s).unwrap();
+        let valid_after = t("1985-10-25T07:00:00Z");
+        let one_hour = Duration::new(3600, 0);
+
+        let mut builder = crate::doc::netstatus::MdConsensus::builder();
+        builder
+            .lifetime(
+                Lifetime::new(
+                    valid_after,
+                    valid_after + one_hour,
+                    valid_after + 2 * one_hour,
+                )
+                .unwrap(),
+            )
+            .consensus_method(32)
+            .shared_rand_prev(5, SharedRandVal([b'x'; 32]), None)
+            .shared_rand_cur(9, SharedRandVal([b'y'; 32]), None);
+        let cons = builder.testing_consensus().unwrap();
+
+        // Without timestamps, each value lasts for a UTC day.
+        assert_eq!(cons.shared_rand_interval(), Duration::new(86400, 0));
+        assert_eq!(
+            cons.shared_rand_cur_lifespan(),
+            Some(t("1985-10-25T00:00:00Z")..t("1985-10-26T00:00:00Z"))
+        );
+        assert_eq!(
+            cons.shared_rand_prev_lifespan(),
+            Some(t("1985-10-24T00:00:00Z")..t("1985-10-25T00:00:00Z"))
+        );
+
+        let (srv, lifespan) = cons.shared_rand_at(t("1985-10-25T12:00:00Z")).unwrap();
+        assert_eq!(srv.value(), &SharedRandVal([b'y'; 32]));
+        assert_eq!(srv.n_reveals(), 9);
+        assert_eq!(lifespan.start, t("1985-10-25T00:00:00Z"));
+        let (srv, _) = cons.shared_rand_at(t("1985-10-24T12:00:00Z")).unwrap();
+        assert_eq!(srv.value(), &SharedRandVal([b'x'; 32]));
+        assert_eq!(srv.n_reveals(), 5);
+        assert!(cons.shared_rand_at(t("1985-10-23T12:00:00Z")).is_none());
+        assert!(cons.shared_rand_at(t("1985-10-26T00:00:00Z")).is_none());
+    }
 }
@@ -0,0 +1,377 @@
+//! Typed access to the network parameters in a consensus.
+//!
+//! The "params" line of a consensus is just a list of `key=value` pairs,
+//! which we expose as a [`NetParams<i32>`].  This module adds a table of the
+//! parameters described in
+//! [param-spec](https://spec.torproject.org/param-spec.html),
+//! with their default values and permitted ranges, so that consumers
+//! don't have to re-implement the bounds for every parameter they read.
+//!
+//! Where a parameter is also recognized by `tor_netdir::params::NetParameters`,
+//! the two agree on its default value and bounds.  (tor-netdir's tests
+//! check this, and that this table lists every parameter that it recognizes.)
+
+use super::NetParams;
+
+/// The specification for a single consensus parameter.
+///
+/// Use [`ConsensusParams::get`] (or [`ParamSpec::value_in`]) to look up the
+/// value of this parameter in a particular consensus.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ParamSpec {
+    /// The name of this parameter, as it appears in the consensus.
+    name: &'static str,
+    /// The value to use when the parameter is absent.
+    default: i32,
+    /// The lowest permitted value.
+    min: i32,
+    /// The highest permitted value.
+    max: i32,
+}
+
+impl ParamSpec {
+    /// Construct a new `ParamSpec`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `default` is not within `min..=max`.
+    pub const fn new(name: &'static str, default: i32, min: i32, max: i32) -> Self {
+        assert!(min <= default && default <= max);
+        ParamSpec {
+            name,
+            default,
+            min,
+            max,
+        }
+    }
+
+    /// Return the name of this parameter, as it appears in the consensus.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Return the value that this parameter has when the consensus doesn't list it.
+    pub fn default_value(&self) -> i32 {
+        self.default
+    }
+
+    /// Return the lowest permitted value for this parameter.
+    pub fn min(&self) -> i32 {
+        self.min
+    }
+
+    /// Return the highest permitted value for this parameter.
+    pub fn max(&self) -> i32 {
+        self.max
+    }
+
+    /// Clamp `val` to the permitted range for this parameter.
+    pub fn clamp(&self, val: i32) -> i32 {
+        val.clamp(self.min, self.max)
+    }
+
+    /// Return the value of this parameter in `params`.
+    ///
+    /// Out-of-range values are clamped; a missing value is replaced with the default.
+    pub fn value_in(&self, params: &NetParams<i32>) -> i32 {
+        params
+            .get(self.name)
+            .map_or(self.default, |val| self.clamp(*val))
+    }
+}
+
+/// A typed view of the network parameters in a consensus.
+///
+/// Returned by [`Consensus::typed_params`](super::Consensus::typed_params),
+/// or constructed from any [`NetParams<i32>`] with [`ConsensusParams::new`].
+#[derive(Debug, Clone, Copy)]
+pub struct ConsensusParams<'a> {
+    /// The underlying list of parameters.
+    params: &'a NetParams<i32>,
+}
+
+impl<'a> ConsensusParams<'a> {
+    /// Wrap `params` to provide typed access to it.
+    pub fn new(params: &'a NetParams<i32>) -> Self {
+        ConsensusParams { params }
+    }
+
+    /// Return the value of the parameter described by `spec`.
+    ///
+    /// Out-of-range values are clamped; a missing value is replaced with the default.
+    pub fn get(&self, spec: &ParamSpec) -> i32 {
+        spec.value_in(self.params)
+    }
+
+    /// Return the value of the boolean parameter described by `spec`.
+    ///
+    /// Any nonzero value is treated as true.
+    pub fn get_bool(&self, spec: &ParamSpec) -> bool {
+        self.get(spec) != 0
+    }
+
+    /// Return the value of every recognized parameter, in the order of [`ALL_PARAMS`].
+    ///
+    /// Parameters that the consensus doesn't list are given their default values.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static ParamSpec, i32)> + '_ {
+        ALL_PARAMS.iter().map(|spec| (spec, self.get(spec)))
+    }
+
+    /// Return every parameter in the consensus that is not in [`ALL_PARAMS`],
+    /// with its unmodified value.
+    pub fn unrecognized(&self) -> impl Iterator<Item = (&'a str, i32)> {
+        self.params
+            .iter()
+            .filter(|(name, _)| find_param(name).is_none())
+            .map(|(name, val)| (name.as_str(), *val))
+    }
+
+    /// Return the underlying list of parameters.
+    pub fn raw(&self) -> &'a NetParams<i32> {
+        self.params
+    }
+}
+
+/// Return the specification for the parameter called `name`, if we know about it.
+pub fn find_param(name: &str) -> Option<&'static ParamSpec> {
+    ALL_PARAMS.iter().find(|spec| spec.name == name)
+}
+
+/// Helper to declare the table of known parameters.
+///
+/// Each entry becomes a public constant, and is also listed in `ALL_PARAMS`.
+macro_rules! declare_param_specs {
+    {
+        $(
+            $(#[$meta:meta])*
+            $id:ident = ($name:literal, $dflt:expr, $min:expr, $max:expr)
+        ),*
+        $(,)?
+    } => {
+        $(
+            $(#[$meta])*
+            pub const $id: ParamSpec = ParamSpec::new($name, $dflt, $min, $max);
+        )*
+
+        /// Every parameter that we know about.
+        pub const ALL_PARAMS: &[ParamSpec] = &[ $( $id ),* ];
+    }
+}
+
+/// Upper limit for channel padding timeouts, in milliseconds.
+///
+/// (This matches `tor_netdir::params::CHANNEL_PADDING_TIMEOUT_UPPER_BOUND`.)
+const CHANNEL_PADDING_TIMEOUT_UPPER_BOUND: i32 = 60_000;
+
+declare_param_specs! {
+    /// A weighting factor for bandwidth calculations.
+    BW_WEIGHT_SCALE = ("bwweightscale", 10_000, 1, i32::MAX),
+    /// The maximum cell window size for circuits.
+    CIRCWINDOW = ("circwindow", 1_000, 100, 1_000),
+    /// The decay parameter for circuit priority, in milliseconds.
+    CIRCUIT_PRIORITY_HALFLIFE_MSEC = ("CircuitPriorityHalflifeMsec", 30_000, 1, i32::MAX),
+    /// Whether exits should refuse to extend to relays they don't know about.
+    REFUSE_UNKNOWN_EXITS = ("refuseunknownexits", 1, 0, 1),
+    /// Whether clients should send data optimistically, before a stream is connected.
+    USE_OPTIMISTIC_DATA = ("UseOptimisticData", 1, 0, 1),
+    /// Whether to perform circuit extensions by Ed25519 ID.
+    EXTEND_BY_ED25519_ID = ("ExtendByEd25519ID", 0, 0, 1),
+    /// The largest number of cells that can be queued on a circuit.
+    CIRC_MAX_CELL_QUEUE_SIZE = ("circ_max_cell_queue_size", 50_000, 1_000, i32::MAX),
+    /// The minimum percentage of paths that must be usable before we build circuits.
+    MIN_PATHS_FOR_CIRCS_PCT = ("min_paths_for_circs_pct", 60, 25, 95),
+
+    /// If true, do not attempt to learn circuit-build timeouts at all.
+    CBT_DISABLED = ("cbtdisabled", 0, 0, 1),
+    /// Number of histogram bins to consider when estimating Xm.
+    CBT_NUM_MODES = ("cbtnummodes", 10, 1, 20),
+    /// How many recent circuit success/timeout statuses to remember.
+    CBT_RECENT_COUNT = ("cbtrecentcount", 20, 3, 1_000),
+    /// How many recent timeouts indicate that our circuit timeouts are too low.
+    CBT_MAX_TIMEOUTS = ("cbtmaxtimeouts", 18, 3, 10_000),
+    /// How many circuit build times we need before using our timeout estimator.
+    CBT_MIN_CIRCS = ("cbtmincircs", 100, 1, 10_000),
+    /// Quantile (as a percentage) to use for the circuit build timeout.
+    CBT_QUANTILE = ("cbtquantile", 80, 10, 99),
+    /// Quantile (as a percentage) at which to abandon circuits completely.
+    CBT_CLOSE_QUANTILE = ("cbtclosequantile", 99, 10, 99),
+    /// Lowest permissible circuit build timeout, in milliseconds.
+    CBT_MIN_TIMEOUT = ("cbtmintimeout", 10, 10, i32::MAX),
+    /// Circuit build timeout to use before we have an estimate, in milliseconds.
+    CBT_INITIAL_TIMEOUT = ("cbtinitialtimeout", 60_000, 10, i32::MAX),
+    /// How long to wait between launching build-time testing circuits, in seconds.
+    CBT_TEST_FREQ = ("cbttestfreq", 10, 1, i32::MAX),
+    /// How many circuits can be open before we stop launching testing circuits.
+    CBT_MAX_OPEN_CIRCS = ("cbtmaxopencircs", 10, 0, 14),
+    /// How long unused client circuits stay available while learning timeouts, in seconds.
+    CBT_LEARN_TIMEOUT = ("cbtlearntimeout", 3 * 60, 10, 60_000),
+
+    /// Channel padding: low end of the random padding interval, in milliseconds.
+    NF_ITO_LOW = ("nf_ito_low", 1_500, 0, CHANNEL_PADDING_TIMEOUT_UPPER_BOUND),
+    /// Channel padding: high end of the random padding interval, in milliseconds.
+    NF_ITO_HIGH = ("nf_ito_high", 9_500, 0, CHANNEL_PADDING_TIMEOUT_UPPER_BOUND),
+    /// Reduced channel padding: low end of the random padding interval, in milliseconds.
+    NF_ITO_LOW_REDUCED = ("nf_ito_low_reduced", 9_000, 0, CHANNEL_PADDING_TIMEOUT_UPPER_BOUND),
+    /// Reduced channel padding: high end of the random padding interval, in milliseconds.
+    NF_ITO_HIGH_REDUCED = ("nf_ito_high_reduced", 14_000, 0, CHANNEL_PADDING_TIMEOUT_UPPER_BOUND),
+    /// How long never-used client circuits stay available, in seconds.
+    NF_CONNTIMEOUT_CLIENTS = ("nf_conntimeout_clients", 30 * 60, 60, 86_400),
+    /// Whether clients should send channel padding before a channel is used.
+    NF_PAD_BEFORE_USAGE = ("nf_pad_before_usage", 1, 0, 1),
+    /// Whether relays should send channel padding to each other.
+    NF_PAD_RELAYS = ("nf_pad_relays", 0, 0, 1),
+    /// Whether single-onion services should send channel padding.
+    NF_PAD_SINGLE_ONION = ("nf_pad_single_onion", 1, 0, 1),
+
+    /// The minimum SENDME version to accept.
+    SENDME_ACCEPT_MIN_VERSION = ("sendme_accept_min_version", 0, 0, 255),
+    /// The minimum SENDME version to transmit.
+    SENDME_EMIT_MIN_VERSION = ("sendme_emit_min_version", 0, 0, 255),
+
+    /// Which congestion control algorithm to use.
+    CC_ALG = ("cc_alg", 2, 0, 2),
+    /// The minimum number of SENDME acks required to estimate RTT and/or bandwidth.
+    CC_BWE_MIN = ("cc_bwe_min", 5, 2, 20),
+    /// The "N" parameter of N-EWMA smoothing, as a percentage of a congestion window.
+    CC_EWMA_CWND_PCT = ("cc_ewma_cwnd_pct", 50, 1, 255),
+    /// The maximum value of the "N" parameter of N-EWMA smoothing.
+    CC_EWMA_MAX = ("cc_ewma_max", 10, 2, i32::MAX),
+    /// The "N" parameter of N-EWMA smoothing during slow start.
+    CC_EWMA_SS = ("cc_ewma_ss", 2, 2, i32::MAX),
+    /// The initial congestion window, in cells.
+    CC_CWND_INIT = ("cc_cwnd_init", 124, 31, 10_000),
+    /// How much to grow the congestion window during slow start, as a percentage.
+    CC_CWND_INC_PCT_SS = ("cc_cwnd_inc_pct_ss", 50, 1, 500),
+    /// How much to grow the congestion window after slow start, in cells.
+    CC_CWND_INC = ("cc_cwnd_inc", 31, 1, 1_000),
+    /// How often to update the congestion window, per congestion window of cells.
+    CC_CWND_INC_RATE = ("cc_cwnd_inc_rate", 1, 1, 250),
+    /// The smallest permitted congestion window, in cells.
+    CC_CWND_MIN = ("cc_cwnd_min", 31, 31, 1_000),
+    /// The largest permitted congestion window, in cells.
+    CC_CWND_MAX = ("cc_cwnd_max", i32::MAX, 500, i32::MAX),
+    /// How many cells a SENDME acknowledges under congestion control.
+    CC_SENDME_INC = ("cc_sendme_inc", 31, 1, 255),
+
+    /// Percentage of guards below which we should use a different guard sample.
+    GUARD_MEANINGFUL_RESTRICTION_PERCENT = ("guard-meaningful-restriction-percent", 20, 1, 100),
+    /// Percentage of guards below which we should warn the user.
+    GUARD_EXTREME_RESTRICTION_PERCENT = ("guard-extreme-restriction-percent", 1, 1, 100),
+    /// How long to keep an unconfirmed guard in the sample, in days.
+    GUARD_LIFETIME_DAYS = ("guard-lifetime-days", 120, 1, 3_650),
+    /// How long to keep a confirmed guard in the sample, in days.
+    GUARD_CONFIRMED_MIN_LIFETIME_DAYS = ("guard-confirmed-min-lifetime-days", 60, 1, 3_650),
+    /// How long all circuits must fail before we treat the internet as probably down, in seconds.
+    GUARD_INTERNET_LIKELY_DOWN_INTERVAL = ("guard-internet-likely-down-interval", 600, 1, i32::MAX),
+    /// The largest number of guards to keep in a guard sample.
+    GUARD_MAX_SAMPLE_SIZE = ("guard-max-sample-size", 60, 1, i32::MAX),
+    /// The largest percentage of guard bandwidth to keep in a guard sample.
+    GUARD_MAX_SAMPLE_THRESHOLD = ("guard-max-sample-threshold", 20, 1, 100),
+    /// The smallest number of usable guards to keep in a guard sample.
+    GUARD_MIN_FILTERED_SAMPLE_SIZE = ("guard-min-filtered-sample-size", 20, 1, i32::MAX),
+    /// The number of primary guards.
+    GUARD_N_PRIMARY_GUARDS = ("guard-n-primary-guards", 3, 1, i32::MAX),
+    /// The number of primary guards to use in parallel.
+    GUARD_N_PRIMARY_GUARDS_TO_USE = ("guard-n-primary-guards-to-use", 1, 1, i32::MAX),
+    /// The number of primary directory guards to use in parallel.
+    GUARD_N_PRIMARY_DIR_GUARDS_TO_USE = ("guard-n-primary-dir-guards-to-use", 3, 1, i32::MAX),
+    /// How long before we treat lower-priority guards as usable, in seconds.
+    GUARD_NONPRIMARY_GUARD_CONNECT_TIMEOUT = ("guard-nonprimary-guard-connect-timeout", 15, 1, i32::MAX),
+    /// How long before we treat an unresponsive nonprimary guard as down, in seconds.
+    GUARD_NONPRIMARY_GUARD_IDLE_TIMEOUT = ("guard-nonprimary-guard-idle-timeout", 600, 1, i32::MAX),
+    /// How long a guard can be unlisted before we remove it from the sample, in days.
+    GUARD_REMOVE_UNLISTED_GUARDS_AFTER_DAYS = ("guard-remove-unlisted-guards-after-days", 20, 1, 3_650),
+
+    /// Which vanguards to use by default: 0 for none, 1 for lite, 2 for full.
+    VANGUARDS_ENABLED = ("vanguards-enabled", 1, 0, 2),
+    /// Which vanguards to use for onion services: 0 for none, 1 for lite, 2 for full.
+    VANGUARDS_HS_SERVICE = ("vanguards-hs-service", 2, 0, 2),
+    /// The number of vanguards in the L2 vanguard set.
+    GUARD_HS_L2_NUMBER = ("guard-hs-l2-number", 4, 1, i32::MAX),
+    /// The minimum lifetime of L2 vanguards, in seconds.
+    GUARD_HS_L2_LIFETIME_MIN = ("guard-hs-l2-lifetime-min", 86_400, 1, i32::MAX),
+    /// The maximum lifetime of L2 vanguards, in seconds.
+    GUARD_HS_L2_LIFETIME_MAX = ("guard-hs-l2-lifetime-max", 1_036_800, 1, i32::MAX),
+    /// The number of vanguards in the L3 vanguard set.
+    GUARD_HS_L3_NUMBER = ("guard-hs-l3-number", 8, 1, i32::MAX),
+    /// The minimum lifetime of L3 vanguards, in seconds.
+    GUARD_HS_L3_LIFETIME_MIN = ("guard-hs-l3-lifetime-min", 3_600, 1, i32::MAX),
+    /// The maximum lifetime of L3 vanguards, in seconds.
+    GUARD_HS_L3_LIFETIME_MAX = ("guard-hs-l3-lifetime-max", 172_800, 1, i32::MAX),
+
+    /// Lower bound on INTRODUCE2 cells per introduction circuit before rotating it.
+    HS_INTRO_MIN_INTRODUCE2 = ("hs_intro_min_introduce2", 16_384, 0, i32::MAX),
+    /// Upper bound on INTRODUCE2 cells per introduction circuit before rotating it.
+    HS_INTRO_MAX_INTRODUCE2 = ("hs_intro_max_introduce2", 32_768, 0, i32::MAX),
+    /// Lower bound on the lifetime of an introduction point, in seconds.
+    HS_INTRO_MIN_LIFETIME = ("hs_intro_min_lifetime", 18 * 60 * 60, 0, i32::MAX),
+    /// Upper bound on the lifetime of an introduction point, in seconds.
+    HS_INTRO_MAX_LIFETIME = ("hs_intro_max_lifetime", 24 * 60 * 60, 0, i32::MAX),
+    /// Number of extra introduction points an onion service may open based on demand.
+    HS_INTRO_NUM_EXTRA = ("hs_intro_num_extra", 2, 0, 128),
+    /// The length of a time period, in minutes.
+    HSDIR_INTERVAL = ("hsdir_interval", 1_440, 30, 14_400),
+    /// The number of positions on the hash ring where a descriptor is stored.
+    HSDIR_N_REPLICAS = ("hsdir_n_replicas", 2, 1, 16),
+    /// The number of HSDirs at each hash ring position to fetch descriptors from.
+    HSDIR_SPREAD_FETCH = ("hsdir_spread_fetch", 3, 1, 128),
+    /// The number of HSDirs at each hash ring position to upload descriptors to.
+    HSDIR_SPREAD_STORE = ("hsdir_spread_store", 4, 1, 128),
+    /// Largest allowable v3 onion service descriptor, in bytes.
+    HSV3_MAX_DESCRIPTOR_SIZE = ("HSV3MaxDescriptorSize", 50_000, 1, i32::MAX),
+    /// Largest number of rendezvous failures an onion service allows for a request.
+    HS_SERVICE_MAX_RDV_FAILURES = ("hs_service_max_rdv_failures", 2, 1, 10),
+    /// Whether introduction points use INTRODUCE1 rate limiting by default.
+    HIDDEN_SERVICE_ENABLE_INTRO_DOS_DEFENSE = ("HiddenServiceEnableIntroDoSDefense", 0, 0, 1),
+    /// Default burst for INTRODUCE1 rate limiting.
+    HIDDEN_SERVICE_ENABLE_INTRO_DOS_BURST_PER_SEC = ("HiddenServiceEnableIntroDoSBurstPerSec", 200, 0, i32::MAX),
+    /// Default rate for INTRODUCE1 rate limiting, in messages per second.
+    HIDDEN_SERVICE_ENABLE_INTRO_DOS_RATE_PER_SEC = ("HiddenServiceEnableIntroDoSRatePerSec", 25, 0, i32::MAX),
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn names_unique() {
+        let names: HashSet<_> = ALL_PARAMS.iter().map(|spec| spec.name()).collect();
+        assert_eq!(names.len(), ALL_PARAMS.len());
+    }
+
+    #[test]
+    fn clamping() {
+        let params: NetParams<i32> = "circwindow=50 cbtnummodes=30 cbtquantile=75 frobnicate=7"
+            .parse()
+            .unwrap();
+        let params = ConsensusParams::new(&params);
+
+        assert_eq!(params.get(&CIRCWINDOW), 100);
+        assert_eq!(params.get(&CBT_NUM_MODES), 20);
+        assert_eq!(params.get(&CBT_QUANTILE), 75);
+        assert_eq!(params.get(&CBT_RECENT_COUNT), 20);
+        assert!(params.get_bool(&USE_OPTIMISTIC_DATA));
+
+        assert_eq!(
+            params.unrecognized().collect::<Vec<_>>(),
+            vec![("frobnicate", 7)]
+        );
+        assert_eq!(params.iter().count(), ALL_PARAMS.len());
+        assert_eq!(find_param("cbtnummodes"), Some(&CBT_NUM_MODES));
+        assert_eq!(find_param("frobnicate"), None);
+    }
+}
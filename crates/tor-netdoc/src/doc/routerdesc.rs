@@ -53,6 +53,12 @@ use tor_llcrypto::pk::rsa::RsaIdentity;
 
 use digest::Digest;
 
+#[cfg(feature = "build_docs")]
+mod build;
+
+#[cfg(feature = "build_docs")]
+pub use build::RouterDescBuilder;
+
 /// The digest of a RouterDesc document, as reported in a NS consensus.
 pub type RdDigest = [u8; 20];
 
@@ -453,6 +459,21 @@ impl RouterDesc {
         Ok(result)
     }
 
+    /// Check the signatures on several router descriptors at once.
+    ///
+    /// Return, for each of `descs` (in order), either the descriptor
+    /// (which still needs to be checked for timeliness),
+    /// or an error if any of its signatures was invalid.
+    ///
+    /// This is equivalent to calling `check_signature()` on each descriptor,
+    /// except that the Ed25519 signatures and certificates of all the
+    /// descriptors are verified in a single batch.
+    pub fn check_all_signatures(
+        descs: Vec<UncheckedRouterDesc>,
+    ) -> Vec<std::result::Result<timed::TimerangeBound<RouterDesc>, signature::Error>> {
+        signed::SignatureGated::check_all_signatures(descs)
+    }
+
     /// Helper: parse a router descriptor from `s`.
     ///
     /// This function does the same as parse(), but returns errors based on
@@ -992,6 +1013,37 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn check_all_signatures() -> Result<()> {
+        use tor_checkable::Timebound;
+        let descs = vec![RouterDesc::parse(TESTDATA)?, RouterDesc::parse(TESTDATA2)?];
+        let checked = RouterDesc::check_all_signatures(descs);
+        assert_eq!(checked.len(), 2);
+        let nicknames: Vec<_> = checked
+            .into_iter()
+            .map(|rd| {
+                rd.unwrap()
+                    .dangerously_assume_timely()
+                    .nickname
+                    .as_str()
+                    .to_owned()
+            })
+            .collect();
+        assert_eq!(nicknames[0], "Akka");
+
+        // A descriptor with a bad signature doesn't spoil the others.
+        let corrupted = TESTDATA.replacen("uptime 1036923", "uptime 1036924", 1);
+        let descs = vec![
+            RouterDesc::parse(&corrupted)?,
+            RouterDesc::parse(TESTDATA2)?,
+        ];
+        let checked = RouterDesc::check_all_signatures(descs);
+        assert!(checked[0].is_err());
+        assert!(checked[1].is_ok());
+
+        Ok(())
+    }
+
     #[test]
     fn parse_no_tap_key() -> Result<()> {
         use tor_checkable::{SelfSigned, Timebound};
@@ -0,0 +1,613 @@
+//! Facilities to construct and sign router descriptors.
+
+use super::{RouterDesc, RouterKwd, ROUTER_EXPIRY_SECONDS};
+
+use crate::build::{NetdocBuilder, NetdocEncoder};
+use crate::parse::keyword::Keyword as _;
+use crate::types::family::RelayFamily;
+use crate::types::misc::{Iso8601TimeSp, Nickname};
+use crate::types::policy::{AddrPortPattern, PortPolicy, RuleKind};
+use crate::{BuildError as Error, BuildResult as Result, Error as ParseError};
+use tor_bytes::EncodeError;
+use tor_cert::{CertType, CertifiedKey, Ed25519Cert};
+use tor_error::{bad_api_usage, internal, into_bad_api_usage};
+use tor_llcrypto::d;
+use tor_llcrypto::pk::{curve25519, ed25519, keymanip, rsa};
+use tor_protover::Protocols;
+
+use base64ct::{Base64Unpadded, Encoding};
+use digest::Digest;
+use itertools::Itertools as _;
+use rand::{CryptoRng, RngCore};
+use std::net::{Ipv4Addr, SocketAddrV6};
+use std::time::{Duration, SystemTime};
+
+/// A builder object used to construct and sign a router descriptor.
+///
+/// Create one of these with the [`RouterDesc::builder`] method,
+/// and encode it with [`NetdocBuilder::build_sign`].
+///
+/// The builder holds references to the relay's secret keys,
+/// which are used to sign the identity certificate, the ntor
+/// cross-certificate, and the descriptor itself.
+///
+/// The deprecated TAP `onion-key` (and its cross-certificate) is never emitted.
+///
+/// This facility is only enabled when the crate is built with
+/// the `build_docs` feature.
+#[cfg_attr(docsrs, doc(cfg(feature = "build_docs")))]
+#[derive(Clone)]
+pub struct RouterDescBuilder<'a> {
+    /// The relay's (legacy) RSA identity key.
+    rsa_identity: Option<&'a rsa::PrivateKey>,
+    /// The relay's ed25519 identity key.
+    ed_identity: Option<&'a ed25519::Keypair>,
+    /// The relay's ed25519 signing key, certified by `ed_identity`.
+    ed_signing: Option<&'a ed25519::Keypair>,
+    /// The relay's ntor onion key.
+    ntor_onion_key: Option<&'a curve25519::StaticSecret>,
+    /// See [`RouterDesc::nickname`](super::RouterDesc)
+    nickname: Option<Nickname>,
+    /// IPv4 address for the ORPort.
+    ipv4addr: Option<Ipv4Addr>,
+    /// IPv4 ORPort.
+    orport: u16,
+    /// IPv6 ORPort address, if any.
+    ipv6addr: Option<SocketAddrV6>,
+    /// Directory port, or 0 for none.
+    dirport: u16,
+    /// Publication time.
+    published: Option<SystemTime>,
+    /// Expiration time of the identity and ntor certificates.
+    ///
+    /// If `None`, we use the publication time plus the lifetime of a
+    /// router descriptor.
+    cert_expiry: Option<SystemTime>,
+    /// Average, burst, and observed bandwidth, in bytes per second.
+    bandwidth: (u32, u32, u32),
+    /// Subprotocol versions supported by this relay.
+    proto: Option<Protocols>,
+    /// Software and version that this relay is running.
+    platform: Option<String>,
+    /// Declared family members.
+    family: RelayFamily,
+    /// Rules in the IPv4 exit policy, in order.
+    ///
+    /// If empty, the policy is `reject *:*`.
+    ipv4_policy: Vec<(RuleKind, AddrPortPattern)>,
+    /// IPv6 exit policy summary.
+    ipv6_policy: PortPolicy,
+}
+
+impl<'a> RouterDescBuilder<'a> {
+    /// Create a new RouterDescBuilder.
+    pub(crate) fn new() -> Self {
+        RouterDescBuilder {
+            rsa_identity: None,
+            ed_identity: None,
+            ed_signing: None,
+            ntor_onion_key: None,
+            nickname: None,
+            ipv4addr: None,
+            orport: 0,
+            ipv6addr: None,
+            dirport: 0,
+            published: None,
+            cert_expiry: None,
+            bandwidth: (0, 0, 0),
+            proto: None,
+            platform: None,
+            family: RelayFamily::new(),
+            ipv4_policy: Vec::new(),
+            ipv6_policy: PortPolicy::new_reject_all(),
+        }
+    }
+
+    /// Set the RSA identity key, used for the `signing-key` item and the
+    /// `router-signature`.
+    ///
+    /// This key is required, and must be a 1024-bit key with exponent 65537.
+    pub fn rsa_identity(&mut self, key: &'a rsa::PrivateKey) -> &mut Self {
+        self.rsa_identity = Some(key);
+        self
+    }
+
+    /// Set the ed25519 identity key.
+    ///
+    /// This key is required.
+    pub fn ed_identity(&mut self, key: &'a ed25519::Keypair) -> &mut Self {
+        self.ed_identity = Some(key);
+        self
+    }
+
+    /// Set the ed25519 signing key, used for the `router-sig-ed25519`.
+    ///
+    /// This key is required.  It will be certified by the identity key.
+    pub fn ed_signing_key(&mut self, key: &'a ed25519::Keypair) -> &mut Self {
+        self.ed_signing = Some(key);
+        self
+    }
+
+    /// Set the ntor onion key.
+    ///
+    /// This key is required.  It is also used to sign the
+    /// `ntor-onion-key-crosscert`.
+    pub fn ntor_key(&mut self, key: &'a curve25519::StaticSecret) -> &mut Self {
+        self.ntor_onion_key = Some(key);
+        self
+    }
+
+    /// Set the nickname of this relay.
+    ///
+    /// A nickname is required.
+    pub fn nickname(&mut self, nickname: &str) -> Result<&mut Self> {
+        self.nickname = Some(nickname.parse()?);
+        Ok(self)
+    }
+
+    /// Set the IPv4 address and ORPort of this relay.
+    ///
+    /// This address is required.
+    pub fn ipv4(&mut self, addr: Ipv4Addr, orport: u16) -> &mut Self {
+        self.ipv4addr = Some(addr);
+        self.orport = orport;
+        self
+    }
+
+    /// Set an IPv6 address and ORPort for this relay.
+    ///
+    /// By default, the relay has no IPv6 address.
+    pub fn ipv6(&mut self, addr: SocketAddrV6) -> &mut Self {
+        self.ipv6addr = Some(addr);
+        self
+    }
+
+    /// Set the directory port of this relay.
+    ///
+    /// By default, this is 0, meaning that there is no directory port.
+    pub fn dirport(&mut self, dirport: u16) -> &mut Self {
+        self.dirport = dirport;
+        self
+    }
+
+    /// Set the publication time of this descriptor.
+    ///
+    /// This time is required.
+    pub fn published(&mut self, published: SystemTime) -> &mut Self {
+        self.published = Some(published);
+        self
+    }
+
+    /// Set the expiration time of the certificates in this descriptor.
+    ///
+    /// By default, the certificates expire when the descriptor does.
+    pub fn cert_expiry(&mut self, expiry: SystemTime) -> &mut Self {
+        self.cert_expiry = Some(expiry);
+        self
+    }
+
+    /// Set the average, burst, and observed bandwidth of this relay,
+    /// in bytes per second.
+    ///
+    /// By default, these are all 0.
+    pub fn bandwidth(&mut self, avg: u32, burst: u32, observed: u32) -> &mut Self {
+        self.bandwidth = (avg, burst, observed);
+        self
+    }
+
+    /// Set the subprotocol versions supported by this relay.
+    ///
+    /// This list is required.
+    pub fn proto(&mut self, proto: Protocols) -> &mut Self {
+        self.proto = Some(proto);
+        self
+    }
+
+    /// Set the software and version that this relay is running.
+    ///
+    /// By default, no platform is given.
+    pub fn platform(&mut self, platform: impl Into<String>) -> &mut Self {
+        self.platform = Some(platform.into());
+        self
+    }
+
+    /// Set the family of this relay.
+    ///
+    /// By default, this family is empty.
+    pub fn family(&mut self, family: RelayFamily) -> &mut Self {
+        self.family = family;
+        self
+    }
+
+    /// Add a rule to the end of the IPv4 exit policy of this relay.
+    ///
+    /// By default, this policy is `reject *:*`.
+    /// If any rules are added, the policy should end with a rule
+    /// that matches every address.
+    pub fn ipv4_policy_rule(&mut self, kind: RuleKind, pattern: AddrPortPattern) -> &mut Self {
+        self.ipv4_policy.push((kind, pattern));
+        self
+    }
+
+    /// Set the ipv6 exit policy of this relay.
+    ///
+    /// By default, this policy is `reject 1-65535`.
+    pub fn ipv6_policy(&mut self, policy: PortPolicy) -> &mut Self {
+        self.ipv6_policy = policy;
+        self
+    }
+
+    /// Set the subprotocol versions of this relay based on parsing a string.
+    pub fn parse_proto(&mut self, proto: &str) -> Result<&mut Self> {
+        let proto = proto
+            .parse()
+            .map_err(|_| Error::CannotBuild("Unparseable protocol list"))?;
+        Ok(self.proto(proto))
+    }
+
+    /// Set the family of this relay based on parsing a string.
+    pub fn parse_family(&mut self, family: &str) -> Result<&mut Self> {
+        Ok(self.family(family.parse()?))
+    }
+
+    /// Set the ipv6 exit policy of this relay based on parsing
+    /// a string.
+    ///
+    /// By default, this policy is `reject 1-65535`.
+    pub fn parse_ipv6_policy(&mut self, policy: &str) -> Result<&mut Self> {
+        Ok(self.ipv6_policy(policy.parse().map_err(ParseError::from)?))
+    }
+}
+
+impl<'a> NetdocBuilder for RouterDescBuilder<'a> {
+    fn build_sign<R: RngCore + CryptoRng>(
+        self,
+        _: &mut R,
+    ) -> std::result::Result<String, EncodeError> {
+        use ed25519::Signer as _;
+        use RouterKwd::*;
+
+        /// Prefix for the text signed by `router-sig-ed25519`.
+        const ED_SIG_PREFIX: &[u8] = b"Tor router descriptor signature v1";
+
+        let rsa_identity = self
+            .rsa_identity
+            .ok_or_else(|| bad_api_usage!("Missing rsa_identity"))?;
+        let ed_identity = self
+            .ed_identity
+            .ok_or_else(|| bad_api_usage!("Missing ed_identity"))?;
+        let ed_signing = self
+            .ed_signing
+            .ok_or_else(|| bad_api_usage!("Missing ed_signing_key"))?;
+        let ntor_onion_key = self
+            .ntor_onion_key
+            .ok_or_else(|| bad_api_usage!("Missing ntor_key"))?;
+        let nickname = self
+            .nickname
+            .ok_or_else(|| bad_api_usage!("Missing nickname"))?;
+        let ipv4addr = self
+            .ipv4addr
+            .ok_or_else(|| bad_api_usage!("Missing ipv4 address"))?;
+        let published = self
+            .published
+            .ok_or_else(|| bad_api_usage!("Missing published time"))?;
+        let proto = self.proto.ok_or_else(|| bad_api_usage!("Missing proto"))?;
+        let cert_expiry = self
+            .cert_expiry
+            .unwrap_or_else(|| published + Duration::from_secs(ROUTER_EXPIRY_SECONDS));
+
+        let ed_id = ed25519::Ed25519Identity::from(ed_identity.verifying_key());
+        let rsa_public = rsa_identity.to_public_key();
+        let ntor_public = curve25519::PublicKey::from(ntor_onion_key);
+
+        let identity_cert = Ed25519Cert::constructor()
+            .cert_type(CertType::IDENTITY_V_SIGNING)
+            .expiration(cert_expiry)
+            .signing_key(ed_id)
+            .cert_key(CertifiedKey::Ed25519(ed_signing.verifying_key().into()))
+            .encode_and_sign(ed_identity)
+            .map_err(into_bad_api_usage!("failed to sign the identity cert"))?;
+
+        let (ntor_as_ed, ntor_signbit) =
+            keymanip::convert_curve25519_to_ed25519_private(ntor_onion_key)
+                .ok_or_else(|| internal!("unable to convert the ntor key to ed25519"))?;
+        let ntor_crosscert = Ed25519Cert::constructor()
+            .cert_type(CertType::NTOR_CC_IDENTITY)
+            .expiration(cert_expiry)
+            .cert_key(CertifiedKey::Ed25519(ed_id))
+            .encode_and_sign(&ntor_as_ed)
+            .map_err(into_bad_api_usage!("failed to sign the ntor crosscert"))?;
+
+        let mut encoder = NetdocEncoder::new();
+        let beginning = encoder.cursor();
+        encoder
+            .item(ROUTER)
+            .arg(&nickname.as_str())
+            .arg(&ipv4addr.to_string())
+            .arg(&self.orport)
+            .arg(&0_u16) // SOCKSPort is obsolete.
+            .arg(&self.dirport);
+        encoder
+            .item(IDENTITY_ED25519)
+            .object("ED25519 CERT", identity_cert.as_ref());
+        encoder
+            .item(MASTER_KEY_ED25519)
+            .arg(&Base64Unpadded::encode_string(ed_id.as_bytes()));
+        if let Some(platform) = &self.platform {
+            encoder.item(PLATFORM).args_raw_string(platform);
+        }
+        encoder.item(PROTO).args_raw_string(&proto);
+        encoder.item(PUBLISHED).arg(&Iso8601TimeSp::from(published));
+        encoder.item(FINGERPRINT).args_raw_string(
+            &hex::encode_upper(rsa_public.to_rsa_identity().as_bytes())
+                .as_bytes()
+                .chunks(4)
+                .map(|c| std::str::from_utf8(c).expect("hex was not ASCII"))
+                .join(" "),
+        );
+        encoder
+            .item(BANDWIDTH)
+            .arg(&self.bandwidth.0)
+            .arg(&self.bandwidth.1)
+            .arg(&self.bandwidth.2);
+        encoder
+            .item(SIGNING_KEY)
+            .object("RSA PUBLIC KEY", rsa_public.to_der());
+        encoder
+            .item(NTOR_ONION_KEY)
+            .arg(&Base64Unpadded::encode_string(ntor_public.as_bytes()));
+        encoder
+            .item(NTOR_ONION_KEY_CROSSCERT)
+            .arg(&ntor_signbit)
+            .object("ED25519 CERT", ntor_crosscert.as_ref());
+        if !self.family.is_empty() {
+            encoder
+                .item(FAMILY)
+                .args_raw_string(&self.family.members().join(" "));
+        }
+        if let Some(addr) = &self.ipv6addr {
+            encoder.item(OR_ADDRESS).arg(&addr.to_string());
+        }
+        // The POLICY keyword stands for both "accept" and "reject",
+        // so we have to write these lines by hand.
+        if self.ipv4_policy.is_empty() {
+            encoder.push_raw_string(&"reject *:*\n");
+        }
+        for (kind, pattern) in &self.ipv4_policy {
+            let kind = match kind {
+                RuleKind::Accept => "accept",
+                RuleKind::Reject => "reject",
+            };
+            encoder.push_raw_string(&format_args!("{kind} {pattern}\n"));
+        }
+        if self.ipv6_policy.allows_some_port() {
+            encoder.item(IPV6_POLICY).args_raw_string(&self.ipv6_policy);
+        }
+
+        // "router-sig-ed25519" covers everything up to and including the
+        // space after its own keyword.
+        let ed_sig_kwd = format!("{} ", ROUTER_SIG_ED25519.to_str());
+        let mut d = d::Sha256::new();
+        d.update(ED_SIG_PREFIX);
+        d.update(encoder.slice(beginning, encoder.cursor())?);
+        d.update(&ed_sig_kwd);
+        let ed_signature = ed_signing.sign(&d.finalize());
+        encoder
+            .item(ROUTER_SIG_ED25519)
+            .arg(&Base64Unpadded::encode_string(&ed_signature.to_bytes()));
+
+        // "router-signature" covers everything up to and including the
+        // newline after its own keyword.
+        let rsa_sig_kwd = format!("{}\n", ROUTER_SIGNATURE.to_str());
+        let mut d = d::Sha1::new();
+        d.update(encoder.slice(beginning, encoder.cursor())?);
+        d.update(&rsa_sig_kwd);
+        let rsa_signature = rsa_identity
+            .sign(&d.finalize())
+            .map_err(into_bad_api_usage!(
+                "failed to sign with the RSA identity key"
+            ))?;
+        encoder
+            .item(ROUTER_SIGNATURE)
+            .object("SIGNATURE", rsa_signature);
+
+        encoder.finish().map_err(|e| e.into())
+    }
+}
+
+impl RouterDesc {
+    /// Create a new RouterDescBuilder that can be used to construct
+    /// and sign router descriptors.
+    ///
+    /// This function is only available when the crate is built with the
+    /// `build_docs` feature.
+    #[cfg_attr(docsrs, doc(cfg(feature = "build_docs")))]
+    pub fn builder<'a>() -> RouterDescBuilder<'a> {
+        RouterDescBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use std::net::{IpAddr, SocketAddr};
+    use tor_basic_utils::test_rng::testing_rng;
+    use tor_checkable::{SelfSigned, Timebound};
+
+    /// A 1024-bit RSA key, from the tor-llcrypto test vectors.
+    const RSA_KEY: &str = "
+MIICXQIBAAKBgQDVJ7bGPW6B05wyipTOFX3M3AROsa2MIQycniJIe0z63m1AQb0Q
+Rpplfj2CvADPYqw7apkkflc7VMEMR/XchJsKzNoDHspvbl3IVnf3bexJ/yTS/LK1
+iH+xJaogR0QRm7ZBf0XuaW+N/Bwvwhsrro6eN6GdwlGKLCTn2P1/rA9GlQIDAQAB
+AoGADZDgfg9s2BBqsYDGZbNSdVZPY97FB9UWo2UhE3HdfV3ooB1O9hk4PFtjeM2U
+U56ZDZMEOiFcVedX/fsad7Vs1I5VUxwZqXdhRgqJllD5RPifSSpt3lnYNE0O/WN5
+DJIUR2yxJ2cXj2D2MUh56T5RnqC17lXWEOmUUlM7u2/po8ECQQD6HVmhcclRWWP8
+/IuSu8rD/2kjjuoAhg1ptfSyzSIp0ipS7xYcru13dDkFG8WllosEt0eu+FIL+Vnz
+VtxyBErRAkEA2iu5yiRSVRHOud2SHtn1wUx2M8pSdB3XtCQjkpiwhsSij20eYFVv
+clS5A6E0D4txRK4flnwYqVTdxlh271fohQJBAMKZ+XX6oWeRBJH/MN1/DZmH7RcE
+iB7WLjN0pipEHvOpGNMkQPEaTZsmq4LFA/f9dLa7n6OMg9HbNdh2WdjAbDECQFqT
+9tG23LvW5dYC6KyIX2C+ZwC/ihYNYcW3j1FItVluf/M+IXNrZRa5mAqqvduKUB9s
+j07B/Ncold7IUbCy9aUCQQDew08R5vjl8n+I44u/KIZ4RR1ntggrnDfKnCD8nNR4
+LnZEGsos1BCJkS31lYl7Jae1QVooa6522Rz8ORo+GfbZ";
+
+    /// Decode [`RSA_KEY`].
+    fn rsa_key() -> rsa::PrivateKey {
+        let der = base64ct::Base64::decode_vec(&RSA_KEY.replace('\n', "")).unwrap();
+        rsa::PrivateKey::from_der(&der).unwrap()
+    }
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let mut rng = testing_rng();
+        let rsa_id = rsa_key();
+        let ed_id = ed25519::Keypair::generate(&mut rng);
+        let ed_signing = ed25519::Keypair::generate(&mut rng);
+        let ntor = curve25519::StaticSecret::random_from_rng(&mut rng);
+        let published = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let mut builder = RouterDesc::builder();
+        builder
+            .rsa_identity(&rsa_id)
+            .ed_identity(&ed_id)
+            .ed_signing_key(&ed_signing)
+            .ntor_key(&ntor)
+            .nickname("Fred")?
+            .ipv4("192.0.2.7".parse().unwrap(), 9001)
+            .ipv6("[2001:db8::7]:9002".parse().unwrap())
+            .published(published)
+            .bandwidth(1000, 2000, 500)
+            .parse_proto("Link=4-5 Relay=2-4")?
+            .platform("Tor 0.4.8.9 on Linux")
+            .parse_family("$aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa")?
+            .ipv4_policy_rule(RuleKind::Reject, "*:25".parse().unwrap())
+            .ipv4_policy_rule(RuleKind::Accept, "*:*".parse().unwrap())
+            .parse_ipv6_policy("accept 80,443")?;
+        let text = builder.build_sign(&mut rng).unwrap();
+
+        let rd = RouterDesc::parse(&text)
+            .unwrap()
+            .check_signature()
+            .unwrap()
+            .check_valid_at(&(published + Duration::from_secs(3600)))
+            .unwrap();
+
+        assert_eq!(rd.nickname.as_str(), "Fred");
+        assert_eq!(rd.rsa_identity(), &rsa_id.to_public_key().to_rsa_identity());
+        assert_eq!(
+            rd.ed_identity(),
+            &ed25519::Ed25519Identity::from(ed_id.verifying_key())
+        );
+        assert_eq!(rd.ntor_onion_key(), &curve25519::PublicKey::from(&ntor));
+        assert_eq!(rd.published(), published);
+        assert_eq!(rd.protocols().to_string(), "Link=4-5 Relay=2-4");
+        assert_eq!(
+            rd.or_ports().collect::<Vec<_>>(),
+            vec![
+                "192.0.2.7:9001".parse::<SocketAddr>().unwrap(),
+                "[2001:db8::7]:9002".parse().unwrap(),
+            ]
+        );
+        // The relay's own identity is added to a nonempty family.
+        assert_eq!(rd.family.members().count(), 2);
+        assert!(rd.family.contains(&[0xaa; 20].into()));
+        let smtp_addr: IpAddr = "198.51.100.1".parse().unwrap();
+        assert_eq!(
+            rd.ipv4_policy.allows(&smtp_addr, 25),
+            Some(RuleKind::Reject)
+        );
+        assert_eq!(
+            rd.ipv4_policy.allows(&smtp_addr, 80),
+            Some(RuleKind::Accept)
+        );
+        assert!(rd.ipv6_policy.allows_port(443));
+        assert!(!rd.ipv6_policy.allows_port(25));
+
+        Ok(())
+    }
+
+    #[test]
+    fn minimal() -> Result<()> {
+        let mut rng = testing_rng();
+        let rsa_id = rsa_key();
+        let ed_id = ed25519::Keypair::generate(&mut rng);
+        let ed_signing = ed25519::Keypair::generate(&mut rng);
+        let ntor = curve25519::StaticSecret::random_from_rng(&mut rng);
+        let published = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let mut builder = RouterDesc::builder();
+        builder
+            .rsa_identity(&rsa_id)
+            .ed_identity(&ed_id)
+            .ed_signing_key(&ed_signing)
+            .ntor_key(&ntor)
+            .nickname("Fred")?
+            .ipv4("192.0.2.7".parse().unwrap(), 9001)
+            .published(published)
+            .parse_proto("Link=4-5")?;
+
+        // Every required field is needed.
+        {
+            let mut b = builder.clone();
+            b.ed_signing = None;
+            assert!(b.build_sign(&mut rng).is_err());
+        }
+        {
+            let mut b = builder.clone();
+            b.published = None;
+            assert!(b.build_sign(&mut rng).is_err());
+        }
+
+        let text = builder.build_sign(&mut rng).unwrap();
+        assert!(text.contains("\nreject *:*\n"));
+        let rd = RouterDesc::parse(&text)
+            .unwrap()
+            .check_signature()
+            .unwrap()
+            .dangerously_assume_timely();
+        assert_eq!(rd.or_ports().count(), 1);
+        assert_eq!(rd.family.members().count(), 0);
+        assert!(!rd.ipv6_policy.allows_some_port());
+
+        Ok(())
+    }
+
+    #[test]
+    fn bad_signature() -> Result<()> {
+        let mut rng = testing_rng();
+        let rsa_id = rsa_key();
+        let ed_id = ed25519::Keypair::generate(&mut rng);
+        let ed_signing = ed25519::Keypair::generate(&mut rng);
+        let ntor = curve25519::StaticSecret::random_from_rng(&mut rng);
+
+        let mut builder = RouterDesc::builder();
+        builder
+            .rsa_identity(&rsa_id)
+            .ed_identity(&ed_id)
+            .ed_signing_key(&ed_signing)
+            .ntor_key(&ntor)
+            .nickname("Fred")?
+            .ipv4("192.0.2.7".parse().unwrap(), 9001)
+            .published(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+            .parse_proto("Link=4-5")?;
+        let text = builder.build_sign(&mut rng).unwrap();
+
+        // Tamper with a signed field.
+        let text = text.replace("Link=4-5", "Link=4-6");
+        assert!(RouterDesc::parse(&text).unwrap().check_signature().is_err());
+
+        Ok(())
+    }
+}
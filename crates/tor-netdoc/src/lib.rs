@@ -41,7 +41,7 @@
 #![allow(clippy::needless_raw_string_hashes)] // complained-about code is fine, often best
 //! <!-- @@ end lint list maintained by maint/add_warning @@ -->
 
-#[cfg(feature = "hs-service")]
+#[cfg(any(feature = "hs-service", feature = "build_docs"))]
 pub(crate) mod build;
 #[macro_use]
 pub(crate) mod parse;
@@ -57,8 +57,8 @@ pub use util::batching_split_before;
 
 pub use err::{BuildError, Error, NetdocErrorKind, Pos};
 
-#[cfg(feature = "hs-service")]
-#[cfg_attr(docsrs, doc(cfg(feature = "hs-service")))]
+#[cfg(any(feature = "hs-service", feature = "build_docs"))]
+#[cfg_attr(docsrs, doc(cfg(any(feature = "hs-service", feature = "build_docs"))))]
 pub use build::NetdocBuilder;
 
 /// Alias for the Result type returned by most objects in this module.
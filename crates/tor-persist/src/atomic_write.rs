@@ -0,0 +1,158 @@
+//! Crash-safe replacement of files on disk
+//!
+//! [`write_and_replace`] stores some data in a file, by writing it to a
+//! temporary file and then renaming that over the target.
+//! Readers see either the old contents or the new ones, never a mixture;
+//! and if we fail (or crash) part way through, the old contents remain.
+//!
+//! This is the machinery that [`StateMgr`](crate::StateMgr) implementations in
+//! this crate use to store their data.  It is exposed for the benefit of code
+//! which keeps its own files (for example, in an instance's `raw_subdir`).
+//! The work is done by [`CheckedDir::write_and_replace`] and
+//! [`CheckedDir::write_and_replace_synced`];
+//! this module adds this crate's error handling.
+//!
+//! The caller is responsible for any necessary locking:
+//! concurrent calls to [`write_and_replace`] for the same file may corrupt it.
+
+use std::path::Path;
+
+use fs_mistrust::CheckedDir;
+use tracing::trace;
+
+use crate::err::{Action, ErrorSource, Resource};
+use crate::Error;
+
+/// How hard [`write_and_replace`] should try to make sure that new data
+/// survives a crash
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Durability {
+    /// Don't wait for the data to reach the disk
+    ///
+    /// After a crash (of the operating system, or a power failure),
+    /// the file may contain either its old contents or its new contents
+    /// (and, on some filesystems, may even be empty).
+    /// This is fine for data that is cheap to recover.
+    #[default]
+    Unsynced,
+
+    /// Wait for the new data, and the rename, to reach the disk
+    ///
+    /// See [`CheckedDir::write_and_replace_synced`].
+    /// Once [`write_and_replace`] has returned, the new contents should survive a crash.
+    ///
+    /// This is much slower than [`Durability::Unsynced`].
+    Synced,
+}
+
+/// Store `contents` in the file at `rel_fname` within `dir`, replacing it atomically
+///
+/// We write `contents` to a temporary file in the same directory as the
+/// target (named after the target, with its extension replaced by `tmp`),
+/// and then rename the temporary file over the target.
+/// If anything fails, we remove the temporary file,
+/// and the target keeps its old contents (if it had any).
+///
+/// See [`CheckedDir::write_and_replace`] for the details,
+/// including the behaviour on Windows.
+pub fn write_and_replace(
+    dir: &CheckedDir,
+    rel_fname: impl AsRef<Path>,
+    contents: impl AsRef<[u8]>,
+    durability: Durability,
+) -> Result<(), Error> {
+    let rel_fname = rel_fname.as_ref();
+    write_and_replace_inner(dir, rel_fname, contents.as_ref(), durability).map_err(|source| {
+        Error::new(
+            source,
+            Action::Storing,
+            Resource::File {
+                container: dir.as_path().to_owned(),
+                file: rel_fname.to_owned(),
+            },
+        )
+    })
+}
+
+/// Implementation of [`write_and_replace`], returning an [`ErrorSource`]
+///
+/// (Used within this crate, by callers that make their own [`Error`]s.)
+pub(crate) fn write_and_replace_inner(
+    dir: &CheckedDir,
+    rel_fname: &Path,
+    contents: &[u8],
+    durability: Durability,
+) -> Result<(), ErrorSource> {
+    trace!("replacing {:?}/{:?}", dir.as_path(), rel_fname);
+    match durability {
+        Durability::Unsynced => dir.write_and_replace(rel_fname, contents)?,
+        Durability::Synced => dir.write_and_replace_synced(rel_fname, contents)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+    use fs_mistrust::Mistrust;
+    use test_temp_dir::test_temp_dir;
+
+    #[test]
+    fn replace() {
+        test_temp_dir!().used_by(|dir| {
+            let dir = Mistrust::new_dangerously_trust_everyone()
+                .verifier()
+                .secure_dir(dir)
+                .unwrap();
+
+            for durability in [Durability::Unsynced, Durability::Synced] {
+                write_and_replace(&dir, "data.json", "old", durability).unwrap();
+                assert_eq!(dir.read_to_string("data.json").unwrap(), "old");
+                write_and_replace(&dir, "data.json", b"new", durability).unwrap();
+                assert_eq!(dir.read_to_string("data.json").unwrap(), "new");
+                assert!(!dir.as_path().join("data.tmp").exists());
+            }
+
+            // Files in subdirectories work too.
+            dir.make_directory("sub").unwrap();
+            write_and_replace(&dir, "sub/data", "sub", Durability::Synced).unwrap();
+            assert_eq!(dir.read_to_string("sub/data").unwrap(), "sub");
+
+            // We can't escape from the directory.
+            let e = write_and_replace(&dir, "../escape", "x", Durability::Unsynced).unwrap_err();
+            assert!(matches!(e.source(), ErrorSource::Inaccessible(_)));
+        });
+    }
+
+    #[test]
+    fn failure_cleans_up() {
+        test_temp_dir!().used_by(|dir| {
+            let dir = Mistrust::new_dangerously_trust_everyone()
+                .verifier()
+                .secure_dir(dir)
+                .unwrap();
+
+            // The target is a non-empty directory, so we can't rename over it.
+            dir.make_directory("target").unwrap();
+            write_and_replace(&dir, "target/inner", "x", Durability::Unsynced).unwrap();
+            write_and_replace(&dir, "target", "contents", Durability::Unsynced).unwrap_err();
+            assert!(!dir.as_path().join("target.tmp").exists());
+            assert_eq!(dir.read_to_string("target/inner").unwrap(), "x");
+        });
+    }
+}
@@ -0,0 +1,121 @@
+//! Reports from storage health checks
+//!
+//! See [`FsStateMgr::check`](crate::FsStateMgr::check),
+//! and (with the `state-dir` feature) `StateDirectory::check_instances`.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+/// How serious is a problem found by a health check?
+#[derive(
+    Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, derive_more::Display,
+)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum CheckSeverity {
+    /// Not a problem, but worth mentioning
+    ///
+    /// For example, an instance that is in use, and therefore can't be repaired.
+    #[display(fmt = "info")]
+    Info,
+    /// Something unexpected, that doesn't stop the state from being used
+    ///
+    /// For example, a stale temporary file or lockfile.
+    #[display(fmt = "warning")]
+    Warning,
+    /// The state can't be used as it is
+    ///
+    /// For example, a storage file that can't be parsed.
+    #[display(fmt = "error")]
+    Error,
+}
+
+/// A single problem found by a health check
+#[derive(Debug, Clone, Serialize)]
+#[non_exhaustive]
+pub struct CheckProblem {
+    /// How serious the problem is
+    severity: CheckSeverity,
+    /// The file or directory with the problem
+    path: PathBuf,
+    /// A human-readable description of the problem
+    description: String,
+    /// Whether we fixed the problem
+    repaired: bool,
+}
+
+impl CheckProblem {
+    /// Return how serious the problem is
+    pub fn severity(&self) -> CheckSeverity {
+        self.severity
+    }
+
+    /// Return the file or directory with the problem
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return a human-readable description of the problem
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Return whether we fixed the problem
+    pub fn repaired(&self) -> bool {
+        self.repaired
+    }
+}
+
+/// The results of a health check
+///
+/// Implements `Serialize`, so that it can be reported in machine-readable form.
+#[derive(Debug, Clone, Default, Serialize)]
+#[non_exhaustive]
+pub struct CheckReport {
+    /// Every problem that was found, in the order we found it
+    pub problems: Vec<CheckProblem>,
+}
+
+impl CheckReport {
+    /// Return the severity of the worst problem that remains unrepaired, if any
+    pub fn worst(&self) -> Option<CheckSeverity> {
+        self.problems
+            .iter()
+            .filter(|p| !p.repaired)
+            .map(|p| p.severity)
+            .max()
+    }
+
+    /// Record a problem
+    ///
+    /// This is for callers that check other kinds of storage,
+    /// and want to report the results alongside ours.
+    pub fn push(
+        &mut self,
+        severity: CheckSeverity,
+        path: PathBuf,
+        description: impl Into<String>,
+        repaired: bool,
+    ) {
+        self.problems.push(CheckProblem {
+            severity,
+            path,
+            description: description.into(),
+            repaired,
+        });
+    }
+
+    /// Add every problem in `other` to this report
+    pub fn merge(&mut self, other: CheckReport) {
+        self.problems.extend(other.problems);
+    }
+}
+
+/// Is `leaf` the name of a file that we write temporarily, during `store`?
+pub(crate) fn is_temp_file(leaf: &Path) -> bool {
+    matches!(
+        leaf.extension().and_then(|e| e.to_str()),
+        Some("tmp") | Some("new")
+    )
+}
@@ -76,6 +76,12 @@ pub(crate) enum Action {
     /// We were trying to enumerate state objects
     #[display(fmt = "enumerating instances")]
     Enumerating,
+    /// We were trying to write an instance's state to an archive
+    #[display(fmt = "exporting instance state")]
+    Exporting,
+    /// We were trying to restore an instance's state from an archive
+    #[display(fmt = "restoring instance state")]
+    Restoring,
 }
 
 /// An underlying error manipulating persistent state.
@@ -117,6 +123,10 @@ pub enum ErrorSource {
     #[error("State already lockedr")]
     AlreadyLocked,
 
+    /// An archive of an instance's state was malformed, or was for a different instance
+    #[error("Invalid state archive: {0}")]
+    BadArchive(String),
+
     /// Programming error
     #[error("Programming error")]
     Bug(#[from] Bug),
@@ -185,6 +195,7 @@ impl tor_error::HasKind for Error {
             E::Inaccessible(e) => e.state_error_kind(),
             E::NoLock          => K::BadApiUsage,
             E::AlreadyLocked   => K::LocalResourceAlreadyInUse,
+            E::BadArchive(..)  => K::PersistentStateCorrupted,
             E::Bug(e)          => e.kind(),
             E::Serde(..) if self.action == Action::Storing  => K::Internal,
             E::Serde(..) => K::PersistentStateCorrupted,
@@ -2,9 +2,10 @@
 
 mod clean;
 
+use crate::check::is_temp_file;
 use crate::err::{Action, ErrorSource, Resource};
 use crate::load_store;
-use crate::{Error, LockStatus, Result, StateMgr};
+use crate::{CheckReport, CheckSeverity, Error, LockStatus, Result, StateMgr};
 use fs_mistrust::anon_home::PathExt as _;
 use fs_mistrust::CheckedDir;
 use futures::FutureExt;
@@ -13,7 +14,7 @@ use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 use tor_async_utils::oneshot;
-use tor_error::warn_report;
+use tor_error::{warn_report, ErrorReport as _};
 use tracing::info;
 
 /// Implementation of StateMgr that stores state as JSON files on disk.
@@ -184,6 +185,32 @@ impl FsStateMgr {
         }
     }
 
+    /// Return a filename, relative to the top of this directory, to use for
+    /// the journal with `key`.
+    fn rel_journal_filename(&self, key: &str) -> PathBuf {
+        (sanitize_filename::sanitize(key) + ".journal").into()
+    }
+
+    /// Return a `Resource` object representing the journal file with a given key.
+    fn err_resource_journal(&self, key: &str) -> Resource {
+        Resource::File {
+            container: self.path().to_path_buf(),
+            file: PathBuf::from("state").join(self.rel_journal_filename(key)),
+        }
+    }
+
+    /// Return an error if we can't store.
+    fn check_can_store(&self) -> Result<()> {
+        if !self.can_store() {
+            return Err(Error::new(
+                ErrorSource::NoLock,
+                Action::Storing,
+                Resource::Manager,
+            ));
+        }
+        Ok(())
+    }
+
     /// Return a `Resource` object representing our lock file.
     fn err_resource_lock(&self) -> Resource {
         Resource::File {
@@ -192,6 +219,87 @@ impl FsStateMgr {
         }
     }
 
+    /// Check the health of the state files in this storage manager.
+    ///
+    /// This checks that every storage file can be parsed as JSON,
+    /// that every journal contains only complete, parseable entries,
+    /// and that there are no stale temporary files from an interrupted write.
+    ///
+    /// If `repair` is true, and we hold the lock
+    /// (see [`try_lock`](StateMgr::try_lock)),
+    /// we also delete stale temporary files.
+    ///
+    /// Problems with the state are returned in the [`CheckReport`];
+    /// an `Err` means we couldn't perform the check at all.
+    pub fn check(&self, repair: bool) -> Result<CheckReport> {
+        let mut report = CheckReport::default();
+        let statepath = &self.inner.statepath;
+        let dir_resource = || Resource::Directory {
+            dir: statepath.as_path().to_path_buf(),
+        };
+        let repair = repair && self.can_store();
+
+        let ents = statepath
+            .read_directory(".")
+            .map_err(|e| Error::new(e, Action::Enumerating, dir_resource()))?;
+        for ent in ents {
+            let ent = ent.map_err(|e| Error::new(e, Action::Enumerating, dir_resource()))?;
+            let leaf = PathBuf::from(ent.file_name());
+            let path = statepath.as_path().join(&leaf);
+            let extension = leaf.extension().and_then(|e| e.to_str());
+
+            let parsed = match extension {
+                Some("json") => statepath
+                    .read_to_string(&leaf)
+                    .map_err(|e| e.report().to_string())
+                    .and_then(|text| {
+                        serde_json::from_str::<crate::JsonValue>(&text)
+                            .map(|_| ())
+                            .map_err(|e| e.report().to_string())
+                    }),
+                Some("journal") => statepath
+                    .read_to_string(&leaf)
+                    .map_err(|e| e.report().to_string())
+                    .and_then(|text| {
+                        // An incomplete last line is discarded by load_journal.
+                        text.split_inclusive('\n')
+                            .filter(|line| line.ends_with('\n') && !line.trim().is_empty())
+                            .try_for_each(|line| {
+                                serde_json::from_str::<crate::JsonValue>(line)
+                                    .map(|_| ())
+                                    .map_err(|e| e.report().to_string())
+                            })
+                    }),
+                _ if leaf == Path::new("state.lock") => Ok(()),
+                _ if is_temp_file(&leaf) => {
+                    let repaired = repair && statepath.remove_file(&leaf).is_ok();
+                    report.push(
+                        CheckSeverity::Warning,
+                        path,
+                        "stale temporary file (from an interrupted write)",
+                        repaired,
+                    );
+                    continue;
+                }
+                _ => {
+                    // Obsolete files are removed by `clean`, in due course.
+                    report.push(CheckSeverity::Info, path, "unrecognised file", false);
+                    continue;
+                }
+            };
+            if let Err(e) = parsed {
+                report.push(
+                    CheckSeverity::Error,
+                    path,
+                    format!("cannot parse storage file: {e}"),
+                    false,
+                );
+            }
+        }
+
+        Ok(report)
+    }
+
     /// Return a handle which resolves when the file is unlocked
     pub fn wait_for_unlock(&self) -> impl futures::Future<Output = ()> + Send + Sync + 'static {
         self.inner.lock_dropped_rx.clone().map(|_| ())
@@ -249,16 +357,115 @@ impl StateMgr for FsStateMgr {
     where
         S: Serialize,
     {
-        if !self.can_store() {
-            return Err(Error::new(
-                ErrorSource::NoLock,
-                Action::Storing,
-                Resource::Manager,
-            ));
-        }
+        self.check_can_store()?;
 
         self.with_load_store_target(key, Action::Storing, |t| t.store(val))
     }
+
+    /// Load the journal for `key`.
+    ///
+    /// The journal is stored as one JSON document per line.
+    /// If the last line is incomplete (because we crashed while appending it),
+    /// we ignore it.
+    fn load_journal<D>(&self, key: &str) -> Result<Vec<D>>
+    where
+        D: DeserializeOwned,
+    {
+        let err = |e: ErrorSource| Error::new(e, Action::Loading, self.err_resource_journal(key));
+        let contents = match self
+            .inner
+            .statepath
+            .read_to_string(self.rel_journal_filename(key))
+        {
+            Ok(contents) => contents,
+            Err(fs_mistrust::Error::NotFound(_)) => return Ok(vec![]),
+            Err(e) => return Err(err(e.into())),
+        };
+
+        let mut lines = contents.split('\n').collect::<Vec<_>>();
+        // The last element is whatever follows the final newline: it should
+        // be empty, unless a write was interrupted.
+        if let Some(partial) = lines.pop() {
+            if !partial.is_empty() {
+                info!(
+                    "Ignoring incomplete entry at end of {}",
+                    self.err_resource_journal(key)
+                );
+            }
+        }
+        lines
+            .into_iter()
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_str(line).map_err(|e| err(e.into())))
+            .collect()
+    }
+
+    /// Append `entry` to the journal for `key`, and flush it to disk.
+    fn append_journal<S>(&self, key: &str, entry: &S) -> Result<()>
+    where
+        S: Serialize,
+    {
+        use std::io::{Read as _, Seek as _, SeekFrom, Write as _};
+
+        self.check_can_store()?;
+        let err = |e: ErrorSource| Error::new(e, Action::Storing, self.err_resource_journal(key));
+
+        let mut line = serde_json::to_string(entry).map_err(|e| err(e.into()))?;
+        line.push('\n');
+
+        let mut file = self
+            .inner
+            .statepath
+            .open(
+                self.rel_journal_filename(key),
+                std::fs::OpenOptions::new()
+                    .read(true)
+                    .append(true)
+                    .create(true),
+            )
+            .map_err(|e| err(e.into()))?;
+
+        // If a previous append was interrupted, discard the incomplete line,
+        // so that it can't be mistaken for (or corrupt) this entry.
+        let len = file.metadata().map_err(|e| err(e.into()))?.len();
+        if len > 0 {
+            let mut last = [0_u8];
+            file.seek(SeekFrom::Start(len - 1))
+                .and_then(|_| file.read_exact(&mut last))
+                .map_err(|e| err(e.into()))?;
+            if last[0] != b'\n' {
+                let mut contents = vec![];
+                file.seek(SeekFrom::Start(0))
+                    .and_then(|_| file.read_to_end(&mut contents))
+                    .map_err(|e| err(e.into()))?;
+                let keep = contents
+                    .iter()
+                    .rposition(|&b| b == b'\n')
+                    .map_or(0, |pos| pos + 1);
+                file.set_len(keep as u64).map_err(|e| err(e.into()))?;
+            }
+        }
+
+        file.write_all(line.as_bytes())
+            .and_then(|()| file.sync_data())
+            .map_err(|e| err(e.into()))
+    }
+
+    fn clear_journal(&self, key: &str) -> Result<()> {
+        self.check_can_store()?;
+        match self
+            .inner
+            .statepath
+            .remove_file(self.rel_journal_filename(key))
+        {
+            Ok(()) | Err(fs_mistrust::Error::NotFound(_)) => Ok(()),
+            Err(e) => Err(Error::new(
+                e,
+                Action::Deleting,
+                self.err_resource_journal(key),
+            )),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -347,6 +554,105 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn journal() -> Result<()> {
+        let dir = tempfile::TempDir::new().unwrap();
+        let statedir = dir.path().join("state");
+        let store = FsStateMgr::from_path(dir.path())?;
+
+        let nothing: Vec<u32> = store.load_journal("xyz")?;
+        assert!(nothing.is_empty());
+        assert!(matches!(
+            store.append_journal("xyz", &1_u32).unwrap_err().source(),
+            ErrorSource::NoLock
+        ));
+
+        assert_eq!(store.try_lock()?, LockStatus::NewlyAcquired);
+        store.append_journal("xyz", &1_u32)?;
+        store.append_journal("xyz", &2_u32)?;
+        assert_eq!(store.load_journal::<u32>("xyz")?, vec![1, 2]);
+
+        // Simulate an interrupted write.
+        let fname = statedir.join("xyz.journal");
+        let mut contents = std::fs::read_to_string(&fname).unwrap();
+        contents.push_str("12");
+        std::fs::write(&fname, contents).unwrap();
+        assert_eq!(store.load_journal::<u32>("xyz")?, vec![1, 2]);
+
+        store.append_journal("xyz", &3_u32)?;
+        assert_eq!(store.load_journal::<u32>("xyz")?, vec![1, 2, 3]);
+
+        // An entry in the middle that doesn't parse is an error.
+        std::fs::write(&fname, "1\n{\n3\n").unwrap();
+        let e = store.load_journal::<u32>("xyz").unwrap_err();
+        assert!(matches!(e.source(), ErrorSource::Serde(_)));
+
+        store.clear_journal("xyz")?;
+        store.clear_journal("xyz")?;
+        assert!(store.load_journal::<u32>("xyz")?.is_empty());
+        assert!(!fname.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn check() -> Result<()> {
+        let dir = tempfile::TempDir::new().unwrap();
+        let statedir = dir.path().join("state");
+        let store = FsStateMgr::from_path(dir.path())?;
+
+        assert_eq!(store.try_lock()?, LockStatus::NewlyAcquired);
+        store.store("good", &1_u32)?;
+        store.append_journal("log", &1_u32)?;
+        assert!(store.check(true)?.problems.is_empty());
+
+        std::fs::write(statedir.join("bad.json"), "{ not json").unwrap();
+        std::fs::write(statedir.join("badlog.journal"), "1\n{\n3\n").unwrap();
+        std::fs::write(statedir.join("good.tmp"), "").unwrap();
+        std::fs::write(statedir.join("default_guards.toml"), "").unwrap();
+        // An incomplete last entry in a journal is not a problem.
+        std::fs::write(statedir.join("log.journal"), "1\n2").unwrap();
+
+        let summarise = |report: &CheckReport| {
+            let mut got = report
+                .problems
+                .iter()
+                .map(|p| {
+                    let leaf = p.path().file_name().unwrap().to_str().unwrap().to_owned();
+                    (leaf, p.severity(), p.repaired())
+                })
+                .collect::<Vec<_>>();
+            got.sort();
+            got
+        };
+        let expect = |repaired| {
+            vec![
+                ("bad.json".to_owned(), CheckSeverity::Error, false),
+                ("badlog.journal".to_owned(), CheckSeverity::Error, false),
+                ("default_guards.toml".to_owned(), CheckSeverity::Info, false),
+                ("good.tmp".to_owned(), CheckSeverity::Warning, repaired),
+            ]
+        };
+
+        // Without the lock, we don't repair anything.
+        store.unlock()?;
+        let report = store.check(true)?;
+        assert_eq!(summarise(&report), expect(false));
+        assert_eq!(report.worst(), Some(CheckSeverity::Error));
+        assert!(statedir.join("good.tmp").exists());
+
+        assert_eq!(store.try_lock()?, LockStatus::NewlyAcquired);
+        let report = store.check(false)?;
+        assert_eq!(summarise(&report), expect(false));
+        assert!(statedir.join("good.tmp").exists());
+
+        let report = store.check(true)?;
+        assert_eq!(summarise(&report), expect(true));
+        assert!(!statedir.join("good.tmp").exists());
+
+        Ok(())
+    }
+
     #[cfg(target_family = "unix")]
     #[test]
     fn permissions() -> Result<()> {
@@ -0,0 +1,374 @@
+//! Storage for frequently-updated objects, as a snapshot plus a journal.
+//!
+//! Rewriting a large object every time a small part of it changes is
+//! wasteful, especially on flash storage.  Instead, a
+//! [`JournaledStorageHandle`] appends each change to a journal,
+//! and only rewrites the whole object ("compacts") once the journal has
+//! grown long enough.
+//!
+//! Versions of Arti that predate journals only read the snapshot,
+//! so callers should [`compact`](JournaledStorageHandle::compact)
+//! before exiting: otherwise, a downgrade would lose the journaled changes.
+
+use crate::{Result, StateMgr};
+use serde::{de::DeserializeOwned, Serialize};
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+
+/// An object that can be stored as a snapshot plus a journal of changes.
+pub trait Journaled: Serialize + DeserializeOwned + Default + Clone {
+    /// A single change to an object of this type.
+    type Delta: Serialize + DeserializeOwned;
+
+    /// Apply `delta` to this object.
+    ///
+    /// Replaying any suffix of the journal over an object that already
+    /// reflects the whole journal must leave that object unchanged.
+    /// (If we crash while compacting, we replay the whole journal over a
+    /// snapshot that already includes it.)
+    ///
+    /// Deltas that set parts of the object to new values, so that the last
+    /// writer of each part wins, have this property.  Deltas that _adjust_
+    /// values (for example, by incrementing a counter) do not.
+    fn apply(&mut self, delta: Self::Delta);
+
+    /// Return a delta that turns this object into `newer`,
+    /// or `None` if they are the same.
+    ///
+    /// Applying the result to `self` must yield an object equal to `newer`.
+    fn delta_to(&self, newer: &Self) -> Option<Self::Delta>;
+}
+
+/// A handle to a storage system that stores objects of a single
+/// [`Journaled`] type to a single location,
+/// by appending [`Delta`](Journaled::Delta)s to a journal,
+/// and periodically replacing the stored snapshot.
+///
+/// To get an object of this type, call [`StateMgr::create_journaled_handle`].
+///
+/// Like [`StorageHandle`](crate::StorageHandle), this trait is object-safe.
+pub trait JournaledStorageHandle<T: Journaled> {
+    /// Try to load the object from storage, applying any journaled changes.
+    ///
+    /// If neither a snapshot nor any journal entries exist, return `Ok(None)`.
+    fn load(&self) -> Result<Option<T>>;
+
+    /// Try to store a value into storage.
+    ///
+    /// Usually this only appends the changes since the last value that we
+    /// loaded or stored to the journal;
+    /// but if the journal is long enough, or we don't know what is stored,
+    /// we store the whole of `val` instead, and clear the journal.
+    fn store(&self, val: &T) -> Result<()>;
+
+    /// Store the whole of `val`, and clear the journal.
+    ///
+    /// Call this before exiting, so that everything is in the snapshot.
+    /// Does nothing if the snapshot already holds `val`, and the journal
+    /// is empty.
+    fn compact(&self, val: &T) -> Result<()>;
+
+    /// Return true if we have the lock; see [`StateMgr::can_store`].
+    fn can_store(&self) -> bool;
+}
+
+/// Type wrapper for a reference-counted `dyn` [`JournaledStorageHandle`].
+///
+/// See [`DynStorageHandle`](crate::DynStorageHandle).
+pub type DynJournaledStorageHandle<T> = Arc<dyn JournaledStorageHandle<T> + Send + Sync + 'static>;
+
+/// Concrete implementation of [`JournaledStorageHandle`].
+///
+/// The snapshot is stored with [`StateMgr::store`] under the handle's key,
+/// in the same format that a [`StorageHandle`](crate::StorageHandle)
+/// for `T` would use, so it is possible to switch an existing key over to a
+/// `JournaledStorageHandle`.
+#[derive(Debug)]
+pub(crate) struct JournaledStorageHandleImpl<M, T> {
+    /// An underlying [`StateMgr`] to use.
+    mgr: M,
+    /// The key to use when loading and storing from the [`StateMgr`].
+    key: String,
+    /// How many journal entries do we allow before compacting?
+    compact_after: usize,
+    /// What we believe is currently in storage.
+    stored: Mutex<StoredState<T>>,
+    /// A zero-sized type to please the type checker.
+    ///
+    /// See `StorageHandleImpl` for why this is `fn(T) -> T`.
+    phantom: PhantomData<fn(T) -> T>,
+}
+
+/// What a [`JournaledStorageHandleImpl`] believes is currently in storage.
+#[derive(Debug)]
+struct StoredState<T> {
+    /// The value that the snapshot and journal together describe,
+    /// or `None` if we don't know.
+    value: Option<T>,
+    /// How many entries are currently in the journal?
+    n_entries: usize,
+}
+
+impl<M, T> JournaledStorageHandle<T> for JournaledStorageHandleImpl<M, T>
+where
+    M: StateMgr,
+    T: Journaled + 'static,
+{
+    fn load(&self) -> Result<Option<T>> {
+        let snapshot: Option<T> = self.mgr.load(&self.key)?;
+        let deltas: Vec<T::Delta> = self.mgr.load_journal(&self.key)?;
+        let n_entries = deltas.len();
+
+        let value = if snapshot.is_none() && deltas.is_empty() {
+            None
+        } else {
+            let mut obj = snapshot.unwrap_or_default();
+            for delta in deltas {
+                obj.apply(delta);
+            }
+            Some(obj)
+        };
+        *self.stored.lock().expect("poisoned lock") = StoredState {
+            value: value.clone(),
+            n_entries,
+        };
+        Ok(value)
+    }
+
+    fn store(&self, val: &T) -> Result<()> {
+        let mut stored = self.stored.lock().expect("poisoned lock");
+        let delta = match &stored.value {
+            Some(old) if stored.n_entries < self.compact_after => old.delta_to(val),
+            _ => return self.compact_locked(&mut stored, val),
+        };
+        if let Some(delta) = delta {
+            self.mgr.append_journal(&self.key, &delta)?;
+            stored.n_entries += 1;
+            stored.value = Some(val.clone());
+        }
+        Ok(())
+    }
+
+    fn compact(&self, val: &T) -> Result<()> {
+        let mut stored = self.stored.lock().expect("poisoned lock");
+        let up_to_date = match &stored.value {
+            Some(old) => stored.n_entries == 0 && old.delta_to(val).is_none(),
+            None => false,
+        };
+        if up_to_date {
+            return Ok(());
+        }
+        self.compact_locked(&mut stored, val)
+    }
+
+    fn can_store(&self) -> bool {
+        self.mgr.can_store()
+    }
+}
+
+impl<M, T> JournaledStorageHandleImpl<M, T>
+where
+    M: StateMgr,
+    T: Journaled,
+{
+    /// Construct a new `JournaledStorageHandleImpl` for `key` in `mgr`.
+    ///
+    /// Once the journal has `compact_after` entries, the next change causes
+    /// the whole object to be stored, and the journal to be cleared.
+    pub(crate) fn new(mgr: M, key: String, compact_after: usize) -> Self {
+        JournaledStorageHandleImpl {
+            mgr,
+            key,
+            compact_after,
+            stored: Mutex::new(StoredState {
+                value: None,
+                n_entries: 0,
+            }),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Helper: store `current` as the new snapshot, and clear the journal,
+    /// given the lock on `stored`.
+    fn compact_locked(&self, stored: &mut StoredState<T>, current: &T) -> Result<()> {
+        // We must store the snapshot before clearing the journal:
+        // if we crash in between, we'll replay deltas that are already
+        // in the snapshot, which is harmless.  The other order would lose them.
+        self.mgr.store(&self.key, current)?;
+        self.mgr.clear_journal(&self.key)?;
+        *stored = StoredState {
+            value: Some(current.clone()),
+            n_entries: 0,
+        };
+        Ok(())
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use crate::{FsStateMgr, LockStatus};
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+    struct Counters(BTreeMap<String, u32>);
+
+    impl Journaled for Counters {
+        /// New values for the counters that changed; `None` for removed ones.
+        type Delta = BTreeMap<String, Option<u32>>;
+
+        fn apply(&mut self, delta: Self::Delta) {
+            for (k, v) in delta {
+                match v {
+                    Some(v) => self.0.insert(k, v),
+                    None => self.0.remove(&k),
+                };
+            }
+        }
+
+        fn delta_to(&self, newer: &Self) -> Option<Self::Delta> {
+            let mut delta = BTreeMap::new();
+            for (k, v) in &newer.0 {
+                if self.0.get(k) != Some(v) {
+                    delta.insert(k.clone(), Some(*v));
+                }
+            }
+            for k in self.0.keys() {
+                if !newer.0.contains_key(k) {
+                    delta.insert(k.clone(), None);
+                }
+            }
+            (!delta.is_empty()).then_some(delta)
+        }
+    }
+
+    /// Set `k` to `v` in `obj`, and store the result with `h`.
+    fn set(h: &DynJournaledStorageHandle<Counters>, obj: &mut Counters, k: &str, v: u32) {
+        obj.0.insert(k.to_string(), v);
+        h.store(obj).unwrap();
+    }
+
+    /// Return the journal for `counters`, as stored in `mgr`.
+    fn journal(mgr: &FsStateMgr) -> Vec<BTreeMap<String, Option<u32>>> {
+        mgr.load_journal("counters").unwrap()
+    }
+
+    #[test]
+    fn journal_and_compact() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let statedir = dir.path().join("state");
+        let mgr = FsStateMgr::from_path(dir.path()).unwrap();
+        assert_eq!(mgr.try_lock().unwrap(), LockStatus::NewlyAcquired);
+
+        let h: DynJournaledStorageHandle<Counters> =
+            mgr.clone().create_journaled_handle("counters", 3);
+        assert!(h.load().unwrap().is_none());
+
+        // The first store is a snapshot, since we don't know what's stored.
+        let mut obj = Counters::default();
+        set(&h, &mut obj, "a", 1);
+        assert!(statedir.join("counters.json").exists());
+        assert!(journal(&mgr).is_empty());
+
+        set(&h, &mut obj, "b", 2);
+        set(&h, &mut obj, "a", 3);
+        // Storing an unchanged object does nothing.
+        h.store(&obj).unwrap();
+        obj.0.remove("b");
+        h.store(&obj).unwrap();
+        assert_eq!(journal(&mgr).len(), 3);
+        let snapshot: Counters = mgr.load("counters").unwrap().unwrap();
+        assert_ne!(snapshot, obj);
+        let h2: DynJournaledStorageHandle<Counters> =
+            mgr.clone().create_journaled_handle("counters", 3);
+        assert_eq!(h2.load().unwrap().as_ref(), Some(&obj));
+
+        // This one causes a compaction.
+        set(&h, &mut obj, "c", 4);
+        assert!(journal(&mgr).is_empty());
+        let snapshot: Counters = mgr.load("counters").unwrap().unwrap();
+        assert_eq!(snapshot, obj);
+        assert_eq!(h2.load().unwrap().as_ref(), Some(&obj));
+
+        // Snapshot plus journal.
+        set(&h, &mut obj, "b", 5);
+        assert_eq!(journal(&mgr).len(), 1);
+        assert_eq!(h2.load().unwrap().as_ref(), Some(&obj));
+
+        // Simulate a crash between storing the snapshot and clearing the journal.
+        mgr.store("counters", &obj).unwrap();
+        assert_eq!(h2.load().unwrap().as_ref(), Some(&obj));
+
+        // A handle that has loaded the journal counts its entries.
+        set(&h2, &mut obj, "d", 6);
+        assert_eq!(journal(&mgr).len(), 2);
+        set(&h2, &mut obj, "d", 7);
+        set(&h2, &mut obj, "d", 8);
+        assert!(journal(&mgr).is_empty());
+        assert_eq!(h.load().unwrap().as_ref(), Some(&obj));
+
+        // Compacting puts everything in the snapshot, where a reader that
+        // doesn't know about the journal can find it.
+        set(&h2, &mut obj, "e", 9);
+        assert_eq!(journal(&mgr).len(), 1);
+        h2.compact(&obj).unwrap();
+        assert!(journal(&mgr).is_empty());
+        let snapshot: Counters = mgr.load("counters").unwrap().unwrap();
+        assert_eq!(snapshot, obj);
+
+        // Compacting again does nothing.
+        std::fs::remove_file(statedir.join("counters.json")).unwrap();
+        h2.compact(&obj).unwrap();
+        assert!(mgr.load::<Counters>("counters").unwrap().is_none());
+    }
+
+    #[test]
+    fn replay_suffix() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mgr = FsStateMgr::from_path(dir.path()).unwrap();
+        assert_eq!(mgr.try_lock().unwrap(), LockStatus::NewlyAcquired);
+
+        let h: DynJournaledStorageHandle<Counters> =
+            mgr.clone().create_journaled_handle("counters", 100);
+        let mut obj = Counters::default();
+        set(&h, &mut obj, "a", 1);
+        set(&h, &mut obj, "b", 2);
+        set(&h, &mut obj, "a", 3);
+        obj.0.remove("b");
+        h.store(&obj).unwrap();
+        set(&h, &mut obj, "b", 4);
+        set(&h, &mut obj, "c", 5);
+        let deltas = journal(&mgr);
+        assert_eq!(deltas.len(), 5);
+
+        // Replaying any suffix of the journal over the final object is a no-op.
+        for start in 0..=deltas.len() {
+            let mut replayed = obj.clone();
+            for delta in &deltas[start..] {
+                replayed.apply(delta.clone());
+            }
+            assert_eq!(replayed, obj, "replaying from entry {}", start);
+        }
+
+        // In particular, this holds when we crash after compacting,
+        // before we have cleared the journal.
+        mgr.store("counters", &obj).unwrap();
+        let h2: DynJournaledStorageHandle<Counters> =
+            mgr.clone().create_journaled_handle("counters", 100);
+        assert_eq!(h2.load().unwrap().as_ref(), Some(&obj));
+    }
+}
@@ -41,12 +41,16 @@
 #![allow(clippy::needless_raw_string_hashes)] // complained-about code is fine, often best
 //! <!-- @@ end lint list maintained by maint/add_warning @@ -->
 
+pub mod atomic_write;
+mod check;
 mod err;
 #[cfg(not(target_arch = "wasm32"))]
 mod fs;
 mod fs_mistrust_error_ext;
 mod handle;
+mod journal;
 mod load_store;
+mod memory;
 pub mod slug;
 #[cfg(feature = "testing")]
 mod testing;
@@ -60,11 +64,14 @@ use std::sync::Arc;
 /// Wrapper type for Results returned from this crate.
 type Result<T> = std::result::Result<T, crate::Error>;
 
+pub use check::{CheckProblem, CheckReport, CheckSeverity};
 pub use err::{Error, ErrorSource};
 #[cfg(not(target_arch = "wasm32"))]
 pub use fs::FsStateMgr;
 pub use fs_mistrust_error_ext::FsMistrustErrorExt;
 pub use handle::{DynStorageHandle, StorageHandle};
+pub use journal::{DynJournaledStorageHandle, Journaled, JournaledStorageHandle};
+pub use memory::MemoryStateMgr;
 pub use serde_json::Value as JsonValue;
 #[cfg(feature = "testing")]
 pub use testing::TestingStateMgr;
@@ -92,6 +99,38 @@ pub trait StateMgr: Clone {
     fn store<S>(&self, key: &str, val: &S) -> Result<()>
     where
         S: Serialize;
+    /// Try to load the journal entries that have been appended with key `key`.
+    ///
+    /// Return an empty list if there is no such journal.
+    ///
+    /// A journal is stored separately from any object stored with
+    /// [`store`](StateMgr::store) under the same key.
+    /// Most users will want to use a [`JournaledStorageHandle`] rather than
+    /// calling this directly.
+    fn load_journal<D>(&self, key: &str) -> Result<Vec<D>>
+    where
+        D: DeserializeOwned,
+    {
+        Ok(self.load(&default_journal_key(key))?.unwrap_or_default())
+    }
+    /// Try to append `entry` to the journal with key `key`.
+    ///
+    /// The default implementation rewrites the entire journal;
+    /// implementations that can do better should override it.
+    fn append_journal<S>(&self, key: &str, entry: &S) -> Result<()>
+    where
+        S: Serialize,
+    {
+        let entry = serde_json::to_value(entry)
+            .map_err(|e| Error::new(e, err::Action::Storing, err::Resource::Manager))?;
+        let mut entries: Vec<JsonValue> = self.load_journal(key)?;
+        entries.push(entry);
+        self.store(&default_journal_key(key), &entries)
+    }
+    /// Try to remove every entry from the journal with key `key`.
+    fn clear_journal(&self, key: &str) -> Result<()> {
+        self.store(&default_journal_key(key), &Vec::<JsonValue>::new())
+    }
     /// Return true if this is a read-write state manager.
     ///
     /// If it returns false, then attempts to `store` will fail with
@@ -120,6 +159,33 @@ pub trait StateMgr: Clone {
     {
         Arc::new(handle::StorageHandleImpl::new(self, key.into()))
     }
+
+    /// Make a new [`JournaledStorageHandle`] to store values of particular
+    /// type at a particular key.
+    ///
+    /// Once the handle has appended `compact_after` changes to the journal,
+    /// it stores the next value as a snapshot, and clears the journal.
+    fn create_journaled_handle<T>(
+        self,
+        key: impl Into<String>,
+        compact_after: usize,
+    ) -> DynJournaledStorageHandle<T>
+    where
+        Self: Send + Sync + Sized + 'static,
+        T: Journaled + 'static,
+    {
+        Arc::new(journal::JournaledStorageHandleImpl::new(
+            self,
+            key.into(),
+            compact_after,
+        ))
+    }
+}
+
+/// Return the key under which the default implementations of the
+/// `StateMgr` journal methods store the journal for `key`.
+fn default_journal_key(key: &str) -> String {
+    format!("{}.journal", key)
 }
 
 /// A possible outcome from calling [`StateMgr::try_lock()`]
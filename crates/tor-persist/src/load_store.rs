@@ -16,6 +16,7 @@ use serde::{de::DeserializeOwned, Serialize};
 use tor_error::ErrorReport as _;
 use tracing::trace;
 
+use crate::atomic_write::{self, Durability};
 use crate::err::ErrorSource;
 
 /// Common arguments to load/store operations
@@ -68,15 +69,18 @@ impl Target<'_> {
     /// for the same file.
     /// That might result in corrupted files.
     ///
-    /// See [`fs_mistrust::CheckedDir::write_and_replace`]
+    /// See [`atomic_write::write_and_replace`]
     /// for more details about the semantics.
     pub(crate) fn store<S: Serialize>(&self, val: &S) -> Result<(), ErrorSource> {
         trace!("storing {self}");
         let output = serde_json::to_string_pretty(val)?;
 
-        self.dir.write_and_replace(self.rel_fname, output)?;
-
-        Ok(())
+        atomic_write::write_and_replace_inner(
+            self.dir,
+            self.rel_fname,
+            output.as_bytes(),
+            Durability::Unsynced,
+        )
     }
 
     /// Delete the file specified by `self`
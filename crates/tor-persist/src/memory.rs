@@ -0,0 +1,191 @@
+//! A StateMgr that keeps its state in memory, for platforms without a filesystem.
+
+use crate::err::{Action, ErrorSource, Resource};
+use crate::{Error, JsonValue, LockStatus, Result, StateMgr};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// A state manager that keeps its state in memory.
+///
+/// This is for environments where `FsStateMgr` can't be used:
+/// for example, WebAssembly in a browser, where there is no filesystem.
+/// Nothing is written anywhere, so the state is lost when the process exits,
+/// unless the caller saves it elsewhere: see
+/// [`snapshot`](MemoryStateMgr::snapshot) and
+/// [`from_snapshot`](MemoryStateMgr::from_snapshot).
+///
+/// Clones of a `MemoryStateMgr` share their state, and their lock:
+/// since there is no other process that could be using the same state,
+/// [`try_lock`](StateMgr::try_lock) always succeeds.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStateMgr {
+    /// The shared state.
+    inner: Arc<Mutex<MemoryStateMgrInner>>,
+}
+
+/// The shared state of a [`MemoryStateMgr`].
+#[derive(Debug, Default)]
+struct MemoryStateMgrInner {
+    /// True if we are a read-write state manager.
+    lock_held: bool,
+    /// Map from key to stored value.
+    entries: HashMap<String, JsonValue>,
+}
+
+impl MemoryStateMgr {
+    /// Create a new, empty, unlocked `MemoryStateMgr`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new unlocked `MemoryStateMgr` holding the entries in `snapshot`.
+    ///
+    /// `snapshot` should come from [`snapshot`](MemoryStateMgr::snapshot),
+    /// perhaps in an earlier run of the program.
+    pub fn from_snapshot(snapshot: HashMap<String, JsonValue>) -> Self {
+        let inner = MemoryStateMgrInner {
+            lock_held: false,
+            entries: snapshot,
+        };
+        MemoryStateMgr {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    /// Return a copy of every entry that is stored in this `MemoryStateMgr`.
+    ///
+    /// Callers that have some other way to keep data (like a browser's local
+    /// storage) can save this, and restore it later with
+    /// [`from_snapshot`](MemoryStateMgr::from_snapshot).
+    pub fn snapshot(&self) -> HashMap<String, JsonValue> {
+        self.inner.lock().expect("Lock poisoned.").entries.clone()
+    }
+}
+
+impl StateMgr for MemoryStateMgr {
+    fn load<D>(&self, key: &str) -> Result<Option<D>>
+    where
+        D: DeserializeOwned,
+    {
+        let inner = self.inner.lock().expect("Lock poisoned.");
+        inner
+            .entries
+            .get(key)
+            .map(|value| {
+                D::deserialize(value).map_err(|e| {
+                    Error::new(
+                        e,
+                        Action::Loading,
+                        Resource::Temporary {
+                            key: key.to_string(),
+                        },
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    fn store<S>(&self, key: &str, val: &S) -> Result<()>
+    where
+        S: Serialize,
+    {
+        let mut inner = self.inner.lock().expect("Lock poisoned.");
+        if !inner.lock_held {
+            return Err(Error::new(
+                ErrorSource::NoLock,
+                Action::Storing,
+                Resource::Manager,
+            ));
+        }
+        let val = serde_json::to_value(val).map_err(|e| {
+            Error::new(
+                e,
+                Action::Storing,
+                Resource::Temporary {
+                    key: key.to_string(),
+                },
+            )
+        })?;
+        inner.entries.insert(key.to_string(), val);
+        Ok(())
+    }
+
+    fn can_store(&self) -> bool {
+        self.inner.lock().expect("Lock poisoned.").lock_held
+    }
+
+    fn try_lock(&self) -> Result<LockStatus> {
+        let mut inner = self.inner.lock().expect("Lock poisoned.");
+        if inner.lock_held {
+            Ok(LockStatus::AlreadyHeld)
+        } else {
+            inner.lock_held = true;
+            Ok(LockStatus::NewlyAcquired)
+        }
+    }
+
+    fn unlock(&self) -> Result<()> {
+        self.inner.lock().expect("Lock poisoned.").lock_held = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+    struct Ex {
+        v: u32,
+        s: String,
+    }
+
+    #[test]
+    fn basics() {
+        let mgr = MemoryStateMgr::new();
+        let ex = Ex {
+            v: 7,
+            s: "seven".into(),
+        };
+
+        assert!(!mgr.can_store());
+        assert!(mgr.store("ex", &ex).is_err());
+        assert_eq!(mgr.load::<Ex>("ex").unwrap(), None);
+
+        assert_eq!(mgr.try_lock().unwrap(), LockStatus::NewlyAcquired);
+        assert_eq!(mgr.clone().try_lock().unwrap(), LockStatus::AlreadyHeld);
+        mgr.store("ex", &ex).unwrap();
+        assert_eq!(mgr.load::<Ex>("ex").unwrap(), Some(ex.clone()));
+        assert!(mgr.load::<String>("ex").is_err());
+
+        // Journals work via the default implementations.
+        mgr.append_journal("log", &1_u8).unwrap();
+        mgr.append_journal("log", &2_u8).unwrap();
+        assert_eq!(mgr.load_journal::<u8>("log").unwrap(), vec![1, 2]);
+
+        mgr.unlock().unwrap();
+        assert!(!mgr.can_store());
+        assert!(mgr.store("ex", &ex).is_err());
+
+        // A snapshot carries everything over.
+        let restored = MemoryStateMgr::from_snapshot(mgr.snapshot());
+        assert_eq!(restored.load::<Ex>("ex").unwrap(), Some(ex));
+        assert_eq!(restored.load_journal::<u8>("log").unwrap(), vec![1, 2]);
+        assert!(!restored.can_store());
+    }
+}
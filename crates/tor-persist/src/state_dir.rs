@@ -44,6 +44,7 @@
 //! STATE_DIR/hss/allium-cepa.lock
 //! STATE_DIR/hss/allium-cepa/ipts.json
 //! STATE_DIR/hss/allium-cepa/iptpub.json
+//! STATE_DIR/hss/allium-cepa/descpub.json
 //! STATE_DIR/hss/allium-cepa/iptreplay/
 //! STATE_DIR/hss/allium-cepa/iptreplay/9aa9517e6901c280a550911d3a3c679630403db1c622eedefbdf1715297f795f.bin
 //! ```
@@ -192,6 +193,12 @@ pub use crate::Error;
 #[allow(unused_imports)] // Simplifies a lot of references in our docs
 use crate::slug;
 
+mod archive;
+mod check;
+
+pub use crate::check::{CheckProblem, CheckReport, CheckSeverity};
+pub use archive::StateArchive;
+
 define_derive_deftly! {
     ContainsInstanceStateGuard:
 
@@ -1680,4 +1687,163 @@ mod test {
             });
         }
     }
+
+    #[test]
+    #[traced_test]
+    fn test_check() {
+        test_temp_dir!().used_by(|dir| {
+            let sd = mk_state_dir(dir);
+            let garlic = Garlic("wild".try_into_slug().unwrap());
+
+            let ih = sd.acquire_instance(&garlic).unwrap();
+            ih.storage_handle::<StoredData>("good")
+                .unwrap()
+                .store(&StoredData { some_value: 1 })
+                .unwrap();
+            drop(ih);
+
+            let inst_path = dir.join("garlic/wild");
+            fs::write(inst_path.join("bad.json"), "{ not json").unwrap();
+            fs::write(inst_path.join("good.tmp"), "").unwrap();
+            fs::write(inst_path.join("Not A Slug.json"), "{}").unwrap();
+            fs::write(dir.join("garlic/orphan.lock"), "").unwrap();
+
+            let summarise = |report: &CheckReport| {
+                let mut got = report
+                    .problems
+                    .iter()
+                    .map(|p| {
+                        let leaf = p.path().file_name().unwrap().to_str().unwrap().to_owned();
+                        (leaf, p.severity(), p.repaired())
+                    })
+                    .collect_vec();
+                got.sort();
+                got
+            };
+            let expect = |repaired| {
+                vec![
+                    ("Not A Slug.json".to_owned(), CheckSeverity::Warning, false),
+                    ("bad.json".to_owned(), CheckSeverity::Error, false),
+                    ("good.tmp".to_owned(), CheckSeverity::Warning, repaired),
+                    ("orphan.lock".to_owned(), CheckSeverity::Warning, repaired),
+                ]
+            };
+
+            // Without repair, nothing changes.
+            let report = sd.check_instances("garlic", false).unwrap();
+            assert_eq!(summarise(&report), expect(false));
+            assert_eq!(report.worst(), Some(CheckSeverity::Error));
+            assert!(inst_path.join("good.tmp").exists());
+            assert!(dir.join("garlic/orphan.lock").exists());
+
+            // With repair, the trivial problems are fixed.
+            let report = sd.check_instances("garlic", true).unwrap();
+            assert_eq!(summarise(&report), expect(true));
+            assert!(!inst_path.join("good.tmp").exists());
+            assert!(!dir.join("garlic/orphan.lock").exists());
+
+            // An instance that is in use is inspected, but not repaired.
+            fs::write(inst_path.join("good.tmp"), "").unwrap();
+            let ih = sd.acquire_instance(&garlic).unwrap();
+            let report = sd.check_instance(&garlic, true).unwrap();
+            assert!(report
+                .problems
+                .iter()
+                .any(|p| p.severity() == CheckSeverity::Info));
+            assert!(inst_path.join("good.tmp").exists());
+            drop(ih);
+
+            // The machine-readable form.
+            let json = serde_json::to_value(&report).unwrap();
+            assert_eq!(json["problems"][0]["severity"], "info");
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_archive() {
+        test_temp_dir!().used_by(|dir| {
+            let src = mk_state_dir(&dir.join("src"));
+            let dst = mk_state_dir(&dir.join("dst"));
+            let garlic = Garlic("wild".try_into_slug().unwrap());
+            let to_store = StoredData { some_value: 42 };
+
+            let ih = src.acquire_instance(&garlic).unwrap();
+            ih.storage_handle::<StoredData>("stored_data")
+                .unwrap()
+                .store(&to_store)
+                .unwrap();
+            let raw = ih.raw_subdir("raw").unwrap();
+            fs::create_dir(raw.as_path().join("nested")).unwrap();
+            fs::write(raw.as_path().join("nested/blob.bin"), b"blob").unwrap();
+            fs::write(dir.join("src/garlic/wild/stale.tmp"), "").unwrap();
+
+            let mut archive = vec![];
+            ih.export_archive(&mut archive, &[("keys", &b"secret"[..])])
+                .unwrap();
+
+            let read = dst.read_archive(&garlic, &archive[..]).unwrap();
+            assert_eq!(read.extras().collect_vec(), [("keys", &b"secret"[..])]);
+            // Reading doesn't touch the state directory.
+            assert!(!dir.join("dst/garlic").exists());
+            let restored = dst.restore_archive(&garlic, read).unwrap();
+            assert_eq!(
+                restored
+                    .storage_handle::<StoredData>("stored_data")
+                    .unwrap()
+                    .load()
+                    .unwrap(),
+                Some(to_store),
+            );
+            let dst_inst = dir.join("dst/garlic/wild");
+            assert_eq!(
+                fs::read(dst_inst.join("raw/nested/blob.bin")).unwrap(),
+                b"blob"
+            );
+            assert!(!dst_inst.join("stale.tmp").exists());
+            assert!(!dir.join("dst/garlic/wild.restoring").exists());
+
+            // Restoring over existing state is refused.
+            drop(restored);
+            let read = dst.read_archive(&garlic, &archive[..]).unwrap();
+            let e = dst.restore_archive(&garlic, read).unwrap_err();
+            assert_eq!(e.kind(), TEK::BadApiUsage);
+
+            // So is reading an archive for a different instance.
+            let other = Garlic("cultivated".try_into_slug().unwrap());
+            let e = dst.read_archive(&other, &archive[..]).unwrap_err();
+            assert!(matches!(e.source(), ErrorSource::BadArchive(_)));
+            // Or restoring one that was read for a different instance.
+            let read = dst.read_archive(&garlic, &archive[..]).unwrap();
+            let e = dst.restore_archive(&other, read).unwrap_err();
+            assert_eq!(e.kind(), TEK::BadApiUsage);
+            assert!(!dir.join("dst/garlic/cultivated").exists());
+
+            // An archive that is too big is rejected before we run out of memory.
+            let mut header = tar::Header::new_gnu();
+            header.set_size(archive::MAX_ARCHIVE_SIZE + 1);
+            header.set_mode(0o600);
+            header.set_path("state/huge.json").unwrap();
+            header.set_cksum();
+            let e = dst
+                .read_archive(&other, &header.as_bytes()[..])
+                .unwrap_err();
+            assert!(matches!(e.source(), ErrorSource::BadArchive(_)));
+
+            // And an archive that tries to escape the instance directory.
+            let path = "state/../../escaped.json";
+            let mut header = tar::Header::new_gnu();
+            header.set_size(2);
+            header.set_mode(0o600);
+            // append_data would refuse the `..`, so set the name directly.
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_cksum();
+            let mut evil = tar::Builder::new(vec![]);
+            evil.append(&header, &b"{}"[..]).unwrap();
+            let evil = evil.into_inner().unwrap();
+            let e = dst.read_archive(&other, &evil[..]).unwrap_err();
+            assert!(matches!(e.source(), ErrorSource::BadArchive(_)));
+            assert!(!dir.join("dst/escaped.json").exists());
+        });
+    }
 }
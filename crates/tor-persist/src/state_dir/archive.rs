@@ -0,0 +1,520 @@
+//! Exporting and restoring an instance's state as a single archive
+//!
+//! See [`InstanceStateHandle::export_archive`], [`StateDirectory::read_archive`]
+//! and [`StateDirectory::restore_archive`].
+//!
+//! ### Archive format
+//!
+//! The archive is a `tar` file containing:
+//!
+//!  * `MANIFEST.json`: the archive format version, and the kind and identity of the instance.
+//!  * `state/...`: the instance's storage files (`KEY.json`) and raw subdirectories.
+//!  * `extra/NAME`: opaque data supplied by the caller, for example the instance's keys.
+//!
+//! Every name in the archive is checked on restore,
+//! so a malicious archive can't write outside the instance directory.
+//! The archive is read into memory, so its size is limited to [`MAX_ARCHIVE_SIZE`].
+
+use super::*;
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Component, PathBuf};
+
+use itertools::Itertools as _;
+use serde::Deserialize;
+
+/// The version of the archive format that we write, and the only one we can read
+const FORMAT_VERSION: u32 = 1;
+
+/// The name of the manifest in the archive
+const MANIFEST: &str = "MANIFEST.json";
+
+/// The directory in the archive containing the instance's state
+const STATE_DIR: &str = "state";
+
+/// The directory in the archive containing the caller's extra data
+const EXTRA_DIR: &str = "extra";
+
+/// The largest archive we will read, in bytes
+///
+/// Since we hold the whole archive in memory while we check it,
+/// a bigger one is rejected, rather than risking running out of memory.
+/// Real instance state is far smaller than this.
+pub(super) const MAX_ARCHIVE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Extension of the directory into which we restore an archive, before moving it into place
+const RESTORING_EXTN: &str = "restoring";
+
+/// The manifest, describing what is in an archive
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    /// The version of the archive format
+    format_version: u32,
+    /// The kind of the instance
+    kind: String,
+    /// The identity of the instance
+    identity: String,
+    /// The names of the extra data items in the archive
+    #[serde(default)]
+    extras: Vec<String>,
+}
+
+/// The validated contents of an archive, read into memory
+#[derive(Debug, Default)]
+struct Contents {
+    /// The manifest
+    manifest: Option<Manifest>,
+    /// Directories in the instance, relative to the instance directory
+    dirs: Vec<PathBuf>,
+    /// Files in the instance, relative to the instance directory, and their contents
+    files: Vec<(PathBuf, Vec<u8>)>,
+    /// The extra data items
+    extras: BTreeMap<String, Vec<u8>>,
+}
+
+/// An archive of an instance's state, read and checked by [`StateDirectory::read_archive`]
+///
+/// Nothing has been written to the state directory yet:
+/// use [`StateDirectory::restore_archive`] for that.
+#[derive(Debug)]
+pub struct StateArchive {
+    /// The contents of the archive, whose manifest is for `kind`/`id`
+    contents: Contents,
+    /// The kind of the instance that the archive is for
+    kind: String,
+    /// The identity of the instance that the archive is for
+    id: String,
+}
+
+impl StateArchive {
+    /// Return the extra items that were passed to
+    /// [`export_archive`](InstanceStateHandle::export_archive)
+    pub fn extras(&self) -> impl Iterator<Item = (&str, &[u8])> + '_ {
+        self.contents
+            .extras
+            .iter()
+            .map(|(name, data)| (name.as_str(), &data[..]))
+    }
+}
+
+/// Error for a malformed archive
+fn bad_archive(msg: impl Into<String>) -> ErrorSource {
+    ErrorSource::BadArchive(msg.into())
+}
+
+/// A reader that fails, rather than stopping, after more than a limited number of bytes
+///
+/// (`Read::take` isn't suitable: if it stopped at the end of a `tar` entry,
+/// the rest of the archive would silently be ignored.)
+struct Capped<R> {
+    /// The underlying reader
+    inner: R,
+    /// How many more bytes we may read
+    remaining: u64,
+}
+
+impl<R: Read> Read for Capped<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Read one more byte than we allow, so that we can tell whether there was more.
+        let max = usize::try_from(self.remaining.saturating_add(1))
+            .unwrap_or(usize::MAX)
+            .min(buf.len());
+        let n = self.inner.read(&mut buf[..max])?;
+        self.remaining = self
+            .remaining
+            .checked_sub(n as u64)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "archive is too large"))?;
+        Ok(n)
+    }
+}
+
+/// Check that `rel`, a path within an instance directory, is something we export and restore
+///
+/// The first component must be a storage file (`KEY.json`) or a raw subdirectory (`KEY`).
+/// Within raw subdirectories, any ordinary names are allowed.
+fn check_state_path(rel: &Path, is_dir: bool) -> StdResult<(), String> {
+    let mut components = rel.components();
+    let Some(Component::Normal(first)) = components.next() else {
+        return Err(format!("bad path {rel:?}"));
+    };
+    let first = first
+        .to_str()
+        .ok_or_else(|| format!("non-UTF-8 name {rel:?}"))?;
+    let nested = components.clone().next().is_some();
+    if !components.all(|c| matches!(c, Component::Normal(_))) {
+        return Err(format!("bad path {rel:?}"));
+    }
+
+    let slug = if is_dir || nested {
+        first
+    } else {
+        first
+            .strip_suffix(".json")
+            .ok_or_else(|| format!("unexpected file {rel:?}"))?
+    };
+    SlugRef::new(slug).map_err(|e| format!("bad name {rel:?}: {e}"))?;
+    Ok(())
+}
+
+/// Return the kind and identity of the instance whose directory is `dir`
+fn instance_names(dir: &Path) -> StdResult<(String, String), ErrorSource> {
+    let name = |p: Option<&Path>| {
+        p.and_then(|p| p.file_name())
+            .and_then(|n| n.to_str())
+            .map(str::to_owned)
+            .ok_or_else(|| tor_error::internal!("instance directory {dir:?} has a strange name"))
+    };
+    Ok((name(dir.parent())?, name(Some(dir))?))
+}
+
+/// Append a file or directory to `builder`
+fn append<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &Path,
+    data: Option<&[u8]>,
+) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    match data {
+        Some(data) => {
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(data.len() as u64);
+            header.set_mode(0o600);
+            builder.append_data(&mut header, path, data)
+        }
+        None => {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            header.set_mode(0o700);
+            builder.append_data(&mut header, path, io::empty())
+        }
+    }
+}
+
+impl InstanceStateHandle {
+    /// Write this instance's state to `out`, as an archive
+    ///
+    /// The archive contains all of the instance's storage files and raw subdirectories,
+    /// and every item in `extras`, which will be returned by
+    /// [`StateDirectory::restore_archive`].
+    /// `extras` is for data that belongs with the state but is kept elsewhere
+    /// (for example, an onion service's keys).
+    /// Each extra item's name must be a valid [`Slug`].
+    ///
+    /// Since we hold the instance's lock, nothing else can modify the state meanwhile.
+    /// However, if the owner of this handle is storing state concurrently,
+    /// the archive may contain some items from before, and some from after, each change.
+    ///
+    /// Stale temporary files, and anything else that
+    /// [`check_instance`](StateDirectory::check_instance) would complain about,
+    /// are left out.
+    pub fn export_archive(&self, out: impl Write, extras: &[(&str, &[u8])]) -> Result<()> {
+        let resource = || Resource::Directory {
+            dir: self.dir.as_path().to_owned(),
+        };
+        self.export_archive_inner(out, extras)
+            .map_err(|source| Error::new(source, Action::Exporting, resource()))
+    }
+
+    /// Implementation of `export_archive`, not generic over `out`
+    fn export_archive_inner(
+        &self,
+        out: impl Write,
+        extras: &[(&str, &[u8])],
+    ) -> StdResult<(), ErrorSource> {
+        let (kind, identity) = instance_names(self.dir.as_path())?;
+        let extra_names = extras
+            .iter()
+            .map(|(name, _)| Ok(SlugRef::new(name)?.to_string()))
+            .collect::<StdResult<Vec<_>, BadSlug>>()?;
+        if extra_names.iter().duplicates().next().is_some() {
+            return Err(tor_error::bad_api_usage!("duplicate extra item names").into());
+        }
+
+        let manifest = Manifest {
+            format_version: FORMAT_VERSION,
+            kind,
+            identity,
+            extras: extra_names,
+        };
+        let manifest = serde_json::to_vec_pretty(&manifest).map_err(Arc::new)?;
+
+        let mut builder = tar::Builder::new(out);
+        append(&mut builder, Path::new(MANIFEST), Some(&manifest))?;
+        append(&mut builder, Path::new(STATE_DIR), None)?;
+        self.append_state_dir(&mut builder, Path::new(""))?;
+        for &(name, data) in extras {
+            append(&mut builder, &Path::new(EXTRA_DIR).join(name), Some(data))?;
+        }
+        builder.into_inner()?.flush()?;
+        Ok(())
+    }
+
+    /// Append the contents of `rel`, a directory within the instance, to `builder`
+    fn append_state_dir<W: Write>(
+        &self,
+        builder: &mut tar::Builder<W>,
+        rel: &Path,
+    ) -> StdResult<(), ErrorSource> {
+        let mut ents = self
+            .dir
+            .read_directory(rel)?
+            .collect::<io::Result<Vec<_>>>()?;
+        // Sort, so that the same state always makes the same archive
+        ents.sort_by_key(|ent| ent.file_name());
+
+        for ent in ents {
+            let rel_path = rel.join(ent.file_name());
+            let file_type = ent.file_type()?;
+            let in_archive = Path::new(STATE_DIR).join(&rel_path);
+            if file_type.is_dir() {
+                if check_state_path(&rel_path, true).is_err() {
+                    continue;
+                }
+                append(builder, &in_archive, None)?;
+                self.append_state_dir(builder, &rel_path)?;
+            } else if file_type.is_file() {
+                // This also skips stale temporary files, which aren't `KEY.json`
+                if check_state_path(&rel_path, false).is_err() {
+                    continue;
+                }
+                let data = self.dir.read(&rel_path)?;
+                append(builder, &in_archive, Some(&data))?;
+            }
+            // Symlinks and other special files are not part of the state.
+        }
+        Ok(())
+    }
+
+    /// Write the validated `contents` of an archive into this (empty) instance
+    ///
+    /// `kind_dir` is the directory containing the instance directory,
+    /// and `id` is the instance's identity.
+    ///
+    /// We write everything into a separate directory,
+    /// and then move that into place,
+    /// so that the instance never contains only some of the archive.
+    fn restore_contents(
+        &self,
+        kind_dir: &CheckedDir,
+        id: &str,
+        contents: &Contents,
+    ) -> StdResult<(), ErrorSource> {
+        let inst_path = self.dir.as_path();
+        if fs::read_dir(inst_path)?.next().is_some() {
+            return Err(tor_error::bad_api_usage!(
+                "restoring an archive into an instance that already has state"
+            )
+            .into());
+        }
+
+        // Anything already here is left over from an earlier attempt that crashed,
+        // since we hold the lock.
+        let staging_name = format!("{id}.{RESTORING_EXTN}");
+        let staging_path = kind_dir.join(&staging_name)?;
+        match fs::remove_dir_all(&staging_path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            other => other?,
+        }
+        let staging = kind_dir.make_secure_directory(&staging_name)?;
+
+        for dir in &contents.dirs {
+            staging.make_directory(dir)?;
+        }
+        for (file, data) in &contents.files {
+            if let Some(parent) = file.parent().filter(|p| !p.as_os_str().is_empty()) {
+                staging.make_directory(parent)?;
+            }
+            staging.write_and_replace(file, data)?;
+        }
+
+        // Not every platform can rename over an empty directory, so remove it first.
+        // If we crash in between, the instance simply has no state, as before.
+        fs::remove_dir(inst_path)?;
+        fs::rename(&staging_path, inst_path)?;
+        Ok(())
+    }
+}
+
+impl Contents {
+    /// Read and validate an archive
+    ///
+    /// We read the whole thing into memory before touching the filesystem,
+    /// so that a bad archive doesn't leave a partially-restored instance.
+    /// Archives bigger than [`MAX_ARCHIVE_SIZE`] are rejected.
+    fn read(input: impl Read) -> StdResult<Self, ErrorSource> {
+        let mut contents = Contents::default();
+        let mut archive = tar::Archive::new(Capped {
+            inner: input,
+            remaining: MAX_ARCHIVE_SIZE,
+        });
+        let mut total_size: u64 = 0;
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            // Check the declared size before reading, to give a clearer error than `Capped`.
+            total_size = total_size.saturating_add(entry.size());
+            if total_size > MAX_ARCHIVE_SIZE {
+                return Err(bad_archive(format!(
+                    "archive is larger than {MAX_ARCHIVE_SIZE} bytes"
+                )));
+            }
+            let is_dir = match entry.header().entry_type() {
+                tar::EntryType::Regular => false,
+                tar::EntryType::Directory => true,
+                other => return Err(bad_archive(format!("{path:?} has type {other:?}"))),
+            };
+            let data = if is_dir {
+                None
+            } else {
+                let mut data = vec![];
+                entry.read_to_end(&mut data)?;
+                Some(data)
+            };
+
+            if let Ok(rel) = path.strip_prefix(STATE_DIR) {
+                if rel.as_os_str().is_empty() {
+                    continue;
+                }
+                check_state_path(rel, is_dir).map_err(bad_archive)?;
+                match data {
+                    None => contents.dirs.push(rel.to_owned()),
+                    Some(data) => contents.files.push((rel.to_owned(), data)),
+                }
+            } else if let Ok(rel) = path.strip_prefix(EXTRA_DIR) {
+                if rel.as_os_str().is_empty() && is_dir {
+                    continue;
+                }
+                let name = rel
+                    .to_str()
+                    .filter(|n| SlugRef::new(n).is_ok())
+                    .ok_or_else(|| bad_archive(format!("bad extra item name {rel:?}")))?;
+                let data = data.ok_or_else(|| bad_archive(format!("{path:?} is a directory")))?;
+                contents.extras.insert(name.to_owned(), data);
+            } else if path == Path::new(MANIFEST) {
+                let data = data.ok_or_else(|| bad_archive("manifest is a directory"))?;
+                let manifest = serde_json::from_slice(&data)
+                    .map_err(|e| bad_archive(format!("bad manifest: {e}")))?;
+                contents.manifest = Some(manifest);
+            } else {
+                return Err(bad_archive(format!("unexpected entry {path:?}")));
+            }
+        }
+
+        Ok(contents)
+    }
+
+    /// Check that this archive is of the right version, and is for instance `kind`/`id`
+    fn check_manifest(&self, kind: &SlugRef, id: &SlugRef) -> StdResult<(), ErrorSource> {
+        let manifest = self
+            .manifest
+            .as_ref()
+            .ok_or_else(|| bad_archive("no manifest"))?;
+        if manifest.format_version != FORMAT_VERSION {
+            return Err(bad_archive(format!(
+                "unsupported format version {} (expected {FORMAT_VERSION})",
+                manifest.format_version
+            )));
+        }
+        if manifest.kind != kind.as_str() || manifest.identity != id.as_str() {
+            return Err(bad_archive(format!(
+                "archive is for instance {:?}/{:?}, not {kind:?}/{id:?}",
+                manifest.kind, manifest.identity,
+            )));
+        }
+        let listed: HashSet<&str> = manifest.extras.iter().map(|s| s.as_str()).collect();
+        if listed.len() != self.extras.len()
+            || !self.extras.keys().all(|k| listed.contains(k.as_str()))
+        {
+            return Err(bad_archive("extra items don't match the manifest"));
+        }
+        Ok(())
+    }
+}
+
+impl StateDirectory {
+    /// Read and check an archive made by [`InstanceStateHandle::export_archive`],
+    /// without restoring it
+    ///
+    /// The archive must have been made for the same kind and identity as `identity`,
+    /// and with a format version we understand.
+    /// Every name in it is validated.
+    ///
+    /// This doesn't touch the state directory.
+    /// The caller can check the [`extras`](StateArchive::extras),
+    /// and then pass the archive to [`restore_archive`](StateDirectory::restore_archive).
+    pub fn read_archive<I: InstanceIdentity>(
+        &self,
+        identity: &I,
+        input: impl Read,
+    ) -> Result<StateArchive> {
+        self.with_instance_path_pieces(
+            I::kind(),
+            &|f| identity.write_identity(f),
+            |kind, id, resource| {
+                (|| {
+                    let contents = Contents::read(input)?;
+                    contents.check_manifest(kind, id)?;
+                    Ok::<_, ErrorSource>(StateArchive {
+                        contents,
+                        kind: kind.to_string(),
+                        id: id.to_string(),
+                    })
+                })()
+                .map_err(|source| Error::new(source, Action::Restoring, resource()))
+            },
+        )
+    }
+
+    /// Restore an instance's state from an archive read by
+    /// [`read_archive`](StateDirectory::read_archive)
+    ///
+    /// `identity` must be the one that the archive was read for.
+    /// The instance must not already have any state:
+    /// to replace existing state, [`purge`](InstanceStateHandle::purge) it first.
+    ///
+    /// The restored state is moved into place all at once,
+    /// so if this fails, the instance is left empty.
+    ///
+    /// On success, returns the (locked) instance.
+    pub fn restore_archive<I: InstanceIdentity>(
+        &self,
+        identity: &I,
+        archive: StateArchive,
+    ) -> Result<InstanceStateHandle> {
+        let kind_dir = self.with_instance_path_pieces(
+            I::kind(),
+            &|f| identity.write_identity(f),
+            |kind, id, resource| {
+                (|| {
+                    if archive.kind != kind.as_str() || archive.id != id.as_str() {
+                        return Err(tor_error::bad_api_usage!(
+                            "archive was read for {:?}/{:?}, not {kind:?}/{id:?}",
+                            archive.kind,
+                            archive.id,
+                        )
+                        .into());
+                    }
+                    Ok::<_, ErrorSource>(self.dir.make_secure_directory(kind)?)
+                })()
+                .map_err(|source| Error::new(source, Action::Restoring, resource()))
+            },
+        )?;
+
+        let handle = self.acquire_instance(identity)?;
+        handle
+            .restore_contents(&kind_dir, &archive.id, &archive.contents)
+            .map_err(|source| {
+                Error::new(
+                    source,
+                    Action::Restoring,
+                    Resource::Directory {
+                        dir: handle.dir.as_path().to_owned(),
+                    },
+                )
+            })?;
+        touch_instance_dir(&handle.dir)?;
+        Ok(handle)
+    }
+}
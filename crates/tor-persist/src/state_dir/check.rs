@@ -0,0 +1,210 @@
+//! Health checks for a state directory
+//!
+//! See [`StateDirectory::check_instances`].
+
+use super::*;
+
+use std::path::PathBuf;
+
+use tor_error::ErrorReport as _;
+
+use crate::check::is_temp_file;
+
+impl StateDirectory {
+    /// Check the health of every instance of kind `kind`
+    ///
+    /// This checks that:
+    ///  * every entry in the kind directory is a valid instance (or its lockfile),
+    ///  * there are no lockfiles for instances that don't exist,
+    ///  * every storage file in each instance can be parsed as JSON,
+    ///  * there are no stale temporary files, or other unexpected files.
+    ///
+    /// If `repair` is true, we also delete stale temporary files and
+    /// orphaned lockfiles.
+    /// We only make changes to an instance if we can lock it;
+    /// instances that are in use are only inspected.
+    ///
+    /// Problems with the state are returned in the [`CheckReport`];
+    /// an `Err` means we couldn't perform the check at all.
+    pub fn check_instances(&self, kind: &'static str, repair: bool) -> Result<CheckReport> {
+        let mut report = CheckReport::default();
+        let kind_path = self.dir.as_path().join(kind);
+
+        for id in self.list_instances_inner(kind) {
+            let id = id?;
+            self.with_instance_path_pieces(kind, &|f| write!(f, "{id}"), |kind, id, resource| {
+                self.check_instance_inner(kind, id, resource, repair, &mut report)
+            })?;
+        }
+
+        // list_instances_inner silently skips things that aren't instances;
+        // go back and complain about them.
+        if let Ok(ents) = self.dir.read_directory(kind) {
+            for ent in ents.flatten() {
+                let name = ent.file_name();
+                let is_instance = name
+                    .to_str()
+                    .map(|n| n.strip_suffix(DOT_LOCK).unwrap_or(n))
+                    .is_some_and(|n| SlugRef::new(n).is_ok());
+                if !is_instance {
+                    report.push(
+                        CheckSeverity::Warning,
+                        kind_path.join(name),
+                        "unexpected entry (not an instance or a lockfile)",
+                        false,
+                    );
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Check the health of a single instance
+    ///
+    /// Like [`check_instances`](StateDirectory::check_instances),
+    /// but only for `identity`.
+    pub fn check_instance<I: InstanceIdentity>(
+        &self,
+        identity: &I,
+        repair: bool,
+    ) -> Result<CheckReport> {
+        let mut report = CheckReport::default();
+        self.with_instance_path_pieces(
+            I::kind(),
+            &|f| identity.write_identity(f),
+            |kind, id, resource| self.check_instance_inner(kind, id, resource, repair, &mut report),
+        )?;
+        Ok(report)
+    }
+
+    /// Check one instance, adding any problems to `report`
+    fn check_instance_inner(
+        &self,
+        kind: &SlugRef,
+        id: &SlugRef,
+        resource: &dyn Fn() -> Resource,
+        repair: bool,
+        report: &mut CheckReport,
+    ) -> Result<()> {
+        let rel_dir = format!("{kind}{PATH_SEPARATOR}{id}");
+        let dir_path = self.dir.as_path().join(kind).join(id);
+        let lock_path = dir_path.with_extension(LOCK_EXTN);
+
+        let dir_exists = dir_path.exists();
+        if !dir_exists && !lock_path.exists() {
+            // Nothing to check.  (And we mustn't lock it: that would create a lockfile.)
+            return Ok(());
+        }
+
+        // We only try to take the lock if we might want to change something.
+        // Note that taking the lock doesn't update the instance's mtime.
+        let flock_guard = if repair {
+            match LockFileGuard::try_lock(&lock_path) {
+                Ok(Some(guard)) => Some(guard),
+                Ok(None) => {
+                    report.push(
+                        CheckSeverity::Info,
+                        dir_path.clone(),
+                        "instance is in use, so not repairing it",
+                        false,
+                    );
+                    None
+                }
+                Err(source) => return Err(Error::new(source, Action::Locking, resource())),
+            }
+        } else {
+            None
+        };
+
+        if !dir_exists {
+            // Recheck now we hold the lock, in case someone just created it.
+            let still_orphaned = !dir_path.exists();
+            let repaired = match flock_guard {
+                Some(guard) if still_orphaned => {
+                    guard
+                        .delete_lock_file(&lock_path)
+                        .map_err(|source| Error::new(source, Action::Deleting, resource()))?;
+                    true
+                }
+                _ => false,
+            };
+            if still_orphaned {
+                report.push(
+                    CheckSeverity::Warning,
+                    lock_path,
+                    "lockfile for an instance that doesn't exist",
+                    repaired,
+                );
+            }
+            return Ok(());
+        }
+
+        let ents = match self.dir.read_directory(&rel_dir) {
+            Ok(ents) => ents,
+            Err(e) => {
+                report.push(
+                    CheckSeverity::Error,
+                    dir_path,
+                    format!("cannot read instance directory: {}", e.report()),
+                    false,
+                );
+                return Ok(());
+            }
+        };
+
+        for ent in ents {
+            let ent = ent.map_err(|source| Error::new(source, Action::Enumerating, resource()))?;
+            let leaf = PathBuf::from(ent.file_name());
+            let path = dir_path.join(&leaf);
+            let rel_path = Path::new(&rel_dir).join(&leaf);
+            let is_dir = ent.file_type().map(|t| t.is_dir()).unwrap_or(false);
+
+            let stem_ok = |name: Option<&std::ffi::OsStr>| {
+                name.and_then(|n| n.to_str())
+                    .is_some_and(|n| SlugRef::new(n).is_ok())
+            };
+
+            if is_dir {
+                // A raw_subdir.  Its contents are up to whoever owns it.
+                if !stem_ok(leaf.file_name()) {
+                    report.push(CheckSeverity::Warning, path, "unexpected directory", false);
+                }
+                continue;
+            }
+
+            if !stem_ok(leaf.file_stem()) {
+                report.push(CheckSeverity::Warning, path, "unexpected file", false);
+            } else if is_temp_file(&leaf) {
+                let repaired = flock_guard.is_some() && self.dir.remove_file(&rel_path).is_ok();
+                report.push(
+                    CheckSeverity::Warning,
+                    path,
+                    "stale temporary file (from an interrupted write)",
+                    repaired,
+                );
+            } else if leaf.extension().and_then(|e| e.to_str()) == Some("json") {
+                let parsed = self
+                    .dir
+                    .read_to_string(&rel_path)
+                    .map_err(|e| e.report().to_string())
+                    .and_then(|text| {
+                        serde_json::from_str::<crate::JsonValue>(&text)
+                            .map_err(|e| e.report().to_string())
+                    });
+                if let Err(e) = parsed {
+                    report.push(
+                        CheckSeverity::Error,
+                        path,
+                        format!("cannot parse storage file: {e}"),
+                        false,
+                    );
+                }
+            } else {
+                report.push(CheckSeverity::Info, path, "unrecognised file", false);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+//! This is a wallclock time microbenchmark for relay cell cryptography,
+//! using the Criterion framework.
+//!
+//! It measures the client and relay sides of `tor1` relay crypto
+//! (AES-128-CTR and a running SHA-1 digest) on a 3-hop circuit.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use tor_cell::chancell::CELL_DATA_LEN;
+use tor_proto::bench_utils::{tor1_circuit, RelayBody};
+
+/// Seeds for the keys of each hop.
+const SEEDS: [&[u8]; 3] = [
+    b"hidden we are free",
+    b"free to speak, to free ourselves",
+    b"free to hide no more",
+];
+
+/// Return a relay cell body full of random bytes.
+fn random_cell(rng: &mut StdRng) -> RelayBody {
+    let mut body = Box::new([0_u8; CELL_DATA_LEN]);
+    rng.fill_bytes(&mut body[..]);
+    body.into()
+}
+
+fn relay_crypto_bench(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut group = c.benchmark_group("tor1");
+    group.throughput(Throughput::Bytes(CELL_DATA_LEN as u64));
+
+    // A client sending a cell to the last hop, which encrypts it once per hop.
+    let (mut client, _) = tor1_circuit(&SEEDS).unwrap();
+    group.bench_function("client-encrypt-3hop", |b| {
+        b.iter_batched_ref(
+            || random_cell(&mut rng),
+            |cell| client.encrypt(cell, 2.into()).unwrap(),
+            BatchSize::SmallInput,
+        );
+    });
+
+    // A relay in the middle of a circuit, passing a cell along.
+    let (_, mut relays) = tor1_circuit(&SEEDS).unwrap();
+    group.bench_function("relay-decrypt-outbound", |b| {
+        b.iter_batched_ref(
+            || random_cell(&mut rng),
+            |cell| relays[1].decrypt_outbound(cell),
+            BatchSize::SmallInput,
+        );
+    });
+
+    // A relay originating a cell to the client.
+    group.bench_function("relay-originate-inbound", |b| {
+        b.iter_batched_ref(
+            || random_cell(&mut rng),
+            |cell| {
+                relays[2].originate(cell);
+                relays[2].encrypt_inbound(cell);
+            },
+            BatchSize::SmallInput,
+        );
+    });
+
+    // A full round trip: the client sends a cell to the last hop,
+    // and the last hop sends one back.
+    let (mut client, mut relays) = tor1_circuit(&SEEDS).unwrap();
+    group.bench_function("roundtrip-3hop", |b| {
+        b.iter_batched_ref(
+            || random_cell(&mut rng),
+            |cell| {
+                client.encrypt(cell, 2.into()).unwrap();
+                for relay in relays.iter_mut() {
+                    if relay.decrypt_outbound(cell) {
+                        break;
+                    }
+                }
+                let last = relays.len() - 1;
+                relays[last].originate(cell);
+                for relay in relays.iter_mut().rev() {
+                    relay.encrypt_inbound(cell);
+                }
+                client.decrypt(cell).unwrap()
+            },
+            BatchSize::SmallInput,
+        );
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, relay_crypto_bench);
+criterion_main!(benches);
@@ -0,0 +1,23 @@
+//! Print the contents of a relay message capture, one message per line.
+//!
+//! Usage: `print-relay-capture FILE`
+//!
+//! Captures are written by attaching a `RelayMsgCapture` to a circuit with
+//! `ClientCirc::set_relay_msg_capture`.
+
+use std::fs::File;
+use std::io::{self, BufReader};
+
+use tor_proto::circuit::capture::CaptureReader;
+
+fn main() -> io::Result<()> {
+    let Some(path) = std::env::args_os().nth(1) else {
+        eprintln!("Usage: print-relay-capture FILE");
+        std::process::exit(1);
+    };
+    let reader = CaptureReader::new(BufReader::new(File::open(path)?))?;
+    for rec in reader {
+        println!("{}", rec?);
+    }
+    Ok(())
+}
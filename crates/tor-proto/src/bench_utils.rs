@@ -0,0 +1,93 @@
+//! Wrappers around our internal relay cell cryptography, for use in benchmarks.
+//!
+//! Nothing here is covered by semantic versioning.
+
+use tor_bytes::SecretBuf;
+use tor_cell::chancell::BoxedCellBody;
+use tor_cell::relaycell::RelayCellFormatV0;
+
+use crate::crypto::cell::{
+    ClientLayer as _, CryptInit as _, InboundClientCrypt, OutboundClientCrypt, RelayCellBody,
+    RelayCrypt as _, Tor1RelayCrypto,
+};
+use crate::crypto::handshake::ShakeKeyGenerator;
+use crate::{HopNum, Result};
+
+/// The body of a relay cell, as it is encrypted and decrypted.
+#[derive(Clone)]
+pub struct RelayBody(RelayCellBody);
+
+impl From<BoxedCellBody> for RelayBody {
+    fn from(body: BoxedCellBody) -> Self {
+        RelayBody(body.into())
+    }
+}
+
+impl AsRef<[u8]> for RelayBody {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+/// A client's relay cell cryptography for a whole circuit.
+pub struct ClientCircuitCrypt {
+    /// Layers for cells that the client sends.
+    outbound: OutboundClientCrypt,
+    /// Layers for cells that the client receives.
+    inbound: InboundClientCrypt,
+}
+
+/// A relay's relay cell cryptography for its hop on a circuit.
+pub struct RelayHopCrypt(Tor1RelayCrypto<RelayCellFormatV0>);
+
+/// Construct the client and relay states for a circuit using `tor1` relay crypto
+/// (AES-128-CTR and SHA-1), with one hop for each of `seeds`.
+///
+/// The keys for each hop are derived from its seed using SHAKE-256.
+pub fn tor1_circuit(seeds: &[&[u8]]) -> Result<(ClientCircuitCrypt, Vec<RelayHopCrypt>)> {
+    let mut client = ClientCircuitCrypt {
+        outbound: OutboundClientCrypt::new(),
+        inbound: InboundClientCrypt::new(),
+    };
+    let mut relays = Vec::new();
+    for seed in seeds {
+        let keygen = || ShakeKeyGenerator::new(SecretBuf::from(seed.to_vec()));
+        let pair = Tor1RelayCrypto::<RelayCellFormatV0>::construct(keygen())?;
+        let (outbound, inbound, _) = pair.split();
+        client.outbound.add_layer(Box::new(outbound));
+        client.inbound.add_layer(Box::new(inbound));
+        relays.push(RelayHopCrypt(Tor1RelayCrypto::construct(keygen())?));
+    }
+    Ok((client, relays))
+}
+
+impl ClientCircuitCrypt {
+    /// Prepare `cell` for the hop `hop`, and encrypt it for every hop up to that one.
+    pub fn encrypt(&mut self, cell: &mut RelayBody, hop: HopNum) -> Result<()> {
+        self.outbound.encrypt(&mut cell.0, hop).map(|_| ())
+    }
+
+    /// Decrypt `cell`, and return the hop that originated it.
+    pub fn decrypt(&mut self, cell: &mut RelayBody) -> Result<HopNum> {
+        self.inbound.decrypt(&mut cell.0).map(|(hop, _)| hop)
+    }
+}
+
+impl RelayHopCrypt {
+    /// Prepare `cell` to be sent towards the client.
+    pub fn originate(&mut self, cell: &mut RelayBody) {
+        self.0.originate(&mut cell.0);
+    }
+
+    /// Encrypt `cell` as it moves towards the client.
+    pub fn encrypt_inbound(&mut self, cell: &mut RelayBody) {
+        self.0.encrypt_inbound(&mut cell.0);
+    }
+
+    /// Decrypt `cell` as it moves away from the client.
+    ///
+    /// Return true if it is addressed to this hop.
+    pub fn decrypt_outbound(&mut self, cell: &mut RelayBody) -> bool {
+        self.0.decrypt_outbound(&mut cell.0)
+    }
+}
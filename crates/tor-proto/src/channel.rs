@@ -59,11 +59,14 @@ pub const CHANNEL_BUFFER_SIZE: usize = 128;
 mod circmap;
 mod codec;
 mod handshake;
+pub mod liveness;
 pub mod padding;
 pub mod params;
 mod reactor;
 mod unique_id;
 
+use crate::channel::codec::ChannelTraffic;
+pub use crate::channel::liveness::ChannelHealth;
 pub use crate::channel::params::*;
 use crate::channel::reactor::{BoxedChannelSink, BoxedChannelStream, Reactor};
 pub use crate::channel::unique_id::UniqId;
@@ -113,7 +116,7 @@ use tracing::trace;
 
 // reexport
 use crate::channel::unique_id::CircUniqIdContext;
-#[cfg(test)]
+#[cfg(any(test, feature = "testing"))]
 pub(crate) use codec::CodecError;
 pub use handshake::{OutboundClientHandshake, UnverifiedChannel, VerifiedChannel};
 
@@ -198,6 +201,8 @@ pub struct Channel {
     opened_at: coarsetime::Instant,
     /// Mutable state used by the `Channel.
     mutable: Mutex<MutableDetails>,
+    /// The number of bytes of cells that this channel has sent and received.
+    traffic: Arc<ChannelTraffic>,
 
     /// Information shared with the reactor
     details: Arc<ChannelDetails>,
@@ -228,6 +233,11 @@ pub(crate) struct ChannelDetails {
     /// Set by reactor when a circuit is added or removed.
     /// Read from `Channel::duration_unused`.
     unused_since: AtomicOptTimestamp,
+    /// Whether the peer is responding on this channel.
+    ///
+    /// Set by the reactor, according to its liveness monitor.
+    /// Read from `Channel::health`.
+    health: liveness::AtomicChannelHealth,
 }
 
 /// Mutable details (state) used by the `Channel` (frontend)
@@ -424,10 +434,11 @@ impl Channel {
     /// Internal method, called to finalize the channel when we've
     /// sent our netinfo cell, received the peer's netinfo cell, and
     /// we're finally ready to create circuits.
-    fn new<S>(
+    pub(crate) fn new<S>(
         link_protocol: u16,
         sink: BoxedChannelSink,
         stream: BoxedChannelStream,
+        traffic: Arc<ChannelTraffic>,
         unique_id: UniqId,
         peer_id: OwnedChanTarget,
         clock_skew: ClockSkew,
@@ -453,6 +464,7 @@ impl Channel {
             closed,
             unused_since,
             reactor_closed_rx,
+            health: Default::default(),
         };
         let details = Arc::new(details);
 
@@ -464,11 +476,17 @@ impl Channel {
             clock_skew,
             opened_at: coarsetime::Instant::now(),
             mutable: Mutex::new(mutable),
+            traffic,
             details: Arc::clone(&details),
         });
 
         // We start disabled; the channel manager will `reconfigure` us soon after creation.
-        let padding_timer = Box::pin(padding::Timer::new_disabled(sleep_prov, None));
+        let mut padding_timer = Box::pin(padding::Timer::new_disabled(sleep_prov.clone(), None));
+        padding_timer
+            .as_mut()
+            .set_coalescing(padding::PADDING_COALESCING);
+        // Likewise, we don't do keepalives until we are told how.
+        let liveness = liveness::Monitor::new_disabled(sleep_prov);
 
         let reactor = Reactor {
             control: control_rx,
@@ -483,6 +501,7 @@ impl Channel {
             details,
             padding_timer,
             special_outgoing: Default::default(),
+            liveness,
         };
 
         (channel, reactor)
@@ -504,6 +523,22 @@ impl Channel {
         self.opened_at.elapsed().into()
     }
 
+    /// Return the number of bytes of cells that we have sent on this channel.
+    ///
+    /// This counts every cell after the `VERSIONS` cell, including the
+    /// rest of the handshake, but not the TLS overhead.
+    pub fn bytes_sent(&self) -> u64 {
+        self.traffic.sent()
+    }
+
+    /// Return the number of bytes of cells that we have received on this channel.
+    ///
+    /// This counts every cell after the `VERSIONS` cell, including the
+    /// rest of the handshake, but not the TLS overhead.
+    pub fn bytes_received(&self) -> u64 {
+        self.traffic.received()
+    }
+
     /// Return a ClockSkew declaring how much clock skew the other side of this channel
     /// claimed that we had when we negotiated the connection.
     pub fn clock_skew(&self) -> ClockSkew {
@@ -553,6 +588,8 @@ impl Channel {
                 if params.padding_negotiate == Some(PaddingNegotiate::start_default()) {
                     params.padding_negotiate = None;
                 }
+                // We passed on any liveness parameters when we were given them.
+                params.liveness = None;
 
                 match self.send_control(CtrlMsg::ConfigUpdate(Arc::new(params))) {
                     Ok(()) => {}
@@ -585,6 +622,14 @@ impl Channel {
             }
             PCS::UsageDoesNotImplyPadding { padding_params } => {
                 padding_params.combine(&params);
+                // Keepalives, and dead channel detection, are wanted whatever the usage.
+                if let Some(liveness) = params.liveness {
+                    let params = ChannelPaddingInstructionsUpdates {
+                        liveness: Some(liveness),
+                        ..Default::default()
+                    };
+                    self.send_control(CtrlMsg::ConfigUpdate(Arc::new(params)))?;
+                }
             }
         }
 
@@ -603,6 +648,15 @@ impl Channel {
         self.details.closed.load(Ordering::SeqCst)
     }
 
+    /// Return whether the peer is responding on this channel.
+    ///
+    /// See [`liveness`] for how we decide.
+    /// An [`Unresponsive`](ChannelHealth::Unresponsive) channel
+    /// should not be used for new circuits, although it may yet recover.
+    pub fn health(&self) -> ChannelHealth {
+        self.details.health.load()
+    }
+
     /// If the channel is not in use, return the amount of time
     /// it has had with no circuits.
     ///
@@ -673,6 +727,14 @@ impl Channel {
         let _ = self.send_control(CtrlMsg::Shutdown);
     }
 
+    /// Tell the reactor that a circuit is about to send a `RELAY*` cell on
+    /// this channel which is sure to get a response.
+    ///
+    /// See [`liveness`].
+    pub(crate) fn note_response_expected(&self) -> StdResult<(), ChannelClosed> {
+        self.send_control(CtrlMsg::ExpectResponse)
+    }
+
     /// Tell the reactor that the circuit with the given ID has gone away.
     pub fn close_circuit(&self, circid: CircId) -> Result<()> {
         self.send_control(CtrlMsg::CloseCircuit(circid))?;
@@ -721,6 +783,7 @@ impl Channel {
             clock_skew: ClockSkew::None,
             opened_at: coarsetime::Instant::now(),
             mutable: Default::default(),
+            traffic: Default::default(),
             details,
         };
         (channel, control_recv)
@@ -778,6 +841,7 @@ fn fake_channel_details() -> Arc<ChannelDetails> {
         closed: AtomicBool::new(false),
         reactor_closed_rx: rx.shared(),
         unused_since,
+        health: Default::default(),
     })
 }
 
@@ -809,6 +873,7 @@ pub(crate) mod test {
             clock_skew: ClockSkew::None,
             opened_at: coarsetime::Instant::now(),
             mutable: Default::default(),
+            traffic: Default::default(),
             details,
         }
     }
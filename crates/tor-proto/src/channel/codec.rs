@@ -1,5 +1,7 @@
 //! Wrap tor_cell::...:::ChannelCodec for use with the futures_codec
 //! crate.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::{io::Error as IoError, marker::PhantomData};
 
 use futures::{AsyncRead, AsyncWrite};
@@ -28,6 +30,30 @@ pub(crate) enum CodecError {
     EncCell(#[source] tor_cell::Error),
 }
 
+/// The number of bytes that a [`ChannelCodec`] has encoded and decoded.
+///
+/// This is shared between the codec and the channel that uses it,
+/// so that the channel can report how much traffic it has carried.
+#[derive(Debug, Default)]
+pub(crate) struct ChannelTraffic {
+    /// The number of bytes of cells that we have encoded to send.
+    sent: AtomicU64,
+    /// The number of bytes of cells that we have decoded after receiving them.
+    received: AtomicU64,
+}
+
+impl ChannelTraffic {
+    /// Return the number of bytes of cells that we have sent.
+    pub(crate) fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of bytes of cells that we have received.
+    pub(crate) fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+}
+
 /// Asynchronous wrapper around ChannelCodec in tor_cell, with implementation
 /// for use with futures_codec.
 ///
@@ -46,6 +72,8 @@ pub(crate) struct ChannelCodec<IN, OUT> {
     /// Tells the compiler that we're using OUT, and we might
     /// produce values of type OUT.
     _phantom_out: PhantomData<fn() -> OUT>,
+    /// The number of bytes that we have encoded and decoded.
+    traffic: Arc<ChannelTraffic>,
 }
 
 impl<IN, OUT> ChannelCodec<IN, OUT> {
@@ -55,18 +83,27 @@ impl<IN, OUT> ChannelCodec<IN, OUT> {
             inner: codec::ChannelCodec::new(link_proto),
             _phantom_in: PhantomData,
             _phantom_out: PhantomData,
+            traffic: Default::default(),
         }
     }
 
     /// Consume this codec, and return a new one that sends and receives
     /// different message types.
+    ///
+    /// The new codec keeps counting traffic in the same [`ChannelTraffic`].
     pub(crate) fn change_message_types<IN2, OUT2>(self) -> ChannelCodec<IN2, OUT2> {
         ChannelCodec {
             inner: self.inner,
             _phantom_in: PhantomData,
             _phantom_out: PhantomData,
+            traffic: self.traffic,
         }
     }
+
+    /// Return the counts of the bytes that this codec has encoded and decoded.
+    pub(crate) fn traffic(&self) -> &Arc<ChannelTraffic> {
+        &self.traffic
+    }
 }
 
 impl<IN, OUT> futures_codec::Encoder for ChannelCodec<IN, OUT>
@@ -77,9 +114,14 @@ where
     type Error = CodecError;
 
     fn encode(&mut self, item: Self::Item<'_>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let len_before = dst.len();
         self.inner
             .write_cell(item, dst)
             .map_err(CodecError::EncCell)?;
+        let n_bytes = dst.len().saturating_sub(len_before);
+        self.traffic
+            .sent
+            .fetch_add(n_bytes as u64, Ordering::Relaxed);
         Ok(())
     }
 }
@@ -92,7 +134,13 @@ where
     type Error = CodecError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        self.inner.decode_cell(src).map_err(CodecError::DecCell)
+        let len_before = src.len();
+        let cell = self.inner.decode_cell(src).map_err(CodecError::DecCell)?;
+        let n_bytes = len_before.saturating_sub(src.len());
+        self.traffic
+            .received
+            .fetch_add(n_bytes as u64, Ordering::Relaxed);
+        Ok(cell)
     }
 }
 
@@ -126,7 +174,8 @@ pub(crate) mod test {
     use std::pin::Pin;
     use tor_cell::chancell::msg::AnyChanMsg;
 
-    use super::{futures_codec, ChannelCodec};
+    use super::{futures_codec, ChannelCodec, ChannelTraffic};
+    use std::sync::Arc;
     use tor_cell::chancell::{msg, AnyChanCell, ChanCmd, ChanMsg, CircId};
 
     /// Helper type for reading and writing bytes to/from buffers.
@@ -185,15 +234,20 @@ pub(crate) mod test {
 
     fn frame_buf(
         mbuf: MsgBuf,
-    ) -> futures_codec::Framed<MsgBuf, ChannelCodec<AnyChanMsg, AnyChanMsg>> {
-        futures_codec::Framed::new(mbuf, ChannelCodec::new(4))
+    ) -> (
+        futures_codec::Framed<MsgBuf, ChannelCodec<AnyChanMsg, AnyChanMsg>>,
+        Arc<ChannelTraffic>,
+    ) {
+        let codec = ChannelCodec::new(4);
+        let traffic = Arc::clone(codec.traffic());
+        (futures_codec::Framed::new(mbuf, codec), traffic)
     }
 
     #[test]
     fn check_encoding() {
         tor_rtcompat::test_with_all_runtimes!(|_rt| async move {
             let mb = MsgBuf::new(&b""[..]);
-            let mut framed = frame_buf(mb);
+            let (mut framed, traffic) = frame_buf(mb);
 
             let destroycell = msg::Destroy::new(2.into());
             framed
@@ -208,6 +262,8 @@ pub(crate) mod test {
                 .unwrap();
 
             framed.flush().await.unwrap();
+            assert_eq!(traffic.sent(), 514 + 8);
+            assert_eq!(traffic.received(), 0);
 
             let data = framed.into_inner().into_response();
 
@@ -225,7 +281,7 @@ pub(crate) mod test {
             dat.resize(514, 0);
             dat.extend_from_slice(&hex!("00000000 81 0001 00")[..]);
             let mb = MsgBuf::new(&dat[..]);
-            let mut framed = frame_buf(mb);
+            let (mut framed, traffic) = frame_buf(mb);
 
             let destroy = framed.next().await.unwrap().unwrap();
             let nocerts = framed.next().await.unwrap().unwrap();
@@ -235,6 +291,8 @@ pub(crate) mod test {
             assert_eq!(nocerts.circid(), None);
             assert_eq!(nocerts.msg().cmd(), ChanCmd::CERTS);
 
+            assert_eq!(traffic.received(), 514 + 8);
+            assert_eq!(traffic.sent(), 0);
             assert!(framed.into_inner().all_consumed());
         });
     }
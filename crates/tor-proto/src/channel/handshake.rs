@@ -7,7 +7,7 @@ use futures::stream::StreamExt;
 use tor_cell::restricted_msg;
 use tor_error::internal;
 
-use crate::channel::codec::{self, ChannelCodec, CodecError};
+use crate::channel::codec::{self, ChannelCodec, ChannelTraffic, CodecError};
 use crate::channel::UniqId;
 use crate::util::skew::ClockSkew;
 use crate::{Error, Result};
@@ -64,6 +64,8 @@ pub struct UnverifiedChannel<T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
     link_protocol: u16,
     /// The Source+Sink on which we're reading and writing cells.
     tls: CellFrame<T>,
+    /// The number of bytes that `tls` has sent and received.
+    traffic: Arc<ChannelTraffic>,
     /// The certs cell that we got from the relay.
     certs_cell: msg::Certs,
     /// Declared target method for this channel, if any.
@@ -94,6 +96,8 @@ pub struct VerifiedChannel<T: AsyncRead + AsyncWrite + Send + Unpin + 'static, S
     link_protocol: u16,
     /// The Source+Sink on which we're reading and writing cells.
     tls: CellFrame<T>,
+    /// The number of bytes that `tls` has sent and received.
+    traffic: Arc<ChannelTraffic>,
     /// Declared target method for this stream, if any.
     target_method: Option<ChannelMethod>,
     /// Logging identifier for this stream.  (Used for logging only.)
@@ -235,6 +239,7 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static, S: SleepProvider>
         // AsyncRead/AsyncWrite aspects of the tls, and just treat it
         // as a stream and a sink for cells.
         let codec = ChannelCodec::<HandshakeMsg, HandshakeMsg>::new(link_protocol);
+        let traffic = Arc::clone(codec.traffic());
         let mut tls = futures_codec::Framed::new(self.tls, codec);
 
         // Read until we have the netinfo cells.
@@ -301,6 +306,7 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static, S: SleepProvider>
                 Ok(UnverifiedChannel {
                     link_protocol,
                     tls: codec::change_message_types(tls),
+                    traffic,
                     certs_cell,
                     netinfo_cell,
                     clock_skew,
@@ -556,6 +562,7 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static, S: SleepProvider> Unver
         Ok(VerifiedChannel {
             link_protocol: self.link_protocol,
             tls: self.tls,
+            traffic: self.traffic,
             unique_id: self.unique_id,
             target_method: self.target_method,
             ed25519_id: *identity_key,
@@ -623,6 +630,7 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static, S: SleepProvider> Verif
             self.link_protocol,
             Box::new(tls_sink),
             Box::new(tls_stream),
+            self.traffic,
             self.unique_id,
             peer_id,
             self.clock_skew,
@@ -851,6 +859,7 @@ pub(super) mod test {
         UnverifiedChannel {
             link_protocol: 4,
             tls: futures_codec::Framed::new(MsgBuf::new(&b""[..]), ChannelCodec::new(4)),
+            traffic: Default::default(),
             certs_cell: certs,
             netinfo_cell,
             clock_skew,
@@ -1112,6 +1121,7 @@ pub(super) mod test {
             let ver = VerifiedChannel {
                 link_protocol: 4,
                 tls: futures_codec::Framed::new(MsgBuf::new(&b""[..]), ChannelCodec::new(4)),
+                traffic: Default::default(),
                 unique_id: UniqId::new(),
                 target_method: Some(ChannelMethod::Direct(vec![peer_addr])),
                 ed25519_id,
@@ -0,0 +1,515 @@
+//! Channel keepalive, and detection of dead channels
+//!
+//! # Keepalive
+//!
+//! A channel that we want to keep open may go for a long time without
+//! carrying any cells, and some middleboxes (and relays) close connections
+//! that look idle.  So whenever we have sent nothing on a channel for the
+//! configured keepalive interval, we send a `PADDING` cell on it.
+//!
+//! # Dead channels
+//!
+//! A channel can stop working without the TCP connection underneath it
+//! noticing for a long time: for example, if a NAT box on the way has
+//! forgotten about it.  So whenever we send a cell that is sure to get a
+//! reply, we start a clock, which any cell from the relay stops.
+//!
+//! The cells that are sure to get a reply are `CREATE*` cells,
+//! and those `RELAY*` cells whose relay command always gets an answer
+//! (such as `BEGIN` or `EXTEND2`: see [`relay_cmd_expects_response`]).
+//! We can't see the relay command of a `RELAY*` cell here, since it is
+//! encrypted, so the circuit reactor tells the channel reactor about these
+//! using [`Monitor::note_response_expected`].
+//! Other cells, such as `RELAY_DATA` on a stream that is only sending,
+//! may go unanswered for as long as the other side likes.
+//!
+//!  * If the clock runs for half of the dead-channel timeout,
+//!    the channel is [`Unresponsive`](ChannelHealth::Unresponsive):
+//!    the channel manager stops handing it out for new circuits,
+//!    but it may yet recover.
+//!  * If the clock runs for the whole timeout,
+//!    the channel is [`Dead`](ChannelHealth::Dead), and the reactor closes it.
+//!
+//! Note that a `PADDING` cell of our own doesn't start the clock,
+//! since the relay needn't respond to it.
+//!
+//! Dead-channel detection is off unless a timeout is configured.
+//!
+//! The parameters are distributed along with the padding instructions:
+//! see [`ChannelPaddingInstructions`](crate::channel::ChannelPaddingInstructions).
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU8, Ordering};
+// TODO, coarsetime maybe?  But see arti#496 and also we want to use the mockable SleepProvider
+use std::time::{Duration, Instant};
+
+use derive_builder::Builder;
+use futures::future::{self, FusedFuture};
+use futures::FutureExt;
+
+use tor_cell::chancell::{msg::AnyChanMsg, ChanCmd, ChanMsg};
+use tor_cell::relaycell::RelayCmd;
+use tor_config::impl_standard_builder;
+use tor_rtcompat::SleepProvider;
+
+/// Parameters for channel keepalive and dead-channel detection
+///
+/// `None` for either parameter disables the corresponding behaviour.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Builder)]
+#[builder(build_fn(error = "tor_error::Bug"))]
+pub struct Parameters {
+    /// How long we may go without sending anything before we send a keepalive
+    ///
+    /// (Since we only check for idleness once per interval,
+    /// a channel may be idle for up to twice this long before we send one.)
+    #[builder(default)]
+    pub(crate) keepalive_interval: Option<Duration>,
+    /// How long we wait for a response before we declare a channel dead
+    #[builder(default)]
+    pub(crate) dead_after: Option<Duration>,
+}
+
+impl_standard_builder! { Parameters: !Deserialize + !Builder + !Default }
+
+impl Parameters {
+    /// Make a Parameters which neither sends keepalives nor detects dead channels
+    pub fn disabled() -> Self {
+        Parameters {
+            keepalive_interval: None,
+            dead_after: None,
+        }
+    }
+}
+
+/// The health of a channel, as judged by whether the relay is responding on it
+///
+/// See the [module documentation](self).
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+#[non_exhaustive]
+pub enum ChannelHealth {
+    /// The relay has responded to everything that it ought to have,
+    /// or has not yet had long to do so.
+    Healthy = 0,
+    /// The relay has not responded for more than half the dead-channel timeout.
+    Unresponsive = 1,
+    /// The relay did not respond within the dead-channel timeout,
+    /// so we have closed the channel.
+    Dead = 2,
+}
+
+/// A [`ChannelHealth`] that can be shared between the reactor and the frontend
+#[derive(Debug, Default)]
+pub(crate) struct AtomicChannelHealth(AtomicU8);
+
+impl AtomicChannelHealth {
+    /// Return the current health.
+    pub(crate) fn load(&self) -> ChannelHealth {
+        match self.0.load(Ordering::Relaxed) {
+            0 => ChannelHealth::Healthy,
+            1 => ChannelHealth::Unresponsive,
+            _ => ChannelHealth::Dead,
+        }
+    }
+
+    /// Set the current health.
+    pub(crate) fn store(&self, health: ChannelHealth) {
+        self.0.store(health as u8, Ordering::Relaxed);
+    }
+}
+
+/// Something that the reactor must do, as instructed by a [`Monitor`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum Action {
+    /// Send a `PADDING` cell, as a keepalive
+    ///
+    /// The caller should pass this cell to `note_cell_sent` as usual.
+    SendKeepalive,
+    /// The channel has become unresponsive
+    Unresponsive,
+    /// The channel is dead, and must be closed
+    Dead {
+        /// How long we have been waiting for the relay to respond
+        waited: Duration,
+    },
+}
+
+/// Keepalive and dead-channel timer for a channel reactor
+///
+/// Use [`next()`](Monitor::next) to find out when the reactor must do something,
+/// and tell the `Monitor` about each cell sent and received.
+///
+/// A `Monitor` with disabled parameters never asks the reactor to do anything.
+pub(crate) struct Monitor<R: SleepProvider> {
+    /// [`SleepProvider`]
+    sleep_prov: R,
+    /// Our parameters
+    parameters: Parameters,
+    /// When we will next check whether we should send a keepalive
+    ///
+    /// `None` if we haven't worked that out yet since we were (re)configured.
+    keepalive_check_at: Option<Instant>,
+    /// Whether we have sent a cell since the last keepalive check
+    sent_since_check: bool,
+    /// Whether we have asked for a keepalive, and haven't seen it sent yet
+    keepalive_pending: bool,
+    /// Since when we have been waiting for a response from the relay
+    ///
+    /// `None` if we have received a cell since we last sent one expecting a response.
+    awaiting_since: Option<Instant>,
+    /// The health of the channel, as we last reported it
+    health: ChannelHealth,
+    /// A sleep future, and the time at which it will complete
+    ///
+    /// This is never later than the time at which we next need to act,
+    /// but it may be earlier: when it fires, we recalculate.
+    waker: Option<(Instant, Pin<Box<R::SleepFuture>>)>,
+}
+
+impl<R: SleepProvider> Monitor<R> {
+    /// Create a new `Monitor`, which starts out disabled
+    pub(crate) fn new_disabled(sleep_prov: R) -> Self {
+        Monitor {
+            sleep_prov,
+            parameters: Parameters::disabled(),
+            keepalive_check_at: None,
+            sent_since_check: false,
+            keepalive_pending: false,
+            awaiting_since: None,
+            health: ChannelHealth::Healthy,
+            waker: None,
+        }
+    }
+
+    /// Set this `Monitor`'s parameters
+    pub(crate) fn reconfigure(&mut self, parameters: &Parameters) {
+        self.parameters = *parameters;
+        self.keepalive_check_at = None;
+        self.waker = None;
+    }
+
+    /// Note that `msg` is about to be sent
+    pub(crate) fn note_cell_sent(&mut self, msg: &AnyChanMsg) {
+        let cmd = msg.cmd();
+        if cmd == ChanCmd::PADDING && self.keepalive_pending {
+            // This is our own keepalive: it shouldn't put off the next one.
+            self.keepalive_pending = false;
+            return;
+        }
+        self.sent_since_check = true;
+
+        if expects_response(cmd) {
+            self.note_response_expected();
+        }
+    }
+
+    /// Note that we are sending a cell that is sure to get a response
+    ///
+    /// This starts the dead-channel clock, unless it is already running.
+    pub(crate) fn note_response_expected(&mut self) {
+        if self.awaiting_since.is_some() {
+            return;
+        }
+        let now = self.sleep_prov.now();
+        self.awaiting_since = Some(now);
+        if let Some(deadline) = self.health_deadline() {
+            if matches!(self.waker, Some((at, _)) if at > deadline) {
+                self.waker = None;
+            }
+        }
+    }
+
+    /// Note that we have received a cell
+    ///
+    /// Returns `true` if the channel was unresponsive, and is now healthy again.
+    pub(crate) fn note_cell_received(&mut self) -> bool {
+        self.awaiting_since = None;
+        if self.health == ChannelHealth::Unresponsive {
+            self.health = ChannelHealth::Healthy;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Return the time at which our health will next change, if nothing arrives
+    fn health_deadline(&self) -> Option<Instant> {
+        let since = self.awaiting_since?;
+        let dead_after = self.parameters.dead_after?;
+        match self.health {
+            ChannelHealth::Healthy => since.checked_add(dead_after / 2),
+            ChannelHealth::Unresponsive => since.checked_add(dead_after),
+            ChannelHealth::Dead => None,
+        }
+    }
+
+    /// Decide whether the reactor must act now; if not, return when we should next check
+    fn check(&mut self, now: Instant) -> Result<Action, Option<Instant>> {
+        if let (Some(since), Some(dead_after)) = (self.awaiting_since, self.parameters.dead_after) {
+            let waited = now.saturating_duration_since(since);
+            if waited >= dead_after {
+                self.health = ChannelHealth::Dead;
+                return Ok(Action::Dead { waited });
+            }
+            if waited >= dead_after / 2 && self.health == ChannelHealth::Healthy {
+                self.health = ChannelHealth::Unresponsive;
+                return Ok(Action::Unresponsive);
+            }
+        }
+
+        if let Some(interval) = self.parameters.keepalive_interval {
+            let check_at = *self
+                .keepalive_check_at
+                .get_or_insert_with(|| now.checked_add(interval).unwrap_or(now));
+            if now >= check_at {
+                self.keepalive_check_at = now.checked_add(interval);
+                if !std::mem::take(&mut self.sent_since_check) {
+                    self.keepalive_pending = true;
+                    return Ok(Action::SendKeepalive);
+                }
+            }
+        }
+
+        Err(match (self.keepalive_check_at, self.health_deadline()) {
+            (Some(a), Some(b)) => Some(std::cmp::min(a, b)),
+            (a, b) => a.or(b),
+        })
+    }
+
+    /// Wait until the reactor must do something, and return what
+    ///
+    /// The returned future is async-cancel-safe,
+    /// but once it yields, the action must actually be taken.
+    pub(crate) fn next(&mut self) -> impl FusedFuture<Output = Action> + '_ {
+        self.next_inner().fuse()
+    }
+
+    /// Wait until the reactor must do something (not `FusedFuture`)
+    async fn next_inner(&mut self) -> Action {
+        loop {
+            if let Some((_, waker)) = &mut self.waker {
+                waker.await;
+                // This sleep is used up; when we go round again we will make a new one.
+                self.waker = None;
+            }
+
+            let now = self.sleep_prov.now();
+            match self.check(now) {
+                Ok(action) => return action,
+                Err(None) => future::pending().await,
+                Err(Some(at)) => {
+                    let sleep = self.sleep_prov.sleep(at.saturating_duration_since(now));
+                    self.waker = Some((at, Box::pin(sleep)));
+                }
+            }
+        }
+    }
+}
+
+/// Return true if a cell with command `cmd` is sure to get a response from the relay
+///
+/// `RELAY*` cells are not included: see [`relay_cmd_expects_response`].
+fn expects_response(cmd: ChanCmd) -> bool {
+    [ChanCmd::CREATE, ChanCmd::CREATE_FAST, ChanCmd::CREATE2].contains(&cmd)
+}
+
+/// Return true if a relay message with command `cmd` is sure to get a response
+///
+/// (Not necessarily an immediate, or a direct, response:
+/// a relay may need to hear from the rest of the circuit first.)
+pub(crate) fn relay_cmd_expects_response(cmd: RelayCmd) -> bool {
+    [
+        RelayCmd::BEGIN,
+        RelayCmd::BEGIN_DIR,
+        RelayCmd::RESOLVE,
+        RelayCmd::EXTEND,
+        RelayCmd::EXTEND2,
+        RelayCmd::ESTABLISH_INTRO,
+        RelayCmd::ESTABLISH_RENDEZVOUS,
+        RelayCmd::INTRODUCE1,
+    ]
+    .contains(&cmd)
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+    use futures::future::ready;
+    use futures::select_biased;
+    use tor_cell::chancell::msg::{self, HandshakeType};
+    use tor_rtmock::MockRuntime;
+
+    /// Return the action `monitor` wants taken right now, if any.
+    async fn ready_action<R: SleepProvider>(monitor: &mut Monitor<R>) -> Option<Action> {
+        select_biased! {
+            a = monitor.next() => Some(a),
+            _ = ready(()) => None,
+        }
+    }
+
+    /// Return a relay cell.
+    ///
+    /// Whether it expects a response depends on what the circuit reactor says.
+    fn relay_cell() -> AnyChanMsg {
+        msg::Relay::new(b"hello").into()
+    }
+
+    /// Send a relay cell that expects a response.
+    fn send_answerable(monitor: &mut Monitor<impl SleepProvider>) {
+        monitor.note_response_expected();
+        monitor.note_cell_sent(&relay_cell());
+    }
+
+    #[test]
+    fn keepalive() {
+        MockRuntime::test_with_various(|rt| async move {
+            let mut monitor = Monitor::new_disabled(rt.clone());
+            assert_eq!(ready_action(&mut monitor).await, None);
+
+            let params = Parameters::builder()
+                .keepalive_interval(Some(Duration::from_secs(60)))
+                .build()
+                .unwrap();
+            monitor.reconfigure(&params);
+            assert_eq!(ready_action(&mut monitor).await, None);
+
+            // Nothing has been sent: we need a keepalive.
+            rt.advance_by(Duration::from_secs(60)).await;
+            assert_eq!(
+                ready_action(&mut monitor).await,
+                Some(Action::SendKeepalive)
+            );
+            monitor.note_cell_sent(&msg::Padding::new().into());
+            assert_eq!(ready_action(&mut monitor).await, None);
+
+            // The keepalive doesn't count as traffic, so we send another.
+            rt.advance_by(Duration::from_secs(60)).await;
+            assert_eq!(
+                ready_action(&mut monitor).await,
+                Some(Action::SendKeepalive)
+            );
+            monitor.note_cell_sent(&msg::Padding::new().into());
+
+            // But other cells do.
+            rt.advance_by(Duration::from_secs(30)).await;
+            monitor.note_cell_sent(&msg::Padding::new().into());
+            rt.advance_by(Duration::from_secs(30)).await;
+            assert_eq!(ready_action(&mut monitor).await, None);
+            rt.advance_by(Duration::from_secs(60)).await;
+            assert_eq!(
+                ready_action(&mut monitor).await,
+                Some(Action::SendKeepalive)
+            );
+        });
+    }
+
+    #[test]
+    fn dead_channel() {
+        MockRuntime::test_with_various(|rt| async move {
+            let mut monitor = Monitor::new_disabled(rt.clone());
+            let params = Parameters::builder()
+                .dead_after(Some(Duration::from_secs(60)))
+                .build()
+                .unwrap();
+            monitor.reconfigure(&params);
+
+            // Padding doesn't expect a response.
+            monitor.note_cell_sent(&msg::Padding::new().into());
+            rt.advance_by(Duration::from_secs(120)).await;
+            assert_eq!(ready_action(&mut monitor).await, None);
+
+            // A relay cell that must be answered does; an answer keeps us healthy.
+            send_answerable(&mut monitor);
+            rt.advance_by(Duration::from_secs(20)).await;
+            assert_eq!(ready_action(&mut monitor).await, None);
+            assert!(!monitor.note_cell_received());
+            rt.advance_by(Duration::from_secs(120)).await;
+            assert_eq!(ready_action(&mut monitor).await, None);
+
+            // Unresponsive, and then recovered.
+            send_answerable(&mut monitor);
+            rt.advance_by(Duration::from_secs(30)).await;
+            assert_eq!(ready_action(&mut monitor).await, Some(Action::Unresponsive));
+            assert_eq!(ready_action(&mut monitor).await, None);
+            assert!(monitor.note_cell_received());
+
+            // Unresponsive, and then dead.
+            send_answerable(&mut monitor);
+            rt.advance_by(Duration::from_secs(10)).await;
+            // Sending more doesn't restart the clock.
+            send_answerable(&mut monitor);
+            rt.advance_by(Duration::from_secs(20)).await;
+            assert_eq!(ready_action(&mut monitor).await, Some(Action::Unresponsive));
+            rt.advance_by(Duration::from_secs(30)).await;
+            assert_eq!(
+                ready_action(&mut monitor).await,
+                Some(Action::Dead {
+                    waited: Duration::from_secs(60)
+                })
+            );
+        });
+    }
+
+    #[test]
+    fn one_way_data() {
+        MockRuntime::test_with_various(|rt| async move {
+            let mut monitor = Monitor::new_disabled(rt.clone());
+            let params = Parameters::builder()
+                .dead_after(Some(Duration::from_secs(60)))
+                .build()
+                .unwrap();
+            monitor.reconfigure(&params);
+
+            // A stream that only uploads sends DATA cells that nobody answers,
+            // on a channel that is otherwise quiet.
+            for _ in 0..10 {
+                monitor.note_cell_sent(&relay_cell());
+                rt.advance_by(Duration::from_secs(30)).await;
+                assert_eq!(ready_action(&mut monitor).await, None);
+            }
+
+            // A CREATE2 cell does expect an answer, though.
+            let create2 = msg::Create2::new(HandshakeType::NTOR, &b"handshake"[..]);
+            monitor.note_cell_sent(&create2.into());
+            rt.advance_by(Duration::from_secs(30)).await;
+            assert_eq!(ready_action(&mut monitor).await, Some(Action::Unresponsive));
+        });
+    }
+
+    #[test]
+    fn relay_cmds() {
+        assert!(relay_cmd_expects_response(RelayCmd::BEGIN));
+        assert!(relay_cmd_expects_response(RelayCmd::EXTEND2));
+        assert!(!relay_cmd_expects_response(RelayCmd::DATA));
+        assert!(!relay_cmd_expects_response(RelayCmd::SENDME));
+        assert!(!relay_cmd_expects_response(RelayCmd::END));
+    }
+
+    #[test]
+    fn atomic_health() {
+        let health = AtomicChannelHealth::default();
+        assert_eq!(health.load(), ChannelHealth::Healthy);
+        for h in [
+            ChannelHealth::Unresponsive,
+            ChannelHealth::Dead,
+            ChannelHealth::Healthy,
+        ] {
+            health.store(h);
+            assert_eq!(health.load(), h);
+        }
+    }
+}
@@ -34,7 +34,7 @@ use std::time::{Duration, Instant};
 use derive_builder::Builder;
 use educe::Educe;
 use futures::future::{self, FusedFuture};
-use futures::FutureExt;
+use futures::{FutureExt, StreamExt as _};
 use pin_project::pin_project;
 use rand::distributions::Distribution;
 use tracing::error;
@@ -42,9 +42,20 @@ use tracing::error;
 use tor_cell::chancell::msg::{Padding, PaddingNegotiate};
 use tor_config::impl_standard_builder;
 use tor_error::into_internal;
+use tor_rtcompat::scheduler::{TaskHandle, TaskSchedule};
 use tor_rtcompat::SleepProvider;
 use tor_units::IntegerMilliseconds;
 
+/// The granularity onto which channels coalesce their padding wakeups
+///
+/// Each channel's padding timer fires at a multiple of this,
+/// so that a process with many channels wakes up less often.
+///
+/// We don't add any jitter:
+/// the padding timeout is already sampled randomly, as `padding-spec.txt` requires,
+/// and extra jitter would distort that distribution.
+pub(crate) const PADDING_COALESCING: Duration = Duration::from_millis(10);
+
 /// Timer that organises wakeups when channel padding should be sent
 ///
 /// Use [`next()`](Timer::next) to find when to send padding, and
@@ -93,32 +104,37 @@ pub(crate) struct Timer<R: SleepProvider> {
     /// If `selected_timeout` is `Some`, and `trigger_at` is therefore valid,
     /// it is (obviously) no later than `selected_timeout` from now.
     ///
-    /// See also `waker`.
+    /// See also `armed`.
     trigger_at: Option<Instant>,
 
-    /// Actual waker from the `SleepProvider`
+    /// The schedule that wakes us up
     ///
-    /// This is created and updated lazily, because we suspect that with some runtimes
+    /// This is armed lazily, because we suspect that with some runtimes
     /// setting timeouts may be slow.
-    /// Lazy updating means that with intermittent data traffic, we do not keep scheduling,
+    /// Lazy arming means that with intermittent data traffic, we do not keep scheduling,
     /// descheduling, and adjusting, a wakeup time.
     ///
-    /// Invariants:
+    /// The schedule may [coalesce](TaskSchedule::set_coalescing) our wakeups,
+    /// making them a little later than we asked for; it never makes them earlier.
+    schedule: TaskSchedule<R>,
+
+    /// Handle for `schedule`
+    ///
+    /// We must keep this alive: the schedule stops firing once its last handle is dropped.
+    _schedule_handle: TaskHandle,
+
+    /// Whether `schedule` has been armed, and has not yet fired
     ///
-    /// If `selected_timeout` is `Some`,
-    /// the time at which this waker will trigger here is never *later* than `trigger_at`,
-    /// and never *later* than `selected_timeout` from now.
+    /// Invariants:
     ///
-    /// The wakeup time here may well be earlier than `trigger_at`,
-    /// and sooner than `selected_timeout` from now.  It may even be in the past.
-    /// When we wake up and discover this situation, we reschedule a new waker.
+    /// If `selected_timeout` is `Some` and this is `true`,
+    /// the schedule will fire no earlier than `trigger_at` was when we armed it.
+    /// That may well be earlier than the current `trigger_at`,
+    /// which is recalculated whenever data flows.
+    /// When we wake up and discover this situation, we arm the schedule again.
     ///
     /// If `selected_timeout` is `None`, the value is unspecified.
-    /// We may retain a `Some` in this case so that if `SleepProvider` is enhanced to
-    /// support rescheduling, we can do that without making a new `SleepFuture`
-    /// (and without completely reorganising this the `Timer` state structure.)
-    #[pin]
-    waker: Option<R::SleepFuture>,
+    armed: bool,
 }
 
 /// Timing parameters, as described in `padding-spec.txt`
@@ -186,8 +202,8 @@ enum SleepInstructions<'f, R: SleepProvider> {
     },
     /// Caller should wait forever
     Forever,
-    /// Caller should `await` this
-    Waker(#[educe(Debug(ignore))] Pin<&'f mut R::SleepFuture>),
+    /// Caller should wait for this to fire
+    Waker(#[educe(Debug(ignore))] &'f mut TaskSchedule<R>),
 }
 
 impl<R: SleepProvider> Timer<R> {
@@ -196,27 +212,49 @@ impl<R: SleepProvider> Timer<R> {
     pub(crate) fn new(sleep_prov: R, parameters: Parameters) -> Self {
         let parameters = parameters.prepare();
         let selected_timeout = parameters.select_timeout();
+        let (schedule, schedule_handle) = Self::new_schedule(&sleep_prov);
         // Too different to new_disabled to share its code, sadly.
         Timer {
             sleep_prov,
             parameters: Some(parameters),
             selected_timeout: Some(selected_timeout),
             trigger_at: None,
-            waker: None,
+            schedule,
+            _schedule_handle: schedule_handle,
+            armed: false,
         }
     }
 
     /// Create a new `Timer` which starts out disabled
     pub(crate) fn new_disabled(sleep_prov: R, parameters: Option<Parameters>) -> Self {
+        let (schedule, schedule_handle) = Self::new_schedule(&sleep_prov);
         Timer {
             sleep_prov,
             parameters: parameters.map(|p| p.prepare()),
             selected_timeout: None,
             trigger_at: None,
-            waker: None,
+            schedule,
+            _schedule_handle: schedule_handle,
+            armed: false,
         }
     }
 
+    /// Create the schedule for a new `Timer`
+    ///
+    /// A new schedule starts off ready, but that doesn't matter:
+    /// we only wait on it after arming it, which replaces that.
+    fn new_schedule(sleep_prov: &R) -> (TaskSchedule<R>, TaskHandle) {
+        TaskSchedule::new(sleep_prov.clone())
+    }
+
+    /// Make this `Timer` coalesce its wakeups onto multiples of `granularity`
+    ///
+    /// See [`TaskSchedule::set_coalescing`].
+    /// Takes effect the next time the timer is armed.
+    pub(crate) fn set_coalescing(self: &mut Pin<&mut Self>, granularity: Duration) {
+        self.as_mut().project().schedule.set_coalescing(granularity);
+    }
+
     /// Disable this `Timer`
     ///
     /// Idempotent.
@@ -262,8 +300,8 @@ impl<R: SleepProvider> Timer<R> {
         *self_.selected_timeout = timeout;
         // This is no longer valid; recalculate it on next poll
         *self_.trigger_at = None;
-        // Timeout might be earlier, so we will need a new waker too.
-        self_.waker.set(None);
+        // Timeout might be earlier, so we will need to arm the schedule again too.
+        *self_.armed = false;
     }
 
     /// Note that data has been sent (ie, reset the timeout, delaying the next padding)
@@ -284,15 +322,9 @@ impl<R: SleepProvider> Timer<R> {
             Some(t) => *t,
         };
 
-        if self_.waker.is_some() {
-            // We need to do this with is_some and expect because we need to consume self
-            // to get a return value with the right lifetimes.
-            let waker = self
-                .project()
-                .waker
-                .as_pin_mut()
-                .expect("None but we just checked");
-            return SleepInstructions::Waker(waker);
+        if *self_.armed {
+            // We need to consume self to get a return value with the right lifetimes.
+            return SleepInstructions::Waker(self.project().schedule);
         }
 
         let now = now.unwrap_or_else(|| self_.sleep_prov.now());
@@ -316,16 +348,9 @@ impl<R: SleepProvider> Timer<R> {
 
         //dbg!(timeout, remaining, now, trigger_at);
 
-        // There is no Option::get_pin_mut_or_set_with
-        if self_.waker.is_none() {
-            self_.waker.set(Some(self_.sleep_prov.sleep(remaining)));
-        }
-        let waker = self
-            .project()
-            .waker
-            .as_pin_mut()
-            .expect("None but we just inserted!");
-        SleepInstructions::Waker(waker)
+        self_.schedule.fire_in(remaining);
+        *self_.armed = true;
+        SleepInstructions::Waker(self.project().schedule)
     }
 
     /// Wait until we should next send padding, and then return the padding message
@@ -352,15 +377,15 @@ impl<R: SleepProvider> Timer<R> {
             match self.as_mut().prepare_to_sleep(None) {
                 SleepInstructions::Forever => future::pending().await,
                 SleepInstructions::Immediate { now } => break now,
-                SleepInstructions::Waker(waker) => waker.await,
+                SleepInstructions::Waker(schedule) => {
+                    // We hold a handle, so the schedule can't end.
+                    let _: Option<()> = schedule.next().await;
+                }
             }
 
-            // This timer has fired and has therefore been used up.
-            // When we go round again we will make a new one.
-            //
-            // TODO: have SleepProviders provide a reschedule function, and use it.
-            // That is likely to be faster where supported.
-            self.as_mut().project().waker.set(None);
+            // The schedule has fired and has therefore been used up.
+            // When we go round again we will arm it again.
+            *self.as_mut().project().armed = false;
         };
 
         // It's time to send padding.
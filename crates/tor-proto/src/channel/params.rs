@@ -7,6 +7,7 @@
 //!   * whether padding is to be sent
 //!   * what timing parameters to use for sending padding
 //!   * what `PADDING_NEGOTIATE` cell to send
+//!   * when to send keepalive `PADDING`, and when to give up on an unresponsive channel
 //!
 //! The instructions are, ultimately, instructions to the channel reactor.
 //! The reactor gets a [`ChannelPaddingInstructionsUpdates`],
@@ -23,7 +24,7 @@ use educe::Educe;
 
 use tor_cell::chancell::msg::PaddingNegotiate;
 
-use super::padding;
+use super::{liveness, padding};
 
 /// Generate most of the types and methods relating to ChannelPaddingInstructions:
 /// things which contain or process all instructions fields (or each one)
@@ -186,6 +187,13 @@ define_channels_insns_and_automatic_impls! {
     ///
     /// [`Channel::engage_padding_activities`]: super::Channel::engage_padding_activities
     padding_negotiate: PaddingNegotiate,
+
+    /// Keepalive and dead-channel detection parameters
+    ///
+    /// Unlike the other instructions, these apply to every channel,
+    /// whether or not its usage implies padding.
+    #[field educe(Default(expression = "liveness::Parameters::disabled()"))]
+    liveness: liveness::Parameters,
 }
 
 /// Builder for a channels padding instructions update
@@ -13,7 +13,7 @@ use crate::circuit::halfcirc::HalfCirc;
 use crate::util::err::{ChannelClosed, ReactorError};
 use crate::{Error, Result};
 use tor_async_utils::SinkPrepareExt as _;
-use tor_cell::chancell::msg::{Destroy, DestroyReason, PaddingNegotiate};
+use tor_cell::chancell::msg::{Destroy, DestroyReason, Padding, PaddingNegotiate};
 use tor_cell::chancell::ChanMsg;
 use tor_cell::chancell::{msg::AnyChanMsg, AnyChanCell, CircId};
 use tor_rtcompat::SleepProvider;
@@ -33,7 +33,9 @@ use std::pin::Pin;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
-use crate::channel::{codec::CodecError, padding, params::*, unique_id, ChannelDetails};
+use crate::channel::{
+    codec::CodecError, liveness, padding, params::*, unique_id, ChannelDetails, ChannelHealth,
+};
 use crate::circuit::celltypes::{ClientCircChanMsg, CreateResponse};
 use tracing::{debug, trace};
 
@@ -86,6 +88,11 @@ pub enum CtrlMsg {
     /// These updates are done via a control message to avoid adding additional branches to the
     /// main reactor `select!`.
     ConfigUpdate(Arc<ChannelPaddingInstructionsUpdates>),
+    /// Tell the reactor that a circuit is about to send a `RELAY*` cell
+    /// which is sure to get a response.
+    ///
+    /// (The reactor can't tell this from the cell, since it is encrypted.)
+    ExpectResponse,
 }
 
 /// Object to handle incoming cells and background tasks on a channel.
@@ -119,6 +126,8 @@ pub struct Reactor<S: SleepProvider> {
     pub(super) padding_timer: Pin<Box<padding::Timer<S>>>,
     /// Outgoing cells introduced at the channel reactor
     pub(super) special_outgoing: SpecialOutgoing,
+    /// Timer tracking when to send keepalives, and whether the channel is dead
+    pub(super) liveness: liveness::Monitor<S>,
     /// A map from circuit ID to Sinks on which we can deliver cells.
     pub(super) circs: CircMap,
     /// A unique identifier for this channel.
@@ -137,6 +146,8 @@ pub struct Reactor<S: SleepProvider> {
 pub(super) struct SpecialOutgoing {
     /// If we must send a `PaddingNegotiate`
     pub(super) padding_negotiate: Option<PaddingNegotiate>,
+    /// If we must send a keepalive
+    pub(super) keepalive: Option<Padding>,
 }
 
 impl SpecialOutgoing {
@@ -151,6 +162,9 @@ impl SpecialOutgoing {
         if let Some(p) = self.padding_negotiate.take() {
             return Some(p.into());
         }
+        if let Some(p) = self.keepalive.take() {
+            return Some(p.into());
+        }
         None
     }
 }
@@ -234,9 +248,14 @@ impl<S: SleepProvider> Reactor<S> {
             }) => {
                 let (msg, sendable) = ret.map_err(codec_err_to_chan)?;
                 let msg = msg.ok_or(ReactorError::Shutdown)?;
+                self.liveness.note_cell_sent(msg.msg());
                 sendable.send(msg).map_err(codec_err_to_chan)?;
             }
 
+            action = self.liveness.next() => {
+                self.handle_liveness(action)?;
+            }
+
             ret = self.control.next() => {
                 let ctrl = match ret {
                     None | Some(CtrlMsg::Shutdown) => return Err(ReactorError::Shutdown),
@@ -250,6 +269,10 @@ impl<S: SleepProvider> Reactor<S> {
                     .ok_or(ReactorError::Shutdown)?
                     .map_err(codec_err_to_chan)?;
                 crate::note_incoming_traffic();
+                if self.liveness.note_cell_received() {
+                    debug!("{}: Channel is responsive again", &self);
+                    self.details.health.store(ChannelHealth::Healthy);
+                }
                 self.handle_cell(item).await?;
             }
 
@@ -263,6 +286,7 @@ impl<S: SleepProvider> Reactor<S> {
         match msg {
             CtrlMsg::Shutdown => panic!(), // was handled in reactor loop.
             CtrlMsg::CloseCircuit(id) => self.outbound_destroy_circ(id).await?,
+            CtrlMsg::ExpectResponse => self.liveness.note_response_expected(),
             CtrlMsg::AllocateCircuit {
                 created_sender,
                 sender,
@@ -279,20 +303,27 @@ impl<S: SleepProvider> Reactor<S> {
                 self.update_disused_since();
             }
             CtrlMsg::ConfigUpdate(updates) => {
-                if self.link_protocol == 4 {
-                    // Link protocol 4 does not permit sending, or negotiating, link padding.
-                    // We test for == 4 so that future updates to handshake.rs LINK_PROTOCOLS
-                    // keep doing padding things.
-                    return Ok(());
-                }
-
                 let ChannelPaddingInstructionsUpdates {
                     // List all the fields explicitly; that way the compiler will warn us
                     // if one is added and we fail to handle it here.
                     padding_enable,
                     padding_parameters,
                     padding_negotiate,
+                    liveness,
                 } = &*updates;
+
+                // Keepalives are PADDING cells, which every link protocol permits.
+                if let Some(liveness) = liveness {
+                    self.liveness.reconfigure(liveness);
+                }
+
+                if self.link_protocol == 4 {
+                    // Link protocol 4 does not permit sending, or negotiating, link padding.
+                    // We test for == 4 so that future updates to handshake.rs LINK_PROTOCOLS
+                    // keep doing padding things.
+                    return Ok(());
+                }
+
                 if let Some(parameters) = padding_parameters {
                     self.padding_timer.as_mut().reconfigure(parameters);
                 }
@@ -314,6 +345,25 @@ impl<S: SleepProvider> Reactor<S> {
         Ok(())
     }
 
+    /// Take an action that our liveness monitor asked for.
+    fn handle_liveness(&mut self, action: liveness::Action) -> Result<()> {
+        match action {
+            liveness::Action::SendKeepalive => {
+                self.special_outgoing.keepalive = Some(Padding::new());
+            }
+            liveness::Action::Unresponsive => {
+                debug!("{}: Channel is unresponsive", &self);
+                self.details.health.store(ChannelHealth::Unresponsive);
+            }
+            liveness::Action::Dead { waited } => {
+                debug!("{}: Channel is dead; closing it", &self);
+                self.details.health.store(ChannelHealth::Dead);
+                return Err(Error::ChanUnresponsive(waited));
+            }
+        }
+        Ok(())
+    }
+
     /// Helper: process a cell on a channel.  Most cell types get ignored
     /// or rejected; a few get delivered to circuits.
     async fn handle_cell(&mut self, cell: OpenChanCellS2C) -> Result<()> {
@@ -507,6 +557,7 @@ pub(crate) mod test {
             link_protocol,
             Box::new(send1),
             Box::new(recv2),
+            Default::default(),
             unique_id,
             dummy_target,
             crate::ClockSkew::None,
@@ -38,6 +38,9 @@
 //!
 //! There is no flow-control or rate-limiting or fairness.
 
+#[cfg(feature = "relay-msg-capture")]
+#[cfg_attr(docsrs, doc(cfg(feature = "relay-msg-capture")))]
+pub mod capture;
 pub(crate) mod celltypes;
 pub(crate) mod halfcirc;
 mod halfstream;
@@ -66,12 +69,14 @@ use crate::crypto::cell::HopNum;
 #[cfg(feature = "ntor_v3")]
 use crate::crypto::handshake::ntor_v3::NtorV3PublicKey;
 use crate::stream::{
-    AnyCmdChecker, DataCmdChecker, DataStream, ResolveCmdChecker, ResolveStream, StreamParameters,
-    StreamReader,
+    AnyCmdChecker, DataCmdChecker, DataStream, ResolveCmdChecker, ResolveStream,
+    StreamBufferWatermarks, StreamFlowCtrl, StreamParameters, StreamReader,
 };
-use crate::{Error, ResolveError, Result};
+use crate::{CircCloseReason, Error, ResolveError, Result};
 use educe::Educe;
 use tor_cell::chancell::msg::HandshakeType;
+#[cfg(feature = "ntor_v3")]
+use tor_cell::relaycell::extend::NtorV3Extension;
 use tor_cell::{
     chancell::{self, msg::AnyChanMsg, CircId},
     relaycell::msg::{AnyRelayMsg, Begin, Resolve, Resolved, ResolvedVal},
@@ -92,7 +97,9 @@ use tor_async_utils::oneshot;
 use crate::circuit::sendme::StreamRecvWindow;
 use futures::{FutureExt as _, SinkExt as _};
 use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tor_cell::relaycell::StreamId;
 // use std::time::Duration;
 
@@ -181,6 +188,17 @@ pub struct ClientCirc {
     /// meaning that the circuit is closed.
     #[cfg_attr(not(feature = "experimental-api"), allow(dead_code))]
     reactor_closed_rx: futures::future::Shared<oneshot::Receiver<void::Void>>,
+    /// When this circuit object was created.
+    ///
+    /// This is when we started building the circuit: before its first hop
+    /// was added.
+    created: Instant,
+    /// The number of streams that we have opened on this circuit and not
+    /// yet dropped.
+    n_streams: Arc<AtomicUsize>,
+    /// The number of streams on this circuit that are still waiting for
+    /// a reply to their BEGIN (or RESOLVE) message.
+    n_pending_begins: Arc<AtomicUsize>,
     /// For testing purposes: the CircId, for use in peek_circid().
     #[cfg(test)]
     circid: CircId,
@@ -206,6 +224,29 @@ struct MutableState {
     /// an `Option`.
     #[educe(Debug(ignore))]
     binding: Vec<Option<CircuitBinding>>,
+
+    /// If a relay closed this circuit, the reason it gave.
+    close_reason: Option<CircCloseReason>,
+
+    /// Timing information for each hop in the circuit's path.
+    hop_timings: Vec<HopTiming>,
+}
+
+/// Information about how long it took to add a hop to a circuit.
+///
+/// Returned by [`ClientCirc::hop_timings`].
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub struct HopTiming {
+    /// The round-trip time of the handshake that added this hop:
+    /// from when we sent the CREATE* or EXTEND2 cell,
+    /// until we received the matching CREATED* or EXTENDED2 cell.
+    ///
+    /// `None` for a virtual hop, which is added without any handshake
+    /// with a relay.
+    pub handshake_rtt: Option<Duration>,
+    /// The time at which the hop was added.
+    pub completed_at: Instant,
 }
 
 /// A ClientCirc that needs to send a create cell and receive a created* cell.
@@ -228,6 +269,9 @@ pub struct CircParameters {
     /// Whether we should include ed25519 identities when we send
     /// EXTEND2 cells.
     extend_by_ed25519_id: bool,
+    /// Whether we should ask hops that we reach with the ntor-v3 handshake
+    /// to enable congestion control.
+    request_congestion_control: bool,
 }
 
 impl Default for CircParameters {
@@ -235,6 +279,7 @@ impl Default for CircParameters {
         CircParameters {
             initial_send_window: 1000,
             extend_by_ed25519_id: true,
+            request_congestion_control: false,
         }
     }
 }
@@ -273,6 +318,36 @@ impl CircParameters {
     pub fn extend_by_ed25519_id(&self) -> bool {
         self.extend_by_ed25519_id
     }
+
+    /// Override the default decision about whether to request congestion
+    /// control from hops that we reach with the ntor-v3 handshake.
+    ///
+    /// Hops that use an older handshake have no way to negotiate this, and
+    /// are not affected.
+    ///
+    /// This isn't public, since we do not yet implement the congestion
+    /// control algorithms themselves.
+    #[allow(dead_code)] // TODO: Only the tests use this until we implement congestion control.
+    pub(crate) fn set_request_congestion_control(&mut self, v: bool) {
+        self.request_congestion_control = v;
+    }
+
+    /// Return true if we're configured to request congestion control from
+    /// hops that support it; false otherwise.
+    pub fn request_congestion_control(&self) -> bool {
+        self.request_congestion_control
+    }
+
+    /// Return the extensions that we should send to a hop as part of an
+    /// ntor-v3 handshake.
+    #[cfg(feature = "ntor_v3")]
+    pub(crate) fn ntor_v3_client_extensions(&self) -> Vec<NtorV3Extension> {
+        let mut exts = Vec::new();
+        if self.request_congestion_control {
+            exts.push(NtorV3Extension::RequestCongestionControl);
+        }
+        exts
+    }
 }
 
 /// Internal handle, used to implement a stream on a particular circuit.
@@ -295,6 +370,33 @@ pub(crate) struct StreamTarget {
     tx: mpsc::Sender<AnyRelayMsg>,
     /// Reference to the circuit that this stream is on.
     circ: Arc<ClientCirc>,
+    /// This stream's entry in the circuit's count of open streams, if it is
+    /// a stream that we opened as a client.
+    ///
+    /// Shared between all the clones of this `StreamTarget`, so that the
+    /// stream stops being counted once they are all dropped.
+    _count: Option<Arc<StreamCountGuard>>,
+}
+
+/// An entry in one of a [`ClientCirc`]'s stream counters.
+///
+/// The counter is incremented when this guard is created, and decremented
+/// when it is dropped.
+#[derive(Debug)]
+struct StreamCountGuard(Arc<AtomicUsize>);
+
+impl StreamCountGuard {
+    /// Add one to `counter`, and return a guard that will subtract it again.
+    fn new(counter: &Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        StreamCountGuard(Arc::clone(counter))
+    }
+}
+
+impl Drop for StreamCountGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl ClientCirc {
@@ -363,12 +465,21 @@ impl ClientCirc {
 
     /// Return a [`Path`] object describing all the hops in this circuit.
     ///
+    /// Each hop's identities and addresses are available from
+    /// [`PathEntry::as_chan_target`], and
+    /// the `Path` can be displayed as a list of hops.
+    ///
     /// Note that this `Path` is not automatically updated if the circuit is
     /// extended.
     pub fn path_ref(&self) -> Arc<Path> {
         self.mutable.lock().expect("poisoned_lock").path.clone()
     }
 
+    /// Return the time at which we started building this circuit.
+    pub fn creation_time(&self) -> Instant {
+        self.created
+    }
+
     /// Return a reference to the channel that this circuit is connected to.
     ///
     /// A client circuit is always connected to some relay via a [`Channel`].
@@ -529,9 +640,9 @@ impl ClientCirc {
         };
         self.control
             .unbounded_send(ctrl_msg)
-            .map_err(|_| Error::CircuitClosed)?;
+            .map_err(|_| self.closed_error())?;
 
-        receiver.await.map_err(|_| Error::CircuitClosed)?
+        receiver.await.map_err(|_| self.closed_error())?
     }
 
     /// Tell this circuit to begin allowing the final hop of the circuit to try
@@ -577,10 +688,10 @@ impl ClientCirc {
                 done: tx,
                 filter: Box::new(filter),
             })
-            .map_err(|_| Error::CircuitClosed)?;
+            .map_err(|_| self.closed_error())?;
 
         // Check whether the AwaitStreamRequest was processed successfully.
-        rx.await.map_err(|_| Error::CircuitClosed)??;
+        rx.await.map_err(|_| self.closed_error())??;
 
         let allowed_hop_num = hop_num;
 
@@ -592,6 +703,7 @@ impl ClientCirc {
                 hop_num,
                 receiver,
                 msg_tx,
+                flow_ctrl,
             } = req_ctx;
 
             // We already enforce this in handle_incoming_stream_request; this
@@ -605,13 +717,16 @@ impl ClientCirc {
                 tx: msg_tx,
                 hop_num,
                 stream_id,
+                _count: None,
             };
 
             let reader = StreamReader {
                 target: target.clone(),
                 receiver,
                 recv_window: StreamRecvWindow::new(RECV_WINDOW_INIT),
+                withheld_sendmes: 0,
                 ended: false,
+                flow_ctrl,
             };
 
             IncomingStream::new(req, target, reader)
@@ -648,9 +763,9 @@ impl ClientCirc {
                 params: params.clone(),
                 done: tx,
             })
-            .map_err(|_| Error::CircuitClosed)?;
+            .map_err(|_| self.closed_error())?;
 
-        rx.await.map_err(|_| Error::CircuitClosed)??;
+        rx.await.map_err(|_| self.closed_error())??;
 
         Ok(())
     }
@@ -686,9 +801,9 @@ impl ClientCirc {
                 params: params.clone(),
                 done: tx,
             })
-            .map_err(|_| Error::CircuitClosed)?;
+            .map_err(|_| self.closed_error())?;
 
-        rx.await.map_err(|_| Error::CircuitClosed)??;
+        rx.await.map_err(|_| self.closed_error())??;
 
         Ok(())
     }
@@ -743,9 +858,9 @@ impl ClientCirc {
 
         self.control
             .unbounded_send(message)
-            .map_err(|_| Error::CircuitClosed)?;
+            .map_err(|_| self.closed_error())?;
 
-        rx.await.map_err(|_| Error::CircuitClosed)?
+        rx.await.map_err(|_| self.closed_error())?
     }
 
     /// Helper, used to begin a stream.
@@ -759,6 +874,7 @@ impl ClientCirc {
         self: &Arc<ClientCirc>,
         begin_msg: AnyRelayMsg,
         cmd_checker: AnyCmdChecker,
+        watermarks: StreamBufferWatermarks,
     ) -> Result<(StreamReader, StreamTarget)> {
         // TODO: Possibly this should take a hop, rather than just
         // assuming it's the last hop.
@@ -771,9 +887,14 @@ impl ClientCirc {
             .last_hop_num()
             .ok_or_else(|| Error::from(internal!("Can't begin a stream at the 0th hop")))?;
 
-        let (sender, receiver) = mpsc::channel(STREAM_READER_BUFFER);
+        // Leave room for the other side to keep sending for a while after we
+        // send an XOFF.
+        let count = Arc::new(StreamCountGuard::new(&self.n_streams));
+        let buffer_size = STREAM_READER_BUFFER.max(watermarks.high().saturating_mul(2));
+        let (sender, receiver) = mpsc::channel(buffer_size);
         let (tx, rx) = oneshot::channel();
         let (msg_tx, msg_rx) = mpsc::channel(CIRCUIT_BUFFER_SIZE);
+        let flow_ctrl = Arc::new(StreamFlowCtrl::new(watermarks));
 
         self.control
             .unbounded_send(CtrlMsg::BeginStream {
@@ -783,23 +904,27 @@ impl ClientCirc {
                 rx: msg_rx,
                 done: tx,
                 cmd_checker,
+                flow_ctrl: Arc::clone(&flow_ctrl),
             })
-            .map_err(|_| Error::CircuitClosed)?;
+            .map_err(|_| self.closed_error())?;
 
-        let stream_id = rx.await.map_err(|_| Error::CircuitClosed)??;
+        let stream_id = rx.await.map_err(|_| self.closed_error())??;
 
         let target = StreamTarget {
             circ: self.clone(),
             tx: msg_tx,
             hop_num,
             stream_id,
+            _count: Some(count),
         };
 
         let reader = StreamReader {
             target: target.clone(),
             receiver,
             recv_window: StreamRecvWindow::new(RECV_WINDOW_INIT),
+            withheld_sendmes: 0,
             ended: false,
+            flow_ctrl,
         };
 
         Ok((reader, target))
@@ -811,9 +936,11 @@ impl ClientCirc {
         self: &Arc<ClientCirc>,
         msg: AnyRelayMsg,
         optimistic: bool,
+        watermarks: StreamBufferWatermarks,
     ) -> Result<DataStream> {
+        let _pending = StreamCountGuard::new(&self.n_pending_begins);
         let (reader, target) = self
-            .begin_stream_impl(msg, DataCmdChecker::new_any())
+            .begin_stream_impl(msg, DataCmdChecker::new_any(), watermarks)
             .await?;
         let mut stream = DataStream::new(reader, target);
         if !optimistic {
@@ -843,7 +970,8 @@ impl ClientCirc {
         };
         let beginmsg = Begin::new(target, port, begin_flags)
             .map_err(|e| Error::from_cell_enc(e, "begin message"))?;
-        self.begin_data_stream(beginmsg.into(), optimistic).await
+        self.begin_data_stream(beginmsg.into(), optimistic, parameters.watermarks())
+            .await
     }
 
     /// Start a new stream to the last relay in the circuit, using
@@ -853,8 +981,12 @@ impl ClientCirc {
         // Since they are local to a relay that we've already authenticated
         // with and built a circuit to, there should be no additional checks
         // we need to perform to see whether the BEGINDIR will succeed.
-        self.begin_data_stream(AnyRelayMsg::BeginDir(Default::default()), true)
-            .await
+        self.begin_data_stream(
+            AnyRelayMsg::BeginDir(Default::default()),
+            true,
+            Default::default(),
+        )
+        .await
     }
 
     /// Perform a DNS lookup, using a RESOLVE cell with the last relay
@@ -905,8 +1037,9 @@ impl ClientCirc {
     /// Helper: Send the resolve message, and read resolved message from
     /// resolve stream.
     async fn try_resolve(self: &Arc<ClientCirc>, msg: Resolve) -> Result<Resolved> {
+        let _pending = StreamCountGuard::new(&self.n_pending_begins);
         let (reader, _) = self
-            .begin_stream_impl(msg.into(), ResolveCmdChecker::new_any())
+            .begin_stream_impl(msg.into(), ResolveCmdChecker::new_any(), Default::default())
             .await?;
         let mut resolve_stream = ResolveStream::new(reader);
         resolve_stream.read_msg().await
@@ -937,11 +1070,82 @@ impl ClientCirc {
         self.terminate();
     }
 
+    /// Start recording every relay message sent or received on this circuit
+    /// to `capture`, or stop recording if `capture` is `None`.
+    ///
+    /// Replaces any capture previously set on this circuit.  Messages that
+    /// were already being processed when this is called may or may not be
+    /// recorded.
+    #[cfg(feature = "relay-msg-capture")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "relay-msg-capture")))]
+    pub fn set_relay_msg_capture(
+        &self,
+        capture: Option<Arc<capture::RelayMsgCapture>>,
+    ) -> Result<()> {
+        self.control
+            .unbounded_send(CtrlMsg::SetRelayMsgCapture { capture })
+            .map_err(|_| self.closed_error())
+    }
+
+    /// Return the number of streams that we have opened on this circuit,
+    /// and that haven't yet been dropped.
+    ///
+    /// This counts data, directory, and resolve streams that we opened as a
+    /// client, including those that are still waiting for a reply from
+    /// the remote relay.  It doesn't count incoming streams.
+    pub fn n_client_streams(&self) -> usize {
+        self.n_streams.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of streams on this circuit that are waiting for the
+    /// remote relay to answer their BEGIN or RESOLVE message.
+    ///
+    /// Optimistic streams stop counting as pending as soon as they are
+    /// created, since we don't wait for them to be connected.
+    pub fn n_pending_begins(&self) -> usize {
+        self.n_pending_begins.load(Ordering::Relaxed)
+    }
+
     /// Return true if this circuit is closed and therefore unusable.
     pub fn is_closing(&self) -> bool {
         self.control.is_closed()
     }
 
+    /// If a relay closed this circuit with a DESTROY cell or a TRUNCATED
+    /// message, return the reason it gave.
+    pub fn close_reason(&self) -> Option<CircCloseReason> {
+        self.mutable.lock().expect("poisoned lock").close_reason
+    }
+
+    /// Return timing information for each hop that has been added to this circuit,
+    /// in order.
+    pub fn hop_timings(&self) -> Vec<HopTiming> {
+        self.mutable
+            .lock()
+            .expect("poisoned lock")
+            .hop_timings
+            .clone()
+    }
+
+    /// Return how long it took to build this circuit:
+    /// from its [creation](ClientCirc::creation_time),
+    /// until the most recent hop was added.
+    ///
+    /// Return `None` if this circuit has no hops yet.
+    pub fn build_duration(&self) -> Option<Duration> {
+        let mutable = self.mutable.lock().expect("poisoned lock");
+        let last = mutable.hop_timings.last()?;
+        Some(last.completed_at.saturating_duration_since(self.created))
+    }
+
+    /// Return the error to report when we find that this circuit is closed.
+    pub(crate) fn closed_error(&self) -> Error {
+        match self.close_reason() {
+            Some(reason) => Error::CircuitDestroyed(reason),
+            None => Error::CircuitClosed,
+        }
+    }
+
     /// Return a process-unique identifier for this circuit.
     pub fn unique_id(&self) -> UniqId {
         self.unique_id
@@ -1010,9 +1214,9 @@ impl Conversation<'_> {
         self.0
             .control
             .unbounded_send(ctrl_msg)
-            .map_err(|_| Error::CircuitClosed)?;
+            .map_err(|_| self.0.closed_error())?;
 
-        receiver.await.map_err(|_| Error::CircuitClosed)?
+        receiver.await.map_err(|_| self.0.closed_error())?
     }
 }
 
@@ -1038,6 +1242,9 @@ impl PendingClientCirc {
             control: control_tx,
             reactor_closed_rx: reactor_closed_rx.shared(),
             channel,
+            created: Instant::now(),
+            n_streams: Default::default(),
+            n_pending_begins: Default::default(),
             #[cfg(test)]
             circid: id,
         };
@@ -1070,9 +1277,9 @@ impl PendingClientCirc {
                 params: params.clone(),
                 done: tx,
             })
-            .map_err(|_| Error::CircuitClosed)?;
+            .map_err(|_| self.circ.closed_error())?;
 
-        rx.await.map_err(|_| Error::CircuitClosed)??;
+        rx.await.map_err(|_| self.circ.closed_error())??;
 
         Ok(self.circ)
     }
@@ -1109,9 +1316,9 @@ impl PendingClientCirc {
                 params: params.clone(),
                 done: tx,
             })
-            .map_err(|_| Error::CircuitClosed)?;
+            .map_err(|_| self.circ.closed_error())?;
 
-        rx.await.map_err(|_| Error::CircuitClosed)??;
+        rx.await.map_err(|_| self.circ.closed_error())??;
 
         Ok(self.circ)
     }
@@ -1150,9 +1357,9 @@ impl PendingClientCirc {
                 params: params.clone(),
                 done: tx,
             })
-            .map_err(|_| Error::CircuitClosed)?;
+            .map_err(|_| self.circ.closed_error())?;
 
-        rx.await.map_err(|_| Error::CircuitClosed)??;
+        rx.await.map_err(|_| self.circ.closed_error())??;
 
         Ok(self.circ)
     }
@@ -1219,7 +1426,10 @@ impl StreamTarget {
     /// right hop, but will not validate that the message is well-formed
     /// or meaningful in context.
     pub(crate) async fn send(&mut self, msg: AnyRelayMsg) -> Result<()> {
-        self.tx.send(msg).await.map_err(|_| Error::CircuitClosed)?;
+        self.tx
+            .send(msg)
+            .await
+            .map_err(|_| self.circ.closed_error())?;
         Ok(())
     }
 
@@ -1265,7 +1475,7 @@ impl StreamTarget {
                 message,
                 done: tx,
             })
-            .map_err(|_| Error::CircuitClosed)?;
+            .map_err(|_| self.circ.closed_error())?;
 
         Ok(rx)
     }
@@ -1284,6 +1494,12 @@ impl StreamTarget {
         self.tx.close_channel();
     }
 
+    /// If a relay closed the circuit that this stream is on, return the reason
+    /// it gave.
+    pub(crate) fn circ_close_reason(&self) -> Option<CircCloseReason> {
+        self.circ.close_reason()
+    }
+
     /// Called when a circuit-level protocol error has occurred and the
     /// circuit needs to shut down.
     pub(crate) fn protocol_error(&mut self) {
@@ -1298,7 +1514,19 @@ impl StreamTarget {
                 stream_id: self.stream_id,
                 hop_num: self.hop_num,
             })
-            .map_err(|_| Error::CircuitClosed)?;
+            .map_err(|_| self.circ.closed_error())?;
+        Ok(())
+    }
+
+    /// Send an XON cell for this stream.
+    pub(crate) fn send_xon(&mut self) -> Result<()> {
+        self.circ
+            .control
+            .unbounded_send(CtrlMsg::SendXon {
+                stream_id: self.stream_id,
+                hop_num: self.hop_num,
+            })
+            .map_err(|_| self.circ.closed_error())?;
         Ok(())
     }
 
@@ -1571,6 +1799,81 @@ mod test {
         });
     }
 
+    /// Try to create a one-hop circuit with ntor-v3, optionally requesting
+    /// congestion control, against a relay that optionally acknowledges it.
+    #[cfg(feature = "ntor_v3")]
+    async fn test_create_ntor_v3_cc<R: Runtime>(
+        rt: &R,
+        request_cc: bool,
+        send_ack: bool,
+    ) -> Result<Arc<ClientCirc>> {
+        use crate::crypto::handshake::ServerHandshake;
+
+        let (chan, mut rx, _sink) = working_fake_channel(rt);
+        let circid = CircId::new(128).unwrap();
+        let (created_send, created_recv) = oneshot::channel();
+        let (_circmsg_send, circmsg_recv) = mpsc::channel(64);
+        let unique_id = UniqId::new(23, 17);
+
+        let (pending, reactor) =
+            PendingClientCirc::new(circid, chan, created_recv, circmsg_recv, unique_id);
+
+        rt.spawn(async {
+            let _ignore = reactor.run().await;
+        })
+        .unwrap();
+
+        let simulate_relay_fut = async move {
+            let mut rng = testing_rng();
+            let create_cell = rx.next().await.unwrap();
+            let c2 = match create_cell.msg() {
+                AnyChanMsg::Create2(c2) => c2,
+                _ => panic!(),
+            };
+            let (_, rep) = NtorV3Server::server(
+                &mut rng,
+                &mut |exts: &[NtorV3Extension]| {
+                    let requested = exts.contains(&NtorV3Extension::RequestCongestionControl);
+                    assert_eq!(requested, request_cc);
+                    Some(if send_ack {
+                        vec![NtorV3Extension::AckCongestionControl { sendme_inc: 31 }]
+                    } else {
+                        vec![]
+                    })
+                },
+                &[example_ntor_v3_key()],
+                c2.body(),
+            )
+            .unwrap();
+            created_send
+                .send(CreateResponse::Created2(Created2::new(rep)))
+                .unwrap();
+        };
+        let client_fut = async move {
+            let target = example_target();
+            let mut params = CircParameters::default();
+            params.set_request_congestion_control(request_cc);
+            pending.create_firsthop_ntor_v3(&target, params).await
+        };
+
+        let (circ, _) = futures::join!(client_fut, simulate_relay_fut);
+        circ
+    }
+
+    #[cfg(feature = "ntor_v3")]
+    #[test]
+    fn test_create_ntor_v3_congestion_control() {
+        tor_rtcompat::test_with_all_runtimes!(|rt| async move {
+            // Requested and acknowledged.
+            assert!(test_create_ntor_v3_cc(&rt, true, true).await.is_ok());
+            // Requested, but the relay declined.
+            assert!(test_create_ntor_v3_cc(&rt, true, false).await.is_ok());
+            // Acknowledged without being requested.
+            let err = test_create_ntor_v3_cc(&rt, false, true).await.unwrap_err();
+            assert!(matches!(err, Error::HandshakeProto(_)));
+        });
+    }
+
     // An encryption layer that doesn't do any crypto.   Can be used
     // as inbound or outbound, but not both at once.
     pub(crate) struct DummyCrypto {
@@ -1621,6 +1924,16 @@ mod test {
         rt: &R,
         chan: Arc<Channel>,
         next_msg_from: HopNum,
+    ) -> (Arc<ClientCirc>, mpsc::Sender<ClientCircChanMsg>) {
+        newcirc_with_params(rt, chan, next_msg_from, CircParameters::default()).await
+    }
+
+    // Helper: as newcirc_ext, but build every hop with the given parameters.
+    async fn newcirc_with_params<R: Runtime>(
+        rt: &R,
+        chan: Arc<Channel>,
+        next_msg_from: HopNum,
+        params: CircParameters,
     ) -> (Arc<ClientCirc>, mpsc::Sender<ClientCircChanMsg>) {
         let circid = CircId::new(128).unwrap();
         let (_created_send, created_recv) = oneshot::channel();
@@ -1643,7 +1956,7 @@ mod test {
         // TODO #1067: Support other formats
         let relay_cell_format = RelayCellFormat::V0;
         for idx in 0_u8..3 {
-            let params = CircParameters::default();
+            let params = params.clone();
             let (tx, rx) = oneshot::channel();
             circ.control
                 .unbounded_send(CtrlMsg::AddFakeHop {
@@ -1669,6 +1982,36 @@ mod test {
         newcirc_ext(rt, chan, 2.into()).await
     }
 
+    #[test]
+    fn path_introspection() {
+        use tor_linkspec::HasRelayIds;
+        tor_rtcompat::test_with_all_runtimes!(|rt| async move {
+            let before = Instant::now();
+            let (chan, _rx, _sink) = working_fake_channel(&rt);
+            let (circ, _send) = newcirc(&rt, chan).await;
+            assert!(circ.creation_time() >= before);
+            assert!(circ.creation_time() <= Instant::now());
+
+            let path = circ.path_ref();
+            assert_eq!(path.n_hops(), 3);
+            for hop in path.iter() {
+                assert!(!hop.is_virtual());
+                let target = hop.as_chan_target().unwrap();
+                assert_eq!(target.ed_identity(), Some(&[4; 32].into()));
+                assert_eq!(target.rsa_identity(), Some(&[5; 20].into()));
+            }
+            let hop = path.hops()[0].to_string();
+            assert_eq!(path.to_string(), format!("{hop} -> {hop} -> {hop}"));
+
+            // The fake hops were added without a handshake.
+            let timings = circ.hop_timings();
+            assert_eq!(timings.len(), 3);
+            assert!(timings.iter().all(|t| t.handshake_rtt.is_none()));
+            assert!(timings[0].completed_at <= timings[2].completed_at);
+            assert!(circ.build_duration().is_some());
+        });
+    }
+
     // Try sending a cell via send_relay_cell
     #[test]
     fn send_simple() {
@@ -1769,6 +2112,15 @@ mod test {
         // Did we really add another hop?
         assert_eq!(circ.n_hops(), 4);
 
+        // Did we record how long the EXTEND2 handshake took?
+        let timings = circ.hop_timings();
+        assert_eq!(timings.len(), 4);
+        assert!(timings[3].handshake_rtt.is_some());
+        assert_eq!(
+            circ.build_duration(),
+            Some(timings[3].completed_at - circ.creation_time())
+        );
+
         // Do the path accessors report a reasonable outcome?
         #[allow(deprecated)]
         {
@@ -1876,7 +2228,9 @@ mod test {
             let cc = ClientCircChanMsg::Destroy(chanmsg::Destroy::new(4.into()));
             let error = bad_extend_test_impl(&rt, 2.into(), cc).await;
             match error {
-                Error::CircuitClosed => {}
+                Error::CircuitDestroyed(CircCloseReason::Destroyed(reason)) => {
+                    assert_eq!(reason, 4.into());
+                }
                 _ => panic!(),
             }
         });
@@ -1971,18 +2325,24 @@ mod test {
         tor_rtcompat::test_with_all_runtimes!(|rt| async move {
             let (chan, mut rx, _sink) = working_fake_channel(&rt);
             let (circ, mut sink) = newcirc(&rt, chan).await;
+            let circ2 = Arc::clone(&circ);
 
             let stream_fut = async move {
                 let stream = circ
                     .begin_stream("www.example.com", 80, None)
                     .await
                     .unwrap();
+                assert_eq!(stream.connected_addr(), Some("10.0.0.1".parse().unwrap()));
+                assert_eq!(circ.n_client_streams(), 1);
+                assert_eq!(circ.n_pending_begins(), 0);
 
                 let (r, mut w) = stream.split();
                 if by_drop {
                     // Drop the writer and the reader, which should close the stream.
                     drop(r);
+                    assert_eq!(circ.n_client_streams(), 1);
                     drop(w);
+                    assert_eq!(circ.n_client_streams(), 0);
                     (None, circ) // make sure to keep the circuit alive
                 } else {
                     // Call close on the writer, while keeping the reader alive.
@@ -2002,6 +2362,7 @@ mod test {
                 };
                 let (streamid, rmsg) = rmsg.into_streamid_and_msg();
                 assert_eq!(rmsg.cmd(), RelayCmd::BEGIN);
+                assert_eq!(circ2.n_pending_begins(), 1);
 
                 // Reply with a CONNECTED.
                 let connected =
@@ -2062,6 +2423,7 @@ mod test {
                     .begin_stream("www.example.com", 443, None)
                     .await
                     .unwrap();
+                assert_eq!(stream.connected_addr(), None);
                 let junk = [0_u8; 1024];
                 let mut remaining = n_to_send;
                 while remaining > 0 {
@@ -2123,6 +2485,114 @@ mod test {
         (circ, stream, sink, streamid, cells_received, rx, sink2)
     }
 
+    // Helper: read the next relay message that the client sent.
+    async fn next_relay_msg(
+        rx: &mut Receiver<AnyChanCell>,
+    ) -> (Option<StreamId>, relaymsg::AnyRelayMsg) {
+        let (_id, chmsg) = rx.next().await.unwrap().into_circid_and_msg();
+        match chmsg {
+            AnyChanMsg::Relay(r) => {
+                AnyRelayMsgOuter::decode_singleton(RelayCellFormat::V0, r.into_relay_body())
+                    .unwrap()
+                    .into_streamid_and_msg()
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn xon_xoff_stream() {
+        tor_rtcompat::test_with_all_runtimes!(|rt| async move {
+            let (chan, mut rx, _sink) = working_fake_channel(&rt);
+            let mut params = CircParameters::default();
+            params.set_request_congestion_control(true);
+            let (circ, mut sink) = newcirc_with_params(&rt, chan, 2.into(), params).await;
+
+            let mut stream_params = StreamParameters::default();
+            stream_params
+                .optimistic(true)
+                .buffer_watermarks(StreamBufferWatermarks::new(4, 1).unwrap());
+            let mut stream = circ
+                .begin_stream("www.example.com", 443, Some(stream_params))
+                .await
+                .unwrap();
+
+            let (streamid, rmsg) = next_relay_msg(&mut rx).await;
+            assert!(matches!(rmsg, AnyRelayMsg::Begin(_)));
+            let connected = relaymsg::Connected::new_empty().into();
+            sink.send(rmsg_to_ccmsg(streamid, connected)).await.unwrap();
+
+            // Send more data than the high watermark; we should get an XOFF.
+            for n in 1_u8..=5 {
+                let data = relaymsg::Data::new(&[n; 10]).unwrap().into();
+                sink.send(rmsg_to_ccmsg(streamid, data)).await.unwrap();
+            }
+            let (id, rmsg) = next_relay_msg(&mut rx).await;
+            assert_eq!(id, streamid);
+            assert!(matches!(rmsg, AnyRelayMsg::Xoff(_)));
+
+            // Once we read it all, we should get an XON, and no SENDME.
+            let mut buf = [0_u8; 50];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf[49], 5);
+            let (id, rmsg) = next_relay_msg(&mut rx).await;
+            assert_eq!(id, streamid);
+            assert!(matches!(rmsg, AnyRelayMsg::Xon(_)));
+        });
+    }
+
+    #[test]
+    fn truncated_reason() {
+        use tor_error::HasKind as _;
+        tor_rtcompat::test_with_all_runtimes!(|rt| async move {
+            let (chan, _rx, _sink) = working_fake_channel(&rt);
+            let (circ, mut sink) = newcirc(&rt, chan).await;
+            assert_eq!(circ.close_reason(), None);
+
+            let truncated = relaymsg::Truncated::new(chanmsg::DestroyReason::RESOURCELIMIT).into();
+            sink.send(rmsg_to_ccmsg(None, truncated)).await.unwrap();
+            let _ = circ.reactor_closed_rx.clone().await;
+
+            let expected = CircCloseReason::Truncated {
+                hop: 2.into(),
+                reason: chanmsg::DestroyReason::RESOURCELIMIT,
+            };
+            assert_eq!(circ.close_reason(), Some(expected));
+            let err = circ
+                .begin_stream("www.example.com", 443, None)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::CircuitDestroyed(r) if r == expected));
+            assert_eq!(err.kind(), tor_error::ErrorKind::RelayTooBusy);
+            assert_eq!(
+                err.to_string(),
+                "Circuit closed by relay: TRUNCATED from hop #3: Relay ran out of resources [RESOURCELIMIT]"
+            );
+        });
+    }
+
+    #[test]
+    fn xon_without_congestion_control() {
+        tor_rtcompat::test_with_all_runtimes!(|rt| async move {
+            let (chan, mut rx, _sink) = working_fake_channel(&rt);
+            let (circ, mut sink) = newcirc(&rt, chan).await;
+
+            let mut stream_params = StreamParameters::default();
+            stream_params.optimistic(true);
+            let _stream = circ
+                .begin_stream("www.example.com", 443, Some(stream_params))
+                .await
+                .unwrap();
+            let (streamid, _) = next_relay_msg(&mut rx).await;
+
+            // Without congestion control, XON is a protocol violation.
+            let xon = relaymsg::Xon::new(0).into();
+            sink.send(rmsg_to_ccmsg(streamid, xon)).await.unwrap();
+            let _ = circ.reactor_closed_rx.clone().await;
+            assert!(circ.is_closing());
+        });
+    }
+
     #[test]
     fn accept_valid_sendme() {
         tor_rtcompat::test_with_all_runtimes!(|rt| async move {
@@ -0,0 +1,592 @@
+//! Opt-in recording of the relay messages sent and received on a circuit.
+//!
+//! This is a debugging facility: when a [`RelayMsgCapture`] is attached to a
+//! circuit (with [`ClientCirc::set_relay_msg_capture`](super::ClientCirc::set_relay_msg_capture)),
+//! the circuit reactor produces one record for every relay message it sends or
+//! receives.  Records are handed to a background thread that owns the
+//! writer, so that a slow writer never stalls the circuit: if the thread
+//! falls too far behind, new records are dropped instead.  Each record holds a timestamp, the direction, the hop, the relay
+//! command, the stream ID, and the length of the message body.  The body
+//! itself is only recorded if the capture was created with
+//! [`RelayMsgCapture::with_payloads`], since it can contain user data.
+//!
+//! Captures can be read back with [`CaptureReader`]; every
+//! [`CapturedRelayMsg`] implements `Display` in a format suitable for
+//! printing one message per line.
+//!
+//! # Format
+//!
+//! A capture begins with the 8-byte magic string `"arti-rmc"`, a two-byte
+//! version (currently 1) and a one-byte flags field (bit 0 set if payloads
+//! are included).  It is followed by any number of records, each prefixed
+//! with its length as a four-byte big-endian integer.  Within a record:
+//!
+//! ```text
+//!   timestamp: u64     (microseconds since the Unix epoch)
+//!   direction: u8      (0 for outbound, 1 for inbound)
+//!   hop: u8
+//!   command: u8
+//!   stream_id: u16     (0 if the message has no stream)
+//!   body_len: u16
+//!   circ_len: u8       followed by circ_len bytes of circuit identifier
+//!   body: [u8]         (body_len bytes, only if payloads are included)
+//! ```
+//!
+//! This format is not stable: it may change in any release.
+
+use std::fmt::{self, Display};
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use tor_bytes::{Reader, Writer};
+use tor_cell::relaycell::msg::AnyRelayMsg;
+use tor_cell::relaycell::{RelayCmd, RelayMsg as _, StreamId};
+use tracing::warn;
+
+use crate::circuit::UniqId;
+use crate::crypto::cell::HopNum;
+
+/// Magic string at the start of every capture.
+const MAGIC: &[u8; 8] = b"arti-rmc";
+
+/// The version of the capture format that we write.
+const VERSION: u16 = 1;
+
+/// Flag set in the capture header if the records include message bodies.
+const FLAG_PAYLOADS: u8 = 1;
+
+/// Upper bound on the size of a single record; anything larger is corrupt.
+const MAX_RECORD_LEN: u32 = 64 * 1024;
+
+/// How many records may be waiting for the writer thread before we start
+/// dropping new ones.
+const QUEUE_LEN: usize = 1024;
+
+/// The direction in which a captured relay message was travelling.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum CaptureDirection {
+    /// The message was sent by us, towards a hop of the circuit.
+    Outbound,
+    /// The message was received by us, from a hop of the circuit.
+    Inbound,
+}
+
+/// A destination for relay message records, which can be attached to one or
+/// more circuits.
+///
+/// Records from every attached circuit are written to the same underlying
+/// writer, and can be told apart by their circuit identifier.
+///
+/// The writer is owned by a background thread, which exits once every
+/// handle to the capture has been dropped and every queued record has been
+/// written.
+pub struct RelayMsgCapture {
+    /// Sender for the records that the writer thread should write.
+    ///
+    /// Sending fails once the writer thread has stopped because a write
+    /// failed.
+    tx: mpsc::SyncSender<WriterMsg>,
+    /// Whether to record message bodies.
+    include_payloads: bool,
+    /// Whether we have already warned about dropping records.
+    warned_full: AtomicBool,
+}
+
+/// A message to the writer thread of a [`RelayMsgCapture`].
+enum WriterMsg {
+    /// Write this encoded record.
+    Record(Vec<u8>),
+    /// Tell us, on this sender, once every earlier record has been written.
+    Sync(mpsc::Sender<()>),
+}
+
+impl RelayMsgCapture {
+    /// Start a new capture that records message metadata, but no message
+    /// bodies, to `out`.
+    ///
+    /// The capture header is written immediately.
+    pub fn new<W: io::Write + Send + 'static>(out: W) -> io::Result<Self> {
+        Self::new_inner(Box::new(out), false)
+    }
+
+    /// Start a new capture that records message metadata and message bodies
+    /// to `out`.
+    ///
+    /// Message bodies can include the user's traffic and other sensitive
+    /// information: use this only when debugging.
+    pub fn with_payloads<W: io::Write + Send + 'static>(out: W) -> io::Result<Self> {
+        Self::new_inner(Box::new(out), true)
+    }
+
+    /// Helper: write the capture header to `out` and construct a capture.
+    fn new_inner(mut out: Box<dyn io::Write + Send>, include_payloads: bool) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(11);
+        header.write_all(MAGIC);
+        header.write_u16(VERSION);
+        header.write_u8(if include_payloads { FLAG_PAYLOADS } else { 0 });
+        out.write_all(&header)?;
+        out.flush()?;
+
+        let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+        thread::Builder::new()
+            .name("relay-msg-capture".into())
+            .spawn(move || run_writer(out, rx))?;
+        Ok(RelayMsgCapture {
+            tx,
+            include_payloads,
+            warned_full: AtomicBool::new(false),
+        })
+    }
+
+    /// Return true if this capture records message bodies.
+    pub fn includes_payloads(&self) -> bool {
+        self.include_payloads
+    }
+
+    /// Wait until every record made so far has been written and flushed.
+    ///
+    /// This blocks the calling thread, so it must not be called from within
+    /// an asynchronous task.  Returns an error if the capture has been
+    /// disabled because a write failed.
+    pub fn sync(&self) -> io::Result<()> {
+        let stopped = || io::Error::new(io::ErrorKind::BrokenPipe, "relay message capture failed");
+        let (done_tx, done_rx) = mpsc::channel();
+        self.tx
+            .send(WriterMsg::Sync(done_tx))
+            .map_err(|_| stopped())?;
+        done_rx.recv().map_err(|_| stopped())
+    }
+
+    /// Record a single relay message.
+    ///
+    /// This never blocks: the record is queued for the writer thread, or
+    /// dropped (with a warning, the first time) if that thread is too far
+    /// behind.  Failures to write are logged by the writer thread; after
+    /// that, the capture is disabled.  They never affect the circuit.
+    pub(crate) fn record(
+        &self,
+        circ: UniqId,
+        direction: CaptureDirection,
+        hop: HopNum,
+        stream_id: Option<StreamId>,
+        msg: AnyRelayMsg,
+    ) {
+        let cmd = msg.cmd();
+        let mut body = Vec::new();
+        if let Err(e) = msg.encode_onto(&mut body) {
+            warn!(
+                "{}: Unable to encode {} message for capture: {}",
+                circ, cmd, e
+            );
+            return;
+        }
+        let rec = CapturedRelayMsg {
+            timestamp: SystemTime::now(),
+            direction,
+            hop,
+            cmd,
+            stream_id,
+            body_len: body.len(),
+            circ: circ.display_chan_circ().to_string(),
+            body: self.include_payloads.then_some(body),
+        };
+
+        match self.tx.try_send(WriterMsg::Record(rec.encode())) {
+            Ok(()) => {}
+            Err(mpsc::TrySendError::Full(_)) => {
+                if !self.warned_full.swap(true, Ordering::Relaxed) {
+                    warn!(
+                        "{}: Relay message capture is falling behind; dropping records",
+                        circ
+                    );
+                }
+            }
+            // The writer thread has already logged why it stopped.
+            Err(mpsc::TrySendError::Disconnected(_)) => {}
+        }
+    }
+}
+
+/// Body of the writer thread of a [`RelayMsgCapture`]: write every record
+/// that we receive on `rx` to `out`, until every sender is gone or a write
+/// fails.
+fn run_writer(mut out: Box<dyn io::Write + Send>, rx: mpsc::Receiver<WriterMsg>) {
+    for msg in rx {
+        match msg {
+            WriterMsg::Record(rec) => {
+                if let Err(e) = out.write_all(&rec).and_then(|()| out.flush()) {
+                    warn!("Relay message capture failed; disabling it: {}", e);
+                    return;
+                }
+            }
+            WriterMsg::Sync(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+impl fmt::Debug for RelayMsgCapture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RelayMsgCapture")
+            .field("include_payloads", &self.include_payloads)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A single relay message, as read back from a capture.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct CapturedRelayMsg {
+    /// When the message was sent or received.
+    pub timestamp: SystemTime,
+    /// Whether the message was sent or received.
+    pub direction: CaptureDirection,
+    /// The hop that sent the message, or that the message was sent to.
+    pub hop: HopNum,
+    /// The relay command of the message.
+    pub cmd: RelayCmd,
+    /// The stream that the message belongs to, if any.
+    pub stream_id: Option<StreamId>,
+    /// The length of the encoded message body.
+    pub body_len: usize,
+    /// An identifier for the circuit, unique within the process that made the
+    /// capture.
+    pub circ: String,
+    /// The encoded message body, if the capture included payloads.
+    pub body: Option<Vec<u8>>,
+}
+
+impl CapturedRelayMsg {
+    /// Encode this record, including its length prefix.
+    fn encode(&self) -> Vec<u8> {
+        let micros = self
+            .timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros();
+        let circ = self.circ.as_bytes();
+        let circ = &circ[..circ.len().min(u8::MAX.into())];
+
+        let mut rec = Vec::new();
+        rec.write_u64(micros.try_into().unwrap_or(u64::MAX));
+        rec.write_u8(match self.direction {
+            CaptureDirection::Outbound => 0,
+            CaptureDirection::Inbound => 1,
+        });
+        rec.write_u8(self.hop.into());
+        rec.write_u8(self.cmd.into());
+        rec.write_u16(StreamId::get_or_zero(self.stream_id));
+        rec.write_u16(self.body_len.try_into().unwrap_or(u16::MAX));
+        rec.write_u8(circ.len().try_into().expect("circuit id was truncated"));
+        rec.write_all(circ);
+        if let Some(body) = &self.body {
+            rec.write_all(body);
+        }
+
+        let mut out = Vec::with_capacity(rec.len() + 4);
+        out.write_u32(rec.len().try_into().expect("record too long"));
+        out.write_all(&rec);
+        out
+    }
+
+    /// Decode a record (without its length prefix) from `rec`.
+    fn decode(rec: &[u8], include_payloads: bool) -> tor_bytes::Result<Self> {
+        let mut r = Reader::from_slice(rec);
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_micros(r.take_u64()?);
+        let direction = match r.take_u8()? {
+            0 => CaptureDirection::Outbound,
+            1 => CaptureDirection::Inbound,
+            _ => return Err(tor_bytes::Error::InvalidMessage("bad direction".into())),
+        };
+        let hop = r.take_u8()?.into();
+        let cmd = r.take_u8()?.into();
+        let stream_id = StreamId::new(r.take_u16()?);
+        let body_len = r.take_u16()?.into();
+        let circ_len = r.take_u8()?.into();
+        let circ = String::from_utf8_lossy(r.take(circ_len)?).into_owned();
+        let body = if include_payloads {
+            Some(r.take(body_len)?.to_vec())
+        } else {
+            None
+        };
+        r.should_be_exhausted()?;
+        Ok(CapturedRelayMsg {
+            timestamp,
+            direction,
+            hop,
+            cmd,
+            stream_id,
+            body_len,
+            circ,
+            body,
+        })
+    }
+}
+
+impl Display for CapturedRelayMsg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since_epoch = self
+            .timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let arrow = match self.direction {
+            CaptureDirection::Outbound => "->",
+            CaptureDirection::Inbound => "<-",
+        };
+        write!(
+            f,
+            "{}.{:06} {} {} hop {} {} stream {} len {}",
+            since_epoch.as_secs(),
+            since_epoch.subsec_micros(),
+            self.circ,
+            arrow,
+            self.hop.display(),
+            self.cmd,
+            StreamId::get_or_zero(self.stream_id),
+            self.body_len,
+        )?;
+        if let Some(body) = &self.body {
+            write!(f, " ")?;
+            for b in body {
+                write!(f, "{:02x}", b)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A reader for captures written by [`RelayMsgCapture`].
+///
+/// This is an iterator over the records in the capture.
+#[derive(Debug)]
+pub struct CaptureReader<R> {
+    /// The underlying reader, positioned at the start of a record.
+    inner: R,
+    /// Whether the records include message bodies.
+    include_payloads: bool,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Start reading a capture from `inner`.
+    ///
+    /// Fails if `inner` does not begin with a capture header that we
+    /// understand.
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut header = [0_u8; 11];
+        inner.read_exact(&mut header)?;
+        let mut r = Reader::from_slice(&header);
+        let (magic, version, flags) =
+            (|| Ok::<_, tor_bytes::Error>((r.take(MAGIC.len())?, r.take_u16()?, r.take_u8()?)))()
+                .map_err(invalid_data)?;
+        if magic != MAGIC {
+            return Err(invalid_data("not a relay message capture"));
+        }
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported capture version {}",
+                version
+            )));
+        }
+        Ok(CaptureReader {
+            inner,
+            include_payloads: flags & FLAG_PAYLOADS != 0,
+        })
+    }
+
+    /// Return true if the records in this capture include message bodies.
+    pub fn includes_payloads(&self) -> bool {
+        self.include_payloads
+    }
+
+    /// Read the next record, or return `None` at the end of the capture.
+    fn read_record(&mut self) -> io::Result<Option<CapturedRelayMsg>> {
+        let mut len = [0_u8; 4];
+        // Distinguish a clean end of the capture from a truncated record.
+        match self.inner.read(&mut len[..1])? {
+            0 => return Ok(None),
+            _ => self.inner.read_exact(&mut len[1..])?,
+        }
+        let len = u32::from_be_bytes(len);
+        if len > MAX_RECORD_LEN {
+            return Err(invalid_data(format!("record of {} bytes is too long", len)));
+        }
+        let mut rec = vec![0_u8; len as usize];
+        self.inner.read_exact(&mut rec)?;
+        CapturedRelayMsg::decode(&rec, self.include_payloads)
+            .map(Some)
+            .map_err(invalid_data)
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<CapturedRelayMsg>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Helper: construct an `InvalidData` I/O error.
+fn invalid_data<E>(e: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tor_cell::relaycell::msg;
+
+    /// A writer that appends to a shared buffer, so we can inspect it later.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Record a few messages on `capture`.
+    fn record_some(capture: &RelayMsgCapture) {
+        let circ = UniqId::new(7, 3);
+        capture.record(
+            circ,
+            CaptureDirection::Outbound,
+            2.into(),
+            StreamId::new(5),
+            msg::Data::new(b"GET / HTTP/1.0\r\n\r\n").unwrap().into(),
+        );
+        capture.record(
+            circ,
+            CaptureDirection::Inbound,
+            0.into(),
+            None,
+            msg::Sendme::new_empty().into(),
+        );
+    }
+
+    #[test]
+    fn roundtrip_metadata() {
+        let buf = SharedBuf::default();
+        let capture = RelayMsgCapture::new(buf.clone()).unwrap();
+        assert!(!capture.includes_payloads());
+        record_some(&capture);
+        capture.sync().unwrap();
+
+        let bytes = buf.0.lock().unwrap().clone();
+        let reader = CaptureReader::new(&bytes[..]).unwrap();
+        assert!(!reader.includes_payloads());
+        let recs: Vec<_> = reader.collect::<io::Result<_>>().unwrap();
+        assert_eq!(recs.len(), 2);
+
+        assert_eq!(recs[0].direction, CaptureDirection::Outbound);
+        assert_eq!(u8::from(recs[0].hop), 2);
+        assert_eq!(recs[0].cmd, RelayCmd::DATA);
+        assert_eq!(recs[0].stream_id, StreamId::new(5));
+        assert_eq!(recs[0].body_len, 18);
+        assert_eq!(recs[0].circ, "7.3");
+        assert!(recs[0].body.is_none());
+
+        assert_eq!(recs[1].direction, CaptureDirection::Inbound);
+        assert_eq!(recs[1].cmd, RelayCmd::SENDME);
+        assert_eq!(recs[1].stream_id, None);
+
+        let line = recs[0].to_string();
+        assert!(
+            line.ends_with(" 7.3 -> hop #3 DATA stream 5 len 18"),
+            "{}",
+            line
+        );
+    }
+
+    #[test]
+    fn roundtrip_payloads() {
+        let buf = SharedBuf::default();
+        let capture = RelayMsgCapture::with_payloads(buf.clone()).unwrap();
+        record_some(&capture);
+        capture.sync().unwrap();
+
+        let bytes = buf.0.lock().unwrap().clone();
+        let reader = CaptureReader::new(&bytes[..]).unwrap();
+        assert!(reader.includes_payloads());
+        let recs: Vec<_> = reader.collect::<io::Result<_>>().unwrap();
+        assert_eq!(
+            recs[0].body.as_deref(),
+            Some(&b"GET / HTTP/1.0\r\n\r\n"[..])
+        );
+        assert!(recs[0]
+            .to_string()
+            .ends_with(" 474554202f20485454502f312e300d0a0d0a"));
+    }
+
+    #[test]
+    fn bad_captures() {
+        assert!(CaptureReader::new(&b"arti-rm"[..]).is_err());
+        assert!(CaptureReader::new(&b"not-arti\x00\x01\x00"[..]).is_err());
+        assert!(CaptureReader::new(&b"arti-rmc\x00\x02\x00"[..]).is_err());
+
+        // A truncated record is an error, not a clean end.
+        let buf = SharedBuf::default();
+        let capture = RelayMsgCapture::new(buf.clone()).unwrap();
+        record_some(&capture);
+        capture.sync().unwrap();
+        let bytes = buf.0.lock().unwrap().clone();
+        let truncated = &bytes[..bytes.len() - 1];
+        let res: io::Result<Vec<_>> = CaptureReader::new(truncated).unwrap().collect();
+        assert!(res.is_err());
+    }
+
+    /// A writer that fails once its header has been written.
+    struct FailingWriter(usize);
+
+    impl io::Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0 == 0 {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "disk full"));
+            }
+            let n = buf.len().min(self.0);
+            self.0 -= n;
+            Ok(n)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_failure() {
+        let capture = RelayMsgCapture::new(FailingWriter(11)).unwrap();
+        capture.sync().unwrap();
+        record_some(&capture);
+        // The writer thread stops after the first failed write, and recording
+        // more messages is harmless.
+        assert!(capture.sync().is_err());
+        record_some(&capture);
+    }
+}
@@ -48,7 +48,7 @@ impl HalfStream {
     /// END cells to this method.
     /// no ends here.
     pub(super) fn handle_msg(&mut self, msg: UnparsedRelayMsg) -> Result<StreamStatus> {
-        use tor_cell::relaycell::msg::Sendme;
+        use tor_cell::relaycell::msg::{Sendme, Xoff, Xon};
         use StreamStatus::*;
         if msg.cmd() == RelayCmd::SENDME {
             // We handle SENDME separately, and don't give it to the checker.
@@ -58,6 +58,21 @@ impl HalfStream {
             self.sendw.put(Some(()))?;
             return Ok(Open);
         }
+        // We aren't sending anything more on this stream, so we don't care
+        // whether the other side wants us to stop; we only check that these
+        // are well-formed.
+        if msg.cmd() == RelayCmd::XOFF {
+            let _ = msg
+                .decode::<Xoff>()
+                .map_err(|e| Error::from_bytes_err(e, "XOFF on half-closed stream"))?;
+            return Ok(Open);
+        }
+        if msg.cmd() == RelayCmd::XON {
+            let _ = msg
+                .decode::<Xon>()
+                .map_err(|e| Error::from_bytes_err(e, "XON on half-closed stream"))?;
+            return Ok(Open);
+        }
 
         if cmd_counts_towards_windows(msg.cmd()) {
             self.recvw.take()?;
@@ -205,6 +220,23 @@ mod test {
         );
     }
 
+    #[test]
+    fn halfstream_xon_xoff() {
+        let mut hs = hs_new();
+        let mut rng = testing_rng();
+        // These are ignored, however many arrive.
+        for _ in 0_u8..3 {
+            let st = hs
+                .handle_msg(to_unparsed(&mut rng, msg::Xoff::new().into()))
+                .unwrap();
+            assert_eq!(st, StreamStatus::Open);
+            let st = hs
+                .handle_msg(to_unparsed(&mut rng, msg::Xon::new(100).into()))
+                .unwrap();
+            assert_eq!(st, StreamStatus::Open);
+        }
+    }
+
     #[test]
     fn halfstream_other() {
         let mut hs = hs_new();
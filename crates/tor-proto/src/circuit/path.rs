@@ -67,6 +67,9 @@ impl PathEntry {
     /// a reference to a ChanTarget representing that instance.
     ///
     /// Otherwise, return None.
+    ///
+    /// The returned target can be used to look up this hop's identities and
+    /// addresses; to find its flags, look it up in a network directory.
     pub fn as_chan_target(&self) -> Option<&impl tor_linkspec::ChanTarget> {
         match &self.inner {
             HopDetail::Relay(chan_target) => Some(chan_target),
@@ -74,6 +77,16 @@ impl PathEntry {
             HopDetail::Virtual => None,
         }
     }
+
+    /// Return true if this is a virtual hop, representing the cryptographic
+    /// connection between a client and an onion service.
+    pub fn is_virtual(&self) -> bool {
+        match &self.inner {
+            HopDetail::Relay(_) => false,
+            #[cfg(feature = "hs-common")]
+            HopDetail::Virtual => true,
+        }
+    }
 }
 
 /// A circuit's path through the network.
@@ -89,6 +102,30 @@ pub struct Path {
     hops: Vec<PathEntry>,
 }
 
+impl Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, hop) in self.hops.iter().enumerate() {
+            if idx > 0 {
+                write!(f, " -> ")?;
+            }
+            write!(f, "{}", hop)?;
+        }
+        Ok(())
+    }
+}
+
+impl Redactable for Path {
+    fn display_redacted(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, hop) in self.hops.iter().enumerate() {
+            if idx > 0 {
+                write!(f, " -> ")?;
+            }
+            hop.display_redacted(f)?;
+        }
+        Ok(())
+    }
+}
+
 impl Path {
     /// Return the number of hops in this path
     pub fn n_hops(&self) -> usize {
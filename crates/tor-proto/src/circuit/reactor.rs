@@ -21,11 +21,13 @@ pub(super) mod syncview;
 use super::handshake::RelayCryptLayerProtocol;
 use super::streammap::{EndSentStreamEnt, OpenStreamEnt, ShouldSendEnd, StreamEntMut};
 use super::MutableState;
+#[cfg(feature = "relay-msg-capture")]
+use crate::circuit::capture::{CaptureDirection, RelayMsgCapture};
 use crate::circuit::celltypes::{ClientCircChanMsg, CreateResponse};
 use crate::circuit::handshake::{BoxedClientLayer, HandshakeRole};
 use crate::circuit::unique_id::UniqId;
 use crate::circuit::{
-    sendme, streammap, CircParameters, Create2Wrap, CreateFastWrap, CreateHandshakeWrap,
+    sendme, streammap, CircParameters, Create2Wrap, CreateFastWrap, CreateHandshakeWrap, HopTiming,
 };
 use crate::crypto::binding::CircuitBinding;
 use crate::crypto::cell::{
@@ -35,8 +37,8 @@ use crate::crypto::cell::{
 use crate::crypto::handshake::fast::CreateFastClient;
 #[cfg(feature = "ntor_v3")]
 use crate::crypto::handshake::ntor_v3::{NtorV3Client, NtorV3PublicKey};
-use crate::stream::{AnyCmdChecker, StreamStatus};
-use crate::util::err::{ChannelClosed, ReactorError};
+use crate::stream::{AnyCmdChecker, StreamFlowCtrl, StreamStatus};
+use crate::util::err::{ChannelClosed, CircCloseReason, ReactorError};
 use crate::util::sometimes_unbounded_sink::SometimesUnboundedSink;
 use crate::util::SinkExt as _;
 use crate::{Error, Result};
@@ -44,11 +46,12 @@ use std::borrow::Borrow;
 use std::collections::VecDeque;
 use std::marker::PhantomData;
 use std::pin::Pin;
+use std::time::{Duration, Instant};
 use tor_cell::chancell::msg::{AnyChanMsg, HandshakeType, Relay};
-use tor_cell::relaycell::msg::{AnyRelayMsg, End, Sendme};
+use tor_cell::relaycell::msg::{AnyRelayMsg, End, Sendme, Xoff, Xon};
 use tor_cell::relaycell::{
-    AnyRelayMsgOuter, RelayCellDecoder, RelayCellFormat, RelayCellFormatTrait, RelayCellFormatV0,
-    RelayCmd, StreamId, UnparsedRelayMsg,
+    AnyRelayMsgOuter, RelayCellDecoder, RelayCellEncoder, RelayCellFormat, RelayCellFormatTrait,
+    RelayCellFormatV0, RelayCmd, StreamId, UnparsedRelayMsg,
 };
 #[cfg(feature = "hs-service")]
 use {
@@ -60,12 +63,13 @@ use futures::channel::mpsc;
 use futures::Stream;
 use futures::{Sink, StreamExt};
 use tor_async_utils::oneshot;
-use tor_error::internal;
+use tor_error::{internal, HasKind as _};
+use tor_events::events::{TorEvent, TorEventKind};
 
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 
-use crate::channel::{Channel, ChannelSender};
+use crate::channel::{liveness, Channel, ChannelSender};
 use crate::circuit::path;
 #[cfg(test)]
 use crate::circuit::sendme::CircTag;
@@ -224,6 +228,8 @@ pub(super) enum CtrlMsg {
         done: ReactorResultChannel<StreamId>,
         /// A `CmdChecker` to keep track of which message types are acceptable.
         cmd_checker: AnyCmdChecker,
+        /// Flow-control state for this stream, shared with its reader.
+        flow_ctrl: Arc<StreamFlowCtrl>,
     },
     /// Close the specified pending incoming stream, sending the provided END message.
     ///
@@ -292,6 +298,20 @@ pub(super) enum CtrlMsg {
         /// The hop number the stream is on.
         hop_num: HopNum,
     },
+    /// Send an XON cell for the specified stream, since its reader has
+    /// drained its buffer after we sent an XOFF.
+    SendXon {
+        /// The stream ID to send an XON for.
+        stream_id: StreamId,
+        /// The hop number the stream is on.
+        hop_num: HopNum,
+    },
+    /// Start or stop recording the relay messages on this circuit.
+    #[cfg(feature = "relay-msg-capture")]
+    SetRelayMsgCapture {
+        /// The capture to record messages to, or `None` to stop recording.
+        capture: Option<Arc<RelayMsgCapture>>,
+    },
     /// Shut down the reactor.
     Shutdown,
     /// (tests only) Add a hop to the list of hops on this circuit, with dummy cryptography.
@@ -341,8 +361,23 @@ pub(super) struct CircHop {
     /// NOTE: Control messages could potentially add unboundedly to this, although that's
     ///       not likely to happen (and isn't triggereable from the network, either).
     outbound: VecDeque<(bool, AnyRelayMsgOuter)>,
+    /// Encodes relay messages that we send to this hop into relay cells.
+    encoder: RelayCellEncoder,
     /// Decodes relay cells received from this hop.
     inbound: RelayCellDecoder,
+    /// Which kind of stream-level flow control this hop expects.
+    flow_ctrl_mode: StreamFlowCtrlMode,
+}
+
+/// The kind of stream-level flow control that a hop expects.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(super) enum StreamFlowCtrlMode {
+    /// Stream-level SENDME messages.
+    #[default]
+    Sendme,
+    /// XON and XOFF messages, since we negotiated congestion control with
+    /// this hop.
+    XonXoff,
 }
 
 /// An indicator on what we should do when we receive a cell for a circuit.
@@ -356,13 +391,19 @@ enum CellStatus {
 
 impl CircHop {
     /// Create a new hop.
-    pub(super) fn new(format: RelayCellFormat, initial_window: u16) -> Self {
+    pub(super) fn new(
+        format: RelayCellFormat,
+        initial_window: u16,
+        flow_ctrl_mode: StreamFlowCtrlMode,
+    ) -> Self {
         CircHop {
             map: streammap::StreamMap::new(),
             recvwindow: sendme::CircRecvWindow::new(1000),
             sendwindow: sendme::CircSendWindow::new(initial_window),
             outbound: VecDeque::new(),
+            encoder: RelayCellEncoder::new(format),
             inbound: RelayCellDecoder::new(format),
+            flow_ctrl_mode,
         }
     }
 }
@@ -455,6 +496,8 @@ where
     relay_cell_format: RelayCellFormat,
     /// A oneshot channel that we should inform when we are done with this extend operation.
     operation_finished: Option<oneshot::Sender<Result<()>>>,
+    /// When we sent the EXTEND2 cell.
+    started: Instant,
     /// `PhantomData` used to make the other type parameters required for a circuit extension
     /// part of the `struct`, instead of having them be provided during a function call.
     ///
@@ -528,6 +571,7 @@ where
                 unique_id,
                 expected_hop: hop,
                 operation_finished: None,
+                started: Instant::now(),
                 phantom: Default::default(),
                 relay_cell_format,
             })
@@ -574,7 +618,7 @@ where
 
         // Handle auxiliary data returned from the server, e.g. validating that
         // requested extensions have been acknowledged.
-        H::handle_server_aux_data(reactor, &self.params, &server_aux_data)?;
+        let flow_ctrl_mode = H::handle_server_aux_data(reactor, &self.params, &server_aux_data)?;
 
         let layer = L::construct(keygen)?;
 
@@ -589,6 +633,8 @@ where
             Box::new(layer_back),
             Some(binding),
             &self.params,
+            flow_ctrl_mode,
+            Some(self.started.elapsed()),
         );
         Ok(MetaCellDisposition::ConversationFinished)
     }
@@ -645,28 +691,54 @@ where
 trait HandshakeAuxDataHandler: ClientHandshake {
     /// Handle auxiliary handshake data returned when creating or extending a
     /// circuit.
+    ///
+    /// Return the kind of stream-level flow control that the new hop expects.
     fn handle_server_aux_data(
         reactor: &mut Reactor,
         params: &CircParameters,
         data: &<Self as ClientHandshake>::ServerAuxData,
-    ) -> Result<()>;
+    ) -> Result<StreamFlowCtrlMode>;
 }
 
 #[cfg(feature = "ntor_v3")]
 impl HandshakeAuxDataHandler for NtorV3Client {
     fn handle_server_aux_data(
         _reactor: &mut Reactor,
-        _params: &CircParameters,
+        params: &CircParameters,
         data: &Vec<NtorV3Extension>,
-    ) -> Result<()> {
-        // There are currently no accepted server extensions,
-        // particularly since we don't request any extensions yet.
-        if !data.is_empty() {
-            return Err(Error::HandshakeProto(
-                "Received unexpected ntorv3 extension".into(),
-            ));
+    ) -> Result<StreamFlowCtrlMode> {
+        // The server may only send extensions in reply to ones we sent.
+        let mut seen_cc_ack = false;
+        for ext in data {
+            match ext {
+                NtorV3Extension::AckCongestionControl { sendme_inc }
+                    if params.request_congestion_control() && !seen_cc_ack =>
+                {
+                    seen_cc_ack = true;
+                    // The valid range for cc_sendme_inc, from the consensus
+                    // parameter list in the spec.
+                    if !(1..=254).contains(sendme_inc) {
+                        return Err(Error::HandshakeProto(format!(
+                            "Invalid sendme_inc {} in congestion control ack",
+                            sendme_inc
+                        )));
+                    }
+                    // TODO: Use sendme_inc once we implement congestion control.
+                }
+                _ => {
+                    return Err(Error::HandshakeProto(
+                        "Received unexpected ntorv3 extension".into(),
+                    ));
+                }
+            }
         }
-        Ok(())
+        // A hop that has negotiated congestion control no longer uses
+        // stream-level SENDMEs (proposal 324).
+        Ok(if seen_cc_ack {
+            StreamFlowCtrlMode::XonXoff
+        } else {
+            StreamFlowCtrlMode::Sendme
+        })
     }
 }
 
@@ -675,9 +747,9 @@ impl HandshakeAuxDataHandler for NtorClient {
         _reactor: &mut Reactor,
         _params: &CircParameters,
         _data: &(),
-    ) -> Result<()> {
+    ) -> Result<StreamFlowCtrlMode> {
         // This handshake doesn't have any auxiliary data; nothing to do.
-        Ok(())
+        Ok(StreamFlowCtrlMode::default())
     }
 }
 
@@ -686,9 +758,9 @@ impl HandshakeAuxDataHandler for CreateFastClient {
         _reactor: &mut Reactor,
         _params: &CircParameters,
         _data: &(),
-    ) -> Result<()> {
+    ) -> Result<StreamFlowCtrlMode> {
         // This handshake doesn't have any auxiliary data; nothing to do.
-        Ok(())
+        Ok(StreamFlowCtrlMode::default())
     }
 }
 
@@ -738,6 +810,10 @@ pub struct Reactor {
     /// A handler for incoming stream requests.
     #[cfg(feature = "hs-service")]
     incoming_stream_req_handler: Option<IncomingStreamRequestHandler>,
+    /// If present, a capture to which we record every relay message that we
+    /// send or receive.
+    #[cfg(feature = "relay-msg-capture")]
+    capture: Option<Arc<RelayMsgCapture>>,
 }
 
 /// Information about an incoming stream request.
@@ -760,6 +836,8 @@ pub(super) struct StreamReqInfo {
     pub(super) receiver: mpsc::Receiver<UnparsedRelayMsg>,
     /// A channel for sending messages to be sent on this stream.
     pub(super) msg_tx: mpsc::Sender<AnyRelayMsg>,
+    /// Flow-control state for this stream, shared with its reader.
+    pub(super) flow_ctrl: Arc<StreamFlowCtrl>,
 }
 
 /// Data required for handling an incoming stream request.
@@ -802,7 +880,12 @@ impl Reactor {
         let (control_tx, control_rx) = mpsc::unbounded();
         let path = Arc::new(path::Path::default());
         let binding = Vec::new();
-        let mutable = Arc::new(Mutex::new(MutableState { path, binding }));
+        let mutable = Arc::new(Mutex::new(MutableState {
+            path,
+            binding,
+            close_reason: None,
+            hop_timings: Vec::new(),
+        }));
 
         let (reactor_closed_tx, reactor_closed_rx) = oneshot::channel();
 
@@ -822,6 +905,8 @@ impl Reactor {
             meta_handler: None,
             #[cfg(feature = "hs-service")]
             incoming_stream_req_handler: None,
+            #[cfg(feature = "relay-msg-capture")]
+            capture: None,
             mutable: mutable.clone(),
         };
 
@@ -843,6 +928,18 @@ impl Reactor {
             }
         };
         trace!("{}: Circuit reactor stopped: {:?}", self.unique_id, result);
+        if tor_events::event_has_subscribers(TorEventKind::CircuitClosed) {
+            // We report only the error kind, since the error itself might
+            // mention an address.
+            let reason = match &result {
+                Ok(()) => "closed".to_string(),
+                Err(e) => e.kind().to_string(),
+            };
+            tor_events::broadcast(TorEvent::CircuitClosed {
+                circuit: self.unique_id.to_string(),
+                reason,
+            });
+        }
         result
     }
 
@@ -950,10 +1047,14 @@ impl Reactor {
                         self.send_relay_cell(cx, hop_num, early, cell)?;
                     }
                     let hop = &mut self.hops[i];
+                    let xon_xoff = hop.flow_ctrl_mode == StreamFlowCtrlMode::XonXoff;
                     // Look at all of the streams on this hop.
                     for (id, stream) in hop.map.iter_mut() {
                         let StreamEntMut::Open(OpenStreamEnt {
-                            rx, send_window, ..
+                            rx,
+                            send_window,
+                            xoff_received,
+                            ..
                         }) = stream
                         else {
                             continue;
@@ -973,7 +1074,16 @@ impl Reactor {
                         // messages that *do*; e.g. we wouldn't want to
                         // accept and send an END message on a stream where we
                         // still have DATA messages queued.
-                        if send_window.window() > 0 && hop.sendwindow.window() > 0 {
+                        //
+                        // On a hop that uses XON/XOFF, there is no stream
+                        // window; instead we stop once the other side sends
+                        // an XOFF.
+                        let stream_may_send = if xon_xoff {
+                            !*xoff_received
+                        } else {
+                            send_window.window() > 0
+                        };
+                        if stream_may_send && hop.sendwindow.window() > 0 {
                             match Pin::new(rx).poll_next(cx) {
                                 Poll::Ready(Some(m)) => {
                                     stream_relaycells
@@ -1140,6 +1250,12 @@ impl Reactor {
         let fwd = Box::new(DummyCrypto::new(fwd_lasthop));
         let rev = Box::new(DummyCrypto::new(rev_lasthop));
         let binding = None;
+        // A fake hop accepts whatever we ask for.
+        let flow_ctrl_mode = if params.request_congestion_control() {
+            StreamFlowCtrlMode::XonXoff
+        } else {
+            StreamFlowCtrlMode::Sendme
+        };
         self.add_hop(
             format,
             path::HopDetail::Relay(dummy_peer_id),
@@ -1147,6 +1263,8 @@ impl Reactor {
             rev,
             binding,
             params,
+            flow_ctrl_mode,
+            None,
         );
         let _ = done.send(Ok(()));
     }
@@ -1187,6 +1305,7 @@ impl Reactor {
             self.unique_id,
             create_cell.cmd()
         );
+        let started = Instant::now();
         self.send_msg(create_cell).await?;
 
         let reply = recvcreated
@@ -1196,7 +1315,7 @@ impl Reactor {
         let relay_handshake = wrap.decode_chanmsg(reply)?;
         let (server_msg, keygen) = H::client2(state, relay_handshake)?;
 
-        H::handle_server_aux_data(self, params, &server_msg)?;
+        let flow_ctrl_mode = H::handle_server_aux_data(self, params, &server_msg)?;
 
         let relay_cell_format = cell_protocol.relay_cell_format();
         let BoxedClientLayer { fwd, back, binding } =
@@ -1213,6 +1332,8 @@ impl Reactor {
             back,
             binding,
             params,
+            flow_ctrl_mode,
+            Some(started.elapsed()),
         );
         Ok(())
     }
@@ -1292,9 +1413,7 @@ impl Reactor {
         // TODO: Add support for negotiating other formats.
         let relay_cell_protocol = RelayCryptLayerProtocol::Tor1(RelayCellFormat::V0);
 
-        // TODO: Set client extensions. e.g. request congestion control
-        // if specified in `params`.
-        let client_extensions = [];
+        let client_extensions = params.ntor_v3_client_extensions();
 
         let wrap = Create2Wrap {
             handshake_type: HandshakeType::NTOR_V3,
@@ -1312,6 +1431,10 @@ impl Reactor {
     }
 
     /// Add a hop to the end of this circuit.
+    ///
+    /// `handshake_rtt` is how long the handshake that added this hop took,
+    /// if there was one.
+    #[allow(clippy::too_many_arguments)]
     fn add_hop(
         &mut self,
         format: RelayCellFormat,
@@ -1320,14 +1443,24 @@ impl Reactor {
         rev: Box<dyn InboundClientLayer + 'static + Send>,
         binding: Option<CircuitBinding>,
         params: &CircParameters,
+        flow_ctrl_mode: StreamFlowCtrlMode,
+        handshake_rtt: Option<Duration>,
     ) {
-        let hop = crate::circuit::reactor::CircHop::new(format, params.initial_send_window());
+        let hop = crate::circuit::reactor::CircHop::new(
+            format,
+            params.initial_send_window(),
+            flow_ctrl_mode,
+        );
         self.hops.push(hop);
         self.crypto_in.add_layer(rev);
         self.crypto_out.add_layer(fwd);
         let mut mutable = self.mutable.lock().expect("poisoned lock");
         Arc::make_mut(&mut mutable.path).push_hop(peer_id);
         mutable.binding.push(binding);
+        mutable.hop_timings.push(HopTiming {
+            handshake_rtt,
+            completed_at: Instant::now(),
+        });
     }
 
     /// Handle a RELAY cell on this circuit with stream ID 0.
@@ -1367,6 +1500,10 @@ impl Reactor {
                 reason.human_str(),
                 reason
             );
+            self.note_close_reason(CircCloseReason::Truncated {
+                hop: hopnum,
+                reason,
+            });
 
             return Ok(CellStatus::CleanShutdown);
         }
@@ -1495,6 +1632,7 @@ impl Reactor {
         msg: AnyRelayMsgOuter,
     ) -> Result<()> {
         let c_t_w = sendme::cmd_counts_towards_windows(msg.cmd());
+        let expects_response = liveness::relay_cmd_expects_response(msg.cmd());
         let stream_id = msg.stream_id();
         // Check whether the hop send window is empty, if this cell counts towards windows.
         // NOTE(eta): It is imperative this happens *before* calling encrypt() below, otherwise
@@ -1516,11 +1654,43 @@ impl Reactor {
                 return Ok(());
             }
         }
-        let mut body: RelayCellBody = msg
-            .encode(&mut rand::thread_rng())
-            .map_err(|e| Error::from_cell_enc(e, "relay cell body"))?
-            .into();
+        #[cfg(feature = "relay-msg-capture")]
+        if let Some(capture) = &self.capture {
+            capture.record(
+                self.unique_id,
+                CaptureDirection::Outbound,
+                hop,
+                stream_id,
+                msg.msg().clone(),
+            );
+        }
+        let encoder = &mut self
+            .hops
+            .get_mut(Into::<usize>::into(hop))
+            .ok_or_else(|| Error::CircProto(format!("Couldn't find hop {}", hop.display())))?
+            .encoder;
+        encoder
+            .push(msg)
+            .map_err(|e| Error::from_cell_enc(e, "relay cell body"))?;
+        let body = encoder
+            .take_cell(&mut rand::thread_rng())
+            .ok_or_else(|| Error::from(internal!("Encoded relay message produced no cell")))?;
+        // TODO prop340: Once we negotiate a format that allows it, we should
+        // pack queued messages together, and let long messages span several
+        // cells.  Until then, every message must fit in exactly one cell.
+        if encoder.has_pending() {
+            return Err(Error::from(internal!(
+                "Relay message did not fit in a single cell"
+            )));
+        }
+        let mut body: RelayCellBody = body.into();
         let tag = self.crypto_out.encrypt(&mut body, hop)?;
+        if expects_response {
+            // Tell the channel first, so that it starts its clock no later
+            // than it sends the cell.  (If the channel has closed, we'll find
+            // out when we try to send the cell.)
+            let _ = self.channel.note_response_expected();
+        }
         // NOTE(eta): Now that we've encrypted the cell, we *must* either send it or abort
         //            the whole circuit (e.g. by returning an error).
         let msg = chancell::msg::Relay::from(BoxedCellBody::from(body));
@@ -1536,6 +1706,8 @@ impl Reactor {
             let hop = &mut self.hops[hop_num];
             // checked by earlier conditional, so this shouldn't fail
             hop.sendwindow.take(tag)?;
+            // Hops that use XON/XOFF have no stream-level window.
+            let xon_xoff = hop.flow_ctrl_mode == StreamFlowCtrlMode::XonXoff;
             if let Some(stream_id) = stream_id {
                 // We need to decrement the stream-level sendme window.
                 // Stream data cells should only be dequeued and fed into this function if
@@ -1543,7 +1715,9 @@ impl Reactor {
                 // enqueuing things.
                 match hop.map.get_mut(stream_id) {
                     Some(StreamEntMut::Open(OpenStreamEnt { send_window, .. })) => {
-                        send_window.take(&())?;
+                        if !xon_xoff {
+                            send_window.take(&())?;
+                        }
                     }
                     _ => {
                         warn!(
@@ -1635,8 +1809,7 @@ impl Reactor {
                 /// Local type alias to ensure consistency below.
                 type Rcf = RelayCellFormatV0;
 
-                // TODO: Set extensions, e.g. based on `params`.
-                let client_extensions = [];
+                let client_extensions = params.ntor_v3_client_extensions();
 
                 let extender = CircuitExtender::<NtorV3Client, Tor1RelayCrypto<Rcf>, _, _>::begin(
                     cx,
@@ -1666,7 +1839,16 @@ impl Reactor {
                 // describe why the virtual hop was added, or something?
                 let peer_id = path::HopDetail::Virtual;
 
-                self.add_hop(format, peer_id, outbound, inbound, binding, &params);
+                self.add_hop(
+                    format,
+                    peer_id,
+                    outbound,
+                    inbound,
+                    binding,
+                    &params,
+                    StreamFlowCtrlMode::default(),
+                    None,
+                );
                 let _ = done.send(Ok(()));
             }
             CtrlMsg::BeginStream {
@@ -1676,8 +1858,10 @@ impl Reactor {
                 rx,
                 done,
                 cmd_checker,
+                flow_ctrl,
             } => {
-                let ret = self.begin_stream(cx, hop_num, message, sender, rx, cmd_checker);
+                let ret =
+                    self.begin_stream(cx, hop_num, message, sender, rx, cmd_checker, flow_ctrl);
                 let _ = done.send(ret); // don't care if sender goes away
             }
             #[cfg(feature = "hs-service")]
@@ -1721,6 +1905,16 @@ impl Reactor {
                 let cell = AnyRelayMsgOuter::new(Some(stream_id), sendme.into());
                 self.send_relay_cell(cx, hop_num, false, cell)?;
             }
+            CtrlMsg::SendXon { stream_id, hop_num } => {
+                // TODO: Report our actual drain rate once we track it.
+                let xon = Xon::new(0);
+                let cell = AnyRelayMsgOuter::new(Some(stream_id), xon.into());
+                self.send_relay_cell(cx, hop_num, false, cell)?;
+            }
+            #[cfg(feature = "relay-msg-capture")]
+            CtrlMsg::SetRelayMsgCapture { capture } => {
+                self.capture = capture;
+            }
             #[cfg(feature = "send-control-msg")]
             CtrlMsg::SendMsg {
                 hop_num,
@@ -1791,6 +1985,7 @@ impl Reactor {
 
     /// Start a stream. Creates an entry in the stream map with the given channels, and sends the
     /// `message` to the provided hop.
+    #[allow(clippy::too_many_arguments)]
     fn begin_stream(
         &mut self,
         cx: &mut Context<'_>,
@@ -1799,12 +1994,16 @@ impl Reactor {
         sender: mpsc::Sender<UnparsedRelayMsg>,
         rx: mpsc::Receiver<AnyRelayMsg>,
         cmd_checker: AnyCmdChecker,
+        flow_ctrl: Arc<StreamFlowCtrl>,
     ) -> Result<StreamId> {
         let hop = self
             .hop_mut(hopnum)
             .ok_or_else(|| Error::from(internal!("No such hop {}", hopnum.display())))?;
+        flow_ctrl.set_xon_xoff(hop.flow_ctrl_mode == StreamFlowCtrlMode::XonXoff);
         let send_window = StreamSendWindow::new(SEND_WINDOW_INIT);
-        let r = hop.map.add_ent(sender, rx, send_window, cmd_checker)?;
+        let r = hop
+            .map
+            .add_ent(sender, rx, send_window, flow_ctrl, cmd_checker)?;
         let cell = AnyRelayMsgOuter::new(Some(r), message);
         self.send_relay_cell(cx, hopnum, false, cell)?;
         Ok(r)
@@ -1867,6 +2066,7 @@ impl Reactor {
                     reason.human_str(),
                     reason
                 );
+                self.note_close_reason(CircCloseReason::Destroyed(reason));
 
                 self.handle_destroy_cell()?;
                 Ok(CellStatus::CleanShutdown)
@@ -1874,6 +2074,12 @@ impl Reactor {
         }
     }
 
+    /// Record that a relay closed this circuit for `reason`, so that our
+    /// users can find out why.
+    fn note_close_reason(&mut self, reason: CircCloseReason) {
+        self.mutable.lock().expect("poisoned lock").close_reason = Some(reason);
+    }
+
     /// React to a Relay or RelayEarly cell.
     fn handle_relay_cell(&mut self, cx: &mut Context<'_>, cell: Relay) -> Result<CellStatus> {
         let mut body = cell.into_relay_body().into();
@@ -1958,6 +2164,32 @@ impl Reactor {
         Ok(CellStatus::Continue)
     }
 
+    /// Record an incoming relay message on our capture, if we have one.
+    #[cfg(feature = "relay-msg-capture")]
+    fn capture_inbound(&self, hopnum: HopNum, msg: &UnparsedRelayMsg) {
+        let Some(capture) = &self.capture else {
+            return;
+        };
+        match msg.clone().decode::<AnyRelayMsg>() {
+            Ok(msg) => {
+                let (stream_id, msg) = msg.into_streamid_and_msg();
+                capture.record(
+                    self.unique_id,
+                    CaptureDirection::Inbound,
+                    hopnum,
+                    stream_id,
+                    msg,
+                );
+            }
+            Err(e) => debug!(
+                "{}: Not capturing undecodable {} message: {}",
+                self.unique_id,
+                msg.cmd(),
+                e
+            ),
+        }
+    }
+
     /// Handle a single incoming relay message.
     fn handle_relay_msg(
         &mut self,
@@ -1966,6 +2198,9 @@ impl Reactor {
         cell_counts_toward_windows: bool,
         msg: UnparsedRelayMsg,
     ) -> Result<CellStatus> {
+        #[cfg(feature = "relay-msg-capture")]
+        self.capture_inbound(hopnum, &msg);
+
         // If this msg wants/refuses to have a Stream ID, does it
         // have/not have one?
         let cmd = msg.cmd();
@@ -1987,16 +2222,43 @@ impl Reactor {
         let hop = self
             .hop_mut(hopnum)
             .ok_or_else(|| Error::CircProto("Cell from nonexistent hop!".into()))?;
+        let xon_xoff = hop.flow_ctrl_mode == StreamFlowCtrlMode::XonXoff;
+        let mut send_xoff = false;
         match hop.map.get_mut(streamid) {
             Some(StreamEntMut::Open(OpenStreamEnt {
                 sink,
                 send_window,
                 dropped,
                 cmd_checker,
+                flow_ctrl,
+                xoff_received,
                 ..
             })) => {
                 // The stream for this message exists, and is open.
 
+                if matches!(msg.cmd(), RelayCmd::XOFF | RelayCmd::XON) {
+                    if !xon_xoff {
+                        return Err(Error::CircProto(format!(
+                            "Received {} on a hop without congestion control",
+                            msg.cmd()
+                        )));
+                    }
+                    // Like SENDMEs, these need to be handled here rather than
+                    // by the stream's reader.
+                    if msg.cmd() == RelayCmd::XOFF {
+                        let _xoff = msg
+                            .decode::<Xoff>()
+                            .map_err(|e| Error::from_bytes_err(e, "Xoff message on stream"))?;
+                        *xoff_received = true;
+                    } else {
+                        let _xon = msg
+                            .decode::<Xon>()
+                            .map_err(|e| Error::from_bytes_err(e, "Xon message on stream"))?;
+                        *xoff_received = false;
+                    }
+                    return Ok(CellStatus::Continue);
+                }
+
                 if msg.cmd() == RelayCmd::SENDME {
                     let _sendme = msg
                         .decode::<Sendme>()
@@ -2011,8 +2273,12 @@ impl Reactor {
 
                 let message_closes_stream = cmd_checker.check_msg(&msg)? == StreamStatus::Closed;
 
-                if let Err(e) = sink.try_send(msg) {
-                    if e.is_full() {
+                match sink.try_send(msg) {
+                    Ok(()) if cell_counts_toward_windows => {
+                        send_xoff = flow_ctrl.queued();
+                    }
+                    Ok(()) => {}
+                    Err(e) if e.is_full() => {
                         // If we get here, we either have a logic bug (!), or an attacker
                         // is sending us more cells than we asked for via congestion control.
                         return Err(Error::CircProto(format!(
@@ -2020,12 +2286,14 @@ impl Reactor {
                             sv(streamid),
                         )));
                     }
-                    if e.is_disconnected() && cell_counts_toward_windows {
-                        // the other side of the stream has gone away; remember
-                        // that we received a cell that we couldn't queue for it.
-                        //
-                        // Later this value will be recorded in a half-stream.
-                        *dropped += 1;
+                    Err(e) => {
+                        if e.is_disconnected() && cell_counts_toward_windows {
+                            // the other side of the stream has gone away; remember
+                            // that we received a cell that we couldn't queue for it.
+                            //
+                            // Later this value will be recorded in a half-stream.
+                            *dropped += 1;
+                        }
                     }
                 }
                 if message_closes_stream {
@@ -2068,6 +2336,12 @@ impl Reactor {
                 ));
             }
         }
+        if send_xoff {
+            // The reader has fallen too far behind: ask the other side to
+            // stop sending on this stream until we send an XON.
+            let cell = AnyRelayMsgOuter::new(Some(streamid), Xoff::new().into());
+            self.send_relay_cell(cx, hopnum, false, cell)?;
+        }
         Ok(CellStatus::Continue)
     }
 
@@ -2158,8 +2432,16 @@ impl Reactor {
 
         let send_window = StreamSendWindow::new(SEND_WINDOW_INIT);
         let cmd_checker = DataCmdChecker::new_connected();
-        hop.map
-            .add_ent_with_id(sender, msg_rx, send_window, stream_id, cmd_checker)?;
+        let flow_ctrl = Arc::new(StreamFlowCtrl::new(Default::default()));
+        flow_ctrl.set_xon_xoff(hop.flow_ctrl_mode == StreamFlowCtrlMode::XonXoff);
+        hop.map.add_ent_with_id(
+            sender,
+            msg_rx,
+            send_window,
+            Arc::clone(&flow_ctrl),
+            stream_id,
+            cmd_checker,
+        )?;
 
         let outcome = handler
             .incoming_sender
@@ -2169,6 +2451,7 @@ impl Reactor {
                 hop_num,
                 msg_tx,
                 receiver,
+                flow_ctrl,
             })
             .map_err(|e| e.into_send_error());
 
@@ -4,7 +4,7 @@ mod counted_map;
 
 use crate::circuit::halfstream::HalfStream;
 use crate::circuit::sendme;
-use crate::stream::AnyCmdChecker;
+use crate::stream::{AnyCmdChecker, StreamFlowCtrl};
 use crate::{Error, Result};
 use tor_cell::relaycell::UnparsedRelayMsg;
 /// Mapping from stream ID to streams.
@@ -14,6 +14,7 @@ use tor_cell::relaycell::{msg::AnyRelayMsg, StreamId};
 
 use futures::channel::mpsc;
 use std::num::NonZeroU16;
+use std::sync::Arc;
 use tor_error::{bad_api_usage, internal};
 
 use rand::Rng;
@@ -35,6 +36,12 @@ pub(super) struct OpenStreamEnt {
     pub(super) rx: mpsc::Receiver<AnyRelayMsg>,
     /// Send window, for congestion control purposes.
     pub(super) send_window: sendme::StreamSendWindow,
+    /// Flow-control state for the data we deliver to `sink`, shared with the
+    /// stream's reader.
+    pub(super) flow_ctrl: Arc<StreamFlowCtrl>,
+    /// True if the other side has sent an XOFF on this stream, and we
+    /// should not send any more data until it sends an XON.
+    pub(super) xoff_received: bool,
     /// Number of cells dropped due to the stream disappearing before we can
     /// transform this into an `EndSent`.
     pub(super) dropped: u16,
@@ -156,12 +163,15 @@ impl StreamMap {
         sink: mpsc::Sender<UnparsedRelayMsg>,
         rx: mpsc::Receiver<AnyRelayMsg>,
         send_window: sendme::StreamSendWindow,
+        flow_ctrl: Arc<StreamFlowCtrl>,
         cmd_checker: AnyCmdChecker,
     ) -> Result<StreamId> {
         let stream_ent = StreamEnt::Open(OpenStreamEnt {
             sink,
             rx,
             send_window,
+            flow_ctrl,
+            xoff_received: false,
             dropped: 0,
             cmd_checker,
         });
@@ -189,6 +199,7 @@ impl StreamMap {
         sink: mpsc::Sender<UnparsedRelayMsg>,
         rx: mpsc::Receiver<AnyRelayMsg>,
         send_window: sendme::StreamSendWindow,
+        flow_ctrl: Arc<StreamFlowCtrl>,
         id: StreamId,
         cmd_checker: AnyCmdChecker,
     ) -> Result<()> {
@@ -196,6 +207,8 @@ impl StreamMap {
             sink,
             rx,
             send_window,
+            flow_ctrl,
+            xoff_received: false,
             dropped: 0,
             cmd_checker,
         });
@@ -383,6 +396,7 @@ mod test {
                 sink,
                 rx,
                 StreamSendWindow::new(500),
+                Arc::new(StreamFlowCtrl::new(Default::default())),
                 DataCmdChecker::new_any(),
             )?;
             let expect_id: StreamId = next_id;
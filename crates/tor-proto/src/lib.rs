@@ -47,13 +47,17 @@
     allow(unused_imports)
 )]
 
+#[cfg(feature = "bench")]
+pub mod bench_utils;
 pub mod channel;
 pub mod circuit;
 mod crypto;
 pub mod stream;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 mod util;
 
-pub use util::err::{Error, ResolveError};
+pub use util::err::{CircCloseReason, Error, ResolveError};
 pub use util::skew::ClockSkew;
 
 pub use channel::params::ChannelPaddingInstructions;
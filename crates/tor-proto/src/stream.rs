@@ -7,12 +7,15 @@
 //!
 //! # Limitations
 //!
-//! There is no fairness, rate-limiting, or flow control.
+//! There is no fairness or rate-limiting.  Flow control is limited to
+//! bounding how much data is buffered for each stream's reader; see
+//! [`StreamBufferWatermarks`].
 
 mod cmdcheck;
 #[cfg(feature = "stream-ctrl")]
 mod ctrl;
 mod data;
+mod flow_ctrl;
 #[cfg(feature = "hs-service")]
 mod incoming;
 mod params;
@@ -21,6 +24,8 @@ mod resolve;
 
 pub(crate) use cmdcheck::{AnyCmdChecker, CmdChecker, StreamStatus};
 pub use data::{DataReader, DataStream, DataWriter};
+pub(crate) use flow_ctrl::StreamFlowCtrl;
+pub use flow_ctrl::{StreamBufferStats, StreamBufferWatermarks};
 #[cfg(feature = "hs-service")]
 #[cfg_attr(docsrs, doc(cfg(feature = "hs-service")))]
 pub(crate) use incoming::IncomingCmdChecker;
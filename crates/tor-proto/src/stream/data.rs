@@ -20,6 +20,7 @@ use tor_cell::restricted_msg;
 
 use std::fmt::Debug;
 use std::io::Result as IoResult;
+use std::net::IpAddr;
 use std::pin::Pin;
 #[cfg(any(feature = "stream-ctrl", feature = "experimental-api"))]
 use std::sync::Arc;
@@ -33,6 +34,8 @@ use crate::circuit::ClientCirc;
 
 use crate::circuit::StreamTarget;
 use crate::stream::StreamReader;
+#[cfg(feature = "stream-ctrl")]
+use crate::stream::{StreamBufferStats, StreamFlowCtrl};
 use tor_basic_utils::skip_fmt;
 use tor_cell::relaycell::msg::Data;
 use tor_error::internal;
@@ -164,6 +167,9 @@ pub struct DataStreamCtrl {
     /// similar, if and when it stops moving around.
     #[cfg(feature = "stream-ctrl")]
     status: Arc<Mutex<DataStreamStatus>>,
+
+    /// Flow-control state for this stream, shared with its reader.
+    flow_ctrl: Arc<StreamFlowCtrl>,
 }
 
 /// The write half of a [`DataStream`], implementing [`futures::io::AsyncWrite`].
@@ -330,6 +336,11 @@ impl DataStreamCtrl {
         s.received_connected && !(s.sent_end || s.received_end || s.received_err)
     }
 
+    /// Return statistics about the data buffered for this stream's reader.
+    pub fn buffer_stats(&self) -> StreamBufferStats {
+        self.flow_ctrl.stats()
+    }
+
     // TODO RPC: Add more functions once we have the desired API more nailed
     // down.
 }
@@ -368,6 +379,7 @@ impl DataStream {
         let ctrl = Arc::new(DataStreamCtrl {
             circuit: Arc::downgrade(target.circuit()),
             status: status.clone(),
+            flow_ctrl: Arc::clone(&reader.flow_ctrl),
         });
         #[cfg(feature = "experimental-api")]
         let circuit = target.circuit().clone();
@@ -377,6 +389,7 @@ impl DataStream {
                 pending: Vec::new(),
                 offset: 0,
                 connected,
+                connected_addr: None,
                 #[cfg(feature = "stream-ctrl")]
                 status: status.clone(),
             })),
@@ -437,6 +450,20 @@ impl DataStream {
         }
     }
 
+    /// Return the address that the exit told us it connected to, if any.
+    ///
+    /// This comes from the CONNECTED message, so it is only available once the
+    /// stream is connected (for example, after
+    /// [`wait_for_connection`](Self::wait_for_connection)), and only if the exit
+    /// chose to include it.  It is also unavailable while a read is in
+    /// progress.
+    pub fn connected_addr(&self) -> Option<IpAddr> {
+        match &self.r.state {
+            Some(DataReaderState::Ready(imp)) => imp.connected_addr,
+            _ => None,
+        }
+    }
+
     /// Return a reference to this stream's circuit?
     ///
     /// This is an experimental API; it is not covered by semver guarantee. It
@@ -794,6 +821,9 @@ struct DataReaderImpl {
     /// If true, we have received a CONNECTED cell on this stream.
     connected: bool,
 
+    /// The address from the CONNECTED cell, if it had one.
+    connected_addr: Option<IpAddr>,
+
     /// Shared user-visible information about the state of this stream.
     #[cfg(feature = "stream-ctrl")]
     status: Arc<Mutex<DataStreamStatus>>,
@@ -912,8 +942,9 @@ impl DataReaderImpl {
         };
 
         let result = match msg {
-            Connected(_) if !self.connected => {
+            Connected(c) if !self.connected => {
                 self.connected = true;
+                self.connected_addr = c.addr();
                 #[cfg(feature = "stream-ctrl")]
                 {
                     self.status
@@ -0,0 +1,298 @@
+//! Stream-level flow control with XON and XOFF messages.
+//!
+//! With Tor's original flow control, each stream has a receive window: the
+//! sender may only send as many DATA messages as the receiver has granted
+//! with SENDME messages, and we only grant more once the reader has consumed
+//! what it already has.  So the amount of data buffered for a slow reader is
+//! bounded by the window.
+//!
+//! Once congestion control is negotiated with a hop (see proposal 324),
+//! that hop stops using stream-level SENDMEs.  Instead, when too much data is
+//! waiting for the reader, we send an XOFF asking the other side to stop
+//! sending on the stream, and once the reader has caught up, we send an XON
+//! asking it to resume.  The thresholds for these are the stream's
+//! [`StreamBufferWatermarks`].
+//!
+//! The watermarks also apply to hops that still use SENDMEs: while more than
+//! the high watermark is waiting for the reader, the reader withholds the
+//! SENDMEs it would otherwise send, so that the other side stops once it has
+//! used up its window; once the reader has caught up, it sends them all.
+//! (A high watermark at or above the initial window never takes effect here.)
+//!
+//! Either way, a stalled reader only ever stalls its own stream: the reactor
+//! never waits for a reader to make room.
+
+use std::sync::Mutex;
+
+use tor_error::bad_api_usage;
+
+use crate::Result;
+
+/// The default high watermark, in messages.
+///
+/// This matches the size of the initial SENDME receive window, so that a
+/// stream buffers about as much with either kind of flow control.
+const DEFAULT_HIGH_WATERMARK: usize = 500;
+
+/// The default low watermark, in messages.
+const DEFAULT_LOW_WATERMARK: usize = 125;
+
+/// Limits on how much data may be waiting for a stream's reader before we
+/// ask the other side to stop sending, and how far the reader must drain it
+/// before we ask the other side to resume.
+///
+/// Both limits are measured in DATA messages.  On hops that use XON/XOFF
+/// flow control, they decide when we send XOFF and XON; on other hops,
+/// they decide when we withhold and resume stream-level SENDMEs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StreamBufferWatermarks {
+    /// Send an XOFF once more than this many messages are buffered.
+    high: usize,
+    /// After an XOFF, send an XON once this many messages or fewer are
+    /// buffered.
+    low: usize,
+}
+
+impl Default for StreamBufferWatermarks {
+    fn default() -> Self {
+        StreamBufferWatermarks {
+            high: DEFAULT_HIGH_WATERMARK,
+            low: DEFAULT_LOW_WATERMARK,
+        }
+    }
+}
+
+impl StreamBufferWatermarks {
+    /// Construct a new set of watermarks.
+    ///
+    /// Gives an error if `high` is zero, or if `low` is not less than `high`.
+    pub fn new(high: usize, low: usize) -> Result<Self> {
+        if high == 0 || low >= high {
+            return Err(bad_api_usage!(
+                "Invalid stream buffer watermarks: low {} must be less than high {}",
+                low,
+                high
+            )
+            .into());
+        }
+        Ok(StreamBufferWatermarks { high, low })
+    }
+
+    /// Return the high watermark: we send an XOFF (or withhold SENDMEs)
+    /// once more than this many messages are buffered.
+    pub fn high(&self) -> usize {
+        self.high
+    }
+
+    /// Return the low watermark: after an XOFF, we send an XON (or resume
+    /// sending SENDMEs) once this many messages or fewer are buffered.
+    pub fn low(&self) -> usize {
+        self.low
+    }
+}
+
+/// Statistics about the data buffered for a stream's reader.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct StreamBufferStats {
+    /// The number of DATA messages currently waiting for the reader.
+    pub buffered_msgs: usize,
+    /// The largest value that `buffered_msgs` has had.
+    pub peak_buffered_msgs: usize,
+    /// The number of XOFF messages we have sent on this stream.
+    pub xoff_sent: u64,
+    /// The number of XON messages we have sent on this stream.
+    pub xon_sent: u64,
+}
+
+/// Flow-control state for a single stream, shared between the reactor
+/// (which queues messages for the reader) and the stream's reader.
+#[derive(Debug)]
+pub(crate) struct StreamFlowCtrl {
+    /// The thresholds for sending XOFF and XON.
+    watermarks: StreamBufferWatermarks,
+    /// The mutable state.
+    inner: Mutex<FlowCtrlInner>,
+}
+
+/// Mutable part of a [`StreamFlowCtrl`].
+#[derive(Debug, Default)]
+struct FlowCtrlInner {
+    /// True if this stream uses XON/XOFF rather than SENDMEs.
+    xon_xoff: bool,
+    /// True if we have gone over the high watermark,
+    /// and not yet drained back to the low watermark.
+    ///
+    /// With XON/XOFF, this means that we have sent an XOFF and not yet sent the matching XON;
+    /// otherwise, it means that the reader is withholding its SENDMEs.
+    paused: bool,
+    /// Statistics about this stream.
+    stats: StreamBufferStats,
+}
+
+impl StreamFlowCtrl {
+    /// Return a new `StreamFlowCtrl` using the given watermarks.
+    ///
+    /// The stream uses SENDMEs until [`set_xon_xoff`](Self::set_xon_xoff) is
+    /// called.
+    pub(crate) fn new(watermarks: StreamBufferWatermarks) -> Self {
+        StreamFlowCtrl {
+            watermarks,
+            inner: Mutex::new(FlowCtrlInner::default()),
+        }
+    }
+
+    /// Record whether this stream uses XON/XOFF rather than SENDMEs.
+    pub(crate) fn set_xon_xoff(&self, xon_xoff: bool) {
+        self.lock().xon_xoff = xon_xoff;
+    }
+
+    /// Return true if this stream uses XON/XOFF rather than SENDMEs.
+    pub(crate) fn uses_xon_xoff(&self) -> bool {
+        self.lock().xon_xoff
+    }
+
+    /// Note that the reactor has queued a DATA message for the reader.
+    ///
+    /// Return true if we should now send an XOFF.
+    pub(crate) fn queued(&self) -> bool {
+        let mut inner = self.lock();
+        inner.stats.buffered_msgs += 1;
+        inner.stats.peak_buffered_msgs = inner
+            .stats
+            .peak_buffered_msgs
+            .max(inner.stats.buffered_msgs);
+        if inner.paused || inner.stats.buffered_msgs <= self.watermarks.high {
+            return false;
+        }
+
+        inner.paused = true;
+        if inner.xon_xoff {
+            inner.stats.xoff_sent += 1;
+        }
+        inner.xon_xoff
+    }
+
+    /// Note that the reader has taken a DATA message.
+    ///
+    /// Return true if we should now send an XON.
+    pub(crate) fn consumed(&self) -> bool {
+        let mut inner = self.lock();
+        inner.stats.buffered_msgs = inner.stats.buffered_msgs.saturating_sub(1);
+        if !inner.paused || inner.stats.buffered_msgs > self.watermarks.low {
+            return false;
+        }
+
+        inner.paused = false;
+        if inner.xon_xoff {
+            inner.stats.xon_sent += 1;
+        }
+        inner.xon_xoff
+    }
+
+    /// Return true if too much data is waiting for the reader.
+    ///
+    /// On a stream that uses SENDMEs, the reader should withhold them while this is true.
+    pub(crate) fn is_paused(&self) -> bool {
+        self.lock().paused
+    }
+
+    /// Return a snapshot of this stream's statistics.
+    pub(crate) fn stats(&self) -> StreamBufferStats {
+        self.lock().stats
+    }
+
+    /// Helper: lock the mutable state.
+    fn lock(&self) -> std::sync::MutexGuard<'_, FlowCtrlInner> {
+        self.inner.lock().expect("poisoned lock")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    #[test]
+    fn watermarks() {
+        let w = StreamBufferWatermarks::default();
+        assert_eq!(w.high(), 500);
+        assert_eq!(w.low(), 125);
+
+        let w = StreamBufferWatermarks::new(10, 2).unwrap();
+        assert_eq!((w.high(), w.low()), (10, 2));
+        assert!(StreamBufferWatermarks::new(0, 0).is_err());
+        assert!(StreamBufferWatermarks::new(10, 10).is_err());
+        assert!(StreamBufferWatermarks::new(10, 20).is_err());
+    }
+
+    #[test]
+    fn sendme_mode() {
+        // Without XON/XOFF, we never send XOFF or XON,
+        // but we still note when the reader should withhold its SENDMEs.
+        let fc = StreamFlowCtrl::new(StreamBufferWatermarks::new(3, 1).unwrap());
+        assert!(!fc.uses_xon_xoff());
+        for n in 1..=10 {
+            assert!(!fc.queued());
+            assert_eq!(fc.is_paused(), n > 3);
+        }
+        for n in (0..10).rev() {
+            assert!(!fc.consumed());
+            assert_eq!(fc.is_paused(), n > 1);
+        }
+        let stats = fc.stats();
+        assert_eq!(stats.buffered_msgs, 0);
+        assert_eq!(stats.peak_buffered_msgs, 10);
+        assert_eq!(stats.xoff_sent, 0);
+        assert_eq!(stats.xon_sent, 0);
+    }
+
+    #[test]
+    fn xon_xoff_mode() {
+        let fc = StreamFlowCtrl::new(StreamBufferWatermarks::new(3, 1).unwrap());
+        fc.set_xon_xoff(true);
+        assert!(fc.uses_xon_xoff());
+
+        // Up to the high watermark, nothing happens.
+        for _ in 0..3 {
+            assert!(!fc.queued());
+        }
+        assert!(!fc.is_paused());
+        // Going over it sends exactly one XOFF.
+        assert!(fc.queued());
+        assert!(fc.is_paused());
+        assert!(!fc.queued());
+        assert_eq!(fc.stats().buffered_msgs, 5);
+
+        // Draining to the low watermark sends exactly one XON.
+        for _ in 0..3 {
+            assert!(!fc.consumed());
+        }
+        assert!(fc.consumed());
+        assert!(!fc.consumed());
+
+        // And then the cycle can repeat.
+        for _ in 0..3 {
+            assert!(!fc.queued());
+        }
+        assert!(fc.queued());
+
+        let stats = fc.stats();
+        assert_eq!(stats.buffered_msgs, 4);
+        assert_eq!(stats.peak_buffered_msgs, 5);
+        assert_eq!(stats.xoff_sent, 2);
+        assert_eq!(stats.xon_sent, 1);
+    }
+}
@@ -2,6 +2,8 @@
 
 use tor_cell::relaycell::msg::{BeginFlags, IpVersionPreference};
 
+use super::StreamBufferWatermarks;
+
 /// A set of preferences used to declare how a new stream should be opened.
 #[derive(Clone, Debug, Default)]
 pub struct StreamParameters {
@@ -13,6 +15,8 @@ pub struct StreamParameters {
     suppress_hostname: bool,
     /// True if we are suppressing flags.
     suppress_begin_flags: bool,
+    /// Limits on how much data may be buffered for the stream's reader.
+    watermarks: StreamBufferWatermarks,
 }
 
 impl StreamParameters {
@@ -70,6 +74,22 @@ impl StreamParameters {
         self
     }
 
+    /// Configure how much data may be buffered for this stream's reader
+    /// before we ask the other side to stop sending, and how far the reader
+    /// must catch up before we ask it to resume.
+    ///
+    /// This only has an effect on hops that use XON/XOFF flow control; see
+    /// [`StreamBufferWatermarks`].
+    pub fn buffer_watermarks(&mut self, watermarks: StreamBufferWatermarks) -> &mut Self {
+        self.watermarks = watermarks;
+        self
+    }
+
+    /// Crate-internal: Return the buffer watermarks for this stream.
+    pub(crate) fn watermarks(&self) -> StreamBufferWatermarks {
+        self.watermarks
+    }
+
     /// Crate-internal: Return true if the stream is optimistic.
     pub(crate) fn is_optimistic(&self) -> bool {
         self.optimistic
@@ -2,12 +2,14 @@
 //! cells.
 
 use crate::circuit::{sendme, StreamTarget};
+use crate::stream::{StreamBufferStats, StreamFlowCtrl};
 use crate::{Error, Result};
 use tor_cell::relaycell::{RelayCmd, UnparsedRelayMsg};
 
 use crate::circuit::sendme::StreamRecvWindow;
 use futures::channel::mpsc;
 use futures::stream::StreamExt;
+use std::sync::Arc;
 
 /// The read part of a stream on a particular circuit.
 #[derive(Debug)]
@@ -29,6 +31,16 @@ pub struct StreamReader {
     /// opposed to having the reactor assume we're always reading, and potentially overwhelm itself
     /// with having to buffer data).
     pub(crate) recv_window: StreamRecvWindow,
+    /// Flow-control state, shared with the reactor.
+    ///
+    /// Used to tell the reactor when we have drained enough of the buffered
+    /// data that it should send an XON.
+    pub(crate) flow_ctrl: Arc<StreamFlowCtrl>,
+    /// The number of SENDMEs that we owe the other side,
+    /// but are withholding because too much data is waiting for us to read it.
+    ///
+    /// Only used on streams that use SENDMEs rather than XON/XOFF.
+    pub(crate) withheld_sendmes: u16,
     /// Whether or not this stream has ended.
     pub(crate) ended: bool,
 }
@@ -45,19 +57,41 @@ impl StreamReader {
             .next()
             .await
             // This probably means that the other side closed the
-            // mpsc channel.  I'm not sure the error type is correct though?
-            .ok_or_else(|| {
-                Error::StreamProto("stream channel disappeared without END cell?".into())
+            // mpsc channel.  If a relay closed the circuit, say why;
+            // otherwise, I'm not sure the error type is correct though?
+            .ok_or_else(|| match self.target.circ_close_reason() {
+                Some(reason) => Error::CircuitDestroyed(reason),
+                None => Error::StreamProto("stream channel disappeared without END cell?".into()),
             })?;
 
-        if sendme::cell_counts_towards_windows(&msg) && self.recv_window.take()? {
-            self.target.send_sendme()?;
-            self.recv_window.put()?;
+        if sendme::cell_counts_towards_windows(&msg) {
+            if self.flow_ctrl.consumed() {
+                self.target.send_xon()?;
+            }
+            // With XON/XOFF, the other side doesn't expect stream-level SENDMEs.
+            if !self.flow_ctrl.uses_xon_xoff() {
+                if self.recv_window.take()? {
+                    self.withheld_sendmes += 1;
+                }
+                // While too much data is waiting for us, we hold our SENDMEs back,
+                // so that the other side stops sending once it has used up its window.
+                if !self.flow_ctrl.is_paused() {
+                    for _ in 0..std::mem::take(&mut self.withheld_sendmes) {
+                        self.target.send_sendme()?;
+                        self.recv_window.put()?;
+                    }
+                }
+            }
         }
 
         Ok(msg)
     }
 
+    /// Return statistics about the data buffered for this reader.
+    pub fn buffer_stats(&self) -> StreamBufferStats {
+        self.flow_ctrl.stats()
+    }
+
     /// As recv_raw, but if there is an error or an end cell, note that this
     /// stream has ended.
     pub async fn recv(&mut self) -> Result<UnparsedRelayMsg> {
@@ -0,0 +1,538 @@
+//! A scripted "relay side" for testing circuits without a network.
+//!
+//! This module is only enabled when the `testing` feature is enabled.
+//! It is not covered by semver.
+//!
+//! A [`ScriptedRelay`] sits at the far end of a fake [`Channel`]: every cell
+//! that the client sends arrives at the `ScriptedRelay`, and every cell that
+//! the test tells the `ScriptedRelay` to send is delivered to the channel
+//! reactor as though it had come from the network.  The `ScriptedRelay` keeps
+//! the relay-side cryptographic state for every hop of the circuit it is
+//! serving, so the client-side code under test runs with real handshakes and
+//! real relay cell cryptography.
+//!
+//! A test drives the relay side step by step: it answers the client's CREATE
+//! and EXTEND2 requests, reads the client's relay messages, and can reply with
+//! well-formed messages, corrupted cells, TRUNCATED, or DESTROY, as the case
+//! requires.
+//!
+//! Only the CREATE_FAST and ntor handshakes are supported, with the `tor1`
+//! relay cell protocol.  Every simulated hop uses the same identity and onion
+//! key; use [`ScriptedRelay::circ_target`] to get a target that matches them.
+//!
+//! # Panics
+//!
+//! The methods on [`ScriptedRelay`] panic if the client does something other
+//! than what the script expects, or if the channel has closed: only use them
+//! for testing.
+
+#![allow(clippy::unwrap_used)]
+
+use std::sync::Arc;
+
+use futures::channel::mpsc;
+use futures::task::SpawnExt as _;
+use futures::{SinkExt as _, StreamExt as _};
+use tor_basic_utils::test_rng::{testing_rng, TestingRng};
+use tor_cell::chancell::msg::{self as chanmsg, AnyChanMsg, DestroyReason, HandshakeType};
+use tor_cell::chancell::{AnyChanCell, BoxedCellBody, CircId};
+use tor_cell::relaycell::msg::{self as relaymsg, AnyRelayMsg};
+use tor_cell::relaycell::{AnyRelayMsgOuter, RelayCellFormat, RelayCellFormatV0};
+use tor_linkspec::{OwnedChanTarget, OwnedCircTarget};
+use tor_llcrypto::pk::curve25519;
+use tor_rtcompat::Runtime;
+
+use crate::channel::{Channel, CodecError, OpenChanCellS2C, OpenChanMsgS2C, UniqId};
+use crate::circuit::{CircParameters, ClientCirc};
+use crate::crypto::cell::{CryptInit as _, RelayCellBody, RelayCrypt as _, Tor1RelayCrypto};
+use crate::crypto::handshake::fast::CreateFastServer;
+use crate::crypto::handshake::ntor::{NtorSecretKey, NtorServer};
+use crate::crypto::handshake::ServerHandshake as _;
+use crate::{ClockSkew, HopNum};
+
+/// Secret ntor onion key used by every simulated hop.
+const ONION_SK: [u8; 32] = [42; 32];
+/// Ed25519 identity used by every simulated hop.
+const ED_ID: [u8; 32] = [6; 32];
+/// RSA identity used by every simulated hop.
+const RSA_ID: [u8; 20] = [10; 20];
+
+/// The link protocol that the fake channel claims to have negotiated.
+const LINK_PROTOCOL: u16 = 4;
+
+/// The relay side of a fake channel, serving a single circuit.
+///
+/// See the [module documentation](self) for details.
+pub struct ScriptedRelay {
+    /// Cells that the client has sent on the channel.
+    from_client: mpsc::Receiver<AnyChanCell>,
+    /// Cells that we are delivering to the client's channel reactor.
+    to_client: mpsc::Sender<std::result::Result<OpenChanCellS2C, CodecError>>,
+    /// The circuit ID that the client chose, once we have seen a CREATE cell.
+    circid: Option<CircId>,
+    /// Relay-side cryptographic state for each hop that we have added to the circuit.
+    hops: Vec<Tor1RelayCrypto<RelayCellFormatV0>>,
+    /// Random number generator used for handshakes and for encoding messages.
+    rng: TestingRng,
+}
+
+impl ScriptedRelay {
+    /// Create a new channel whose far end is a `ScriptedRelay`, and launch
+    /// the channel's reactor on `runtime`.
+    ///
+    /// Dropping the `ScriptedRelay` closes the channel.
+    pub fn new<R: Runtime>(runtime: &R) -> (Arc<Channel>, Self) {
+        let (to_relay, from_client) = mpsc::channel(128);
+        let (to_client, from_relay) = mpsc::channel(128);
+        let sink = to_relay.sink_map_err(|_| {
+            CodecError::DecCell(tor_cell::Error::ChanProto("scripted relay is gone".into()))
+        });
+        let (channel, reactor) = Channel::new(
+            LINK_PROTOCOL,
+            Box::new(sink),
+            Box::new(from_relay),
+            Default::default(),
+            UniqId::new(),
+            chan_target(),
+            ClockSkew::None,
+            runtime.clone(),
+        );
+        runtime
+            .spawn(async {
+                let _ignore = reactor.run().await;
+            })
+            .expect("couldn't spawn channel reactor");
+        let relay = ScriptedRelay {
+            from_client,
+            to_client,
+            circid: None,
+            hops: Vec::new(),
+            rng: testing_rng(),
+        };
+        (channel, relay)
+    }
+
+    /// Build a circuit of `n_hops` hops on a new channel, answering the
+    /// client's CREATE_FAST and EXTEND2 requests as they arrive.
+    ///
+    /// Returns the circuit along with the `ScriptedRelay` serving it.
+    pub async fn build_circuit<R: Runtime>(runtime: &R, n_hops: usize) -> (Arc<ClientCirc>, Self) {
+        assert!(n_hops > 0, "a circuit needs at least one hop");
+        let (channel, mut relay) = ScriptedRelay::new(runtime);
+        let (pending, reactor) = channel.new_circ().await.unwrap();
+        runtime
+            .spawn(async {
+                let _ignore = reactor.run().await;
+            })
+            .expect("couldn't spawn circuit reactor");
+
+        let params = CircParameters::default();
+        let (circ, ()) =
+            futures::join!(pending.create_firsthop_fast(&params), relay.answer_create());
+        let circ = circ.unwrap();
+
+        let target = relay.circ_target();
+        for _ in 1..n_hops {
+            let (outcome, ()) =
+                futures::join!(circ.extend_ntor(&target, &params), relay.answer_extend());
+            outcome.unwrap();
+        }
+        (circ, relay)
+    }
+
+    /// Return a target whose identities and onion key match every hop that
+    /// this `ScriptedRelay` simulates.
+    ///
+    /// Use this for `create_firsthop_ntor` and `extend_ntor`.
+    pub fn circ_target(&self) -> OwnedCircTarget {
+        let mut builder = OwnedCircTarget::builder();
+        builder
+            .chan_target()
+            .ed_identity(ED_ID.into())
+            .rsa_identity(RSA_ID.into());
+        builder
+            .ntor_onion_key(onion_keypair().1)
+            .protocols("FlowCtrl=1".parse().unwrap())
+            .build()
+            .unwrap()
+    }
+
+    /// Return the circuit ID that the client chose, if it has sent a CREATE
+    /// cell yet.
+    pub fn circid(&self) -> Option<CircId> {
+        self.circid
+    }
+
+    /// Return the number of hops that we have added to the circuit.
+    pub fn n_hops(&self) -> usize {
+        self.hops.len()
+    }
+
+    /// Wait for the next cell that the client sends on the channel, and return it
+    /// without interpreting it.
+    ///
+    /// Returns `None` if the client has closed the channel.
+    pub async fn next_cell(&mut self) -> Option<AnyChanCell> {
+        self.from_client.next().await
+    }
+
+    /// Wait for the client to send CREATE_FAST or an ntor CREATE2, and
+    /// complete the handshake.
+    pub async fn answer_create(&mut self) {
+        let cell = self.next_cell().await.expect("channel closed");
+        let (circid, msg) = cell.into_circid_and_msg();
+        let circid = circid.expect("CREATE cell with no circuit ID");
+        assert!(self.hops.is_empty(), "circuit was already created");
+        self.circid = Some(circid);
+
+        let reply: OpenChanMsgS2C = match msg {
+            AnyChanMsg::CreateFast(cf) => {
+                let (keygen, reply) = CreateFastServer::server(
+                    &mut self.rng,
+                    &mut |_: &()| Some(()),
+                    &[()],
+                    cf.handshake(),
+                )
+                .unwrap();
+                self.hops.push(Tor1RelayCrypto::construct(keygen).unwrap());
+                chanmsg::CreatedFast::new(reply).into()
+            }
+            AnyChanMsg::Create2(c2) => {
+                assert_eq!(c2.handshake_type(), HandshakeType::NTOR);
+                let (keygen, reply) = NtorServer::server(
+                    &mut self.rng,
+                    &mut |_: &()| Some(()),
+                    &[onion_key()],
+                    c2.body(),
+                )
+                .unwrap();
+                self.hops.push(Tor1RelayCrypto::construct(keygen).unwrap());
+                chanmsg::Created2::new(reply).into()
+            }
+            other => panic!("expected a CREATE message, got {}", other.cmd()),
+        };
+        self.send_to_client(reply).await;
+    }
+
+    /// Reply to the client's CREATE cell with `reply`, whatever it is.
+    ///
+    /// The circuit is not considered created: use this to test how the client
+    /// handles a malformed or refused CREATED.
+    pub async fn answer_create_with(&mut self, reply: crate::channel::CreateResponse) {
+        use crate::channel::CreateResponse as CR;
+        let cell = self.next_cell().await.expect("channel closed");
+        self.circid = cell.circid();
+        let reply: OpenChanMsgS2C = match reply {
+            CR::Destroy(m) => m.into(),
+            CR::CreatedFast(m) => m.into(),
+            CR::Created2(m) => m.into(),
+        };
+        self.send_to_client(reply).await;
+    }
+
+    /// Wait for the client to send an ntor EXTEND2 to the last hop, and
+    /// complete the handshake, adding a hop to the circuit.
+    pub async fn answer_extend(&mut self) {
+        let last_hop = self.last_hop();
+        let (hop, msg) = self.recv_relay().await;
+        assert_eq!(hop, last_hop, "EXTEND2 was sent to the wrong hop");
+        let e2 = match msg.msg() {
+            AnyRelayMsg::Extend2(e2) => e2,
+            other => panic!("expected EXTEND2, got {}", other.cmd()),
+        };
+        assert_eq!(e2.handshake_type(), HandshakeType::NTOR);
+        let (keygen, reply) = NtorServer::server(
+            &mut self.rng,
+            &mut |_: &()| Some(()),
+            &[onion_key()],
+            e2.handshake(),
+        )
+        .unwrap();
+
+        let extended2 = relaymsg::Extended2::new(reply).into();
+        self.send_relay(last_hop, AnyRelayMsgOuter::new(None, extended2))
+            .await;
+        self.hops.push(Tor1RelayCrypto::construct(keygen).unwrap());
+    }
+
+    /// Wait for the client to send a relay cell, and return the hop that it
+    /// was addressed to, along with the decoded message.
+    ///
+    /// This accepts both RELAY and RELAY_EARLY cells.
+    pub async fn recv_relay(&mut self) -> (HopNum, AnyRelayMsgOuter) {
+        let cell = self.next_cell().await.expect("channel closed");
+        assert_eq!(cell.circid(), self.circid, "cell for the wrong circuit");
+        let body = match cell.into_circid_and_msg().1 {
+            AnyChanMsg::Relay(r) | AnyChanMsg::RelayEarly(r) => r.into_relay_body(),
+            other => panic!("expected a relay cell, got {}", other.cmd()),
+        };
+        let mut body = RelayCellBody::from(body);
+        let hop = self
+            .hops
+            .iter_mut()
+            .position(|hop| hop.decrypt_outbound(&mut body))
+            .expect("relay cell was not recognized by any hop");
+        let msg = AnyRelayMsgOuter::decode_singleton(RelayCellFormat::V0, body.into()).unwrap();
+        (u8::try_from(hop).unwrap().into(), msg)
+    }
+
+    /// Send `msg` to the client, as though it came from `hop`.
+    pub async fn send_relay(&mut self, hop: HopNum, msg: AnyRelayMsgOuter) {
+        let body = msg.encode(&mut self.rng).unwrap();
+        self.send_relay_body(hop, body).await;
+    }
+
+    /// Send an encoded relay cell body to the client, as though it came
+    /// from `hop`.
+    ///
+    /// The body is sent as-is, apart from its digest: use this to inject
+    /// malformed messages that the client will nonetheless accept as
+    /// coming from `hop`.
+    pub async fn send_relay_body(&mut self, hop: HopNum, body: BoxedCellBody) {
+        let mut body = RelayCellBody::from(body);
+        self.hops[usize::from(hop)].originate(&mut body);
+        self.encrypt_inbound_from(hop, body).await;
+    }
+
+    /// Send an encoded relay cell body through the layers of `hop` and the
+    /// hops before it, without setting its digest.
+    ///
+    /// The client won't be able to recognize the resulting cell, and should
+    /// treat it as a protocol violation.
+    pub async fn send_unrecognized_relay_body(&mut self, hop: HopNum, body: BoxedCellBody) {
+        self.encrypt_inbound_from(hop, RelayCellBody::from(body))
+            .await;
+    }
+
+    /// Send a TRUNCATED message from `hop` with a given `reason`, and forget
+    /// about every hop after it.
+    pub async fn send_truncated(&mut self, hop: HopNum, reason: DestroyReason) {
+        let truncated = relaymsg::Truncated::new(reason).into();
+        self.send_relay(hop, AnyRelayMsgOuter::new(None, truncated))
+            .await;
+        self.hops.truncate(usize::from(hop) + 1);
+    }
+
+    /// Destroy the circuit with a given `reason`.
+    pub async fn send_destroy(&mut self, reason: DestroyReason) {
+        self.send_to_client(chanmsg::Destroy::new(reason).into())
+            .await;
+        self.hops.clear();
+    }
+
+    /// Wait for the client to tear down the circuit, and return the reason
+    /// it gave in its DESTROY cell.
+    ///
+    /// The client sends a DESTROY cell whenever its circuit reactor shuts down,
+    /// including after we have destroyed or truncated the circuit ourselves.
+    pub async fn expect_destroy(&mut self) -> DestroyReason {
+        let cell = self.next_cell().await.expect("channel closed");
+        assert_eq!(cell.circid(), self.circid, "cell for the wrong circuit");
+        match cell.into_circid_and_msg().1 {
+            AnyChanMsg::Destroy(d) => d.reason(),
+            other => panic!("expected DESTROY, got {}", other.cmd()),
+        }
+    }
+
+    /// Return the number of the last hop that we have added to the circuit.
+    fn last_hop(&self) -> HopNum {
+        let n = self.hops.len().checked_sub(1).expect("no hops on circuit");
+        u8::try_from(n).unwrap().into()
+    }
+
+    /// Apply the inbound encryption of `hop` and every hop before it to
+    /// `body`, and send the result to the client.
+    async fn encrypt_inbound_from(&mut self, hop: HopNum, mut body: RelayCellBody) {
+        for layer in self.hops[..=usize::from(hop)].iter_mut().rev() {
+            layer.encrypt_inbound(&mut body);
+        }
+        let body: BoxedCellBody = body.into();
+        self.send_to_client(chanmsg::Relay::from(body).into()).await;
+    }
+
+    /// Deliver `msg` on our circuit to the client's channel reactor.
+    async fn send_to_client(&mut self, msg: OpenChanMsgS2C) {
+        let cell = OpenChanCellS2C::new(self.circid, msg);
+        self.to_client.send(Ok(cell)).await.expect("channel closed");
+    }
+}
+
+/// Return the identities that the fake channel's peer claims.
+fn chan_target() -> OwnedChanTarget {
+    OwnedChanTarget::builder()
+        .ed_identity(ED_ID.into())
+        .rsa_identity(RSA_ID.into())
+        .build()
+        .unwrap()
+}
+
+/// Return the ntor onion keypair used by every simulated hop.
+fn onion_keypair() -> (curve25519::StaticSecret, curve25519::PublicKey) {
+    let sk = curve25519::StaticSecret::from(ONION_SK);
+    let pk = curve25519::PublicKey::from(&sk);
+    (sk, pk)
+}
+
+/// Return the secret ntor onion key used by every simulated hop.
+fn onion_key() -> NtorSecretKey {
+    let (sk, pk) = onion_keypair();
+    NtorSecretKey::new(sk, pk, RSA_ID.into())
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::mixed_attributes_style)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+    use crate::{CircCloseReason, Error};
+    use futures::AsyncReadExt as _;
+    use tor_cell::chancell::CELL_DATA_LEN;
+    use tor_cell::relaycell::msg::EndReason;
+
+    #[test]
+    fn build_and_extend() {
+        tor_rtcompat::test_with_all_runtimes!(|rt| async move {
+            let (circ, relay) = ScriptedRelay::build_circuit(&rt, 3).await;
+            assert_eq!(circ.n_hops(), 3);
+            assert_eq!(relay.n_hops(), 3);
+            assert!(relay.circid().is_some());
+        });
+    }
+
+    #[test]
+    fn create_ntor() {
+        tor_rtcompat::test_with_all_runtimes!(|rt| async move {
+            let (channel, mut relay) = ScriptedRelay::new(&rt);
+            let (pending, reactor) = channel.new_circ().await.unwrap();
+            rt.spawn(async {
+                let _ignore = reactor.run().await;
+            })
+            .unwrap();
+            let target = relay.circ_target();
+            let (circ, ()) = futures::join!(
+                pending.create_firsthop_ntor(&target, CircParameters::default()),
+                relay.answer_create()
+            );
+            assert_eq!(circ.unwrap().n_hops(), 1);
+        });
+    }
+
+    #[test]
+    fn refused_create() {
+        tor_rtcompat::test_with_all_runtimes!(|rt| async move {
+            let (channel, mut relay) = ScriptedRelay::new(&rt);
+            let (pending, reactor) = channel.new_circ().await.unwrap();
+            rt.spawn(async {
+                let _ignore = reactor.run().await;
+            })
+            .unwrap();
+            let reply =
+                crate::channel::CreateResponse::CreatedFast(chanmsg::CreatedFast::new(vec![0; 40]));
+            let (circ, ()) = futures::join!(
+                pending.create_firsthop_fast(&CircParameters::default()),
+                relay.answer_create_with(reply)
+            );
+            assert!(matches!(circ, Err(Error::BadCircHandshakeAuth)));
+        });
+    }
+
+    #[test]
+    fn dir_stream() {
+        tor_rtcompat::test_with_all_runtimes!(|rt| async move {
+            let (circ, mut relay) = ScriptedRelay::build_circuit(&rt, 1).await;
+
+            let client = async move {
+                let mut stream = circ.begin_dir_stream().await.unwrap();
+                let mut buf = Vec::new();
+                stream.read_to_end(&mut buf).await.unwrap();
+                buf
+            };
+            let server = async move {
+                let (hop, msg) = relay.recv_relay().await;
+                assert_eq!(hop, 0.into());
+                assert!(matches!(msg.msg(), AnyRelayMsg::BeginDir(_)));
+                let id = msg.stream_id();
+                let connected = relaymsg::Connected::new_empty().into();
+                relay
+                    .send_relay(hop, AnyRelayMsgOuter::new(id, connected))
+                    .await;
+                let data = relaymsg::Data::new(b"hello").unwrap().into();
+                relay.send_relay(hop, AnyRelayMsgOuter::new(id, data)).await;
+                let end = relaymsg::End::new_with_reason(EndReason::DONE).into();
+                relay.send_relay(hop, AnyRelayMsgOuter::new(id, end)).await;
+                relay
+            };
+            let (buf, _relay) = futures::join!(client, server);
+            assert_eq!(&buf[..], b"hello");
+        });
+    }
+
+    #[test]
+    fn truncated() {
+        tor_rtcompat::test_with_all_runtimes!(|rt| async move {
+            let (circ, mut relay) = ScriptedRelay::build_circuit(&rt, 3).await;
+            relay
+                .send_truncated(1.into(), DestroyReason::CONNECTFAILED)
+                .await;
+            assert_eq!(relay.n_hops(), 2);
+            let _ = relay.expect_destroy().await;
+            assert_eq!(
+                circ.close_reason(),
+                Some(CircCloseReason::Truncated {
+                    hop: 1.into(),
+                    reason: DestroyReason::CONNECTFAILED,
+                })
+            );
+        });
+    }
+
+    #[test]
+    fn destroyed() {
+        tor_rtcompat::test_with_all_runtimes!(|rt| async move {
+            let (circ, mut relay) = ScriptedRelay::build_circuit(&rt, 2).await;
+            relay.send_destroy(DestroyReason::FINISHED).await;
+            let _ = relay.expect_destroy().await;
+            assert_eq!(
+                circ.close_reason(),
+                Some(CircCloseReason::Destroyed(DestroyReason::FINISHED))
+            );
+        });
+    }
+
+    #[test]
+    fn unrecognized_cell() {
+        tor_rtcompat::test_with_all_runtimes!(|rt| async move {
+            let (_circ, mut relay) = ScriptedRelay::build_circuit(&rt, 3).await;
+            relay
+                .send_unrecognized_relay_body(2.into(), Box::new([7; CELL_DATA_LEN]))
+                .await;
+            assert_eq!(relay.expect_destroy().await, DestroyReason::NONE);
+        });
+    }
+
+    #[test]
+    fn malformed_message() {
+        tor_rtcompat::test_with_all_runtimes!(|rt| async move {
+            let (_circ, mut relay) = ScriptedRelay::build_circuit(&rt, 3).await;
+            // A DATA message claiming to be longer than a cell.
+            let mut body = Box::new([0; CELL_DATA_LEN]);
+            body[0] = 2; // RELAY_DATA
+            body[3..5].copy_from_slice(&[0, 1]); // stream 1
+            body[9..11].copy_from_slice(&[0xff, 0xff]);
+            relay.send_relay_body(2.into(), body).await;
+            assert_eq!(relay.expect_destroy().await, DestroyReason::NONE);
+        });
+    }
+}
@@ -1,10 +1,13 @@
 //! Define an error type for the tor-proto crate.
 use std::{sync::Arc, time::Duration};
 use thiserror::Error;
+use tor_cell::chancell::msg::DestroyReason;
 use tor_cell::relaycell::{msg::EndReason, StreamId};
 use tor_error::{ErrorKind, HasKind};
 use tor_linkspec::RelayIdType;
 
+use crate::crypto::cell::HopNum;
+
 /// An error type for the tor-proto crate.
 ///
 /// This type should probably be split into several.  There's more
@@ -102,10 +105,21 @@ pub enum Error {
     /// operation.
     #[error("Channel closed")]
     ChannelClosed(#[from] ChannelClosed),
+    /// We closed a channel because the relay stopped responding on it.
+    ///
+    /// The duration is how long we waited for a response.
+    #[error("Relay stopped responding on channel (waited {})", humantime::format_duration(*.0))]
+    ChanUnresponsive(Duration),
     /// Circuit is closed, or became closed while we were trying to so some
     /// operation.
     #[error("Circuit closed")]
     CircuitClosed,
+    /// Circuit was closed by a relay, which told us why.
+    ///
+    /// We return this instead of [`Error::CircuitClosed`] when a DESTROY cell
+    /// or a TRUNCATED message closed the circuit.
+    #[error("Circuit closed by relay: {0}")]
+    CircuitDestroyed(CircCloseReason),
     /// Can't allocate any more circuit or stream IDs on a channel.
     #[error("Too many entries in map: can't allocate ID")]
     IdRangeFull,
@@ -158,6 +172,62 @@ impl HasKind for ChannelClosed {
     }
 }
 
+/// Why a relay closed one of our circuits.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum CircCloseReason {
+    /// The first hop sent us a DESTROY cell.
+    Destroyed(DestroyReason),
+    /// A relay on the circuit sent us a TRUNCATED message: the circuit has
+    /// been torn down after that relay.
+    Truncated {
+        /// The hop that sent the TRUNCATED message.
+        hop: HopNum,
+        /// The reason it gave.
+        reason: DestroyReason,
+    },
+}
+
+impl CircCloseReason {
+    /// Return the reason code that the relay gave for closing the circuit.
+    pub fn reason(&self) -> DestroyReason {
+        match self {
+            CircCloseReason::Destroyed(reason) => *reason,
+            CircCloseReason::Truncated { reason, .. } => *reason,
+        }
+    }
+}
+
+impl std::fmt::Display for CircCloseReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CircCloseReason::Destroyed(reason) => {
+                write!(f, "DESTROY: {} [{}]", reason.human_str(), reason)
+            }
+            CircCloseReason::Truncated { hop, reason } => write!(
+                f,
+                "TRUNCATED from hop {}: {} [{}]",
+                hop.display(),
+                reason.human_str(),
+                reason
+            ),
+        }
+    }
+}
+
+impl HasKind for CircCloseReason {
+    fn kind(&self) -> ErrorKind {
+        use ErrorKind as EK;
+        match self.reason() {
+            DestroyReason::PROTOCOL => EK::TorProtocolViolation,
+            DestroyReason::HIBERNATING | DestroyReason::RESOURCELIMIT => EK::RelayTooBusy,
+            DestroyReason::TIMEOUT => EK::TorNetworkTimeout,
+            DestroyReason::NOSUCHSERVICE => EK::OnionServiceNotFound,
+            _ => EK::CircuitCollapse,
+        }
+    }
+}
+
 /// Details about an error received while resolving a domain
 #[derive(Error, Debug, Clone)]
 #[non_exhaustive]
@@ -216,9 +286,11 @@ impl From<Error> for std::io::Error {
 
             NotConnected => ErrorKind::NotConnected,
 
+            ChanUnresponsive(_) => ErrorKind::TimedOut,
+
             EndReceived(end_reason) => end_reason.into(),
 
-            CircuitClosed => ErrorKind::ConnectionReset,
+            CircuitClosed | CircuitDestroyed(_) => ErrorKind::ConnectionReset,
 
             BytesErr { .. }
             | BadCellAuth
@@ -272,7 +344,9 @@ impl HasKind for Error {
             E::ChanProto(_) => EK::TorProtocolViolation,
             E::CircProto(_) => EK::TorProtocolViolation,
             E::ChannelClosed(e) => e.kind(),
+            E::ChanUnresponsive(_) => EK::TorNetworkTimeout,
             E::CircuitClosed => EK::CircuitCollapse,
+            E::CircuitDestroyed(reason) => reason.kind(),
             E::IdRangeFull => EK::BadApiUsage,
             E::CircRefused(_) => EK::CircuitRefused,
             E::BadStreamAddress => EK::BadApiUsage,
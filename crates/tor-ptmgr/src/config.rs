@@ -67,6 +67,12 @@ pub struct TransportConfig {
 impl_standard_builder! { TransportConfig: !Default }
 
 impl TransportConfig {
+    /// Return the names of the transport protocols that we use from this
+    /// transport.
+    pub fn protocols(&self) -> &[PtTransportName] {
+        &self.protocols
+    }
+
     /// Return true if this transport is managed.
     pub(crate) fn is_managed(&self) -> bool {
         self.path.is_some()
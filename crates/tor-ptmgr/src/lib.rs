@@ -57,6 +57,7 @@ use futures::task::SpawnExt;
 use futures::{select, FutureExt, StreamExt};
 use std::collections::{HashMap, HashSet};
 use std::future::Future;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::{Arc, RwLock};
@@ -71,8 +72,10 @@ use {
     tor_chanmgr::{
         builder::ChanBuilder,
         factory::{AbstractPtError, ChannelFactory},
-        transport::ExternalProxyPlugin,
+        transport::{ExternalProxyPlugin, TransportImplHelper as _},
     },
+    tor_error::bad_api_usage,
+    tor_linkspec::{ChannelMethod, HasChanMethod as _, OwnedChanTarget},
 };
 
 /// Shared mutable state between the `PtReactor` and `PtMgr`.
@@ -84,6 +87,18 @@ struct PtSharedState {
     managed_cmethods: HashMap<PtTransportName, PtClientMethod>,
     /// Current configured set of pluggable transports.
     configured: HashMap<PtTransportName, TransportConfig>,
+    /// The local addresses from which managed transports should make their
+    /// outgoing connections.
+    outbound_bind: OutboundBind,
+}
+
+/// The local addresses, if any, from which managed transports make their outgoing connections.
+#[derive(Clone, Copy, Default, Debug)]
+struct OutboundBind {
+    /// The address for IPv4 connections.
+    ipv4: Option<Ipv4Addr>,
+    /// The address for IPv6 connections.
+    ipv6: Option<Ipv6Addr>,
 }
 
 /// A message to the `PtReactor`.
@@ -264,9 +279,9 @@ impl<R: Runtime> PtReactor<R> {
                             }
                         }
                         // We don't, so time to spawn one.
-                        let config = {
+                        let (config, outbound_bind) = {
                             let state = self.state.read().expect("ptmgr state poisoned");
-                            state.configured.get(&pt).cloned()
+                            (state.configured.get(&pt).cloned(), state.outbound_bind)
                         };
                         let config = match config {
                             Some(v) if v.is_managed() => v,
@@ -288,7 +303,12 @@ impl<R: Runtime> PtReactor<R> {
 
                         // Add the spawn future to our pile of them.
                         let spawn_fut = Box::pin(
-                            spawn_from_config(self.rt.clone(), self.state_dir.clone(), config.clone())
+                            spawn_from_config(
+                                self.rt.clone(),
+                                self.state_dir.clone(),
+                                config.clone(),
+                                outbound_bind,
+                            )
                                 .map(|result| (config.protocols, result))
                         );
                         self.spawning.push(spawn_fut);
@@ -341,6 +361,7 @@ impl<R: Runtime> PtMgr<R> {
         let state = PtSharedState {
             managed_cmethods: Default::default(),
             configured: Self::transform_config(transports),
+            outbound_bind: Default::default(),
         };
         let state = Arc::new(RwLock::new(state));
         let (tx, rx) = mpsc::unbounded();
@@ -367,6 +388,20 @@ impl<R: Runtime> PtMgr<R> {
         })
     }
 
+    /// Set the local addresses from which managed transports should make
+    /// their outgoing connections.
+    ///
+    /// These are passed to transport binaries that we launch from now on;
+    /// ones that are already running keep the addresses they were given.
+    ///
+    /// Transports can only be asked to bind to addresses: we have no way to
+    /// make them bind to a network interface, or to refuse an address family
+    /// that has no bind address.
+    pub fn set_outbound_bind(&self, ipv4: Option<Ipv4Addr>, ipv6: Option<Ipv6Addr>) {
+        let mut state = self.state.write().expect("ptmgr poisoned");
+        state.outbound_bind = OutboundBind { ipv4, ipv6 };
+    }
+
     /// Reload the configuration
     pub fn reconfigure(
         &self,
@@ -496,6 +531,7 @@ async fn spawn_from_config<R: Runtime>(
     rt: R,
     state_dir: PathBuf,
     cfg: TransportConfig,
+    outbound_bind: OutboundBind,
 ) -> Result<PluggableClientTransport, PtError> {
     // FIXME(eta): I really think this expansion should happen at builder validation time...
 
@@ -522,6 +558,8 @@ async fn spawn_from_config<R: Runtime>(
     // FIXME(eta): make the rest of these parameters configurable
     let pt_common_params = PtCommonParameters::builder()
         .state_location(new_state_dir)
+        .outbound_bind_v4(outbound_bind.ipv4)
+        .outbound_bind_v6(outbound_bind.ipv6)
         .build()
         .expect("PtCommonParameters constructed incorrectly");
 
@@ -561,6 +599,39 @@ impl<R: Runtime> tor_chanmgr::factory::AbstractPtMgr for PtMgr<R> {
     }
 }
 
+#[cfg(feature = "tor-channel-factory")]
+impl<R: Runtime> PtMgr<R> {
+    /// Open a stream to `target` through the pluggable transport that it
+    /// names, launching the transport if necessary.
+    ///
+    /// Unlike the factories from `factory_for_transport`, this doesn't build a
+    /// Tor channel over the stream, so it can be used to talk to other
+    /// services through a transport: for example, to a moat bridge
+    /// distributor through `meek`.
+    pub async fn connect_stream(
+        &self,
+        target: &OwnedChanTarget,
+    ) -> Result<R::TcpStream, tor_chanmgr::Error> {
+        let transport = match target.chan_method() {
+            ChannelMethod::Pluggable(pt_target) => pt_target.transport().clone(),
+            _ => {
+                return Err(tor_chanmgr::Error::UnusableTarget(bad_api_usage!(
+                "Tried to connect through a pluggable transport to a target that doesn't use one."
+            )))
+            }
+        };
+        let cmethod = self
+            .get_cmethod_for_transport(&transport)
+            .await
+            .map_err(|e| tor_chanmgr::Error::Pt(Arc::new(e)))?
+            .ok_or_else(|| tor_chanmgr::Error::NoSuchTransport(transport.into()))?;
+
+        let proxy = ExternalProxyPlugin::new(self.runtime.clone(), cmethod.endpoint, cmethod.kind);
+        let (_, stream) = proxy.connect(target).await?;
+        Ok(stream)
+    }
+}
+
 /// Given a path to a binary for a pluggable transport, return an identifier for
 /// that binary in a format that can be used as a path component.
 fn pt_identifier_as_path(binary_path: impl AsRef<Path>) -> Result<PathBuf, PtError> {
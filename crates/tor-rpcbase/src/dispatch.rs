@@ -878,6 +878,21 @@ pub(crate) mod test {
             todo!()
         }
 
+        fn downgrade_owned(
+            &self,
+            _object: &crate::ObjectId,
+        ) -> Result<crate::ObjectId, crate::LookupError> {
+            todo!()
+        }
+
+        fn watch_expiry(
+            &self,
+            _objects: Vec<crate::ObjectId>,
+        ) -> Result<futures::stream::BoxStream<'static, crate::ObjectId>, crate::LookupError>
+        {
+            todo!()
+        }
+
         fn dispatch_table(&self) -> &Arc<RwLock<crate::DispatchTable>> {
             &self.table
         }
@@ -128,6 +128,27 @@ pub trait Context: Send + Sync {
     /// TODO RPC should this really return a LookupError?
     fn release_owned(&self, object: &ObjectId) -> Result<(), LookupError>;
 
+    /// Replace the owning reference called `object` with a non-owning reference.
+    ///
+    /// Return an ObjectId for the new non-owning reference.
+    /// If nothing else owns the object, it may be dropped immediately.
+    ///
+    /// This will return an error if `object` is not an owning reference.
+    fn downgrade_owned(&self, object: &ObjectId) -> Result<ObjectId, LookupError>;
+
+    /// Return a stream that yields each of `objects` once it can no longer be
+    /// looked up in this context.
+    ///
+    /// (That is, once an owning reference has been released,
+    /// or once the target of a non-owning reference has been dropped.)
+    /// The stream ends once every object has been reported.
+    ///
+    /// Expiry is not necessarily reported as soon as it happens.
+    fn watch_expiry(
+        &self,
+        objects: Vec<ObjectId>,
+    ) -> Result<futures::stream::BoxStream<'static, ObjectId>, LookupError>;
+
     /// Return a dispatch table that can be used to invoke other RPC methods.
     fn dispatch_table(&self) -> &Arc<std::sync::RwLock<DispatchTable>>;
 }
@@ -169,6 +169,11 @@ where
         self.inner.tcp.connect(addr).await
     }
 
+    #[inline]
+    async fn connect_from(&self, bind: &TcpBind, addr: &SocketAddr) -> IoResult<Self::TcpStream> {
+        self.inner.tcp.connect_from(bind, addr).await
+    }
+
     #[inline]
     async fn listen(&self, addr: &SocketAddr) -> IoResult<Self::TcpListener> {
         self.inner.tcp.listen(addr).await
@@ -13,3 +13,16 @@ pub(crate) mod rustls;
 
 #[cfg(feature = "native-tls")]
 pub(crate) mod native_tls;
+
+/// Return the error for an attempt to bind a connection to the network
+/// interface `interface`, on a platform where we can't.
+#[cfg(all(
+    any(feature = "async-std", feature = "tokio"),
+    not(any(target_os = "android", target_os = "linux"))
+))]
+pub(crate) fn bind_device_unsupported(interface: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("can't bind a connection to interface {interface:?} on this platform"),
+    )
+}
@@ -105,6 +105,45 @@ mod net {
         async fn connect(&self, addr: &SocketAddr) -> IoResult<Self::TcpStream> {
             TcpStream::connect(addr).await
         }
+        async fn connect_from(
+            &self,
+            bind: &traits::TcpBind,
+            addr: &SocketAddr,
+        ) -> IoResult<Self::TcpStream> {
+            use socket2::{Domain, Protocol, Socket, Type};
+            // async_std has no way to bind a socket before connecting it,
+            // so we start a nonblocking connection ourselves, and wait for
+            // it with async_io.  (Dropping this future abandons the attempt.)
+            let sock = Socket::new(
+                Domain::for_address(*addr),
+                Type::STREAM,
+                Some(Protocol::TCP),
+            )?;
+            sock.set_nonblocking(true)?;
+            if let Some(interface) = &bind.interface {
+                #[cfg(any(target_os = "android", target_os = "linux"))]
+                sock.bind_device(Some(interface.as_bytes()))?;
+                #[cfg(not(any(target_os = "android", target_os = "linux")))]
+                return Err(crate::impls::bind_device_unsupported(interface));
+            }
+            if let Some(local) = bind.local_addr {
+                sock.bind(&local.into())?;
+            }
+            match sock.connect(&(*addr).into()) {
+                Ok(()) => {}
+                #[cfg(unix)]
+                Err(e) if e.raw_os_error() == Some(libc::EINPROGRESS) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+            let stream = async_io::Async::new(std::net::TcpStream::from(sock))?;
+            // The socket becomes writable once the connection succeeds or fails.
+            stream.writable().await?;
+            if let Some(e) = stream.get_ref().take_error()? {
+                return Err(e);
+            }
+            Ok(TcpStream::from(stream.into_inner()?))
+        }
         async fn listen(&self, addr: &SocketAddr) -> IoResult<Self::TcpListener> {
             TcpListener::bind(*addr).await
         }
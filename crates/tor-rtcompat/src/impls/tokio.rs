@@ -154,6 +154,27 @@ impl crate::traits::TcpProvider for TokioRuntimeHandle {
         let s = net::TokioTcpStream::connect(addr).await?;
         Ok(s.into())
     }
+    async fn connect_from(
+        &self,
+        bind: &crate::traits::TcpBind,
+        addr: &std::net::SocketAddr,
+    ) -> IoResult<Self::TcpStream> {
+        let sock = match addr {
+            std::net::SocketAddr::V4(_) => tokio_crate::net::TcpSocket::new_v4()?,
+            std::net::SocketAddr::V6(_) => tokio_crate::net::TcpSocket::new_v6()?,
+        };
+        if let Some(interface) = &bind.interface {
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            sock.bind_device(Some(interface.as_bytes()))?;
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            return Err(crate::impls::bind_device_unsupported(interface));
+        }
+        if let Some(local) = bind.local_addr {
+            sock.bind(local)?;
+        }
+        let s = sock.connect(*addr).await?;
+        Ok(s.into())
+    }
     async fn listen(&self, addr: &std::net::SocketAddr) -> IoResult<Self::TcpListener> {
         let lis = net::TokioTcpListener::bind(*addr).await?;
         Ok(net::TcpListener { lis })
@@ -58,8 +58,8 @@ mod traits;
 #[cfg(any(feature = "async-std", feature = "tokio"))]
 use std::io;
 pub use traits::{
-    BlockOn, CertifiedConn, CoarseTimeProvider, Runtime, SleepProvider, TcpListener, TcpProvider,
-    TlsProvider, UdpProvider, UdpSocket,
+    BlockOn, CertifiedConn, CoarseTimeProvider, Runtime, SleepProvider, TcpBind, TcpListener,
+    TcpProvider, TlsProvider, UdpProvider, UdpSocket,
 };
 
 pub use coarse_time::{CoarseDuration, CoarseInstant, RealCoarseTimeProvider};
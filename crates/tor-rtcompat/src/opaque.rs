@@ -48,6 +48,14 @@ macro_rules! implement_opaque_runtime {
             self.$member.connect(addr).await
         }
         #[inline]
+        async fn connect_from(
+            &self,
+            bind: &$crate::traits::TcpBind,
+            addr: &std::net::SocketAddr,
+        ) -> std::io::Result<Self::TcpStream> {
+            self.$member.connect_from(bind, addr).await
+        }
+        #[inline]
         async fn listen(&self, addr: &std::net::SocketAddr) -> std::io::Result<Self::TcpListener> {
             self.$member.listen(addr).await
         }
@@ -4,9 +4,11 @@ use crate::SleepProvider;
 use futures::channel::mpsc;
 use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
 use futures::{Stream, StreamExt};
+use rand::Rng as _;
 use std::future::Future;
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
 use std::time::{Duration, Instant, SystemTime};
 
 use pin_project::pin_project;
@@ -29,7 +31,7 @@ pub enum SleepError {
 }
 
 /// A command sent from task handles to schedule objects.
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 enum SchedulerCommand {
     /// Run the task now.
     Fire,
@@ -43,6 +45,8 @@ enum SchedulerCommand {
     /// Resume execution.  If there is a pending timer, start waiting for it again;
     /// otherwise, fire immediately.
     Resume,
+    /// Become a member of a group.
+    JoinGroup(ScheduleGroup),
 }
 
 /// A remotely-controllable trigger for recurring tasks.
@@ -65,6 +69,125 @@ pub struct TaskSchedule<R: SleepProvider> {
     /// Whether we are currently "suspended".  If we are suspended, we won't
     /// start executing again till we're explicitly "resumed".
     suspended: bool,
+    /// How we adjust the delays we're asked to sleep for.
+    timing: Timing,
+    /// The group we belong to, if any.
+    ///
+    /// While the group is suspended, we're suspended too.
+    group: Option<ScheduleGroup>,
+}
+
+/// Adjustments that a [`TaskSchedule`] makes to the delays it's asked to wait for
+#[derive(Copy, Clone, Debug, Default)]
+struct Timing {
+    /// Add a random delay of up to this much.
+    jitter: Duration,
+    /// Round the wallclock time at which we fire up to a multiple of this, if nonzero.
+    coalescing: Duration,
+}
+
+impl Timing {
+    /// Return the delay we should actually wait for, when asked to wait for `dur`
+    ///
+    /// We add jitter first and coalesce afterwards:
+    /// otherwise, the jitter would move our wakeups off the coalescing grid,
+    /// and we would lose the wakeups that coalescing saves.
+    fn adjust(&self, dur: Duration, now: SystemTime) -> Duration {
+        let dur = if self.jitter.is_zero() {
+            dur
+        } else {
+            dur + rand::thread_rng().gen_range(Duration::ZERO..=self.jitter)
+        };
+        coalesced_delay(now, dur, self.coalescing)
+    }
+}
+
+/// Return a delay of at least `dur`, which ends at a multiple of `granularity`
+///
+/// The multiples are counted from the Unix epoch,
+/// so every schedule (in this process, or another) using the same granularity
+/// fires at the same moments.
+fn coalesced_delay(now: SystemTime, dur: Duration, granularity: Duration) -> Duration {
+    let granularity = granularity.as_nanos();
+    let Ok(since_epoch) = now.duration_since(SystemTime::UNIX_EPOCH) else {
+        return dur;
+    };
+    if granularity == 0 {
+        return dur;
+    }
+    let target = since_epoch.as_nanos() + dur.as_nanos();
+    let extra = (granularity - target % granularity) % granularity;
+    dur + Duration::from_nanos(u64::try_from(extra).unwrap_or(u64::MAX))
+}
+
+/// A set of [`TaskSchedule`]s that can be suspended and resumed together
+///
+/// This is intended for "dormant" modes, where we want to stop all our periodic
+/// background activity at once, without keeping track of every task.
+///
+/// While the group is suspended, its schedules behave as if each of their
+/// [`TaskHandle`]s had been [suspended](TaskHandle::suspend):
+/// timers keep counting, but nothing fires until the group is resumed.
+/// A schedule that was separately suspended via its handle stays suspended
+/// when the group is resumed.
+#[derive(Clone, Debug, Default)]
+pub struct ScheduleGroup {
+    /// The shared state
+    inner: Arc<Mutex<GroupState>>,
+}
+
+/// The shared state of a [`ScheduleGroup`]
+#[derive(Debug, Default)]
+struct GroupState {
+    /// Whether the group is suspended
+    suspended: bool,
+    /// Schedules that were polled while we were suspended, and need waking on resume
+    wakers: Vec<Waker>,
+}
+
+impl ScheduleGroup {
+    /// Create a new group, which is not suspended
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Suspend every schedule in this group
+    pub fn suspend(&self) {
+        self.lock().suspended = true;
+    }
+
+    /// Resume every schedule in this group
+    pub fn resume(&self) {
+        let wakers = {
+            let mut state = self.lock();
+            state.suspended = false;
+            std::mem::take(&mut state.wakers)
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Return true if this group is suspended
+    pub fn is_suspended(&self) -> bool {
+        self.lock().suspended
+    }
+
+    /// If we are suspended, arrange for the task in `cx` to be woken on resume,
+    /// and return true
+    fn poll_suspended(&self, cx: &mut Context<'_>) -> bool {
+        let mut state = self.lock();
+        if state.suspended && !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        state.suspended
+    }
+
+    /// Lock the shared state
+    fn lock(&self) -> std::sync::MutexGuard<'_, GroupState> {
+        // The state is always consistent, so a poisoned lock is fine.
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
 }
 
 /// A handle used to control a [`TaskSchedule`].
@@ -89,13 +212,45 @@ impl<R: SleepProvider> TaskSchedule<R> {
                 // Start off ready.
                 instant_fire: true,
                 suspended: false,
+                timing: Timing::default(),
+                group: None,
             },
             TaskHandle { tx },
         )
     }
 
+    /// Add up to `jitter` of random extra delay, whenever this schedule waits for a timer.
+    ///
+    /// This applies to [`fire_in`](TaskSchedule::fire_in) and [`sleep`](TaskSchedule::sleep),
+    /// and to [`TaskHandle::fire_at`].
+    /// It's useful for tasks whose timing would otherwise be observable from outside.
+    ///
+    /// The jitter is added before any [coalescing](TaskSchedule::set_coalescing),
+    /// so with coalescing, it chooses among the moments on the coalescing grid
+    /// (and a jitter smaller than the granularity may have little effect).
+    pub fn set_jitter(&mut self, jitter: Duration) {
+        self.timing.jitter = jitter;
+    }
+
+    /// Round the time at which this schedule's timers fire up to a multiple of `granularity`.
+    ///
+    /// Schedules with the same granularity wake up at the same moments,
+    /// so that a process with many periodic tasks wakes up less often.
+    /// Any [jitter](TaskSchedule::set_jitter) is added first.
+    ///
+    /// A zero `granularity` (the default) disables coalescing.
+    pub fn set_coalescing(&mut self, granularity: Duration) {
+        self.timing.coalescing = granularity;
+    }
+
+    /// Make this schedule a member of `group`, so that it is suspended along with the group.
+    pub fn join_group(&mut self, group: &ScheduleGroup) {
+        self.group = Some(group.clone());
+    }
+
     /// Trigger the schedule after `dur`.
     pub fn fire_in(&mut self, dur: Duration) {
+        let dur = self.timing.adjust(dur, self.rt.wallclock());
         self.instant_fire = false;
         self.sleep = Some(Box::pin(self.rt.sleep(dur)));
     }
@@ -189,6 +344,17 @@ impl TaskHandle {
     pub fn resume(&self) -> bool {
         self.tx.unbounded_send(SchedulerCommand::Resume).is_ok()
     }
+
+    /// Make the corresponding schedule a member of `group`.
+    ///
+    /// Like [`TaskSchedule::join_group`], but usable by code that only has the handle.
+    ///
+    /// Returns `true` if the schedule still exists, and `false` otherwise.
+    pub fn join_group(&self, group: &ScheduleGroup) -> bool {
+        self.tx
+            .unbounded_send(SchedulerCommand::JoinGroup(group.clone()))
+            .is_ok()
+    }
 }
 
 // NOTE(eta): implemented on the *pin projection*, not the original type, because we don't want
@@ -204,6 +370,7 @@ impl<R: SleepProvider> TaskScheduleP<'_, R> {
             SchedulerCommand::FireAt(instant) => {
                 let now = self.rt.now();
                 let dur = instant.saturating_duration_since(now);
+                let dur = self.timing.adjust(dur, self.rt.wallclock());
                 *self.instant_fire = false;
                 *self.sleep = Some(Box::pin(self.rt.sleep(dur)));
             }
@@ -217,6 +384,9 @@ impl<R: SleepProvider> TaskScheduleP<'_, R> {
             SchedulerCommand::Resume => {
                 *self.suspended = false;
             }
+            SchedulerCommand::JoinGroup(group) => {
+                *self.group = Some(group);
+            }
         }
     }
 }
@@ -238,6 +408,11 @@ impl<R: SleepProvider> Stream for TaskSchedule<R> {
         if *this.suspended {
             return Poll::Pending;
         }
+        if let Some(group) = this.group {
+            if group.poll_suspended(cx) {
+                return Poll::Pending;
+            }
+        }
         if *this.instant_fire {
             *this.instant_fire = false;
             return Poll::Ready(Some(()));
@@ -427,4 +602,77 @@ mod test {
             hdl.resume();
         });
     }
+
+    #[test]
+    fn coalescing() {
+        use super::coalesced_delay;
+        use std::time::SystemTime;
+
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let secs = Duration::from_secs;
+
+        assert_eq!(coalesced_delay(at(1000), secs(5), secs(0)), secs(5));
+        assert_eq!(coalesced_delay(at(1000), secs(5), secs(10)), secs(10));
+        assert_eq!(coalesced_delay(at(1000), secs(10), secs(10)), secs(10));
+        assert_eq!(coalesced_delay(at(1003), secs(5), secs(10)), secs(7));
+        assert_eq!(coalesced_delay(at(1003), secs(0), secs(10)), secs(7));
+    }
+
+    #[test]
+    fn jitter_before_coalescing() {
+        use super::Timing;
+        use std::time::SystemTime;
+
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1003);
+        let timing = Timing {
+            jitter: Duration::from_secs(8),
+            coalescing: Duration::from_secs(10),
+        };
+        for _ in 0..100 {
+            // 5 to 13 seconds of delay, rounded up to the grid.
+            let dur = timing.adjust(Duration::from_secs(5), now);
+            assert!(
+                dur == Duration::from_secs(7) || dur == Duration::from_secs(17),
+                "{:?}",
+                dur
+            );
+        }
+    }
+
+    #[test]
+    fn group_suspend_and_resume() {
+        use crate::scheduler::ScheduleGroup;
+
+        test_with_all_runtimes!(|rt| async move {
+            let group = ScheduleGroup::new();
+            let (mut sch, hdl) = TaskSchedule::new(rt.clone());
+            sch.join_group(&group);
+            assert!(sch.next().now_or_never().is_some());
+
+            group.suspend();
+            hdl.fire();
+            assert!(sch.next().now_or_never().is_none());
+            assert!(group.is_suspended());
+
+            group.resume();
+            assert!(sch.next().now_or_never().is_some());
+
+            // Suspension via the handle is independent of the group.
+            hdl.suspend();
+            hdl.fire();
+            group.suspend();
+            group.resume();
+            assert!(sch.next().now_or_never().is_none());
+            hdl.resume();
+            assert!(sch.next().now_or_never().is_some());
+
+            // A schedule can also join a group via its handle.
+            let (mut sch, hdl) = TaskSchedule::new(rt.clone());
+            assert!(hdl.join_group(&group));
+            group.suspend();
+            assert!(sch.next().now_or_never().is_none());
+            group.resume();
+            assert!(sch.next().now_or_never().is_some());
+        });
+    }
 }
@@ -172,10 +172,64 @@ pub trait TcpProvider: Clone + Send + Sync + 'static {
     /// unnecessary DNS lookups.
     async fn connect(&self, addr: &SocketAddr) -> IoResult<Self::TcpStream>;
 
+    /// Launch a TCP connection to `addr`, bound as `bind` says.
+    ///
+    /// Use this to make sure that a connection leaves through a particular
+    /// local address or network interface, on a host that has more than one.
+    ///
+    /// The default implementation fails with [`std::io::ErrorKind::Unsupported`]:
+    /// providers that can bind their outgoing connections should override it.
+    async fn connect_from(&self, bind: &TcpBind, addr: &SocketAddr) -> IoResult<Self::TcpStream> {
+        let _ = (bind, addr);
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "this TcpProvider can't bind outgoing connections",
+        ))
+    }
+
     /// Open a TCP listener on a given socket address.
     async fn listen(&self, addr: &SocketAddr) -> IoResult<Self::TcpListener>;
 }
 
+/// Where an outgoing TCP connection should come from.
+///
+/// Used by [`TcpProvider::connect_from`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct TcpBind {
+    /// The local address to bind the connection to, if any.
+    ///
+    /// If the port is 0, the operating system picks one.
+    pub local_addr: Option<SocketAddr>,
+    /// The name of the network interface to bind the connection to, if any.
+    ///
+    /// Unlike a local address, this makes sure that the connection leaves
+    /// through that interface, whatever the routing table says.  It is only
+    /// supported on Linux and Android (with `SO_BINDTODEVICE`): elsewhere,
+    /// connecting with an interface set fails with
+    /// [`std::io::ErrorKind::Unsupported`].
+    pub interface: Option<String>,
+}
+
+impl TcpBind {
+    /// Return a new `TcpBind` that binds to nothing in particular.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bind to the local address `addr`.
+    pub fn with_local_addr(mut self, addr: SocketAddr) -> Self {
+        self.local_addr = Some(addr);
+        self
+    }
+
+    /// Bind to the network interface called `name`.
+    pub fn with_interface(mut self, name: impl Into<String>) -> Self {
+        self.interface = Some(name.into());
+        self
+    }
+}
+
 /// Trait for a local socket that accepts incoming TCP streams.
 ///
 /// These objects are returned by instances of [`TcpProvider`].  To use
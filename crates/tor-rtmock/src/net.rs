@@ -9,11 +9,12 @@ use super::io::{stream_pair, LocalStream};
 use super::MockNetRuntime;
 use core::fmt;
 use tor_rtcompat::tls::TlsConnector;
-use tor_rtcompat::{CertifiedConn, Runtime, TcpListener, TcpProvider, TlsProvider};
+use tor_rtcompat::{CertifiedConn, Runtime, TcpBind, TcpListener, TcpProvider, TlsProvider};
 use tor_rtcompat::{UdpProvider, UdpSocket};
 
 use async_trait::async_trait;
 use futures::channel::mpsc;
+use futures::future::BoxFuture;
 use futures::io::{AsyncRead, AsyncWrite};
 use futures::lock::Mutex as AsyncMutex;
 use futures::sink::SinkExt;
@@ -27,7 +28,9 @@ use std::pin::Pin;
 use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
+use std::time::Duration;
 use thiserror::Error;
+use tor_rtcompat::SleepProvider;
 use void::Void;
 
 /// A channel sender that we use to send incoming connections to
@@ -36,6 +39,12 @@ type ConnSender = mpsc::Sender<(LocalStream, SocketAddr)>;
 /// A channel receiver that listeners use to receive incoming connections.
 type ConnReceiver = mpsc::Receiver<(LocalStream, SocketAddr)>;
 
+/// A type-erased sleep function, used to simulate latency.
+///
+/// We keep this type-erased so that `MockNetProvider` doesn't need to be
+/// generic over a `SleepProvider`.
+type DynSleeper = Arc<dyn Fn(Duration) -> BoxFuture<'static, ()> + Send + Sync>;
+
 /// A simulated Internet, for testing.
 ///
 /// We simulate TCP streams only, and skip all the details. Connection
@@ -46,6 +55,33 @@ type ConnReceiver = mpsc::Receiver<(LocalStream, SocketAddr)>;
 pub struct MockNetwork {
     /// A map from address to the entries about listeners there.
     listening: Mutex<HashMap<SocketAddr, AddrBehavior>>,
+    /// A map from address to the simulated conditions for reaching that address.
+    ///
+    /// Addresses not in this map are reached instantly and reliably.
+    conditions: Mutex<HashMap<SocketAddr, LinkConditions>>,
+}
+
+/// Simulated network conditions for connections to a single address.
+///
+/// Set with [`MockNetwork::set_conditions`].
+///
+/// All of these conditions are deterministic,
+/// so that tests using them are reproducible.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct LinkConditions {
+    /// How long each connection attempt to the address takes to complete
+    /// (successfully or otherwise).
+    ///
+    /// The delay is measured using the `SleepProvider` given to the connecting
+    /// provider's [`ProviderBuilder`]; see [`ProviderBuilder::sleep_provider`].
+    pub connect_latency: Duration,
+    /// How many upcoming connection attempts to the address should be lost.
+    ///
+    /// Each lost attempt fails with [`ErrorKind::ConnectionReset`],
+    /// after `connect_latency` has elapsed.
+    /// Each attempt decrements this count.
+    pub lose_next: usize,
 }
 
 /// The `MockNetwork`'s view of a listener.
@@ -80,7 +116,8 @@ enum AddrBehavior {
 /// # Limitations
 ///
 /// There's no randomness here, so we can't simulate the weirdness of
-/// real networks.
+/// real networks.  We can, however, simulate deterministic connection
+/// latency and connection loss: see [`MockNetwork::set_conditions`].
 ///
 /// So far, there's no support for DNS or UDP.
 ///
@@ -129,6 +166,8 @@ struct MockNetProviderInner {
     addrs: Vec<IpAddr>,
     /// Shared reference to the network.
     net: Arc<MockNetwork>,
+    /// A way to sleep, if we have one.  Used to simulate latency.
+    sleeper: Option<DynSleeper>,
     /// Next port number to hand out when we're asked to listen on
     /// port 0.
     ///
@@ -151,11 +190,14 @@ pub struct MockNetListener {
 /// A builder object used to configure a [`MockNetProvider`]
 ///
 /// Returned by [`MockNetwork::builder()`].
+#[derive(Clone)]
 pub struct ProviderBuilder {
     /// List of public addresses.
     addrs: Vec<IpAddr>,
     /// Shared reference to the network.
     net: Arc<MockNetwork>,
+    /// A way to sleep, if we have one.
+    sleeper: Option<DynSleeper>,
 }
 
 impl Default for MockNetProvider {
@@ -186,6 +228,57 @@ impl MockNetwork {
         ProviderBuilder {
             addrs: vec![],
             net: Arc::clone(self),
+            sleeper: None,
+        }
+    }
+
+    /// Set the simulated conditions for connections to `address`.
+    ///
+    /// Replaces any conditions previously set for `address`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use tor_rtmock::net::*;
+    /// # use std::time::Duration;
+    /// # let mock_network = MockNetwork::new();
+    /// let mut conditions = LinkConditions::default();
+    /// conditions.connect_latency = Duration::from_millis(300);
+    /// conditions.lose_next = 2;
+    /// mock_network.set_conditions("198.51.100.6:443".parse().unwrap(), conditions);
+    /// ```
+    pub fn set_conditions(&self, address: SocketAddr, conditions: LinkConditions) {
+        let mut map = self
+            .conditions
+            .lock()
+            .expect("Poisoned lock for conditions");
+        map.insert(address, conditions);
+    }
+
+    /// Remove any simulated conditions for connections to `address`.
+    pub fn clear_conditions(&self, address: &SocketAddr) {
+        let mut map = self
+            .conditions
+            .lock()
+            .expect("Poisoned lock for conditions");
+        map.remove(address);
+    }
+
+    /// Consult the conditions for a connection attempt to `address`.
+    ///
+    /// Returns the latency to simulate, and whether this attempt should be lost.
+    fn take_conditions(&self, address: &SocketAddr) -> (Duration, bool) {
+        let mut map = self
+            .conditions
+            .lock()
+            .expect("Poisoned lock for conditions");
+        match map.get_mut(address) {
+            Some(cond) => {
+                let lost = cond.lose_next > 0;
+                cond.lose_next = cond.lose_next.saturating_sub(1);
+                (cond.connect_latency, lost)
+            }
+            None => (Duration::ZERO, false),
         }
     }
 
@@ -259,16 +352,37 @@ impl ProviderBuilder {
         self.addrs.push(addr);
         self
     }
+    /// Use `sleep` to simulate the latency configured with
+    /// [`MockNetwork::set_conditions`].
+    ///
+    /// If no sleep provider is set, connecting to an address with nonzero
+    /// `connect_latency` will panic.
+    ///
+    /// [`runtime()`](ProviderBuilder::runtime) sets this automatically.
+    pub fn sleep_provider<SP: SleepProvider>(&mut self, sleep: SP) -> &mut Self {
+        self.sleeper = Some(Arc::new(move |d: Duration| -> BoxFuture<'static, ()> {
+            Box::pin(sleep.sleep(d))
+        }));
+        self
+    }
     /// Use this builder to return a new [`MockNetRuntime`] wrapping
     /// an existing `runtime`.
+    ///
+    /// The runtime's `SleepProvider` is used to simulate latency.
     pub fn runtime<R: Runtime>(&self, runtime: R) -> super::MockNetRuntime<R> {
-        MockNetRuntime::new(runtime, self.provider())
+        let provider = {
+            let mut builder = self.clone();
+            builder.sleep_provider(runtime.clone());
+            builder.provider()
+        };
+        MockNetRuntime::new(runtime, provider)
     }
     /// Use this builder to return a new [`MockNetProvider`]
     pub fn provider(&self) -> MockNetProvider {
         let inner = MockNetProviderInner {
             addrs: self.addrs.clone(),
             net: Arc::clone(&self.net),
+            sleeper: self.sleeper.clone(),
             next_port: AtomicU16::new(1),
         };
         MockNetProvider {
@@ -431,15 +545,26 @@ impl MockNetProvider {
 
         Ok(MockNetListener { addr, receiver })
     }
-}
 
-#[async_trait]
-impl TcpProvider for MockNetProvider {
-    type TcpStream = LocalStream;
-    type TcpListener = MockNetListener;
+    /// Helper for connecting: open a connection from `my_addr` to `addr`.
+    async fn connect_from_addr(
+        &self,
+        my_addr: SocketAddr,
+        addr: &SocketAddr,
+    ) -> IoResult<LocalStream> {
+        let (latency, lost) = self.inner.net.take_conditions(addr);
+        if !latency.is_zero() {
+            let sleeper = self
+                .inner
+                .sleeper
+                .as_ref()
+                .expect("simulated latency requires a sleep provider; see ProviderBuilder");
+            sleeper(latency).await;
+        }
+        if lost {
+            return Err(err(ErrorKind::ConnectionReset));
+        }
 
-    async fn connect(&self, addr: &SocketAddr) -> IoResult<LocalStream> {
-        let my_addr = self.get_origin_addr_for(addr)?;
         let (mut mine, theirs) = stream_pair();
 
         let cert = self
@@ -452,6 +577,32 @@ impl TcpProvider for MockNetProvider {
 
         Ok(mine)
     }
+}
+
+#[async_trait]
+impl TcpProvider for MockNetProvider {
+    type TcpStream = LocalStream;
+    type TcpListener = MockNetListener;
+
+    async fn connect(&self, addr: &SocketAddr) -> IoResult<LocalStream> {
+        let my_addr = self.get_origin_addr_for(addr)?;
+        self.connect_from_addr(my_addr, addr).await
+    }
+
+    async fn connect_from(&self, bind: &TcpBind, addr: &SocketAddr) -> IoResult<LocalStream> {
+        // Our simulated hosts have no network interfaces to bind to.
+        if bind.interface.is_some() {
+            return Err(err(ErrorKind::Unsupported));
+        }
+        let my_addr = match &bind.local_addr {
+            Some(local) if local.is_ipv4() != addr.is_ipv4() => {
+                return Err(err(ErrorKind::AddrNotAvailable));
+            }
+            Some(local) => self.get_listener_addr(local)?,
+            None => self.get_origin_addr_for(addr)?,
+        };
+        self.connect_from_addr(my_addr, addr).await
+    }
 
     async fn listen(&self, addr: &SocketAddr) -> IoResult<Self::TcpListener> {
         let addr = self.get_listener_addr(addr)?;
@@ -678,6 +829,50 @@ mod test {
         IoResult::Ok(())
     }
 
+    #[test]
+    fn connect_from() {
+        test_with_all_runtimes!(|_rt| async {
+            let net = MockNetwork::new();
+            let ip_a: IpAddr = "192.0.2.55".parse().unwrap();
+            let ip_b: IpAddr = "192.0.2.66".parse().unwrap();
+            let client = net.builder().add_address(ip_a).add_address(ip_b).provider();
+            let server = net
+                .builder()
+                .add_address("198.51.100.7".parse().unwrap())
+                .provider();
+            let lis = server.listen(&"0.0.0.0:99".parse().unwrap()).await?;
+            let address = lis.local_addr()?;
+
+            let (r1, r2): (IoResult<()>, IoResult<()>) = futures::join!(
+                async {
+                    let bound = TcpBind::new().with_local_addr(SocketAddr::new(ip_b, 0));
+                    let mut conn = client.connect_from(&bound, &address).await?;
+                    conn.close().await?;
+
+                    // We can't bind to somebody else's address, to the
+                    // wrong address family, or to an interface.
+                    let not_ours = TcpBind::new().with_local_addr("192.0.2.77:0".parse().unwrap());
+                    assert!(client.connect_from(&not_ours, &address).await.is_err());
+                    let wrong_family = TcpBind::new().with_local_addr("[::]:0".parse().unwrap());
+                    assert!(client.connect_from(&wrong_family, &address).await.is_err());
+                    let interface = TcpBind::new().with_interface("wg0");
+                    assert!(client.connect_from(&interface, &address).await.is_err());
+                    Ok(())
+                },
+                async {
+                    let (mut conn, a) = lis.accept().await?;
+                    assert_eq!(a.ip(), ip_b);
+                    let mut v = Vec::new();
+                    let _ = conn.read_to_end(&mut v).await?;
+                    Ok(())
+                }
+            );
+            r1?;
+            r2?;
+            IoResult::Ok(())
+        });
+    }
+
     #[test]
     fn listener_stream() {
         test_with_all_runtimes!(|_rt| async {
@@ -711,6 +906,61 @@ mod test {
         });
     }
 
+    #[test]
+    fn latency_and_loss() {
+        use crate::MockRuntime;
+
+        MockRuntime::test_with_various(|rt| async move {
+            let net = MockNetwork::new();
+            let client = net
+                .builder()
+                .add_address("192.0.2.55".parse().unwrap())
+                .runtime(rt.clone());
+            let server = net
+                .builder()
+                .add_address("198.51.100.7".parse().unwrap())
+                .provider();
+            let lis = server.listen(&"0.0.0.0:99".parse().unwrap()).await.unwrap();
+            let address = lis.local_addr().unwrap();
+
+            let mut conditions = LinkConditions::default();
+            conditions.connect_latency = Duration::from_secs(3);
+            conditions.lose_next = 1;
+            net.set_conditions(address, conditions);
+
+            let spawn_connect = || {
+                let client = client.clone();
+                rt.spawn_join("connect", async move {
+                    client.connect(&address).await.map(|_| ())
+                })
+            };
+
+            // The first attempt is delayed, and then lost.
+            let start = rt.now();
+            let attempt = spawn_connect();
+            rt.advance_until_stalled().await;
+            assert_eq!(rt.now() - start, Duration::from_secs(3));
+            let e = attempt.await.unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::ConnectionReset);
+
+            // The second attempt is delayed, and then succeeds.
+            let start = rt.now();
+            let attempt = spawn_connect();
+            rt.advance_until_stalled().await;
+            assert_eq!(rt.now() - start, Duration::from_secs(3));
+            let _ = lis.accept().await.unwrap();
+            attempt.await.unwrap();
+
+            // With conditions cleared, connecting is instant.
+            net.clear_conditions(&address);
+            let start = rt.now();
+            let (r1, r2) = futures::join!(client.connect(&address), lis.accept());
+            r1.unwrap();
+            r2.unwrap();
+            assert_eq!(rt.now(), start);
+        });
+    }
+
     #[test]
     fn tls_basics() {
         let (client1, client2) = client_pair();
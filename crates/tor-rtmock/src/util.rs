@@ -45,6 +45,13 @@ define_derive_deftly! {
         async fn connect(&self, addr: &SocketAddr) -> IoResult<Self::TcpStream> {
             self.$fname.connect(addr).await
         }
+        async fn connect_from(
+            &self,
+            bind: &TcpBind,
+            addr: &SocketAddr,
+        ) -> IoResult<Self::TcpStream> {
+            self.$fname.connect_from(bind, addr).await
+        }
         async fn listen(&self, addr: &SocketAddr) -> IoResult<Self::TcpListener> {
             self.$fname.listen(addr).await
         }
@@ -137,7 +144,7 @@ pub(crate) mod impl_runtime_prelude {
     pub(crate) use std::net::SocketAddr;
     pub(crate) use std::time::{Duration, Instant, SystemTime};
     pub(crate) use tor_rtcompat::{
-        BlockOn, CoarseInstant, CoarseTimeProvider, Runtime, SleepProvider, TcpProvider,
+        BlockOn, CoarseInstant, CoarseTimeProvider, Runtime, SleepProvider, TcpBind, TcpProvider,
         TlsProvider, UdpProvider,
     };
 }
@@ -1,7 +1,9 @@
 //! Types to implement the SOCKS handshake.
 
 use super::Action;
-use crate::msg::{SocksAddr, SocksAuth, SocksCmd, SocksRequest, SocksStatus, SocksVersion};
+use crate::msg::{
+    SocksAddr, SocksAuth, SocksCmd, SocksReply, SocksRequest, SocksStatus, SocksVersion,
+};
 use crate::{Error, Result, TResult, Truncated};
 
 use tor_bytes::{EncodeResult, Error as BytesError};
@@ -250,25 +252,66 @@ impl Default for SocksProxyHandshake {
 }
 
 impl SocksRequest {
+    /// Record whether the client that sent this request understands Tor's
+    /// extended SOCKS5 error codes.
+    ///
+    /// SOCKS has no way for a client to advertise this during the
+    /// handshake, so the proxy must learn it some other way: typically,
+    /// from its configuration.
+    ///
+    /// By default, replies to this request include the extended codes.
+    /// Once this is set to false, they replace the extended codes
+    /// (see [`SocksStatus::is_tor_extension`]) with
+    /// [`SocksStatus::HOST_UNREACHABLE`].
+    pub fn set_extended_errors(&mut self, supported: bool) {
+        self.extended_errors = supported;
+    }
+
     /// Format a reply to this request, indicating success or failure.
     ///
-    /// Note that an address should be provided only when the request
-    /// was for a RESOLVE.
+    /// If `addr` is provided, it is sent along with the port from this
+    /// request.  Note that an address should be provided only when the request
+    /// was for a RESOLVE; to tell the client about some other address and
+    /// port, use [`encode_reply`](SocksRequest::encode_reply).
     pub fn reply(&self, status: SocksStatus, addr: Option<&SocksAddr>) -> EncodeResult<Vec<u8>> {
+        let reply = match addr {
+            Some(a) => SocksReply::new(status, a.clone(), self.port()),
+            // TODO: sometimes I think we want to answer with ::, not 0.0.0.0
+            None => SocksReply::new(
+                status,
+                SocksAddr::Ip(std::net::Ipv4Addr::UNSPECIFIED.into()),
+                0,
+            ),
+        };
+        self.encode_reply(&reply)
+    }
+
+    /// Format `reply` as a reply to this request.
+    ///
+    /// The reply's address and port become the `BND.ADDR` and `BND.PORT`
+    /// fields of the message.
+    ///
+    /// SOCKS4 replies can only contain an IPv4 address; if this request was
+    /// SOCKS4 and `reply` has some other kind of address, we send zeros instead.
+    ///
+    /// Tor's extended error codes are not sent if the proxy has said that the
+    /// client doesn't support them:
+    /// see [`set_extended_errors`](SocksRequest::set_extended_errors).
+    pub fn encode_reply(&self, reply: &SocksReply) -> EncodeResult<Vec<u8>> {
         match self.version() {
-            SocksVersion::V4 => self.s4(status, addr),
-            SocksVersion::V5 => self.s5(status, addr),
+            SocksVersion::V4 => self.s4(reply),
+            SocksVersion::V5 => self.s5(reply),
         }
     }
 
     /// Format a SOCKS4 reply.
-    fn s4(&self, status: SocksStatus, addr: Option<&SocksAddr>) -> EncodeResult<Vec<u8>> {
+    fn s4(&self, reply: &SocksReply) -> EncodeResult<Vec<u8>> {
         let mut w = Vec::new();
         w.write_u8(0);
-        w.write_u8(status.into_socks4_status());
-        match addr {
-            Some(SocksAddr::Ip(IpAddr::V4(ip))) => {
-                w.write_u16(self.port());
+        w.write_u8(reply.status().into_socks4_status());
+        match reply.addr() {
+            SocksAddr::Ip(IpAddr::V4(ip)) => {
+                w.write_u16(reply.port());
                 w.write(ip)?;
             }
             _ => {
@@ -280,19 +323,18 @@ impl SocksRequest {
     }
 
     /// Format a SOCKS5 reply.
-    fn s5(&self, status: SocksStatus, addr: Option<&SocksAddr>) -> EncodeResult<Vec<u8>> {
+    fn s5(&self, reply: &SocksReply) -> EncodeResult<Vec<u8>> {
         let mut w = Vec::new();
+        let status = if self.extended_errors() {
+            reply.status()
+        } else {
+            reply.status().without_tor_extensions()
+        };
         w.write_u8(5);
         w.write_u8(status.into());
         w.write_u8(0); // reserved.
-        if let Some(a) = addr {
-            w.write(a)?;
-            w.write_u16(self.port());
-        } else {
-            // TODO: sometimes I think we want to answer with ::, not 0.0.0.0
-            w.write(&SocksAddr::Ip(std::net::Ipv4Addr::UNSPECIFIED.into()))?;
-            w.write_u16(0);
-        }
+        w.write(reply.addr())?;
+        w.write_u16(reply.port());
         Ok(w)
     }
 }
@@ -473,6 +515,94 @@ mod test {
         );
     }
 
+    #[test]
+    fn encode_reply() {
+        let v4 = SocksAddr::Ip("192.0.2.9".parse().unwrap());
+        let v6 = SocksAddr::Ip("2001:db8::1122".parse().unwrap());
+
+        let mut h = SocksProxyHandshake::new();
+        let _a = h.handshake(&hex!("05 01 00")).unwrap().unwrap();
+        let _a = h
+            .handshake(&hex!("05 01 00 01 7f000007 1f90"))
+            .unwrap()
+            .unwrap();
+        let req = h.into_request().unwrap();
+        assert_eq!(
+            req.encode_reply(&SocksReply::new(SocksStatus::SUCCEEDED, v4.clone(), 4321))
+                .unwrap(),
+            hex!("05 00 00 01 c0000209 10e1")
+        );
+        assert_eq!(
+            req.encode_reply(&SocksReply::new(SocksStatus::SUCCEEDED, v6.clone(), 4321))
+                .unwrap(),
+            hex!("05 00 00 04 20010db8 00000000 00000000 00001122 10e1")
+        );
+
+        let mut h = SocksProxyHandshake::new();
+        let _a = h
+            .handshake(&hex!("04 01 0050 CB007107 00"))
+            .unwrap()
+            .unwrap();
+        let req = h.into_request().unwrap();
+        assert_eq!(
+            req.encode_reply(&SocksReply::new(SocksStatus::SUCCEEDED, v4, 4321))
+                .unwrap(),
+            hex!("00 5A 10e1 c0000209")
+        );
+        // SOCKS4 can't express an IPv6 address.
+        assert_eq!(
+            req.encode_reply(&SocksReply::new(SocksStatus::SUCCEEDED, v6, 4321))
+                .unwrap(),
+            hex!("00 5A 0000 00000000")
+        );
+    }
+
+    #[test]
+    fn extended_errors() {
+        let mut h = SocksProxyHandshake::new();
+        let _a = h.handshake(&hex!("05 01 00")).unwrap().unwrap();
+        let _a = h
+            .handshake(&hex!("05 01 00 01 7f000007 1f90"))
+            .unwrap()
+            .unwrap();
+        let mut req = h.into_request().unwrap();
+
+        // By default, we send the extended codes, as we always have.
+        assert!(req.extended_errors());
+        assert_eq!(
+            req.reply(SocksStatus::HS_DESC_NOT_FOUND, None).unwrap(),
+            hex!("05 F0 00 01 00000000 0000")
+        );
+        assert_eq!(
+            req.reply(SocksStatus::HS_INTRO_TIMEOUT, None).unwrap(),
+            hex!("05 F7 00 01 00000000 0000")
+        );
+
+        // Without support, extended codes are replaced; others are unchanged.
+        req.set_extended_errors(false);
+        assert!(!req.extended_errors());
+        assert_eq!(
+            req.reply(SocksStatus::HS_DESC_NOT_FOUND, None).unwrap(),
+            hex!("05 04 00 01 00000000 0000")
+        );
+        assert_eq!(
+            req.reply(SocksStatus::TTL_EXPIRED, None).unwrap(),
+            hex!("05 06 00 01 00000000 0000")
+        );
+
+        // SOCKS4 can only say "rejected", whatever we set.
+        let mut h = SocksProxyHandshake::new();
+        let _a = h
+            .handshake(&hex!("04 01 0050 CB007107 00"))
+            .unwrap()
+            .unwrap();
+        let req = h.into_request().unwrap();
+        assert_eq!(
+            req.reply(SocksStatus::HS_REND_FAILED, None).unwrap(),
+            hex!("00 5B 0000 00000000")
+        );
+    }
+
     #[test]
     fn socks5_request_ok_hostname() {
         let mut h = SocksProxyHandshake::new();
@@ -57,6 +57,10 @@ pub struct SocksRequest {
     /// (Tor doesn't believe in SOCKS authentication, since it cannot
     /// possibly secure.  Instead, we use it for circuit isolation.)
     auth: SocksAuth,
+    /// True if we may send Tor's extended SOCKS5 error codes in our reply.
+    ///
+    /// Defaults to true.
+    extended_errors: bool,
 }
 
 #[cfg(feature = "arbitrary")]
@@ -221,6 +225,39 @@ impl SocksCmd {
 }
 
 impl SocksStatus {
+    /// Return true if this is one of Tor's extended error codes for onion
+    /// service failures, which are not part of RFC 1928.
+    ///
+    /// SOCKS clients that don't know about these codes may not be able to
+    /// handle them.
+    pub fn is_tor_extension(self) -> bool {
+        matches!(
+            self,
+            SocksStatus::HS_DESC_NOT_FOUND
+                | SocksStatus::HS_DESC_INVALID
+                | SocksStatus::HS_INTRO_FAILED
+                | SocksStatus::HS_REND_FAILED
+                | SocksStatus::HS_MISSING_CLIENT_AUTH
+                | SocksStatus::HS_WRONG_CLIENT_AUTH
+                | SocksStatus::HS_BAD_ADDRESS
+                | SocksStatus::HS_INTRO_TIMEOUT
+        )
+    }
+
+    /// Return a status with the same meaning as this one that any SOCKS5
+    /// client can understand.
+    ///
+    /// Tor's extended error codes become `HOST_UNREACHABLE`, which is what C
+    /// Tor sends for onion service failures when extended errors are
+    /// disabled.  Every other status is returned unchanged.
+    pub fn without_tor_extensions(self) -> Self {
+        if self.is_tor_extension() {
+            SocksStatus::HOST_UNREACHABLE
+        } else {
+            self
+        }
+    }
+
     /// Convert this status into a value for use with SOCKS4 or SOCKS4a.
     #[cfg(feature = "proxy-handshake")]
     pub(crate) fn into_socks4_status(self) -> u8 {
@@ -328,6 +365,9 @@ impl SocksRequest {
             addr,
             port,
             auth,
+            // Replies have always included the extended codes;
+            // proxies that know better turn them off.
+            extended_errors: true,
         })
     }
 
@@ -355,6 +395,12 @@ impl SocksRequest {
     pub fn addr(&self) -> &SocksAddr {
         &self.addr
     }
+
+    /// Return true if we may send Tor's extended SOCKS5 error codes in reply
+    /// to this request.
+    pub fn extended_errors(&self) -> bool {
+        self.extended_errors
+    }
 }
 
 impl fmt::Display for SocksAddr {
@@ -381,8 +427,9 @@ pub struct SocksReply {
 
 impl SocksReply {
     /// Create a new SocksReply.
-    #[cfg(feature = "client-handshake")]
-    pub(crate) fn new(status: SocksStatus, addr: SocksAddr, port: u16) -> Self {
+    ///
+    /// A proxy can send this to its client with [`SocksRequest::encode_reply`].
+    pub fn new(status: SocksStatus, addr: SocksAddr, port: u16) -> Self {
         Self { status, addr, port }
     }
 